@@ -1,11 +1,11 @@
-use super::{resolve_env_id_pretty, EXIT_SUCCESS};
-use karapace_core::{Engine, StoreLock};
+use super::{acquire_store_lock, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
 use karapace_store::StoreLayout;
 use std::path::Path;
 
 pub fn run(engine: &Engine, store_path: &Path, env_id: &str) -> Result<u8, String> {
     let layout = StoreLayout::new(store_path);
-    let _lock = StoreLock::acquire(&layout.lock_file()).map_err(|e| format!("store lock: {e}"))?;
+    let _lock = acquire_store_lock(&layout, "archive")?;
 
     let resolved = resolve_env_id_pretty(engine, env_id)?;
     engine.archive(&resolved).map_err(|e| e.to_string())?;
@@ -0,0 +1,11 @@
+use super::{resolve_env_id_pretty, EXIT_FAILURE};
+use karapace_core::Engine;
+
+pub fn run(engine: &Engine, env_id: &str, command: &[String]) -> Result<u8, String> {
+    // No store lock: any number of shells may share the session.
+    let resolved = resolve_env_id_pretty(engine, env_id)?;
+    let code = engine
+        .attach(&resolved, command)
+        .map_err(|e| e.to_string())?;
+    Ok(u8::try_from(code).unwrap_or(EXIT_FAILURE))
+}
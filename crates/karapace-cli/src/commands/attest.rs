@@ -0,0 +1,78 @@
+use super::{json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
+use std::path::Path;
+
+pub fn run(
+    engine: &Engine,
+    env_id: &str,
+    verify: bool,
+    key: Option<&str>,
+    output: Option<&Path>,
+    json: bool,
+) -> Result<u8, String> {
+    let resolved = if json {
+        resolve_env_id(engine, env_id)?
+    } else {
+        resolve_env_id_pretty(engine, env_id)?
+    };
+
+    if !verify {
+        let envelope = engine
+            .attestation(&resolved)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("environment {env_id} has no attestation; rebuild it"))?;
+        let document = envelope.to_json().map_err(|e| e.to_string())?;
+        if let Some(path) = output {
+            std::fs::write(path, format!("{document}\n"))
+                .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+            if !json {
+                println!("wrote attestation of {env_id} to {}", path.display());
+            }
+        } else {
+            println!("{document}");
+        }
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let (envelope, statement) = engine
+        .verify_attestation(&resolved)
+        .map_err(|e| e.to_string())?;
+    let keyids: Vec<&str> = envelope.keyids().collect();
+    if let Some(key) = key {
+        if !keyids.contains(&key) {
+            return Err(format!("attestation of {env_id} is not signed by {key}"));
+        }
+    }
+
+    if json {
+        let payload = serde_json::json!({
+            "verified": true,
+            "keyids": keyids,
+            "statement": statement,
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else {
+        let run = &statement.predicate.run_details;
+        let host = &run.builder.host;
+        println!("attestation of {env_id} verified");
+        for keyid in &keyids {
+            println!("  signed by:  {keyid}");
+        }
+        println!("  layer:      {}", statement.layer_hash().unwrap_or("-"));
+        println!(
+            "  manifest:   {}",
+            statement
+                .predicate
+                .build_definition
+                .external_parameters
+                .manifest_hash
+        );
+        println!("  builder:    {}", run.builder.id);
+        println!(
+            "  host:       {} ({} {})",
+            host.hostname, host.kernel, host.arch
+        );
+        println!("  built:      {}", run.metadata.finished_on);
+    }
+    Ok(EXIT_SUCCESS)
+}
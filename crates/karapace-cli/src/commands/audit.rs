@@ -0,0 +1,72 @@
+use super::{
+    json_pretty, progress_json, resolve_env_id, resolve_env_id_pretty, spin_fail, spin_ok, spinner,
+    EXIT_FAILURE, EXIT_SUCCESS,
+};
+use karapace_core::{CachePolicy, CachedFeed, Engine, OsvFeed, Severity};
+
+pub fn run(
+    engine: &Engine,
+    env_id: &str,
+    policy: CachePolicy,
+    fail_on: Severity,
+    json: bool,
+) -> Result<u8, String> {
+    let resolved = if json {
+        resolve_env_id(engine, env_id)?
+    } else {
+        resolve_env_id_pretty(engine, env_id)?
+    };
+    let feed = CachedFeed::new(
+        OsvFeed::new(),
+        engine.store_layout().audit_cache_dir(),
+        policy,
+    );
+    let pb = (!json && !progress_json()).then(|| spinner("checking packages…"));
+    let report = engine.audit(&resolved, &feed).map_err(|e| {
+        if let Some(pb) = &pb {
+            spin_fail(pb, "audit failed");
+        }
+        e.to_string()
+    })?;
+    if let Some(pb) = &pb {
+        spin_ok(pb, "audit complete");
+    }
+    let failed = report.any_at_least(fail_on);
+
+    if json {
+        println!("{}", json_pretty(&report)?);
+    } else {
+        println!(
+            "{} packages of {} checked against {}",
+            report.packages,
+            &resolved[..12],
+            report.ecosystem
+        );
+        if !report.findings.is_empty() {
+            println!(
+                "{:<9} {:<24} {:<20} {:<22} {:<20} SUMMARY",
+                "SEVERITY", "PACKAGE", "VERSION", "ID", "FIXED"
+            );
+        }
+        for finding in &report.findings {
+            println!(
+                "{:<9} {:<24} {:<20} {:<22} {:<20} {}",
+                finding.severity,
+                finding.package,
+                finding.version,
+                finding.id,
+                finding.fixed.as_deref().unwrap_or("-"),
+                finding.summary
+            );
+        }
+        let counts: Vec<String> = Severity::ALL
+            .iter()
+            .map(|s| format!("{} {s}", report.count(*s)))
+            .collect();
+        println!("{}", counts.join(", "));
+        if failed {
+            eprintln!("found vulnerabilities rated {fail_on} or worse");
+        }
+    }
+    Ok(if failed { EXIT_FAILURE } else { EXIT_SUCCESS })
+}
@@ -0,0 +1,172 @@
+use super::{acquire_store_lock, json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use clap::Subcommand;
+use karapace_core::{autosnap, shutdown_requested, AutosnapRun, Engine, StoreLock};
+use karapace_store::StoreLayout;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Longest the agent sleeps, so schedules enabled meanwhile are picked up.
+const MAX_WAIT: Duration = Duration::from_mins(1);
+
+#[derive(Debug, Subcommand)]
+pub enum AutosnapAction {
+    /// Snapshot an environment periodically while `autosnap run` is active.
+    Enable {
+        /// Environment ID, short ID, or name.
+        env_id: String,
+        /// Time between snapshots (e.g. "1h", "30m").
+        #[arg(long, value_name = "AGE", value_parser = super::gc::parse_age)]
+        interval: Duration,
+        /// Number of automatic snapshots to keep.
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        keep: usize,
+    },
+    /// Stop snapshotting an environment.
+    Disable {
+        /// Environment ID, short ID, or name.
+        env_id: String,
+    },
+    /// List snapshot schedules.
+    List,
+    /// Take due snapshots until interrupted.
+    Run {
+        /// Take the snapshots due now and exit.
+        #[arg(long, default_value_t = false)]
+        once: bool,
+    },
+}
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    action: &AutosnapAction,
+    json: bool,
+) -> Result<u8, String> {
+    let layout = StoreLayout::new(store_path);
+    match action {
+        AutosnapAction::Enable {
+            env_id,
+            interval,
+            keep,
+        } => {
+            let _lock = acquire_store_lock(&layout, "autosnap")?;
+            let resolved = resolve(engine, env_id, json)?;
+            let schedule =
+                autosnap::enable(engine, &resolved, *interval, *keep).map_err(|e| e.to_string())?;
+            if json {
+                let payload = serde_json::json!({
+                    "env_id": resolved,
+                    "schedule": schedule,
+                });
+                println!("{}", json_pretty(&payload)?);
+            } else {
+                println!(
+                    "{} is snapshotted every {}s, keeping {keep}",
+                    &resolved[..12],
+                    schedule.interval_secs
+                );
+            }
+        }
+        AutosnapAction::Disable { env_id } => {
+            let _lock = acquire_store_lock(&layout, "autosnap")?;
+            let resolved = resolve(engine, env_id, json)?;
+            let removed = autosnap::disable(&layout, &resolved).map_err(|e| e.to_string())?;
+            if json {
+                let payload = serde_json::json!({
+                    "env_id": resolved,
+                    "disabled": removed,
+                });
+                println!("{}", json_pretty(&payload)?);
+            } else if removed {
+                println!("{} is no longer snapshotted", &resolved[..12]);
+            } else {
+                println!("{} had no snapshot schedule", &resolved[..12]);
+            }
+        }
+        AutosnapAction::List => {
+            let schedules = autosnap::load_schedules(&layout).map_err(|e| e.to_string())?;
+            if json {
+                println!("{}", json_pretty(&schedules)?);
+            } else if schedules.is_empty() {
+                println!("no snapshot schedules");
+            } else {
+                println!(
+                    "{:<14} {:>10} {:>5}  LAST SNAPSHOT",
+                    "ENV", "INTERVAL", "KEEP"
+                );
+                for (env_id, schedule) in &schedules {
+                    println!(
+                        "{:<14} {:>9}s {:>5}  {}",
+                        &env_id[..12.min(env_id.len())],
+                        schedule.interval_secs,
+                        schedule.keep,
+                        schedule.last_snapshot_at.as_deref().unwrap_or("never")
+                    );
+                }
+            }
+        }
+        AutosnapAction::Run { once } => run_agent(engine, &layout, *once, json)?,
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn resolve(engine: &Engine, env_id: &str, json: bool) -> Result<String, String> {
+    if json {
+        resolve_env_id(engine, env_id)
+    } else {
+        resolve_env_id_pretty(engine, env_id)
+    }
+}
+
+/// Take due snapshots, then sleep until the next one is due. A store held
+/// by another command is retried a second later.
+fn run_agent(engine: &Engine, layout: &StoreLayout, once: bool, json: bool) -> Result<(), String> {
+    while !shutdown_requested() {
+        let lock = StoreLock::try_acquire(&layout.lock_file()).map_err(|e| e.to_string())?;
+        let wait = if let Some(_lock) = lock {
+            let runs = autosnap::run_due(engine).map_err(|e| e.to_string())?;
+            report(&runs, json)?;
+            if once {
+                break;
+            }
+            autosnap::next_due(layout)
+                .map_err(|e| e.to_string())?
+                .unwrap_or(MAX_WAIT)
+                .clamp(Duration::from_secs(1), MAX_WAIT)
+        } else if once {
+            return Err("store lock: the store is busy".to_owned());
+        } else {
+            Duration::from_secs(1)
+        };
+
+        let until = Instant::now() + wait;
+        while Instant::now() < until && !shutdown_requested() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    Ok(())
+}
+
+fn report(runs: &[AutosnapRun], json: bool) -> Result<(), String> {
+    for run in runs {
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string(run)
+                    .map_err(|e| format!("JSON serialization failed: {e}"))?
+            );
+            continue;
+        }
+        let short = &run.env_id[..12.min(run.env_id.len())];
+        match (&run.snapshot, &run.error) {
+            (Some(snapshot), _) => {
+                println!("{short}: snapshot {snapshot}, pruned {}", run.pruned.len());
+            }
+            (None, e) => eprintln!(
+                "{short}: no snapshot: {}",
+                e.as_deref().unwrap_or("unknown")
+            ),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,278 @@
+use super::{json_pretty, EXIT_FAILURE, EXIT_SUCCESS};
+use karapace_core::{Engine, StoreLock};
+use karapace_store::chaos::{self, FaultPlan};
+use karapace_store::{
+    verify_env_integrity, verify_store_integrity, EnvState, StoreLayout, WriteAheadLog,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const PACKAGES: &[&str] = &["git", "curl", "vim", "make", "clang", "jq"];
+
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    None,
+    FailedWrite,
+    DelayedRename,
+    PowerCut,
+}
+
+impl Fault {
+    fn label(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::FailedWrite => "failed write",
+            Self::DelayedRename => "delayed rename",
+            Self::PowerCut => "power cut",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    clean: usize,
+    failed_writes: usize,
+    delayed_renames: usize,
+    power_cuts: usize,
+    failures: Vec<String>,
+}
+
+/// xorshift64, enough to make a run reproducible from its seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+}
+
+/// Run `cycles` rounds of build/commit/destroy/gc against a scratch store
+/// in `dir`, each under a random injected fault, and check after every
+/// round that the store recovers to a consistent state.
+pub fn run(dir: Option<&Path>, cycles: usize, seed: Option<u64>, json: bool) -> Result<u8, String> {
+    // The scratch store defaults to the working directory so the faults hit
+    // the disk under test rather than a tmpfs.
+    let (root, scratch) = if let Some(dir) = dir {
+        (dir.to_path_buf(), None)
+    } else {
+        let tmp = tempfile::Builder::new()
+            .prefix(".karapace-chaos-")
+            .tempdir_in(".")
+            .map_err(|e| format!("failed to create scratch directory: {e}"))?;
+        (tmp.path().to_path_buf(), Some(tmp))
+    };
+    let store = root.join("store");
+    let project = root.join("project");
+    fs::create_dir_all(&project)
+        .map_err(|e| format!("failed to create {}: {e}", project.display()))?;
+
+    let seed = seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| d.as_nanos() as u64)
+    });
+    let mut rng = Rng(seed.max(1));
+    let mut tally = Tally::default();
+    // Bytes a clean cycle wrote last; failed-write budgets are drawn below it.
+    let mut budget_hint = 1 << 16;
+
+    for cycle in 0..cycles {
+        let (fault, plan) = pick_fault(&mut rng, budget_hint);
+        let manifest = write_manifest(&project, &mut rng)?;
+
+        let guard = chaos::install(&store, plan);
+        let result = run_ops(&store, &manifest, cycle);
+        let written = guard.bytes_written();
+        let snapshots = guard.take_snapshots();
+        drop(guard);
+
+        match fault {
+            Fault::None => {
+                tally.clean += 1;
+                if result.is_ok() {
+                    budget_hint = written.max(1);
+                }
+            }
+            Fault::FailedWrite => tally.failed_writes += 1,
+            Fault::DelayedRename => tally.delayed_renames += 1,
+            Fault::PowerCut => {
+                tally.power_cuts += 1;
+                if !snapshots.is_empty() {
+                    let cut = &snapshots[rng.below(snapshots.len() as u64) as usize];
+                    cut.restore(&store)
+                        .map_err(|e| format!("failed to restore power-cut state: {e}"))?;
+                }
+            }
+        }
+
+        // Only a failed write may make the operations themselves fail.
+        let op_error = match (fault, result) {
+            (Fault::None | Fault::DelayedRename, Err(e)) => Some(e),
+            _ => None,
+        };
+        for e in op_error.into_iter().chain(check_store(&store).err()) {
+            let failure = format!("cycle {cycle} ({}): {e}", fault.label());
+            if !json {
+                eprintln!("FAIL {failure}");
+            }
+            tally.failures.push(failure);
+        }
+    }
+
+    let kept = if tally.failures.is_empty() {
+        dir.is_some().then(|| root.clone())
+    } else {
+        // Leave the store behind for inspection.
+        Some(scratch.map_or_else(|| root.clone(), tempfile::TempDir::keep))
+    };
+
+    print_summary(seed, cycles, &tally, kept.as_deref(), json)?;
+
+    Ok(if tally.failures.is_empty() {
+        EXIT_SUCCESS
+    } else {
+        EXIT_FAILURE
+    })
+}
+
+fn pick_fault(rng: &mut Rng, budget_hint: u64) -> (Fault, FaultPlan) {
+    let fault = match rng.below(4) {
+        0 => Fault::None,
+        1 => Fault::FailedWrite,
+        2 => Fault::DelayedRename,
+        _ => Fault::PowerCut,
+    };
+    let plan = match fault {
+        Fault::None => FaultPlan::default(),
+        Fault::FailedWrite => FaultPlan {
+            fail_writes_after: Some(rng.below(budget_hint)),
+            ..FaultPlan::default()
+        },
+        Fault::DelayedRename => FaultPlan {
+            rename_delay: Some(Duration::from_millis(1 + rng.below(5))),
+            ..FaultPlan::default()
+        },
+        Fault::PowerCut => FaultPlan {
+            record_snapshots: true,
+            ..FaultPlan::default()
+        },
+    };
+    (fault, plan)
+}
+
+fn print_summary(
+    seed: u64,
+    cycles: usize,
+    tally: &Tally,
+    kept: Option<&Path>,
+    json: bool,
+) -> Result<(), String> {
+    if json {
+        let payload = serde_json::json!({
+            "seed": seed,
+            "cycles": cycles,
+            "clean": tally.clean,
+            "failed_writes": tally.failed_writes,
+            "delayed_renames": tally.delayed_renames,
+            "power_cuts": tally.power_cuts,
+            "failures": tally.failures,
+            "dir": kept,
+        });
+        println!("{}", json_pretty(&payload)?);
+        return Ok(());
+    }
+    println!(
+        "chaos: {cycles} cycles (seed {seed}): {} clean, {} failed writes, {} delayed renames, {} power cuts",
+        tally.clean, tally.failed_writes, tally.delayed_renames, tally.power_cuts
+    );
+    if tally.failures.is_empty() {
+        println!("store recovered after every cycle");
+    } else {
+        println!("{} failures", tally.failures.len());
+    }
+    if let Some(kept) = kept {
+        println!("store kept at {}", kept.display());
+    }
+    Ok(())
+}
+
+fn write_manifest(project: &Path, rng: &mut Rng) -> Result<PathBuf, String> {
+    let packages: Vec<String> = PACKAGES
+        .iter()
+        .filter(|_| rng.below(2) == 0)
+        .map(|p| format!("\"{p}\""))
+        .collect();
+    let path = project.join("karapace.toml");
+    fs::write(
+        &path,
+        format!(
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[system]\npackages = [{}]\n[runtime]\nbackend = \"mock\"\n",
+            packages.join(", ")
+        ),
+    )
+    .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+    Ok(path)
+}
+
+fn run_ops(store: &Path, manifest: &Path, cycle: usize) -> Result<(), String> {
+    let engine = Engine::new(store);
+    let env_id = engine
+        .build(manifest)
+        .map_err(|e| format!("build: {e}"))?
+        .identity
+        .env_id
+        .to_string();
+    let upper = engine.store_layout().upper_dir(&env_id);
+    fs::create_dir_all(&upper).map_err(|e| format!("write upper: {e}"))?;
+    fs::write(upper.join("chaos.txt"), format!("cycle {cycle}"))
+        .map_err(|e| format!("write upper: {e}"))?;
+    engine.commit(&env_id).map_err(|e| format!("commit: {e}"))?;
+
+    if cycle % 4 == 3 {
+        engine
+            .destroy(&env_id)
+            .map_err(|e| format!("destroy: {e}"))?;
+        let lock = StoreLock::acquire(&engine.store_layout().lock_file())
+            .map_err(|e| format!("store lock: {e}"))?;
+        engine.gc(&lock, false).map_err(|e| format!("gc: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Reopen the store, which runs WAL recovery, and check it is consistent.
+fn check_store(store: &Path) -> Result<(), String> {
+    let engine = Engine::new(store);
+    let layout = StoreLayout::new(store);
+
+    let incomplete = WriteAheadLog::new(&layout)
+        .list_incomplete()
+        .map_err(|e| e.to_string())?;
+    if !incomplete.is_empty() {
+        return Err(format!(
+            "{} WAL entries left after recovery",
+            incomplete.len()
+        ));
+    }
+    let report = verify_store_integrity(&layout).map_err(|e| e.to_string())?;
+    if let Some(f) = report.failed.first() {
+        return Err(format!("integrity: {}: {}", f.hash, f.reason));
+    }
+    for env in engine.list().map_err(|e| e.to_string())? {
+        if env.state == EnvState::Running {
+            return Err(format!("env {} stuck running", env.env_id));
+        }
+        let report = verify_env_integrity(&layout, &env.env_id).map_err(|e| e.to_string())?;
+        if let Some(f) = report.failed.first() {
+            return Err(format!("env {}: {}: {}", env.env_id, f.hash, f.reason));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use super::{
+    acquire_store_lock, json_pretty, print_manifest_warnings, spin_fail, spin_ok, spinner,
+    EXIT_FAILURE, EXIT_SUCCESS,
+};
+use karapace_core::Engine;
+use karapace_schema::parse_manifest_file_with_warnings;
+use karapace_store::StoreLayout;
+use std::path::Path;
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    manifest: &Path,
+    frozen: bool,
+    offline: bool,
+    json: bool,
+) -> Result<u8, String> {
+    // Resolution populates the image cache, so it needs the store lock.
+    let _lock = if frozen {
+        let layout = StoreLayout::new(store_path);
+        Some(acquire_store_lock(&layout, "check")?)
+    } else {
+        None
+    };
+
+    let pb = if json || !frozen {
+        None
+    } else {
+        Some(spinner("re-resolving lock file..."))
+    };
+    let diff = match engine.check_lock(manifest, frozen, offline) {
+        Ok(d) => d,
+        Err(e) => {
+            if let Some(ref pb) = pb {
+                spin_fail(pb, "check failed");
+            }
+            return Err(e.to_string());
+        }
+    };
+    // check_lock already parsed the manifest; a failure here only drops the warnings.
+    let warnings = parse_manifest_file_with_warnings(manifest)
+        .map(|(_, warnings)| warnings)
+        .unwrap_or_default();
+    if let Some(ref pb) = pb {
+        if diff.is_empty() {
+            spin_ok(pb, "lock file is current");
+        } else {
+            spin_fail(pb, "lock file is stale");
+        }
+    }
+
+    if json {
+        let payload = serde_json::json!({
+            "status": if diff.is_empty() { "ok" } else { "drift" },
+            "frozen": frozen,
+            "diff": diff,
+            "warnings": warnings,
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else {
+        print_manifest_warnings(manifest, &warnings);
+        if diff.is_empty() {
+            println!("karapace.lock is up to date");
+        } else {
+            println!("karapace.lock differs from fresh resolution:");
+            print!("{diff}");
+            println!("run 'karapace build' to update the lock file");
+        }
+    }
+
+    if diff.is_empty() {
+        Ok(EXIT_SUCCESS)
+    } else {
+        Ok(EXIT_FAILURE)
+    }
+}
@@ -0,0 +1,40 @@
+use super::{acquire_store_lock, json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
+use karapace_store::StoreLayout;
+use std::path::Path;
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    env_id: &str,
+    new_name: &str,
+    json: bool,
+) -> Result<u8, String> {
+    let layout = StoreLayout::new(store_path);
+    let _lock = acquire_store_lock(&layout, "clone")?;
+
+    let resolved = if json {
+        resolve_env_id(engine, env_id)?
+    } else {
+        resolve_env_id_pretty(engine, env_id)?
+    };
+    let clone_id = engine
+        .clone_env(&resolved, new_name)
+        .map_err(|e| e.to_string())?;
+    if json {
+        let payload = serde_json::json!({
+            "source": resolved,
+            "env_id": clone_id,
+            "name": new_name,
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else {
+        println!(
+            "cloned {} → '{}' ({})",
+            &resolved[..12],
+            new_name,
+            &clone_id[..12]
+        );
+    }
+    Ok(EXIT_SUCCESS)
+}
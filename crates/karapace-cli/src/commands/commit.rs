@@ -1,18 +1,26 @@
-use super::{json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
-use karapace_core::{Engine, StoreLock};
+use super::{acquire_store_lock, json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::{CommitOptions, Engine};
 use karapace_store::StoreLayout;
 use std::path::Path;
 
-pub fn run(engine: &Engine, store_path: &Path, env_id: &str, json: bool) -> Result<u8, String> {
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    env_id: &str,
+    incremental: bool,
+    json: bool,
+) -> Result<u8, String> {
     let layout = StoreLayout::new(store_path);
-    let _lock = StoreLock::acquire(&layout.lock_file()).map_err(|e| format!("store lock: {e}"))?;
+    let _lock = acquire_store_lock(&layout, "commit")?;
 
     let resolved = if json {
         resolve_env_id(engine, env_id)?
     } else {
         resolve_env_id_pretty(engine, env_id)?
     };
-    let tar_hash = engine.commit(&resolved).map_err(|e| e.to_string())?;
+    let tar_hash = engine
+        .commit_with_options(&resolved, CommitOptions { incremental })
+        .map_err(|e| e.to_string())?;
     if json {
         let payload = serde_json::json!({
             "env_id": resolved,
@@ -0,0 +1,76 @@
+use super::{json_pretty, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
+use karapace_runtime::export;
+use std::path::Path;
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    env_id: &str,
+    app: Option<&str>,
+    remove: bool,
+    json: bool,
+) -> Result<u8, String> {
+    let resolved = resolve_env_id_pretty(engine, env_id)?;
+
+    match (app, remove) {
+        (None, false) => {
+            let apps = export::list_exported(&resolved).map_err(|e| e.to_string())?;
+            if json {
+                println!("{}", json_pretty(&apps)?);
+            } else if apps.is_empty() {
+                println!("no exported applications");
+            } else {
+                for app in apps {
+                    println!("{app}");
+                }
+            }
+        }
+        (None, true) => {
+            let removed = export::unexport_all(&resolved).map_err(|e| e.to_string())?;
+            if json {
+                println!("{}", json_pretty(&removed)?);
+            } else {
+                println!("removed {} launcher entries", removed.len());
+            }
+        }
+        (Some(app), true) => {
+            export::unexport_app(&resolved, app).map_err(|e| e.to_string())?;
+            if json {
+                println!("{}", json_pretty(&[app])?);
+            } else {
+                println!("removed launcher entry for {app}");
+            }
+        }
+        (Some(app), false) => {
+            let upper = engine.store_layout().upper_dir(&resolved);
+            let entry = export::find_app(&upper, app)
+                .ok_or_else(|| format!("no application '{app}' in environment {env_id}"))?;
+            let karapace_bin = std::env::current_exe()
+                .map_err(|e| format!("failed to locate the karapace binary: {e}"))?;
+            let exported = export::export_app(
+                &resolved,
+                app,
+                &entry,
+                &karapace_bin.to_string_lossy(),
+                &store_path.to_string_lossy(),
+            )
+            .map_err(|e| e.to_string())?;
+            if json {
+                let out = serde_json::json!({
+                    "name": exported.name,
+                    "desktop_file": exported.desktop_file,
+                    "exec": exported.exec_command,
+                });
+                println!("{}", json_pretty(&out)?);
+            } else {
+                println!(
+                    "exported {} to {}",
+                    entry.name,
+                    exported.desktop_file.display()
+                );
+            }
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
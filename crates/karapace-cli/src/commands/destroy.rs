@@ -1,14 +1,39 @@
-use super::{resolve_env_id_pretty, EXIT_SUCCESS};
-use karapace_core::{Engine, StoreLock};
+use super::{acquire_store_lock, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
 use karapace_store::StoreLayout;
 use std::path::Path;
 
-pub fn run(engine: &Engine, store_path: &Path, env_id: &str) -> Result<u8, String> {
+pub fn run(engine: &Engine, store_path: &Path, env_ids: &[String], gc: bool) -> Result<u8, String> {
     let layout = StoreLayout::new(store_path);
-    let _lock = StoreLock::acquire(&layout.lock_file()).map_err(|e| format!("store lock: {e}"))?;
+    let lock = acquire_store_lock(&layout, "destroy")?;
 
-    let resolved = resolve_env_id_pretty(engine, env_id)?;
-    engine.destroy(&resolved).map_err(|e| e.to_string())?;
-    println!("destroyed environment {env_id}");
+    let resolved = env_ids
+        .iter()
+        .map(|env_id| resolve_env_id_pretty(engine, env_id))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let [env_id] = resolved.as_slice() {
+        // Recorded in the history as a destroy of this environment.
+        engine.destroy(env_id).map_err(|e| e.to_string())?;
+        if gc {
+            engine.gc(&lock, false).map_err(|e| e.to_string())?;
+        }
+    } else {
+        engine
+            .with_transaction(&lock, |tx| {
+                for env_id in &resolved {
+                    tx.destroy(env_id)?;
+                }
+                if gc {
+                    tx.gc();
+                }
+                Ok(())
+            })
+            .map_err(|e| e.to_string())?;
+    }
+    for (env_id, resolved) in env_ids.iter().zip(&resolved) {
+        // Launcher entries would only fail to start now.
+        let _ = karapace_runtime::export::unexport_all(resolved);
+        println!("destroyed environment {env_id}");
+    }
     Ok(EXIT_SUCCESS)
 }
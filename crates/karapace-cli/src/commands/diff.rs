@@ -1,17 +1,28 @@
 use super::{json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
-use karapace_core::Engine;
+use karapace_core::{Engine, PATCH_SIZE_LIMIT};
 
-pub fn run(engine: &Engine, env_id: &str, json: bool) -> Result<u8, String> {
+pub fn run(engine: &Engine, env_id: &str, patch: bool, json: bool) -> Result<u8, String> {
     let resolved = if json {
         resolve_env_id(engine, env_id)?
     } else {
         resolve_env_id_pretty(engine, env_id)?
     };
-    let report =
-        karapace_core::diff_overlay(engine.store_layout(), &resolved).map_err(|e| e.to_string())?;
+    let layout = engine.store_layout();
+    let report = karapace_core::diff_overlay(layout, &resolved).map_err(|e| e.to_string())?;
+    let patches = if patch {
+        karapace_core::overlay_patches(layout, &report, PATCH_SIZE_LIMIT)
+    } else {
+        Vec::new()
+    };
 
     if json {
-        println!("{}", json_pretty(&report)?);
+        let mut payload =
+            serde_json::to_value(&report).map_err(|e| format!("JSON serialization failed: {e}"))?;
+        if patch {
+            payload["patches"] = serde_json::to_value(&patches)
+                .map_err(|e| format!("JSON serialization failed: {e}"))?;
+        }
+        println!("{}", json_pretty(&payload)?);
     } else if report.has_drift {
         println!("drift detected in environment {env_id}:");
         for f in &report.added {
@@ -23,6 +34,15 @@ pub fn run(engine: &Engine, env_id: &str, json: bool) -> Result<u8, String> {
         for f in &report.removed {
             println!("  - {f}");
         }
+        if !patches.is_empty() {
+            println!();
+        }
+        for file in &patches {
+            match (&file.diff, &file.note) {
+                (Some(diff), _) => print!("{diff}"),
+                (None, note) => println!("{}: {}", file.path, note.as_deref().unwrap_or("")),
+            }
+        }
     } else {
         println!("no drift detected in environment {env_id}");
     }
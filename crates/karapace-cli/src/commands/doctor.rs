@@ -1,20 +1,21 @@
 use super::{EXIT_FAILURE, EXIT_SUCCESS};
-use karapace_store::StoreLayout;
+use karapace_core::health::{self, CheckStatus, HealthCheck};
+use karapace_store::{EnvMetadata, MetadataIndex, MetadataStore, StoreLayout};
 use std::path::Path;
 
-pub fn run(store_path: &Path, json_output: bool) -> Result<u8, String> {
-    let mut checks: Vec<Check> = Vec::new();
+pub fn run(store_path: &Path, rebuild_index: bool, json_output: bool) -> Result<u8, String> {
+    let mut checks: Vec<HealthCheck> = Vec::new();
     let mut all_pass = true;
 
     check_prereqs(&mut checks, &mut all_pass);
 
     let layout = StoreLayout::new(store_path);
     if store_path.join("store").exists() {
-        checks.push(Check::pass("store_exists", "Store directory exists"));
-        check_store(&layout, &mut checks, &mut all_pass);
-        check_disk_space(store_path, &mut checks);
+        checks.push(HealthCheck::pass("store_exists", "Store directory exists"));
+        check_store(&layout, rebuild_index, &mut checks, &mut all_pass);
+        checks.extend(health::check_disk_space(store_path));
     } else {
-        checks.push(Check::info(
+        checks.push(HealthCheck::info(
             "store_exists",
             "Store not initialized (will be created on first build)",
         ));
@@ -23,16 +24,16 @@ pub fn run(store_path: &Path, json_output: bool) -> Result<u8, String> {
     print_results(&checks, all_pass, json_output)
 }
 
-fn check_prereqs(checks: &mut Vec<Check>, all_pass: &mut bool) {
+fn check_prereqs(checks: &mut Vec<HealthCheck>, all_pass: &mut bool) {
     let missing = karapace_runtime::check_namespace_prereqs();
     if missing.is_empty() {
-        checks.push(Check::pass(
+        checks.push(HealthCheck::pass(
             "runtime_prereqs",
             "Runtime prerequisites satisfied",
         ));
     } else {
         *all_pass = false;
-        checks.push(Check::fail(
+        checks.push(HealthCheck::fail(
             "runtime_prereqs",
             &format!(
                 "Missing prerequisites: {}",
@@ -40,32 +41,47 @@ fn check_prereqs(checks: &mut Vec<Check>, all_pass: &mut bool) {
             ),
         ));
     }
+
+    // Optional: only resource limits depend on it.
+    match karapace_runtime::check_cgroup_prereqs().first() {
+        None => checks.push(HealthCheck::pass(
+            "cgroup_delegation",
+            "cgroup v2 delegated; resource limits are enforced",
+        )),
+        Some(missing) => checks.push(HealthCheck::warn(
+            "cgroup_delegation",
+            &format!(
+                "Resource limits are not enforced: no {} ({})",
+                missing.name, missing.install_hint
+            ),
+        )),
+    }
 }
 
-fn check_store(layout: &StoreLayout, checks: &mut Vec<Check>, all_pass: &mut bool) {
+fn check_store(
+    layout: &StoreLayout,
+    rebuild_index: bool,
+    checks: &mut Vec<HealthCheck>,
+    all_pass: &mut bool,
+) {
     // Version
-    match layout.initialize() {
-        Ok(()) => checks.push(Check::pass("store_version", "Store format version valid")),
-        Err(e) => {
-            *all_pass = false;
-            checks.push(Check::fail(
-                "store_version",
-                &format!("Store version check failed: {e}"),
-            ));
-        }
+    let version = health::check_store_version(layout);
+    if version.status == CheckStatus::Fail {
+        *all_pass = false;
     }
+    checks.push(version);
 
     // Integrity
     match karapace_store::verify_store_integrity(layout) {
         Ok(report) if report.failed.is_empty() => {
-            checks.push(Check::pass(
+            checks.push(HealthCheck::pass(
                 "store_integrity",
                 &format!("Store integrity OK ({} objects checked)", report.checked),
             ));
         }
         Ok(report) => {
             *all_pass = false;
-            checks.push(Check::fail(
+            checks.push(HealthCheck::fail(
                 "store_integrity",
                 &format!(
                     "{} of {} objects corrupted",
@@ -76,7 +92,7 @@ fn check_store(layout: &StoreLayout, checks: &mut Vec<Check>, all_pass: &mut boo
         }
         Err(e) => {
             *all_pass = false;
-            checks.push(Check::fail(
+            checks.push(HealthCheck::fail(
                 "store_integrity",
                 &format!("Integrity check failed: {e}"),
             ));
@@ -84,36 +100,22 @@ fn check_store(layout: &StoreLayout, checks: &mut Vec<Check>, all_pass: &mut boo
     }
 
     // WAL
-    let wal = karapace_store::WriteAheadLog::new(layout);
-    match wal.list_incomplete() {
-        Ok(entries) if entries.is_empty() => {
-            checks.push(Check::pass(
-                "wal_clean",
-                "WAL is clean (no incomplete entries)",
-            ));
-        }
-        Ok(entries) => {
-            checks.push(Check::warn(
-                "wal_clean",
-                &format!(
-                    "WAL has {} incomplete entries (will recover on next start)",
-                    entries.len()
-                ),
-            ));
-        }
-        Err(e) => checks.push(Check::warn("wal_clean", &format!("Cannot read WAL: {e}"))),
-    }
+    checks.push(health::check_wal(layout));
 
     // Lock
     match karapace_core::StoreLock::try_acquire(&layout.lock_file()) {
-        Ok(Some(_)) => checks.push(Check::pass("store_lock", "Store lock is free")),
-        Ok(None) => checks.push(Check::warn(
-            "store_lock",
-            "Store lock is held by another process",
-        )),
+        Ok(Some(_)) => checks.push(HealthCheck::pass("store_lock", "Store lock is free")),
+        Ok(None) => {
+            let holder = karapace_core::StoreLock::holder(&layout.lock_file())
+                .map_or_else(|| "another process".to_owned(), |h| h.to_string());
+            checks.push(HealthCheck::warn(
+                "store_lock",
+                &format!("Store lock is held by {holder}"),
+            ));
+        }
         Err(e) => {
             *all_pass = false;
-            checks.push(Check::fail(
+            checks.push(HealthCheck::fail(
                 "store_lock",
                 &format!("Cannot check store lock: {e}"),
             ));
@@ -121,32 +123,71 @@ fn check_store(layout: &StoreLayout, checks: &mut Vec<Check>, all_pass: &mut boo
     }
 
     // Environments
-    let meta_store = karapace_store::MetadataStore::new(layout.clone());
+    let meta_store = MetadataStore::new(layout.clone());
     match meta_store.list() {
         Ok(envs) => {
             let running = envs
                 .iter()
                 .filter(|e| e.state == karapace_store::EnvState::Running)
                 .count();
-            checks.push(Check::info(
+            checks.push(HealthCheck::info(
                 "environments",
                 &format!("{} environments ({running} running)", envs.len()),
             ));
+            let index = check_index(&meta_store, &envs, rebuild_index);
+            if index.status == CheckStatus::Fail {
+                *all_pass = false;
+            }
+            checks.push(index);
         }
-        Err(e) => checks.push(Check::warn(
+        Err(e) => checks.push(HealthCheck::warn(
             "environments",
             &format!("Cannot list environments: {e}"),
         )),
     }
 }
 
-fn print_results(checks: &[Check], all_pass: bool, json_output: bool) -> Result<u8, String> {
+/// Compare the metadata index with the environments it indexes, or rebuild
+/// it.
+fn check_index(meta_store: &MetadataStore, envs: &[EnvMetadata], rebuild: bool) -> HealthCheck {
+    if rebuild {
+        return match meta_store.rebuild_index() {
+            Ok(index) => HealthCheck::pass(
+                "metadata_index",
+                &format!("Metadata index rebuilt ({} environments)", index.len()),
+            ),
+            Err(e) => HealthCheck::fail(
+                "metadata_index",
+                &format!("Cannot rebuild the metadata index: {e}"),
+            ),
+        };
+    }
+    match meta_store.stored_index() {
+        Ok(Some(index)) if index == MetadataIndex::build(envs) => {
+            HealthCheck::pass("metadata_index", "Metadata index is up to date")
+        }
+        Ok(Some(_)) => HealthCheck::warn(
+            "metadata_index",
+            "Metadata index is out of date (run 'karapace doctor --rebuild-index')",
+        ),
+        Ok(None) => HealthCheck::info(
+            "metadata_index",
+            "No metadata index yet (built on the next lookup)",
+        ),
+        Err(e) => HealthCheck::warn(
+            "metadata_index",
+            &format!("Cannot read the metadata index: {e}"),
+        ),
+    }
+}
+
+fn print_results(checks: &[HealthCheck], all_pass: bool, json_output: bool) -> Result<u8, String> {
     if json_output {
         let json = serde_json::json!({
             "healthy": all_pass,
             "checks": checks.iter().map(|c| serde_json::json!({
                 "name": c.name,
-                "status": c.status,
+                "status": c.status.as_str(),
                 "message": c.message,
             })).collect::<Vec<_>>(),
         });
@@ -157,11 +198,11 @@ fn print_results(checks: &[Check], all_pass: bool, json_output: bool) -> Result<
     } else {
         println!("Karapace Doctor\n");
         for check in checks {
-            let icon = match check.status.as_str() {
-                "pass" => "✓",
-                "fail" => "✗",
-                "warn" => "⚠",
-                _ => "ℹ",
+            let icon = match check.status {
+                CheckStatus::Pass => "✓",
+                CheckStatus::Fail => "✗",
+                CheckStatus::Warn => "⚠",
+                CheckStatus::Info => "ℹ",
             };
             println!("  {icon} {}", check.message);
         }
@@ -174,82 +215,3 @@ fn print_results(checks: &[Check], all_pass: bool, json_output: bool) -> Result<
     }
     Ok(if all_pass { EXIT_SUCCESS } else { EXIT_FAILURE })
 }
-
-struct Check {
-    name: String,
-    status: String,
-    message: String,
-}
-
-impl Check {
-    fn pass(name: &str, message: &str) -> Self {
-        Self {
-            name: name.to_owned(),
-            status: "pass".to_owned(),
-            message: message.to_owned(),
-        }
-    }
-
-    fn fail(name: &str, message: &str) -> Self {
-        Self {
-            name: name.to_owned(),
-            status: "fail".to_owned(),
-            message: message.to_owned(),
-        }
-    }
-
-    fn warn(name: &str, message: &str) -> Self {
-        Self {
-            name: name.to_owned(),
-            status: "warn".to_owned(),
-            message: message.to_owned(),
-        }
-    }
-
-    fn info(name: &str, message: &str) -> Self {
-        Self {
-            name: name.to_owned(),
-            status: "info".to_owned(),
-            message: message.to_owned(),
-        }
-    }
-}
-
-fn check_disk_space(store_path: &Path, checks: &mut Vec<Check>) {
-    let Ok(c_path) = std::ffi::CString::new(store_path.to_string_lossy().as_bytes()) else {
-        return;
-    };
-
-    // SAFETY: zeroed statvfs is a valid initial state for the struct.
-    #[allow(unsafe_code, clippy::undocumented_unsafe_blocks)]
-    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
-    // SAFETY: statvfs with a valid, NUL-terminated path and a properly
-    // zeroed output struct is well-defined. The struct is stack-allocated
-    // and only read after the call succeeds (ret == 0).
-    #[allow(unsafe_code, clippy::undocumented_unsafe_blocks)]
-    let ret = unsafe { libc::statvfs(c_path.as_ptr(), &raw mut stat) };
-    if ret != 0 {
-        return;
-    }
-
-    let avail_bytes = stat.f_bavail * stat.f_frsize;
-    let avail_mb = avail_bytes / (1024 * 1024);
-
-    if avail_mb < 100 {
-        checks.push(Check::fail(
-            "disk_space",
-            &format!("Low disk space: {avail_mb} MB available"),
-        ));
-    } else if avail_mb < 1024 {
-        checks.push(Check::warn(
-            "disk_space",
-            &format!("Disk space: {avail_mb} MB available (consider freeing space)"),
-        ));
-    } else {
-        let free_gb = avail_mb / 1024;
-        checks.push(Check::pass(
-            "disk_space",
-            &format!("Disk space: {free_gb} GB available"),
-        ));
-    }
-}
@@ -0,0 +1,94 @@
+use super::{acquire_store_lock, json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use clap::Subcommand;
+use karapace_core::Engine;
+use karapace_store::StoreLayout;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Subcommand)]
+pub enum DriftAction {
+    /// Write the overlay changes of an environment to a bundle.
+    Export {
+        /// Environment ID, short ID, or name.
+        env_id: String,
+        /// Bundle file to write (a zstd-compressed tar).
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Merge the overlay changes in a bundle into an environment.
+    Apply {
+        /// Environment ID, short ID, or name.
+        env_id: String,
+        /// Bundle written by `drift export`.
+        bundle: PathBuf,
+    },
+}
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    action: &DriftAction,
+    json: bool,
+) -> Result<u8, String> {
+    match action {
+        DriftAction::Export { env_id, output } => {
+            let resolved = resolve(engine, env_id, json)?;
+            let bundle = engine.export_drift(&resolved).map_err(|e| e.to_string())?;
+            std::fs::write(output, &bundle)
+                .map_err(|e| format!("failed to write {}: {e}", output.display()))?;
+            if json {
+                let payload = serde_json::json!({
+                    "env_id": resolved,
+                    "output": output,
+                    "bytes": bundle.len(),
+                });
+                println!("{}", json_pretty(&payload)?);
+            } else {
+                println!(
+                    "exported drift of {} to {}",
+                    &resolved[..12],
+                    output.display()
+                );
+            }
+        }
+        DriftAction::Apply { env_id, bundle } => {
+            let layout = StoreLayout::new(store_path);
+            let _lock = acquire_store_lock(&layout, "drift apply")?;
+            let resolved = resolve(engine, env_id, json)?;
+            let data = std::fs::read(bundle)
+                .map_err(|e| format!("failed to read {}: {e}", bundle.display()))?;
+            let info = engine
+                .apply_drift(&resolved, &data)
+                .map_err(|e| e.to_string())?;
+            let meta = engine.inspect(&resolved).map_err(|e| e.to_string())?;
+            let same_base = info.base_layer == meta.base_layer.as_str();
+            if json {
+                let payload = serde_json::json!({
+                    "env_id": resolved,
+                    "source": info.env_id,
+                    "same_base": same_base,
+                });
+                println!("{}", json_pretty(&payload)?);
+            } else {
+                println!(
+                    "applied drift of {} to {}",
+                    &info.env_id[..12.min(info.env_id.len())],
+                    &resolved[..12]
+                );
+                if !same_base {
+                    eprintln!(
+                        "warning: the bundle was made on another base layer; review with `karapace diff`"
+                    );
+                }
+            }
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn resolve(engine: &Engine, env_id: &str, json: bool) -> Result<String, String> {
+    if json {
+        resolve_env_id(engine, env_id)
+    } else {
+        resolve_env_id_pretty(engine, env_id)
+    }
+}
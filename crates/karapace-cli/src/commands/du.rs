@@ -0,0 +1,46 @@
+use super::{format_size, json_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
+
+pub fn run(engine: &Engine, json: bool) -> Result<u8, String> {
+    let usage = engine.store_usage().map_err(|e| e.to_string())?;
+    if json {
+        let mut payload =
+            serde_json::to_value(&usage).map_err(|e| format!("JSON serialization failed: {e}"))?;
+        payload["total_bytes"] = usage.total_bytes().into();
+        println!("{}", json_pretty(&payload)?);
+        return Ok(EXIT_SUCCESS);
+    }
+
+    println!("{:<10} {:>10}", "CATEGORY", "SIZE");
+    for (category, bytes) in [
+        ("objects", usage.objects_bytes),
+        ("layers", usage.layers_bytes),
+        ("envs", usage.env_bytes),
+        ("images", usage.images_bytes),
+        ("wal", usage.wal_bytes),
+        ("other", usage.other_bytes),
+        ("total", usage.total_bytes()),
+    ] {
+        println!("{category:<10} {:>10}", format_size(bytes));
+    }
+    if usage.envs.is_empty() {
+        return Ok(EXIT_SUCCESS);
+    }
+    println!();
+    println!(
+        "{:<14} {:<16} {:>10} {:>10} {:>10} {:>10}",
+        "ENV", "NAME", "ENV DIR", "EXCLUSIVE", "SHARED", "ATTRIBUTED"
+    );
+    for env in &usage.envs {
+        println!(
+            "{:<14} {:<16} {:>10} {:>10} {:>10} {:>10}",
+            &env.env_id[..12.min(env.env_id.len())],
+            env.name.as_deref().unwrap_or("-"),
+            format_size(env.env_dir_bytes),
+            format_size(env.exclusive_bytes),
+            format_size(env.shared_bytes),
+            format_size(env.attributed_bytes),
+        );
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -1,5 +1,5 @@
-use super::{resolve_env_id_pretty, EXIT_SUCCESS};
-use karapace_core::{Engine, StoreLock};
+use super::{acquire_store_lock, resolve_env_id_pretty, EXIT_FAILURE, EXIT_SUCCESS};
+use karapace_core::{Engine, EnterOptions};
 use karapace_store::StoreLayout;
 use std::path::Path;
 
@@ -8,15 +8,48 @@ pub fn run(
     store_path: &Path,
     env_id: &str,
     command: &[String],
+    strict_gpu: bool,
+    read_only: bool,
 ) -> Result<u8, String> {
     let layout = StoreLayout::new(store_path);
-    let _lock = StoreLock::acquire(&layout.lock_file()).map_err(|e| format!("store lock: {e}"))?;
+    let _lock = acquire_store_lock(&layout, "enter")?;
 
     let resolved = resolve_env_id_pretty(engine, env_id)?;
+    let options = EnterOptions {
+        strict_gpu,
+        read_only,
+        tty: false,
+    };
     if command.is_empty() {
-        engine.enter(&resolved).map_err(|e| e.to_string())?;
+        engine
+            .enter_with_options(&resolved, options)
+            .map_err(|e| e.to_string())?;
+        Ok(EXIT_SUCCESS)
     } else {
-        engine.exec(&resolved, command).map_err(|e| e.to_string())?;
+        let code = engine
+            .exec_with_options(&resolved, command, options)
+            .map_err(|e| e.to_string())?;
+        Ok(u8::try_from(code).unwrap_or(EXIT_FAILURE))
     }
+}
+
+pub fn run_detached(
+    engine: &Engine,
+    store_path: &Path,
+    env_id: &str,
+    strict_gpu: bool,
+) -> Result<u8, String> {
+    let layout = StoreLayout::new(store_path);
+    let _lock = acquire_store_lock(&layout, "enter")?;
+
+    let resolved = resolve_env_id_pretty(engine, env_id)?;
+    let options = EnterOptions {
+        strict_gpu,
+        ..EnterOptions::default()
+    };
+    engine
+        .enter_detached(&resolved, options)
+        .map_err(|e| e.to_string())?;
+    println!("started detached session of {env_id}; join it with 'karapace attach {env_id}'");
     Ok(EXIT_SUCCESS)
 }
@@ -1,5 +1,5 @@
-use super::{resolve_env_id_pretty, EXIT_SUCCESS};
-use karapace_core::{Engine, StoreLock};
+use super::{acquire_store_lock, resolve_env_id_pretty, EXIT_FAILURE};
+use karapace_core::{Engine, EnterOptions};
 use karapace_store::StoreLayout;
 use std::path::Path;
 
@@ -8,12 +8,20 @@ pub fn run(
     store_path: &Path,
     env_id: &str,
     command: &[String],
-    _json: bool,
+    strict_gpu: bool,
+    tty: bool,
 ) -> Result<u8, String> {
     let layout = StoreLayout::new(store_path);
-    let _lock = StoreLock::acquire(&layout.lock_file()).map_err(|e| format!("store lock: {e}"))?;
+    let _lock = acquire_store_lock(&layout, "exec")?;
 
     let resolved = resolve_env_id_pretty(engine, env_id)?;
-    engine.exec(&resolved, command).map_err(|e| e.to_string())?;
-    Ok(EXIT_SUCCESS)
+    let options = EnterOptions {
+        strict_gpu,
+        read_only: false,
+        tty,
+    };
+    let code = engine
+        .exec_with_options(&resolved, command, options)
+        .map_err(|e| e.to_string())?;
+    Ok(u8::try_from(code).unwrap_or(EXIT_FAILURE))
 }
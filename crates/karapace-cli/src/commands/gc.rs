@@ -1,33 +1,150 @@
-use super::{json_pretty, EXIT_SUCCESS};
-use karapace_core::{Engine, StoreLock};
-use karapace_store::StoreLayout;
+use super::{acquire_store_lock, format_size, json_pretty, EXIT_SUCCESS};
+use karapace_core::{expire, Engine};
+use karapace_store::{RetentionPolicy, StoreLayout, DEFAULT_PACK_THRESHOLD};
 use std::path::Path;
 
-pub fn run(engine: &Engine, store_path: &Path, dry_run: bool, json: bool) -> Result<u8, String> {
+pub use karapace_schema::parse_age;
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    dry_run: bool,
+    repack: bool,
+    retention: RetentionPolicy,
+    json: bool,
+) -> Result<u8, String> {
     let layout = StoreLayout::new(store_path);
-    let lock = StoreLock::acquire(&layout.lock_file()).map_err(|e| format!("store lock: {e}"))?;
+    let expired = {
+        let lock = acquire_store_lock(&layout, "gc")?;
+        expire(engine, &lock, dry_run).map_err(|e| e.to_string())?
+    };
 
-    let report = engine.gc(&lock, dry_run).map_err(|e| e.to_string())?;
+    // Marking reads the whole store; builds may run meanwhile. Only the
+    // sweep holds the store lock.
+    let plan = engine.plan_gc(retention).map_err(|e| e.to_string())?;
+    let lock = acquire_store_lock(&layout, "gc")?;
+    let pack_threshold = repack.then_some(DEFAULT_PACK_THRESHOLD);
+    let report = engine
+        .sweep_gc(&lock, plan, dry_run, pack_threshold)
+        .map_err(|e| e.to_string())?;
     if json {
         let payload = serde_json::json!({
             "dry_run": dry_run,
+            "expired_archived": expired.archived,
+            "expired_destroyed": expired.destroyed,
             "orphaned_envs": report.orphaned_envs,
             "orphaned_layers": report.orphaned_layers,
             "orphaned_objects": report.orphaned_objects,
+            "evicted_envs": report.evicted_envs,
+            "evicted_snapshots": report.evicted_snapshots,
+            "store_size_before": report.store_size_before,
+            "store_size_after": report.store_size_after,
             "removed_envs": report.removed_envs,
             "removed_layers": report.removed_layers,
             "removed_objects": report.removed_objects,
+            "packed_objects": report.packed_objects,
         });
         println!("{}", json_pretty(&payload)?);
     } else {
+        if !expired.is_empty() {
+            let (archive, destroy) = if dry_run {
+                ("would archive", "would destroy")
+            } else {
+                ("archived", "destroyed")
+            };
+            println!(
+                "gc: {archive} {} expired envs, {destroy} {}",
+                expired.archived.len(),
+                expired.destroyed.len()
+            );
+        }
         let prefix = if dry_run { "would remove" } else { "removed" };
-        println!(
-            "gc: {prefix} {} envs, {} layers, {} objects",
-            report.removed_envs, report.removed_layers, report.removed_objects
-        );
+        let envs = report.orphaned_envs.len() + report.evicted_envs.len();
+        let (env_count, layer_count, object_count) = if dry_run {
+            (
+                envs,
+                report.orphaned_layers.len(),
+                report.orphaned_objects.len(),
+            )
+        } else {
+            (
+                report.removed_envs,
+                report.removed_layers,
+                report.removed_objects,
+            )
+        };
+        println!("gc: {prefix} {env_count} envs, {layer_count} layers, {object_count} objects");
+        if !report.evicted_envs.is_empty() || !report.evicted_snapshots.is_empty() {
+            println!(
+                "gc: retention evicted {} archived envs, {} snapshots",
+                report.evicted_envs.len(),
+                report.evicted_snapshots.len()
+            );
+        }
+        if let (Some(before), Some(after)) = (report.store_size_before, report.store_size_after) {
+            println!(
+                "gc: store size {} -> {}",
+                format_size(before),
+                format_size(after)
+            );
+        }
+        if report.packed_objects > 0 {
+            println!("gc: packed {} small objects", report.packed_objects);
+        }
         if dry_run && !report.orphaned_envs.is_empty() {
             println!("orphaned envs: {:?}", report.orphaned_envs);
         }
+        if dry_run && !report.evicted_envs.is_empty() {
+            println!("evicted envs: {:?}", report.evicted_envs);
+        }
     }
     Ok(EXIT_SUCCESS)
 }
+
+/// Parse a size such as `20G`, `512M`, `1.5T` or a plain byte count.
+/// Units are binary (`1K` = 1024 bytes); a trailing `B` or `iB` is accepted.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let trimmed = value.trim();
+    let upper = trimmed.to_ascii_uppercase();
+    let number_part = upper
+        .strip_suffix("IB")
+        .or_else(|| upper.strip_suffix('B'))
+        .unwrap_or(&upper);
+    let (number, multiplier) = match number_part.chars().last() {
+        Some('K') => (&number_part[..number_part.len() - 1], 1u64 << 10),
+        Some('M') => (&number_part[..number_part.len() - 1], 1u64 << 20),
+        Some('G') => (&number_part[..number_part.len() - 1], 1u64 << 30),
+        Some('T') => (&number_part[..number_part.len() - 1], 1u64 << 40),
+        _ => (number_part, 1),
+    };
+    let amount: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size '{trimmed}': expected e.g. 20G or 512M"))?;
+    if !amount.is_finite() || amount < 0.0 {
+        return Err(format!("invalid size '{trimmed}'"));
+    }
+    Ok((amount * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("20G").unwrap(), 20 << 30);
+        assert_eq!(parse_size("512MiB").unwrap(), 512 << 20);
+        assert_eq!(parse_size("1.5k").unwrap(), 1536);
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("2TB").unwrap(), 2 << 40);
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("-1G").is_err());
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(3 << 30), "3.0 GiB");
+    }
+}
@@ -0,0 +1,51 @@
+use super::{json_pretty, resolve_env_id, EXIT_SUCCESS};
+use karapace_core::Engine;
+use karapace_store::WalRecord;
+
+pub fn run(engine: &Engine, env_id: Option<&str>, limit: usize, json: bool) -> Result<u8, String> {
+    // A destroyed environment no longer resolves; its id prefix still
+    // matches its records.
+    let env_id =
+        env_id.map(|input| resolve_env_id(engine, input).unwrap_or_else(|_| input.to_owned()));
+    let records = engine
+        .history(env_id.as_deref(), limit)
+        .map_err(|e| e.to_string())?;
+    if json {
+        println!("{}", json_pretty(&records)?);
+    } else if records.is_empty() {
+        println!("no operations recorded");
+    } else {
+        print_table(&records);
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn print_table(records: &[WalRecord]) {
+    println!(
+        "{:<20} {:<12} {:<14} {:>10}  OUTCOME",
+        "STARTED", "OPERATION", "ENVIRONMENT", "DURATION"
+    );
+    for record in records {
+        let started = chrono::DateTime::parse_from_rfc3339(&record.timestamp).map_or_else(
+            |_| record.timestamp.clone(),
+            |at| at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        );
+        let env = &record.env_id[..record.env_id.len().min(12)];
+        println!(
+            "{started:<20} {:<12} {env:<14} {:>10}  {}",
+            record.kind.to_string(),
+            format_duration(record.duration_ms),
+            record.outcome
+        );
+    }
+}
+
+fn format_duration(ms: u64) -> String {
+    if ms < 1000 {
+        format!("{ms}ms")
+    } else if ms < 60_000 {
+        format!("{}.{}s", ms / 1000, ms % 1000 / 100)
+    } else {
+        format!("{}m{:02}s", ms / 60_000, ms % 60_000 / 1000)
+    }
+}
@@ -0,0 +1,140 @@
+use super::{
+    acquire_store_lock, format_size, json_pretty, progress_json, spin_fail, spin_ok, spinner,
+    with_build_progress, EXIT_SUCCESS,
+};
+use clap::Subcommand;
+use karapace_core::Engine;
+use karapace_store::StoreLayout;
+use std::path::Path;
+
+#[derive(Debug, Subcommand)]
+pub enum ImageAction {
+    /// Download a base image into the image cache, for later offline builds.
+    Pull {
+        /// Image name as written in `base.image` (e.g. "debian/bookworm" or a URL).
+        name: String,
+    },
+    /// List the cached base images.
+    List,
+    /// Remove a cached base image.
+    Rm {
+        /// Image name as written in `base.image`, or its cache key.
+        name: String,
+        /// Remove the image even if environments are built on it.
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+}
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    action: &ImageAction,
+    json: bool,
+) -> Result<u8, String> {
+    match action {
+        ImageAction::Pull { name } => pull(engine, store_path, name, json),
+        ImageAction::List => list(engine, json),
+        ImageAction::Rm { name, force } => remove(engine, store_path, name, *force, json),
+    }
+}
+
+fn pull(engine: &Engine, store_path: &Path, name: &str, json: bool) -> Result<u8, String> {
+    let layout = StoreLayout::new(store_path);
+    let _lock = acquire_store_lock(&layout, "image pull")?;
+
+    let pb = if json || progress_json() {
+        None
+    } else {
+        Some(spinner(&format!("pulling {name}...")))
+    };
+    let pulled = with_build_progress(pb.as_ref(), |progress| engine.pull_image(name, progress));
+    let image = match pulled {
+        Ok(image) => {
+            if let Some(ref pb) = pb {
+                spin_ok(pb, "image cached");
+            }
+            image
+        }
+        Err(e) => {
+            if let Some(ref pb) = pb {
+                spin_fail(pb, "pull failed");
+            }
+            return Err(e.to_string());
+        }
+    };
+    if json {
+        println!("{}", json_pretty(&image)?);
+    } else {
+        println!("cached {name} as {}", image.cache_key);
+        println!("digest: {}", image.digest);
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn list(engine: &Engine, json: bool) -> Result<u8, String> {
+    let images = engine.list_images().map_err(|e| e.to_string())?;
+    if json {
+        let payload: Vec<_> = images
+            .iter()
+            .map(|entry| {
+                serde_json::json!({
+                    "cache_key": entry.image.cache_key,
+                    "source": entry.image.source,
+                    "digest": entry.image.digest,
+                    "size_bytes": entry.image.size_bytes,
+                    "used_by": entry.used_by,
+                })
+            })
+            .collect();
+        println!("{}", json_pretty(&payload)?);
+    } else if images.is_empty() {
+        println!("no cached images");
+    } else {
+        println!(
+            "{:<28} {:<14} {:>10} {:>5}  SOURCE",
+            "IMAGE", "DIGEST", "SIZE", "ENVS"
+        );
+        for entry in &images {
+            let digest = &entry.image.digest[..12.min(entry.image.digest.len())];
+            println!(
+                "{:<28} {:<14} {:>10} {:>5}  {}",
+                entry.image.cache_key,
+                digest,
+                format_size(entry.image.size_bytes),
+                entry.used_by.len(),
+                entry.image.source,
+            );
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn remove(
+    engine: &Engine,
+    store_path: &Path,
+    name: &str,
+    force: bool,
+    json: bool,
+) -> Result<u8, String> {
+    let layout = StoreLayout::new(store_path);
+    let _lock = acquire_store_lock(&layout, "image rm")?;
+
+    let image = engine
+        .remove_image(name, force)
+        .map_err(|e| e.to_string())?;
+    if json {
+        let payload = serde_json::json!({
+            "cache_key": image.cache_key,
+            "removed_bytes": image.size_bytes,
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else {
+        println!(
+            "removed {} ({})",
+            image.cache_key,
+            format_size(image.size_bytes)
+        );
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,65 @@
+use super::{
+    acquire_store_lock, json_pretty, print_manifest_warnings, progress_json, spin_fail, spin_ok,
+    spinner, with_build_progress, EXIT_SUCCESS,
+};
+use karapace_core::{Engine, ImportOptions};
+use karapace_store::StoreLayout;
+use std::path::Path;
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    source: &str,
+    output: &Path,
+    options: &ImportOptions,
+    json: bool,
+) -> Result<u8, String> {
+    let layout = StoreLayout::new(store_path);
+    let _lock = acquire_store_lock(&layout, "import")?;
+
+    let pb = if json || progress_json() {
+        None
+    } else {
+        Some(spinner(&format!("importing {source}...")))
+    };
+    let imported = with_build_progress(pb.as_ref(), |progress| {
+        engine.import_oci(source, output, options, progress)
+    });
+    let result = match imported {
+        Ok(r) => {
+            if let Some(ref pb) = pb {
+                spin_ok(pb, "image imported and environment built");
+            }
+            r
+        }
+        Err(e) => {
+            if let Some(ref pb) = pb {
+                spin_fail(pb, "import failed");
+            }
+            return Err(e.to_string());
+        }
+    };
+    if json {
+        let payload = serde_json::json!({
+            "env_id": result.build.identity.env_id,
+            "short_id": result.build.identity.short_id,
+            "image": result.image.reference,
+            "layers": result.image.layers,
+            "cached": result.image.cached,
+            "manifest": result.manifest_path,
+            "status": "built",
+            "warnings": result.build.warnings,
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else {
+        print_manifest_warnings(output, &result.build.warnings);
+        println!(
+            "imported {} ({} layer(s))",
+            result.image.reference, result.image.layers
+        );
+        println!("wrote {}", result.manifest_path.display());
+        println!("built environment {}", result.build.identity.short_id);
+        println!("env_id: {}", result.build.identity.env_id);
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -1,5 +1,8 @@
-use super::{colorize_state, json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use super::{
+    colorize_state, format_size, json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS,
+};
 use karapace_core::Engine;
+use karapace_store::DEFAULT_WORKSPACE;
 
 pub fn run(engine: &Engine, env_id: &str, json: bool) -> Result<u8, String> {
     let resolved = if json {
@@ -8,18 +11,57 @@ pub fn run(engine: &Engine, env_id: &str, json: bool) -> Result<u8, String> {
         resolve_env_id_pretty(engine, env_id)?
     };
     let meta = engine.inspect(&resolved).map_err(|e| e.to_string())?;
+    let usage = engine.usage(&resolved).ok();
     if json {
-        println!("{}", json_pretty(&meta)?);
+        let mut value =
+            serde_json::to_value(&meta).map_err(|e| format!("JSON serialization failed: {e}"))?;
+        if let (Some(usage), Some(object)) = (usage, value.as_object_mut()) {
+            object.insert("disk_usage_bytes".to_owned(), usage.upper_bytes.into());
+            object.insert("disk_limit_mb".to_owned(), usage.limit_mb.into());
+        }
+        println!("{}", json_pretty(&value)?);
     } else {
         println!("env_id:      {}", meta.env_id);
         println!("short_id:    {}", meta.short_id);
         println!("name:        {}", meta.name.as_deref().unwrap_or("(none)"));
+        if !meta.aliases.is_empty() {
+            println!("aliases:     {}", meta.aliases.join(", "));
+        }
         println!("state:       {}", colorize_state(&meta.state.to_string()));
+        if !meta.labels.is_empty() {
+            let labels: Vec<String> = meta
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect();
+            println!("labels:      {}", labels.join(", "));
+        }
+        println!(
+            "workspace:   {}",
+            meta.workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE)
+        );
         println!("base_layer:  {}", meta.base_layer);
         println!("deps:        {}", meta.dependency_layers.len());
         println!("ref_count:   {}", meta.ref_count);
+        if let Some(usage) = usage {
+            let limit = match usage.limit_mb {
+                Some(limit) if usage.exceeded() => format!(" (over its {limit} MB limit)"),
+                Some(limit) => format!(" (limit {limit} MB)"),
+                None => String::new(),
+            };
+            println!("disk:        {}{limit}", format_size(usage.upper_bytes));
+        }
         println!("created_at:  {}", meta.created_at);
         println!("updated_at:  {}", meta.updated_at);
+        println!(
+            "built_at:    {}",
+            meta.last_built_at.as_deref().unwrap_or("never")
+        );
+        println!(
+            "entered_at:  {} ({} sessions)",
+            meta.last_entered_at.as_deref().unwrap_or("never"),
+            meta.enter_count
+        );
     }
     Ok(EXIT_SUCCESS)
 }
@@ -0,0 +1,49 @@
+use super::{acquire_store_lock, json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
+use karapace_schema::parse_label;
+use karapace_store::StoreLayout;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Set `key=value` labels and remove `key-` ones, then print the labels.
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    env_id: &str,
+    changes: &[String],
+    json: bool,
+) -> Result<u8, String> {
+    let mut set = BTreeMap::new();
+    let mut remove = Vec::new();
+    for change in changes {
+        match change.strip_suffix('-') {
+            Some(key) if !change.contains('=') => remove.push(key.to_owned()),
+            _ => {
+                let (key, value) = parse_label(change)?;
+                set.insert(key, value);
+            }
+        }
+    }
+
+    let layout = StoreLayout::new(store_path);
+    let _lock = acquire_store_lock(&layout, "label")?;
+    let resolved = if json {
+        resolve_env_id(engine, env_id)?
+    } else {
+        resolve_env_id_pretty(engine, env_id)?
+    };
+    let labels = engine
+        .set_labels(&resolved, &set, &remove)
+        .map_err(|e| e.to_string())?;
+
+    if json {
+        println!("{}", json_pretty(&labels)?);
+    } else if labels.is_empty() {
+        println!("{} has no labels", &resolved[..12]);
+    } else {
+        for (key, value) in &labels {
+            println!("{key}={value}");
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
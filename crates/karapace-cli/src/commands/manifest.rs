@@ -0,0 +1,59 @@
+use super::{json_pretty, pin::write_atomic, EXIT_SUCCESS};
+use clap::Subcommand;
+use karapace_schema::{parse_manifest_file, upgrade_manifest, CURRENT_MANIFEST_VERSION};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Subcommand)]
+pub enum ManifestAction {
+    /// Rewrite a manifest to the current manifest_version, keeping its meaning.
+    Upgrade {
+        /// Path to manifest TOML file.
+        #[arg(default_value = "karapace.toml")]
+        manifest: PathBuf,
+    },
+}
+
+pub fn run(action: &ManifestAction, json: bool) -> Result<u8, String> {
+    match action {
+        ManifestAction::Upgrade { manifest } => upgrade(manifest, json),
+    }
+}
+
+fn upgrade(manifest_path: &Path, json: bool) -> Result<u8, String> {
+    // Refuse to rewrite a manifest that does not parse as it is.
+    parse_manifest_file(manifest_path).map_err(|e| format!("failed to parse manifest: {e}"))?;
+
+    // Upgrade the file's own table; manifests it extends or includes are
+    // upgraded on their own.
+    let content = std::fs::read_to_string(manifest_path)
+        .map_err(|e| format!("failed to read {}: {e}", manifest_path.display()))?;
+    let mut doc: toml::Table =
+        toml::from_str(&content).map_err(|e| format!("failed to parse manifest: {e}"))?;
+    let upgraded =
+        upgrade_manifest(&mut doc).map_err(|e| format!("failed to upgrade manifest: {e}"))?;
+    if upgraded {
+        let toml =
+            toml::to_string_pretty(&doc).map_err(|e| format!("TOML serialization failed: {e}"))?;
+        write_atomic(manifest_path, &toml)?;
+    }
+
+    if json {
+        let payload = serde_json::json!({
+            "status": if upgraded { "upgraded" } else { "current" },
+            "manifest": manifest_path,
+            "manifest_version": CURRENT_MANIFEST_VERSION,
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else if upgraded {
+        println!(
+            "upgraded {} to manifest_version {CURRENT_MANIFEST_VERSION}",
+            manifest_path.display()
+        );
+    } else {
+        println!(
+            "{} is already at manifest_version {CURRENT_MANIFEST_VERSION}",
+            manifest_path.display()
+        );
+    }
+    Ok(EXIT_SUCCESS)
+}
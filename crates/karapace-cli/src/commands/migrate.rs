@@ -1,7 +1,15 @@
-use super::{EXIT_FAILURE, EXIT_SUCCESS};
+use super::{acquire_store_lock, EXIT_FAILURE, EXIT_SUCCESS};
+use karapace_store::{MetadataBackend, StoreLayout};
 use std::path::Path;
 
-pub fn run(store_path: &Path, json_output: bool) -> Result<u8, String> {
+pub fn run(
+    store_path: &Path,
+    metadata_backend: Option<&str>,
+    json_output: bool,
+) -> Result<u8, String> {
+    if let Some(backend) = metadata_backend {
+        return move_metadata(store_path, backend.parse()?, json_output);
+    }
     let store_dir = store_path.join("store");
     if !store_dir.exists() {
         msg(
@@ -55,17 +63,19 @@ pub fn run(store_path: &Path, json_output: bool) -> Result<u8, String> {
             msg(
                 json_output,
                 &format!(
-                    r#"{{"status": "migrated", "from": {}, "to": {}, "environments": {}, "backup": "{}"}}"#,
+                    r#"{{"status": "migrated", "from": {}, "to": {}, "environments": {}, "objects_compressed": {}, "backup": "{}"}}"#,
                     result.from_version,
                     result.to_version,
                     result.environments_migrated,
+                    result.objects_compressed,
                     result.backup_path.display()
                 ),
                 &format!(
-                    "Migrated store from v{} to v{}.\n{} environments updated.\nBackup: {}",
+                    "Migrated store from v{} to v{}.\n{} environments updated.\n{} objects compressed.\nBackup: {}",
                     result.from_version,
                     result.to_version,
                     result.environments_migrated,
+                    result.objects_compressed,
                     result.backup_path.display()
                 ),
             );
@@ -91,6 +101,29 @@ pub fn run(store_path: &Path, json_output: bool) -> Result<u8, String> {
     }
 }
 
+/// Move the metadata of every environment to `backend`.
+fn move_metadata(
+    store_path: &Path,
+    backend: MetadataBackend,
+    json_output: bool,
+) -> Result<u8, String> {
+    let layout = StoreLayout::new(store_path);
+    if let Err(e) = layout.verify_version() {
+        return Err(format!("{e}; run 'karapace migrate' first"));
+    }
+    let _lock = acquire_store_lock(&layout, "migrate")?;
+    let moved =
+        karapace_store::migrate_metadata_backend(store_path, backend).map_err(|e| e.to_string())?;
+    msg(
+        json_output,
+        &format!(
+            r#"{{"status": "moved", "metadata_backend": "{backend}", "environments": {moved}}}"#
+        ),
+        &format!("Metadata of {moved} environments is now kept in the {backend} backend."),
+    );
+    Ok(EXIT_SUCCESS)
+}
+
 fn msg(json_output: bool, json: &str, human: &str) {
     if json_output {
         println!("{json}");
@@ -1,32 +1,61 @@
 pub mod archive;
+pub mod attach;
+pub mod attest;
+pub mod audit;
+pub mod autosnap;
 pub mod build;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod check;
+pub mod clone;
 pub mod commit;
 pub mod completions;
+pub mod desktop_export;
 pub mod destroy;
 pub mod diff;
 pub mod doctor;
+pub mod drift;
+pub mod du;
 pub mod enter;
 pub mod exec;
 pub mod freeze;
 pub mod gc;
+pub mod history;
+pub mod image;
+pub mod import;
 pub mod inspect;
+pub mod label;
 pub mod list;
 pub mod man_pages;
+pub mod manifest;
 pub mod migrate;
 pub mod new;
 pub mod pin;
+pub mod prune;
+pub mod ps;
 pub mod pull;
 pub mod push;
 pub mod rebuild;
+pub mod remote;
 pub mod rename;
+pub mod repack;
 pub mod restore;
+pub mod shellenv;
 pub mod snapshots;
+pub mod stats;
 pub mod stop;
+pub mod sync;
 pub mod tui;
 pub mod verify_store;
+pub mod workspace;
 
 use indicatif::{ProgressBar, ProgressStyle};
-use karapace_core::Engine;
+use karapace_core::{Engine, ProjectBinding, StoreLock};
+use karapace_runtime::{BuildPhase, ProgressSink, StderrProgress};
+use karapace_store::StoreLayout;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
 use std::time::Duration;
 
 pub const EXIT_SUCCESS: u8 = 0;
@@ -34,10 +63,156 @@ pub const EXIT_FAILURE: u8 = 1;
 pub const EXIT_MANIFEST_ERROR: u8 = 2;
 pub const EXIT_STORE_ERROR: u8 = 3;
 
+/// How long commands wait for a busy store lock (`None` waits forever).
+static LOCK_WAIT: OnceLock<Option<Duration>> = OnceLock::new();
+
+pub fn set_lock_wait(wait: Option<Duration>) {
+    let _ = LOCK_WAIT.set(wait);
+}
+
+/// How operations report progress on stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Spinners and status lines for humans.
+    Auto,
+    /// One JSON event per line, for tools that render their own progress.
+    Json,
+}
+
+/// Parse a `--progress` value.
+pub fn parse_progress_mode(s: &str) -> Result<ProgressMode, String> {
+    match s {
+        "auto" => Ok(ProgressMode::Auto),
+        "json" => Ok(ProgressMode::Json),
+        other => Err(format!(
+            "unknown progress mode '{other}' (expected: auto, json)"
+        )),
+    }
+}
+
+static PROGRESS_MODE: OnceLock<ProgressMode> = OnceLock::new();
+
+pub fn set_progress_mode(mode: ProgressMode) {
+    let _ = PROGRESS_MODE.set(mode);
+}
+
+/// Whether progress goes to stderr as JSON events instead of spinners.
+pub fn progress_json() -> bool {
+    PROGRESS_MODE.get() == Some(&ProgressMode::Json)
+}
+
+/// Write one progress event to stderr as a line of JSON.
+fn emit_progress_event(event: &serde_json::Value) {
+    eprintln!("{event}");
+}
+
+/// Tracing layer of JSON progress mode: every log line goes out as a `log`
+/// event instead of plain text.
+pub struct JsonLogLayer;
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for JsonLogLayer {
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut message = LogMessage::default();
+        event.record(&mut message);
+        emit_progress_event(&serde_json::json!({
+            "event": "log",
+            "level": event.metadata().level().as_str().to_ascii_lowercase(),
+            "message": message.0,
+        }));
+    }
+}
+
+/// The message of a tracing event, followed by its other fields as
+/// `name=value`.
+#[derive(Default)]
+struct LogMessage(String);
+
+impl tracing::field::Visit for LogMessage {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        use std::fmt::Write;
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, "{}={value:?}", field.name());
+        }
+    }
+}
+
+/// Print a warning to stderr, or emit it as a `warning` event in JSON
+/// progress mode.
+pub fn warn(message: &str) {
+    if progress_json() {
+        emit_progress_event(&serde_json::json!({ "event": "warning", "message": message }));
+    } else {
+        eprintln!("warning: {message}");
+    }
+}
+
+/// Acquire the store lock for `op`, honoring `--lock-wait` and telling the
+/// user which operation they are waiting on.
+pub fn acquire_store_lock(layout: &StoreLayout, op: &str) -> Result<StoreLock, String> {
+    let wait = LOCK_WAIT.get().copied().flatten();
+    StoreLock::acquire_with_timeout(&layout.lock_file(), op, wait, |holder| {
+        if progress_json() {
+            emit_progress_event(&serde_json::json!({
+                "event": "lock_wait",
+                "op": holder.map(|h| &h.op),
+                "pid": holder.map(|h| h.pid),
+            }));
+            return;
+        }
+        match holder {
+            Some(h) => eprintln!("waiting for {} (pid {}) to finish…", h.op, h.pid),
+            None => eprintln!("waiting for the store lock…"),
+        }
+    })
+    .map_err(|e| format!("store lock: {e}"))
+}
+
 pub fn json_pretty(value: &impl serde::Serialize) -> Result<String, String> {
     serde_json::to_string_pretty(value).map_err(|e| format!("JSON serialization failed: {e}"))
 }
 
+/// `bytes` in binary units, e.g. `3.0 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// How long ago the RFC 3339 `timestamp` was, e.g. `3d ago`, or `never`.
+pub fn format_ago(timestamp: Option<&str>) -> String {
+    let Some(then) = timestamp.and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok()) else {
+        return "never".to_owned();
+    };
+    let secs = chrono::Utc::now()
+        .signed_duration_since(then)
+        .num_seconds()
+        .max(0);
+    match secs {
+        0..60 => "just now".to_owned(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86_400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
+
 pub fn spinner(msg: &str) -> ProgressBar {
     let pb = ProgressBar::new_spinner();
     let style = ProgressStyle::with_template("{spinner:.cyan} {msg}")
@@ -49,6 +224,170 @@ pub fn spinner(msg: &str) -> ProgressBar {
     pb
 }
 
+/// Push/pull progress: a spinner that turns into an object-count bar once
+/// the first object is reported, or in JSON progress mode `phase` and
+/// `object` events, then a `total` event.
+pub struct TransferProgress {
+    /// `"pushing"` or `"pulling"`.
+    verb: &'static str,
+    pb: Option<ProgressBar>,
+    /// Bytes transferred so far.
+    bytes: AtomicU64,
+}
+
+impl TransferProgress {
+    /// Start with a spinner, unless progress goes out as JSON.
+    pub fn new(verb: &'static str) -> Self {
+        Self {
+            verb,
+            pb: (!progress_json()).then(|| spinner(&format!("{verb} environment…"))),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn spinner(&self) -> Option<&ProgressBar> {
+        self.pb.as_ref()
+    }
+
+    pub fn phase(&self, phase: karapace_remote::TransferPhase) {
+        if let Some(pb) = &self.pb {
+            pb.set_message(format!("{} {}…", self.verb, phase.name()));
+        } else if progress_json() {
+            emit_progress_event(&serde_json::json!({ "event": "phase", "phase": phase.name() }));
+        }
+    }
+
+    pub fn object(&self, progress: &karapace_remote::ObjectProgress<'_>) {
+        self.bytes.fetch_add(progress.bytes, Ordering::Relaxed);
+        let Some(pb) = &self.pb else {
+            if progress_json() {
+                emit_progress_event(&serde_json::json!({
+                    "event": "object",
+                    "hash": progress.hash,
+                    "bytes": progress.bytes,
+                    "skipped": progress.skipped,
+                    "done": progress.done,
+                    "total": progress.total,
+                }));
+            }
+            return;
+        };
+        if pb.length().is_none() {
+            let style = ProgressStyle::with_template(
+                "{spinner:.cyan} {msg} [{bar:30.cyan/blue}] {pos}/{len} objects",
+            )
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> ");
+            pb.set_style(style);
+            pb.set_length(progress.total as u64);
+        }
+        pb.set_position(progress.done as u64);
+    }
+
+    /// Emit the `total` event of a finished transfer of `objects` objects
+    /// and `layers` layers, skipped ones excluded.
+    pub fn total(&self, objects: usize, layers: usize) {
+        if self.pb.is_none() && progress_json() {
+            emit_progress_event(&serde_json::json!({
+                "event": "total",
+                "objects": objects,
+                "layers": layers,
+                "bytes": self.bytes.load(Ordering::Relaxed),
+            }));
+        }
+    }
+}
+
+/// Build progress on a spinner: the current phase, then the latest status
+/// line reported during it.
+pub struct SpinnerProgress<'a>(pub &'a ProgressBar);
+
+impl ProgressSink for SpinnerProgress<'_> {
+    fn phase(&self, phase: BuildPhase) {
+        self.0.set_prefix(phase.label());
+        self.0.set_message(format!("{}…", phase.label()));
+    }
+
+    fn message(&self, message: &str) {
+        let phase = self.0.prefix();
+        if phase.is_empty() {
+            self.0.set_message(message.to_owned());
+        } else {
+            self.0.set_message(format!("{phase}: {message}"));
+        }
+    }
+
+    fn download(&self, done: u64, total: Option<u64>) {
+        let size = match total {
+            Some(total) => format!("{} / {}", format_size(done), format_size(total)),
+            None => format_size(done),
+        };
+        self.message(&format!("downloaded {size}"));
+    }
+}
+
+/// Build progress as JSON events: `phase` when one starts, and `message`
+/// and `download` events tagged with the current phase.
+#[derive(Default)]
+pub struct JsonProgress(Mutex<Option<BuildPhase>>);
+
+impl JsonProgress {
+    fn current_phase(&self) -> Option<BuildPhase> {
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl ProgressSink for JsonProgress {
+    fn phase(&self, phase: BuildPhase) {
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(phase);
+        emit_progress_event(&serde_json::json!({ "event": "phase", "phase": phase }));
+    }
+
+    fn message(&self, message: &str) {
+        emit_progress_event(&serde_json::json!({
+            "event": "message",
+            "phase": self.current_phase(),
+            "message": message,
+        }));
+    }
+
+    fn download(&self, done: u64, total: Option<u64>) {
+        emit_progress_event(&serde_json::json!({
+            "event": "download",
+            "phase": self.current_phase(),
+            "bytes": done,
+            "total": total,
+        }));
+    }
+}
+
+/// Run `f` with build progress going to `pb`, or without one to stderr, as
+/// JSON events in JSON progress mode.
+pub fn with_build_progress<T>(
+    pb: Option<&ProgressBar>,
+    f: impl FnOnce(&dyn ProgressSink) -> T,
+) -> T {
+    match pb {
+        Some(pb) => f(&SpinnerProgress(pb)),
+        None if progress_json() => f(&JsonProgress::default()),
+        None => f(&StderrProgress),
+    }
+}
+
+/// Print the deprecated keys found in `manifest` to stderr, as `warning`
+/// events in JSON progress mode.
+pub fn print_manifest_warnings(manifest: &Path, warnings: &[karapace_schema::DeprecationWarning]) {
+    for w in warnings {
+        warn(&format!("{}: {w}", manifest.display()));
+    }
+}
+
 pub fn spin_ok(pb: &ProgressBar, msg: &str) {
     if let Ok(style) = ProgressStyle::with_template("{msg}") {
         pb.set_style(style);
@@ -80,14 +419,11 @@ pub fn resolve_env_id(engine: &Engine, input: &str) -> Result<String, String> {
         return Ok(input.to_owned());
     }
 
-    let envs = engine.list().map_err(|e| e.to_string())?;
-
-    for e in &envs {
-        if *e.env_id == *input || *e.short_id == *input || e.name.as_deref() == Some(input) {
-            return Ok(e.env_id.to_string());
-        }
+    if let Some(env_id) = resolve_exact(engine, input)? {
+        return Ok(env_id);
     }
 
+    let envs = engine.list().map_err(|e| e.to_string())?;
     let matches: Vec<_> = envs
         .iter()
         .filter(|e| e.env_id.starts_with(input) || e.short_id.starts_with(input))
@@ -102,6 +438,24 @@ pub fn resolve_env_id(engine: &Engine, input: &str) -> Result<String, String> {
     }
 }
 
+/// Match `input` exactly against env ids, short ids and names, then former
+/// names, warning that an alias is deprecated. Reads only the metadata index
+/// and the matching entry.
+fn resolve_exact(engine: &Engine, input: &str) -> Result<Option<String>, String> {
+    if let Some(meta) = engine.find(input).map_err(|e| e.to_string())? {
+        return Ok(Some(meta.env_id.to_string()));
+    }
+    let Some(meta) = engine.find_by_alias(input).map_err(|e| e.to_string())? else {
+        return Ok(None);
+    };
+    warn(&format!(
+        "'{input}' is a former name of '{}' ({}); refer to it by its current name",
+        meta.name.as_deref().unwrap_or("(none)"),
+        meta.short_id
+    ));
+    Ok(Some(meta.env_id.to_string()))
+}
+
 fn format_env_suggestion(meta: &karapace_store::EnvMetadata) -> String {
     let label = meta
         .name
@@ -115,13 +469,11 @@ pub fn resolve_env_id_pretty(engine: &Engine, input: &str) -> Result<String, Str
         return Ok(input.to_owned());
     }
 
-    let envs = engine.list().map_err(|e| e.to_string())?;
-    for e in &envs {
-        if *e.env_id == *input || *e.short_id == *input || e.name.as_deref() == Some(input) {
-            return Ok(e.env_id.to_string());
-        }
+    if let Some(env_id) = resolve_exact(engine, input)? {
+        return Ok(env_id);
     }
 
+    let envs = engine.list().map_err(|e| e.to_string())?;
     let prefix_matches: Vec<_> = envs
         .iter()
         .filter(|e| e.env_id.starts_with(input) || e.short_id.starts_with(input))
@@ -171,16 +523,104 @@ pub fn resolve_env_id_pretty(engine: &Engine, input: &str) -> Result<String, Str
     }
 }
 
-pub fn make_remote_backend(
-    remote_url: Option<&str>,
-) -> Result<karapace_remote::http::HttpBackend, String> {
+/// `env_id` as given, or else the environment of the project binding found
+/// from the current directory.
+pub fn env_or_project(engine: &Engine, env_id: Option<String>) -> Result<String, String> {
+    if let Some(env_id) = env_id {
+        return Ok(env_id);
+    }
+    let cwd = std::env::current_dir().map_err(|e| format!("current directory: {e}"))?;
+    let Some((project_dir, binding)) = ProjectBinding::find(&cwd) else {
+        return Err(
+            "no environment given and no project binding found; build the project's karapace.toml or name an environment"
+                .to_owned(),
+        );
+    };
+    if engine.inspect(&binding.env_id).is_ok() {
+        return Ok(binding.env_id);
+    }
+    // The recorded environment may have been rebuilt since under its name.
+    if let Some(env_id) = binding
+        .name
+        .as_deref()
+        .and_then(|name| resolve_env_id(engine, name).ok())
+    {
+        return Ok(env_id);
+    }
+    Err(format!(
+        "environment {} bound to {} is not in store {}; rebuild the project",
+        &binding.env_id[..12.min(binding.env_id.len())],
+        project_dir.display(),
+        binding.store.display()
+    ))
+}
+
+/// Record `env_id` as the environment of the project of `manifest`. A
+/// binding that cannot be written only warns: the build itself succeeded.
+pub fn bind_project(engine: &Engine, store_path: &Path, manifest: &Path, env_id: &str) {
+    let project_dir = match manifest.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let binding = std::path::absolute(store_path).map(|store| ProjectBinding {
+        env_id: env_id.to_owned(),
+        name: engine.inspect(env_id).ok().and_then(|meta| meta.name),
+        store,
+    });
+    if let Err(e) = binding
+        .map_err(karapace_core::CoreError::from)
+        .and_then(|binding| binding.save(project_dir))
+    {
+        warn(&format!("failed to record the project binding: {e}"));
+    }
+}
+
+/// The remote `--remote` names, otherwise the one in the remote config.
+/// `$KARAPACE_REMOTE_TOKEN` overrides the config's auth token.
+pub fn remote_config(remote_url: Option<&str>) -> Result<karapace_remote::RemoteConfig, String> {
     let config = if let Some(url) = remote_url {
         karapace_remote::RemoteConfig::new(url)
     } else {
         karapace_remote::RemoteConfig::load_default()
             .map_err(|e| format!("no --remote and no config: {e}"))?
     };
-    Ok(karapace_remote::http::HttpBackend::new(config))
+    Ok(match std::env::var("KARAPACE_REMOTE_TOKEN") {
+        Ok(token) if !token.is_empty() => config.with_token(&token),
+        _ => config,
+    })
+}
+
+pub fn make_remote_backend(
+    config: &karapace_remote::RemoteConfig,
+) -> Result<Box<dyn karapace_remote::RemoteBackend>, String> {
+    karapace_remote::open_backend(config.clone()).map_err(|e| e.to_string())
+}
+
+/// The store's cache of the registry of the remote `config` describes.
+pub fn registry_cache(
+    layout: &StoreLayout,
+    config: &karapace_remote::RemoteConfig,
+) -> karapace_remote::RegistryCache {
+    karapace_remote::RegistryCache::new(&layout.registry_cache_dir(), &config.location())
+}
+
+/// age cipher from the remote config (unless `--remote` overrides it),
+/// extended by command-line recipients and identity.
+pub fn make_remote_cipher(
+    remote_url: Option<&str>,
+    recipients: &[String],
+    identity: Option<&Path>,
+) -> Option<karapace_remote::AgeCipher> {
+    let mut config = match remote_url {
+        Some(url) => karapace_remote::RemoteConfig::new(url),
+        None => karapace_remote::RemoteConfig::load_default()
+            .unwrap_or_else(|_| karapace_remote::RemoteConfig::new("")),
+    }
+    .with_age_recipients(recipients);
+    if let Some(id) = identity {
+        config = config.with_age_identity(id);
+    }
+    karapace_remote::AgeCipher::from_config(&config)
 }
 
 #[cfg(test)]
@@ -202,6 +642,19 @@ mod tests {
         assert!(result.contains('1'));
     }
 
+    #[test]
+    fn format_ago_rounds_down_to_the_largest_unit() {
+        let ago = |secs: i64| {
+            let then = chrono::Utc::now() - chrono::Duration::seconds(secs);
+            format_ago(Some(&then.to_rfc3339()))
+        };
+        assert_eq!(format_ago(None), "never");
+        assert_eq!(ago(5), "just now");
+        assert_eq!(ago(150), "2m ago");
+        assert_eq!(ago(7300), "2h ago");
+        assert_eq!(ago(3 * 86_400 + 60), "3d ago");
+    }
+
     #[test]
     fn colorize_state_built() {
         let result = colorize_state("built");
@@ -249,9 +702,7 @@ mod tests {
     fn resolve_env_id_not_found() {
         let dir = tempfile::tempdir().unwrap();
         let engine = Engine::new(dir.path());
-        karapace_store::StoreLayout::new(dir.path())
-            .initialize()
-            .unwrap();
+        StoreLayout::new(dir.path()).initialize().unwrap();
         let result = resolve_env_id(&engine, "nonexistent");
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("no environment matching"));
@@ -266,8 +717,8 @@ mod tests {
 
     #[test]
     fn make_remote_backend_with_url() {
-        let backend = make_remote_backend(Some("http://localhost:8080"));
-        assert!(backend.is_ok());
+        let config = remote_config(Some("http://localhost:8080")).unwrap();
+        assert!(make_remote_backend(&config).is_ok());
     }
 
     #[test]
@@ -1,14 +1,18 @@
 use super::{json_pretty, EXIT_SUCCESS};
 use dialoguer::{Confirm, Input, Select};
+use karapace_runtime::shellenv;
 use karapace_schema::manifest::{
-    parse_manifest_str, BaseSection, GuiSection, HardwareSection, ManifestV1, MountsSection,
-    RuntimeSection, SystemSection,
+    parse_manifest_str, BaseSection, BuildSection, GuiSection, HardwareSection, HooksSection,
+    ManifestV1, MountsSection, NetworkSection, RuntimeSection, SystemSection, ToolchainSection,
 };
+use karapace_schema::CURRENT_MANIFEST_VERSION;
+use std::collections::BTreeMap;
 use std::io::{stderr, stdin, IsTerminal};
 use std::path::{Path, PathBuf};
 use tempfile::NamedTempFile;
 
 const DEST_MANIFEST: &str = "karapace.toml";
+const DEST_ENVRC: &str = ".envrc";
 
 fn template_source(name: &str) -> Option<&'static str> {
     match name {
@@ -48,32 +52,33 @@ fn ensure_can_write(dest: &Path, force: bool, is_tty: bool) -> Result<(), String
     if !dest.exists() || force {
         return Ok(());
     }
+    let refusal = format!(
+        "refusing to overwrite existing ./{} (pass --force)",
+        dest.display()
+    );
     if !is_tty {
-        return Err(format!(
-            "refusing to overwrite existing ./{DEST_MANIFEST} (pass --force)"
-        ));
+        return Err(refusal);
     }
     let overwrite = Confirm::new()
-        .with_prompt(format!("overwrite ./{DEST_MANIFEST}?"))
+        .with_prompt(format!("overwrite ./{}?", dest.display()))
         .default(false)
         .interact()
         .map_err(|e| format!("prompt failed: {e}"))?;
     if overwrite {
         Ok(())
     } else {
-        Err(format!(
-            "refusing to overwrite existing ./{DEST_MANIFEST} (pass --force)"
-        ))
+        Err(refusal)
     }
 }
 
-fn print_result(name: &str, template: Option<&str>, json: bool) -> Result<(), String> {
+fn print_result(name: &str, template: Option<&str>, envrc: bool, json: bool) -> Result<(), String> {
     if json {
         let payload = serde_json::json!({
             "status": "written",
             "path": format!("./{DEST_MANIFEST}"),
             "name": name,
             "template": template,
+            "envrc": envrc.then(|| format!("./{DEST_ENVRC}")),
         });
         println!("{}", json_pretty(&payload)?);
     } else {
@@ -81,13 +86,25 @@ fn print_result(name: &str, template: Option<&str>, json: bool) -> Result<(), St
         if let Some(tpl) = template {
             println!("template: {tpl}");
         }
+        if envrc {
+            println!("wrote ./{DEST_ENVRC}; run `direnv allow` after building");
+        }
     }
     Ok(())
 }
 
-pub fn run(name: &str, template: Option<&str>, force: bool, json: bool) -> Result<u8, String> {
+pub fn run(
+    name: &str,
+    template: Option<&str>,
+    force: bool,
+    envrc: bool,
+    json: bool,
+) -> Result<u8, String> {
     let dest = Path::new(DEST_MANIFEST);
     let is_tty = stdin().is_terminal() && stderr().is_terminal();
+    if envrc {
+        ensure_can_write(Path::new(DEST_ENVRC), force, is_tty)?;
+    }
 
     let mut manifest = if let Some(tpl) = template {
         let m = load_template(tpl)?;
@@ -104,13 +121,23 @@ pub fn run(name: &str, template: Option<&str>, force: bool, json: bool) -> Resul
             .interact_text()
             .map_err(|e| format!("prompt failed: {e}"))?;
         ManifestV1 {
-            manifest_version: 1,
+            manifest_version: CURRENT_MANIFEST_VERSION,
+            extends: None,
+            include: Vec::new(),
             base: BaseSection { image },
             system: SystemSection::default(),
             gui: GuiSection::default(),
             hardware: HardwareSection::default(),
             mounts: MountsSection::default(),
             runtime: RuntimeSection::default(),
+            network: NetworkSection::default(),
+            hooks: HooksSection::default(),
+            build: BuildSection::default(),
+            python: ToolchainSection::default(),
+            node: ToolchainSection::default(),
+            rust: ToolchainSection::default(),
+            labels: BTreeMap::new(),
+            variables: BTreeMap::new(),
         }
     };
     if is_tty {
@@ -137,7 +164,7 @@ pub fn run(name: &str, template: Option<&str>, force: bool, json: bool) -> Resul
                 .entries
                 .insert("workspace".to_owned(), mount);
         }
-        let backends = ["namespace", "oci", "mock"];
+        let backends = ["namespace", "oci", "podman", "mock"];
         let default_idx = backends
             .iter()
             .position(|b| *b == manifest.runtime.backend.as_str())
@@ -162,10 +189,26 @@ pub fn run(name: &str, template: Option<&str>, force: bool, json: bool) -> Resul
     let toml =
         toml::to_string_pretty(&manifest).map_err(|e| format!("TOML serialization failed: {e}"))?;
     write_atomic(dest, &toml)?;
-    print_result(name, template, json)?;
+    if envrc {
+        write_atomic(Path::new(DEST_ENVRC), &envrc_for(name, &manifest))?;
+    }
+    print_result(name, template, envrc, json)?;
     Ok(EXIT_SUCCESS)
 }
 
+/// An `.envrc` routing the manifest's packages that are also command names,
+/// such as `git` or `make`, into the environment.
+fn envrc_for(name: &str, manifest: &ManifestV1) -> String {
+    let commands: Vec<String> = manifest
+        .system
+        .packages
+        .iter()
+        .filter(|p| shellenv::is_command_name(p))
+        .cloned()
+        .collect();
+    shellenv::envrc(name, &commands)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,8 +217,16 @@ mod tests {
     fn templates_parse() {
         for tpl in ["minimal", "dev", "gui-dev", "rust-dev", "ubuntu-dev"] {
             let m = load_template(tpl).unwrap();
-            assert_eq!(m.manifest_version, 1);
+            assert_eq!(m.manifest_version, CURRENT_MANIFEST_VERSION);
             assert!(!m.base.image.is_empty());
         }
     }
+
+    #[test]
+    fn envrc_routes_package_commands() {
+        let mut manifest = load_template("minimal").unwrap();
+        manifest.system.packages = vec!["git".to_owned(), "python3".to_owned()];
+        let envrc = envrc_for("web", &manifest);
+        assert!(envrc.ends_with("eval \"$(karapace shellenv 'web' 'git' 'python3')\"\n"));
+    }
 }
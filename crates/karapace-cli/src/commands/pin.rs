@@ -1,10 +1,10 @@
 use super::{json_pretty, EXIT_SUCCESS};
-use karapace_runtime::image::resolve_pinned_image_url;
-use karapace_schema::manifest::{parse_manifest_file, ManifestV1};
+use karapace_runtime::image::{is_pinned_image, resolve_pinned_image_url};
+use karapace_schema::manifest::parse_manifest_file;
 use std::path::{Path, PathBuf};
 use tempfile::NamedTempFile;
 
-fn write_atomic(dest: &Path, content: &str) -> Result<(), String> {
+pub(super) fn write_atomic(dest: &Path, content: &str) -> Result<(), String> {
     let dir = dest
         .parent()
         .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
@@ -20,11 +20,6 @@ fn write_atomic(dest: &Path, content: &str) -> Result<(), String> {
     Ok(())
 }
 
-fn is_pinned(image: &str) -> bool {
-    let s = image.trim();
-    s.starts_with("http://") || s.starts_with("https://")
-}
-
 pub fn run(
     manifest_path: &Path,
     check: bool,
@@ -36,7 +31,7 @@ pub fn run(
         parse_manifest_file(manifest_path).map_err(|e| format!("failed to parse manifest: {e}"))?;
 
     if check {
-        if is_pinned(&manifest.base.image) {
+        if is_pinned_image(&manifest.base.image) {
             if json {
                 let payload = serde_json::json!({
                     "status": "pinned",
@@ -55,11 +50,26 @@ pub fn run(
     let pinned = resolve_pinned_image_url(&manifest.base.image)
         .map_err(|e| format!("failed to resolve pinned image URL: {e}"))?;
 
-    let mut updated: ManifestV1 = manifest;
-    updated.base.image = pinned;
+    // Edit the file's own table, so what it extends, includes and expands
+    // is written back as it was.
+    let content = std::fs::read_to_string(manifest_path)
+        .map_err(|e| format!("failed to read {}: {e}", manifest_path.display()))?;
+    let mut doc: toml::Table =
+        toml::from_str(&content).map_err(|e| format!("failed to parse manifest: {e}"))?;
+    let Some(base) = doc
+        .get_mut("base")
+        .and_then(toml::Value::as_table_mut)
+        .filter(|base| base.contains_key("image"))
+    else {
+        return Err(format!(
+            "base.image is not set in {}; pin the manifest it extends or includes",
+            manifest_path.display()
+        ));
+    };
+    base.insert("image".to_owned(), toml::Value::String(pinned.clone()));
 
     let toml =
-        toml::to_string_pretty(&updated).map_err(|e| format!("TOML serialization failed: {e}"))?;
+        toml::to_string_pretty(&doc).map_err(|e| format!("TOML serialization failed: {e}"))?;
     write_atomic(manifest_path, &toml)?;
 
     if write_lock {
@@ -72,7 +82,7 @@ pub fn run(
         let payload = serde_json::json!({
             "status": "pinned",
             "manifest": manifest_path,
-            "base_image": updated.base.image,
+            "base_image": pinned,
         });
         println!("{}", json_pretty(&payload)?);
     } else {
@@ -0,0 +1,96 @@
+use super::{acquire_store_lock, format_size, json_pretty, EXIT_SUCCESS};
+use karapace_core::{prune, Engine, PruneOptions};
+use karapace_store::StoreLayout;
+use std::path::Path;
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    options: &PruneOptions,
+    json: bool,
+) -> Result<u8, String> {
+    let layout = StoreLayout::new(store_path);
+    let lock = acquire_store_lock(&layout, "prune")?;
+
+    let report = prune(engine, &lock, options).map_err(|e| e.to_string())?;
+    let gc = &report.gc;
+    if json {
+        let workspaces: Vec<_> = report
+            .workspaces
+            .iter()
+            .map(|w| {
+                serde_json::json!({
+                    "env_id": w.env_id,
+                    "name": w.name,
+                    "size_bytes": w.size_bytes,
+                })
+            })
+            .collect();
+        let images: Vec<_> = report
+            .images
+            .iter()
+            .map(|i| {
+                serde_json::json!({
+                    "cache_key": i.cache_key,
+                    "size_bytes": i.size_bytes,
+                })
+            })
+            .collect();
+        let payload = serde_json::json!({
+            "dry_run": options.dry_run,
+            "workspaces": workspaces,
+            "images": images,
+            "evicted_snapshots": gc.evicted_snapshots,
+            "orphaned_envs": gc.orphaned_envs,
+            "orphaned_layers": gc.orphaned_layers,
+            "orphaned_objects": gc.orphaned_objects,
+            "removed_envs": gc.removed_envs,
+            "removed_layers": gc.removed_layers,
+            "removed_objects": gc.removed_objects,
+            "reclaimed_bytes": report.reclaimed_bytes(),
+        });
+        println!("{}", json_pretty(&payload)?);
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let (prefix, reclaim) = if options.dry_run {
+        ("would remove", "would reclaim")
+    } else {
+        ("removed", "reclaimed")
+    };
+    for w in &report.workspaces {
+        println!(
+            "workspace {} of {} ({})",
+            w.name,
+            &w.env_id[..12.min(w.env_id.len())],
+            format_size(w.size_bytes)
+        );
+    }
+    for image in &report.images {
+        println!(
+            "image {} ({})",
+            image.cache_key,
+            format_size(image.size_bytes)
+        );
+    }
+    let (env_count, layer_count, object_count) = if options.dry_run {
+        (
+            gc.orphaned_envs.len(),
+            gc.orphaned_layers.len(),
+            gc.orphaned_objects.len(),
+        )
+    } else {
+        (gc.removed_envs, gc.removed_layers, gc.removed_objects)
+    };
+    println!(
+        "prune: {prefix} {} workspaces, {} images, {} snapshots, {env_count} envs, {layer_count} layers, {object_count} objects",
+        report.workspaces.len(),
+        report.images.len(),
+        gc.evicted_snapshots.len(),
+    );
+    println!(
+        "prune: {reclaim} {} from workspaces and images",
+        format_size(report.reclaimed_bytes())
+    );
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,24 @@
+use super::{json_pretty, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
+
+pub fn run(engine: &Engine, env_id: &str, json: bool) -> Result<u8, String> {
+    let resolved = resolve_env_id_pretty(engine, env_id)?;
+    let processes = engine.ps(&resolved).map_err(|e| e.to_string())?;
+    if json {
+        println!("{}", json_pretty(&processes)?);
+    } else if processes.is_empty() {
+        println!("no processes running");
+    } else {
+        println!(
+            "{:>8} {:>8} {:>6} {:>10} COMMAND",
+            "PID", "PPID", "%CPU", "RSS_KB"
+        );
+        for p in &processes {
+            println!(
+                "{:>8} {:>8} {:>6.1} {:>10} {}",
+                p.pid, p.ppid, p.cpu_percent, p.rss_kb, p.command
+            );
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -1,26 +1,65 @@
-use super::{json_pretty, make_remote_backend, spin_fail, spin_ok, spinner, EXIT_SUCCESS};
+use super::{
+    json_pretty, make_remote_backend, make_remote_cipher, registry_cache, remote_config, spin_fail,
+    spin_ok, TransferProgress, EXIT_SUCCESS,
+};
 use karapace_core::Engine;
+use karapace_remote::{BlobCipher, ObjectProgress, TransferOptions, TransferPhase};
+use std::path::Path;
 
 pub fn run(
     engine: &Engine,
     reference: &str,
     remote_url: Option<&str>,
+    age_identity: Option<&Path>,
+    jobs: usize,
     json: bool,
 ) -> Result<u8, String> {
-    let backend = make_remote_backend(remote_url)?;
+    let config = remote_config(remote_url)?;
+    let backend = make_remote_backend(&config)?;
+
+    let cipher = make_remote_cipher(remote_url, &[], age_identity);
+    let trusted_keys = config.load_trusted_keys().map_err(|e| e.to_string())?;
 
     // Resolve reference: try as registry ref first, fall back to raw env_id
-    let env_id = match Engine::resolve_remote_ref(&backend, reference) {
-        Ok(id) => id,
+    let cache = registry_cache(engine.store_layout(), &config);
+    let env_id = match karapace_remote::resolve_entry_with_cache(&*backend, reference, Some(&cache))
+    {
+        Ok(entry) => {
+            if !entry.key_fingerprints.is_empty() && cipher.is_none() {
+                return Err(format!(
+                    "'{reference}' is encrypted for key {}; pass --age-identity or set age_identity in the remote config",
+                    entry.key_fingerprints.join(", ")
+                ));
+            }
+            entry.env_id
+        }
         Err(_) => reference.to_owned(),
     };
 
-    let pb = spinner("pulling environment…");
-    let result = engine.pull(&env_id, &backend).map_err(|e| {
-        spin_fail(&pb, "pull failed");
-        e.to_string()
-    })?;
-    spin_ok(&pb, "pull complete");
+    let progress = TransferProgress::new("pulling");
+    let on_object = |p: &ObjectProgress<'_>| progress.object(p);
+    let on_phase = |phase: TransferPhase| progress.phase(phase);
+    let options = TransferOptions {
+        concurrency: jobs,
+        cipher: cipher.as_ref().map(|c| c as &dyn BlobCipher),
+        on_object: Some(&on_object),
+        on_phase: Some(&on_phase),
+        trusted_keys: trusted_keys.as_ref(),
+        require_signed: config.require_signed,
+        ..TransferOptions::default()
+    };
+    let result = engine
+        .pull_with_options(&env_id, &*backend, &options)
+        .map_err(|e| {
+            if let Some(pb) = progress.spinner() {
+                spin_fail(pb, "pull failed");
+            }
+            e.to_string()
+        })?;
+    if let Some(pb) = progress.spinner() {
+        spin_ok(pb, "pull complete");
+    }
+    progress.total(result.objects_pulled, result.layers_pulled);
 
     if json {
         let payload = serde_json::json!({
@@ -29,6 +68,9 @@ pub fn run(
             "layers_pulled": result.layers_pulled,
             "objects_skipped": result.objects_skipped,
             "layers_skipped": result.layers_skipped,
+            "chunks_pulled": result.chunks_pulled,
+            "chunks_skipped": result.chunks_skipped,
+            "signed_by": result.signed_by,
         });
         println!("{}", json_pretty(&payload)?);
     } else {
@@ -39,6 +81,22 @@ pub fn run(
             result.layers_pulled,
             result.objects_skipped + result.layers_skipped,
         );
+        if result.chunks_pulled + result.chunks_skipped > 0 {
+            println!(
+                "{} chunks downloaded, {} already local",
+                result.chunks_pulled, result.chunks_skipped
+            );
+        }
+        if let Some(keyid) = &result.signed_by {
+            let label = trusted_keys
+                .as_ref()
+                .and_then(|keys| keys.label(keyid))
+                .filter(|label| !label.is_empty());
+            match label {
+                Some(label) => println!("signed by {keyid} ({label})"),
+                None => println!("signed by {keyid}"),
+            }
+        }
     }
     Ok(EXIT_SUCCESS)
 }
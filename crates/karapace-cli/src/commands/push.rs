@@ -1,29 +1,70 @@
 use super::{
-    json_pretty, make_remote_backend, resolve_env_id, resolve_env_id_pretty, spin_fail, spin_ok,
-    spinner, EXIT_SUCCESS,
+    json_pretty, make_remote_backend, make_remote_cipher, remote_config, resolve_env_id,
+    resolve_env_id_pretty, spin_fail, spin_ok, TransferProgress, EXIT_SUCCESS,
 };
-use karapace_core::Engine;
+use karapace_core::{AttestationKey, Engine};
+use karapace_remote::{BlobCipher, ObjectProgress, TransferOptions, TransferPhase};
 
-pub fn run(
-    engine: &Engine,
-    env_id: &str,
-    tag: Option<&str>,
-    remote_url: Option<&str>,
-    json: bool,
-) -> Result<u8, String> {
+pub struct PushArgs<'a> {
+    pub tag: Option<&'a str>,
+    pub remote_url: Option<&'a str>,
+    pub age_recipients: &'a [String],
+    /// Objects uploaded at once.
+    pub jobs: usize,
+    /// Sign the pushed environment with the store's attestation key.
+    pub sign: bool,
+    pub json: bool,
+}
+
+pub fn run(engine: &Engine, env_id: &str, args: &PushArgs<'_>) -> Result<u8, String> {
+    let PushArgs {
+        tag,
+        remote_url,
+        age_recipients,
+        jobs,
+        sign,
+        json,
+    } = *args;
     let resolved = if json {
         resolve_env_id(engine, env_id)?
     } else {
         resolve_env_id_pretty(engine, env_id)?
     };
-    let backend = make_remote_backend(remote_url)?;
+    let config = remote_config(remote_url)?;
+    let backend = make_remote_backend(&config)?;
+    let cipher = make_remote_cipher(remote_url, age_recipients, None);
+    let fingerprints = cipher
+        .as_ref()
+        .map(BlobCipher::fingerprints)
+        .unwrap_or_default();
+    let key = sign
+        .then(|| AttestationKey::load_or_create(&engine.store_layout().attestation_key_file()))
+        .transpose()
+        .map_err(|e| format!("signing key: {e}"))?;
 
-    let pb = spinner("pushing environment…");
-    let result = engine.push(&resolved, &backend, tag).map_err(|e| {
-        spin_fail(&pb, "push failed");
-        e.to_string()
-    })?;
-    spin_ok(&pb, "push complete");
+    let progress = TransferProgress::new("pushing");
+    let on_object = |p: &ObjectProgress<'_>| progress.object(p);
+    let on_phase = |phase: TransferPhase| progress.phase(phase);
+    let options = TransferOptions {
+        concurrency: jobs,
+        cipher: cipher.as_ref().map(|c| c as &dyn BlobCipher),
+        on_object: Some(&on_object),
+        on_phase: Some(&on_phase),
+        sign_with: key.as_ref().map(AttestationKey::signing_key),
+        ..TransferOptions::default()
+    };
+    let result = engine
+        .push_with_options(&resolved, &*backend, tag, &options)
+        .map_err(|e| {
+            if let Some(pb) = progress.spinner() {
+                spin_fail(pb, "push failed");
+            }
+            e.to_string()
+        })?;
+    if let Some(pb) = progress.spinner() {
+        spin_ok(pb, "push complete");
+    }
+    progress.total(result.objects_pushed, result.layers_pushed);
 
     if json {
         let payload = serde_json::json!({
@@ -33,6 +74,10 @@ pub fn run(
             "layers_pushed": result.layers_pushed,
             "objects_skipped": result.objects_skipped,
             "layers_skipped": result.layers_skipped,
+            "chunks_pushed": result.chunks_pushed,
+            "chunks_skipped": result.chunks_skipped,
+            "key_fingerprints": fingerprints,
+            "signed_by": result.signed_by,
         });
         println!("{}", json_pretty(&payload)?);
     } else {
@@ -43,9 +88,21 @@ pub fn run(
             result.layers_pushed,
             result.objects_skipped + result.layers_skipped,
         );
+        if result.chunks_pushed + result.chunks_skipped > 0 {
+            println!(
+                "{} chunks uploaded, {} already on the remote",
+                result.chunks_pushed, result.chunks_skipped
+            );
+        }
         if let Some(t) = tag {
             println!("tagged as '{t}'");
         }
+        if !fingerprints.is_empty() {
+            println!("encrypted for {}", fingerprints.join(", "));
+        }
+        if let Some(keyid) = &result.signed_by {
+            println!("signed by {keyid}");
+        }
     }
     Ok(EXIT_SUCCESS)
 }
@@ -1,5 +1,8 @@
-use super::{json_pretty, spin_fail, spin_ok, spinner, EXIT_SUCCESS};
-use karapace_core::{BuildOptions, Engine, StoreLock};
+use super::{
+    acquire_store_lock, json_pretty, print_manifest_warnings, progress_json, spin_fail, spin_ok,
+    spinner, with_build_progress, EXIT_SUCCESS,
+};
+use karapace_core::{BuildOptions, Engine};
 use karapace_store::StoreLayout;
 use std::path::Path;
 
@@ -12,14 +15,17 @@ pub fn run(
     json: bool,
 ) -> Result<u8, String> {
     let layout = StoreLayout::new(store_path);
-    let _lock = StoreLock::acquire(&layout.lock_file()).map_err(|e| format!("store lock: {e}"))?;
+    let _lock = acquire_store_lock(&layout, "rebuild")?;
 
-    let pb = if json {
+    let pb = if json || progress_json() {
         None
     } else {
         Some(spinner("rebuilding environment..."))
     };
-    let result = match engine.rebuild_with_options(manifest, options) {
+    let built = with_build_progress(pb.as_ref(), |progress| {
+        engine.rebuild_with_options(manifest, options, progress)
+    });
+    let result = match built {
         Ok(r) => {
             if let Some(ref pb) = pb {
                 spin_ok(pb, "environment rebuilt");
@@ -38,15 +44,19 @@ pub fn run(
             .set_name(&result.identity.env_id, Some(n.to_owned()))
             .map_err(|e| e.to_string())?;
     }
+    super::bind_project(engine, store_path, manifest, &result.identity.env_id);
     if json {
         let payload = serde_json::json!({
             "env_id": result.identity.env_id,
             "short_id": result.identity.short_id,
             "name": name,
-            "status": "rebuilt"
+            "status": "rebuilt",
+            "cached_packages": result.cached_packages,
+            "warnings": result.warnings,
         });
         println!("{}", json_pretty(&payload)?);
     } else {
+        print_manifest_warnings(manifest, &result.warnings);
         if let Some(n) = name {
             println!("rebuilt environment '{}' ({})", n, result.identity.short_id);
         } else {
@@ -0,0 +1,275 @@
+use super::{
+    json_pretty, make_remote_backend, make_remote_cipher, registry_cache, remote_config,
+    EXIT_SUCCESS,
+};
+use clap::Subcommand;
+use karapace_remote::{BlobCipher, RegistryOrigin};
+use karapace_store::StoreLayout;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Subcommand)]
+pub enum RemoteAction {
+    /// Show what pulling a reference would download, without pulling it.
+    Show {
+        /// Registry reference (e.g. "my-env@latest") or raw env_id.
+        reference: String,
+        /// Remote store URL (overrides config file).
+        #[arg(long)]
+        remote: Option<String>,
+        /// age identity file for decrypting encrypted pushes (overrides config).
+        #[arg(long, value_name = "PATH")]
+        age_identity: Option<PathBuf>,
+    },
+    /// Update the local copy of the remote registry, used when the remote
+    /// is unreachable.
+    Refresh {
+        /// Remote store URL (overrides config file).
+        #[arg(long)]
+        remote: Option<String>,
+    },
+    /// List the tags published under an environment name.
+    Tags {
+        /// Environment name, without a tag.
+        name: String,
+        /// Remote store URL (overrides config file).
+        #[arg(long)]
+        remote: Option<String>,
+    },
+    /// Unpublish a tag. The blobs it pointed at stay on the remote until
+    /// the server collects garbage.
+    Untag {
+        /// Registry reference to remove, as "name@tag".
+        reference: String,
+        /// Remote store URL (overrides config file).
+        #[arg(long)]
+        remote: Option<String>,
+    },
+}
+
+pub fn run(store_path: &Path, action: &RemoteAction, json: bool) -> Result<u8, String> {
+    match action {
+        RemoteAction::Show {
+            reference,
+            remote,
+            age_identity,
+        } => show(
+            store_path,
+            reference,
+            remote.as_deref(),
+            age_identity.as_deref(),
+            json,
+        ),
+        RemoteAction::Refresh { remote } => refresh(store_path, remote.as_deref(), json),
+        RemoteAction::Tags { name, remote } => tags(name, remote.as_deref(), json),
+        RemoteAction::Untag { reference, remote } => {
+            untag(store_path, reference, remote.as_deref(), json)
+        }
+    }
+}
+
+fn tags(name: &str, remote_url: Option<&str>, json: bool) -> Result<u8, String> {
+    if name.contains('@') {
+        return Err(format!(
+            "'{name}' includes a tag; pass just the environment name"
+        ));
+    }
+    let config = remote_config(remote_url)?;
+    let backend = make_remote_backend(&config)?;
+    let tags = backend.list_tags(name).map_err(|e| e.to_string())?;
+
+    if json {
+        let payload: Vec<_> = tags
+            .iter()
+            .map(|(tag, entry)| {
+                serde_json::json!({
+                    "tag": tag,
+                    "env_id": entry.env_id,
+                    "short_id": entry.short_id,
+                    "pushed_at": entry.pushed_at,
+                    "encrypted": !entry.key_fingerprints.is_empty(),
+                })
+            })
+            .collect();
+        println!("{}", json_pretty(&payload)?);
+    } else if tags.is_empty() {
+        println!("no tags for '{name}' on {}", config.location());
+    } else {
+        for (tag, entry) in &tags {
+            println!(
+                "{name}@{tag:<16} {}  {}{}",
+                entry.short_id,
+                entry.pushed_at,
+                if entry.key_fingerprints.is_empty() {
+                    ""
+                } else {
+                    "  (encrypted)"
+                },
+            );
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn untag(
+    store_path: &Path,
+    reference: &str,
+    remote_url: Option<&str>,
+    json: bool,
+) -> Result<u8, String> {
+    let Some((name, tag)) = reference.split_once('@') else {
+        return Err(format!("'{reference}' has no tag; pass it as name@tag"));
+    };
+    let config = remote_config(remote_url)?;
+    let backend = make_remote_backend(&config)?;
+    let entry = backend.delete_tag(name, tag).map_err(|e| e.to_string())?;
+
+    // Keep the local registry copy from resolving the removed tag offline.
+    let cache = registry_cache(&StoreLayout::new(store_path), &config);
+    if let Err(e) = cache.refresh(&*backend) {
+        tracing::debug!("registry cache not refreshed after untag: {e}");
+    }
+
+    if json {
+        let payload = serde_json::json!({
+            "untagged": reference,
+            "env_id": entry.env_id,
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else {
+        println!("untagged {reference} (was {})", entry.short_id);
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn refresh(store_path: &Path, remote_url: Option<&str>, json: bool) -> Result<u8, String> {
+    let config = remote_config(remote_url)?;
+    let backend = make_remote_backend(&config)?;
+    let cache = registry_cache(&StoreLayout::new(store_path), &config);
+    let refreshed = cache.refresh(&*backend).map_err(|e| e.to_string())?;
+
+    let status = match refreshed.as_ref().map(|r| r.origin) {
+        None => "empty",
+        Some(RegistryOrigin::NotModified) => "unchanged",
+        Some(RegistryOrigin::Downloaded | RegistryOrigin::Cached) => "updated",
+    };
+    let entries = refreshed.as_ref().map_or(0, |r| r.registry.entries.len());
+    if json {
+        let payload = serde_json::json!({
+            "remote": config.location(),
+            "status": status,
+            "entries": entries,
+            "fetched_at": refreshed.as_ref().map(|r| &r.fetched_at),
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else if refreshed.is_none() {
+        println!("{} has no registry yet", config.location());
+    } else {
+        println!("registry {status}: {entries} entries");
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn show(
+    store_path: &Path,
+    reference: &str,
+    remote_url: Option<&str>,
+    age_identity: Option<&Path>,
+    json: bool,
+) -> Result<u8, String> {
+    let config = remote_config(remote_url)?;
+    let backend = make_remote_backend(&config)?;
+    let cipher = make_remote_cipher(remote_url, &[], age_identity);
+
+    let layout = StoreLayout::new(store_path);
+    let cache = registry_cache(&layout, &config);
+
+    let (env_id, key_fingerprints) =
+        match karapace_remote::resolve_entry_with_cache(&*backend, reference, Some(&cache)) {
+            Ok(entry) => (entry.env_id, entry.key_fingerprints),
+            Err(_) => (reference.to_owned(), Vec::new()),
+        };
+    if !key_fingerprints.is_empty() && cipher.is_none() {
+        return Err(format!(
+            "'{reference}' is encrypted for key {}; pass --age-identity or set age_identity in the remote config",
+            key_fingerprints.join(", ")
+        ));
+    }
+
+    let peek = karapace_remote::peek_env_with_cipher(
+        &layout,
+        &env_id,
+        &*backend,
+        cipher.as_ref().map(|c| c as &dyn BlobCipher),
+    )
+    .map_err(|e| e.to_string())?;
+
+    if json {
+        let layers: Vec<_> = peek
+            .layers
+            .iter()
+            .map(|l| {
+                serde_json::json!({
+                    "hash": l.hash,
+                    "kind": l.kind,
+                    "objects": l.object_count,
+                    "size": l.size,
+                    "local": l.local,
+                })
+            })
+            .collect();
+        let payload = serde_json::json!({
+            "env_id": peek.env_id,
+            "short_id": peek.short_id,
+            "name": peek.name,
+            "base_image": peek.base_image,
+            "base_image_digest": peek.base_image_digest,
+            "packages": peek.packages,
+            "layers": layers,
+            "objects_total": peek.objects_total,
+            "objects_missing": peek.objects_missing,
+            "download_bytes": peek.download_bytes,
+        });
+        println!("{}", json_pretty(&payload)?);
+        return Ok(EXIT_SUCCESS);
+    }
+
+    println!("env_id:      {}", peek.env_id);
+    println!("name:        {}", peek.name.as_deref().unwrap_or("(none)"));
+    println!(
+        "base_image:  {}",
+        peek.base_image.as_deref().unwrap_or("(unknown)")
+    );
+    println!(
+        "digest:      {}",
+        peek.base_image_digest
+            .as_deref()
+            .unwrap_or("(not recorded)")
+    );
+    if peek.packages.is_empty() {
+        println!("packages:    (none)");
+    } else {
+        println!("packages:    {}", peek.packages.join(" "));
+    }
+    println!("layers:");
+    for layer in &peek.layers {
+        println!(
+            "  {:<10} {}  {} objects, {}{}",
+            format!("{:?}", layer.kind).to_lowercase(),
+            &layer.hash[..12.min(layer.hash.len())],
+            layer.object_count,
+            format_size(layer.size),
+            if layer.local { "  (local)" } else { "" },
+        );
+    }
+    println!(
+        "download:    {} of {} objects, {}",
+        peek.objects_missing,
+        peek.objects_total,
+        format_size(peek.download_bytes)
+    );
+    Ok(EXIT_SUCCESS)
+}
+
+fn format_size(size: Option<u64>) -> String {
+    size.map_or_else(|| "size unknown".to_owned(), |s| format!("{s} bytes"))
+}
@@ -0,0 +1,46 @@
+use super::{acquire_store_lock, json_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
+use karapace_store::StoreLayout;
+use std::path::Path;
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    threshold: u64,
+    unpack: bool,
+    json: bool,
+) -> Result<u8, String> {
+    let layout = StoreLayout::new(store_path);
+    let lock = acquire_store_lock(&layout, "repack")?;
+
+    if unpack {
+        let count = engine.unpack(&lock).map_err(|e| e.to_string())?;
+        if json {
+            let payload = serde_json::json!({ "unpacked_objects": count });
+            println!("{}", json_pretty(&payload)?);
+        } else {
+            println!("unpacked {count} objects");
+        }
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let report = engine.repack(&lock, threshold).map_err(|e| e.to_string())?;
+    if json {
+        let payload = serde_json::json!({
+            "threshold": threshold,
+            "loose_packed": report.loose_packed,
+            "packs_merged": report.packs_merged,
+            "pack_id": report.pack_id,
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else {
+        println!(
+            "repack: packed {} loose objects, merged {} packs",
+            report.loose_packed, report.packs_merged
+        );
+        if let Some(id) = &report.pack_id {
+            println!("pack: {}", &id[..12]);
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
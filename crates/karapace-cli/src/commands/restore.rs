@@ -1,5 +1,5 @@
-use super::{json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
-use karapace_core::{Engine, StoreLock};
+use super::{acquire_store_lock, json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
 use karapace_store::StoreLayout;
 use std::path::Path;
 
@@ -11,7 +11,7 @@ pub fn run(
     json: bool,
 ) -> Result<u8, String> {
     let layout = StoreLayout::new(store_path);
-    let _lock = StoreLock::acquire(&layout.lock_file()).map_err(|e| format!("store lock: {e}"))?;
+    let _lock = acquire_store_lock(&layout, "restore")?;
 
     let resolved = if json {
         resolve_env_id(engine, env_id)?
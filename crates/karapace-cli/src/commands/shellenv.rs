@@ -0,0 +1,53 @@
+use super::{json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::Engine;
+use karapace_runtime::shellenv;
+use std::path::Path;
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    env_id: &str,
+    commands: &[String],
+    fish: bool,
+    json: bool,
+) -> Result<u8, String> {
+    let resolved = if json {
+        resolve_env_id(engine, env_id)?
+    } else {
+        resolve_env_id_pretty(engine, env_id)?
+    };
+    if let Some(bad) = commands.iter().find(|c| !shellenv::is_command_name(c)) {
+        return Err(format!(
+            "'{bad}' is not a command name; give commands as they are typed, without paths"
+        ));
+    }
+
+    // Shims may run from any directory, so they name the store absolutely.
+    let store_path = std::path::absolute(store_path)
+        .map_err(|e| format!("failed to resolve the store path: {e}"))?;
+    let karapace_bin = std::env::current_exe()
+        .map_err(|e| format!("failed to locate the karapace binary: {e}"))?;
+    let shims = shellenv::shims_dir(&engine.store_layout().env_path(&resolved));
+    shellenv::write_shims(
+        &shims,
+        commands,
+        &resolved,
+        &karapace_bin.to_string_lossy(),
+        &store_path.to_string_lossy(),
+    )
+    .map_err(|e| e.to_string())?;
+
+    if json {
+        let payload = serde_json::json!({
+            "env_id": resolved,
+            "shims_dir": shims,
+            "commands": commands,
+        });
+        println!("{}", json_pretty(&payload)?);
+    } else if fish {
+        print!("{}", shellenv::fish_exports(&resolved, &shims));
+    } else {
+        print!("{}", shellenv::posix_exports(&resolved, &shims));
+    }
+    Ok(EXIT_SUCCESS)
+}
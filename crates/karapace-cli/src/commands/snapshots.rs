@@ -24,6 +24,7 @@ pub fn run(engine: &Engine, store_path: &Path, env_id: &str, json: bool) -> Resu
                 "restore_hash": restore_hash,
                 "tar_hash": s.tar_hash,
                 "parent": s.parent,
+                "delta_parent": s.delta_parent,
             }));
         }
         let payload = serde_json::json!({
@@ -37,7 +38,15 @@ pub fn run(engine: &Engine, store_path: &Path, env_id: &str, json: bool) -> Resu
         println!("snapshots for {env_id}:");
         for s in &snapshots {
             let restore_hash = LayerStore::compute_hash(s).map_err(|e| e.to_string())?;
-            println!("  {} (tar: {})", restore_hash, &s.tar_hash[..12]);
+            match &s.delta_parent {
+                Some(parent) => println!(
+                    "  {} (tar: {}, delta of {})",
+                    restore_hash,
+                    &s.tar_hash[..12],
+                    &parent[..12.min(parent.len())]
+                ),
+                None => println!("  {} (tar: {})", restore_hash, &s.tar_hash[..12]),
+            }
         }
     }
     Ok(EXIT_SUCCESS)
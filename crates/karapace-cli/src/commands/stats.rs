@@ -0,0 +1,121 @@
+use super::{colorize_state, format_size, json_pretty, resolve_env_id_pretty, EXIT_SUCCESS};
+use karapace_core::{shutdown_requested, Engine, EnvStats};
+use karapace_store::EnvMetadata;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{stdout, IsTerminal};
+use std::time::{Duration, Instant};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Row {
+    env_id: String,
+    short_id: String,
+    name: Option<String>,
+    state: String,
+    #[serde(flatten)]
+    stats: EnvStats,
+}
+
+pub fn run(engine: &Engine, env_id: Option<&str>, watch: bool, json: bool) -> Result<u8, String> {
+    let envs = match env_id {
+        Some(env_id) => {
+            let resolved = resolve_env_id_pretty(engine, env_id)?;
+            vec![engine.inspect(&resolved).map_err(|e| e.to_string())?]
+        }
+        None => engine.list().map_err(|e| e.to_string())?,
+    };
+
+    if !watch {
+        let rows = sample(engine, &envs)?;
+        if json {
+            println!("{}", json_pretty(&rows)?);
+        } else {
+            print_table(&rows);
+        }
+        return Ok(EXIT_SUCCESS);
+    }
+
+    // In watch mode %CPU is the usage since the previous sample rather than
+    // the average since each process started.
+    let clear = !json && stdout().is_terminal();
+    let mut previous: Option<(Instant, HashMap<String, f64>)> = None;
+    while !shutdown_requested() {
+        let mut rows = sample(engine, &envs)?;
+        let now = Instant::now();
+        if let Some((at, cpu)) = &previous {
+            let elapsed = now.duration_since(*at).as_secs_f64();
+            for row in &mut rows {
+                let before = cpu.get(&row.env_id).copied().unwrap_or(0.0);
+                row.stats.cpu_percent = (100.0 * (row.stats.cpu_secs - before) / elapsed).max(0.0);
+            }
+        }
+        previous = Some((
+            now,
+            rows.iter()
+                .map(|row| (row.env_id.clone(), row.stats.cpu_secs))
+                .collect(),
+        ));
+
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string(&rows)
+                    .map_err(|e| format!("JSON serialization failed: {e}"))?
+            );
+        } else {
+            if clear {
+                print!("\x1b[2J\x1b[H");
+            }
+            print_table(&rows);
+        }
+
+        let until = now + WATCH_INTERVAL;
+        while Instant::now() < until && !shutdown_requested() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn sample(engine: &Engine, envs: &[EnvMetadata]) -> Result<Vec<Row>, String> {
+    envs.iter()
+        .map(|env| {
+            let stats = engine
+                .stats(&env.env_id)
+                .map_err(|e| format!("{}: {e}", env.short_id))?;
+            Ok(Row {
+                env_id: env.env_id.to_string(),
+                short_id: env.short_id.to_string(),
+                name: env.name.clone(),
+                state: env.state.to_string(),
+                stats,
+            })
+        })
+        .collect()
+}
+
+fn print_table(rows: &[Row]) {
+    if rows.is_empty() {
+        println!("no environments found");
+        return;
+    }
+    println!(
+        "{:<14} {:<16} {:<10} {:>5} {:>9} {:>6} {:>10} {:>10}",
+        "SHORT_ID", "NAME", "STATE", "PROCS", "CPU_TIME", "%CPU", "MEMORY", "DISK"
+    );
+    for row in rows {
+        println!(
+            "{:<14} {:<16} {:<10} {:>5} {:>8.1}s {:>6.1} {:>10} {:>10}",
+            row.short_id,
+            row.name.as_deref().unwrap_or(""),
+            colorize_state(&row.state),
+            row.stats.processes,
+            row.stats.cpu_secs,
+            row.stats.cpu_percent,
+            format_size(row.stats.memory_bytes),
+            format_size(row.stats.disk_bytes),
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use super::{
+    acquire_store_lock, json_pretty, make_remote_backend, make_remote_cipher, registry_cache,
+    remote_config, spin_fail, spin_ok, spinner, EXIT_FAILURE, EXIT_SUCCESS,
+};
+use karapace_core::{Engine, SyncManifest, SyncOptions, SyncReport, SyncStatus};
+use karapace_remote::BlobCipher;
+use karapace_store::StoreLayout;
+use std::path::Path;
+
+pub struct SyncArgs<'a> {
+    pub remote_url: Option<&'a str>,
+    pub age_identity: Option<&'a Path>,
+    /// Environments pulled at once.
+    pub jobs: usize,
+    /// Objects downloaded at once within each pull.
+    pub object_jobs: usize,
+    pub prune: bool,
+    pub json: bool,
+}
+
+/// `karapace sync`: bring the store in line with a sync manifest.
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    manifest_path: &Path,
+    args: &SyncArgs<'_>,
+) -> Result<u8, String> {
+    let manifest = SyncManifest::load(manifest_path).map_err(|e| e.to_string())?;
+    let remote_url = args.remote_url.or(manifest.remote.as_deref());
+    let state_key = std::path::absolute(manifest_path)
+        .map_err(|e| format!("{}: {e}", manifest_path.display()))?;
+    let state_key = state_key.to_string_lossy();
+    sync_refs(
+        engine,
+        store_path,
+        &manifest.refs,
+        Some(&state_key),
+        &SyncArgs {
+            remote_url,
+            prune: args.prune || manifest.prune,
+            ..*args
+        },
+    )
+}
+
+/// `karapace pull --all`: every reference in the remote registry.
+pub fn pull_all(engine: &Engine, store_path: &Path, args: &SyncArgs<'_>) -> Result<u8, String> {
+    let config = remote_config(args.remote_url)?;
+    let backend = make_remote_backend(&config)?;
+    let cache = registry_cache(&StoreLayout::new(store_path), &config);
+    let refs = karapace_remote::list_refs_with_cache(&*backend, Some(&cache))
+        .map_err(|e| e.to_string())?;
+    sync_refs(engine, store_path, &refs, None, args)
+}
+
+fn sync_refs(
+    engine: &Engine,
+    store_path: &Path,
+    refs: &[String],
+    state_key: Option<&str>,
+    args: &SyncArgs<'_>,
+) -> Result<u8, String> {
+    let config = remote_config(args.remote_url)?;
+    let backend = make_remote_backend(&config)?;
+    let cipher = make_remote_cipher(args.remote_url, &[], args.age_identity);
+    let trusted_keys = config.load_trusted_keys().map_err(|e| e.to_string())?;
+    let layout = StoreLayout::new(store_path);
+    let _lock = acquire_store_lock(&layout, "sync")?;
+
+    let options = SyncOptions {
+        jobs: args.jobs,
+        object_jobs: args.object_jobs,
+        cipher: cipher.as_ref().map(|c| c as &dyn BlobCipher),
+        trusted_keys: trusted_keys.as_ref(),
+        require_signed: config.require_signed,
+        state_key,
+        prune: args.prune,
+    };
+    let pb = spinner(&format!("syncing {} environment(s)…", refs.len()));
+    let report = engine.sync(refs, &*backend, &options).map_err(|e| {
+        spin_fail(&pb, "sync failed");
+        e.to_string()
+    })?;
+    if report.failed() == 0 {
+        spin_ok(&pb, "sync complete");
+    } else {
+        spin_fail(&pb, &format!("{} failed", report.failed()));
+    }
+
+    print_report(&report, args.json)?;
+    Ok(if report.failed() == 0 {
+        EXIT_SUCCESS
+    } else {
+        EXIT_FAILURE
+    })
+}
+
+fn print_report(report: &SyncReport, json: bool) -> Result<(), String> {
+    if json {
+        println!("{}", json_pretty(report)?);
+        return Ok(());
+    }
+    for outcome in &report.outcomes {
+        let short = outcome
+            .env_id
+            .as_deref()
+            .map_or("-", |id| &id[..12.min(id.len())]);
+        let status = match outcome.status {
+            SyncStatus::Pulled => format!(
+                "pulled ({} objects, {} layers)",
+                outcome.objects_pulled, outcome.layers_pulled
+            ),
+            SyncStatus::UpToDate => "up to date".to_owned(),
+            SyncStatus::Removed => "removed".to_owned(),
+            SyncStatus::Failed => {
+                format!("failed: {}", outcome.error.as_deref().unwrap_or("unknown"))
+            }
+        };
+        println!("{:<24} {short:<12} {status}", outcome.reference);
+    }
+    Ok(())
+}
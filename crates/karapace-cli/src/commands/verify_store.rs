@@ -1,16 +1,60 @@
-use super::{json_pretty, EXIT_STORE_ERROR, EXIT_SUCCESS};
-use karapace_core::Engine;
-use karapace_store::verify_store_integrity;
+use super::{
+    acquire_store_lock, json_pretty, make_remote_backend, make_remote_cipher, remote_config,
+    EXIT_STORE_ERROR, EXIT_SUCCESS,
+};
+use karapace_core::{Engine, RepairItem, RepairReport, RepairSource};
+use karapace_remote::BlobCipher;
+use karapace_store::{scrub_store, verify_store_integrity, ScrubReport, SCRUB_BUCKETS};
+use std::path::Path;
+use std::time::Duration;
 
-pub fn run(engine: &Engine, json: bool) -> Result<u8, String> {
-    let report = verify_store_integrity(engine.store_layout()).map_err(|e| e.to_string())?;
+pub struct RepairArgs<'a> {
+    pub remote_url: Option<&'a str>,
+    pub age_identity: Option<&'a Path>,
+}
+
+/// `incremental` is the time budget of an incremental run.
+pub fn run(
+    engine: &Engine,
+    repair: Option<&RepairArgs<'_>>,
+    incremental: Option<Duration>,
+    json: bool,
+) -> Result<u8, String> {
+    let layout = engine.store_layout();
+    let (report, scrub) = match incremental {
+        Some(max_duration) => {
+            let mut scrub = scrub_store(layout, max_duration).map_err(|e| e.to_string())?;
+            (std::mem::take(&mut scrub.integrity), Some(scrub))
+        }
+        None => (
+            verify_store_integrity(layout).map_err(|e| e.to_string())?,
+            None,
+        ),
+    };
+    let repaired = repair.map(|args| run_repair(engine, args)).transpose()?;
 
     if json {
-        let payload = serde_json::json!({
+        let mut payload = serde_json::json!({
             "checked": report.checked,
             "passed": report.passed,
             "failed": report.failed.len(),
+            "verified_bytes": karapace_store::verified_bytes(),
         });
+        if let Some(scrub) = &scrub {
+            payload["scrub"] = serde_json::json!({
+                "buckets": scrub.buckets,
+                "total_buckets": SCRUB_BUCKETS,
+                "never_verified": scrub.never_verified,
+                "oldest": scrub.oldest,
+            });
+        }
+        if let Some(repaired) = &repaired {
+            payload["repair"] = serde_json::json!({
+                "quarantined": items_json(&repaired.quarantined),
+                "recovered": items_json(&repaired.recovered),
+                "lost": items_json(&repaired.lost),
+            });
+        }
         println!("{}", json_pretty(&payload)?);
     } else {
         println!(
@@ -20,11 +64,76 @@ pub fn run(engine: &Engine, json: bool) -> Result<u8, String> {
         for f in &report.failed {
             println!("  FAIL {}: {}", f.hash, f.reason);
         }
+        if let Some(scrub) = &scrub {
+            print_scrub(scrub);
+        }
+        if let Some(repaired) = &repaired {
+            print_repair(repaired);
+        }
     }
 
-    if report.failed.is_empty() {
+    let healthy = match &repaired {
+        Some(repaired) => repaired.lost.is_empty(),
+        None => report.failed.is_empty(),
+    };
+    if healthy {
         Ok(EXIT_SUCCESS)
     } else {
         Ok(EXIT_STORE_ERROR)
     }
 }
+
+fn run_repair(engine: &Engine, args: &RepairArgs<'_>) -> Result<RepairReport, String> {
+    let lock = acquire_store_lock(engine.store_layout(), "verify-store --repair")?;
+    // Without --remote, the configured remote is used if there is one.
+    let backend = match remote_config(args.remote_url) {
+        Ok(config) => Some(make_remote_backend(&config)?),
+        Err(e) if args.remote_url.is_some() => return Err(e),
+        Err(_) => None,
+    };
+    let cipher = make_remote_cipher(args.remote_url, &[], args.age_identity);
+    let source = backend.as_deref().map(|backend| RepairSource {
+        backend,
+        cipher: cipher.as_ref().map(|c| c as &dyn BlobCipher),
+    });
+    karapace_core::repair(engine, &lock, source).map_err(|e| e.to_string())
+}
+
+fn print_scrub(scrub: &ScrubReport) {
+    print!("scrub: {}/{SCRUB_BUCKETS} buckets verified", scrub.buckets);
+    match &scrub.oldest {
+        Some(oldest) => println!(", every bucket verified since {oldest}"),
+        None => println!(", {} never verified", scrub.never_verified),
+    }
+}
+
+fn items_json(items: &[RepairItem]) -> Vec<serde_json::Value> {
+    items
+        .iter()
+        .map(|item| {
+            serde_json::json!({
+                "kind": item.kind.to_string(),
+                "hash": item.hash,
+                "detail": item.detail,
+            })
+        })
+        .collect()
+}
+
+fn print_repair(report: &RepairReport) {
+    println!(
+        "repair: {} quarantined, {} recovered, {} lost",
+        report.quarantined.len(),
+        report.recovered.len(),
+        report.lost.len()
+    );
+    for (label, items) in [
+        ("quarantined", &report.quarantined),
+        ("recovered", &report.recovered),
+        ("LOST", &report.lost),
+    ] {
+        for item in items {
+            println!("  {label} {} {}: {}", item.kind, item.hash, item.detail);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use super::{acquire_store_lock, json_pretty, resolve_env_id, resolve_env_id_pretty, EXIT_SUCCESS};
+use clap::Subcommand;
+use karapace_core::Engine;
+use karapace_store::StoreLayout;
+use std::path::Path;
+
+#[derive(Debug, Subcommand)]
+pub enum WorkspaceAction {
+    /// Switch an environment to a workspace, creating it if needed.
+    Use {
+        /// Environment ID, short ID, or name.
+        env_id: String,
+        /// Workspace name.
+        name: String,
+        /// Create the workspace as a copy of the active one.
+        #[arg(long, default_value_t = false)]
+        branch: bool,
+    },
+    /// List the workspaces of an environment.
+    List {
+        /// Environment ID, short ID, or name.
+        env_id: String,
+    },
+    /// Delete an inactive workspace.
+    Rm {
+        /// Environment ID, short ID, or name.
+        env_id: String,
+        /// Workspace name.
+        name: String,
+    },
+}
+
+pub fn run(
+    engine: &Engine,
+    store_path: &Path,
+    action: &WorkspaceAction,
+    json: bool,
+) -> Result<u8, String> {
+    match action {
+        WorkspaceAction::Use {
+            env_id,
+            name,
+            branch,
+        } => {
+            let layout = StoreLayout::new(store_path);
+            let _lock = acquire_store_lock(&layout, "workspace")?;
+
+            let resolved = resolve_env_id_pretty(engine, env_id)?;
+            engine
+                .use_workspace(&resolved, name, *branch)
+                .map_err(|e| e.to_string())?;
+            if json {
+                let payload = serde_json::json!({
+                    "env_id": resolved,
+                    "workspace": name,
+                });
+                println!("{}", json_pretty(&payload)?);
+            } else {
+                println!("{} now uses workspace '{name}'", &resolved[..12]);
+            }
+        }
+        WorkspaceAction::List { env_id } => {
+            let resolved = if json {
+                resolve_env_id(engine, env_id)?
+            } else {
+                resolve_env_id_pretty(engine, env_id)?
+            };
+            let workspaces = engine
+                .list_workspaces(&resolved)
+                .map_err(|e| e.to_string())?;
+            if json {
+                let entries: Vec<_> = workspaces
+                    .iter()
+                    .map(|w| serde_json::json!({ "name": w.name, "active": w.active }))
+                    .collect();
+                let payload = serde_json::json!({
+                    "env_id": resolved,
+                    "workspaces": entries,
+                });
+                println!("{}", json_pretty(&payload)?);
+            } else {
+                println!("workspaces for {env_id}:");
+                for w in &workspaces {
+                    let marker = if w.active { "*" } else { " " };
+                    println!("{marker} {}", w.name);
+                }
+            }
+        }
+        WorkspaceAction::Rm { env_id, name } => {
+            let layout = StoreLayout::new(store_path);
+            let _lock = acquire_store_lock(&layout, "workspace")?;
+
+            let resolved = resolve_env_id_pretty(engine, env_id)?;
+            engine
+                .remove_workspace(&resolved, name)
+                .map_err(|e| e.to_string())?;
+            if json {
+                let payload = serde_json::json!({
+                    "env_id": resolved,
+                    "removed": name,
+                });
+                println!("{}", json_pretty(&payload)?);
+            } else {
+                println!("removed workspace '{name}' from {}", &resolved[..12]);
+            }
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -3,9 +3,16 @@ mod commands;
 use clap::{Parser, Subcommand};
 use clap_complete::Shell;
 use commands::{EXIT_FAILURE, EXIT_MANIFEST_ERROR, EXIT_STORE_ERROR};
-use karapace_core::{install_signal_handler, BuildOptions, Engine};
+use karapace_core::{
+    discover_store, install_signal_handler, BuildOptions, Engine, ImportOptions, PruneOptions,
+    StoreSource, UserConfig,
+};
+use karapace_store::RetentionPolicy;
 use std::path::PathBuf;
 use std::process::ExitCode;
+use std::time::Duration;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -14,9 +21,11 @@ use std::process::ExitCode;
     about = "Deterministic environment engine for immutable systems"
 )]
 struct Cli {
-    /// Path to the Karapace store directory.
-    #[arg(long, default_value = "~/.local/share/karapace")]
-    store: String,
+    /// Path to the Karapace store directory. Defaults to $KARAPACE_STORE, then a
+    /// project-local `.karapace/store`, then the `store` key of
+    /// `~/.config/karapace/config.toml`, then `~/.local/share/karapace`.
+    #[arg(long)]
+    store: Option<String>,
 
     /// Output results as structured JSON.
     #[arg(long, default_value_t = false, global = true)]
@@ -30,6 +39,22 @@ struct Cli {
     #[arg(long, default_value_t = false, global = true)]
     trace: bool,
 
+    /// Seconds to wait for a busy store lock (0 fails immediately).
+    /// Defaults to $KARAPACE_LOCK_WAIT, otherwise waits indefinitely.
+    #[arg(long, value_name = "SECS", global = true)]
+    lock_wait: Option<u64>,
+
+    /// Progress reporting of build, pull and push on stderr: `auto` for
+    /// spinners, `json` for one JSON event per line.
+    #[arg(
+        long,
+        value_name = "MODE",
+        global = true,
+        default_value = "auto",
+        value_parser = commands::parse_progress_mode
+    )]
+    progress: commands::ProgressMode,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -42,6 +67,10 @@ enum Commands {
         template: Option<String>,
         #[arg(long, default_value_t = false)]
         force: bool,
+        /// Also write a direnv `.envrc` that runs the manifest's package
+        /// commands in the environment.
+        #[arg(long, default_value_t = false)]
+        envrc: bool,
     },
     /// Build an environment from a manifest.
     Build {
@@ -60,6 +89,9 @@ enum Commands {
         /// Require base.image to be a pinned http(s) URL.
         #[arg(long, default_value_t = false)]
         require_pinned_image: bool,
+        /// Install packages even if the build cache has them.
+        #[arg(long, default_value_t = false)]
+        no_cache: bool,
     },
     /// Destroy and rebuild an environment from manifest.
     Rebuild {
@@ -78,6 +110,21 @@ enum Commands {
         /// Require base.image to be a pinned http(s) URL.
         #[arg(long, default_value_t = false)]
         require_pinned_image: bool,
+        /// Install packages even if the build cache has them.
+        #[arg(long, default_value_t = false)]
+        no_cache: bool,
+    },
+    /// Verify that karapace.lock is consistent with the manifest, without building.
+    Check {
+        /// Path to manifest TOML file.
+        #[arg(default_value = "karapace.toml")]
+        manifest: PathBuf,
+        /// Re-resolve package versions and base digest and fail if they differ from the lock.
+        #[arg(long, default_value_t = false)]
+        frozen: bool,
+        /// Forbid all network access during resolution.
+        #[arg(long, default_value_t = false)]
+        offline: bool,
     },
 
     /// Rewrite a manifest to use an explicit pinned base image reference.
@@ -92,27 +139,124 @@ enum Commands {
         #[arg(long, default_value_t = false)]
         write_lock: bool,
     },
+    /// Import an OCI or Docker image as a base image and build an environment on it.
+    Import {
+        /// OCI layout directory, `docker save` or OCI archive, or registry reference.
+        source: String,
+        /// Path of the manifest to generate.
+        #[arg(short, long, default_value = "karapace.toml")]
+        output: PathBuf,
+        /// Runtime backend written to the manifest.
+        #[arg(long)]
+        backend: Option<String>,
+        /// Overwrite an existing manifest.
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Work with manifest files.
+    Manifest {
+        #[command(subcommand)]
+        action: commands::manifest::ManifestAction,
+    },
+    /// Manage the cache of base images.
+    Image {
+        #[command(subcommand)]
+        action: commands::image::ImageAction,
+    },
     /// Enter a built environment (use -- to pass a command instead of interactive shell).
     Enter {
-        /// Environment ID (full or short).
-        env_id: String,
+        /// Environment ID (full or short). Defaults to the environment the
+        /// current project was last built into.
+        env_id: Option<String>,
+        /// Refuse to start if the host GPU driver changed since build.
+        #[arg(long, default_value_t = false)]
+        strict_gpu: bool,
+        /// Discard all changes on exit; also allowed on frozen or archived environments.
+        #[arg(long = "ro", default_value_t = false)]
+        read_only: bool,
+        /// Start the session in the background; join it with `karapace attach`.
+        #[arg(short = 'd', long, default_value_t = false, conflicts_with_all = ["read_only", "command"])]
+        detach: bool,
         /// Command to run inside the environment (after --).
         #[arg(last = true)]
         command: Vec<String>,
     },
-    /// Execute a command inside a built environment (non-interactive).
-    Exec {
+    /// Open a shell, or run a command, in a detached session.
+    Attach {
         /// Environment ID (full or short).
         env_id: String,
+        /// Command to run instead of a login shell (after --).
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    /// Execute a command inside a built environment and exit with its code.
+    Exec {
+        /// Environment ID (full or short). Defaults to the environment the
+        /// current project was last built into.
+        env_id: Option<String>,
+        /// Refuse to start if the host GPU driver changed since build.
+        #[arg(long, default_value_t = false)]
+        strict_gpu: bool,
+        /// Run the command on a new pseudo-terminal.
+        #[arg(short = 't', long, default_value_t = false)]
+        tty: bool,
         /// Command and arguments to run.
         #[arg(required = true, last = true)]
         command: Vec<String>,
     },
-    /// Destroy an environment and its overlay.
+    /// Print shell code that runs the given commands in an environment,
+    /// for `eval` in a shell profile or a direnv `.envrc`.
+    Shellenv {
+        /// Environment ID, short ID, or name.
+        env_id: String,
+        /// Commands to run in the environment instead of on the host.
+        commands: Vec<String>,
+        /// Print fish code instead of POSIX shell code.
+        #[arg(long, default_value_t = false)]
+        fish: bool,
+    },
+    /// Add an application in an environment to the host's app launcher.
+    DesktopExport {
+        /// Environment ID (full or short).
+        env_id: String,
+        /// Desktop entry name, such as `org.gnome.TextEditor`, or a binary
+        /// in /usr/bin. Lists exported applications when omitted.
+        app: Option<String>,
+        /// Remove the application's launcher entry, or every entry of the
+        /// environment when no application is given.
+        #[arg(long, default_value_t = false)]
+        remove: bool,
+    },
+    /// Destroy environments and their overlays.
     Destroy {
-        /// Environment ID.
+        /// Environment IDs. Either all of them are destroyed or none is.
+        #[arg(required = true)]
+        env_ids: Vec<String>,
+        /// Collect garbage once they are destroyed.
+        #[arg(long, default_value_t = false)]
+        gc: bool,
+    },
+    /// List the processes running in an environment.
+    Ps {
+        /// Environment ID (full or short).
         env_id: String,
     },
+    /// Show CPU, memory and disk usage of environments.
+    Stats {
+        /// Environment ID (full or short); all environments when omitted.
+        env_id: Option<String>,
+        /// Refresh every two seconds until interrupted.
+        #[arg(short, long, default_value_t = false)]
+        watch: bool,
+    },
+    /// Show recently completed store operations.
+    History {
+        /// Environment ID, name or ID prefix; all operations when omitted.
+        env_id: Option<String>,
+        /// Number of operations to show, newest first.
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
     /// Stop a running environment.
     Stop {
         /// Environment ID.
@@ -129,16 +273,58 @@ enum Commands {
         env_id: String,
     },
     /// List all known environments.
-    List,
+    List {
+        /// Only list environments with this label: `key=value`, or `key`
+        /// for any value. Repeat to require several.
+        #[arg(long = "filter", value_name = "KEY[=VALUE]")]
+        filters: Vec<String>,
+    },
     /// Inspect environment metadata.
     Inspect {
         /// Environment ID.
         env_id: String,
     },
-    /// Show drift in the writable overlay of an environment.
-    Diff {
+    /// Print or verify the signed build attestation of an environment.
+    Attest {
         /// Environment ID.
         env_id: String,
+        /// Check the signature and that it matches the environment.
+        #[arg(long, default_value_t = false)]
+        verify: bool,
+        /// Require a signature by this public key (hex). Implies --verify.
+        #[arg(long, value_name = "KEYID")]
+        key: Option<String>,
+        /// Write the attestation to a file instead of stdout.
+        #[arg(long, short = 'o', value_name = "FILE", conflicts_with_all = ["verify", "key"])]
+        output: Option<PathBuf>,
+    },
+    /// Check an environment's packages for known vulnerabilities.
+    Audit {
+        /// Environment ID, short ID, or name.
+        env_id: String,
+        /// Use only cached vulnerability data, however old.
+        #[arg(long, default_value_t = false, conflicts_with = "refresh")]
+        offline: bool,
+        /// Ignore cached vulnerability data and fetch it again.
+        #[arg(long, default_value_t = false)]
+        refresh: bool,
+        /// Exit 1 if a finding is rated this or worse.
+        #[arg(long, value_name = "SEVERITY", default_value = "critical")]
+        fail_on: karapace_core::Severity,
+    },
+    /// Show drift in the writable overlay of an environment.
+    Diff {
+        /// Environment ID. Defaults to the environment the current project
+        /// was last built into.
+        env_id: Option<String>,
+        /// Also show unified diffs of added and modified text files.
+        #[arg(long, default_value_t = false)]
+        patch: bool,
+    },
+    /// Move overlay changes between machines as a bundle file.
+    Drift {
+        #[command(subcommand)]
+        action: commands::drift::DriftAction,
     },
     /// List snapshots for an environment.
     Snapshots {
@@ -149,6 +335,10 @@ enum Commands {
     Commit {
         /// Environment ID.
         env_id: String,
+        /// Pack only files changed since the last committed or restored
+        /// snapshot.
+        #[arg(long, default_value_t = false)]
+        incremental: bool,
     },
     /// Restore an environment's overlay from a snapshot.
     Restore {
@@ -162,9 +352,68 @@ enum Commands {
         /// Only report what would be removed.
         #[arg(long, default_value_t = false)]
         dry_run: bool,
+        /// Consolidate small loose objects into a pack after collection.
+        #[arg(long, default_value_t = false)]
+        repack: bool,
+        /// Keep only the newest N snapshots of each environment workspace.
+        #[arg(long, value_name = "N")]
+        keep_last: Option<usize>,
+        /// Evict archived environments and snapshots older than this (e.g. "30d", "12h").
+        #[arg(long, value_name = "AGE", value_parser = commands::gc::parse_age)]
+        older_than: Option<Duration>,
+        /// Evict the oldest archived environments and snapshots until the store fits (e.g. "20G").
+        #[arg(long, value_name = "SIZE", value_parser = commands::gc::parse_size)]
+        max_store_size: Option<u64>,
+    },
+    /// Remove unused data across the store in one pass, then collect garbage.
+    Prune {
+        /// Remove inactive workspaces and unused cached images too.
+        #[arg(long, default_value_t = false)]
+        all: bool,
+        /// Remove the inactive workspaces of every environment.
+        #[arg(long, default_value_t = false)]
+        volumes: bool,
+        /// Remove cached base images no environment is built on.
+        #[arg(long, default_value_t = false)]
+        images: bool,
+        /// Keep only the newest N snapshots of each environment workspace.
+        #[arg(long, value_name = "N")]
+        snapshots_keep: Option<usize>,
+        /// Only report what would be removed.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Consolidate small objects into packfiles, or unpack them again.
+    Repack {
+        /// Pack loose objects smaller than this many bytes.
+        #[arg(long, default_value_t = karapace_store::DEFAULT_PACK_THRESHOLD)]
+        threshold: u64,
+        /// Move all packed objects back to loose files and delete the packs.
+        #[arg(long, default_value_t = false, conflicts_with = "threshold")]
+        unpack: bool,
     },
     /// Verify store integrity.
-    VerifyStore,
+    VerifyStore {
+        /// Quarantine damaged objects and layers and restore what
+        /// environments reference, from the remote when one is configured.
+        #[arg(long, default_value_t = false)]
+        repair: bool,
+        /// Remote store URL to restore from (overrides config file).
+        #[arg(long, requires = "repair")]
+        remote: Option<String>,
+        /// age identity file for decrypting encrypted pushes (overrides config).
+        #[arg(long, value_name = "PATH", requires = "repair")]
+        age_identity: Option<PathBuf>,
+        /// Verify only the objects and layers checked least recently, until
+        /// --max-duration has passed. Repeated runs cover the whole store.
+        #[arg(long, default_value_t = false, conflicts_with = "repair")]
+        incremental: bool,
+        /// Time budget of an incremental run (e.g. "5m", "1h").
+        #[arg(long, value_name = "DURATION", default_value = "5m", value_parser = commands::gc::parse_age, requires = "incremental")]
+        max_duration: Duration,
+    },
+    /// Show disk use of the store by category and by environment.
+    Du,
     /// Push an environment to a remote store.
     Push {
         /// Environment ID, short ID, or name.
@@ -175,14 +424,72 @@ enum Commands {
         /// Remote store URL (overrides config file).
         #[arg(long)]
         remote: Option<String>,
+        /// Encrypt blobs to this age recipient (repeatable; adds to config).
+        #[arg(long = "age-recipient", value_name = "RECIPIENT")]
+        age_recipients: Vec<String>,
+        /// Number of objects to upload at once.
+        #[arg(long, value_name = "N", default_value_t = karapace_remote::DEFAULT_CONCURRENCY)]
+        jobs: usize,
+        /// Sign the environment with the store's key (`store/attestation.key`).
+        #[arg(long, default_value_t = false)]
+        sign: bool,
     },
     /// Pull an environment from a remote store.
     Pull {
         /// Registry reference (e.g. "my-env@latest") or raw env_id.
-        reference: String,
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        reference: Option<String>,
+        /// Pull every environment in the remote registry.
+        #[arg(long, default_value_t = false)]
+        all: bool,
         /// Remote store URL (overrides config file).
         #[arg(long)]
         remote: Option<String>,
+        /// age identity file for decrypting encrypted pushes (overrides config).
+        #[arg(long, value_name = "PATH")]
+        age_identity: Option<PathBuf>,
+        /// Number of objects to download at once.
+        #[arg(long, value_name = "N", default_value_t = karapace_remote::DEFAULT_CONCURRENCY)]
+        jobs: usize,
+    },
+    /// Pull the environments listed in a sync manifest.
+    Sync {
+        /// Path to the sync manifest.
+        #[arg(default_value = karapace_core::sync::SYNC_MANIFEST_FILE)]
+        manifest: PathBuf,
+        /// Remote store URL (overrides the manifest and config file).
+        #[arg(long)]
+        remote: Option<String>,
+        /// age identity file for decrypting encrypted pushes (overrides config).
+        #[arg(long, value_name = "PATH")]
+        age_identity: Option<PathBuf>,
+        /// Destroy previously synced environments the manifest no longer lists.
+        #[arg(long, default_value_t = false)]
+        prune: bool,
+        /// Number of environments to pull at once.
+        #[arg(long, value_name = "N", default_value_t = karapace_core::sync::DEFAULT_SYNC_JOBS)]
+        jobs: usize,
+    },
+    /// Inspect environments on a remote store.
+    Remote {
+        #[command(subcommand)]
+        action: commands::remote::RemoteAction,
+    },
+    /// Copy an environment and its current overlay under a new name,
+    /// sharing its base layers.
+    Clone {
+        /// Environment ID or name.
+        env_id: String,
+        /// Name of the clone.
+        new_name: String,
+    },
+    /// Set or remove labels of an environment, then print its labels.
+    Label {
+        /// Environment ID or name.
+        env_id: String,
+        /// `key=value` to set a label, `key-` to remove one.
+        #[arg(value_name = "KEY=VALUE|KEY-")]
+        changes: Vec<String>,
     },
     /// Rename an environment.
     Rename {
@@ -190,6 +497,19 @@ enum Commands {
         env_id: String,
         /// New name for the environment.
         new_name: String,
+        /// Do not keep the previous name as an alias.
+        #[arg(long, default_value_t = false)]
+        no_alias: bool,
+    },
+    /// Snapshot environments on a schedule.
+    Autosnap {
+        #[command(subcommand)]
+        action: commands::autosnap::AutosnapAction,
+    },
+    /// Manage named writable workspaces of an environment.
+    Workspace {
+        #[command(subcommand)]
+        action: commands::workspace::WorkspaceAction,
     },
     /// Generate shell completions for bash, zsh, fish, elvish, or powershell.
     Completions {
@@ -205,13 +525,38 @@ enum Commands {
     /// Launch the terminal UI.
     Tui,
     /// Run diagnostic checks on the system and store.
-    Doctor,
+    Doctor {
+        /// Rebuild the metadata index from the environments' metadata.
+        #[arg(long)]
+        rebuild_index: bool,
+    },
     /// Check store version and show migration guidance.
-    Migrate,
+    Migrate {
+        /// Move environment metadata to another backend instead.
+        #[arg(long, value_name = "BACKEND", value_parser = ["json", "sqlite"])]
+        metadata_backend: Option<String>,
+    },
+    /// Soak-test engine operations under injected store faults.
+    #[cfg(feature = "chaos")]
+    #[command(hide = true)]
+    Chaos {
+        /// Scratch directory for the test store (default: a temp directory
+        /// in the working directory, removed unless a cycle fails).
+        dir: Option<PathBuf>,
+        /// Number of build/commit/destroy/gc cycles to run.
+        #[arg(long, default_value_t = 100)]
+        cycles: usize,
+        /// Seed for fault selection, to replay a run.
+        #[arg(long)]
+        seed: Option<u64>,
+    },
 }
 
 #[allow(clippy::too_many_lines)]
 fn main() -> ExitCode {
+    // Sessions re-run this executable as their init (PID 1).
+    karapace_runtime::init::run_if_requested();
+
     let default_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |info| {
         let msg = info.to_string();
@@ -234,18 +579,63 @@ fn main() -> ExitCode {
     } else {
         "warn"
     };
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_env("KARAPACE_LOG")
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(default_level)),
-        )
-        .with_target(false)
-        .without_time()
-        .init();
+    let filter = tracing_subscriber::EnvFilter::try_from_env("KARAPACE_LOG")
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(default_level));
+    if cli.progress == commands::ProgressMode::Json {
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(commands::JsonLogLayer)
+            .init();
+    } else {
+        tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_target(false)
+            .without_time()
+            .init();
+    }
 
     install_signal_handler();
 
-    let store_path = expand_tilde(&cli.store);
+    let lock_wait = match (cli.lock_wait, std::env::var("KARAPACE_LOCK_WAIT")) {
+        (Some(secs), _) => Some(secs),
+        (None, Ok(v)) => {
+            let Ok(secs) = v.trim().parse::<u64>() else {
+                eprintln!("error: KARAPACE_LOCK_WAIT must be a number of seconds, got '{v}'");
+                return ExitCode::from(EXIT_FAILURE);
+            };
+            Some(secs)
+        }
+        (None, Err(_)) => None,
+    };
+    commands::set_lock_wait(lock_wait.map(Duration::from_secs));
+    commands::set_progress_mode(cli.progress);
+
+    let explicit_store = cli
+        .store
+        .clone()
+        .or_else(|| std::env::var("KARAPACE_STORE").ok())
+        .map(|s| expand_tilde(&s));
+    let user_config = match UserConfig::load_default() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+    let start_dir = discovery_start_dir(&cli.command);
+    let discovered = discover_store(explicit_store.as_deref(), &start_dir, &user_config);
+    if discovered.source == StoreSource::Local {
+        if let Err(e) = karapace_core::discovery::ensure_local_store_ignored(&discovered.root) {
+            eprintln!("error: {e}");
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    }
+    tracing::debug!(
+        "store root: {} ({:?})",
+        discovered.root.display(),
+        discovered.source
+    );
+    let store_path = discovered.root;
     let engine = Engine::new(&store_path);
     let json_output = cli.json;
 
@@ -254,11 +644,14 @@ fn main() -> ExitCode {
         Commands::Build { .. }
             | Commands::Enter { .. }
             | Commands::Exec { .. }
+            | Commands::Attach { .. }
             | Commands::Rebuild { .. }
+            | Commands::Check { frozen: true, .. }
             | Commands::Pin {
                 write_lock: true,
                 ..
             }
+            | Commands::Import { .. }
             | Commands::Tui
     );
     if needs_runtime && std::env::var("KARAPACE_SKIP_PREREQS").as_deref() != Ok("1") {
@@ -274,13 +667,15 @@ fn main() -> ExitCode {
             name,
             template,
             force,
-        } => commands::new::run(&name, template.as_deref(), force, json_output),
+            envrc,
+        } => commands::new::run(&name, template.as_deref(), force, envrc, json_output),
         Commands::Build {
             manifest,
             name,
             locked,
             offline,
             require_pinned_image,
+            no_cache,
         } => commands::build::run(
             &engine,
             &store_path,
@@ -290,6 +685,7 @@ fn main() -> ExitCode {
                 locked,
                 offline,
                 require_pinned_image,
+                no_cache,
             },
             json_output,
         ),
@@ -299,6 +695,7 @@ fn main() -> ExitCode {
             locked,
             offline,
             require_pinned_image,
+            no_cache,
         } => commands::rebuild::run(
             &engine,
             &store_path,
@@ -308,60 +705,305 @@ fn main() -> ExitCode {
                 locked,
                 offline,
                 require_pinned_image,
+                no_cache,
             },
             json_output,
         ),
+        Commands::Check {
+            manifest,
+            frozen,
+            offline,
+        } => commands::check::run(
+            &engine,
+            &store_path,
+            &manifest,
+            frozen,
+            offline,
+            json_output,
+        ),
         Commands::Pin {
             manifest,
             check,
             write_lock,
         } => commands::pin::run(&manifest, check, write_lock, json_output, Some(&store_path)),
-        Commands::Enter { env_id, command } => {
-            commands::enter::run(&engine, &store_path, &env_id, &command)
+        Commands::Import {
+            source,
+            output,
+            backend,
+            force,
+        } => commands::import::run(
+            &engine,
+            &store_path,
+            &source,
+            &output,
+            &ImportOptions {
+                backend,
+                overwrite: force,
+            },
+            json_output,
+        ),
+        Commands::Enter {
+            env_id,
+            strict_gpu,
+            read_only,
+            detach,
+            command,
+        } => commands::env_or_project(&engine, env_id).and_then(|env_id| {
+            if detach {
+                commands::enter::run_detached(&engine, &store_path, &env_id, strict_gpu)
+            } else {
+                commands::enter::run(
+                    &engine,
+                    &store_path,
+                    &env_id,
+                    &command,
+                    strict_gpu,
+                    read_only,
+                )
+            }
+        }),
+        Commands::Attach { env_id, command } => commands::attach::run(&engine, &env_id, &command),
+        Commands::Exec {
+            env_id,
+            strict_gpu,
+            tty,
+            command,
+        } => commands::env_or_project(&engine, env_id).and_then(|env_id| {
+            commands::exec::run(&engine, &store_path, &env_id, &command, strict_gpu, tty)
+        }),
+        Commands::Shellenv {
+            env_id,
+            commands,
+            fish,
+        } => commands::shellenv::run(&engine, &store_path, &env_id, &commands, fish, json_output),
+        Commands::DesktopExport {
+            env_id,
+            app,
+            remove,
+        } => commands::desktop_export::run(
+            &engine,
+            &store_path,
+            &env_id,
+            app.as_deref(),
+            remove,
+            json_output,
+        ),
+        Commands::Destroy { env_ids, gc } => {
+            commands::destroy::run(&engine, &store_path, &env_ids, gc)
+        }
+        Commands::Ps { env_id } => commands::ps::run(&engine, &env_id, json_output),
+        Commands::Stats { env_id, watch } => {
+            commands::stats::run(&engine, env_id.as_deref(), watch, json_output)
         }
-        Commands::Exec { env_id, command } => {
-            commands::exec::run(&engine, &store_path, &env_id, &command, json_output)
+        Commands::History { env_id, limit } => {
+            commands::history::run(&engine, env_id.as_deref(), limit, json_output)
         }
-        Commands::Destroy { env_id } => commands::destroy::run(&engine, &store_path, &env_id),
         Commands::Stop { env_id } => commands::stop::run(&engine, &store_path, &env_id),
         Commands::Freeze { env_id } => commands::freeze::run(&engine, &store_path, &env_id),
         Commands::Archive { env_id } => commands::archive::run(&engine, &store_path, &env_id),
-        Commands::List => commands::list::run(&engine, json_output),
+        Commands::List { filters } => commands::list::run(&engine, &filters, json_output),
         Commands::Inspect { env_id } => commands::inspect::run(&engine, &env_id, json_output),
-        Commands::Diff { env_id } => commands::diff::run(&engine, &env_id, json_output),
+        Commands::Attest {
+            env_id,
+            verify,
+            key,
+            output,
+        } => commands::attest::run(
+            &engine,
+            &env_id,
+            verify || key.is_some(),
+            key.as_deref(),
+            output.as_deref(),
+            json_output,
+        ),
+        Commands::Audit {
+            env_id,
+            offline,
+            refresh,
+            fail_on,
+        } => {
+            let policy = if offline {
+                karapace_core::CachePolicy::Offline
+            } else if refresh {
+                karapace_core::CachePolicy::Refresh
+            } else {
+                karapace_core::CachePolicy::Fresh
+            };
+            commands::audit::run(&engine, &env_id, policy, fail_on, json_output)
+        }
+        Commands::Diff { env_id, patch } => commands::env_or_project(&engine, env_id)
+            .and_then(|env_id| commands::diff::run(&engine, &env_id, patch, json_output)),
+        Commands::Drift { action } => {
+            commands::drift::run(&engine, &store_path, &action, json_output)
+        }
         Commands::Snapshots { env_id } => {
             commands::snapshots::run(&engine, &store_path, &env_id, json_output)
         }
-        Commands::Commit { env_id } => {
-            commands::commit::run(&engine, &store_path, &env_id, json_output)
-        }
+        Commands::Commit {
+            env_id,
+            incremental,
+        } => commands::commit::run(&engine, &store_path, &env_id, incremental, json_output),
         Commands::Restore { env_id, snapshot } => {
             commands::restore::run(&engine, &store_path, &env_id, &snapshot, json_output)
         }
-        Commands::Gc { dry_run } => commands::gc::run(&engine, &store_path, dry_run, json_output),
-        Commands::VerifyStore => commands::verify_store::run(&engine, json_output),
+        Commands::Gc {
+            dry_run,
+            repack,
+            keep_last,
+            older_than,
+            max_store_size,
+        } => commands::gc::run(
+            &engine,
+            &store_path,
+            dry_run,
+            repack,
+            RetentionPolicy {
+                keep_last,
+                older_than,
+                max_store_size,
+            },
+            json_output,
+        ),
+        Commands::Prune {
+            all,
+            volumes,
+            images,
+            snapshots_keep,
+            dry_run,
+        } => commands::prune::run(
+            &engine,
+            &store_path,
+            &PruneOptions {
+                volumes: all || volumes,
+                images: all || images,
+                snapshots_keep,
+                dry_run,
+            },
+            json_output,
+        ),
+        Commands::Repack { threshold, unpack } => {
+            commands::repack::run(&engine, &store_path, threshold, unpack, json_output)
+        }
+        Commands::VerifyStore {
+            repair,
+            remote,
+            age_identity,
+            incremental,
+            max_duration,
+        } => {
+            let repair = repair.then_some(commands::verify_store::RepairArgs {
+                remote_url: remote.as_deref(),
+                age_identity: age_identity.as_deref(),
+            });
+            let incremental = incremental.then_some(max_duration);
+            commands::verify_store::run(&engine, repair.as_ref(), incremental, json_output)
+        }
+        Commands::Du => commands::du::run(&engine, json_output),
         Commands::Push {
             env_id,
             tag,
             remote,
+            age_recipients,
+            jobs,
+            sign,
         } => commands::push::run(
             &engine,
             &env_id,
-            tag.as_deref(),
+            &commands::push::PushArgs {
+                tag: tag.as_deref(),
+                remote_url: remote.as_deref(),
+                age_recipients: &age_recipients,
+                jobs,
+                sign,
+                json: json_output,
+            },
+        ),
+        Commands::Pull {
+            reference: Some(reference),
+            remote,
+            age_identity,
+            jobs,
+            ..
+        } => commands::pull::run(
+            &engine,
+            &reference,
             remote.as_deref(),
+            age_identity.as_deref(),
+            jobs,
             json_output,
         ),
-        Commands::Pull { reference, remote } => {
-            commands::pull::run(&engine, &reference, remote.as_deref(), json_output)
+        Commands::Pull {
+            reference: None,
+            remote,
+            age_identity,
+            jobs,
+            ..
+        } => commands::sync::pull_all(
+            &engine,
+            &store_path,
+            &commands::sync::SyncArgs {
+                remote_url: remote.as_deref(),
+                age_identity: age_identity.as_deref(),
+                jobs: karapace_core::sync::DEFAULT_SYNC_JOBS,
+                object_jobs: jobs,
+                prune: false,
+                json: json_output,
+            },
+        ),
+        Commands::Sync {
+            manifest,
+            remote,
+            age_identity,
+            prune,
+            jobs,
+        } => commands::sync::run(
+            &engine,
+            &store_path,
+            &manifest,
+            &commands::sync::SyncArgs {
+                remote_url: remote.as_deref(),
+                age_identity: age_identity.as_deref(),
+                jobs,
+                object_jobs: karapace_remote::DEFAULT_CONCURRENCY,
+                prune,
+                json: json_output,
+            },
+        ),
+        Commands::Clone { env_id, new_name } => {
+            commands::clone::run(&engine, &store_path, &env_id, &new_name, json_output)
+        }
+        Commands::Label { env_id, changes } => {
+            commands::label::run(&engine, &store_path, &env_id, &changes, json_output)
         }
-        Commands::Rename { env_id, new_name } => {
-            commands::rename::run(&engine, &store_path, &env_id, &new_name)
+        Commands::Rename {
+            env_id,
+            new_name,
+            no_alias,
+        } => commands::rename::run(&engine, &store_path, &env_id, &new_name, no_alias),
+        Commands::Image { action } => {
+            commands::image::run(&engine, &store_path, &action, json_output)
+        }
+        Commands::Manifest { action } => commands::manifest::run(&action, json_output),
+        Commands::Remote { action } => commands::remote::run(&store_path, &action, json_output),
+        Commands::Autosnap { action } => {
+            commands::autosnap::run(&engine, &store_path, &action, json_output)
+        }
+        Commands::Workspace { action } => {
+            commands::workspace::run(&engine, &store_path, &action, json_output)
         }
         Commands::Completions { shell } => commands::completions::run::<Cli>(shell),
         Commands::ManPages { dir } => commands::man_pages::run::<Cli>(&dir),
         Commands::Tui => commands::tui::run(&store_path, json_output),
-        Commands::Doctor => commands::doctor::run(&store_path, json_output),
-        Commands::Migrate => commands::migrate::run(&store_path, json_output),
+        Commands::Doctor { rebuild_index } => {
+            commands::doctor::run(&store_path, rebuild_index, json_output)
+        }
+        Commands::Migrate { metadata_backend } => {
+            commands::migrate::run(&store_path, metadata_backend.as_deref(), json_output)
+        }
+        #[cfg(feature = "chaos")]
+        Commands::Chaos { dir, cycles, seed } => {
+            commands::chaos::run(dir.as_deref(), cycles, seed, json_output)
+        }
     };
 
     match result {
@@ -383,6 +1025,27 @@ fn main() -> ExitCode {
     }
 }
 
+/// Directory store discovery starts from: the manifest's directory for
+/// manifest-driven commands, otherwise the working directory.
+fn discovery_start_dir(command: &Commands) -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let (Commands::Build { manifest, .. }
+    | Commands::Rebuild { manifest, .. }
+    | Commands::Check { manifest, .. }
+    | Commands::Pin { manifest, .. }
+    | Commands::Import {
+        output: manifest, ..
+    }
+    | Commands::Sync { manifest, .. }) = command
+    else {
+        return cwd;
+    };
+    match manifest.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => cwd.join(dir),
+        _ => cwd,
+    }
+}
+
 fn expand_tilde(path: &str) -> PathBuf {
     if let Some(stripped) = path.strip_prefix("~/") {
         if let Ok(home) = std::env::var("HOME") {
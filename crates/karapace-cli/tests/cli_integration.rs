@@ -178,6 +178,58 @@ fn cli_build_offline_fails_fast_with_packages() {
     );
 }
 
+#[test]
+fn cli_check_frozen_passes_after_build() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_arg = store.path().to_string_lossy().to_string();
+
+    let build = karapace_bin()
+        .args(["--store", &store_arg, "build", &manifest.to_string_lossy()])
+        .output()
+        .unwrap();
+    assert!(build.status.success());
+
+    let output = karapace_bin()
+        .args([
+            "--store",
+            &store_arg,
+            "--json",
+            "check",
+            &manifest.to_string_lossy(),
+            "--frozen",
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "check --frozen must exit 0 on a fresh lock. stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["frozen"], true);
+}
+
+#[test]
+fn cli_check_fails_without_lock() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+
+    let output = karapace_bin()
+        .args([
+            "--store",
+            &store.path().to_string_lossy(),
+            "check",
+            &manifest.to_string_lossy(),
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "check without a lock must fail");
+}
+
 #[test]
 fn cli_snapshots_restore_hash_matches_commit() {
     let store = temp_store();
@@ -344,6 +396,49 @@ fn cli_inspect_json_output_stable() {
         .unwrap_or_else(|e| panic!("inspect --json must produce valid JSON: {e}\n{stdout}"));
     assert_eq!(inspect_json["env_id"].as_str().unwrap(), env_id);
     assert_eq!(inspect_json["state"].as_str().unwrap(), "Built");
+    assert!(inspect_json["disk_usage_bytes"].is_u64());
+    assert!(inspect_json["disk_limit_mb"].is_null());
+}
+
+#[test]
+fn cli_inspect_reports_disk_limit() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = project.path().join("karapace.toml");
+    std::fs::write(
+        &manifest,
+        r#"manifest_version = 1
+
+[base]
+image = "rolling"
+
+[runtime]
+backend = "mock"
+
+[runtime.resource_limits]
+disk_limit_mb = 64
+"#,
+    )
+    .unwrap();
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let build = karapace(&["--json", "build", &manifest.to_string_lossy()]);
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap();
+
+    let text = karapace(&["inspect", env_id]);
+    assert!(String::from_utf8_lossy(&text.stdout).contains("(limit 64 MB)"));
+    let json = karapace(&["--json", "inspect", env_id]);
+    let inspect_json: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(inspect_json["disk_limit_mb"], 64);
 }
 
 // A5: CLI Validation — destroy succeeds
@@ -387,6 +482,174 @@ fn cli_destroy_succeeds() {
     );
 }
 
+// Renamed environments stay reachable through their former name
+#[test]
+fn cli_rename_keeps_old_name_as_alias() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_arg = store.path().to_string_lossy().to_string();
+
+    let build_out = karapace_bin()
+        .args([
+            "--store",
+            &store_arg,
+            "build",
+            "--name",
+            "old-name",
+            &manifest.to_string_lossy(),
+        ])
+        .output()
+        .unwrap();
+    assert!(build_out.status.success());
+
+    let rename_out = karapace_bin()
+        .args(["--store", &store_arg, "rename", "old-name", "new-name"])
+        .output()
+        .unwrap();
+    assert!(rename_out.status.success());
+
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "--json", "inspect", "old-name"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("former name"), "stderr: {stderr}");
+    let json: serde_json::Value =
+        serde_json::from_str(&String::from_utf8_lossy(&output.stdout)).unwrap();
+    assert_eq!(json["name"].as_str().unwrap(), "new-name");
+    assert_eq!(json["aliases"][0].as_str().unwrap(), "old-name");
+}
+
+#[test]
+fn cli_clone_creates_a_named_copy() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_arg = store.path().to_string_lossy().to_string();
+
+    let build_out = karapace_bin()
+        .args([
+            "--store",
+            &store_arg,
+            "build",
+            "--name",
+            "origin",
+            &manifest.to_string_lossy(),
+        ])
+        .output()
+        .unwrap();
+    assert!(build_out.status.success());
+
+    let clone_out = karapace_bin()
+        .args([
+            "--store", &store_arg, "--json", "clone", "origin", "variant",
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        clone_out.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&clone_out.stderr)
+    );
+    let json: serde_json::Value =
+        serde_json::from_str(&String::from_utf8_lossy(&clone_out.stdout)).unwrap();
+    assert_ne!(json["env_id"], json["source"]);
+
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "--json", "inspect", "variant"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let inspected: serde_json::Value =
+        serde_json::from_str(&String::from_utf8_lossy(&output.stdout)).unwrap();
+    assert_eq!(inspected["env_id"], json["env_id"]);
+    assert_eq!(inspected["state"], "Built");
+
+    let again = karapace_bin()
+        .args(["--store", &store_arg, "clone", "origin", "variant"])
+        .output()
+        .unwrap();
+    assert!(!again.status.success());
+}
+
+#[test]
+fn cli_workspace_use_and_list() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_arg = store.path().to_string_lossy().to_string();
+
+    let build_out = karapace_bin()
+        .args([
+            "--store",
+            &store_arg,
+            "build",
+            "--name",
+            "ws-env",
+            &manifest.to_string_lossy(),
+        ])
+        .output()
+        .unwrap();
+    assert!(build_out.status.success());
+
+    let use_out = karapace_bin()
+        .args([
+            "--store",
+            &store_arg,
+            "workspace",
+            "use",
+            "ws-env",
+            "experiment-a",
+            "--branch",
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        use_out.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&use_out.stderr)
+    );
+
+    let output = karapace_bin()
+        .args([
+            "--store",
+            &store_arg,
+            "--json",
+            "workspace",
+            "list",
+            "ws-env",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value =
+        serde_json::from_str(&String::from_utf8_lossy(&output.stdout)).unwrap();
+    let workspaces = json["workspaces"].as_array().unwrap();
+    assert_eq!(workspaces.len(), 2);
+    assert_eq!(workspaces[0]["name"], "default");
+    assert_eq!(workspaces[0]["active"], false);
+    assert_eq!(workspaces[1]["name"], "experiment-a");
+    assert_eq!(workspaces[1]["active"], true);
+
+    let rm_out = karapace_bin()
+        .args([
+            "--store",
+            &store_arg,
+            "workspace",
+            "rm",
+            "ws-env",
+            "experiment-a",
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        !rm_out.status.success(),
+        "active workspace must not be removed"
+    );
+}
+
 // A5: CLI Validation — build with nonexistent manifest fails
 #[test]
 fn cli_build_nonexistent_manifest_fails() {
@@ -444,42 +707,1434 @@ fn cli_gc_empty_store_succeeds() {
     );
 }
 
-// A5: CLI Validation — verify-store on clean store
 #[test]
-fn cli_verify_store_clean() {
+fn cli_image_list_and_rm() {
+    let store = temp_store();
+    let store_arg = store.path().to_string_lossy().into_owned();
+    let rootfs = store.path().join("images/debian-bookworm/rootfs");
+    std::fs::create_dir_all(rootfs.join("etc")).unwrap();
+    std::fs::write(
+        store.path().join("images/debian-bookworm/rootfs.blake3"),
+        "ab".repeat(32),
+    )
+    .unwrap();
+
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "--json", "image", "list"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let images: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(images[0]["cache_key"], "debian-bookworm");
+    assert_eq!(images[0]["digest"], "ab".repeat(32));
+
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "image", "rm", "debian/bookworm"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "image rm must exit 0. stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!rootfs.exists());
+
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "image", "rm", "debian/bookworm"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn cli_gc_older_than_evicts_archived_env() {
     let store = temp_store();
+    let store_arg = store.path().to_string_lossy().to_string();
     let project = tempfile::tempdir().unwrap();
     let manifest = write_test_manifest(project.path());
-
-    // Build to populate store
-    let _ = karapace_bin()
+    let build = karapace_bin()
         .args([
             "--store",
-            &store.path().to_string_lossy(),
+            &store_arg,
+            "--json",
             "build",
             &manifest.to_string_lossy(),
         ])
         .output()
         .unwrap();
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap().to_owned();
+
+    let bad = karapace_bin()
+        .args(["--store", &store_arg, "gc", "--older-than", "30"])
+        .output()
+        .unwrap();
+    assert!(
+        !bad.status.success(),
+        "an age without unit must be rejected"
+    );
+
+    // Not archived yet: retention leaves it alone.
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "--json", "gc", "--older-than", "0s"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["evicted_envs"], serde_json::json!([]));
+
+    let archive = karapace_bin()
+        .args(["--store", &store_arg, "archive", &env_id])
+        .output()
+        .unwrap();
+    assert!(archive.status.success());
 
     let output = karapace_bin()
         .args([
             "--store",
-            &store.path().to_string_lossy(),
+            &store_arg,
             "--json",
-            "verify-store",
+            "gc",
+            "--older-than",
+            "0s",
+            "--max-store-size",
+            "1T",
         ])
         .output()
         .unwrap();
-
     assert!(
         output.status.success(),
-        "verify-store must exit 0. stderr: {}",
+        "stderr: {}",
         String::from_utf8_lossy(&output.stderr)
     );
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["evicted_envs"], serde_json::json!([env_id]));
+    assert_eq!(report["removed_envs"], 1);
+    assert!(report["store_size_before"].as_u64().unwrap() > 0);
+    assert!(report["store_size_after"].as_u64().is_some());
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let json: serde_json::Value = serde_json::from_str(&stdout)
-        .unwrap_or_else(|e| panic!("verify-store --json must produce valid JSON: {e}\n{stdout}"));
-    assert_eq!(json["failed"].as_u64().unwrap(), 0);
+#[test]
+fn cli_repack_and_unpack_keep_store_readable() {
+    let store = temp_store();
+    let store_arg = store.path().to_string_lossy().to_string();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let build = karapace_bin()
+        .args(["--store", &store_arg, "build", &manifest.to_string_lossy()])
+        .output()
+        .unwrap();
+    assert!(build.status.success());
+
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "--json", "repack"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value =
+        serde_json::from_str(&String::from_utf8_lossy(&output.stdout)).unwrap();
+    assert!(json["loose_packed"].as_u64().unwrap() > 0);
+
+    for args in [
+        &["verify-store"][..],
+        &["repack", "--unpack"],
+        &["verify-store"],
+    ] {
+        let output = karapace_bin()
+            .args(["--store", &store_arg])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn cli_lock_wait_zero_reports_holder() {
+    let store = temp_store();
+    let store_arg = store.path().to_string_lossy().to_string();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let build = karapace_bin()
+        .args(["--store", &store_arg, "build", &manifest.to_string_lossy()])
+        .output()
+        .unwrap();
+    assert!(build.status.success());
+
+    let lock_path = store.path().join("store").join(".lock");
+    let _held = karapace_core::StoreLock::acquire_for(&lock_path, "test-holder").unwrap();
+
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "--lock-wait", "0", "gc"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("test-holder"), "stderr: {stderr}");
+
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "gc"])
+        .env("KARAPACE_LOCK_WAIT", "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("waiting for test-holder"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn cli_du_reports_categories_and_environments() {
+    let store = temp_store();
+    let store_arg = store.path().to_string_lossy().to_string();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let build = karapace_bin()
+        .args([
+            "--store",
+            &store_arg,
+            "--json",
+            "build",
+            &manifest.to_string_lossy(),
+        ])
+        .output()
+        .unwrap();
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+
+    let output = karapace_bin()
+        .args(["--store", &store_arg, "--json", "du"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "du must exit 0. stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let usage: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(usage["objects_bytes"].as_u64().unwrap() > 0);
+    let total: u64 = [
+        "objects_bytes",
+        "layers_bytes",
+        "env_bytes",
+        "images_bytes",
+        "wal_bytes",
+        "other_bytes",
+    ]
+    .iter()
+    .map(|key| usage[key].as_u64().unwrap())
+    .sum();
+    assert_eq!(usage["total_bytes"].as_u64().unwrap(), total);
+    assert_eq!(usage["envs"][0]["env_id"], build_json["env_id"]);
+    assert!(usage["envs"][0]["exclusive_bytes"].as_u64().unwrap() > 0);
+
+    let table = karapace_bin()
+        .args(["--store", &store_arg, "du"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&table.stdout);
+    assert!(stdout.contains("total"), "{stdout}");
+    assert!(stdout.contains("ATTRIBUTED"), "{stdout}");
+}
+
+// A5: CLI Validation — verify-store on clean store
+#[test]
+fn cli_verify_store_clean() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+
+    // Build to populate store
+    let _ = karapace_bin()
+        .args([
+            "--store",
+            &store.path().to_string_lossy(),
+            "build",
+            &manifest.to_string_lossy(),
+        ])
+        .output()
+        .unwrap();
+
+    let output = karapace_bin()
+        .args([
+            "--store",
+            &store.path().to_string_lossy(),
+            "--json",
+            "verify-store",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "verify-store must exit 0. stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let json: serde_json::Value = serde_json::from_str(&stdout)
+        .unwrap_or_else(|e| panic!("verify-store --json must produce valid JSON: {e}\n{stdout}"));
+    assert_eq!(json["failed"].as_u64().unwrap(), 0);
+}
+
+#[test]
+fn cli_verify_store_incremental_records_progress() {
+    let store = temp_store();
+    let store_arg = store.path().to_string_lossy().to_string();
+    let scrub = |budget: &str| {
+        let output = karapace_bin()
+            .args([
+                "--store",
+                &store_arg,
+                "--json",
+                "verify-store",
+                "--incremental",
+                "--max-duration",
+                budget,
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["scrub"].clone()
+    };
+
+    let first = scrub("0s");
+    assert_eq!(first["buckets"], 1);
+    assert_eq!(first["never_verified"], 255);
+    let rest = scrub("1h");
+    assert_eq!(rest["never_verified"], 0);
+    assert!(rest["oldest"].is_string());
+    assert!(store.path().join("store/scrub.json").exists());
+}
+
+#[test]
+fn cli_verify_store_repair_quarantines_corrupt_objects() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_arg = store.path().to_string_lossy().to_string();
+    let build = karapace_bin()
+        .args(["--store", &store_arg, "build", &manifest.to_string_lossy()])
+        .output()
+        .unwrap();
+    assert!(build.status.success());
+
+    let objects = store.path().join("store/objects");
+    let victim = std::fs::read_dir(&objects)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.is_file())
+        .unwrap();
+    std::fs::write(&victim, b"bit rot").unwrap();
+
+    let output = karapace_bin()
+        .env("HOME", store.path())
+        .args(["--store", &store_arg, "--json", "verify-store", "--repair"])
+        .output()
+        .unwrap();
+    assert_eq!(
+        output.status.code(),
+        Some(3),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["failed"].as_u64().unwrap(), 1);
+    assert_eq!(json["repair"]["quarantined"].as_array().unwrap().len(), 1);
+    assert!(!json["repair"]["lost"].as_array().unwrap().is_empty());
+    let name = victim.file_name().unwrap();
+    assert!(!victim.exists());
+    assert!(store
+        .path()
+        .join("store/quarantine/objects")
+        .join(name)
+        .exists());
+}
+
+#[test]
+fn cli_discovers_project_local_store() {
+    let home = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let local_store = project.path().join(".karapace/store");
+    std::fs::create_dir_all(&local_store).unwrap();
+    let manifest = write_test_manifest(project.path());
+
+    let output = karapace_bin()
+        .env("HOME", home.path())
+        .env_remove("KARAPACE_STORE")
+        .args(["build", &manifest.to_string_lossy()])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "build must exit 0. stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(local_store.join("store").is_dir());
+    assert!(project.path().join(".karapace/.gitignore").is_file());
+    assert!(!home.path().join(".local/share/karapace").exists());
+
+    let output = karapace_bin()
+        .env("HOME", home.path())
+        .env_remove("KARAPACE_STORE")
+        .current_dir(project.path())
+        .args(["list", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let envs: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(envs.as_array().map(Vec::len), Some(1));
+}
+
+#[test]
+fn cli_build_warns_about_deprecated_manifest_keys() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = project.path().join("karapace.toml");
+    std::fs::write(
+        &manifest,
+        "manifest_version = 1\n\n[base]\nimage = \"rolling\"\n\n[hardware]\naudio = true\n\n[runtime]\nbackend = \"mock\"\n",
+    )
+    .unwrap();
+
+    let output = karapace_bin()
+        .args([
+            "--store",
+            &store.path().to_string_lossy(),
+            "build",
+            &manifest.to_string_lossy(),
+        ])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success(),
+        "build must exit 0. stderr: {stderr}"
+    );
+    assert!(
+        stderr.contains("`hardware.audio` is deprecated, use `hardware.audio_out`"),
+        "stderr: {stderr}"
+    );
+
+    let output = karapace_bin()
+        .args([
+            "--store",
+            &store.path().to_string_lossy(),
+            "--json",
+            "build",
+            &manifest.to_string_lossy(),
+        ])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["warnings"][0]["key"], "hardware.audio");
+}
+
+#[cfg(feature = "chaos")]
+#[test]
+fn cli_chaos_recovers_and_stays_hidden() {
+    let help = karapace_bin().arg("--help").output().unwrap();
+    assert!(!String::from_utf8_lossy(&help.stdout).contains("chaos"));
+
+    let store = temp_store();
+    let scratch = tempfile::tempdir().unwrap();
+    let output = karapace_bin()
+        .env("KARAPACE_LOG", "off")
+        .args([
+            "--store",
+            &store.path().to_string_lossy(),
+            "--json",
+            "chaos",
+            &scratch.path().to_string_lossy(),
+            "--cycles",
+            "12",
+            "--seed",
+            "7",
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "chaos failed: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["seed"], 7);
+    assert_eq!(report["cycles"], 12);
+    assert_eq!(report["failures"], serde_json::json!([]));
+}
+
+#[test]
+fn cli_init_exits_with_command_status() {
+    let status = karapace_bin()
+        .args(["__karapace-init", "--", "/bin/sh", "-c", "exit 3"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+
+    let status = karapace_bin()
+        .args(["__karapace-init", "--", "/nonexistent/command"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(127));
+}
+
+#[test]
+fn cli_init_forwards_sigterm() {
+    let mut init = karapace_bin()
+        .args([
+            "__karapace-init",
+            "--",
+            "/bin/sh",
+            "-c",
+            "trap 'exit 42' TERM; while :; do sleep 0.05; done",
+        ])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    let killed = Command::new("kill")
+        .args(["-TERM", &init.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    assert_eq!(init.wait().unwrap().code(), Some(42));
+}
+
+#[test]
+fn cli_init_reaps_orphans() {
+    // The inner subshell exits at once, orphaning `true`, which the init
+    // adopts as a subreaper and must reap while the session keeps running.
+    let mut init = karapace_bin()
+        .args([
+            "__karapace-init",
+            "--",
+            "/bin/sh",
+            "-c",
+            "(/bin/true &); sleep 1",
+        ])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    let init_pid = init.id().to_string();
+    let zombies: Vec<String> = std::fs::read_dir("/proc")
+        .unwrap()
+        .filter_map(|e| std::fs::read_to_string(e.ok()?.path().join("stat")).ok())
+        .filter(|stat| {
+            // Fields after the parenthesised command: state, ppid, ...
+            let rest = stat.rsplit_once(')').map_or("", |(_, r)| r);
+            let mut fields = rest.split_whitespace();
+            fields.next() == Some("Z") && fields.next() == Some(init_pid.as_str())
+        })
+        .collect();
+    assert!(zombies.is_empty(), "unreaped children: {zombies:?}");
+    assert!(init.wait().unwrap().success());
+}
+
+#[test]
+fn cli_remote_refresh_reuses_unchanged_registry() {
+    let server_dir = tempfile::tempdir().unwrap();
+    let server = karapace_server::TestServer::start(server_dir.path().to_path_buf());
+    let store = temp_store();
+    let store_path = store.path().to_string_lossy().to_string();
+    let refresh = || {
+        let output = karapace_bin()
+            .args(["--store", &store_path, "--json", "remote", "refresh"])
+            .args(["--remote", &server.url])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        (
+            report["status"].as_str().unwrap().to_owned(),
+            report["entries"].as_u64().unwrap(),
+        )
+    };
+    assert_eq!(refresh(), ("empty".to_owned(), 0));
+
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    for args in [
+        vec!["build", &manifest.to_str().unwrap(), "--name", "dev"],
+        vec![
+            "push",
+            "dev",
+            "--tag",
+            "dev@latest",
+            "--remote",
+            &server.url,
+        ],
+    ] {
+        let output = karapace_bin()
+            .args(["--store", &store_path])
+            .args(&args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{args:?}");
+    }
+
+    assert_eq!(refresh(), ("updated".to_owned(), 1));
+    assert_eq!(refresh(), ("unchanged".to_owned(), 1));
+    assert_eq!(
+        std::fs::read_dir(store.path().join("store/registry-cache"))
+            .unwrap()
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn cli_remote_tags_and_untag() {
+    let server_dir = tempfile::tempdir().unwrap();
+    let server = karapace_server::TestServer::start(server_dir.path().to_path_buf());
+    let store = temp_store();
+    let store_path = store.path().to_string_lossy().to_string();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let run = |args: &[&str]| {
+        let output = karapace_bin()
+            .args(["--store", &store_path, "--json"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap_or_default()
+    };
+    run(&["build", manifest.to_str().unwrap(), "--name", "dev"]);
+    for tag in ["dev@latest", "dev@v1"] {
+        run(&["push", "dev", "--tag", tag, "--remote", &server.url]);
+    }
+
+    let tags = |run: &dyn Fn(&[&str]) -> serde_json::Value| -> Vec<String> {
+        run(&["remote", "tags", "dev", "--remote", &server.url])
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["tag"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    assert_eq!(tags(&run), ["latest", "v1"]);
+
+    let report = run(&["remote", "untag", "dev@v1", "--remote", &server.url]);
+    assert_eq!(report["untagged"], "dev@v1");
+    assert_eq!(tags(&run), ["latest"]);
+
+    let output = karapace_bin()
+        .args(["--store", &store_path, "remote", "untag", "dev"])
+        .args(["--remote", &server.url])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("name@tag"));
+}
+
+#[test]
+fn cli_sync_pulls_listed_refs_and_prunes() {
+    let server_dir = tempfile::tempdir().unwrap();
+    let server = karapace_server::TestServer::start(server_dir.path().to_path_buf());
+    let source = temp_store();
+    let source_store = source.path().to_string_lossy().to_string();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    for args in [
+        vec!["build", &manifest.to_str().unwrap(), "--name", "dev"],
+        vec![
+            "push",
+            "dev",
+            "--tag",
+            "dev@latest",
+            "--remote",
+            &server.url,
+        ],
+        vec!["push", "dev", "--tag", "ci@latest", "--remote", &server.url],
+    ] {
+        let output = karapace_bin()
+            .args(["--store", &source_store])
+            .args(&args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let target = temp_store();
+    let sync_file = project.path().join("karapace-sync.toml");
+    let sync = |refs: &str| {
+        std::fs::write(
+            &sync_file,
+            format!("remote = \"{}\"\nrefs = [{refs}]\n", server.url),
+        )
+        .unwrap();
+        let output = karapace_bin()
+            .args([
+                "--store",
+                &target.path().to_string_lossy(),
+                "--json",
+                "sync",
+            ])
+            .arg(&sync_file)
+            .arg("--prune")
+            .output()
+            .unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let statuses: Vec<(String, String)> = report["outcomes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|o| {
+                (
+                    o["reference"].as_str().unwrap().to_owned(),
+                    o["status"].as_str().unwrap().to_owned(),
+                )
+            })
+            .collect();
+        (output.status.code(), statuses)
+    };
+    let pair = |r: &str, s: &str| (r.to_owned(), s.to_owned());
+
+    let (code, statuses) = sync("\"dev@latest\", \"nope@latest\"");
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        statuses,
+        [pair("dev@latest", "pulled"), pair("nope@latest", "failed")]
+    );
+
+    let (code, statuses) = sync("\"dev@latest\"");
+    assert_eq!(code, Some(0));
+    assert_eq!(statuses, [pair("dev@latest", "up_to_date")]);
+
+    let (code, statuses) = sync("");
+    assert_eq!(code, Some(0));
+    assert_eq!(statuses, [pair("dev@latest", "removed")]);
+
+    // pull --all takes every registry entry; both tags name the same env.
+    let output = karapace_bin()
+        .args([
+            "--store",
+            &target.path().to_string_lossy(),
+            "--json",
+            "pull",
+        ])
+        .args(["--all", "--remote", &server.url])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let refs: Vec<&str> = report["outcomes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| o["reference"].as_str().unwrap())
+        .collect();
+    assert_eq!(refs, ["ci@latest", "dev@latest"]);
+}
+
+#[test]
+fn cli_push_and_pull_over_ssh() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Runs the remote scripts locally instead of on a host.
+    let remote_dir = tempfile::tempdir().unwrap();
+    let ssh = remote_dir.path().join("ssh");
+    std::fs::write(
+        &ssh,
+        "#!/bin/sh\nwhile [ \"$1\" != -- ]; do shift; done\nexec sh -c \"$3\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let remote = format!("ssh://me@host{}/store", remote_dir.path().display());
+
+    let source = temp_store();
+    let target = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let karapace = |store: &tempfile::TempDir, args: &[&str]| {
+        let output = karapace_bin()
+            .args(["--store", &store.path().to_string_lossy(), "--json"])
+            .args(args)
+            .env("KARAPACE_SSH", &ssh)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    let built = karapace(&source, &["build", manifest.to_str().unwrap()]);
+    let env_id = built["env_id"].as_str().unwrap();
+    karapace(
+        &source,
+        &["push", env_id, "--tag", "dev@latest", "--remote", &remote],
+    );
+    assert!(remote_dir.path().join("store/registry").is_file());
+    assert!(remote_dir
+        .path()
+        .join("store/metadata")
+        .join(env_id)
+        .is_file());
+
+    let pulled = karapace(&target, &["pull", "dev@latest", "--remote", &remote]);
+    assert_eq!(pulled["env_id"], env_id);
+    let listed = karapace(&target, &["list"]);
+    assert_eq!(listed[0]["env_id"], env_id);
+}
+
+#[test]
+fn cli_attest_exports_and_verifies() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path, "--json"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let build = karapace(&["build", &manifest.to_string_lossy()]);
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap();
+
+    let exported = project.path().join("attestation.json");
+    let export = karapace(&["attest", env_id, "--output", &exported.to_string_lossy()]);
+    assert!(export.status.success());
+    let envelope: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&exported).unwrap()).unwrap();
+    assert_eq!(envelope["payloadType"], "application/vnd.in-toto+json");
+    let keyid = envelope["signatures"][0]["keyid"].as_str().unwrap();
+
+    let verify = karapace(&["attest", env_id, "--key", keyid]);
+    assert!(
+        verify.status.success(),
+        "{}",
+        String::from_utf8_lossy(&verify.stderr)
+    );
+    let report: serde_json::Value = serde_json::from_slice(&verify.stdout).unwrap();
+    assert_eq!(report["verified"], true);
+    assert_eq!(report["statement"]["subject"][0]["name"], env_id);
+
+    let wrong_key = karapace(&["attest", env_id, "--key", &"0".repeat(64)]);
+    assert!(!wrong_key.status.success());
+}
+
+#[test]
+fn cli_exec_exits_with_the_command_code() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let build = karapace(&["--json", "build", &manifest.to_string_lossy()]);
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap();
+
+    let ok = karapace(&["exec", env_id, "--", "echo", "hi"]);
+    assert!(ok.status.success());
+    assert_eq!(String::from_utf8_lossy(&ok.stdout), "mock-exec: echo hi\n");
+
+    let failed = karapace(&["exec", env_id, "--", "exit", "3"]);
+    assert_eq!(failed.status.code(), Some(3));
+    assert!(
+        failed.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&failed.stderr)
+    );
+
+    let entered = karapace(&["enter", env_id, "--", "false"]);
+    assert_eq!(entered.status.code(), Some(1));
+}
+
+#[test]
+fn cli_enter_detach_and_attach() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let build = karapace(&["--json", "build", &manifest.to_string_lossy()]);
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap();
+
+    let detached = karapace(&["enter", env_id, "--detach"]);
+    assert!(
+        detached.status.success(),
+        "{}",
+        String::from_utf8_lossy(&detached.stderr)
+    );
+    assert!(String::from_utf8_lossy(&detached.stdout).contains("karapace attach"));
+
+    let attached = karapace(&["attach", env_id, "--", "echo", "hi"]);
+    assert!(attached.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&attached.stdout),
+        "mock-attach: echo hi\n"
+    );
+    let failed = karapace(&["attach", env_id, "--", "exit", "4"]);
+    assert_eq!(failed.status.code(), Some(4));
+
+    assert!(karapace(&["stop", env_id]).status.success());
+    assert!(!karapace(&["attach", env_id]).status.success());
+    assert!(!karapace(&["enter", env_id, "--detach", "--ro"])
+        .status
+        .success());
+}
+
+#[test]
+fn cli_ps_lists_session_processes() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let build = karapace(&["--json", "build", &manifest.to_string_lossy()]);
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap();
+
+    assert!(!karapace(&["ps", env_id]).status.success());
+    assert!(karapace(&["enter", env_id, "--detach"]).status.success());
+
+    let table = karapace(&["ps", env_id]);
+    assert!(table.status.success());
+    let table = String::from_utf8_lossy(&table.stdout);
+    assert!(table.starts_with("     PID"));
+    assert!(table.contains("/bin/sh -l"));
+
+    let json = karapace(&["--json", "ps", env_id]);
+    let processes: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(processes[0]["pid"], 99999);
+    assert_eq!(processes[0]["command"], "/bin/sh -l");
+
+    assert!(karapace(&["stop", env_id]).status.success());
+}
+
+#[test]
+fn cli_destroy_is_all_or_nothing() {
+    let store = temp_store();
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let projects = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    let manifests = [
+        write_minimal_manifest(projects[0].path(), "rolling"),
+        write_test_manifest(projects[1].path()),
+    ];
+    let env_ids: Vec<String> = manifests
+        .iter()
+        .map(|manifest| {
+            let build = karapace(&["--json", "build", &manifest.to_string_lossy()]);
+            assert!(build.status.success());
+            let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+            build_json["env_id"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert!(karapace(&["enter", &env_ids[1], "--detach"])
+        .status
+        .success());
+
+    let refused = karapace(&["destroy", &env_ids[0], &env_ids[1]]);
+    assert!(!refused.status.success());
+    assert!(karapace(&["inspect", &env_ids[0]]).status.success());
+
+    assert!(karapace(&["stop", &env_ids[1]]).status.success());
+    let destroyed = karapace(&["destroy", &env_ids[0], &env_ids[1], "--gc"]);
+    assert!(destroyed.status.success());
+    let list = karapace(&["--json", "list"]);
+    let envs: serde_json::Value = serde_json::from_slice(&list.stdout).unwrap();
+    assert_eq!(envs.as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn cli_history_lists_completed_operations() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let build = karapace(&["--json", "build", &manifest.to_string_lossy()]);
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap().to_owned();
+    assert!(karapace(&["destroy", &env_id]).status.success());
+
+    let json = karapace(&["--json", "history"]);
+    assert!(json.status.success());
+    let records: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(records[0]["kind"], "Destroy");
+    assert_eq!(records[1]["kind"], "Build");
+    assert_eq!(records[1]["env_id"], env_id.as_str());
+    assert_eq!(records[1]["outcome"], "committed");
+
+    // A destroyed environment is still found by its id prefix.
+    let table = karapace(&["history", &env_id[..12], "-n", "1"]);
+    let table = String::from_utf8_lossy(&table.stdout);
+    assert!(table.starts_with("STARTED"));
+    assert!(table.contains("destroy"));
+    assert!(!table.contains("build"));
+}
+
+#[test]
+fn cli_stats_reports_usage() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let build = karapace(&["--json", "build", &manifest.to_string_lossy()]);
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap();
+
+    let table = karapace(&["stats"]);
+    assert!(table.status.success());
+    assert!(String::from_utf8_lossy(&table.stdout).contains("PROCS"));
+
+    assert!(karapace(&["enter", env_id, "--detach"]).status.success());
+    let json = karapace(&["--json", "stats", env_id]);
+    assert!(json.status.success());
+    let rows: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(rows[0]["env_id"], env_id);
+    assert_eq!(rows[0]["state"], "running");
+    assert_eq!(rows[0]["processes"], 1);
+    assert!(rows[0]["disk_bytes"].is_u64());
+
+    assert!(karapace(&["stop", env_id]).status.success());
+    assert!(!karapace(&["stats", "no-such-env"]).status.success());
+}
+
+#[test]
+fn cli_import_builds_on_docker_archive() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let archive = project.path().join("archive");
+    std::fs::create_dir_all(archive.join("rootfs/etc")).unwrap();
+    std::fs::write(archive.join("rootfs/etc/hostname"), "app").unwrap();
+    let tar = |dest: &std::path::Path, dir: &std::path::Path| {
+        assert!(Command::new("tar")
+            .arg("-cf")
+            .arg(dest)
+            .arg("-C")
+            .arg(dir)
+            .arg(".")
+            .status()
+            .unwrap()
+            .success());
+    };
+    tar(&archive.join("layer.tar"), &archive.join("rootfs"));
+    std::fs::remove_dir_all(archive.join("rootfs")).unwrap();
+    std::fs::write(
+        archive.join("config.json"),
+        r#"{"config":{"Cmd":["/bin/sh"]}}"#,
+    )
+    .unwrap();
+    std::fs::write(
+        archive.join("manifest.json"),
+        r#"[{"Config":"config.json","Layers":["layer.tar"]}]"#,
+    )
+    .unwrap();
+    let image = project.path().join("app.tar");
+    tar(&image, &archive);
+
+    let manifest = project.path().join("karapace.toml");
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let import = |extra: &[&str]| {
+        let mut args = vec![
+            "--json",
+            "import",
+            image.to_str().unwrap(),
+            "-o",
+            manifest.to_str().unwrap(),
+            "--backend",
+            "mock",
+        ];
+        args.extend_from_slice(extra);
+        karapace(&args)
+    };
+
+    let output = import(&[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["status"], "built");
+    let reference = json["image"].as_str().unwrap();
+    assert!(reference.starts_with("oci:sha256:"));
+
+    let contents = std::fs::read_to_string(&manifest).unwrap();
+    assert!(contents.contains(&format!("image = \"{reference}\"")));
+    assert!(contents.contains("backend = \"mock\""));
+    assert!(karapace(&["pin", "--check", manifest.to_str().unwrap()])
+        .status
+        .success());
+
+    assert!(!import(&[]).status.success());
+    let again = import(&["--force"]);
+    assert!(again.status.success());
+    let again: serde_json::Value = serde_json::from_slice(&again.stdout).unwrap();
+    assert_eq!(again["cached"], true);
+    assert_eq!(again["env_id"], json["env_id"]);
+}
+
+#[test]
+fn cli_desktop_export_writes_launcher_entries() {
+    let store = temp_store();
+    let home = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .env("HOME", home.path())
+            .output()
+            .unwrap()
+    };
+
+    let build = karapace(&["--json", "build", &manifest.to_string_lossy()]);
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap();
+
+    let apps = store
+        .path()
+        .join("env")
+        .join(env_id)
+        .join("upper/usr/share/applications");
+    std::fs::create_dir_all(&apps).unwrap();
+    std::fs::write(
+        apps.join("org.example.Viewer.desktop"),
+        "[Desktop Entry]\nType=Application\nName=Viewer\nExec=viewer %f\nIcon=viewer\n",
+    )
+    .unwrap();
+
+    assert!(!karapace(&["desktop-export", env_id, "missing"])
+        .status
+        .success());
+    let export = karapace(&["desktop-export", env_id, "org.example.Viewer"]);
+    assert!(export.status.success());
+
+    let short_id = &env_id[..12];
+    let entry = home
+        .path()
+        .join(".local/share/applications")
+        .join(format!("karapace-{short_id}-org.example.Viewer.desktop"));
+    let contents = std::fs::read_to_string(&entry).unwrap();
+    assert!(contents.contains("Name=Viewer (Karapace "));
+    assert!(contents.contains(&format!("enter {short_id} -- viewer %f")));
+
+    let list = karapace(&["--json", "desktop-export", env_id]);
+    let listed: serde_json::Value = serde_json::from_slice(&list.stdout).unwrap();
+    assert_eq!(listed, serde_json::json!(["org.example.Viewer"]));
+
+    assert!(karapace(&["destroy", env_id]).status.success());
+    assert!(!entry.exists());
+}
+
+#[test]
+fn cli_manifest_upgrade_keeps_the_environment_identity() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = project.path().join("karapace.toml");
+    std::fs::write(
+        &manifest,
+        "manifest_version = 1\n[base]\nimage = \"rolling\"\n[hardware]\naudio = true\n\
+         [runtime]\nbackend = \"mock\"\nnetwork_isolation = true\n",
+    )
+    .unwrap();
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path, "--json"])
+            .args(args)
+            .arg(&manifest)
+            .output()
+            .unwrap()
+    };
+    let env_id = |output: std::process::Output| {
+        assert!(output.status.success(), "{output:?}");
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["env_id"].as_str().unwrap().to_owned()
+    };
+
+    let before = env_id(karapace(&["build"]));
+    let output = karapace(&["manifest", "upgrade"]);
+    assert!(output.status.success(), "{output:?}");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["status"], "upgraded");
+
+    let contents = std::fs::read_to_string(&manifest).unwrap();
+    assert!(contents.contains("manifest_version = 2"), "{contents}");
+    assert!(contents.contains("audio_out = true"), "{contents}");
+    assert!(contents.contains("mode = \"isolated\""), "{contents}");
+    assert!(!contents.contains("network_isolation"), "{contents}");
+    assert!(karapace(&["check"]).status.success());
+    assert_eq!(env_id(karapace(&["build"])), before);
+
+    let again = karapace(&["manifest", "upgrade"]);
+    let json: serde_json::Value = serde_json::from_slice(&again.stdout).unwrap();
+    assert_eq!(json["status"], "current");
+}
+
+#[test]
+fn cli_shellenv_shims_run_commands_in_the_environment() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_path = store.path().to_string_lossy().to_string();
+    let karapace = |args: &[&str]| {
+        karapace_bin()
+            .args(["--store", &store_path])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let build = karapace(&["--json", "build", &manifest.to_string_lossy()]);
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+    let env_id = build_json["env_id"].as_str().unwrap();
+
+    let exports = karapace(&["shellenv", env_id, "echo"]);
+    assert!(exports.status.success());
+    let exports = String::from_utf8_lossy(&exports.stdout);
+    assert!(exports.starts_with(&format!("export KARAPACE_ENV='{env_id}'\n")));
+
+    let shellenv = karapace(&["--json", "shellenv", env_id, "echo"]);
+    let json: serde_json::Value = serde_json::from_slice(&shellenv.stdout).unwrap();
+    let shim = std::path::Path::new(json["shims_dir"].as_str().unwrap()).join("echo");
+    let ran = Command::new(&shim)
+        .arg("hi")
+        .env("KARAPACE_SKIP_PREREQS", "1")
+        .current_dir(project.path())
+        .output()
+        .unwrap();
+    assert!(
+        ran.status.success(),
+        "{}",
+        String::from_utf8_lossy(&ran.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&ran.stdout), "mock-exec: echo hi\n");
+
+    assert!(!karapace(&["shellenv", env_id, "../echo"]).status.success());
+}
+
+#[test]
+fn cli_project_binding_lets_commands_omit_the_environment() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let store_arg = store.path().to_string_lossy().to_string();
+
+    let build = karapace_bin()
+        .args([
+            "--store",
+            &store_arg,
+            "--json",
+            "build",
+            "--name",
+            "bound",
+            &manifest.to_string_lossy(),
+        ])
+        .output()
+        .unwrap();
+    assert!(build.status.success());
+    let build_json: serde_json::Value = serde_json::from_slice(&build.stdout).unwrap();
+
+    let state: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(project.path().join(".karapace/state.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(state["env_id"], build_json["env_id"]);
+    assert_eq!(state["name"], "bound");
+
+    // No --store and no env_id: both come from the binding.
+    let nested = project.path().join("src");
+    std::fs::create_dir_all(&nested).unwrap();
+    let exec = karapace_bin()
+        .args(["exec", "--", "echo", "hi"])
+        .env_remove("KARAPACE_STORE")
+        .current_dir(&nested)
+        .output()
+        .unwrap();
+    assert!(
+        exec.status.success(),
+        "{}",
+        String::from_utf8_lossy(&exec.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&exec.stdout),
+        "mock-exec: echo hi\n"
+    );
+
+    let diff = karapace_bin()
+        .args(["--json", "diff"])
+        .env_remove("KARAPACE_STORE")
+        .current_dir(project.path())
+        .output()
+        .unwrap();
+    assert!(diff.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&diff.stdout).unwrap();
+    assert_eq!(report["env_id"], build_json["env_id"]);
+
+    let elsewhere = tempfile::tempdir().unwrap();
+    let unbound = karapace_bin()
+        .args(["--store", &store_arg, "diff"])
+        .current_dir(elsewhere.path())
+        .output()
+        .unwrap();
+    assert!(!unbound.status.success());
+}
+
+/// Run `karapace --progress json` and parse every stderr line as an event.
+fn progress_events(store: &std::path::Path, args: &[&str]) -> Vec<serde_json::Value> {
+    let output = karapace_bin()
+        .env("KARAPACE_LOG", "debug")
+        .args(["--store", &store.to_string_lossy(), "--progress", "json"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect(line))
+        .collect()
+}
+
+fn event_phases(events: &[serde_json::Value]) -> Vec<&str> {
+    events
+        .iter()
+        .filter(|e| e["event"] == "phase")
+        .filter_map(|e| e["phase"].as_str())
+        .collect()
+}
+
+#[test]
+fn cli_progress_json_emits_one_event_per_stderr_line() {
+    let store = temp_store();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_test_manifest(project.path());
+    let text = std::fs::read_to_string(&manifest).unwrap();
+    std::fs::write(&manifest, format!("{text}\n[hardware]\naudio = true\n")).unwrap();
+
+    let events = progress_events(
+        store.path(),
+        &["build", &manifest.to_string_lossy(), "--name", "dev"],
+    );
+    let phases = event_phases(&events);
+    assert_eq!(phases.first(), Some(&"resolve"));
+    assert_eq!(phases.last(), Some(&"pack_layer"));
+    assert!(events.iter().any(|e| e["event"] == "warning"
+        && e["message"]
+            .as_str()
+            .unwrap()
+            .contains("`hardware.audio` is deprecated")));
+    assert!(events
+        .iter()
+        .any(|e| e["event"] == "log" && e["level"] == "debug"));
+
+    let server_dir = tempfile::tempdir().unwrap();
+    let server = karapace_server::TestServer::start(server_dir.path().to_path_buf());
+    let events = progress_events(store.path(), &["push", "dev", "--remote", &server.url]);
+    assert_eq!(event_phases(&events), ["objects", "layers", "metadata"]);
+    let total = events.iter().find(|e| e["event"] == "total").unwrap();
+    assert!(total["layers"].as_u64().unwrap() > 0);
+    assert!(total["bytes"].as_u64().unwrap() > 0);
+
+    let other = temp_store();
+    let list = karapace_bin()
+        .args(["--store", &store.path().to_string_lossy(), "--json", "list"])
+        .output()
+        .unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&list.stdout).unwrap();
+    let env_id = list[0]["env_id"].as_str().unwrap();
+    let events = progress_events(other.path(), &["pull", env_id, "--remote", &server.url]);
+    assert_eq!(event_phases(&events), ["metadata", "layers", "objects"]);
+    assert!(events.iter().any(|e| e["event"] == "total"));
+
+    let bad = karapace_bin()
+        .args(["--progress", "fancy", "list"])
+        .output()
+        .unwrap();
+    assert!(!bad.status.success());
 }
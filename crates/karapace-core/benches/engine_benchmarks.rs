@@ -1,4 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
@@ -144,6 +145,18 @@ fn bench_gc(c: &mut Criterion) {
                         updated_at: "2026-01-01T00:00:00Z".to_owned(),
                         ref_count: u32::from(i < 25),
                         checksum: None,
+                        aliases: Vec::new(),
+                        host_gpu: None,
+                        base_image_digest: None,
+                        workspace: None,
+                        snapshot: None,
+                        expires_after_secs: None,
+                        last_entered_at: None,
+                        enter_count: 0,
+                        last_built_at: None,
+                        labels: BTreeMap::new(),
+                        revision: 0,
+                        attestation: None,
                     };
                     meta_store.put(&meta).unwrap();
                 }
@@ -190,6 +203,43 @@ fn bench_verify_store(c: &mut Criterion) {
     });
 }
 
+/// An upper dir of 64 directories of 32 files of 64 KiB each, 128 MiB.
+fn create_upper_dir(dir: &Path) {
+    for d in 0..64 {
+        let sub = dir.join(format!("dir{d:02}"));
+        fs::create_dir_all(&sub).unwrap();
+        for f in 0..32 {
+            let seed = u8::try_from((d * 32 + f) % 251).unwrap();
+            fs::write(sub.join(format!("file{f:02}")), vec![seed; 64 * 1024]).unwrap();
+        }
+    }
+}
+
+fn bench_pack_layer(c: &mut Criterion) {
+    let upper = tempfile::tempdir().unwrap();
+    create_upper_dir(upper.path());
+    let mut group = c.benchmark_group("pack_layer_2048files");
+    group.sample_size(10);
+    group.bench_function("tar", |b| {
+        b.iter(|| karapace_store::pack_layer(upper.path()).unwrap());
+    });
+    group.bench_function("file_objects", |b| {
+        b.iter_with_setup(
+            || {
+                let store_dir = tempfile::tempdir().unwrap();
+                let layout = karapace_store::StoreLayout::new(store_dir.path());
+                layout.initialize().unwrap();
+                let obj_store = karapace_store::ObjectStore::new(layout);
+                (store_dir, obj_store)
+            },
+            |(_sd, obj_store)| {
+                karapace_store::pack_layer_with_objects(upper.path(), &obj_store).unwrap();
+            },
+        );
+    });
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_build,
@@ -198,5 +248,6 @@ criterion_group!(
     bench_restore,
     bench_gc,
     bench_verify_store,
+    bench_pack_layer,
 );
 criterion_main!(benches);
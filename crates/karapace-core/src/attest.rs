@@ -0,0 +1,501 @@
+//! Signed build provenance.
+//!
+//! Every build records an in-toto statement with a SLSA provenance
+//! predicate: the environment's build layer as subject, the manifest hash,
+//! the lock file, the base image digest, the pinned package versions and the
+//! building host. The statement is wrapped in a DSSE envelope signed with the
+//! store's ed25519 key (`store/attestation.key`, created on first use) and
+//! kept as an object referenced from the environment's metadata, so it
+//! travels with `push` and `pull`.
+//!
+//! A signature's `keyid` is the hex-encoded public key. [`Envelope::verify`]
+//! checks signatures against it; whether that key is trusted is up to the
+//! consumer.
+
+use crate::CoreError;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use karapace_schema::LockFile;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+pub const PROVENANCE_TYPE: &str = "https://slsa.dev/provenance/v1";
+pub const BUILD_TYPE: &str = "https://github.com/marcoallegretti/karapace/build/v1";
+pub const PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+/// An in-toto statement about one environment build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Statement {
+    #[serde(rename = "_type")]
+    pub statement_type: String,
+    pub subject: Vec<Subject>,
+    pub predicate_type: String,
+    pub predicate: Provenance,
+}
+
+/// An artifact the statement is about, by digest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subject {
+    pub name: String,
+    pub digest: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    pub build_definition: BuildDefinition,
+    pub run_details: RunDetails,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildDefinition {
+    pub build_type: String,
+    pub external_parameters: ExternalParameters,
+    pub internal_parameters: InternalParameters,
+    pub resolved_dependencies: Vec<ResourceDescriptor>,
+}
+
+/// What the builder was asked to build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalParameters {
+    /// Object hash of the normalized manifest.
+    pub manifest_hash: String,
+    pub lock: LockFile,
+}
+
+/// How the builder ran the build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InternalParameters {
+    pub backend: String,
+    pub offline: bool,
+}
+
+/// The base image or a package the build installed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceDescriptor {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub digest: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunDetails {
+    pub builder: Builder,
+    pub metadata: BuildMetadata,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Builder {
+    pub id: String,
+    pub version: BTreeMap<String, String>,
+    pub host: HostInfo,
+}
+
+/// The machine the build ran on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostInfo {
+    pub hostname: String,
+    pub kernel: String,
+    pub arch: String,
+}
+
+impl HostInfo {
+    pub fn detect() -> Self {
+        let read = |path: &str| {
+            std::fs::read_to_string(path)
+                .map(|s| s.trim().to_owned())
+                .unwrap_or_default()
+        };
+        Self {
+            hostname: read("/proc/sys/kernel/hostname"),
+            kernel: read("/proc/sys/kernel/osrelease"),
+            arch: std::env::consts::ARCH.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildMetadata {
+    pub invocation_id: String,
+    pub started_on: String,
+    pub finished_on: String,
+}
+
+/// Inputs of [`Statement::for_build`].
+#[derive(Debug, Clone)]
+pub struct BuildRecord<'a> {
+    pub lock: &'a LockFile,
+    pub manifest_hash: &'a str,
+    /// blake3 hash of the packed build layer.
+    pub layer_hash: &'a str,
+    pub backend: &'a str,
+    pub offline: bool,
+    pub started_on: String,
+    pub finished_on: String,
+    pub host: HostInfo,
+}
+
+impl Statement {
+    pub fn for_build(record: BuildRecord<'_>) -> Self {
+        let blake3 = |hash: &str| BTreeMap::from([("blake3".to_owned(), hash.to_owned())]);
+        let mut dependencies = vec![ResourceDescriptor {
+            name: format!("image:{}", record.lock.base_image),
+            digest: blake3(&record.lock.base_image_digest),
+            version: None,
+        }];
+        dependencies.extend(
+            record
+                .lock
+                .resolved_packages
+                .iter()
+                .map(|pkg| ResourceDescriptor {
+                    name: format!("package:{}", pkg.name),
+                    digest: BTreeMap::new(),
+                    version: Some(pkg.version.clone()),
+                }),
+        );
+
+        Self {
+            statement_type: STATEMENT_TYPE.to_owned(),
+            subject: vec![Subject {
+                name: record.lock.env_id.clone(),
+                digest: blake3(record.layer_hash),
+            }],
+            predicate_type: PROVENANCE_TYPE.to_owned(),
+            predicate: Provenance {
+                build_definition: BuildDefinition {
+                    build_type: BUILD_TYPE.to_owned(),
+                    external_parameters: ExternalParameters {
+                        manifest_hash: record.manifest_hash.to_owned(),
+                        lock: record.lock.clone(),
+                    },
+                    internal_parameters: InternalParameters {
+                        backend: record.backend.to_owned(),
+                        offline: record.offline,
+                    },
+                    resolved_dependencies: dependencies,
+                },
+                run_details: RunDetails {
+                    builder: Builder {
+                        id: format!("karapace/{}", env!("CARGO_PKG_VERSION")),
+                        version: BTreeMap::from([(
+                            "karapace".to_owned(),
+                            env!("CARGO_PKG_VERSION").to_owned(),
+                        )]),
+                        host: record.host,
+                    },
+                    metadata: BuildMetadata {
+                        invocation_id: record.lock.env_id.clone(),
+                        started_on: record.started_on,
+                        finished_on: record.finished_on,
+                    },
+                },
+            },
+        }
+    }
+
+    /// The environment the statement is about.
+    pub fn env_id(&self) -> Option<&str> {
+        self.subject.first().map(|s| s.name.as_str())
+    }
+
+    /// blake3 hash of the attested build layer.
+    pub fn layer_hash(&self) -> Option<&str> {
+        self.subject
+            .first()
+            .and_then(|s| s.digest.get("blake3"))
+            .map(String::as_str)
+    }
+}
+
+/// A DSSE envelope around a [`Statement`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub payload_type: String,
+    /// Base64 of the statement's JSON.
+    pub payload: String,
+    pub signatures: Vec<EnvelopeSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvelopeSignature {
+    /// Hex-encoded ed25519 public key.
+    pub keyid: String,
+    /// Base64 of the signature over the DSSE pre-authentication encoding.
+    pub sig: String,
+}
+
+impl Envelope {
+    pub fn sign(statement: &Statement, key: &AttestationKey) -> Result<Self, CoreError> {
+        let payload = serde_json::to_vec(statement)?;
+        let signature = key.key.sign(&pae(PAYLOAD_TYPE, &payload));
+        Ok(Self {
+            payload_type: PAYLOAD_TYPE.to_owned(),
+            payload: BASE64.encode(&payload),
+            signatures: vec![EnvelopeSignature {
+                keyid: key.keyid(),
+                sig: BASE64.encode(signature.to_bytes()),
+            }],
+        })
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, CoreError> {
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, CoreError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Check every signature and return the statement. An envelope without
+    /// signatures does not verify.
+    pub fn verify(&self) -> Result<Statement, CoreError> {
+        if self.payload_type != PAYLOAD_TYPE {
+            return Err(invalid(format!(
+                "unexpected payload type '{}'",
+                self.payload_type
+            )));
+        }
+        if self.signatures.is_empty() {
+            return Err(invalid("attestation is not signed"));
+        }
+        let payload = BASE64
+            .decode(&self.payload)
+            .map_err(|e| invalid(format!("payload is not base64: {e}")))?;
+        let message = pae(&self.payload_type, &payload);
+        for signature in &self.signatures {
+            let key = parse_public_key(&signature.keyid)?;
+            let sig = BASE64
+                .decode(&signature.sig)
+                .ok()
+                .and_then(|bytes| ed25519_dalek::Signature::from_slice(&bytes).ok())
+                .ok_or_else(|| invalid(format!("malformed signature by {}", signature.keyid)))?;
+            key.verify_strict(&message, &sig)
+                .map_err(|_| invalid(format!("bad signature by {}", signature.keyid)))?;
+        }
+        let statement: Statement = serde_json::from_slice(&payload)?;
+        if statement.statement_type != STATEMENT_TYPE || statement.predicate_type != PROVENANCE_TYPE
+        {
+            return Err(invalid("payload is not a provenance statement"));
+        }
+        Ok(statement)
+    }
+
+    pub fn keyids(&self) -> impl Iterator<Item = &str> {
+        self.signatures.iter().map(|s| s.keyid.as_str())
+    }
+}
+
+/// The store's signing key.
+pub struct AttestationKey {
+    key: SigningKey,
+}
+
+impl AttestationKey {
+    /// Read the key at `path`, or create one there. When several builds
+    /// create it at once, the first one written wins.
+    pub fn load_or_create(path: &Path) -> Result<Self, CoreError> {
+        match std::fs::read_to_string(path) {
+            Ok(hex) => {
+                let seed = decode_hex32(hex.trim()).ok_or_else(|| {
+                    CoreError::Attestation(format!("{}: malformed key", path.display()))
+                })?;
+                Ok(Self {
+                    key: SigningKey::from_bytes(&seed),
+                })
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => match Self::create(path) {
+                Err(CoreError::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    Self::load_or_create(path)
+                }
+                result => result,
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn create(path: &Path) -> Result<Self, CoreError> {
+        use std::io::Write;
+        let mut seed = [0u8; 32];
+        std::fs::File::open("/dev/urandom")?.read_exact(&mut seed)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir)?;
+        // Temporary files are created with mode 0600.
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        writeln!(tmp, "{}", encode_hex(&seed))?;
+        tmp.as_file().sync_all()?;
+        tmp.persist_noclobber(path).map_err(|e| e.error)?;
+        Ok(Self {
+            key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    /// The ed25519 key itself, which also signs pushed environments.
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.key
+    }
+
+    /// Hex-encoded public key, used as the `keyid` of signatures.
+    pub fn keyid(&self) -> String {
+        encode_hex(self.key.verifying_key().as_bytes())
+    }
+}
+
+/// DSSE pre-authentication encoding of a payload.
+fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    message.extend_from_slice(payload);
+    message
+}
+
+fn parse_public_key(keyid: &str) -> Result<VerifyingKey, CoreError> {
+    decode_hex32(keyid)
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| invalid(format!("keyid '{keyid}' is not an ed25519 public key")))
+}
+
+fn invalid(message: impl Into<String>) -> CoreError {
+    CoreError::Attestation(message.into())
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write as _;
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+fn decode_hex32(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karapace_schema::{parse_manifest_str, ResolutionResult, ResolvedPackage};
+
+    fn statement() -> Statement {
+        let normalized = parse_manifest_str(
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[system]\npackages = [\"git\"]\n",
+        )
+        .unwrap()
+        .normalize()
+        .unwrap();
+        let lock = LockFile::from_resolved(
+            &normalized,
+            &ResolutionResult {
+                base_image_digest: "b".repeat(64),
+                resolved_packages: vec![ResolvedPackage {
+                    name: "git".to_owned(),
+                    version: "2.44.0-1".to_owned(),
+                }],
+            },
+        );
+        Statement::for_build(BuildRecord {
+            lock: &lock,
+            manifest_hash: "m1",
+            layer_hash: "l1",
+            backend: "mock",
+            offline: false,
+            started_on: "2026-01-01T00:00:00Z".to_owned(),
+            finished_on: "2026-01-01T00:01:00Z".to_owned(),
+            host: HostInfo::detect(),
+        })
+    }
+
+    #[test]
+    fn statement_records_build_inputs() {
+        let statement = statement();
+        let json = serde_json::to_value(&statement).unwrap();
+        assert_eq!(json["_type"], STATEMENT_TYPE);
+        assert_eq!(json["predicateType"], PROVENANCE_TYPE);
+        assert_eq!(statement.layer_hash(), Some("l1"));
+        let definition = &json["predicate"]["buildDefinition"];
+        assert_eq!(definition["externalParameters"]["manifest_hash"], "m1");
+        assert_eq!(
+            definition["externalParameters"]["lock"]["base_image"],
+            "rolling"
+        );
+        assert_eq!(
+            definition["resolvedDependencies"][0]["name"],
+            "image:rolling"
+        );
+        assert_eq!(
+            definition["resolvedDependencies"][0]["digest"]["blake3"],
+            "b".repeat(64)
+        );
+        assert_eq!(definition["resolvedDependencies"][1]["version"], "2.44.0-1");
+        assert_eq!(
+            json["predicate"]["runDetails"]["builder"]["host"]["arch"],
+            std::env::consts::ARCH
+        );
+    }
+
+    #[test]
+    fn signed_envelope_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("attestation.key");
+        let key = AttestationKey::load_or_create(&path).unwrap();
+        let envelope = Envelope::sign(&statement(), &key).unwrap();
+        assert_eq!(envelope.keyids().collect::<Vec<_>>(), [key.keyid()]);
+
+        let reloaded = Envelope::from_json(envelope.to_json().unwrap().as_bytes()).unwrap();
+        assert_eq!(reloaded.verify().unwrap(), statement());
+
+        // The key is reused once created.
+        let again = AttestationKey::load_or_create(&path).unwrap();
+        assert_eq!(again.keyid(), key.keyid());
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn tampered_envelope_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = AttestationKey::load_or_create(&dir.path().join("k")).unwrap();
+        let mut statement = statement();
+        let envelope = Envelope::sign(&statement, &key).unwrap();
+
+        statement.subject[0]
+            .digest
+            .insert("blake3".to_owned(), "l2".to_owned());
+        let mut forged = envelope.clone();
+        forged.payload = BASE64.encode(serde_json::to_vec(&statement).unwrap());
+        assert!(matches!(forged.verify(), Err(CoreError::Attestation(_))));
+
+        let other = AttestationKey::load_or_create(&dir.path().join("other")).unwrap();
+        let mut rekeyed = envelope.clone();
+        rekeyed.signatures[0].keyid = other.keyid();
+        assert!(rekeyed.verify().is_err());
+
+        let mut unsigned = envelope;
+        unsigned.signatures.clear();
+        assert!(unsigned.verify().is_err());
+    }
+}
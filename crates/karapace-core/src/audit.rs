@@ -0,0 +1,886 @@
+//! Vulnerability audit of the packages an environment was built with.
+//!
+//! The packages come from the lock in the environment's attestation, and
+//! are looked up in the distribution's [OSV](https://osv.dev) ecosystem,
+//! which carries the Debian, Ubuntu, Alpine and openSUSE security trackers.
+//! OSV matches versions itself, so a query returns only the
+//! vulnerabilities that affect the exact version installed. Answers are
+//! kept under `store/audit-cache/` and reused for [`CACHE_TTL`].
+
+use crate::CoreError;
+use karapace_runtime::download::Downloader;
+use karapace_runtime::image::{resolve_image, ImageSource};
+use karapace_schema::{LockFile, ResolvedPackage};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The public OSV API, unless `$KARAPACE_OSV_API` names another.
+pub const OSV_API: &str = "https://api.osv.dev/v1";
+
+/// How long cached query results and records are used before asking again.
+pub const CACHE_TTL: Duration = Duration::from_hours(24);
+
+/// Queries sent in one `querybatch` request, the API's limit.
+const QUERY_BATCH: usize = 1000;
+
+/// Images on this server can be pinned by URL; the path still names the
+/// distribution and release.
+const LXC_IMAGE_PREFIX: &str = "https://images.linuxcontainers.org/images/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Unknown,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub const ALL: [Severity; 5] = [
+        Severity::Critical,
+        Severity::High,
+        Severity::Medium,
+        Severity::Low,
+        Severity::Unknown,
+    ];
+
+    /// A rating in the words distributions use (Debian's `unimportant`,
+    /// Red Hat's `important`, GitHub's `moderate`).
+    pub fn from_label(label: &str) -> Self {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "critical" => Self::Critical,
+            "high" | "important" => Self::High,
+            "medium" | "moderate" => Self::Medium,
+            "low" | "negligible" | "unimportant" => Self::Low,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// The CVSS qualitative rating of a base score.
+    pub fn from_score(score: f64) -> Self {
+        match score {
+            s if s >= 9.0 => Self::Critical,
+            s if s >= 7.0 => Self::High,
+            s if s >= 4.0 => Self::Medium,
+            s if s > 0.0 => Self::Low,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Self::from_label(s) {
+            Self::Unknown if !s.eq_ignore_ascii_case("unknown") => Err(format!(
+                "unknown severity '{s}': expected critical, high, medium, low or unknown"
+            )),
+            severity => Ok(severity),
+        }
+    }
+}
+
+/// The parts of an OSV record the audit reads.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OsvRecord {
+    pub id: String,
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub severity: Vec<OsvSeverity>,
+    #[serde(default)]
+    pub affected: Vec<OsvAffected>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_specific: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OsvSeverity {
+    /// `CVSS_V3`, `CVSS_V4`, or a distribution name such as `Ubuntu`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// A CVSS vector, or a distribution's rating.
+    pub score: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OsvAffected {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<OsvPackage>,
+    #[serde(default)]
+    pub ranges: Vec<OsvRange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ecosystem_specific: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OsvPackage {
+    pub ecosystem: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OsvRange {
+    /// Events such as `{"introduced": "0"}` and `{"fixed": "1.2-3"}`.
+    #[serde(default)]
+    pub events: Vec<std::collections::BTreeMap<String, String>>,
+}
+
+impl OsvRecord {
+    /// The worst rating any source in the record gives, for `package` in
+    /// `ecosystem`, and the CVSS v3 base score if there is one.
+    pub fn rating(&self, ecosystem: &str, package: &str) -> (Severity, Option<f64>) {
+        let mut severity = Severity::Unknown;
+        let mut score = None;
+        for entry in &self.severity {
+            if entry.kind == "CVSS_V3" {
+                if let Some(base) = cvss3_base_score(&entry.score) {
+                    score = Some(score.map_or(base, |s: f64| s.max(base)));
+                    severity = severity.max(Severity::from_score(base));
+                }
+            } else {
+                severity = severity.max(Severity::from_label(&entry.score));
+            }
+        }
+        let label = |value: Option<&serde_json::Value>, key: &str| {
+            value
+                .and_then(|v| v.get(key))
+                .and_then(serde_json::Value::as_str)
+                .map_or(Severity::Unknown, Severity::from_label)
+        };
+        severity = severity.max(label(self.database_specific.as_ref(), "severity"));
+        for affected in self.affected_entries(ecosystem, package) {
+            let specific = affected.ecosystem_specific.as_ref();
+            severity = severity
+                .max(label(specific, "urgency"))
+                .max(label(specific, "severity"));
+        }
+        (severity, score)
+    }
+
+    /// The first version the record lists as fixing `package`.
+    pub fn fixed_version(&self, ecosystem: &str, package: &str) -> Option<String> {
+        self.affected_entries(ecosystem, package)
+            .flat_map(|affected| &affected.ranges)
+            .flat_map(|range| &range.events)
+            .find_map(|event| event.get("fixed").cloned())
+    }
+
+    fn affected_entries<'a>(
+        &'a self,
+        ecosystem: &'a str,
+        package: &'a str,
+    ) -> impl Iterator<Item = &'a OsvAffected> {
+        self.affected.iter().filter(move |affected| {
+            affected
+                .package
+                .as_ref()
+                .is_some_and(|p| p.name == package && p.ecosystem == ecosystem)
+        })
+    }
+}
+
+/// Where vulnerability data comes from.
+pub trait VulnFeed {
+    /// The IDs of the vulnerabilities affecting each of `packages` in
+    /// `ecosystem`, in the same order.
+    fn query(
+        &self,
+        ecosystem: &str,
+        packages: &[ResolvedPackage],
+    ) -> Result<Vec<Vec<String>>, CoreError>;
+
+    /// The record of vulnerability `id`.
+    fn record(&self, id: &str) -> Result<OsvRecord, CoreError>;
+}
+
+/// The OSV API over HTTPS.
+pub struct OsvFeed {
+    api: String,
+    downloader: Downloader,
+}
+
+impl Default for OsvFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OsvFeed {
+    /// The API at `$KARAPACE_OSV_API`, or [`OSV_API`].
+    pub fn new() -> Self {
+        let api = std::env::var("KARAPACE_OSV_API").unwrap_or_else(|_| OSV_API.to_owned());
+        Self::with_api(&api)
+    }
+
+    pub fn with_api(api: &str) -> Self {
+        Self {
+            api: api.trim_end_matches('/').to_owned(),
+            downloader: Downloader::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    #[serde(default)]
+    results: Vec<BatchResult>,
+}
+
+#[derive(Deserialize)]
+struct BatchResult {
+    #[serde(default)]
+    vulns: Vec<BatchVuln>,
+}
+
+#[derive(Deserialize)]
+struct BatchVuln {
+    id: String,
+}
+
+impl VulnFeed for OsvFeed {
+    fn query(
+        &self,
+        ecosystem: &str,
+        packages: &[ResolvedPackage],
+    ) -> Result<Vec<Vec<String>>, CoreError> {
+        let url = format!("{}/querybatch", self.api);
+        let mut ids = Vec::with_capacity(packages.len());
+        for batch in packages.chunks(QUERY_BATCH) {
+            let queries: Vec<_> = batch
+                .iter()
+                .map(|pkg| {
+                    serde_json::json!({
+                        "package": { "ecosystem": ecosystem, "name": pkg.name },
+                        "version": pkg.version,
+                    })
+                })
+                .collect();
+            let body = serde_json::json!({ "queries": queries }).to_string();
+            let response: BatchResponse =
+                serde_json::from_str(&self.downloader.post_json(&url, &body)?)?;
+            if response.results.len() != batch.len() {
+                return Err(CoreError::Audit(format!(
+                    "{url} answered {} of {} queries",
+                    response.results.len(),
+                    batch.len()
+                )));
+            }
+            ids.extend(
+                response
+                    .results
+                    .into_iter()
+                    .map(|result| result.vulns.into_iter().map(|v| v.id).collect()),
+            );
+        }
+        Ok(ids)
+    }
+
+    fn record(&self, id: &str) -> Result<OsvRecord, CoreError> {
+        let url = format!("{}/vulns/{id}", self.api);
+        Ok(serde_json::from_str(&self.downloader.get_text(&url)?)?)
+    }
+}
+
+/// How [`CachedFeed`] uses what it has cached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Use entries younger than the TTL, ask the feed for the rest.
+    #[default]
+    Fresh,
+    /// Use whatever is cached, however old; fail on anything missing.
+    Offline,
+    /// Ask the feed for everything and replace the cache.
+    Refresh,
+}
+
+/// A [`VulnFeed`] that keeps the answers of another one on disk.
+pub struct CachedFeed<F> {
+    feed: F,
+    dir: PathBuf,
+    ttl: Duration,
+    policy: CachePolicy,
+}
+
+impl<F: VulnFeed> CachedFeed<F> {
+    pub fn new(feed: F, dir: impl Into<PathBuf>, policy: CachePolicy) -> Self {
+        Self {
+            feed,
+            dir: dir.into(),
+            ttl: CACHE_TTL,
+            policy,
+        }
+    }
+
+    #[must_use]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn query_path(&self, ecosystem: &str, pkg: &ResolvedPackage) -> PathBuf {
+        let key = blake3::hash(format!("{ecosystem}\n{}\n{}", pkg.name, pkg.version).as_bytes());
+        self.dir
+            .join("queries")
+            .join(format!("{}.json", key.to_hex()))
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        let safe = !id.is_empty()
+            && id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+            && !id.starts_with('.');
+        let name = if safe {
+            id.to_owned()
+        } else {
+            blake3::hash(id.as_bytes()).to_hex().to_string()
+        };
+        self.dir.join("records").join(format!("{name}.json"))
+    }
+
+    /// The cached value at `path`, if the policy allows using it.
+    fn cached<T: serde::de::DeserializeOwned>(&self, path: &Path) -> Option<T> {
+        let usable = match self.policy {
+            CachePolicy::Refresh => false,
+            CachePolicy::Offline => true,
+            CachePolicy::Fresh => std::fs::metadata(path)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age < self.ttl),
+        };
+        if !usable {
+            return None;
+        }
+        std::fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+    }
+}
+
+impl<F: VulnFeed> VulnFeed for CachedFeed<F> {
+    fn query(
+        &self,
+        ecosystem: &str,
+        packages: &[ResolvedPackage],
+    ) -> Result<Vec<Vec<String>>, CoreError> {
+        let mut ids: Vec<Option<Vec<String>>> = packages
+            .iter()
+            .map(|pkg| self.cached(&self.query_path(ecosystem, pkg)))
+            .collect();
+        let missing: Vec<ResolvedPackage> = packages
+            .iter()
+            .zip(&ids)
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(pkg, _)| pkg.clone())
+            .collect();
+        if let Some(pkg) = missing.first() {
+            if self.policy == CachePolicy::Offline {
+                return Err(not_cached(&format!("{} {}", pkg.name, pkg.version)));
+            }
+            let mut fetched = self.feed.query(ecosystem, &missing)?.into_iter();
+            for (pkg, slot) in packages.iter().zip(ids.iter_mut()) {
+                if slot.is_none() {
+                    let found = fetched.next().unwrap_or_default();
+                    write_atomic(
+                        &self.query_path(ecosystem, pkg),
+                        &serde_json::to_vec(&found)?,
+                    )?;
+                    *slot = Some(found);
+                }
+            }
+        }
+        Ok(ids.into_iter().map(Option::unwrap_or_default).collect())
+    }
+
+    fn record(&self, id: &str) -> Result<OsvRecord, CoreError> {
+        let path = self.record_path(id);
+        if let Some(record) = self.cached(&path) {
+            return Ok(record);
+        }
+        if self.policy == CachePolicy::Offline {
+            return Err(not_cached(id));
+        }
+        let record = self.feed.record(id)?;
+        write_atomic(&path, &serde_json::to_vec(&record)?)?;
+        Ok(record)
+    }
+}
+
+fn not_cached(what: &str) -> CoreError {
+    CoreError::Audit(format!(
+        "no cached vulnerability data for {what}; run without --offline"
+    ))
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<(), CoreError> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| CoreError::Audit(format!("{} has no parent", path.display())))?;
+    std::fs::create_dir_all(dir)?;
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    std::io::Write::write_all(&mut tmp, data)?;
+    tmp.persist(path).map_err(|e| CoreError::Io(e.error))?;
+    Ok(())
+}
+
+/// One vulnerability affecting one installed package.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub package: String,
+    pub version: String,
+    pub id: String,
+    pub aliases: Vec<String>,
+    pub summary: String,
+    pub severity: Severity,
+    /// CVSS v3 base score, when the record has a vector.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cvss: Option<f64>,
+    /// First version that fixes it, if one is known.
+    pub fixed: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditReport {
+    pub env_id: String,
+    pub ecosystem: String,
+    /// Packages checked.
+    pub packages: usize,
+    /// Worst first.
+    pub findings: Vec<Finding>,
+}
+
+impl AuditReport {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == severity)
+            .count()
+    }
+
+    /// Whether any finding is rated `threshold` or worse.
+    pub fn any_at_least(&self, threshold: Severity) -> bool {
+        self.findings.iter().any(|f| f.severity >= threshold)
+    }
+}
+
+/// The OSV ecosystem of the distribution `base_image` names, or `None` when
+/// OSV does not track it (Fedora, Arch, NixOS, rolling releases such as
+/// Debian sid, and custom or imported images).
+pub fn osv_ecosystem(base_image: &str) -> Option<String> {
+    let source = match resolve_image(base_image).ok()?.source {
+        ImageSource::Custom { url } => lxc_image_source(&url)?,
+        source => source,
+    };
+    Some(match source {
+        ImageSource::OpenSuse { variant } if variant == "tumbleweed" => {
+            "openSUSE:Tumbleweed".to_owned()
+        }
+        ImageSource::OpenSuse { variant } => format!("openSUSE:Leap {variant}"),
+        ImageSource::Ubuntu { codename } => match codename.as_str() {
+            "focal" => "Ubuntu:20.04:LTS",
+            "jammy" => "Ubuntu:22.04:LTS",
+            "noble" => "Ubuntu:24.04:LTS",
+            "oracular" => "Ubuntu:24.10",
+            _ => return None,
+        }
+        .to_owned(),
+        ImageSource::Debian { codename } => match codename.as_str() {
+            "bullseye" => "Debian:11",
+            "bookworm" => "Debian:12",
+            "trixie" => "Debian:13",
+            _ => return None,
+        }
+        .to_owned(),
+        ImageSource::Alpine { version } if version != "edge" => format!("Alpine:v{version}"),
+        _ => return None,
+    })
+}
+
+/// The distribution and release in the path of a linuxcontainers.org image
+/// URL, as written by `karapace pin`.
+fn lxc_image_source(url: &str) -> Option<ImageSource> {
+    let mut parts = url.strip_prefix(LXC_IMAGE_PREFIX)?.split('/');
+    let (distro, release) = (parts.next()?, parts.next()?.to_owned());
+    Some(match distro {
+        "opensuse" => ImageSource::OpenSuse { variant: release },
+        "ubuntu" => ImageSource::Ubuntu { codename: release },
+        "debian" => ImageSource::Debian { codename: release },
+        "alpine" => ImageSource::Alpine { version: release },
+        _ => return None,
+    })
+}
+
+/// Check the packages of `lock` against `feed`.
+pub fn audit_lock(lock: &LockFile, feed: &dyn VulnFeed) -> Result<AuditReport, CoreError> {
+    let ecosystem = osv_ecosystem(&lock.base_image).ok_or_else(|| {
+        CoreError::Audit(format!(
+            "no vulnerability feed covers base image '{}'",
+            lock.base_image
+        ))
+    })?;
+    let packages = &lock.resolved_packages;
+    let ids = feed.query(&ecosystem, packages)?;
+    let mut findings = Vec::new();
+    for (pkg, ids) in packages.iter().zip(ids) {
+        for id in ids {
+            let record = feed.record(&id)?;
+            let (severity, cvss) = record.rating(&ecosystem, &pkg.name);
+            findings.push(Finding {
+                package: pkg.name.clone(),
+                version: pkg.version.clone(),
+                fixed: record.fixed_version(&ecosystem, &pkg.name),
+                id: record.id,
+                aliases: record.aliases,
+                summary: record.summary,
+                severity,
+                cvss,
+            });
+        }
+    }
+    findings.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| b.cvss.unwrap_or(0.0).total_cmp(&a.cvss.unwrap_or(0.0)))
+            .then_with(|| a.package.cmp(&b.package))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    Ok(AuditReport {
+        env_id: lock.env_id.clone(),
+        ecosystem,
+        packages: packages.len(),
+        findings,
+    })
+}
+
+/// The base score of a CVSS v3.0 or v3.1 vector such as
+/// `CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H`.
+pub fn cvss3_base_score(vector: &str) -> Option<f64> {
+    let mut metrics = std::collections::HashMap::new();
+    let mut parts = vector.split('/');
+    if !parts.next()?.starts_with("CVSS:3") {
+        return None;
+    }
+    for part in parts {
+        let (key, value) = part.split_once(':')?;
+        metrics.insert(key, value);
+    }
+    let get = |key: &str| metrics.get(key).copied();
+    let changed = match get("S")? {
+        "U" => false,
+        "C" => true,
+        _ => return None,
+    };
+    let av = match get("AV")? {
+        "N" => 0.85,
+        "A" => 0.62,
+        "L" => 0.55,
+        "P" => 0.2,
+        _ => return None,
+    };
+    let ac = match get("AC")? {
+        "L" => 0.77,
+        "H" => 0.44,
+        _ => return None,
+    };
+    let pr = match (get("PR")?, changed) {
+        ("N", _) => 0.85,
+        ("L", false) => 0.62,
+        ("L", true) => 0.68,
+        ("H", false) => 0.27,
+        ("H", true) => 0.5,
+        _ => return None,
+    };
+    let ui = match get("UI")? {
+        "N" => 0.85,
+        "R" => 0.62,
+        _ => return None,
+    };
+    let impact_of = |key: &str| match get(key)? {
+        "H" => Some(0.56),
+        "L" => Some(0.22),
+        "N" => Some(0.0),
+        _ => None,
+    };
+    let iss = 1.0 - (1.0 - impact_of("C")?) * (1.0 - impact_of("I")?) * (1.0 - impact_of("A")?);
+    let impact = if changed {
+        7.52 * (iss - 0.029) - 3.25 * (iss - 0.02_f64).powi(15)
+    } else {
+        6.42 * iss
+    };
+    if impact <= 0.0 {
+        return Some(0.0);
+    }
+    let exploitability = 8.22 * av * ac * pr * ui;
+    let base = if changed {
+        1.08 * (impact + exploitability)
+    } else {
+        impact + exploitability
+    };
+    Some(round_up(base.min(10.0)))
+}
+
+/// CVSS v3.1 `Roundup`: the smallest one-decimal number not below `value`,
+/// computed on integers to avoid floating point surprises.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)] // scores are 0..=10
+fn round_up(value: f64) -> f64 {
+    let scaled = (value * 100_000.0).round() as i64;
+    if scaled % 10_000 == 0 {
+        scaled as f64 / 100_000.0
+    } else {
+        (scaled / 10_000 + 1) as f64 / 10.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct FakeFeed {
+        affected: HashMap<(String, String), Vec<String>>,
+        records: HashMap<String, OsvRecord>,
+        queries: RefCell<usize>,
+    }
+
+    impl VulnFeed for FakeFeed {
+        fn query(
+            &self,
+            _ecosystem: &str,
+            packages: &[ResolvedPackage],
+        ) -> Result<Vec<Vec<String>>, CoreError> {
+            *self.queries.borrow_mut() += packages.len();
+            Ok(packages
+                .iter()
+                .map(|p| {
+                    self.affected
+                        .get(&(p.name.clone(), p.version.clone()))
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .collect())
+        }
+
+        fn record(&self, id: &str) -> Result<OsvRecord, CoreError> {
+            self.records
+                .get(id)
+                .cloned()
+                .ok_or_else(|| CoreError::Audit(format!("no record {id}")))
+        }
+    }
+
+    fn pkg(name: &str, version: &str) -> ResolvedPackage {
+        ResolvedPackage {
+            name: name.to_owned(),
+            version: version.to_owned(),
+        }
+    }
+
+    fn feed() -> FakeFeed {
+        let record =
+            |json: serde_json::Value| -> OsvRecord { serde_json::from_value(json).unwrap() };
+        let mut feed = FakeFeed::default();
+        feed.affected.insert(
+            ("openssl".to_owned(), "3.0.11-1".to_owned()),
+            vec![
+                "DEBIAN-CVE-2024-0001".to_owned(),
+                "DEBIAN-CVE-2024-0002".to_owned(),
+            ],
+        );
+        feed.affected.insert(
+            ("curl".to_owned(), "7.88.1-10".to_owned()),
+            vec!["DEBIAN-CVE-2024-0003".to_owned()],
+        );
+        feed.records.insert(
+            "DEBIAN-CVE-2024-0001".to_owned(),
+            record(serde_json::json!({
+                "id": "DEBIAN-CVE-2024-0001",
+                "summary": "heap overflow",
+                "aliases": ["CVE-2024-0001"],
+                "severity": [{"type": "CVSS_V3", "score": "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"}],
+                "affected": [{
+                    "package": {"ecosystem": "Debian:12", "name": "openssl"},
+                    "ranges": [{"type": "ECOSYSTEM", "events": [{"introduced": "0"}, {"fixed": "3.0.13-1"}]}]
+                }]
+            })),
+        );
+        feed.records.insert(
+            "DEBIAN-CVE-2024-0002".to_owned(),
+            record(serde_json::json!({
+                "id": "DEBIAN-CVE-2024-0002",
+                "affected": [{
+                    "package": {"ecosystem": "Debian:12", "name": "openssl"},
+                    "ecosystem_specific": {"urgency": "unimportant"}
+                }]
+            })),
+        );
+        feed.records.insert(
+            "DEBIAN-CVE-2024-0003".to_owned(),
+            record(serde_json::json!({
+                "id": "DEBIAN-CVE-2024-0003",
+                "database_specific": {"severity": "MODERATE"}
+            })),
+        );
+        feed
+    }
+
+    fn lock_for(base_image: &str, packages: Vec<ResolvedPackage>) -> LockFile {
+        let manifest = karapace_schema::parse_manifest_str(&format!(
+            "manifest_version = 1\n[base]\nimage = \"{base_image}\"\n[runtime]\nbackend = \"mock\"\n"
+        ))
+        .unwrap()
+        .normalize()
+        .unwrap();
+        LockFile::from_resolved(
+            &manifest,
+            &karapace_schema::ResolutionResult {
+                base_image_digest: "digest".to_owned(),
+                resolved_packages: packages,
+            },
+        )
+    }
+
+    #[test]
+    fn cvss3_scores_match_the_specification() {
+        let score = |v: &str| cvss3_base_score(v).unwrap();
+        assert!((score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H") - 9.8).abs() < 1e-9);
+        assert!((score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:C/C:H/I:H/A:H") - 10.0).abs() < 1e-9);
+        assert!((score("CVSS:3.0/AV:L/AC:L/PR:L/UI:N/S:U/C:H/I:N/A:N") - 5.5).abs() < 1e-9);
+        assert!((score("CVSS:3.1/AV:N/AC:L/PR:N/UI:R/S:C/C:L/I:L/A:N") - 6.1).abs() < 1e-9);
+        assert!(cvss3_base_score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:N/I:N/A:N") == Some(0.0));
+        assert!(cvss3_base_score("AV:N/AC:L/Au:N/C:P/I:P/A:P").is_none());
+        assert!(cvss3_base_score("CVSS:3.1/AV:X/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H").is_none());
+    }
+
+    #[test]
+    fn ecosystems_follow_the_base_image() {
+        assert_eq!(osv_ecosystem("debian").as_deref(), Some("Debian:12"));
+        assert_eq!(
+            osv_ecosystem("ubuntu/jammy").as_deref(),
+            Some("Ubuntu:22.04:LTS")
+        );
+        assert_eq!(
+            osv_ecosystem("alpine/3.21").as_deref(),
+            Some("Alpine:v3.21")
+        );
+        assert_eq!(
+            osv_ecosystem("rolling").as_deref(),
+            Some("openSUSE:Tumbleweed")
+        );
+        assert_eq!(
+            osv_ecosystem(
+                "https://images.linuxcontainers.org/images/debian/trixie/amd64/default/20250101_05:24/rootfs.tar.xz"
+            )
+            .as_deref(),
+            Some("Debian:13")
+        );
+        assert_eq!(osv_ecosystem("fedora"), None);
+        assert_eq!(osv_ecosystem("debian/sid"), None);
+        assert_eq!(osv_ecosystem("https://example.com/rootfs.tar.xz"), None);
+    }
+
+    #[test]
+    fn findings_are_rated_and_sorted_worst_first() {
+        let lock = lock_for(
+            "debian/bookworm",
+            vec![
+                pkg("curl", "7.88.1-10"),
+                pkg("openssl", "3.0.11-1"),
+                pkg("zlib1g", "1:1.2.13"),
+            ],
+        );
+        let report = audit_lock(&lock, &feed()).unwrap();
+        assert_eq!(report.ecosystem, "Debian:12");
+        assert_eq!(report.packages, 3);
+        let summary: Vec<_> = report
+            .findings
+            .iter()
+            .map(|f| (f.id.as_str(), f.package.as_str(), f.severity))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("DEBIAN-CVE-2024-0001", "openssl", Severity::Critical),
+                ("DEBIAN-CVE-2024-0003", "curl", Severity::Medium),
+                ("DEBIAN-CVE-2024-0002", "openssl", Severity::Low),
+            ]
+        );
+        assert_eq!(report.findings[0].fixed.as_deref(), Some("3.0.13-1"));
+        assert_eq!(report.findings[0].cvss, Some(9.8));
+        assert_eq!(report.count(Severity::Critical), 1);
+        assert!(report.any_at_least(Severity::High));
+
+        let fedora = lock_for("fedora", vec![pkg("curl", "8.0")]);
+        assert!(matches!(
+            audit_lock(&fedora, &feed()),
+            Err(CoreError::Audit(_))
+        ));
+    }
+
+    #[test]
+    fn cached_feed_reuses_answers_and_works_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let packages = vec![pkg("openssl", "3.0.11-1"), pkg("zlib1g", "1:1.2.13")];
+        let lock = lock_for("debian", packages.clone());
+
+        let offline = CachedFeed::new(feed(), dir.path(), CachePolicy::Offline);
+        assert!(matches!(
+            audit_lock(&lock, &offline),
+            Err(CoreError::Audit(_))
+        ));
+
+        let online = CachedFeed::new(feed(), dir.path(), CachePolicy::Fresh);
+        let first = audit_lock(&lock, &online).unwrap();
+        assert_eq!(*online.feed.queries.borrow(), 2);
+        let again = audit_lock(&lock, &online).unwrap();
+        assert_eq!(*online.feed.queries.borrow(), 2);
+        assert_eq!(first, again);
+
+        let offline = CachedFeed::new(FakeFeed::default(), dir.path(), CachePolicy::Offline);
+        assert_eq!(audit_lock(&lock, &offline).unwrap(), first);
+
+        let stale =
+            CachedFeed::new(feed(), dir.path(), CachePolicy::Fresh).with_ttl(Duration::ZERO);
+        audit_lock(&lock, &stale).unwrap();
+        assert_eq!(*stale.feed.queries.borrow(), 2);
+
+        let refresh = CachedFeed::new(feed(), dir.path(), CachePolicy::Refresh);
+        audit_lock(&lock, &refresh).unwrap();
+        assert_eq!(*refresh.feed.queries.borrow(), 2);
+    }
+
+    #[test]
+    fn severities_parse_from_labels() {
+        assert_eq!("CRITICAL".parse::<Severity>(), Ok(Severity::Critical));
+        assert_eq!("important".parse::<Severity>(), Ok(Severity::High));
+        assert_eq!("unknown".parse::<Severity>(), Ok(Severity::Unknown));
+        assert!("severe".parse::<Severity>().is_err());
+        assert!(Severity::Critical > Severity::High && Severity::Low > Severity::Unknown);
+    }
+}
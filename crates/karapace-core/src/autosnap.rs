@@ -0,0 +1,242 @@
+//! Time-based snapshots.
+//!
+//! `karapace autosnap enable` records a schedule for an environment in
+//! `store/autosnap.json`: how often to snapshot it and how many snapshots to
+//! keep. [`run_due`] commits every environment whose interval has elapsed
+//! since its last automatic snapshot, as an incremental commit, then prunes
+//! the automatic snapshots beyond `keep`. The schedule records which
+//! snapshots it took, so snapshots committed by hand are never pruned.
+//! Nothing runs on its own:
+//! `karapace autosnap run` is the agent that calls [`run_due`] in a loop.
+//!
+//! An environment that cannot be committed, such as one with a session
+//! running, is retried on the next run. Schedules of destroyed environments
+//! are dropped.
+
+use crate::{CommitOptions, CoreError, Engine};
+use chrono::{DateTime, Utc};
+use karapace_store::{LayerStore, StoreLayout};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Snapshot schedule of one environment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutosnapSchedule {
+    pub interval_secs: u64,
+    /// Automatic snapshots kept by pruning, the one just taken included.
+    pub keep: usize,
+    /// When the last automatic snapshot was taken (RFC 3339).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_snapshot_at: Option<String>,
+    /// Snapshots taken by this schedule, oldest first. Pruning only ever
+    /// removes these.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snapshots: Vec<String>,
+}
+
+impl AutosnapSchedule {
+    /// Time left until the schedule is due at `now`; zero when it is.
+    pub fn due_in(&self, now: DateTime<Utc>) -> Duration {
+        let Some(last) = self
+            .last_snapshot_at
+            .as_deref()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        else {
+            return Duration::ZERO;
+        };
+        let elapsed = (now - last.with_timezone(&Utc))
+            .to_std()
+            .unwrap_or_default();
+        Duration::from_secs(self.interval_secs).saturating_sub(elapsed)
+    }
+}
+
+/// `store/autosnap.json`: env_id → schedule.
+pub type AutosnapSchedules = BTreeMap<String, AutosnapSchedule>;
+
+/// Outcome of one environment's due snapshot.
+#[derive(Debug, Clone, Serialize)]
+pub struct AutosnapRun {
+    pub env_id: String,
+    /// The snapshot taken.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    /// Why no snapshot was taken.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Snapshots removed by pruning.
+    pub pruned: Vec<String>,
+}
+
+pub fn load_schedules(layout: &StoreLayout) -> Result<AutosnapSchedules, CoreError> {
+    let path = layout.autosnap_file();
+    if !path.exists() {
+        return Ok(AutosnapSchedules::new());
+    }
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+fn save_schedules(layout: &StoreLayout, schedules: &AutosnapSchedules) -> Result<(), CoreError> {
+    let path = layout.autosnap_file();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(schedules)?)?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Snapshot `env_id` every `interval`, keeping `keep` automatic snapshots.
+/// Replacing an existing schedule keeps the time of its last snapshot and
+/// the snapshots it took.
+pub fn enable(
+    engine: &Engine,
+    env_id: &str,
+    interval: Duration,
+    keep: usize,
+) -> Result<AutosnapSchedule, CoreError> {
+    engine.inspect(env_id)?;
+    if interval.is_zero() || keep == 0 {
+        return Err(CoreError::Config(
+            "autosnap interval and keep must be greater than zero".to_owned(),
+        ));
+    }
+    let layout = engine.store_layout();
+    let mut schedules = load_schedules(layout)?;
+    let old = schedules.remove(env_id);
+    let schedule = AutosnapSchedule {
+        interval_secs: interval.as_secs().max(1),
+        keep,
+        last_snapshot_at: old.as_ref().and_then(|old| old.last_snapshot_at.clone()),
+        snapshots: old.map(|old| old.snapshots).unwrap_or_default(),
+    };
+    schedules.insert(env_id.to_owned(), schedule.clone());
+    save_schedules(layout, &schedules)?;
+    Ok(schedule)
+}
+
+/// Remove the schedule of `env_id`. Returns `false` if it had none.
+pub fn disable(layout: &StoreLayout, env_id: &str) -> Result<bool, CoreError> {
+    let mut schedules = load_schedules(layout)?;
+    let removed = schedules.remove(env_id).is_some();
+    if removed {
+        save_schedules(layout, &schedules)?;
+    }
+    Ok(removed)
+}
+
+/// Time until the next schedule is due, or `None` without schedules.
+pub fn next_due(layout: &StoreLayout) -> Result<Option<Duration>, CoreError> {
+    let now = Utc::now();
+    Ok(load_schedules(layout)?
+        .values()
+        .map(|schedule| schedule.due_in(now))
+        .min())
+}
+
+/// Snapshot and prune every environment that is due now. The caller holds
+/// the store lock.
+pub fn run_due(engine: &Engine) -> Result<Vec<AutosnapRun>, CoreError> {
+    run_due_at(engine, Utc::now())
+}
+
+/// [`run_due`] as of `now`.
+pub fn run_due_at(engine: &Engine, now: DateTime<Utc>) -> Result<Vec<AutosnapRun>, CoreError> {
+    let layout = engine.store_layout();
+    let mut schedules = load_schedules(layout)?;
+    let mut runs = Vec::new();
+    let mut changed = false;
+    schedules.retain(|env_id, schedule| {
+        if !schedule.due_in(now).is_zero() {
+            return true;
+        }
+        let committed = engine.commit_with_options(env_id, CommitOptions { incremental: true });
+        let snapshot = match committed {
+            Ok(snapshot) => snapshot,
+            Err(CoreError::EnvNotFound(_)) if engine.inspect(env_id).is_err() => {
+                changed = true;
+                return false;
+            }
+            Err(e) => {
+                runs.push(AutosnapRun {
+                    env_id: env_id.clone(),
+                    snapshot: None,
+                    error: Some(e.to_string()),
+                    pruned: Vec::new(),
+                });
+                return true;
+            }
+        };
+        changed = true;
+        schedule.last_snapshot_at = Some(now.to_rfc3339());
+        if !schedule.snapshots.contains(&snapshot) {
+            schedule.snapshots.push(snapshot.clone());
+        }
+        let pruned = prune(engine, env_id, schedule);
+        runs.push(AutosnapRun {
+            env_id: env_id.clone(),
+            snapshot: Some(snapshot),
+            error: None,
+            pruned,
+        });
+        true
+    });
+    if changed {
+        save_schedules(layout, &schedules)?;
+    }
+    Ok(runs)
+}
+
+/// Remove the automatic snapshots of `schedule` beyond its newest `keep`
+/// and return their hashes. The snapshot the upper dir was last committed
+/// as or restored from is kept, and so is one that cannot be removed now,
+/// such as one of another workspace; both are tried again next time.
+fn prune(engine: &Engine, env_id: &str, schedule: &mut AutosnapSchedule) -> Vec<String> {
+    let current = engine.inspect(env_id).ok().and_then(|meta| meta.snapshot);
+    let layers = LayerStore::new(engine.store_layout().clone());
+    let mut excess = schedule.snapshots.len().saturating_sub(schedule.keep);
+    let mut pruned = Vec::new();
+    schedule.snapshots.retain(|hash| {
+        if excess == 0 || current.as_deref() == Some(hash.as_str()) {
+            return true;
+        }
+        excess -= 1;
+        if !layers.exists(hash) {
+            return false;
+        }
+        match engine.remove_snapshot(env_id, hash) {
+            Ok(()) => {
+                pruned.push(hash.clone());
+                false
+            }
+            Err(e) => {
+                tracing::warn!("pruning snapshot {hash} of {env_id} failed: {e}");
+                true
+            }
+        }
+    });
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_are_due_once_their_interval_elapsed() {
+        let now = Utc::now();
+        let mut schedule = AutosnapSchedule {
+            interval_secs: 3600,
+            keep: 3,
+            last_snapshot_at: None,
+            snapshots: Vec::new(),
+        };
+        assert_eq!(schedule.due_in(now), Duration::ZERO);
+        schedule.last_snapshot_at = Some((now - chrono::Duration::minutes(20)).to_rfc3339());
+        assert_eq!(schedule.due_in(now), Duration::from_mins(40));
+        schedule.last_snapshot_at = Some((now - chrono::Duration::hours(2)).to_rfc3339());
+        assert_eq!(schedule.due_in(now), Duration::ZERO);
+    }
+}
@@ -8,7 +8,7 @@
 //!   cargo run --bin stress_test -- [--cycles N]
 
 use karapace_core::Engine;
-use karapace_store::{verify_store_integrity, GarbageCollector, StoreLayout};
+use karapace_store::{verify_store_integrity, GarbageCollector, StoreLayout, WriteAheadLog};
 use std::fs;
 use std::path::Path;
 use std::time::{Duration, Instant};
@@ -38,11 +38,19 @@ fn count_files_in(dir: &Path) -> usize {
         .map(|rd| {
             rd.filter_map(Result::ok)
                 .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+                .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
                 .count()
         })
         .unwrap_or(0)
 }
 
+/// Incomplete WAL entries; the history of completed operations stays.
+fn count_wal_entries(layout: &StoreLayout) -> usize {
+    WriteAheadLog::new(layout)
+        .list_incomplete()
+        .map_or(0, |entries| entries.len())
+}
+
 struct Timings {
     build: Duration,
     commit: Duration,
@@ -95,7 +103,7 @@ fn run_cycle(
     Ok(())
 }
 
-fn check_health(layout: &StoreLayout, wal_dir: &Path, cycle: usize) -> u64 {
+fn check_health(layout: &StoreLayout, cycle: usize) -> u64 {
     let mut failures = 0u64;
     match verify_store_integrity(layout) {
         Ok(report) => {
@@ -112,7 +120,7 @@ fn check_health(layout: &StoreLayout, wal_dir: &Path, cycle: usize) -> u64 {
             failures += 1;
         }
     }
-    let wal_files = count_files_in(wal_dir);
+    let wal_files = count_wal_entries(layout);
     if wal_files > 0 {
         eprintln!("  cycle {cycle}: WAL LEAK: {wal_files} stale entries");
         failures += 1;
@@ -124,15 +132,9 @@ fn check_health(layout: &StoreLayout, wal_dir: &Path, cycle: usize) -> u64 {
     failures
 }
 
-fn print_report(
-    cycles: usize,
-    failures: u64,
-    timings: &Timings,
-    layout: &StoreLayout,
-    wal_dir: &Path,
-) {
+fn print_report(cycles: usize, failures: u64, timings: &Timings, layout: &StoreLayout) {
     let final_integrity = verify_store_integrity(layout);
-    let wal_files = count_files_in(wal_dir);
+    let wal_files = count_wal_entries(layout);
 
     println!();
     println!("============================================");
@@ -203,7 +205,6 @@ fn main() {
     let layout = StoreLayout::new(store_dir.path());
     layout.initialize().expect("initialize store");
     let engine = Engine::new(store_dir.path());
-    let wal_dir = store_dir.path().join("store").join("wal");
 
     let mut timings = Timings {
         build: Duration::ZERO,
@@ -220,7 +221,7 @@ fn main() {
             continue;
         }
         if cycle.is_multiple_of(50) {
-            failures += check_health(&layout, &wal_dir, cycle);
+            failures += check_health(&layout, cycle);
         }
         if cycle.is_multiple_of(100) {
             let elapsed = timings.build + timings.commit + timings.destroy + timings.gc;
@@ -234,5 +235,5 @@ fn main() {
     let gc = GarbageCollector::new(layout.clone());
     let _ = gc.collect(false);
 
-    print_report(cycles, failures, &timings, &layout, &wal_dir);
+    print_report(cycles, failures, &timings, &layout);
 }
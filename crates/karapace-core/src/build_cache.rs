@@ -0,0 +1,167 @@
+//! Reuse of package installation layers between builds.
+//!
+//! After installing packages, [`Engine::build`](crate::Engine::build)
+//! stores the resulting upper directory as a `Dependency` layer and records
+//! it under a key derived from the backend, the resolved base image digest
+//! and the sorted, version-pinned package set. A later build with the same
+//! key unpacks that layer instead of running the package manager again.
+//!
+//! `[build] steps` are cached the same way, one layer per step. A step's
+//! layer is a delta against the layer before it, the package layer or the
+//! previous step's, and its key combines the step string with that parent
+//! ([`step_key`]). A changed step therefore reruns itself and every step
+//! after it, but reuses the layers of the steps before it.
+//!
+//! Entries live in `store/build-cache/<key>` and hold the layer hash. The
+//! cache does not keep layers alive: every environment built from an entry
+//! lists its layer in `dependency_layers`, and an entry whose layer was
+//! collected is dropped on lookup.
+
+use crate::CoreError;
+use karapace_schema::{NormalizedToolchain, ResolvedPackage};
+use karapace_store::{LayerKind, LayerStore, ObjectStore, StoreLayout};
+use std::fs;
+
+/// Cache key of a package layer.
+pub fn cache_key(backend: &str, base_image_digest: &str, packages: &[ResolvedPackage]) -> String {
+    let mut pinned: Vec<String> = packages
+        .iter()
+        .map(|p| format!("{}={}", p.name, p.version))
+        .collect();
+    pinned.sort();
+    pinned.dedup();
+    let input = format!(
+        "packages:{backend}:{base_image_digest}:{}",
+        pinned.join(",")
+    );
+    blake3::hash(input.as_bytes()).to_hex().to_string()
+}
+
+/// Parent of the first build step: the package layer, or the base image
+/// when there are no packages, with the toolchains installed on top.
+pub fn step_root(
+    backend: &str,
+    base_image_digest: &str,
+    package_layer: Option<&str>,
+    toolchains: &[NormalizedToolchain],
+) -> String {
+    let toolchains = serde_json::to_string(toolchains).unwrap_or_default();
+    let input = format!(
+        "steps:{backend}:{base_image_digest}:{}:{toolchains}",
+        package_layer.unwrap_or("none")
+    );
+    blake3::hash(input.as_bytes()).to_hex().to_string()
+}
+
+/// Cache key of the layer `step` produces on top of `parent`: the
+/// [`step_root`] for the first step, the previous step's layer hash after.
+pub fn step_key(parent: &str, step: &str) -> String {
+    blake3::hash(format!("step:{parent}:{step}").as_bytes())
+        .to_hex()
+        .to_string()
+}
+
+/// The layer recorded under `key`, if it is still in the store with
+/// all of its objects.
+pub fn lookup(
+    layout: &StoreLayout,
+    layers: &LayerStore,
+    objects: &ObjectStore,
+    key: &str,
+) -> Option<String> {
+    let path = layout.build_cache_dir().join(key);
+    let hash = fs::read_to_string(&path).ok()?.trim().to_owned();
+    let usable = layers.get(&hash).is_ok_and(|layer| {
+        layer.kind == LayerKind::Dependency && layer.object_refs.iter().all(|o| objects.exists(o))
+    });
+    if !usable {
+        let _ = fs::remove_file(&path);
+        return None;
+    }
+    Some(hash)
+}
+
+/// Record `layer_hash` as the layer for `key`.
+pub fn record(layout: &StoreLayout, key: &str, layer_hash: &str) -> Result<(), CoreError> {
+    let dir = layout.build_cache_dir();
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(key);
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, layer_hash)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karapace_store::LayerManifest;
+
+    fn pkg(name: &str, version: &str) -> ResolvedPackage {
+        ResolvedPackage {
+            name: name.to_owned(),
+            version: version.to_owned(),
+        }
+    }
+
+    #[test]
+    fn key_ignores_package_order() {
+        let a = cache_key("mock", "d1", &[pkg("git", "1"), pkg("curl", "2")]);
+        let b = cache_key("mock", "d1", &[pkg("curl", "2"), pkg("git", "1")]);
+        assert_eq!(a, b);
+        assert_ne!(
+            a,
+            cache_key("mock", "d2", &[pkg("git", "1"), pkg("curl", "2")])
+        );
+        assert_ne!(
+            a,
+            cache_key("mock", "d1", &[pkg("git", "1"), pkg("curl", "3")])
+        );
+        assert_ne!(
+            a,
+            cache_key("oci", "d1", &[pkg("git", "1"), pkg("curl", "2")])
+        );
+    }
+
+    #[test]
+    fn step_keys_chain_on_their_parent() {
+        let root = step_root("mock", "d1", Some("pkgs"), &[]);
+        assert_ne!(root, step_root("mock", "d1", None, &[]));
+        assert_ne!(root, step_root("mock", "d2", Some("pkgs"), &[]));
+        let first = step_key(&root, "make deps");
+        assert_ne!(first, step_key(&root, "make all"));
+        assert_ne!(step_key(&first, "make all"), step_key(&root, "make all"));
+    }
+
+    #[test]
+    fn lookup_drops_entries_without_their_layer() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        let layers = LayerStore::new(layout.clone());
+        let objects = ObjectStore::new(layout.clone());
+
+        let tar = objects.put(b"tar").unwrap();
+        let layer = layers
+            .put(&LayerManifest {
+                hash: tar.clone(),
+                kind: LayerKind::Dependency,
+                parent: None,
+                object_refs: vec![tar.clone()],
+                read_only: true,
+                tar_hash: tar.clone(),
+                workspace: None,
+                delta_parent: None,
+                file_objects: Vec::new(),
+                pax_metadata: false,
+            })
+            .unwrap();
+        record(&layout, "k", &layer).unwrap();
+        assert_eq!(lookup(&layout, &layers, &objects, "k"), Some(layer.clone()));
+        assert_eq!(lookup(&layout, &layers, &objects, "other"), None);
+
+        objects.remove(&tar).unwrap();
+        assert_eq!(lookup(&layout, &layers, &objects, "k"), None);
+        assert!(!layout.build_cache_dir().join("k").exists());
+    }
+}
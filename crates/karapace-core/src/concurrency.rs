@@ -1,51 +1,138 @@
 use crate::CoreError;
 use fs2::FileExt;
+use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
+use std::io::Write;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Interval between lock attempts while waiting for another holder.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Who currently holds the store lock. Written into the lock file on
+/// acquisition so waiting commands can say what they are waiting for.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub op: String,
+    pub since: String,
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (pid {})", self.op, self.pid)
+    }
+}
 
 pub struct StoreLock {
     lock_file: File,
 }
 
 impl StoreLock {
-    pub fn acquire(lock_path: &Path) -> Result<Self, CoreError> {
+    fn open(lock_path: &Path) -> Result<File, CoreError> {
         if let Some(parent) = lock_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
-        let file = OpenOptions::new()
+        Ok(OpenOptions::new()
             .create(true)
+            .read(true)
             .write(true)
             .truncate(false)
-            .open(lock_path)?;
+            .open(lock_path)?)
+    }
+
+    fn locked(file: File, op: &str) -> Self {
+        let holder = LockHolder {
+            pid: std::process::id(),
+            op: op.to_owned(),
+            since: chrono::Utc::now().to_rfc3339(),
+        };
+        // Holder info is advisory; failing to record it must not fail the lock.
+        if let Ok(json) = serde_json::to_string(&holder) {
+            let _ = file.set_len(0);
+            let _ = (&file).write_all(json.as_bytes());
+        }
+        Self { lock_file: file }
+    }
+
+    pub fn acquire(lock_path: &Path) -> Result<Self, CoreError> {
+        Self::acquire_for(lock_path, "unknown")
+    }
+
+    /// Block until the lock is free, recording `op` as the holder.
+    pub fn acquire_for(lock_path: &Path, op: &str) -> Result<Self, CoreError> {
+        let file = Self::open(lock_path)?;
 
         file.lock_exclusive()
             .map_err(|e| CoreError::Io(std::io::Error::new(std::io::ErrorKind::WouldBlock, e)))?;
 
-        Ok(Self { lock_file: file })
+        Ok(Self::locked(file, op))
     }
 
-    pub fn try_acquire(lock_path: &Path) -> Result<Option<Self>, CoreError> {
-        if let Some(parent) = lock_path.parent() {
-            std::fs::create_dir_all(parent)?;
+    /// Acquire the lock, waiting at most `timeout` (forever if `None`).
+    ///
+    /// If the lock is busy, `on_wait` is called once with the current holder
+    /// before waiting starts, so callers can report what they are waiting on.
+    pub fn acquire_with_timeout(
+        lock_path: &Path,
+        op: &str,
+        timeout: Option<Duration>,
+        on_wait: impl FnOnce(Option<&LockHolder>),
+    ) -> Result<Self, CoreError> {
+        let file = Self::open(lock_path)?;
+        if file.try_lock_exclusive().is_ok() {
+            return Ok(Self::locked(file, op));
         }
 
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(false)
-            .open(lock_path)?;
+        let holder = Self::holder(lock_path);
+        if timeout == Some(Duration::ZERO) {
+            return Err(CoreError::LockTimeout {
+                waited_secs: 0,
+                holder: holder.map_or_else(|| "another process".to_owned(), |h| h.to_string()),
+            });
+        }
+        on_wait(holder.as_ref());
+
+        let start = Instant::now();
+        loop {
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+            if file.try_lock_exclusive().is_ok() {
+                return Ok(Self::locked(file, op));
+            }
+            if let Some(limit) = timeout {
+                if start.elapsed() >= limit {
+                    let holder = Self::holder(lock_path).or(holder);
+                    return Err(CoreError::LockTimeout {
+                        waited_secs: limit.as_secs(),
+                        holder: holder
+                            .map_or_else(|| "another process".to_owned(), |h| h.to_string()),
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn try_acquire(lock_path: &Path) -> Result<Option<Self>, CoreError> {
+        let file = Self::open(lock_path)?;
 
         match file.try_lock_exclusive() {
-            Ok(()) => Ok(Some(Self { lock_file: file })),
+            Ok(()) => Ok(Some(Self::locked(file, "unknown"))),
             Err(_) => Ok(None),
         }
     }
+
+    /// Read the recorded holder of the lock, if any.
+    pub fn holder(lock_path: &Path) -> Option<LockHolder> {
+        let content = std::fs::read_to_string(lock_path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
 }
 
 impl Drop for StoreLock {
     fn drop(&mut self) {
+        let _ = self.lock_file.set_len(0);
         let _ = self.lock_file.unlock();
     }
 }
@@ -103,4 +190,72 @@ mod tests {
         let lock2 = StoreLock::try_acquire(&lock_path).unwrap();
         assert!(lock2.is_some());
     }
+
+    #[test]
+    fn holder_recorded_and_cleared() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("test.lock");
+
+        {
+            let _lock = StoreLock::acquire_for(&lock_path, "gc").unwrap();
+            let holder = StoreLock::holder(&lock_path).unwrap();
+            assert_eq!(holder.op, "gc");
+            assert_eq!(holder.pid, std::process::id());
+        }
+        assert!(StoreLock::holder(&lock_path).is_none());
+    }
+
+    #[test]
+    fn zero_timeout_fails_fast_with_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("test.lock");
+        let _lock = StoreLock::acquire_for(&lock_path, "build").unwrap();
+
+        let result =
+            StoreLock::acquire_with_timeout(&lock_path, "gc", Some(Duration::ZERO), |_| {
+                panic!("must not wait with a zero timeout")
+            });
+        match result {
+            Err(CoreError::LockTimeout { holder, .. }) => assert!(holder.contains("build")),
+            other => panic!("expected LockTimeout, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn bounded_wait_reports_holder_then_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("test.lock");
+        let _lock = StoreLock::acquire_for(&lock_path, "gc").unwrap();
+
+        let mut seen = None;
+        let result = StoreLock::acquire_with_timeout(
+            &lock_path,
+            "build",
+            Some(Duration::from_millis(200)),
+            |h| seen = h.map(|h| h.op.clone()),
+        );
+        assert!(matches!(result, Err(CoreError::LockTimeout { .. })));
+        assert_eq!(seen.as_deref(), Some("gc"));
+    }
+
+    #[test]
+    fn wait_succeeds_once_holder_releases() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("test.lock");
+        let lock = StoreLock::acquire_for(&lock_path, "gc").unwrap();
+
+        let releaser = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(150));
+            drop(lock);
+        });
+        let acquired = StoreLock::acquire_with_timeout(
+            &lock_path,
+            "build",
+            Some(Duration::from_secs(5)),
+            |_| {},
+        );
+        releaser.join().unwrap();
+        assert!(acquired.is_ok());
+        assert_eq!(StoreLock::holder(&lock_path).unwrap().op, "build");
+    }
 }
@@ -0,0 +1,332 @@
+//! Store root discovery.
+//!
+//! The store root is picked in this order:
+//!
+//! 1. an explicit path (`--store` / `KARAPACE_STORE`);
+//! 2. an existing project-local store, `.karapace/store` in the start
+//!    directory or any of its ancestors;
+//! 3. the store recorded in the nearest project binding,
+//!    `.karapace/state.json`, that `build` and `rebuild` write next to the
+//!    manifest along with the environment they produced;
+//! 4. the `store` key of `~/.config/karapace/config.toml` — `"local"` places
+//!    the store at `.karapace/store` next to the nearest `karapace.toml`, any
+//!    other value is a path;
+//! 5. `~/.local/share/karapace`.
+
+use crate::CoreError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Project-local store location, relative to the project directory.
+pub const LOCAL_STORE_DIR: &str = ".karapace/store";
+
+/// Project binding location, relative to the project directory.
+pub const PROJECT_STATE_FILE: &str = ".karapace/state.json";
+
+/// `.karapace/.gitignore` as written for a new project directory: the store
+/// and the binding are local to the machine.
+const DOT_DIR_GITIGNORE: &str = "store/\nstate.json\n";
+
+const MANIFEST_FILE: &str = "karapace.toml";
+
+/// User-level settings read from `~/.config/karapace/config.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    /// `"local"` for a project-local store, otherwise a store path.
+    #[serde(default)]
+    pub store: Option<String>,
+}
+
+impl UserConfig {
+    pub fn load(path: &Path) -> Result<Self, CoreError> {
+        let content = std::fs::read_to_string(path)?;
+        toml::from_str(&content)
+            .map_err(|e| CoreError::Config(format!("{}: {}", path.display(), e.message().trim())))
+    }
+
+    /// Load `~/.config/karapace/config.toml`; a missing file yields defaults.
+    pub fn load_default() -> Result<Self, CoreError> {
+        let Ok(home) = std::env::var("HOME") else {
+            return Ok(Self::default());
+        };
+        let path = PathBuf::from(home).join(".config/karapace/config.toml");
+        if path.exists() {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+}
+
+/// Why a store root was chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreSource {
+    Explicit,
+    Local,
+    Project,
+    Config,
+    Default,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredStore {
+    pub root: PathBuf,
+    pub source: StoreSource,
+}
+
+pub fn default_store_root() -> PathBuf {
+    if let Ok(home) = std::env::var("HOME") {
+        PathBuf::from(home).join(".local/share/karapace")
+    } else {
+        PathBuf::from("/tmp/karapace")
+    }
+}
+
+/// Resolve the store root for a command started in `start_dir`.
+pub fn discover_store(
+    explicit: Option<&Path>,
+    start_dir: &Path,
+    config: &UserConfig,
+) -> DiscoveredStore {
+    if let Some(root) = explicit {
+        return DiscoveredStore {
+            root: root.to_path_buf(),
+            source: StoreSource::Explicit,
+        };
+    }
+
+    if let Some(root) = find_local_store(start_dir) {
+        return DiscoveredStore {
+            root,
+            source: StoreSource::Local,
+        };
+    }
+
+    if let Some((_, binding)) = ProjectBinding::find(start_dir) {
+        return DiscoveredStore {
+            root: binding.store,
+            source: StoreSource::Project,
+        };
+    }
+
+    match config.store.as_deref().map(str::trim) {
+        Some("local") => DiscoveredStore {
+            root: project_dir(start_dir).join(LOCAL_STORE_DIR),
+            source: StoreSource::Local,
+        },
+        Some(path) if !path.is_empty() => DiscoveredStore {
+            root: expand_tilde(path),
+            source: StoreSource::Config,
+        },
+        _ => DiscoveredStore {
+            root: default_store_root(),
+            source: StoreSource::Default,
+        },
+    }
+}
+
+/// Nearest `.karapace/store` directory at or above `start_dir`.
+pub fn find_local_store(start_dir: &Path) -> Option<PathBuf> {
+    start_dir
+        .ancestors()
+        .map(|dir| dir.join(LOCAL_STORE_DIR))
+        .find(|candidate| candidate.is_dir())
+}
+
+/// Keep a project-local store out of version control by writing
+/// `.karapace/.gitignore` next to it, unless one already exists.
+pub fn ensure_local_store_ignored(store_root: &Path) -> Result<(), CoreError> {
+    let Some(dot_dir) = store_root.parent() else {
+        return Ok(());
+    };
+    ensure_dot_dir_ignored(dot_dir)
+}
+
+fn ensure_dot_dir_ignored(dot_dir: &Path) -> Result<(), CoreError> {
+    std::fs::create_dir_all(dot_dir)?;
+    let gitignore = dot_dir.join(".gitignore");
+    if !gitignore.exists() {
+        std::fs::write(gitignore, DOT_DIR_GITIGNORE)?;
+    }
+    Ok(())
+}
+
+/// The environment a project's manifest was last built into, so commands
+/// run in the project can leave out the environment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectBinding {
+    pub env_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Absolute path of the store holding the environment.
+    pub store: PathBuf,
+}
+
+impl ProjectBinding {
+    /// The binding of `project_dir`, if it has one.
+    pub fn load(project_dir: &Path) -> Result<Option<Self>, CoreError> {
+        let path = project_dir.join(PROJECT_STATE_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    /// Record the binding in `project_dir`, replacing any previous one.
+    pub fn save(&self, project_dir: &Path) -> Result<(), CoreError> {
+        let path = project_dir.join(PROJECT_STATE_FILE);
+        if let Some(dot_dir) = path.parent() {
+            ensure_dot_dir_ignored(dot_dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// The nearest binding at or above `start_dir`, with its project
+    /// directory. Unreadable bindings are skipped.
+    pub fn find(start_dir: &Path) -> Option<(PathBuf, Self)> {
+        start_dir.ancestors().find_map(|dir| {
+            let binding = Self::load(dir).ok().flatten()?;
+            Some((dir.to_path_buf(), binding))
+        })
+    }
+}
+
+/// Nearest directory at or above `start_dir` holding a `karapace.toml`,
+/// falling back to `start_dir` itself.
+fn project_dir(start_dir: &Path) -> PathBuf {
+    start_dir
+        .ancestors()
+        .find(|dir| dir.join(MANIFEST_FILE).is_file())
+        .unwrap_or(start_dir)
+        .to_path_buf()
+}
+
+fn expand_tilde(path: &str) -> PathBuf {
+    if let Some(stripped) = path.strip_prefix("~/") {
+        if let Ok(home) = std::env::var("HOME") {
+            return PathBuf::from(home).join(stripped);
+        }
+    }
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_store_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join(LOCAL_STORE_DIR)).unwrap();
+        let found = discover_store(
+            Some(Path::new("/srv/karapace")),
+            dir.path(),
+            &UserConfig::default(),
+        );
+        assert_eq!(found.root, PathBuf::from("/srv/karapace"));
+        assert_eq!(found.source, StoreSource::Explicit);
+    }
+
+    #[test]
+    fn existing_local_store_found_from_subdirectory() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join(LOCAL_STORE_DIR);
+        std::fs::create_dir_all(&store).unwrap();
+        let nested = dir.path().join("services/api");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        let config = UserConfig {
+            store: Some("/elsewhere".to_owned()),
+        };
+        let found = discover_store(None, &nested, &config);
+        assert_eq!(found.root, store);
+        assert_eq!(found.source, StoreSource::Local);
+    }
+
+    #[test]
+    fn config_local_uses_manifest_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("services/api");
+        let src = project.join("src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(project.join(MANIFEST_FILE), "").unwrap();
+
+        let config = UserConfig {
+            store: Some("local".to_owned()),
+        };
+        let found = discover_store(None, &src, &config);
+        assert_eq!(found.root, project.join(LOCAL_STORE_DIR));
+        assert_eq!(found.source, StoreSource::Local);
+    }
+
+    #[test]
+    fn config_path_and_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = UserConfig {
+            store: Some("/var/lib/karapace".to_owned()),
+        };
+        let found = discover_store(None, dir.path(), &config);
+        assert_eq!(found.root, PathBuf::from("/var/lib/karapace"));
+        assert_eq!(found.source, StoreSource::Config);
+
+        let found = discover_store(None, dir.path(), &UserConfig::default());
+        assert_eq!(found.source, StoreSource::Default);
+    }
+
+    #[test]
+    fn user_config_rejects_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "store = \"local\"\n").unwrap();
+        assert_eq!(
+            UserConfig::load(&path).unwrap().store.as_deref(),
+            Some("local")
+        );
+
+        std::fs::write(&path, "stroe = \"local\"\n").unwrap();
+        assert!(matches!(UserConfig::load(&path), Err(CoreError::Config(_))));
+    }
+
+    #[test]
+    fn local_store_gets_gitignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join(LOCAL_STORE_DIR);
+        ensure_local_store_ignored(&store).unwrap();
+        let ignore = std::fs::read_to_string(dir.path().join(".karapace/.gitignore")).unwrap();
+        assert_eq!(ignore, "store/\nstate.json\n");
+
+        std::fs::write(dir.path().join(".karapace/.gitignore"), "custom\n").unwrap();
+        ensure_local_store_ignored(&store).unwrap();
+        let ignore = std::fs::read_to_string(dir.path().join(".karapace/.gitignore")).unwrap();
+        assert_eq!(ignore, "custom\n");
+    }
+
+    #[test]
+    fn project_binding_found_from_subdirectory_and_names_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src/bin");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert!(ProjectBinding::find(&nested).is_none());
+
+        let binding = ProjectBinding {
+            env_id: "a".repeat(64),
+            name: Some("web".to_owned()),
+            store: PathBuf::from("/srv/karapace"),
+        };
+        binding.save(dir.path()).unwrap();
+        assert_eq!(
+            ProjectBinding::find(&nested),
+            Some((dir.path().to_path_buf(), binding))
+        );
+        let ignore = std::fs::read_to_string(dir.path().join(".karapace/.gitignore")).unwrap();
+        assert!(ignore.lines().any(|l| l == "state.json"));
+
+        let found = discover_store(None, &nested, &UserConfig::default());
+        assert_eq!(found.root, PathBuf::from("/srv/karapace"));
+        assert_eq!(found.source, StoreSource::Project);
+    }
+}
@@ -1,11 +1,25 @@
 use crate::CoreError;
 use karapace_store::StoreLayout;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::fs;
+use std::io::Read;
 use std::path::Path;
 
 const WHITEOUT_PREFIX: &str = ".wh.";
 
+/// Format version of drift bundles.
+pub const DRIFT_BUNDLE_FORMAT: u32 = 1;
+
+/// Entries of the tar inside a drift bundle's zstd frame.
+const BUNDLE_INFO_ENTRY: &str = "drift.json";
+const BUNDLE_UPPER_ENTRY: &str = "upper.tar";
+
+/// Files larger than this are left out of [`overlay_patches`].
+pub const PATCH_SIZE_LIMIT: u64 = 1024 * 1024;
+
+/// Bytes searched for a NUL when telling binary files from text, as git does.
+const BINARY_SNIFF_LEN: usize = 8000;
+
 /// Report of filesystem drift detected in an environment's overlay upper layer.
 #[derive(Debug, Serialize)]
 pub struct DriftReport {
@@ -111,6 +125,253 @@ fn collect_drift(
     Ok(())
 }
 
+/// Compare two upper directories, such as a restored snapshot (`old`) and
+/// the current overlay (`new`). Files only in `new` are added, files only
+/// in `old` removed, and files whose type or content differ modified.
+/// Whiteouts are compared like any other file.
+pub fn diff_upper_dirs(env_id: &str, old: &Path, new: &Path) -> Result<DriftReport, CoreError> {
+    let mut old_files = std::collections::BTreeSet::new();
+    let mut new_files = std::collections::BTreeSet::new();
+    list_files(old, old, &mut old_files)?;
+    list_files(new, new, &mut new_files)?;
+
+    let added: Vec<String> = new_files.difference(&old_files).cloned().collect();
+    let removed: Vec<String> = old_files.difference(&new_files).cloned().collect();
+    let mut modified = Vec::new();
+    for rel in old_files.intersection(&new_files) {
+        if !same_file(&old.join(rel), &new.join(rel))? {
+            modified.push(rel.clone());
+        }
+    }
+    let has_drift = !added.is_empty() || !modified.is_empty() || !removed.is_empty();
+    Ok(DriftReport {
+        env_id: env_id.to_owned(),
+        added,
+        modified,
+        removed,
+        has_drift,
+    })
+}
+
+/// Relative paths of everything but directories under `dir`.
+fn list_files(
+    base: &Path,
+    dir: &Path,
+    files: &mut std::collections::BTreeSet<String>,
+) -> Result<(), CoreError> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            list_files(base, &path, files)?;
+        } else {
+            let rel = path.strip_prefix(base).unwrap_or(&path);
+            files.insert(rel.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> Result<bool, CoreError> {
+    let (meta_a, meta_b) = (fs::symlink_metadata(a)?, fs::symlink_metadata(b)?);
+    if meta_a.file_type() != meta_b.file_type() {
+        return Ok(false);
+    }
+    if meta_a.file_type().is_symlink() {
+        return Ok(fs::read_link(a)? == fs::read_link(b)?);
+    }
+    if !meta_a.is_file() {
+        return Ok(true);
+    }
+    Ok(meta_a.len() == meta_b.len() && fs::read(a)? == fs::read(b)?)
+}
+
+/// Content diff of one added or modified file.
+#[derive(Debug, Serialize)]
+pub struct FilePatch {
+    pub path: String,
+    /// Unified diff against the lower layer, `/dev/null` for added files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    /// Why there is no diff: binary, too large, or not a regular file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Unified diffs of the added and modified files of `report`, comparing the
+/// upper layer against the lower one. Binary files, files over `size_limit`
+/// bytes and anything but regular files get a note instead of a diff.
+pub fn overlay_patches(
+    layout: &StoreLayout,
+    report: &DriftReport,
+    size_limit: u64,
+) -> Vec<FilePatch> {
+    let upper_dir = layout.upper_dir(&report.env_id);
+    let lower_dir = layout.env_path(&report.env_id).join("lower");
+
+    let modified = report.modified.iter().map(|rel| (rel, true));
+    let added = report.added.iter().map(|rel| (rel, false));
+    let mut patches: Vec<FilePatch> = modified
+        .chain(added)
+        .map(|(rel, in_lower)| {
+            let lower = in_lower.then(|| lower_dir.join(rel));
+            file_patch(rel, lower.as_deref(), &upper_dir.join(rel), size_limit)
+        })
+        .collect();
+    patches.sort_by(|a, b| a.path.cmp(&b.path));
+    patches
+}
+
+fn file_patch(rel: &str, lower: Option<&Path>, upper: &Path, size_limit: u64) -> FilePatch {
+    let texts = read_text(upper, size_limit).and_then(|new| {
+        let old = match lower {
+            Some(lower) => read_text(lower, size_limit)?,
+            None => String::new(),
+        };
+        Ok((old, new))
+    });
+    let (diff, note) = match texts {
+        Ok((old, new)) => {
+            let old_label = if lower.is_some() {
+                format!("a/{rel}")
+            } else {
+                "/dev/null".to_owned()
+            };
+            match crate::textdiff::unified_diff(&old, &new, &old_label, &format!("b/{rel}")) {
+                Some(diff) => (Some(diff), None),
+                None => (None, Some("content unchanged".to_owned())),
+            }
+        }
+        Err(note) => (None, Some(note)),
+    };
+    FilePatch {
+        path: rel.to_owned(),
+        diff,
+        note,
+    }
+}
+
+/// The content of a regular text file of at most `size_limit` bytes, or
+/// why it cannot be diffed.
+fn read_text(path: &Path, size_limit: u64) -> Result<String, String> {
+    let meta = fs::symlink_metadata(path).map_err(|e| format!("unreadable: {e}"))?;
+    if !meta.is_file() {
+        return Err("not a regular file".to_owned());
+    }
+    if meta.len() > size_limit {
+        return Err(format!("larger than {size_limit} bytes"));
+    }
+    let data = fs::read(path).map_err(|e| format!("unreadable: {e}"))?;
+    if data[..data.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+        return Err("binary file".to_owned());
+    }
+    String::from_utf8(data).map_err(|_| "binary file".to_owned())
+}
+
+/// Describes the overlay a drift bundle was exported from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriftBundleInfo {
+    pub format: u32,
+    pub env_id: String,
+    /// Base layer the overlay was made on. Applying it on another base may
+    /// not give the same result.
+    pub base_layer: String,
+    pub created_at: String,
+}
+
+/// Wrap the packed upper layer `upper_tar` and `info` in a zstd-compressed
+/// drift bundle.
+pub fn pack_drift_bundle(info: &DriftBundleInfo, upper_tar: &[u8]) -> Result<Vec<u8>, CoreError> {
+    let info_json = serde_json::to_vec_pretty(info)?;
+    let mut ar = tar::Builder::new(Vec::new());
+    for (name, data) in [
+        (BUNDLE_INFO_ENTRY, info_json.as_slice()),
+        (BUNDLE_UPPER_ENTRY, upper_tar),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        ar.append_data(&mut header, name, data)?;
+    }
+    Ok(zstd::encode_all(ar.into_inner()?.as_slice(), 3)?)
+}
+
+/// Split a drift bundle into its info and the packed upper layer.
+pub fn unpack_drift_bundle(bundle: &[u8]) -> Result<(DriftBundleInfo, Vec<u8>), CoreError> {
+    let invalid = |what: &str| CoreError::DriftBundle(format!("not a drift bundle: {what}"));
+    let tar_data = zstd::decode_all(bundle).map_err(|_| invalid("not zstd-compressed"))?;
+    let mut info = None;
+    let mut upper = None;
+    let mut ar = tar::Archive::new(tar_data.as_slice());
+    for entry in ar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        match name.as_str() {
+            BUNDLE_INFO_ENTRY => info = Some(data),
+            BUNDLE_UPPER_ENTRY => upper = Some(data),
+            _ => {}
+        }
+    }
+    let info: DriftBundleInfo =
+        serde_json::from_slice(&info.ok_or_else(|| invalid("no drift.json"))?)?;
+    if info.format != DRIFT_BUNDLE_FORMAT {
+        return Err(CoreError::DriftBundle(format!(
+            "unsupported drift bundle format {}",
+            info.format
+        )));
+    }
+    Ok((info, upper.ok_or_else(|| invalid("no upper.tar"))?))
+}
+
+/// Unpack the packed upper layer `upper_tar` over the upper directory
+/// `target`. Files replace what is at their path; a whiteout also removes
+/// the file it hides.
+pub(crate) fn merge_upper(upper_tar: &[u8], target: &Path) -> Result<(), CoreError> {
+    fs::create_dir_all(target)?;
+    let mut ar = tar::Archive::new(upper_tar);
+    ar.set_preserve_permissions(true);
+    ar.set_preserve_mtime(false);
+    ar.set_unpack_xattrs(false);
+    for entry in ar.entries()? {
+        let mut entry = entry?;
+        let rel = entry.path()?.into_owned();
+        let dest = target.join(&rel);
+        if let Some(name) = rel.file_name().and_then(|name| name.to_str()) {
+            // A whiteout hides the file, and a file lifts its whiteout.
+            match name.strip_prefix(WHITEOUT_PREFIX) {
+                Some(hidden) => remove_path(&dest.with_file_name(hidden))?,
+                None => remove_path(&dest.with_file_name(format!("{WHITEOUT_PREFIX}{name}")))?,
+            }
+        }
+        // A directory merges into a directory; anything else replaces
+        // what is there.
+        let is_dir = entry.header().entry_type().is_dir();
+        if let Ok(meta) = dest.symlink_metadata() {
+            if !(is_dir && meta.is_dir()) {
+                remove_path(&dest)?;
+            }
+        }
+        entry.unpack_in(target)?;
+    }
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> Result<(), CoreError> {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
 pub fn export_overlay(layout: &StoreLayout, env_id: &str, dest: &Path) -> Result<usize, CoreError> {
     let upper_dir = layout.upper_dir(env_id);
     if !upper_dir.exists() {
@@ -249,6 +510,37 @@ mod tests {
         assert!(report.added.contains(&"brand_new.txt".to_owned()));
     }
 
+    #[test]
+    fn patches_diff_text_files_and_skip_binary_and_large_ones() {
+        let (_dir, layout) = setup();
+        let lower = layout.env_path("test-env").join("lower");
+        fs::create_dir_all(&lower).unwrap();
+        fs::write(lower.join("config"), "a = 1\nb = 2\n").unwrap();
+        fs::write(lower.join("blob"), b"\0\x01").unwrap();
+
+        let upper = layout.upper_dir("test-env");
+        fs::create_dir_all(&upper).unwrap();
+        fs::write(upper.join("config"), "a = 1\nb = 3\n").unwrap();
+        fs::write(upper.join("blob"), b"\0\x02").unwrap();
+        fs::write(upper.join("notes"), "hello\n").unwrap();
+        fs::write(upper.join("big"), "x".repeat(64)).unwrap();
+
+        let report = diff_overlay(&layout, "test-env").unwrap();
+        let patches = overlay_patches(&layout, &report, 32);
+        let by_path = |p: &str| patches.iter().find(|f| f.path == p).unwrap();
+
+        assert_eq!(
+            by_path("config").diff.as_deref(),
+            Some("--- a/config\n+++ b/config\n@@ -1,2 +1,2 @@\n a = 1\n-b = 2\n+b = 3\n")
+        );
+        assert_eq!(
+            by_path("notes").diff.as_deref(),
+            Some("--- /dev/null\n+++ b/notes\n@@ -0,0 +1 @@\n+hello\n")
+        );
+        assert_eq!(by_path("blob").note.as_deref(), Some("binary file"));
+        assert_eq!(by_path("big").note.as_deref(), Some("larger than 32 bytes"));
+    }
+
     #[test]
     fn export_copies_overlay_files() {
         let (_dir, layout) = setup();
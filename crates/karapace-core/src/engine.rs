@@ -1,17 +1,37 @@
+use crate::attest::{AttestationKey, BuildRecord, Envelope, HostInfo, Statement};
+use crate::build_cache;
 use crate::concurrency::StoreLock;
+use crate::drift::{
+    diff_upper_dirs, merge_upper, pack_drift_bundle, unpack_drift_bundle, DriftBundleInfo,
+    DriftReport, DRIFT_BUNDLE_FORMAT,
+};
+use crate::hooks::{EngineEvent, Hooks};
 use crate::lifecycle::validate_transition;
 use crate::CoreError;
-use karapace_runtime::backend::{select_backend, RuntimeSpec};
-use karapace_runtime::SecurityPolicy;
-use karapace_schema::types::{LayerHash, ObjectHash};
+use karapace_runtime::backend::{select_backend, RuntimeBackend, RuntimeSpec};
+use karapace_runtime::host::{detect_gpu_drivers, gpu_driver_drift};
+use karapace_runtime::image::{is_pinned_image, resolve_image, CachedImage, ImageCache};
+use karapace_runtime::import::{import_image, ImportedImage};
+use karapace_runtime::process::ProcessInfo;
+use karapace_runtime::quota::{check_quota, dir_usage};
+use karapace_runtime::session::DetachedSession;
+use karapace_runtime::toolchain;
+use karapace_runtime::{BuildPhase, EnvStats, ProgressSink, SecurityPolicy, StderrProgress};
+use karapace_schema::types::{EnvId, LayerHash, ObjectHash, ShortId};
 use karapace_schema::{
-    compute_env_id, parse_manifest_file, EnvIdentity, LockFile, ManifestV1, NormalizedManifest,
-    ResolutionResult,
+    compute_env_id, parse_age, parse_manifest_file, parse_manifest_file_with_warnings,
+    validate_label, DeprecationWarning, EnvIdentity, LockDiff, LockFile, ManifestV1,
+    NormalizedManifest, NormalizedToolchain, ResolutionResult,
 };
 use karapace_store::{
-    pack_layer, unpack_layer, EnvMetadata, EnvState, LayerKind, LayerManifest, LayerStore,
-    MetadataStore, ObjectStore, RollbackStep, StoreLayout, WalOpKind, WriteAheadLog,
+    has_pax_metadata, pack_layer, pack_layer_delta, pack_layer_delta_with_objects,
+    pack_layer_with_objects, unpack_layer, unpack_layers_with_objects, validate_env_name,
+    EnvMetadata, EnvState, GcPlan, LayerIndex, LayerKind, LayerManifest, LayerStore, MetadataStore,
+    ObjectStore, PackedLayer, RetentionPolicy, RollbackStep, StoreConfig, StoreError, StoreLayout,
+    WalOpKind, WalRecord, WriteAheadLog, DEFAULT_WORKSPACE, HISTORY_LIMIT,
 };
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
 use std::path::{Path, PathBuf};
 use tracing::{debug, info, warn};
 
@@ -28,19 +48,159 @@ pub struct Engine {
     obj_store: ObjectStore,
     layer_store: LayerStore,
     wal: WriteAheadLog,
+    hooks: Hooks,
 }
 
 /// Result of a successful environment build.
 pub struct BuildResult {
     pub identity: EnvIdentity,
     pub lock_file: LockFile,
+    /// Deprecated manifest keys the build accepted.
+    pub warnings: Vec<DeprecationWarning>,
+    /// Packages came from the build cache instead of being installed.
+    pub cached_packages: bool,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
+#[allow(clippy::struct_excessive_bools)] // independent build flags
 pub struct BuildOptions {
     pub locked: bool,
     pub offline: bool,
     pub require_pinned_image: bool,
+    /// Install packages even when the build cache holds a layer for the
+    /// same base image and package set.
+    pub no_cache: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommitOptions {
+    /// Pack only what changed since the snapshot the upper directory was
+    /// last committed as or restored from. Falls back to a full snapshot
+    /// when there is none.
+    pub incremental: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnterOptions {
+    /// Refuse to start when the host GPU driver changed since build.
+    pub strict_gpu: bool,
+    /// Discard every write made during the session. Allowed on frozen and
+    /// archived environments, whose state is left untouched.
+    pub read_only: bool,
+    /// Run an `exec` command on a new pseudo-terminal instead of the
+    /// caller's stdin, stdout and stderr. `enter` always uses the caller's
+    /// terminal.
+    pub tty: bool,
+}
+
+/// Disk use of an environment's writable upper layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnvUsage {
+    pub upper_bytes: u64,
+    /// The manifest's `max_overlay_mb`, if set.
+    pub limit_mb: Option<u64>,
+}
+
+impl EnvUsage {
+    pub fn exceeded(&self) -> bool {
+        self.limit_mb
+            .is_some_and(|limit| self.upper_bytes > limit.saturating_mul(1024 * 1024))
+    }
+}
+
+/// Disk use of a store by category, from [`Engine::store_usage`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct StoreUsage {
+    /// Loose objects and packs.
+    pub objects_bytes: u64,
+    /// Layer manifests.
+    pub layers_bytes: u64,
+    /// Environment directories: overlays, upper dirs and workspaces.
+    pub env_bytes: u64,
+    /// The base image cache.
+    pub images_bytes: u64,
+    /// Write-ahead log entries.
+    pub wal_bytes: u64,
+    /// Metadata, caches, staging and everything else.
+    pub other_bytes: u64,
+    pub envs: Vec<EnvDiskUsage>,
+}
+
+impl StoreUsage {
+    pub fn total_bytes(&self) -> u64 {
+        self.objects_bytes
+            + self.layers_bytes
+            + self.env_bytes
+            + self.images_bytes
+            + self.wal_bytes
+            + self.other_bytes
+    }
+}
+
+/// Disk use attributable to one environment.
+///
+/// Objects of the environment's layers, snapshots and metadata that no
+/// other environment references are exclusive; each shared object is split
+/// evenly between the environments referencing it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct EnvDiskUsage {
+    pub env_id: String,
+    pub name: Option<String>,
+    /// The environment directory.
+    pub env_dir_bytes: u64,
+    /// Objects only this environment references.
+    pub exclusive_bytes: u64,
+    /// Objects this environment shares with others, counted in full.
+    pub shared_bytes: u64,
+    /// `env_dir_bytes`, `exclusive_bytes` and this environment's share of
+    /// `shared_bytes`.
+    pub attributed_bytes: u64,
+}
+
+/// A snapshot of an environment, from [`Engine::snapshot_details`].
+#[derive(Debug, Clone)]
+pub struct SnapshotInfo {
+    /// Hash the snapshot is stored under, as [`Engine::restore`] takes it.
+    pub hash: String,
+    pub layer: LayerManifest,
+    /// When the snapshot was taken.
+    pub created: std::time::SystemTime,
+    /// Bytes stored for this snapshot's own tar and file objects. A delta
+    /// also needs the snapshots it is based on.
+    pub size_bytes: u64,
+    /// The upper directory was last committed as or restored from it.
+    pub current: bool,
+}
+
+/// Options for [`Engine::import_oci`].
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// `runtime.backend` of the generated manifest; left to the default
+    /// when unset.
+    pub backend: Option<String>,
+    /// Replace an existing manifest at the destination.
+    pub overwrite: bool,
+}
+
+/// Result of [`Engine::import_oci`].
+pub struct ImportResult {
+    pub image: ImportedImage,
+    pub manifest_path: PathBuf,
+    pub build: BuildResult,
+}
+
+/// A cached base image and the environments built on it.
+#[derive(Debug, Clone)]
+pub struct ImageUsage {
+    pub image: CachedImage,
+    pub used_by: Vec<String>,
+}
+
+/// A named writable workspace of an environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkspaceInfo {
+    pub name: String,
+    pub active: bool,
 }
 
 impl Engine {
@@ -69,7 +229,11 @@ impl Engine {
                     if let Ok(entries) = std::fs::read_dir(&env_base) {
                         for entry in entries.flatten() {
                             let running_marker = entry.path().join(".running");
-                            if running_marker.exists() {
+                            // Detached sessions outlive the process that
+                            // started them.
+                            let detached = DetachedSession::load(&entry.path())
+                                .is_some_and(|session| session.is_alive());
+                            if running_marker.exists() && !detached {
                                 debug!(
                                     "removing stale .running marker: {}",
                                     running_marker.display()
@@ -81,7 +245,11 @@ impl Engine {
                 }
             }
             Ok(None) => {
-                debug!("store lock held; skipping WAL recovery and stale marker cleanup");
+                let holder = StoreLock::holder(&layout.lock_file())
+                    .map_or_else(|| "another process".to_owned(), |h| h.to_string());
+                debug!(
+                    "store lock held by {holder}; skipping WAL recovery and stale marker cleanup"
+                );
             }
             Err(e) => {
                 warn!("store lock check failed; skipping WAL recovery: {e}");
@@ -96,6 +264,7 @@ impl Engine {
             obj_store,
             layer_store,
             wal,
+            hooks: Hooks::default(),
         }
     }
 
@@ -104,7 +273,7 @@ impl Engine {
         info!("initializing environment from {}", manifest_path.display());
         self.layout.initialize()?;
 
-        let manifest = parse_manifest_file(manifest_path)?;
+        let (manifest, warnings) = parse_manifest_file_with_warnings(manifest_path)?;
         let normalized = manifest.normalize()?;
 
         let identity = compute_env_id(&normalized)?;
@@ -127,8 +296,24 @@ impl Engine {
                 updated_at: now,
                 ref_count: 1,
                 checksum: None,
+                aliases: Vec::new(),
+                host_gpu: None,
+                base_image_digest: None,
+                workspace: None,
+                snapshot: None,
+                expires_after_secs: self.expires_after(&normalized)?,
+                last_entered_at: None,
+                enter_count: 0,
+                last_built_at: None,
+                labels: normalized.labels.clone(),
+                revision: 0,
+                attestation: None,
             };
-            self.meta_store.put(&meta)?;
+            match self.meta_store.put(&meta) {
+                // Another engine initialized it first.
+                Err(StoreError::Conflict { .. }) => {}
+                result => result?,
+            }
         }
 
         let preliminary_resolution = ResolutionResult {
@@ -157,24 +342,50 @@ impl Engine {
         Ok(BuildResult {
             identity,
             lock_file: lock,
+            warnings,
+            cached_packages: false,
         })
     }
 
     pub fn build(&self, manifest_path: &Path) -> Result<BuildResult, CoreError> {
-        self.build_with_options(manifest_path, BuildOptions::default())
+        self.build_with_options(manifest_path, BuildOptions::default(), &StderrProgress)
     }
 
-    #[allow(clippy::too_many_lines)]
+    /// Build with `options`, reporting each phase and status line to
+    /// `progress`.
     pub fn build_with_options(
         &self,
         manifest_path: &Path,
         options: BuildOptions,
+        progress: &dyn ProgressSink,
+    ) -> Result<BuildResult, CoreError> {
+        self.hooks.emit(&EngineEvent::PreBuild {
+            manifest: manifest_path,
+        })?;
+        let result = self.build_env(manifest_path, options, progress)?;
+        self.hooks.emit(&EngineEvent::PostBuild {
+            manifest: manifest_path,
+            env_id: &result.identity.env_id,
+            lock: &result.lock_file,
+        })?;
+        Ok(result)
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn build_env(
+        &self,
+        manifest_path: &Path,
+        options: BuildOptions,
+        progress: &dyn ProgressSink,
     ) -> Result<BuildResult, CoreError> {
         info!("building environment from {}", manifest_path.display());
+        let started_on = chrono::Utc::now().to_rfc3339();
         self.layout.initialize()?;
 
-        let manifest = parse_manifest_file(manifest_path)?;
-        let normalized = manifest.normalize()?;
+        let (manifest, warnings) = parse_manifest_file_with_warnings(manifest_path)?;
+        let manifest_dir = manifest_path.parent().unwrap_or(Path::new("."));
+        let mut normalized = manifest.normalize()?;
+        normalized.hash_toolchain_lockfiles(manifest_dir)?;
 
         if options.offline && !normalized.system_packages.is_empty() {
             return Err(CoreError::Runtime(
@@ -183,20 +394,26 @@ impl Engine {
                 ),
             ));
         }
-
-        if options.require_pinned_image
-            && !(normalized.base_image.starts_with("http://")
-                || normalized.base_image.starts_with("https://"))
+        if options.offline
+            && normalized
+                .toolchains
+                .iter()
+                .any(NormalizedToolchain::needs_network)
         {
+            return Err(CoreError::Runtime(
+                karapace_runtime::RuntimeError::ExecFailed(
+                    "offline mode: cannot install language packages".to_owned(),
+                ),
+            ));
+        }
+
+        if options.require_pinned_image && !is_pinned_image(&normalized.base_image) {
             return Err(CoreError::Manifest(
                 karapace_schema::ManifestError::UnpinnedBaseImage(normalized.base_image.clone()),
             ));
         }
 
-        let lock_path = manifest_path
-            .parent()
-            .unwrap_or(Path::new("."))
-            .join("karapace.lock");
+        let lock_path = manifest_dir.join("karapace.lock");
 
         let locked = if options.locked {
             let lock = LockFile::read_from_file(&lock_path)?;
@@ -207,37 +424,23 @@ impl Engine {
             None
         };
 
+        if let Some(lock) = &locked {
+            self.check_pinned_image(&normalized, lock)?;
+        }
+
         let policy = SecurityPolicy::from_manifest(&normalized);
         policy.validate_mounts(&normalized)?;
         policy.validate_devices(&normalized)?;
+        policy.validate_network(&normalized)?;
         policy.validate_resource_limits(&normalized)?;
 
         let store_str = self.store_root_str.clone();
         let backend = select_backend(&normalized.runtime_backend, &store_str)?;
 
-        let preliminary_id = compute_env_id(&normalized)?;
-        let preliminary_spec = RuntimeSpec {
-            env_id: preliminary_id.env_id.to_string(),
-            root_path: self
-                .layout
-                .env_path(&preliminary_id.env_id)
-                .to_string_lossy()
-                .to_string(),
-            overlay_path: self
-                .layout
-                .env_path(&preliminary_id.env_id)
-                .to_string_lossy()
-                .to_string(),
-            store_root: store_str.clone(),
-            manifest: normalized.clone(),
-            offline: options.offline,
-        };
-        let resolution = backend.resolve(&preliminary_spec)?;
-        debug!(
-            "resolved {} packages, base digest {}",
-            resolution.resolved_packages.len(),
-            &resolution.base_image_digest[..12]
-        );
+        progress.phase(BuildPhase::Resolve);
+        let resolution =
+            self.resolve_normalized(backend.as_ref(), &normalized, options.offline, progress)?;
+        check_cancelled(progress)?;
 
         let lock = LockFile::from_resolved(&normalized, &resolution);
         let identity = lock.compute_identity();
@@ -271,26 +474,136 @@ impl Engine {
             .add_rollback_step(&wal_op, RollbackStep::RemoveDir(env_dir.clone()))?;
         std::fs::create_dir_all(&env_dir)?;
 
+        // Reuse the packages of an earlier build with the same base image
+        // and package set when the build cache has them.
+        let package_cache_key =
+            (!options.no_cache && !lock.resolved_packages.is_empty()).then(|| {
+                build_cache::cache_key(
+                    backend.name(),
+                    &lock.base_image_digest,
+                    &lock.resolved_packages,
+                )
+            });
+        let cached_layer = package_cache_key.as_deref().and_then(|key| {
+            build_cache::lookup(&self.layout, &self.layer_store, &self.obj_store, key)
+        });
+
+        // Likewise the leading build steps, which sit on the package layer.
+        let step_caching = !options.no_cache && !normalized.build_steps.is_empty();
+        let step_root = |package_layer: Option<&str>| {
+            build_cache::step_root(
+                backend.name(),
+                &lock.base_image_digest,
+                package_layer,
+                &lock.toolchains,
+            )
+        };
+        let cached_steps = if step_caching
+            && (lock.resolved_packages.is_empty() || cached_layer.is_some())
+        {
+            self.cached_build_steps(&step_root(cached_layer.as_deref()), &normalized.build_steps)
+        } else {
+            Vec::new()
+        };
+        let resume_layer = cached_steps.last().or(cached_layer.as_ref());
+
+        // Install exactly what the lock records, with package patterns
+        // already expanded by the resolver.
+        let mut build_manifest = normalized.clone();
+        if resume_layer.is_none() {
+            build_manifest.system_packages = lock
+                .resolved_packages
+                .iter()
+                .map(|p| p.name.clone())
+                .collect();
+        } else {
+            build_manifest.system_packages.clear();
+        }
         let spec = RuntimeSpec {
             env_id: identity.env_id.to_string(),
             root_path: env_dir.to_string_lossy().to_string(),
             overlay_path: env_dir.to_string_lossy().to_string(),
             store_root: store_str,
-            manifest: normalized.clone(),
+            manifest: build_manifest,
             offline: options.offline,
+            read_only: false,
+        };
+        let upper_dir = self.layout.upper_dir(&identity.env_id);
+        let built = resume_layer
+            .map_or(Ok(()), |layer| {
+                self.unpack_cached_layer(layer, &upper_dir, progress)
+            })
+            .and_then(|()| backend.build(&spec, progress).map_err(CoreError::from))
+            .and_then(|()| check_cancelled(progress))
+            .and_then(|()| match (&package_cache_key, &cached_layer) {
+                (Some(key), None) => self.cache_layer(key, &upper_dir, None).map(Some),
+                _ => Ok(cached_layer.clone()),
+            })
+            .and_then(|package_layer| {
+                // Cached steps already contain the toolchains.
+                if cached_steps.is_empty() {
+                    for toolchain in &normalized.toolchains {
+                        progress
+                            .message(&format!("installing {} toolchain...", toolchain.language));
+                        install_toolchain(
+                            backend.as_ref(),
+                            &spec,
+                            toolchain,
+                            manifest_dir,
+                            &upper_dir,
+                        )?;
+                    }
+                }
+                let root = step_caching.then(|| step_root(package_layer.as_deref()));
+                let step_layers = self.run_build_steps(
+                    backend.as_ref(),
+                    &spec,
+                    cached_steps.clone(),
+                    root.as_deref(),
+                    package_layer.as_deref(),
+                    progress,
+                )?;
+                if let Some(script) = &normalized.post_build_hook {
+                    progress.message("running post_build hook...");
+                    run_post_build_hook(backend.as_ref(), &spec, script)?;
+                }
+                Ok(package_layer
+                    .into_iter()
+                    .chain(step_layers)
+                    .collect::<Vec<_>>())
+            });
+        let dependency_layers = match built {
+            Ok(layers) => layers,
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&env_dir);
+                let _ = self.wal.abort(&wal_op);
+                return Err(e);
+            }
         };
-        if let Err(e) = backend.build(&spec) {
+
+        if let Err(e) = check_quota(&upper_dir, normalized.max_overlay_mb)
+            .map_err(CoreError::from)
+            .and_then(|_| check_cancelled(progress))
+        {
             let _ = std::fs::remove_dir_all(&env_dir);
-            let _ = self.wal.commit(&wal_op);
-            return Err(e.into());
+            let _ = self.wal.abort(&wal_op);
+            return Err(e);
         }
-
-        let upper_dir = self.layout.upper_dir(&identity.env_id);
-        let build_tar = if upper_dir.exists() {
-            pack_layer(&upper_dir)?
+        progress.phase(BuildPhase::PackLayer);
+        let packed = if upper_dir.exists() {
+            self.pack_upper(&upper_dir, None)?.0
         } else {
-            Vec::new()
+            PackedLayer {
+                tar: Vec::new(),
+                files: Vec::new(),
+                pax_metadata: false,
+            }
         };
+        let PackedLayer {
+            tar: build_tar,
+            files: file_objects,
+            pax_metadata,
+        } = packed;
         let build_tar_hash = self.obj_store.put(&build_tar)?;
         debug!(
             "captured build layer: {} bytes, hash {}",
@@ -302,15 +615,32 @@ impl Engine {
             hash: build_tar_hash.clone(),
             kind: LayerKind::Base,
             parent: None,
-            object_refs: vec![build_tar_hash.clone()],
+            object_refs: std::iter::once(build_tar_hash.clone())
+                .chain(file_objects.iter().cloned())
+                .collect(),
             read_only: true,
             tar_hash: build_tar_hash.clone(),
+            workspace: None,
+            delta_parent: None,
+            file_objects,
+            pax_metadata,
         };
         let base_layer_hash = self.layer_store.put(&base_layer)?;
 
-        let dep_layers = Vec::new();
+        let dep_layers: Vec<LayerHash> =
+            dependency_layers.into_iter().map(LayerHash::new).collect();
 
         let now = chrono::Utc::now().to_rfc3339();
+        let attestation = self.store_attestation(BuildRecord {
+            lock: &lock,
+            manifest_hash: &manifest_hash,
+            layer_hash: &build_tar_hash,
+            backend: backend.name(),
+            offline: options.offline,
+            started_on,
+            finished_on: now.clone(),
+            host: HostInfo::detect(),
+        })?;
         let meta = EnvMetadata {
             env_id: identity.env_id.clone(),
             short_id: identity.short_id.clone(),
@@ -321,16 +651,25 @@ impl Engine {
             dependency_layers: dep_layers,
             policy_layer: None,
             created_at: now.clone(),
-            updated_at: now,
+            updated_at: now.clone(),
             ref_count: 1,
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: normalized.hardware_gpu.then(detect_gpu_drivers),
+            base_image_digest: Some(lock.base_image_digest.clone()),
+            attestation: Some(ObjectHash::new(attestation)),
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: self.expires_after(&normalized)?,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: Some(now),
+            labels: normalized.labels.clone(),
+            revision: 0,
         };
 
         let finalize = || -> Result<(), CoreError> {
-            if let Ok(existing) = self.meta_store.get(&identity.env_id) {
-                validate_transition(existing.state, EnvState::Built)?;
-            }
-            self.meta_store.put(&meta)?;
+            self.record_build(meta.clone())?;
 
             if !options.locked {
                 lock.write_to_file(&lock_path)?;
@@ -341,7 +680,7 @@ impl Engine {
         if let Err(e) = finalize() {
             warn!("post-build finalization failed, cleaning up env_dir: {e}");
             let _ = std::fs::remove_dir_all(&env_dir);
-            let _ = self.wal.commit(&wal_op);
+            let _ = self.wal.abort(&wal_op);
             return Err(e);
         }
 
@@ -351,9 +690,102 @@ impl Engine {
         Ok(BuildResult {
             identity,
             lock_file: lock,
+            warnings,
+            cached_packages: cached_layer.is_some(),
         })
     }
 
+    /// Refuse a locked build whose cached base image is not the one the lock
+    /// pins. Builds reuse cached images as they are, so this is the only
+    /// point where a replaced image would be noticed before resolving.
+    fn check_pinned_image(
+        &self,
+        normalized: &NormalizedManifest,
+        lock: &LockFile,
+    ) -> Result<(), CoreError> {
+        let Ok(resolved) = resolve_image(&normalized.base_image) else {
+            return Ok(());
+        };
+        let cache = ImageCache::new(self.layout.root());
+        match cache.stored_digest(&resolved.cache_key) {
+            Some(digest) if digest != lock.base_image_digest => Err(CoreError::Lock(
+                karapace_schema::LockError::ManifestDrift(format!(
+                    "locked mode: cached image '{}' has digest {}, but the lock pins {}",
+                    resolved.display_name,
+                    &digest[..12.min(digest.len())],
+                    &lock.base_image_digest[..12.min(lock.base_image_digest.len())]
+                )),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run the backend resolver for a manifest without building anything.
+    fn resolve_normalized(
+        &self,
+        backend: &dyn RuntimeBackend,
+        normalized: &NormalizedManifest,
+        offline: bool,
+        progress: &dyn ProgressSink,
+    ) -> Result<ResolutionResult, CoreError> {
+        let preliminary_id = compute_env_id(normalized)?;
+        let env_path = self
+            .layout
+            .env_path(&preliminary_id.env_id)
+            .to_string_lossy()
+            .into_owned();
+        let preliminary_spec = RuntimeSpec {
+            env_id: preliminary_id.env_id.to_string(),
+            root_path: env_path.clone(),
+            overlay_path: env_path,
+            store_root: self.store_root_str.clone(),
+            manifest: normalized.clone(),
+            offline,
+            read_only: false,
+        };
+        let resolution = backend.resolve(&preliminary_spec, progress)?;
+        debug!(
+            "resolved {} packages, base digest {}",
+            resolution.resolved_packages.len(),
+            &resolution.base_image_digest[..12]
+        );
+        Ok(resolution)
+    }
+
+    /// Check that `karapace.lock` next to the manifest is current.
+    ///
+    /// The lock is verified for integrity and manifest intent; with `frozen`,
+    /// package versions and the base digest are additionally re-resolved
+    /// (without building) and compared against it. Returns the drift, which
+    /// is empty when the lock is up to date.
+    pub fn check_lock(
+        &self,
+        manifest_path: &Path,
+        frozen: bool,
+        offline: bool,
+    ) -> Result<LockDiff, CoreError> {
+        info!("checking lock file for {}", manifest_path.display());
+        let manifest = parse_manifest_file(manifest_path)?;
+        let manifest_dir = manifest_path.parent().unwrap_or(Path::new("."));
+        let mut normalized = manifest.normalize()?;
+        normalized.hash_toolchain_lockfiles(manifest_dir)?;
+
+        let locked = LockFile::read_from_file(manifest_dir.join("karapace.lock"))?;
+        locked.verify_integrity()?;
+        locked.verify_manifest_intent(&normalized)?;
+
+        if !frozen {
+            return Ok(LockDiff::default());
+        }
+
+        self.layout.initialize()?;
+        let backend = select_backend(&normalized.runtime_backend, &self.store_root_str)?;
+        let resolution =
+            self.resolve_normalized(backend.as_ref(), &normalized, offline, &StderrProgress)?;
+        let fresh = LockFile::from_resolved(&normalized, &resolution);
+        Ok(locked.diff(&fresh))
+    }
+
     fn load_manifest(&self, manifest_hash: &str) -> Result<NormalizedManifest, CoreError> {
         let data = self.obj_store.get(manifest_hash)?;
         Ok(serde_json::from_slice(&data)?)
@@ -368,22 +800,138 @@ impl Engine {
             store_root: self.store_root_str.clone(),
             manifest,
             offline: false,
+            read_only: false,
+        }
+    }
+
+    /// Check that an environment in `state` may be entered, and whether the
+    /// session moves it to `Running`. Read-only sessions may enter frozen
+    /// and archived environments; those keep their state.
+    /// Move `env_id` to `to`, validated against the state current at write
+    /// time so concurrent engines cannot both make the same transition.
+    fn transition(&self, env_id: &str, to: EnvState) -> Result<(), CoreError> {
+        self.meta_store.update(env_id, |meta| {
+            validate_transition(meta.state, to)?;
+            meta.state = to;
+            if to == EnvState::Running {
+                meta.record_entry();
+            }
+            Ok::<_, CoreError>(())
+        })?;
+        Ok(())
+    }
+
+    /// Idle time after which an environment built from `normalized`
+    /// expires: its `runtime.expires_after`, else the store default.
+    fn expires_after(&self, normalized: &NormalizedManifest) -> Result<Option<u64>, CoreError> {
+        if let Some(secs) = normalized.expires_after_secs {
+            return Ok(Some(secs));
+        }
+        StoreConfig::load_or_default(&self.layout)
+            .expires_after
+            .as_deref()
+            .map(|value| {
+                parse_age(value)
+                    .map(|age| age.as_secs())
+                    .map_err(|e| CoreError::Config(format!("store expires_after: {e}")))
+            })
+            .transpose()
+    }
+
+    /// Store the metadata of a finished build, replacing an earlier build of
+    /// the same environment if its state allows a rebuild.
+    /// Sign the provenance of a build with the store's key and keep it as
+    /// an object.
+    fn store_attestation(&self, record: BuildRecord<'_>) -> Result<String, CoreError> {
+        let key = AttestationKey::load_or_create(&self.layout.attestation_key_file())?;
+        let envelope = Envelope::sign(&Statement::for_build(record), &key)?;
+        Ok(self.obj_store.put(envelope.to_json()?.as_bytes())?)
+    }
+
+    fn record_build(&self, mut meta: EnvMetadata) -> Result<(), CoreError> {
+        loop {
+            let Ok(existing) = self.meta_store.get(&meta.env_id) else {
+                // Nothing readable to replace.
+                return Ok(self.meta_store.overwrite(&meta)?);
+            };
+            validate_transition(existing.state, EnvState::Built)?;
+            meta.revision = existing.revision;
+            meta.created_at = existing.created_at;
+            meta.last_entered_at = existing.last_entered_at;
+            meta.enter_count = existing.enter_count;
+            // Labels set with `karapace label` survive a rebuild.
+            let declared = std::mem::replace(&mut meta.labels, existing.labels);
+            meta.labels.extend(declared);
+            match self.meta_store.put(&meta) {
+                Err(StoreError::Conflict { .. }) => std::thread::yield_now(),
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    fn tracks_running_state(state: EnvState, read_only: bool) -> Result<bool, CoreError> {
+        if read_only && matches!(state, EnvState::Frozen | EnvState::Archived) {
+            return Ok(false);
+        }
+        validate_transition(state, EnvState::Running)?;
+        Ok(true)
+    }
+
+    /// Compare the host GPU drivers recorded at build time with the current
+    /// host. Warns on drift, or fails when `strict` is set.
+    fn check_gpu_drift(meta: &EnvMetadata, strict: bool) -> Result<(), CoreError> {
+        let Some(built) = &meta.host_gpu else {
+            return Ok(());
+        };
+        let changes = gpu_driver_drift(built, &detect_gpu_drivers());
+        if changes.is_empty() {
+            return Ok(());
         }
+        let detail = changes.join("; ");
+        if strict {
+            return Err(CoreError::GpuDriverDrift(detail));
+        }
+        warn!(
+            "host GPU driver changed since {} was built ({detail}); \
+             GPU access may fail until the environment is rebuilt (karapace rebuild)",
+            meta.short_id
+        );
+        Ok(())
     }
 
     pub fn enter(&self, env_id: &str) -> Result<(), CoreError> {
+        self.enter_with_options(env_id, EnterOptions::default())
+    }
+
+    pub fn enter_with_options(&self, env_id: &str, options: EnterOptions) -> Result<(), CoreError> {
+        let read_only = options.read_only;
+        self.hooks
+            .emit(&EngineEvent::PreEnter { env_id, read_only })?;
+        self.enter_session(env_id, options)?;
+        self.hooks
+            .emit(&EngineEvent::PostEnter { env_id, read_only })
+    }
+
+    fn enter_session(&self, env_id: &str, options: EnterOptions) -> Result<(), CoreError> {
         info!("entering environment {env_id}");
         let meta = self
             .meta_store
             .get(env_id)
             .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
 
-        validate_transition(meta.state, EnvState::Running)?;
+        let tracked = Self::tracks_running_state(meta.state, options.read_only)?;
+        Self::check_gpu_drift(&meta, options.strict_gpu)?;
 
         let normalized = self.load_manifest(&meta.manifest_hash)?;
         let store_str = self.store_root_str.clone();
         let backend = select_backend(&normalized.runtime_backend, &store_str)?;
-        let spec = self.prepare_spec(env_id, normalized);
+        let mut spec = self.prepare_spec(env_id, normalized);
+        spec.read_only = options.read_only;
+
+        if !tracked {
+            self.meta_store.record_entry(env_id)?;
+            return backend.enter(&spec).map_err(Into::into);
+        }
 
         // WAL: if we crash while Running, recover back to Built
         self.wal.initialize()?;
@@ -396,35 +944,51 @@ impl Engine {
             },
         )?;
 
-        self.meta_store.update_state(env_id, EnvState::Running)?;
+        self.transition(env_id, EnvState::Running)?;
         if let Err(e) = backend.enter(&spec) {
             let _ = self.meta_store.update_state(env_id, EnvState::Built);
-            let _ = self.wal.commit(&wal_op);
+            let _ = self.wal.abort(&wal_op);
             return Err(e.into());
         }
         self.meta_store.update_state(env_id, EnvState::Built)?;
         self.wal.commit(&wal_op)?;
 
+        check_quota(&self.layout.upper_dir(env_id), spec.manifest.max_overlay_mb)?;
         Ok(())
     }
 
-    pub fn exec(&self, env_id: &str, command: &[String]) -> Result<(), CoreError> {
-        info!("exec in environment {env_id}: {command:?}");
+    /// Start a session of `env_id` that keeps running in the background,
+    /// leaving the environment `Running` until [`stop`](Self::stop). Shells
+    /// join it with [`attach`](Self::attach). `pre_enter` hooks run;
+    /// `post_enter` hooks do not, since the session outlives the call.
+    pub fn enter_detached(&self, env_id: &str, options: EnterOptions) -> Result<(), CoreError> {
+        info!("starting detached session of {env_id}");
+        if options.read_only {
+            return Err(CoreError::Runtime(
+                karapace_runtime::RuntimeError::ExecFailed(
+                    "read-only sessions cannot be detached".to_owned(),
+                ),
+            ));
+        }
+        self.hooks.emit(&EngineEvent::PreEnter {
+            env_id,
+            read_only: false,
+        })?;
         let meta = self
             .meta_store
             .get(env_id)
             .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
-
         validate_transition(meta.state, EnvState::Running)?;
+        Self::check_gpu_drift(&meta, options.strict_gpu)?;
 
         let normalized = self.load_manifest(&meta.manifest_hash)?;
         let store_str = self.store_root_str.clone();
         let backend = select_backend(&normalized.runtime_backend, &store_str)?;
         let spec = self.prepare_spec(env_id, normalized);
 
-        // WAL: if we crash while Running, recover back to Built
+        // WAL: if we crash before the session is up, recover back to Built
         self.wal.initialize()?;
-        let wal_op = self.wal.begin(WalOpKind::Exec, env_id)?;
+        let wal_op = self.wal.begin(WalOpKind::Enter, env_id)?;
         self.wal.add_rollback_step(
             &wal_op,
             RollbackStep::ResetState {
@@ -433,44 +997,148 @@ impl Engine {
             },
         )?;
 
-        self.meta_store.update_state(env_id, EnvState::Running)?;
-        let result = backend.exec(&spec, command);
-        let _ = self.meta_store.update_state(env_id, EnvState::Built);
-        let _ = self.wal.commit(&wal_op);
-
-        match result {
-            Ok(output) => {
-                use std::io::Write;
-                let _ = std::io::stdout().write_all(&output.stdout);
-                let _ = std::io::stderr().write_all(&output.stderr);
-                if output.status.success() {
-                    Ok(())
-                } else {
-                    let detail = if let Some(code) = output.status.code() {
-                        format!("command exited with code {code}")
-                    } else {
-                        #[cfg(unix)]
-                        {
-                            use std::os::unix::process::ExitStatusExt;
-                            match output.status.signal() {
-                                Some(sig) => format!("command killed by signal {sig}"),
-                                None => "command failed with unknown status".to_owned(),
-                            }
-                        }
-                        #[cfg(not(unix))]
-                        {
-                            "command failed with unknown status".to_owned()
-                        }
-                    };
-                    Err(CoreError::Runtime(
-                        karapace_runtime::RuntimeError::ExecFailed(detail),
-                    ))
-                }
-            }
-            Err(e) => Err(e.into()),
+        self.transition(env_id, EnvState::Running)?;
+        if let Err(e) = backend.start_detached(&spec) {
+            let _ = self.meta_store.update_state(env_id, EnvState::Built);
+            let _ = self.wal.abort(&wal_op);
+            return Err(e.into());
+        }
+        self.wal.commit(&wal_op)?;
+        Ok(())
+    }
+
+    /// Run `command`, or a login shell when it is empty, in the detached
+    /// session of `env_id` and return its exit code.
+    pub fn attach(&self, env_id: &str, command: &[String]) -> Result<i32, CoreError> {
+        info!("attaching to environment {env_id}");
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        if meta.state != EnvState::Running {
+            return Err(CoreError::Runtime(
+                karapace_runtime::RuntimeError::NotRunning(env_id.to_owned()),
+            ));
+        }
+
+        let normalized = self.load_manifest(&meta.manifest_hash)?;
+        let store_str = self.store_root_str.clone();
+        let backend = select_backend(&normalized.runtime_backend, &store_str)?;
+        let spec = self.prepare_spec(env_id, normalized);
+        self.meta_store.record_entry(env_id)?;
+        let status = backend.attach(&spec, command)?;
+        Ok(exit_code(status))
+    }
+
+    /// Processes running in the environment, in PID order.
+    pub fn ps(&self, env_id: &str) -> Result<Vec<ProcessInfo>, CoreError> {
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        if meta.state != EnvState::Running {
+            return Err(CoreError::Runtime(
+                karapace_runtime::RuntimeError::NotRunning(env_id.to_owned()),
+            ));
+        }
+
+        let normalized = self.load_manifest(&meta.manifest_hash)?;
+        let backend = select_backend(&normalized.runtime_backend, &self.store_root_str)?;
+        Ok(backend.processes(env_id)?)
+    }
+
+    /// Resource usage of the environment. Only the upper directory is
+    /// measured unless it is running.
+    pub fn stats(&self, env_id: &str) -> Result<EnvStats, CoreError> {
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        if meta.state != EnvState::Running {
+            return Ok(EnvStats::new(
+                &[],
+                dir_usage(&self.layout.upper_dir(env_id)),
+            ));
+        }
+
+        let normalized = self.load_manifest(&meta.manifest_hash)?;
+        let backend = select_backend(&normalized.runtime_backend, &self.store_root_str)?;
+        let spec = self.prepare_spec(env_id, normalized);
+        Ok(backend.stats(&spec)?)
+    }
+
+    /// Run `command` in the environment and fail unless it exits with 0.
+    pub fn exec(&self, env_id: &str, command: &[String]) -> Result<(), CoreError> {
+        match self.exec_with_options(env_id, command, EnterOptions::default())? {
+            0 => Ok(()),
+            code => Err(CoreError::Runtime(
+                karapace_runtime::RuntimeError::ExecFailed(format!(
+                    "command exited with code {code}"
+                )),
+            )),
         }
     }
 
+    /// Run `command` in the environment attached to the caller's terminal
+    /// and return its exit code. A command killed by a signal reports 128
+    /// plus the signal number, as shells do.
+    pub fn exec_with_options(
+        &self,
+        env_id: &str,
+        command: &[String],
+        options: EnterOptions,
+    ) -> Result<i32, CoreError> {
+        info!("exec in environment {env_id}: {command:?}");
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+
+        let tracked = Self::tracks_running_state(meta.state, options.read_only)?;
+        Self::check_gpu_drift(&meta, options.strict_gpu)?;
+
+        let normalized = self.load_manifest(&meta.manifest_hash)?;
+        let store_str = self.store_root_str.clone();
+        let backend = select_backend(&normalized.runtime_backend, &store_str)?;
+        let mut spec = self.prepare_spec(env_id, normalized);
+        spec.read_only = options.read_only;
+
+        let result = if tracked {
+            // WAL: if we crash while Running, recover back to Built
+            self.wal.initialize()?;
+            let wal_op = self.wal.begin(WalOpKind::Exec, env_id)?;
+            self.wal.add_rollback_step(
+                &wal_op,
+                RollbackStep::ResetState {
+                    env_id: env_id.to_owned(),
+                    target_state: "Built".to_owned(),
+                },
+            )?;
+
+            self.transition(env_id, EnvState::Running)?;
+            let result = backend.exec_attached(&spec, command, options.tty);
+            let _ = self.meta_store.update_state(env_id, EnvState::Built);
+            let _ = if result.is_ok() {
+                self.wal.commit(&wal_op)
+            } else {
+                self.wal.abort(&wal_op)
+            };
+            result
+        } else {
+            self.meta_store.record_entry(env_id)?;
+            backend.exec_attached(&spec, command, options.tty)
+        };
+        let quota = if tracked {
+            check_quota(&self.layout.upper_dir(env_id), spec.manifest.max_overlay_mb)
+        } else {
+            Ok(0)
+        };
+
+        let status = result?;
+        quota?;
+        Ok(exit_code(status))
+    }
+
     pub fn stop(&self, env_id: &str) -> Result<(), CoreError> {
         info!("stopping environment {env_id}");
         let meta = self
@@ -532,12 +1200,20 @@ impl Engine {
         // Clean up running marker
         let running_file = self.layout.env_path(env_id).join(".running");
         let _ = std::fs::remove_file(running_file);
+        let spec = self.prepare_spec(env_id, normalized);
+        backend.end_detached(&spec)?;
 
         self.meta_store.update_state(env_id, EnvState::Built)?;
         Ok(())
     }
 
     pub fn destroy(&self, env_id: &str) -> Result<(), CoreError> {
+        self.hooks.emit(&EngineEvent::PreDestroy { env_id })?;
+        self.destroy_env(env_id)?;
+        self.hooks.emit(&EngineEvent::PostDestroy { env_id })
+    }
+
+    fn destroy_env(&self, env_id: &str) -> Result<(), CoreError> {
         info!("destroying environment {env_id}");
         let meta = self
             .meta_store
@@ -569,16 +1245,19 @@ impl Engine {
             .add_rollback_step(&wal_op, RollbackStep::RemoveDir(env_dir.clone()))?;
 
         if let Err(e) = backend.destroy(&spec) {
-            let _ = self.wal.commit(&wal_op);
+            let _ = self.wal.abort(&wal_op);
             return Err(e.into());
         }
         if env_dir.exists() {
             std::fs::remove_dir_all(&env_dir)?;
         }
 
-        let metadata_path = self.layout.metadata_dir().join(env_id);
-        self.wal
-            .add_rollback_step(&wal_op, RollbackStep::RemoveFile(metadata_path))?;
+        self.wal.add_rollback_step(
+            &wal_op,
+            RollbackStep::RemoveMetadata {
+                env_id: env_id.to_owned(),
+            },
+        )?;
         let remaining = self.meta_store.decrement_ref(env_id)?;
         if remaining == 0 {
             let _ = self.meta_store.remove(env_id);
@@ -590,14 +1269,74 @@ impl Engine {
         Ok(())
     }
 
+    /// Run `f` as one transaction: either every mutation it makes through
+    /// the [`Transaction`] takes effect, or none does. An error from `f`
+    /// rolls them back before it is returned; a crash leaves a WAL entry
+    /// that recovery rolls back on the next start.
+    ///
+    /// Destroyed environments are moved aside and only deleted once `f`
+    /// returns, and a garbage collection requested with
+    /// [`Transaction::gc`] runs after the commit, since what it deletes
+    /// could not be put back. Mutations made on the engine directly inside
+    /// `f` are not part of the transaction.
+    pub fn with_transaction<T>(
+        &self,
+        lock: &StoreLock,
+        f: impl FnOnce(&mut Transaction<'_>) -> Result<T, CoreError>,
+    ) -> Result<T, CoreError> {
+        self.wal.initialize()?;
+        let op_id = self.wal.begin(WalOpKind::Transaction, "transaction")?;
+        let trash = self.layout.staging_dir().join(format!("tx-{op_id}"));
+        let mut tx = Transaction {
+            engine: self,
+            op_id: op_id.clone(),
+            trash: trash.clone(),
+            saved: HashSet::new(),
+            destroyed: Vec::new(),
+            gc: false,
+        };
+        // Registered first so that it runs last, once rollback has moved
+        // destroyed environments back.
+        let result = self
+            .wal
+            .add_rollback_step(&op_id, RollbackStep::RemoveDir(trash.clone()))
+            .map_err(CoreError::from)
+            .and_then(|()| f(&mut tx));
+        let value = match result {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("transaction failed, rolling back: {e}");
+                if let Err(rollback) = self.wal.rollback(&op_id) {
+                    warn!("transaction rollback failed: {rollback}");
+                }
+                return Err(e);
+            }
+        };
+        self.wal.commit(&op_id)?;
+
+        if trash.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&trash) {
+                warn!("failed to remove {}: {e}", trash.display());
+            }
+        }
+        for env_id in &tx.destroyed {
+            self.hooks.emit(&EngineEvent::PostDestroy { env_id })?;
+        }
+        if tx.gc {
+            self.gc(lock, false)?;
+        }
+        Ok(value)
+    }
+
     pub fn rebuild(&self, manifest_path: &Path) -> Result<BuildResult, CoreError> {
-        self.rebuild_with_options(manifest_path, BuildOptions::default())
+        self.rebuild_with_options(manifest_path, BuildOptions::default(), &StderrProgress)
     }
 
     pub fn rebuild_with_options(
         &self,
         manifest_path: &Path,
         options: BuildOptions,
+        progress: &dyn ProgressSink,
     ) -> Result<BuildResult, CoreError> {
         // Collect the old env_id(s) to clean up AFTER a successful build.
         // This ensures we don't lose the old environment if the new build fails.
@@ -622,7 +1361,7 @@ impl Engine {
         }
 
         // Build first — if this fails, old environment is preserved.
-        let result = self.build_with_options(manifest_path, options)?;
+        let result = self.build_with_options(manifest_path, options, progress)?;
 
         // Only destroy the old environment(s) after the new build succeeds.
         for old_id in &old_env_ids {
@@ -637,16 +1376,308 @@ impl Engine {
         Ok(result)
     }
 
+    /// Import an OCI or Docker image as a base image, write a manifest
+    /// pinned to it at `manifest_path`, and build the environment.
+    ///
+    /// `source` is an OCI image layout directory, a `docker save` or OCI
+    /// archive, or a registry reference fetched with skopeo or podman.
+    pub fn import_oci(
+        &self,
+        source: &str,
+        manifest_path: &Path,
+        options: &ImportOptions,
+        progress: &dyn ProgressSink,
+    ) -> Result<ImportResult, CoreError> {
+        if manifest_path.exists() && !options.overwrite {
+            return Err(CoreError::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", manifest_path.display()),
+            )));
+        }
+        self.layout.initialize()?;
+        let image = import_image(source, &ImageCache::new(self.layout.root()), progress)?;
+        std::fs::write(
+            manifest_path,
+            imported_manifest(source, &image, options.backend.as_deref()),
+        )?;
+        let build = self.build_with_options(manifest_path, BuildOptions::default(), progress)?;
+        Ok(ImportResult {
+            image,
+            manifest_path: manifest_path.to_path_buf(),
+            build,
+        })
+    }
+
+    /// Download the base image `name`, spelled as in `base.image`, into the
+    /// image cache so later builds, including offline ones, can use it.
+    pub fn pull_image(
+        &self,
+        name: &str,
+        progress: &dyn ProgressSink,
+    ) -> Result<CachedImage, CoreError> {
+        self.layout.initialize()?;
+        let resolved = resolve_image(name)?;
+        Ok(ImageCache::new(self.layout.root()).pull(&resolved, progress)?)
+    }
+
+    /// The cached base images, with the environments built on each.
+    pub fn list_images(&self) -> Result<Vec<ImageUsage>, CoreError> {
+        let mut users = self.image_users()?;
+        Ok(ImageCache::new(self.layout.root())
+            .list()?
+            .into_iter()
+            .map(|image| ImageUsage {
+                used_by: users.remove(&image.cache_key).unwrap_or_default(),
+                image,
+            })
+            .collect())
+    }
+
+    /// Remove a cached base image, named as in `base.image` or by its cache
+    /// key. Images environments are built on are kept unless `force`: their
+    /// rootfs is the lower layer of every session.
+    pub fn remove_image(&self, name: &str, force: bool) -> Result<CachedImage, CoreError> {
+        let cache = ImageCache::new(self.layout.root());
+        let cache_key = if cache.is_cached(name) {
+            name.to_owned()
+        } else {
+            resolve_image(name)?.cache_key
+        };
+        let image = cache.entry(&cache_key)?;
+        let used_by = self.image_users()?.remove(&cache_key).unwrap_or_default();
+        if !used_by.is_empty() && !force {
+            return Err(CoreError::ImageInUse {
+                image: cache_key,
+                envs: used_by,
+            });
+        }
+        cache.remove(&cache_key)?;
+        info!("removed cached image {cache_key}");
+        Ok(image)
+    }
+
+    /// Environments by the cache key of their base image.
+    fn image_users(&self) -> Result<HashMap<String, Vec<String>>, CoreError> {
+        let mut users: HashMap<String, Vec<String>> = HashMap::new();
+        for meta in self.meta_store.list()? {
+            let Ok(normalized) = self.load_manifest(&meta.manifest_hash) else {
+                continue;
+            };
+            if let Ok(resolved) = resolve_image(&normalized.base_image) {
+                users
+                    .entry(resolved.cache_key)
+                    .or_default()
+                    .push(meta.env_id.to_string());
+            }
+        }
+        Ok(users)
+    }
+
     pub fn inspect(&self, env_id: &str) -> Result<EnvMetadata, CoreError> {
         self.meta_store
             .get(env_id)
             .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))
     }
 
+    /// The signed build provenance of an environment, if it has one.
+    pub fn attestation(&self, env_id: &str) -> Result<Option<Envelope>, CoreError> {
+        let meta = self.inspect(env_id)?;
+        match &meta.attestation {
+            Some(hash) => Ok(Some(Envelope::from_json(&self.obj_store.get(hash)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Check the signatures of an environment's attestation, and that it
+    /// describes this environment's manifest and build layer.
+    pub fn verify_attestation(&self, env_id: &str) -> Result<(Envelope, Statement), CoreError> {
+        let meta = self.inspect(env_id)?;
+        let envelope = self.attestation(env_id)?.ok_or_else(|| {
+            CoreError::Attestation(format!("environment {} has no attestation", meta.short_id))
+        })?;
+        let statement = envelope.verify()?;
+        let layer = self.layer_store.get(&meta.base_layer)?;
+        let mismatch = if statement.env_id() != Some(meta.env_id.as_str()) {
+            Some("environment")
+        } else if statement.layer_hash() != Some(layer.tar_hash.as_str()) {
+            Some("build layer")
+        } else if statement
+            .predicate
+            .build_definition
+            .external_parameters
+            .manifest_hash
+            != meta.manifest_hash.as_str()
+        {
+            Some("manifest")
+        } else {
+            None
+        };
+        if let Some(what) = mismatch {
+            return Err(CoreError::Attestation(format!(
+                "attestation does not match the {what} of {}",
+                meta.short_id
+            )));
+        }
+        Ok((envelope, statement))
+    }
+
+    /// Check the packages in an environment's attested lock against `feed`.
+    /// Environments without an attestation cannot be audited.
+    pub fn audit(
+        &self,
+        env_id: &str,
+        feed: &dyn crate::audit::VulnFeed,
+    ) -> Result<crate::audit::AuditReport, CoreError> {
+        let (_, statement) = self.verify_attestation(env_id)?;
+        crate::audit::audit_lock(
+            &statement
+                .predicate
+                .build_definition
+                .external_parameters
+                .lock,
+            feed,
+        )
+    }
+
+    /// Current size of the environment's upper layer and its limit.
+    pub fn usage(&self, env_id: &str) -> Result<EnvUsage, CoreError> {
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        let normalized = self.load_manifest(&meta.manifest_hash)?;
+        Ok(EnvUsage {
+            upper_bytes: dir_usage(&self.layout.upper_dir(env_id)),
+            limit_mb: normalized.max_overlay_mb,
+        })
+    }
+
+    /// Disk use of the store by category and by environment.
+    pub fn store_usage(&self) -> Result<StoreUsage, CoreError> {
+        let mut usage = StoreUsage::default();
+        for entry in std::fs::read_dir(self.layout.root())? {
+            let entry = entry?;
+            let bytes = dir_usage(&entry.path());
+            match entry.file_name().to_str() {
+                Some("env") => usage.env_bytes += bytes,
+                Some("images") => usage.images_bytes += bytes,
+                Some("store") => Self::store_dir_usage(&entry.path(), &mut usage)?,
+                _ => usage.other_bytes += bytes,
+            }
+        }
+
+        let sizes = self.obj_store.stored_sizes()?;
+        let mut snapshot_refs: HashMap<String, Vec<String>> = HashMap::new();
+        for hash in self.layer_store.list()? {
+            if let Ok(layer) = self.layer_store.get(&hash) {
+                if let (LayerKind::Snapshot, Some(parent)) = (&layer.kind, layer.parent) {
+                    snapshot_refs
+                        .entry(parent)
+                        .or_default()
+                        .extend(layer.object_refs);
+                }
+            }
+        }
+        let mut env_objects = Vec::new();
+        let mut referrers: HashMap<String, u64> = HashMap::new();
+        for meta in self.meta_store.list()? {
+            let objects = self.env_objects(&meta, &snapshot_refs)?;
+            for hash in &objects {
+                *referrers.entry(hash.clone()).or_default() += 1;
+            }
+            env_objects.push((meta, objects));
+        }
+        for (meta, objects) in env_objects {
+            let env_id = meta.env_id.to_string();
+            let env_dir_bytes = dir_usage(&self.layout.env_path(&env_id));
+            let (mut exclusive_bytes, mut shared_bytes, mut share) = (0, 0, 0);
+            for hash in &objects {
+                let size = sizes.get(hash).copied().unwrap_or(0);
+                match referrers.get(hash).copied().unwrap_or(1) {
+                    1 => exclusive_bytes += size,
+                    n => {
+                        shared_bytes += size;
+                        share += size / n;
+                    }
+                }
+            }
+            usage.envs.push(EnvDiskUsage {
+                env_id,
+                name: meta.name,
+                env_dir_bytes,
+                exclusive_bytes,
+                shared_bytes,
+                attributed_bytes: env_dir_bytes + exclusive_bytes + share,
+            });
+        }
+        usage
+            .envs
+            .sort_by_key(|env| std::cmp::Reverse(env.attributed_bytes));
+        Ok(usage)
+    }
+
+    fn store_dir_usage(dir: &Path, usage: &mut StoreUsage) -> Result<(), CoreError> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let bytes = dir_usage(&entry.path());
+            match entry.file_name().to_str() {
+                Some("objects" | "packs") => usage.objects_bytes += bytes,
+                Some("layers") => usage.layers_bytes += bytes,
+                Some("wal") => usage.wal_bytes += bytes,
+                _ => usage.other_bytes += bytes,
+            }
+        }
+        Ok(())
+    }
+
+    /// Objects an environment keeps alive: its metadata objects and those
+    /// of its layers and snapshots, chunks included. `snapshot_refs` maps
+    /// each base layer to the objects of the snapshots taken on it.
+    fn env_objects(
+        &self,
+        meta: &EnvMetadata,
+        snapshot_refs: &HashMap<String, Vec<String>>,
+    ) -> Result<Vec<String>, CoreError> {
+        let mut objects = meta.direct_objects();
+        let layers = std::iter::once(&meta.base_layer)
+            .chain(&meta.dependency_layers)
+            .chain(&meta.policy_layer);
+        for hash in layers {
+            if let Ok(layer) = self.layer_store.get(hash) {
+                objects.extend(layer.object_refs);
+            }
+        }
+        if let Some(refs) = snapshot_refs.get(meta.base_layer.as_str()) {
+            objects.extend(refs.iter().cloned());
+        }
+        Ok(self.obj_store.with_chunks(objects)?)
+    }
+
     pub fn list(&self) -> Result<Vec<EnvMetadata>, CoreError> {
         Ok(self.meta_store.list()?)
     }
 
+    /// Environments carrying every label in `selectors` (`key` or
+    /// `key=value`).
+    pub fn list_matching(&self, selectors: &[String]) -> Result<Vec<EnvMetadata>, CoreError> {
+        Ok(self.meta_store.list_matching(selectors)?)
+    }
+
+    /// The environment whose env_id, short id or name is exactly `key`,
+    /// looked up through the metadata index.
+    pub fn find(&self, key: &str) -> Result<Option<EnvMetadata>, CoreError> {
+        Ok(self.meta_store.find(key)?)
+    }
+
+    /// The environment that formerly carried the name `alias`.
+    pub fn find_by_alias(&self, alias: &str) -> Result<Option<EnvMetadata>, CoreError> {
+        match self.meta_store.get_by_alias(alias) {
+            Ok(meta) => Ok(Some(meta)),
+            Err(StoreError::EnvNotFound(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub fn freeze(&self, env_id: &str) -> Result<(), CoreError> {
         info!("freezing environment {env_id}");
         let meta = self
@@ -655,8 +1686,7 @@ impl Engine {
             .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
 
         validate_transition(meta.state, EnvState::Frozen)?;
-        self.meta_store.update_state(env_id, EnvState::Frozen)?;
-        Ok(())
+        self.transition(env_id, EnvState::Frozen)
     }
 
     pub fn archive(&self, env_id: &str) -> Result<(), CoreError> {
@@ -667,8 +1697,7 @@ impl Engine {
             .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
 
         validate_transition(meta.state, EnvState::Archived)?;
-        self.meta_store.update_state(env_id, EnvState::Archived)?;
-        Ok(())
+        self.transition(env_id, EnvState::Archived)
     }
 
     pub fn set_name(&self, env_id: &str, name: Option<String>) -> Result<(), CoreError> {
@@ -679,12 +1708,139 @@ impl Engine {
         Ok(())
     }
 
+    /// Set the labels in `set` and remove those named in `remove`.
+    /// Returns the labels the environment has afterwards.
+    pub fn set_labels(
+        &self,
+        env_id: &str,
+        set: &BTreeMap<String, String>,
+        remove: &[String],
+    ) -> Result<BTreeMap<String, String>, CoreError> {
+        for (key, value) in set {
+            validate_label(key, value).map_err(StoreError::InvalidLabel)?;
+        }
+        let meta = self.meta_store.update(env_id, |meta| {
+            for key in remove {
+                meta.labels.remove(key);
+            }
+            meta.labels.extend(set.clone());
+            Ok::<_, StoreError>(())
+        })?;
+        Ok(meta.labels)
+    }
+
     pub fn rename(&self, env_id: &str, new_name: &str) -> Result<(), CoreError> {
+        self.rename_with_options(env_id, new_name, true)
+    }
+
+    /// Rename an environment. When `keep_alias` is set, the previous name
+    /// stays resolvable as an alias.
+    pub fn rename_with_options(
+        &self,
+        env_id: &str,
+        new_name: &str,
+        keep_alias: bool,
+    ) -> Result<(), CoreError> {
         info!("renaming environment {env_id} to '{new_name}'");
-        self.set_name(env_id, Some(new_name.to_owned()))
+        self.meta_store.rename(env_id, new_name, keep_alias)?;
+        Ok(())
+    }
+
+    /// Clone an environment as `new_name` and return the clone's ID.
+    ///
+    /// The clone gets a fresh ID and shares the source's manifest and
+    /// layers, which the store keeps while either environment references
+    /// them. Its upper dir starts as a copy of the source's active one;
+    /// inactive workspaces are not copied. A frozen source gives a built
+    /// clone. The build attestation is not carried over, since it names the
+    /// source.
+    pub fn clone_env(&self, src_env_id: &str, new_name: &str) -> Result<String, CoreError> {
+        info!("cloning environment {src_env_id} as '{new_name}'");
+        let src = self
+            .meta_store
+            .get(src_env_id)
+            .map_err(|_| CoreError::EnvNotFound(src_env_id.to_owned()))?;
+        if src.state != EnvState::Built && src.state != EnvState::Frozen {
+            return Err(CoreError::InvalidTransition {
+                from: src.state.to_string(),
+                to: "cloning requires built or frozen state".to_owned(),
+            });
+        }
+
+        let now = chrono::Utc::now().to_rfc3339();
+        let env_id = blake3::hash(format!("clone:{}:{new_name}:{now}", src.env_id).as_bytes())
+            .to_hex()
+            .to_string();
+        self.meta_store.check_name_available(&env_id, new_name)?;
+
+        self.wal.initialize()?;
+        let wal_op = self.wal.begin(WalOpKind::Clone, &env_id)?;
+
+        let env_dir = self.layout.env_path(&env_id);
+        self.wal
+            .add_rollback_step(&wal_op, RollbackStep::RemoveDir(env_dir.clone()))?;
+        std::fs::create_dir_all(&env_dir)?;
+        let src_upper = self.layout.upper_dir(src_env_id);
+        if src_upper.exists() {
+            unpack_layer(&pack_layer(&src_upper)?, &self.layout.upper_dir(&env_id))?;
+        }
+        // Backends refuse to enter environments they have not built.
+        let built_marker = self.layout.env_path(src_env_id).join(".built");
+        if built_marker.exists() {
+            std::fs::copy(&built_marker, env_dir.join(".built"))?;
+        }
+
+        self.wal.add_rollback_step(
+            &wal_op,
+            RollbackStep::RemoveMetadata {
+                env_id: env_id.clone(),
+            },
+        )?;
+        let meta = EnvMetadata {
+            env_id: EnvId::new(env_id.clone()),
+            short_id: ShortId::new(env_id[..12].to_owned()),
+            name: Some(new_name.to_owned()),
+            state: EnvState::Built,
+            created_at: now.clone(),
+            updated_at: now,
+            ref_count: 1,
+            aliases: Vec::new(),
+            attestation: None,
+            workspace: None,
+            last_entered_at: None,
+            enter_count: 0,
+            revision: 0,
+            checksum: None,
+            ..src
+        };
+        self.meta_store.put(&meta)?;
+
+        self.wal.commit(&wal_op)?;
+        Ok(env_id)
     }
 
     pub fn commit(&self, env_id: &str) -> Result<String, CoreError> {
+        self.commit_with_options(env_id, CommitOptions::default())
+    }
+
+    pub fn commit_with_options(
+        &self,
+        env_id: &str,
+        options: CommitOptions,
+    ) -> Result<String, CoreError> {
+        self.hooks.emit(&EngineEvent::PreCommit {
+            env_id,
+            incremental: options.incremental,
+        })?;
+        let snapshot = self.commit_upper(env_id, options)?;
+        self.hooks.emit(&EngineEvent::PostCommit {
+            env_id,
+            snapshot: &snapshot,
+        })?;
+        Ok(snapshot)
+    }
+
+    fn commit_upper(&self, env_id: &str, options: CommitOptions) -> Result<String, CoreError> {
         info!("committing overlay drift for {env_id}");
         let meta = self
             .meta_store
@@ -704,37 +1860,52 @@ impl Engine {
 
         // Pack the overlay upper directory as a deterministic tar layer.
         let upper_dir = self.layout.upper_dir(env_id);
-        let tar_data = if upper_dir.exists() {
-            pack_layer(&upper_dir)?
-        } else {
-            let _ = self.wal.commit(&wal_op);
+        if !upper_dir.exists() {
+            let _ = self.wal.abort(&wal_op);
             return Err(CoreError::EnvNotFound(format!(
                 "no overlay upper directory for {env_id}"
             )));
+        }
+        let delta_base = if options.incremental {
+            self.delta_base(&meta)
+        } else {
+            None
         };
+        let (packed, mut object_refs) = self.pack_upper(&upper_dir, delta_base.as_ref())?;
+        let PackedLayer {
+            tar: tar_data,
+            files: file_objects,
+            pax_metadata,
+        } = packed;
 
         let tar_hash = self.obj_store.put(&tar_data)?;
+        object_refs.push(tar_hash.clone());
+        object_refs.extend(file_objects.iter().cloned());
         debug!(
-            "committed snapshot layer: {} bytes, hash {}",
+            "committed {} snapshot layer: {} bytes, hash {}",
+            if delta_base.is_some() {
+                "delta"
+            } else {
+                "full"
+            },
             tar_data.len(),
             &tar_hash[..12]
         );
 
-        // Compute a unique layer hash for this snapshot.
-        // The tar_hash alone may collide with the base layer if the upper
-        // dir content hasn't changed. Use a composite identity.
-        let snapshot_id_input = format!("snapshot:{}:{}:{}", env_id, meta.base_layer, tar_hash);
-        let snapshot_hash = blake3::hash(snapshot_id_input.as_bytes())
-            .to_hex()
-            .to_string();
+        let delta_parent = delta_base.as_ref().and(meta.snapshot.clone());
+        let snapshot_hash = snapshot_id(&meta, &tar_hash, delta_parent.as_deref());
 
         let snapshot_layer = LayerManifest {
             hash: snapshot_hash.clone(),
             kind: LayerKind::Snapshot,
             parent: Some(meta.base_layer.to_string()),
-            object_refs: vec![tar_hash.clone()],
+            object_refs,
             read_only: true,
             tar_hash,
+            workspace: meta.workspace.clone(),
+            delta_parent,
+            file_objects,
+            pax_metadata,
         };
         // Compute the content hash before writing so we can register the
         // correct rollback path. Uses LayerStore::compute_hash() to ensure
@@ -747,6 +1918,8 @@ impl Engine {
         self.wal
             .add_rollback_step(&wal_op, RollbackStep::RemoveFile(layer_path))?;
         let stored_hash = self.layer_store.put(&snapshot_layer)?;
+        self.meta_store
+            .update_snapshot(env_id, Some(stored_hash.clone()))?;
 
         // Commit succeeded — remove WAL entry
         self.wal.commit(&wal_op)?;
@@ -754,6 +1927,171 @@ impl Engine {
         Ok(stored_hash)
     }
 
+    /// Unpack the cached layer `hash`, with the layers it is a delta of,
+    /// into a new environment's upper directory, for the backend to build
+    /// on top of.
+    fn unpack_cached_layer(
+        &self,
+        hash: &str,
+        upper_dir: &Path,
+        progress: &dyn ProgressSink,
+    ) -> Result<(), CoreError> {
+        progress.phase(BuildPhase::InstallPackages);
+        progress.message(&format!("reusing cached build layer ({})", &hash[..12]));
+        let layer = self.layer_store.get(hash)?;
+        let tars = layer
+            .tar_chain()
+            .iter()
+            .map(|tar| self.obj_store.get(tar))
+            .collect::<Result<Vec<_>, _>>()?;
+        unpack_layers_with_objects(&tars, upper_dir, &self.obj_store)?;
+        Ok(())
+    }
+
+    /// Store a freshly built upper directory as a `Dependency` layer, as a
+    /// delta against the layer `parent` when given, and record it in the
+    /// build cache under `key`.
+    fn cache_layer(
+        &self,
+        key: &str,
+        upper_dir: &Path,
+        parent: Option<&str>,
+    ) -> Result<String, CoreError> {
+        let base = parent.map(|hash| self.layer_store.get(hash)).transpose()?;
+        let (packed, chain) = self.pack_upper(upper_dir, base.as_ref())?;
+        let tar_hash = self.obj_store.put(&packed.tar)?;
+        let layer = LayerManifest {
+            hash: tar_hash.clone(),
+            kind: LayerKind::Dependency,
+            parent: None,
+            object_refs: chain
+                .into_iter()
+                .chain(std::iter::once(tar_hash.clone()))
+                .chain(packed.files.iter().cloned())
+                .collect(),
+            read_only: true,
+            tar_hash,
+            workspace: None,
+            delta_parent: parent.map(str::to_owned),
+            file_objects: packed.files,
+            pax_metadata: packed.pax_metadata,
+        };
+        let hash = self.layer_store.put(&layer)?;
+        build_cache::record(&self.layout, key, &hash)?;
+        debug!("cached build layer {} under {}", &hash[..12], &key[..12]);
+        Ok(hash)
+    }
+
+    /// Layers of the leading `steps` the build cache holds, chained from
+    /// `root`.
+    fn cached_build_steps(&self, root: &str, steps: &[String]) -> Vec<String> {
+        let mut layers: Vec<String> = Vec::new();
+        for step in steps {
+            let parent = layers.last().map_or(root, String::as_str);
+            let key = build_cache::step_key(parent, step);
+            match build_cache::lookup(&self.layout, &self.layer_store, &self.obj_store, &key) {
+                Some(layer) => layers.push(layer),
+                None => break,
+            }
+        }
+        layers
+    }
+
+    /// Run the manifest's build steps after the `cached` ones. With a cache
+    /// `root`, each step's changes are stored as a layer on top of the
+    /// previous one, starting from `package_layer`. Returns the layers of
+    /// all steps, or none without caching.
+    fn run_build_steps(
+        &self,
+        backend: &dyn RuntimeBackend,
+        spec: &RuntimeSpec,
+        cached: Vec<String>,
+        root: Option<&str>,
+        package_layer: Option<&str>,
+        progress: &dyn ProgressSink,
+    ) -> Result<Vec<String>, CoreError> {
+        let steps = &spec.manifest.build_steps;
+        let upper_dir = self.layout.upper_dir(&spec.env_id);
+        let mut layers = cached;
+        for (i, step) in steps.iter().enumerate().skip(layers.len()) {
+            progress.message(&format!("build step {}/{}: {step}", i + 1, steps.len()));
+            run_build_step(backend, spec, step)?;
+            if let Some(root) = root {
+                let parent = layers.last().map(String::as_str);
+                let key = build_cache::step_key(parent.unwrap_or(root), step);
+                let layer = self.cache_layer(&key, &upper_dir, parent.or(package_layer))?;
+                layers.push(layer);
+            }
+        }
+        Ok(layers)
+    }
+
+    /// Pack an upper directory as a full tar, or as a delta against
+    /// `delta_base`. Returns the tar with the file objects the whole chain
+    /// refers to and whether the chain records extended attributes or
+    /// holes, and the tars it applies on top of.
+    fn pack_upper(
+        &self,
+        upper_dir: &Path,
+        delta_base: Option<&LayerManifest>,
+    ) -> Result<(PackedLayer, Vec<String>), CoreError> {
+        // With file dedup, a delta also keeps the file objects of its
+        // parent chain, since its tars still refer to them.
+        let file_dedup = self.obj_store.config().file_dedup;
+        let (tar, chain, mut files, pax_metadata) = match delta_base {
+            Some(parent) => {
+                let chain = parent.tar_chain();
+                let tars = chain
+                    .iter()
+                    .map(|hash| self.obj_store.get(hash))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let index = LayerIndex::from_tars(&tars)?;
+                let mut files = parent.file_objects.clone();
+                let (tar, pax_metadata) = if file_dedup {
+                    let packed = pack_layer_delta_with_objects(upper_dir, &index, &self.obj_store)?;
+                    files.extend(packed.files);
+                    (packed.tar, packed.pax_metadata)
+                } else {
+                    let tar = pack_layer_delta(upper_dir, &index)?;
+                    let pax_metadata = has_pax_metadata(&tar)?;
+                    (tar, pax_metadata)
+                };
+                (tar, chain, files, pax_metadata || parent.pax_metadata)
+            }
+            None if file_dedup => {
+                let packed = pack_layer_with_objects(upper_dir, &self.obj_store)?;
+                (packed.tar, Vec::new(), packed.files, packed.pax_metadata)
+            }
+            None => {
+                let tar = pack_layer(upper_dir)?;
+                let pax_metadata = has_pax_metadata(&tar)?;
+                (tar, Vec::new(), Vec::new(), pax_metadata)
+            }
+        };
+        files.sort();
+        files.dedup();
+        Ok((
+            PackedLayer {
+                tar,
+                files,
+                pax_metadata,
+            },
+            chain,
+        ))
+    }
+
+    /// The snapshot an incremental commit of `meta` can be encoded against:
+    /// the one its upper directory was last committed as or restored from,
+    /// if it still exists and belongs to the same base layer and workspace.
+    fn delta_base(&self, meta: &EnvMetadata) -> Option<LayerManifest> {
+        let layer = self.layer_store.get(meta.snapshot.as_deref()?).ok()?;
+        let usable = layer.kind == LayerKind::Snapshot
+            && !layer.tar_hash.is_empty()
+            && layer.parent.as_deref() == Some(&meta.base_layer)
+            && layer.workspace == meta.workspace;
+        usable.then_some(layer)
+    }
+
     /// Restore an environment's overlay from a snapshot layer.
     ///
     /// Unpacks the snapshot tar into the overlay upper directory, replacing
@@ -774,11 +2112,10 @@ impl Engine {
         }
 
         // Verify the snapshot layer exists and is a Snapshot kind.
-        let layer = self.layer_store.get(snapshot_hash).map_err(|_| {
-            CoreError::Store(karapace_store::StoreError::LayerNotFound(
-                snapshot_hash.to_owned(),
-            ))
-        })?;
+        let layer = self
+            .layer_store
+            .get(snapshot_hash)
+            .map_err(|_| CoreError::Store(StoreError::LayerNotFound(snapshot_hash.to_owned())))?;
         if layer.kind != LayerKind::Snapshot {
             return Err(CoreError::InvalidTransition {
                 from: format!("{:?}", layer.kind),
@@ -786,13 +2123,18 @@ impl Engine {
             });
         }
         if layer.tar_hash.is_empty() {
-            return Err(CoreError::Store(karapace_store::StoreError::LayerNotFound(
-                format!("snapshot {snapshot_hash} has no tar content (legacy layer)"),
-            )));
+            return Err(CoreError::Store(StoreError::LayerNotFound(format!(
+                "snapshot {snapshot_hash} has no tar content (legacy layer)"
+            ))));
         }
 
-        // Retrieve the tar data from the object store.
-        let tar_data = self.obj_store.get(&layer.tar_hash)?;
+        // Retrieve the tar data from the object store: just the snapshot's
+        // own tar, or the whole chain for a delta.
+        let tars = layer
+            .tar_chain()
+            .iter()
+            .map(|hash| self.obj_store.get(hash))
+            .collect::<Result<Vec<_>, _>>()?;
 
         // Begin WAL entry for restore
         self.wal.initialize()?;
@@ -810,7 +2152,7 @@ impl Engine {
             std::fs::remove_dir_all(&staging)?;
         }
 
-        unpack_layer(&tar_data, &staging)?;
+        unpack_layers_with_objects(&tars, &staging, &self.obj_store)?;
 
         // Swap: remove old upper, rename staging to upper.
         let upper_dir = self.layout.upper_dir(env_id);
@@ -818,6 +2160,8 @@ impl Engine {
             std::fs::remove_dir_all(&upper_dir)?;
         }
         std::fs::rename(&staging, &upper_dir)?;
+        self.meta_store
+            .update_snapshot(env_id, Some(snapshot_hash.to_owned()))?;
 
         // Restore succeeded — remove WAL entry
         self.wal.commit(&wal_op)?;
@@ -826,40 +2170,461 @@ impl Engine {
         Ok(())
     }
 
+    /// Pack the overlay of `env_id` into a drift bundle, for
+    /// [`Engine::apply_drift`] on another store.
+    pub fn export_drift(&self, env_id: &str) -> Result<Vec<u8>, CoreError> {
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        let info = DriftBundleInfo {
+            format: DRIFT_BUNDLE_FORMAT,
+            env_id: meta.env_id.to_string(),
+            base_layer: meta.base_layer.to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        pack_drift_bundle(&info, &pack_layer(&self.layout.upper_dir(env_id))?)
+    }
+
+    /// Merge the overlay in a drift bundle into the overlay of `env_id`.
+    ///
+    /// Files in the bundle replace those at the same path and its whiteouts
+    /// remove files; everything else in the overlay is kept. The merge is
+    /// staged and swapped in like a restore. A bundle exported from another
+    /// base layer is applied with a warning.
+    pub fn apply_drift(&self, env_id: &str, bundle: &[u8]) -> Result<DriftBundleInfo, CoreError> {
+        info!("applying drift bundle to {env_id}");
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        if meta.state != EnvState::Built && meta.state != EnvState::Frozen {
+            return Err(CoreError::InvalidTransition {
+                from: meta.state.to_string(),
+                to: "applying drift requires built or frozen state".to_owned(),
+            });
+        }
+        let (bundle_info, upper_tar) = unpack_drift_bundle(bundle)?;
+        if bundle_info.base_layer != meta.base_layer.as_str() {
+            warn!(
+                "drift bundle was exported from base layer {}, {env_id} is on {}",
+                bundle_info.base_layer, meta.base_layer
+            );
+        }
+
+        self.wal.initialize()?;
+        let wal_op = self.wal.begin(WalOpKind::DriftApply, env_id)?;
+        let staging = self.layout.staging_dir().join(format!("drift-{env_id}"));
+        self.wal
+            .add_rollback_step(&wal_op, RollbackStep::RemoveDir(staging.clone()))?;
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+
+        let upper_dir = self.layout.upper_dir(env_id);
+        unpack_layer(&pack_layer(&upper_dir)?, &staging)?;
+        merge_upper(&upper_tar, &staging)?;
+        if upper_dir.exists() {
+            std::fs::remove_dir_all(&upper_dir)?;
+        }
+        std::fs::rename(&staging, &upper_dir)?;
+
+        self.wal.commit(&wal_op)?;
+        Ok(bundle_info)
+    }
+
     /// List all snapshot layers associated with an environment.
     ///
     /// Returns snapshot `LayerManifest` entries whose parent matches
-    /// the environment's base layer, ordered by hash.
+    /// the environment's base layer and that were taken from the active
+    /// workspace, ordered by hash.
     pub fn list_snapshots(&self, env_id: &str) -> Result<Vec<LayerManifest>, CoreError> {
         let meta = self
             .meta_store
             .get(env_id)
             .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
 
-        let all_hashes = self.layer_store.list()?;
-        let mut snapshots = Vec::new();
-        for hash in &all_hashes {
-            if let Ok(layer) = self.layer_store.get(hash) {
-                if layer.kind == LayerKind::Snapshot
-                    && layer.parent.as_deref() == Some(&meta.base_layer)
-                {
-                    snapshots.push(layer);
-                }
-            }
-        }
+        let mut snapshots: Vec<LayerManifest> = self
+            .snapshot_layers(&meta)?
+            .into_iter()
+            .map(|(_, layer)| layer)
+            .collect();
         snapshots.sort_by(|a, b| a.hash.cmp(&b.hash));
         Ok(snapshots)
     }
 
+    /// Snapshots of an environment's active workspace with their sizes and
+    /// creation times, newest first.
+    pub fn snapshot_details(&self, env_id: &str) -> Result<Vec<SnapshotInfo>, CoreError> {
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        let sizes = self.obj_store.stored_sizes()?;
+        let layers_dir = self.layout.layers_dir();
+        let mut snapshots: Vec<SnapshotInfo> = self
+            .snapshot_layers(&meta)?
+            .into_iter()
+            .map(|(hash, layer)| {
+                let created = std::fs::metadata(layers_dir.join(&hash))
+                    .and_then(|m| m.modified())
+                    .unwrap_or(std::time::UNIX_EPOCH);
+                let size_bytes = std::iter::once(&layer.tar_hash)
+                    .chain(&layer.file_objects)
+                    .filter_map(|object| sizes.get(object))
+                    .sum();
+                SnapshotInfo {
+                    current: meta.snapshot.as_deref() == Some(hash.as_str()),
+                    hash,
+                    layer,
+                    created,
+                    size_bytes,
+                }
+            })
+            .collect();
+        snapshots.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| a.hash.cmp(&b.hash)));
+        Ok(snapshots)
+    }
+
+    /// Remove one snapshot of an environment's active workspace. Its objects
+    /// are freed by the next GC; deltas based on it keep what they need.
+    pub fn remove_snapshot(&self, env_id: &str, snapshot_hash: &str) -> Result<(), CoreError> {
+        info!("removing snapshot {snapshot_hash} of {env_id}");
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        if !self
+            .snapshot_layers(&meta)?
+            .iter()
+            .any(|(hash, _)| hash == snapshot_hash)
+        {
+            return Err(CoreError::Store(StoreError::LayerNotFound(
+                snapshot_hash.to_owned(),
+            )));
+        }
+        self.layer_store.remove(snapshot_hash)?;
+        Ok(())
+    }
+
+    /// Compare a snapshot of an environment with its current upper
+    /// directory: added files are new since the snapshot.
+    pub fn diff_snapshot(
+        &self,
+        env_id: &str,
+        snapshot_hash: &str,
+    ) -> Result<DriftReport, CoreError> {
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        let layer = self
+            .snapshot_layers(&meta)?
+            .into_iter()
+            .find_map(|(hash, layer)| (hash == snapshot_hash).then_some(layer))
+            .ok_or_else(|| CoreError::Store(StoreError::LayerNotFound(snapshot_hash.to_owned())))?;
+        let tars = layer
+            .tar_chain()
+            .iter()
+            .map(|hash| self.obj_store.get(hash))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let staging = self
+            .layout
+            .staging_dir()
+            .join(format!("snapshot-diff-{env_id}"));
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        let report = unpack_layers_with_objects(&tars, &staging, &self.obj_store)
+            .map_err(CoreError::from)
+            .and_then(|()| diff_upper_dirs(env_id, &staging, &self.layout.upper_dir(env_id)));
+        let _ = std::fs::remove_dir_all(&staging);
+        report
+    }
+
+    /// Snapshots of `meta`'s base layer and active workspace, with the
+    /// hash each is stored under.
+    fn snapshot_layers(
+        &self,
+        meta: &EnvMetadata,
+    ) -> Result<Vec<(String, LayerManifest)>, CoreError> {
+        let mut snapshots = Vec::new();
+        for hash in self.layer_store.list()? {
+            if let Ok(layer) = self.layer_store.get(&hash) {
+                if layer.kind == LayerKind::Snapshot
+                    && layer.parent.as_deref() == Some(&meta.base_layer)
+                    && layer.workspace == meta.workspace
+                {
+                    snapshots.push((hash, layer));
+                }
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Remove all but the newest `keep` snapshots of an environment's active
+    /// workspace and return the stored hashes of those removed. The snapshot
+    /// the upper dir was last committed as or restored from counts as the
+    /// newest. Their objects are freed by the next GC.
+    pub fn prune_snapshots(&self, env_id: &str, keep: usize) -> Result<Vec<String>, CoreError> {
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        let layers_dir = self.layout.layers_dir();
+        let mut snapshots: Vec<(bool, std::time::SystemTime, String)> = self
+            .snapshot_layers(&meta)?
+            .into_iter()
+            .map(|(hash, _)| {
+                let time = std::fs::metadata(layers_dir.join(&hash))
+                    .and_then(|m| m.modified())
+                    .unwrap_or(std::time::UNIX_EPOCH);
+                (meta.snapshot.as_deref() == Some(hash.as_str()), time, hash)
+            })
+            .collect();
+        snapshots.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then_with(|| b.1.cmp(&a.1))
+                .then_with(|| a.2.cmp(&b.2))
+        });
+
+        let mut removed = Vec::new();
+        for (_, _, hash) in snapshots.into_iter().skip(keep) {
+            self.layer_store.remove(&hash)?;
+            removed.push(hash);
+        }
+        Ok(removed)
+    }
+
+    /// List the workspaces of an environment, ordered by name.
+    pub fn list_workspaces(&self, env_id: &str) -> Result<Vec<WorkspaceInfo>, CoreError> {
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        let active = meta.workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE);
+
+        let mut workspaces = vec![WorkspaceInfo {
+            name: active.to_owned(),
+            active: true,
+        }];
+        let dir = self.layout.workspaces_dir(env_id);
+        if dir.exists() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                // Leftovers of an interrupted switch have no upper dir.
+                if name != active && entry.path().join("upper").is_dir() {
+                    workspaces.push(WorkspaceInfo {
+                        name,
+                        active: false,
+                    });
+                }
+            }
+        }
+        workspaces.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(workspaces)
+    }
+
+    /// Make `name` the active workspace of an environment.
+    ///
+    /// The current upper dir is parked under `workspaces/<current>/upper`
+    /// and the target's upper dir takes its place. A workspace that does
+    /// not exist yet is created empty, or as a copy of the current upper
+    /// dir when `branch` is set.
+    pub fn use_workspace(&self, env_id: &str, name: &str, branch: bool) -> Result<(), CoreError> {
+        info!("switching {env_id} to workspace '{name}'");
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+
+        if meta.state != EnvState::Built && meta.state != EnvState::Frozen {
+            return Err(CoreError::InvalidTransition {
+                from: meta.state.to_string(),
+                to: "switching workspaces requires built or frozen state".to_owned(),
+            });
+        }
+        validate_env_name(name)?;
+
+        let current = meta.workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE);
+        let target = self.layout.workspace_upper_dir(env_id, name);
+        if name == current || target.exists() {
+            if branch {
+                return Err(CoreError::Workspace(format!(
+                    "workspace '{name}' already exists"
+                )));
+            }
+            if name == current {
+                debug!("workspace '{name}' is already active");
+                return Ok(());
+            }
+        }
+
+        self.wal.initialize()?;
+        let wal_op = self.wal.begin(WalOpKind::Workspace, env_id)?;
+
+        let upper_dir = self.layout.upper_dir(env_id);
+        let target_root = self.layout.workspaces_dir(env_id).join(name);
+        if !target.exists() {
+            let staging = self
+                .layout
+                .staging_dir()
+                .join(format!("workspace-{env_id}"));
+            self.wal
+                .add_rollback_step(&wal_op, RollbackStep::RemoveDir(staging.clone()))?;
+            if staging.exists() {
+                std::fs::remove_dir_all(&staging)?;
+            }
+            if branch && upper_dir.exists() {
+                unpack_layer(&pack_layer(&upper_dir)?, &staging)?;
+            } else {
+                std::fs::create_dir_all(&staging)?;
+            }
+
+            self.wal
+                .add_rollback_step(&wal_op, RollbackStep::RemoveDir(target_root.clone()))?;
+            std::fs::create_dir_all(&target_root)?;
+            std::fs::rename(&staging, &target)?;
+        }
+
+        // Park the current upper dir. Each rename registers its inverse first;
+        // rollback skips renames that never happened.
+        if upper_dir.exists() {
+            let parked = self.layout.workspace_upper_dir(env_id, current);
+            if let Some(parent) = parked.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            self.wal.add_rollback_step(
+                &wal_op,
+                RollbackStep::RenameDir {
+                    from: parked.clone(),
+                    to: upper_dir.clone(),
+                },
+            )?;
+            std::fs::rename(&upper_dir, &parked)?;
+        }
+
+        self.wal.add_rollback_step(
+            &wal_op,
+            RollbackStep::RenameDir {
+                from: upper_dir.clone(),
+                to: target.clone(),
+            },
+        )?;
+        std::fs::rename(&target, &upper_dir)?;
+        let _ = std::fs::remove_dir(&target_root);
+
+        self.wal.add_rollback_step(
+            &wal_op,
+            RollbackStep::ResetWorkspace {
+                env_id: env_id.to_owned(),
+                workspace: meta.workspace.clone(),
+            },
+        )?;
+        self.meta_store
+            .update_workspace(env_id, (name != DEFAULT_WORKSPACE).then(|| name.to_owned()))?;
+
+        self.wal.commit(&wal_op)?;
+        Ok(())
+    }
+
+    /// Delete an inactive workspace and its upper dir. Snapshots taken from
+    /// it are kept.
+    pub fn remove_workspace(&self, env_id: &str, name: &str) -> Result<(), CoreError> {
+        info!("removing workspace '{name}' of {env_id}");
+        let meta = self
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        validate_env_name(name)?;
+
+        if meta.workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE) == name {
+            return Err(CoreError::Workspace(format!(
+                "cannot remove the active workspace '{name}'; switch to another first"
+            )));
+        }
+        let target_root = self.layout.workspaces_dir(env_id).join(name);
+        if !target_root.join("upper").is_dir() {
+            return Err(CoreError::Workspace(format!(
+                "workspace '{name}' does not exist"
+            )));
+        }
+        std::fs::remove_dir_all(&target_root)?;
+        Ok(())
+    }
+
     /// Run garbage collection on the store.
     ///
     /// Requires a `&StoreLock` parameter as compile-time proof that the caller
     /// holds the store lock. The lock is not used internally — its presence in
     /// the signature enforces the invariant at the type level.
     pub fn gc(
+        &self,
+        lock: &StoreLock,
+        dry_run: bool,
+    ) -> Result<karapace_store::GcReport, CoreError> {
+        self.gc_with_options(lock, dry_run, None)
+    }
+
+    /// Garbage collect, then optionally consolidate loose objects smaller
+    /// than `pack_threshold` bytes into a pack.
+    pub fn gc_with_options(
+        &self,
+        lock: &StoreLock,
+        dry_run: bool,
+        pack_threshold: Option<u64>,
+    ) -> Result<karapace_store::GcReport, CoreError> {
+        self.gc_with_retention(lock, dry_run, pack_threshold, RetentionPolicy::default())
+    }
+
+    /// Garbage collect after evicting archived environments and snapshots
+    /// according to `retention`.
+    pub fn gc_with_retention(
+        &self,
+        lock: &StoreLock,
+        dry_run: bool,
+        pack_threshold: Option<u64>,
+        retention: RetentionPolicy,
+    ) -> Result<karapace_store::GcReport, CoreError> {
+        let plan = self.plan_gc(retention)?;
+        self.sweep_gc(lock, plan, dry_run, pack_threshold)
+    }
+
+    /// Mark phase of garbage collection: find what is unreferenced. Takes
+    /// no lock, so on a large store builds can go on while it runs; pass
+    /// the plan to [`sweep_gc`](Self::sweep_gc) under the store lock.
+    pub fn plan_gc(&self, retention: RetentionPolicy) -> Result<GcPlan, CoreError> {
+        info!("marking unreferenced store content");
+        Ok(karapace_store::GarbageCollector::new(self.layout.clone())
+            .with_retention(retention)
+            .mark()?)
+    }
+
+    /// Sweep phase of garbage collection: remove what `plan` found
+    /// unreferenced, except what became referenced since.
+    pub fn sweep_gc(
         &self,
         _lock: &StoreLock,
+        plan: GcPlan,
+        dry_run: bool,
+        pack_threshold: Option<u64>,
+    ) -> Result<karapace_store::GcReport, CoreError> {
+        self.hooks.emit(&EngineEvent::PreGc { dry_run })?;
+        let report = self.collect_garbage(plan, dry_run, pack_threshold)?;
+        self.hooks.emit(&EngineEvent::PostGc {
+            dry_run,
+            report: &report,
+        })?;
+        Ok(report)
+    }
+
+    fn collect_garbage(
+        &self,
+        plan: GcPlan,
         dry_run: bool,
+        pack_threshold: Option<u64>,
     ) -> Result<karapace_store::GcReport, CoreError> {
         info!("running garbage collection (dry_run={dry_run})");
 
@@ -869,13 +2634,51 @@ impl Engine {
         self.wal.initialize()?;
         let wal_op = self.wal.begin(WalOpKind::Gc, "gc")?;
 
-        let gc = karapace_store::GarbageCollector::new(self.layout.clone());
-        let report = gc.collect_with_cancel(dry_run, crate::shutdown_requested)?;
+        let mut gc = karapace_store::GarbageCollector::new(self.layout.clone());
+        if let Some(threshold) = pack_threshold {
+            gc = gc.with_pack_threshold(threshold);
+        }
+        let report = gc.sweep(plan, dry_run, crate::shutdown_requested)?;
+        if !dry_run {
+            let dropped = self.wal.compact(HISTORY_LIMIT)?;
+            if dropped > 0 {
+                info!("dropped {dropped} old records from the operation history");
+            }
+        }
 
         self.wal.commit(&wal_op)?;
         Ok(report)
     }
 
+    /// Completed operations, newest first, optionally only those on
+    /// environments whose id starts with `env_id`.
+    pub fn history(&self, env_id: Option<&str>, limit: usize) -> Result<Vec<WalRecord>, CoreError> {
+        let mut records = self.wal.history()?;
+        if let Some(env_id) = env_id {
+            records.retain(|record| record.env_id.starts_with(env_id));
+        }
+        records.reverse();
+        records.truncate(limit);
+        Ok(records)
+    }
+
+    /// Consolidate loose objects smaller than `threshold` bytes and all
+    /// existing packs into a single pack.
+    pub fn repack(
+        &self,
+        _lock: &StoreLock,
+        threshold: u64,
+    ) -> Result<karapace_store::RepackReport, CoreError> {
+        info!("repacking objects below {threshold} bytes");
+        Ok(self.obj_store.repack(threshold)?)
+    }
+
+    /// Move all packed objects back to loose files and delete the packs.
+    pub fn unpack(&self, _lock: &StoreLock) -> Result<usize, CoreError> {
+        info!("unpacking all packed objects");
+        Ok(self.obj_store.unpack()?)
+    }
+
     /// Push an environment to a remote store.
     ///
     /// Transfers metadata, layers, and objects to the remote backend,
@@ -886,13 +2689,42 @@ impl Engine {
         env_id: &str,
         backend: &dyn karapace_remote::RemoteBackend,
         registry_tag: Option<&str>,
+    ) -> Result<karapace_remote::PushResult, CoreError> {
+        self.push_with_cipher(env_id, backend, registry_tag, None)
+    }
+
+    /// Push an environment, encrypting every uploaded blob with `cipher`
+    /// and recording its key fingerprints in the registry entry.
+    pub fn push_with_cipher(
+        &self,
+        env_id: &str,
+        backend: &dyn karapace_remote::RemoteBackend,
+        registry_tag: Option<&str>,
+        cipher: Option<&dyn karapace_remote::BlobCipher>,
+    ) -> Result<karapace_remote::PushResult, CoreError> {
+        let options = karapace_remote::TransferOptions {
+            cipher,
+            ..karapace_remote::TransferOptions::default()
+        };
+        self.push_with_options(env_id, backend, registry_tag, &options)
+    }
+
+    /// Push an environment with explicit transfer settings: concurrency,
+    /// encryption, and a per-object progress callback.
+    pub fn push_with_options(
+        &self,
+        env_id: &str,
+        backend: &dyn karapace_remote::RemoteBackend,
+        registry_tag: Option<&str>,
+        options: &karapace_remote::TransferOptions<'_>,
     ) -> Result<karapace_remote::PushResult, CoreError> {
         info!("pushing environment {env_id}");
-        Ok(karapace_remote::push_env(
+        Ok(karapace_remote::push_env_with_options(
             &self.layout,
             env_id,
             backend,
             registry_tag,
+            options,
         )?)
     }
 
@@ -905,10 +2737,52 @@ impl Engine {
         &self,
         env_id: &str,
         backend: &dyn karapace_remote::RemoteBackend,
+    ) -> Result<karapace_remote::PullResult, CoreError> {
+        self.pull_with_cipher(env_id, backend, None)
+    }
+
+    /// Pull an environment, decrypting age-encrypted blobs with `cipher`.
+    pub fn pull_with_cipher(
+        &self,
+        env_id: &str,
+        backend: &dyn karapace_remote::RemoteBackend,
+        cipher: Option<&dyn karapace_remote::BlobCipher>,
+    ) -> Result<karapace_remote::PullResult, CoreError> {
+        let options = karapace_remote::TransferOptions {
+            cipher,
+            ..karapace_remote::TransferOptions::default()
+        };
+        self.pull_with_options(env_id, backend, &options)
+    }
+
+    /// Pull an environment with explicit transfer settings.
+    pub fn pull_with_options(
+        &self,
+        env_id: &str,
+        backend: &dyn karapace_remote::RemoteBackend,
+        options: &karapace_remote::TransferOptions<'_>,
     ) -> Result<karapace_remote::PullResult, CoreError> {
         info!("pulling environment {env_id}");
         self.layout.initialize()?;
-        Ok(karapace_remote::pull_env(&self.layout, env_id, backend)?)
+        Ok(karapace_remote::pull_env_with_options(
+            &self.layout,
+            env_id,
+            backend,
+            options,
+        )?)
+    }
+
+    /// Pull every reference in `refs` that is not in the store yet, several
+    /// at once. A failing reference is reported, not fatal. See
+    /// [`crate::sync`].
+    pub fn sync(
+        &self,
+        refs: &[String],
+        backend: &dyn karapace_remote::RemoteBackend,
+        options: &crate::sync::SyncOptions<'_>,
+    ) -> Result<crate::sync::SyncReport, CoreError> {
+        info!("syncing {} environment(s)", refs.len());
+        crate::sync::run(self, refs, backend, options)
     }
 
     /// Resolve a registry reference to an env_id using the remote registry.
@@ -919,6 +2793,11 @@ impl Engine {
         Ok(karapace_remote::resolve_ref(backend, reference)?)
     }
 
+    /// Handlers notified of lifecycle events. See [`crate::hooks`].
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
     pub fn store_layout(&self) -> &StoreLayout {
         &self.layout
     }
@@ -934,6 +2813,271 @@ impl Engine {
     }
 }
 
+/// Mutations grouped into one WAL entry by [`Engine::with_transaction`].
+pub struct Transaction<'a> {
+    engine: &'a Engine,
+    op_id: String,
+    /// Where destroyed environments' directories wait for the commit.
+    trash: PathBuf,
+    /// Environments whose metadata rollback already restores.
+    saved: HashSet<String>,
+    destroyed: Vec<String>,
+    gc: bool,
+}
+
+impl Transaction<'_> {
+    /// Destroy an environment. Its directory is kept aside until the
+    /// transaction commits.
+    pub fn destroy(&mut self, env_id: &str) -> Result<(), CoreError> {
+        let engine = self.engine;
+        engine.hooks.emit(&EngineEvent::PreDestroy { env_id })?;
+        info!(
+            "destroying environment {env_id} (transaction {})",
+            self.op_id
+        );
+        let meta = self.save(env_id)?;
+        if meta.state == EnvState::Running {
+            return Err(CoreError::InvalidTransition {
+                from: "Running".to_owned(),
+                to: "cannot destroy a running environment; stop it first".to_owned(),
+            });
+        }
+
+        let env_dir = engine.layout.env_path(env_id);
+        if env_dir.exists() {
+            let kept = self.trash.join(env_id);
+            std::fs::create_dir_all(&self.trash)?;
+            engine.wal.add_rollback_step(
+                &self.op_id,
+                RollbackStep::RenameDir {
+                    from: kept.clone(),
+                    to: env_dir.clone(),
+                },
+            )?;
+            std::fs::rename(&env_dir, &kept)?;
+        }
+        // With the directory gone, the backend only clears runtime state.
+        let normalized = engine.load_manifest(&meta.manifest_hash)?;
+        let backend = select_backend(&normalized.runtime_backend, &engine.store_root_str)?;
+        backend.destroy(&engine.prepare_spec(env_id, normalized))?;
+
+        if engine.meta_store.decrement_ref(env_id)? == 0 {
+            engine.meta_store.remove(env_id)?;
+        }
+        self.destroyed.push(env_id.to_owned());
+        Ok(())
+    }
+
+    pub fn freeze(&mut self, env_id: &str) -> Result<(), CoreError> {
+        self.save(env_id)?;
+        self.engine.freeze(env_id)
+    }
+
+    pub fn archive(&mut self, env_id: &str) -> Result<(), CoreError> {
+        self.save(env_id)?;
+        self.engine.archive(env_id)
+    }
+
+    pub fn rename(&mut self, env_id: &str, new_name: &str) -> Result<(), CoreError> {
+        self.save(env_id)?;
+        self.engine.rename(env_id, new_name)
+    }
+
+    /// Set the labels in `set` and remove those named in `remove`.
+    pub fn set_labels(
+        &mut self,
+        env_id: &str,
+        set: &BTreeMap<String, String>,
+        remove: &[String],
+    ) -> Result<BTreeMap<String, String>, CoreError> {
+        self.save(env_id)?;
+        self.engine.set_labels(env_id, set, remove)
+    }
+
+    /// Collect garbage once the transaction has committed.
+    pub fn gc(&mut self) {
+        self.gc = true;
+    }
+
+    /// Record the metadata of `env_id` for rollback, the first time the
+    /// transaction touches it, and return it.
+    fn save(&mut self, env_id: &str) -> Result<EnvMetadata, CoreError> {
+        let meta = self
+            .engine
+            .meta_store
+            .get(env_id)
+            .map_err(|_| CoreError::EnvNotFound(env_id.to_owned()))?;
+        if self.saved.insert(env_id.to_owned()) {
+            self.engine.wal.add_rollback_step(
+                &self.op_id,
+                RollbackStep::RestoreMetadata {
+                    metadata: Box::new(meta.clone()),
+                },
+            )?;
+        }
+        Ok(meta)
+    }
+}
+
+/// Identity of a snapshot of `meta` whose tar is `tar_hash`.
+///
+/// The tar_hash alone may collide with the base layer if the upper dir
+/// content hasn't changed, so the identity is composite. Non-default
+/// workspaces get their own lineage, so identical content committed from
+/// two workspaces yields two distinct snapshots. A delta is only
+/// meaningful on top of its parent, so the parent is part of its identity.
+pub(crate) fn snapshot_id(
+    meta: &EnvMetadata,
+    tar_hash: &str,
+    delta_parent: Option<&str>,
+) -> String {
+    let mut input = match &meta.workspace {
+        Some(ws) => format!(
+            "snapshot:{}:{}:{}:{}",
+            meta.env_id, meta.base_layer, ws, tar_hash
+        ),
+        None => format!("snapshot:{}:{}:{}", meta.env_id, meta.base_layer, tar_hash),
+    };
+    if let Some(parent) = delta_parent {
+        input.push_str(":delta:");
+        input.push_str(parent);
+    }
+    blake3::hash(input.as_bytes()).to_hex().to_string()
+}
+
+/// Fail with [`CoreError::Cancelled`] once `progress` asks the build to stop.
+fn check_cancelled(progress: &dyn ProgressSink) -> Result<(), CoreError> {
+    if progress.cancelled() {
+        Err(CoreError::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// The shell-style exit code of a finished command.
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status
+        .code()
+        .or_else(|| status.signal().map(|sig| 128 + sig))
+        .unwrap_or(1)
+}
+
+/// Run the manifest's `post_build` hook in the freshly built environment,
+/// passing its output through to the caller's terminal.
+fn run_post_build_hook(
+    backend: &dyn RuntimeBackend,
+    spec: &RuntimeSpec,
+    script: &str,
+) -> Result<(), CoreError> {
+    use std::io::Write;
+    info!("running post_build hook for {}", spec.env_id);
+    let command = ["/bin/sh".to_owned(), "-c".to_owned(), script.to_owned()];
+    let output = backend.exec(spec, &command)?;
+    let _ = std::io::stderr().write_all(&output.stdout);
+    let _ = std::io::stderr().write_all(&output.stderr);
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(CoreError::Hook {
+            event: "post_build".to_owned(),
+            message: format!("`{script}` {}", output.status),
+        })
+    }
+}
+
+/// Run one `[build] steps` entry with `/bin/sh -c` in the environment being
+/// built, passing its output through to stderr.
+fn run_build_step(
+    backend: &dyn RuntimeBackend,
+    spec: &RuntimeSpec,
+    step: &str,
+) -> Result<(), CoreError> {
+    use std::io::Write;
+    info!("running build step `{step}` for {}", spec.env_id);
+    let command = ["/bin/sh".to_owned(), "-c".to_owned(), step.to_owned()];
+    let output = backend.exec(spec, &command)?;
+    let _ = std::io::stderr().write_all(&output.stdout);
+    let _ = std::io::stderr().write_all(&output.stderr);
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(CoreError::Runtime(
+            karapace_runtime::RuntimeError::ExecFailed(format!(
+                "build step `{step}` failed ({})",
+                output.status
+            )),
+        ))
+    }
+}
+
+/// Install a `[python]`, `[node]` or `[rust]` section into the freshly
+/// built environment, passing the installer's output through to stderr.
+fn install_toolchain(
+    backend: &dyn RuntimeBackend,
+    spec: &RuntimeSpec,
+    toolchain: &NormalizedToolchain,
+    manifest_dir: &Path,
+    upper_dir: &Path,
+) -> Result<(), CoreError> {
+    use std::io::Write;
+    info!(
+        "installing {} toolchain for {}",
+        toolchain.language, spec.env_id
+    );
+    let lockfile = toolchain::stage_lockfile(toolchain, manifest_dir, upper_dir)?;
+    let script = toolchain::install_script(toolchain, lockfile.as_deref());
+    let command = ["/bin/sh".to_owned(), "-c".to_owned(), script];
+    let output = backend.exec(spec, &command)?;
+    let _ = std::io::stderr().write_all(&output.stdout);
+    let _ = std::io::stderr().write_all(&output.stderr);
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(CoreError::Runtime(
+            karapace_runtime::RuntimeError::ExecFailed(format!(
+                "installing the {} toolchain failed ({})",
+                toolchain.language, output.status
+            )),
+        ))
+    }
+}
+
+/// The manifest `karapace import` writes for `image`. The image's command
+/// and environment have no manifest equivalent and are noted in comments.
+fn imported_manifest(source: &str, image: &ImportedImage, backend: Option<&str>) -> String {
+    let mut manifest = format!(
+        "# Imported from {} by karapace import.\n",
+        source.replace(['\n', '\r'], " ")
+    );
+    let config = &image.config;
+    let command: Vec<&String> = config
+        .entrypoint
+        .iter()
+        .chain(&config.cmd)
+        .flatten()
+        .collect();
+    if !command.is_empty() {
+        let _ = writeln!(manifest, "# Image command: {command:?}");
+    }
+    for var in &config.env {
+        let _ = writeln!(manifest, "# Image environment: {var}");
+    }
+    let _ = write!(
+        manifest,
+        "manifest_version = 1
+
+[base]
+image = \"{}\"
+",
+        image.reference
+    );
+    if let Some(backend) = backend {
+        let _ = write!(manifest, "\n[runtime]\nbackend = {backend:?}\n");
+    }
+    manifest
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1084,6 +3228,106 @@ backend = "mock"
         assert!(content.contains("lock_version"));
     }
 
+    #[test]
+    fn build_expands_package_patterns() {
+        let (_store, engine, project) = test_engine();
+        let manifest_path = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest_path,
+            r#"
+manifest_version = 1
+[base]
+image = "rolling"
+[system]
+packages = ["git", "python3-*-dev"]
+[runtime]
+backend = "mock"
+"#,
+        )
+        .unwrap();
+
+        let result = engine.build(&manifest_path).unwrap();
+        let names: Vec<&str> = result
+            .lock_file
+            .resolved_packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["git", "python3-all-dev", "python3-venv-dev"]);
+
+        let upper = engine.store_layout().upper_dir(&result.identity.env_id);
+        assert!(upper.join(".pkg-python3-venv-dev").exists());
+        assert!(!upper.join(".pkg-python3-*-dev").exists());
+
+        let locked = engine
+            .build_with_options(
+                &manifest_path,
+                BuildOptions {
+                    locked: true,
+                    ..BuildOptions::default()
+                },
+                &karapace_runtime::NoProgress,
+            )
+            .unwrap();
+        assert_eq!(locked.identity.env_id, result.identity.env_id);
+    }
+
+    #[test]
+    fn build_reports_phases_in_order() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<BuildPhase>>);
+        impl ProgressSink for Recorder {
+            fn phase(&self, phase: BuildPhase) {
+                self.0.lock().unwrap().push(phase);
+            }
+        }
+
+        let (_store, engine, project) = test_engine();
+        let manifest_path = project.path().join("karapace.toml");
+        let recorder = Recorder::default();
+        engine
+            .build_with_options(&manifest_path, BuildOptions::default(), &recorder)
+            .unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                BuildPhase::Resolve,
+                BuildPhase::Unpack,
+                BuildPhase::InstallPackages,
+                BuildPhase::PackLayer
+            ]
+        );
+    }
+
+    #[test]
+    fn build_rejects_unmatched_package_pattern() {
+        let (_store, engine, project) = test_engine();
+        let manifest_path = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest_path,
+            r#"
+manifest_version = 1
+[base]
+image = "rolling"
+[system]
+packages = ["nothing-*"]
+[runtime]
+backend = "mock"
+"#,
+        )
+        .unwrap();
+
+        let Err(err) = engine.build(&manifest_path) else {
+            panic!("build should fail");
+        };
+        assert!(
+            err.to_string().contains("'nothing-*' matched no packages"),
+            "{err}"
+        );
+    }
+
     #[test]
     fn resolve_manifest_returns_identity() {
         let (_store, engine, project) = test_engine();
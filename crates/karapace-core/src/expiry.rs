@@ -0,0 +1,170 @@
+//! Expiry of idle environments, applied by `karapace gc`.
+//!
+//! An environment built with `runtime.expires_after`, or in a store whose
+//! config sets a default `expires_after`, expires once no session has used
+//! it for that long: [`expire`] archives it. Once it has stayed archived
+//! that long again, and been idle for twice the expiry, it is destroyed.
+//! Idle time counts from `last_entered_at`, or from creation for an
+//! environment that was never entered. Running environments never expire.
+
+use crate::{CoreError, Engine, StoreLock};
+use chrono::{DateTime, Utc};
+use karapace_store::{EnvMetadata, EnvState};
+use tracing::info;
+
+/// What [`expire`] does to an expired environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    Archive,
+    Destroy,
+}
+
+#[derive(Debug, Default)]
+pub struct ExpiryReport {
+    /// Environments archived, or that would be on a dry run.
+    pub archived: Vec<String>,
+    /// Environments destroyed, or that would be on a dry run.
+    pub destroyed: Vec<String>,
+}
+
+impl ExpiryReport {
+    pub fn is_empty(&self) -> bool {
+        self.archived.is_empty() && self.destroyed.is_empty()
+    }
+}
+
+/// Whether `meta` has expired at `now`, and what happens to it.
+pub fn expiry_of(meta: &EnvMetadata, now: DateTime<Utc>) -> Option<Expiry> {
+    let ttl = meta.expires_after_secs?;
+    let idle = seconds_since(
+        meta.last_entered_at.as_deref().unwrap_or(&meta.created_at),
+        now,
+    )?;
+    match meta.state {
+        EnvState::Built | EnvState::Frozen if idle >= ttl => Some(Expiry::Archive),
+        // `updated_at` of an archived environment is when it was archived,
+        // so an environment archived by hand gets the full grace period.
+        EnvState::Archived | EnvState::Defined
+            if idle >= ttl.saturating_mul(2) && seconds_since(&meta.updated_at, now)? >= ttl =>
+        {
+            Some(Expiry::Destroy)
+        }
+        _ => None,
+    }
+}
+
+/// Archive or destroy every expired environment. An environment that a
+/// session starts using meanwhile is left alone.
+pub fn expire(
+    engine: &Engine,
+    _lock: &StoreLock,
+    dry_run: bool,
+) -> Result<ExpiryReport, CoreError> {
+    let now = Utc::now();
+    let mut report = ExpiryReport::default();
+    for meta in engine.list()? {
+        let env_id = meta.env_id.to_string();
+        let Some(expiry) = expiry_of(&meta, now) else {
+            continue;
+        };
+        if !dry_run {
+            info!("environment {env_id} expired: {expiry:?}");
+            let result = match expiry {
+                Expiry::Archive => engine.archive(&env_id),
+                Expiry::Destroy => engine.destroy(&env_id),
+            };
+            match result {
+                Err(CoreError::InvalidTransition { .. }) => continue,
+                result => result?,
+            }
+        }
+        match expiry {
+            Expiry::Archive => report.archived.push(env_id),
+            Expiry::Destroy => report.destroyed.push(env_id),
+        }
+    }
+    Ok(report)
+}
+
+fn seconds_since(timestamp: &str, now: DateTime<Utc>) -> Option<u64> {
+    let then = DateTime::parse_from_rfc3339(timestamp).ok()?;
+    u64::try_from(now.signed_duration_since(then).num_seconds()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karapace_schema::types::{EnvId, LayerHash, ObjectHash, ShortId};
+    use std::collections::BTreeMap;
+
+    fn meta(state: EnvState, ttl: Option<u64>, idle_days: i64, archived_days: i64) -> EnvMetadata {
+        let now = Utc::now();
+        let at = |days: i64| (now - chrono::Duration::days(days)).to_rfc3339();
+        EnvMetadata {
+            env_id: EnvId::new("e".repeat(64)),
+            short_id: ShortId::new("e".repeat(12)),
+            name: None,
+            state,
+            manifest_hash: ObjectHash::new(""),
+            base_layer: LayerHash::new(""),
+            dependency_layers: Vec::new(),
+            policy_layer: None,
+            created_at: at(idle_days + 10),
+            updated_at: at(archived_days),
+            ref_count: 1,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            attestation: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: ttl,
+            last_entered_at: Some(at(idle_days)),
+            enter_count: 1,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            checksum: None,
+        }
+    }
+
+    #[test]
+    fn idle_environments_are_archived_then_destroyed() {
+        let day = 86_400;
+        let now = Utc::now();
+        let expiry = |m: EnvMetadata| expiry_of(&m, now);
+
+        assert_eq!(expiry(meta(EnvState::Built, None, 90, 90)), None);
+        assert_eq!(expiry(meta(EnvState::Built, Some(30 * day), 29, 29)), None);
+        assert_eq!(
+            expiry(meta(EnvState::Built, Some(30 * day), 31, 31)),
+            Some(Expiry::Archive)
+        );
+        assert_eq!(
+            expiry(meta(EnvState::Frozen, Some(30 * day), 31, 31)),
+            Some(Expiry::Archive)
+        );
+        assert_eq!(expiry(meta(EnvState::Running, Some(day), 90, 90)), None);
+
+        // Archived only recently: kept for another full period.
+        assert_eq!(
+            expiry(meta(EnvState::Archived, Some(30 * day), 90, 1)),
+            None
+        );
+        assert_eq!(
+            expiry(meta(EnvState::Archived, Some(30 * day), 45, 15)),
+            None
+        );
+        assert_eq!(
+            expiry(meta(EnvState::Archived, Some(30 * day), 61, 31)),
+            Some(Expiry::Destroy)
+        );
+    }
+
+    #[test]
+    fn never_used_environments_age_from_creation() {
+        let mut never_used = meta(EnvState::Built, Some(86_400), 0, 0);
+        never_used.last_entered_at = None;
+        assert_eq!(expiry_of(&never_used, Utc::now()), Some(Expiry::Archive));
+    }
+}
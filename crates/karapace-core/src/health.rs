@@ -0,0 +1,191 @@
+//! Store health checks shared by `karapace doctor` and the TUI banner.
+//!
+//! Each check is cheap and read-only, so frontends can run them on every
+//! refresh.
+
+pub use karapace_runtime::watchdog::available_disk_mb;
+use karapace_store::{StoreLayout, WriteAheadLog};
+use std::path::Path;
+
+/// Below this much free space, a disk check fails.
+pub const DISK_FAIL_MB: u64 = 100;
+/// Below this much free space, a disk check warns.
+pub const DISK_WARN_MB: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+    Info,
+}
+
+impl CheckStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Warn => "warn",
+            Self::Fail => "fail",
+            Self::Info => "info",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+impl HealthCheck {
+    pub fn new(name: &str, status: CheckStatus, message: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            status,
+            message: message.to_owned(),
+        }
+    }
+
+    pub fn pass(name: &str, message: &str) -> Self {
+        Self::new(name, CheckStatus::Pass, message)
+    }
+
+    pub fn fail(name: &str, message: &str) -> Self {
+        Self::new(name, CheckStatus::Fail, message)
+    }
+
+    pub fn warn(name: &str, message: &str) -> Self {
+        Self::new(name, CheckStatus::Warn, message)
+    }
+
+    pub fn info(name: &str, message: &str) -> Self {
+        Self::new(name, CheckStatus::Info, message)
+    }
+
+    /// Whether this check should be brought to the user's attention.
+    pub fn is_problem(&self) -> bool {
+        matches!(self.status, CheckStatus::Warn | CheckStatus::Fail)
+    }
+}
+
+/// The store format version matches what this build understands.
+pub fn check_store_version(layout: &StoreLayout) -> HealthCheck {
+    match layout.verify_version() {
+        Ok(()) => HealthCheck::pass("store_version", "Store format version valid"),
+        Err(e) => HealthCheck::fail("store_version", &format!("Store version check failed: {e}")),
+    }
+}
+
+/// No interrupted operations are waiting in the write-ahead log.
+pub fn check_wal(layout: &StoreLayout) -> HealthCheck {
+    match WriteAheadLog::new(layout).list_incomplete() {
+        Ok(entries) if entries.is_empty() => {
+            HealthCheck::pass("wal_clean", "WAL is clean (no incomplete entries)")
+        }
+        Ok(entries) => HealthCheck::warn(
+            "wal_clean",
+            &format!(
+                "WAL has {} incomplete entries (will recover on next start)",
+                entries.len()
+            ),
+        ),
+        Err(e) => HealthCheck::warn("wal_clean", &format!("Cannot read WAL: {e}")),
+    }
+}
+
+/// Free space on the filesystem holding `path`. `None` if it cannot be
+/// determined.
+pub fn check_disk_space(path: &Path) -> Option<HealthCheck> {
+    let avail_mb = available_disk_mb(path)?;
+    Some(if avail_mb < DISK_FAIL_MB {
+        HealthCheck::fail(
+            "disk_space",
+            &format!("Low disk space: {avail_mb} MB available"),
+        )
+    } else if avail_mb < DISK_WARN_MB {
+        HealthCheck::warn(
+            "disk_space",
+            &format!("Disk space: {avail_mb} MB available (consider freeing space)"),
+        )
+    } else {
+        let free_gb = avail_mb / 1024;
+        HealthCheck::pass("disk_space", &format!("Disk space: {free_gb} GB available"))
+    })
+}
+
+/// The cheap checks suitable for running on every refresh: store version,
+/// WAL state, and free disk. Empty when the store does not exist yet.
+pub fn quick_check(layout: &StoreLayout) -> Vec<HealthCheck> {
+    if !layout.root().join("store").exists() {
+        return Vec::new();
+    }
+    let mut checks = vec![check_store_version(layout), check_wal(layout)];
+    checks.extend(check_disk_space(layout.root()));
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_store_is_healthy() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+
+        let checks = quick_check(&layout);
+        assert!(checks.iter().any(|c| c.name == "store_version"));
+        assert!(checks.iter().any(|c| c.name == "wal_clean"));
+        assert!(checks
+            .iter()
+            .filter(|c| c.name != "disk_space")
+            .all(|c| c.status == CheckStatus::Pass));
+    }
+
+    #[test]
+    fn missing_store_has_no_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path().join("nowhere"));
+        assert!(quick_check(&layout).is_empty());
+    }
+
+    #[test]
+    fn version_mismatch_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        std::fs::write(
+            dir.path().join("store").join("version"),
+            r#"{"format_version": 999}"#,
+        )
+        .unwrap();
+
+        let check = check_store_version(&layout);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.is_problem());
+    }
+
+    #[test]
+    fn incomplete_wal_warns() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        let wal = WriteAheadLog::new(&layout);
+        wal.initialize().unwrap();
+        wal.begin(karapace_store::WalOpKind::Build, "env1").unwrap();
+
+        let check = check_wal(&layout);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert!(check.message.contains("1 incomplete"));
+    }
+
+    #[test]
+    fn disk_space_reported_for_existing_path() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(available_disk_mb(dir.path()).is_some());
+        assert!(check_disk_space(dir.path()).is_some());
+        assert!(available_disk_mb(&dir.path().join("missing")).is_none());
+    }
+}
@@ -0,0 +1,192 @@
+//! Lifecycle events and the hooks that observe them.
+//!
+//! Callers register handlers on [`Engine::hooks`](crate::Engine::hooks).
+//! Every handler sees every [`EngineEvent`] in registration order. A handler
+//! failing a `Pre*` event aborts the operation before it has side effects;
+//! failures on `Post*` events are logged, since the operation already
+//! happened. `Post*` events are only emitted when the operation succeeded.
+
+use crate::CoreError;
+use karapace_schema::LockFile;
+use karapace_store::GcReport;
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock};
+use tracing::warn;
+
+/// Something the engine is about to do or just did.
+#[derive(Debug, Clone, Copy)]
+pub enum EngineEvent<'a> {
+    PreBuild {
+        manifest: &'a Path,
+    },
+    PostBuild {
+        manifest: &'a Path,
+        env_id: &'a str,
+        lock: &'a LockFile,
+    },
+    PreEnter {
+        env_id: &'a str,
+        read_only: bool,
+    },
+    PostEnter {
+        env_id: &'a str,
+        read_only: bool,
+    },
+    PreDestroy {
+        env_id: &'a str,
+    },
+    PostDestroy {
+        env_id: &'a str,
+    },
+    PreCommit {
+        env_id: &'a str,
+        incremental: bool,
+    },
+    PostCommit {
+        env_id: &'a str,
+        snapshot: &'a str,
+    },
+    PreGc {
+        dry_run: bool,
+    },
+    PostGc {
+        dry_run: bool,
+        report: &'a GcReport,
+    },
+}
+
+impl EngineEvent<'_> {
+    /// Stable event name, e.g. `"pre_build"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PreBuild { .. } => "pre_build",
+            Self::PostBuild { .. } => "post_build",
+            Self::PreEnter { .. } => "pre_enter",
+            Self::PostEnter { .. } => "post_enter",
+            Self::PreDestroy { .. } => "pre_destroy",
+            Self::PostDestroy { .. } => "post_destroy",
+            Self::PreCommit { .. } => "pre_commit",
+            Self::PostCommit { .. } => "post_commit",
+            Self::PreGc { .. } => "pre_gc",
+            Self::PostGc { .. } => "post_gc",
+        }
+    }
+
+    /// Whether handlers can still veto the operation.
+    pub fn is_pre(&self) -> bool {
+        self.name().starts_with("pre_")
+    }
+}
+
+type Handler = Arc<dyn Fn(&EngineEvent<'_>) -> Result<(), String> + Send + Sync>;
+
+/// Registry of event handlers.
+#[derive(Default)]
+pub struct Hooks {
+    handlers: RwLock<Vec<Handler>>,
+}
+
+impl Hooks {
+    pub fn subscribe(
+        &self,
+        handler: impl Fn(&EngineEvent<'_>) -> Result<(), String> + Send + Sync + 'static,
+    ) {
+        self.handlers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Arc::new(handler));
+    }
+
+    pub fn len(&self) -> usize {
+        self.handlers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Run every handler on `event`. The first failure of a `Pre*` event is
+    /// returned as [`CoreError::Hook`] and stops the remaining handlers.
+    pub(crate) fn emit(&self, event: &EngineEvent<'_>) -> Result<(), CoreError> {
+        // Handlers run without the lock held, so they may subscribe more.
+        let handlers = self
+            .handlers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for handler in handlers {
+            match handler(event) {
+                Ok(()) => {}
+                Err(message) if event.is_pre() => {
+                    return Err(CoreError::Hook {
+                        event: event.name().to_owned(),
+                        message,
+                    });
+                }
+                Err(message) => warn!("{} hook failed: {message}", event.name()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("handlers", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn handlers_run_in_order_and_pre_failures_abort() {
+        let hooks = Hooks::default();
+        assert!(hooks.is_empty());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for tag in ["a", "b"] {
+            let seen = Arc::clone(&seen);
+            hooks.subscribe(move |event| {
+                seen.lock().unwrap().push(format!("{tag}:{}", event.name()));
+                match event {
+                    EngineEvent::PreDestroy { env_id } if *env_id == "keep" => {
+                        Err(format!("{tag} refuses"))
+                    }
+                    _ => Ok(()),
+                }
+            });
+        }
+        assert_eq!(hooks.len(), 2);
+
+        hooks.emit(&EngineEvent::PreGc { dry_run: true }).unwrap();
+        match hooks.emit(&EngineEvent::PreDestroy { env_id: "keep" }) {
+            Err(CoreError::Hook { event, message }) => {
+                assert_eq!(event, "pre_destroy");
+                assert_eq!(message, "a refuses");
+            }
+            other => panic!("expected a hook error, got {other:?}"),
+        }
+        // Failures after the fact are only logged.
+        hooks
+            .emit(&EngineEvent::PostDestroy { env_id: "keep" })
+            .unwrap();
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                "a:pre_gc",
+                "b:pre_gc",
+                "a:pre_destroy",
+                "a:post_destroy",
+                "b:post_destroy"
+            ]
+        );
+    }
+}
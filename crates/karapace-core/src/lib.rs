@@ -3,17 +3,54 @@
 //! This crate ties together schema parsing, store operations, and runtime backends
 //! into the `Engine` — the central API for building, entering, stopping, destroying,
 //! and inspecting deterministic container environments. It also provides overlay
-//! drift detection, concurrent store locking, and state-machine lifecycle validation.
+//! drift detection, concurrent store locking, state-machine lifecycle validation,
+//! the store health checks shared by the CLI and TUI, store root discovery
+//! (including project-local `.karapace/store` stores), syncing listed
+//! remote environments into the store, signed build attestations, the
+//! cache of package layers shared between builds, scheduled snapshots,
+//! store-wide pruning, expiry of idle environments, vulnerability audits
+//! of installed packages, all-or-nothing transactions over several
+//! environments, and repair of a damaged store.
 
+pub mod attest;
+pub mod audit;
+pub mod autosnap;
+pub mod build_cache;
 pub mod concurrency;
+pub mod discovery;
 pub mod drift;
 pub mod engine;
+pub mod expiry;
+pub mod health;
+pub mod hooks;
 pub mod lifecycle;
+pub mod prune;
+pub mod repair;
+pub mod sync;
+mod textdiff;
 
-pub use concurrency::{install_signal_handler, shutdown_requested, StoreLock};
-pub use drift::{commit_overlay, diff_overlay, export_overlay, DriftReport};
-pub use engine::{BuildOptions, BuildResult, Engine};
+pub use attest::{AttestationKey, Envelope, Statement};
+pub use audit::{AuditReport, CachePolicy, CachedFeed, Finding, OsvFeed, Severity, VulnFeed};
+pub use autosnap::{AutosnapRun, AutosnapSchedule, AutosnapSchedules};
+pub use concurrency::{install_signal_handler, shutdown_requested, LockHolder, StoreLock};
+pub use discovery::{discover_store, DiscoveredStore, ProjectBinding, StoreSource, UserConfig};
+pub use drift::{
+    commit_overlay, diff_overlay, diff_upper_dirs, export_overlay, overlay_patches,
+    pack_drift_bundle, unpack_drift_bundle, DriftBundleInfo, DriftReport, FilePatch,
+    DRIFT_BUNDLE_FORMAT, PATCH_SIZE_LIMIT,
+};
+pub use engine::{
+    BuildOptions, BuildResult, CommitOptions, Engine, EnterOptions, EnvDiskUsage, EnvUsage,
+    ImageUsage, ImportOptions, ImportResult, SnapshotInfo, StoreUsage, Transaction, WorkspaceInfo,
+};
+pub use expiry::{expire, expiry_of, Expiry, ExpiryReport};
+pub use health::{CheckStatus, HealthCheck};
+pub use hooks::{EngineEvent, Hooks};
+pub use karapace_runtime::EnvStats;
 pub use lifecycle::validate_transition;
+pub use prune::{prune, PruneOptions, PruneReport, PrunedWorkspace};
+pub use repair::{repair, RepairItem, RepairReport, RepairSource};
+pub use sync::{SyncManifest, SyncOptions, SyncOutcome, SyncReport, SyncStatus};
 
 use thiserror::Error;
 
@@ -37,4 +74,24 @@ pub enum CoreError {
     Serialization(#[from] serde_json::Error),
     #[error("remote error: {0}")]
     Remote(#[from] karapace_remote::RemoteError),
+    #[error("store is locked by {holder}; gave up after {waited_secs}s")]
+    LockTimeout { waited_secs: u64, holder: String },
+    #[error("host GPU driver changed since build ({0}); rebuild the environment")]
+    GpuDriverDrift(String),
+    #[error("workspace error: {0}")]
+    Workspace(String),
+    #[error("config error: {0}")]
+    Config(String),
+    #[error("{event} hook failed: {message}")]
+    Hook { event: String, message: String },
+    #[error("attestation error: {0}")]
+    Attestation(String),
+    #[error("audit error: {0}")]
+    Audit(String),
+    #[error("drift bundle error: {0}")]
+    DriftBundle(String),
+    #[error("image {image} is the base of {} environment(s); destroy them or pass --force", envs.len())]
+    ImageInUse { image: String, envs: Vec<String> },
+    #[error("cancelled")]
+    Cancelled,
 }
@@ -0,0 +1,104 @@
+//! Store-wide cleanup for `karapace prune`.
+//!
+//! [`prune`] removes, in one pass under the store lock, what the separate
+//! cleanup commands would: inactive workspaces (`--volumes`), cached base
+//! images no environment is built on (`--images`), snapshots beyond the
+//! newest `N` of each workspace, and finally everything garbage collection
+//! finds unreferenced — including the layers and objects the earlier steps
+//! just released.
+
+use crate::{CoreError, Engine, StoreLock};
+use karapace_runtime::image::CachedImage;
+use karapace_runtime::quota::dir_usage;
+use karapace_store::{GcReport, RetentionPolicy};
+
+/// What [`prune`] removes besides garbage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneOptions {
+    /// Remove the inactive workspaces of every environment.
+    pub volumes: bool,
+    /// Remove cached base images no environment uses.
+    pub images: bool,
+    /// Keep only the newest `N` snapshots of each environment workspace.
+    pub snapshots_keep: Option<usize>,
+    /// Report what would be removed without removing it.
+    pub dry_run: bool,
+}
+
+/// An inactive workspace removed by [`prune`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrunedWorkspace {
+    pub env_id: String,
+    pub name: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub workspaces: Vec<PrunedWorkspace>,
+    pub images: Vec<CachedImage>,
+    /// Garbage collection, with snapshot retention applied.
+    pub gc: GcReport,
+}
+
+impl PruneReport {
+    /// Bytes freed by removed workspaces and images. Garbage collection
+    /// reports counts, not sizes, so its share is not included.
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.workspaces.iter().map(|w| w.size_bytes).sum::<u64>()
+            + self.images.iter().map(|i| i.size_bytes).sum::<u64>()
+    }
+}
+
+/// Remove inactive workspaces and unused images as `options` ask, then
+/// collect garbage with snapshot retention. The first failure stops the
+/// run; what was removed before it stays removed.
+pub fn prune(
+    engine: &Engine,
+    lock: &StoreLock,
+    options: &PruneOptions,
+) -> Result<PruneReport, CoreError> {
+    let mut report = PruneReport::default();
+    let layout = engine.store_layout();
+
+    if options.volumes {
+        for meta in engine.list()? {
+            let env_id = meta.env_id.to_string();
+            for workspace in engine.list_workspaces(&env_id)? {
+                if workspace.active {
+                    continue;
+                }
+                let size_bytes = dir_usage(&layout.workspaces_dir(&env_id).join(&workspace.name));
+                if !options.dry_run {
+                    engine.remove_workspace(&env_id, &workspace.name)?;
+                }
+                report.workspaces.push(PrunedWorkspace {
+                    env_id: env_id.clone(),
+                    name: workspace.name,
+                    size_bytes,
+                });
+            }
+        }
+    }
+
+    if options.images {
+        for entry in engine.list_images()? {
+            if !entry.used_by.is_empty() {
+                continue;
+            }
+            let image = if options.dry_run {
+                entry.image
+            } else {
+                engine.remove_image(&entry.image.cache_key, false)?
+            };
+            report.images.push(image);
+        }
+    }
+
+    let retention = RetentionPolicy {
+        keep_last: options.snapshots_keep,
+        ..RetentionPolicy::default()
+    };
+    report.gc = engine.gc_with_retention(lock, options.dry_run, None, retention)?;
+    Ok(report)
+}
@@ -0,0 +1,244 @@
+//! Repair of a damaged store, for `karapace verify-store --repair`.
+//!
+//! [`repair`] moves objects and layer manifests that fail verification to
+//! `store/quarantine/`, then restores what the environments still
+//! reference: from a remote when one is given, and for a layer manifest by
+//! rebuilding it from the objects in the store when it is a plain layer
+//! (one tar, no file objects, not a delta) whose content hash can be
+//! matched. Metadata that fails its checksum is pulled again from the
+//! remote, or left in place. Whatever cannot be restored is reported lost.
+
+use crate::engine::snapshot_id;
+use crate::{CoreError, Engine, StoreLock};
+use karapace_remote::{BlobCipher, RemoteBackend, TransferOptions};
+use karapace_store::{
+    verify_store_integrity, EnvMetadata, IntegrityKind, LayerKind, LayerManifest, LayerStore,
+    MetadataStore, ObjectStore, StoreLayout,
+};
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use tracing::info;
+
+/// Where [`repair`] fetches what the store has lost.
+#[derive(Clone, Copy)]
+pub struct RepairSource<'a> {
+    pub backend: &'a dyn RemoteBackend,
+    pub cipher: Option<&'a dyn BlobCipher>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairItem {
+    pub kind: IntegrityKind,
+    /// Object or layer hash, or env_id for metadata.
+    pub hash: String,
+    /// Why it was quarantined, how it was recovered, or why it is lost.
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// Damaged objects and layers moved to the quarantine directory.
+    pub quarantined: Vec<RepairItem>,
+    pub recovered: Vec<RepairItem>,
+    /// Referenced items that are missing or damaged and could not be
+    /// restored.
+    pub lost: Vec<RepairItem>,
+}
+
+impl RepairReport {
+    fn recovered(&mut self, kind: IntegrityKind, hash: &str, detail: impl Into<String>) {
+        info!("repair: recovered {kind} {hash}");
+        self.recovered.push(RepairItem {
+            kind,
+            hash: hash.to_owned(),
+            detail: detail.into(),
+        });
+    }
+
+    fn lost(&mut self, kind: IntegrityKind, hash: &str, detail: impl Into<String>) {
+        info!("repair: lost {kind} {hash}");
+        self.lost.push(RepairItem {
+            kind,
+            hash: hash.to_owned(),
+            detail: detail.into(),
+        });
+    }
+}
+
+/// Quarantine what fails verification and restore what environments
+/// reference but the store no longer holds intact.
+pub fn repair(
+    engine: &Engine,
+    _lock: &StoreLock,
+    remote: Option<RepairSource<'_>>,
+) -> Result<RepairReport, CoreError> {
+    let layout = engine.store_layout();
+    let mut report = RepairReport::default();
+
+    for failure in verify_store_integrity(layout)?.failed {
+        if failure.kind == IntegrityKind::Metadata {
+            restore_metadata(engine, &failure.hash, &failure.reason, remote, &mut report);
+            continue;
+        }
+        quarantine(layout, failure.kind, &failure.hash)?;
+        report.quarantined.push(RepairItem {
+            kind: failure.kind,
+            hash: failure.hash,
+            detail: failure.reason,
+        });
+    }
+
+    let layer_store = LayerStore::new(layout.clone());
+    let object_store = ObjectStore::new(layout.clone());
+    let stored_objects = object_store.list()?;
+    let mut objects = BTreeSet::new();
+    let mut layers = HashSet::new();
+    for meta in MetadataStore::new(layout.clone()).list()? {
+        objects.extend(meta.direct_objects());
+        let referenced = std::iter::once(&meta.base_layer)
+            .chain(&meta.dependency_layers)
+            .chain(&meta.policy_layer)
+            .map(ToString::to_string)
+            .chain(meta.snapshot.clone());
+        for hash in referenced {
+            if !layers.insert(hash.clone()) {
+                continue;
+            }
+            let layer = match layer_store.get(&hash) {
+                Ok(layer) => Some(layer),
+                Err(_) => restore_layer(layout, &hash, &meta, &stored_objects, remote, &mut report),
+            };
+            if let Some(layer) = layer {
+                objects.extend(layer.object_refs);
+                objects.extend(Some(layer.tar_hash).filter(|tar| !tar.is_empty()));
+            }
+        }
+    }
+
+    for hash in objects {
+        if object_store.exists(&hash) {
+            continue;
+        }
+        let Some(source) = remote else {
+            report.lost(IntegrityKind::Object, &hash, "no remote to fetch it from");
+            continue;
+        };
+        match karapace_remote::fetch_object(layout, source.backend, &hash, source.cipher) {
+            Ok(()) => report.recovered(IntegrityKind::Object, &hash, "fetched from the remote"),
+            Err(e) => report.lost(IntegrityKind::Object, &hash, e.to_string()),
+        }
+    }
+    Ok(report)
+}
+
+/// Move a damaged object or layer manifest to the quarantine directory. A
+/// packed object cannot be moved and is dropped from its pack instead.
+fn quarantine(layout: &StoreLayout, kind: IntegrityKind, hash: &str) -> Result<(), CoreError> {
+    let (source, dir) = match kind {
+        IntegrityKind::Layer => (layout.layers_dir().join(hash), "layers"),
+        _ => (layout.objects_dir().join(hash), "objects"),
+    };
+    if !source.exists() {
+        ObjectStore::new(layout.clone()).remove(hash)?;
+        return Ok(());
+    }
+    let dest = layout.quarantine_dir().join(dir);
+    fs::create_dir_all(&dest)?;
+    fs::rename(&source, dest.join(hash))?;
+    Ok(())
+}
+
+fn restore_metadata(
+    engine: &Engine,
+    env_id: &str,
+    reason: &str,
+    remote: Option<RepairSource<'_>>,
+    report: &mut RepairReport,
+) {
+    let Some(source) = remote else {
+        report.lost(
+            IntegrityKind::Metadata,
+            env_id,
+            format!("{reason}; no remote to fetch it from"),
+        );
+        return;
+    };
+    let options = TransferOptions {
+        cipher: source.cipher,
+        ..TransferOptions::default()
+    };
+    match engine.pull_with_options(env_id, source.backend, &options) {
+        Ok(_) => report.recovered(IntegrityKind::Metadata, env_id, "pulled from the remote"),
+        Err(e) => report.lost(IntegrityKind::Metadata, env_id, format!("{reason}; {e}")),
+    }
+}
+
+fn restore_layer(
+    layout: &StoreLayout,
+    hash: &str,
+    meta: &EnvMetadata,
+    stored_objects: &[String],
+    remote: Option<RepairSource<'_>>,
+    report: &mut RepairReport,
+) -> Option<LayerManifest> {
+    let mut fetch_error = None;
+    if let Some(source) = remote {
+        match karapace_remote::fetch_layer(layout, source.backend, hash, source.cipher) {
+            Ok(layer) => {
+                report.recovered(IntegrityKind::Layer, hash, "fetched from the remote");
+                return Some(layer);
+            }
+            Err(e) => fetch_error = Some(e.to_string()),
+        }
+    }
+    if let Some(layer) = rebuild_layer(hash, meta, stored_objects) {
+        if LayerStore::new(layout.clone()).put(&layer).is_ok() {
+            report.recovered(
+                IntegrityKind::Layer,
+                hash,
+                format!("rebuilt from object {}", layer.tar_hash),
+            );
+            return Some(layer);
+        }
+    }
+    let detail = match fetch_error {
+        Some(e) => format!("cannot be rebuilt; {e}"),
+        None => "cannot be rebuilt and no remote to fetch it from".to_owned(),
+    };
+    report.lost(IntegrityKind::Layer, hash, detail);
+    None
+}
+
+/// The layer manifest stored under `hash`, if it is a plain layer of `meta`
+/// with one of `objects` as its tar. The tar is not read, so both values of
+/// [`LayerManifest::pax_metadata`] are tried.
+fn rebuild_layer(hash: &str, meta: &EnvMetadata, objects: &[String]) -> Option<LayerManifest> {
+    let mut candidates = objects.iter().flat_map(|tar| [(tar, false), (tar, true)]);
+    candidates.find_map(|(tar, pax_metadata)| {
+        let plain = |kind| LayerManifest {
+            hash: tar.clone(),
+            kind,
+            parent: None,
+            object_refs: vec![tar.clone()],
+            read_only: true,
+            tar_hash: tar.clone(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata,
+        };
+        let snapshot = LayerManifest {
+            hash: snapshot_id(meta, tar, None),
+            parent: Some(meta.base_layer.to_string()),
+            workspace: meta.workspace.clone(),
+            ..plain(LayerKind::Snapshot)
+        };
+        [
+            plain(LayerKind::Base),
+            plain(LayerKind::Dependency),
+            snapshot,
+        ]
+        .into_iter()
+        .find(|layer| LayerStore::compute_hash(layer).is_ok_and(|h| h == hash))
+    })
+}
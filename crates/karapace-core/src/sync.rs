@@ -0,0 +1,555 @@
+//! Syncing a listed set of remote environments into the store.
+//!
+//! A sync manifest, `karapace-sync.toml`, lists registry references:
+//!
+//! ```toml
+//! remote = "https://karapace.example.com"  # optional
+//! prune = true                             # optional
+//! refs = ["dev@latest", "ci@stable"]
+//! ```
+//!
+//! [`Engine::sync`] pulls every reference that is not already in the store,
+//! several at once. The environments a manifest brought in are recorded in
+//! `store/sync.json` under a caller-chosen key (the CLI uses the manifest's
+//! path). With pruning on, recorded environments that the listed references
+//! no longer resolve to (the reference was dropped or its tag moved on) are
+//! destroyed.
+
+use crate::{CoreError, Engine};
+use karapace_remote::{
+    BlobCipher, Registry, RemoteBackend, RemoteError, TransferOptions, TrustedKeys,
+};
+use karapace_store::StoreLayout;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// File name `karapace sync` looks for in the working directory.
+pub const SYNC_MANIFEST_FILE: &str = "karapace-sync.toml";
+
+/// References pulled at once when the caller does not say.
+pub const DEFAULT_SYNC_JOBS: usize = 4;
+
+/// A parsed `karapace-sync.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyncManifest {
+    /// Remote store URL; the remote config is used when absent.
+    #[serde(default)]
+    pub remote: Option<String>,
+    /// Destroy previously synced environments that are no longer listed.
+    #[serde(default)]
+    pub prune: bool,
+    /// Registry references (`name@tag`) or raw env_ids.
+    #[serde(default)]
+    pub refs: Vec<String>,
+}
+
+impl SyncManifest {
+    pub fn load(path: &Path) -> Result<Self, CoreError> {
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|e| CoreError::Config(format!("{}: {e}", path.display())))
+    }
+
+    /// Parse manifest text. References must be non-empty and unique.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let manifest: Self = toml::from_str(content).map_err(|e| e.message().trim().to_owned())?;
+        let mut seen = BTreeSet::new();
+        for reference in &manifest.refs {
+            if reference.trim().is_empty() {
+                return Err("empty reference in refs".to_owned());
+            }
+            if !seen.insert(reference.as_str()) {
+                return Err(format!("'{reference}' is listed more than once"));
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// Settings for [`Engine::sync`].
+#[derive(Clone, Copy)]
+pub struct SyncOptions<'a> {
+    /// References pulled at once. 0 and 1 both mean one at a time.
+    pub jobs: usize,
+    /// Objects downloaded at once within each pull.
+    pub object_jobs: usize,
+    /// Decrypts blobs of encrypted pushes.
+    pub cipher: Option<&'a dyn BlobCipher>,
+    /// Keys signatures of pulled environments are checked against.
+    pub trusted_keys: Option<&'a TrustedKeys>,
+    /// Refuse environments without a signature by a trusted key.
+    pub require_signed: bool,
+    /// Where the synced environments are recorded. `None` records nothing
+    /// and cannot prune.
+    pub state_key: Option<&'a str>,
+    /// Destroy environments recorded under `state_key` that the references
+    /// no longer resolve to.
+    pub prune: bool,
+}
+
+impl Default for SyncOptions<'_> {
+    fn default() -> Self {
+        Self {
+            jobs: DEFAULT_SYNC_JOBS,
+            object_jobs: karapace_remote::DEFAULT_CONCURRENCY,
+            cipher: None,
+            trusted_keys: None,
+            require_signed: false,
+            state_key: None,
+            prune: false,
+        }
+    }
+}
+
+/// What happened to one reference, or to one pruned environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncStatus {
+    Pulled,
+    UpToDate,
+    Removed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncOutcome {
+    pub reference: String,
+    /// `None` when the reference could not be resolved.
+    pub env_id: Option<String>,
+    pub status: SyncStatus,
+    pub objects_pulled: usize,
+    pub layers_pulled: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SyncOutcome {
+    fn new(reference: &str, env_id: Option<&str>, status: SyncStatus) -> Self {
+        Self {
+            reference: reference.to_owned(),
+            env_id: env_id.map(str::to_owned),
+            status,
+            objects_pulled: 0,
+            layers_pulled: 0,
+            error: None,
+        }
+    }
+
+    fn failed(reference: &str, env_id: Option<&str>, error: String) -> Self {
+        Self {
+            error: Some(error),
+            ..Self::new(reference, env_id, SyncStatus::Failed)
+        }
+    }
+}
+
+/// Per-reference results of [`Engine::sync`], in the order the references
+/// were given, followed by pruned environments.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport {
+    pub outcomes: Vec<SyncOutcome>,
+}
+
+impl SyncReport {
+    pub fn failed(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|o| o.status == SyncStatus::Failed)
+            .count()
+    }
+}
+
+/// `store/sync.json`: for each state key, env_id → the reference that
+/// brought it in.
+type SyncState = BTreeMap<String, BTreeMap<String, String>>;
+
+fn load_state(layout: &StoreLayout) -> Result<SyncState, CoreError> {
+    let path = layout.sync_state_file();
+    if !path.exists() {
+        return Ok(SyncState::new());
+    }
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+fn save_state(layout: &StoreLayout, state: &SyncState) -> Result<(), CoreError> {
+    let path = layout.sync_state_file();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(state)?)?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Look `reference` up in the registry, falling back to treating it as a
+/// raw env_id like `karapace pull` does.
+fn resolve(
+    registry: Option<&Registry>,
+    reference: &str,
+    has_cipher: bool,
+) -> Result<String, String> {
+    let (name, tag) = karapace_remote::parse_ref(reference);
+    match registry.and_then(|r| r.lookup(&format!("{name}@{tag}"))) {
+        Some(entry) if !entry.key_fingerprints.is_empty() && !has_cipher => Err(format!(
+            "encrypted for key {}; no age identity given",
+            entry.key_fingerprints.join(", ")
+        )),
+        Some(entry) => Ok(entry.env_id.clone()),
+        None => Ok(reference.to_owned()),
+    }
+}
+
+fn sync_one(
+    engine: &Engine,
+    registry: Option<&Registry>,
+    reference: &str,
+    backend: &dyn RemoteBackend,
+    options: &SyncOptions<'_>,
+) -> SyncOutcome {
+    let env_id = match resolve(registry, reference, options.cipher.is_some()) {
+        Ok(id) => id,
+        Err(e) => return SyncOutcome::failed(reference, None, e),
+    };
+    // Metadata is written last by a pull, so its presence means the
+    // environment is complete; env_ids are content-derived, so it is current.
+    if engine.inspect(&env_id).is_ok() {
+        return SyncOutcome::new(reference, Some(&env_id), SyncStatus::UpToDate);
+    }
+    let transfer = TransferOptions {
+        concurrency: options.object_jobs,
+        cipher: options.cipher,
+        trusted_keys: options.trusted_keys,
+        require_signed: options.require_signed,
+        ..TransferOptions::default()
+    };
+    match engine.pull_with_options(&env_id, backend, &transfer) {
+        Ok(result) => SyncOutcome {
+            objects_pulled: result.objects_pulled,
+            layers_pulled: result.layers_pulled,
+            ..SyncOutcome::new(reference, Some(&env_id), SyncStatus::Pulled)
+        },
+        Err(e) => SyncOutcome::failed(reference, Some(&env_id), e.to_string()),
+    }
+}
+
+pub(crate) fn run(
+    engine: &Engine,
+    refs: &[String],
+    backend: &dyn RemoteBackend,
+    options: &SyncOptions<'_>,
+) -> Result<SyncReport, CoreError> {
+    let registry = match backend.get_registry() {
+        Ok(bytes) => Some(Registry::from_bytes(&bytes)?),
+        Err(RemoteError::NotFound(_)) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let results: Vec<Mutex<Option<SyncOutcome>>> = refs.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+    let workers = options.jobs.clamp(1, refs.len().max(1));
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                let mut i = next.fetch_add(1, Ordering::Relaxed);
+                while let Some(reference) = refs.get(i) {
+                    let outcome = sync_one(engine, registry.as_ref(), reference, backend, options);
+                    *results[i].lock().unwrap_or_else(PoisonError::into_inner) = Some(outcome);
+                    i = next.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    let mut outcomes: Vec<SyncOutcome> = results
+        .into_iter()
+        .filter_map(|slot| slot.into_inner().unwrap_or_else(PoisonError::into_inner))
+        .collect();
+
+    if let Some(key) = options.state_key {
+        let layout = engine.store_layout();
+        let mut state = load_state(layout)?;
+        let previous = state.remove(key).unwrap_or_default();
+        let recorded = record(engine, &previous, &mut outcomes, options.prune);
+        if !recorded.is_empty() {
+            state.insert(key.to_owned(), recorded);
+        }
+        save_state(layout, &state)?;
+    }
+    Ok(SyncReport { outcomes })
+}
+
+/// The new state entry for one key, pruning stale environments on the way
+/// when asked. Environments of references that failed this time are kept,
+/// since it is unknown what they resolve to now.
+fn record(
+    engine: &Engine,
+    previous: &BTreeMap<String, String>,
+    outcomes: &mut Vec<SyncOutcome>,
+    prune: bool,
+) -> BTreeMap<String, String> {
+    let mut current: BTreeMap<String, String> = outcomes
+        .iter()
+        .filter(|o| o.status != SyncStatus::Failed)
+        .filter_map(|o| Some((o.env_id.clone()?, o.reference.clone())))
+        .collect();
+    let failed: BTreeSet<&str> = outcomes
+        .iter()
+        .filter(|o| o.status == SyncStatus::Failed)
+        .map(|o| o.reference.as_str())
+        .collect();
+
+    let mut pruned = Vec::new();
+    for (env_id, reference) in previous {
+        if current.contains_key(env_id) {
+            continue;
+        }
+        if !prune || failed.contains(reference.as_str()) {
+            current.insert(env_id.clone(), reference.clone());
+            continue;
+        }
+        let result = if engine.inspect(env_id).is_ok() {
+            engine.destroy(env_id)
+        } else {
+            Ok(())
+        };
+        match result {
+            Ok(()) => pruned.push(SyncOutcome::new(
+                reference,
+                Some(env_id),
+                SyncStatus::Removed,
+            )),
+            Err(e) => {
+                current.insert(env_id.clone(), reference.clone());
+                pruned.push(SyncOutcome::failed(reference, Some(env_id), e.to_string()));
+            }
+        }
+    }
+    outcomes.extend(pruned);
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karapace_remote::BlobKind;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MemoryRemote {
+        blobs: Mutex<HashMap<String, Vec<u8>>>,
+        registry: Mutex<Option<Vec<u8>>>,
+    }
+
+    impl RemoteBackend for MemoryRemote {
+        fn put_blob(&self, kind: BlobKind, key: &str, data: &[u8]) -> Result<(), RemoteError> {
+            self.blobs
+                .lock()
+                .unwrap()
+                .insert(format!("{kind:?}/{key}"), data.to_vec());
+            Ok(())
+        }
+
+        fn get_blob(&self, kind: BlobKind, key: &str) -> Result<Vec<u8>, RemoteError> {
+            self.blobs
+                .lock()
+                .unwrap()
+                .get(&format!("{kind:?}/{key}"))
+                .cloned()
+                .ok_or_else(|| RemoteError::NotFound(key.to_owned()))
+        }
+
+        fn has_blob(&self, kind: BlobKind, key: &str) -> Result<bool, RemoteError> {
+            Ok(self
+                .blobs
+                .lock()
+                .unwrap()
+                .contains_key(&format!("{kind:?}/{key}")))
+        }
+
+        fn list_blobs(&self, _kind: BlobKind) -> Result<Vec<String>, RemoteError> {
+            Ok(Vec::new())
+        }
+
+        fn put_registry(&self, data: &[u8]) -> Result<(), RemoteError> {
+            *self.registry.lock().unwrap() = Some(data.to_vec());
+            Ok(())
+        }
+
+        fn get_registry(&self) -> Result<Vec<u8>, RemoteError> {
+            self.registry
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or_else(|| RemoteError::NotFound("registry".to_owned()))
+        }
+    }
+
+    /// Build an environment with `packages` in a scratch store and push it
+    /// as `tag`. Returns its env_id.
+    fn publish(remote: &MemoryRemote, tag: &str, packages: &str) -> String {
+        let store = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        let manifest = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest,
+            format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[system]\npackages = [{packages}]\n[runtime]\nbackend = \"mock\"\n"
+            ),
+        )
+        .unwrap();
+        let engine = Engine::new(store.path());
+        let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+        engine.push(&env_id, remote, Some(tag)).unwrap();
+        env_id
+    }
+
+    fn refs(list: &[&str]) -> Vec<String> {
+        list.iter().map(|r| (*r).to_owned()).collect()
+    }
+
+    fn statuses(report: &SyncReport) -> Vec<(&str, SyncStatus)> {
+        report
+            .outcomes
+            .iter()
+            .map(|o| (o.reference.as_str(), o.status))
+            .collect()
+    }
+
+    #[test]
+    fn manifest_parses_and_rejects_bad_refs() {
+        let manifest = SyncManifest::parse(
+            "remote = \"http://r\"\nprune = true\nrefs = [\"dev@latest\", \"ci\"]\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.remote.as_deref(), Some("http://r"));
+        assert!(manifest.prune);
+        assert_eq!(manifest.refs, ["dev@latest", "ci"]);
+
+        assert!(SyncManifest::parse("refs = [\"a\", \"a\"]")
+            .unwrap_err()
+            .contains("more than once"));
+        assert!(SyncManifest::parse("refs = [\" \"]").is_err());
+        assert!(SyncManifest::parse("ref = [\"a\"]").is_err());
+        assert_eq!(SyncManifest::parse("").unwrap(), SyncManifest::default());
+    }
+
+    #[test]
+    fn sync_pulls_missing_and_reports_each_ref() {
+        let remote = MemoryRemote::default();
+        let dev = publish(&remote, "dev@latest", "\"git\"");
+        let ci = publish(&remote, "ci@latest", "\"clang\"");
+        let store = tempfile::tempdir().unwrap();
+        let engine = Engine::new(store.path());
+
+        let list = refs(&["dev@latest", "missing@latest", "ci"]);
+        let report = engine
+            .sync(&list, &remote, &SyncOptions::default())
+            .unwrap();
+        assert_eq!(
+            statuses(&report),
+            [
+                ("dev@latest", SyncStatus::Pulled),
+                ("missing@latest", SyncStatus::Failed),
+                ("ci", SyncStatus::Pulled),
+            ]
+        );
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.outcomes[0].env_id.as_deref(), Some(dev.as_str()));
+        assert!(report.outcomes[0].objects_pulled > 0);
+        assert!(engine.inspect(&ci).is_ok());
+
+        let report = engine
+            .sync(&list, &remote, &SyncOptions::default())
+            .unwrap();
+        assert_eq!(report.outcomes[0].status, SyncStatus::UpToDate);
+        assert_eq!(report.outcomes[2].status, SyncStatus::UpToDate);
+        // Nothing was recorded without a state key.
+        assert!(!engine.store_layout().sync_state_file().exists());
+    }
+
+    #[test]
+    fn prune_removes_dropped_and_superseded_envs() {
+        let remote = MemoryRemote::default();
+        let dev_v1 = publish(&remote, "dev@latest", "\"git\"");
+        let ci = publish(&remote, "ci@latest", "\"clang\"");
+        let store = tempfile::tempdir().unwrap();
+        let engine = Engine::new(store.path());
+        let options = SyncOptions {
+            state_key: Some("/team/karapace-sync.toml"),
+            ..SyncOptions::default()
+        };
+
+        engine
+            .sync(&refs(&["dev@latest", "ci@latest"]), &remote, &options)
+            .unwrap();
+
+        // Dropping ci without pruning keeps it, and keeps it recorded.
+        engine
+            .sync(&refs(&["dev@latest"]), &remote, &options)
+            .unwrap();
+        assert!(engine.inspect(&ci).is_ok());
+
+        // Move the dev tag on, then prune.
+        let dev_v2 = publish(&remote, "dev@latest", "\"git\", \"curl\"");
+        let prune = SyncOptions {
+            prune: true,
+            ..options
+        };
+        let report = engine
+            .sync(&refs(&["dev@latest"]), &remote, &prune)
+            .unwrap();
+        let mut got = statuses(&report);
+        got.sort_unstable();
+        assert_eq!(
+            got,
+            [
+                ("ci@latest", SyncStatus::Removed),
+                ("dev@latest", SyncStatus::Pulled),
+                ("dev@latest", SyncStatus::Removed),
+            ]
+        );
+        assert!(engine.inspect(&dev_v2).is_ok());
+        assert!(engine.inspect(&dev_v1).is_err());
+        assert!(engine.inspect(&ci).is_err());
+
+        // Environments not brought in by this manifest are never pruned.
+        let other = SyncOptions {
+            state_key: Some("/other/karapace-sync.toml"),
+            prune: true,
+            ..SyncOptions::default()
+        };
+        let report = engine.sync(&[], &remote, &other).unwrap();
+        assert!(report.outcomes.is_empty());
+        assert!(engine.inspect(&dev_v2).is_ok());
+    }
+
+    #[test]
+    fn failed_refs_keep_their_previous_env() {
+        let remote = MemoryRemote::default();
+        let dev = publish(&remote, "dev@latest", "\"git\"");
+        let store = tempfile::tempdir().unwrap();
+        let engine = Engine::new(store.path());
+        let options = SyncOptions {
+            state_key: Some("k"),
+            prune: true,
+            ..SyncOptions::default()
+        };
+        engine
+            .sync(&refs(&["dev@latest"]), &remote, &options)
+            .unwrap();
+
+        // The tag now points at an env whose blobs are missing.
+        let mut registry = Registry::from_bytes(&remote.get_registry().unwrap()).unwrap();
+        registry.entries.get_mut("dev@latest").unwrap().env_id = "gone".to_owned();
+        remote.put_registry(&registry.to_bytes().unwrap()).unwrap();
+
+        let report = engine
+            .sync(&refs(&["dev@latest"]), &remote, &options)
+            .unwrap();
+        assert_eq!(statuses(&report), [("dev@latest", SyncStatus::Failed)]);
+        assert!(engine.inspect(&dev).is_ok());
+    }
+}
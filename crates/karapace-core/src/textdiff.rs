@@ -0,0 +1,215 @@
+//! Line-based unified diffs of text files, for `karapace diff --patch`.
+//!
+//! Common leading and trailing lines are trimmed first; the rest is aligned
+//! by longest common subsequence. When the middle is too large for that, it
+//! is shown as removed and re-added as a whole.
+
+use std::fmt::Write;
+
+/// Lines of context around each change.
+const CONTEXT: usize = 3;
+
+/// Most cells of the LCS table before falling back to a whole replacement.
+const LCS_CELL_LIMIT: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// An edit, with the old and new line indices it applies at.
+#[derive(Debug, Clone, Copy)]
+struct Edit {
+    op: Op,
+    old: usize,
+    new: usize,
+}
+
+/// Unified diff from `old` to `new`, headed with the two labels, or `None`
+/// when the texts are equal.
+pub(crate) fn unified_diff(
+    old: &str,
+    new: &str,
+    old_label: &str,
+    new_label: &str,
+) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits = edit_script(&a, &b);
+
+    let mut out = format!("--- {old_label}\n+++ {new_label}\n");
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.op != Op::Equal)
+        .map(|(k, _)| k)
+        .collect();
+    let mut group_start = 0;
+    while group_start < changes.len() {
+        let mut group_end = group_start;
+        while group_end + 1 < changes.len()
+            && changes[group_end + 1] - changes[group_end] - 1 <= 2 * CONTEXT
+        {
+            group_end += 1;
+        }
+        let from = changes[group_start].saturating_sub(CONTEXT);
+        let to = (changes[group_end] + CONTEXT + 1).min(edits.len());
+        write_hunk(&mut out, &edits[from..to], &a, &b);
+        group_start = group_end + 1;
+    }
+    Some(out)
+}
+
+fn edit_script(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut edits: Vec<Edit> = (0..prefix)
+        .map(|k| Edit {
+            op: Op::Equal,
+            old: k,
+            new: k,
+        })
+        .collect();
+    for (op, i, j) in align(a_mid, b_mid) {
+        edits.push(Edit {
+            op,
+            old: prefix + i,
+            new: prefix + j,
+        });
+    }
+    for k in 0..suffix {
+        edits.push(Edit {
+            op: Op::Equal,
+            old: a.len() - suffix + k,
+            new: b.len() - suffix + k,
+        });
+    }
+    edits
+}
+
+/// Align `old` and `new` along a longest common subsequence.
+fn align(old: &[&str], new: &[&str]) -> Vec<(Op, usize, usize)> {
+    let (n, m) = (old.len(), new.len());
+    if (n + 1).saturating_mul(m + 1) > LCS_CELL_LIMIT {
+        return (0..n)
+            .map(|i| (Op::Delete, i, 0))
+            .chain((0..m).map(|j| (Op::Insert, n, j)))
+            .collect();
+    }
+    // lcs[i * (m + 1) + j]: length of the LCS of old[i..] and new[j..].
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * (m + 1) + j] = if old[i] == new[j] {
+                lcs[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                lcs[(i + 1) * (m + 1) + j].max(lcs[i * (m + 1) + j + 1])
+            };
+        }
+    }
+    let mut script = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            script.push((Op::Equal, i, j));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[(i + 1) * (m + 1) + j] >= lcs[i * (m + 1) + j + 1]) {
+            script.push((Op::Delete, i, j));
+            i += 1;
+        } else {
+            script.push((Op::Insert, i, j));
+            j += 1;
+        }
+    }
+    script
+}
+
+fn write_hunk(out: &mut String, edits: &[Edit], a: &[&str], b: &[&str]) {
+    let old_count = edits.iter().filter(|e| e.op != Op::Insert).count();
+    let new_count = edits.iter().filter(|e| e.op != Op::Delete).count();
+    let _ = writeln!(
+        out,
+        "@@ -{} +{} @@",
+        range(edits[0].old, old_count),
+        range(edits[0].new, new_count)
+    );
+    for edit in edits {
+        let (marker, line) = match edit.op {
+            Op::Equal => (' ', a[edit.old]),
+            Op::Delete => ('-', a[edit.old]),
+            Op::Insert => ('+', b[edit.new]),
+        };
+        out.push(marker);
+        out.push_str(line);
+        if !line.ends_with('\n') {
+            out.push_str("\n\\ No newline at end of file\n");
+        }
+    }
+}
+
+/// A hunk range: 1-based start line and line count, where an empty range
+/// starts at the line before it.
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{count}", start + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_texts_have_no_diff() {
+        assert!(unified_diff("a\nb\n", "a\nb\n", "a/f", "b/f").is_none());
+    }
+
+    #[test]
+    fn changes_are_grouped_into_hunks_with_context() {
+        let lines = |skip: u32| -> String {
+            (1..=20)
+                .filter(|&n| n != skip)
+                .map(|n| {
+                    if n == 2 && skip != 0 {
+                        "two".to_owned()
+                    } else {
+                        n.to_string()
+                    }
+                })
+                .map(|line| line + "\n")
+                .collect()
+        };
+        let (old, new) = (lines(0), lines(18));
+        let diff = unified_diff(&old, &new, "a/f", "b/f").unwrap();
+        assert_eq!(
+            diff,
+            "--- a/f\n+++ b/f\n\
+             @@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n\
+             @@ -15,6 +15,5 @@\n 15\n 16\n 17\n-18\n 19\n 20\n"
+        );
+    }
+
+    #[test]
+    fn new_files_and_missing_newlines_are_marked() {
+        let diff = unified_diff("", "x\ny", "/dev/null", "b/f").unwrap();
+        assert_eq!(
+            diff,
+            "--- /dev/null\n+++ b/f\n@@ -0,0 +1,2 @@\n+x\n+y\n\\ No newline at end of file\n"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Chaos tests driven by the `karapace-store` fault hooks.
+//!
+//! Unlike `crash.rs`, which kills a forked child at a random moment, these
+//! tests replay every state a power cut could leave on disk during an
+//! operation, and fail writes at every point of a byte budget, then check
+//! that the engine recovers to a consistent store.
+
+use karapace_core::Engine;
+use karapace_store::chaos::{self, DirSnapshot, FaultPlan};
+use karapace_store::{verify_env_integrity, verify_store_integrity, EnvState, StoreLayout};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn write_manifest(dir: &Path, packages: &str) -> PathBuf {
+    let path = dir.join("karapace.toml");
+    fs::write(
+        &path,
+        format!(
+            r#"manifest_version = 1
+[base]
+image = "rolling"
+[system]
+packages = [{packages}]
+[runtime]
+backend = "mock"
+"#
+        ),
+    )
+    .unwrap();
+    path
+}
+
+/// Open the store (running WAL recovery) and check it is consistent.
+fn assert_recovers(store: &Path, context: &str) -> Engine {
+    let engine = Engine::new(store);
+    let layout = StoreLayout::new(store);
+
+    let wal = karapace_store::WriteAheadLog::new(&layout);
+    let incomplete = wal.list_incomplete().unwrap();
+    assert!(
+        incomplete.is_empty(),
+        "{context}: {} WAL entries left after recovery",
+        incomplete.len()
+    );
+
+    let report = verify_store_integrity(&layout).unwrap();
+    assert!(
+        report.failed.is_empty(),
+        "{context}: store integrity failures: {:?}",
+        report.failed
+    );
+
+    for env in engine.list().unwrap() {
+        assert_ne!(env.state, EnvState::Running, "{context}: env stuck running");
+        let report = verify_env_integrity(&layout, &env.env_id).unwrap();
+        assert!(
+            report.failed.is_empty(),
+            "{context}: env {} integrity failures: {:?}",
+            env.env_id,
+            report.failed
+        );
+    }
+    engine
+}
+
+/// Restore each snapshot into a fresh store, recover it, and check that a
+/// build still succeeds on top of it.
+fn replay_power_cuts(snapshots: &[DirSnapshot], manifest: &Path) {
+    assert!(!snapshots.is_empty());
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let dir = tempfile::tempdir().unwrap();
+        snapshot.restore(dir.path()).unwrap();
+        let context = format!("power cut after write {i}");
+        let engine = assert_recovers(dir.path(), &context);
+        engine
+            .build(manifest)
+            .unwrap_or_else(|e| panic!("{context}: build after recovery failed: {e}"));
+        assert_recovers(dir.path(), &context);
+    }
+}
+
+#[test]
+fn power_cut_during_build() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(project.path(), r#""git""#);
+    let engine = Engine::new(store.path());
+
+    let (result, snapshots) =
+        chaos::record_durable_states(store.path(), || engine.build(&manifest));
+    result.unwrap();
+    replay_power_cuts(&snapshots, &manifest);
+}
+
+#[test]
+fn power_cut_during_commit_and_destroy() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(project.path(), r#""git", "curl""#);
+    let engine = Engine::new(store.path());
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    fs::write(
+        store
+            .path()
+            .join("env")
+            .join(&env_id)
+            .join("upper/file.txt"),
+        "work",
+    )
+    .unwrap();
+
+    let (result, snapshots) = chaos::record_durable_states(store.path(), || {
+        engine.commit(&env_id)?;
+        engine.destroy(&env_id)
+    });
+    result.unwrap();
+    replay_power_cuts(&snapshots, &manifest);
+}
+
+#[test]
+fn failed_writes_at_every_budget_leave_store_recoverable() {
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(project.path(), r#""git""#);
+
+    // Measure how much a clean build writes, then fail it at points across
+    // that range.
+    let probe = tempfile::tempdir().unwrap();
+    let guard = chaos::install(probe.path(), FaultPlan::default());
+    Engine::new(probe.path()).build(&manifest).unwrap();
+    let total = guard.bytes_written();
+    drop(guard);
+    assert!(total > 0);
+
+    // Timestamps make builds differ in size by a few bytes, so budgets stay
+    // clear of the end of the range.
+    let end = total.saturating_sub(64);
+    for budget in (0..end).step_by((total / 24).max(1) as usize) {
+        let store = tempfile::tempdir().unwrap();
+        let guard = chaos::install(
+            store.path(),
+            FaultPlan {
+                fail_writes_after: Some(budget),
+                ..FaultPlan::default()
+            },
+        );
+        let result = Engine::new(store.path()).build(&manifest);
+        assert!(result.is_err(), "build within {budget} bytes succeeded");
+        assert!(guard.failed_writes() > 0);
+        drop(guard);
+
+        let context = format!("write budget {budget}");
+        let engine = assert_recovers(store.path(), &context);
+        engine
+            .build(&manifest)
+            .unwrap_or_else(|e| panic!("{context}: build after recovery failed: {e}"));
+    }
+}
+
+#[test]
+fn delayed_renames_do_not_change_results() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(project.path(), r#""git""#);
+    let _guard = chaos::install(
+        store.path(),
+        FaultPlan {
+            rename_delay: Some(Duration::from_millis(1)),
+            ..FaultPlan::default()
+        },
+    );
+
+    let engine = Engine::new(store.path());
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    engine.commit(&env_id).unwrap();
+    assert_recovers(store.path(), "delayed renames");
+}
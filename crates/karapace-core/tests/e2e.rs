@@ -3,7 +3,6 @@
 //! These tests are `#[ignore]` by default because they require:
 //! - Linux with user namespace support
 //! - `fuse-overlayfs` installed
-//! - `curl` installed
 //! - Network access (to download base images)
 //!
 //! Run with: `cargo test --test e2e -- --ignored`
@@ -54,7 +53,7 @@ fn prereqs_available() -> bool {
 
 /// Build a minimal environment with the namespace backend (no packages).
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_build_minimal_namespace() {
     if !prereqs_available() {
         return;
@@ -83,7 +82,7 @@ fn e2e_build_minimal_namespace() {
 
 /// Exec a command inside a built environment and verify stdout.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_exec_in_namespace() {
     if !prereqs_available() {
         return;
@@ -104,7 +103,7 @@ fn e2e_exec_in_namespace() {
 
 /// Destroy cleans up all overlay directories.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_destroy_cleans_up() {
     if !prereqs_available() {
         return;
@@ -129,7 +128,7 @@ fn e2e_destroy_cleans_up() {
 
 /// Rebuild produces the same env_id for the same manifest.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_rebuild_determinism() {
     if !prereqs_available() {
         return;
@@ -151,7 +150,7 @@ fn e2e_rebuild_determinism() {
 
 /// Snapshot and restore round-trip with real namespace backend.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_snapshot_and_restore() {
     if !prereqs_available() {
         return;
@@ -202,7 +201,7 @@ fn e2e_snapshot_and_restore() {
 
 /// Overlay correctness: files written in upper are visible, base is read-only.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_overlay_file_visibility() {
     if !prereqs_available() {
         return;
@@ -235,7 +234,7 @@ fn e2e_overlay_file_visibility() {
 
 /// Enter/exit cycle: repeated enter should not leak state.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_enter_exit_cycle() {
     if !prereqs_available() {
         return;
@@ -268,7 +267,7 @@ fn e2e_enter_exit_cycle() {
 
 /// Verify no fuse-overlayfs mounts leak after build + exec + destroy cycle.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_mount_leak_detection() {
     if !prereqs_available() {
         return;
@@ -310,7 +309,7 @@ fn e2e_mount_leak_detection() {
 
 /// Repeated build/destroy cycles must not accumulate state or stale mounts.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_build_destroy_20_cycles() {
     if !prereqs_available() {
         return;
@@ -355,7 +354,7 @@ fn e2e_build_destroy_20_cycles() {
 
 /// If an OCI runtime (crun/runc) is available, build and destroy with it.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_oci_build_if_available() {
     if !prereqs_available() {
         return;
@@ -411,7 +410,7 @@ backend = "oci"
 
 /// Concurrent exec calls on the same environment must all succeed.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_namespace_concurrent_exec() {
     if !prereqs_available() {
         return;
@@ -464,7 +463,7 @@ fn e2e_namespace_concurrent_exec() {
 
 /// Verify resolved packages have real versions (not mock/unresolved).
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_resolve_pins_exact_versions() {
     if !prereqs_available() {
         return;
@@ -500,7 +499,7 @@ fn e2e_resolve_pins_exact_versions() {
 
 /// Rebuild same manifest must produce identical env_id and resolved versions.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_resolve_deterministic_across_rebuilds() {
     if !prereqs_available() {
         return;
@@ -526,7 +525,7 @@ fn e2e_resolve_deterministic_across_rebuilds() {
 
 /// Building with a non-existent package must fail cleanly.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_resolve_nonexistent_package_fails() {
     if !prereqs_available() {
         return;
@@ -565,7 +564,7 @@ fn e2e_resolve_nonexistent_package_fails() {
 
 /// Build with multiple packages — all must have non-empty resolved versions.
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_resolve_multiple_packages() {
     if !prereqs_available() {
         return;
@@ -594,7 +593,7 @@ fn e2e_resolve_multiple_packages() {
 
 /// Build with packages (requires network to download image + install).
 #[test]
-#[ignore = "requires Linux user namespaces, fuse-overlayfs, curl, and network"]
+#[ignore = "requires Linux user namespaces, fuse-overlayfs, and network"]
 fn e2e_build_with_packages() {
     if !prereqs_available() {
         return;
@@ -614,17 +613,12 @@ fn e2e_build_with_packages() {
     let lock = result.lock_file;
     assert_eq!(lock.lock_version, 2);
     assert!(!lock.resolved_packages.is_empty());
-    // At least one package should have a resolved version.
-    // Note: some package names may not match their RPM names exactly,
-    // causing fallback to "unresolved". This is a known limitation.
-    let resolved_count = lock
-        .resolved_packages
-        .iter()
-        .filter(|p| p.version != "unresolved")
-        .count();
+    // Resolution fails rather than locking a package without a version.
     assert!(
-        resolved_count > 0,
-        "at least one package should have a resolved version, got: {:?}",
+        lock.resolved_packages
+            .iter()
+            .all(|p| !p.version.is_empty() && p.version != "unresolved"),
+        "every package should have a resolved version, got: {:?}",
         lock.resolved_packages
     );
 }
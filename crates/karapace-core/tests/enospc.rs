@@ -4,6 +4,7 @@
 //! They require root (or equivalent) to mount tmpfs, so they are ignored
 //! by default and run in CI with: `sudo -E cargo test --test enospc -- --ignored`
 
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -342,6 +343,10 @@ fn enospc_layer_put_fails_cleanly() {
         object_refs: vec!["obj1".to_owned(), "obj2".to_owned()],
         read_only: true,
         tar_hash: String::new(),
+        workspace: None,
+        delta_parent: None,
+        file_objects: Vec::new(),
+        pax_metadata: false,
     };
 
     let result = layer_store.put(&manifest);
@@ -394,6 +399,18 @@ fn enospc_metadata_put_fails_cleanly() {
         created_at: "2025-01-01T00:00:00Z".to_owned(),
         updated_at: "2025-01-01T00:00:00Z".to_owned(),
         checksum: None,
+        aliases: Vec::new(),
+        host_gpu: None,
+        base_image_digest: None,
+        workspace: None,
+        snapshot: None,
+        expires_after_secs: None,
+        last_entered_at: None,
+        enter_count: 0,
+        last_built_at: None,
+        labels: BTreeMap::new(),
+        revision: 0,
+        attestation: None,
     };
 
     let result = meta_store.put(&meta);
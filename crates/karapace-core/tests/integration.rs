@@ -1,7 +1,10 @@
 #![allow(unsafe_code)]
 
-use karapace_core::{Engine, StoreLock};
+use karapace_core::{
+    prune, BuildOptions, CommitOptions, Engine, EnterOptions, PruneOptions, StoreLock,
+};
 use karapace_store::{EnvState, StoreLayout};
+use std::collections::BTreeMap;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
@@ -538,6 +541,68 @@ fn exec_via_mock_backend() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn exec_returns_the_command_exit_code() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+
+    let exec = |command: &[&str]| {
+        let command: Vec<String> = command.iter().map(|&arg| arg.to_owned()).collect();
+        engine.exec_with_options(&env_id, &command, EnterOptions::default())
+    };
+    assert_eq!(exec(&["true"]).unwrap(), 0);
+    assert_eq!(exec(&["exit", "42"]).unwrap(), 42);
+    assert!(engine.exec(&env_id, &["false".to_owned()]).is_err());
+
+    let meta_store = karapace_store::MetadataStore::new(StoreLayout::new(store.path()));
+    assert_eq!(meta_store.get(&env_id).unwrap().state, EnvState::Built);
+}
+
+#[test]
+fn detached_session_runs_until_stopped() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let meta_store = karapace_store::MetadataStore::new(StoreLayout::new(store.path()));
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+
+    assert!(engine.attach(&env_id, &[]).is_err(), "nothing to attach to");
+    assert!(engine.ps(&env_id).is_err(), "not running");
+    let read_only = EnterOptions {
+        read_only: true,
+        ..EnterOptions::default()
+    };
+    assert!(engine.enter_detached(&env_id, read_only).is_err());
+
+    engine
+        .enter_detached(&env_id, EnterOptions::default())
+        .unwrap();
+    assert_eq!(meta_store.get(&env_id).unwrap().state, EnvState::Running);
+    assert_eq!(
+        engine
+            .attach(&env_id, &["exit".to_owned(), "5".to_owned()])
+            .unwrap(),
+        5
+    );
+    assert_eq!(engine.attach(&env_id, &[]).unwrap(), 0);
+    assert!(engine.enter(&env_id).is_err(), "already running");
+    let processes = engine.ps(&env_id).unwrap();
+    assert_eq!(processes.len(), 1);
+    assert_eq!(processes[0].command, "/bin/sh -l");
+    assert_eq!(engine.stats(&env_id).unwrap().processes, 1);
+
+    engine.stop(&env_id).unwrap();
+    assert_eq!(meta_store.get(&env_id).unwrap().state, EnvState::Built);
+    assert_eq!(engine.stats(&env_id).unwrap().processes, 0);
+    assert!(engine.attach(&env_id, &[]).is_err());
+}
+
 // §3.2: Lock file integrity verifiable after build
 #[test]
 fn lock_file_integrity_after_build() {
@@ -576,6 +641,68 @@ fn frozen_env_cannot_be_entered() {
     assert!(result.is_err(), "entering a frozen env must fail");
 }
 
+#[test]
+fn read_only_session_enters_frozen_and_archived_envs() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let meta_store = karapace_store::MetadataStore::new(StoreLayout::new(store.path()));
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let read_only = EnterOptions {
+        read_only: true,
+        ..EnterOptions::default()
+    };
+
+    engine.freeze(&env_id).unwrap();
+    engine.enter_with_options(&env_id, read_only).unwrap();
+    assert_eq!(meta_store.get(&env_id).unwrap().state, EnvState::Frozen);
+
+    engine.archive(&env_id).unwrap();
+    engine
+        .exec_with_options(&env_id, &["true".to_owned()], read_only)
+        .unwrap();
+    assert_eq!(meta_store.get(&env_id).unwrap().state, EnvState::Archived);
+}
+
+#[test]
+fn gpu_driver_drift_warns_or_refuses_entry() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+
+    let manifest = write_manifest(
+        project.path(),
+        &format!("{}[hardware]\ngpu = true\n", mock_manifest(&[])),
+    );
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+
+    let meta_store = karapace_store::MetadataStore::new(StoreLayout::new(store.path()));
+    let mut meta = meta_store.get(&env_id).unwrap();
+    assert!(meta.host_gpu.is_some(), "gpu envs record host drivers");
+
+    // Pretend the env was built against a driver the host no longer has.
+    meta.host_gpu = Some(karapace_store::GpuDriverInfo {
+        nvidia_kernel: Some("0.0.0-removed".to_owned()),
+        ..Default::default()
+    });
+    meta_store.put(&meta).unwrap();
+
+    let strict = EnterOptions {
+        strict_gpu: true,
+        ..EnterOptions::default()
+    };
+    let err = engine.enter_with_options(&env_id, strict).unwrap_err();
+    assert!(
+        matches!(err, karapace_core::CoreError::GpuDriverDrift(_)),
+        "unexpected error: {err}"
+    );
+    assert_eq!(meta_store.get(&env_id).unwrap().state, EnvState::Built);
+
+    engine.enter(&env_id).unwrap();
+}
+
 // §15: Crash injection — partial write must not corrupt store
 #[test]
 fn crash_injection_partial_write_detected() {
@@ -1206,6 +1333,109 @@ fn destroy_with_wal_commits_cleanly() {
     );
 }
 
+fn build_envs(engine: &Engine, packages: &[&str]) -> (Vec<tempfile::TempDir>, Vec<String>) {
+    let mut projects = Vec::new();
+    let mut env_ids = Vec::new();
+    for pkg in packages {
+        let project = tempfile::tempdir().unwrap();
+        let manifest = write_manifest(project.path(), &mock_manifest(&[pkg]));
+        env_ids.push(engine.build(&manifest).unwrap().identity.env_id.to_string());
+        projects.push(project);
+    }
+    (projects, env_ids)
+}
+
+#[test]
+fn transaction_commits_all_mutations_then_collects_garbage() {
+    let store = tempfile::tempdir().unwrap();
+    let layout = StoreLayout::new(store.path());
+    let engine = Engine::new(store.path());
+    let (_projects, env_ids) = build_envs(&engine, &["git", "curl", "vim"]);
+    let objects_before = fs::read_dir(layout.objects_dir()).unwrap().count();
+
+    let lock = StoreLock::acquire(&layout.lock_file()).unwrap();
+    engine
+        .with_transaction(&lock, |tx| {
+            tx.destroy(&env_ids[0])?;
+            tx.destroy(&env_ids[1])?;
+            tx.archive(&env_ids[2])?;
+            tx.gc();
+            Ok(())
+        })
+        .unwrap();
+
+    let remaining = engine.list().unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].state, EnvState::Archived);
+    assert!(!layout.env_path(&env_ids[0]).exists());
+    assert!(fs::read_dir(layout.objects_dir()).unwrap().count() < objects_before);
+    assert_eq!(fs::read_dir(layout.staging_dir()).unwrap().count(), 0);
+    let wal = karapace_store::WriteAheadLog::new(&layout);
+    assert!(wal.list_incomplete().unwrap().is_empty());
+}
+
+#[test]
+fn failed_transaction_rolls_back_every_mutation() {
+    let store = tempfile::tempdir().unwrap();
+    let layout = StoreLayout::new(store.path());
+    let engine = Engine::new(store.path());
+    let (_projects, env_ids) = build_envs(&engine, &["git", "curl"]);
+    engine
+        .set_name(&env_ids[1], Some("keep".to_owned()))
+        .unwrap();
+    fs::write(layout.upper_dir(&env_ids[0]).join("work.txt"), "unsaved").unwrap();
+
+    let lock = StoreLock::acquire(&layout.lock_file()).unwrap();
+    let result = engine.with_transaction(&lock, |tx| {
+        tx.destroy(&env_ids[0])?;
+        tx.rename(&env_ids[1], "renamed")?;
+        tx.freeze(&env_ids[1])?;
+        tx.destroy("no-such-env")
+    });
+    assert!(result.is_err());
+
+    let first = engine.inspect(&env_ids[0]).unwrap();
+    assert_eq!(first.state, EnvState::Built);
+    assert_eq!(
+        fs::read_to_string(layout.upper_dir(&env_ids[0]).join("work.txt")).unwrap(),
+        "unsaved"
+    );
+    let second = engine.find("keep").unwrap().unwrap();
+    assert_eq!(second.env_id.as_str(), env_ids[1]);
+    assert_eq!(second.state, EnvState::Built);
+    assert!(engine.find("renamed").unwrap().is_none());
+    assert_eq!(fs::read_dir(layout.staging_dir()).unwrap().count(), 0);
+}
+
+#[test]
+fn transaction_interrupted_by_a_crash_is_rolled_back_on_restart() {
+    let store = tempfile::tempdir().unwrap();
+    let layout = StoreLayout::new(store.path());
+    let engine = Engine::new(store.path());
+    let (_projects, env_ids) = build_envs(&engine, &["git", "curl"]);
+
+    let lock = StoreLock::acquire(&layout.lock_file()).unwrap();
+    let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        engine.with_transaction(&lock, |tx| {
+            tx.destroy(&env_ids[0])?;
+            tx.destroy(&env_ids[1])?;
+            panic!("simulated crash");
+            #[allow(unreachable_code)]
+            Ok(())
+        })
+    }));
+    assert!(crashed.is_err());
+    assert!(engine.list().unwrap().is_empty());
+    drop(lock);
+
+    let engine = Engine::new(store.path());
+    let envs = engine.list().unwrap();
+    assert_eq!(envs.len(), 2);
+    for env_id in &env_ids {
+        assert!(layout.env_path(env_id).exists());
+    }
+}
+
 // M1.2: Destroy WAL crash recovery — crash between env_dir removal and metadata removal
 #[test]
 fn wal_crash_during_destroy_is_recoverable() {
@@ -1429,6 +1659,18 @@ fn metadata_put_fails_on_read_only_dir() {
         updated_at: "2025-01-01T00:00:00Z".to_owned(),
         ref_count: 1,
         checksum: None,
+        aliases: Vec::new(),
+        host_gpu: None,
+        base_image_digest: None,
+        workspace: None,
+        snapshot: None,
+        expires_after_secs: None,
+        last_entered_at: None,
+        enter_count: 0,
+        last_built_at: None,
+        labels: BTreeMap::new(),
+        revision: 0,
+        attestation: None,
     };
     let result = meta_store.put(&meta);
     assert!(result.is_err(), "put must fail on read-only metadata dir");
@@ -1477,6 +1719,10 @@ fn layer_corruption_detected_on_get() {
         object_refs: vec![],
         read_only: true,
         tar_hash: String::new(),
+        workspace: None,
+        delta_parent: None,
+        file_objects: Vec::new(),
+        pax_metadata: false,
     };
     let content_hash = layer_store.put(&layer).unwrap();
 
@@ -1512,6 +1758,18 @@ fn metadata_corruption_detected_on_get() {
         updated_at: "2025-01-01T00:00:00Z".to_owned(),
         ref_count: 1,
         checksum: None,
+        aliases: Vec::new(),
+        host_gpu: None,
+        base_image_digest: None,
+        workspace: None,
+        snapshot: None,
+        expires_after_secs: None,
+        last_entered_at: None,
+        enter_count: 0,
+        last_built_at: None,
+        labels: BTreeMap::new(),
+        revision: 0,
+        attestation: None,
     };
     meta_store.put(&meta).unwrap();
 
@@ -1731,6 +1989,10 @@ fn write_failure_never_panics() {
         object_refs: vec![],
         read_only: true,
         tar_hash: "test".into(),
+        workspace: None,
+        delta_parent: None,
+        file_objects: Vec::new(),
+        pax_metadata: false,
     };
     let result = layer_store.put(&layer);
     fs::set_permissions(&layers_dir, fs::Permissions::from_mode(0o755)).unwrap();
@@ -1756,6 +2018,18 @@ fn write_failure_never_panics() {
         updated_at: "2025-01-01T00:00:00Z".to_owned(),
         ref_count: 1,
         checksum: None,
+        aliases: Vec::new(),
+        host_gpu: None,
+        base_image_digest: None,
+        workspace: None,
+        snapshot: None,
+        expires_after_secs: None,
+        last_entered_at: None,
+        enter_count: 0,
+        last_built_at: None,
+        labels: BTreeMap::new(),
+        revision: 0,
+        attestation: None,
     };
     let result = meta_store.put(&meta);
     fs::set_permissions(&meta_dir, fs::Permissions::from_mode(0o755)).unwrap();
@@ -2316,6 +2590,28 @@ fn rename_environment_works() {
     assert_eq!(meta.name, Some("new-name".to_owned()));
 }
 
+#[test]
+fn rename_keeps_alias_unless_opted_out() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let r = engine.build(&manifest).unwrap();
+    let env_id = r.identity.env_id.to_string();
+
+    engine.rename(&env_id, "old-name").unwrap();
+    engine.rename(&env_id, "new-name").unwrap();
+    assert_eq!(engine.inspect(&env_id).unwrap().aliases, vec!["old-name"]);
+
+    engine
+        .rename_with_options(&env_id, "newest", false)
+        .unwrap();
+    let meta = engine.inspect(&env_id).unwrap();
+    assert_eq!(meta.name.as_deref(), Some("newest"));
+    assert_eq!(meta.aliases, vec!["old-name"]);
+}
+
 #[test]
 fn verify_store_reports_all_clean_after_fresh_build() {
     let store = tempfile::tempdir().unwrap();
@@ -2368,3 +2664,1226 @@ fn rebuild_preserves_new_and_cleans_old() {
     // Old env must be gone (destroyed by rebuild)
     assert!(engine.inspect(&old_id).is_err());
 }
+
+#[test]
+fn check_lock_frozen_clean_after_build() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    engine.build(&manifest).unwrap();
+
+    let diff = engine.check_lock(&manifest, true, false).unwrap();
+    assert!(diff.is_empty(), "fresh lock must not drift: {diff}");
+}
+
+#[test]
+fn check_lock_frozen_reports_stale_digest() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let result = engine.build(&manifest).unwrap();
+
+    // Simulate an upstream image update: the lock still pins the old digest.
+    let (_, normalized, _) = engine.resolve_manifest(&manifest).unwrap();
+    let stale = karapace_schema::LockFile::from_resolved(
+        &normalized,
+        &karapace_schema::ResolutionResult {
+            base_image_digest: "0".repeat(64),
+            resolved_packages: result.lock_file.resolved_packages.clone(),
+        },
+    );
+    stale
+        .write_to_file(project.path().join("karapace.lock"))
+        .unwrap();
+
+    // Without --frozen only integrity and intent are checked.
+    assert!(engine
+        .check_lock(&manifest, false, false)
+        .unwrap()
+        .is_empty());
+
+    let diff = engine.check_lock(&manifest, true, false).unwrap();
+    assert_eq!(diff.fields.len(), 1);
+    assert_eq!(diff.fields[0].field, "base_image_digest");
+    assert!(diff.packages.is_empty());
+}
+
+#[test]
+fn check_lock_missing_lock_fails() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&[]));
+    assert!(engine.check_lock(&manifest, true, false).is_err());
+}
+
+#[test]
+fn workspaces_keep_separate_upper_dirs_and_snapshots() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let r = engine.build(&manifest).unwrap();
+    let env_id = r.identity.env_id.to_string();
+
+    let upper = engine.store_layout().upper_dir(&env_id);
+    fs::create_dir_all(&upper).unwrap();
+    fs::write(upper.join("state.txt"), "default").unwrap();
+    engine.commit(&env_id).unwrap();
+
+    // A branch starts from the current state, then diverges.
+    engine.use_workspace(&env_id, "experiment-a", true).unwrap();
+    assert_eq!(
+        fs::read_to_string(upper.join("state.txt")).unwrap(),
+        "default"
+    );
+    assert!(engine.list_snapshots(&env_id).unwrap().is_empty());
+    fs::write(upper.join("state.txt"), "experiment").unwrap();
+    engine.commit(&env_id).unwrap();
+    assert_eq!(engine.list_snapshots(&env_id).unwrap().len(), 1);
+    assert!(engine.use_workspace(&env_id, "experiment-a", true).is_err());
+
+    // A plain switch to a new name starts empty.
+    engine.use_workspace(&env_id, "scratch", false).unwrap();
+    assert!(!upper.join("state.txt").exists());
+
+    engine.use_workspace(&env_id, "default", false).unwrap();
+    assert_eq!(
+        fs::read_to_string(upper.join("state.txt")).unwrap(),
+        "default"
+    );
+    assert_eq!(engine.inspect(&env_id).unwrap().workspace, None);
+    assert_eq!(engine.list_snapshots(&env_id).unwrap().len(), 1);
+
+    let names: Vec<(String, bool)> = engine
+        .list_workspaces(&env_id)
+        .unwrap()
+        .into_iter()
+        .map(|w| (w.name, w.active))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("default".to_owned(), true),
+            ("experiment-a".to_owned(), false),
+            ("scratch".to_owned(), false),
+        ]
+    );
+
+    assert!(engine.remove_workspace(&env_id, "default").is_err());
+    engine.remove_workspace(&env_id, "scratch").unwrap();
+    assert_eq!(engine.list_workspaces(&env_id).unwrap().len(), 2);
+
+    engine
+        .use_workspace(&env_id, "experiment-a", false)
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(upper.join("state.txt")).unwrap(),
+        "experiment"
+    );
+    assert_eq!(
+        engine.inspect(&env_id).unwrap().workspace.as_deref(),
+        Some("experiment-a")
+    );
+}
+
+#[test]
+fn clones_share_layers_and_start_from_a_copy_of_the_upper_dir() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let upper = engine.store_layout().upper_dir(&env_id);
+    fs::write(upper.join("state.txt"), "original").unwrap();
+    engine.set_name(&env_id, Some("main".to_owned())).unwrap();
+
+    assert!(engine.clone_env(&env_id, "main").is_err());
+    let clone_id = engine.clone_env(&env_id, "experiment").unwrap();
+    assert_ne!(clone_id, env_id);
+
+    let source = engine.inspect(&env_id).unwrap();
+    let clone = engine.inspect(&clone_id).unwrap();
+    assert_eq!(clone.name.as_deref(), Some("experiment"));
+    assert_eq!(clone.state, EnvState::Built);
+    assert_eq!(clone.manifest_hash, source.manifest_hash);
+    assert_eq!(clone.base_layer, source.base_layer);
+    assert_eq!(clone.dependency_layers, source.dependency_layers);
+
+    // The upper dirs diverge from the copy.
+    let clone_upper = engine.store_layout().upper_dir(&clone_id);
+    assert_eq!(
+        fs::read_to_string(clone_upper.join("state.txt")).unwrap(),
+        "original"
+    );
+    fs::write(clone_upper.join("state.txt"), "experiment").unwrap();
+    assert_eq!(
+        fs::read_to_string(upper.join("state.txt")).unwrap(),
+        "original"
+    );
+
+    // The shared layers outlive the source.
+    engine.destroy(&env_id).unwrap();
+    let lock = StoreLock::acquire(&engine.store_layout().lock_file()).unwrap();
+    engine.gc(&lock, false).unwrap();
+    let layers = karapace_store::LayerStore::new(engine.store_layout().clone());
+    assert!(layers.exists(&clone.base_layer));
+    for layer in &clone.dependency_layers {
+        assert!(layers.exists(layer));
+    }
+    engine.exec(&clone_id, &["true".to_owned()]).unwrap();
+}
+
+#[test]
+fn autosnap_commits_due_environments_and_prunes_old_snapshots() {
+    use karapace_core::autosnap;
+
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let upper = engine.store_layout().upper_dir(&env_id);
+
+    autosnap::enable(&engine, &env_id, std::time::Duration::from_hours(1), 2).unwrap();
+    let start = chrono::Utc::now();
+    for hour in 0..3 {
+        fs::write(upper.join("state.txt"), format!("hour {hour}")).unwrap();
+        let now = start + chrono::Duration::hours(hour * 2);
+        let runs = autosnap::run_due_at(&engine, now).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert!(runs[0].snapshot.is_some(), "{:?}", runs[0].error);
+        // Not due again until the interval has passed.
+        assert!(autosnap::run_due_at(&engine, now).unwrap().is_empty());
+    }
+    let snapshots = engine.list_snapshots(&env_id).unwrap();
+    assert_eq!(snapshots.len(), 2);
+    let current = engine.inspect(&env_id).unwrap().snapshot.unwrap();
+    assert!(karapace_store::LayerStore::new(engine.store_layout().clone()).exists(&current));
+
+    // Destroyed environments lose their schedule.
+    engine.destroy(&env_id).unwrap();
+    assert!(
+        autosnap::run_due_at(&engine, start + chrono::Duration::days(1))
+            .unwrap()
+            .is_empty()
+    );
+    assert!(autosnap::load_schedules(engine.store_layout())
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn autosnap_pruning_keeps_manual_snapshots() {
+    use karapace_core::autosnap;
+
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let upper = engine.store_layout().upper_dir(&env_id);
+
+    fs::write(upper.join("state.txt"), "by hand").unwrap();
+    let manual = engine.commit(&env_id).unwrap();
+
+    autosnap::enable(&engine, &env_id, std::time::Duration::from_hours(1), 1).unwrap();
+    let start = chrono::Utc::now();
+    let mut pruned = Vec::new();
+    for hour in 0..3 {
+        fs::write(upper.join("state.txt"), format!("hour {hour}")).unwrap();
+        let runs =
+            autosnap::run_due_at(&engine, start + chrono::Duration::hours(hour * 2)).unwrap();
+        assert!(runs[0].snapshot.is_some(), "{:?}", runs[0].error);
+        pruned.extend(runs[0].pruned.clone());
+    }
+    assert_eq!(pruned.len(), 2);
+    assert!(!pruned.contains(&manual));
+
+    let snapshots: Vec<String> = engine
+        .snapshot_details(&env_id)
+        .unwrap()
+        .into_iter()
+        .map(|s| s.hash)
+        .collect();
+    assert_eq!(snapshots.len(), 2);
+    assert!(snapshots.contains(&manual));
+    let schedules = autosnap::load_schedules(engine.store_layout()).unwrap();
+    assert_eq!(schedules[&env_id].snapshots.len(), 1);
+}
+
+#[test]
+fn overlay_limit_reported_and_enforced_after_sessions() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(
+        project.path(),
+        "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\nbackend = \"mock\"\nmax_overlay_mb = 1\n",
+    );
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+
+    let usage = engine.usage(&env_id).unwrap();
+    assert_eq!(usage.limit_mb, Some(1));
+    assert!(!usage.exceeded());
+
+    let upper = StoreLayout::new(store.path()).upper_dir(&env_id);
+    fs::write(upper.join("big.bin"), vec![7u8; 2 * 1024 * 1024]).unwrap();
+    let usage = engine.usage(&env_id).unwrap();
+    assert!(usage.upper_bytes >= 2 * 1024 * 1024);
+    assert!(usage.exceeded());
+
+    let err = engine.enter(&env_id).unwrap_err().to_string();
+    assert!(err.contains("max_overlay_mb limit of 1 MB"), "{err}");
+    let err = engine
+        .exec(&env_id, &["true".to_owned()])
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("max_overlay_mb"), "{err}");
+    // The session itself completed; the environment is usable once trimmed.
+    assert_eq!(engine.inspect(&env_id).unwrap().state, EnvState::Built);
+    fs::remove_file(upper.join("big.bin")).unwrap();
+    engine.enter(&env_id).unwrap();
+}
+
+#[test]
+fn unlimited_environment_reports_usage_without_limit() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+
+    let usage = engine.usage(&env_id).unwrap();
+    assert_eq!(usage.limit_mb, None);
+    assert!(!usage.exceeded());
+    assert!(engine.usage("no-such-env").is_err());
+}
+
+#[test]
+fn incremental_commits_restore_through_delta_chain() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let upper = engine.store_layout().upper_dir(&env_id);
+    let incremental = CommitOptions { incremental: true };
+    let layers = karapace_store::LayerStore::new(StoreLayout::new(store.path()));
+
+    fs::write(upper.join("big.bin"), vec![3u8; 512 * 1024]).unwrap();
+    fs::create_dir_all(upper.join("data")).unwrap();
+    fs::write(upper.join("data/config"), "v1").unwrap();
+    // Nothing to encode against yet, so this is a full snapshot.
+    let s1 = engine.commit_with_options(&env_id, incremental).unwrap();
+    let l1 = layers.get(&s1).unwrap();
+    assert_eq!(l1.delta_parent, None);
+
+    fs::write(upper.join("data/config"), "v2").unwrap();
+    let s2 = engine.commit_with_options(&env_id, incremental).unwrap();
+    let l2 = layers.get(&s2).unwrap();
+    assert_eq!(l2.delta_parent.as_deref(), Some(s1.as_str()));
+    assert_eq!(l2.object_refs, [l1.tar_hash.clone(), l2.tar_hash.clone()]);
+    let objects = karapace_store::ObjectStore::new(StoreLayout::new(store.path()));
+    assert!(objects.get(&l2.tar_hash).unwrap().len() < 64 * 1024);
+
+    fs::remove_dir_all(upper.join("data")).unwrap();
+    fs::write(upper.join("notes"), "n").unwrap();
+    let s3 = engine.commit_with_options(&env_id, incremental).unwrap();
+    assert_eq!(layers.get(&s3).unwrap().object_refs.len(), 3);
+
+    engine.restore(&env_id, &s2).unwrap();
+    assert_eq!(fs::read_to_string(upper.join("data/config")).unwrap(), "v2");
+    assert!(!upper.join("notes").exists());
+    assert_eq!(fs::read(upper.join("big.bin")).unwrap().len(), 512 * 1024);
+
+    engine.restore(&env_id, &s3).unwrap();
+    assert!(!upper.join("data").exists());
+    assert_eq!(fs::read_to_string(upper.join("notes")).unwrap(), "n");
+
+    // After restoring s1, the next delta is encoded against s1.
+    engine.restore(&env_id, &s1).unwrap();
+    fs::write(upper.join("data/config"), "v3").unwrap();
+    let s4 = engine.commit_with_options(&env_id, incremental).unwrap();
+    assert_eq!(
+        layers.get(&s4).unwrap().delta_parent.as_deref(),
+        Some(s1.as_str())
+    );
+    // A plain commit is always a full snapshot.
+    let s5 = engine.commit(&env_id).unwrap();
+    assert_eq!(layers.get(&s5).unwrap().delta_parent, None);
+}
+
+#[test]
+fn gc_retention_keeps_delta_chains_restorable() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let upper = engine.store_layout().upper_dir(&env_id);
+
+    for i in 0..3 {
+        fs::write(upper.join(format!("file{i}")), format!("{i}")).unwrap();
+        engine
+            .commit_with_options(&env_id, CommitOptions { incremental: true })
+            .unwrap();
+    }
+
+    let lock = StoreLock::acquire(&engine.store_layout().lock_file()).unwrap();
+    let retention = karapace_store::RetentionPolicy {
+        keep_last: Some(1),
+        ..karapace_store::RetentionPolicy::default()
+    };
+    engine
+        .gc_with_retention(&lock, false, None, retention)
+        .unwrap();
+    drop(lock);
+
+    let snapshots = engine.list_snapshots(&env_id).unwrap();
+    assert_eq!(snapshots.len(), 1);
+    let kept = karapace_store::LayerStore::compute_hash(&snapshots[0]).unwrap();
+    fs::remove_dir_all(&upper).unwrap();
+    engine.restore(&env_id, &kept).unwrap();
+    assert!(upper.join("file0").exists());
+}
+
+#[test]
+fn file_dedup_snapshots_share_file_objects() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let layout = StoreLayout::new(store.path());
+    karapace_store::StoreConfig {
+        file_dedup: true,
+        ..karapace_store::StoreConfig::default()
+    }
+    .save(&layout)
+    .unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let upper = engine.store_layout().upper_dir(&env_id);
+    let layers = karapace_store::LayerStore::new(layout.clone());
+
+    fs::write(upper.join("big.bin"), vec![5u8; 256 * 1024]).unwrap();
+    fs::write(upper.join("config"), "v1").unwrap();
+    let s1 = engine.commit(&env_id).unwrap();
+    fs::write(upper.join("config"), "v2").unwrap();
+    let s2 = engine
+        .commit_with_options(&env_id, CommitOptions { incremental: true })
+        .unwrap();
+
+    let l1 = layers.get(&s1).unwrap();
+    let l2 = layers.get(&s2).unwrap();
+    let big = blake3::hash(&vec![5u8; 256 * 1024]).to_hex().to_string();
+    assert!(l1.file_objects.contains(&big));
+    // The delta keeps its parent's file objects alive.
+    assert!(l2.file_objects.contains(&big));
+    assert!(l2.object_refs.contains(&big));
+    assert_eq!(l2.tar_chain(), [l1.tar_hash.clone(), l2.tar_hash.clone()]);
+    let objects = karapace_store::ObjectStore::new(layout);
+    assert!(objects.get(&l1.tar_hash).unwrap().len() < 64 * 1024);
+
+    let lock = StoreLock::acquire(&engine.store_layout().lock_file()).unwrap();
+    engine.gc(&lock, false).unwrap();
+    drop(lock);
+
+    fs::remove_dir_all(&upper).unwrap();
+    engine.restore(&env_id, &s2).unwrap();
+    assert_eq!(fs::read_to_string(upper.join("config")).unwrap(), "v2");
+    assert_eq!(fs::read(upper.join("big.bin")).unwrap().len(), 256 * 1024);
+    engine.restore(&env_id, &s1).unwrap();
+    assert_eq!(fs::read_to_string(upper.join("config")).unwrap(), "v1");
+}
+
+#[test]
+fn builds_with_the_same_packages_reuse_the_package_layer() {
+    let store = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let layers = karapace_store::LayerStore::new(StoreLayout::new(store.path()));
+    let build = |extra: &str, options: BuildOptions| {
+        let project = tempfile::tempdir().unwrap();
+        let manifest = write_manifest(
+            project.path(),
+            &format!("{}{extra}", mock_manifest(&["git", "curl"])),
+        );
+        engine
+            .build_with_options(&manifest, options, &karapace_runtime::NoProgress)
+            .unwrap()
+    };
+
+    let first = build("", BuildOptions::default());
+    assert!(!first.cached_packages);
+    let first_meta = engine.inspect(&first.identity.env_id).unwrap();
+    assert_eq!(first_meta.dependency_layers.len(), 1);
+    let package_layer = layers.get(&first_meta.dependency_layers[0]).unwrap();
+    assert_eq!(package_layer.kind, karapace_store::LayerKind::Dependency);
+
+    // A different environment with the same image and packages.
+    let second = build("network_isolation = true\n", BuildOptions::default());
+    assert_ne!(second.identity.env_id, first.identity.env_id);
+    assert!(second.cached_packages);
+    let second_meta = engine.inspect(&second.identity.env_id).unwrap();
+    assert_eq!(second_meta.dependency_layers, first_meta.dependency_layers);
+    let upper = engine.store_layout().upper_dir(&second.identity.env_id);
+    assert!(upper.join(".pkg-git").exists());
+    assert!(upper.join(".pkg-curl").exists());
+    // The backend still ran and wrote its own per-environment files.
+    assert_eq!(
+        fs::read_to_string(upper.join(".karapace-mock")).unwrap(),
+        format!("mock-env:{}", second.identity.env_id)
+    );
+
+    let uncached = build(
+        "[runtime.resource_limits]\ncpu_shares = 512\n",
+        BuildOptions {
+            no_cache: true,
+            ..BuildOptions::default()
+        },
+    );
+    assert!(!uncached.cached_packages);
+    let no_packages = {
+        let project = tempfile::tempdir().unwrap();
+        let manifest = write_manifest(project.path(), &mock_manifest(&[]));
+        engine.build(&manifest).unwrap()
+    };
+    assert!(engine
+        .inspect(&no_packages.identity.env_id)
+        .unwrap()
+        .dependency_layers
+        .is_empty());
+}
+
+#[test]
+fn hooks_see_lifecycle_events_in_order() {
+    use karapace_core::EngineEvent;
+    use std::sync::Mutex;
+
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&seen);
+    engine.hooks().subscribe(move |event| {
+        let detail = match event {
+            EngineEvent::PostBuild { env_id, lock, .. } => {
+                assert_eq!(*env_id, lock.env_id);
+                String::new()
+            }
+            EngineEvent::PostCommit { snapshot, .. } => format!(":{}", snapshot.len()),
+            EngineEvent::PostGc { report, .. } => format!(":{}", report.removed_envs),
+            _ => String::new(),
+        };
+        log.lock()
+            .unwrap()
+            .push(format!("{}{detail}", event.name()));
+        Ok(())
+    });
+
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    engine.enter(&env_id).unwrap();
+    engine.commit(&env_id).unwrap();
+    engine.destroy(&env_id).unwrap();
+    let lock = StoreLock::acquire(&engine.store_layout().lock_file()).unwrap();
+    engine.gc(&lock, true).unwrap();
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            "pre_build",
+            "post_build",
+            "pre_enter",
+            "post_enter",
+            "pre_commit",
+            "post_commit:64",
+            "pre_destroy",
+            "post_destroy",
+            "pre_gc",
+            "post_gc:0",
+        ]
+    );
+}
+
+#[test]
+fn failing_pre_hook_vetoes_the_operation() {
+    use karapace_core::{CoreError, EngineEvent};
+
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+
+    engine.hooks().subscribe(|event| match event {
+        EngineEvent::PreDestroy { .. } => Err("environment is pinned".to_owned()),
+        _ => Ok(()),
+    });
+    match engine.destroy(&env_id) {
+        Err(CoreError::Hook { event, message }) => {
+            assert_eq!(event, "pre_destroy");
+            assert_eq!(message, "environment is pinned");
+        }
+        other => panic!("expected a hook veto, got {other:?}"),
+    }
+    assert_eq!(engine.inspect(&env_id).unwrap().state, EnvState::Built);
+}
+
+#[test]
+fn manifest_post_build_hook_runs_in_the_build() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let plain = mock_manifest(&["git"]);
+    let hooked = format!("{plain}[hooks]\npost_build = \"./bootstrap.sh --quiet\"\n");
+    let manifest = write_manifest(project.path(), &hooked);
+
+    let result = engine.build(&manifest).unwrap();
+    assert_eq!(
+        result.lock_file.post_build_hook.as_deref(),
+        Some("./bootstrap.sh --quiet")
+    );
+    let upper = engine.store_layout().upper_dir(&result.identity.env_id);
+    let log = fs::read_to_string(upper.join(karapace_runtime::mock::MOCK_EXEC_LOG)).unwrap();
+    assert_eq!(log, "mock-exec: /bin/sh -c ./bootstrap.sh --quiet\n");
+
+    // The hook is part of the identity.
+    let other = tempfile::tempdir().unwrap();
+    let plain_manifest = write_manifest(other.path(), &plain);
+    let plain_id = engine.build(&plain_manifest).unwrap().identity.env_id;
+    assert_ne!(plain_id, result.identity.env_id);
+    assert!(!engine
+        .store_layout()
+        .upper_dir(&plain_id)
+        .join(karapace_runtime::mock::MOCK_EXEC_LOG)
+        .exists());
+}
+
+#[test]
+fn engines_sharing_a_store_see_each_others_metadata_writes() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let first = Engine::new(store.path());
+    let second = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = first.build(&manifest).unwrap().identity.env_id;
+    let built = first.inspect(&env_id).unwrap().revision;
+
+    second.freeze(&env_id).unwrap();
+    // The first engine validates against the state the second one wrote.
+    assert!(first.freeze(&env_id).is_err());
+    first.archive(&env_id).unwrap();
+
+    let meta = second.inspect(&env_id).unwrap();
+    assert_eq!(meta.state, EnvState::Archived);
+    assert_eq!(meta.revision, built + 2);
+
+    // Rebuilding replaces the entry at the current revision.
+    first.build(&manifest).unwrap();
+    assert_eq!(second.inspect(&env_id).unwrap().revision, built + 3);
+}
+
+#[test]
+fn builds_record_a_verifiable_attestation() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let other_project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id;
+    let (envelope, statement) = engine.verify_attestation(&env_id).unwrap();
+    assert_eq!(statement.env_id(), Some(env_id.as_str()));
+    let lock = &statement
+        .predicate
+        .build_definition
+        .external_parameters
+        .lock;
+    assert_eq!(lock.env_id, env_id.as_str());
+    assert_eq!(lock.resolved_packages[0].name, "git");
+
+    // Every build in the store is signed with the same key.
+    let other = write_manifest(other_project.path(), &mock_manifest(&["curl"]));
+    let other_id = engine.build(&other).unwrap().identity.env_id;
+    let (other_envelope, _) = engine.verify_attestation(&other_id).unwrap();
+    assert_eq!(
+        envelope.keyids().collect::<Vec<_>>(),
+        other_envelope.keyids().collect::<Vec<_>>()
+    );
+
+    // An attestation of another build does not vouch for this one.
+    let meta_store = karapace_store::MetadataStore::new(StoreLayout::new(store.path()));
+    let mut meta = meta_store.get(&env_id).unwrap();
+    meta.attestation = meta_store.get(&other_id).unwrap().attestation;
+    meta_store.put(&meta).unwrap();
+    assert!(matches!(
+        engine.verify_attestation(&env_id),
+        Err(karapace_core::CoreError::Attestation(_))
+    ));
+}
+
+/// Put a fake base image for `name` in the store's image cache.
+fn cache_fake_image(store: &Path, name: &str, digest: &str) {
+    let cache = karapace_runtime::image::ImageCache::new(store);
+    let key = karapace_runtime::image::resolve_image(name)
+        .unwrap()
+        .cache_key;
+    let rootfs = cache.rootfs_path(&key);
+    fs::create_dir_all(rootfs.join("etc")).unwrap();
+    fs::write(rootfs.join("etc/os-release"), "ID=test").unwrap();
+    fs::write(rootfs.parent().unwrap().join("rootfs.blake3"), digest).unwrap();
+}
+
+#[test]
+fn cached_images_in_use_are_kept_unless_forced() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&[]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id;
+
+    cache_fake_image(store.path(), "rolling", &"a".repeat(64));
+    cache_fake_image(store.path(), "debian/bookworm", &"b".repeat(64));
+    let images = engine.list_images().unwrap();
+    assert_eq!(images.len(), 2);
+    let rolling = images
+        .iter()
+        .find(|i| i.image.cache_key == "opensuse-tumbleweed")
+        .unwrap();
+    assert_eq!(rolling.used_by, vec![env_id.to_string()]);
+
+    assert!(matches!(
+        engine.remove_image("rolling", false),
+        Err(karapace_core::CoreError::ImageInUse { .. })
+    ));
+    // Unused images go by name or cache key.
+    engine.remove_image("debian-bookworm", false).unwrap();
+    engine.remove_image("rolling", true).unwrap();
+    assert!(engine.list_images().unwrap().is_empty());
+}
+
+#[test]
+fn prune_removes_inactive_workspaces_unused_images_and_old_snapshots() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&[]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+
+    let upper = engine.store_layout().upper_dir(&env_id);
+    for n in 0..3 {
+        fs::create_dir_all(&upper).unwrap();
+        fs::write(upper.join("n.txt"), n.to_string()).unwrap();
+        engine.commit(&env_id).unwrap();
+    }
+    engine.use_workspace(&env_id, "scratch", true).unwrap();
+    engine.use_workspace(&env_id, "default", false).unwrap();
+    cache_fake_image(store.path(), "rolling", &"a".repeat(64));
+    cache_fake_image(store.path(), "debian/bookworm", &"b".repeat(64));
+
+    let lock = StoreLock::acquire(&engine.store_layout().lock_file()).unwrap();
+    let options = PruneOptions {
+        volumes: true,
+        images: true,
+        snapshots_keep: Some(1),
+        dry_run: true,
+    };
+    let planned = prune(&engine, &lock, &options).unwrap();
+    assert_eq!(planned.workspaces.len(), 1);
+    assert_eq!(planned.images.len(), 1);
+    assert_eq!(planned.gc.evicted_snapshots.len(), 2);
+    assert_eq!(engine.list_workspaces(&env_id).unwrap().len(), 2);
+    assert_eq!(engine.list_images().unwrap().len(), 2);
+
+    let report = prune(
+        &engine,
+        &lock,
+        &PruneOptions {
+            dry_run: false,
+            ..options
+        },
+    )
+    .unwrap();
+    assert_eq!(report.workspaces[0].name, "scratch");
+    assert_eq!(report.images[0].cache_key, "debian-bookworm");
+    assert!(report.reclaimed_bytes() > 0);
+    assert_eq!(engine.list_workspaces(&env_id).unwrap().len(), 1);
+    assert_eq!(engine.list_images().unwrap().len(), 1);
+    assert_eq!(engine.list_snapshots(&env_id).unwrap().len(), 1);
+    assert_eq!(fs::read_to_string(upper.join("n.txt")).unwrap(), "2");
+}
+
+#[test]
+fn locked_build_refuses_a_cached_image_the_lock_does_not_pin() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let digest = engine.build(&manifest).unwrap().lock_file.base_image_digest;
+    let locked = BuildOptions {
+        locked: true,
+        ..BuildOptions::default()
+    };
+
+    cache_fake_image(store.path(), "rolling", &digest);
+    engine
+        .build_with_options(&manifest, locked, &karapace_runtime::NoProgress)
+        .unwrap();
+
+    cache_fake_image(store.path(), "rolling", &"f".repeat(64));
+    let Err(err) = engine.build_with_options(&manifest, locked, &karapace_runtime::NoProgress)
+    else {
+        panic!("locked build accepted an image the lock does not pin");
+    };
+    assert!(err.to_string().contains("the lock pins"), "{err}");
+}
+
+#[test]
+fn language_toolchains_install_after_packages_and_lock_their_lockfiles() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    fs::write(project.path().join("requirements.txt"), "flask==3.0.3\n").unwrap();
+    let content = format!(
+        "{}[python]\nversion = \"3.12\"\npackages = [\"requests==2.32.3\"]\nlockfile = \"requirements.txt\"\n",
+        mock_manifest(&["python3.12"])
+    );
+    let manifest = write_manifest(project.path(), &content);
+
+    let result = engine.build(&manifest).unwrap();
+    let toolchain = &result.lock_file.toolchains[0];
+    assert_eq!(toolchain.packages, ["requests==2.32.3"]);
+    assert!(toolchain.lockfile_hash.is_some());
+    let upper = engine.store_layout().upper_dir(&result.identity.env_id);
+    assert_eq!(
+        fs::read_to_string(upper.join("opt/karapace/python/project/requirements.txt")).unwrap(),
+        "flask==3.0.3\n"
+    );
+    let log = fs::read_to_string(upper.join(karapace_runtime::mock::MOCK_EXEC_LOG)).unwrap();
+    assert!(
+        log.contains("python3.12 -m venv /opt/karapace/python"),
+        "{log}"
+    );
+
+    // The lock records the toolchain, and the lockfile's content is part
+    // of the identity.
+    let lock =
+        karapace_schema::LockFile::read_from_file(project.path().join("karapace.lock")).unwrap();
+    assert_eq!(lock.toolchains, result.lock_file.toolchains);
+    engine.check_lock(&manifest, false, false).unwrap();
+    fs::write(project.path().join("requirements.txt"), "flask==3.1.0\n").unwrap();
+    assert!(engine.check_lock(&manifest, false, false).is_err());
+    let rebuilt = engine.build(&manifest).unwrap();
+    assert_ne!(rebuilt.identity.env_id, result.identity.env_id);
+
+    let offline_project = tempfile::tempdir().unwrap();
+    let offline_manifest = write_manifest(
+        offline_project.path(),
+        &format!(
+            "{}[node]\npackages = [\"typescript@5.4.5\"]\n",
+            mock_manifest(&[])
+        ),
+    );
+    let Err(err) = engine.build_with_options(
+        &offline_manifest,
+        BuildOptions {
+            offline: true,
+            ..BuildOptions::default()
+        },
+        &karapace_runtime::NoProgress,
+    ) else {
+        panic!("offline build with language packages succeeded");
+    };
+    assert!(err.to_string().contains("language packages"), "{err}");
+}
+
+#[test]
+fn build_steps_run_in_order_and_are_cached_one_layer_each() {
+    let store = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let build = |steps: &str| {
+        let project = tempfile::tempdir().unwrap();
+        let manifest = write_manifest(
+            project.path(),
+            &format!("{}[build]\nsteps = [{steps}]\n", mock_manifest(&["git"])),
+        );
+        let result = engine.build(&manifest).unwrap();
+        let upper = engine.store_layout().upper_dir(&result.identity.env_id);
+        let log = fs::read_to_string(upper.join(karapace_runtime::mock::MOCK_EXEC_LOG)).unwrap();
+        let layers = engine
+            .inspect(&result.identity.env_id)
+            .unwrap()
+            .dependency_layers;
+        (result, log, layers)
+    };
+
+    let (first, log, first_layers) = build("\"./setup.sh\", \"make deps\"");
+    let setup = log.find("-c ./setup.sh").expect(&log);
+    let make = log.find("-c make deps").expect(&log);
+    assert!(setup < make, "{log}");
+    assert_eq!(first.lock_file.build_steps, ["./setup.sh", "make deps"]);
+    // The package layer, then one layer per step.
+    assert_eq!(first_layers.len(), 3);
+
+    // Changing the last step keeps the earlier layers and reruns only it.
+    let (second, log, second_layers) = build("\"./setup.sh\", \"make all\"");
+    assert_ne!(second.identity.env_id, first.identity.env_id);
+    assert!(second.cached_packages);
+    assert_eq!(log.matches("-c ./setup.sh").count(), 1, "{log}");
+    assert!(log.contains("-c make all"), "{log}");
+    assert!(!log.contains("-c make deps"), "{log}");
+    assert_eq!(second_layers[..2], first_layers[..2]);
+    assert_ne!(second_layers[2], first_layers[2]);
+
+    // Changing the first step reruns both.
+    let (_, _, third_layers) = build("\"./bootstrap.sh\", \"make all\"");
+    assert_eq!(third_layers[0], first_layers[0]);
+    assert_ne!(third_layers[1..], second_layers[1..]);
+}
+
+#[test]
+fn manifests_extending_a_shared_base_add_its_packages() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    fs::write(
+        project.path().join("base.karapace.toml"),
+        mock_manifest(&["git", "curl"]),
+    )
+    .unwrap();
+    let manifest = write_manifest(
+        project.path(),
+        "extends = \"base.karapace.toml\"\n[system]\npackages = [\"clang\"]\n",
+    );
+
+    let result = engine.build(&manifest).unwrap();
+    let names: Vec<&str> = result
+        .lock_file
+        .resolved_packages
+        .iter()
+        .map(|p| p.name.as_str())
+        .collect();
+    assert_eq!(names, ["clang", "curl", "git"]);
+
+    // Editing the shared base is drift for every manifest extending it.
+    engine.check_lock(&manifest, false, false).unwrap();
+    fs::write(
+        project.path().join("base.karapace.toml"),
+        mock_manifest(&["git", "curl", "make"]),
+    )
+    .unwrap();
+    assert!(engine.check_lock(&manifest, false, false).is_err());
+}
+
+#[test]
+fn mount_variables_are_expanded_and_recorded_in_the_lock() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(
+        project.path(),
+        &format!(
+            "{}[mounts]\nworkspace = \"${{project_dir}}:/workspace\"\n",
+            mock_manifest(&[])
+        ),
+    );
+
+    let result = engine.build(&manifest).unwrap();
+    let project_dir = fs::canonicalize(project.path()).unwrap();
+    let project_dir = project_dir.to_string_lossy();
+    assert_eq!(result.lock_file.mounts[0].host_path, project_dir);
+    let lock =
+        karapace_schema::LockFile::read_from_file(project.path().join("karapace.lock")).unwrap();
+    assert_eq!(lock.variables["project_dir"], project_dir);
+    engine.check_lock(&manifest, false, false).unwrap();
+}
+
+#[test]
+fn drift_bundles_carry_overlay_changes_to_another_store() {
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+
+    let source_store = tempfile::tempdir().unwrap();
+    let source = Engine::new(source_store.path());
+    let source_id = source.build(&manifest).unwrap().identity.env_id.to_string();
+    let upper = source.store_layout().upper_dir(&source_id);
+    fs::create_dir_all(upper.join("etc")).unwrap();
+    fs::write(upper.join("etc/app.conf"), "level = debug\n").unwrap();
+    fs::write(upper.join(".wh.obsolete"), "").unwrap();
+    let bundle = source.export_drift(&source_id).unwrap();
+
+    let target_store = tempfile::tempdir().unwrap();
+    let target = Engine::new(target_store.path());
+    let target_id = target.build(&manifest).unwrap().identity.env_id.to_string();
+    let target_upper = target.store_layout().upper_dir(&target_id);
+    fs::create_dir_all(target_upper.join("etc")).unwrap();
+    fs::write(target_upper.join("etc/app.conf"), "level = info\n").unwrap();
+    fs::write(target_upper.join("local.txt"), "kept").unwrap();
+    fs::write(target_upper.join("obsolete"), "hidden").unwrap();
+
+    let info = target.apply_drift(&target_id, &bundle).unwrap();
+    assert_eq!(info.env_id, source_id);
+    assert_eq!(
+        fs::read_to_string(target_upper.join("etc/app.conf")).unwrap(),
+        "level = debug\n"
+    );
+    assert_eq!(
+        fs::read_to_string(target_upper.join("local.txt")).unwrap(),
+        "kept"
+    );
+    assert!(target_upper.join(".wh.obsolete").exists());
+    assert!(!target_upper.join("obsolete").exists());
+
+    assert!(matches!(
+        target.apply_drift(&target_id, b"not a bundle"),
+        Err(karapace_core::CoreError::DriftBundle(_))
+    ));
+}
+
+#[test]
+fn build_stops_when_the_progress_sink_cancels() {
+    struct Cancelled;
+    impl karapace_runtime::ProgressSink for Cancelled {
+        fn cancelled(&self) -> bool {
+            true
+        }
+    }
+
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(project.path(), &mock_manifest(&[]));
+    let engine = Engine::new(store.path());
+
+    let result = engine.build_with_options(&manifest, BuildOptions::default(), &Cancelled);
+    assert!(matches!(result, Err(karapace_core::CoreError::Cancelled)));
+    assert!(engine.list().unwrap().is_empty());
+}
+
+#[test]
+fn audit_checks_the_attested_packages() {
+    use karapace_core::audit::{OsvRecord, VulnFeed};
+    use karapace_core::{CoreError, Severity};
+    use karapace_schema::ResolvedPackage;
+
+    struct Feed;
+    impl VulnFeed for Feed {
+        fn query(
+            &self,
+            ecosystem: &str,
+            packages: &[ResolvedPackage],
+        ) -> Result<Vec<Vec<String>>, CoreError> {
+            assert_eq!(ecosystem, "openSUSE:Tumbleweed");
+            Ok(packages
+                .iter()
+                .map(|p| match p.name.as_str() {
+                    "git" => vec!["CVE-2024-32002".to_owned()],
+                    _ => Vec::new(),
+                })
+                .collect())
+        }
+
+        fn record(&self, id: &str) -> Result<OsvRecord, CoreError> {
+            Ok(serde_json::from_value(serde_json::json!({
+                "id": id,
+                "severity": [{"type": "CVSS_V3", "score": "CVSS:3.1/AV:N/AC:L/PR:N/UI:R/S:U/C:H/I:H/A:H"}],
+            }))
+            .unwrap())
+        }
+    }
+
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git", "curl"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id;
+
+    let report = engine.audit(&env_id, &Feed).unwrap();
+    assert_eq!(report.packages, 2);
+    assert_eq!(report.findings.len(), 1);
+    assert_eq!(report.findings[0].package, "git");
+    assert_eq!(report.findings[0].severity, Severity::High);
+    assert!(!report.any_at_least(Severity::Critical));
+}
+
+#[test]
+fn idle_environments_expire_to_archived_then_destroyed() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let layout = StoreLayout::new(store.path());
+    karapace_store::StoreConfig {
+        expires_after: Some("1d".to_owned()),
+        ..karapace_store::StoreConfig::default()
+    }
+    .save(&layout)
+    .unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let meta = engine.inspect(&env_id).unwrap();
+    assert_eq!(meta.expires_after_secs, Some(86_400));
+    assert_eq!(meta.last_entered_at, None);
+
+    engine.exec(&env_id, &["true".to_owned()]).unwrap();
+    assert!(engine.inspect(&env_id).unwrap().last_entered_at.is_some());
+
+    let meta_store = karapace_store::MetadataStore::new(layout.clone());
+    let backdate = |idle_days: i64, updated_days: i64| {
+        let now = chrono::Utc::now();
+        let mut meta = meta_store.get(&env_id).unwrap();
+        meta.last_entered_at = Some((now - chrono::Duration::days(idle_days)).to_rfc3339());
+        meta.updated_at = (now - chrono::Duration::days(updated_days)).to_rfc3339();
+        meta_store.overwrite(&meta).unwrap();
+    };
+
+    let lock = StoreLock::acquire(&layout.lock_file()).unwrap();
+    assert!(karapace_core::expire(&engine, &lock, false)
+        .unwrap()
+        .is_empty());
+
+    backdate(2, 2);
+    let dry = karapace_core::expire(&engine, &lock, true).unwrap();
+    assert_eq!(dry.archived, vec![env_id.clone()]);
+    assert_eq!(engine.inspect(&env_id).unwrap().state, EnvState::Built);
+    let report = karapace_core::expire(&engine, &lock, false).unwrap();
+    assert_eq!(report.archived, vec![env_id.clone()]);
+    assert_eq!(engine.inspect(&env_id).unwrap().state, EnvState::Archived);
+
+    // Just archived: not destroyed before another full day passes.
+    assert!(karapace_core::expire(&engine, &lock, false)
+        .unwrap()
+        .is_empty());
+    backdate(3, 1);
+    let report = karapace_core::expire(&engine, &lock, false).unwrap();
+    assert_eq!(report.destroyed, vec![env_id.clone()]);
+    assert!(engine.inspect(&env_id).is_err());
+}
+
+#[test]
+fn sessions_and_builds_are_recorded_in_metadata() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let built = engine.inspect(&env_id).unwrap();
+    assert!(built.last_built_at.is_some());
+    assert_eq!(built.enter_count, 0);
+    assert_eq!(built.last_entered_at, None);
+
+    engine.exec(&env_id, &["true".to_owned()]).unwrap();
+    engine.enter(&env_id).unwrap();
+    let used = engine.inspect(&env_id).unwrap();
+    assert_eq!(used.enter_count, 2);
+    assert!(used.last_entered_at.is_some());
+
+    // A rebuild refreshes the build time but keeps the usage history.
+    engine.rebuild(&manifest).unwrap();
+    let rebuilt = engine.inspect(&env_id).unwrap();
+    assert_eq!(rebuilt.enter_count, 2);
+    assert_eq!(rebuilt.last_entered_at, used.last_entered_at);
+    assert_eq!(rebuilt.created_at, built.created_at);
+    assert!(rebuilt.last_built_at >= built.last_built_at);
+}
+
+#[test]
+fn labels_come_from_the_manifest_and_survive_rebuilds() {
+    let store = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let engine = Engine::new(store.path());
+    let manifest = write_manifest(
+        project.path(),
+        &format!("{}[labels]\nteam = \"infra\"\n", mock_manifest(&["git"])),
+    );
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    let meta = engine.inspect(&env_id).unwrap();
+    assert!(meta.matches_label("team=infra"));
+    assert!(meta.matches_label("team"));
+    assert!(!meta.matches_label("team=web"));
+
+    let set = BTreeMap::from([("owner".to_owned(), "alice".to_owned())]);
+    let labels = engine.set_labels(&env_id, &set, &[]).unwrap();
+    assert_eq!(labels.len(), 2);
+    let bad = BTreeMap::from([("owner".to_owned(), "two words".to_owned())]);
+    assert!(engine.set_labels(&env_id, &bad, &[]).is_err());
+
+    engine.rebuild(&manifest).unwrap();
+    let labels = engine.inspect(&env_id).unwrap().labels;
+    assert_eq!(labels.get("owner").map(String::as_str), Some("alice"));
+    assert_eq!(labels.get("team").map(String::as_str), Some("infra"));
+
+    let labels = engine
+        .set_labels(&env_id, &BTreeMap::new(), &["team".to_owned()])
+        .unwrap();
+    assert_eq!(labels.keys().collect::<Vec<_>>(), ["owner"]);
+}
+
+#[test]
+fn repair_rebuilds_plain_layers_and_quarantines_damaged_objects() {
+    let store = tempfile::tempdir().unwrap();
+    let layout = StoreLayout::new(store.path());
+    let engine = Engine::new(store.path());
+    let (_projects, env_ids) = build_envs(&engine, &["git"]);
+    let meta = engine.inspect(&env_ids[0]).unwrap();
+    let layer_store = karapace_store::LayerStore::new(layout.clone());
+    let base_hash = meta.base_layer.to_string();
+    let base = layer_store.get(&base_hash).unwrap();
+    let lock = StoreLock::acquire(&layout.lock_file()).unwrap();
+
+    fs::remove_file(layout.layers_dir().join(&base_hash)).unwrap();
+    let report = karapace_core::repair(&engine, &lock, None).unwrap();
+    assert!(report.quarantined.is_empty());
+    assert_eq!(report.recovered.len(), 1);
+    assert_eq!(report.recovered[0].hash, base_hash);
+    assert!(report.lost.is_empty());
+    assert_eq!(layer_store.get(&base_hash).unwrap(), base);
+
+    fs::write(layout.objects_dir().join(&base.tar_hash), b"bit rot").unwrap();
+    let report = karapace_core::repair(&engine, &lock, None).unwrap();
+    assert_eq!(report.quarantined.len(), 1);
+    assert_eq!(report.quarantined[0].hash, base.tar_hash);
+    assert!(layout
+        .quarantine_dir()
+        .join("objects")
+        .join(&base.tar_hash)
+        .exists());
+    assert_eq!(report.lost.len(), 1);
+    assert_eq!(report.lost[0].hash, base.tar_hash);
+    assert!(karapace_store::verify_store_integrity(&layout)
+        .unwrap()
+        .failed
+        .is_empty());
+}
+
+#[test]
+fn gc_sweep_spares_a_build_that_ran_after_the_mark() {
+    let store = tempfile::tempdir().unwrap();
+    let layout = StoreLayout::new(store.path());
+    let engine = Engine::new(store.path());
+    let project = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(project.path(), &mock_manifest(&["git"]));
+    let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    engine.destroy(&env_id).unwrap();
+
+    let plan = engine
+        .plan_gc(karapace_store::RetentionPolicy::default())
+        .unwrap();
+    assert!(!plan.report().orphaned_objects.is_empty());
+    // Reuses the objects and layers the mark found orphaned.
+    let rebuilt = engine.build(&manifest).unwrap().identity.env_id.to_string();
+    assert_eq!(rebuilt, env_id);
+
+    let lock = StoreLock::acquire(&layout.lock_file()).unwrap();
+    let fresh = engine.gc(&lock, true).unwrap();
+    let report = engine.sweep_gc(&lock, plan, false, None).unwrap();
+    assert_eq!(report.orphaned_objects, fresh.orphaned_objects);
+    assert!(report.orphaned_layers.is_empty());
+    let integrity = karapace_store::verify_env_integrity(&layout, &env_id).unwrap();
+    assert!(integrity.failed.is_empty(), "{:?}", integrity.failed);
+}
@@ -1,14 +1,22 @@
-use karapace_core::StoreLock;
+use crate::jobs::{Job, JobSink, JobState, Jobs};
+use crate::polkit::{check_authorization, PolkitConfig, Privileged};
+use karapace_core::{BuildOptions, StoreLock};
+use karapace_runtime::{BuildPhase, ProgressSink};
 use karapace_store::StoreLayout;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use tracing::{error, info, warn};
 use zbus::interface;
+use zbus::message::Header;
+use zbus::names::BusName;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath};
 
 pub const DBUS_INTERFACE: &str = "org.karapace.Manager1";
 pub const DBUS_PATH: &str = "/org/karapace/Manager1";
 pub const API_VERSION: u32 = 1;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EnvInfo {
     pub env_id: String,
     pub short_id: String,
@@ -49,13 +57,115 @@ fn send_notification(summary: &str, body: &str) {
     }
 }
 
+/// Emit the manager signal `member` with `body`, when connected.
+pub(crate) fn emit<B>(connection: Option<&zbus::blocking::Connection>, member: &str, body: &B)
+where
+    B: Serialize + zbus::zvariant::DynamicType,
+{
+    let Some(connection) = connection else {
+        return;
+    };
+    if let Err(e) =
+        connection.emit_signal(None::<BusName<'_>>, DBUS_PATH, DBUS_INTERFACE, member, body)
+    {
+        tracing::debug!("{member} signal failed (non-fatal): {e}");
+    }
+}
+
+/// Broadcasts build progress as `BuildProgress` signals.
+struct SignalProgress<'a> {
+    connection: Option<zbus::blocking::Connection>,
+    manifest_path: &'a str,
+    phase: Mutex<&'static str>,
+}
+
+impl SignalProgress<'_> {
+    fn emit(&self, phase: &str, message: &str) {
+        info!("build {}: {phase}: {message}", self.manifest_path);
+        let Some(connection) = &self.connection else {
+            return;
+        };
+        if let Err(e) = connection.emit_signal(
+            None::<BusName<'_>>,
+            DBUS_PATH,
+            DBUS_INTERFACE,
+            "BuildProgress",
+            &(self.manifest_path, phase, message),
+        ) {
+            tracing::debug!("BuildProgress signal failed (non-fatal): {e}");
+        }
+    }
+}
+
+impl ProgressSink for SignalProgress<'_> {
+    fn phase(&self, phase: BuildPhase) {
+        *self.phase.lock().unwrap_or_else(PoisonError::into_inner) = phase.name();
+        self.emit(phase.name(), phase.label());
+    }
+
+    fn message(&self, message: &str) {
+        let phase = *self.phase.lock().unwrap_or_else(PoisonError::into_inner);
+        self.emit(phase, message);
+    }
+}
+
 pub struct KarapaceManager {
     store_root: String,
+    /// Connection the manager is served on, for emitting signals from
+    /// blocking engine callbacks. Unset when called directly, as in tests.
+    connection: OnceLock<zbus::Connection>,
+    jobs: Arc<Jobs>,
+    /// Action IDs privileged calls are checked against; `None` to allow
+    /// every caller.
+    polkit: Option<PolkitConfig>,
 }
 
 impl KarapaceManager {
     pub fn new(store_root: String) -> Self {
-        Self { store_root }
+        Self {
+            store_root,
+            connection: OnceLock::new(),
+            jobs: Arc::new(Jobs::default()),
+            polkit: None,
+        }
+    }
+
+    /// Check privileged calls with polkit, over the connection the manager
+    /// is attached to, which must be the system bus.
+    #[must_use]
+    pub fn with_polkit(mut self, config: PolkitConfig) -> Self {
+        self.polkit = config.enabled.then_some(config);
+        self
+    }
+
+    /// Emit signals on `connection` from now on.
+    pub fn attach(&self, connection: zbus::Connection) {
+        let _ = self.connection.set(connection);
+    }
+
+    fn progress_sink<'a>(&self, manifest_path: &'a str) -> SignalProgress<'a> {
+        SignalProgress {
+            connection: self.connection.get().cloned().map(Into::into),
+            manifest_path,
+            phase: Mutex::new(""),
+        }
+    }
+
+    fn build(
+        &self,
+        manifest_path: &str,
+    ) -> Result<karapace_core::BuildResult, karapace_core::CoreError> {
+        self.engine().build_with_options(
+            std::path::Path::new(manifest_path),
+            BuildOptions::default(),
+            &self.progress_sink(manifest_path),
+        )
+    }
+
+    fn job(&self, job: &ObjectPath<'_>) -> Result<Arc<Job>, zbus::fdo::Error> {
+        self.jobs
+            .get(job)
+            .ok_or_else(|| zbus::fdo::Error::UnknownObject(format!("no job {}", job.as_str())))
     }
 
     fn engine(&self) -> karapace_core::Engine {
@@ -64,26 +174,49 @@ impl KarapaceManager {
 
     fn acquire_lock(&self) -> Result<StoreLock, zbus::fdo::Error> {
         let layout = StoreLayout::new(&self.store_root);
-        StoreLock::acquire(&layout.lock_file()).map_err(|e| {
+        StoreLock::acquire_for(&layout.lock_file(), "dbus").map_err(|e| {
             error!("store lock acquisition failed: {e}");
             to_fdo(format!("store lock: {e}"))
         })
     }
 
+    /// Fail with `AccessDenied` unless polkit is off or lets the sender of
+    /// `header` perform `call`.
+    async fn authorize(&self, header: &Header<'_>, call: Privileged) -> zbus::fdo::Result<()> {
+        let Some(polkit) = &self.polkit else {
+            return Ok(());
+        };
+        let action = polkit.action(call);
+        let denied = |reason: String| {
+            warn!("{action} denied: {reason}");
+            zbus::fdo::Error::AccessDenied(format!("not authorized for {action}: {reason}"))
+        };
+        let Some(sender) = header.sender() else {
+            return Err(denied("caller has no bus name".to_owned()));
+        };
+        let Some(connection) = self.connection.get() else {
+            return Err(denied("not connected to the system bus".to_owned()));
+        };
+        match check_authorization(connection, sender.as_str(), action).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(denied(format!("polkit refused {sender}"))),
+            Err(e) => Err(denied(format!("polkit: {e}"))),
+        }
+    }
+
     fn resolve_env(&self, id_or_name: &str) -> Result<String, zbus::fdo::Error> {
         let engine = self.engine();
         if id_or_name.len() == 64 {
             return Ok(id_or_name.to_owned());
         }
-        let envs = engine.list().map_err(to_fdo)?;
-        for e in &envs {
-            if *e.env_id == *id_or_name
-                || *e.short_id == *id_or_name
-                || e.name.as_deref() == Some(id_or_name)
-            {
-                return Ok(e.env_id.to_string());
-            }
+        if let Some(e) = engine.find(id_or_name).map_err(to_fdo)? {
+            return Ok(e.env_id.to_string());
         }
+        if let Some(e) = engine.find_by_alias(id_or_name).map_err(to_fdo)? {
+            warn!("'{id_or_name}' is a former name of {}", e.short_id);
+            return Ok(e.env_id.to_string());
+        }
+        let envs = engine.list().map_err(to_fdo)?;
         for e in &envs {
             if e.env_id.starts_with(id_or_name) || e.short_id.starts_with(id_or_name) {
                 return Ok(e.env_id.to_string());
@@ -106,6 +239,17 @@ impl KarapaceManager {
         &self.store_root
     }
 
+    /// Emitted while `BuildEnvironment` or `BuildNamedEnvironment` runs.
+    /// `phase` is one of `resolve`, `fetch_image`, `unpack`,
+    /// `install_packages` and `pack_layer`.
+    #[zbus(signal)]
+    async fn build_progress(
+        emitter: &SignalEmitter<'_>,
+        manifest_path: &str,
+        phase: &str,
+        message: &str,
+    ) -> zbus::Result<()>;
+
     async fn list_environments(&self) -> Result<String, zbus::fdo::Error> {
         info!("D-Bus: ListEnvironments");
         let envs = self.engine().list().map_err(|e| {
@@ -150,10 +294,15 @@ impl KarapaceManager {
         Ok(meta.env_id.to_string())
     }
 
-    async fn build_environment(&self, manifest_path: String) -> Result<String, zbus::fdo::Error> {
+    async fn build_environment(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        manifest_path: String,
+    ) -> Result<String, zbus::fdo::Error> {
         info!("D-Bus: BuildEnvironment {manifest_path}");
+        self.authorize(&header, Privileged::Build).await?;
         let _lock = self.acquire_lock()?;
-        let result = match self.engine().build(std::path::Path::new(&manifest_path)) {
+        let result = match self.build(&manifest_path) {
             Ok(r) => {
                 send_notification(
                     "Build Complete",
@@ -178,13 +327,14 @@ impl KarapaceManager {
 
     async fn build_named_environment(
         &self,
+        #[zbus(header)] header: Header<'_>,
         manifest_path: String,
         name: String,
     ) -> Result<String, zbus::fdo::Error> {
         info!("D-Bus: BuildNamedEnvironment {manifest_path} name={name}");
+        self.authorize(&header, Privileged::Build).await?;
         let _lock = self.acquire_lock()?;
-        let engine = self.engine();
-        let result = match engine.build(std::path::Path::new(&manifest_path)) {
+        let result = match self.build(&manifest_path) {
             Ok(r) => {
                 send_notification(
                     "Build Complete",
@@ -198,7 +348,7 @@ impl KarapaceManager {
                 return Err(to_fdo(e));
             }
         };
-        engine
+        self.engine()
             .set_name(&result.identity.env_id, Some(name.clone()))
             .map_err(|e| {
                 error!("BuildNamedEnvironment set_name failed: {e}");
@@ -213,8 +363,136 @@ impl KarapaceManager {
         .map_err(to_fdo)
     }
 
-    async fn destroy_environment(&self, id_or_name: String) -> Result<String, zbus::fdo::Error> {
+    /// An environment appeared in the store. `name` is empty when it has
+    /// none.
+    #[zbus(signal)]
+    async fn environment_added(
+        emitter: &SignalEmitter<'_>,
+        env_id: &str,
+        short_id: &str,
+        name: &str,
+        state: &str,
+    ) -> zbus::Result<()>;
+
+    /// An environment was destroyed or otherwise left the store.
+    #[zbus(signal)]
+    async fn environment_removed(emitter: &SignalEmitter<'_>, env_id: &str) -> zbus::Result<()>;
+
+    /// An environment changed state, e.g. from `built` to `running`.
+    #[zbus(signal)]
+    async fn state_changed(
+        emitter: &SignalEmitter<'_>,
+        env_id: &str,
+        old_state: &str,
+        new_state: &str,
+    ) -> zbus::Result<()>;
+
+    /// Emitted while a `BuildAsync` job runs, with the same phases as
+    /// `BuildProgress`.
+    #[zbus(signal)]
+    async fn progress(
+        emitter: &SignalEmitter<'_>,
+        job: ObjectPath<'_>,
+        phase: &str,
+        message: &str,
+    ) -> zbus::Result<()>;
+
+    /// A job finished; `result` is the environment as JSON, like the reply
+    /// of `BuildEnvironment`.
+    #[zbus(signal)]
+    async fn completed(
+        emitter: &SignalEmitter<'_>,
+        job: ObjectPath<'_>,
+        result: &str,
+    ) -> zbus::Result<()>;
+
+    /// A job failed or was cancelled; `error` is `"cancelled"` for the latter.
+    #[zbus(signal)]
+    async fn failed(
+        emitter: &SignalEmitter<'_>,
+        job: ObjectPath<'_>,
+        error: &str,
+    ) -> zbus::Result<()>;
+
+    /// Build `manifest_path` on a background thread, naming the result
+    /// `name` unless it is empty, and return the job's path at once.
+    async fn build_async(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        manifest_path: String,
+        name: String,
+    ) -> Result<OwnedObjectPath, zbus::fdo::Error> {
+        info!("D-Bus: BuildAsync {manifest_path} name={name}");
+        self.authorize(&header, Privileged::Build).await?;
+        let (path, job) = self.jobs.start();
+        let sink = JobSink {
+            connection: self.connection.get().cloned().map(Into::into),
+            path: path.clone(),
+            job: Arc::clone(&job),
+            phase: Mutex::new(""),
+        };
+        let store_root = self.store_root.clone();
+        std::thread::spawn(move || {
+            let outcome = run_build_job(&store_root, &manifest_path, &name, &sink);
+            let connection = sink.connection.as_ref();
+            let job_path = sink.path.as_ref();
+            match outcome {
+                Ok(info) => {
+                    send_notification(
+                        "Build Complete",
+                        &format!("Environment {} built", info.short_id),
+                    );
+                    let json = serde_json::to_string(&info).unwrap_or_default();
+                    job.set_state(JobState::Completed { result: info });
+                    emit(connection, "Completed", &(job_path, json.as_str()));
+                }
+                Err(karapace_core::CoreError::Cancelled) => {
+                    info!("job {} cancelled", job_path.as_str());
+                    job.set_state(JobState::Cancelled);
+                    emit(connection, "Failed", &(job_path, "cancelled"));
+                }
+                Err(e) => {
+                    send_notification("Build Failed", &e.to_string());
+                    error!("BuildAsync {manifest_path} failed: {e}");
+                    let message = e.to_string();
+                    job.set_state(JobState::Failed {
+                        error: message.clone(),
+                    });
+                    emit(connection, "Failed", &(job_path, message.as_str()));
+                }
+            }
+        });
+        Ok(path)
+    }
+
+    /// Ask a running job to stop. The build stops at its next phase and the
+    /// job ends with `Failed(job, "cancelled")`.
+    async fn cancel_job(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        job: OwnedObjectPath,
+    ) -> Result<(), zbus::fdo::Error> {
+        info!("D-Bus: CancelJob {}", job.as_str());
+        self.authorize(&header, Privileged::Cancel).await?;
+        if self.job(&job)?.cancel() {
+            Ok(())
+        } else {
+            Err(to_fdo(format!("job {} already finished", job.as_str())))
+        }
+    }
+
+    /// A job's state as JSON, for clients that missed its signals.
+    async fn get_job_status(&self, job: OwnedObjectPath) -> Result<String, zbus::fdo::Error> {
+        serde_json::to_string(&self.job(&job)?.state()).map_err(to_fdo)
+    }
+
+    async fn destroy_environment(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        id_or_name: String,
+    ) -> Result<String, zbus::fdo::Error> {
         info!("D-Bus: DestroyEnvironment {id_or_name}");
+        self.authorize(&header, Privileged::Destroy).await?;
         let resolved = self.resolve_env(&id_or_name)?;
         let _lock = self.acquire_lock()?;
         self.engine().destroy(&resolved).map_err(|e| {
@@ -227,8 +505,13 @@ impl KarapaceManager {
         .map_err(to_fdo)
     }
 
-    async fn run_environment(&self, id_or_name: String) -> Result<String, zbus::fdo::Error> {
+    async fn run_environment(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        id_or_name: String,
+    ) -> Result<String, zbus::fdo::Error> {
         info!("D-Bus: RunEnvironment {id_or_name}");
+        self.authorize(&header, Privileged::Enter).await?;
         let resolved = self.resolve_env(&id_or_name)?;
         let _lock = self.acquire_lock()?;
         self.engine().enter(&resolved).map_err(|e| {
@@ -240,10 +523,12 @@ impl KarapaceManager {
 
     async fn rename_environment(
         &self,
+        #[zbus(header)] header: Header<'_>,
         id_or_name: String,
         new_name: String,
     ) -> Result<String, zbus::fdo::Error> {
         info!("D-Bus: RenameEnvironment {id_or_name} -> {new_name}");
+        self.authorize(&header, Privileged::Rename).await?;
         let resolved = self.resolve_env(&id_or_name)?;
         let _lock = self.acquire_lock()?;
         self.engine().rename(&resolved, &new_name).map_err(|e| {
@@ -271,8 +556,13 @@ impl KarapaceManager {
         serde_json::to_string(&presets).map_err(to_fdo)
     }
 
-    async fn garbage_collect(&self, dry_run: bool) -> Result<String, zbus::fdo::Error> {
+    async fn garbage_collect(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        dry_run: bool,
+    ) -> Result<String, zbus::fdo::Error> {
         info!("D-Bus: GarbageCollect (dry_run={dry_run})");
+        self.authorize(&header, Privileged::Gc).await?;
         let lock = self.acquire_lock()?;
         let report = self.engine().gc(&lock, dry_run).map_err(|e| {
             error!("GarbageCollect failed: {e}");
@@ -303,9 +593,41 @@ impl KarapaceManager {
     }
 }
 
+/// The body of a `BuildAsync` job: lock the store, build, and name the
+/// result.
+fn run_build_job(
+    store_root: &str,
+    manifest_path: &str,
+    name: &str,
+    sink: &JobSink,
+) -> Result<EnvInfo, karapace_core::CoreError> {
+    let layout = StoreLayout::new(store_root);
+    let _lock = StoreLock::acquire_for(&layout.lock_file(), "dbus")?;
+    if sink.cancelled() {
+        return Err(karapace_core::CoreError::Cancelled);
+    }
+    let engine = karapace_core::Engine::new(store_root);
+    let result = engine.build_with_options(
+        std::path::Path::new(manifest_path),
+        BuildOptions::default(),
+        sink,
+    )?;
+    let name = (!name.is_empty()).then(|| name.to_owned());
+    if name.is_some() {
+        engine.set_name(&result.identity.env_id, name.clone())?;
+    }
+    Ok(EnvInfo {
+        env_id: result.identity.env_id.to_string(),
+        short_id: result.identity.short_id.to_string(),
+        name,
+        state: "built".to_owned(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::jobs::JOB_PATH_PREFIX;
 
     fn setup() -> (tempfile::TempDir, tempfile::TempDir, KarapaceManager) {
         let store = tempfile::tempdir().unwrap();
@@ -314,6 +636,15 @@ mod tests {
         (store, project, manager)
     }
 
+    /// A method call as it would reach the manager, for the calls that
+    /// take its header.
+    fn call() -> zbus::Message {
+        zbus::Message::method_call(DBUS_PATH, "Call")
+            .unwrap()
+            .build(&())
+            .unwrap()
+    }
+
     fn write_mock_manifest(dir: &std::path::Path) -> std::path::PathBuf {
         let path = dir.join("karapace.toml");
         std::fs::write(
@@ -346,7 +677,7 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let build_result = mgr
-            .build_environment(manifest.to_string_lossy().to_string())
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&build_result).unwrap();
@@ -365,7 +696,7 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let build_result = mgr
-            .build_environment(manifest.to_string_lossy().to_string())
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&build_result).unwrap();
@@ -384,7 +715,7 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let build_result = mgr
-            .build_environment(manifest.to_string_lossy().to_string())
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&build_result).unwrap();
@@ -399,12 +730,14 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let build_result = mgr
-            .build_environment(manifest.to_string_lossy().to_string())
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&build_result).unwrap();
 
-        mgr.destroy_environment(info.env_id.clone()).await.unwrap();
+        mgr.destroy_environment(call().header(), info.env_id.clone())
+            .await
+            .unwrap();
 
         // Should no longer be in the list
         let list_result = mgr.list_environments().await.unwrap();
@@ -416,7 +749,7 @@ backend = "mock"
     async fn gc_on_empty_store() {
         let (_store, _project, mgr) = setup();
         // GC on empty/uninitialized store should not panic
-        let result = mgr.garbage_collect(true).await;
+        let result = mgr.garbage_collect(call().header(), true).await;
         // May succeed or fail depending on store init — should not panic
         assert!(result.is_ok() || result.is_err());
     }
@@ -434,7 +767,11 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let result = mgr
-            .build_named_environment(manifest.to_string_lossy().to_string(), "my-env".to_owned())
+            .build_named_environment(
+                call().header(),
+                manifest.to_string_lossy().to_string(),
+                "my-env".to_owned(),
+            )
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&result).unwrap();
@@ -453,14 +790,18 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let build_result = mgr
-            .build_environment(manifest.to_string_lossy().to_string())
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&build_result).unwrap();
 
-        mgr.rename_environment(info.env_id.clone(), "renamed-env".to_owned())
-            .await
-            .unwrap();
+        mgr.rename_environment(
+            call().header(),
+            info.env_id.clone(),
+            "renamed-env".to_owned(),
+        )
+        .await
+        .unwrap();
 
         // Verify name via status
         let status = mgr
@@ -478,6 +819,7 @@ backend = "mock"
 
         let build_result = mgr
             .build_named_environment(
+                call().header(),
                 manifest.to_string_lossy().to_string(),
                 "named-env".to_owned(),
             )
@@ -510,13 +852,14 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         mgr.build_named_environment(
+            call().header(),
             manifest.to_string_lossy().to_string(),
             "to-destroy".to_owned(),
         )
         .await
         .unwrap();
 
-        mgr.destroy_environment("to-destroy".to_owned())
+        mgr.destroy_environment(call().header(), "to-destroy".to_owned())
             .await
             .unwrap();
 
@@ -530,7 +873,7 @@ backend = "mock"
         let (_store, project, mgr) = setup();
         let bad_path = project.path().join("nonexistent.toml");
         let result = mgr
-            .build_environment(bad_path.to_string_lossy().to_string())
+            .build_environment(call().header(), bad_path.to_string_lossy().to_string())
             .await;
         assert!(result.is_err());
     }
@@ -541,6 +884,7 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         mgr.build_named_environment(
+            call().header(),
             manifest.to_string_lossy().to_string(),
             "first-env".to_owned(),
         )
@@ -562,13 +906,13 @@ backend = "mock"
         )
         .unwrap();
         let build2 = mgr
-            .build_environment(path2.to_string_lossy().to_string())
+            .build_environment(call().header(), path2.to_string_lossy().to_string())
             .await
             .unwrap();
         let info2: EnvInfo = serde_json::from_str(&build2).unwrap();
 
         let result = mgr
-            .rename_environment(info2.env_id, "first-env".to_owned())
+            .rename_environment(call().header(), info2.env_id, "first-env".to_owned())
             .await;
         assert!(result.is_err());
     }
@@ -576,7 +920,9 @@ backend = "mock"
     #[tokio::test]
     async fn destroy_nonexistent_returns_error() {
         let (_store, _project, mgr) = setup();
-        let result = mgr.destroy_environment("does-not-exist".to_owned()).await;
+        let result = mgr
+            .destroy_environment(call().header(), "does-not-exist".to_owned())
+            .await;
         assert!(result.is_err());
     }
 
@@ -586,14 +932,16 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let build_result = mgr
-            .build_environment(manifest.to_string_lossy().to_string())
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&build_result).unwrap();
 
-        mgr.destroy_environment(info.env_id).await.unwrap();
+        mgr.destroy_environment(call().header(), info.env_id)
+            .await
+            .unwrap();
 
-        let gc_result = mgr.garbage_collect(false).await.unwrap();
+        let gc_result = mgr.garbage_collect(call().header(), false).await.unwrap();
         let gc: serde_json::Value = serde_json::from_str(&gc_result).unwrap();
         assert_eq!(gc["dry_run"], false);
     }
@@ -603,7 +951,7 @@ backend = "mock"
         let (_store, project, mgr) = setup();
         let manifest = write_mock_manifest(project.path());
 
-        mgr.build_environment(manifest.to_string_lossy().to_string())
+        mgr.build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
 
@@ -619,12 +967,12 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let build_result = mgr
-            .build_environment(manifest.to_string_lossy().to_string())
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&build_result).unwrap();
 
-        mgr.rename_environment(info.env_id.clone(), "new-name".to_owned())
+        mgr.rename_environment(call().header(), info.env_id.clone(), "new-name".to_owned())
             .await
             .unwrap();
 
@@ -640,12 +988,15 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let build_result = mgr
-            .build_environment(manifest.to_string_lossy().to_string())
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&build_result).unwrap();
 
-        let destroy_result = mgr.destroy_environment(info.env_id.clone()).await.unwrap();
+        let destroy_result = mgr
+            .destroy_environment(call().header(), info.env_id.clone())
+            .await
+            .unwrap();
         let parsed: serde_json::Value = serde_json::from_str(&destroy_result).unwrap();
         assert_eq!(parsed["destroyed"].as_str().unwrap(), info.env_id);
     }
@@ -656,17 +1007,175 @@ backend = "mock"
         let manifest = write_mock_manifest(project.path());
 
         let build_result = mgr
-            .build_environment(manifest.to_string_lossy().to_string())
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
             .await
             .unwrap();
         let info: EnvInfo = serde_json::from_str(&build_result).unwrap();
 
         let rename_result = mgr
-            .rename_environment(info.env_id.clone(), "test-rename".to_owned())
+            .rename_environment(
+                call().header(),
+                info.env_id.clone(),
+                "test-rename".to_owned(),
+            )
             .await
             .unwrap();
         let parsed: serde_json::Value = serde_json::from_str(&rename_result).unwrap();
         assert_eq!(parsed["env_id"].as_str().unwrap(), info.env_id);
         assert_eq!(parsed["name"].as_str().unwrap(), "test-rename");
     }
+
+    async fn wait_for_job(mgr: &KarapaceManager, job: &OwnedObjectPath) -> serde_json::Value {
+        for _ in 0..1000 {
+            let status: serde_json::Value =
+                serde_json::from_str(&mgr.get_job_status(job.clone()).await.unwrap()).unwrap();
+            if status["state"] != "running" {
+                return status;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("job did not finish");
+    }
+
+    #[tokio::test]
+    async fn build_async_runs_a_job_to_completion() {
+        let (_store, project, mgr) = setup();
+        let manifest = write_mock_manifest(project.path());
+
+        let job = mgr
+            .build_async(
+                call().header(),
+                manifest.to_string_lossy().to_string(),
+                "async-env".to_owned(),
+            )
+            .await
+            .unwrap();
+        assert!(job.as_str().starts_with(JOB_PATH_PREFIX));
+
+        let status = wait_for_job(&mgr, &job).await;
+        assert_eq!(status["state"], "completed");
+        assert_eq!(status["result"]["name"], "async-env");
+        let envs: Vec<EnvInfo> =
+            serde_json::from_str(&mgr.list_environments().await.unwrap()).unwrap();
+        assert_eq!(envs.len(), 1);
+        assert!(mgr.cancel_job(call().header(), job).await.is_err());
+
+        let bad = mgr
+            .build_async(
+                call().header(),
+                project
+                    .path()
+                    .join("missing.toml")
+                    .to_string_lossy()
+                    .to_string(),
+                String::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(wait_for_job(&mgr, &bad).await["state"], "failed");
+    }
+
+    #[tokio::test]
+    async fn cancel_job_stops_a_waiting_build() {
+        let (store, project, mgr) = setup();
+        let manifest = write_mock_manifest(project.path());
+        let layout = StoreLayout::new(store.path());
+        layout.initialize().unwrap();
+        let lock = StoreLock::acquire(&layout.lock_file()).unwrap();
+
+        let job = mgr
+            .build_async(
+                call().header(),
+                manifest.to_string_lossy().to_string(),
+                String::new(),
+            )
+            .await
+            .unwrap();
+        mgr.cancel_job(call().header(), job.clone()).await.unwrap();
+        drop(lock);
+
+        assert_eq!(wait_for_job(&mgr, &job).await["state"], "cancelled");
+        let envs: Vec<EnvInfo> =
+            serde_json::from_str(&mgr.list_environments().await.unwrap()).unwrap();
+        assert!(envs.is_empty());
+
+        let unknown = ObjectPath::try_from(format!("{JOB_PATH_PREFIX}/999"))
+            .unwrap()
+            .into();
+        assert!(mgr.cancel_job(call().header(), unknown).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn polkit_guards_privileged_calls_only() {
+        let (store, project, mgr) = setup();
+        let manifest = write_mock_manifest(project.path());
+        let json = mgr
+            .build_environment(call().header(), manifest.to_string_lossy().to_string())
+            .await
+            .unwrap();
+        let info: EnvInfo = serde_json::from_str(&json).unwrap();
+        let mgr = KarapaceManager::new(store.path().to_string_lossy().to_string())
+            .with_polkit(PolkitConfig::default());
+
+        let denied = mgr
+            .destroy_environment(call().header(), info.env_id.clone())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&denied, zbus::fdo::Error::AccessDenied(m) if m.contains("org.karapace.manage.destroy")),
+            "{denied:?}"
+        );
+        assert!(matches!(
+            mgr.garbage_collect(call().header(), true).await,
+            Err(zbus::fdo::Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            mgr.run_environment(call().header(), info.env_id.clone())
+                .await,
+            Err(zbus::fdo::Error::AccessDenied(_))
+        ));
+        let manifest = manifest.to_string_lossy().to_string();
+        assert!(matches!(
+            mgr.build_environment(call().header(), manifest.clone())
+                .await,
+            Err(zbus::fdo::Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            mgr.build_named_environment(call().header(), manifest.clone(), "dev".to_owned())
+                .await,
+            Err(zbus::fdo::Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            mgr.build_async(call().header(), manifest, String::new())
+                .await,
+            Err(zbus::fdo::Error::AccessDenied(_))
+        ));
+        assert!(matches!(
+            mgr.rename_environment(call().header(), info.env_id.clone(), "dev".to_owned())
+                .await,
+            Err(zbus::fdo::Error::AccessDenied(_))
+        ));
+        let job = ObjectPath::try_from(format!("{JOB_PATH_PREFIX}/1"))
+            .unwrap()
+            .into();
+        assert!(matches!(
+            mgr.cancel_job(call().header(), job).await,
+            Err(zbus::fdo::Error::AccessDenied(_))
+        ));
+        assert!(mgr
+            .list_environments()
+            .await
+            .unwrap()
+            .contains(&info.env_id));
+
+        let disabled = PolkitConfig {
+            enabled: false,
+            ..PolkitConfig::default()
+        };
+        let mgr =
+            KarapaceManager::new(store.path().to_string_lossy().to_string()).with_polkit(disabled);
+        mgr.destroy_environment(call().header(), info.env_id)
+            .await
+            .unwrap();
+    }
 }
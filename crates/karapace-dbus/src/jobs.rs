@@ -0,0 +1,133 @@
+//! Builds started with `BuildAsync`.
+//!
+//! Each job runs on its own thread and gets an object path under
+//! [`JOB_PATH_PREFIX`], which the `Progress`, `Completed` and `Failed`
+//! signals of the manager carry so clients can tell concurrent jobs apart.
+//! `CancelJob` sets a flag the build checks between phases.
+
+use crate::interface::{emit, EnvInfo};
+use karapace_runtime::{BuildPhase, ProgressSink};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::info;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath};
+
+pub const JOB_PATH_PREFIX: &str = "/org/karapace/Manager1/jobs";
+
+/// Where a job is, as `GetJobStatus` reports it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Completed { result: EnvInfo },
+    Failed { error: String },
+    Cancelled,
+}
+
+pub struct Job {
+    cancel: AtomicBool,
+    state: Mutex<JobState>,
+}
+
+impl Job {
+    pub fn state(&self) -> JobState {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub(crate) fn set_state(&self, state: JobState) {
+        *self.state.lock().unwrap_or_else(PoisonError::into_inner) = state;
+    }
+
+    /// Ask the build to stop. Returns `false` when the job already ended.
+    pub fn cancel(&self) -> bool {
+        if self.state() != JobState::Running {
+            return false;
+        }
+        self.cancel.store(true, Ordering::Relaxed);
+        true
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+/// Jobs of this service run, finished ones included, by number.
+#[derive(Default)]
+pub struct Jobs {
+    next: AtomicU32,
+    jobs: Mutex<HashMap<u32, Arc<Job>>>,
+}
+
+impl Jobs {
+    /// Register a new running job.
+    pub fn start(&self) -> (OwnedObjectPath, Arc<Job>) {
+        let number = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        let job = Arc::new(Job {
+            cancel: AtomicBool::new(false),
+            state: Mutex::new(JobState::Running),
+        });
+        self.jobs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(number, Arc::clone(&job));
+        // A prefix of plain elements and a number make a valid path.
+        let path = ObjectPath::from_string_unchecked(format!("{JOB_PATH_PREFIX}/{number}"));
+        (path.into(), job)
+    }
+
+    pub fn get(&self, path: &ObjectPath<'_>) -> Option<Arc<Job>> {
+        let number = path
+            .as_str()
+            .strip_prefix(JOB_PATH_PREFIX)?
+            .strip_prefix('/')?
+            .parse()
+            .ok()?;
+        self.jobs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&number)
+            .cloned()
+    }
+}
+
+/// Reports a job's build as `Progress` signals and passes its cancellation
+/// on to the engine.
+pub(crate) struct JobSink {
+    pub connection: Option<zbus::blocking::Connection>,
+    pub path: OwnedObjectPath,
+    pub job: Arc<Job>,
+    pub phase: Mutex<&'static str>,
+}
+
+impl JobSink {
+    fn progress(&self, phase: &str, message: &str) {
+        info!("job {}: {phase}: {message}", self.path.as_str());
+        emit(
+            self.connection.as_ref(),
+            "Progress",
+            &(self.path.as_ref(), phase, message),
+        );
+    }
+}
+
+impl ProgressSink for JobSink {
+    fn phase(&self, phase: BuildPhase) {
+        *self.phase.lock().unwrap_or_else(PoisonError::into_inner) = phase.name();
+        self.progress(phase.name(), phase.label());
+    }
+
+    fn message(&self, message: &str) {
+        let phase = *self.phase.lock().unwrap_or_else(PoisonError::into_inner);
+        self.progress(phase, message);
+    }
+
+    fn cancelled(&self) -> bool {
+        self.job.is_cancelled()
+    }
+}
@@ -2,11 +2,20 @@
 //!
 //! This crate exposes the Karapace engine over the `org.karapace.Manager1` D-Bus
 //! interface, enabling desktop applications and system services to build, destroy,
-//! enter, and query environments without invoking the CLI directly. Designed for
-//! socket activation with an idle timeout.
+//! enter, and query environments without invoking the CLI directly. Long builds
+//! can run as jobs that report progress and can be cancelled, and signals report
+//! environments being added, removed, or changing state. Designed for socket
+//! activation with an idle timeout, per session or, with polkit guarding
+//! privileged calls, on the system bus.
 
 pub mod interface;
+pub mod jobs;
+pub mod polkit;
 pub mod service;
+pub mod watch;
 
 pub use interface::{KarapaceManager, API_VERSION, DBUS_INTERFACE, DBUS_PATH};
-pub use service::{run_service, run_service_with_timeout, ServiceError};
+pub use jobs::{JobState, JOB_PATH_PREFIX};
+pub use polkit::{PolkitConfig, Privileged};
+pub use service::{run_service, run_service_on, run_service_with_timeout, Bus, ServiceError};
+pub use watch::{diff_envs, watch_environments, EnvChange};
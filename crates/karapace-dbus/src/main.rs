@@ -1,16 +1,14 @@
-use std::path::PathBuf;
+use karapace_core::{discover_store, UserConfig};
+use karapace_dbus::polkit::SYSTEM_CONFIG_PATH;
+use karapace_dbus::{Bus, PolkitConfig};
+use std::path::{Path, PathBuf};
 use tracing::info;
 
-fn default_store_path() -> PathBuf {
-    if let Ok(home) = std::env::var("HOME") {
-        PathBuf::from(home).join(".local/share/karapace")
-    } else {
-        PathBuf::from("/tmp/karapace")
-    }
-}
-
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Sessions entered over D-Bus re-run this executable as their init.
+    karapace_runtime::init::run_if_requested();
+
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_env("KARAPACE_LOG")
@@ -20,11 +18,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .without_time()
         .init();
 
-    let store_root =
-        std::env::var("KARAPACE_STORE").map_or_else(|_| default_store_path(), PathBuf::from);
+    let explicit = std::env::var("KARAPACE_STORE").ok().map(PathBuf::from);
+    let cwd = std::env::current_dir()?;
+    let store_root = discover_store(explicit.as_deref(), &cwd, &UserConfig::load_default()?).root;
+
+    // `KARAPACE_DBUS_BUS=system` runs the shared, polkit-guarded service.
+    let bus = match std::env::var("KARAPACE_DBUS_BUS").as_deref() {
+        Ok("system") => Bus::System,
+        Ok("session") | Err(_) => Bus::Session,
+        Ok(other) => return Err(format!("KARAPACE_DBUS_BUS: unknown bus '{other}'").into()),
+    };
+    let polkit = match bus {
+        Bus::System => Some(PolkitConfig::load(Path::new(SYSTEM_CONFIG_PATH))?),
+        Bus::Session => None,
+    };
 
     info!("karapace-dbus starting, store: {}", store_root.display());
-    karapace_dbus::run_service(store_root.to_string_lossy().to_string()).await?;
+    karapace_dbus::run_service_on(
+        store_root.to_string_lossy().to_string(),
+        bus,
+        polkit,
+        Some(karapace_dbus::service::IDLE_TIMEOUT_SECS),
+    )
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,159 @@
+//! Authorization of privileged calls.
+//!
+//! On the session bus every caller is the user who owns the store, so
+//! nothing is checked. A service on the system bus is shared by all users
+//! and runs as root; there every method that changes the store or runs
+//! something in it asks polkit whether the caller may perform the
+//! corresponding action first.
+//! The action IDs default to the ones in `data/polkit/org.karapace.policy`
+//! and can be changed in `/etc/karapace/dbus.toml`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use zbus::zvariant::Value;
+
+/// Where the system service reads its [`PolkitConfig`].
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/karapace/dbus.toml";
+
+/// Let polkit ask the user for a password when the action requires it.
+const ALLOW_USER_INTERACTION: u32 = 1;
+
+/// A call that needs authorization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privileged {
+    /// `BuildEnvironment`, `BuildNamedEnvironment` and `BuildAsync`.
+    Build,
+    Rename,
+    /// `CancelJob`.
+    Cancel,
+    Destroy,
+    Gc,
+    Enter,
+}
+
+/// The `[polkit]` table of `dbus.toml`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolkitConfig {
+    /// Check privileged calls. Only takes effect on the system bus.
+    pub enabled: bool,
+    pub build: String,
+    pub rename: String,
+    pub cancel: String,
+    pub destroy: String,
+    pub gc: String,
+    pub enter: String,
+}
+
+impl Default for PolkitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            build: "org.karapace.manage.build".to_owned(),
+            rename: "org.karapace.manage.rename".to_owned(),
+            cancel: "org.karapace.manage.cancel".to_owned(),
+            destroy: "org.karapace.manage.destroy".to_owned(),
+            gc: "org.karapace.manage.gc".to_owned(),
+            enter: "org.karapace.manage.enter".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DbusConfig {
+    #[serde(default)]
+    polkit: PolkitConfig,
+}
+
+impl PolkitConfig {
+    /// Read the `[polkit]` table of `path`; a missing file or table yields
+    /// the defaults.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str::<DbusConfig>(&content)
+                .map(|config| config.polkit)
+                .map_err(|e| format!("{}: {}", path.display(), e.message().trim())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {e}", path.display())),
+        }
+    }
+
+    /// The polkit action ID guarding `call`.
+    pub fn action(&self, call: Privileged) -> &str {
+        match call {
+            Privileged::Build => &self.build,
+            Privileged::Rename => &self.rename,
+            Privileged::Cancel => &self.cancel,
+            Privileged::Destroy => &self.destroy,
+            Privileged::Gc => &self.gc,
+            Privileged::Enter => &self.enter,
+        }
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+trait Authority {
+    /// Returns `(is_authorized, is_challenge, details)`.
+    fn check_authorization(
+        &self,
+        subject: &(&str, HashMap<&str, Value<'_>>),
+        action_id: &str,
+        details: HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> zbus::Result<(bool, bool, HashMap<String, String>)>;
+}
+
+/// Ask polkit, over the system bus `connection`, whether the client with
+/// the unique bus name `sender` may perform `action_id`.
+pub async fn check_authorization(
+    connection: &zbus::Connection,
+    sender: &str,
+    action_id: &str,
+) -> zbus::Result<bool> {
+    let authority = AuthorityProxy::new(connection).await?;
+    let subject = (
+        "system-bus-name",
+        HashMap::from([("name", Value::from(sender))]),
+    );
+    let (authorized, _, _) = authority
+        .check_authorization(
+            &subject,
+            action_id,
+            HashMap::new(),
+            ALLOW_USER_INTERACTION,
+            "",
+        )
+        .await?;
+    Ok(authorized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_overrides_action_ids_and_keeps_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dbus.toml");
+        assert_eq!(PolkitConfig::load(&path).unwrap(), PolkitConfig::default());
+
+        std::fs::write(&path, "[polkit]\ndestroy = \"com.example.wipe\"\n").unwrap();
+        let config = PolkitConfig::load(&path).unwrap();
+        assert!(config.enabled);
+        assert_eq!(config.action(Privileged::Destroy), "com.example.wipe");
+        assert_eq!(config.action(Privileged::Gc), "org.karapace.manage.gc");
+        assert_eq!(
+            config.action(Privileged::Build),
+            "org.karapace.manage.build"
+        );
+
+        std::fs::write(&path, "[polkit]\nenabled = \"yes\"\n").unwrap();
+        assert!(PolkitConfig::load(&path).is_err());
+    }
+}
@@ -1,10 +1,13 @@
-use crate::interface::{KarapaceManager, DBUS_PATH};
+use crate::interface::{emit, KarapaceManager, DBUS_PATH};
+use crate::polkit::PolkitConfig;
+use crate::watch::{watch_environments, EnvChange};
+use std::path::Path;
 use thiserror::Error;
-use tracing::info;
+use tracing::{info, warn};
 use zbus::connection::Builder;
 
 /// Default idle timeout before the service exits (for socket activation).
-const IDLE_TIMEOUT_SECS: u64 = 30;
+pub const IDLE_TIMEOUT_SECS: u64 = 30;
 
 #[derive(Debug, Error)]
 pub enum ServiceError {
@@ -12,6 +15,24 @@ pub enum ServiceError {
     Dbus(#[from] zbus::Error),
 }
 
+/// The bus the service is offered on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    /// One service per user, for that user's store.
+    Session,
+    /// One service shared by all users; privileged calls go through polkit.
+    System,
+}
+
+impl Bus {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Session => "session",
+            Self::System => "system",
+        }
+    }
+}
+
 /// Run the D-Bus service. If `idle_timeout` is Some, the service will exit
 /// after that many seconds of inactivity. Use None for infinite runtime.
 pub async fn run_service(store_root: String) -> Result<(), ServiceError> {
@@ -22,15 +43,70 @@ pub async fn run_service_with_timeout(
     store_root: String,
     idle_timeout: Option<u64>,
 ) -> Result<(), ServiceError> {
-    let manager = KarapaceManager::new(store_root);
+    run_service_on(store_root, Bus::Session, None, idle_timeout).await
+}
 
-    let _conn = Builder::session()?
+/// Run the service on `bus`. On the system bus, privileged calls are
+/// checked against `polkit`, or the default action IDs when it is `None`.
+pub async fn run_service_on(
+    store_root: String,
+    bus: Bus,
+    polkit: Option<PolkitConfig>,
+    idle_timeout: Option<u64>,
+) -> Result<(), ServiceError> {
+    let mut manager = KarapaceManager::new(store_root.clone());
+    let builder = match bus {
+        Bus::Session => Builder::session()?,
+        Bus::System => {
+            let polkit = polkit.unwrap_or_default();
+            if !polkit.enabled {
+                warn!("polkit disabled: any user on the system bus may destroy, enter and gc");
+            }
+            manager = manager.with_polkit(polkit);
+            Builder::system()?
+        }
+    };
+
+    let conn = builder
         .name("org.karapace.Manager1")?
         .serve_at(DBUS_PATH, manager)?
         .build()
         .await?;
+    conn.object_server()
+        .interface::<_, KarapaceManager>(DBUS_PATH)
+        .await?
+        .get()
+        .await
+        .attach(conn.clone());
+
+    let signals = zbus::blocking::Connection::from(conn.clone());
+    if let Err(e) = watch_environments(Path::new(&store_root), move |change| {
+        let connection = Some(&signals);
+        match change {
+            EnvChange::Added(info) => emit(
+                connection,
+                "EnvironmentAdded",
+                &(
+                    info.env_id.as_str(),
+                    info.short_id.as_str(),
+                    info.name.as_deref().unwrap_or(""),
+                    info.state.as_str(),
+                ),
+            ),
+            EnvChange::Removed(env_id) => {
+                emit(connection, "EnvironmentRemoved", &(env_id.as_str(),));
+            }
+            EnvChange::StateChanged { env_id, old, new } => emit(
+                connection,
+                "StateChanged",
+                &(env_id.as_str(), old.as_str(), new.as_str()),
+            ),
+        }
+    }) {
+        warn!("environment change signals disabled: {e}");
+    }
 
-    info!("karapace-dbus service started on session bus");
+    info!("karapace-dbus service started on {} bus", bus.name());
 
     match idle_timeout {
         Some(secs) => {
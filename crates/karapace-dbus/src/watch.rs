@@ -0,0 +1,288 @@
+//! Environment change notifications.
+//!
+//! The service watches the store's metadata directory with inotify, so
+//! changes made by the CLI or any other process are seen as well as its own.
+//! After each burst of events it re-reads the metadata and compares it with
+//! what it saw before; the differences become `EnvironmentAdded`,
+//! `EnvironmentRemoved` and `StateChanged` signals.
+
+use crate::interface::EnvInfo;
+use karapace_store::{MetadataStore, StoreLayout};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// How long to let a burst of writes settle before re-reading metadata.
+const SETTLE: Duration = Duration::from_millis(50);
+
+/// How often to look for a metadata directory that does not exist yet.
+const MISSING_DIR_POLL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvChange {
+    Added(EnvInfo),
+    Removed(String),
+    StateChanged {
+        env_id: String,
+        old: String,
+        new: String,
+    },
+}
+
+/// Environments by env_id, as far as change signals are concerned.
+pub type EnvSnapshot = BTreeMap<String, EnvInfo>;
+
+/// Changes that turn `old` into `new`.
+pub fn diff_envs(old: &EnvSnapshot, new: &EnvSnapshot) -> Vec<EnvChange> {
+    let mut changes: Vec<EnvChange> = old
+        .keys()
+        .filter(|env_id| !new.contains_key(*env_id))
+        .map(|env_id| EnvChange::Removed(env_id.clone()))
+        .collect();
+    for (env_id, info) in new {
+        match old.get(env_id) {
+            None => changes.push(EnvChange::Added(info.clone())),
+            Some(before) if before.state != info.state => {
+                changes.push(EnvChange::StateChanged {
+                    env_id: env_id.clone(),
+                    old: before.state.clone(),
+                    new: info.state.clone(),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    changes
+}
+
+/// The environments in the store's metadata. Entries that cannot be read
+/// are left out.
+pub fn snapshot(layout: &StoreLayout) -> EnvSnapshot {
+    let store = MetadataStore::new(layout.clone());
+    let entries = match store.list_with_errors() {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("cannot list environments: {e}");
+            return EnvSnapshot::new();
+        }
+    };
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            entry
+                .map_err(|(name, e)| debug!("skipping unreadable metadata {name}: {e}"))
+                .ok()
+        })
+        .map(|meta| {
+            (
+                meta.env_id.to_string(),
+                EnvInfo {
+                    env_id: meta.env_id.to_string(),
+                    short_id: meta.short_id.to_string(),
+                    name: meta.name,
+                    state: meta.state.to_string(),
+                },
+            )
+        })
+        .collect()
+}
+
+/// An inotify instance watching one directory for entries being created,
+/// replaced, or removed.
+struct DirWatch(File);
+
+impl DirWatch {
+    fn new(dir: &Path) -> std::io::Result<Self> {
+        #[allow(unsafe_code)]
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just returned by inotify_init1 and is owned here.
+        #[allow(unsafe_code)]
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+        let mask = libc::IN_CREATE
+            | libc::IN_CLOSE_WRITE
+            | libc::IN_MOVED_TO
+            | libc::IN_MOVED_FROM
+            | libc::IN_DELETE
+            | libc::IN_DELETE_SELF;
+        #[allow(unsafe_code)]
+        let wd = unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self(File::from(fd)))
+    }
+
+    /// Block until something happens in the directory.
+    fn wait(&mut self) -> std::io::Result<()> {
+        let mut events = [0u8; 4096];
+        loop {
+            match self.0.read(&mut events) {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                other => return other.map(drop),
+            }
+        }
+    }
+}
+
+/// Call `on_change` for every change to the environments of the store at
+/// `store_root` from now on, on a thread of its own. When the metadata
+/// directory exists it is watched before this returns, so no later change
+/// is missed.
+pub fn watch_environments(
+    store_root: &Path,
+    on_change: impl Fn(&EnvChange) + Send + 'static,
+) -> std::io::Result<()> {
+    let layout = StoreLayout::new(store_root);
+    let dir = layout.metadata_dir();
+    let mut watched = if dir.is_dir() {
+        Some((DirWatch::new(&dir)?, snapshot(&layout)))
+    } else {
+        None
+    };
+    std::thread::spawn(move || loop {
+        let (mut watch, mut seen) = match watched.take() {
+            Some(watched) => watched,
+            None => match wait_for_dir(&dir) {
+                Ok(watch) => (watch, EnvSnapshot::new()),
+                Err(e) => {
+                    warn!("cannot watch {}: {e}", dir.display());
+                    return;
+                }
+            },
+        };
+        // Environments already there when a new directory appears count
+        // as added.
+        report(&mut seen, snapshot(&layout), &on_change);
+        loop {
+            if let Err(e) = watch.wait() {
+                warn!("watching {} failed: {e}", dir.display());
+                return;
+            }
+            std::thread::sleep(SETTLE);
+            report(&mut seen, snapshot(&layout), &on_change);
+            if !dir.is_dir() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+fn report(seen: &mut EnvSnapshot, now: EnvSnapshot, on_change: &impl Fn(&EnvChange)) {
+    for change in diff_envs(seen, &now) {
+        on_change(&change);
+    }
+    *seen = now;
+}
+
+fn wait_for_dir(dir: &Path) -> std::io::Result<DirWatch> {
+    while !dir.is_dir() {
+        std::thread::sleep(MISSING_DIR_POLL);
+    }
+    DirWatch::new(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(env_id: &str, state: &str) -> (String, EnvInfo) {
+        (
+            env_id.to_owned(),
+            EnvInfo {
+                env_id: env_id.to_owned(),
+                short_id: env_id.to_owned(),
+                name: None,
+                state: state.to_owned(),
+            },
+        )
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_state_changes() {
+        let old: EnvSnapshot = [info("a", "built"), info("b", "built"), info("c", "built")]
+            .into_iter()
+            .collect();
+        let new: EnvSnapshot = [
+            info("b", "frozen"),
+            info("c", "built"),
+            info("d", "defined"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            diff_envs(&old, &new),
+            vec![
+                EnvChange::Removed("a".to_owned()),
+                EnvChange::StateChanged {
+                    env_id: "b".to_owned(),
+                    old: "built".to_owned(),
+                    new: "frozen".to_owned(),
+                },
+                EnvChange::Added(info("d", "defined").1),
+            ]
+        );
+        assert!(diff_envs(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn watch_reports_changes_made_by_another_engine() {
+        let store = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        StoreLayout::new(store.path()).initialize().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        watch_environments(store.path(), move |change| {
+            let _ = tx.send(change.clone());
+        })
+        .unwrap();
+        let next = || rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        let manifest = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest,
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\nbackend = \"mock\"\n",
+        )
+        .unwrap();
+        let engine = karapace_core::Engine::new(store.path());
+        let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+
+        // The metadata is written as `defined` before the build finishes,
+        // so the addition may arrive in either state.
+        let EnvChange::Added(added) = next() else {
+            panic!("expected an addition first");
+        };
+        assert_eq!(added.env_id, env_id);
+        if added.state != "built" {
+            assert_eq!(
+                next(),
+                EnvChange::StateChanged {
+                    env_id: env_id.clone(),
+                    old: added.state,
+                    new: "built".to_owned(),
+                }
+            );
+        }
+
+        engine.freeze(&env_id).unwrap();
+        assert_eq!(
+            next(),
+            EnvChange::StateChanged {
+                env_id: env_id.clone(),
+                old: "built".to_owned(),
+                new: "frozen".to_owned(),
+            }
+        );
+
+        engine.destroy(&env_id).unwrap();
+        assert_eq!(next(), EnvChange::Removed(env_id));
+    }
+}
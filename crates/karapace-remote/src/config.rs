@@ -2,18 +2,112 @@ use crate::RemoteError;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
+/// Protocol a remote store speaks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteKind {
+    /// The karapace-server REST API, or a directory over SSH for
+    /// `ssh://` URLs.
+    #[default]
+    Http,
+    /// An S3-compatible bucket; `url` is the endpoint.
+    S3,
+}
+
+impl RemoteKind {
+    #[allow(clippy::trivially_copy_pass_by_ref)] // signature required by serde
+    fn is_http(&self) -> bool {
+        *self == Self::Http
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RemoteConfig {
+    #[serde(default, skip_serializing_if = "RemoteKind::is_http")]
+    pub kind: RemoteKind,
+    /// Server URL, or for S3 the endpoint. An empty S3 endpoint means AWS
+    /// in `region`.
+    #[serde(default)]
     pub url: String,
     #[serde(default)]
     pub auth_token: Option<String>,
+    /// age recipients blobs are encrypted to before upload. Empty disables
+    /// encryption on push.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub age_recipients: Vec<String>,
+    /// age identity file used to decrypt pulled blobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age_identity: Option<PathBuf>,
+    /// S3 bucket holding the store.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// S3 signing region. Defaults to `us-east-1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Key prefix of the store inside the bucket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// S3 credentials. Default to `$AWS_ACCESS_KEY_ID` and
+    /// `$AWS_SECRET_ACCESS_KEY`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_access_key: Option<String>,
+    /// Refuse to pull environments without a signature by a trusted key.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_signed: bool,
+    /// File of trusted public keys. Defaults to
+    /// `~/.config/karapace/trusted_keys`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted_keys: Option<PathBuf>,
 }
 
 impl RemoteConfig {
     pub fn new(url: &str) -> Self {
         Self {
+            kind: RemoteKind::Http,
             url: url.trim_end_matches('/').to_owned(),
             auth_token: None,
+            age_recipients: Vec::new(),
+            age_identity: None,
+            bucket: None,
+            region: None,
+            prefix: None,
+            access_key_id: None,
+            secret_access_key: None,
+            require_signed: false,
+            trusted_keys: None,
+        }
+    }
+
+    /// An S3-compatible store in `bucket` at `endpoint`.
+    pub fn s3(endpoint: &str, bucket: &str, region: &str) -> Self {
+        Self {
+            kind: RemoteKind::S3,
+            bucket: Some(bucket.to_owned()),
+            region: Some(region.to_owned()),
+            ..Self::new(endpoint)
+        }
+    }
+
+    /// Where the store lives, for display and as the registry cache key.
+    pub fn location(&self) -> String {
+        match self.kind {
+            RemoteKind::Http => self.url.clone(),
+            RemoteKind::S3 => {
+                let mut location = format!("s3://{}", self.bucket.as_deref().unwrap_or_default());
+                if let Some(prefix) = self.prefix.as_deref().map(|p| p.trim_matches('/')) {
+                    if !prefix.is_empty() {
+                        location.push('/');
+                        location.push_str(prefix);
+                    }
+                }
+                if !self.url.is_empty() {
+                    location.push_str(" at ");
+                    location.push_str(&self.url);
+                }
+                location
+            }
         }
     }
 
@@ -23,6 +117,18 @@ impl RemoteConfig {
         self
     }
 
+    #[must_use]
+    pub fn with_age_recipients(mut self, recipients: &[String]) -> Self {
+        self.age_recipients.extend(recipients.iter().cloned());
+        self
+    }
+
+    #[must_use]
+    pub fn with_age_identity(mut self, identity: &Path) -> Self {
+        self.age_identity = Some(identity.to_path_buf());
+        self
+    }
+
     /// Load config from `~/.config/karapace/remote.json`.
     pub fn load_default() -> Result<Self, RemoteError> {
         let path = default_config_path()?;
@@ -68,6 +174,54 @@ mod tests {
         assert_eq!(loaded.auth_token.as_deref(), Some("secret123"));
     }
 
+    #[test]
+    fn config_age_settings_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("remote.json");
+
+        let config = RemoteConfig::new("https://store.example.com")
+            .with_age_recipients(&["age1abc".to_owned()])
+            .with_age_identity(Path::new("/keys/id.txt"));
+        config.save(&path).unwrap();
+
+        let loaded = RemoteConfig::load(&path).unwrap();
+        assert_eq!(loaded.age_recipients, vec!["age1abc"]);
+        assert_eq!(
+            loaded.age_identity.as_deref(),
+            Some(Path::new("/keys/id.txt"))
+        );
+
+        // Configs without age settings keep their old shape.
+        RemoteConfig::new("https://x").save(&path).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("age_"));
+    }
+
+    #[test]
+    fn config_s3_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("remote.json");
+        std::fs::write(
+            &path,
+            r#"{"kind": "s3", "bucket": "envs", "region": "eu-central-1", "prefix": "team/"}"#,
+        )
+        .unwrap();
+
+        let loaded = RemoteConfig::load(&path).unwrap();
+        assert_eq!(loaded.kind, RemoteKind::S3);
+        assert_eq!(loaded.url, "");
+        assert_eq!(loaded.location(), "s3://envs/team");
+        assert_eq!(
+            RemoteConfig::s3("http://minio:9000", "envs", "us-east-1").location(),
+            "s3://envs at http://minio:9000"
+        );
+
+        // HTTP configs keep their old shape.
+        RemoteConfig::new("https://x").save(&path).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("kind"));
+        assert!(!saved.contains("bucket"));
+    }
+
     #[test]
     fn config_strips_trailing_slash() {
         let config = RemoteConfig::new("https://example.com/");
@@ -0,0 +1,245 @@
+use crate::{RemoteConfig, RemoteError};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// First line of every binary age file.
+pub const AGE_HEADER: &[u8] = b"age-encryption.org/v1\n";
+
+/// Client-side encryption applied to blobs before upload and after download.
+pub trait BlobCipher: Send + Sync {
+    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, RemoteError>;
+
+    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, RemoteError>;
+
+    /// Fingerprints of the keys able to decrypt what `encrypt` produces.
+    /// Recorded in registry entries so pullers know which key they need.
+    fn fingerprints(&self) -> Vec<String>;
+}
+
+/// Short, stable identifier for an age recipient.
+pub fn key_fingerprint(recipient: &str) -> String {
+    let hash = blake3::hash(recipient.trim().as_bytes()).to_hex();
+    format!("blake3:{}", &hash[..16])
+}
+
+/// Which keys an environment was encrypted for when it was pushed. Stored
+/// unencrypted as its [`BlobKind::Seal`](crate::BlobKind::Seal) blob;
+/// pulls decrypt according to it, never by looking at blob content.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealRecord {
+    /// Empty when the last push of the environment was unencrypted.
+    pub key_fingerprints: Vec<String>,
+}
+
+impl SealRecord {
+    pub fn to_json(&self) -> Result<Vec<u8>, RemoteError> {
+        serde_json::to_vec_pretty(self).map_err(|e| RemoteError::Serialization(e.to_string()))
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, RemoteError> {
+        serde_json::from_slice(data)
+            .map_err(|e| RemoteError::Serialization(format!("invalid seal record: {e}")))
+    }
+
+    /// Suffix of the remote keys of blobs encrypted for these keys, or
+    /// `None` when there are none.
+    pub fn seal_id(&self) -> Option<String> {
+        seal_id(&self.key_fingerprints)
+    }
+}
+
+/// Encrypted blobs are stored under `<key>.<seal id>`, so a blob encrypted
+/// for one set of recipients never stands in for plaintext or for another
+/// set. `None` for an empty set.
+pub fn seal_id(fingerprints: &[String]) -> Option<String> {
+    if fingerprints.is_empty() {
+        return None;
+    }
+    let mut fps = fingerprints.to_vec();
+    fps.sort();
+    fps.dedup();
+    let hash = blake3::hash(fps.join("\n").as_bytes()).to_hex();
+    Some(format!("age-{}", &hash[..16]))
+}
+
+/// Whether `data` looks like an age-encrypted blob.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(AGE_HEADER)
+}
+
+/// [`BlobCipher`] backed by the `age` command-line tool.
+///
+/// Encrypts to every configured recipient and decrypts with an identity
+/// file. The program defaults to `$KARAPACE_AGE`, then `age` on `PATH`.
+#[derive(Debug, Clone)]
+pub struct AgeCipher {
+    recipients: Vec<String>,
+    identity: Option<PathBuf>,
+    program: PathBuf,
+}
+
+impl AgeCipher {
+    pub fn new(recipients: Vec<String>, identity: Option<PathBuf>) -> Self {
+        let program = std::env::var_os("KARAPACE_AGE").map_or_else(|| "age".into(), PathBuf::from);
+        Self {
+            recipients,
+            identity,
+            program,
+        }
+    }
+
+    /// Build a cipher from the age settings of a remote config, if any.
+    pub fn from_config(config: &RemoteConfig) -> Option<Self> {
+        if config.age_recipients.is_empty() && config.age_identity.is_none() {
+            return None;
+        }
+        Some(Self::new(
+            config.age_recipients.clone(),
+            config.age_identity.clone(),
+        ))
+    }
+
+    #[must_use]
+    pub fn with_program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    fn run(&self, args: &[&std::ffi::OsStr], input: &[u8]) -> Result<Vec<u8>, RemoteError> {
+        let mut child = Command::new(&self.program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                RemoteError::Encryption(format!("failed to run {}: {e}", self.program.display()))
+            })?;
+
+        // Feed stdin from a separate thread so large blobs cannot deadlock
+        // against a full stdout pipe.
+        let mut stdin = child.stdin.take();
+        let output = std::thread::scope(|s| {
+            let writer = s.spawn(move || match stdin.take() {
+                Some(mut pipe) => pipe.write_all(input),
+                None => Ok(()),
+            });
+            let output = child.wait_with_output();
+            let written = writer
+                .join()
+                .unwrap_or_else(|_| Err(std::io::Error::other("stdin writer panicked")));
+            output.and_then(|o| written.map(|()| o))
+        })?;
+
+        if !output.status.success() {
+            return Err(RemoteError::Encryption(format!(
+                "{} exited with {}: {}",
+                self.program.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl BlobCipher for AgeCipher {
+    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, RemoteError> {
+        if self.recipients.is_empty() {
+            return Err(RemoteError::Encryption(
+                "no age recipients configured".to_owned(),
+            ));
+        }
+        let mut args: Vec<&std::ffi::OsStr> = vec!["--encrypt".as_ref()];
+        for r in &self.recipients {
+            args.push("--recipient".as_ref());
+            args.push(r.as_ref());
+        }
+        self.run(&args, data)
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, RemoteError> {
+        let Some(identity) = &self.identity else {
+            return Err(RemoteError::Encryption(
+                "no age identity configured".to_owned(),
+            ));
+        };
+        self.run(
+            &[
+                "--decrypt".as_ref(),
+                "--identity".as_ref(),
+                identity.as_os_str(),
+            ],
+            data,
+        )
+    }
+
+    fn fingerprints(&self) -> Vec<String> {
+        let mut fps: Vec<String> = self.recipients.iter().map(|r| key_fingerprint(r)).collect();
+        fps.sort();
+        fps.dedup();
+        fps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_is_stable_and_trimmed() {
+        let fp = key_fingerprint("age1qyqszqgpqyqszqgpqyqszqgpqyqszqgp");
+        assert_eq!(
+            fp,
+            key_fingerprint(" age1qyqszqgpqyqszqgpqyqszqgpqyqszqgp\n")
+        );
+        assert!(fp.starts_with("blake3:"));
+        assert_eq!(fp.len(), "blake3:".len() + 16);
+        assert_ne!(fp, key_fingerprint("age1other"));
+    }
+
+    #[test]
+    fn seal_id_ignores_order_and_duplicates() {
+        let a = key_fingerprint("age1a");
+        let b = key_fingerprint("age1b");
+        assert_eq!(seal_id(&[]), None);
+        let id = seal_id(&[a.clone(), b.clone()]).unwrap();
+        assert_eq!(seal_id(&[b.clone(), a.clone(), b.clone()]).unwrap(), id);
+        assert_ne!(seal_id(&[a]).unwrap(), id);
+        assert!(id.starts_with("age-"));
+    }
+
+    #[test]
+    fn detects_age_header() {
+        assert!(is_encrypted(b"age-encryption.org/v1\n-> X25519 ..."));
+        assert!(!is_encrypted(b"{\"env_id\": \"abc\"}"));
+    }
+
+    #[test]
+    fn from_config_requires_age_settings() {
+        let config = RemoteConfig::new("https://example.com");
+        assert!(AgeCipher::from_config(&config).is_none());
+        let config = config.with_age_recipients(&["age1abc".to_owned()]);
+        let cipher = AgeCipher::from_config(&config).unwrap();
+        assert_eq!(cipher.fingerprints(), vec![key_fingerprint("age1abc")]);
+    }
+
+    #[test]
+    fn missing_program_is_an_encryption_error() {
+        let cipher = AgeCipher::new(vec!["age1abc".to_owned()], None)
+            .with_program("/nonexistent/karapace-age");
+        let err = cipher.encrypt(b"data").unwrap_err();
+        assert!(matches!(err, RemoteError::Encryption(_)), "{err}");
+    }
+
+    #[test]
+    fn decrypt_without_identity_fails() {
+        let cipher = AgeCipher::new(vec!["age1abc".to_owned()], None);
+        assert!(matches!(
+            cipher.decrypt(b"age-encryption.org/v1\n"),
+            Err(RemoteError::Encryption(_))
+        ));
+    }
+}
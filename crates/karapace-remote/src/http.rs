@@ -1,16 +1,27 @@
-use crate::{BlobKind, RemoteBackend, RemoteConfig, RemoteError};
+use crate::{BlobKind, RegistryEntry, RegistryFetch, RemoteBackend, RemoteConfig, RemoteError};
+use std::collections::BTreeMap;
 use std::io::Read;
 
+/// Response header a HEAD on a blob uses to report its size in bytes.
+pub const BLOB_SIZE_HEADER: &str = "X-Karapace-Blob-Size";
+
 /// HTTP-based remote store backend.
 ///
 /// Expects a simple REST API:
 /// - `PUT  /objects/<key>`   — upload object blob
 /// - `GET  /objects/<key>`   — download object blob
-/// - `HEAD /objects/<key>`   — check existence
+/// - `HEAD /objects/<key>`   — check existence; `X-Karapace-Blob-Size` carries the size
 /// - `GET  /objects/`        — list objects (JSON array of strings)
+/// - `PATCH /objects/<key>?offset=N[&final=1]` — append a chunk to a resumable upload
+/// - `GET  /uploads/objects/<key>` — bytes received so far, as `{"offset": N}`
+/// - `GET` with `Range: bytes=N-M` — download part of a blob
 /// - Same pattern for `/layers/` and `/metadata/`
 /// - `PUT  /registry`        — upload registry index
-/// - `GET  /registry`        — download registry index
+/// - `GET  /registry`        — download registry index; honours `If-None-Match`
+///   with `304 Not Modified` when the server sends an `ETag`
+/// - `GET  /registry/tags?name=<name>` — the tags published under a name,
+///   as a JSON object of tag to entry
+/// - `DELETE /registry/<name>@<tag>` — unpublish a tag, answering with its entry
 pub struct HttpBackend {
     config: RemoteConfig,
     agent: ureq::Agent,
@@ -22,11 +33,18 @@ impl HttpBackend {
         Self { config, agent }
     }
 
+    /// The configuration this backend talks to.
+    pub fn config(&self) -> &RemoteConfig {
+        &self.config
+    }
+
     fn kind_path(kind: BlobKind) -> &'static str {
         match kind {
             BlobKind::Object => "objects",
             BlobKind::Layer => "layers",
             BlobKind::Metadata => "metadata",
+            BlobKind::Signature => "signatures",
+            BlobKind::Seal => "seals",
         }
     }
 
@@ -34,6 +52,17 @@ impl HttpBackend {
         format!("{}/{}/{}", self.config.url, Self::kind_path(kind), key)
     }
 
+    /// The error for a status the caller does not handle, naming what a
+    /// `401` or `403` means for the auth token.
+    fn status_error(code: u16, request: &str) -> RemoteError {
+        let hint = match code {
+            401 => ": the server needs a token (auth_token in the remote config, or $KARAPACE_REMOTE_TOKEN)",
+            403 => ": the token does not allow this",
+            _ => "",
+        };
+        RemoteError::Http(format!("HTTP {code} for {request}{hint}"))
+    }
+
     fn do_put(&self, url: &str, content_type: &str, data: &[u8]) -> Result<(), RemoteError> {
         let mut req = self
             .agent
@@ -43,12 +72,41 @@ impl HttpBackend {
         if let Some(ref token) = self.config.auth_token {
             req = req.header("Authorization", &format!("Bearer {token}"));
         }
-        req.send(data as &[u8])
-            .map_err(|e| RemoteError::Http(e.to_string()))?;
-        Ok(())
+        match req.send(data as &[u8]) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::StatusCode(code)) => {
+                Err(Self::status_error(code, &format!("PUT {url}")))
+            }
+            Err(e) => Err(RemoteError::Http(e.to_string())),
+        }
+    }
+
+    fn do_patch(&self, url: &str, data: &[u8]) -> Result<(), RemoteError> {
+        let mut req = self
+            .agent
+            .patch(url)
+            .header("Content-Type", "application/octet-stream")
+            .header("X-Karapace-Protocol", &crate::PROTOCOL_VERSION.to_string());
+        if let Some(ref token) = self.config.auth_token {
+            req = req.header("Authorization", &format!("Bearer {token}"));
+        }
+        match req.send(data) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::StatusCode(code)) => {
+                Err(Self::status_error(code, &format!("PATCH {url}")))
+            }
+            Err(e) => Err(RemoteError::Http(e.to_string())),
+        }
     }
 
     fn do_get(&self, url: &str) -> Result<Vec<u8>, RemoteError> {
+        self.do_get_range(url, None)
+    }
+
+    /// GET `url`, asking for `len` bytes from `offset` when `range` is given.
+    /// A `416 Range Not Satisfiable` yields an empty body, and a server that
+    /// ignores `Range` has its full response cut down to the requested part.
+    fn do_get_range(&self, url: &str, range: Option<(u64, u64)>) -> Result<Vec<u8>, RemoteError> {
         let mut req = self
             .agent
             .get(url)
@@ -56,13 +114,20 @@ impl HttpBackend {
         if let Some(ref token) = self.config.auth_token {
             req = req.header("Authorization", &format!("Bearer {token}"));
         }
+        if let Some((offset, len)) = range {
+            let last = offset.saturating_add(len.saturating_sub(1));
+            req = req.header("Range", &format!("bytes={offset}-{last}"));
+        }
         let resp = match req.call() {
             Ok(r) => r,
             Err(ureq::Error::StatusCode(404)) => {
                 return Err(RemoteError::NotFound(url.to_owned()));
             }
+            Err(ureq::Error::StatusCode(416)) if range.is_some() => {
+                return Ok(Vec::new());
+            }
             Err(ureq::Error::StatusCode(code)) => {
-                return Err(RemoteError::Http(format!("HTTP {code} for {url}")));
+                return Err(Self::status_error(code, url));
             }
             Err(e) => {
                 return Err(RemoteError::Http(e.to_string()));
@@ -75,7 +140,7 @@ impl HttpBackend {
             return Err(RemoteError::NotFound(url.to_owned()));
         }
         if code >= 400 {
-            return Err(RemoteError::Http(format!("HTTP {code} for {url}")));
+            return Err(Self::status_error(code, url));
         }
 
         let mut reader = resp.into_body().into_reader();
@@ -83,10 +148,46 @@ impl HttpBackend {
         reader
             .read_to_end(&mut body)
             .map_err(|e| RemoteError::Http(e.to_string()))?;
+        if let (200, Some((offset, len))) = (code, range) {
+            let start = usize::try_from(offset)
+                .unwrap_or(usize::MAX)
+                .min(body.len());
+            let end = usize::try_from(offset.saturating_add(len))
+                .unwrap_or(usize::MAX)
+                .min(body.len());
+            body = body[start..end].to_vec();
+        }
         Ok(body)
     }
 
-    fn do_head(&self, url: &str) -> Result<u16, RemoteError> {
+    /// DELETE `url`, returning the response body.
+    fn do_delete(&self, url: &str) -> Result<Vec<u8>, RemoteError> {
+        let mut req = self
+            .agent
+            .delete(url)
+            .header("X-Karapace-Protocol", &crate::PROTOCOL_VERSION.to_string());
+        if let Some(ref token) = self.config.auth_token {
+            req = req.header("Authorization", &format!("Bearer {token}"));
+        }
+        let resp = match req.call() {
+            Ok(r) => r,
+            Err(ureq::Error::StatusCode(404)) => return Err(RemoteError::NotFound(url.to_owned())),
+            Err(ureq::Error::StatusCode(code)) => {
+                return Err(Self::status_error(code, &format!("DELETE {url}")));
+            }
+            Err(e) => return Err(RemoteError::Http(e.to_string())),
+        };
+        let mut body = Vec::new();
+        resp.into_body()
+            .into_reader()
+            .read_to_end(&mut body)
+            .map_err(|e| RemoteError::Http(e.to_string()))?;
+        Ok(body)
+    }
+
+    /// HEAD `url`, returning the status and the `X-Karapace-Blob-Size`
+    /// header when the server sends one.
+    fn do_head(&self, url: &str) -> Result<(u16, Option<u64>), RemoteError> {
         let mut req = self
             .agent
             .head(url)
@@ -95,8 +196,15 @@ impl HttpBackend {
             req = req.header("Authorization", &format!("Bearer {token}"));
         }
         match req.call() {
-            Ok(resp) => Ok(resp.status().into()),
-            Err(ureq::Error::StatusCode(code)) => Ok(code),
+            Ok(resp) => {
+                let size = resp
+                    .headers()
+                    .get(BLOB_SIZE_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse().ok());
+                Ok((resp.status().into(), size))
+            }
+            Err(ureq::Error::StatusCode(code)) => Ok((code, None)),
             Err(e) => Err(RemoteError::Http(e.to_string())),
         }
     }
@@ -118,13 +226,78 @@ impl RemoteBackend for HttpBackend {
     fn has_blob(&self, kind: BlobKind, key: &str) -> Result<bool, RemoteError> {
         let url = self.url(kind, key);
         tracing::debug!("HEAD {url}");
-        match self.do_head(&url)? {
+        match self.do_head(&url)?.0 {
             200 => Ok(true),
             404 => Ok(false),
-            code => Err(RemoteError::Http(format!("HTTP {code} for HEAD {url}"))),
+            code => Err(Self::status_error(code, &format!("HEAD {url}"))),
+        }
+    }
+
+    fn blob_size(&self, kind: BlobKind, key: &str) -> Result<Option<u64>, RemoteError> {
+        let url = self.url(kind, key);
+        tracing::debug!("HEAD {url}");
+        match self.do_head(&url)? {
+            (200, size) => Ok(size),
+            (404, _) => Err(RemoteError::NotFound(url)),
+            (code, _) => Err(Self::status_error(code, &format!("HEAD {url}"))),
         }
     }
 
+    fn supports_resume(&self) -> bool {
+        true
+    }
+
+    fn upload_offset(&self, kind: BlobKind, key: &str) -> Result<u64, RemoteError> {
+        let url = format!(
+            "{}/uploads/{}/{}",
+            self.config.url,
+            Self::kind_path(kind),
+            key
+        );
+        tracing::debug!("GET {url}");
+        let body = match self.do_get(&url) {
+            Ok(body) => body,
+            Err(RemoteError::NotFound(_)) => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let value: serde_json::Value =
+            serde_json::from_slice(&body).map_err(|e| RemoteError::Serialization(e.to_string()))?;
+        value["offset"].as_u64().ok_or_else(|| {
+            RemoteError::Serialization(format!("missing upload offset in response from {url}"))
+        })
+    }
+
+    fn put_blob_chunk(
+        &self,
+        kind: BlobKind,
+        key: &str,
+        offset: u64,
+        data: &[u8],
+        last: bool,
+    ) -> Result<(), RemoteError> {
+        let mut url = format!("{}?offset={offset}", self.url(kind, key));
+        if last {
+            url.push_str("&final=1");
+        }
+        tracing::debug!("PATCH {url} ({} bytes)", data.len());
+        self.do_patch(&url, data)
+    }
+
+    fn get_blob_range(
+        &self,
+        kind: BlobKind,
+        key: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, RemoteError> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let url = self.url(kind, key);
+        tracing::debug!("GET {url} ({len} bytes from {offset})");
+        self.do_get_range(&url, Some((offset, len)))
+    }
+
     fn list_blobs(&self, kind: BlobKind) -> Result<Vec<String>, RemoteError> {
         let url = format!("{}/{}/", self.config.url, Self::kind_path(kind));
         tracing::debug!("GET {url}");
@@ -146,6 +319,70 @@ impl RemoteBackend for HttpBackend {
         tracing::debug!("GET {url}");
         self.do_get(&url)
     }
+
+    fn get_registry_if_changed(&self, etag: Option<&str>) -> Result<RegistryFetch, RemoteError> {
+        let url = format!("{}/registry", self.config.url);
+        tracing::debug!("GET {url} (If-None-Match: {etag:?})");
+        let mut req = self
+            .agent
+            .get(&url)
+            .header("X-Karapace-Protocol", &crate::PROTOCOL_VERSION.to_string());
+        if let Some(ref token) = self.config.auth_token {
+            req = req.header("Authorization", &format!("Bearer {token}"));
+        }
+        if let Some(etag) = etag {
+            req = req.header("If-None-Match", etag);
+        }
+        let resp = match req.call() {
+            Ok(r) => r,
+            Err(ureq::Error::StatusCode(304)) => return Ok(RegistryFetch::NotModified),
+            Err(ureq::Error::StatusCode(404)) => return Err(RemoteError::NotFound(url)),
+            Err(ureq::Error::StatusCode(code)) => {
+                return Err(Self::status_error(code, &url));
+            }
+            Err(e) => return Err(RemoteError::Http(e.to_string())),
+        };
+        match resp.status().as_u16() {
+            304 => return Ok(RegistryFetch::NotModified),
+            404 => return Err(RemoteError::NotFound(url)),
+            code if code >= 400 => {
+                return Err(Self::status_error(code, &url));
+            }
+            _ => {}
+        }
+        let etag = resp
+            .headers()
+            .get("ETag")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+        let mut data = Vec::new();
+        resp.into_body()
+            .into_reader()
+            .read_to_end(&mut data)
+            .map_err(|e| RemoteError::Http(e.to_string()))?;
+        Ok(RegistryFetch::Modified { data, etag })
+    }
+
+    fn list_tags(&self, name: &str) -> Result<Vec<(String, RegistryEntry)>, RemoteError> {
+        let url = format!("{}/registry/tags?name={name}", self.config.url);
+        tracing::debug!("GET {url}");
+        let tags: BTreeMap<String, RegistryEntry> = serde_json::from_slice(&self.do_get(&url)?)
+            .map_err(|e| RemoteError::Serialization(format!("invalid tag list: {e}")))?;
+        Ok(tags.into_iter().collect())
+    }
+
+    fn delete_tag(&self, name: &str, tag: &str) -> Result<RegistryEntry, RemoteError> {
+        let url = format!("{}/registry/{name}@{tag}", self.config.url);
+        tracing::debug!("DELETE {url}");
+        match self.do_delete(&url) {
+            Ok(body) => serde_json::from_slice(&body)
+                .map_err(|e| RemoteError::Serialization(format!("invalid registry entry: {e}"))),
+            Err(RemoteError::NotFound(_)) => Err(RemoteError::NotFound(format!(
+                "registry key '{name}@{tag}' not found"
+            ))),
+            Err(e) => Err(e),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -283,6 +520,7 @@ mod tests {
         HttpBackend::new(RemoteConfig {
             url: url.to_owned(),
             auth_token: None,
+            ..RemoteConfig::new(url)
         })
     }
 
@@ -290,6 +528,7 @@ mod tests {
         HttpBackend::new(RemoteConfig {
             url: url.to_owned(),
             auth_token: Some(token.to_owned()),
+            ..RemoteConfig::new(url)
         })
     }
 
@@ -1,17 +1,33 @@
 //! Remote store synchronization for sharing Karapace environments.
 //!
 //! This crate provides push/pull transfer of content-addressable objects and layer
-//! manifests to/from a remote HTTP backend, a registry for named environment
-//! references, and configuration for remote endpoints with optional authentication.
+//! manifests to/from a remote HTTP, S3-compatible or SSH backend, a registry for named environment
+//! references with a local cache of it, and configuration for remote endpoints with optional authentication,
+//! client-side age encryption and ed25519 signatures of pushed environments.
 
 pub mod config;
+pub mod crypt;
 pub mod http;
 pub mod registry;
+pub mod s3;
+pub mod sign;
+pub mod ssh;
 pub mod transfer;
 
-pub use config::RemoteConfig;
-pub use registry::{parse_ref, Registry, RegistryEntry};
-pub use transfer::{pull_env, push_env, resolve_ref, PullResult, PushResult};
+pub use config::{RemoteConfig, RemoteKind};
+pub use crypt::{key_fingerprint, AgeCipher, BlobCipher, SealRecord};
+pub use ed25519_dalek::SigningKey;
+pub use registry::{
+    parse_ref, CachedRegistry, Registry, RegistryCache, RegistryEntry, RegistryOrigin,
+};
+pub use sign::{EnvSignature, TrustedKeys};
+pub use transfer::{
+    fetch_layer, fetch_object, list_refs, list_refs_with_cache, peek_env, peek_env_with_cipher,
+    pull_env, pull_env_with_cipher, pull_env_with_options, push_env, push_env_with_cipher,
+    push_env_with_options, resolve_entry, resolve_entry_with_cache, resolve_ref, EnvPeek,
+    LayerPeek, ObjectProgress, PullResult, PushResult, TransferOptions, TransferPhase,
+    DEFAULT_CONCURRENCY,
+};
 
 /// Protocol version sent as `X-Karapace-Protocol` header on all HTTP requests.
 /// Servers can reject clients with incompatible protocol versions.
@@ -33,6 +49,12 @@ pub enum RemoteError {
     NotFound(String),
     #[error("remote config error: {0}")]
     Config(String),
+    #[error("SSH error: {0}")]
+    Ssh(String),
+    #[error("encryption error: {0}")]
+    Encryption(String),
+    #[error("signature error: {0}")]
+    Signature(String),
     #[error("integrity failure for '{key}': expected {expected}, got {actual}")]
     IntegrityFailure {
         key: String,
@@ -47,6 +69,31 @@ pub enum BlobKind {
     Object,
     Layer,
     Metadata,
+    /// An [`EnvSignature`], keyed by env_id like the metadata it signs.
+    Signature,
+    /// A [`SealRecord`], keyed by env_id: whether and for whom the
+    /// environment's blobs are encrypted.
+    Seal,
+}
+
+/// Result of a conditional registry download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryFetch {
+    /// The registry still has the entity tag the caller holds.
+    NotModified,
+    /// The current registry, with its entity tag when the backend has one.
+    Modified { data: Vec<u8>, etag: Option<String> },
+}
+
+/// Open the backend `config` describes.
+pub fn open_backend(config: RemoteConfig) -> Result<Box<dyn RemoteBackend>, RemoteError> {
+    match config.kind {
+        RemoteKind::Http if config.url.starts_with("ssh://") => {
+            Ok(Box::new(ssh::SshBackend::new(config)?))
+        }
+        RemoteKind::Http => Ok(Box::new(http::HttpBackend::new(config))),
+        RemoteKind::S3 => Ok(Box::new(s3::S3Backend::new(config)?)),
+    }
 }
 
 /// Trait for remote storage backends.
@@ -60,6 +107,62 @@ pub trait RemoteBackend: Send + Sync {
     /// Check if a blob exists in the remote store.
     fn has_blob(&self, kind: BlobKind, key: &str) -> Result<bool, RemoteError>;
 
+    /// Size in bytes of a blob, without downloading it. `None` when the
+    /// backend cannot tell.
+    fn blob_size(&self, kind: BlobKind, key: &str) -> Result<Option<u64>, RemoteError> {
+        if self.has_blob(kind, key)? {
+            Ok(None)
+        } else {
+            Err(RemoteError::NotFound(key.to_owned()))
+        }
+    }
+
+    /// Whether the backend implements [`upload_offset`](Self::upload_offset)
+    /// and [`put_blob_chunk`](Self::put_blob_chunk), so interrupted
+    /// uploads can be resumed.
+    fn supports_resume(&self) -> bool {
+        false
+    }
+
+    /// Bytes the remote already holds of an unfinished chunked upload.
+    fn upload_offset(&self, _kind: BlobKind, _key: &str) -> Result<u64, RemoteError> {
+        Ok(0)
+    }
+
+    /// Append `data` to a chunked upload at `offset`. The blob becomes
+    /// visible once a chunk with `last` set has been accepted.
+    fn put_blob_chunk(
+        &self,
+        _kind: BlobKind,
+        key: &str,
+        _offset: u64,
+        _data: &[u8],
+        _last: bool,
+    ) -> Result<(), RemoteError> {
+        Err(RemoteError::Http(format!(
+            "chunked upload of '{key}' is not supported by this backend"
+        )))
+    }
+
+    /// Download `len` bytes of a blob starting at `offset`. Fewer bytes are
+    /// returned when the blob ends first.
+    fn get_blob_range(
+        &self,
+        kind: BlobKind,
+        key: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, RemoteError> {
+        let data = self.get_blob(kind, key)?;
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        let end = usize::try_from(offset.saturating_add(len))
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
     /// List all blobs of a given kind.
     fn list_blobs(&self, kind: BlobKind) -> Result<Vec<String>, RemoteError>;
 
@@ -68,6 +171,46 @@ pub trait RemoteBackend: Send + Sync {
 
     /// Download the registry index.
     fn get_registry(&self) -> Result<Vec<u8>, RemoteError>;
+
+    /// Download the registry index unless it still has entity tag `etag`.
+    /// Backends without entity tags always download it.
+    fn get_registry_if_changed(&self, _etag: Option<&str>) -> Result<RegistryFetch, RemoteError> {
+        Ok(RegistryFetch::Modified {
+            data: self.get_registry()?,
+            etag: None,
+        })
+    }
+
+    /// Tags published under `name`, sorted, with their entries. Backends
+    /// without a tags route filter the whole registry.
+    fn list_tags(&self, name: &str) -> Result<Vec<(String, RegistryEntry)>, RemoteError> {
+        let registry = match self.get_registry() {
+            Ok(data) => Registry::from_bytes(&data)?,
+            Err(RemoteError::NotFound(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(registry
+            .tags(name)
+            .into_iter()
+            .map(|(tag, entry)| (tag.to_owned(), entry.clone()))
+            .collect())
+    }
+
+    /// Remove `name@tag` from the registry and return its entry. Blobs stay
+    /// on the remote until it collects garbage.
+    fn delete_tag(&self, name: &str, tag: &str) -> Result<RegistryEntry, RemoteError> {
+        let key = format!("{name}@{tag}");
+        let mut registry = match self.get_registry() {
+            Ok(data) => Registry::from_bytes(&data)?,
+            Err(RemoteError::NotFound(_)) => Registry::new(),
+            Err(e) => return Err(e),
+        };
+        let entry = registry
+            .unpublish(&key)
+            .ok_or_else(|| RemoteError::NotFound(format!("registry key '{key}' not found")))?;
+        self.put_registry(&registry.to_bytes()?)?;
+        Ok(entry)
+    }
 }
 
 #[cfg(test)]
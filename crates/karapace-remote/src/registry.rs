@@ -1,6 +1,7 @@
-use crate::RemoteError;
+use crate::{RegistryFetch, RemoteBackend, RemoteError};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 /// A single entry in the remote registry, mapping a tag to an env_id.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -10,6 +11,10 @@ pub struct RegistryEntry {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     pub pushed_at: String,
+    /// Fingerprints of the age keys the blobs were encrypted to. Empty for
+    /// unencrypted pushes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_fingerprints: Vec<String>,
 }
 
 /// The registry index: maps `name@tag` keys to environment entries.
@@ -43,6 +48,22 @@ impl Registry {
         self.entries.get(key)
     }
 
+    /// Remove an entry, returning it if it was present.
+    pub fn unpublish(&mut self, key: &str) -> Option<RegistryEntry> {
+        self.entries.remove(key)
+    }
+
+    /// Tags published under `name`, sorted, with their entries.
+    pub fn tags(&self, name: &str) -> Vec<(&str, &RegistryEntry)> {
+        self.entries
+            .iter()
+            .filter_map(|(key, entry)| match key.split_once('@') {
+                Some((n, tag)) if n == name => Some((tag, entry)),
+                _ => None,
+            })
+            .collect()
+    }
+
     /// List all keys in the registry.
     pub fn list_keys(&self) -> Vec<&str> {
         self.entries.keys().map(String::as_str).collect()
@@ -67,9 +88,330 @@ pub fn parse_ref(reference: &str) -> (&str, &str) {
     }
 }
 
+/// Where a [`CachedRegistry`] came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryOrigin {
+    /// Downloaded from the remote just now.
+    Downloaded,
+    /// The remote confirmed the cached copy is current.
+    NotModified,
+    /// The remote was unreachable, so this is the last copy fetched.
+    Cached,
+}
+
+/// A registry together with when it was last fetched from the remote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedRegistry {
+    pub registry: Registry,
+    /// RFC 3339 time of the last successful contact with the remote.
+    pub fetched_at: String,
+    pub origin: RegistryOrigin,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    fetched_at: String,
+    registry: Registry,
+}
+
+/// Local copy of one remote's registry.
+///
+/// Refreshing sends the cached entity tag, so an unchanged registry is not
+/// downloaded again, and [`fetch`](Self::fetch) falls back to the cached copy
+/// when the remote cannot be reached.
+#[derive(Debug, Clone)]
+pub struct RegistryCache {
+    path: PathBuf,
+    url: String,
+}
+
+impl RegistryCache {
+    /// Cache for the remote at `remote_url`, kept in `dir`.
+    pub fn new(dir: &Path, remote_url: &str) -> Self {
+        let url = remote_url.trim_end_matches('/').to_owned();
+        let hash = blake3::hash(url.as_bytes()).to_hex();
+        Self {
+            path: dir.join(format!("{}.json", &hash[..16])),
+            url,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The cached registry, if there is a readable one for this remote.
+    pub fn load(&self) -> Option<CachedRegistry> {
+        self.read().map(|file| CachedRegistry {
+            registry: file.registry,
+            fetched_at: file.fetched_at,
+            origin: RegistryOrigin::Cached,
+        })
+    }
+
+    fn read(&self) -> Option<CacheFile> {
+        let data = std::fs::read(&self.path).ok()?;
+        match serde_json::from_slice::<CacheFile>(&data) {
+            Ok(file) if file.url == self.url => Some(file),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::debug!(
+                    "ignoring unreadable registry cache {}: {e}",
+                    self.path.display()
+                );
+                None
+            }
+        }
+    }
+
+    fn write(&self, file: &CacheFile) -> Result<(), RemoteError> {
+        let data = serde_json::to_vec_pretty(file)
+            .map_err(|e| RemoteError::Serialization(e.to_string()))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Bring the cache up to date with `backend`, downloading the registry
+    /// only when it changed. Returns `None`, and drops the cache, when the
+    /// remote has no registry.
+    pub fn refresh(
+        &self,
+        backend: &dyn RemoteBackend,
+    ) -> Result<Option<CachedRegistry>, RemoteError> {
+        let cached = self.read();
+        let etag = cached.as_ref().and_then(|file| file.etag.as_deref());
+        let fetched = match backend.get_registry_if_changed(etag) {
+            Ok(fetched) => fetched,
+            Err(RemoteError::NotFound(_)) => {
+                let _ = std::fs::remove_file(&self.path);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let fetched_at = chrono::Utc::now().to_rfc3339();
+        let (file, origin) = match (fetched, cached) {
+            (RegistryFetch::NotModified, Some(mut file)) => {
+                file.fetched_at = fetched_at;
+                (file, RegistryOrigin::NotModified)
+            }
+            (RegistryFetch::NotModified, None) => {
+                return Err(RemoteError::Http(
+                    "remote answered 304 Not Modified for an uncached registry".to_owned(),
+                ));
+            }
+            (RegistryFetch::Modified { data, etag }, _) => {
+                let file = CacheFile {
+                    url: self.url.clone(),
+                    etag,
+                    fetched_at,
+                    registry: Registry::from_bytes(&data)?,
+                };
+                (file, RegistryOrigin::Downloaded)
+            }
+        };
+        if let Err(e) = self.write(&file) {
+            tracing::warn!("cannot update registry cache {}: {e}", self.path.display());
+        }
+        Ok(Some(CachedRegistry {
+            registry: file.registry,
+            fetched_at: file.fetched_at,
+            origin,
+        }))
+    }
+
+    /// Like [`refresh`](Self::refresh), but when the remote cannot be reached
+    /// the cached registry is used instead, with a warning saying how old it is.
+    pub fn fetch(
+        &self,
+        backend: &dyn RemoteBackend,
+    ) -> Result<Option<CachedRegistry>, RemoteError> {
+        match self.refresh(backend) {
+            Err(RemoteError::Http(e)) => match self.load() {
+                Some(cached) => {
+                    tracing::warn!(
+                        "remote {} unreachable ({e}); using registry cached {}",
+                        self.url,
+                        describe_age(&cached.fetched_at)
+                    );
+                    Ok(Some(cached))
+                }
+                None => Err(RemoteError::Http(e)),
+            },
+            other => other,
+        }
+    }
+}
+
+/// `fetched_at` as a rough age, e.g. "3h ago (2025-01-01T00:00:00+00:00)".
+fn describe_age(fetched_at: &str) -> String {
+    let Ok(then) = chrono::DateTime::parse_from_rfc3339(fetched_at) else {
+        return format!("at {fetched_at}");
+    };
+    let secs = (chrono::Utc::now() - then.with_timezone(&chrono::Utc)).num_seconds();
+    let age = match secs {
+        s if s < 60 => "just now".to_owned(),
+        s if s < 3600 => format!("{}m ago", s / 60),
+        s if s < 86400 => format!("{}h ago", s / 3600),
+        s => format!("{}d ago", s / 86400),
+    };
+    format!("{age} ({fetched_at})")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::BlobKind;
+    use std::sync::Mutex;
+
+    /// Registry-only backend whose entity tag is the registry's length.
+    #[derive(Default)]
+    struct FakeRemote {
+        registry: Mutex<Option<Vec<u8>>>,
+        offline: Mutex<bool>,
+        downloads: Mutex<usize>,
+    }
+
+    impl FakeRemote {
+        fn set(&self, reg: &Registry) {
+            *self.registry.lock().unwrap() = Some(reg.to_bytes().unwrap());
+        }
+    }
+
+    impl RemoteBackend for FakeRemote {
+        fn put_blob(&self, _: BlobKind, _: &str, _: &[u8]) -> Result<(), RemoteError> {
+            unimplemented!()
+        }
+        fn get_blob(&self, _: BlobKind, key: &str) -> Result<Vec<u8>, RemoteError> {
+            Err(RemoteError::NotFound(key.to_owned()))
+        }
+        fn has_blob(&self, _: BlobKind, _: &str) -> Result<bool, RemoteError> {
+            Ok(false)
+        }
+        fn list_blobs(&self, _: BlobKind) -> Result<Vec<String>, RemoteError> {
+            Ok(Vec::new())
+        }
+        fn put_registry(&self, data: &[u8]) -> Result<(), RemoteError> {
+            *self.registry.lock().unwrap() = Some(data.to_vec());
+            Ok(())
+        }
+        fn get_registry(&self) -> Result<Vec<u8>, RemoteError> {
+            self.registry
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or_else(|| RemoteError::NotFound("registry".to_owned()))
+        }
+        fn get_registry_if_changed(
+            &self,
+            etag: Option<&str>,
+        ) -> Result<RegistryFetch, RemoteError> {
+            if *self.offline.lock().unwrap() {
+                return Err(RemoteError::Http("connection refused".to_owned()));
+            }
+            let data = self
+                .registry
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or_else(|| RemoteError::NotFound("registry".to_owned()))?;
+            let current = data.len().to_string();
+            if etag == Some(current.as_str()) {
+                return Ok(RegistryFetch::NotModified);
+            }
+            *self.downloads.lock().unwrap() += 1;
+            Ok(RegistryFetch::Modified {
+                data,
+                etag: Some(current),
+            })
+        }
+    }
+
+    fn entry(env_id: &str) -> RegistryEntry {
+        RegistryEntry {
+            env_id: env_id.to_owned(),
+            short_id: env_id.to_owned(),
+            name: None,
+            pushed_at: "t".to_owned(),
+            key_fingerprints: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn cache_skips_unchanged_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RegistryCache::new(dir.path(), "http://remote");
+        let remote = FakeRemote::default();
+        let mut reg = Registry::new();
+        reg.publish("dev@latest", entry("hash1"));
+        remote.set(&reg);
+
+        let first = cache.refresh(&remote).unwrap().unwrap();
+        assert_eq!(first.origin, RegistryOrigin::Downloaded);
+        let second = cache.refresh(&remote).unwrap().unwrap();
+        assert_eq!(second.origin, RegistryOrigin::NotModified);
+        assert_eq!(second.registry, reg);
+        assert_eq!(*remote.downloads.lock().unwrap(), 1);
+
+        reg.publish("dev@v2", entry("hash2"));
+        remote.set(&reg);
+        let third = cache.refresh(&remote).unwrap().unwrap();
+        assert_eq!(third.origin, RegistryOrigin::Downloaded);
+        assert!(third.registry.lookup("dev@v2").is_some());
+    }
+
+    #[test]
+    fn cache_serves_registry_when_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RegistryCache::new(dir.path(), "http://remote/");
+        let remote = FakeRemote::default();
+        *remote.offline.lock().unwrap() = true;
+        assert!(matches!(cache.fetch(&remote), Err(RemoteError::Http(_))));
+
+        *remote.offline.lock().unwrap() = false;
+        let mut reg = Registry::new();
+        reg.publish("dev@latest", entry("hash1"));
+        remote.set(&reg);
+        cache.refresh(&remote).unwrap();
+
+        *remote.offline.lock().unwrap() = true;
+        let cached = cache.fetch(&remote).unwrap().unwrap();
+        assert_eq!(cached.origin, RegistryOrigin::Cached);
+        assert_eq!(cached.registry, reg);
+        assert!(matches!(cache.refresh(&remote), Err(RemoteError::Http(_))));
+    }
+
+    #[test]
+    fn cache_is_per_remote_and_dropped_with_the_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = RegistryCache::new(dir.path(), "http://remote");
+        let other = RegistryCache::new(dir.path(), "http://elsewhere");
+        assert_ne!(cache.path(), other.path());
+
+        let remote = FakeRemote::default();
+        remote.set(&Registry::new());
+        cache.refresh(&remote).unwrap();
+        assert!(cache.load().is_some());
+        assert!(other.load().is_none());
+
+        *remote.registry.lock().unwrap() = None;
+        assert!(cache.refresh(&remote).unwrap().is_none());
+        assert!(!cache.path().exists());
+    }
+
+    #[test]
+    fn age_is_described_roughly() {
+        let hours_ago = (chrono::Utc::now() - chrono::Duration::hours(3)).to_rfc3339();
+        assert!(describe_age(&hours_ago).starts_with("3h ago"));
+        assert_eq!(describe_age("yesterday"), "at yesterday");
+    }
 
     #[test]
     fn registry_roundtrip() {
@@ -81,6 +423,7 @@ mod tests {
                 short_id: "abc123".to_owned(),
                 name: Some("my-env".to_owned()),
                 pushed_at: "2025-01-01T00:00:00Z".to_owned(),
+                key_fingerprints: Vec::new(),
             },
         );
 
@@ -99,6 +442,7 @@ mod tests {
                 short_id: "hash1".to_owned(),
                 name: None,
                 pushed_at: "2025-01-01T00:00:00Z".to_owned(),
+                key_fingerprints: Vec::new(),
             },
         );
         assert!(reg.lookup("dev@v1").is_some());
@@ -125,6 +469,7 @@ mod tests {
                 short_id: "hash1".to_owned(),
                 name: None,
                 pushed_at: "t".to_owned(),
+                key_fingerprints: Vec::new(),
             },
         );
         reg.publish(
@@ -134,6 +479,7 @@ mod tests {
                 short_id: "hash1".to_owned(),
                 name: None,
                 pushed_at: "t".to_owned(),
+                key_fingerprints: Vec::new(),
             },
         );
         reg.publish(
@@ -143,12 +489,52 @@ mod tests {
                 short_id: "hash2".to_owned(),
                 name: None,
                 pushed_at: "t".to_owned(),
+                key_fingerprints: Vec::new(),
             },
         );
         let found = reg.find_by_env_id("hash1");
         assert_eq!(found.len(), 2);
     }
 
+    #[test]
+    fn tags_and_unpublish() {
+        let mut reg = Registry::new();
+        reg.publish("dev@v2", entry("hash2"));
+        reg.publish("dev@latest", entry("hash2"));
+        reg.publish("dev-tools@latest", entry("hash3"));
+        reg.publish("dev", entry("hash4"));
+
+        let tags: Vec<_> = reg.tags("dev").into_iter().map(|(t, _)| t).collect();
+        assert_eq!(tags, ["latest", "v2"]);
+        assert!(reg.tags("nope").is_empty());
+
+        assert_eq!(reg.unpublish("dev@v2").unwrap().env_id, "hash2");
+        assert!(reg.unpublish("dev@v2").is_none());
+        assert_eq!(reg.tags("dev").len(), 1);
+    }
+
+    #[test]
+    fn backend_tag_defaults_edit_the_registry() {
+        let remote = FakeRemote::default();
+        assert!(remote.list_tags("dev").unwrap().is_empty());
+        assert!(matches!(
+            remote.delete_tag("dev", "latest"),
+            Err(RemoteError::NotFound(_))
+        ));
+
+        let mut reg = Registry::new();
+        reg.publish("dev@latest", entry("hash1"));
+        reg.publish("dev@v1", entry("hash0"));
+        remote.set(&reg);
+        let tags = remote.list_tags("dev").unwrap();
+        assert_eq!(tags[0], ("latest".to_owned(), entry("hash1")));
+        assert_eq!(tags.len(), 2);
+
+        assert_eq!(remote.delete_tag("dev", "v1").unwrap().env_id, "hash0");
+        let stored = Registry::from_bytes(&remote.get_registry().unwrap()).unwrap();
+        assert_eq!(stored.list_keys(), ["dev@latest"]);
+    }
+
     #[test]
     fn empty_registry_roundtrip() {
         let reg = Registry::new();
@@ -0,0 +1,247 @@
+use crate::{RemoteConfig, RemoteError};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Prefix of every signed message, so an environment signature cannot be
+/// passed off as a signature over anything else.
+const SIGNATURE_CONTEXT: &str = "karapace-env-signature-v1";
+
+/// Signature of a pushed environment, stored as its `Signature` blob.
+///
+/// It covers the blake3 hash of the metadata exactly as pushed, before
+/// encryption. The metadata names every layer, the manifest and the
+/// attestation (which holds the lock file) by hash, so the signature vouches
+/// for the whole environment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvSignature {
+    pub env_id: String,
+    /// blake3 hex of the metadata blob's plaintext.
+    pub metadata_hash: String,
+    pub signatures: Vec<KeySignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeySignature {
+    /// Hex-encoded ed25519 public key.
+    pub keyid: String,
+    /// Hex-encoded ed25519 signature.
+    pub sig: String,
+}
+
+impl EnvSignature {
+    /// Sign the metadata `metadata` of `env_id` with `key`.
+    pub fn sign(env_id: &str, metadata: &[u8], key: &SigningKey) -> Self {
+        let metadata_hash = blake3::hash(metadata).to_hex().to_string();
+        let signature = key.sign(&message(env_id, &metadata_hash));
+        Self {
+            env_id: env_id.to_owned(),
+            metadata_hash,
+            signatures: vec![KeySignature {
+                keyid: keyid(&key.verifying_key()),
+                sig: encode_hex(&signature.to_bytes()),
+            }],
+        }
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, RemoteError> {
+        serde_json::from_slice(data)
+            .map_err(|e| RemoteError::Signature(format!("invalid signature blob: {e}")))
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, RemoteError> {
+        serde_json::to_vec_pretty(self).map_err(|e| RemoteError::Serialization(e.to_string()))
+    }
+
+    /// Check that this signature covers `metadata` of `env_id` and that a
+    /// key in `trusted` made it. Returns that key. Signatures by keys not
+    /// in `trusted` are ignored; a bad one by a trusted key is an error.
+    pub fn verify(
+        &self,
+        env_id: &str,
+        metadata: &[u8],
+        trusted: &TrustedKeys,
+    ) -> Result<String, RemoteError> {
+        let actual = blake3::hash(metadata).to_hex().to_string();
+        if self.env_id != env_id || self.metadata_hash != actual {
+            return Err(RemoteError::Signature(format!(
+                "signature of {env_id} does not cover its metadata"
+            )));
+        }
+        let message = message(env_id, &self.metadata_hash);
+        for signature in &self.signatures {
+            if !trusted.contains(&signature.keyid) {
+                continue;
+            }
+            let key = decode_hex::<32>(&signature.keyid)
+                .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+            let sig = decode_hex::<64>(&signature.sig)
+                .map(|bytes| ed25519_dalek::Signature::from_bytes(&bytes));
+            let (Some(key), Some(sig)) = (key, sig) else {
+                return Err(RemoteError::Signature(format!(
+                    "malformed signature by {}",
+                    signature.keyid
+                )));
+            };
+            key.verify_strict(&message, &sig).map_err(|_| {
+                RemoteError::Signature(format!("bad signature by {}", signature.keyid))
+            })?;
+            return Ok(signature.keyid.clone());
+        }
+        Err(RemoteError::Signature(format!(
+            "{env_id} is not signed by a trusted key"
+        )))
+    }
+}
+
+/// Public keys whose signatures `pull` accepts.
+///
+/// The file holds one hex-encoded ed25519 public key per line, optionally
+/// followed by a label. Blank lines and lines starting with `#` are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedKeys {
+    /// Labels by keyid.
+    keys: BTreeMap<String, String>,
+}
+
+impl TrustedKeys {
+    /// `~/.config/karapace/trusted_keys`, or `None` when `HOME` is not set.
+    pub fn default_path() -> Option<PathBuf> {
+        let home = std::env::var("HOME").ok()?;
+        Some(PathBuf::from(home).join(".config/karapace/trusted_keys"))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, RemoteError> {
+        let mut keys = BTreeMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyid, label) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let keyid = keyid.to_ascii_lowercase();
+            if decode_hex::<32>(&keyid).is_none() {
+                return Err(RemoteError::Config(format!(
+                    "trusted keys line {}: '{keyid}' is not an ed25519 public key",
+                    number + 1
+                )));
+            }
+            keys.insert(keyid, label.trim().to_owned());
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RemoteError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| RemoteError::Config(format!("{}: {e}", path.display())))?;
+        Self::parse(&content)
+    }
+
+    pub fn contains(&self, keyid: &str) -> bool {
+        self.keys.contains_key(keyid)
+    }
+
+    /// Label given to `keyid`, if it is trusted.
+    pub fn label(&self, keyid: &str) -> Option<&str> {
+        self.keys.get(keyid).map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl RemoteConfig {
+    /// The trusted keys of this remote: the `trusted_keys` file if set,
+    /// otherwise `~/.config/karapace/trusted_keys` when it exists.
+    pub fn load_trusted_keys(&self) -> Result<Option<TrustedKeys>, RemoteError> {
+        if let Some(path) = &self.trusted_keys {
+            return TrustedKeys::load(path).map(Some);
+        }
+        match TrustedKeys::default_path() {
+            Some(path) if path.exists() => TrustedKeys::load(&path).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Hex-encoded public key of `key`, as used for `keyid`.
+pub fn keyid(key: &VerifyingKey) -> String {
+    encode_hex(key.as_bytes())
+}
+
+fn message(env_id: &str, metadata_hash: &str) -> Vec<u8> {
+    format!("{SIGNATURE_CONTEXT}\n{env_id}\n{metadata_hash}\n").into_bytes()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write as _;
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    #[test]
+    fn signatures_verify_only_for_trusted_keys_and_intact_metadata() {
+        let signer = key(1);
+        let signature = EnvSignature::sign("env1", b"{\"meta\": 1}", &signer);
+        let signature = EnvSignature::from_json(&signature.to_json().unwrap()).unwrap();
+        let trusted = TrustedKeys::parse(&format!(
+            "# team keys\n\n{} alice\n",
+            keyid(&signer.verifying_key())
+        ))
+        .unwrap();
+        assert_eq!(
+            signature
+                .verify("env1", b"{\"meta\": 1}", &trusted)
+                .unwrap(),
+            keyid(&signer.verifying_key())
+        );
+        assert_eq!(
+            trusted.label(&keyid(&signer.verifying_key())),
+            Some("alice")
+        );
+
+        assert!(signature
+            .verify("env1", b"{\"meta\": 2}", &trusted)
+            .is_err());
+        assert!(signature
+            .verify("env2", b"{\"meta\": 1}", &trusted)
+            .is_err());
+        let stranger = TrustedKeys::parse(&keyid(&key(2).verifying_key())).unwrap();
+        assert!(signature
+            .verify("env1", b"{\"meta\": 1}", &stranger)
+            .is_err());
+
+        let mut forged = signature.clone();
+        forged.signatures[0].sig = encode_hex(&key(2).sign(b"other").to_bytes());
+        assert!(forged.verify("env1", b"{\"meta\": 1}", &trusted).is_err());
+    }
+
+    #[test]
+    fn trusted_keys_reject_malformed_lines() {
+        assert!(TrustedKeys::parse("not-a-key\n").is_err());
+        assert!(TrustedKeys::parse("# only a comment\n").unwrap().is_empty());
+    }
+}
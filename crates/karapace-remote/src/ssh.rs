@@ -0,0 +1,454 @@
+use crate::{BlobKind, RemoteBackend, RemoteConfig, RemoteError};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Exit status the remote scripts use for a missing blob.
+const MISSING: i32 = 44;
+
+/// Remote store in a directory on any host reachable with `ssh`.
+///
+/// Configured with an `ssh://[user@]host[:port]/path` URL; `/~/path` (or
+/// no path) is relative to the remote home directory. Blobs are files under
+/// `<path>/objects/`, `<path>/layers/` and `<path>/metadata/`, the registry
+/// is `<path>/registry`. Every operation runs a short POSIX `sh` script on
+/// the remote, so it needs nothing there but a shell and coreutils.
+///
+/// The program defaults to `$KARAPACE_SSH`, then `ssh` on `PATH`. It runs in
+/// batch mode (keys or an agent, no password prompts) and shares one
+/// connection between operations through `ControlMaster`.
+#[derive(Debug, Clone)]
+pub struct SshBackend {
+    config: RemoteConfig,
+    program: PathBuf,
+    destination: String,
+    port: Option<u16>,
+    root: String,
+}
+
+impl SshBackend {
+    pub fn new(config: RemoteConfig) -> Result<Self, RemoteError> {
+        let invalid = || RemoteError::Config(format!("invalid SSH remote URL '{}'", config.url));
+        let rest = config.url.strip_prefix("ssh://").ok_or_else(invalid)?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user), host),
+            None => (None, authority),
+        };
+        let (host, port) = match host_port.strip_prefix('[') {
+            Some(bracketed) => bracketed.split_once(']').ok_or_else(invalid)?,
+            None => host_port.rsplit_once(':').unwrap_or((host_port, "")),
+        };
+        let port = match port.strip_prefix(':').unwrap_or(port) {
+            "" => None,
+            port => Some(port.parse::<u16>().map_err(|_| invalid())?),
+        };
+        if host.is_empty() || host.starts_with('-') || user.is_some_and(str::is_empty) {
+            return Err(invalid());
+        }
+        let path = path.trim_end_matches('/');
+        let root = if path.is_empty() || path == "~" {
+            ".".to_owned()
+        } else if let Some(home) = path.strip_prefix("~/") {
+            home.trim_start_matches('/').to_owned()
+        } else {
+            format!("/{path}")
+        };
+        let program = std::env::var_os("KARAPACE_SSH").map_or_else(|| "ssh".into(), PathBuf::from);
+        Ok(Self {
+            destination: user.map_or_else(|| host.to_owned(), |u| format!("{u}@{host}")),
+            port,
+            root,
+            program,
+            config,
+        })
+    }
+
+    #[must_use]
+    pub fn with_program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// The configuration this backend talks to.
+    pub fn config(&self) -> &RemoteConfig {
+        &self.config
+    }
+
+    fn kind_path(kind: BlobKind) -> &'static str {
+        match kind {
+            BlobKind::Object => "objects",
+            BlobKind::Layer => "layers",
+            BlobKind::Metadata => "metadata",
+            BlobKind::Signature => "signatures",
+            BlobKind::Seal => "seals",
+        }
+    }
+
+    fn dir(&self, kind: BlobKind) -> String {
+        format!("{}/{}", self.root, Self::kind_path(kind))
+    }
+
+    /// Blob keys are file names in the kind's directory; anything that
+    /// could name another file is refused.
+    fn blob_path(&self, kind: BlobKind, key: &str) -> Result<String, RemoteError> {
+        if key.is_empty() || key.starts_with('.') || key.contains(['/', '\0', '\n']) {
+            return Err(RemoteError::Config(format!("invalid blob key '{key}'")));
+        }
+        Ok(format!("{}/{key}", self.dir(kind)))
+    }
+
+    fn registry_path(&self) -> String {
+        format!("{}/registry", self.root)
+    }
+
+    /// Run `script` on the remote with `input` on its stdin. A script
+    /// exiting with [`MISSING`] yields `NotFound(what)`.
+    fn run(&self, script: &str, input: &[u8], what: &str) -> Result<Vec<u8>, RemoteError> {
+        let control_dir =
+            std::env::var_os("XDG_RUNTIME_DIR").map_or_else(std::env::temp_dir, PathBuf::from);
+        let mut command = Command::new(&self.program);
+        command
+            .args(["-o", "BatchMode=yes", "-o", "ControlMaster=auto", "-o"])
+            .arg(format!(
+                "ControlPath={}/karapace-ssh-%C",
+                control_dir.display()
+            ))
+            .args(["-o", "ControlPersist=60"]);
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        let mut child = command
+            .arg("--")
+            .arg(&self.destination)
+            .arg(script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                RemoteError::Ssh(format!("failed to run {}: {e}", self.program.display()))
+            })?;
+
+        // Feed stdin from a separate thread so large blobs cannot deadlock
+        // against a full stdout pipe.
+        let mut stdin = child.stdin.take();
+        let output = std::thread::scope(|s| {
+            let writer = s.spawn(move || match stdin.take() {
+                Some(mut pipe) => pipe.write_all(input),
+                None => Ok(()),
+            });
+            let output = child.wait_with_output();
+            // The remote may exit without reading its input (a missing
+            // blob); that is reported by the exit status, not the pipe.
+            let _ = writer.join();
+            output
+        })?;
+
+        match output.status.code() {
+            Some(0) => Ok(output.stdout),
+            Some(MISSING) => Err(RemoteError::NotFound(what.to_owned())),
+            _ => Err(RemoteError::Ssh(format!(
+                "{}: {} exited with {}: {}",
+                self.destination,
+                self.program.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+
+    /// Atomically replace the file at `path` with `data`.
+    fn put_file(&self, path: &str, data: &[u8], what: &str) -> Result<(), RemoteError> {
+        let (dir, name) = path.rsplit_once('/').unwrap_or((".", path));
+        tracing::debug!("SSH PUT {path} ({} bytes)", data.len());
+        let script = format!(
+            "set -e; mkdir -p {dir}; t={tmp}.$$; cat > \"$t\"; mv -f \"$t\" {path}",
+            dir = shell_quote(dir),
+            tmp = shell_quote(&format!("{dir}/.{name}.tmp")),
+            path = shell_quote(path),
+        );
+        self.run(&script, data, what).map(drop)
+    }
+
+    fn get_file(&self, path: &str, what: &str) -> Result<Vec<u8>, RemoteError> {
+        tracing::debug!("SSH GET {path}");
+        let path = shell_quote(path);
+        self.run(
+            &format!("test -f {path} || exit {MISSING}; cat {path}"),
+            &[],
+            what,
+        )
+    }
+
+    fn partial_path(&self, kind: BlobKind, key: &str) -> Result<String, RemoteError> {
+        self.blob_path(kind, key)?;
+        Ok(format!("{}/.{key}.partial", self.dir(kind)))
+    }
+}
+
+impl RemoteBackend for SshBackend {
+    fn put_blob(&self, kind: BlobKind, key: &str, data: &[u8]) -> Result<(), RemoteError> {
+        self.put_file(&self.blob_path(kind, key)?, data, key)
+    }
+
+    fn get_blob(&self, kind: BlobKind, key: &str) -> Result<Vec<u8>, RemoteError> {
+        self.get_file(&self.blob_path(kind, key)?, key)
+    }
+
+    fn has_blob(&self, kind: BlobKind, key: &str) -> Result<bool, RemoteError> {
+        match self.blob_size(kind, key) {
+            Ok(_) => Ok(true),
+            Err(RemoteError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn blob_size(&self, kind: BlobKind, key: &str) -> Result<Option<u64>, RemoteError> {
+        let path = shell_quote(&self.blob_path(kind, key)?);
+        tracing::debug!("SSH SIZE {path}");
+        let out = self.run(
+            &format!("test -f {path} || exit {MISSING}; wc -c < {path}"),
+            &[],
+            key,
+        )?;
+        Ok(String::from_utf8_lossy(&out).trim().parse().ok())
+    }
+
+    fn supports_resume(&self) -> bool {
+        true
+    }
+
+    fn upload_offset(&self, kind: BlobKind, key: &str) -> Result<u64, RemoteError> {
+        let partial = shell_quote(&self.partial_path(kind, key)?);
+        let out = self.run(
+            &format!("test -f {partial} || exit {MISSING}; wc -c < {partial}"),
+            &[],
+            key,
+        );
+        match out {
+            Ok(out) => String::from_utf8_lossy(&out).trim().parse().map_err(|_| {
+                RemoteError::Ssh(format!("unexpected size of partial upload of '{key}'"))
+            }),
+            Err(RemoteError::NotFound(_)) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put_blob_chunk(
+        &self,
+        kind: BlobKind,
+        key: &str,
+        offset: u64,
+        data: &[u8],
+        last: bool,
+    ) -> Result<(), RemoteError> {
+        let partial = self.partial_path(kind, key)?;
+        tracing::debug!("SSH APPEND {partial} at {offset} ({} bytes)", data.len());
+        let dir = shell_quote(&self.dir(kind));
+        let partial = shell_quote(&partial);
+        let mut script = if offset == 0 {
+            format!("set -e; mkdir -p {dir}; cat > {partial}")
+        } else {
+            format!(
+                "set -e; size=$(wc -c < {partial}); if [ $((size)) -ne {offset} ]; then \
+                 echo \"upload is at $((size)) bytes, not {offset}\" >&2; exit 1; fi; \
+                 cat >> {partial}"
+            )
+        };
+        if last {
+            let path = shell_quote(&self.blob_path(kind, key)?);
+            let _ = write!(script, "; mv -f {partial} {path}");
+        }
+        self.run(&script, data, key).map(drop)
+    }
+
+    fn get_blob_range(
+        &self,
+        kind: BlobKind,
+        key: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, RemoteError> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let path = shell_quote(&self.blob_path(kind, key)?);
+        tracing::debug!("SSH GET {path} ({len} bytes from {offset})");
+        self.run(
+            &format!(
+                "test -f {path} || exit {MISSING}; tail -c +{start} {path} | head -c {len}",
+                start = offset.saturating_add(1)
+            ),
+            &[],
+            key,
+        )
+    }
+
+    fn list_blobs(&self, kind: BlobKind) -> Result<Vec<String>, RemoteError> {
+        let dir = shell_quote(&self.dir(kind));
+        tracing::debug!("SSH LIST {dir}");
+        let out = self.run(
+            &format!("test -d {dir} || exit 0; cd {dir} && ls -1"),
+            &[],
+            Self::kind_path(kind),
+        )?;
+        Ok(String::from_utf8_lossy(&out)
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(str::to_owned)
+            .collect())
+    }
+
+    fn put_registry(&self, data: &[u8]) -> Result<(), RemoteError> {
+        self.put_file(&self.registry_path(), data, "registry")
+    }
+
+    fn get_registry(&self) -> Result<Vec<u8>, RemoteError> {
+        self.get_file(&self.registry_path(), "registry")
+    }
+}
+
+/// Single-quote `s` for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// An `ssh` stand-in that runs the script locally, and records the
+    /// destination it was given.
+    fn fake_ssh(dir: &std::path::Path) -> PathBuf {
+        let path = dir.join("ssh");
+        std::fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\nwhile [ \"$1\" != -- ]; do shift; done\necho \"$2\" >> '{}/destinations'\nexec sh -c \"$3\"\n",
+                dir.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn backend(dir: &std::path::Path) -> SshBackend {
+        let url = format!("ssh://builder@example.com:2222{}/store", dir.display());
+        SshBackend::new(RemoteConfig::new(&url))
+            .unwrap()
+            .with_program(fake_ssh(dir))
+    }
+
+    #[test]
+    fn parses_urls() {
+        let parse = |url: &str| {
+            SshBackend::new(RemoteConfig::new(url)).map(|b| (b.destination, b.port, b.root))
+        };
+        assert_eq!(
+            parse("ssh://me@host:2222/srv/karapace").unwrap(),
+            ("me@host".to_owned(), Some(2222), "/srv/karapace".to_owned())
+        );
+        assert_eq!(
+            parse("ssh://host/~/karapace/").unwrap(),
+            ("host".to_owned(), None, "karapace".to_owned())
+        );
+        assert_eq!(parse("ssh://host").unwrap().2, ".");
+        assert_eq!(
+            parse("ssh://[::1]:22/x").unwrap(),
+            ("::1".to_owned(), Some(22), "/x".to_owned())
+        );
+        assert!(parse("ssh://-oProxyCommand=x/path").is_err());
+        assert!(parse("ssh://host:port/path").is_err());
+        assert!(parse("https://host/path").is_err());
+    }
+
+    #[test]
+    fn ssh_blob_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = backend(dir.path());
+
+        assert!(!remote.has_blob(BlobKind::Object, "abc").unwrap());
+        assert!(matches!(
+            remote.get_blob(BlobKind::Object, "abc"),
+            Err(RemoteError::NotFound(_))
+        ));
+        assert!(remote.list_blobs(BlobKind::Object).unwrap().is_empty());
+
+        remote
+            .put_blob(BlobKind::Object, "abc", b"object data")
+            .unwrap();
+        remote
+            .put_blob(BlobKind::Layer, "def", b"it's a layer")
+            .unwrap();
+        assert!(remote.has_blob(BlobKind::Object, "abc").unwrap());
+        assert_eq!(remote.blob_size(BlobKind::Object, "abc").unwrap(), Some(11));
+        assert_eq!(
+            remote.get_blob(BlobKind::Layer, "def").unwrap(),
+            b"it's a layer"
+        );
+        assert_eq!(
+            remote
+                .get_blob_range(BlobKind::Object, "abc", 7, 100)
+                .unwrap(),
+            b"data"
+        );
+        assert_eq!(remote.list_blobs(BlobKind::Object).unwrap(), vec!["abc"]);
+        assert!(dir.path().join("store/objects/abc").is_file());
+
+        remote.put_registry(b"{\"entries\":{}}").unwrap();
+        assert_eq!(remote.get_registry().unwrap(), b"{\"entries\":{}}");
+
+        let destinations = std::fs::read_to_string(dir.path().join("destinations")).unwrap();
+        assert!(destinations.lines().all(|d| d == "builder@example.com"));
+    }
+
+    #[test]
+    fn ssh_resumes_uploads() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = backend(dir.path());
+
+        assert_eq!(remote.upload_offset(BlobKind::Object, "big").unwrap(), 0);
+        remote
+            .put_blob_chunk(BlobKind::Object, "big", 0, b"hello ", false)
+            .unwrap();
+        assert_eq!(remote.upload_offset(BlobKind::Object, "big").unwrap(), 6);
+        assert!(!remote.has_blob(BlobKind::Object, "big").unwrap());
+        assert!(remote.list_blobs(BlobKind::Object).unwrap().is_empty());
+
+        assert!(remote
+            .put_blob_chunk(BlobKind::Object, "big", 3, b"world", true)
+            .is_err());
+        remote
+            .put_blob_chunk(BlobKind::Object, "big", 6, b"world", true)
+            .unwrap();
+        assert_eq!(
+            remote.get_blob(BlobKind::Object, "big").unwrap(),
+            b"hello world"
+        );
+        assert_eq!(remote.upload_offset(BlobKind::Object, "big").unwrap(), 0);
+    }
+
+    #[test]
+    fn rejects_keys_outside_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = backend(dir.path());
+        for key in ["", "../registry", ".hidden", "a/b"] {
+            assert!(matches!(
+                remote.put_blob(BlobKind::Object, key, b"x"),
+                Err(RemoteError::Config(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn missing_program_is_an_ssh_error() {
+        let remote = SshBackend::new(RemoteConfig::new("ssh://host/store"))
+            .unwrap()
+            .with_program("/nonexistent/karapace-ssh");
+        assert!(matches!(remote.get_registry(), Err(RemoteError::Ssh(_))));
+    }
+}
@@ -1,5 +1,111 @@
-use crate::{BlobKind, Registry, RegistryEntry, RemoteBackend, RemoteError};
-use karapace_store::{LayerStore, MetadataStore, ObjectStore, StoreLayout};
+use crate::crypt::{seal_id, BlobCipher, SealRecord};
+use crate::sign::{keyid, EnvSignature, TrustedKeys};
+use crate::{BlobKind, Registry, RegistryCache, RegistryEntry, RemoteBackend, RemoteError};
+use karapace_store::chunking::{as_chunk_list, encode_chunk_list};
+use karapace_store::{
+    ChunkRef, EnvMetadata, LayerKind, LayerManifest, LayerStore, MetadataStore, ObjectStore,
+    StoreLayout,
+};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// Objects larger than this are transferred in chunks of this size when the
+/// backend supports it, so an interrupted push or pull picks up where it
+/// stopped instead of starting over.
+pub const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Objects transferred at once unless [`TransferOptions`] says otherwise.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Reported after each object of a push or pull, from whichever worker
+/// finished it.
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectProgress<'a> {
+    pub hash: &'a str,
+    /// Bytes sent or received; 0 for skipped objects.
+    pub bytes: u64,
+    /// The object was already on the other side.
+    pub skipped: bool,
+    /// Objects finished so far, including this one.
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Stage of a push or pull, reported as it starts. A push goes through
+/// objects, layers, metadata and, with a registry key, the registry; a pull
+/// through metadata, layers and objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferPhase {
+    /// Writing, or downloading and verifying, the metadata and its
+    /// signature.
+    Metadata,
+    Layers,
+    Objects,
+    /// Publishing the registry entry.
+    Registry,
+}
+
+impl TransferPhase {
+    /// Stable phase name, e.g. `"objects"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Metadata => "metadata",
+            Self::Layers => "layers",
+            Self::Objects => "objects",
+            Self::Registry => "registry",
+        }
+    }
+}
+
+/// Settings for [`push_env_with_options`] and [`pull_env_with_options`].
+#[derive(Clone, Copy)]
+pub struct TransferOptions<'a> {
+    /// Objects transferred at once. 0 and 1 both mean one at a time.
+    pub concurrency: usize,
+    /// Encrypts pushed blobs and decrypts pulled ones.
+    pub cipher: Option<&'a dyn BlobCipher>,
+    /// Called once per object, possibly from several threads at once.
+    pub on_object: Option<&'a (dyn Fn(&ObjectProgress<'_>) + Sync)>,
+    /// Called when each [`TransferPhase`] starts.
+    pub on_phase: Option<&'a (dyn Fn(TransferPhase) + Sync)>,
+    /// Signs pushed environments.
+    pub sign_with: Option<&'a ed25519_dalek::SigningKey>,
+    /// Keys whose signatures pulled environments are checked against.
+    pub trusted_keys: Option<&'a TrustedKeys>,
+    /// Refuse to pull environments without a signature by a trusted key.
+    pub require_signed: bool,
+}
+
+impl Default for TransferOptions<'_> {
+    fn default() -> Self {
+        Self {
+            concurrency: DEFAULT_CONCURRENCY,
+            cipher: None,
+            on_object: None,
+            on_phase: None,
+            sign_with: None,
+            trusted_keys: None,
+            require_signed: false,
+        }
+    }
+}
+
+impl TransferOptions<'_> {
+    fn report(&self, progress: &ObjectProgress<'_>) {
+        if let Some(on_object) = self.on_object {
+            on_object(progress);
+        }
+    }
+
+    fn phase(&self, phase: TransferPhase) {
+        if let Some(on_phase) = self.on_phase {
+            on_phase(phase);
+        }
+    }
+}
 
 /// Result of a push operation.
 #[derive(Debug)]
@@ -8,6 +114,11 @@ pub struct PushResult {
     pub layers_pushed: usize,
     pub objects_skipped: usize,
     pub layers_skipped: usize,
+    /// Chunks of chunked objects uploaded, and those the remote already had.
+    pub chunks_pushed: usize,
+    pub chunks_skipped: usize,
+    /// Key the environment was signed with, when signing.
+    pub signed_by: Option<String>,
 }
 
 /// Result of a pull operation.
@@ -17,6 +128,67 @@ pub struct PullResult {
     pub layers_pulled: usize,
     pub objects_skipped: usize,
     pub layers_skipped: usize,
+    /// Chunks of chunked objects downloaded, and those already stored locally.
+    pub chunks_pulled: usize,
+    pub chunks_skipped: usize,
+    /// Trusted key whose signature of the environment verified.
+    pub signed_by: Option<String>,
+}
+
+/// Chunks moved and skipped across the workers of one transfer.
+#[derive(Default)]
+struct ChunkCounts {
+    transferred: AtomicUsize,
+    skipped: AtomicUsize,
+}
+
+/// How an encrypted environment's blobs are stored: under `<key>.<id>`,
+/// encrypted for the keys the id stands for. Unencrypted environments have
+/// none, and their blobs are stored under their own keys and never
+/// decrypted.
+struct Seal<'a> {
+    id: String,
+    cipher: Option<&'a dyn BlobCipher>,
+}
+
+impl<'a> Seal<'a> {
+    /// The seal `cipher` encrypts pushed blobs under.
+    fn for_push(cipher: Option<&'a dyn BlobCipher>) -> Result<Option<Self>, RemoteError> {
+        let Some(c) = cipher else {
+            return Ok(None);
+        };
+        let id = seal_id(&c.fingerprints())
+            .ok_or_else(|| RemoteError::Encryption("no age recipients configured".to_owned()))?;
+        Ok(Some(Self {
+            id,
+            cipher: Some(c),
+        }))
+    }
+
+    fn seal(&self, data: &[u8]) -> Result<Vec<u8>, RemoteError> {
+        match self.cipher {
+            Some(c) => c.encrypt(data),
+            None => Err(RemoteError::Encryption(
+                "no cipher to encrypt with".to_owned(),
+            )),
+        }
+    }
+}
+
+/// The remote key of blob `key` under `seal`.
+fn remote_key(seal: Option<&Seal<'_>>, key: &str) -> String {
+    match seal {
+        Some(seal) => format!("{key}.{}", seal.id),
+        None => key.to_owned(),
+    }
+}
+
+/// Encrypt `data` under `seal`, if any.
+fn seal_blob(seal: Option<&Seal<'_>>, data: Vec<u8>) -> Result<Vec<u8>, RemoteError> {
+    match seal {
+        Some(seal) => seal.seal(&data),
+        None => Ok(data),
+    }
 }
 
 /// Push an environment (metadata + layers + objects) to a remote store.
@@ -27,6 +199,68 @@ pub fn push_env(
     backend: &dyn RemoteBackend,
     registry_key: Option<&str>,
 ) -> Result<PushResult, RemoteError> {
+    push_env_with_cipher(layout, env_id, backend, registry_key, None)
+}
+
+/// Like [`push_env`], but encrypts every uploaded blob with `cipher`.
+///
+/// Encrypted blobs are stored under their plaintext hash suffixed with an
+/// id of the recipients (see [`seal_id`]), so blobs already on the remote
+/// are only skipped when they were encrypted for the same recipients.
+/// Plaintext copies from earlier unencrypted pushes are left in place.
+pub fn push_env_with_cipher(
+    layout: &StoreLayout,
+    env_id: &str,
+    backend: &dyn RemoteBackend,
+    registry_key: Option<&str>,
+    cipher: Option<&dyn BlobCipher>,
+) -> Result<PushResult, RemoteError> {
+    let options = TransferOptions {
+        cipher,
+        ..TransferOptions::default()
+    };
+    push_env_with_options(layout, env_id, backend, registry_key, &options)
+}
+
+/// Upload the layers in `hashes` the remote does not have yet, returning
+/// how many were uploaded.
+fn push_layers(
+    backend: &dyn RemoteBackend,
+    layer_store: &LayerStore,
+    hashes: &[impl AsRef<str>],
+    seal: Option<&Seal<'_>>,
+) -> Result<usize, RemoteError> {
+    let mut pushed = 0;
+    for lh in hashes {
+        let key = remote_key(seal, lh.as_ref());
+        if backend.has_blob(BlobKind::Layer, &key)? {
+            continue;
+        }
+        let layer = layer_store.get(lh.as_ref())?;
+        let data = seal_blob(
+            seal,
+            serde_json::to_vec_pretty(&layer)
+                .map_err(|e| RemoteError::Serialization(e.to_string()))?,
+        )?;
+        backend.put_blob(BlobKind::Layer, &key, &data)?;
+        pushed += 1;
+    }
+    Ok(pushed)
+}
+
+/// Like [`push_env`], uploading up to `options.concurrency` objects at once.
+/// Layers, metadata and the registry entry are written after every object
+/// has been uploaded.
+pub fn push_env_with_options(
+    layout: &StoreLayout,
+    env_id: &str,
+    backend: &dyn RemoteBackend,
+    registry_key: Option<&str>,
+    options: &TransferOptions<'_>,
+) -> Result<PushResult, RemoteError> {
+    let cipher = options.cipher;
+    let seal = Seal::for_push(cipher)?;
+    let seal = seal.as_ref();
     let meta_store = MetadataStore::new(layout.clone());
     let layer_store = LayerStore::new(layout.clone());
     let object_store = ObjectStore::new(layout.clone());
@@ -40,11 +274,8 @@ pub fn push_env(
     let mut layer_hashes = vec![meta.base_layer.clone()];
     layer_hashes.extend(meta.dependency_layers.iter().cloned());
 
-    // 3. Collect all object hashes from layers + manifest
-    let mut object_hashes = Vec::new();
-    if !meta.manifest_hash.is_empty() {
-        object_hashes.push(meta.manifest_hash.to_string());
-    }
+    // 3. Collect all object hashes from layers, manifest and attestation
+    let mut object_hashes = meta.direct_objects();
     for lh in &layer_hashes {
         let layer = layer_store.get(lh)?;
         object_hashes.extend(layer.object_refs.iter().cloned());
@@ -52,55 +283,87 @@ pub fn push_env(
     object_hashes.sort();
     object_hashes.dedup();
 
-    // 4. Push objects (skip existing)
-    let mut objects_pushed = 0;
-    let mut objects_skipped = 0;
-    for hash in &object_hashes {
-        if backend.has_blob(BlobKind::Object, hash)? {
-            objects_skipped += 1;
-            continue;
+    // 4. Push objects (skip existing). A chunked object goes up as its
+    // chunk list, after the chunks the remote does not have yet.
+    options.phase(TransferPhase::Objects);
+    let objects_pushed = AtomicUsize::new(0);
+    let chunks = ChunkCounts::default();
+    let done = AtomicUsize::new(0);
+    for_each_parallel(&object_hashes, options.concurrency, |hash| {
+        let mut bytes = 0;
+        let key = remote_key(seal, hash);
+        let skipped = backend.has_blob(BlobKind::Object, &key)?;
+        if !skipped {
+            let data = match object_store.chunk_list(hash)? {
+                Some(list) => {
+                    bytes += push_chunks(backend, &object_store, &list, seal, &chunks)?;
+                    seal_blob(seal, encode_chunk_list(&list))?
+                }
+                None => seal_blob(seal, object_store.get(hash)?)?,
+            };
+            // Encrypted output differs on every run, so a half-finished
+            // upload from an earlier push cannot be continued.
+            upload_blob(
+                backend,
+                BlobKind::Object,
+                &key,
+                &data,
+                seal.is_none(),
+                CHUNK_SIZE,
+            )?;
+            objects_pushed.fetch_add(1, Ordering::Relaxed);
+            bytes += data.len() as u64;
         }
-        let data = object_store.get(hash)?;
-        backend.put_blob(BlobKind::Object, hash, &data)?;
-        objects_pushed += 1;
-    }
+        options.report(&ObjectProgress {
+            hash,
+            bytes,
+            skipped,
+            done: done.fetch_add(1, Ordering::Relaxed) + 1,
+            total: object_hashes.len(),
+        });
+        Ok(())
+    })?;
+    let objects_pushed = objects_pushed.into_inner();
+    let objects_skipped = object_hashes.len() - objects_pushed;
 
     // 5. Push layers (skip existing)
-    let mut layers_pushed = 0;
-    let mut layers_skipped = 0;
-    for lh in &layer_hashes {
-        if backend.has_blob(BlobKind::Layer, lh)? {
-            layers_skipped += 1;
-            continue;
-        }
-        let layer = layer_store.get(lh)?;
-        let data = serde_json::to_vec_pretty(&layer)
-            .map_err(|e| RemoteError::Serialization(e.to_string()))?;
-        backend.put_blob(BlobKind::Layer, lh, &data)?;
-        layers_pushed += 1;
+    options.phase(TransferPhase::Layers);
+    let layers_pushed = push_layers(backend, &layer_store, &layer_hashes, seal)?;
+    let layers_skipped = layer_hashes.len() - layers_pushed;
+
+    // 6. Push metadata, and its signature. The signature covers the
+    // plaintext, so it is checked after decryption on pull.
+    options.phase(TransferPhase::Metadata);
+    let signature = options
+        .sign_with
+        .map(|key| EnvSignature::sign(env_id, &meta_json, key));
+    backend.put_blob(
+        BlobKind::Metadata,
+        &remote_key(seal, env_id),
+        &seal_blob(seal, meta_json)?,
+    )?;
+    if let Some(signature) = &signature {
+        backend.put_blob(BlobKind::Signature, env_id, &signature.to_json()?)?;
     }
 
-    // 6. Push metadata
-    backend.put_blob(BlobKind::Metadata, env_id, &meta_json)?;
+    // 7. Record how the environment is encrypted, once all of it is in
+    // place.
+    let key_fingerprints = write_seal_record(backend, env_id, seal)?;
 
-    // 7. Update registry if key provided
+    // 8. Update registry if key provided
     if let Some(key) = registry_key {
-        let mut registry = match backend.get_registry() {
-            Ok(data) => Registry::from_bytes(&data)?,
-            Err(RemoteError::NotFound(_)) => Registry::new(),
-            Err(e) => return Err(e),
-        };
-        registry.publish(
+        options.phase(TransferPhase::Registry);
+        publish_entry(
+            backend,
             key,
             RegistryEntry {
                 env_id: meta.env_id.to_string(),
                 short_id: meta.short_id.to_string(),
                 name: meta.name.clone(),
                 pushed_at: chrono::Utc::now().to_rfc3339(),
+                key_fingerprints,
             },
-        );
-        let reg_bytes = registry.to_bytes()?;
-        backend.put_registry(&reg_bytes)?;
+        )?;
     }
 
     Ok(PushResult {
@@ -108,49 +371,102 @@ pub fn push_env(
         layers_pushed,
         objects_skipped,
         layers_skipped,
+        chunks_pushed: chunks.transferred.into_inner(),
+        chunks_skipped: chunks.skipped.into_inner(),
+        signed_by: options.sign_with.map(|key| keyid(&key.verifying_key())),
     })
 }
 
+/// Publish `entry` under `key` in the remote's registry.
+fn publish_entry(
+    backend: &dyn RemoteBackend,
+    key: &str,
+    entry: RegistryEntry,
+) -> Result<(), RemoteError> {
+    let mut registry = match backend.get_registry() {
+        Ok(data) => Registry::from_bytes(&data)?,
+        Err(RemoteError::NotFound(_)) => Registry::new(),
+        Err(e) => return Err(e),
+    };
+    registry.publish(key, entry);
+    backend.put_registry(&registry.to_bytes()?)
+}
+
+/// Write the [`SealRecord`] of `env_id`, returning its fingerprints. An
+/// unencrypted push only clears the record of an earlier encrypted one, so
+/// remotes predating seal records are never asked to store one.
+fn write_seal_record(
+    backend: &dyn RemoteBackend,
+    env_id: &str,
+    seal: Option<&Seal<'_>>,
+) -> Result<Vec<String>, RemoteError> {
+    let record = SealRecord {
+        key_fingerprints: seal
+            .and_then(|seal| seal.cipher)
+            .map(BlobCipher::fingerprints)
+            .unwrap_or_default(),
+    };
+    if seal.is_some() || matches!(backend.has_blob(BlobKind::Seal, env_id), Ok(true)) {
+        backend.put_blob(BlobKind::Seal, env_id, &record.to_json()?)?;
+    }
+    Ok(record.key_fingerprints)
+}
+
 /// Pull an environment from a remote store into the local store.
 pub fn pull_env(
     layout: &StoreLayout,
     env_id: &str,
     backend: &dyn RemoteBackend,
+) -> Result<PullResult, RemoteError> {
+    pull_env_with_cipher(layout, env_id, backend, None)
+}
+
+/// Like [`pull_env`], but decrypts with `cipher` when the environment's
+/// [`SealRecord`] says it was pushed encrypted. Unencrypted environments
+/// are pulled as they are either way.
+pub fn pull_env_with_cipher(
+    layout: &StoreLayout,
+    env_id: &str,
+    backend: &dyn RemoteBackend,
+    cipher: Option<&dyn BlobCipher>,
+) -> Result<PullResult, RemoteError> {
+    let options = TransferOptions {
+        cipher,
+        ..TransferOptions::default()
+    };
+    pull_env_with_options(layout, env_id, backend, &options)
+}
+
+/// Like [`pull_env`], downloading up to `options.concurrency` objects at
+/// once. Each object is verified before it is stored, and the metadata is
+/// only written once every object is in place.
+pub fn pull_env_with_options(
+    layout: &StoreLayout,
+    env_id: &str,
+    backend: &dyn RemoteBackend,
+    options: &TransferOptions<'_>,
 ) -> Result<PullResult, RemoteError> {
     let meta_store = MetadataStore::new(layout.clone());
     let layer_store = LayerStore::new(layout.clone());
     let object_store = ObjectStore::new(layout.clone());
 
-    // 1. Download metadata and verify checksum if present
-    let meta_bytes = backend.get_blob(BlobKind::Metadata, env_id)?;
-    let meta: karapace_store::EnvMetadata = serde_json::from_slice(&meta_bytes)
-        .map_err(|e| RemoteError::Serialization(format!("invalid metadata: {e}")))?;
-    if let Some(ref expected) = meta.checksum {
-        let mut copy = meta.clone();
-        copy.checksum = None;
-        let json = serde_json::to_string_pretty(&copy)
-            .map_err(|e| RemoteError::Serialization(e.to_string()))?;
-        let actual = blake3::hash(json.as_bytes()).to_hex().to_string();
-        if actual != *expected {
-            return Err(RemoteError::IntegrityFailure {
-                key: format!("metadata:{env_id}"),
-                expected: expected.clone(),
-                actual,
-            });
-        }
-    }
+    // 1. Download metadata and verify checksum if present, then its
+    // signature, before anything is stored
+    options.phase(TransferPhase::Metadata);
+    let seal = fetch_seal(env_id, backend, options.cipher)?;
+    let seal = seal.as_ref();
+    let (meta, meta_bytes) = fetch_metadata(env_id, backend, seal)?;
+    let signed_by = check_signature(env_id, &meta_bytes, backend, options)?;
 
     // 2. Collect layer hashes
     let mut layer_hashes = vec![meta.base_layer.clone()];
     layer_hashes.extend(meta.dependency_layers.iter().cloned());
 
     // 3. Download layers (skip existing)
+    options.phase(TransferPhase::Layers);
     let mut layers_pulled = 0;
     let mut layers_skipped = 0;
-    let mut object_hashes = Vec::new();
-    if !meta.manifest_hash.is_empty() {
-        object_hashes.push(meta.manifest_hash.to_string());
-    }
+    let mut object_hashes = meta.direct_objects();
     for lh in &layer_hashes {
         if layer_store.exists(lh) {
             let layer = layer_store.get(lh)?;
@@ -158,71 +474,721 @@ pub fn pull_env(
             layers_skipped += 1;
             continue;
         }
-        let data = backend.get_blob(BlobKind::Layer, lh)?;
-        let layer: karapace_store::LayerManifest = serde_json::from_slice(&data)
-            .map_err(|e| RemoteError::Serialization(format!("invalid layer: {e}")))?;
+        let layer = pull_layer(backend, &layer_store, lh, seal)?;
         object_hashes.extend(layer.object_refs.iter().cloned());
-        let stored_hash = layer_store.put(&layer)?;
-        if stored_hash != **lh {
-            return Err(RemoteError::IntegrityFailure {
-                key: lh.to_string(),
-                expected: lh.to_string(),
-                actual: stored_hash,
-            });
-        }
         layers_pulled += 1;
     }
     object_hashes.sort();
     object_hashes.dedup();
 
-    // 4. Download objects (skip existing, verify blake3 integrity)
-    let mut objects_pulled = 0;
-    let mut objects_skipped = 0;
+    // 4. Download objects (skip existing, verify blake3 integrity). Of a
+    // chunked object, only the chunks missing locally are downloaded.
+    options.phase(TransferPhase::Objects);
+    let objects_pulled = AtomicUsize::new(0);
+    let chunks = ChunkCounts::default();
+    let done = AtomicUsize::new(0);
+    for_each_parallel(&object_hashes, options.concurrency, |hash| {
+        let mut bytes = 0;
+        let skipped = object_store.exists(hash);
+        if !skipped {
+            bytes = pull_object(layout, backend, &object_store, hash, seal, &chunks)?;
+            objects_pulled.fetch_add(1, Ordering::Relaxed);
+        }
+        options.report(&ObjectProgress {
+            hash,
+            bytes,
+            skipped,
+            done: done.fetch_add(1, Ordering::Relaxed) + 1,
+            total: object_hashes.len(),
+        });
+        Ok(())
+    })?;
+    let objects_pulled = objects_pulled.into_inner();
+    let objects_skipped = object_hashes.len() - objects_pulled;
+
+    // 5. Store metadata locally
+    meta_store.overwrite(&meta)?;
+
+    Ok(PullResult {
+        objects_pulled,
+        layers_pulled,
+        objects_skipped,
+        layers_skipped,
+        chunks_pulled: chunks.transferred.into_inner(),
+        chunks_skipped: chunks.skipped.into_inner(),
+        signed_by,
+    })
+}
+
+/// One layer of a remote environment, as reported by [`peek_env`].
+#[derive(Debug, Clone)]
+pub struct LayerPeek {
+    pub hash: String,
+    pub kind: LayerKind,
+    pub object_count: usize,
+    /// Remote size of the layer's objects. `None` when the backend does not
+    /// report blob sizes.
+    pub size: Option<u64>,
+    /// The layer manifest is already in the local store.
+    pub local: bool,
+}
+
+/// What pulling an environment would bring in, read from its metadata,
+/// manifest and layer manifests without downloading any layer content.
+#[derive(Debug, Clone)]
+pub struct EnvPeek {
+    pub env_id: String,
+    pub short_id: String,
+    pub name: Option<String>,
+    pub base_image: Option<String>,
+    /// Recorded at build time; `None` for environments built before it was.
+    pub base_image_digest: Option<String>,
+    pub packages: Vec<String>,
+    pub layers: Vec<LayerPeek>,
+    pub objects_total: usize,
+    /// Objects not yet in the local store.
+    pub objects_missing: usize,
+    /// Remote size of the missing objects, when the backend reports sizes.
+    pub download_bytes: Option<u64>,
+}
+
+/// Describe what [`pull_env`] would download for `env_id` without pulling it.
+pub fn peek_env(
+    layout: &StoreLayout,
+    env_id: &str,
+    backend: &dyn RemoteBackend,
+) -> Result<EnvPeek, RemoteError> {
+    peek_env_with_cipher(layout, env_id, backend, None)
+}
+
+/// Like [`peek_env`], but decrypts the metadata and manifests of an
+/// encrypted environment with `cipher`.
+pub fn peek_env_with_cipher(
+    layout: &StoreLayout,
+    env_id: &str,
+    backend: &dyn RemoteBackend,
+    cipher: Option<&dyn BlobCipher>,
+) -> Result<EnvPeek, RemoteError> {
+    let layer_store = LayerStore::new(layout.clone());
+    let object_store = ObjectStore::new(layout.clone());
+
+    let seal = fetch_seal(env_id, backend, cipher)?;
+    let seal = seal.as_ref();
+    let (meta, _) = fetch_metadata(env_id, backend, seal)?;
+
+    let (base_image, packages) = fetch_manifest_summary(&meta, backend, seal)?;
+
+    let mut layer_hashes = vec![meta.base_layer.clone()];
+    layer_hashes.extend(meta.dependency_layers.iter().cloned());
+
+    let mut sizes: HashMap<String, Option<u64>> = HashMap::new();
+    let mut object_size = |hash: &str| -> Result<Option<u64>, RemoteError> {
+        if let Some(size) = sizes.get(hash) {
+            return Ok(*size);
+        }
+        let size = backend.blob_size(BlobKind::Object, &remote_key(seal, hash))?;
+        sizes.insert(hash.to_owned(), size);
+        Ok(size)
+    };
+
+    let mut layers = Vec::with_capacity(layer_hashes.len());
+    let mut object_hashes = meta.direct_objects();
+    for lh in &layer_hashes {
+        let local = layer_store.exists(lh);
+        let layer = if local {
+            layer_store.get(lh)?
+        } else {
+            let data = open_blob(
+                seal,
+                &format!("layer:{lh}"),
+                backend.get_blob(BlobKind::Layer, &remote_key(seal, lh))?,
+            )?;
+            serde_json::from_slice::<LayerManifest>(&data)
+                .map_err(|e| RemoteError::Serialization(format!("invalid layer: {e}")))?
+        };
+        let mut size = Some(0u64);
+        for hash in &layer.object_refs {
+            size = match (size, object_size(hash)?) {
+                (Some(total), Some(s)) => Some(total + s),
+                _ => None,
+            };
+        }
+        object_hashes.extend(layer.object_refs.iter().cloned());
+        layers.push(LayerPeek {
+            hash: lh.to_string(),
+            kind: layer.kind,
+            object_count: layer.object_refs.len(),
+            size,
+            local,
+        });
+    }
+    object_hashes.sort();
+    object_hashes.dedup();
+
+    let mut objects_missing = 0;
+    let mut download_bytes = Some(0u64);
     for hash in &object_hashes {
         if object_store.exists(hash) {
-            objects_skipped += 1;
             continue;
         }
-        let data = backend.get_blob(BlobKind::Object, hash)?;
+        objects_missing += 1;
+        download_bytes = match (download_bytes, object_size(hash)?) {
+            (Some(total), Some(s)) => Some(total + s),
+            _ => None,
+        };
+    }
+
+    Ok(EnvPeek {
+        env_id: meta.env_id.to_string(),
+        short_id: meta.short_id.to_string(),
+        name: meta.name.clone(),
+        base_image,
+        base_image_digest: meta.base_image_digest.clone(),
+        packages,
+        layers,
+        objects_total: object_hashes.len(),
+        objects_missing,
+        download_bytes,
+    })
+}
+
+/// Base image and package list from an environment's manifest object, which
+/// is small enough to fetch when peeking.
+fn fetch_manifest_summary(
+    meta: &EnvMetadata,
+    backend: &dyn RemoteBackend,
+    seal: Option<&Seal<'_>>,
+) -> Result<(Option<String>, Vec<String>), RemoteError> {
+    let manifest: Option<serde_json::Value> = if meta.manifest_hash.is_empty() {
+        None
+    } else {
+        let hash = meta.manifest_hash.to_string();
+        let data = open_blob(
+            seal,
+            &hash,
+            backend.get_blob(BlobKind::Object, &remote_key(seal, &hash))?,
+        )?;
         let actual = blake3::hash(&data).to_hex().to_string();
-        if actual != *hash {
+        if actual != hash {
             return Err(RemoteError::IntegrityFailure {
                 key: hash.clone(),
-                expected: hash.clone(),
+                expected: hash,
                 actual,
             });
         }
+        serde_json::from_slice(&data).ok()
+    };
+    let base_image = manifest
+        .as_ref()
+        .and_then(|m| m.get("base_image"))
+        .and_then(serde_json::Value::as_str)
+        .map(str::to_owned);
+    let packages = manifest
+        .as_ref()
+        .and_then(|m| m.get("system_packages"))
+        .and_then(serde_json::Value::as_array)
+        .map(|a| {
+            a.iter()
+                .filter_map(serde_json::Value::as_str)
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok((base_image, packages))
+}
+
+/// Run `f` over `items` on up to `concurrency` scoped threads. After the
+/// first error no new items are started, and that error is returned once
+/// the running ones finish.
+fn for_each_parallel<T: Sync>(
+    items: &[T],
+    concurrency: usize,
+    f: impl Fn(&T) -> Result<(), RemoteError> + Sync,
+) -> Result<(), RemoteError> {
+    let workers = concurrency.clamp(1, items.len().max(1));
+    if workers == 1 {
+        return items.iter().try_for_each(f);
+    }
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let first_error = Mutex::new(None);
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while !failed.load(Ordering::Relaxed) {
+                    let Some(item) = items.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    if let Err(e) = f(item) {
+                        failed.store(true, Ordering::Relaxed);
+                        first_error
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .get_or_insert(e);
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    match first_error
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+    {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Upload a blob, in `chunk_size` pieces when it is larger than that and the
+/// backend can resume. With `resume` set, an unfinished upload of the same
+/// key continues from the offset the remote reports.
+fn upload_blob(
+    backend: &dyn RemoteBackend,
+    kind: BlobKind,
+    key: &str,
+    data: &[u8],
+    resume: bool,
+    chunk_size: usize,
+) -> Result<(), RemoteError> {
+    if data.len() <= chunk_size || !backend.supports_resume() {
+        return backend.put_blob(kind, key, data);
+    }
+    let mut offset = if resume {
+        usize::try_from(backend.upload_offset(kind, key)?).unwrap_or(usize::MAX)
+    } else {
+        0
+    };
+    if offset > data.len() {
+        offset = 0;
+    } else if offset > 0 {
+        tracing::info!("resuming upload of {key} at {offset}/{} bytes", data.len());
+    }
+    loop {
+        let end = (offset + chunk_size).min(data.len());
+        let last = end == data.len();
+        backend.put_blob_chunk(kind, key, offset as u64, &data[offset..end], last)?;
+        if last {
+            return Ok(());
+        }
+        offset = end;
+    }
+}
+
+/// Download a blob, in `chunk_size` ranges when the remote reports it as
+/// larger than that. Ranges are appended to a partial file in the staging
+/// directory, so a pull that was cut off resumes from what it already has.
+fn download_blob(
+    layout: &StoreLayout,
+    backend: &dyn RemoteBackend,
+    kind: BlobKind,
+    key: &str,
+    chunk_size: usize,
+) -> Result<Vec<u8>, RemoteError> {
+    let size = match backend.blob_size(kind, key)? {
+        Some(size) if size > chunk_size as u64 => size,
+        _ => return backend.get_blob(kind, key),
+    };
+    let staging = layout.staging_dir();
+    fs::create_dir_all(&staging)?;
+    let partial = staging.join(format!("pull-{key}.partial"));
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&partial)?;
+    let mut offset = file.metadata()?.len();
+    if offset > size {
+        file.set_len(0)?;
+        offset = 0;
+    } else if offset > 0 {
+        tracing::info!("resuming download of {key} at {offset}/{size} bytes");
+    }
+    while offset < size {
+        let chunk = backend.get_blob_range(kind, key, offset, chunk_size as u64)?;
+        if chunk.is_empty() {
+            return Err(RemoteError::Http(format!(
+                "remote blob '{key}' ended at {offset} of {size} bytes"
+            )));
+        }
+        file.write_all(&chunk)?;
+        offset += chunk.len() as u64;
+    }
+    drop(file);
+    let data = fs::read(&partial)?;
+    fs::remove_file(&partial)?;
+    Ok(data)
+}
+
+/// Download object `hash` from `backend` into the store at `layout`, in
+/// place of a missing or damaged copy. Of a chunked object, only the
+/// chunks missing locally are downloaded.
+///
+/// With a `cipher`, the copy encrypted for its recipients is tried first,
+/// then the plaintext one.
+pub fn fetch_object(
+    layout: &StoreLayout,
+    backend: &dyn RemoteBackend,
+    hash: &str,
+    cipher: Option<&dyn BlobCipher>,
+) -> Result<(), RemoteError> {
+    let object_store = ObjectStore::new(layout.clone());
+    with_seal_fallback(cipher, |seal| {
+        pull_object(
+            layout,
+            backend,
+            &object_store,
+            hash,
+            seal,
+            &ChunkCounts::default(),
+        )
+    })?;
+    Ok(())
+}
+
+/// Download layer manifest `hash` from `backend` into the store at
+/// `layout`, in place of a missing or damaged copy. Copies are tried as
+/// in [`fetch_object`].
+pub fn fetch_layer(
+    layout: &StoreLayout,
+    backend: &dyn RemoteBackend,
+    hash: &str,
+    cipher: Option<&dyn BlobCipher>,
+) -> Result<LayerManifest, RemoteError> {
+    let layer_store = LayerStore::new(layout.clone());
+    with_seal_fallback(cipher, |seal| pull_layer(backend, &layer_store, hash, seal))
+}
+
+/// Run `fetch` with the seal of `cipher`'s recipients, and again without
+/// one if the encrypted copy is not on the remote.
+fn with_seal_fallback<T>(
+    cipher: Option<&dyn BlobCipher>,
+    fetch: impl Fn(Option<&Seal<'_>>) -> Result<T, RemoteError>,
+) -> Result<T, RemoteError> {
+    let seal = cipher.and_then(|c| {
+        seal_id(&c.fingerprints()).map(|id| Seal {
+            id,
+            cipher: Some(c),
+        })
+    });
+    match seal {
+        Some(seal) => match fetch(Some(&seal)) {
+            Err(RemoteError::NotFound(_)) => fetch(None),
+            result => result,
+        },
+        None => fetch(None),
+    }
+}
+
+/// Download layer manifest `hash` and store it, checking that it is
+/// stored under `hash`.
+fn pull_layer(
+    backend: &dyn RemoteBackend,
+    layer_store: &LayerStore,
+    hash: &str,
+    seal: Option<&Seal<'_>>,
+) -> Result<LayerManifest, RemoteError> {
+    let data = open_blob(
+        seal,
+        &format!("layer:{hash}"),
+        backend.get_blob(BlobKind::Layer, &remote_key(seal, hash))?,
+    )?;
+    let layer: LayerManifest = serde_json::from_slice(&data)
+        .map_err(|e| RemoteError::Serialization(format!("invalid layer: {e}")))?;
+    let stored_hash = layer_store.put(&layer)?;
+    if stored_hash != hash {
+        return Err(RemoteError::IntegrityFailure {
+            key: hash.to_owned(),
+            expected: hash.to_owned(),
+            actual: stored_hash,
+        });
+    }
+    Ok(layer)
+}
+
+/// Download object `hash` and store it, verified. Returns the bytes
+/// received.
+fn pull_object(
+    layout: &StoreLayout,
+    backend: &dyn RemoteBackend,
+    object_store: &ObjectStore,
+    hash: &str,
+    seal: Option<&Seal<'_>>,
+    chunks: &ChunkCounts,
+) -> Result<u64, RemoteError> {
+    let data = open_blob(
+        seal,
+        hash,
+        download_blob(
+            layout,
+            backend,
+            BlobKind::Object,
+            &remote_key(seal, hash),
+            CHUNK_SIZE,
+        )?,
+    )?;
+    if let Some(list) = as_chunk_list(hash, &data) {
+        let bytes = pull_chunks(layout, backend, object_store, &list, seal, chunks)?;
+        // Hashes the reassembled object before recording it.
+        object_store.put_chunk_list(hash, &list)?;
+        Ok(bytes)
+    } else {
+        verify_blob(hash, &data)?;
         object_store.put(&data)?;
-        objects_pulled += 1;
+        Ok(data.len() as u64)
     }
+}
 
-    // 5. Store metadata locally
-    meta_store.put(&meta)?;
+/// Upload the chunks in `list` the remote does not have. Returns the bytes
+/// sent.
+fn push_chunks(
+    backend: &dyn RemoteBackend,
+    object_store: &ObjectStore,
+    list: &[ChunkRef],
+    seal: Option<&Seal<'_>>,
+    counts: &ChunkCounts,
+) -> Result<u64, RemoteError> {
+    let mut bytes = 0;
+    for chunk in list {
+        let key = remote_key(seal, &chunk.hash);
+        if backend.has_blob(BlobKind::Object, &key)? {
+            counts.skipped.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        let data = seal_blob(seal, object_store.get(&chunk.hash)?)?;
+        upload_blob(
+            backend,
+            BlobKind::Object,
+            &key,
+            &data,
+            seal.is_none(),
+            CHUNK_SIZE,
+        )?;
+        counts.transferred.fetch_add(1, Ordering::Relaxed);
+        bytes += data.len() as u64;
+    }
+    Ok(bytes)
+}
 
-    Ok(PullResult {
-        objects_pulled,
-        layers_pulled,
-        objects_skipped,
-        layers_skipped,
-    })
+/// Download and store the chunks in `list` missing from the local store.
+/// Returns the bytes received.
+fn pull_chunks(
+    layout: &StoreLayout,
+    backend: &dyn RemoteBackend,
+    object_store: &ObjectStore,
+    list: &[ChunkRef],
+    seal: Option<&Seal<'_>>,
+    counts: &ChunkCounts,
+) -> Result<u64, RemoteError> {
+    let mut bytes = 0;
+    for chunk in list {
+        if object_store.exists(&chunk.hash) {
+            counts.skipped.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        let data = open_blob(
+            seal,
+            &chunk.hash,
+            download_blob(
+                layout,
+                backend,
+                BlobKind::Object,
+                &remote_key(seal, &chunk.hash),
+                CHUNK_SIZE,
+            )?,
+        )?;
+        verify_blob(&chunk.hash, &data)?;
+        object_store.put_unchunked(&data)?;
+        counts.transferred.fetch_add(1, Ordering::Relaxed);
+        bytes += data.len() as u64;
+    }
+    Ok(bytes)
+}
+
+/// Check downloaded object content against the hash it was stored under.
+fn verify_blob(hash: &str, data: &[u8]) -> Result<(), RemoteError> {
+    let actual = blake3::hash(data).to_hex().to_string();
+    if actual != hash {
+        return Err(RemoteError::IntegrityFailure {
+            key: hash.to_owned(),
+            expected: hash.to_owned(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Decrypt `data` if it was stored under `seal`. Blobs without one are
+/// plaintext whatever they contain.
+fn open_blob(seal: Option<&Seal<'_>>, key: &str, data: Vec<u8>) -> Result<Vec<u8>, RemoteError> {
+    match seal.map(|seal| seal.cipher) {
+        None => Ok(data),
+        Some(Some(c)) => c.decrypt(&data),
+        Some(None) => Err(RemoteError::Encryption(format!(
+            "'{key}' is age-encrypted; an age identity is required to pull it"
+        ))),
+    }
+}
+
+/// How `env_id` is encrypted on the remote, from its [`SealRecord`]. `None`
+/// when it is not, including on remotes that predate seal records.
+fn fetch_seal<'a>(
+    env_id: &str,
+    backend: &dyn RemoteBackend,
+    cipher: Option<&'a dyn BlobCipher>,
+) -> Result<Option<Seal<'a>>, RemoteError> {
+    let record = match backend.get_blob(BlobKind::Seal, env_id) {
+        Ok(data) => SealRecord::from_json(&data)?,
+        Err(RemoteError::NotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(record.seal_id().map(|id| Seal { id, cipher }))
+}
+
+/// Download an environment's metadata and verify its checksum if present.
+/// Returns the metadata and the plaintext it was parsed from.
+fn fetch_metadata(
+    env_id: &str,
+    backend: &dyn RemoteBackend,
+    seal: Option<&Seal<'_>>,
+) -> Result<(EnvMetadata, Vec<u8>), RemoteError> {
+    let meta_bytes = open_blob(
+        seal,
+        &format!("metadata:{env_id}"),
+        backend.get_blob(BlobKind::Metadata, &remote_key(seal, env_id))?,
+    )?;
+    let meta: EnvMetadata = serde_json::from_slice(&meta_bytes)
+        .map_err(|e| RemoteError::Serialization(format!("invalid metadata: {e}")))?;
+    if let Some(ref expected) = meta.checksum {
+        let mut copy = meta.clone();
+        copy.checksum = None;
+        let json = serde_json::to_string_pretty(&copy)
+            .map_err(|e| RemoteError::Serialization(e.to_string()))?;
+        let actual = blake3::hash(json.as_bytes()).to_hex().to_string();
+        if actual != *expected {
+            return Err(RemoteError::IntegrityFailure {
+                key: format!("metadata:{env_id}"),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    Ok((meta, meta_bytes))
+}
+
+/// Check the signature of `env_id`'s metadata against the trusted keys.
+/// Returns the key that signed it, or `None` when it is unsigned or not
+/// verified, which is an error if `options.require_signed` is set.
+fn check_signature(
+    env_id: &str,
+    metadata: &[u8],
+    backend: &dyn RemoteBackend,
+    options: &TransferOptions<'_>,
+) -> Result<Option<String>, RemoteError> {
+    let signature = match backend.get_blob(BlobKind::Signature, env_id) {
+        Ok(data) => Some(EnvSignature::from_json(&data)?),
+        Err(RemoteError::NotFound(_)) => None,
+        // Servers predating signatures reject the kind outright.
+        Err(e) if !options.require_signed => {
+            tracing::debug!("no signature for {env_id}: {e}");
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    let verified = match (signature, options.trusted_keys) {
+        (None, _) => Err(RemoteError::Signature(format!("{env_id} is not signed"))),
+        (Some(_), None) => Err(RemoteError::Signature(format!(
+            "{env_id} is signed, but no trusted keys are configured"
+        ))),
+        (Some(signature), Some(trusted)) => {
+            signature.verify(env_id, metadata, trusted).map_err(|e| {
+                if !options.require_signed {
+                    tracing::warn!("{e}");
+                }
+                e
+            })
+        }
+    };
+    match verified {
+        Ok(keyid) => Ok(Some(keyid)),
+        Err(e) if options.require_signed => Err(e),
+        Err(_) => Ok(None),
+    }
 }
 
 /// Resolve a registry reference (e.g. "my-env@latest") to an env_id using the remote registry.
 pub fn resolve_ref(backend: &dyn RemoteBackend, reference: &str) -> Result<String, RemoteError> {
-    let reg_bytes = backend.get_registry()?;
-    let registry = Registry::from_bytes(&reg_bytes)?;
+    Ok(resolve_entry(backend, reference)?.env_id)
+}
+
+/// Resolve a registry reference to its full entry, including the key
+/// fingerprints of encrypted pushes.
+pub fn resolve_entry(
+    backend: &dyn RemoteBackend,
+    reference: &str,
+) -> Result<RegistryEntry, RemoteError> {
+    resolve_entry_with_cache(backend, reference, None)
+}
+
+/// [`resolve_entry`], reading the registry through `cache` when one is
+/// given, so an unchanged registry is not downloaded again and resolution
+/// still works while the remote is unreachable.
+pub fn resolve_entry_with_cache(
+    backend: &dyn RemoteBackend,
+    reference: &str,
+    cache: Option<&RegistryCache>,
+) -> Result<RegistryEntry, RemoteError> {
+    let registry = fetch_registry(backend, cache)?
+        .ok_or_else(|| RemoteError::NotFound("remote has no registry".to_owned()))?;
     let (name, tag) = crate::registry::parse_ref(reference);
     let key = format!("{name}@{tag}");
     let entry = registry
         .lookup(&key)
         .ok_or_else(|| RemoteError::NotFound(format!("registry key '{key}' not found")))?;
-    Ok(entry.env_id.clone())
+    Ok(entry.clone())
+}
+
+/// Every `name@tag` key in the remote registry, sorted. A remote without a
+/// registry has none.
+pub fn list_refs(backend: &dyn RemoteBackend) -> Result<Vec<String>, RemoteError> {
+    list_refs_with_cache(backend, None)
+}
+
+/// [`list_refs`], reading the registry through `cache` when one is given.
+pub fn list_refs_with_cache(
+    backend: &dyn RemoteBackend,
+    cache: Option<&RegistryCache>,
+) -> Result<Vec<String>, RemoteError> {
+    Ok(fetch_registry(backend, cache)?
+        .map(|registry| {
+            registry
+                .list_keys()
+                .into_iter()
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// The remote registry, or `None` when the remote has none.
+fn fetch_registry(
+    backend: &dyn RemoteBackend,
+    cache: Option<&RegistryCache>,
+) -> Result<Option<Registry>, RemoteError> {
+    if let Some(cache) = cache {
+        return Ok(cache.fetch(backend)?.map(|cached| cached.registry));
+    }
+    match backend.get_registry() {
+        Ok(bytes) => Registry::from_bytes(&bytes).map(Some),
+        Err(RemoteError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
+    use crate::crypt::is_encrypted;
+    use std::collections::{BTreeMap, HashMap};
     use std::sync::Mutex;
 
     /// In-memory mock remote backend for testing.
@@ -270,6 +1236,15 @@ mod tests {
                 .contains_key(&Self::blob_key(kind, key)))
         }
 
+        fn blob_size(&self, kind: BlobKind, key: &str) -> Result<Option<u64>, RemoteError> {
+            self.blobs
+                .lock()
+                .unwrap()
+                .get(&Self::blob_key(kind, key))
+                .map(|b| Some(b.len() as u64))
+                .ok_or_else(|| RemoteError::NotFound(key.to_owned()))
+        }
+
         fn list_blobs(&self, kind: BlobKind) -> Result<Vec<String>, RemoteError> {
             let prefix = format!("{kind:?}/");
             let blobs = self.blobs.lock().unwrap();
@@ -309,18 +1284,22 @@ mod tests {
         let manifest_hash = obj_store.put(b"{\"manifest\": \"test\"}").unwrap();
 
         // Create a base layer referencing the object
-        let layer = karapace_store::LayerManifest {
+        let layer = LayerManifest {
             hash: "layer_hash_001".to_owned(),
-            kind: karapace_store::LayerKind::Base,
+            kind: LayerKind::Base,
             parent: None,
             object_refs: vec![obj_hash],
             read_only: true,
             tar_hash: String::new(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata: false,
         };
         let layer_content_hash = layer_store.put(&layer).unwrap();
 
         // Create environment metadata
-        let meta = karapace_store::EnvMetadata {
+        let meta = EnvMetadata {
             env_id: "env_abc123".into(),
             short_id: "env_abc123".into(),
             name: Some("test-env".to_owned()),
@@ -333,6 +1312,18 @@ mod tests {
             created_at: "2025-01-01T00:00:00Z".to_owned(),
             updated_at: "2025-01-01T00:00:00Z".to_owned(),
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
         };
         meta_store.put(&meta).unwrap();
 
@@ -397,6 +1388,7 @@ mod tests {
                 short_id: "hash_xyz".to_owned(),
                 name: None,
                 pushed_at: "t".to_owned(),
+                key_fingerprints: Vec::new(),
             },
         );
         remote.put_registry(&reg.to_bytes().unwrap()).unwrap();
@@ -409,6 +1401,31 @@ mod tests {
         assert_eq!(resolved2, "hash_xyz");
     }
 
+    #[test]
+    fn list_refs_returns_sorted_registry_keys() {
+        let remote = MockRemote::new();
+        assert!(list_refs(&remote).unwrap().is_empty());
+
+        let mut reg = Registry::new();
+        for key in ["web@latest", "api@v2", "api@latest"] {
+            reg.publish(
+                key,
+                RegistryEntry {
+                    env_id: key.to_owned(),
+                    short_id: key.to_owned(),
+                    name: None,
+                    pushed_at: "t".to_owned(),
+                    key_fingerprints: Vec::new(),
+                },
+            );
+        }
+        remote.put_registry(&reg.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            list_refs(&remote).unwrap(),
+            ["api@latest", "api@v2", "web@latest"]
+        );
+    }
+
     #[test]
     fn pull_nonexistent_env_fails() {
         let remote = MockRemote::new();
@@ -431,6 +1448,7 @@ mod tests {
                 short_id: "xyz".to_owned(),
                 name: None,
                 pushed_at: "t".to_owned(),
+                key_fingerprints: Vec::new(),
             },
         );
         remote.put_registry(&reg.to_bytes().unwrap()).unwrap();
@@ -489,16 +1507,99 @@ mod tests {
 
         pull_env(&dst_layout, &env_id, &remote).unwrap();
 
-        // Verify the manifest object is accessible in the destination store
-        let dst_meta = MetadataStore::new(dst_layout.clone());
-        let meta = dst_meta.get(&env_id).unwrap();
-        let dst_obj = ObjectStore::new(dst_layout);
-        let manifest_data = dst_obj.get(&meta.manifest_hash);
-        assert!(
-            manifest_data.is_ok(),
-            "manifest object must be available after pull: {:?}",
-            manifest_data.err()
-        );
+        // Verify the manifest object is accessible in the destination store
+        let dst_meta = MetadataStore::new(dst_layout.clone());
+        let meta = dst_meta.get(&env_id).unwrap();
+        let dst_obj = ObjectStore::new(dst_layout);
+        let manifest_data = dst_obj.get(&meta.manifest_hash);
+        assert!(
+            manifest_data.is_ok(),
+            "manifest object must be available after pull: {:?}",
+            manifest_data.err()
+        );
+    }
+
+    #[test]
+    fn push_and_pull_carry_the_attestation() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_local_env(src_dir.path());
+        let src_meta = MetadataStore::new(src_layout.clone());
+        let mut meta = src_meta.get(&env_id).unwrap();
+        let attestation = ObjectStore::new(src_layout.clone())
+            .put(b"{\"payloadType\": \"application/vnd.in-toto+json\"}")
+            .unwrap();
+        meta.attestation = Some(attestation.clone().into());
+        src_meta.put(&meta).unwrap();
+        let remote = MockRemote::new();
+
+        let pushed = push_env(&src_layout, &env_id, &remote, None).unwrap();
+        assert_eq!(pushed.objects_pushed, 3);
+
+        let dst_dir = tempfile::tempdir().unwrap();
+        let dst_layout = StoreLayout::new(dst_dir.path());
+        dst_layout.initialize().unwrap();
+        pull_env(&dst_layout, &env_id, &remote).unwrap();
+
+        let pulled = MetadataStore::new(dst_layout.clone()).get(&env_id).unwrap();
+        assert_eq!(pulled.attestation.as_deref(), Some(attestation.as_str()));
+        assert!(ObjectStore::new(dst_layout).get(&attestation).is_ok());
+    }
+
+    #[test]
+    fn signed_pushes_verify_and_unsigned_ones_are_refused_when_required() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_local_env(src_dir.path());
+        let remote = MockRemote::new();
+        let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        let trusted = TrustedKeys::parse(&keyid(&key.verifying_key())).unwrap();
+        let strict = TransferOptions {
+            trusted_keys: Some(&trusted),
+            require_signed: true,
+            ..TransferOptions::default()
+        };
+        let pull_into = |options: &TransferOptions<'_>| {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = StoreLayout::new(dir.path());
+            layout.initialize().unwrap();
+            let result = pull_env_with_options(&layout, &env_id, &remote, options);
+            let stored = MetadataStore::new(layout).exists(&env_id);
+            (result, stored)
+        };
+
+        push_env(&src_layout, &env_id, &remote, None).unwrap();
+        let (refused, stored) = pull_into(&strict);
+        assert!(matches!(refused, Err(RemoteError::Signature(_))));
+        assert!(!stored);
+        let (lenient, _) = pull_into(&TransferOptions {
+            trusted_keys: Some(&trusted),
+            ..TransferOptions::default()
+        });
+        assert_eq!(lenient.unwrap().signed_by, None);
+
+        let signing = TransferOptions {
+            sign_with: Some(&key),
+            ..TransferOptions::default()
+        };
+        let pushed = push_env_with_options(&src_layout, &env_id, &remote, None, &signing).unwrap();
+        assert_eq!(pushed.signed_by, Some(keyid(&key.verifying_key())));
+        let (pulled, stored) = pull_into(&strict);
+        assert_eq!(pulled.unwrap().signed_by, pushed.signed_by);
+        assert!(stored);
+
+        // Metadata changed after signing no longer matches the signature.
+        let meta = remote.get_blob(BlobKind::Metadata, &env_id).unwrap();
+        let mut edited: serde_json::Value = serde_json::from_slice(&meta).unwrap();
+        edited["name"] = serde_json::json!("impostor");
+        edited["checksum"] = serde_json::Value::Null;
+        remote
+            .put_blob(
+                BlobKind::Metadata,
+                &env_id,
+                &serde_json::to_vec_pretty(&edited).unwrap(),
+            )
+            .unwrap();
+        let (tampered, _) = pull_into(&strict);
+        assert!(matches!(tampered, Err(RemoteError::Signature(_))));
     }
 
     #[test]
@@ -547,6 +1648,159 @@ mod tests {
         assert_eq!(entry.env_id, env_id);
     }
 
+    /// Reversible stand-in for age: header plus XOR-ed payload.
+    struct XorCipher(u8);
+
+    impl BlobCipher for XorCipher {
+        fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, RemoteError> {
+            let mut out = crate::crypt::AGE_HEADER.to_vec();
+            out.extend(data.iter().map(|b| b ^ self.0));
+            Ok(out)
+        }
+
+        fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, RemoteError> {
+            let body = &data[crate::crypt::AGE_HEADER.len()..];
+            Ok(body.iter().map(|b| b ^ self.0).collect())
+        }
+
+        fn fingerprints(&self) -> Vec<String> {
+            vec![crate::key_fingerprint("age1xor")]
+        }
+    }
+
+    #[test]
+    fn encrypted_push_pull_roundtrip() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_local_env(src_dir.path());
+        let remote = MockRemote::new();
+        let cipher = XorCipher(0x5a);
+
+        push_env_with_cipher(
+            &src_layout,
+            &env_id,
+            &remote,
+            Some("secret@latest"),
+            Some(&cipher),
+        )
+        .unwrap();
+
+        // Every blob on the remote but the seal record is ciphertext.
+        for (key, data) in remote.blobs.lock().unwrap().iter() {
+            if key.starts_with("Seal/") {
+                continue;
+            }
+            assert!(is_encrypted(data), "{key} uploaded in plaintext");
+        }
+        let entry = resolve_entry(&remote, "secret@latest").unwrap();
+        assert_eq!(
+            entry.key_fingerprints,
+            vec![crate::key_fingerprint("age1xor")]
+        );
+
+        let dst_dir = tempfile::tempdir().unwrap();
+        let dst_layout = StoreLayout::new(dst_dir.path());
+        dst_layout.initialize().unwrap();
+
+        let err = pull_env(&dst_layout, &env_id, &remote).unwrap_err();
+        assert!(matches!(err, RemoteError::Encryption(_)), "{err}");
+
+        let result = pull_env_with_cipher(&dst_layout, &env_id, &remote, Some(&cipher)).unwrap();
+        assert_eq!(result.objects_pulled, 2);
+        let meta = MetadataStore::new(dst_layout).get(&env_id).unwrap();
+        assert_eq!(meta.name, Some("test-env".to_owned()));
+    }
+
+    #[test]
+    fn plaintext_objects_that_look_encrypted_are_not_decrypted() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_local_env(src_dir.path());
+        let mut age_file = crate::crypt::AGE_HEADER.to_vec();
+        age_file.extend_from_slice(b"-> X25519 a file in the user's tree\n");
+        let age_hash = ObjectStore::new(src_layout.clone()).put(&age_file).unwrap();
+        let meta_store = MetadataStore::new(src_layout.clone());
+        let mut meta = meta_store.get(&env_id).unwrap();
+        let mut layer = LayerStore::new(src_layout.clone())
+            .get(&meta.base_layer)
+            .unwrap();
+        layer.object_refs.push(age_hash.clone());
+        meta.base_layer = LayerStore::new(src_layout.clone())
+            .put(&layer)
+            .unwrap()
+            .into();
+        meta_store.overwrite(&meta).unwrap();
+
+        let remote = MockRemote::new();
+        push_env(&src_layout, &env_id, &remote, None).unwrap();
+
+        let dst_dir = tempfile::tempdir().unwrap();
+        let dst_layout = StoreLayout::new(dst_dir.path());
+        dst_layout.initialize().unwrap();
+        let result = pull_env(&dst_layout, &env_id, &remote).unwrap();
+        assert_eq!(result.objects_pulled, 3);
+        assert_eq!(
+            ObjectStore::new(dst_layout).get(&age_hash).unwrap(),
+            age_file
+        );
+    }
+
+    #[test]
+    fn encrypted_push_reuploads_plaintext_blobs() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_local_env(src_dir.path());
+        let remote = MockRemote::new();
+        let cipher = XorCipher(0x5a);
+
+        push_env(&src_layout, &env_id, &remote, None).unwrap();
+        let result = push_env_with_cipher(
+            &src_layout,
+            &env_id,
+            &remote,
+            Some("secret@latest"),
+            Some(&cipher),
+        )
+        .unwrap();
+        assert_eq!((result.objects_pushed, result.objects_skipped), (2, 0));
+        assert_eq!((result.layers_pushed, result.layers_skipped), (1, 0));
+
+        // Pulls follow the seal record to the encrypted copies.
+        let dst_dir = tempfile::tempdir().unwrap();
+        let dst_layout = StoreLayout::new(dst_dir.path());
+        dst_layout.initialize().unwrap();
+        let err = pull_env(&dst_layout, &env_id, &remote).unwrap_err();
+        assert!(matches!(err, RemoteError::Encryption(_)), "{err}");
+        pull_env_with_cipher(&dst_layout, &env_id, &remote, Some(&cipher)).unwrap();
+
+        // Another set of recipients gets its own copies.
+        let other = XorCipherFor(0x33, "age1other");
+        let result =
+            push_env_with_cipher(&src_layout, &env_id, &remote, None, Some(&other)).unwrap();
+        assert_eq!(result.objects_pushed, 2);
+
+        // An unencrypted push clears the seal record again.
+        push_env(&src_layout, &env_id, &remote, None).unwrap();
+        let dst_dir = tempfile::tempdir().unwrap();
+        let dst_layout = StoreLayout::new(dst_dir.path());
+        dst_layout.initialize().unwrap();
+        pull_env(&dst_layout, &env_id, &remote).unwrap();
+    }
+
+    /// [`XorCipher`] reporting a different recipient.
+    struct XorCipherFor(u8, &'static str);
+
+    impl BlobCipher for XorCipherFor {
+        fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, RemoteError> {
+            XorCipher(self.0).encrypt(data)
+        }
+
+        fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, RemoteError> {
+            XorCipher(self.0).decrypt(data)
+        }
+
+        fn fingerprints(&self) -> Vec<String> {
+            vec![crate::key_fingerprint(self.1)]
+        }
+    }
+
     // --- §7: Network failure simulation ---
 
     /// Mock remote that fails on the Nth put_blob call.
@@ -698,17 +1952,21 @@ mod tests {
 
         let manifest_hash = obj_store.put(b"{\"manifest\": \"large\"}").unwrap();
 
-        let layer = karapace_store::LayerManifest {
+        let layer = LayerManifest {
             hash: "large_layer".to_owned(),
-            kind: karapace_store::LayerKind::Base,
+            kind: LayerKind::Base,
             parent: None,
             object_refs: vec![obj_hash],
             read_only: true,
             tar_hash: String::new(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata: false,
         };
         let layer_hash = layer_store.put(&layer).unwrap();
 
-        let meta = karapace_store::EnvMetadata {
+        let meta = EnvMetadata {
             env_id: "large_env".into(),
             short_id: "large_env".into(),
             name: None,
@@ -721,6 +1979,18 @@ mod tests {
             created_at: "2025-01-01T00:00:00Z".to_owned(),
             updated_at: "2025-01-01T00:00:00Z".to_owned(),
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
         };
         meta_store.put(&meta).unwrap();
 
@@ -740,4 +2010,454 @@ mod tests {
         let pulled = dst_obj.get(&meta.manifest_hash).unwrap();
         assert_eq!(pulled, b"{\"manifest\": \"large\"}");
     }
+
+    #[test]
+    fn peek_reports_download_without_pulling() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_local_env(src_dir.path());
+        let remote = MockRemote::new();
+        push_env(&src_layout, &env_id, &remote, None).unwrap();
+
+        let dst_dir = tempfile::tempdir().unwrap();
+        let dst_layout = StoreLayout::new(dst_dir.path());
+        dst_layout.initialize().unwrap();
+
+        let peek = peek_env(&dst_layout, &env_id, &remote).unwrap();
+        assert_eq!(peek.name.as_deref(), Some("test-env"));
+        assert_eq!(peek.layers.len(), 1);
+        assert!(!peek.layers[0].local);
+        assert_eq!(peek.layers[0].kind, LayerKind::Base);
+        assert_eq!(peek.layers[0].size, Some(b"test data content".len() as u64));
+        assert_eq!(peek.objects_total, 2);
+        assert_eq!(peek.objects_missing, 2);
+        let expected = b"test data content".len() + b"{\"manifest\": \"test\"}".len();
+        assert_eq!(peek.download_bytes, Some(expected as u64));
+
+        // Nothing was written locally.
+        assert!(!MetadataStore::new(dst_layout.clone()).exists(&env_id));
+        assert!(!LayerStore::new(dst_layout.clone()).exists(&peek.layers[0].hash));
+
+        pull_env(&dst_layout, &env_id, &remote).unwrap();
+        let peek = peek_env(&dst_layout, &env_id, &remote).unwrap();
+        assert!(peek.layers[0].local);
+        assert_eq!(peek.objects_missing, 0);
+        assert_eq!(peek.download_bytes, Some(0));
+    }
+
+    #[test]
+    fn peek_reads_packages_and_base_image_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        let manifest_hash = ObjectStore::new(layout.clone())
+            .put(br#"{"base_image":"rolling","system_packages":["git","curl"]}"#)
+            .unwrap();
+        let layer_hash = LayerStore::new(layout.clone())
+            .put(&LayerManifest {
+                hash: "l".to_owned(),
+                kind: LayerKind::Base,
+                parent: None,
+                object_refs: Vec::new(),
+                read_only: true,
+                tar_hash: String::new(),
+                workspace: None,
+                delta_parent: None,
+                file_objects: Vec::new(),
+                pax_metadata: false,
+            })
+            .unwrap();
+        MetadataStore::new(layout.clone())
+            .put(&EnvMetadata {
+                env_id: "env_pkgs".into(),
+                short_id: "env_pkgs".into(),
+                name: None,
+                state: karapace_store::EnvState::Built,
+                base_layer: layer_hash.into(),
+                dependency_layers: vec![],
+                policy_layer: None,
+                manifest_hash: manifest_hash.into(),
+                ref_count: 1,
+                created_at: "2025-01-01T00:00:00Z".to_owned(),
+                updated_at: "2025-01-01T00:00:00Z".to_owned(),
+                checksum: None,
+                aliases: Vec::new(),
+                host_gpu: None,
+                base_image_digest: Some("d".repeat(64)),
+                workspace: None,
+                snapshot: None,
+                expires_after_secs: None,
+                last_entered_at: None,
+                enter_count: 0,
+                last_built_at: None,
+                labels: BTreeMap::new(),
+                revision: 0,
+                attestation: None,
+            })
+            .unwrap();
+        let remote = MockRemote::new();
+        push_env(&layout, "env_pkgs", &remote, None).unwrap();
+
+        let empty = tempfile::tempdir().unwrap();
+        let empty_layout = StoreLayout::new(empty.path());
+        empty_layout.initialize().unwrap();
+        let peek = peek_env(&empty_layout, "env_pkgs", &remote).unwrap();
+        assert_eq!(peek.base_image.as_deref(), Some("rolling"));
+        assert_eq!(peek.base_image_digest, Some("d".repeat(64)));
+        assert_eq!(peek.packages, ["git", "curl"]);
+    }
+
+    /// Mock remote with chunked uploads and ranged downloads that fails
+    /// once a budget of chunk calls is used up.
+    struct ResumableRemote {
+        inner: MockRemote,
+        uploads: Mutex<HashMap<String, Vec<u8>>>,
+        budget: Mutex<Option<usize>>,
+        bytes_sent: Mutex<usize>,
+    }
+
+    impl ResumableRemote {
+        fn new() -> Self {
+            Self {
+                inner: MockRemote::new(),
+                uploads: Mutex::new(HashMap::new()),
+                budget: Mutex::new(None),
+                bytes_sent: Mutex::new(0),
+            }
+        }
+
+        fn fail_after(&self, calls: Option<usize>) {
+            *self.budget.lock().unwrap() = calls;
+        }
+
+        fn spend(&self) -> Result<(), RemoteError> {
+            let mut budget = self.budget.lock().unwrap();
+            match *budget {
+                Some(0) => Err(RemoteError::Http("simulated network failure".to_owned())),
+                Some(ref mut n) => {
+                    *n -= 1;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl RemoteBackend for ResumableRemote {
+        fn put_blob(&self, kind: BlobKind, key: &str, data: &[u8]) -> Result<(), RemoteError> {
+            self.inner.put_blob(kind, key, data)
+        }
+
+        fn get_blob(&self, kind: BlobKind, key: &str) -> Result<Vec<u8>, RemoteError> {
+            self.inner.get_blob(kind, key)
+        }
+
+        fn has_blob(&self, kind: BlobKind, key: &str) -> Result<bool, RemoteError> {
+            self.inner.has_blob(kind, key)
+        }
+
+        fn blob_size(&self, kind: BlobKind, key: &str) -> Result<Option<u64>, RemoteError> {
+            self.inner.blob_size(kind, key)
+        }
+
+        fn supports_resume(&self) -> bool {
+            true
+        }
+
+        fn upload_offset(&self, kind: BlobKind, key: &str) -> Result<u64, RemoteError> {
+            Ok(self
+                .uploads
+                .lock()
+                .unwrap()
+                .get(&MockRemote::blob_key(kind, key))
+                .map_or(0, |u| u.len() as u64))
+        }
+
+        fn put_blob_chunk(
+            &self,
+            kind: BlobKind,
+            key: &str,
+            offset: u64,
+            data: &[u8],
+            last: bool,
+        ) -> Result<(), RemoteError> {
+            self.spend()?;
+            let mut uploads = self.uploads.lock().unwrap();
+            let session = uploads.entry(MockRemote::blob_key(kind, key)).or_default();
+            if offset == 0 {
+                session.clear();
+            }
+            if offset != session.len() as u64 {
+                return Err(RemoteError::Http("offset mismatch".to_owned()));
+            }
+            session.extend_from_slice(data);
+            *self.bytes_sent.lock().unwrap() += data.len();
+            if last {
+                let blob = uploads.remove(&MockRemote::blob_key(kind, key)).unwrap();
+                self.inner.put_blob(kind, key, &blob)?;
+            }
+            Ok(())
+        }
+
+        fn get_blob_range(
+            &self,
+            kind: BlobKind,
+            key: &str,
+            offset: u64,
+            len: u64,
+        ) -> Result<Vec<u8>, RemoteError> {
+            self.spend()?;
+            let data = self.inner.get_blob(kind, key)?;
+            let start = (offset as usize).min(data.len());
+            let end = (start + len as usize).min(data.len());
+            *self.bytes_sent.lock().unwrap() += end - start;
+            Ok(data[start..end].to_vec())
+        }
+
+        fn list_blobs(&self, kind: BlobKind) -> Result<Vec<String>, RemoteError> {
+            self.inner.list_blobs(kind)
+        }
+
+        fn put_registry(&self, data: &[u8]) -> Result<(), RemoteError> {
+            self.inner.put_registry(data)
+        }
+
+        fn get_registry(&self) -> Result<Vec<u8>, RemoteError> {
+            self.inner.get_registry()
+        }
+    }
+
+    #[test]
+    fn interrupted_upload_resumes_from_remote_offset() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let remote = ResumableRemote::new();
+
+        remote.fail_after(Some(4));
+        assert!(upload_blob(&remote, BlobKind::Object, "big", &data, true, 1024).is_err());
+        assert!(!remote.has_blob(BlobKind::Object, "big").unwrap());
+        assert_eq!(remote.upload_offset(BlobKind::Object, "big").unwrap(), 4096);
+
+        remote.fail_after(None);
+        *remote.bytes_sent.lock().unwrap() = 0;
+        upload_blob(&remote, BlobKind::Object, "big", &data, true, 1024).unwrap();
+        assert_eq!(*remote.bytes_sent.lock().unwrap(), data.len() - 4096);
+        assert_eq!(remote.get_blob(BlobKind::Object, "big").unwrap(), data);
+    }
+
+    #[test]
+    fn upload_without_resume_restarts_from_zero() {
+        let data = vec![7u8; 5000];
+        let remote = ResumableRemote::new();
+        remote.fail_after(Some(2));
+        assert!(upload_blob(&remote, BlobKind::Object, "enc", &data, false, 1024).is_err());
+
+        remote.fail_after(None);
+        *remote.bytes_sent.lock().unwrap() = 0;
+        upload_blob(&remote, BlobKind::Object, "enc", &data, false, 1024).unwrap();
+        assert_eq!(*remote.bytes_sent.lock().unwrap(), data.len());
+        assert_eq!(remote.get_blob(BlobKind::Object, "enc").unwrap(), data);
+    }
+
+    #[test]
+    fn interrupted_download_resumes_from_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 239) as u8).collect();
+        let remote = ResumableRemote::new();
+        remote.put_blob(BlobKind::Object, "big", &data).unwrap();
+
+        remote.fail_after(Some(3));
+        assert!(download_blob(&layout, &remote, BlobKind::Object, "big", 1024).is_err());
+        let partial = layout.staging_dir().join("pull-big.partial");
+        assert_eq!(fs::metadata(&partial).unwrap().len(), 3072);
+
+        remote.fail_after(None);
+        *remote.bytes_sent.lock().unwrap() = 0;
+        let pulled = download_blob(&layout, &remote, BlobKind::Object, "big", 1024).unwrap();
+        assert_eq!(pulled, data);
+        assert_eq!(*remote.bytes_sent.lock().unwrap(), data.len() - 3072);
+        assert!(!partial.exists());
+    }
+
+    #[test]
+    fn small_blobs_skip_chunking() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+
+        let remote = ResumableRemote::new();
+        remote.fail_after(Some(0));
+        upload_blob(&remote, BlobKind::Object, "small", b"tiny", true, 1024).unwrap();
+        let pulled = download_blob(&layout, &remote, BlobKind::Object, "small", 1024).unwrap();
+        assert_eq!(pulled, b"tiny");
+    }
+
+    /// One environment whose base layer holds `count` distinct objects.
+    fn setup_wide_env(dir: &std::path::Path, count: usize) -> (StoreLayout, String) {
+        let layout = StoreLayout::new(dir);
+        layout.initialize().unwrap();
+        let obj_store = ObjectStore::new(layout.clone());
+        let object_refs = (0..count)
+            .map(|i| obj_store.put(format!("object {i}").as_bytes()).unwrap())
+            .collect();
+        let manifest_hash = obj_store.put(b"{\"manifest\": \"wide\"}").unwrap();
+        let layer = LayerManifest {
+            hash: "wide_layer".to_owned(),
+            kind: LayerKind::Base,
+            parent: None,
+            object_refs,
+            read_only: true,
+            tar_hash: String::new(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata: false,
+        };
+        let layer_hash = LayerStore::new(layout.clone()).put(&layer).unwrap();
+        let meta = EnvMetadata {
+            env_id: "wide_env".into(),
+            short_id: "wide_env".into(),
+            name: None,
+            state: karapace_store::EnvState::Built,
+            base_layer: layer_hash.into(),
+            dependency_layers: vec![],
+            policy_layer: None,
+            manifest_hash: manifest_hash.into(),
+            ref_count: 1,
+            created_at: "2025-01-01T00:00:00Z".to_owned(),
+            updated_at: "2025-01-01T00:00:00Z".to_owned(),
+            checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
+        };
+        MetadataStore::new(layout.clone()).put(&meta).unwrap();
+        (layout, "wide_env".to_owned())
+    }
+
+    #[test]
+    fn parallel_push_pull_reports_every_object() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_wide_env(src_dir.path(), 20);
+        let remote = MockRemote::new();
+
+        let seen = Mutex::new(Vec::new());
+        let record = |p: &ObjectProgress<'_>| {
+            assert_eq!(p.total, 21);
+            seen.lock()
+                .unwrap()
+                .push((p.hash.to_owned(), p.skipped, p.done));
+        };
+        let options = TransferOptions {
+            concurrency: 8,
+            on_object: Some(&record),
+            ..TransferOptions::default()
+        };
+
+        let pushed = push_env_with_options(&src_layout, &env_id, &remote, None, &options).unwrap();
+        assert_eq!(pushed.objects_pushed, 21);
+        let mut done: Vec<usize> = seen.lock().unwrap().iter().map(|s| s.2).collect();
+        done.sort_unstable();
+        assert_eq!(done, (1..=21).collect::<Vec<_>>());
+        assert!(seen.lock().unwrap().iter().all(|s| !s.1));
+
+        seen.lock().unwrap().clear();
+        let again = push_env_with_options(&src_layout, &env_id, &remote, None, &options).unwrap();
+        assert_eq!(again.objects_skipped, 21);
+        assert!(seen.lock().unwrap().iter().all(|s| s.1));
+
+        seen.lock().unwrap().clear();
+        let dst_dir = tempfile::tempdir().unwrap();
+        let dst_layout = StoreLayout::new(dst_dir.path());
+        dst_layout.initialize().unwrap();
+        let pulled = pull_env_with_options(&dst_layout, &env_id, &remote, &options).unwrap();
+        assert_eq!(pulled.objects_pulled, 21);
+        assert_eq!(seen.lock().unwrap().len(), 21);
+
+        let dst_objects = ObjectStore::new(dst_layout);
+        for (hash, _, _) in seen.lock().unwrap().iter() {
+            assert!(dst_objects.exists(hash));
+        }
+    }
+
+    #[test]
+    fn push_and_pull_report_their_phases() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_wide_env(src_dir.path(), 2);
+        let remote = MockRemote::new();
+
+        let phases = Mutex::new(Vec::new());
+        let record = |phase: TransferPhase| phases.lock().unwrap().push(phase);
+        let options = TransferOptions {
+            on_phase: Some(&record),
+            ..TransferOptions::default()
+        };
+
+        push_env_with_options(&src_layout, &env_id, &remote, Some("wide@latest"), &options)
+            .unwrap();
+        assert_eq!(
+            std::mem::take(&mut *phases.lock().unwrap()),
+            [
+                TransferPhase::Objects,
+                TransferPhase::Layers,
+                TransferPhase::Metadata,
+                TransferPhase::Registry
+            ]
+        );
+
+        let dst_dir = tempfile::tempdir().unwrap();
+        let dst_layout = StoreLayout::new(dst_dir.path());
+        dst_layout.initialize().unwrap();
+        pull_env_with_options(&dst_layout, &env_id, &remote, &options).unwrap();
+        assert_eq!(
+            *phases.lock().unwrap(),
+            [
+                TransferPhase::Metadata,
+                TransferPhase::Layers,
+                TransferPhase::Objects
+            ]
+        );
+    }
+
+    #[test]
+    fn parallel_push_fails_without_writing_metadata() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_wide_env(src_dir.path(), 20);
+        let remote = FailOnPutRemote::new(5);
+        let options = TransferOptions {
+            concurrency: 4,
+            ..TransferOptions::default()
+        };
+        assert!(push_env_with_options(&src_layout, &env_id, &remote, None, &options).is_err());
+        assert!(!remote.has_blob(BlobKind::Metadata, &env_id).unwrap());
+    }
+
+    #[test]
+    fn parallel_pull_still_verifies_objects() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let (src_layout, env_id) = setup_wide_env(src_dir.path(), 10);
+        let remote = CorruptGetRemote::new();
+        push_env(&src_layout, &env_id, &remote, None).unwrap();
+
+        let dst_dir = tempfile::tempdir().unwrap();
+        let dst_layout = StoreLayout::new(dst_dir.path());
+        dst_layout.initialize().unwrap();
+        let options = TransferOptions {
+            concurrency: 4,
+            ..TransferOptions::default()
+        };
+        let err = pull_env_with_options(&dst_layout, &env_id, &remote, &options).unwrap_err();
+        assert!(matches!(err, RemoteError::IntegrityFailure { .. }));
+        assert!(!MetadataStore::new(dst_layout).exists(&env_id));
+    }
 }
@@ -1,4 +1,6 @@
-use crate::RuntimeError;
+use crate::process::ProcessInfo;
+use crate::quota::dir_usage;
+use crate::{ProgressSink, RuntimeError};
 use karapace_schema::{NormalizedManifest, ResolutionResult};
 use serde::{Deserialize, Serialize};
 
@@ -11,6 +13,9 @@ pub struct RuntimeSpec {
     pub manifest: NormalizedManifest,
     #[serde(default)]
     pub offline: bool,
+    /// Enter without persisting anything to the environment's upper layer.
+    #[serde(default)]
+    pub read_only: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -18,6 +23,37 @@ pub struct RuntimeStatus {
     pub env_id: String,
     pub running: bool,
     pub pid: Option<u32>,
+    /// Whether the running session has the built-in init as PID 1.
+    #[serde(default)]
+    pub init: bool,
+}
+
+/// Resource usage of an environment.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct EnvStats {
+    /// Number of processes running in the environment.
+    pub processes: usize,
+    /// CPU time used by those processes, in seconds.
+    pub cpu_secs: f64,
+    /// Their average CPU usage since each started, in percent of one core.
+    pub cpu_percent: f64,
+    /// Sum of their resident set sizes, in bytes. Pages shared between
+    /// processes count once per process.
+    pub memory_bytes: u64,
+    /// Bytes used by the overlay upper directory.
+    pub disk_bytes: u64,
+}
+
+impl EnvStats {
+    pub fn new(processes: &[ProcessInfo], disk_bytes: u64) -> Self {
+        Self {
+            processes: processes.len(),
+            cpu_secs: processes.iter().map(|p| p.cpu_secs).sum(),
+            cpu_percent: processes.iter().map(|p| p.cpu_percent).sum(),
+            memory_bytes: processes.iter().map(|p| p.rss_kb * 1024).sum(),
+            disk_bytes,
+        }
+    }
 }
 
 pub trait RuntimeBackend: Send + Sync {
@@ -28,9 +64,13 @@ pub trait RuntimeBackend: Send + Sync {
     /// Resolve dependencies: download/identify the base image and query the
     /// package manager for exact versions of each requested package.
     /// Returns a ResolutionResult with content digest and pinned versions.
-    fn resolve(&self, spec: &RuntimeSpec) -> Result<ResolutionResult, RuntimeError>;
+    fn resolve(
+        &self,
+        spec: &RuntimeSpec,
+        progress: &dyn ProgressSink,
+    ) -> Result<ResolutionResult, RuntimeError>;
 
-    fn build(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError>;
+    fn build(&self, spec: &RuntimeSpec, progress: &dyn ProgressSink) -> Result<(), RuntimeError>;
 
     fn enter(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError>;
 
@@ -45,8 +85,67 @@ pub trait RuntimeBackend: Send + Sync {
         )))
     }
 
+    /// Run `command` attached to the caller's stdin, stdout and stderr, or
+    /// to a new pseudo-terminal when `tty` is set, and return its status.
+    fn exec_attached(
+        &self,
+        _spec: &RuntimeSpec,
+        _command: &[String],
+        _tty: bool,
+    ) -> Result<std::process::ExitStatus, RuntimeError> {
+        Err(RuntimeError::ExecFailed(format!(
+            "exec not supported by {} backend",
+            self.name()
+        )))
+    }
+
+    /// Start a session without a terminal that keeps running after the
+    /// caller exits, for [`attach`](Self::attach) to join.
+    fn start_detached(&self, _spec: &RuntimeSpec) -> Result<(), RuntimeError> {
+        Err(RuntimeError::ExecFailed(format!(
+            "detached sessions not supported by {} backend",
+            self.name()
+        )))
+    }
+
+    /// Run `command`, or a login shell when it is empty, in the running
+    /// detached session and return its status.
+    fn attach(
+        &self,
+        _spec: &RuntimeSpec,
+        _command: &[String],
+    ) -> Result<std::process::ExitStatus, RuntimeError> {
+        Err(RuntimeError::ExecFailed(format!(
+            "detached sessions not supported by {} backend",
+            self.name()
+        )))
+    }
+
+    /// Release what [`start_detached`](Self::start_detached) left behind,
+    /// once the session's process has been stopped.
+    fn end_detached(&self, _spec: &RuntimeSpec) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+
     fn destroy(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError>;
 
+    /// Processes running in the environment, in PID order. Empty when it is
+    /// not running.
+    fn processes(&self, _env_id: &str) -> Result<Vec<ProcessInfo>, RuntimeError> {
+        Err(RuntimeError::ExecFailed(format!(
+            "process listing not supported by {} backend",
+            self.name()
+        )))
+    }
+
+    /// Resource usage of the environment's processes and of its overlay
+    /// upper directory.
+    fn stats(&self, spec: &RuntimeSpec) -> Result<EnvStats, RuntimeError> {
+        let processes = self.processes(&spec.env_id)?;
+        let upper = std::path::Path::new(&spec.overlay_path).join("upper");
+        Ok(EnvStats::new(&processes, dir_usage(&upper)))
+    }
+
     fn status(&self, env_id: &str) -> Result<RuntimeStatus, RuntimeError>;
 }
 
@@ -61,6 +160,9 @@ pub fn select_backend(
         "oci" => Ok(Box::new(crate::oci::OciBackend::with_store_root(
             store_root,
         ))),
+        "podman" => Ok(Box::new(crate::podman::PodmanBackend::with_store_root(
+            store_root,
+        ))),
         "mock" => Ok(Box::new(crate::mock::MockBackend::new())),
         other => Err(RuntimeError::BackendUnavailable(other.to_owned())),
     }
@@ -74,6 +176,7 @@ mod tests {
     fn select_valid_backends() {
         assert!(select_backend("namespace", "/tmp/test-store").is_ok());
         assert!(select_backend("oci", "/tmp/test-store").is_ok());
+        assert!(select_backend("podman", "/tmp/test-store").is_ok());
         assert!(select_backend("mock", "/tmp/test-store").is_ok());
     }
 
@@ -81,4 +184,22 @@ mod tests {
     fn select_invalid_backend_fails() {
         assert!(select_backend("nonexistent", "/tmp/test-store").is_err());
     }
+
+    #[test]
+    fn stats_sum_process_usage() {
+        let process = |pid, cpu_secs, rss_kb| ProcessInfo {
+            pid,
+            ppid: 1,
+            command: "sh".to_owned(),
+            cpu_secs,
+            cpu_percent: cpu_secs,
+            rss_kb,
+        };
+        let stats = EnvStats::new(&[process(2, 1.5, 100), process(3, 0.5, 20)], 4096);
+        assert_eq!(stats.processes, 2);
+        assert!((stats.cpu_secs - 2.0).abs() < f64::EPSILON);
+        assert_eq!(stats.memory_bytes, 120 * 1024);
+        assert_eq!(stats.disk_bytes, 4096);
+        assert_eq!(EnvStats::new(&[], 0), EnvStats::default());
+    }
 }
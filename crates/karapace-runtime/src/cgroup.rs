@@ -0,0 +1,299 @@
+//! cgroup v2 resource limits for namespace sessions.
+//!
+//! A manifest's `cpu_shares` and `memory_limit_mb` are applied by giving each
+//! session a cgroup of its own with `cpu.weight` and `memory.max` set. It is
+//! created under karapace's own cgroup, or next to it when that one holds
+//! processes and so cannot enable controllers for its children. The sandbox
+//! process joins it before it execs, so everything in the session is limited
+//! and accounted together.
+//!
+//! This needs a cgroup v2 hierarchy delegated to the user with the `cpu` and
+//! `memory` controllers, as systemd provides under `user@.service`. Without
+//! one the session runs unlimited and a warning is printed.
+
+use karapace_schema::NormalizedManifest;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Limits written to a session cgroup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CgroupLimits {
+    /// `cpu.weight`, 1..=10000.
+    pub cpu_weight: Option<u64>,
+    /// `memory.max` in bytes.
+    pub memory_max: Option<u64>,
+}
+
+impl CgroupLimits {
+    pub fn from_manifest(manifest: &NormalizedManifest) -> Self {
+        Self {
+            cpu_weight: manifest.cpu_shares.map(cpu_weight),
+            memory_max: manifest
+                .memory_limit_mb
+                .map(|mb| mb.saturating_mul(1024 * 1024)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cpu_weight.is_none() && self.memory_max.is_none()
+    }
+
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.cpu_weight.is_some() {
+            controllers.push("cpu");
+        }
+        if self.memory_max.is_some() {
+            controllers.push("memory");
+        }
+        controllers
+    }
+}
+
+/// cgroup v1 `cpu.shares` as a v2 `cpu.weight`, converted as crun and
+/// systemd do: shares 2..=262144 map linearly onto weights 1..=10000, so
+/// the default of 1024 becomes 39.
+pub fn cpu_weight(shares: u64) -> u64 {
+    let shares = shares.clamp(2, 262_144);
+    1 + (shares - 2) * 9999 / 262_142
+}
+
+/// The cgroup v2 directory of `pid`, from the `0::/path` line of
+/// `/proc/<pid>/cgroup`.
+pub(crate) fn cgroup_dir(pid: u32) -> Option<PathBuf> {
+    let content = std::fs::read_to_string(format!("/proc/{pid}/cgroup")).ok()?;
+    let path = parse_cgroup_v2_path(&content)?;
+    let dir = Path::new(CGROUP_ROOT).join(path.trim_start_matches('/'));
+    dir.is_dir().then_some(dir)
+}
+
+fn parse_cgroup_v2_path(content: &str) -> Option<&str> {
+    content.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+fn has_all(list: &str, controllers: &[&str]) -> bool {
+    controllers
+        .iter()
+        .all(|c| list.split_whitespace().any(|have| have == *c))
+}
+
+fn read_list(dir: &Path, file: &str) -> String {
+    std::fs::read_to_string(dir.join(file)).unwrap_or_default()
+}
+
+#[allow(unsafe_code)]
+fn writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: access() only inspects the NUL-terminated path.
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Whether session cgroups with `controllers` can be created in `dir` and
+/// processes moved into them, enabling the controllers for its children
+/// when `enable` is set.
+fn usable(dir: &Path, controllers: &[&str], enable: bool) -> bool {
+    if !has_all(&read_list(dir, "cgroup.controllers"), controllers)
+        || !writable(dir)
+        || !writable(&dir.join("cgroup.procs"))
+    {
+        return false;
+    }
+    if has_all(&read_list(dir, "cgroup.subtree_control"), controllers) {
+        return true;
+    }
+    let wanted: Vec<String> = controllers.iter().map(|c| format!("+{c}")).collect();
+    // Fails with EBUSY while `dir` itself holds processes.
+    enable && std::fs::write(dir.join("cgroup.subtree_control"), wanted.join(" ")).is_ok()
+}
+
+/// The cgroup new session cgroups are created in.
+fn session_parent(controllers: &[&str], enable: bool) -> Result<PathBuf, String> {
+    let own = cgroup_dir(std::process::id())
+        .ok_or_else(|| format!("no cgroup v2 hierarchy mounted at {CGROUP_ROOT}"))?;
+    let parent = own
+        .parent()
+        .filter(|p| p.starts_with(CGROUP_ROOT))
+        .map(Path::to_path_buf);
+    std::iter::once(own)
+        .chain(parent)
+        .find(|dir| usable(dir, controllers, enable))
+        .ok_or_else(|| {
+            format!(
+                "the {} cgroup controllers are not delegated to this user",
+                controllers.join(" and ")
+            )
+        })
+}
+
+/// Check that session cgroups with the `cpu` and `memory` controllers can be
+/// created, without changing anything.
+pub fn check_delegation() -> Result<(), String> {
+    session_parent(&["cpu", "memory"], false).map(drop)
+}
+
+/// The delegated cgroup rootless OCI containers are created under, as a
+/// path relative to the cgroup root, for an OCI `cgroupsPath`.
+pub(crate) fn delegated_cgroup() -> Option<String> {
+    let parent = session_parent(&[], false).ok()?;
+    let relative = parent.strip_prefix(CGROUP_ROOT).ok()?;
+    Some(format!("/{}", relative.display()))
+}
+
+/// A session's cgroup, removed when dropped.
+#[derive(Debug)]
+pub struct SessionCgroup {
+    dir: PathBuf,
+}
+
+impl SessionCgroup {
+    /// Create a cgroup for a session of `env_id` with `limits` applied.
+    pub fn create(env_id: &str, limits: &CgroupLimits) -> Result<Self, String> {
+        let parent = session_parent(&limits.controllers(), true)?;
+        let dir = parent.join(format!(
+            "karapace-{}-{}",
+            &env_id[..12.min(env_id.len())],
+            std::process::id()
+        ));
+        std::fs::create_dir(&dir)
+            .map_err(|e| format!("failed to create cgroup {}: {e}", dir.display()))?;
+        let cgroup = Self { dir };
+
+        let limit = |file: &str, value: u64| {
+            std::fs::write(cgroup.dir.join(file), value.to_string())
+                .map_err(|e| format!("failed to set {file}: {e}"))
+        };
+        if let Some(weight) = limits.cpu_weight {
+            limit("cpu.weight", weight)?;
+        }
+        if let Some(max) = limits.memory_max {
+            limit("memory.max", max)?;
+        }
+        Ok(cgroup)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Leave the cgroup in place for a detached session; [`remove`] deletes
+    /// it once the session has ended.
+    pub fn keep(self) -> PathBuf {
+        let this = std::mem::ManuallyDrop::new(self);
+        this.dir.clone()
+    }
+}
+
+impl Drop for SessionCgroup {
+    fn drop(&mut self) {
+        remove(&self.dir);
+    }
+}
+
+/// Remove a session cgroup. The kernel refuses while exiting processes are
+/// still being reaped, so this retries briefly.
+pub fn remove(dir: &Path) {
+    for _ in 0..20 {
+        match std::fs::remove_dir(dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            _ => return,
+        }
+    }
+    tracing::warn!("failed to remove cgroup {}", dir.display());
+}
+
+/// Make `cmd` move itself into the cgroup at `dir` before it execs, so
+/// nothing it starts runs outside it.
+#[allow(unsafe_code)]
+pub fn join_before_exec(cmd: &mut Command, dir: &Path) {
+    let Ok(procs) = CString::new(dir.join("cgroup.procs").as_os_str().as_bytes()) else {
+        return;
+    };
+    // SAFETY: open, write and close are async-signal-safe, and `procs` was
+    // allocated before the fork.
+    unsafe {
+        cmd.pre_exec(move || {
+            let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+            libc::close(fd);
+            if written < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_shares_convert_like_crun() {
+        assert_eq!(cpu_weight(2), 1);
+        assert_eq!(cpu_weight(1024), 39);
+        assert_eq!(cpu_weight(262_144), 10_000);
+        assert_eq!(cpu_weight(0), 1);
+        assert_eq!(cpu_weight(u64::MAX), 10_000);
+    }
+
+    #[test]
+    fn limits_need_matching_controllers() {
+        let limits = CgroupLimits {
+            cpu_weight: None,
+            memory_max: Some(1 << 30),
+        };
+        assert!(!limits.is_empty());
+        assert_eq!(limits.controllers(), ["memory"]);
+        assert!(CgroupLimits::default().is_empty());
+
+        assert!(has_all("cpuset cpu io memory pids", &["cpu", "memory"]));
+        assert!(!has_all("cpuset io memory", &["cpu", "memory"]));
+        assert!(has_all("", &[]));
+    }
+
+    #[test]
+    fn parse_cgroup_file() {
+        let cgroup = "1:name=systemd:/legacy\n0::/user.slice/user-1000.slice/session-2.scope\n";
+        assert_eq!(
+            parse_cgroup_v2_path(cgroup),
+            Some("/user.slice/user-1000.slice/session-2.scope")
+        );
+        assert_eq!(parse_cgroup_v2_path("1:name=systemd:/legacy\n"), None);
+    }
+
+    #[test]
+    fn session_cgroup_applies_limits_where_delegated() {
+        let limits = CgroupLimits {
+            cpu_weight: Some(cpu_weight(512)),
+            memory_max: Some(256 << 20),
+        };
+        let Ok(cgroup) = SessionCgroup::create("abc123def456", &limits) else {
+            return; // no delegated cgroup v2 hierarchy here
+        };
+        let dir = cgroup.path().to_path_buf();
+        let read = |file: &str| std::fs::read_to_string(dir.join(file)).unwrap();
+        assert_eq!(read("memory.max").trim(), (256u64 << 20).to_string());
+        assert_eq!(read("cpu.weight").trim(), cpu_weight(512).to_string());
+
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", "cat /proc/self/cgroup"]);
+        join_before_exec(&mut cmd, &dir);
+        let output = cmd.output().unwrap();
+        assert!(String::from_utf8_lossy(&output.stdout).contains("karapace-abc123def456"));
+
+        drop(cgroup);
+        assert!(!dir.exists());
+    }
+}
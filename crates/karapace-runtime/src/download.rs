@@ -0,0 +1,350 @@
+//! HTTP downloads of base images.
+//!
+//! Requests are retried with exponential backoff after connection errors,
+//! timeouts and `429` or `5xx` responses; other statuses fail at once.
+//! [`Downloader::download`] streams the body to disk, reporting bytes to the
+//! [`ProgressSink`] and hashing them with sha256 on the way, so the caller
+//! can check the file against the `SHA256SUMS` index image servers publish
+//! next to each build ([`sha256sums_entry`]).
+
+use crate::{ProgressSink, RuntimeError};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+use tracing::warn;
+
+/// Attempts per request before giving up.
+pub const DEFAULT_ATTEMPTS: u32 = 4;
+
+/// Wait after the first failed attempt; it doubles after each further one.
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Bytes downloaded between two progress reports.
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+/// Why an attempt failed, and whether trying again may help.
+enum Failure {
+    Transient(String),
+    Permanent(RuntimeError),
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Self {
+        Self::Permanent(e.into())
+    }
+}
+
+/// Blocking HTTP client for image indexes and rootfs tarballs.
+pub struct Downloader {
+    agent: ureq::Agent,
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Downloader {
+    pub fn new() -> Self {
+        Self::with_retries(DEFAULT_ATTEMPTS, DEFAULT_BACKOFF)
+    }
+
+    /// Try each request up to `attempts` times, waiting `backoff` after the
+    /// first failure.
+    pub fn with_retries(attempts: u32, backoff: Duration) -> Self {
+        let agent = ureq::Agent::new_with_config(
+            ureq::Agent::config_builder()
+                .timeout_connect(Some(Duration::from_secs(30)))
+                .timeout_recv_response(Some(Duration::from_mins(1)))
+                .build(),
+        );
+        Self {
+            agent,
+            attempts: attempts.max(1),
+            backoff,
+        }
+    }
+
+    /// The body of `url` as text.
+    pub fn get_text(&self, url: &str) -> Result<String, RuntimeError> {
+        self.get_text_if_exists(url)?
+            .ok_or_else(|| RuntimeError::Download(format!("HTTP 404 for {url}")))
+    }
+
+    /// The body of `url` as text, or `None` if the server answers `404`.
+    pub fn get_text_if_exists(&self, url: &str) -> Result<Option<String>, RuntimeError> {
+        self.retry(|| {
+            let mut resp = match self.agent.get(url).call() {
+                Ok(resp) => resp,
+                Err(ureq::Error::StatusCode(404)) => return Ok(None),
+                Err(e) => return Err(classify(url, e)),
+            };
+            resp.body_mut()
+                .read_to_string()
+                .map(Some)
+                .map_err(|e| classify(url, e))
+        })
+    }
+
+    /// POST the JSON `body` to `url` and return the response as text.
+    pub fn post_json(&self, url: &str, body: &str) -> Result<String, RuntimeError> {
+        self.retry(|| {
+            let mut resp = self
+                .agent
+                .post(url)
+                .header("Content-Type", "application/json")
+                .send(body)
+                .map_err(|e| classify(url, e))?;
+            resp.body_mut()
+                .read_to_string()
+                .map_err(|e| classify(url, e))
+        })
+    }
+
+    /// Download `url` to `dest` and return the sha256 of what was written,
+    /// as lowercase hex. A body shorter than its `Content-Length` counts as
+    /// a failed attempt.
+    pub fn download(
+        &self,
+        url: &str,
+        dest: &Path,
+        progress: &dyn ProgressSink,
+    ) -> Result<String, RuntimeError> {
+        self.retry(|| {
+            let resp = self.agent.get(url).call().map_err(|e| classify(url, e))?;
+            let total = resp.body().content_length();
+            let mut reader = resp.into_body().into_reader();
+            let mut file = std::fs::File::create(dest)?;
+            let mut hasher = Sha256::new();
+            let mut buf = vec![0u8; 64 * 1024];
+            let mut done = 0u64;
+            let mut reported = 0u64;
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(Failure::Transient(format!("reading {url}: {e}"))),
+                };
+                hasher.update(&buf[..n]);
+                file.write_all(&buf[..n])?;
+                done += n as u64;
+                if done - reported >= PROGRESS_STEP {
+                    progress.download(done, total);
+                    reported = done;
+                }
+            }
+            if total.is_some_and(|total| done != total) {
+                return Err(Failure::Transient(format!(
+                    "{url}: got {done} of {} bytes",
+                    total.unwrap_or_default()
+                )));
+            }
+            file.sync_all()?;
+            progress.download(done, total);
+            Ok(format!("{:x}", hasher.finalize()))
+        })
+    }
+
+    fn retry<T>(&self, mut attempt: impl FnMut() -> Result<T, Failure>) -> Result<T, RuntimeError> {
+        let mut delay = self.backoff;
+        let mut tries = 1;
+        loop {
+            match attempt() {
+                Ok(value) => return Ok(value),
+                Err(Failure::Permanent(e)) => return Err(e),
+                Err(Failure::Transient(msg)) if tries >= self.attempts => {
+                    return Err(RuntimeError::Download(format!(
+                        "{msg} (gave up after {tries} attempts)"
+                    )));
+                }
+                Err(Failure::Transient(msg)) => {
+                    warn!("{msg}; retrying in {}ms", delay.as_millis());
+                    std::thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                    tries += 1;
+                }
+            }
+        }
+    }
+}
+
+fn classify(url: &str, e: ureq::Error) -> Failure {
+    match e {
+        ureq::Error::StatusCode(code) if code == 429 || code >= 500 => {
+            Failure::Transient(format!("HTTP {code} for {url}"))
+        }
+        ureq::Error::StatusCode(code) => {
+            Failure::Permanent(RuntimeError::Download(format!("HTTP {code} for {url}")))
+        }
+        ureq::Error::Io(_)
+        | ureq::Error::Timeout(_)
+        | ureq::Error::HostNotFound
+        | ureq::Error::ConnectionFailed
+        | ureq::Error::Protocol(_)
+        | ureq::Error::BodyStalled => Failure::Transient(format!("{url}: {e}")),
+        e => Failure::Permanent(RuntimeError::Download(format!("{url}: {e}"))),
+    }
+}
+
+/// The sha256 a `SHA256SUMS` index lists for `file_name`, lowercased.
+///
+/// Lines are `<hex digest>  <name>`, with `*<name>` for binary mode as
+/// written by `sha256sum -b`.
+pub fn sha256sums_entry(index: &str, file_name: &str) -> Option<String> {
+    index.lines().find_map(|line| {
+        let (digest, name) = line.trim().split_once(char::is_whitespace)?;
+        let name = name.trim_start();
+        let name = name.strip_prefix('*').unwrap_or(name);
+        (name == file_name && digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit()))
+            .then(|| digest.to_ascii_lowercase())
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::NoProgress;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Serves fixed responses by path. A path listed in `failures` answers
+    /// `503` that many times before its content.
+    pub(crate) struct FileServer {
+        pub(crate) url: String,
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl FileServer {
+        pub(crate) fn start(files: &[(&str, &[u8])], failures: &[(&str, usize)]) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let files: HashMap<String, Vec<u8>> = files
+                .iter()
+                .map(|(path, body)| ((*path).to_owned(), body.to_vec()))
+                .collect();
+            let failures: Arc<Mutex<HashMap<String, usize>>> = Arc::new(Mutex::new(
+                failures
+                    .iter()
+                    .map(|(path, n)| ((*path).to_owned(), *n))
+                    .collect(),
+            ));
+            let requests = Arc::new(AtomicUsize::new(0));
+            let counter = Arc::clone(&requests);
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else { break };
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    if reader.read_line(&mut request_line).is_err() {
+                        continue;
+                    }
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).is_err() || line.trim().is_empty() {
+                            break;
+                        }
+                    }
+                    let path = request_line
+                        .split_whitespace()
+                        .nth(1)
+                        .unwrap_or("/")
+                        .to_owned();
+                    let failing = {
+                        let mut failures = failures.lock().unwrap();
+                        match failures.get_mut(&path) {
+                            Some(n) if *n > 0 => {
+                                *n -= 1;
+                                true
+                            }
+                            _ => false,
+                        }
+                    };
+                    let (status, body) = match files.get(&path) {
+                        _ if failing => ("503 Service Unavailable", &[][..]),
+                        Some(body) => ("200 OK", body.as_slice()),
+                        None => ("404 Not Found", &[][..]),
+                    };
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    let _ = stream.write_all(body);
+                    let _ = stream.flush();
+                }
+            });
+            Self { url, requests }
+        }
+
+        pub(crate) fn requests(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+
+    fn quick() -> Downloader {
+        Downloader::with_retries(3, Duration::from_millis(1))
+    }
+
+    #[test]
+    fn download_retries_transient_failures() {
+        let server = FileServer::start(&[("/rootfs.tar.xz", b"rootfs")], &[("/rootfs.tar.xz", 2)]);
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("rootfs.tar.xz");
+        let sha = quick()
+            .download(&format!("{}/rootfs.tar.xz", server.url), &dest, &NoProgress)
+            .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"rootfs");
+        assert_eq!(sha, format!("{:x}", Sha256::digest(b"rootfs")));
+        assert_eq!(server.requests(), 3);
+    }
+
+    #[test]
+    fn download_gives_up_after_its_attempts() {
+        let server = FileServer::start(&[("/rootfs.tar.xz", b"rootfs")], &[("/rootfs.tar.xz", 5)]);
+        let dir = tempfile::tempdir().unwrap();
+        let err = quick()
+            .download(
+                &format!("{}/rootfs.tar.xz", server.url),
+                &dir.path().join("rootfs.tar.xz"),
+                &NoProgress,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("3 attempts"), "{err}");
+        assert_eq!(server.requests(), 3);
+    }
+
+    #[test]
+    fn missing_files_are_not_retried() {
+        let server = FileServer::start(&[], &[]);
+        let url = format!("{}/SHA256SUMS", server.url);
+        assert_eq!(quick().get_text_if_exists(&url).unwrap(), None);
+        assert!(matches!(
+            quick().get_text(&url),
+            Err(RuntimeError::Download(_))
+        ));
+        assert_eq!(server.requests(), 2);
+    }
+
+    #[test]
+    fn sha256sums_entries_by_name() {
+        let a = "a".repeat(64);
+        let b = "B".repeat(64);
+        let index = format!("{a}  meta.tar.xz\n{b} *rootfs.tar.xz\nshort  other\n");
+        assert_eq!(sha256sums_entry(&index, "meta.tar.xz"), Some(a));
+        assert_eq!(
+            sha256sums_entry(&index, "rootfs.tar.xz"),
+            Some("b".repeat(64))
+        );
+        assert_eq!(sha256sums_entry(&index, "other"), None);
+        assert_eq!(sha256sums_entry(&index, "rootfs.squashfs"), None);
+    }
+}
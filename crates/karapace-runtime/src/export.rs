@@ -1,4 +1,13 @@
+//! Desktop entries for applications inside environments.
+//!
+//! `karapace desktop-export` writes a `.desktop` file to the host's
+//! `~/.local/share/applications` that launches an application with
+//! `karapace enter`, so it shows up in the desktop's launcher. Name, icon
+//! and command come from the application's own desktop entry in the
+//! environment when it has one.
+
 use crate::RuntimeError;
+use std::fmt::Write as _;
 use std::path::{Path, PathBuf};
 
 pub struct ExportedApp {
@@ -7,6 +16,66 @@ pub struct ExportedApp {
     pub exec_command: String,
 }
 
+/// The parts of an application's desktop entry carried over to the
+/// exported one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppEntry {
+    pub name: String,
+    /// Command line, with any field codes such as `%U` left for the
+    /// launcher to expand.
+    pub exec: String,
+    pub icon: Option<String>,
+    pub categories: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+/// Parse the unlocalized keys of the `[Desktop Entry]` group.
+fn parse_desktop_entry(contents: &str) -> Option<AppEntry> {
+    let mut in_entry = false;
+    let mut keys = std::collections::HashMap::new();
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if let (true, Some((key, value))) = (in_entry, line.split_once('=')) {
+            keys.entry(key.trim())
+                .or_insert_with(|| value.trim().to_owned());
+        }
+    }
+    if keys.get("NoDisplay").is_some_and(|v| v == "true") {
+        return None;
+    }
+    Some(AppEntry {
+        name: keys.remove("Name")?,
+        exec: keys.remove("Exec")?,
+        icon: keys.remove("Icon"),
+        categories: keys.remove("Categories"),
+        mime_type: keys.remove("MimeType"),
+    })
+}
+
+/// Find `app` in the environment filesystem at `root`: its desktop entry
+/// `usr/share/applications/<app>.desktop`, or else an executable
+/// `usr/bin/<app>`.
+pub fn find_app(root: &Path, app: &str) -> Option<AppEntry> {
+    if app.is_empty() || app.contains('/') {
+        return None;
+    }
+    let desktop = root.join(format!("usr/share/applications/{app}.desktop"));
+    if let Ok(contents) = std::fs::read_to_string(desktop) {
+        return parse_desktop_entry(&contents);
+    }
+    // Not followed: links point at paths inside the environment.
+    let is_file =
+        std::fs::symlink_metadata(root.join("usr/bin").join(app)).is_ok_and(|meta| !meta.is_dir());
+    is_file.then(|| AppEntry {
+        name: app.to_owned(),
+        exec: format!("/usr/bin/{app}"),
+        icon: None,
+        categories: None,
+        mime_type: None,
+    })
+}
+
 fn default_desktop_dir() -> Result<PathBuf, RuntimeError> {
     if let Ok(home) = std::env::var("HOME") {
         Ok(PathBuf::from(home).join(".local/share/applications"))
@@ -31,7 +100,7 @@ fn write_desktop_entry(
     desktop_dir: &Path,
     env_id: &str,
     app_name: &str,
-    binary_path: &str,
+    app: &AppEntry,
     karapace_bin: &str,
     store_path: &str,
 ) -> Result<ExportedApp, RuntimeError> {
@@ -42,22 +111,30 @@ fn write_desktop_entry(
     let desktop_id = desktop_file_name(env_id, app_name);
     let desktop_path = desktop_dir.join(&desktop_id);
 
-    let exec_cmd = format!("{karapace_bin} --store {store_path} enter {short_id} -- {binary_path}");
+    let exec_cmd = format!(
+        "{karapace_bin} --store {store_path} enter {short_id} -- {}",
+        app.exec
+    );
 
-    let icon = app_name;
+    let icon = app.icon.as_deref().unwrap_or(app_name);
+    let categories = app.categories.as_deref().unwrap_or("");
 
-    let contents = format!(
+    let mut contents = format!(
         "[Desktop Entry]\n\
          Type=Application\n\
-         Name={app_name} (Karapace {short_id})\n\
+         Name={} (Karapace {short_id})\n\
          Exec={exec_cmd}\n\
          Icon={icon}\n\
          Terminal=false\n\
-         Categories=Karapace;\n\
+         Categories={categories}Karapace;\n\
          X-Karapace-EnvId={env_id}\n\
          X-Karapace-Store={store_path}\n\
-         Comment=Launched inside Karapace environment {short_id}\n"
+         Comment=Launched inside Karapace environment {short_id}\n",
+        app.name
     );
+    if let Some(mime_type) = &app.mime_type {
+        let _ = writeln!(contents, "MimeType={mime_type}");
+    }
 
     std::fs::write(&desktop_path, &contents)?;
 
@@ -77,7 +154,7 @@ fn write_desktop_entry(
 pub fn export_app(
     env_id: &str,
     app_name: &str,
-    binary_path: &str,
+    app: &AppEntry,
     karapace_bin: &str,
     store_path: &str,
 ) -> Result<ExportedApp, RuntimeError> {
@@ -86,7 +163,7 @@ pub fn export_app(
         &desktop_dir,
         env_id,
         app_name,
-        binary_path,
+        app,
         karapace_bin,
         store_path,
     )
@@ -174,6 +251,16 @@ mod tests {
 
     const TEST_ENV_ID: &str = "abc123def456789012345678901234567890123456789012345678901234";
 
+    fn binary_app(name: &str) -> AppEntry {
+        AppEntry {
+            name: name.to_owned(),
+            exec: format!("/usr/bin/{name}"),
+            icon: None,
+            categories: None,
+            mime_type: None,
+        }
+    }
+
     #[test]
     fn export_unexport_roundtrip() {
         let (_dir, apps) = test_desktop_dir();
@@ -182,7 +269,7 @@ mod tests {
             &apps,
             TEST_ENV_ID,
             "test-app",
-            "/usr/bin/test-app",
+            &binary_app("test-app"),
             "/usr/bin/karapace",
             "/tmp/store",
         )
@@ -208,7 +295,7 @@ mod tests {
             &apps,
             TEST_ENV_ID,
             "app1",
-            "/usr/bin/app1",
+            &binary_app("app1"),
             "/usr/bin/karapace",
             "/tmp/store",
         )
@@ -217,7 +304,7 @@ mod tests {
             &apps,
             TEST_ENV_ID,
             "app2",
-            "/usr/bin/app2",
+            &binary_app("app2"),
             "/usr/bin/karapace",
             "/tmp/store",
         )
@@ -229,4 +316,58 @@ mod tests {
         let found = list_entries(&apps, TEST_ENV_ID).unwrap();
         assert!(found.is_empty());
     }
+
+    #[test]
+    fn find_app_reads_the_desktop_entry() {
+        let root = tempfile::tempdir().unwrap();
+        let apps = root.path().join("usr/share/applications");
+        std::fs::create_dir_all(&apps).unwrap();
+        std::fs::write(
+            apps.join("org.example.Editor.desktop"),
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=Editor\n\
+             Name[de]=Bearbeiter\n\
+             Exec=editor --new-window %U\n\
+             Icon=org.example.Editor\n\
+             Categories=Utility;TextEditor;\n\
+             MimeType=text/plain;\n\
+             \n\
+             [Desktop Action new]\n\
+             Name=New\n\
+             Exec=editor --new\n",
+        )
+        .unwrap();
+
+        let app = find_app(root.path(), "org.example.Editor").unwrap();
+        assert_eq!(app.name, "Editor");
+        assert_eq!(app.exec, "editor --new-window %U");
+        assert_eq!(app.icon.as_deref(), Some("org.example.Editor"));
+
+        let (_dir, desktop_dir) = test_desktop_dir();
+        let exported = write_desktop_entry(
+            &desktop_dir,
+            TEST_ENV_ID,
+            "org.example.Editor",
+            &app,
+            "/usr/bin/karapace",
+            "/tmp/store",
+        )
+        .unwrap();
+        let contents = std::fs::read_to_string(exported.desktop_file).unwrap();
+        assert!(contents.contains("Exec=/usr/bin/karapace --store /tmp/store enter abc123def456 -- editor --new-window %U\n"));
+        assert!(contents.contains("Categories=Utility;TextEditor;Karapace;\n"));
+        assert!(contents.contains("MimeType=text/plain;\n"));
+    }
+
+    #[test]
+    fn find_app_falls_back_to_binaries() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        std::fs::write(root.path().join("usr/bin/htop"), b"").unwrap();
+
+        assert_eq!(find_app(root.path(), "htop"), Some(binary_app("htop")));
+        assert_eq!(find_app(root.path(), "missing"), None);
+        assert_eq!(find_app(root.path(), "../bin/htop"), None);
+    }
 }
@@ -1,6 +1,11 @@
 use crate::sandbox::BindMount;
+use crate::security::SecurityPolicy;
+use crate::RuntimeError;
 use karapace_schema::NormalizedManifest;
+use karapace_store::GpuDriverInfo;
+use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 pub struct HostIntegration {
     pub bind_mounts: Vec<BindMount>,
@@ -8,15 +13,12 @@ pub struct HostIntegration {
 }
 
 #[allow(clippy::too_many_lines)]
-pub fn compute_host_integration(manifest: &NormalizedManifest) -> HostIntegration {
+pub fn compute_host_integration(
+    manifest: &NormalizedManifest,
+) -> Result<HostIntegration, RuntimeError> {
     let mut bind_mounts = Vec::new();
     let mut env_vars = Vec::new();
 
-    // Wayland display
-    if let Ok(wayland) = std::env::var("WAYLAND_DISPLAY") {
-        env_vars.push(("WAYLAND_DISPLAY".to_owned(), wayland));
-    }
-
     // X11 display
     if let Ok(display) = std::env::var("DISPLAY") {
         env_vars.push(("DISPLAY".to_owned(), display));
@@ -25,6 +27,8 @@ pub fn compute_host_integration(manifest: &NormalizedManifest) -> HostIntegratio
                 source: PathBuf::from("/tmp/.X11-unix"),
                 target: PathBuf::from("/tmp/.X11-unix"),
                 read_only: true,
+                options: Vec::new(),
+                pinned: None,
             });
         }
         // Xauthority
@@ -34,60 +38,25 @@ pub fn compute_host_integration(manifest: &NormalizedManifest) -> HostIntegratio
                     source: PathBuf::from(&xauth),
                     target: PathBuf::from(&xauth),
                     read_only: true,
+                    options: Vec::new(),
+                    pinned: None,
                 });
                 env_vars.push(("XAUTHORITY".to_owned(), xauth));
             }
         }
     }
 
-    // XDG_RUNTIME_DIR sockets
-    if let Ok(xdg_run) = std::env::var("XDG_RUNTIME_DIR") {
-        let xdg_path = PathBuf::from(&xdg_run);
-        env_vars.push(("XDG_RUNTIME_DIR".to_owned(), xdg_run.clone()));
-
-        // PipeWire socket
-        let pipewire = xdg_path.join("pipewire-0");
-        if pipewire.exists() {
-            bind_mounts.push(BindMount {
-                source: pipewire.clone(),
-                target: pipewire,
-                read_only: false,
-            });
-        }
-
-        // PulseAudio socket
-        let pulse = xdg_path.join("pulse/native");
-        if pulse.exists() {
-            bind_mounts.push(BindMount {
-                source: pulse.clone(),
-                target: pulse,
-                read_only: false,
-            });
-        }
-
-        // D-Bus session socket
-        let dbus = xdg_path.join("bus");
-        if dbus.exists() {
-            bind_mounts.push(BindMount {
-                source: dbus.clone(),
-                target: dbus,
-                read_only: false,
-            });
-            env_vars.push((
-                "DBUS_SESSION_BUS_ADDRESS".to_owned(),
-                format!("unix:path={xdg_run}/bus"),
-            ));
-        }
-
-        // Wayland socket
-        let wayland_sock = xdg_path.join("wayland-0");
-        if wayland_sock.exists() {
-            bind_mounts.push(BindMount {
-                source: wayland_sock.clone(),
-                target: wayland_sock,
-                read_only: false,
-            });
-        }
+    // Sockets and portals from the host's runtime directory
+    if let Ok(host_run) = std::env::var("XDG_RUNTIME_DIR") {
+        let runtime = runtime_dir_integration(
+            manifest,
+            Path::new(&host_run),
+            &container_runtime_dir(),
+            std::env::var("WAYLAND_DISPLAY").ok().as_deref(),
+            pulse_cookie().as_deref(),
+        );
+        bind_mounts.extend(runtime.bind_mounts);
+        env_vars.extend(runtime.env_vars);
     }
 
     // GPU passthrough
@@ -98,41 +67,60 @@ pub fn compute_host_integration(manifest: &NormalizedManifest) -> HostIntegratio
                 source: PathBuf::from("/dev/dri"),
                 target: PathBuf::from("/dev/dri"),
                 read_only: false,
+                options: Vec::new(),
+                pinned: None,
             });
         }
-        // Nvidia devices
-        for dev in &[
-            "/dev/nvidia0",
-            "/dev/nvidiactl",
-            "/dev/nvidia-modeset",
-            "/dev/nvidia-uvm",
-        ] {
-            if Path::new(dev).exists() {
-                bind_mounts.push(BindMount {
-                    source: PathBuf::from(dev),
-                    target: PathBuf::from(dev),
-                    read_only: false,
-                });
-            }
-        }
+        // NVIDIA device nodes, driver libraries and tools
+        let nvidia = nvidia_integration_in(Path::new("/"));
+        bind_mounts.extend(nvidia.bind_mounts);
+        env_vars.extend(nvidia.env_vars);
     }
 
-    // Audio passthrough
-    if manifest.hardware_audio && Path::new("/dev/snd").exists() {
+    // Audio and camera passthrough: only the device nodes for the granted
+    // directions, so playback-only environments cannot open capture PCMs.
+    let mut devices = alsa_devices(
+        Path::new("/dev/snd"),
+        manifest.hardware_audio_out,
+        manifest.hardware_audio_in,
+    );
+    if manifest.hardware_camera {
+        devices.extend(camera_devices(Path::new("/dev")));
+    }
+    for dev in devices {
         bind_mounts.push(BindMount {
-            source: PathBuf::from("/dev/snd"),
-            target: PathBuf::from("/dev/snd"),
+            source: dev.clone(),
+            target: dev,
             read_only: false,
+            options: Vec::new(),
+            pinned: None,
         });
     }
 
-    // Manifest-declared mounts
+    // Manifest-declared mounts. Re-resolved against the live filesystem on
+    // every enter: the manifest was validated at build time, but a symlink
+    // planted since then must not redirect the bind outside the policy roots.
+    // The source stays open until it is bound, see `BindMount::pinned`.
+    let policy = SecurityPolicy::from_manifest(manifest);
     for mount in &manifest.mounts {
-        let host_path = expand_path(&mount.host_path);
+        let source = match policy.resolve_mount_source(&mount.host_path, mount.is_read_only()) {
+            Ok(source) => source,
+            Err(RuntimeError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::warn!(
+                    "skipping mount '{}': host path {} does not exist",
+                    mount.label,
+                    mount.host_path
+                );
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
         bind_mounts.push(BindMount {
-            source: host_path,
+            source: source.path,
             target: PathBuf::from(&mount.container_path),
-            read_only: false,
+            read_only: mount.is_read_only(),
+            options: mount.options.clone(),
+            pinned: Some(Arc::new(source.fd)),
         });
     }
 
@@ -157,6 +145,101 @@ pub fn compute_host_integration(manifest: &NormalizedManifest) -> HostIntegratio
                 source: PathBuf::from(dir),
                 target: PathBuf::from(dir),
                 read_only: true,
+                options: Vec::new(),
+                pinned: None,
+            });
+        }
+    }
+
+    Ok(HostIntegration {
+        bind_mounts,
+        env_vars,
+    })
+}
+
+/// `$XDG_RUNTIME_DIR` inside the environment. Whatever the host's runtime
+/// directory is called, its sockets are mounted here.
+pub fn container_runtime_dir() -> PathBuf {
+    PathBuf::from(format!("/run/user/{}", crate::sandbox::current_uid()))
+}
+
+/// What the session gets from the host runtime directory `host_run`, mounted
+/// under `container_run`: the sockets of the running sound server the
+/// manifest's media policy grants, with PulseAudio's cookie, the session
+/// bus, the Wayland socket named by `wayland_display`, and the document
+/// portal. Portal requests go over the session bus; files picked through
+/// them are exposed under `doc/`, which GTK is told to use.
+fn runtime_dir_integration(
+    manifest: &NormalizedManifest,
+    host_run: &Path,
+    container_run: &Path,
+    wayland_display: Option<&str>,
+    pulse_cookie: Option<&Path>,
+) -> HostIntegration {
+    let mut bind_mounts = Vec::new();
+    let mut env_vars = vec![(
+        "XDG_RUNTIME_DIR".to_owned(),
+        container_run.to_string_lossy().into_owned(),
+    )];
+    let mut bind = |socket: &Path, target: &str| -> bool {
+        if !socket.exists() {
+            return false;
+        }
+        bind_mounts.push(BindMount {
+            source: socket.to_path_buf(),
+            target: container_run.join(target),
+            read_only: false,
+            options: Vec::new(),
+            pinned: None,
+        });
+        true
+    };
+
+    let audio = AudioStack::detect(host_run);
+    for socket in media_sockets(manifest, audio) {
+        if bind(&host_run.join(socket), socket) && socket == PULSE_SOCKET {
+            env_vars.push((
+                "PULSE_SERVER".to_owned(),
+                format!("unix:{}", container_run.join(PULSE_SOCKET).display()),
+            ));
+        }
+    }
+
+    if bind(&host_run.join("bus"), "bus") {
+        env_vars.push((
+            "DBUS_SESSION_BUS_ADDRESS".to_owned(),
+            format!("unix:path={}", container_run.join("bus").display()),
+        ));
+    }
+
+    // WAYLAND_DISPLAY is a socket name in the runtime directory or an
+    // absolute path; either way it becomes a name in the container's.
+    let display = host_run.join(wayland_display.unwrap_or("wayland-0"));
+    if let Some(name) = display.file_name().and_then(|n| n.to_str()) {
+        if bind(&display, name) {
+            env_vars.push(("WAYLAND_DISPLAY".to_owned(), name.to_owned()));
+        }
+    }
+
+    if bind(&host_run.join("doc"), "doc") {
+        env_vars.push(("GTK_USE_PORTAL".to_owned(), "1".to_owned()));
+    }
+
+    // PulseAudio itself authenticates clients with a cookie; pipewire-pulse
+    // does not.
+    if audio == AudioStack::PulseAudio && env_vars.iter().any(|(k, _)| k == "PULSE_SERVER") {
+        if let Some(cookie) = pulse_cookie.filter(|c| c.is_file()) {
+            let target = container_run.join("pulse/cookie");
+            env_vars.push((
+                "PULSE_COOKIE".to_owned(),
+                target.to_string_lossy().into_owned(),
+            ));
+            bind_mounts.push(BindMount {
+                source: cookie.to_path_buf(),
+                target,
+                read_only: true,
+                options: Vec::new(),
+                pinned: None,
             });
         }
     }
@@ -167,7 +250,308 @@ pub fn compute_host_integration(manifest: &NormalizedManifest) -> HostIntegratio
     }
 }
 
-fn expand_path(path: &str) -> PathBuf {
+const PIPEWIRE_SOCKET: &str = "pipewire-0";
+const PULSE_SOCKET: &str = "pulse/native";
+
+/// The sound server running on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioStack {
+    /// PipeWire, usually with pipewire-pulse serving PulseAudio clients on
+    /// `pulse/native` as well.
+    PipeWire,
+    PulseAudio,
+    None,
+}
+
+impl AudioStack {
+    /// Detect the sound server from the sockets in the host runtime
+    /// directory `host_run` that accept connections. A socket left behind
+    /// by a server that has exited does not count.
+    pub fn detect(host_run: &Path) -> Self {
+        let listening = |socket: &str| UnixStream::connect(host_run.join(socket)).is_ok();
+        if listening(PIPEWIRE_SOCKET) {
+            Self::PipeWire
+        } else if listening(PULSE_SOCKET) {
+            Self::PulseAudio
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Sockets under `$XDG_RUNTIME_DIR` the manifest's media policy grants on a
+/// host running `audio`. PulseAudio carries audio only; PipeWire carries
+/// audio and camera streams, and PulseAudio clients through pipewire-pulse.
+fn media_sockets(manifest: &NormalizedManifest, audio: AudioStack) -> Vec<&'static str> {
+    let wants_audio = manifest.hardware_audio_out || manifest.hardware_audio_in;
+    let mut sockets = Vec::new();
+    if audio == AudioStack::PipeWire && (wants_audio || manifest.hardware_camera) {
+        sockets.push(PIPEWIRE_SOCKET);
+    }
+    if wants_audio && audio != AudioStack::None {
+        sockets.push(PULSE_SOCKET);
+    }
+    sockets
+}
+
+/// The cookie PulseAudio clients authenticate with, where libpulse looks
+/// for it.
+fn pulse_cookie() -> Option<PathBuf> {
+    if let Ok(cookie) = std::env::var("PULSE_COOKIE") {
+        return Some(PathBuf::from(cookie));
+    }
+    let home = std::env::var("HOME").map(PathBuf::from).ok();
+    let config = std::env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .ok()
+        .or_else(|| Some(home.as_ref()?.join(".config")));
+    [
+        config.map(|c| c.join("pulse/cookie")),
+        home.map(|h| h.join(".pulse-cookie")),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|cookie| cookie.is_file())
+}
+
+/// ALSA device nodes in `snd_dir` for the requested directions: playback
+/// PCMs (`pcmC*D*p`) for output, capture PCMs (`pcmC*D*c`) for input, and
+/// the shared control/timer nodes for either. The sequencer is output-only.
+fn alsa_devices(snd_dir: &Path, output: bool, input: bool) -> Vec<PathBuf> {
+    if !output && !input {
+        return Vec::new();
+    }
+    let Ok(entries) = std::fs::read_dir(snd_dir) else {
+        return Vec::new();
+    };
+    let mut devices: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                return false;
+            };
+            if let Some(pcm) = name.strip_prefix("pcmC") {
+                (output && pcm.ends_with('p')) || (input && pcm.ends_with('c'))
+            } else if name.starts_with("controlC") || name == "timer" {
+                true
+            } else {
+                output && name == "seq"
+            }
+        })
+        .map(|entry| entry.path())
+        .collect();
+    devices.sort();
+    devices
+}
+
+/// Video capture nodes (`video*`) and their media controllers (`media*`).
+fn camera_devices(dev_dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dev_dir) else {
+        return Vec::new();
+    };
+    let mut devices: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| {
+            entry.file_name().to_str().is_some_and(|name| {
+                ["video", "media"].iter().any(|prefix| {
+                    name.strip_prefix(prefix)
+                        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+                })
+            })
+        })
+        .map(|entry| entry.path())
+        .collect();
+    devices.sort();
+    devices
+}
+
+/// Library directories searched for the NVIDIA userspace driver.
+const NVIDIA_LIB_DIRS: &[&str] = &[
+    "usr/lib",
+    "usr/lib64",
+    "usr/lib/x86_64-linux-gnu",
+    "usr/lib/aarch64-linux-gnu",
+];
+
+/// The directory under `root` holding the NVIDIA userland libraries, and
+/// the driver version taken from the name of `libnvidia-glcore.so.<version>`.
+fn nvidia_lib_dir(root: &Path) -> Option<(PathBuf, String)> {
+    NVIDIA_LIB_DIRS.iter().find_map(|dir| {
+        let dir = root.join(dir);
+        let version = std::fs::read_dir(&dir).ok()?.flatten().find_map(|entry| {
+            let name = entry.file_name();
+            let version = name.to_str()?.strip_prefix("libnvidia-glcore.so.")?;
+            Some(version.to_owned())
+        })?;
+        Some((dir, version))
+    })
+}
+
+/// Where the NVIDIA userland libraries are mounted inside the environment.
+/// Images keep their libraries in different directories, so this one is put
+/// on `LD_LIBRARY_PATH` instead of mounting over the image's own.
+pub const NVIDIA_LIB_TARGET: &str = "/usr/lib/karapace-nvidia";
+
+/// Name prefixes of the driver's userland libraries: the ones
+/// nvidia-container-cli mounts for the compute, utility, video and graphics
+/// capabilities.
+const NVIDIA_LIBS: &[&str] = &[
+    "libnvidia-",
+    "libcuda.so",
+    "libcudadebugger.so",
+    "libnvcuvid.so",
+    "libnvoptix.so",
+    "libGLX_nvidia.so",
+    "libEGL_nvidia.so",
+    "libGLESv2_nvidia.so",
+    "libGLESv1_CM_nvidia.so",
+];
+
+/// Driver tools and vendor files, mounted at their host paths.
+const NVIDIA_FILES: &[&str] = &[
+    "usr/bin/nvidia-smi",
+    "usr/bin/nvidia-debugdump",
+    "usr/share/vulkan/icd.d/nvidia_icd.json",
+    "usr/share/glvnd/egl_vendor.d/10_nvidia.json",
+    "usr/share/egl/egl_external_platform.d/15_nvidia_gbm.json",
+];
+
+/// NVIDIA passthrough from the host at `root`: every `/dev/nvidia*` node,
+/// and when the userland driver is installed its libraries under
+/// [`NVIDIA_LIB_TARGET`] and its tools and vendor files. Empty on hosts
+/// without the driver.
+fn nvidia_integration_in(root: &Path) -> HostIntegration {
+    let mut bind_mounts = Vec::new();
+    let mut env_vars = Vec::new();
+    let bind = |source: PathBuf, target: PathBuf, read_only: bool| BindMount {
+        source,
+        target,
+        read_only,
+        options: Vec::new(),
+        pinned: None,
+    };
+
+    let mut devices: Vec<String> = std::fs::read_dir(root.join("dev"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with("nvidia"))
+        .collect();
+    devices.sort();
+    for name in devices {
+        let target = Path::new("/dev").join(&name);
+        bind_mounts.push(bind(root.join("dev").join(name), target, false));
+    }
+
+    let Some((lib_dir, version)) = nvidia_lib_dir(root) else {
+        return HostIntegration {
+            bind_mounts,
+            env_vars,
+        };
+    };
+    tracing::debug!("passing through NVIDIA driver {version}");
+
+    // Versioned libraries and the soname links pointing at them; binding a
+    // link mounts the file it resolves to.
+    let mut libraries: Vec<String> = std::fs::read_dir(&lib_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| NVIDIA_LIBS.iter().any(|prefix| name.starts_with(prefix)))
+        .filter(|name| name.contains(".so."))
+        .collect();
+    libraries.sort();
+    for name in libraries {
+        let target = Path::new(NVIDIA_LIB_TARGET).join(&name);
+        bind_mounts.push(bind(lib_dir.join(name), target, true));
+    }
+    env_vars.push(("LD_LIBRARY_PATH".to_owned(), NVIDIA_LIB_TARGET.to_owned()));
+
+    for file in NVIDIA_FILES {
+        let source = root.join(file);
+        if source.exists() {
+            bind_mounts.push(bind(source, Path::new("/").join(file), true));
+        }
+    }
+
+    HostIntegration {
+        bind_mounts,
+        env_vars,
+    }
+}
+
+/// Detect the host GPU driver versions relevant to passthrough.
+pub fn detect_gpu_drivers() -> GpuDriverInfo {
+    detect_gpu_drivers_in(Path::new("/"))
+}
+
+fn detect_gpu_drivers_in(root: &Path) -> GpuDriverInfo {
+    let nvidia_kernel = std::fs::read_to_string(root.join("sys/module/nvidia/version"))
+        .ok()
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty());
+
+    let nvidia_userspace = nvidia_lib_dir(root).map(|(_, version)| version);
+
+    let mut drm_drivers: Vec<String> = std::fs::read_dir(root.join("sys/class/drm"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with("card") && !name.contains('-')
+        })
+        .filter_map(|entry| {
+            let driver = std::fs::read_link(entry.path().join("device/driver")).ok()?;
+            Some(driver.file_name()?.to_string_lossy().into_owned())
+        })
+        .collect();
+    drm_drivers.sort();
+    drm_drivers.dedup();
+
+    GpuDriverInfo {
+        nvidia_kernel,
+        nvidia_userspace,
+        drm_drivers,
+    }
+}
+
+/// Describe every difference between the GPU drivers recorded at build time
+/// and the ones currently on the host. Empty when nothing changed.
+pub fn gpu_driver_drift(built: &GpuDriverInfo, current: &GpuDriverInfo) -> Vec<String> {
+    fn show(v: Option<&str>) -> &str {
+        v.unwrap_or("(none)")
+    }
+    let mut changes = Vec::new();
+    if built.nvidia_kernel != current.nvidia_kernel {
+        changes.push(format!(
+            "nvidia kernel module: {} → {}",
+            show(built.nvidia_kernel.as_deref()),
+            show(current.nvidia_kernel.as_deref())
+        ));
+    }
+    if built.nvidia_userspace != current.nvidia_userspace {
+        changes.push(format!(
+            "nvidia userspace: {} → {}",
+            show(built.nvidia_userspace.as_deref()),
+            show(current.nvidia_userspace.as_deref())
+        ));
+    }
+    if built.drm_drivers != current.drm_drivers {
+        changes.push(format!(
+            "drm drivers: [{}] → [{}]",
+            built.drm_drivers.join(", "),
+            current.drm_drivers.join(", ")
+        ));
+    }
+    changes
+}
+
+pub(crate) fn expand_path(path: &str) -> PathBuf {
     if let Some(stripped) = path.strip_prefix("~/") {
         if let Ok(home) = std::env::var("HOME") {
             return PathBuf::from(home).join(stripped);
@@ -185,6 +569,7 @@ fn expand_path(path: &str) -> PathBuf {
 mod tests {
     use super::*;
     use karapace_schema::parse_manifest_str;
+    use std::collections::HashMap;
 
     #[test]
     fn host_integration_includes_gpu_when_requested() {
@@ -201,7 +586,7 @@ gpu = true
         .normalize()
         .unwrap();
 
-        let hi = compute_host_integration(&manifest);
+        let hi = compute_host_integration(&manifest).unwrap();
         let has_dri = hi
             .bind_mounts
             .iter()
@@ -227,7 +612,7 @@ gpu = false
         .normalize()
         .unwrap();
 
-        let hi = compute_host_integration(&manifest);
+        let hi = compute_host_integration(&manifest).unwrap();
         let has_dri = hi
             .bind_mounts
             .iter()
@@ -237,26 +622,333 @@ gpu = false
 
     #[test]
     fn manifest_mounts_included() {
-        let manifest = parse_manifest_str(
+        let src = tempfile::tempdir_in("/tmp").unwrap();
+        let manifest = parse_manifest_str(&format!(
             r#"
 manifest_version = 1
 [base]
 image = "rolling"
 [mounts]
-workspace = "/tmp/test-src:/workspace"
+workspace = "{}:/workspace"
 "#,
-        )
+            src.path().display()
+        ))
         .unwrap()
         .normalize()
         .unwrap();
 
-        let hi = compute_host_integration(&manifest);
+        let hi = compute_host_integration(&manifest).unwrap();
         assert!(hi
             .bind_mounts
             .iter()
             .any(|m| m.target.as_path() == Path::new("/workspace")));
     }
 
+    fn hardware_manifest(hardware: &str) -> NormalizedManifest {
+        parse_manifest_str(&format!(
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[hardware]\n{hardware}\n"
+        ))
+        .unwrap()
+        .normalize()
+        .unwrap()
+    }
+
+    fn fake_dev(names: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for name in names {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+        dir
+    }
+
+    fn file_names(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn alsa_devices_split_by_direction() {
+        let snd = fake_dev(&[
+            "controlC0",
+            "pcmC0D0p",
+            "pcmC0D0c",
+            "pcmC1D3p",
+            "seq",
+            "timer",
+        ]);
+        std::fs::create_dir(snd.path().join("by-path")).unwrap();
+
+        assert_eq!(
+            file_names(&alsa_devices(snd.path(), true, false)),
+            vec!["controlC0", "pcmC0D0p", "pcmC1D3p", "seq", "timer"]
+        );
+        assert_eq!(
+            file_names(&alsa_devices(snd.path(), false, true)),
+            vec!["controlC0", "pcmC0D0c", "timer"]
+        );
+        assert_eq!(alsa_devices(snd.path(), true, true).len(), 6);
+        assert!(alsa_devices(snd.path(), false, false).is_empty());
+    }
+
+    #[test]
+    fn camera_devices_match_video_and_media_nodes() {
+        let dev = fake_dev(&["video0", "video12", "media0", "videodev", "null", "snd"]);
+        assert_eq!(
+            file_names(&camera_devices(dev.path())),
+            vec!["media0", "video0", "video12"]
+        );
+    }
+
+    #[test]
+    fn media_sockets_follow_policy() {
+        use AudioStack::{PipeWire, PulseAudio};
+        assert!(media_sockets(&hardware_manifest(""), PipeWire).is_empty());
+        assert_eq!(
+            media_sockets(&hardware_manifest("audio = true"), PipeWire),
+            vec!["pipewire-0", "pulse/native"]
+        );
+        assert_eq!(
+            media_sockets(&hardware_manifest("audio_in = true"), PipeWire),
+            vec!["pipewire-0", "pulse/native"]
+        );
+        assert_eq!(
+            media_sockets(&hardware_manifest("camera = true"), PipeWire),
+            vec!["pipewire-0"]
+        );
+        assert_eq!(
+            media_sockets(&hardware_manifest("audio = true"), PulseAudio),
+            vec!["pulse/native"]
+        );
+        assert!(media_sockets(&hardware_manifest("camera = true"), PulseAudio).is_empty());
+        assert!(media_sockets(&hardware_manifest("audio = true"), AudioStack::None).is_empty());
+    }
+
+    /// Listen on `socket` under `run`, as a running server would.
+    fn listen(run: &Path, socket: &str) -> std::os::unix::net::UnixListener {
+        let path = run.join(socket);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::os::unix::net::UnixListener::bind(path).unwrap()
+    }
+
+    #[test]
+    fn audio_stack_follows_listening_sockets() {
+        let run = tempfile::tempdir().unwrap();
+        assert_eq!(AudioStack::detect(run.path()), AudioStack::None);
+
+        let pulse = listen(run.path(), "pulse/native");
+        assert_eq!(AudioStack::detect(run.path()), AudioStack::PulseAudio);
+
+        // A stale PipeWire socket nobody listens on.
+        drop(listen(run.path(), "pipewire-0"));
+        assert_eq!(AudioStack::detect(run.path()), AudioStack::PulseAudio);
+
+        std::fs::remove_file(run.path().join("pipewire-0")).unwrap();
+        let _pipewire = listen(run.path(), "pipewire-0");
+        assert_eq!(AudioStack::detect(run.path()), AudioStack::PipeWire);
+        drop(pulse);
+    }
+
+    #[test]
+    fn pulseaudio_sessions_get_the_cookie() {
+        let host = tempfile::tempdir().unwrap();
+        let run = host.path().join("run");
+        let _pulse = listen(&run, "pulse/native");
+        let cookie = host.path().join("cookie");
+        std::fs::write(&cookie, [0u8; 256]).unwrap();
+        let container = Path::new("/run/user/1000");
+
+        let hi = runtime_dir_integration(
+            &hardware_manifest("audio = true"),
+            &run,
+            container,
+            None,
+            Some(&cookie),
+        );
+        let mounted = hi.bind_mounts.iter().find(|m| m.source == cookie).unwrap();
+        assert_eq!(mounted.target, container.join("pulse/cookie"));
+        assert!(mounted.read_only);
+        let env: HashMap<_, _> = hi.env_vars.into_iter().collect();
+        assert_eq!(env["PULSE_SERVER"], "unix:/run/user/1000/pulse/native");
+        assert_eq!(env["PULSE_COOKIE"], "/run/user/1000/pulse/cookie");
+
+        let hi =
+            runtime_dir_integration(&hardware_manifest(""), &run, container, None, Some(&cookie));
+        assert!(hi.bind_mounts.is_empty());
+    }
+
+    #[test]
+    fn runtime_dir_sockets_move_to_the_container_runtime_dir() {
+        let host = tempfile::tempdir().unwrap();
+        let run = host.path().join("run");
+        std::fs::create_dir_all(run.join("doc")).unwrap();
+        let _servers = ["bus", "wayland-1", "pipewire-0", "pulse/native"].map(|s| listen(&run, s));
+        let container = Path::new("/run/user/1000");
+
+        let hi = runtime_dir_integration(
+            &hardware_manifest("audio = true"),
+            &run,
+            container,
+            Some("wayland-1"),
+            None,
+        );
+        let targets: Vec<&Path> = hi.bind_mounts.iter().map(|m| m.target.as_path()).collect();
+        assert_eq!(
+            targets,
+            [
+                "/run/user/1000/pipewire-0",
+                "/run/user/1000/pulse/native",
+                "/run/user/1000/bus",
+                "/run/user/1000/wayland-1",
+                "/run/user/1000/doc",
+            ]
+            .map(Path::new)
+        );
+        let env: HashMap<_, _> = hi.env_vars.into_iter().collect();
+        assert_eq!(env["XDG_RUNTIME_DIR"], "/run/user/1000");
+        assert_eq!(
+            env["DBUS_SESSION_BUS_ADDRESS"],
+            "unix:path=/run/user/1000/bus"
+        );
+        assert_eq!(env["WAYLAND_DISPLAY"], "wayland-1");
+        assert_eq!(env["GTK_USE_PORTAL"], "1");
+        assert_eq!(env["PULSE_SERVER"], "unix:/run/user/1000/pulse/native");
+        assert!(!env.contains_key("PULSE_COOKIE"));
+
+        // An absolute WAYLAND_DISPLAY names a socket outside the runtime dir.
+        let socket = host.path().join("compositor.sock");
+        std::fs::write(&socket, b"").unwrap();
+        let hi = runtime_dir_integration(
+            &hardware_manifest(""),
+            &run,
+            container,
+            socket.to_str(),
+            None,
+        );
+        let wayland = hi.bind_mounts.iter().find(|m| m.source == socket).unwrap();
+        assert_eq!(wayland.target, container.join("compositor.sock"));
+        assert!(!hi
+            .bind_mounts
+            .iter()
+            .any(|m| m.source.ends_with("pipewire-0")));
+    }
+
+    #[test]
+    fn no_media_grants_without_policy() {
+        let hi = compute_host_integration(&hardware_manifest("gpu = false")).unwrap();
+        assert!(!hi.bind_mounts.iter().any(|m| {
+            m.source.starts_with("/dev/snd")
+                || m.source.to_string_lossy().starts_with("/dev/video")
+                || m.source.ends_with("pipewire-0")
+                || m.source.ends_with("pulse/native")
+        }));
+    }
+
+    #[test]
+    fn detect_gpu_drivers_reads_sysfs_and_libs() {
+        let root = tempfile::tempdir().unwrap();
+        let r = root.path();
+        std::fs::create_dir_all(r.join("sys/module/nvidia")).unwrap();
+        std::fs::write(r.join("sys/module/nvidia/version"), "550.54.14\n").unwrap();
+        std::fs::create_dir_all(r.join("usr/lib64")).unwrap();
+        std::fs::write(r.join("usr/lib64/libnvidia-glcore.so.550.54.14"), b"").unwrap();
+        std::fs::create_dir_all(r.join("drivers/nvidia")).unwrap();
+        for card in ["card0", "card0-DP-1"] {
+            std::fs::create_dir_all(r.join("sys/class/drm").join(card).join("device")).unwrap();
+            std::os::unix::fs::symlink(
+                r.join("drivers/nvidia"),
+                r.join("sys/class/drm").join(card).join("device/driver"),
+            )
+            .unwrap();
+        }
+
+        let info = detect_gpu_drivers_in(r);
+        assert_eq!(info.nvidia_kernel.as_deref(), Some("550.54.14"));
+        assert_eq!(info.nvidia_userspace.as_deref(), Some("550.54.14"));
+        assert_eq!(info.drm_drivers, vec!["nvidia"]);
+    }
+
+    #[test]
+    fn detect_gpu_drivers_empty_root() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(detect_gpu_drivers_in(root.path()), GpuDriverInfo::default());
+    }
+
+    #[test]
+    fn nvidia_integration_mounts_devices_and_driver_libraries() {
+        let root = tempfile::tempdir().unwrap();
+        let r = root.path();
+        std::fs::create_dir_all(r.join("dev/nvidia-caps")).unwrap();
+        for dev in ["nvidia0", "nvidiactl", "nvidia-uvm", "null"] {
+            std::fs::write(r.join("dev").join(dev), b"").unwrap();
+        }
+        let lib = r.join("usr/lib/x86_64-linux-gnu");
+        std::fs::create_dir_all(&lib).unwrap();
+        for name in [
+            "libnvidia-glcore.so.550.54.14",
+            "libcuda.so.550.54.14",
+            "libz.so.1",
+        ] {
+            std::fs::write(lib.join(name), b"").unwrap();
+        }
+        std::os::unix::fs::symlink("libcuda.so.550.54.14", lib.join("libcuda.so.1")).unwrap();
+        std::fs::create_dir_all(r.join("usr/bin")).unwrap();
+        std::fs::write(r.join("usr/bin/nvidia-smi"), b"").unwrap();
+
+        let nvidia = nvidia_integration_in(r);
+        let targets: Vec<&Path> = nvidia
+            .bind_mounts
+            .iter()
+            .map(|m| m.target.as_path())
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                "/dev/nvidia-caps",
+                "/dev/nvidia-uvm",
+                "/dev/nvidia0",
+                "/dev/nvidiactl",
+                "/usr/lib/karapace-nvidia/libcuda.so.1",
+                "/usr/lib/karapace-nvidia/libcuda.so.550.54.14",
+                "/usr/lib/karapace-nvidia/libnvidia-glcore.so.550.54.14",
+                "/usr/bin/nvidia-smi",
+            ]
+            .map(Path::new)
+        );
+        assert!(nvidia.bind_mounts[4].read_only);
+        assert_eq!(nvidia.bind_mounts[4].source, lib.join("libcuda.so.1"));
+        assert_eq!(
+            nvidia.env_vars,
+            [("LD_LIBRARY_PATH".to_owned(), NVIDIA_LIB_TARGET.to_owned())]
+        );
+    }
+
+    #[test]
+    fn nvidia_integration_empty_without_driver() {
+        let root = tempfile::tempdir().unwrap();
+        let nvidia = nvidia_integration_in(root.path());
+        assert!(nvidia.bind_mounts.is_empty());
+        assert!(nvidia.env_vars.is_empty());
+    }
+
+    #[test]
+    fn gpu_driver_drift_reports_changes() {
+        let built = GpuDriverInfo {
+            nvidia_kernel: Some("535.104.05".to_owned()),
+            nvidia_userspace: Some("535.104.05".to_owned()),
+            drm_drivers: vec!["nvidia".to_owned()],
+        };
+        assert!(gpu_driver_drift(&built, &built).is_empty());
+
+        let current = GpuDriverInfo {
+            nvidia_kernel: Some("550.54.14".to_owned()),
+            ..built.clone()
+        };
+        let drift = gpu_driver_drift(&built, &current);
+        assert_eq!(drift, vec!["nvidia kernel module: 535.104.05 → 550.54.14"]);
+    }
+
     #[test]
     fn expand_tilde_path() {
         let expanded = expand_path("~/projects");
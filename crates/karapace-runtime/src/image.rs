@@ -1,17 +1,43 @@
-use crate::RuntimeError;
+use crate::download::{sha256sums_entry, Downloader};
+use crate::{BuildPhase, ProgressSink, RuntimeError};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 const LXC_IMAGE_BASE: &str = "https://images.linuxcontainers.org/images";
 
+/// Binaries of a NixOS image's system profile. NixOS keeps its shells and
+/// `nix-env` here rather than in `/bin` or `/usr/bin`.
+pub const NIX_SYSTEM_BIN: &str = "/nix/var/nix/profiles/system/sw/bin";
+
 #[derive(Debug, Clone)]
 pub enum ImageSource {
-    OpenSuse { variant: String },
-    Ubuntu { codename: String },
-    Debian { codename: String },
-    Fedora { version: String },
+    OpenSuse {
+        variant: String,
+    },
+    Ubuntu {
+        codename: String,
+    },
+    Debian {
+        codename: String,
+    },
+    Fedora {
+        version: String,
+    },
     Arch,
-    Custom { url: String },
+    Alpine {
+        version: String,
+    },
+    NixOs {
+        release: String,
+    },
+    Custom {
+        url: String,
+    },
+    /// An OCI or Docker image added with `karapace import`, named by the
+    /// sha256 digest of its config. It exists only in the local image cache.
+    Imported {
+        digest: String,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -21,9 +47,24 @@ pub struct ResolvedImage {
     pub display_name: String,
 }
 
+/// Prefix of `base.image` for images added with `karapace import`.
+pub const IMPORTED_IMAGE_PREFIX: &str = "oci:sha256:";
+
+/// Whether `image` names exactly one image: a download URL, or an imported
+/// image's digest.
+pub fn is_pinned_image(image: &str) -> bool {
+    let image = image.trim();
+    image.starts_with("http://")
+        || image.starts_with("https://")
+        || image.starts_with(IMPORTED_IMAGE_PREFIX)
+}
+
 pub fn resolve_pinned_image_url(name: &str) -> Result<String, RuntimeError> {
     let resolved = resolve_image(name)?;
-    download_url(&resolved.source)
+    if matches!(resolved.source, ImageSource::Imported { .. }) {
+        return Ok(name.trim().to_owned());
+    }
+    download_url(&resolved.source, &Downloader::new())
 }
 
 #[allow(clippy::too_many_lines)]
@@ -119,8 +160,56 @@ pub fn resolve_image(name: &str) -> Result<ResolvedImage, RuntimeError> {
             "archlinux".to_owned(),
             "Arch Linux".to_owned(),
         ),
+        "alpine" | "alpine/3.20" => (
+            ImageSource::Alpine {
+                version: "3.20".to_owned(),
+            },
+            "alpine-3.20".to_owned(),
+            "Alpine Linux 3.20".to_owned(),
+        ),
+        "alpine/3.21" => (
+            ImageSource::Alpine {
+                version: "3.21".to_owned(),
+            },
+            "alpine-3.21".to_owned(),
+            "Alpine Linux 3.21".to_owned(),
+        ),
+        "alpine/edge" => (
+            ImageSource::Alpine {
+                version: "edge".to_owned(),
+            },
+            "alpine-edge".to_owned(),
+            "Alpine Linux Edge".to_owned(),
+        ),
+        "nixos" | "nixos/24.11" => (
+            ImageSource::NixOs {
+                release: "24.11".to_owned(),
+            },
+            "nixos-24.11".to_owned(),
+            "NixOS 24.11".to_owned(),
+        ),
+        "nixos/unstable" => (
+            ImageSource::NixOs {
+                release: "unstable".to_owned(),
+            },
+            "nixos-unstable".to_owned(),
+            "NixOS Unstable".to_owned(),
+        ),
         other => {
-            if other.starts_with("http://") || other.starts_with("https://") {
+            if let Some(digest) = other.strip_prefix(IMPORTED_IMAGE_PREFIX) {
+                if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(RuntimeError::ImageNotFound(format!(
+                        "invalid imported image '{other}': expected {IMPORTED_IMAGE_PREFIX}<64 hex digits>"
+                    )));
+                }
+                (
+                    ImageSource::Imported {
+                        digest: digest.to_owned(),
+                    },
+                    format!("oci-{digest}"),
+                    format!("imported image sha256:{}", &digest[..12]),
+                )
+            } else if other.starts_with("http://") || other.starts_with("https://") {
                 (
                     ImageSource::Custom {
                         url: other.to_owned(),
@@ -134,7 +223,8 @@ pub fn resolve_image(name: &str) -> Result<ResolvedImage, RuntimeError> {
                      ubuntu, ubuntu/24.04, ubuntu/22.04, ubuntu/20.04, \
                      debian, debian/bookworm, debian/trixie, debian/sid, \
                      fedora, fedora/40, fedora/41, fedora/42, \
-                     arch, archlinux, or a URL"
+                     arch, archlinux, alpine, alpine/3.20, alpine/3.21, alpine/edge, \
+                     nixos, nixos/24.11, nixos/unstable, or a URL"
                 )));
             }
         }
@@ -151,20 +241,8 @@ fn lxc_rootfs_url(distro: &str, variant: &str) -> String {
     format!("{LXC_IMAGE_BASE}/{distro}/{variant}/amd64/default/")
 }
 
-fn fetch_latest_build(index_url: &str) -> Result<String, RuntimeError> {
-    let output = Command::new("curl")
-        .args(["-fsSL", "--max-time", "30", index_url])
-        .output()
-        .map_err(|e| RuntimeError::ExecFailed(format!("curl failed: {e}")))?;
-
-    if !output.status.success() {
-        return Err(RuntimeError::ExecFailed(format!(
-            "failed to fetch image index from {index_url}: {}",
-            String::from_utf8_lossy(&output.stderr)
-        )));
-    }
-
-    let body = String::from_utf8_lossy(&output.stdout);
+fn fetch_latest_build(index_url: &str, downloader: &Downloader) -> Result<String, RuntimeError> {
+    let body = downloader.get_text(index_url)?;
     // LXC image server uses build dates like "20260220_04:20/" or URL-encoded "20260220_04%3A20/"
     let mut builds: Vec<String> = body
         .lines()
@@ -194,13 +272,13 @@ fn url_encode_build(build: &str) -> String {
     build.replace(':', "%3A")
 }
 
-fn build_download_url(base_idx: &str) -> Result<String, RuntimeError> {
-    let build = fetch_latest_build(base_idx)?;
+fn build_download_url(base_idx: &str, downloader: &Downloader) -> Result<String, RuntimeError> {
+    let build = fetch_latest_build(base_idx, downloader)?;
     let encoded = url_encode_build(&build);
     Ok(format!("{base_idx}{encoded}/rootfs.tar.xz"))
 }
 
-fn download_url(source: &ImageSource) -> Result<String, RuntimeError> {
+fn download_url(source: &ImageSource, downloader: &Downloader) -> Result<String, RuntimeError> {
     match source {
         ImageSource::OpenSuse { variant } => {
             let idx = if variant == "tumbleweed" {
@@ -208,41 +286,69 @@ fn download_url(source: &ImageSource) -> Result<String, RuntimeError> {
             } else {
                 lxc_rootfs_url("opensuse", variant)
             };
-            build_download_url(&idx)
+            build_download_url(&idx, downloader)
         }
         ImageSource::Ubuntu { codename } => {
             let idx = lxc_rootfs_url("ubuntu", codename);
-            build_download_url(&idx)
+            build_download_url(&idx, downloader)
         }
         ImageSource::Debian { codename } => {
             let idx = lxc_rootfs_url("debian", codename);
-            build_download_url(&idx)
+            build_download_url(&idx, downloader)
         }
         ImageSource::Fedora { version } => {
             let idx = lxc_rootfs_url("fedora", version);
-            build_download_url(&idx)
+            build_download_url(&idx, downloader)
         }
         ImageSource::Arch => {
             let idx = lxc_rootfs_url("archlinux", "current");
-            build_download_url(&idx)
+            build_download_url(&idx, downloader)
+        }
+        ImageSource::Alpine { version } => {
+            let idx = lxc_rootfs_url("alpine", version);
+            build_download_url(&idx, downloader)
+        }
+        ImageSource::NixOs { release } => {
+            let idx = lxc_rootfs_url("nixos", release);
+            build_download_url(&idx, downloader)
         }
         ImageSource::Custom { url } => Ok(url.clone()),
+        ImageSource::Imported { digest } => Err(imported_image_missing(digest)),
     }
 }
 
+/// Imported images cannot be downloaded again.
+fn imported_image_missing(digest: &str) -> RuntimeError {
+    RuntimeError::ImageNotFound(format!(
+        "imported image sha256:{digest} is not in the image cache; \
+         run 'karapace import' with the original image again"
+    ))
+}
+
 pub struct ImageCache {
     cache_dir: PathBuf,
+    downloader: Downloader,
 }
 
 impl ImageCache {
     pub fn new(store_root: &Path) -> Self {
         Self {
             cache_dir: store_root.join("images"),
+            downloader: Downloader::new(),
         }
     }
 
     pub fn rootfs_path(&self, cache_key: &str) -> PathBuf {
-        self.cache_dir.join(cache_key).join("rootfs")
+        self.image_dir(cache_key).join("rootfs")
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// The directory holding an image's rootfs and its digest file.
+    pub(crate) fn image_dir(&self, cache_key: &str) -> PathBuf {
+        self.cache_dir.join(cache_key)
     }
 
     pub fn is_cached(&self, cache_key: &str) -> bool {
@@ -252,12 +358,12 @@ impl ImageCache {
     pub fn ensure_image(
         &self,
         resolved: &ResolvedImage,
-        progress: &dyn Fn(&str),
+        progress: &dyn ProgressSink,
         offline: bool,
     ) -> Result<PathBuf, RuntimeError> {
         let rootfs = self.rootfs_path(&resolved.cache_key);
         if self.is_cached(&resolved.cache_key) {
-            progress(&format!("using cached image: {}", resolved.display_name));
+            progress.message(&format!("using cached image: {}", resolved.display_name));
             return Ok(rootfs);
         }
 
@@ -267,42 +373,31 @@ impl ImageCache {
                 resolved.display_name
             )));
         }
+        if let ImageSource::Imported { digest } = &resolved.source {
+            return Err(imported_image_missing(digest));
+        }
 
         std::fs::create_dir_all(&rootfs)?;
 
-        progress(&format!(
+        progress.phase(BuildPhase::FetchImage);
+        progress.message(&format!(
             "resolving image URL for {}...",
             resolved.display_name
         ));
-        let url = download_url(&resolved.source)?;
+        let url = download_url(&resolved.source, &self.downloader)?;
 
         let tarball = self
             .cache_dir
             .join(&resolved.cache_key)
             .join("rootfs.tar.xz");
-        progress(&format!("downloading {url}..."));
-
-        let status = Command::new("curl")
-            .args([
-                "-fSL",
-                "--progress-bar",
-                "--max-time",
-                "600",
-                "-o",
-                &tarball.to_string_lossy(),
-                &url,
-            ])
-            .status()
-            .map_err(|e| RuntimeError::ExecFailed(format!("curl download failed: {e}")))?;
-
-        if !status.success() {
+        let require_sums = !matches!(resolved.source, ImageSource::Custom { .. });
+        if let Err(e) = self.fetch_verified(&url, require_sums, &tarball, progress) {
             let _ = std::fs::remove_dir_all(self.cache_dir.join(&resolved.cache_key));
-            return Err(RuntimeError::ExecFailed(format!(
-                "failed to download image from {url}"
-            )));
+            return Err(e);
         }
 
-        progress("extracting rootfs...");
+        progress.phase(BuildPhase::Unpack);
+        progress.message("extracting rootfs...");
         let status = Command::new("tar")
             .args([
                 "xf",
@@ -332,18 +427,67 @@ impl ImageCache {
         let _ = std::fs::remove_file(&tarball);
 
         // Compute and store the content digest for future integrity verification.
-        progress("computing image digest...");
+        progress.message("computing image digest...");
         let digest = compute_image_digest(&rootfs)?;
         let digest_file = self
             .cache_dir
             .join(&resolved.cache_key)
             .join("rootfs.blake3");
         std::fs::write(&digest_file, &digest)?;
+        std::fs::write(self.image_dir(&resolved.cache_key).join("source"), &url)?;
 
-        progress(&format!("image {} ready", resolved.display_name));
+        progress.message(&format!("image {} ready", resolved.display_name));
         Ok(rootfs)
     }
 
+    /// Download `url` to `dest` and check it against the `SHA256SUMS` index
+    /// in the same directory. The LXC image server publishes one for every
+    /// build, so it is required there; for custom URLs a missing index is
+    /// reported and the file accepted unchecked.
+    fn fetch_verified(
+        &self,
+        url: &str,
+        require_sums: bool,
+        dest: &Path,
+        progress: &dyn ProgressSink,
+    ) -> Result<(), RuntimeError> {
+        let (dir, file_name) = url
+            .rsplit_once('/')
+            .ok_or_else(|| RuntimeError::Download(format!("not a file URL: {url}")))?;
+        let sums_url = format!("{dir}/SHA256SUMS");
+        let expected = match self.downloader.get_text_if_exists(&sums_url)? {
+            Some(index) => Some(sha256sums_entry(&index, file_name).ok_or_else(|| {
+                RuntimeError::Download(format!("{sums_url} does not list {file_name}"))
+            })?),
+            None if require_sums => {
+                return Err(RuntimeError::Download(format!(
+                    "{sums_url} not found; refusing to cache an unverified image"
+                )));
+            }
+            None => {
+                progress.message(&format!(
+                    "no SHA256SUMS next to {url}; skipping checksum verification"
+                ));
+                None
+            }
+        };
+
+        progress.message(&format!("downloading {url}..."));
+        let actual = self.downloader.download(url, dest, progress)?;
+        if let Some(expected) = expected {
+            if expected != actual {
+                let _ = std::fs::remove_file(dest);
+                return Err(RuntimeError::ChecksumMismatch {
+                    file: url.to_owned(),
+                    expected,
+                    actual,
+                });
+            }
+            progress.message("checksum verified");
+        }
+        Ok(())
+    }
+
     /// Verify the integrity of a cached image by recomputing its digest
     /// and comparing it to the stored value. Returns an error if the image
     /// has been corrupted or tampered with.
@@ -370,6 +514,88 @@ impl ImageCache {
 
         Ok(())
     }
+
+    /// Download `resolved` into the cache unless it is already there, and
+    /// verify its digest.
+    pub fn pull(
+        &self,
+        resolved: &ResolvedImage,
+        progress: &dyn ProgressSink,
+    ) -> Result<CachedImage, RuntimeError> {
+        self.ensure_image(resolved, progress, false)?;
+        self.verify_image(&resolved.cache_key)?;
+        self.entry(&resolved.cache_key)
+    }
+
+    /// The cached image stored under `cache_key`.
+    pub fn entry(&self, cache_key: &str) -> Result<CachedImage, RuntimeError> {
+        if !self.is_cached(cache_key) {
+            return Err(RuntimeError::ImageNotFound(format!(
+                "'{cache_key}' is not in the image cache"
+            )));
+        }
+        let dir = self.image_dir(cache_key);
+        let read = |name: &str| {
+            std::fs::read_to_string(dir.join(name))
+                .map(|s| s.trim().to_owned())
+                .unwrap_or_default()
+        };
+        Ok(CachedImage {
+            cache_key: cache_key.to_owned(),
+            source: read("source"),
+            digest: read("rootfs.blake3"),
+            size_bytes: crate::quota::dir_usage(&dir),
+        })
+    }
+
+    /// All images in the cache, sorted by cache key. Directories without a
+    /// usable rootfs, such as interrupted downloads, are skipped.
+    pub fn list(&self) -> Result<Vec<CachedImage>, RuntimeError> {
+        let entries = match std::fs::read_dir(&self.cache_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut images = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if self.is_cached(&name) {
+                images.push(self.entry(&name)?);
+            }
+        }
+        images.sort_by(|a, b| a.cache_key.cmp(&b.cache_key));
+        Ok(images)
+    }
+
+    /// The digest recorded for `cache_key` when it was cached, if any.
+    pub fn stored_digest(&self, cache_key: &str) -> Option<String> {
+        std::fs::read_to_string(self.image_dir(cache_key).join("rootfs.blake3"))
+            .ok()
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Delete the image stored under `cache_key`.
+    pub fn remove(&self, cache_key: &str) -> Result<(), RuntimeError> {
+        if !self.image_dir(cache_key).is_dir() {
+            return Err(RuntimeError::ImageNotFound(format!(
+                "'{cache_key}' is not in the image cache"
+            )));
+        }
+        force_remove(&self.image_dir(cache_key))
+    }
+}
+
+/// A base image in the store's image cache.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CachedImage {
+    pub cache_key: String,
+    /// The URL the image was downloaded from, or the reference it was
+    /// imported from. Empty for images cached before sources were recorded.
+    pub source: String,
+    /// Content digest recorded when the image was cached.
+    pub digest: String,
+    pub size_bytes: u64,
 }
 
 /// Compute a content digest (blake3) of a rootfs directory.
@@ -426,60 +652,6 @@ fn collect_file_entries(
     Ok(())
 }
 
-/// Build a command to query installed package versions from the container.
-pub fn query_versions_command(pkg_manager: &str, packages: &[String]) -> Vec<String> {
-    match pkg_manager {
-        "apt" => {
-            // dpkg-query outputs name\tversion for each installed package
-            let mut cmd = vec![
-                "dpkg-query".to_owned(),
-                "-W".to_owned(),
-                "-f".to_owned(),
-                "${Package}\\t${Version}\\n".to_owned(),
-            ];
-            cmd.extend(packages.iter().cloned());
-            cmd
-        }
-        "dnf" | "zypper" => {
-            let mut cmd = vec![
-                "rpm".to_owned(),
-                "-q".to_owned(),
-                "--qf".to_owned(),
-                "%{NAME}\\t%{VERSION}-%{RELEASE}\\n".to_owned(),
-            ];
-            cmd.extend(packages.iter().cloned());
-            cmd
-        }
-        "pacman" => {
-            // pacman -Q outputs "name version" per line
-            let mut cmd = vec!["pacman".to_owned(), "-Q".to_owned()];
-            cmd.extend(packages.iter().cloned());
-            cmd
-        }
-        _ => Vec::new(),
-    }
-}
-
-/// Parse the output of a version query command into (name, version) pairs.
-pub fn parse_version_output(pkg_manager: &str, output: &str) -> Vec<(String, String)> {
-    let mut results = Vec::new();
-    for line in output.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
-        let parts: Vec<&str> = if pkg_manager == "pacman" {
-            line.splitn(2, ' ').collect()
-        } else {
-            line.splitn(2, '\t').collect()
-        };
-        if parts.len() == 2 {
-            results.push((parts[0].to_owned(), parts[1].to_owned()));
-        }
-    }
-    results
-}
-
 pub fn force_remove(path: &Path) -> Result<(), RuntimeError> {
     if path.exists() {
         let _ = Command::new("chmod")
@@ -490,57 +662,14 @@ pub fn force_remove(path: &Path) -> Result<(), RuntimeError> {
     Ok(())
 }
 
-pub fn detect_package_manager(rootfs: &Path) -> Option<&'static str> {
-    if rootfs.join("usr/bin/apt-get").exists() || rootfs.join("usr/bin/apt").exists() {
-        Some("apt")
-    } else if rootfs.join("usr/bin/dnf").exists() || rootfs.join("usr/bin/dnf5").exists() {
-        Some("dnf")
-    } else if rootfs.join("usr/bin/zypper").exists() {
-        Some("zypper")
-    } else if rootfs.join("usr/bin/pacman").exists() {
-        Some("pacman")
-    } else {
-        None
-    }
-}
-
-pub fn install_packages_command(pkg_manager: &str, packages: &[String]) -> Vec<String> {
-    if packages.is_empty() {
-        return Vec::new();
-    }
-    let mut cmd = Vec::new();
-    match pkg_manager {
-        "apt" => {
-            cmd.push("apt-get".to_owned());
-            cmd.push("install".to_owned());
-            cmd.push("-y".to_owned());
-            cmd.push("--no-install-recommends".to_owned());
-            cmd.extend(packages.iter().cloned());
-        }
-        "dnf" => {
-            cmd.push("dnf".to_owned());
-            cmd.push("install".to_owned());
-            cmd.push("-y".to_owned());
-            cmd.push("--setopt=install_weak_deps=False".to_owned());
-            cmd.extend(packages.iter().cloned());
-        }
-        "zypper" => {
-            cmd.push("zypper".to_owned());
-            cmd.push("--non-interactive".to_owned());
-            cmd.push("install".to_owned());
-            cmd.push("--no-recommends".to_owned());
-            cmd.extend(packages.iter().cloned());
-        }
-        "pacman" => {
-            cmd.push("pacman".to_owned());
-            cmd.push("-S".to_owned());
-            cmd.push("--noconfirm".to_owned());
-            cmd.push("--needed".to_owned());
-            cmd.extend(packages.iter().cloned());
-        }
-        _ => {}
-    }
-    cmd
+/// Whether `rootfs` is a NixOS image. The system profile is a symlink into
+/// the image's `/nix/store`, so only the link itself is checked.
+pub fn is_nixos(rootfs: &Path) -> bool {
+    rootfs.join("etc/NIXOS").exists()
+        || rootfs
+            .join("nix/var/nix/profiles/system")
+            .symlink_metadata()
+            .is_ok()
 }
 
 #[cfg(test)]
@@ -568,80 +697,130 @@ mod tests {
     }
 
     #[test]
-    fn install_commands_correct() {
-        let pkgs = vec!["git".to_owned(), "cmake".to_owned()];
-        let cmd = install_packages_command("apt", &pkgs);
-        assert_eq!(cmd[0], "apt-get");
-        assert!(cmd.contains(&"git".to_owned()));
-
-        let cmd = install_packages_command("zypper", &pkgs);
-        assert_eq!(cmd[0], "zypper");
-        assert!(cmd.contains(&"--non-interactive".to_owned()));
-
-        let cmd = install_packages_command("pacman", &pkgs);
-        assert_eq!(cmd[0], "pacman");
+    fn resolve_imported_image() {
+        let digest = "ab".repeat(32);
+        let name = format!("oci:sha256:{digest}");
+        let r = resolve_image(&name).unwrap();
+        assert_eq!(r.cache_key, format!("oci-{digest}"));
+        assert!(is_pinned_image(&name));
+        assert_eq!(resolve_pinned_image_url(&name).unwrap(), name);
+        assert!(matches!(
+            download_url(&r.source, &Downloader::new()),
+            Err(RuntimeError::ImageNotFound(_))
+        ));
+        assert!(resolve_image("oci:sha256:abc").is_err());
+        assert!(!is_pinned_image("rolling"));
     }
 
-    #[test]
-    fn detect_no_pkg_manager_on_empty_dir() {
-        let dir = tempfile::tempdir().unwrap();
-        assert!(detect_package_manager(dir.path()).is_none());
+    fn fake_image(cache: &ImageCache, cache_key: &str, source: &str) {
+        let rootfs = cache.rootfs_path(cache_key);
+        std::fs::create_dir_all(rootfs.join("etc")).unwrap();
+        std::fs::write(rootfs.join("etc/os-release"), "ID=test").unwrap();
+        let digest = compute_image_digest(&rootfs).unwrap();
+        std::fs::write(cache.image_dir(cache_key).join("rootfs.blake3"), digest).unwrap();
+        std::fs::write(cache.image_dir(cache_key).join("source"), source).unwrap();
     }
 
     #[test]
-    fn parse_apt_version_output() {
-        let output = "git\t1:2.43.0-1ubuntu7\nclang\t1:18.1.3-1\n";
-        let versions = parse_version_output("apt", output);
-        assert_eq!(versions.len(), 2);
+    fn list_and_remove_cached_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(dir.path());
+        assert!(cache.list().unwrap().is_empty());
+
+        fake_image(&cache, "debian-bookworm", "https://example.com/a.tar.xz");
+        fake_image(&cache, "archlinux", "https://example.com/b.tar.xz");
+        // An interrupted download has no rootfs/etc and is not listed.
+        std::fs::create_dir_all(cache.rootfs_path("fedora-41")).unwrap();
+
+        let images = cache.list().unwrap();
+        let keys: Vec<&str> = images.iter().map(|i| i.cache_key.as_str()).collect();
+        assert_eq!(keys, ["archlinux", "debian-bookworm"]);
+        assert_eq!(images[0].source, "https://example.com/b.tar.xz");
         assert_eq!(
-            versions[0],
-            ("git".to_owned(), "1:2.43.0-1ubuntu7".to_owned())
+            Some(images[0].digest.clone()),
+            cache.stored_digest("archlinux")
         );
-        assert_eq!(versions[1], ("clang".to_owned(), "1:18.1.3-1".to_owned()));
-    }
+        assert!(images[0].size_bytes > 0);
 
-    #[test]
-    fn parse_rpm_version_output() {
-        let output = "git\t2.44.0-1.fc41\ncmake\t3.28.3-1.fc41\n";
-        let versions = parse_version_output("zypper", output);
-        assert_eq!(versions.len(), 2);
-        assert_eq!(versions[0].0, "git");
-        assert_eq!(versions[0].1, "2.44.0-1.fc41");
+        cache.remove("archlinux").unwrap();
+        assert!(!cache.is_cached("archlinux"));
+        assert!(matches!(
+            cache.remove("archlinux"),
+            Err(RuntimeError::ImageNotFound(_))
+        ));
+        assert!(matches!(
+            cache.entry("archlinux"),
+            Err(RuntimeError::ImageNotFound(_))
+        ));
     }
 
     #[test]
-    fn parse_pacman_version_output() {
-        let output = "git 2.44.0-1\ncmake 3.28.3-1\n";
-        let versions = parse_version_output("pacman", output);
-        assert_eq!(versions.len(), 2);
-        assert_eq!(versions[0], ("git".to_owned(), "2.44.0-1".to_owned()));
+    fn pull_reuses_cached_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(dir.path());
+        let resolved = resolve_image("debian/bookworm").unwrap();
+        fake_image(&cache, &resolved.cache_key, "https://example.com/a.tar.xz");
+        let image = cache.pull(&resolved, &crate::NoProgress).unwrap();
+        assert_eq!(image.cache_key, resolved.cache_key);
+
+        let imported = resolve_image(&format!("oci:sha256:{}", "cd".repeat(32))).unwrap();
+        assert!(matches!(
+            cache.pull(&imported, &crate::NoProgress),
+            Err(RuntimeError::ImageNotFound(_))
+        ));
     }
 
-    #[test]
-    fn parse_empty_version_output() {
-        let versions = parse_version_output("apt", "");
-        assert!(versions.is_empty());
-        let versions = parse_version_output("apt", "\n\n");
-        assert!(versions.is_empty());
+    fn cache_with_quick_retries(dir: &Path) -> ImageCache {
+        ImageCache {
+            cache_dir: dir.join("images"),
+            downloader: Downloader::with_retries(2, std::time::Duration::from_millis(1)),
+        }
     }
 
     #[test]
-    fn query_versions_commands_generated() {
-        let pkgs = vec!["git".to_owned()];
-        let cmd = query_versions_command("apt", &pkgs);
-        assert_eq!(cmd[0], "dpkg-query");
-
-        let cmd = query_versions_command("zypper", &pkgs);
-        assert_eq!(cmd[0], "rpm");
+    fn downloads_are_checked_against_sha256sums() {
+        use crate::download::tests::FileServer;
+        use sha2::{Digest, Sha256};
+
+        let good = format!("{:x}  rootfs.tar.xz\n", Sha256::digest(b"rootfs"));
+        let bad = format!("{}  rootfs.tar.xz\n", "0".repeat(64));
+        let server = FileServer::start(
+            &[
+                ("/good/rootfs.tar.xz", b"rootfs"),
+                ("/good/SHA256SUMS", good.as_bytes()),
+                ("/bad/rootfs.tar.xz", b"rootfs"),
+                ("/bad/SHA256SUMS", bad.as_bytes()),
+                ("/unlisted/rootfs.tar.xz", b"rootfs"),
+            ],
+            &[],
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_with_quick_retries(dir.path());
+        let dest = dir.path().join("rootfs.tar.xz");
+        let fetch = |path: &str, require: bool| {
+            cache.fetch_verified(
+                &format!("{}{path}", server.url),
+                require,
+                &dest,
+                &crate::NoProgress,
+            )
+        };
 
-        let cmd = query_versions_command("dnf", &pkgs);
-        assert_eq!(cmd[0], "rpm");
+        fetch("/good/rootfs.tar.xz", true).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"rootfs");
 
-        let cmd = query_versions_command("pacman", &pkgs);
-        assert_eq!(cmd[0], "pacman");
+        assert!(matches!(
+            fetch("/bad/rootfs.tar.xz", true),
+            Err(RuntimeError::ChecksumMismatch { .. })
+        ));
+        assert!(!dest.exists());
 
-        let cmd = query_versions_command("unknown", &pkgs);
-        assert!(cmd.is_empty());
+        // Without an index, only custom URLs are accepted.
+        assert!(matches!(
+            fetch("/unlisted/rootfs.tar.xz", true),
+            Err(RuntimeError::Download(_))
+        ));
+        fetch("/unlisted/rootfs.tar.xz", false).unwrap();
     }
 
     #[test]
@@ -661,30 +840,6 @@ mod tests {
         assert_eq!(digest, digest2);
     }
 
-    #[test]
-    fn detect_apt_package_manager() {
-        let dir = tempfile::tempdir().unwrap();
-        std::fs::create_dir_all(dir.path().join("usr/bin")).unwrap();
-        std::fs::write(dir.path().join("usr/bin/apt-get"), "").unwrap();
-        assert_eq!(detect_package_manager(dir.path()), Some("apt"));
-    }
-
-    #[test]
-    fn detect_zypper_package_manager() {
-        let dir = tempfile::tempdir().unwrap();
-        std::fs::create_dir_all(dir.path().join("usr/bin")).unwrap();
-        std::fs::write(dir.path().join("usr/bin/zypper"), "").unwrap();
-        assert_eq!(detect_package_manager(dir.path()), Some("zypper"));
-    }
-
-    #[test]
-    fn detect_pacman_package_manager() {
-        let dir = tempfile::tempdir().unwrap();
-        std::fs::create_dir_all(dir.path().join("usr/bin")).unwrap();
-        std::fs::write(dir.path().join("usr/bin/pacman"), "").unwrap();
-        assert_eq!(detect_package_manager(dir.path()), Some("pacman"));
-    }
-
     #[test]
     fn resolve_all_image_aliases() {
         // Verify every documented alias resolves correctly
@@ -714,15 +869,16 @@ mod tests {
             "fedora/42",
             "arch",
             "archlinux",
+            "alpine",
+            "alpine/3.20",
+            "alpine/3.21",
+            "alpine/edge",
+            "nixos",
+            "nixos/24.11",
+            "nixos/unstable",
         ] {
             let result = resolve_image(alias);
             assert!(result.is_ok(), "failed to resolve alias: {alias}");
         }
     }
-
-    #[test]
-    fn install_empty_packages_returns_empty() {
-        let cmd = install_packages_command("apt", &[]);
-        assert!(cmd.is_empty());
-    }
 }
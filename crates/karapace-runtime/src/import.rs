@@ -0,0 +1,745 @@
+//! Importing OCI and Docker images as base images.
+//!
+//! `karapace import` turns an existing container image into a base image
+//! in the image cache, so teams can move environments they already build
+//! with Docker into karapace. The image may be an OCI image layout
+//! directory, a `docker save` or OCI archive, or a registry reference,
+//! which is copied to an OCI layout with `skopeo` (or `podman`) first.
+//!
+//! The image's layers are applied in order, whiteouts included, to a fresh
+//! rootfs stored under `images/oci-<digest>/`, where `<digest>` is the
+//! sha256 of the image config, the same image ID Docker shows. Manifests
+//! refer to it as `oci:sha256:<digest>`, which pins it.
+
+use crate::image::{compute_image_digest, force_remove, ImageCache, IMPORTED_IMAGE_PREFIX};
+use crate::{BuildPhase, ProgressSink, RuntimeError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+/// The parts of an image's config a manifest cannot express, kept next to
+/// the imported rootfs for reference.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmd: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportedImage {
+    /// `base.image` naming the imported image, `oci:sha256:<digest>`.
+    pub reference: String,
+    pub rootfs: PathBuf,
+    pub layers: usize,
+    pub config: ImageConfig,
+    /// Whether the image was already in the cache.
+    pub cached: bool,
+}
+
+/// An image in an unpacked layout: its config and layer blobs, in order.
+#[derive(Debug, PartialEq, Eq)]
+struct ImageFiles {
+    config: PathBuf,
+    /// sha256 of the config, when the layout names it.
+    config_digest: Option<String>,
+    layers: Vec<Blob>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Blob {
+    path: PathBuf,
+    /// sha256 the blob must match, when the layout names it.
+    digest: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    digest: String,
+    #[serde(default)]
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize)]
+struct Platform {
+    architecture: String,
+    os: String,
+}
+
+/// An OCI index or Docker manifest list, or an image manifest.
+#[derive(Deserialize)]
+struct ManifestBlob {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+/// An entry of a `docker save` archive's `manifest.json`.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerManifest {
+    config: String,
+    layers: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ConfigBlob {
+    #[serde(default)]
+    config: ImageConfig,
+}
+
+/// Import the image at `source` into `cache`.
+pub fn import_image(
+    source: &str,
+    cache: &ImageCache,
+    progress: &dyn ProgressSink,
+) -> Result<ImportedImage, RuntimeError> {
+    // Unpacked next to the cache so the finished rootfs can be moved in.
+    std::fs::create_dir_all(cache.dir())?;
+    let work = tempfile::tempdir_in(cache.dir())?;
+
+    progress.phase(BuildPhase::FetchImage);
+    let path = Path::new(source);
+    let layout = if path.is_dir() {
+        path.to_path_buf()
+    } else if path.is_file() {
+        progress.message(&format!("unpacking {source}..."));
+        let dir = work.path().join("archive");
+        std::fs::create_dir(&dir)?;
+        run_tar(Command::new("tar").arg("-xf").arg(path).arg("-C").arg(&dir))?;
+        dir
+    } else {
+        let dir = work.path().join("oci");
+        fetch_reference(source, &dir, progress)?;
+        dir
+    };
+
+    let files = read_layout(&layout)?;
+    let config_digest = match &files.config_digest {
+        Some(digest) => {
+            verify_blob(&files.config, digest)?;
+            digest.clone()
+        }
+        None => sha256_file(&files.config)?,
+    };
+    let config: ConfigBlob = serde_json::from_slice(&std::fs::read(&files.config)?)
+        .map_err(|e| RuntimeError::ImageImport(format!("invalid image config: {e}")))?;
+
+    let reference = format!("{IMPORTED_IMAGE_PREFIX}{config_digest}");
+    let cache_key = format!("oci-{config_digest}");
+    let imported = |cached| ImportedImage {
+        reference: reference.clone(),
+        rootfs: cache.rootfs_path(&cache_key),
+        layers: files.layers.len(),
+        config: config.config.clone(),
+        cached,
+    };
+    if cache.is_cached(&cache_key) {
+        progress.message(&format!(
+            "image sha256:{} already imported",
+            &config_digest[..12]
+        ));
+        return Ok(imported(true));
+    }
+
+    progress.phase(BuildPhase::Unpack);
+    let rootfs = work.path().join("rootfs");
+    std::fs::create_dir(&rootfs)?;
+    for (i, layer) in files.layers.iter().enumerate() {
+        progress.message(&format!(
+            "applying layer {}/{}...",
+            i + 1,
+            files.layers.len()
+        ));
+        if let Some(digest) = &layer.digest {
+            verify_blob(&layer.path, digest)?;
+        }
+        apply_layer(&rootfs, &layer.path)?;
+    }
+    // As for downloaded images: layers are full of root-owned, restrictive
+    // permissions the unprivileged store must still be able to manage.
+    let _ = Command::new("chmod")
+        .args(["-R", "u+rwX"])
+        .arg(&rootfs)
+        .status();
+
+    progress.message("computing image digest...");
+    let digest = compute_image_digest(&rootfs)?;
+    let image_dir = cache.image_dir(&cache_key);
+    let _ = force_remove(&image_dir);
+    std::fs::create_dir_all(&image_dir)?;
+    std::fs::rename(&rootfs, image_dir.join("rootfs"))?;
+    std::fs::write(image_dir.join("rootfs.blake3"), digest)?;
+    std::fs::write(
+        image_dir.join("config.json"),
+        serde_json::to_vec_pretty(&config.config).map_err(std::io::Error::other)?,
+    )?;
+    std::fs::write(image_dir.join("source"), source)?;
+
+    progress.message(&format!("imported {source} as {reference}"));
+    Ok(imported(false))
+}
+
+/// Copy the registry image `reference` to an OCI layout at `dest`.
+fn fetch_reference(
+    reference: &str,
+    dest: &Path,
+    progress: &dyn ProgressSink,
+) -> Result<(), RuntimeError> {
+    let image = reference.strip_prefix("docker://").unwrap_or(reference);
+    let dest_str = dest.to_string_lossy();
+    let output = if crate::prereq::command_exists("skopeo") {
+        progress.message(&format!("copying {image} with skopeo..."));
+        Command::new("skopeo")
+            .args(["copy", &format!("docker://{image}")])
+            .arg(format!("oci:{dest_str}:image"))
+            .output()?
+    } else if crate::prereq::command_exists("podman") {
+        progress.message(&format!("pulling {image} with podman..."));
+        let pull = Command::new("podman").args(["pull", image]).output()?;
+        if !pull.status.success() {
+            return Err(RuntimeError::ImageImport(format!(
+                "podman pull {image} failed: {}",
+                String::from_utf8_lossy(&pull.stderr).trim()
+            )));
+        }
+        Command::new("podman")
+            .args(["save", "--format", "oci-dir", "-o", &dest_str, image])
+            .output()?
+    } else {
+        return Err(RuntimeError::ImageImport(format!(
+            "'{reference}' is not a file or directory, and importing from a registry \
+             needs skopeo or podman"
+        )));
+    };
+    if !output.status.success() {
+        return Err(RuntimeError::ImageImport(format!(
+            "failed to fetch {image}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// The Go architecture name OCI platforms use for this host.
+fn host_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64le",
+        other => other,
+    }
+}
+
+fn blob_path(layout: &Path, digest: &str) -> Result<(PathBuf, String), RuntimeError> {
+    let hex = digest
+        .strip_prefix("sha256:")
+        .filter(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or_else(|| RuntimeError::ImageImport(format!("unsupported digest '{digest}'")))?;
+    Ok((layout.join("blobs/sha256").join(hex), hex.to_owned()))
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, RuntimeError> {
+    let data = std::fs::read(path)
+        .map_err(|e| RuntimeError::ImageImport(format!("cannot read {}: {e}", path.display())))?;
+    serde_json::from_slice(&data)
+        .map_err(|e| RuntimeError::ImageImport(format!("invalid {}: {e}", path.display())))
+}
+
+/// Find the image in an OCI layout (`index.json`) or an unpacked
+/// `docker save` archive (`manifest.json`). Recent Docker versions write
+/// both; the OCI index is preferred.
+fn read_layout(layout: &Path) -> Result<ImageFiles, RuntimeError> {
+    if layout.join("index.json").is_file() {
+        let mut manifest: ManifestBlob = read_json(&layout.join("index.json"))?;
+        // Indexes may nest, one per platform.
+        for _ in 0..4 {
+            if manifest.manifests.is_empty() {
+                return oci_image(layout, manifest);
+            }
+            let descriptor = select_platform(manifest.manifests)?;
+            let (path, hex) = blob_path(layout, &descriptor.digest)?;
+            verify_blob(&path, &hex)?;
+            manifest = read_json(&path)?;
+        }
+        return Err(RuntimeError::ImageImport(
+            "image indexes are nested too deeply".to_owned(),
+        ));
+    }
+    if layout.join("manifest.json").is_file() {
+        let manifests: Vec<DockerManifest> = read_json(&layout.join("manifest.json"))?;
+        let manifest = manifests.into_iter().next().ok_or_else(|| {
+            RuntimeError::ImageImport("the archive's manifest.json lists no images".to_owned())
+        })?;
+        return Ok(ImageFiles {
+            config: archive_path(layout, &manifest.config)?,
+            config_digest: None,
+            layers: manifest
+                .layers
+                .iter()
+                .map(|layer| {
+                    Ok(Blob {
+                        path: archive_path(layout, layer)?,
+                        digest: None,
+                    })
+                })
+                .collect::<Result<_, RuntimeError>>()?,
+        });
+    }
+    Err(RuntimeError::ImageImport(format!(
+        "{} is neither an OCI image layout nor a docker archive",
+        layout.display()
+    )))
+}
+
+fn oci_image(layout: &Path, manifest: ManifestBlob) -> Result<ImageFiles, RuntimeError> {
+    let config = manifest
+        .config
+        .ok_or_else(|| RuntimeError::ImageImport("image manifest has no config".to_owned()))?;
+    let (config, config_digest) = blob_path(layout, &config.digest)?;
+    let layers = manifest
+        .layers
+        .iter()
+        .map(|layer| {
+            let (path, hex) = blob_path(layout, &layer.digest)?;
+            Ok(Blob {
+                path,
+                digest: Some(hex),
+            })
+        })
+        .collect::<Result<_, RuntimeError>>()?;
+    Ok(ImageFiles {
+        config,
+        config_digest: Some(config_digest),
+        layers,
+    })
+}
+
+/// The index entry for this host's platform, or the only image in the
+/// index. Attestations are listed with an `unknown` platform.
+fn select_platform(manifests: Vec<Descriptor>) -> Result<Descriptor, RuntimeError> {
+    let mut images: Vec<Descriptor> = manifests
+        .into_iter()
+        .filter(|m| m.platform.as_ref().is_none_or(|p| p.os != "unknown"))
+        .collect();
+    if let Some(i) = images.iter().position(|m| {
+        m.platform
+            .as_ref()
+            .is_some_and(|p| p.os == "linux" && p.architecture == host_architecture())
+    }) {
+        return Ok(images.swap_remove(i));
+    }
+    match images.len() {
+        1 => Ok(images.swap_remove(0)),
+        0 => Err(RuntimeError::ImageImport(
+            "the image index is empty".to_owned(),
+        )),
+        _ => Err(RuntimeError::ImageImport(format!(
+            "the image has no variant for linux/{}",
+            host_architecture()
+        ))),
+    }
+}
+
+/// A path named by a docker archive, which must stay inside it.
+fn archive_path(layout: &Path, name: &str) -> Result<PathBuf, RuntimeError> {
+    layer_path(name)
+        .map(|path| layout.join(path))
+        .ok_or_else(|| RuntimeError::ImageImport(format!("invalid path '{name}' in archive")))
+}
+
+/// A tar member name as a relative path, or `None` for the root itself and
+/// for names leaving it.
+fn layer_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+fn sha256_file(path: &Path) -> Result<String, RuntimeError> {
+    let output = Command::new("sha256sum").arg(path).output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.split_whitespace().next() {
+        Some(hex) if output.status.success() => Ok(hex.to_owned()),
+        _ => Err(RuntimeError::ImageImport(format!(
+            "sha256sum {} failed: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+fn verify_blob(path: &Path, hex: &str) -> Result<(), RuntimeError> {
+    let actual = sha256_file(path)?;
+    if actual.eq_ignore_ascii_case(hex) {
+        Ok(())
+    } else {
+        Err(RuntimeError::ImageImport(format!(
+            "blob {} has digest sha256:{actual}, expected sha256:{hex}",
+            path.display()
+        )))
+    }
+}
+
+fn run_tar(cmd: &mut Command) -> Result<String, RuntimeError> {
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(RuntimeError::ImageImport(format!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Apply the layer tarball `layer` to `rootfs`: remove what its whiteouts
+/// hide in the layers below, then extract the rest.
+fn apply_layer(rootfs: &Path, layer: &Path) -> Result<(), RuntimeError> {
+    let listing = run_tar(Command::new("tar").arg("-tf").arg(layer))?;
+    let entries: Vec<PathBuf> = listing.lines().filter_map(layer_path).collect();
+    check_symlinks(rootfs, &entries)?;
+
+    for entry in &entries {
+        let (Some(name), Some(parent)) = (entry.file_name(), entry.parent()) else {
+            continue;
+        };
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name == OPAQUE_WHITEOUT {
+            if let Ok(children) = std::fs::read_dir(rootfs.join(parent)) {
+                for child in children.flatten() {
+                    remove_path(&child.path())?;
+                }
+            }
+        } else if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            remove_path(&rootfs.join(parent).join(hidden))?;
+        }
+    }
+
+    run_tar(
+        Command::new("tar")
+            .arg("-xf")
+            .arg(layer)
+            .arg("-C")
+            .arg(rootfs)
+            .args([
+                "--no-same-owner",
+                "--no-same-permissions",
+                "--exclude=dev/*",
+                &format!("--exclude={WHITEOUT_PREFIX}*"),
+            ]),
+    )?;
+    Ok(())
+}
+
+/// Remove whatever is at `path`, without following it if it is a symlink.
+fn remove_path(path: &Path) -> Result<(), RuntimeError> {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => force_remove(path),
+        Ok(_) => Ok(std::fs::remove_file(path)?),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Refuse a layer that would write or remove through a symlink left by the
+/// layers below, which could point anywhere on the host. Layers that
+/// replace the symlink with a directory of their own are fine.
+fn check_symlinks(rootfs: &Path, entries: &[PathBuf]) -> Result<(), RuntimeError> {
+    let own: HashSet<&Path> = entries.iter().map(PathBuf::as_path).collect();
+    let mut checked: HashSet<&Path> = HashSet::new();
+    for entry in entries {
+        let mut ancestors: Vec<&Path> = entry
+            .ancestors()
+            .skip(1)
+            .filter(|a| !a.as_os_str().is_empty())
+            .collect();
+        ancestors.reverse();
+        for ancestor in ancestors {
+            if own.contains(ancestor) || !checked.insert(ancestor) {
+                continue;
+            }
+            let is_link = rootfs
+                .join(ancestor)
+                .symlink_metadata()
+                .is_ok_and(|meta| meta.file_type().is_symlink());
+            if is_link {
+                return Err(RuntimeError::ImageImport(format!(
+                    "layer writes {} through the symlink /{}",
+                    entry.display(),
+                    ancestor.display()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoProgress;
+
+    /// Tar up `files` (path, contents) as a layer; a `None` content makes a
+    /// directory.
+    fn layer(dir: &Path, name: &str, files: &[(&str, Option<&str>)]) -> PathBuf {
+        let src = dir.join(format!("{name}.src"));
+        let mut members = Vec::new();
+        for (path, contents) in files {
+            let full = src.join(path);
+            match contents {
+                Some(contents) => {
+                    std::fs::create_dir_all(full.parent().unwrap()).unwrap();
+                    std::fs::write(&full, contents).unwrap();
+                }
+                None => std::fs::create_dir_all(&full).unwrap(),
+            }
+            members.push(*path);
+        }
+        let tarball = dir.join(format!("{name}.tar"));
+        let status = Command::new("tar")
+            .arg("-cf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(&src)
+            .arg("--no-recursion")
+            .args(&members)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        tarball
+    }
+
+    /// Store `data` as a blob of the OCI layout at `layout`.
+    fn add_blob(layout: &Path, data: &[u8]) -> String {
+        let tmp = layout.join("blob.tmp");
+        std::fs::write(&tmp, data).unwrap();
+        let hex = sha256_file(&tmp).unwrap();
+        let blobs = layout.join("blobs/sha256");
+        std::fs::create_dir_all(&blobs).unwrap();
+        std::fs::rename(&tmp, blobs.join(&hex)).unwrap();
+        format!("sha256:{hex}")
+    }
+
+    /// An OCI layout at `layout` with `layers` and a config setting `Cmd`.
+    fn oci_layout(layout: &Path, layers: &[PathBuf]) -> String {
+        std::fs::create_dir_all(layout).unwrap();
+        std::fs::write(
+            layout.join("oci-layout"),
+            r#"{"imageLayoutVersion":"1.0.0"}"#,
+        )
+        .unwrap();
+        let config = add_blob(
+            layout,
+            br#"{"architecture":"amd64","os":"linux","config":{"Env":["PATH=/usr/bin"],"Cmd":["/bin/sh"]}}"#,
+        );
+        let layers: Vec<serde_json::Value> = layers
+            .iter()
+            .map(|l| {
+                serde_json::json!({
+                    "mediaType": "application/vnd.oci.image.layer.v1.tar",
+                    "digest": add_blob(layout, &std::fs::read(l).unwrap()),
+                })
+            })
+            .collect();
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "config": {"mediaType": "application/vnd.oci.image.config.v1+json", "digest": config},
+            "layers": layers,
+        });
+        let manifest = add_blob(layout, manifest.to_string().as_bytes());
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "manifests": [
+                {"digest": manifest, "platform": {"architecture": host_architecture(), "os": "linux"}},
+                {"digest": "sha256:".to_owned() + &"0".repeat(64), "platform": {"architecture": "unknown", "os": "unknown"}},
+            ],
+        });
+        std::fs::write(layout.join("index.json"), index.to_string()).unwrap();
+        config.trim_start_matches("sha256:").to_owned()
+    }
+
+    #[test]
+    fn layer_paths_stay_inside_the_rootfs() {
+        assert_eq!(
+            layer_path("./etc/passwd"),
+            Some(PathBuf::from("etc/passwd"))
+        );
+        assert_eq!(layer_path("/usr/bin/"), Some(PathBuf::from("usr/bin")));
+        assert_eq!(layer_path("./"), None);
+        assert_eq!(layer_path("etc/../../x"), None);
+    }
+
+    #[test]
+    fn import_applies_layers_and_whiteouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = layer(
+            dir.path(),
+            "base",
+            &[
+                ("etc", None),
+                ("etc/os-release", Some("ID=test\n")),
+                ("etc/old.conf", Some("old")),
+                ("opt", None),
+                ("opt/app", None),
+                ("opt/app/stale", Some("stale")),
+            ],
+        );
+        let update = layer(
+            dir.path(),
+            "update",
+            &[
+                ("etc/.wh.old.conf", Some("")),
+                ("opt/app/.wh..wh..opq", Some("")),
+                ("opt/app/fresh", Some("fresh")),
+            ],
+        );
+        let layout = dir.path().join("layout");
+        let config_digest = oci_layout(&layout, &[base, update]);
+
+        let store = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(store.path());
+        let image = import_image(&layout.to_string_lossy(), &cache, &NoProgress).unwrap();
+        assert_eq!(image.reference, format!("oci:sha256:{config_digest}"));
+        assert_eq!(image.layers, 2);
+        assert!(!image.cached);
+        assert_eq!(image.config.cmd, Some(vec!["/bin/sh".to_owned()]));
+
+        let rootfs = &image.rootfs;
+        assert!(rootfs.join("etc/os-release").is_file());
+        assert!(!rootfs.join("etc/old.conf").exists());
+        assert!(!rootfs.join("opt/app/stale").exists());
+        assert!(rootfs.join("opt/app/fresh").is_file());
+        assert!(!rootfs.join("etc/.wh.old.conf").exists());
+
+        let resolved = crate::image::resolve_image(&image.reference).unwrap();
+        assert!(cache.is_cached(&resolved.cache_key));
+        cache.verify_image(&resolved.cache_key).unwrap();
+
+        let again = import_image(&layout.to_string_lossy(), &cache, &NoProgress).unwrap();
+        assert!(again.cached);
+    }
+
+    #[test]
+    fn import_reads_docker_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = layer(
+            dir.path(),
+            "base",
+            &[("etc", None), ("etc/hostname", Some("x"))],
+        );
+        let archive = dir.path().join("archive");
+        std::fs::create_dir_all(archive.join("abc")).unwrap();
+        std::fs::copy(&base, archive.join("abc/layer.tar")).unwrap();
+        std::fs::write(archive.join("cfg.json"), r#"{"config":{"User":"app"}}"#).unwrap();
+        std::fs::write(
+            archive.join("manifest.json"),
+            r#"[{"Config":"cfg.json","RepoTags":["app:latest"],"Layers":["abc/layer.tar"]}]"#,
+        )
+        .unwrap();
+        let tarball = dir.path().join("app.tar");
+        assert!(Command::new("tar")
+            .arg("-cf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(&archive)
+            .arg(".")
+            .status()
+            .unwrap()
+            .success());
+
+        let store = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(store.path());
+        let image = import_image(&tarball.to_string_lossy(), &cache, &NoProgress).unwrap();
+        let config_digest = sha256_file(&archive.join("cfg.json")).unwrap();
+        assert_eq!(image.reference, format!("oci:sha256:{config_digest}"));
+        assert_eq!(image.config.user.as_deref(), Some("app"));
+        assert!(image.rootfs.join("etc/hostname").is_file());
+    }
+
+    #[test]
+    fn import_rejects_tampered_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = layer(dir.path(), "base", &[("etc", None)]);
+        let layout = dir.path().join("layout");
+        oci_layout(&layout, &[base]);
+        let index: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(layout.join("index.json")).unwrap()).unwrap();
+        let manifest = index["manifests"][0]["digest"].as_str().unwrap();
+        let (path, _) = blob_path(&layout, manifest).unwrap();
+        let mut data = std::fs::read(&path).unwrap();
+        data.push(b' ');
+        std::fs::write(&path, data).unwrap();
+
+        let store = tempfile::tempdir().unwrap();
+        let err = import_image(
+            &layout.to_string_lossy(),
+            &ImageCache::new(store.path()),
+            &NoProgress,
+        )
+        .unwrap_err();
+        assert!(matches!(err, RuntimeError::ImageImport(_)), "{err}");
+    }
+
+    #[test]
+    fn layers_cannot_write_through_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let rootfs = dir.path().join("rootfs");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir_all(&rootfs).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, rootfs.join("link")).unwrap();
+
+        let entries = [PathBuf::from("link/file")];
+        assert!(check_symlinks(&rootfs, &entries).is_err());
+        let whiteout = [PathBuf::from("link/.wh.file")];
+        assert!(check_symlinks(&rootfs, &whiteout).is_err());
+        // A layer that replaces the link with a directory may fill it.
+        let replaced = [PathBuf::from("link"), PathBuf::from("link/file")];
+        assert!(check_symlinks(&rootfs, &replaced).is_ok());
+    }
+
+    #[test]
+    fn select_platform_skips_attestations() {
+        let descriptor = |arch: &str, os: &str| Descriptor {
+            digest: format!("{arch}/{os}"),
+            platform: Some(Platform {
+                architecture: arch.to_owned(),
+                os: os.to_owned(),
+            }),
+        };
+        let chosen = select_platform(vec![
+            descriptor("unknown", "unknown"),
+            descriptor("s390x-none", "linux"),
+        ])
+        .unwrap();
+        assert_eq!(chosen.digest, "s390x-none/linux");
+
+        let chosen = select_platform(vec![
+            descriptor("s390x-none", "linux"),
+            descriptor(host_architecture(), "linux"),
+        ])
+        .unwrap();
+        assert_eq!(chosen.digest, format!("{}/linux", host_architecture()));
+    }
+}
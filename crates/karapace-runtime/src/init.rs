@@ -0,0 +1,198 @@
+//! Minimal init for entered environments.
+//!
+//! Without it the session shell is PID 1 of the environment's PID namespace,
+//! so orphaned processes are reparented to a shell that never waits for them
+//! and long sessions pile up zombies. The init spawns the session command,
+//! forwards signals to it, reaps every child, and exits with the command's
+//! status once the command itself exits.
+//!
+//! The init is the host's own executable re-run with [`INIT_ARG`]: binaries
+//! that enter environments call [`run_if_requested`] at the top of `main`.
+
+use crate::backend::RuntimeSpec;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// First argument that turns the executable into the init.
+pub const INIT_ARG: &str = "__karapace-init";
+
+/// Where the OCI backend bind-mounts the init inside the container.
+pub const CONTAINER_INIT_PATH: &str = "/.karapace-init";
+
+/// Marker in the environment directory while a session runs under the init.
+pub(crate) const INIT_MARKER: &str = ".init";
+
+/// Signals passed on to the session command instead of acted on.
+const FORWARDED: &[libc::c_int] = &[
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGWINCH,
+];
+
+/// Executable to run as the init: the current one.
+pub fn init_binary() -> Option<PathBuf> {
+    std::env::current_exe().ok()
+}
+
+/// Init for a session of `spec`, unless its manifest turns it off.
+pub fn session_init(spec: &RuntimeSpec) -> Option<PathBuf> {
+    if spec.manifest.runtime_init {
+        init_binary()
+    } else {
+        None
+    }
+}
+
+/// Create or clear the [`INIT_MARKER`] for a starting session.
+pub(crate) fn write_init_marker(env_dir: &Path, init: bool) -> std::io::Result<()> {
+    let marker = env_dir.join(INIT_MARKER);
+    if init {
+        std::fs::write(marker, "")
+    } else if marker.exists() {
+        std::fs::remove_file(marker)
+    } else {
+        Ok(())
+    }
+}
+
+/// Arguments that make the init executable run `command` under the init.
+pub fn init_args<S: AsRef<str>>(command: &[S]) -> Vec<String> {
+    let mut args = vec![INIT_ARG.to_owned(), "--".to_owned()];
+    args.extend(command.iter().map(|a| a.as_ref().to_owned()));
+    args
+}
+
+/// If this process was started as the init, run it and exit. Otherwise
+/// return immediately.
+pub fn run_if_requested() {
+    let mut args = std::env::args_os().skip(1);
+    if args.next().as_deref() != Some(OsStr::new(INIT_ARG)) {
+        return;
+    }
+    let mut command: Vec<OsString> = args.collect();
+    if command.first().is_some_and(|a| a == "--") {
+        command.remove(0);
+    }
+    std::process::exit(run(&command));
+}
+
+/// Run `command` under the init and return the exit code to leave with:
+/// the command's own, or 128 plus the signal that killed it.
+#[allow(unsafe_code)]
+pub fn run(command: &[OsString]) -> i32 {
+    let Some((program, args)) = command.split_first() else {
+        eprintln!("karapace-init: no command given");
+        return 127;
+    };
+
+    // Block the signals handled here before spawning so none arrive between
+    // the spawn and the wait loop. The standard library clears the signal
+    // mask in the child.
+    let set = handled_signals();
+    // SAFETY: `set` is an initialized sigset_t and the old mask is not requested.
+    unsafe { libc::sigprocmask(libc::SIG_BLOCK, &raw const set, std::ptr::null_mut()) };
+    // Outside a fresh PID namespace the init is not PID 1; becoming a
+    // subreaper still makes orphans its children. Failure only means the
+    // kernel reparents them elsewhere.
+    // SAFETY: prctl(PR_SET_CHILD_SUBREAPER) takes plain integer arguments.
+    unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+
+    let child = match Command::new(program).args(args).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("karapace-init: {}: {e}", program.to_string_lossy());
+            return 127;
+        }
+    };
+    let Ok(pid) = libc::pid_t::try_from(child.id()) else {
+        return 1;
+    };
+
+    loop {
+        // SAFETY: `set` is initialized; a null siginfo pointer is allowed.
+        let sig = unsafe { libc::sigwaitinfo(&raw const set, std::ptr::null_mut()) };
+        if sig == libc::SIGCHLD {
+            if let Some(code) = reap(pid) {
+                return code;
+            }
+        } else if sig > 0 {
+            // SAFETY: kill() with a child pid and a valid signal number.
+            unsafe { libc::kill(pid, sig) };
+        }
+    }
+}
+
+#[allow(unsafe_code)]
+fn handled_signals() -> libc::sigset_t {
+    // SAFETY: sigemptyset initializes the zeroed set before any other use.
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&raw mut set);
+        libc::sigaddset(&raw mut set, libc::SIGCHLD);
+        for &sig in FORWARDED {
+            libc::sigaddset(&raw mut set, sig);
+        }
+        set
+    }
+}
+
+/// Reap every exited child. Returns the exit code of `main` if it was
+/// among them.
+#[allow(unsafe_code)]
+fn reap(main: libc::pid_t) -> Option<i32> {
+    let mut main_code = None;
+    loop {
+        let mut status = 0;
+        // SAFETY: waitpid() writes only to `status`.
+        let pid = unsafe { libc::waitpid(-1, &raw mut status, libc::WNOHANG) };
+        if pid <= 0 {
+            return main_code;
+        }
+        if pid == main {
+            main_code = Some(exit_code(status));
+        }
+    }
+}
+
+fn exit_code(status: libc::c_int) -> i32 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn init_args_wrap_command() {
+        assert_eq!(
+            init_args(&["/bin/sh", "-c", "true"]),
+            vec![INIT_ARG, "--", "/bin/sh", "-c", "true"]
+        );
+    }
+
+    #[test]
+    fn exit_codes_follow_shell_convention() {
+        let run = |script: &str| {
+            let status = Command::new("/bin/sh")
+                .args(["-c", script])
+                .status()
+                .unwrap();
+            exit_code(status.into_raw())
+        };
+        assert_eq!(run("exit 0"), 0);
+        assert_eq!(run("exit 7"), 7);
+        assert_eq!(run("kill -TERM $$"), 128 + libc::SIGTERM);
+    }
+}
@@ -1,24 +1,45 @@
 //! Runtime backends and sandbox infrastructure for Karapace environments.
 //!
 //! This crate implements the execution layer: pluggable `RuntimeBackend` trait with
-//! namespace (user-namespace + fuse-overlayfs) and OCI (runc) backends, sandbox
+//! namespace (user-namespace + fuse-overlayfs), OCI (runc) and podman/crun backends, sandbox
 //! setup script generation, host integration (GPU, audio, X11/Wayland passthrough),
-//! base image resolution, prerequisite checking, and security policy enforcement.
+//! base image resolution and download, language toolchain installation, build progress reporting, port forwarding, DNS and hosts overrides, pseudo-terminals for `exec`, process listing and resource statistics, prerequisite checking, security policy enforcement, cgroup v2 resource limits, detached sessions, command shims for shell integration, upper
+//! layer size limits, and a resource watchdog and minimal init for entered environments.
 
 pub mod backend;
+pub mod cgroup;
+pub mod download;
 pub mod export;
 pub mod host;
 pub mod image;
+pub mod import;
+pub mod init;
 pub mod mock;
 pub mod namespace;
+pub mod netconf;
 pub mod oci;
+pub mod podman;
+pub mod portfwd;
 pub mod prereq;
+pub mod process;
+pub mod progress;
+pub mod pty;
+pub mod quota;
+pub mod rootless;
 pub mod sandbox;
 pub mod security;
+pub mod session;
+pub mod shellenv;
 pub mod terminal;
+pub mod toolchain;
+pub mod watchdog;
 
-pub use backend::{select_backend, RuntimeBackend, RuntimeSpec, RuntimeStatus};
-pub use prereq::{check_namespace_prereqs, check_oci_prereqs, format_missing, MissingPrereq};
+pub use backend::{select_backend, EnvStats, RuntimeBackend, RuntimeSpec, RuntimeStatus};
+pub use prereq::{
+    check_cgroup_prereqs, check_namespace_prereqs, check_oci_prereqs, check_podman_prereqs,
+    format_missing, MissingPrereq,
+};
+pub use progress::{BuildPhase, NoProgress, ProgressSink, StderrProgress};
 pub use security::SecurityPolicy;
 
 use thiserror::Error;
@@ -43,4 +64,18 @@ pub enum RuntimeError {
     ExecFailed(String),
     #[error("image not found: {0}")]
     ImageNotFound(String),
+    #[error("image import failed: {0}")]
+    ImageImport(String),
+    #[error("download failed: {0}")]
+    Download(String),
+    #[error("checksum mismatch for {file}: SHA256SUMS lists {expected}, downloaded {actual}")]
+    ChecksumMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("manifest error: {0}")]
+    Manifest(#[from] karapace_schema::ManifestError),
+    #[error("environment uses {used_mb} MB, over its max_overlay_mb limit of {limit_mb} MB")]
+    QuotaExceeded { used_mb: u64, limit_mb: u64 },
 }
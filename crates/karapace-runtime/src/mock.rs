@@ -1,9 +1,26 @@
 use crate::backend::{RuntimeBackend, RuntimeSpec, RuntimeStatus};
-use crate::RuntimeError;
+use crate::process::ProcessInfo;
+use crate::{BuildPhase, ProgressSink, RuntimeError};
 use karapace_schema::{ResolutionResult, ResolvedPackage};
 use std::collections::HashMap;
 use std::sync::Mutex;
 
+/// Package index the mock resolver expands patterns against. Literal package
+/// names resolve whether or not they appear here.
+pub const MOCK_PACKAGE_INDEX: &[&str] = &[
+    "clang",
+    "cmake",
+    "git",
+    "python3",
+    "python3-all-dev",
+    "python3-dev",
+    "python3-venv-dev",
+];
+
+/// File in the upper directory that [`MockBackend`] appends each executed
+/// command to.
+pub const MOCK_EXEC_LOG: &str = ".karapace-mock-exec";
+
 pub struct MockBackend {
     state: Mutex<HashMap<String, bool>>,
 }
@@ -20,6 +37,36 @@ impl MockBackend {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Pretend to run `command`: its output is `mock-exec: <command>`, it
+    /// exits with 1 for `false` and with `<n>` for `exit <n>`, and 0
+    /// otherwise.
+    fn run_command(
+        spec: &RuntimeSpec,
+        command: &[String],
+    ) -> Result<(String, std::process::ExitStatus), RuntimeError> {
+        use std::os::unix::process::ExitStatusExt;
+        let stdout = format!("mock-exec: {}\n", command.join(" "));
+
+        // Record the command in the writable layer, as a real command's
+        // writes would land there, so engine tests can see what ran.
+        let upper = std::path::Path::new(&spec.overlay_path).join("upper");
+        if upper.is_dir() && !spec.read_only {
+            use std::io::Write;
+            let mut log = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(upper.join(MOCK_EXEC_LOG))?;
+            log.write_all(stdout.as_bytes())?;
+        }
+
+        let code = match command {
+            [cmd] if cmd == "false" => 1,
+            [cmd, code] if cmd == "exit" => code.parse().unwrap_or(1),
+            _ => 0,
+        };
+        Ok((stdout, std::process::ExitStatus::from_raw(code << 8)))
+    }
 }
 
 impl RuntimeBackend for MockBackend {
@@ -31,7 +78,11 @@ impl RuntimeBackend for MockBackend {
         true
     }
 
-    fn resolve(&self, spec: &RuntimeSpec) -> Result<ResolutionResult, RuntimeError> {
+    fn resolve(
+        &self,
+        spec: &RuntimeSpec,
+        _progress: &dyn ProgressSink,
+    ) -> Result<ResolutionResult, RuntimeError> {
         // Mock resolution: deterministic digest from image name,
         // packages get version "0.0.0-mock" for deterministic identity.
         let base_image_digest =
@@ -39,12 +90,13 @@ impl RuntimeBackend for MockBackend {
                 .to_hex()
                 .to_string();
 
-        let resolved_packages = spec
-            .manifest
-            .system_packages
-            .iter()
+        let index: Vec<String> = MOCK_PACKAGE_INDEX.iter().map(|p| (*p).to_owned()).collect();
+        let packages =
+            karapace_schema::expand_package_patterns(&spec.manifest.system_packages, &index)?;
+        let resolved_packages = packages
+            .into_iter()
             .map(|name| ResolvedPackage {
-                name: name.clone(),
+                name,
                 version: "0.0.0-mock".to_owned(),
             })
             .collect();
@@ -55,7 +107,7 @@ impl RuntimeBackend for MockBackend {
         })
     }
 
-    fn build(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
+    fn build(&self, spec: &RuntimeSpec, progress: &dyn ProgressSink) -> Result<(), RuntimeError> {
         let mut state = self
             .state
             .lock()
@@ -73,12 +125,16 @@ impl RuntimeBackend for MockBackend {
 
         // Create upper dir with mock filesystem content so engine tests
         // exercise the real layer capture path (pack_layer on upper dir).
+        progress.phase(BuildPhase::Unpack);
         let upper = overlay.join("upper");
         std::fs::create_dir_all(&upper)?;
         std::fs::write(
             upper.join(".karapace-mock"),
             format!("mock-env:{}", spec.env_id),
         )?;
+        if !spec.manifest.system_packages.is_empty() {
+            progress.phase(BuildPhase::InstallPackages);
+        }
         for pkg in &spec.manifest.system_packages {
             std::fs::write(
                 upper.join(format!(".pkg-{pkg}")),
@@ -103,26 +159,31 @@ impl RuntimeBackend for MockBackend {
 
     fn exec(
         &self,
-        _spec: &RuntimeSpec,
+        spec: &RuntimeSpec,
         command: &[String],
     ) -> Result<std::process::Output, RuntimeError> {
-        let stdout = format!("mock-exec: {}\n", command.join(" "));
-
-        #[cfg(unix)]
-        let success_status = {
-            use std::os::unix::process::ExitStatusExt;
-            std::process::ExitStatus::from_raw(0)
-        };
-
-        #[cfg(not(unix))]
-        let success_status = std::process::Command::new("true").status()?;
+        let (stdout, status) = Self::run_command(spec, command)?;
         Ok(std::process::Output {
-            status: success_status,
+            status,
             stdout: stdout.into_bytes(),
             stderr: Vec::new(),
         })
     }
 
+    fn exec_attached(
+        &self,
+        spec: &RuntimeSpec,
+        command: &[String],
+        _tty: bool,
+    ) -> Result<std::process::ExitStatus, RuntimeError> {
+        use std::io::Write;
+        let (stdout, status) = Self::run_command(spec, command)?;
+        let mut out = std::io::stdout().lock();
+        out.write_all(stdout.as_bytes())?;
+        out.flush()?;
+        Ok(status)
+    }
+
     fn destroy(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
         let mut state = self
             .state
@@ -137,6 +198,36 @@ impl RuntimeBackend for MockBackend {
         Ok(())
     }
 
+    fn start_detached(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
+        self.enter(spec)
+    }
+
+    fn attach(
+        &self,
+        spec: &RuntimeSpec,
+        command: &[String],
+    ) -> Result<std::process::ExitStatus, RuntimeError> {
+        use std::io::Write;
+        let (stdout, status) = Self::run_command(spec, command)?;
+        let mut out = std::io::stdout().lock();
+        out.write_all(stdout.replacen("mock-exec", "mock-attach", 1).as_bytes())?;
+        out.flush()?;
+        Ok(status)
+    }
+
+    /// The mock runs no processes; report a session shell with the PID
+    /// `status` uses.
+    fn processes(&self, _env_id: &str) -> Result<Vec<ProcessInfo>, RuntimeError> {
+        Ok(vec![ProcessInfo {
+            pid: 99999,
+            ppid: 1,
+            command: "/bin/sh -l".to_owned(),
+            cpu_secs: 0.0,
+            cpu_percent: 0.0,
+            rss_kb: 0,
+        }])
+    }
+
     fn status(&self, env_id: &str) -> Result<RuntimeStatus, RuntimeError> {
         let state = self
             .state
@@ -147,6 +238,7 @@ impl RuntimeBackend for MockBackend {
             env_id: env_id.to_owned(),
             running,
             pid: if running { Some(99999) } else { None },
+            init: false,
         })
     }
 }
@@ -154,6 +246,7 @@ impl RuntimeBackend for MockBackend {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::NoProgress;
     use karapace_schema::parse_manifest_str;
 
     fn test_spec(dir: &std::path::Path) -> RuntimeSpec {
@@ -175,6 +268,7 @@ image = "rolling"
             store_root: dir.to_string_lossy().to_string(),
             manifest,
             offline: false,
+            read_only: false,
         }
     }
 
@@ -184,8 +278,8 @@ image = "rolling"
         let backend = MockBackend::new();
         let spec = test_spec(dir.path());
 
-        let r1 = backend.resolve(&spec).unwrap();
-        let r2 = backend.resolve(&spec).unwrap();
+        let r1 = backend.resolve(&spec, &NoProgress).unwrap();
+        let r2 = backend.resolve(&spec, &NoProgress).unwrap();
 
         assert_eq!(r1.base_image_digest, r2.base_image_digest);
         assert_eq!(r1.resolved_packages.len(), r2.resolved_packages.len());
@@ -220,10 +314,11 @@ backend = "mock"
             store_root: dir.path().to_string_lossy().to_string(),
             manifest,
             offline: false,
+            read_only: false,
         };
 
         let backend = MockBackend::new();
-        let result = backend.resolve(&spec).unwrap();
+        let result = backend.resolve(&spec, &NoProgress).unwrap();
 
         assert_eq!(result.resolved_packages.len(), 3);
         assert!(result
@@ -234,13 +329,32 @@ backend = "mock"
         assert!(!result.base_image_digest.is_empty());
     }
 
+    #[test]
+    fn mock_resolve_expands_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spec = test_spec(dir.path());
+        spec.manifest.system_packages = vec!["git".to_owned(), "python3-*-dev".to_owned()];
+
+        let result = MockBackend::new().resolve(&spec, &NoProgress).unwrap();
+        let names: Vec<&str> = result
+            .resolved_packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["git", "python3-all-dev", "python3-venv-dev"]);
+
+        spec.manifest.system_packages = vec!["nothing-*".to_owned()];
+        let err = MockBackend::new().resolve(&spec, &NoProgress).unwrap_err();
+        assert!(matches!(err, RuntimeError::Manifest(_)), "{err}");
+    }
+
     #[test]
     fn mock_lifecycle() {
         let dir = tempfile::tempdir().unwrap();
         let backend = MockBackend::new();
         let spec = test_spec(dir.path());
 
-        backend.build(&spec).unwrap();
+        backend.build(&spec, &NoProgress).unwrap();
         let status = backend.status(&spec.env_id).unwrap();
         assert!(!status.running);
 
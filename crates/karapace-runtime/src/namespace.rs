@@ -1,16 +1,25 @@
 use crate::backend::{RuntimeBackend, RuntimeSpec, RuntimeStatus};
+use crate::cgroup::{CgroupLimits, SessionCgroup};
 use crate::host::compute_host_integration;
-use crate::image::{
-    compute_image_digest, detect_package_manager, force_remove, install_packages_command,
-    parse_version_output, query_versions_command, resolve_image, ImageCache,
+use crate::image::{compute_image_digest, force_remove, resolve_image, ImageCache};
+use crate::init::{session_init, write_init_marker, INIT_MARKER};
+use crate::portfwd::{
+    build_network_mode, forward_session_ports, session_forward_ports, session_network_mode,
+    PortForwarder,
 };
+use crate::process::{namespace_processes, ProcessInfo};
+use crate::quota::check_quota;
 use crate::sandbox::{
-    exec_in_container, install_packages_in_container, mount_overlay, setup_container_rootfs,
-    spawn_enter_interactive, unmount_overlay, SandboxConfig,
+    attach_to_session, exec_attached_in_container, exec_in_container,
+    install_packages_in_container, mount_overlay, resolve_packages_in_container,
+    select_package_manager, setup_container_rootfs, spawn_detached, spawn_enter_interactive,
+    unmount_overlay, SandboxConfig,
 };
+use crate::session::{attach_target, DetachedSession};
 use crate::terminal;
-use crate::RuntimeError;
-use karapace_schema::{ResolutionResult, ResolvedPackage};
+use crate::watchdog::{Watchdog, WatchdogConfig};
+use crate::{BuildPhase, ProgressSink, RuntimeError};
+use karapace_schema::{NetworkMode, ResolutionResult, ResolvedPackage};
 use libc::{SIGKILL, SIGTERM};
 use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
@@ -41,6 +50,98 @@ impl NamespaceBackend {
     fn env_dir(&self, env_id: &str) -> PathBuf {
         self.store_root.join("env").join(env_id)
     }
+
+    /// Sandbox of an entered session of `spec`, before anything is mounted.
+    fn session_sandbox(&self, spec: &RuntimeSpec) -> Result<SandboxConfig, RuntimeError> {
+        let env_dir = self.env_dir(&spec.env_id);
+        if !env_dir.join(".built").exists() {
+            return Err(RuntimeError::ExecFailed(format!(
+                "environment {} has not been built yet. Run 'karapace build' first.",
+                &spec.env_id[..12.min(spec.env_id.len())]
+            )));
+        }
+
+        let resolved = resolve_image(&spec.manifest.base_image)?;
+        let image_cache = ImageCache::new(&self.store_root);
+        let rootfs = image_cache.rootfs_path(&resolved.cache_key);
+
+        if !rootfs.join("etc").exists() {
+            return Err(RuntimeError::ExecFailed(
+                "base image rootfs is missing or corrupted. Run 'karapace rebuild'.".to_owned(),
+            ));
+        }
+
+        let mut sandbox = SandboxConfig::new(rootfs, &spec.env_id, &env_dir);
+        sandbox.network = session_network_mode(spec);
+        sandbox.forward_ports = session_forward_ports(spec);
+        sandbox.dns_servers.clone_from(&spec.manifest.dns_servers);
+        sandbox.extra_hosts.clone_from(&spec.manifest.extra_hosts);
+        sandbox.read_only = spec.read_only;
+        sandbox.max_overlay_mb = spec.manifest.max_overlay_mb;
+        sandbox.init = session_init(spec);
+        sandbox.hostname = format!("karapace-{}", &spec.env_id[..12.min(spec.env_id.len())]);
+
+        let host = compute_host_integration(&spec.manifest)?;
+        sandbox.bind_mounts.extend(host.bind_mounts);
+        sandbox.env_vars.extend(host.env_vars);
+
+        Ok(sandbox)
+    }
+
+    /// Mount the environment for `exec` and return its sandbox; the caller
+    /// unmounts it.
+    fn exec_sandbox(&self, spec: &RuntimeSpec) -> Result<SandboxConfig, RuntimeError> {
+        let env_dir = self.env_dir(&spec.env_id);
+        if !env_dir.join(".built").exists() {
+            return Err(RuntimeError::ExecFailed(format!(
+                "environment {} has not been built yet. Run 'karapace build' first.",
+                &spec.env_id[..12.min(spec.env_id.len())]
+            )));
+        }
+
+        let resolved = resolve_image(&spec.manifest.base_image)?;
+        let image_cache = ImageCache::new(&self.store_root);
+        let rootfs = image_cache.rootfs_path(&resolved.cache_key);
+
+        let mut sandbox = SandboxConfig::new(rootfs, &spec.env_id, &env_dir);
+        sandbox.network = session_network_mode(spec);
+        sandbox.forward_ports = session_forward_ports(spec);
+        sandbox.dns_servers.clone_from(&spec.manifest.dns_servers);
+        sandbox.extra_hosts.clone_from(&spec.manifest.extra_hosts);
+        sandbox.read_only = spec.read_only;
+        sandbox.max_overlay_mb = spec.manifest.max_overlay_mb;
+        sandbox.init = session_init(spec);
+
+        let host = compute_host_integration(&spec.manifest)?;
+        sandbox.bind_mounts.extend(host.bind_mounts);
+        sandbox.env_vars.extend(host.env_vars);
+
+        mount_overlay(&sandbox)?;
+        setup_container_rootfs(&sandbox)?;
+
+        Ok(sandbox)
+    }
+}
+
+/// Give the session a cgroup with the manifest's resource limits. Without a
+/// delegated cgroup v2 hierarchy the session runs unlimited, with a warning.
+fn limit_session(spec: &RuntimeSpec, sandbox: &mut SandboxConfig) -> Option<SessionCgroup> {
+    let limits = CgroupLimits::from_manifest(&spec.manifest);
+    if limits.is_empty() {
+        return None;
+    }
+    match SessionCgroup::create(&spec.env_id, &limits) {
+        Ok(cgroup) => {
+            sandbox.cgroup = Some(cgroup.path().to_path_buf());
+            Some(cgroup)
+        }
+        Err(e) => {
+            let message = format!("resource limits not enforced: {e}");
+            tracing::warn!("environment {}: {message}", spec.env_id);
+            terminal::print_warning(&spec.env_id, &message);
+            None
+        }
+    }
 }
 
 impl RuntimeBackend for NamespaceBackend {
@@ -55,14 +156,14 @@ impl RuntimeBackend for NamespaceBackend {
         matches!(output, Ok(o) if o.status.success())
     }
 
-    fn resolve(&self, spec: &RuntimeSpec) -> Result<ResolutionResult, RuntimeError> {
-        let progress = |msg: &str| {
-            eprintln!("[karapace] {msg}");
-        };
-
+    fn resolve(
+        &self,
+        spec: &RuntimeSpec,
+        progress: &dyn ProgressSink,
+    ) -> Result<ResolutionResult, RuntimeError> {
         let resolved = resolve_image(&spec.manifest.base_image)?;
         let image_cache = ImageCache::new(&self.store_root);
-        let rootfs = image_cache.ensure_image(&resolved, &progress, spec.offline)?;
+        let rootfs = image_cache.ensure_image(&resolved, progress, spec.offline)?;
 
         let base_image_digest = compute_image_digest(&rootfs)?;
 
@@ -81,27 +182,14 @@ impl RuntimeBackend for NamespaceBackend {
             std::fs::create_dir_all(&tmp_env)?;
 
             let mut sandbox = SandboxConfig::new(rootfs.clone(), "resolve-tmp", &tmp_env);
-            sandbox.isolate_network = false;
+            sandbox.network = NetworkMode::Host;
 
             mount_overlay(&sandbox)?;
             setup_container_rootfs(&sandbox)?;
 
-            let resolve_inner = || -> Result<Vec<(String, String)>, RuntimeError> {
-                let pkg_mgr = detect_package_manager(&sandbox.overlay_merged)
-                    .or_else(|| detect_package_manager(&rootfs))
-                    .ok_or_else(|| {
-                        RuntimeError::ExecFailed(
-                            "no supported package manager found in the image".to_owned(),
-                        )
-                    })?;
-
-                let install_cmd = install_packages_command(pkg_mgr, &spec.manifest.system_packages);
-                install_packages_in_container(&sandbox, &install_cmd)?;
-
-                let query_cmd = query_versions_command(pkg_mgr, &spec.manifest.system_packages);
-                let output = exec_in_container(&sandbox, &query_cmd)?;
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                Ok(parse_version_output(pkg_mgr, &stdout))
+            let resolve_inner = || -> Result<Vec<ResolvedPackage>, RuntimeError> {
+                let pm = select_package_manager(&resolved, &sandbox.overlay_merged, &rootfs)?;
+                resolve_packages_in_container(&sandbox, pm, &spec.manifest.system_packages)
             };
 
             let result = resolve_inner();
@@ -109,22 +197,7 @@ impl RuntimeBackend for NamespaceBackend {
             let _ = unmount_overlay(&sandbox);
             let _ = std::fs::remove_dir_all(&tmp_env);
 
-            let versions = result?;
-
-            spec.manifest
-                .system_packages
-                .iter()
-                .map(|name| {
-                    let version = versions
-                        .iter()
-                        .find(|(n, _)| n == name)
-                        .map_or_else(|| "unresolved".to_owned(), |(_, v)| v.clone());
-                    ResolvedPackage {
-                        name: name.clone(),
-                        version,
-                    }
-                })
-                .collect()
+            result?
         };
 
         Ok(ResolutionResult {
@@ -133,21 +206,22 @@ impl RuntimeBackend for NamespaceBackend {
         })
     }
 
-    fn build(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
+    fn build(&self, spec: &RuntimeSpec, progress: &dyn ProgressSink) -> Result<(), RuntimeError> {
         let env_dir = self.env_dir(&spec.env_id);
         std::fs::create_dir_all(&env_dir)?;
 
-        let progress = |msg: &str| {
-            eprintln!("[karapace] {msg}");
-        };
-
         let resolved = resolve_image(&spec.manifest.base_image)?;
         let image_cache = ImageCache::new(&self.store_root);
-        let rootfs = image_cache.ensure_image(&resolved, &progress, spec.offline)?;
+        let rootfs = image_cache.ensure_image(&resolved, progress, spec.offline)?;
 
         let mut sandbox = SandboxConfig::new(rootfs.clone(), &spec.env_id, &env_dir);
-        sandbox.isolate_network = spec.offline || spec.manifest.network_isolation;
+        sandbox.network = build_network_mode(spec);
+        sandbox.dns_servers.clone_from(&spec.manifest.dns_servers);
+        sandbox.extra_hosts.clone_from(&spec.manifest.extra_hosts);
+        sandbox.max_overlay_mb = spec.manifest.max_overlay_mb;
 
+        progress.phase(BuildPhase::Unpack);
+        progress.message("assembling root filesystem...");
         mount_overlay(&sandbox)?;
 
         setup_container_rootfs(&sandbox)?;
@@ -158,32 +232,28 @@ impl RuntimeBackend for NamespaceBackend {
                     "offline mode: cannot install system packages".to_owned(),
                 ));
             }
-            let pkg_mgr = detect_package_manager(&sandbox.overlay_merged)
-                .or_else(|| detect_package_manager(&rootfs))
-                .ok_or_else(|| {
-                    RuntimeError::ExecFailed(
-                        "no supported package manager found in the image. \
-                         Supported: apt, dnf, zypper, pacman"
-                            .to_owned(),
-                    )
-                })?;
-
-            progress(&format!(
-                "installing {} packages via {pkg_mgr}...",
-                spec.manifest.system_packages.len()
+            let pm = select_package_manager(&resolved, &sandbox.overlay_merged, &rootfs)?;
+
+            progress.phase(BuildPhase::InstallPackages);
+            progress.message(&format!(
+                "installing {} packages via {}...",
+                spec.manifest.system_packages.len(),
+                pm.name()
             ));
 
-            let install_cmd = install_packages_command(pkg_mgr, &spec.manifest.system_packages);
-            install_packages_in_container(&sandbox, &install_cmd)?;
+            install_packages_in_container(
+                &sandbox,
+                &pm.install_command(&spec.manifest.system_packages),
+            )?;
 
-            progress("packages installed");
+            progress.message("packages installed");
         }
 
         unmount_overlay(&sandbox)?;
 
         std::fs::write(env_dir.join(".built"), "1")?;
 
-        progress(&format!(
+        progress.message(&format!(
             "environment {} built successfully ({} base)",
             &spec.env_id[..12.min(spec.env_id.len())],
             resolved.display_name
@@ -194,30 +264,8 @@ impl RuntimeBackend for NamespaceBackend {
 
     fn enter(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
         let env_dir = self.env_dir(&spec.env_id);
-        if !env_dir.join(".built").exists() {
-            return Err(RuntimeError::ExecFailed(format!(
-                "environment {} has not been built yet. Run 'karapace build' first.",
-                &spec.env_id[..12.min(spec.env_id.len())]
-            )));
-        }
-
-        let resolved = resolve_image(&spec.manifest.base_image)?;
-        let image_cache = ImageCache::new(&self.store_root);
-        let rootfs = image_cache.rootfs_path(&resolved.cache_key);
-
-        if !rootfs.join("etc").exists() {
-            return Err(RuntimeError::ExecFailed(
-                "base image rootfs is missing or corrupted. Run 'karapace rebuild'.".to_owned(),
-            ));
-        }
-
-        let mut sandbox = SandboxConfig::new(rootfs, &spec.env_id, &env_dir);
-        sandbox.isolate_network = spec.offline || spec.manifest.network_isolation;
-        sandbox.hostname = format!("karapace-{}", &spec.env_id[..12.min(spec.env_id.len())]);
-
-        let host = compute_host_integration(&spec.manifest);
-        sandbox.bind_mounts.extend(host.bind_mounts);
-        sandbox.env_vars.extend(host.env_vars);
+        let mut sandbox = self.session_sandbox(spec)?;
+        let cgroup = limit_session(spec, &mut sandbox);
 
         mount_overlay(&sandbox)?;
         setup_container_rootfs(&sandbox)?;
@@ -229,7 +277,11 @@ impl RuntimeBackend for NamespaceBackend {
             &sandbox.hostname,
         );
 
-        let mut child = match spawn_enter_interactive(&sandbox) {
+        let spawned = spawn_enter_interactive(&sandbox).and_then(|mut child| {
+            let forwarder = forward_session_ports(&sandbox, &mut child)?;
+            Ok((child, forwarder))
+        });
+        let (mut child, forwarder) = match spawned {
             Ok(c) => c,
             Err(e) => {
                 terminal::emit_container_pop();
@@ -239,7 +291,9 @@ impl RuntimeBackend for NamespaceBackend {
             }
         };
 
-        if let Err(e) = std::fs::write(env_dir.join(".running"), format!("{}", child.id())) {
+        let markers = std::fs::write(env_dir.join(".running"), format!("{}", child.id()))
+            .and_then(|()| write_init_marker(&env_dir, sandbox.init.is_some()));
+        if let Err(e) = markers {
             let _ = child.kill();
             terminal::emit_container_pop();
             terminal::print_container_exit(&spec.env_id);
@@ -247,6 +301,20 @@ impl RuntimeBackend for NamespaceBackend {
             return Err(e.into());
         }
 
+        let env_id = spec.env_id.clone();
+        let watchdog = Watchdog::spawn(
+            child.id(),
+            sandbox.session_upper(),
+            WatchdogConfig {
+                quota_mb: sandbox.max_overlay_mb,
+                ..WatchdogConfig::default()
+            },
+            move |event| {
+                tracing::warn!("environment {env_id}: {event}");
+                terminal::print_resource_event(&env_id, event);
+            },
+        );
+
         // Wait for the interactive session to complete.
         let exit_code = match child.wait() {
             Ok(status) => {
@@ -262,10 +330,17 @@ impl RuntimeBackend for NamespaceBackend {
         };
 
         // Cleanup
+        watchdog.stop();
+        drop(forwarder);
+        // Measured before unmounting, which discards a read-only scratch upper.
+        let quota = check_quota(&sandbox.session_upper(), sandbox.max_overlay_mb);
         terminal::emit_container_pop();
         terminal::print_container_exit(&spec.env_id);
         let _ = std::fs::remove_file(env_dir.join(".running"));
+        let _ = std::fs::remove_file(env_dir.join(INIT_MARKER));
         let _ = unmount_overlay(&sandbox);
+        drop(cgroup);
+        quota?;
 
         match exit_code {
             Ok(0) => Ok(()),
@@ -281,32 +356,96 @@ impl RuntimeBackend for NamespaceBackend {
         spec: &RuntimeSpec,
         command: &[String],
     ) -> Result<std::process::Output, RuntimeError> {
-        let env_dir = self.env_dir(&spec.env_id);
-        if !env_dir.join(".built").exists() {
-            return Err(RuntimeError::ExecFailed(format!(
-                "environment {} has not been built yet. Run 'karapace build' first.",
-                &spec.env_id[..12.min(spec.env_id.len())]
-            )));
-        }
-
-        let resolved = resolve_image(&spec.manifest.base_image)?;
-        let image_cache = ImageCache::new(&self.store_root);
-        let rootfs = image_cache.rootfs_path(&resolved.cache_key);
+        let mut sandbox = self.exec_sandbox(spec)?;
+        let _cgroup = limit_session(spec, &mut sandbox);
+        let output = exec_in_container(&sandbox, command);
+        let _ = unmount_overlay(&sandbox);
+        output
+    }
 
-        let mut sandbox = SandboxConfig::new(rootfs, &spec.env_id, &env_dir);
-        sandbox.isolate_network = spec.offline || spec.manifest.network_isolation;
+    fn exec_attached(
+        &self,
+        spec: &RuntimeSpec,
+        command: &[String],
+        tty: bool,
+    ) -> Result<std::process::ExitStatus, RuntimeError> {
+        let mut sandbox = self.exec_sandbox(spec)?;
+        let _cgroup = limit_session(spec, &mut sandbox);
+        let status = exec_attached_in_container(&sandbox, command, tty);
+        let _ = unmount_overlay(&sandbox);
+        status
+    }
 
-        let host = compute_host_integration(&spec.manifest);
-        sandbox.bind_mounts.extend(host.bind_mounts);
-        sandbox.env_vars.extend(host.env_vars);
+    fn start_detached(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
+        let env_dir = self.env_dir(&spec.env_id);
+        let mut sandbox = self.session_sandbox(spec)?;
+        let cgroup = limit_session(spec, &mut sandbox);
 
         mount_overlay(&sandbox)?;
         setup_container_rootfs(&sandbox)?;
 
-        let output = exec_in_container(&sandbox, command);
-        let _ = unmount_overlay(&sandbox);
+        let spawned = spawn_detached(&sandbox).and_then(|mut child| {
+            let forwarder = forward_session_ports(&sandbox, &mut child)?;
+            Ok((child, forwarder))
+        });
+        let (mut child, forwarder) = match spawned {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = unmount_overlay(&sandbox);
+                return Err(e);
+            }
+        };
 
-        output
+        let (slirp_pid, api_socket) = forwarder.map(PortForwarder::detach).unzip();
+        let session = DetachedSession {
+            pid: child.id(),
+            slirp_pid,
+            api_socket,
+            cgroup: cgroup.map(SessionCgroup::keep),
+        };
+        let markers = session
+            .save(&env_dir)
+            .and_then(|()| std::fs::write(env_dir.join(".running"), format!("{}", child.id())))
+            .and_then(|()| write_init_marker(&env_dir, sandbox.init.is_some()));
+        if let Err(e) = markers {
+            let _ = child.kill();
+            let _ = child.wait();
+            session.release();
+            DetachedSession::remove(&env_dir);
+            let _ = unmount_overlay(&sandbox);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn attach(
+        &self,
+        spec: &RuntimeSpec,
+        command: &[String],
+    ) -> Result<std::process::ExitStatus, RuntimeError> {
+        let env_dir = self.env_dir(&spec.env_id);
+        let short_id = &spec.env_id[..12.min(spec.env_id.len())];
+        let not_detached = || RuntimeError::NotRunning(format!("{short_id} (no detached session)"));
+        let session = DetachedSession::load(&env_dir).ok_or_else(not_detached)?;
+        if !session.is_alive() {
+            return Err(not_detached());
+        }
+        let mut sandbox = self.session_sandbox(spec)?;
+        sandbox.cgroup.clone_from(&session.cgroup);
+        let target = attach_target(&sandbox.overlay_merged).ok_or_else(not_detached)?;
+        attach_to_session(&sandbox, target, command)
+    }
+
+    fn end_detached(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
+        let env_dir = self.env_dir(&spec.env_id);
+        let Some(session) = DetachedSession::load(&env_dir) else {
+            return Ok(());
+        };
+        session.release();
+        DetachedSession::remove(&env_dir);
+        let _ = std::fs::remove_file(env_dir.join(INIT_MARKER));
+        let sandbox = SandboxConfig::new(PathBuf::from("/nonexistent"), &spec.env_id, &env_dir);
+        unmount_overlay(&sandbox)
     }
 
     fn destroy(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
@@ -323,6 +462,14 @@ impl RuntimeBackend for NamespaceBackend {
         Ok(())
     }
 
+    fn processes(&self, env_id: &str) -> Result<Vec<ProcessInfo>, RuntimeError> {
+        Ok(self
+            .status(env_id)?
+            .pid
+            .map(namespace_processes)
+            .unwrap_or_default())
+    }
+
     fn status(&self, env_id: &str) -> Result<RuntimeStatus, RuntimeError> {
         let env_dir = self.env_dir(env_id);
         let running_file = env_dir.join(".running");
@@ -339,6 +486,7 @@ impl RuntimeBackend for NamespaceBackend {
                         env_id: env_id.to_owned(),
                         running: false,
                         pid: None,
+                        init: false,
                     });
                 }
             };
@@ -360,12 +508,14 @@ impl RuntimeBackend for NamespaceBackend {
                         env_id: env_id.to_owned(),
                         running: false,
                         pid: None,
+                        init: false,
                     });
                 }
                 return Ok(RuntimeStatus {
                     env_id: env_id.to_owned(),
                     running: true,
                     pid: Some(p),
+                    init: env_dir.join(INIT_MARKER).exists(),
                 });
             }
         }
@@ -374,6 +524,7 @@ impl RuntimeBackend for NamespaceBackend {
             env_id: env_id.to_owned(),
             running: false,
             pid: None,
+            init: false,
         })
     }
 }
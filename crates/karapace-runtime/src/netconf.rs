@@ -0,0 +1,144 @@
+//! Name resolution inside sessions.
+//!
+//! A session normally sees the host's `/etc/resolv.conf` and the image's own
+//! `/etc/hosts`. `slirp` sessions resolve through the slirp4netns DNS
+//! forwarder, `[network] dns` replaces the nameservers, and `[network]
+//! extra_hosts` adds entries to the hosts file. The replacement files are
+//! written to the session's `net_dir` and bound over the originals.
+
+use crate::portfwd::SLIRP_DNS;
+use crate::sandbox::SandboxConfig;
+use crate::RuntimeError;
+use karapace_schema::ExtraHost;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Hosts file of images that do not ship one.
+const DEFAULT_HOSTS: &str = "127.0.0.1\tlocalhost\n::1\tlocalhost\n";
+
+/// Nameservers of the session's own `resolv.conf`, or `None` when it uses
+/// the host's.
+fn nameservers(config: &SandboxConfig) -> Option<Vec<&str>> {
+    if !config.dns_servers.is_empty() {
+        Some(config.dns_servers.iter().map(String::as_str).collect())
+    } else if config.uses_slirp() {
+        Some(vec![SLIRP_DNS])
+    } else {
+        None
+    }
+}
+
+/// The file bound to the session's `/etc/resolv.conf`.
+pub fn resolv_conf_source(config: &SandboxConfig) -> PathBuf {
+    if nameservers(config).is_some() {
+        config.net_dir.join("resolv.conf")
+    } else {
+        PathBuf::from("/etc/resolv.conf")
+    }
+}
+
+/// The file bound to the session's `/etc/hosts`, when the manifest adds
+/// entries to it.
+pub fn hosts_source(config: &SandboxConfig) -> Option<PathBuf> {
+    (!config.extra_hosts.is_empty()).then(|| config.net_dir.join("hosts"))
+}
+
+/// Write the files [`resolv_conf_source`] and [`hosts_source`] name into
+/// `net_dir`. The hosts file starts from the one in `rootfs`.
+pub fn write_session_files(config: &SandboxConfig, rootfs: &Path) -> Result<(), RuntimeError> {
+    if let Some(servers) = nameservers(config) {
+        std::fs::create_dir_all(&config.net_dir)?;
+        std::fs::write(config.net_dir.join("resolv.conf"), resolv_conf(&servers))?;
+    }
+    if let Some(path) = hosts_source(config) {
+        std::fs::create_dir_all(&config.net_dir)?;
+        let base = std::fs::read_to_string(rootfs.join("etc/hosts"))
+            .unwrap_or_else(|_| DEFAULT_HOSTS.to_owned());
+        std::fs::write(path, hosts_file(&base, &config.extra_hosts))?;
+    }
+    Ok(())
+}
+
+fn resolv_conf(servers: &[&str]) -> String {
+    let mut resolv = String::new();
+    for server in servers {
+        let _ = writeln!(resolv, "nameserver {server}");
+    }
+    resolv
+}
+
+fn hosts_file(base: &str, extra: &[ExtraHost]) -> String {
+    let mut hosts = base.to_owned();
+    if !hosts.is_empty() && !hosts.ends_with('\n') {
+        hosts.push('\n');
+    }
+    hosts.push_str("# [network] extra_hosts\n");
+    for host in extra {
+        let _ = writeln!(hosts, "{}\t{}", host.address, host.hostname);
+    }
+    hosts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karapace_schema::NetworkMode;
+
+    #[test]
+    fn host_files_are_kept_without_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SandboxConfig::new(dir.path().join("rootfs"), "abc123def456", dir.path());
+        write_session_files(&config, &config.rootfs).unwrap();
+        assert_eq!(
+            resolv_conf_source(&config),
+            PathBuf::from("/etc/resolv.conf")
+        );
+        assert!(hosts_source(&config).is_none());
+        assert!(!config.net_dir.exists());
+    }
+
+    #[test]
+    fn overrides_are_written_to_net_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let rootfs = dir.path().join("rootfs");
+        std::fs::create_dir_all(rootfs.join("etc")).unwrap();
+        std::fs::write(rootfs.join("etc/hosts"), "127.0.0.1 localhost").unwrap();
+        let mut config = SandboxConfig::new(rootfs.clone(), "abc123def456", dir.path());
+
+        config.network = NetworkMode::Slirp;
+        write_session_files(&config, &rootfs).unwrap();
+        let resolv = resolv_conf_source(&config);
+        assert_eq!(resolv, dir.path().join("net/resolv.conf"));
+        assert_eq!(
+            std::fs::read_to_string(&resolv).unwrap(),
+            "nameserver 10.0.2.3\n"
+        );
+
+        // Manifest nameservers win over the slirp4netns forwarder.
+        config.dns_servers = vec!["10.0.0.53".to_owned(), "fd00::53".to_owned()];
+        config.extra_hosts = vec![
+            ExtraHost::parse("db.local:10.0.0.5").unwrap(),
+            ExtraHost::parse("db.local:fd00::5").unwrap(),
+        ];
+        write_session_files(&config, &rootfs).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&resolv).unwrap(),
+            "nameserver 10.0.0.53\nnameserver fd00::53\n"
+        );
+        let hosts = hosts_source(&config).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(hosts).unwrap(),
+            "127.0.0.1 localhost\n# [network] extra_hosts\n10.0.0.5\tdb.local\nfd00::5\tdb.local\n"
+        );
+    }
+
+    #[test]
+    fn hosts_file_falls_back_to_localhost() {
+        let hosts = hosts_file(
+            DEFAULT_HOSTS,
+            &[ExtraHost::parse("db.local:10.0.0.5").unwrap()],
+        );
+        assert!(hosts.starts_with("127.0.0.1\tlocalhost\n::1\tlocalhost\n"));
+        assert!(hosts.ends_with("10.0.0.5\tdb.local\n"));
+    }
+}
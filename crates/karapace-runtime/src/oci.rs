@@ -1,18 +1,25 @@
 use crate::backend::{RuntimeBackend, RuntimeSpec, RuntimeStatus};
 use crate::host::compute_host_integration;
-use crate::image::{
-    compute_image_digest, detect_package_manager, force_remove, install_packages_command,
-    parse_version_output, query_versions_command, resolve_image, ImageCache,
+use crate::image::{compute_image_digest, force_remove, resolve_image, ImageCache};
+use crate::init::{init_args, session_init, write_init_marker, CONTAINER_INIT_PATH, INIT_MARKER};
+use crate::netconf::{hosts_source, resolv_conf_source};
+use crate::portfwd::{
+    build_network_mode, session_forward_ports, session_network_mode, PortForwarder,
 };
+use crate::process::{runtime_processes, ProcessInfo};
+use crate::rootless::{IdMapping, Rootless};
 use crate::sandbox::{
-    exec_in_container, install_packages_in_container, mount_overlay, setup_container_rootfs,
-    unmount_overlay, SandboxConfig,
+    exec_attached_in_container, exec_in_container, install_packages_in_container, mount_overlay,
+    resolve_packages_in_container, select_package_manager, setup_container_rootfs, unmount_overlay,
+    SandboxConfig,
 };
 use crate::terminal;
-use crate::RuntimeError;
-use karapace_schema::{ResolutionResult, ResolvedPackage};
-use std::path::PathBuf;
-use std::process::Command;
+use crate::{BuildPhase, ProgressSink, RuntimeError};
+use karapace_schema::{NetworkMode, ResolutionResult, ResolvedPackage};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::time::{Duration, Instant};
 
 pub struct OciBackend {
     store_root: PathBuf,
@@ -52,7 +59,59 @@ impl OciBackend {
         self.store_root.join("env").join(env_id)
     }
 
-    fn generate_oci_spec(config: &SandboxConfig, spec: &RuntimeSpec) -> String {
+    /// Mount the environment for `exec` and return its sandbox; the caller
+    /// unmounts it.
+    fn exec_sandbox(&self, spec: &RuntimeSpec) -> Result<SandboxConfig, RuntimeError> {
+        let env_dir = self.env_dir(&spec.env_id);
+        if !env_dir.join(".built").exists() {
+            return Err(RuntimeError::ExecFailed(format!(
+                "environment {} has not been built yet",
+                &spec.env_id[..12.min(spec.env_id.len())]
+            )));
+        }
+
+        let resolved = resolve_image(&spec.manifest.base_image)?;
+        let image_cache = ImageCache::new(&self.store_root);
+        let rootfs = image_cache.rootfs_path(&resolved.cache_key);
+
+        let mut sandbox = SandboxConfig::new(rootfs, &spec.env_id, &env_dir);
+        sandbox.network = session_network_mode(spec);
+        sandbox.forward_ports = session_forward_ports(spec);
+        sandbox.dns_servers.clone_from(&spec.manifest.dns_servers);
+        sandbox.extra_hosts.clone_from(&spec.manifest.extra_hosts);
+        sandbox.read_only = spec.read_only;
+        sandbox.max_overlay_mb = spec.manifest.max_overlay_mb;
+        sandbox.init = session_init(spec);
+
+        let host = compute_host_integration(&spec.manifest)?;
+        sandbox.bind_mounts.extend(host.bind_mounts);
+        sandbox.env_vars.extend(host.env_vars);
+
+        mount_overlay(&sandbox)?;
+        setup_container_rootfs(&sandbox)?;
+
+        Ok(sandbox)
+    }
+
+    /// The login shell, started under the bind-mounted init when one is set.
+    fn process_args_json(config: &SandboxConfig) -> String {
+        let shell = ["/bin/bash", "-l"];
+        let args: Vec<String> = match &config.init {
+            Some(_) => std::iter::once(CONTAINER_INIT_PATH.to_owned())
+                .chain(init_args(&shell))
+                .collect(),
+            None => shell.iter().map(|a| (*a).to_owned()).collect(),
+        };
+        args.iter()
+            .map(|a| format!("\"{a}\""))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// `config.json` of the bundle running `config`; `rootless` adds the
+    /// user namespace and cgroup of a container started without root.
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn generate_oci_spec(config: &SandboxConfig, rootless: Option<&Rootless>) -> String {
         let uid = config.uid;
         let gid = config.gid;
         let home = config.home_dir.display().to_string();
@@ -68,6 +127,14 @@ impl OciBackend {
             env_arr.push(format!("\"{}={}\"", k, v.replace('"', "\\\"")));
         }
 
+        // sysfs can only be mounted from a user namespace that owns the
+        // network namespace; without one, bind the host's /sys.
+        let sys = if rootless.is_some() && !config.unshares_network() {
+            r#"{"destination":"/sys","type":"bind","source":"/sys","options":["rbind","nosuid","noexec","nodev","ro"]}"#
+        } else {
+            r#"{"destination":"/sys","type":"sysfs","source":"sysfs","options":["nosuid","noexec","nodev","ro"]}"#
+        };
+
         let mut mounts = Vec::new();
         // Standard mounts
         mounts.push(r#"{"destination":"/proc","type":"proc","source":"proc"}"#.to_owned());
@@ -83,10 +150,7 @@ impl OciBackend {
             r#"{"destination":"/dev/shm","type":"tmpfs","source":"shm","options":["nosuid","noexec","nodev","mode=1777","size=65536k"]}"#
                 .to_owned(),
         );
-        mounts.push(
-            r#"{"destination":"/sys","type":"sysfs","source":"sysfs","options":["nosuid","noexec","nodev","ro"]}"#
-                .to_owned(),
-        );
+        mounts.push(sys.to_owned());
 
         // Home bind mount
         mounts.push(format!(
@@ -94,34 +158,66 @@ impl OciBackend {
         ));
 
         // resolv.conf
-        mounts.push(
-            r#"{"destination":"/etc/resolv.conf","type":"bind","source":"/etc/resolv.conf","options":["bind","ro"]}"#
-                .to_owned(),
-        );
+        mounts.push(format!(
+            r#"{{"destination":"/etc/resolv.conf","type":"bind","source":"{}","options":["bind","ro"]}}"#,
+            resolv_conf_source(config).display()
+        ));
+        if let Some(hosts) = hosts_source(config) {
+            mounts.push(format!(
+                r#"{{"destination":"/etc/hosts","type":"bind","source":"{}","options":["bind","ro"]}}"#,
+                hosts.display()
+            ));
+        }
+
+        if let Some(init) = &config.init {
+            mounts.push(format!(
+                r#"{{"destination":"{CONTAINER_INIT_PATH}","type":"bind","source":"{}","options":["bind","ro"]}}"#,
+                init.display()
+            ));
+        }
 
         // Custom bind mounts
         for bm in &config.bind_mounts {
-            let opts = if bm.read_only {
-                "\"rbind\",\"ro\""
-            } else {
-                "\"rbind\",\"rw\""
-            };
+            let mut opts = vec!["rbind"];
+            if !bm.read_only {
+                opts.push("rw");
+            }
+            opts.extend(bm.flags());
+            let opts: Vec<String> = opts.iter().map(|opt| format!("\"{opt}\"")).collect();
             mounts.push(format!(
                 r#"{{"destination":"{}","type":"bind","source":"{}","options":[{}]}}"#,
                 bm.target.display(),
                 bm.source.display(),
-                opts
+                opts.join(",")
             ));
         }
 
         let mounts_json = mounts.join(",");
         let env_json = env_arr.join(",");
 
-        let network_ns = if spec.manifest.network_isolation {
-            r#",{"type":"network"}"#
-        } else {
-            ""
-        };
+        let args_json = Self::process_args_json(config);
+        let mut extra_ns = String::new();
+        if config.unshares_network() {
+            extra_ns.push_str(r#",{"type":"network"}"#);
+        }
+        let mut user_ns = String::new();
+        if let Some(rootless) = rootless {
+            extra_ns.push_str(r#",{"type":"user"}"#);
+            user_ns = format!(
+                r#",
+    "uidMappings": [{}],
+    "gidMappings": [{}]"#,
+                id_mappings_json(&rootless.uid_mappings),
+                id_mappings_json(&rootless.gid_mappings)
+            );
+            if let Some(path) = &rootless.cgroups_path {
+                let _ = write!(
+                    user_ns,
+                    r#",
+    "cgroupsPath": "{path}""#
+                );
+            }
+        }
 
         let oci_spec = format!(
             r#"{{
@@ -129,7 +225,7 @@ impl OciBackend {
   "process": {{
     "terminal": true,
     "user": {{ "uid": {uid}, "gid": {gid} }},
-    "args": ["/bin/bash", "-l"],
+    "args": [{args_json}],
     "env": [{env_json}],
     "cwd": "{home}"
   }},
@@ -145,16 +241,59 @@ impl OciBackend {
       {{"type":"mount"}},
       {{"type":"ipc"}},
       {{"type":"uts"}}
-      {network_ns}
-    ],
-    "uidMappings": [{{ "containerID": 0, "hostID": {uid}, "size": 1 }}],
-    "gidMappings": [{{ "containerID": 0, "hostID": {gid}, "size": 1 }}]
+      {extra_ns}
+    ]{user_ns}
   }}
 }}"#
         );
 
         oci_spec
     }
+
+    /// `<runtime> run` the bundle in the foreground, forwarding the
+    /// sandbox's ports once the container is up.
+    pub(crate) fn run_bundle(
+        runtime: &str,
+        bundle_dir: &Path,
+        container_id: &str,
+        sandbox: &SandboxConfig,
+    ) -> Result<ExitStatus, RuntimeError> {
+        let pid_file = bundle_dir.join("container.pid");
+        let _ = std::fs::remove_file(&pid_file);
+        let run_failed = |e| RuntimeError::ExecFailed(format!("{runtime} run failed: {e}"));
+        let mut child = Command::new(runtime)
+            .args([
+                "run",
+                "--bundle",
+                &bundle_dir.to_string_lossy(),
+                "--pid-file",
+                &pid_file.to_string_lossy(),
+                container_id,
+            ])
+            .stdin(std::process::Stdio::inherit())
+            .stdout(std::process::Stdio::inherit())
+            .stderr(std::process::Stdio::inherit())
+            .spawn()
+            .map_err(run_failed)?;
+
+        let forwarder = if sandbox.uses_slirp() {
+            wait_for_pid_file(&pid_file, &mut child)
+                .and_then(|pid| PortForwarder::start(pid, &sandbox.forward_ports, &sandbox.net_dir))
+                .map(Some)
+        } else {
+            Ok(None)
+        };
+        let status = match forwarder {
+            Ok(_forwarder) => child.wait().map_err(run_failed),
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(e)
+            }
+        };
+        let _ = std::fs::remove_file(&pid_file);
+        status
+    }
 }
 
 impl RuntimeBackend for OciBackend {
@@ -166,14 +305,14 @@ impl RuntimeBackend for OciBackend {
         Self::find_runtime().is_some()
     }
 
-    fn resolve(&self, spec: &RuntimeSpec) -> Result<ResolutionResult, RuntimeError> {
-        let progress = |msg: &str| {
-            eprintln!("[karapace/oci] {msg}");
-        };
-
+    fn resolve(
+        &self,
+        spec: &RuntimeSpec,
+        progress: &dyn ProgressSink,
+    ) -> Result<ResolutionResult, RuntimeError> {
         let resolved = resolve_image(&spec.manifest.base_image)?;
         let image_cache = ImageCache::new(&self.store_root);
-        let rootfs = image_cache.ensure_image(&resolved, &progress, spec.offline)?;
+        let rootfs = image_cache.ensure_image(&resolved, progress, spec.offline)?;
         let base_image_digest = compute_image_digest(&rootfs)?;
 
         if spec.offline && !spec.manifest.system_packages.is_empty() {
@@ -191,29 +330,16 @@ impl RuntimeBackend for OciBackend {
             std::fs::create_dir_all(&tmp_env)?;
 
             let mut sandbox = SandboxConfig::new(rootfs.clone(), "resolve-tmp", &tmp_env);
-            sandbox.isolate_network = false;
+            sandbox.network = NetworkMode::Host;
 
             mount_overlay(&sandbox)?;
             setup_container_rootfs(&sandbox)?;
 
             // Run resolution inside an inner closure so cleanup always runs,
             // even if detect/install/query fails.
-            let resolve_inner = || -> Result<Vec<(String, String)>, RuntimeError> {
-                let pkg_mgr = detect_package_manager(&sandbox.overlay_merged)
-                    .or_else(|| detect_package_manager(&rootfs))
-                    .ok_or_else(|| {
-                        RuntimeError::ExecFailed(
-                            "no supported package manager found in the image".to_owned(),
-                        )
-                    })?;
-
-                let install_cmd = install_packages_command(pkg_mgr, &spec.manifest.system_packages);
-                install_packages_in_container(&sandbox, &install_cmd)?;
-
-                let query_cmd = query_versions_command(pkg_mgr, &spec.manifest.system_packages);
-                let output = exec_in_container(&sandbox, &query_cmd)?;
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                Ok(parse_version_output(pkg_mgr, &stdout))
+            let resolve_inner = || -> Result<Vec<ResolvedPackage>, RuntimeError> {
+                let pm = select_package_manager(&resolved, &sandbox.overlay_merged, &rootfs)?;
+                resolve_packages_in_container(&sandbox, pm, &spec.manifest.system_packages)
             };
 
             let result = resolve_inner();
@@ -222,22 +348,7 @@ impl RuntimeBackend for OciBackend {
             let _ = unmount_overlay(&sandbox);
             let _ = std::fs::remove_dir_all(&tmp_env);
 
-            let versions = result?;
-
-            spec.manifest
-                .system_packages
-                .iter()
-                .map(|name| {
-                    let version = versions
-                        .iter()
-                        .find(|(n, _)| n == name)
-                        .map_or_else(|| "unresolved".to_owned(), |(_, v)| v.clone());
-                    ResolvedPackage {
-                        name: name.clone(),
-                        version,
-                    }
-                })
-                .collect()
+            result?
         };
 
         Ok(ResolutionResult {
@@ -246,21 +357,22 @@ impl RuntimeBackend for OciBackend {
         })
     }
 
-    fn build(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
+    fn build(&self, spec: &RuntimeSpec, progress: &dyn ProgressSink) -> Result<(), RuntimeError> {
         let env_dir = self.env_dir(&spec.env_id);
         std::fs::create_dir_all(&env_dir)?;
 
-        let progress = |msg: &str| {
-            eprintln!("[karapace/oci] {msg}");
-        };
-
         let resolved = resolve_image(&spec.manifest.base_image)?;
         let image_cache = ImageCache::new(&self.store_root);
-        let rootfs = image_cache.ensure_image(&resolved, &progress, spec.offline)?;
+        let rootfs = image_cache.ensure_image(&resolved, progress, spec.offline)?;
 
         let mut sandbox = SandboxConfig::new(rootfs.clone(), &spec.env_id, &env_dir);
-        sandbox.isolate_network = spec.offline || spec.manifest.network_isolation;
+        sandbox.network = build_network_mode(spec);
+        sandbox.dns_servers.clone_from(&spec.manifest.dns_servers);
+        sandbox.extra_hosts.clone_from(&spec.manifest.extra_hosts);
+        sandbox.max_overlay_mb = spec.manifest.max_overlay_mb;
 
+        progress.phase(BuildPhase::Unpack);
+        progress.message("assembling root filesystem...");
         mount_overlay(&sandbox)?;
         setup_container_rootfs(&sandbox)?;
 
@@ -270,22 +382,20 @@ impl RuntimeBackend for OciBackend {
                     "offline mode: cannot install system packages".to_owned(),
                 ));
             }
-            let pkg_mgr = detect_package_manager(&sandbox.overlay_merged)
-                .or_else(|| detect_package_manager(&rootfs))
-                .ok_or_else(|| {
-                    RuntimeError::ExecFailed(
-                        "no supported package manager found in the image".to_owned(),
-                    )
-                })?;
-
-            progress(&format!(
-                "installing {} packages via {pkg_mgr}...",
-                spec.manifest.system_packages.len()
+            let pm = select_package_manager(&resolved, &sandbox.overlay_merged, &rootfs)?;
+
+            progress.phase(BuildPhase::InstallPackages);
+            progress.message(&format!(
+                "installing {} packages via {}...",
+                spec.manifest.system_packages.len(),
+                pm.name()
             ));
 
-            let install_cmd = install_packages_command(pkg_mgr, &spec.manifest.system_packages);
-            install_packages_in_container(&sandbox, &install_cmd)?;
-            progress("packages installed");
+            install_packages_in_container(
+                &sandbox,
+                &pm.install_command(&spec.manifest.system_packages),
+            )?;
+            progress.message("packages installed");
         }
 
         unmount_overlay(&sandbox)?;
@@ -303,7 +413,7 @@ impl RuntimeBackend for OciBackend {
 
         std::fs::write(env_dir.join(".built"), "1")?;
 
-        progress(&format!(
+        progress.message(&format!(
             "environment {} built (OCI, {} base)",
             &spec.env_id[..12.min(spec.env_id.len())],
             resolved.display_name
@@ -330,9 +440,15 @@ impl RuntimeBackend for OciBackend {
         let rootfs = image_cache.rootfs_path(&resolved.cache_key);
 
         let mut sandbox = SandboxConfig::new(rootfs, &spec.env_id, &env_dir);
-        sandbox.isolate_network = spec.offline || spec.manifest.network_isolation;
-
-        let host = compute_host_integration(&spec.manifest);
+        sandbox.network = session_network_mode(spec);
+        sandbox.forward_ports = session_forward_ports(spec);
+        sandbox.dns_servers.clone_from(&spec.manifest.dns_servers);
+        sandbox.extra_hosts.clone_from(&spec.manifest.extra_hosts);
+        sandbox.read_only = spec.read_only;
+        sandbox.max_overlay_mb = spec.manifest.max_overlay_mb;
+        sandbox.init = session_init(spec);
+
+        let host = compute_host_integration(&spec.manifest)?;
         sandbox.bind_mounts.extend(host.bind_mounts);
         sandbox.env_vars.extend(host.env_vars);
 
@@ -349,12 +465,13 @@ impl RuntimeBackend for OciBackend {
             std::os::unix::fs::symlink(&sandbox.overlay_merged, &bundle_rootfs)?;
         }
 
-        let oci_config = Self::generate_oci_spec(&sandbox, spec);
-        std::fs::write(bundle_dir.join("config.json"), &oci_config)?;
-
         let container_id = format!("karapace-{}", &spec.env_id[..12.min(spec.env_id.len())]);
+        let rootless = Rootless::detect(&sandbox, &container_id);
+        let oci_config = Self::generate_oci_spec(&sandbox, rootless.as_ref());
+        std::fs::write(bundle_dir.join("config.json"), &oci_config)?;
 
         std::fs::write(env_dir.join(".running"), format!("{}", std::process::id()))?;
+        write_init_marker(&env_dir, sandbox.init.is_some())?;
 
         terminal::emit_container_push(&spec.env_id, &sandbox.hostname);
         terminal::print_container_banner(
@@ -363,22 +480,12 @@ impl RuntimeBackend for OciBackend {
             &sandbox.hostname,
         );
 
-        let status = Command::new(&runtime)
-            .args([
-                "run",
-                "--bundle",
-                &bundle_dir.to_string_lossy(),
-                &container_id,
-            ])
-            .stdin(std::process::Stdio::inherit())
-            .stdout(std::process::Stdio::inherit())
-            .stderr(std::process::Stdio::inherit())
-            .status()
-            .map_err(|e| RuntimeError::ExecFailed(format!("{runtime} run failed: {e}")))?;
+        let status = Self::run_bundle(&runtime, &bundle_dir, &container_id, &sandbox);
 
         terminal::emit_container_pop();
         terminal::print_container_exit(&spec.env_id);
         let _ = std::fs::remove_file(env_dir.join(".running"));
+        let _ = std::fs::remove_file(env_dir.join(INIT_MARKER));
         let _ = unmount_overlay(&sandbox);
 
         // Clean up OCI container state
@@ -387,6 +494,7 @@ impl RuntimeBackend for OciBackend {
             .stdout(std::process::Stdio::null())
             .stderr(std::process::Stdio::null())
             .status();
+        let status = status?;
 
         if status.success() {
             Ok(())
@@ -403,34 +511,24 @@ impl RuntimeBackend for OciBackend {
         spec: &RuntimeSpec,
         command: &[String],
     ) -> Result<std::process::Output, RuntimeError> {
-        let env_dir = self.env_dir(&spec.env_id);
-        if !env_dir.join(".built").exists() {
-            return Err(RuntimeError::ExecFailed(format!(
-                "environment {} has not been built yet",
-                &spec.env_id[..12.min(spec.env_id.len())]
-            )));
-        }
-
-        let resolved = resolve_image(&spec.manifest.base_image)?;
-        let image_cache = ImageCache::new(&self.store_root);
-        let rootfs = image_cache.rootfs_path(&resolved.cache_key);
-
-        let mut sandbox = SandboxConfig::new(rootfs, &spec.env_id, &env_dir);
-        sandbox.isolate_network = spec.offline || spec.manifest.network_isolation;
-
-        let host = compute_host_integration(&spec.manifest);
-        sandbox.bind_mounts.extend(host.bind_mounts);
-        sandbox.env_vars.extend(host.env_vars);
-
-        mount_overlay(&sandbox)?;
-        setup_container_rootfs(&sandbox)?;
-
+        let sandbox = self.exec_sandbox(spec)?;
         let output = exec_in_container(&sandbox, command);
         let _ = unmount_overlay(&sandbox);
-
         output
     }
 
+    fn exec_attached(
+        &self,
+        spec: &RuntimeSpec,
+        command: &[String],
+        tty: bool,
+    ) -> Result<ExitStatus, RuntimeError> {
+        let sandbox = self.exec_sandbox(spec)?;
+        let status = exec_attached_in_container(&sandbox, command, tty);
+        let _ = unmount_overlay(&sandbox);
+        status
+    }
+
     fn destroy(&self, spec: &RuntimeSpec) -> Result<(), RuntimeError> {
         let env_dir = self.env_dir(&spec.env_id);
         let sandbox = SandboxConfig::new(PathBuf::from("/nonexistent"), &spec.env_id, &env_dir);
@@ -450,6 +548,17 @@ impl RuntimeBackend for OciBackend {
         Ok(())
     }
 
+    fn processes(&self, env_id: &str) -> Result<Vec<ProcessInfo>, RuntimeError> {
+        if !self.status(env_id)?.running {
+            return Ok(Vec::new());
+        }
+        let runtime = Self::find_runtime().ok_or_else(|| {
+            RuntimeError::BackendUnavailable("no OCI runtime found (crun/runc/youki)".to_owned())
+        })?;
+        let container_id = format!("karapace-{}", &env_id[..12.min(env_id.len())]);
+        runtime_processes(&runtime, &container_id)
+    }
+
     fn status(&self, env_id: &str) -> Result<RuntimeStatus, RuntimeError> {
         let runtime = Self::find_runtime().ok_or_else(|| {
             RuntimeError::BackendUnavailable("no OCI runtime found (crun/runc/youki)".to_owned())
@@ -471,6 +580,7 @@ impl RuntimeBackend for OciBackend {
                     env_id: env_id.to_owned(),
                     running: false,
                     pid: None,
+                    init: false,
                 });
             }
             return Err(RuntimeError::ExecFailed(format!(
@@ -492,11 +602,50 @@ impl RuntimeBackend for OciBackend {
         Ok(RuntimeStatus {
             env_id: env_id.to_owned(),
             running: pid.is_some(),
+            init: pid.is_some() && self.env_dir(env_id).join(INIT_MARKER).exists(),
             pid,
         })
     }
 }
 
+/// Pid of the container's init, once the OCI runtime has written it to
+/// `pid_file`.
+fn id_mappings_json(mappings: &[IdMapping]) -> String {
+    mappings
+        .iter()
+        .map(|m| {
+            format!(
+                r#"{{ "containerID": {}, "hostID": {}, "size": {} }}"#,
+                m.container_id, m.host_id, m.size
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn wait_for_pid_file(pid_file: &Path, child: &mut Child) -> Result<u32, RuntimeError> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(pid) = std::fs::read_to_string(pid_file)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+        {
+            return Ok(pid);
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(RuntimeError::ExecFailed(format!(
+                "OCI runtime exited before the container started ({status})"
+            )));
+        }
+        if Instant::now() > deadline {
+            return Err(RuntimeError::ExecFailed(
+                "OCI runtime did not report the container pid".to_owned(),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
 fn default_store_root() -> PathBuf {
     if let Ok(home) = std::env::var("HOME") {
         PathBuf::from(home).join(".local/share/karapace")
@@ -508,6 +657,7 @@ fn default_store_root() -> PathBuf {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sandbox::BindMount;
 
     #[test]
     fn oci_env_dir_layout() {
@@ -573,6 +723,203 @@ exit 1\n",
         assert!(!status.running);
     }
 
+    #[test]
+    fn oci_spec_starts_shell_under_init() {
+        use crate::init::INIT_ARG;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(dir.path().join("rootfs"), "oci-test", dir.path());
+
+        let plain: serde_json::Value =
+            serde_json::from_str(&OciBackend::generate_oci_spec(&config, None)).unwrap();
+        assert_eq!(
+            plain["process"]["args"],
+            serde_json::json!(["/bin/bash", "-l"])
+        );
+
+        config.init = Some(PathBuf::from("/usr/bin/karapace"));
+        let wrapped: serde_json::Value =
+            serde_json::from_str(&OciBackend::generate_oci_spec(&config, None)).unwrap();
+        assert_eq!(
+            wrapped["process"]["args"],
+            serde_json::json!([CONTAINER_INIT_PATH, INIT_ARG, "--", "/bin/bash", "-l"])
+        );
+        let mounts = wrapped["mounts"].as_array().unwrap();
+        assert!(
+            mounts
+                .iter()
+                .any(|m| m["destination"] == CONTAINER_INIT_PATH
+                    && m["source"] == "/usr/bin/karapace")
+        );
+    }
+
+    #[test]
+    fn oci_spec_network_namespace_follows_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(dir.path().join("rootfs"), "oci-test", dir.path());
+        let namespaces = |config: &SandboxConfig| {
+            let spec: serde_json::Value =
+                serde_json::from_str(&OciBackend::generate_oci_spec(config, None)).unwrap();
+            let has_net = spec["linux"]["namespaces"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|ns| ns["type"] == "network");
+            let resolv = spec["mounts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|m| m["destination"] == "/etc/resolv.conf")
+                .unwrap()["source"]
+                .clone();
+            (has_net, resolv)
+        };
+        assert_eq!(namespaces(&config), (false, "/etc/resolv.conf".into()));
+        config.network = NetworkMode::Isolated;
+        assert_eq!(namespaces(&config), (true, "/etc/resolv.conf".into()));
+        config.network = NetworkMode::Slirp;
+        let resolv = dir.path().join("net/resolv.conf");
+        assert_eq!(
+            namespaces(&config),
+            (true, resolv.to_string_lossy().as_ref().into())
+        );
+    }
+
+    #[test]
+    fn oci_spec_binds_extra_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(dir.path().join("rootfs"), "oci-test", dir.path());
+        let hosts_mount = |config: &SandboxConfig| {
+            let spec: serde_json::Value =
+                serde_json::from_str(&OciBackend::generate_oci_spec(config, None)).unwrap();
+            spec["mounts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|m| m["destination"] == "/etc/hosts")
+                .map(|m| m["source"].clone())
+        };
+        assert!(hosts_mount(&config).is_none());
+        config.extra_hosts = vec![karapace_schema::ExtraHost::parse("db.local:10.0.0.5").unwrap()];
+        let hosts = dir.path().join("net/hosts");
+        assert_eq!(
+            hosts_mount(&config),
+            Some(hosts.to_string_lossy().as_ref().into())
+        );
+    }
+
+    #[test]
+    fn rootless_oci_spec_maps_ids_in_a_user_namespace() {
+        use crate::rootless::{id_mappings, SubidRange};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(dir.path().join("rootfs"), "oci-test", dir.path());
+        config.uid = 1000;
+        config.gid = 1000;
+        let ranges = [SubidRange {
+            start: 100_000,
+            count: 65536,
+        }];
+        let rootless = Rootless {
+            uid_mappings: id_mappings(1000, &ranges),
+            gid_mappings: id_mappings(1000, &[]),
+            cgroups_path: Some("/user.slice/user-1000.slice/karapace-oci-test".to_owned()),
+        };
+        let spec = |config: &SandboxConfig, rootless: Option<&Rootless>| {
+            serde_json::from_str::<serde_json::Value>(&OciBackend::generate_oci_spec(
+                config, rootless,
+            ))
+            .unwrap()
+        };
+        let sys_type = |spec: &serde_json::Value| {
+            spec["mounts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|m| m["destination"] == "/sys")
+                .unwrap()["type"]
+                .clone()
+        };
+
+        let rootful = spec(&config, None);
+        assert!(rootful["linux"].get("uidMappings").is_none());
+        assert!(!rootful["linux"]["namespaces"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|ns| ns["type"] == "user"));
+
+        let unprivileged = spec(&config, Some(&rootless));
+        let linux = &unprivileged["linux"];
+        assert!(linux["namespaces"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|ns| ns["type"] == "user"));
+        assert_eq!(
+            linux["uidMappings"],
+            serde_json::json!([
+                {"containerID": 1000, "hostID": 1000, "size": 1},
+                {"containerID": 0, "hostID": 100_000, "size": 1000},
+                {"containerID": 1001, "hostID": 101_000, "size": 64536},
+            ])
+        );
+        assert_eq!(
+            linux["gidMappings"],
+            serde_json::json!([{"containerID": 1000, "hostID": 1000, "size": 1}])
+        );
+        assert_eq!(
+            linux["cgroupsPath"],
+            "/user.slice/user-1000.slice/karapace-oci-test"
+        );
+        assert_eq!(unprivileged["process"]["user"]["uid"], 1000);
+        assert_eq!(sys_type(&unprivileged), "bind");
+
+        config.network = NetworkMode::Isolated;
+        assert_eq!(sys_type(&spec(&config, Some(&rootless))), "sysfs");
+    }
+
+    #[test]
+    fn oci_spec_applies_bind_mount_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(dir.path().join("rootfs"), "oci-test", dir.path());
+        config.bind_mounts = vec![
+            BindMount {
+                source: PathBuf::from("/srv/data"),
+                target: PathBuf::from("/data"),
+                read_only: true,
+                options: vec![karapace_schema::MountOption::Nodev],
+                pinned: None,
+            },
+            BindMount {
+                source: PathBuf::from("/tmp/scratch"),
+                target: PathBuf::from("/scratch"),
+                read_only: false,
+                options: vec![karapace_schema::MountOption::Noexec],
+                pinned: None,
+            },
+        ];
+        let spec: serde_json::Value =
+            serde_json::from_str(&OciBackend::generate_oci_spec(&config, None)).unwrap();
+        let options = |destination: &str| {
+            spec["mounts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|m| m["destination"] == destination)
+                .map(|m| m["options"].clone())
+                .unwrap()
+        };
+        assert_eq!(
+            options("/data"),
+            serde_json::json!(["rbind", "ro", "nodev"])
+        );
+        assert_eq!(
+            options("/scratch"),
+            serde_json::json!(["rbind", "rw", "noexec"])
+        );
+    }
+
     #[test]
     fn oci_availability_check() {
         let backend = OciBackend::new();
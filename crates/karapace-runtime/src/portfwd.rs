@@ -0,0 +1,266 @@
+//! Session networking through slirp4netns.
+//!
+//! Sessions in the `slirp` [`NetworkMode`] get a network namespace of their
+//! own, which `slirp4netns` connects to the host: it provides outbound access
+//! (and a DNS forwarder at [`SLIRP_DNS`]), and each of `[network]
+//! forward_ports` is added through its API socket. Host ports listen on
+//! `127.0.0.1` only. A `host` session with forwarded ports runs as `slirp`.
+
+use crate::backend::RuntimeSpec;
+use crate::sandbox::SandboxConfig;
+use crate::RuntimeError;
+use karapace_schema::{NetworkMode, PortForward};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Address of the DNS forwarder inside a slirp4netns network.
+pub const SLIRP_DNS: &str = "10.0.2.3";
+
+/// Interface slirp4netns creates inside the namespace.
+pub const SLIRP_DEVICE: &str = "tap0";
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A running slirp4netns, stopped on drop.
+#[derive(Debug)]
+pub struct PortForwarder {
+    child: Child,
+    socket: PathBuf,
+}
+
+impl PortForwarder {
+    /// Connect the network namespace of `pid` to the host and add `forwards`.
+    /// The API socket is created in `net_dir`.
+    pub fn start(pid: u32, forwards: &[PortForward], net_dir: &Path) -> Result<Self, RuntimeError> {
+        wait_for_netns(pid)?;
+        std::fs::create_dir_all(net_dir)?;
+        let socket = net_dir.join(format!("slirp-{pid}.sock"));
+        let _ = std::fs::remove_file(&socket);
+
+        let child = Command::new("slirp4netns")
+            .args(["--configure", "--mtu=65520", "--disable-host-loopback"])
+            .arg("--api-socket")
+            .arg(&socket)
+            .arg(pid.to_string())
+            .arg(SLIRP_DEVICE)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| {
+                RuntimeError::ExecFailed(format!("port forwarding needs slirp4netns: {e}"))
+            })?;
+        let mut forwarder = Self { child, socket };
+
+        forwarder.wait_for_socket()?;
+        for fwd in forwards {
+            forwarder.add(*fwd)?;
+        }
+        Ok(forwarder)
+    }
+
+    /// Leave slirp4netns running after this handle is gone, for a session
+    /// that outlives karapace, and return its PID and API socket.
+    pub fn detach(self) -> (u32, PathBuf) {
+        let this = std::mem::ManuallyDrop::new(self);
+        (this.child.id(), this.socket.clone())
+    }
+
+    fn wait_for_socket(&mut self) -> Result<(), RuntimeError> {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while !self.socket.exists() {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                return Err(RuntimeError::ExecFailed(format!(
+                    "slirp4netns exited during startup ({status})"
+                )));
+            }
+            if Instant::now() > deadline {
+                return Err(RuntimeError::ExecFailed(
+                    "slirp4netns did not open its API socket".to_owned(),
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    fn add(&self, fwd: PortForward) -> Result<(), RuntimeError> {
+        let mut stream = UnixStream::connect(&self.socket)?;
+        stream.write_all(hostfwd_request(fwd).as_bytes())?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply)?;
+        parse_reply(&reply)
+            .map_err(|e| RuntimeError::ExecFailed(format!("cannot forward host port {fwd}: {e}")))
+    }
+}
+
+impl Drop for PortForwarder {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+/// Ports to forward for a session of `spec`. Offline sessions have no
+/// network to forward from.
+pub fn session_forward_ports(spec: &RuntimeSpec) -> Vec<PortForward> {
+    if spec.offline {
+        Vec::new()
+    } else {
+        spec.manifest.forward_ports.clone()
+    }
+}
+
+/// Network mode of a session of `spec`. Offline sessions lose outbound
+/// access, and forwarding ports needs a namespace of the session's own.
+pub fn session_network_mode(spec: &RuntimeSpec) -> NetworkMode {
+    match spec.manifest.network_mode {
+        NetworkMode::None => NetworkMode::None,
+        _ if spec.offline => NetworkMode::Isolated,
+        NetworkMode::Host if !spec.manifest.forward_ports.is_empty() => NetworkMode::Slirp,
+        mode => mode,
+    }
+}
+
+/// Network mode of builds of `spec`. Nothing is forwarded into builds, so a
+/// `host` manifest keeps the host's network even when it forwards ports.
+pub fn build_network_mode(spec: &RuntimeSpec) -> NetworkMode {
+    match spec.manifest.network_mode {
+        NetworkMode::Host if !spec.offline => NetworkMode::Host,
+        _ => session_network_mode(spec),
+    }
+}
+
+/// Connect a `slirp` session spawned from `config` and add its forwards.
+/// Kills `child` when that fails, since the session would otherwise run
+/// without the network it declared.
+pub fn forward_session_ports(
+    config: &SandboxConfig,
+    child: &mut Child,
+) -> Result<Option<PortForwarder>, RuntimeError> {
+    if !config.uses_slirp() {
+        return Ok(None);
+    }
+    match PortForwarder::start(child.id(), &config.forward_ports, &config.net_dir) {
+        Ok(forwarder) => Ok(Some(forwarder)),
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(e)
+        }
+    }
+}
+
+/// Wait until `pid` has unshared its network namespace and has a user
+/// mapping, so slirp4netns joins the session's namespaces and not ours.
+fn wait_for_netns(pid: u32) -> Result<(), RuntimeError> {
+    let own = std::fs::read_link("/proc/self/ns/net")?;
+    let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    loop {
+        let netns = std::fs::read_link(proc_dir.join("ns/net"))?;
+        let mapped = std::fs::read_to_string(proc_dir.join("uid_map"))
+            .is_ok_and(|map| !map.trim().is_empty());
+        if netns != own && mapped {
+            return Ok(());
+        }
+        if Instant::now() > deadline {
+            return Err(RuntimeError::ExecFailed(format!(
+                "process {pid} did not enter its own network namespace"
+            )));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn hostfwd_request(fwd: PortForward) -> String {
+    json!({
+        "execute": "add_hostfwd",
+        "arguments": {
+            "proto": fwd.protocol.as_str(),
+            "host_addr": "127.0.0.1",
+            "host_port": fwd.host_port,
+            "guest_port": fwd.container_port,
+        }
+    })
+    .to_string()
+}
+
+fn parse_reply(reply: &str) -> Result<(), String> {
+    let value: serde_json::Value = serde_json::from_str(reply.trim())
+        .map_err(|e| format!("bad reply from slirp4netns: {e}"))?;
+    match value.get("error") {
+        Some(error) => Err(error
+            .get("desc")
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or("unknown error")
+            .to_owned()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostfwd_request_binds_loopback() {
+        let fwd = PortForward::parse("8080:80").unwrap();
+        let request: serde_json::Value = serde_json::from_str(&hostfwd_request(fwd)).unwrap();
+        assert_eq!(request["execute"], "add_hostfwd");
+        assert_eq!(request["arguments"]["proto"], "tcp");
+        assert_eq!(request["arguments"]["host_addr"], "127.0.0.1");
+        assert_eq!(request["arguments"]["host_port"], 8080);
+        assert_eq!(request["arguments"]["guest_port"], 80);
+
+        let udp = PortForward::parse("53/udp").unwrap();
+        assert!(hostfwd_request(udp).contains(r#""proto":"udp""#));
+    }
+
+    #[test]
+    fn replies_surface_slirp_errors() {
+        assert!(parse_reply(r#"{"return": {"id": 1}}"#).is_ok());
+        assert_eq!(
+            parse_reply(r#"{"error": {"desc": "bad request: add_hostfwd: bind failed"}}"#),
+            Err("bad request: add_hostfwd: bind failed".to_owned())
+        );
+        assert!(parse_reply("garbage").is_err());
+    }
+
+    #[test]
+    fn session_network_mode_follows_offline_and_forwards() {
+        let spec = |network: &str, offline: bool| RuntimeSpec {
+            env_id: "net-test".to_owned(),
+            root_path: String::new(),
+            overlay_path: String::new(),
+            store_root: String::new(),
+            manifest: karapace_schema::parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[network]\n{network}"
+            ))
+            .unwrap()
+            .normalize()
+            .unwrap(),
+            offline,
+            read_only: false,
+        };
+        let mode = |network: &str, offline: bool| session_network_mode(&spec(network, offline));
+        assert_eq!(mode("", false), NetworkMode::Host);
+        assert_eq!(mode("forward_ports = [\"80\"]", false), NetworkMode::Slirp);
+        assert_eq!(mode("mode = \"slirp\"", false), NetworkMode::Slirp);
+        assert_eq!(mode("mode = \"slirp\"", true), NetworkMode::Isolated);
+        assert_eq!(mode("", true), NetworkMode::Isolated);
+        assert_eq!(mode("mode = \"none\"", true), NetworkMode::None);
+
+        let build = |network: &str| build_network_mode(&spec(network, false));
+        assert_eq!(build("forward_ports = [\"80\"]"), NetworkMode::Host);
+        assert_eq!(build("mode = \"slirp\""), NetworkMode::Slirp);
+        assert_eq!(build_network_mode(&spec("", true)), NetworkMode::Isolated);
+    }
+}
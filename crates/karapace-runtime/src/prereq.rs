@@ -1,4 +1,7 @@
+use crate::rootless;
+use crate::sandbox::{current_euid, current_uid};
 use std::fmt;
+use std::path::Path;
 use std::process::Command;
 
 /// A missing prerequisite with actionable install instructions.
@@ -19,7 +22,7 @@ impl fmt::Display for MissingPrereq {
     }
 }
 
-fn command_exists(name: &str) -> bool {
+pub(crate) fn command_exists(name: &str) -> bool {
     Command::new("which")
         .arg(name)
         .output()
@@ -63,19 +66,11 @@ pub fn check_namespace_prereqs() -> Vec<MissingPrereq> {
         });
     }
 
-    if !command_exists("curl") {
-        missing.push(MissingPrereq {
-            name: "curl",
-            purpose: "downloading container images",
-            install_hint:
-                "zypper install curl | apt install curl | dnf install curl | pacman -S curl",
-        });
-    }
-
     missing
 }
 
-/// Check prerequisites for the OCI backend.
+/// Check prerequisites for the OCI backend. Without root these include
+/// what rootless containers need, see [`crate::rootless`].
 pub fn check_oci_prereqs() -> Vec<MissingPrereq> {
     let mut missing = Vec::new();
 
@@ -89,18 +84,78 @@ pub fn check_oci_prereqs() -> Vec<MissingPrereq> {
         });
     }
 
-    if !command_exists("curl") {
+    if current_euid() != 0 {
+        missing.extend(check_rootless_prereqs());
+    }
+
+    missing
+}
+
+fn check_rootless_prereqs() -> Vec<MissingPrereq> {
+    let mut missing = Vec::new();
+
+    if !command_exists("unshare") || !user_namespaces_work() {
         missing.push(MissingPrereq {
-            name: "curl",
-            purpose: "downloading container images",
+            name: "user namespaces",
+            purpose: "rootless OCI containers",
             install_hint:
-                "zypper install curl | apt install curl | dnf install curl | pacman -S curl",
+                "enable CONFIG_USER_NS=y in kernel, or: sysctl kernel.unprivileged_userns_clone=1",
+        });
+    }
+
+    // Subordinate ids are optional: without them only the user's own id is
+    // mapped. With them, the setuid helpers write the mappings.
+    let user = std::env::var("USER").unwrap_or_default();
+    let has_subids =
+        !rootless::subid_ranges(Path::new(rootless::SUBUID_FILE), &user, current_uid()).is_empty();
+    if has_subids && !(command_exists("newuidmap") && command_exists("newgidmap")) {
+        missing.push(MissingPrereq {
+            name: "newuidmap and newgidmap",
+            purpose: "mapping the subordinate ids of /etc/subuid and /etc/subgid",
+            install_hint: "zypper install shadow | apt install uidmap | dnf install shadow-utils | pacman -S shadow",
         });
     }
 
     missing
 }
 
+/// Check prerequisites for the podman backend.
+pub fn check_podman_prereqs() -> Vec<MissingPrereq> {
+    let mut missing = Vec::new();
+
+    if !command_exists("podman") && !command_exists("crun") {
+        missing.push(MissingPrereq {
+            name: "podman or crun",
+            purpose: "OCI container execution",
+            install_hint: "zypper install podman | apt install podman | dnf install podman | pacman -S podman",
+        });
+    }
+
+    if !command_exists("fuse-overlayfs") {
+        missing.push(MissingPrereq {
+            name: "fuse-overlayfs",
+            purpose: "overlay filesystem for writable container layers",
+            install_hint: "zypper install fuse-overlayfs | apt install fuse-overlayfs | dnf install fuse-overlayfs | pacman -S fuse-overlayfs",
+        });
+    }
+
+    missing
+}
+
+/// Check that the namespace backend can enforce `cpu_shares` and
+/// `memory_limit_mb`. Unlike the other checks this is optional: without a
+/// delegated cgroup hierarchy sessions run without those limits.
+pub fn check_cgroup_prereqs() -> Vec<MissingPrereq> {
+    if crate::cgroup::check_delegation().is_ok() {
+        return Vec::new();
+    }
+    vec![MissingPrereq {
+        name: "delegated cgroup v2",
+        purpose: "enforcing cpu_shares and memory_limit_mb",
+        install_hint: "use the unified cgroup hierarchy and run karapace in a systemd user session, which delegates the cpu and memory controllers",
+    }]
+}
+
 /// Format a list of missing prerequisites into a user-friendly error message.
 pub fn format_missing(missing: &[MissingPrereq]) -> String {
     use std::fmt::Write as _;
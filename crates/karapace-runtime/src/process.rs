@@ -0,0 +1,267 @@
+//! Processes of running environments, read from `/proc`.
+//!
+//! `karapace ps` lists what runs inside an environment. The namespace
+//! backend finds the processes sharing the session's PID namespace; the OCI
+//! and podman backends ask their runtime for the container's host PIDs. The
+//! details of each PID come from `/proc` either way.
+
+use crate::RuntimeError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::process::Command;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProcessInfo {
+    /// Host PID.
+    pub pid: u32,
+    pub ppid: u32,
+    pub command: String,
+    /// User and system CPU time used so far.
+    pub cpu_secs: f64,
+    /// Average CPU usage since the process started, in percent of one core.
+    pub cpu_percent: f64,
+    /// Resident set size in KiB.
+    pub rss_kb: u64,
+}
+
+/// Fields of `/proc/<pid>/stat` used for [`ProcessInfo`].
+#[derive(Debug, PartialEq, Eq)]
+struct Stat<'a> {
+    name: &'a str,
+    ppid: u32,
+    /// utime + stime, in clock ticks.
+    cpu_ticks: u64,
+    /// Start time after boot, in clock ticks.
+    start_ticks: u64,
+    rss_pages: u64,
+}
+
+/// Parse `/proc/<pid>/stat`. The command name may contain spaces and
+/// parentheses, so fields are counted after the last `)`.
+fn parse_stat(stat: &str) -> Option<Stat<'_>> {
+    let (head, rest) = stat.rsplit_once(')')?;
+    let (_, name) = head.split_once('(')?;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    // `fields[0]` is field 3 of proc(5), the process state.
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+    Some(Stat {
+        name,
+        ppid: u32::try_from(field(4)?).ok()?,
+        cpu_ticks: field(14)? + field(15)?,
+        start_ticks: field(22)?,
+        rss_pages: field(24)?,
+    })
+}
+
+/// Parent pid from the contents of `/proc/<pid>/stat`.
+pub(crate) fn parse_stat_ppid(stat: &str) -> Option<u32> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// `root` and all of its descendants, given `(pid, ppid)` pairs.
+pub(crate) fn process_tree(root: u32, parents: &[(u32, u32)]) -> Vec<u32> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for &(pid, ppid) in parents {
+        children.entry(ppid).or_default().push(pid);
+    }
+    let mut tree = vec![root];
+    let mut i = 0;
+    while let Some(&pid) = tree.get(i) {
+        if let Some(kids) = children.get(&pid) {
+            tree.extend(kids);
+        }
+        i += 1;
+    }
+    tree
+}
+
+/// `(pid, ppid)` of every process in `/proc`.
+pub(crate) fn all_parents() -> Vec<(u32, u32)> {
+    std::fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            Some((pid, parse_stat_ppid(&stat)?))
+        })
+        .collect()
+}
+
+/// Command line from the contents of `/proc/<pid>/cmdline`, falling back to
+/// `name` for processes without one, such as zombies.
+fn command_line(cmdline: &[u8], name: &str) -> String {
+    let args: Vec<String> = cmdline
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    if args.is_empty() {
+        format!("[{name}]")
+    } else {
+        args.join(" ")
+    }
+}
+
+#[allow(unsafe_code)]
+fn sysconf(name: libc::c_int) -> Option<u64> {
+    // SAFETY: sysconf only reads a configuration value.
+    let value = unsafe { libc::sysconf(name) };
+    u64::try_from(value).ok().filter(|&v| v > 0)
+}
+
+fn uptime_secs() -> Option<f64> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    uptime.split_whitespace().next()?.parse().ok()
+}
+
+/// Details of `pid`, or `None` once it has exited.
+pub fn read_process(pid: u32) -> Option<ProcessInfo> {
+    let dir = format!("/proc/{pid}");
+    let stat = std::fs::read_to_string(format!("{dir}/stat")).ok()?;
+    let stat = parse_stat(&stat)?;
+    let cmdline = std::fs::read(format!("{dir}/cmdline")).unwrap_or_default();
+
+    let ticks = sysconf(libc::_SC_CLK_TCK).unwrap_or(100);
+    let page_kb = sysconf(libc::_SC_PAGESIZE).unwrap_or(4096) / 1024;
+    let (cpu_secs, started) = (
+        stat.cpu_ticks as f64 / ticks as f64,
+        stat.start_ticks as f64 / ticks as f64,
+    );
+    let cpu_percent = uptime_secs().map_or(0.0, |uptime| {
+        let elapsed = uptime - started;
+        if elapsed > 0.0 {
+            100.0 * cpu_secs / elapsed
+        } else {
+            0.0
+        }
+    });
+
+    Some(ProcessInfo {
+        pid,
+        ppid: stat.ppid,
+        command: command_line(&cmdline, stat.name),
+        cpu_secs,
+        cpu_percent,
+        rss_kb: stat.rss_pages * page_kb,
+    })
+}
+
+fn pid_namespace(pid: u32) -> Option<std::path::PathBuf> {
+    std::fs::read_link(format!("/proc/{pid}/ns/pid")).ok()
+}
+
+/// Every process in the PID namespace the sandbox rooted at `root` created,
+/// including shells that joined it with `attach`. The sandbox process itself
+/// still lives in the caller's namespace and is not listed.
+pub fn namespace_processes(root: u32) -> Vec<ProcessInfo> {
+    let Some(own) = pid_namespace(std::process::id()) else {
+        return Vec::new();
+    };
+    let Some(namespace) = process_tree(root, &all_parents())
+        .into_iter()
+        .filter_map(pid_namespace)
+        .find(|ns| *ns != own)
+    else {
+        return Vec::new();
+    };
+
+    let mut pids: Vec<u32> = std::fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter(|&pid| pid_namespace(pid).as_ref() == Some(&namespace))
+        .collect();
+    pids.sort_unstable();
+    pids.into_iter().filter_map(read_process).collect()
+}
+
+/// Host PIDs from `runc ps --format json`, a JSON array of numbers.
+pub fn parse_runtime_ps(output: &[u8]) -> Option<Vec<u32>> {
+    serde_json::from_slice(output).ok()
+}
+
+/// Processes of `container_id` as listed by `<runtime> ps`.
+pub(crate) fn runtime_processes(
+    runtime: &str,
+    container_id: &str,
+) -> Result<Vec<ProcessInfo>, RuntimeError> {
+    let output = Command::new(runtime)
+        .args(["ps", "--format", "json", container_id])
+        .output()?;
+    if !output.status.success() {
+        return Err(RuntimeError::ExecFailed(format!(
+            "{runtime} ps failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let mut pids = parse_runtime_ps(&output.stdout)
+        .ok_or_else(|| RuntimeError::ExecFailed(format!("failed to parse {runtime} ps output")))?;
+    pids.sort_unstable();
+    Ok(pids.into_iter().filter_map(read_process).collect())
+}
+
+/// Host PIDs from `podman top <container> hpid`, one per line under a
+/// `HPID` header.
+pub fn parse_podman_top(output: &str) -> Vec<u32> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAT: &str = "4242 (my (weird) cmd) S 17 4242 4242 0 -1 4194560 \
+                        1053 0 0 0 25 15 0 0 20 0 1 0 8100 12734464 812 \
+                        18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 3 0 0";
+
+    #[test]
+    fn parse_stat_handles_odd_command_names() {
+        assert_eq!(parse_stat_ppid(STAT), Some(17));
+        assert_eq!(
+            parse_stat(STAT),
+            Some(Stat {
+                name: "my (weird) cmd",
+                ppid: 17,
+                cpu_ticks: 40,
+                start_ticks: 8100,
+                rss_pages: 812,
+            })
+        );
+    }
+
+    #[test]
+    fn process_tree_collects_descendants() {
+        let parents = [(2, 1), (3, 2), (4, 3), (5, 1), (6, 2)];
+        let mut tree = process_tree(2, &parents);
+        tree.sort_unstable();
+        assert_eq!(tree, [2, 3, 4, 6]);
+    }
+
+    #[test]
+    fn command_line_joins_arguments() {
+        assert_eq!(command_line(b"sleep\x00100\x00", "sleep"), "sleep 100");
+        assert_eq!(command_line(b"", "kworker"), "[kworker]");
+    }
+
+    #[test]
+    fn parse_runtime_listings() {
+        assert_eq!(parse_runtime_ps(b"[101,205]"), Some(vec![101, 205]));
+        assert_eq!(parse_runtime_ps(b"null"), None);
+        assert_eq!(parse_podman_top("HPID\n101\n205\n"), [101, 205]);
+    }
+
+    #[test]
+    fn reads_own_process() {
+        let info = read_process(std::process::id()).unwrap();
+        assert_eq!(info.pid, std::process::id());
+        assert!(info.rss_kb > 0);
+        assert!(!info.command.is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+//! Progress reporting for builds.
+//!
+//! A build spends most of its time downloading images and installing
+//! packages. Backends and the engine announce each [`BuildPhase`] and
+//! free-form status lines to a [`ProgressSink`], which front ends render
+//! however suits them.
+
+use serde::{Deserialize, Serialize};
+
+/// Stage of a build.
+///
+/// Phases normally arrive in declaration order, but resolution fetches and
+/// unpacks the base image itself when it is not cached yet, so a sink can
+/// see [`FetchImage`](Self::FetchImage) and [`Unpack`](Self::Unpack) twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildPhase {
+    /// Pinning the base image digest and package versions.
+    Resolve,
+    /// Downloading the base image.
+    FetchImage,
+    /// Extracting the image and assembling the root filesystem.
+    Unpack,
+    /// Running the image's package manager.
+    InstallPackages,
+    /// Capturing the built filesystem as the base layer.
+    PackLayer,
+}
+
+impl BuildPhase {
+    /// Stable phase name, e.g. `"fetch_image"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Resolve => "resolve",
+            Self::FetchImage => "fetch_image",
+            Self::Unpack => "unpack",
+            Self::InstallPackages => "install_packages",
+            Self::PackLayer => "pack_layer",
+        }
+    }
+
+    /// Short description for humans, e.g. `"fetching image"`.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Resolve => "resolving",
+            Self::FetchImage => "fetching image",
+            Self::Unpack => "unpacking",
+            Self::InstallPackages => "installing packages",
+            Self::PackLayer => "packing layer",
+        }
+    }
+}
+
+impl std::fmt::Display for BuildPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Receives build progress. Called on the building thread, so
+/// implementations should return quickly.
+pub trait ProgressSink: Send + Sync {
+    /// `phase` started.
+    fn phase(&self, _phase: BuildPhase) {}
+
+    /// Status line within the current phase.
+    fn message(&self, _message: &str) {}
+
+    /// `done` bytes of a download arrived, out of `total` when the server
+    /// announced a length. Reported every megabyte and once at the end.
+    fn download(&self, _done: u64, _total: Option<u64>) {}
+
+    /// Whether the build should stop. Checked between phases; a build that
+    /// sees `true` cleans up and fails as cancelled.
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Discards all progress.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// Prints status lines to stderr as `[karapace] ...`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrProgress;
+
+impl ProgressSink for StderrProgress {
+    fn message(&self, message: &str) {
+        eprintln!("[karapace] {message}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phase_names_match_serde() {
+        for phase in [
+            BuildPhase::Resolve,
+            BuildPhase::FetchImage,
+            BuildPhase::Unpack,
+            BuildPhase::InstallPackages,
+            BuildPhase::PackLayer,
+        ] {
+            let json = serde_json::to_string(&phase).unwrap();
+            assert_eq!(json, format!("\"{phase}\""));
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Pseudo-terminals for `karapace exec --tty`.
+//!
+//! The command gets the slave side as its stdin, stdout, stderr and
+//! controlling terminal. The caller's stdin is copied to the master and the
+//! master's output to the caller's stdout. When stdin is itself a terminal it
+//! is switched to raw mode for the duration, so keys such as Ctrl-C reach the
+//! command instead of karapace.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus};
+
+pub struct Pty {
+    master: File,
+    slave: File,
+}
+
+impl Pty {
+    /// Open a pseudo-terminal, sized like the caller's terminal if stdin is
+    /// one.
+    #[allow(unsafe_code)]
+    pub fn open() -> std::io::Result<Self> {
+        // SAFETY: posix_openpt only returns a new descriptor or -1.
+        let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a fresh descriptor that nothing else owns.
+        let master = unsafe { File::from_raw_fd(fd) };
+
+        let mut name = [0 as libc::c_char; 128];
+        // SAFETY: `master` is an open pty master and `name` is a writable
+        // buffer of the length passed.
+        let ret = unsafe {
+            if libc::grantpt(fd) == 0 && libc::unlockpt(fd) == 0 {
+                libc::ptsname_r(fd, name.as_mut_ptr(), name.len())
+            } else {
+                -1
+            }
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: ptsname_r succeeded, so `name` is NUL-terminated.
+        let path = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+        let slave = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(std::ffi::OsStr::from_bytes(path.to_bytes()))?;
+
+        // SAFETY: TIOCGWINSZ/TIOCSWINSZ read and write a `winsize`; failure
+        // (stdin is not a terminal) just leaves the default size.
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &raw mut size) == 0 {
+                libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &raw const size);
+            }
+        }
+
+        Ok(Self { master, slave })
+    }
+
+    /// Spawn `cmd` in a new session with the slave side as its standard
+    /// streams and controlling terminal.
+    #[allow(unsafe_code)]
+    pub fn spawn(self, mut cmd: Command) -> std::io::Result<PtySession> {
+        cmd.stdin(self.slave.try_clone()?)
+            .stdout(self.slave.try_clone()?)
+            .stderr(self.slave);
+        // SAFETY: setsid and ioctl are async-signal-safe. By the time
+        // pre_exec runs, fd 0 is the slave.
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = cmd.spawn()?;
+        // `cmd` holds the last copies of the slave; the master only sees EOF
+        // once they are closed.
+        drop(cmd);
+        Ok(PtySession {
+            master: self.master,
+            child,
+        })
+    }
+}
+
+pub struct PtySession {
+    master: File,
+    pub child: Child,
+}
+
+impl PtySession {
+    /// Relay the caller's terminal to the command until it exits.
+    pub fn wait(mut self) -> std::io::Result<ExitStatus> {
+        let _raw = RawMode::enable(libc::STDIN_FILENO);
+
+        // Never joined: it stays blocked on stdin until karapace exits.
+        let mut input = self.master.try_clone()?;
+        std::thread::spawn(move || {
+            let _ = copy_until_error(&mut std::io::stdin().lock(), &mut input);
+        });
+        let mut output = self.master.try_clone()?;
+        let relay =
+            std::thread::spawn(move || copy_until_error(&mut output, &mut std::io::stdout()));
+
+        let status = self.child.wait()?;
+        let _ = relay.join();
+        Ok(status)
+    }
+}
+
+/// Copy until either side fails. Reading the master fails with `EIO` once
+/// the last process on the slave side has exited.
+fn copy_until_error(from: &mut impl Read, to: &mut impl Write) -> std::io::Result<()> {
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = from.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        to.write_all(&buf[..n])?;
+        to.flush()?;
+    }
+}
+
+/// Raw mode on a terminal, restored on drop.
+struct RawMode {
+    fd: RawFd,
+    saved: libc::termios,
+}
+
+impl RawMode {
+    #[allow(unsafe_code)]
+    fn enable(fd: RawFd) -> Option<Self> {
+        // SAFETY: tcgetattr/tcsetattr read and write a `termios`; both fail
+        // harmlessly when `fd` is not a terminal.
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &raw mut saved) != 0 {
+                return None;
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&raw mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw const raw) != 0 {
+                return None;
+            }
+            Some(Self { fd, saved })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    #[allow(unsafe_code)]
+    fn drop(&mut self) {
+        // SAFETY: restores the attributes read in `enable`.
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &raw const self.saved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_sees_a_terminal() {
+        let Ok(pty) = Pty::open() else {
+            return; // no /dev/ptmx in this sandbox
+        };
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", "test -t 0 && test -t 1 && exit 7"]);
+        let session = pty.spawn(cmd).unwrap();
+        assert_eq!(session.wait().unwrap().code(), Some(7));
+    }
+}
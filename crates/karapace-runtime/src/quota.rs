@@ -0,0 +1,183 @@
+//! Size limit for an environment's writable upper layer.
+//!
+//! Where the store sits on a filesystem with project quotas (XFS, or ext4
+//! mounted with `prjquota`) and `xfs_quota` can administer them, the upper
+//! directory gets its own project with a hard block limit, so the kernel
+//! fails writes with `EDQUOT` once the limit is hit. Everywhere else the
+//! limit is polled: the watchdog measures the upper directory during a
+//! session and ends it when the limit is exceeded, and the engine checks it
+//! again when a build or session finishes.
+
+use crate::RuntimeError;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// How a limit is being enforced for a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaMethod {
+    /// A kernel project quota on the filesystem mounted at `mount`.
+    Project { id: u32, mount: PathBuf },
+    /// Usage is measured and compared against the limit.
+    Polled,
+}
+
+/// Bytes the files under `path` occupy on disk, without following symlinks.
+/// Hard links are counted once per link.
+pub fn dir_usage(path: &Path) -> u64 {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    let own = meta.blocks() * 512;
+    if !meta.is_dir() {
+        return own;
+    }
+    own + std::fs::read_dir(path).map_or(0, |entries| {
+        entries
+            .filter_map(Result::ok)
+            .map(|entry| dir_usage(&entry.path()))
+            .sum()
+    })
+}
+
+/// Whole megabytes, rounded up, so any use past the limit counts.
+pub fn usage_mb(bytes: u64) -> u64 {
+    bytes.div_ceil(1024 * 1024)
+}
+
+/// Fail with [`RuntimeError::QuotaExceeded`] if `upper` uses more than
+/// `limit_mb`. Returns the usage in bytes.
+pub fn check_quota(upper: &Path, limit_mb: Option<u64>) -> Result<u64, RuntimeError> {
+    let used = dir_usage(upper);
+    match limit_mb {
+        Some(limit_mb) if used > limit_mb.saturating_mul(1024 * 1024) => {
+            Err(RuntimeError::QuotaExceeded {
+                used_mb: usage_mb(used),
+                limit_mb,
+            })
+        }
+        _ => Ok(used),
+    }
+}
+
+/// Put `upper` under a project quota of `limit_mb`, falling back to
+/// [`QuotaMethod::Polled`] when the filesystem or privileges do not allow it.
+pub fn apply_quota(upper: &Path, limit_mb: u64) -> QuotaMethod {
+    let Some(mount) = mount_point(upper) else {
+        return QuotaMethod::Polled;
+    };
+    let id = project_id(upper);
+    let output = Command::new("xfs_quota")
+        .arg("-x")
+        .arg("-c")
+        .arg(format!("project -s -p {} {id}", upper.display()))
+        .arg("-c")
+        .arg(format!("limit -p bhard={limit_mb}m {id}"))
+        .arg(&mount)
+        .output();
+    match output {
+        Ok(out) if out.status.success() && out.stderr.is_empty() => {
+            tracing::debug!("project quota {id} of {limit_mb} MB on {}", upper.display());
+            QuotaMethod::Project { id, mount }
+        }
+        Ok(out) => {
+            tracing::debug!(
+                "project quota unavailable for {}: {}",
+                upper.display(),
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            QuotaMethod::Polled
+        }
+        Err(e) => {
+            tracing::debug!("xfs_quota not usable: {e}");
+            QuotaMethod::Polled
+        }
+    }
+}
+
+/// Project id for a directory: stable per path, never 0 (the default
+/// project every file starts in).
+fn project_id(dir: &Path) -> u32 {
+    let hash = blake3::hash(dir.as_os_str().as_encoded_bytes());
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&hash.as_bytes()[..4]);
+    (u32::from_le_bytes(bytes) & 0x7fff_ffff).max(1)
+}
+
+/// Mount point of the filesystem holding `path`, from `/proc/self/mountinfo`.
+fn mount_point(path: &Path) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+    longest_mount_prefix(&mountinfo, &path)
+}
+
+fn longest_mount_prefix(mountinfo: &str, path: &Path) -> Option<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(4))
+        .map(|mount| PathBuf::from(mount.replace("\\040", " ")))
+        .filter(|mount| path.starts_with(mount))
+        .max_by_key(|mount| mount.as_os_str().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_counts_file_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir_usage(dir.path());
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::fs::write(dir.path().join("a/b/data"), vec![1u8; 3 * 1024 * 1024]).unwrap();
+        let used = dir_usage(dir.path());
+        assert!(used >= empty + 3 * 1024 * 1024);
+        assert_eq!(usage_mb(1), 1);
+        assert_eq!(usage_mb(1024 * 1024), 1);
+    }
+
+    #[test]
+    fn check_quota_reports_overage() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("data"), vec![1u8; 2 * 1024 * 1024]).unwrap();
+        assert!(check_quota(dir.path(), None).is_ok());
+        assert!(check_quota(dir.path(), Some(8)).is_ok());
+        match check_quota(dir.path(), Some(1)) {
+            Err(RuntimeError::QuotaExceeded { used_mb, limit_mb }) => {
+                assert!(used_mb >= 2);
+                assert_eq!(limit_mb, 1);
+            }
+            other => panic!("expected QuotaExceeded, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn project_ids_are_stable_and_nonzero() {
+        let a = project_id(Path::new("/store/env/a/upper"));
+        assert_eq!(a, project_id(Path::new("/store/env/a/upper")));
+        assert_ne!(a, project_id(Path::new("/store/env/b/upper")));
+        assert_ne!(a, 0);
+        assert!(a <= 0x7fff_ffff);
+    }
+
+    #[test]
+    fn mount_point_is_longest_prefix() {
+        let mountinfo = "\
+22 1 8:1 / / rw - ext4 /dev/sda1 rw
+30 22 8:2 / /home rw - xfs /dev/sda2 rw,prjquota
+31 30 8:3 / /home/my\\040disk rw - xfs /dev/sda3 rw
+";
+        assert_eq!(
+            longest_mount_prefix(mountinfo, Path::new("/home/u/store/env/x/upper")),
+            Some(PathBuf::from("/home"))
+        );
+        assert_eq!(
+            longest_mount_prefix(mountinfo, Path::new("/home/my disk/x")),
+            Some(PathBuf::from("/home/my disk"))
+        );
+        assert_eq!(
+            longest_mount_prefix(mountinfo, Path::new("/var/lib")),
+            Some(PathBuf::from("/"))
+        );
+    }
+}
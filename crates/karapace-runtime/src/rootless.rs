@@ -0,0 +1,183 @@
+//! Rootless OCI containers.
+//!
+//! Run by an unprivileged user, the OCI runtime needs the container in a
+//! user namespace of its own. The user's id is mapped to itself, so files in
+//! the home directory keep their owner, and the other container ids, root
+//! included, are taken from the user's subordinate ranges in `/etc/subuid`
+//! and `/etc/subgid`. Mapping more than the user's own id goes through the
+//! setuid `newuidmap` and `newgidmap` helpers; without subordinate ranges
+//! only the user's id is mapped.
+//!
+//! The container's cgroup is created under the cgroup delegated to the
+//! user, as [`crate::cgroup`] finds it, since the runtime cannot write
+//! anywhere else in the hierarchy.
+
+use crate::sandbox::{current_euid, SandboxConfig};
+use std::path::Path;
+
+pub const SUBUID_FILE: &str = "/etc/subuid";
+pub const SUBGID_FILE: &str = "/etc/subgid";
+
+/// A range of host ids subordinate to a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubidRange {
+    pub start: u32,
+    pub count: u32,
+}
+
+/// One entry of an OCI `uidMappings` or `gidMappings` list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdMapping {
+    pub container_id: u32,
+    pub host_id: u32,
+    pub size: u32,
+}
+
+/// The user namespace and cgroup of a container started without root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rootless {
+    pub uid_mappings: Vec<IdMapping>,
+    pub gid_mappings: Vec<IdMapping>,
+    /// `cgroupsPath` of the container, when a delegated cgroup was found.
+    pub cgroups_path: Option<String>,
+}
+
+impl Rootless {
+    /// The rootless setup of a container named `container_id` running
+    /// `config`, or `None` when karapace runs as root.
+    pub fn detect(config: &SandboxConfig, container_id: &str) -> Option<Self> {
+        if current_euid() == 0 {
+            return None;
+        }
+        let uid_ranges = subid_ranges(Path::new(SUBUID_FILE), &config.username, config.uid);
+        let gid_ranges = subid_ranges(Path::new(SUBGID_FILE), &config.username, config.gid);
+        Some(Self {
+            uid_mappings: id_mappings(config.uid, &uid_ranges),
+            gid_mappings: id_mappings(config.gid, &gid_ranges),
+            cgroups_path: crate::cgroup::delegated_cgroup()
+                .map(|parent| format!("{}/{container_id}", parent.trim_end_matches('/'))),
+        })
+    }
+}
+
+/// The ranges `file` subordinates to `user`, whose id is `id`. A missing or
+/// unreadable file has none.
+pub fn subid_ranges(file: &Path, user: &str, id: u32) -> Vec<SubidRange> {
+    std::fs::read_to_string(file)
+        .map(|content| parse_subid(&content, user, id))
+        .unwrap_or_default()
+}
+
+/// Parse `/etc/subuid` or `/etc/subgid` content, keeping the
+/// `owner:start:count` lines whose owner is `user` by name or `id`.
+pub fn parse_subid(content: &str, user: &str, id: u32) -> Vec<SubidRange> {
+    let id = id.to_string();
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let owner = fields.next()?;
+            let start = fields.next()?.parse().ok()?;
+            let count = fields.next()?.parse().ok()?;
+            (fields.next().is_none() && (owner == user || owner == id) && count > 0)
+                .then_some(SubidRange { start, count })
+        })
+        .collect()
+}
+
+/// Map `id` to itself and fill the other container ids, from 0 up, with
+/// the host ids of `ranges`.
+pub fn id_mappings(id: u32, ranges: &[SubidRange]) -> Vec<IdMapping> {
+    let mut mappings = vec![IdMapping {
+        container_id: id,
+        host_id: id,
+        size: 1,
+    }];
+    let mut next = 0u32;
+    for range in ranges {
+        let mut host_id = range.start;
+        let mut remaining = range.count;
+        while remaining > 0 {
+            if next == id {
+                let Some(after) = next.checked_add(1) else {
+                    return mappings;
+                };
+                next = after;
+                continue;
+            }
+            let mut size = remaining;
+            if next < id {
+                size = size.min(id - next);
+            }
+            let (Some(end), Some(host_end)) = (next.checked_add(size), host_id.checked_add(size))
+            else {
+                return mappings;
+            };
+            mappings.push(IdMapping {
+                container_id: next,
+                host_id,
+                size,
+            });
+            next = end;
+            host_id = host_end;
+            remaining -= size;
+        }
+    }
+    mappings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subid_lines_match_by_name_or_id() {
+        let content = "# users\nalice:100000:65536\n1000:300000:10\nbob:200000:65536\n\
+                       alice:bad:1\nalice:1:0\n";
+        assert_eq!(
+            parse_subid(content, "alice", 1000),
+            [
+                SubidRange {
+                    start: 100_000,
+                    count: 65536
+                },
+                SubidRange {
+                    start: 300_000,
+                    count: 10
+                },
+            ]
+        );
+        assert!(parse_subid(content, "carol", 1001).is_empty());
+    }
+
+    #[test]
+    fn own_id_maps_to_itself_and_ranges_fill_around_it() {
+        let ranges = [SubidRange {
+            start: 100_000,
+            count: 65536,
+        }];
+        assert_eq!(
+            id_mappings(1000, &ranges),
+            [
+                IdMapping {
+                    container_id: 1000,
+                    host_id: 1000,
+                    size: 1
+                },
+                IdMapping {
+                    container_id: 0,
+                    host_id: 100_000,
+                    size: 1000
+                },
+                IdMapping {
+                    container_id: 1001,
+                    host_id: 101_000,
+                    size: 64536
+                },
+            ]
+        );
+        assert_eq!(id_mappings(1000, &[]).len(), 1);
+    }
+}
@@ -0,0 +1,1297 @@
+use crate::image::{is_nixos, NIX_SYSTEM_BIN};
+use crate::netconf::{hosts_source, resolv_conf_source};
+use crate::toolchain::toolchain_bin_dirs;
+use crate::watchdog::{Watchdog, WatchdogConfig};
+use crate::RuntimeError;
+use karapace_schema::{ExtraHost, Language, MountOption, NetworkMode, PortForward};
+use std::fmt::Write as _;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+pub mod packages;
+
+pub use packages::{
+    detect_package_manager, package_manager_for, resolved_versions, select_package_manager,
+    PackageManager,
+};
+
+pub(crate) fn shell_quote(s: &str) -> String {
+    // Single-quoting in POSIX shell: replace ' with '\'' then wrap in '
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Shell-escape a Path for safe interpolation.
+fn shell_quote_path(p: &Path) -> String {
+    shell_quote(&p.to_string_lossy())
+}
+
+#[derive(Debug, Clone)]
+pub struct BindMount {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub read_only: bool,
+    /// `nodev`, `noexec` and `nosuid` from the manifest; read-only-ness is
+    /// carried by `read_only`.
+    pub options: Vec<MountOption>,
+    /// `O_PATH` descriptor of `source` as it was checked against the
+    /// security policy. The namespace backend binds through it instead of
+    /// looking `source` up again.
+    pub pinned: Option<Arc<OwnedFd>>,
+}
+
+impl BindMount {
+    /// Mount flags to apply on top of the bind: `ro` when read-only, then
+    /// the remaining options.
+    pub fn flags(&self) -> Vec<&'static str> {
+        let ro = self.read_only.then_some(MountOption::Ro.as_str());
+        ro.into_iter()
+            .chain(
+                self.options
+                    .iter()
+                    .filter(|option| **option != MountOption::Ro)
+                    .map(|option| option.as_str()),
+            )
+            .collect()
+    }
+
+    /// Whether the source is a directory, asked of the pinned descriptor
+    /// when there is one.
+    fn source_is_dir(&self) -> bool {
+        match &self.pinned {
+            Some(fd) => fd
+                .try_clone()
+                .map(std::fs::File::from)
+                .and_then(|file| file.metadata())
+                .is_ok_and(|meta| meta.is_dir()),
+            None => self.source.is_dir(),
+        }
+    }
+}
+
+/// First descriptor pinned bind sources are handed to the session on. The
+/// setup script runs under `/bin/sh`, which can only name descriptors 0-9
+/// and so could not close higher ones before the container starts; exec
+/// sessions keep their stdin on 3.
+const PINNED_FD_BASE: i32 = 4;
+/// Pinned bind sources a session can take, on descriptors 4 to 9.
+const MAX_PINNED_FDS: usize = 6;
+
+#[derive(Debug, Clone)]
+pub struct SandboxConfig {
+    pub env_id: String,
+    pub rootfs: PathBuf,
+    pub overlay_lower: PathBuf,
+    pub overlay_upper: PathBuf,
+    pub overlay_work: PathBuf,
+    pub overlay_merged: PathBuf,
+    /// Throwaway upper and work directories used by read-only sessions.
+    pub overlay_scratch: PathBuf,
+    pub hostname: String,
+    pub bind_mounts: Vec<BindMount>,
+    pub env_vars: Vec<(String, String)>,
+    /// How the session reaches the network. `Slirp` sessions are connected
+    /// by [`crate::portfwd`].
+    pub network: NetworkMode,
+    /// Host ports forwarded into the session, see [`crate::portfwd`].
+    pub forward_ports: Vec<PortForward>,
+    /// Nameservers of the session's `resolv.conf`, see [`crate::netconf`].
+    pub dns_servers: Vec<String>,
+    /// Entries added to the session's `/etc/hosts`.
+    pub extra_hosts: Vec<ExtraHost>,
+    /// Runtime files of the session's network: slirp4netns API sockets and
+    /// the files written by [`crate::netconf`].
+    pub net_dir: PathBuf,
+    /// Stack the environment's upper directory below a scratch upper, so
+    /// nothing written during the session reaches the environment.
+    pub read_only: bool,
+    /// Executable to run as PID 1 of the session, see [`crate::init`].
+    pub init: Option<PathBuf>,
+    /// Size limit of the session's upper directory, see [`crate::quota`].
+    pub max_overlay_mb: Option<u64>,
+    /// cgroup the sandbox process joins before it execs, see
+    /// [`crate::cgroup`].
+    pub cgroup: Option<PathBuf>,
+    pub uid: u32,
+    pub gid: u32,
+    pub username: String,
+    pub home_dir: PathBuf,
+}
+
+/// Safe wrapper around libc::getuid().
+#[allow(unsafe_code)]
+pub(crate) fn current_uid() -> u32 {
+    // SAFETY: getuid() is always safe — no arguments, no side effects, cannot fail.
+    unsafe { libc::getuid() }
+}
+
+/// Safe wrapper around libc::geteuid().
+#[allow(unsafe_code)]
+pub(crate) fn current_euid() -> u32 {
+    // SAFETY: geteuid() is always safe — no arguments, no side effects, cannot fail.
+    unsafe { libc::geteuid() }
+}
+
+/// Safe wrapper around libc::getgid().
+#[allow(unsafe_code)]
+fn current_gid() -> u32 {
+    // SAFETY: getgid() is always safe — no arguments, no side effects, cannot fail.
+    unsafe { libc::getgid() }
+}
+
+impl SandboxConfig {
+    pub fn new(rootfs: PathBuf, env_id: &str, env_dir: &Path) -> Self {
+        let uid = current_uid();
+        let gid = current_gid();
+        let username = std::env::var("USER").unwrap_or_else(|_| "user".to_owned());
+        let home_dir =
+            PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| format!("/home/{username}")));
+
+        Self {
+            env_id: env_id.to_owned(),
+            rootfs,
+            overlay_lower: env_dir.join("lower"),
+            overlay_upper: env_dir.join("upper"),
+            overlay_work: env_dir.join("work"),
+            overlay_merged: env_dir.join("merged"),
+            overlay_scratch: env_dir.join("scratch"),
+            hostname: format!("karapace-{}", &env_id[..12.min(env_id.len())]),
+            bind_mounts: Vec::new(),
+            env_vars: Vec::new(),
+            network: NetworkMode::Host,
+            forward_ports: Vec::new(),
+            dns_servers: Vec::new(),
+            extra_hosts: Vec::new(),
+            net_dir: env_dir.join("net"),
+            read_only: false,
+            init: None,
+            max_overlay_mb: None,
+            cgroup: None,
+            uid,
+            gid,
+            username,
+            home_dir,
+        }
+    }
+
+    /// Whether the session runs in a network namespace of its own.
+    pub fn unshares_network(&self) -> bool {
+        self.network != NetworkMode::Host
+    }
+
+    /// Whether slirp4netns connects the session's namespace to the host.
+    pub fn uses_slirp(&self) -> bool {
+        self.network == NetworkMode::Slirp
+    }
+
+    /// Directory that receives writes for this session.
+    pub fn session_upper(&self) -> PathBuf {
+        if self.read_only {
+            self.overlay_scratch.join("upper")
+        } else {
+            self.overlay_upper.clone()
+        }
+    }
+
+    fn session_work(&self) -> PathBuf {
+        if self.read_only {
+            self.overlay_scratch.join("work")
+        } else {
+            self.overlay_work.clone()
+        }
+    }
+
+    /// `fuse-overlayfs` mount options for this session.
+    fn overlay_options(&self) -> String {
+        let lower = if self.read_only {
+            format!("{}:{}", self.overlay_upper.display(), self.rootfs.display())
+        } else {
+            self.rootfs.display().to_string()
+        };
+        format!(
+            "lowerdir={lower},upperdir={},workdir={}",
+            self.session_upper().display(),
+            self.session_work().display()
+        )
+    }
+}
+
+pub fn mount_overlay(config: &SandboxConfig) -> Result<(), RuntimeError> {
+    let _ = unmount_overlay(config);
+
+    let work = config.session_work();
+    if work.exists() {
+        let _ = std::fs::remove_dir_all(&work);
+    }
+
+    for dir in [
+        &config.overlay_upper,
+        &config.session_upper(),
+        &work,
+        &config.overlay_merged,
+    ] {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    if let Some(limit_mb) = config.max_overlay_mb {
+        crate::quota::apply_quota(&config.session_upper(), limit_mb);
+    }
+
+    // Create a symlink to rootfs as lower dir if needed
+    if !config.overlay_lower.exists() {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&config.rootfs, &config.overlay_lower)?;
+    }
+
+    let status = Command::new("fuse-overlayfs")
+        .args([
+            "-o",
+            &config.overlay_options(),
+            &config.overlay_merged.to_string_lossy(),
+        ])
+        .status()
+        .map_err(|e| {
+            RuntimeError::ExecFailed(format!(
+                "fuse-overlayfs not found or failed to start: {e}. Install with: sudo zypper install fuse-overlayfs"
+            ))
+        })?;
+
+    if !status.success() {
+        return Err(RuntimeError::ExecFailed(
+            "fuse-overlayfs mount failed".to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check if a path is currently a mount point by inspecting /proc/mounts.
+fn is_mounted(path: &Path) -> bool {
+    let canonical = match std::fs::canonicalize(path) {
+        Ok(p) => p.to_string_lossy().to_string(),
+        Err(_) => path.to_string_lossy().to_string(),
+    };
+    match std::fs::read_to_string("/proc/mounts") {
+        Ok(mounts) => mounts
+            .lines()
+            .any(|line| line.split_whitespace().nth(1) == Some(&canonical)),
+        Err(_) => false,
+    }
+}
+
+pub fn unmount_overlay(config: &SandboxConfig) -> Result<(), RuntimeError> {
+    if config.overlay_merged.exists() && is_mounted(&config.overlay_merged) {
+        let _ = Command::new("fusermount3")
+            .args(["-u", &config.overlay_merged.to_string_lossy()])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+        if is_mounted(&config.overlay_merged) {
+            let _ = Command::new("fusermount")
+                .args(["-u", &config.overlay_merged.to_string_lossy()])
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status();
+        }
+    }
+    // Scratch space only ever holds writes from read-only sessions.
+    if config.overlay_scratch.exists() && !is_mounted(&config.overlay_merged) {
+        let _ = std::fs::remove_dir_all(&config.overlay_scratch);
+    }
+    Ok(())
+}
+
+pub fn setup_container_rootfs(config: &SandboxConfig) -> Result<PathBuf, RuntimeError> {
+    let merged = &config.overlay_merged;
+
+    for subdir in [
+        "proc", "sys", "dev", "dev/pts", "dev/shm", "tmp", "run", "run/user", "etc", "var",
+        "var/tmp",
+    ] {
+        std::fs::create_dir_all(merged.join(subdir))?;
+    }
+
+    // The session's $XDG_RUNTIME_DIR, see `host::container_runtime_dir`.
+    // Clients such as Qt refuse one others can read.
+    let user_run = merged.join(format!("run/user/{}", config.uid));
+    std::fs::create_dir_all(&user_run)?;
+    std::fs::set_permissions(&user_run, std::fs::Permissions::from_mode(0o700))?;
+
+    let container_home = merged.join(
+        config
+            .home_dir
+            .strip_prefix("/")
+            .unwrap_or(&config.home_dir),
+    );
+    std::fs::create_dir_all(&container_home)?;
+
+    let _ = std::fs::write(merged.join("etc/hostname"), &config.hostname);
+
+    if !merged.join("etc/resolv.conf").exists() && Path::new("/etc/resolv.conf").exists() {
+        let _ = std::fs::copy("/etc/resolv.conf", merged.join("etc/resolv.conf"));
+    }
+
+    crate::netconf::write_session_files(config, merged)?;
+
+    ensure_user_in_container(config, merged)?;
+
+    Ok(merged.clone())
+}
+
+fn ensure_user_in_container(config: &SandboxConfig, merged: &Path) -> Result<(), RuntimeError> {
+    let passwd_path = merged.join("etc/passwd");
+    let existing = std::fs::read_to_string(&passwd_path).unwrap_or_default();
+
+    let shell = login_shell(merged);
+    let user_entry = format!(
+        "{}:x:{}:{}::/{}:{shell}\n",
+        config.username,
+        config.uid,
+        config.gid,
+        config
+            .home_dir
+            .strip_prefix("/")
+            .unwrap_or(&config.home_dir)
+            .display()
+    );
+
+    if !existing.contains(&format!("{}:", config.username)) {
+        let mut content = existing;
+        if !content.contains("root:") {
+            let _ = writeln!(content, "root:x:0:0:root:/root:{shell}");
+        }
+        content.push_str(&user_entry);
+        std::fs::write(&passwd_path, content)?;
+    }
+
+    // Ensure group exists
+    let group_path = merged.join("etc/group");
+    let existing_groups = std::fs::read_to_string(&group_path).unwrap_or_default();
+    let group_entry = format!("{}:x:{}:\n", config.username, config.gid);
+    if !existing_groups.contains(&format!("{}:", config.username)) {
+        let mut content = existing_groups;
+        if !content.contains("root:") {
+            content.push_str("root:x:0:\n");
+        }
+        content.push_str(&group_entry);
+        std::fs::write(&group_path, content)?;
+    }
+
+    Ok(())
+}
+
+fn build_unshare_command(config: &SandboxConfig) -> Command {
+    let mut cmd = Command::new("unshare");
+    cmd.args([
+        "--user",
+        "--map-root-user",
+        "--mount",
+        "--pid",
+        "--fork",
+        "--kill-child=SIGTERM",
+    ]);
+
+    if config.unshares_network() {
+        cmd.arg("--net");
+    }
+    if let Some(cgroup) = &config.cgroup {
+        crate::cgroup::join_before_exec(&mut cmd, cgroup);
+    }
+
+    cmd
+}
+
+/// `unshare` running `setup` with `/bin/sh`, under the init if one is set.
+fn build_session_command(config: &SandboxConfig, setup: &str) -> Result<Command, RuntimeError> {
+    let mut cmd = build_unshare_command(config);
+    pass_pinned_sources(&mut cmd, config)?;
+    let shell = ["/bin/sh", "-c", setup];
+    match &config.init {
+        Some(init) => cmd.arg(init).args(crate::init::init_args(&shell)),
+        None => cmd.args(shell),
+    };
+    Ok(cmd)
+}
+
+/// Hand the pinned bind sources to the session on descriptors
+/// `PINNED_FD_BASE` and up, in `bind_mounts` order, where the setup script
+/// binds them from `/proc/self/fd`.
+#[allow(unsafe_code)]
+fn pass_pinned_sources(cmd: &mut Command, config: &SandboxConfig) -> Result<(), RuntimeError> {
+    use std::os::unix::process::CommandExt;
+
+    let pinned: Vec<Arc<OwnedFd>> = config
+        .bind_mounts
+        .iter()
+        .filter_map(|bm| bm.pinned.clone())
+        .collect();
+    if pinned.is_empty() {
+        return Ok(());
+    }
+    if pinned.len() > MAX_PINNED_FDS {
+        return Err(RuntimeError::MountDenied(format!(
+            "{} manifest mounts declared, a session can bind at most {MAX_PINNED_FDS}",
+            pinned.len()
+        )));
+    }
+    // SAFETY: fcntl and dup2 are async-signal-safe, and `pinned` was
+    // allocated before the fork.
+    unsafe {
+        cmd.pre_exec(move || {
+            // Move every source above the target range first, so placing
+            // one cannot close another that happens to sit there.
+            let mut high = [0; MAX_PINNED_FDS];
+            for (fd, source) in high.iter_mut().zip(&pinned) {
+                *fd = libc::fcntl(source.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 10);
+                if *fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            for (target, &fd) in (PINNED_FD_BASE..).zip(&high[..pinned.len()]) {
+                if libc::dup2(fd, target) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+fn build_setup_script(config: &SandboxConfig) -> String {
+    let merged = &config.overlay_merged;
+    let qm = shell_quote_path(merged);
+    let mut script = String::new();
+
+    let _ = writeln!(script, "mount -t proc proc {qm}/proc 2>/dev/null || true");
+
+    let _ = writeln!(script, "mount --rbind /sys {qm}/sys 2>/dev/null && mount --make-rslave {qm}/sys 2>/dev/null || true");
+
+    let _ = writeln!(script, "mount --rbind /dev {qm}/dev 2>/dev/null && mount --make-rslave {qm}/dev 2>/dev/null || true");
+
+    let container_home = merged.join(
+        config
+            .home_dir
+            .strip_prefix("/")
+            .unwrap_or(&config.home_dir),
+    );
+    let _ = writeln!(
+        script,
+        "mount --bind {} {} 2>/dev/null || true",
+        shell_quote_path(&config.home_dir),
+        shell_quote_path(&container_home)
+    );
+
+    let _ = writeln!(
+        script,
+        "touch {qm}/etc/resolv.conf 2>/dev/null; mount --bind {} {qm}/etc/resolv.conf 2>/dev/null || true",
+        shell_quote_path(&resolv_conf_source(config))
+    );
+    if let Some(hosts) = hosts_source(config) {
+        let _ = writeln!(
+            script,
+            "touch {qm}/etc/hosts 2>/dev/null; mount --bind {} {qm}/etc/hosts 2>/dev/null || true",
+            shell_quote_path(&hosts)
+        );
+    }
+
+    let _ = writeln!(script, "mount --bind /tmp {qm}/tmp 2>/dev/null || true");
+
+    let mut next_pinned = PINNED_FD_BASE;
+    for bm in &config.bind_mounts {
+        let target = if bm.target.is_absolute() {
+            merged.join(bm.target.strip_prefix("/").unwrap_or(&bm.target))
+        } else {
+            merged.join(&bm.target)
+        };
+        let qt = shell_quote_path(&target);
+        // Pinned sources are bound through the descriptor handed over by
+        // `pass_pinned_sources`, so the path is not looked up again.
+        let qs = if bm.pinned.is_some() {
+            let fd = next_pinned;
+            next_pinned += 1;
+            format!("/proc/self/fd/{fd}")
+        } else {
+            shell_quote_path(&bm.source)
+        };
+        if bm.source_is_dir() {
+            let _ = writeln!(script, "mkdir -p {qt} 2>/dev/null");
+        } else {
+            // Files, sockets and device nodes need a file to bind onto.
+            if let Some(parent) = target.parent() {
+                let _ = writeln!(script, "mkdir -p {} 2>/dev/null", shell_quote_path(parent));
+            }
+            let _ = writeln!(script, "touch {qt} 2>/dev/null");
+        }
+        let _ = writeln!(script, "mount --bind {qs} {qt} 2>/dev/null || true");
+        let flags = bm.flags();
+        if !flags.is_empty() {
+            let _ = writeln!(
+                script,
+                "mount -o remount,bind,{} {qt} 2>/dev/null || true",
+                flags.join(",")
+            );
+        }
+    }
+    // The container must not inherit the pinned descriptors.
+    if next_pinned > PINNED_FD_BASE {
+        let closes: Vec<String> = (PINNED_FD_BASE..next_pinned)
+            .map(|fd| format!("{fd}<&-"))
+            .collect();
+        let _ = writeln!(script, "exec {}", closes.join(" "));
+    }
+
+    if Path::new("/tmp/.X11-unix").exists() {
+        let _ = writeln!(
+            script,
+            "mount --bind /tmp/.X11-unix {qm}/tmp/.X11-unix 2>/dev/null || true"
+        );
+    }
+
+    if config.network == NetworkMode::Isolated {
+        let _ = writeln!(script, "ip link set lo up 2>/dev/null || true");
+    }
+    if config.uses_slirp() {
+        // slirp4netns attaches once the namespace exists; give it a moment
+        // so the session starts with its network up.
+        let _ = writeln!(
+            script,
+            "i=0; while ! grep -q '{}:' /proc/net/dev 2>/dev/null && [ $i -lt 100 ]; do sleep 0.05; i=$((i+1)); done",
+            crate::portfwd::SLIRP_DEVICE
+        );
+    }
+
+    let _ = writeln!(
+        script,
+        "exec chroot {qm} {} -s <<'__KARAPACE_EOF__'",
+        system_shell(merged)
+    );
+
+    script
+}
+
+/// `export` statements for an interactive shell in the session.
+fn interactive_exports(config: &SandboxConfig) -> String {
+    let mut env_exports = String::new();
+    for (key, val) in &config.env_vars {
+        if !key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            continue;
+        }
+        let _ = write!(env_exports, "export {}={}; ", key, shell_quote(val));
+    }
+
+    let _ = write!(
+        env_exports,
+        "export HOME={}; ",
+        shell_quote_path(&config.home_dir)
+    );
+    let _ = write!(
+        env_exports,
+        "export USER={}; ",
+        shell_quote(&config.username)
+    );
+    let _ = write!(
+        env_exports,
+        "export HOSTNAME={}; ",
+        shell_quote(&config.hostname)
+    );
+    if let Ok(display) = std::env::var("DISPLAY") {
+        let _ = write!(env_exports, "export DISPLAY={}; ", shell_quote(&display));
+    }
+    env_exports.push_str("export TERM=${TERM:-xterm-256color}; ");
+    env_exports.push_str(&path_export(&config.overlay_merged));
+    let _ = write!(
+        env_exports,
+        "export KARAPACE_ENV=1; export KARAPACE_HOSTNAME={}; ",
+        shell_quote(&config.hostname)
+    );
+    env_exports
+}
+
+/// The login shell of the image mounted at `merged`. Alpine images have no
+/// bash, and NixOS keeps it in the system profile.
+fn login_shell(merged: &Path) -> String {
+    if is_nixos(merged) {
+        format!("{NIX_SYSTEM_BIN}/bash")
+    } else if merged.join("bin/bash").exists() || merged.join("usr/bin/bash").exists() {
+        "/bin/bash".to_owned()
+    } else {
+        "/bin/sh".to_owned()
+    }
+}
+
+/// The POSIX shell scripts run with inside the image mounted at `merged`.
+/// NixOS images may not have `/bin/sh` until they boot.
+fn system_shell(merged: &Path) -> String {
+    if is_nixos(merged) {
+        format!("{NIX_SYSTEM_BIN}/sh")
+    } else {
+        "/bin/sh".to_owned()
+    }
+}
+
+/// An `export PATH` putting the NixOS system profile first, whose binaries
+/// are not in the host's `PATH` the session inherits. Empty elsewhere.
+fn path_export(merged: &Path) -> String {
+    let mut dirs: Vec<String> = [Language::Python, Language::Node, Language::Rust]
+        .into_iter()
+        .flat_map(toolchain_bin_dirs)
+        .filter(|dir| merged.join(dir.trim_start_matches('/')).is_dir())
+        .collect();
+    if is_nixos(merged) {
+        dirs.push("/run/wrappers/bin".to_owned());
+        dirs.push(NIX_SYSTEM_BIN.to_owned());
+    }
+    if dirs.is_empty() {
+        String::new()
+    } else {
+        format!("export PATH={}:$PATH; ", dirs.join(":"))
+    }
+}
+
+pub fn enter_interactive(config: &SandboxConfig) -> Result<i32, RuntimeError> {
+    let merged = &config.overlay_merged;
+
+    let mut setup = build_setup_script(config);
+
+    let env_exports = interactive_exports(config);
+    let shell = login_shell(merged);
+
+    let _ = write!(
+        setup,
+        "{env_exports}cd ~; exec {shell} -l </dev/tty >/dev/tty 2>/dev/tty\n__KARAPACE_EOF__\n"
+    );
+
+    let mut cmd = build_session_command(config, &setup)?;
+
+    cmd.stdin(std::process::Stdio::inherit());
+    cmd.stdout(std::process::Stdio::inherit());
+    cmd.stderr(std::process::Stdio::inherit());
+
+    let enter_failed = |e| RuntimeError::ExecFailed(format!("failed to enter sandbox: {e}"));
+    let mut child = cmd.spawn().map_err(enter_failed)?;
+    let _forwarder = crate::portfwd::forward_session_ports(config, &mut child)?;
+    let status = child.wait().map_err(enter_failed)?;
+
+    Ok(status.code().unwrap_or(1))
+}
+
+pub fn spawn_enter_interactive(
+    config: &SandboxConfig,
+) -> Result<std::process::Child, RuntimeError> {
+    let merged = &config.overlay_merged;
+
+    let mut setup = build_setup_script(config);
+
+    let env_exports = interactive_exports(config);
+    let shell = login_shell(merged);
+
+    let _ = write!(
+        setup,
+        "{env_exports}cd ~; exec {shell} -l </dev/tty >/dev/tty 2>/dev/tty\n__KARAPACE_EOF__\n"
+    );
+
+    let mut cmd = build_session_command(config, &setup)?;
+
+    cmd.stdin(std::process::Stdio::inherit());
+    cmd.stdout(std::process::Stdio::inherit());
+    cmd.stderr(std::process::Stdio::inherit());
+
+    cmd.spawn()
+        .map_err(|e| RuntimeError::ExecFailed(format!("failed to spawn sandbox: {e}")))
+}
+
+/// Start a session with no terminal that keeps running until it is
+/// stopped. It is put in a process group of its own, so signals meant for
+/// the caller's terminal do not reach it.
+pub fn spawn_detached(config: &SandboxConfig) -> Result<std::process::Child, RuntimeError> {
+    use std::os::unix::process::CommandExt;
+    let mut setup = build_setup_script(config);
+    let _ = write!(
+        setup,
+        "cd /; while :; do sleep 3600; done\n__KARAPACE_EOF__\n"
+    );
+
+    let mut cmd = build_session_command(config, &setup)?;
+    cmd.stdin(std::process::Stdio::null());
+    cmd.stdout(std::process::Stdio::null());
+    cmd.stderr(std::process::Stdio::null());
+    cmd.process_group(0);
+
+    cmd.spawn()
+        .map_err(|e| RuntimeError::ExecFailed(format!("failed to start detached session: {e}")))
+}
+
+/// Join the detached session whose process `target` runs in the container,
+/// and run `command` there, or a login shell when it is empty.
+pub fn attach_to_session(
+    config: &SandboxConfig,
+    target: u32,
+    command: &[String],
+) -> Result<std::process::ExitStatus, RuntimeError> {
+    let env_exports = interactive_exports(config);
+    let run = if command.is_empty() {
+        format!("exec {} -l", login_shell(&config.overlay_merged))
+    } else {
+        let escaped: Vec<String> = command.iter().map(|a| shell_quote(a)).collect();
+        format!("exec {}", escaped.join(" "))
+    };
+
+    let mut cmd = crate::session::nsenter_command(config, target);
+    if let Some(cgroup) = &config.cgroup {
+        crate::cgroup::join_before_exec(&mut cmd, cgroup);
+    }
+    cmd.args([
+        &system_shell(&config.overlay_merged),
+        "-c",
+        &format!("{env_exports}cd ~; {run}"),
+    ]);
+    cmd.stdin(std::process::Stdio::inherit());
+    cmd.stdout(std::process::Stdio::inherit());
+    cmd.stderr(std::process::Stdio::inherit());
+
+    cmd.status()
+        .map_err(|e| RuntimeError::ExecFailed(format!("failed to attach to session: {e}")))
+}
+
+/// The session command running `command` in the container. The command's
+/// stdin is the one the session is spawned with, not the setup script.
+fn build_exec_command(config: &SandboxConfig, command: &[String]) -> Result<Command, RuntimeError> {
+    // The inner shell reads the script from a here-document; keep the
+    // session's stdin on fd 3 and hand it back to the command.
+    let mut setup = format!("exec 3<&0\n{}", build_setup_script(config));
+
+    let mut env_exports = String::new();
+    for (key, val) in &config.env_vars {
+        if !key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            continue;
+        }
+        let _ = write!(env_exports, "export {}={}; ", key, shell_quote(val));
+    }
+    let _ = write!(
+        env_exports,
+        "export HOME={}; ",
+        shell_quote_path(&config.home_dir)
+    );
+    let _ = write!(
+        env_exports,
+        "export USER={}; ",
+        shell_quote(&config.username)
+    );
+    env_exports.push_str("export KARAPACE_ENV=1; ");
+    env_exports.push_str(&path_export(&config.overlay_merged));
+
+    let escaped_cmd: Vec<String> = command.iter().map(|a| shell_quote(a)).collect();
+    let _ = write!(
+        setup,
+        "{env_exports}{} <&3 3<&-\n__KARAPACE_EOF__\n",
+        escaped_cmd.join(" ")
+    );
+
+    build_session_command(config, &setup)
+}
+
+/// Watch a session spawned as `pid` against its `max_overlay_mb` limit,
+/// as entered shells are, so a runaway build or command is stopped before
+/// the environment outgrows it.
+fn watch_quota(config: &SandboxConfig, pid: u32) -> Option<Watchdog> {
+    let quota_mb = config.max_overlay_mb?;
+    let env_id = config.env_id.clone();
+    Some(Watchdog::spawn(
+        pid,
+        config.session_upper(),
+        WatchdogConfig {
+            quota_mb: Some(quota_mb),
+            ..WatchdogConfig::default()
+        },
+        move |event| {
+            tracing::warn!("environment {env_id}: {event}");
+            crate::terminal::print_resource_event(&env_id, event);
+        },
+    ))
+}
+
+pub fn exec_in_container(
+    config: &SandboxConfig,
+    command: &[String],
+) -> Result<std::process::Output, RuntimeError> {
+    let mut cmd = build_exec_command(config, command)?;
+    cmd.stdin(std::process::Stdio::null());
+    cmd.stdout(std::process::Stdio::piped());
+    cmd.stderr(std::process::Stdio::piped());
+
+    let exec_failed = |e| RuntimeError::ExecFailed(format!("exec in container failed: {e}"));
+    let mut child = cmd.spawn().map_err(exec_failed)?;
+    let _forwarder = crate::portfwd::forward_session_ports(config, &mut child)?;
+    let watchdog = watch_quota(config, child.id());
+    let output = child.wait_with_output();
+    if let Some(watchdog) = watchdog {
+        watchdog.stop();
+    }
+    output.map_err(exec_failed)
+}
+
+/// Run `command` in the container attached to the caller's stdin, stdout
+/// and stderr, or to a new pseudo-terminal when `tty` is set.
+pub fn exec_attached_in_container(
+    config: &SandboxConfig,
+    command: &[String],
+    tty: bool,
+) -> Result<std::process::ExitStatus, RuntimeError> {
+    let cmd = build_exec_command(config, command)?;
+    let exec_failed = |e| RuntimeError::ExecFailed(format!("exec in container failed: {e}"));
+    if tty {
+        let pty = crate::pty::Pty::open()
+            .map_err(|e| RuntimeError::ExecFailed(format!("failed to allocate a pty: {e}")))?;
+        let mut session = pty.spawn(cmd).map_err(exec_failed)?;
+        let _forwarder = crate::portfwd::forward_session_ports(config, &mut session.child)?;
+        let watchdog = watch_quota(config, session.child.id());
+        let status = session.wait();
+        if let Some(watchdog) = watchdog {
+            watchdog.stop();
+        }
+        status.map_err(exec_failed)
+    } else {
+        let mut cmd = cmd;
+        cmd.stdin(std::process::Stdio::inherit());
+        cmd.stdout(std::process::Stdio::inherit());
+        cmd.stderr(std::process::Stdio::inherit());
+        let mut child = cmd.spawn().map_err(exec_failed)?;
+        let _forwarder = crate::portfwd::forward_session_ports(config, &mut child)?;
+        let watchdog = watch_quota(config, child.id());
+        let status = child.wait();
+        if let Some(watchdog) = watchdog {
+            watchdog.stop();
+        }
+        status.map_err(exec_failed)
+    }
+}
+
+pub fn install_packages_in_container(
+    config: &SandboxConfig,
+    install_cmd: &[String],
+) -> Result<(), RuntimeError> {
+    if install_cmd.is_empty() {
+        return Ok(());
+    }
+
+    let output = exec_in_container(config, install_cmd)?;
+
+    if !output.status.success() {
+        // A failure caused by the watchdog ending an oversized build.
+        crate::quota::check_quota(&config.session_upper(), config.max_overlay_mb)?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        return Err(RuntimeError::ExecFailed(format!(
+            "package installation failed:\nstdout: {stdout}\nstderr: {stderr}"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Expand glob patterns in `packages` against the container's package index.
+/// Lists without patterns are returned unchanged and no index is queried.
+pub fn expand_packages_in_container(
+    config: &SandboxConfig,
+    pm: &dyn PackageManager,
+    packages: &[String],
+) -> Result<Vec<String>, RuntimeError> {
+    if !packages
+        .iter()
+        .any(|p| karapace_schema::is_package_pattern(p))
+    {
+        return Ok(packages.to_vec());
+    }
+
+    let list_cmd = pm.list_packages_command();
+    if list_cmd.is_empty() {
+        return Err(RuntimeError::ExecFailed(format!(
+            "package patterns are not supported for {}",
+            pm.name()
+        )));
+    }
+    let output = exec_in_container(config, &list_cmd)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(RuntimeError::ExecFailed(format!(
+            "listing available packages failed: {stderr}"
+        )));
+    }
+    let index = pm.parse_package_index(&String::from_utf8_lossy(&output.stdout));
+    Ok(karapace_schema::expand_package_patterns(packages, &index)?)
+}
+
+/// Install `packages` with `pm` and return the version the container
+/// reports for each, after expanding patterns.
+pub fn resolve_packages_in_container(
+    config: &SandboxConfig,
+    pm: &dyn PackageManager,
+    packages: &[String],
+) -> Result<Vec<karapace_schema::ResolvedPackage>, RuntimeError> {
+    let packages = expand_packages_in_container(config, pm, packages)?;
+    if packages.is_empty() {
+        return Ok(Vec::new());
+    }
+    install_packages_in_container(config, &pm.install_command(&packages))?;
+
+    let output = exec_in_container(config, &pm.query_versions_command(&packages))?;
+    resolved_versions(pm, packages, &String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sandbox_config_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let rootfs = dir.path().join("rootfs");
+        std::fs::create_dir_all(&rootfs).unwrap();
+        let config = SandboxConfig::new(rootfs, "abc123def456", dir.path());
+        assert!(config.hostname.starts_with("karapace-"));
+        assert_eq!(config.network, NetworkMode::Host);
+        assert!(!config.read_only);
+    }
+
+    #[test]
+    fn read_only_session_stacks_upper_below_scratch() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        let upper = dir.path().join("upper");
+        assert_eq!(config.session_upper(), upper);
+        assert_eq!(
+            config.overlay_options(),
+            format!(
+                "lowerdir=/rootfs,upperdir={},workdir={}",
+                upper.display(),
+                dir.path().join("work").display()
+            )
+        );
+
+        config.read_only = true;
+        let scratch = dir.path().join("scratch");
+        assert_eq!(config.session_upper(), scratch.join("upper"));
+        assert_eq!(
+            config.overlay_options(),
+            format!(
+                "lowerdir={}:/rootfs,upperdir={},workdir={}",
+                upper.display(),
+                scratch.join("upper").display(),
+                scratch.join("work").display()
+            )
+        );
+    }
+
+    #[test]
+    fn unmount_discards_scratch() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        std::fs::create_dir_all(config.overlay_scratch.join("upper/etc")).unwrap();
+        std::fs::create_dir_all(&config.overlay_upper).unwrap();
+        unmount_overlay(&config).unwrap();
+        assert!(!config.overlay_scratch.exists());
+        assert!(config.overlay_upper.exists());
+    }
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("hello"), "'hello'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn shell_quote_prevents_injection() {
+        // Command substitution is safely wrapped in single quotes
+        let malicious = "$(rm -rf /)";
+        let quoted = shell_quote(malicious);
+        assert_eq!(quoted, "'$(rm -rf /)'");
+        assert!(quoted.starts_with('\'') && quoted.ends_with('\''));
+
+        // Backtick injection is also safely quoted
+        let backtick = "`whoami`";
+        let quoted = shell_quote(backtick);
+        assert_eq!(quoted, "'`whoami`'");
+
+        // Newline injection
+        let newline = "value\n; rm -rf /";
+        let quoted = shell_quote(newline);
+        assert!(quoted.starts_with('\'') && quoted.ends_with('\''));
+    }
+
+    #[test]
+    fn shell_quote_path_handles_spaces() {
+        let p = PathBuf::from("/home/user/my project/dir");
+        let quoted = shell_quote_path(&p);
+        assert_eq!(quoted, "'/home/user/my project/dir'");
+    }
+
+    #[test]
+    fn build_setup_script_contains_essential_mounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let rootfs = dir.path().join("rootfs");
+        std::fs::create_dir_all(&rootfs).unwrap();
+        let config = SandboxConfig::new(rootfs, "abc123def456", dir.path());
+        let script = build_setup_script(&config);
+        assert!(script.contains("mount -t proc"));
+        assert!(script.contains("mount --rbind /sys"));
+        assert!(script.contains("mount --rbind /dev"));
+        assert!(script.contains("chroot"));
+    }
+
+    #[test]
+    fn nixos_and_alpine_images_get_their_own_shells() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        let merged = &config.overlay_merged;
+        std::fs::create_dir_all(merged.join("etc")).unwrap();
+
+        // Alpine: no bash, so the user's shell is /bin/sh.
+        ensure_user_in_container(&config, merged).unwrap();
+        let passwd = std::fs::read_to_string(merged.join("etc/passwd")).unwrap();
+        assert!(passwd.lines().all(|l| l.ends_with(":/bin/sh")), "{passwd}");
+        assert!(build_setup_script(&config).contains("/bin/sh -s <<"));
+        assert!(path_export(merged).is_empty());
+
+        std::fs::write(merged.join("etc/NIXOS"), "").unwrap();
+        let script = build_setup_script(&config);
+        assert!(script.contains(&format!("{NIX_SYSTEM_BIN}/sh -s <<")));
+        assert_eq!(login_shell(merged), format!("{NIX_SYSTEM_BIN}/bash"));
+        assert!(interactive_exports(&config).contains(&format!(":{NIX_SYSTEM_BIN}:$PATH")));
+
+        // Installed toolchains come first.
+        std::fs::create_dir_all(merged.join("opt/karapace/python/bin")).unwrap();
+        assert!(path_export(merged)
+            .starts_with("export PATH=/opt/karapace/python/bin:/run/wrappers/bin:"));
+    }
+
+    #[test]
+    fn network_modes_shape_the_session_network() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        let unshares_net = |config: &SandboxConfig| {
+            build_unshare_command(config)
+                .get_args()
+                .any(|a| a == "--net")
+        };
+        assert!(!unshares_net(&config));
+        assert!(build_setup_script(&config).contains("mount --bind '/etc/resolv.conf'"));
+
+        config.network = NetworkMode::Slirp;
+        config.forward_ports = vec![PortForward::parse("8080:80").unwrap()];
+        assert!(unshares_net(&config));
+        let script = build_setup_script(&config);
+        let resolv = shell_quote_path(&dir.path().join("net/resolv.conf"));
+        assert!(script.contains(&format!("mount --bind {resolv}")));
+        assert!(script.contains("grep -q 'tap0:' /proc/net/dev"));
+        assert!(!script.contains("ip link set lo up"));
+
+        config.network = NetworkMode::Isolated;
+        assert!(unshares_net(&config));
+        let script = build_setup_script(&config);
+        assert!(script.contains("ip link set lo up"));
+        assert!(!script.contains("tap0"));
+
+        config.network = NetworkMode::None;
+        assert!(unshares_net(&config));
+        assert!(!build_setup_script(&config).contains("ip link set lo up"));
+    }
+
+    #[test]
+    fn name_resolution_overrides_are_bound() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        assert!(!build_setup_script(&config).contains("/etc/hosts"));
+
+        config.dns_servers = vec!["10.0.0.53".to_owned()];
+        config.extra_hosts = vec![ExtraHost::parse("db.local:10.0.0.5").unwrap()];
+        let script = build_setup_script(&config);
+        let resolv = shell_quote_path(&dir.path().join("net/resolv.conf"));
+        let hosts = shell_quote_path(&dir.path().join("net/hosts"));
+        assert!(script.contains(&format!("mount --bind {resolv}")));
+        assert!(script.contains(&format!("mount --bind {hosts}")));
+    }
+
+    #[test]
+    fn bind_mount_flags_are_remounted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        config.bind_mounts = vec![
+            BindMount {
+                source: dir.path().to_path_buf(),
+                target: PathBuf::from("/data"),
+                read_only: true,
+                options: vec![MountOption::Nodev, MountOption::Noexec],
+                pinned: None,
+            },
+            BindMount {
+                source: dir.path().to_path_buf(),
+                target: PathBuf::from("/scratch"),
+                read_only: false,
+                options: vec![MountOption::Nosuid],
+                pinned: None,
+            },
+        ];
+        let script = build_setup_script(&config);
+        assert!(script.contains("mount -o remount,bind,ro,nodev,noexec "));
+        assert!(script.contains("mount -o remount,bind,nosuid "));
+    }
+
+    #[test]
+    fn pinned_sources_are_bound_through_their_descriptors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        let pinned = |target: &str| BindMount {
+            source: dir.path().to_path_buf(),
+            target: PathBuf::from(target),
+            read_only: false,
+            options: Vec::new(),
+            pinned: Some(Arc::new(OwnedFd::from(
+                std::fs::File::open(dir.path()).unwrap(),
+            ))),
+        };
+        config.bind_mounts = vec![
+            pinned("/a"),
+            BindMount {
+                source: PathBuf::from("/dev/dri"),
+                target: PathBuf::from("/dev/dri"),
+                read_only: false,
+                options: Vec::new(),
+                pinned: None,
+            },
+            pinned("/b"),
+        ];
+        let script = build_setup_script(&config);
+        let merged = config.overlay_merged.display();
+        assert!(script.contains(&format!("mkdir -p '{merged}/a'")));
+        assert!(script.contains(&format!("mount --bind /proc/self/fd/4 '{merged}/a'")));
+        assert!(script.contains(&format!("mount --bind '/dev/dri' '{merged}/dev/dri'")));
+        assert!(script.contains(&format!("mount --bind /proc/self/fd/5 '{merged}/b'")));
+        assert!(script.contains("exec 4<&- 5<&-\n"));
+
+        config.bind_mounts = (0..=MAX_PINNED_FDS).map(|_| pinned("/c")).collect();
+        assert!(matches!(
+            build_session_command(&config, "true"),
+            Err(RuntimeError::MountDenied(_))
+        ));
+    }
+
+    #[test]
+    fn pinned_source_is_bound_after_its_path_is_swapped() {
+        let userns = Command::new("unshare")
+            .args(["--user", "--map-root-user", "--mount", "true"])
+            .status();
+        if !userns.is_ok_and(|status| status.success()) {
+            return; // no user namespaces here
+        }
+
+        let root = tempfile::tempdir().unwrap();
+        let data = root.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        std::fs::write(data.join("marker"), "pinned").unwrap();
+        let policy = crate::security::SecurityPolicy {
+            allowed_mount_prefixes: vec![root.path().to_string_lossy().into_owned()],
+            ..crate::security::SecurityPolicy::default()
+        };
+        let source = policy
+            .resolve_mount_source(&data.to_string_lossy(), false)
+            .unwrap();
+
+        // Swap the checked directory for a symlink out of the root.
+        std::fs::rename(&data, root.path().join("moved")).unwrap();
+        std::os::unix::fs::symlink("/etc", &data).unwrap();
+
+        let target = root.path().join("target");
+        std::fs::create_dir(&target).unwrap();
+        let mut config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", root.path());
+        config.bind_mounts = vec![BindMount {
+            source: source.path,
+            target: target.clone(),
+            read_only: false,
+            options: Vec::new(),
+            pinned: Some(Arc::new(source.fd)),
+        }];
+        let mut cmd = Command::new("unshare");
+        cmd.args(["--user", "--map-root-user", "--mount"]);
+        pass_pinned_sources(&mut cmd, &config).unwrap();
+        let qt = shell_quote_path(&target);
+        let output = cmd
+            .args([
+                "/bin/sh",
+                "-c",
+                &format!("mount --bind /proc/self/fd/4 {qt} && exec 4<&- && cat {qt}/marker"),
+            ])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "pinned");
+    }
+
+    #[test]
+    fn exec_command_gets_the_session_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        let cmd = build_exec_command(&config, &["cat".to_owned()]).unwrap();
+        let script = cmd
+            .get_args()
+            .last()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        assert!(script.starts_with("exec 3<&0\n"));
+        assert!(script.contains("'cat' <&3 3<&-\n__KARAPACE_EOF__"));
+    }
+
+    #[test]
+    fn session_command_runs_under_init_when_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        let args = |config: &SandboxConfig| -> Vec<String> {
+            build_session_command(config, "true")
+                .unwrap()
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        };
+
+        let plain = args(&config);
+        assert!(plain.ends_with(&["/bin/sh".into(), "-c".into(), "true".into()]));
+        assert!(!plain.iter().any(|a| a == crate::init::INIT_ARG));
+
+        config.init = Some(PathBuf::from("/usr/bin/karapace"));
+        let wrapped = args(&config);
+        assert!(wrapped.ends_with(&[
+            "/usr/bin/karapace".into(),
+            crate::init::INIT_ARG.into(),
+            "--".into(),
+            "/bin/sh".into(),
+            "-c".into(),
+            "true".into(),
+        ]));
+    }
+
+    #[test]
+    fn is_mounted_returns_false_for_regular_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_mounted(dir.path()));
+    }
+
+    #[test]
+    fn unmount_overlay_noop_on_non_mounted() {
+        let dir = tempfile::tempdir().unwrap();
+        let rootfs = dir.path().join("rootfs");
+        std::fs::create_dir_all(&rootfs).unwrap();
+        let config = SandboxConfig::new(rootfs, "abc123def456", dir.path());
+        // Create the merged dir but don't mount anything
+        std::fs::create_dir_all(&config.overlay_merged).unwrap();
+        // Should not error — just returns Ok because nothing is mounted
+        assert!(unmount_overlay(&config).is_ok());
+    }
+}
@@ -0,0 +1,509 @@
+//! Package managers driven inside the sandbox.
+//!
+//! Each supported distribution family implements [`PackageManager`]: how to
+//! install packages, how to ask for the installed version of each, and how
+//! to list the repository for expanding package patterns. The manager of a
+//! build is chosen from the resolved image ([`select_package_manager`]), and
+//! detected from the root filesystem for custom and imported images.
+
+use crate::image::{is_nixos, ImageSource, ResolvedImage, NIX_SYSTEM_BIN};
+use crate::RuntimeError;
+use karapace_schema::ResolvedPackage;
+use std::path::Path;
+
+/// A distribution's package manager, run as commands in the container.
+pub trait PackageManager: Send + Sync {
+    /// Short name, e.g. `"apt"`.
+    fn name(&self) -> &'static str;
+
+    /// Command installing `packages`. Never called with an empty list.
+    fn install_command(&self, packages: &[String]) -> Vec<String>;
+
+    /// Command printing the installed version of `packages`.
+    fn query_versions_command(&self, packages: &[String]) -> Vec<String>;
+
+    /// `(name, version)` pairs from the output of the version query. The
+    /// default reads `name<TAB>version` lines.
+    fn parse_versions(&self, output: &str) -> Vec<(String, String)> {
+        split_lines(output, '\t')
+    }
+
+    /// Command listing every package name in the configured repositories,
+    /// or an empty command when package patterns are not supported.
+    fn list_packages_command(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Sorted, unique package names from the output of the listing
+    /// command. The default reads one name per line.
+    fn parse_package_index(&self, output: &str) -> Vec<String> {
+        sorted_names(output.lines().map(str::trim))
+    }
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| (*a).to_owned()).collect()
+}
+
+fn with_packages(mut cmd: Vec<String>, packages: &[String]) -> Vec<String> {
+    cmd.extend(packages.iter().cloned());
+    cmd
+}
+
+fn sorted_names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut names: Vec<String> = names
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn split_lines(output: &str, separator: char) -> Vec<(String, String)> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| line.split_once(separator))
+        .map(|(name, version)| (name.to_owned(), version.trim().to_owned()))
+        .collect()
+}
+
+/// Debian and Ubuntu.
+pub struct Apt;
+
+impl PackageManager for Apt {
+    fn name(&self) -> &'static str {
+        "apt"
+    }
+
+    fn install_command(&self, packages: &[String]) -> Vec<String> {
+        with_packages(
+            args(&["apt-get", "install", "-y", "--no-install-recommends"]),
+            packages,
+        )
+    }
+
+    fn query_versions_command(&self, packages: &[String]) -> Vec<String> {
+        with_packages(
+            args(&["dpkg-query", "-W", "-f", "${Package}\\t${Version}\\n"]),
+            packages,
+        )
+    }
+
+    fn list_packages_command(&self) -> Vec<String> {
+        args(&["apt-cache", "pkgnames"])
+    }
+}
+
+const RPM_QUERY: &[&str] = &["rpm", "-q", "--qf", "%{NAME}\\t%{VERSION}-%{RELEASE}\\n"];
+
+/// Fedora.
+pub struct Dnf;
+
+impl PackageManager for Dnf {
+    fn name(&self) -> &'static str {
+        "dnf"
+    }
+
+    fn install_command(&self, packages: &[String]) -> Vec<String> {
+        with_packages(
+            args(&["dnf", "install", "-y", "--setopt=install_weak_deps=False"]),
+            packages,
+        )
+    }
+
+    fn query_versions_command(&self, packages: &[String]) -> Vec<String> {
+        with_packages(args(RPM_QUERY), packages)
+    }
+
+    fn list_packages_command(&self) -> Vec<String> {
+        args(&["dnf", "repoquery", "--quiet", "--queryformat", "%{name}\\n"])
+    }
+}
+
+/// openSUSE.
+pub struct Zypper;
+
+impl PackageManager for Zypper {
+    fn name(&self) -> &'static str {
+        "zypper"
+    }
+
+    fn install_command(&self, packages: &[String]) -> Vec<String> {
+        with_packages(
+            args(&["zypper", "--non-interactive", "install", "--no-recommends"]),
+            packages,
+        )
+    }
+
+    fn query_versions_command(&self, packages: &[String]) -> Vec<String> {
+        with_packages(args(RPM_QUERY), packages)
+    }
+
+    fn list_packages_command(&self) -> Vec<String> {
+        args(&[
+            "zypper",
+            "--quiet",
+            "--non-interactive",
+            "search",
+            "--type",
+            "package",
+        ])
+    }
+
+    fn parse_package_index(&self, output: &str) -> Vec<String> {
+        // Table rows: "S | Name | Summary | Type"
+        sorted_names(
+            output
+                .lines()
+                .filter_map(|line| line.split('|').nth(1))
+                .map(str::trim)
+                .filter(|name| *name != "Name"),
+        )
+    }
+}
+
+/// Arch Linux.
+pub struct Pacman;
+
+impl PackageManager for Pacman {
+    fn name(&self) -> &'static str {
+        "pacman"
+    }
+
+    fn install_command(&self, packages: &[String]) -> Vec<String> {
+        with_packages(args(&["pacman", "-S", "--noconfirm", "--needed"]), packages)
+    }
+
+    fn query_versions_command(&self, packages: &[String]) -> Vec<String> {
+        with_packages(args(&["pacman", "-Q"]), packages)
+    }
+
+    fn parse_versions(&self, output: &str) -> Vec<(String, String)> {
+        // "name version" per line
+        split_lines(output, ' ')
+    }
+
+    fn list_packages_command(&self) -> Vec<String> {
+        args(&["pacman", "-Slq"])
+    }
+}
+
+/// Alpine Linux.
+pub struct Apk;
+
+impl PackageManager for Apk {
+    fn name(&self) -> &'static str {
+        "apk"
+    }
+
+    fn install_command(&self, packages: &[String]) -> Vec<String> {
+        with_packages(args(&["apk", "add", "--no-cache"]), packages)
+    }
+
+    /// apk prints "name-version", which cannot be split reliably; read name
+    /// and version of every installed package from its database.
+    fn query_versions_command(&self, _packages: &[String]) -> Vec<String> {
+        args(&[
+            "awk",
+            "-F:",
+            "/^P:/ { name = $2 } /^V:/ { print name \"\\t\" $2 }",
+            "/lib/apk/db/installed",
+        ])
+    }
+
+    fn list_packages_command(&self) -> Vec<String> {
+        args(&["apk", "--update-cache", "search", "--quiet"])
+    }
+}
+
+/// NixOS. Packages are attributes of root's `nixos` channel, e.g. `git` or
+/// `python3`.
+pub struct Nix;
+
+impl Nix {
+    fn nix_env() -> String {
+        format!("{NIX_SYSTEM_BIN}/nix-env")
+    }
+}
+
+impl PackageManager for Nix {
+    fn name(&self) -> &'static str {
+        "nix"
+    }
+
+    fn install_command(&self, packages: &[String]) -> Vec<String> {
+        let mut cmd = vec![Self::nix_env()];
+        cmd.extend(args(&["--file", "<nixos>", "--install", "--attr"]));
+        with_packages(cmd, packages)
+    }
+
+    fn query_versions_command(&self, _packages: &[String]) -> Vec<String> {
+        let mut cmd = vec![Self::nix_env()];
+        cmd.extend(args(&["--query", "--installed", "--json"]));
+        cmd
+    }
+
+    /// `nix-env --query --json` maps store names such as `git-2.44.0` to
+    /// objects with `pname` and `version`.
+    fn parse_versions(&self, output: &str) -> Vec<(String, String)> {
+        let Ok(serde_json::Value::Object(entries)) = serde_json::from_str(output) else {
+            return Vec::new();
+        };
+        entries
+            .values()
+            .filter_map(|entry| {
+                let pname = entry.get("pname")?.as_str()?;
+                let version = entry.get("version")?.as_str()?;
+                Some((pname.to_owned(), version.to_owned()))
+            })
+            .collect()
+    }
+
+    fn list_packages_command(&self) -> Vec<String> {
+        let mut cmd = vec![Self::nix_env()];
+        cmd.extend(args(&[
+            "--file",
+            "<nixos>",
+            "--query",
+            "--available",
+            "--attr-path",
+            "--no-name",
+        ]));
+        cmd
+    }
+}
+
+/// The package manager the image's distribution ships, when the image
+/// source names one.
+pub fn package_manager_for(source: &ImageSource) -> Option<&'static dyn PackageManager> {
+    match source {
+        ImageSource::Ubuntu { .. } | ImageSource::Debian { .. } => Some(&Apt),
+        ImageSource::Fedora { .. } => Some(&Dnf),
+        ImageSource::OpenSuse { .. } => Some(&Zypper),
+        ImageSource::Arch => Some(&Pacman),
+        ImageSource::Alpine { .. } => Some(&Apk),
+        ImageSource::NixOs { .. } => Some(&Nix),
+        ImageSource::Custom { .. } | ImageSource::Imported { .. } => None,
+    }
+}
+
+/// The package manager found in `rootfs`.
+pub fn detect_package_manager(rootfs: &Path) -> Option<&'static dyn PackageManager> {
+    if rootfs.join("usr/bin/apt-get").exists() || rootfs.join("usr/bin/apt").exists() {
+        Some(&Apt)
+    } else if rootfs.join("usr/bin/dnf").exists() || rootfs.join("usr/bin/dnf5").exists() {
+        Some(&Dnf)
+    } else if rootfs.join("usr/bin/zypper").exists() {
+        Some(&Zypper)
+    } else if rootfs.join("usr/bin/pacman").exists() {
+        Some(&Pacman)
+    } else if rootfs.join("sbin/apk").exists() {
+        Some(&Apk)
+    } else if is_nixos(rootfs) {
+        Some(&Nix)
+    } else {
+        None
+    }
+}
+
+/// The package manager for building on `image`: the one its distribution
+/// ships, or else the one found in the assembled root at `merged` or in the
+/// image's `rootfs`.
+pub fn select_package_manager(
+    image: &ResolvedImage,
+    merged: &Path,
+    rootfs: &Path,
+) -> Result<&'static dyn PackageManager, RuntimeError> {
+    package_manager_for(&image.source)
+        .or_else(|| detect_package_manager(merged))
+        .or_else(|| detect_package_manager(rootfs))
+        .ok_or_else(|| {
+            RuntimeError::ExecFailed(format!(
+                "no supported package manager found in {}. \
+                 Supported: apt, dnf, zypper, pacman, apk, nix",
+                image.display_name
+            ))
+        })
+}
+
+/// Pair each of `packages` with its version from the output of `pm`'s
+/// version query. A package without one fails resolution, so locks only
+/// ever record installed versions.
+pub fn resolved_versions(
+    pm: &dyn PackageManager,
+    packages: Vec<String>,
+    output: &str,
+) -> Result<Vec<ResolvedPackage>, RuntimeError> {
+    let versions = pm.parse_versions(output);
+    let mut resolved = Vec::with_capacity(packages.len());
+    let mut missing = Vec::new();
+    for name in packages {
+        if let Some((_, version)) = versions.iter().find(|(n, v)| *n == name && !v.is_empty()) {
+            resolved.push(ResolvedPackage {
+                version: version.clone(),
+                name,
+            });
+        } else {
+            missing.push(name);
+        }
+    }
+    if missing.is_empty() {
+        Ok(resolved)
+    } else {
+        Err(RuntimeError::ExecFailed(format!(
+            "{} did not report an installed version for: {}",
+            pm.name(),
+            missing.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::resolve_image;
+
+    fn pkgs(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| (*n).to_owned()).collect()
+    }
+
+    #[test]
+    fn install_commands_correct() {
+        let pkgs = pkgs(&["git", "cmake"]);
+        let cmd = Apt.install_command(&pkgs);
+        assert_eq!(cmd[0], "apt-get");
+        assert!(cmd.contains(&"git".to_owned()));
+
+        let cmd = Zypper.install_command(&pkgs);
+        assert_eq!(cmd[0], "zypper");
+        assert!(cmd.contains(&"--non-interactive".to_owned()));
+
+        assert_eq!(Pacman.install_command(&pkgs)[0], "pacman");
+        assert_eq!(
+            Apk.install_command(&pkgs[..1]),
+            ["apk", "add", "--no-cache", "git"]
+        );
+        let nix = Nix.install_command(&pkgs[..1]);
+        assert_eq!(nix[0], format!("{NIX_SYSTEM_BIN}/nix-env"));
+        assert_eq!(&nix[3..], ["--install", "--attr", "git"]);
+    }
+
+    #[test]
+    fn query_versions_commands_generated() {
+        let pkgs = pkgs(&["git"]);
+        assert_eq!(Apt.query_versions_command(&pkgs)[0], "dpkg-query");
+        assert_eq!(Zypper.query_versions_command(&pkgs)[0], "rpm");
+        assert_eq!(Dnf.query_versions_command(&pkgs)[0], "rpm");
+        assert_eq!(Pacman.query_versions_command(&pkgs)[0], "pacman");
+        assert_eq!(Apk.query_versions_command(&pkgs)[0], "awk");
+        assert!(Nix
+            .query_versions_command(&pkgs)
+            .contains(&"--json".to_owned()));
+    }
+
+    #[test]
+    fn parse_version_outputs() {
+        let apt = Apt.parse_versions("git\t1:2.43.0-1ubuntu7\nclang\t1:18.1.3-1\n");
+        assert_eq!(apt[0], ("git".to_owned(), "1:2.43.0-1ubuntu7".to_owned()));
+        assert_eq!(apt[1], ("clang".to_owned(), "1:18.1.3-1".to_owned()));
+
+        let rpm = Zypper.parse_versions("git\t2.44.0-1.fc41\ncmake\t3.28.3-1.fc41\n");
+        assert_eq!(rpm[0], ("git".to_owned(), "2.44.0-1.fc41".to_owned()));
+
+        let pacman = Pacman.parse_versions("git 2.44.0-1\ncmake 3.28.3-1\n");
+        assert_eq!(pacman[0], ("git".to_owned(), "2.44.0-1".to_owned()));
+
+        let apk = Apk.parse_versions("musl\t1.2.5-r0\ngit\t2.45.2-r0\n");
+        assert_eq!(apk[1], ("git".to_owned(), "2.45.2-r0".to_owned()));
+
+        let nix = r#"{"git-2.44.0":{"name":"git-2.44.0","pname":"git","version":"2.44.0"},
+                      "odd":{"name":"odd"}}"#;
+        assert_eq!(
+            Nix.parse_versions(nix),
+            [("git".to_owned(), "2.44.0".to_owned())]
+        );
+        assert!(Nix.parse_versions("not json").is_empty());
+        assert!(Apt.parse_versions("\n\n").is_empty());
+        assert_eq!(Apt.parse_package_index("vim\n\ngit\nvim\n"), ["git", "vim"]);
+    }
+
+    #[test]
+    fn list_packages_commands_generated() {
+        assert_eq!(Apt.list_packages_command(), ["apt-cache", "pkgnames"]);
+        assert_eq!(Pacman.list_packages_command(), ["pacman", "-Slq"]);
+        assert_eq!(Dnf.list_packages_command()[1], "repoquery");
+        assert_eq!(Zypper.list_packages_command()[3], "search");
+        assert_eq!(Apk.list_packages_command()[2], "search");
+        assert!(Nix
+            .list_packages_command()
+            .contains(&"--attr-path".to_owned()));
+    }
+
+    #[test]
+    fn parse_zypper_package_index() {
+        let output = "S | Name        | Summary     | Type\n\
+                      --+-------------+-------------+--------\n\
+                        | git         | Fast VCS    | package\n\
+                      i | python3-devel | Headers   | package\n";
+        assert_eq!(Zypper.parse_package_index(output), ["git", "python3-devel"]);
+    }
+
+    #[test]
+    fn detect_package_managers() {
+        let name = |dir: &Path| detect_package_manager(dir).map(PackageManager::name);
+        let empty = tempfile::tempdir().unwrap();
+        assert_eq!(name(empty.path()), None);
+
+        for (file, expected) in [
+            ("usr/bin/apt-get", "apt"),
+            ("usr/bin/dnf5", "dnf"),
+            ("usr/bin/zypper", "zypper"),
+            ("usr/bin/pacman", "pacman"),
+            ("sbin/apk", "apk"),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "").unwrap();
+            assert_eq!(name(dir.path()), Some(expected), "{file}");
+        }
+
+        // The system profile links into the image's store, which does not
+        // resolve on the host.
+        let nixos = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(nixos.path().join("nix/var/nix/profiles")).unwrap();
+        std::os::unix::fs::symlink(
+            "/nix/store/0000-nixos-system",
+            nixos.path().join("nix/var/nix/profiles/system"),
+        )
+        .unwrap();
+        assert_eq!(name(nixos.path()), Some("nix"));
+    }
+
+    #[test]
+    fn selection_prefers_the_image_distribution() {
+        let empty = tempfile::tempdir().unwrap();
+        let alpine = resolve_image("alpine").unwrap();
+        let pm = select_package_manager(&alpine, empty.path(), empty.path()).unwrap();
+        assert_eq!(pm.name(), "apk");
+
+        let custom = resolve_image("https://example.com/rootfs.tar.xz").unwrap();
+        assert!(select_package_manager(&custom, empty.path(), empty.path()).is_err());
+        std::fs::create_dir_all(empty.path().join("usr/bin")).unwrap();
+        std::fs::write(empty.path().join("usr/bin/pacman"), "").unwrap();
+        let pm = select_package_manager(&custom, empty.path(), empty.path()).unwrap();
+        assert_eq!(pm.name(), "pacman");
+    }
+
+    #[test]
+    fn packages_without_a_version_fail_resolution() {
+        let resolved =
+            resolved_versions(&Apt, pkgs(&["git"]), "git\t1:2.43.0\nclang\t18\n").unwrap();
+        assert_eq!(resolved[0].version, "1:2.43.0");
+
+        let err = resolved_versions(&Apt, pkgs(&["git", "vim"]), "git\t1:2.43.0\n").unwrap_err();
+        assert!(err.to_string().contains("vim"), "{err}");
+    }
+}
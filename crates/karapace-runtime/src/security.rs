@@ -1,6 +1,8 @@
 use crate::RuntimeError;
-use karapace_schema::NormalizedManifest;
+use karapace_schema::{NetworkMode, NormalizedManifest};
 use serde::{Deserialize, Serialize};
+use std::os::unix::io::OwnedFd;
+use std::path::{Path, PathBuf};
 
 /// Resolve `.` and `..` components in an absolute path without touching the filesystem.
 ///
@@ -20,27 +22,172 @@ fn canonicalize_logical(path: &str) -> String {
     format!("/{}", parts.join("/"))
 }
 
+/// Component-wise prefix check: `/home` covers `/home/user` but not `/homework`.
+fn path_is_under(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty()
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// `struct open_how` from `<linux/openat2.h>`.
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+const RESOLVE_BENEATH: u64 = 0x08;
+
+/// Open `rel` beneath the directory `root` with `openat2(RESOLVE_BENEATH)`
+/// and return the `O_PATH` descriptor of what the kernel resolved.
+///
+/// The kernel refuses (`EXDEV`) any resolution that leaves `root`, whether via
+/// `..`, an absolute symlink, or a relative symlink climbing out of it. Magic
+/// links such as `/proc/self/root` are rejected outright.
+#[allow(unsafe_code)]
+fn openat2_beneath(root: &Path, rel: &Path) -> std::io::Result<OwnedFd> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let dir = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+        .open(root)?;
+
+    let rel_bytes = if rel.as_os_str().is_empty() {
+        b".".as_slice()
+    } else {
+        rel.as_os_str().as_bytes()
+    };
+    let c_rel = CString::new(rel_bytes)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let how = OpenHow {
+        flags: (libc::O_PATH | libc::O_CLOEXEC) as u64,
+        mode: 0,
+        resolve: RESOLVE_BENEATH | RESOLVE_NO_MAGICLINKS,
+    };
+
+    // SAFETY: `dir` is a valid open descriptor for the duration of the call,
+    // `c_rel` is NUL-terminated, and `how` is a properly sized `open_how`.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir.as_raw_fd(),
+            c_rel.as_ptr(),
+            std::ptr::addr_of!(how),
+            size_of::<OpenHow>(),
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: openat2 returned a fresh descriptor that nothing else owns.
+    Ok(unsafe { OwnedFd::from_raw_fd(ret as i32) })
+}
+
+/// The path an open descriptor refers to, as the kernel reports it.
+fn fd_path(fd: &OwnedFd) -> std::io::Result<PathBuf> {
+    use std::os::unix::io::AsRawFd;
+    std::fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+}
+
+fn resolve_beneath(root: &Path, rel: &Path) -> std::io::Result<OwnedFd> {
+    match openat2_beneath(root, rel) {
+        Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => canonicalize_beneath(root, rel),
+        other => other,
+    }
+}
+
+/// Fallback for kernels without openat2 (< 5.6): resolve with `realpath`,
+/// open the result and check that what was opened is still under the root.
+/// Racier than openat2, but still catches symlinks that were planted before
+/// enter.
+fn canonicalize_beneath(root: &Path, rel: &Path) -> std::io::Result<OwnedFd> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let real_root = std::fs::canonicalize(root)?;
+    let resolved = std::fs::canonicalize(root.join(rel))?;
+    let fd = OwnedFd::from(
+        std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_NOFOLLOW)
+            .open(resolved)?,
+    );
+    if fd_path(&fd)?.starts_with(&real_root) {
+        Ok(fd)
+    } else {
+        Err(std::io::Error::from_raw_os_error(libc::EXDEV))
+    }
+}
+
+/// A manifest mount source resolved by
+/// [`SecurityPolicy::resolve_mount_source`].
+#[derive(Debug)]
+pub struct ResolvedSource {
+    /// The path the kernel resolved.
+    pub path: PathBuf,
+    /// `O_PATH` descriptor of the resolved source. Binding through it, rather
+    /// than through `path`, keeps a component swapped after the check from
+    /// redirecting the bind.
+    pub fd: OwnedFd,
+}
+
+#[allow(clippy::struct_excessive_bools)] // independent policy flags
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct SecurityPolicy {
     pub allowed_mount_prefixes: Vec<String>,
+    /// Extra prefixes that may only be bound read-only (`:ro`).
+    #[serde(default = "default_read_only_mount_prefixes")]
+    pub read_only_mount_prefixes: Vec<String>,
     pub allowed_devices: Vec<String>,
     pub allow_network: bool,
+    /// How sessions reach the network; `allow_network` is whether that
+    /// includes outbound access.
+    pub network_mode: NetworkMode,
     pub allow_gpu: bool,
-    pub allow_audio: bool,
+    /// Audio playback.
+    #[serde(alias = "allow_audio")]
+    pub allow_audio_out: bool,
+    /// Microphone / audio capture.
+    #[serde(default)]
+    pub allow_audio_in: bool,
+    /// Video capture devices.
+    #[serde(default)]
+    pub allow_camera: bool,
     pub allowed_env_vars: Vec<String>,
     pub denied_env_vars: Vec<String>,
     pub max_cpu_shares: Option<u64>,
     pub max_memory_mb: Option<u64>,
+    pub max_overlay_mb: Option<u64>,
+}
+
+fn default_read_only_mount_prefixes() -> Vec<String> {
+    vec![
+        "/opt".to_owned(),
+        "/srv".to_owned(),
+        "/usr/share".to_owned(),
+    ]
 }
 
 impl Default for SecurityPolicy {
     fn default() -> Self {
         Self {
             allowed_mount_prefixes: vec!["/home".to_owned(), "/tmp".to_owned()],
+            read_only_mount_prefixes: default_read_only_mount_prefixes(),
             allowed_devices: Vec::new(),
             allow_network: false,
+            network_mode: NetworkMode::Isolated,
             allow_gpu: false,
-            allow_audio: false,
+            allow_audio_out: false,
+            allow_audio_in: false,
+            allow_camera: false,
             allowed_env_vars: vec![
                 "TERM".to_owned(),
                 "LANG".to_owned(),
@@ -58,6 +205,7 @@ impl Default for SecurityPolicy {
             ],
             max_cpu_shares: None,
             max_memory_mb: None,
+            max_overlay_mb: None,
         }
     }
 }
@@ -68,52 +216,166 @@ impl SecurityPolicy {
         if manifest.hardware_gpu {
             allowed_devices.push("/dev/dri".to_owned());
         }
-        if manifest.hardware_audio {
+        if manifest.hardware_audio_out || manifest.hardware_audio_in {
             allowed_devices.push("/dev/snd".to_owned());
         }
+        if manifest.hardware_camera {
+            allowed_devices.push("/dev/video*".to_owned());
+            allowed_devices.push("/dev/media*".to_owned());
+        }
 
         Self {
             allow_gpu: manifest.hardware_gpu,
-            allow_audio: manifest.hardware_audio,
-            allow_network: !manifest.network_isolation,
+            allow_audio_out: manifest.hardware_audio_out,
+            allow_audio_in: manifest.hardware_audio_in,
+            allow_camera: manifest.hardware_camera,
+            allow_network: manifest.network_mode.has_outbound(),
+            network_mode: manifest.network_mode,
             allowed_devices,
             max_cpu_shares: manifest.cpu_shares,
             max_memory_mb: manifest.memory_limit_mb,
+            max_overlay_mb: manifest.max_overlay_mb,
             ..Self::default()
         }
     }
 
+    /// Prefixes a mount may come from: the read-only ones count only when
+    /// the mount is bound read-only.
+    fn mount_prefixes(&self, read_only: bool) -> impl Iterator<Item = &String> {
+        let read_only_prefixes: &[String] = if read_only {
+            &self.read_only_mount_prefixes
+        } else {
+            &[]
+        };
+        self.allowed_mount_prefixes.iter().chain(read_only_prefixes)
+    }
+
+    fn mount_denied(&self, host: &str, canonical: &str, read_only: bool) -> RuntimeError {
+        if read_only {
+            RuntimeError::MountDenied(format!(
+                "mount '{host}' (resolved: {canonical}) is not under any allowed prefix: {:?} (read-only: {:?})",
+                self.allowed_mount_prefixes, self.read_only_mount_prefixes
+            ))
+        } else {
+            RuntimeError::MountDenied(format!(
+                "mount '{host}' (resolved: {canonical}) is not under any allowed prefix: {:?}",
+                self.allowed_mount_prefixes
+            ))
+        }
+    }
+
     pub fn validate_mounts(&self, manifest: &NormalizedManifest) -> Result<(), RuntimeError> {
         for mount in &manifest.mounts {
             let host = &mount.host_path;
             if host.starts_with('/') {
                 let canonical = canonicalize_logical(host);
+                let read_only = mount.is_read_only();
                 let allowed = self
-                    .allowed_mount_prefixes
-                    .iter()
-                    .any(|prefix| canonical.starts_with(prefix));
+                    .mount_prefixes(read_only)
+                    .any(|prefix| path_is_under(&canonical, prefix));
                 if !allowed {
-                    return Err(RuntimeError::MountDenied(format!(
-                        "mount '{host}' (resolved: {canonical}) is not under any allowed prefix: {:?}",
-                        self.allowed_mount_prefixes
-                    )));
+                    return Err(self.mount_denied(host, &canonical, read_only));
                 }
             }
         }
         Ok(())
     }
 
+    /// Resolve a manifest mount's host path against the live filesystem.
+    ///
+    /// `validate_mounts` only checks the declared path string at build time;
+    /// this runs at enter time and follows symlinks, so a link planted inside
+    /// an allowed root that points outside it is rejected. Absolute paths are
+    /// confined to the allowed prefix they fall under, `~/` paths to `$HOME`,
+    /// and relative paths to the current directory. `read_only` admits the
+    /// read-only prefixes as well. The source comes back open, and is bound
+    /// through its descriptor.
+    pub fn resolve_mount_source(
+        &self,
+        host_path: &str,
+        read_only: bool,
+    ) -> Result<ResolvedSource, RuntimeError> {
+        let (root, rel) = if host_path.starts_with('/') {
+            let canonical = canonicalize_logical(host_path);
+            let prefix = self
+                .mount_prefixes(read_only)
+                .filter(|prefix| path_is_under(&canonical, prefix))
+                .max_by_key(|prefix| prefix.len())
+                .ok_or_else(|| self.mount_denied(host_path, &canonical, read_only))?;
+            let root = PathBuf::from(prefix);
+            let rel = Path::new(&canonical)
+                .strip_prefix(&root)
+                .unwrap_or(Path::new(""))
+                .to_path_buf();
+            (root, rel)
+        } else {
+            let root = if host_path.starts_with("~/") {
+                std::env::var("HOME").map(PathBuf::from).map_err(|_| {
+                    RuntimeError::MountDenied(format!("mount '{host_path}': HOME is not set"))
+                })?
+            } else {
+                std::env::current_dir()?
+            };
+            let expanded = crate::host::expand_path(host_path);
+            let rel = expanded
+                .strip_prefix(&root)
+                .unwrap_or(&expanded)
+                .to_path_buf();
+            (root, rel)
+        };
+
+        match resolve_beneath(&root, &rel) {
+            Ok(fd) => Ok(ResolvedSource {
+                path: fd_path(&fd)?,
+                fd,
+            }),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EXDEV | libc::ELOOP)) => {
+                Err(RuntimeError::MountDenied(format!(
+                    "mount '{host_path}' escapes {} via symlink or '..'",
+                    root.display()
+                )))
+            }
+            Err(e) => Err(RuntimeError::Io(e)),
+        }
+    }
+
     pub fn validate_devices(&self, manifest: &NormalizedManifest) -> Result<(), RuntimeError> {
         if manifest.hardware_gpu && !self.allow_gpu {
             return Err(RuntimeError::DeviceDenied(
                 "GPU access requested but not allowed by policy".to_owned(),
             ));
         }
-        if manifest.hardware_audio && !self.allow_audio {
+        if manifest.hardware_audio_out && !self.allow_audio_out {
             return Err(RuntimeError::DeviceDenied(
-                "audio access requested but not allowed by policy".to_owned(),
+                "audio playback requested but not allowed by policy".to_owned(),
             ));
         }
+        if manifest.hardware_audio_in && !self.allow_audio_in {
+            return Err(RuntimeError::DeviceDenied(
+                "audio capture (microphone) requested but not allowed by policy".to_owned(),
+            ));
+        }
+        if manifest.hardware_camera && !self.allow_camera {
+            return Err(RuntimeError::DeviceDenied(
+                "camera access requested but not allowed by policy".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_network(&self, manifest: &NormalizedManifest) -> Result<(), RuntimeError> {
+        let mode = manifest.network_mode;
+        if mode.has_outbound() && !self.allow_network {
+            return Err(RuntimeError::PolicyViolation(format!(
+                "network mode '{mode}' needs network access, which is not allowed by policy"
+            )));
+        }
+        if mode == NetworkMode::Host && self.network_mode != NetworkMode::Host {
+            return Err(RuntimeError::PolicyViolation(format!(
+                "host networking requested but policy allows only '{}'",
+                self.network_mode
+            )));
+        }
         Ok(())
     }
 
@@ -148,6 +410,13 @@ impl SecurityPolicy {
                 )));
             }
         }
+        if let (Some(req), Some(max)) = (manifest.max_overlay_mb, self.max_overlay_mb) {
+            if req > max {
+                return Err(RuntimeError::PolicyViolation(format!(
+                    "requested max_overlay_mb {req}MB exceeds policy max {max}MB"
+                )));
+            }
+        }
         Ok(())
     }
 }
@@ -176,6 +445,31 @@ gpu = true
         assert!(policy.validate_devices(&manifest).is_err());
     }
 
+    #[test]
+    fn network_policy_follows_mode() {
+        let manifest = |network: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[network]\n{network}"
+            ))
+            .unwrap()
+            .normalize()
+            .unwrap()
+        };
+        let slirp = manifest("mode = \"slirp\"");
+        let policy = SecurityPolicy::from_manifest(&slirp);
+        assert!(policy.allow_network);
+        assert_eq!(policy.network_mode, NetworkMode::Slirp);
+        assert!(policy.validate_network(&slirp).is_ok());
+        assert!(policy.validate_network(&manifest("")).is_err());
+
+        let default = SecurityPolicy::default();
+        assert!(default.validate_network(&slirp).is_err());
+        assert!(default
+            .validate_network(&manifest("mode = \"none\""))
+            .is_ok());
+        assert!(!SecurityPolicy::from_manifest(&manifest("mode = \"isolated\"")).allow_network);
+    }
+
     #[test]
     fn manifest_derived_policy_allows_declared_hardware() {
         let manifest = parse_manifest_str(
@@ -195,10 +489,48 @@ audio = true
         let policy = SecurityPolicy::from_manifest(&manifest);
         assert!(policy.validate_devices(&manifest).is_ok());
         assert!(policy.allow_gpu);
-        assert!(policy.allow_audio);
+        assert!(policy.allow_audio_out);
+        assert!(!policy.allow_audio_in);
+        assert!(!policy.allow_camera);
         assert!(policy.allowed_devices.contains(&"/dev/dri".to_owned()));
     }
 
+    #[test]
+    fn capture_grants_are_separate_from_playback() {
+        let manifest = parse_manifest_str(
+            r#"
+manifest_version = 1
+[base]
+image = "rolling"
+[hardware]
+audio_out = true
+audio_in = true
+camera = true
+"#,
+        )
+        .unwrap()
+        .normalize()
+        .unwrap();
+
+        let policy = SecurityPolicy::from_manifest(&manifest);
+        assert!(policy.validate_devices(&manifest).is_ok());
+        assert!(policy.allowed_devices.contains(&"/dev/video*".to_owned()));
+
+        let playback_only = SecurityPolicy {
+            allow_audio_out: true,
+            ..SecurityPolicy::default()
+        };
+        let err = playback_only.validate_devices(&manifest).unwrap_err();
+        assert!(err.to_string().contains("microphone"), "{err}");
+
+        let no_camera = SecurityPolicy {
+            allow_camera: false,
+            ..policy
+        };
+        let err = no_camera.validate_devices(&manifest).unwrap_err();
+        assert!(err.to_string().contains("camera"), "{err}");
+    }
+
     #[test]
     fn absolute_mounts_checked_against_whitelist() {
         let manifest = parse_manifest_str(
@@ -240,6 +572,7 @@ manifest_version = 1
 image = "rolling"
 [runtime]
 backend = "namespace"
+max_overlay_mb = 4096
 [runtime.resource_limits]
 cpu_shares = 2048
 memory_limit_mb = 8192
@@ -254,6 +587,10 @@ memory_limit_mb = 8192
 
         policy.max_cpu_shares = Some(1024);
         assert!(policy.validate_resource_limits(&manifest).is_err());
+
+        policy.max_cpu_shares = None;
+        policy.max_overlay_mb = Some(2048);
+        assert!(policy.validate_resource_limits(&manifest).is_err());
     }
 
     #[test]
@@ -385,4 +722,220 @@ bad = "/proc/self/root:/escape"
             "/proc must be rejected"
         );
     }
+
+    fn confined_policy(root: &Path) -> SecurityPolicy {
+        SecurityPolicy {
+            allowed_mount_prefixes: vec![root.to_string_lossy().into_owned()],
+            ..SecurityPolicy::default()
+        }
+    }
+
+    #[test]
+    fn prefix_match_is_component_wise() {
+        assert!(path_is_under("/home", "/home"));
+        assert!(path_is_under("/home/user", "/home"));
+        assert!(path_is_under("/home/user", "/home/"));
+        assert!(!path_is_under("/homework", "/home"));
+        assert!(!path_is_under("/home-evil/x", "/home"));
+    }
+
+    #[test]
+    fn sibling_prefix_mount_is_rejected() {
+        let manifest = parse_manifest_str(
+            r#"
+manifest_version = 1
+[base]
+image = "rolling"
+[mounts]
+bad = "/homeevil/data:/data"
+"#,
+        )
+        .unwrap()
+        .normalize()
+        .unwrap();
+
+        let policy = SecurityPolicy::default();
+        assert!(policy.validate_mounts(&manifest).is_err());
+    }
+
+    #[test]
+    fn read_only_prefixes_require_ro_mounts() {
+        let parse = |mounts: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[mounts]\n{mounts}\n"
+            ))
+            .unwrap()
+            .normalize()
+            .unwrap()
+        };
+        let policy = SecurityPolicy::default();
+        assert!(policy
+            .validate_mounts(&parse(r#"data = "/srv/data:/data:ro,nodev""#))
+            .is_ok());
+        assert!(matches!(
+            policy.validate_mounts(&parse(r#"data = "/srv/data:/data:nodev""#)),
+            Err(RuntimeError::MountDenied(_))
+        ));
+        assert!(policy
+            .validate_mounts(&parse(r#"data = "/etc/ssl:/ssl:ro""#))
+            .is_err());
+    }
+
+    #[test]
+    fn resolve_read_only_prefix_only_for_ro_mounts() {
+        let root = tempfile::tempdir().unwrap();
+        let data = root.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        let policy = SecurityPolicy {
+            allowed_mount_prefixes: Vec::new(),
+            read_only_mount_prefixes: vec![root.path().to_string_lossy().into_owned()],
+            ..SecurityPolicy::default()
+        };
+        assert!(matches!(
+            policy.resolve_mount_source(&data.to_string_lossy(), false),
+            Err(RuntimeError::MountDenied(_))
+        ));
+        assert_eq!(
+            policy
+                .resolve_mount_source(&data.to_string_lossy(), true)
+                .unwrap()
+                .path,
+            std::fs::canonicalize(&data).unwrap()
+        );
+    }
+
+    #[test]
+    fn resolve_plain_directory_beneath_root() {
+        let root = tempfile::tempdir().unwrap();
+        let data = root.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+
+        let policy = confined_policy(root.path());
+        let resolved = policy
+            .resolve_mount_source(&data.to_string_lossy(), false)
+            .unwrap();
+        assert_eq!(resolved.path, std::fs::canonicalize(&data).unwrap());
+    }
+
+    #[test]
+    fn resolve_rejects_absolute_symlink_escape() {
+        let root = tempfile::tempdir().unwrap();
+        let link = root.path().join("escape");
+        std::os::unix::fs::symlink("/etc", &link).unwrap();
+
+        let policy = confined_policy(root.path());
+        let err = policy
+            .resolve_mount_source(&link.to_string_lossy(), false)
+            .unwrap_err();
+        assert!(matches!(err, RuntimeError::MountDenied(_)), "got {err:?}");
+    }
+
+    #[test]
+    fn resolve_rejects_relative_symlink_escape() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let nested = root.path().join("a/b");
+        std::fs::create_dir_all(&nested).unwrap();
+        // a/b/up -> ../../.. climbs out of the allowed root
+        std::os::unix::fs::symlink("../../..", nested.join("up")).unwrap();
+        let target = nested.join("up").join(
+            outside
+                .path()
+                .strip_prefix(root.path().parent().unwrap())
+                .unwrap_or(outside.path()),
+        );
+
+        let policy = confined_policy(root.path());
+        let err = policy
+            .resolve_mount_source(&target.to_string_lossy(), false)
+            .unwrap_err();
+        assert!(matches!(err, RuntimeError::MountDenied(_)), "got {err:?}");
+    }
+
+    #[test]
+    fn resolve_rejects_symlinked_intermediate_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret"), "s").unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("proj")).unwrap();
+
+        let policy = confined_policy(root.path());
+        let err = policy
+            .resolve_mount_source(&root.path().join("proj/secret").to_string_lossy(), false)
+            .unwrap_err();
+        assert!(matches!(err, RuntimeError::MountDenied(_)), "got {err:?}");
+    }
+
+    #[test]
+    fn resolve_allows_symlink_within_root() {
+        let root = tempfile::tempdir().unwrap();
+        let real = root.path().join("real");
+        std::fs::create_dir(&real).unwrap();
+        std::os::unix::fs::symlink("real", root.path().join("alias")).unwrap();
+
+        let policy = confined_policy(root.path());
+        let resolved = policy
+            .resolve_mount_source(&root.path().join("alias").to_string_lossy(), false)
+            .unwrap();
+        assert_eq!(resolved.path, std::fs::canonicalize(&real).unwrap());
+    }
+
+    #[test]
+    fn resolved_source_survives_a_swapped_component() {
+        use std::os::unix::io::AsRawFd;
+
+        let root = tempfile::tempdir().unwrap();
+        let data = root.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        std::fs::write(data.join("marker"), "m").unwrap();
+
+        let policy = confined_policy(root.path());
+        let resolved = policy
+            .resolve_mount_source(&data.to_string_lossy(), false)
+            .unwrap();
+
+        // Swap the checked directory for a symlink out of the root.
+        let moved = root.path().join("moved");
+        std::fs::rename(&data, &moved).unwrap();
+        std::os::unix::fs::symlink("/etc", &data).unwrap();
+
+        let pinned = PathBuf::from(format!("/proc/self/fd/{}", resolved.fd.as_raw_fd()));
+        assert_eq!(
+            std::fs::read_link(&pinned).unwrap(),
+            std::fs::canonicalize(&moved).unwrap()
+        );
+        assert!(pinned.join("marker").exists());
+        assert!(!pinned.join("passwd").exists());
+    }
+
+    #[test]
+    fn resolve_rejects_magic_proc_links() {
+        let policy = confined_policy(Path::new("/proc"));
+        assert!(policy
+            .resolve_mount_source("/proc/self/root", false)
+            .is_err());
+    }
+
+    #[test]
+    fn resolve_missing_path_reports_not_found() {
+        let root = tempfile::tempdir().unwrap();
+        let policy = confined_policy(root.path());
+        let err = policy
+            .resolve_mount_source(&root.path().join("missing").to_string_lossy(), false)
+            .unwrap_err();
+        assert!(
+            matches!(&err, RuntimeError::Io(e) if e.kind() == std::io::ErrorKind::NotFound),
+            "got {err:?}"
+        );
+    }
+
+    #[test]
+    fn resolve_outside_prefixes_is_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let policy = confined_policy(root.path());
+        assert!(matches!(
+            policy.resolve_mount_source("/etc", false),
+            Err(RuntimeError::MountDenied(_))
+        ));
+    }
 }
@@ -0,0 +1,154 @@
+//! Detached sessions.
+//!
+//! `karapace enter --detach` starts an environment's sandbox without a
+//! terminal and leaves it running after karapace exits. `karapace attach`
+//! joins the session's namespaces with `nsenter` and starts a shell in its
+//! root, so any number of shells share one running environment.
+//!
+//! A detached session is recorded in [`SESSION_FILE`] in the environment
+//! directory: the sandbox PID and, for `slirp` sessions, the slirp4netns
+//! PID and API socket, which the session keeps after karapace exits, and
+//! the cgroup holding its resource limits.
+
+use crate::sandbox::SandboxConfig;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Record of a detached session in the environment directory.
+pub const SESSION_FILE: &str = "session.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DetachedSession {
+    /// The sandbox process, also written to `.running`.
+    pub pid: u32,
+    /// slirp4netns connecting a `slirp` session to the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slirp_pid: Option<u32>,
+    /// The slirp4netns API socket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_socket: Option<PathBuf>,
+    /// The session's cgroup, see [`crate::cgroup`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<PathBuf>,
+}
+
+impl DetachedSession {
+    /// The session recorded in `env_dir`, if any.
+    pub fn load(env_dir: &Path) -> Option<Self> {
+        let data = std::fs::read(env_dir.join(SESSION_FILE)).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    pub fn save(&self, env_dir: &Path) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(env_dir.join(SESSION_FILE), data)
+    }
+
+    pub fn remove(env_dir: &Path) {
+        let _ = std::fs::remove_file(env_dir.join(SESSION_FILE));
+    }
+
+    /// Whether the sandbox process still exists.
+    pub fn is_alive(&self) -> bool {
+        Path::new(&format!("/proc/{}", self.pid)).exists()
+    }
+
+    /// Stop slirp4netns, remove its API socket and the session's cgroup.
+    /// The sandbox itself is stopped through `.running` like any other
+    /// session.
+    pub fn release(&self) {
+        if let Some(pid) = self.slirp_pid {
+            terminate(pid);
+        }
+        if let Some(socket) = &self.api_socket {
+            let _ = std::fs::remove_file(socket);
+        }
+        if let Some(cgroup) = &self.cgroup {
+            crate::cgroup::remove(cgroup);
+        }
+    }
+}
+
+fn terminate(pid: u32) {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return;
+    };
+    // SAFETY: kill() only delivers a signal; a stale pid yields ESRCH.
+    #[allow(unsafe_code)]
+    unsafe {
+        libc::kill(pid, libc::SIGTERM);
+    }
+}
+
+/// A process of the session chrooted into `merged`, whose namespaces and
+/// root `attach` joins. The lowest such PID is the session's own shell.
+pub fn attach_target(merged: &Path) -> Option<u32> {
+    let merged = std::fs::canonicalize(merged).ok()?;
+    std::fs::read_dir("/proc")
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let root = std::fs::read_link(entry.path().join("root")).ok()?;
+            (root == merged).then_some(pid)
+        })
+        .min()
+}
+
+/// `nsenter` joining the namespaces and root of `target`, with the working
+/// directory left to the caller's script.
+pub fn nsenter_command(config: &SandboxConfig, target: u32) -> Command {
+    let mut cmd = Command::new("nsenter");
+    cmd.arg(format!("--target={target}")).args([
+        "--user",
+        "--mount",
+        "--pid",
+        "--preserve-credentials",
+        "--root",
+    ]);
+    if config.unshares_network() {
+        cmd.arg("--net");
+    }
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karapace_schema::NetworkMode;
+
+    #[test]
+    fn session_record_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(DetachedSession::load(dir.path()).is_none());
+
+        let session = DetachedSession {
+            pid: std::process::id(),
+            slirp_pid: None,
+            api_socket: Some(dir.path().join("net/slirp.sock")),
+            cgroup: None,
+        };
+        session.save(dir.path()).unwrap();
+        let loaded = DetachedSession::load(dir.path()).unwrap();
+        assert_eq!(loaded, session);
+        assert!(loaded.is_alive());
+
+        DetachedSession::remove(dir.path());
+        assert!(DetachedSession::load(dir.path()).is_none());
+    }
+
+    #[test]
+    fn nsenter_joins_the_network_namespace_only_when_unshared() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SandboxConfig::new(PathBuf::from("/rootfs"), "abc123def456", dir.path());
+        let joins_net =
+            |config: &SandboxConfig| nsenter_command(config, 42).get_args().any(|a| a == "--net");
+        assert!(!joins_net(&config));
+        config.network = NetworkMode::Isolated;
+        assert!(joins_net(&config));
+        assert!(nsenter_command(&config, 42)
+            .get_args()
+            .any(|a| a == "--target=42"));
+    }
+}
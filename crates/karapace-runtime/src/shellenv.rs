@@ -0,0 +1,182 @@
+//! Shell integration for environments.
+//!
+//! `karapace shellenv` writes a shim for each command it is given: a small
+//! script that runs the command with `karapace exec` in the environment.
+//! The shims live in the environment's `shims` directory, which the
+//! printed shell code puts first on `PATH`. Since direnv only carries
+//! environment variables, not functions or aliases, this also works from an
+//! `.envrc`, and leaving the directory restores the previous `PATH`.
+
+use crate::RuntimeError;
+use std::path::{Path, PathBuf};
+
+/// The shim directory of the environment whose directory is `env_dir`.
+pub fn shims_dir(env_dir: &Path) -> PathBuf {
+    env_dir.join("shims")
+}
+
+/// Whether `name` can be a shim: a plain command name, not a path or an
+/// option.
+pub fn is_command_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '+' | '-'))
+}
+
+/// Replace the shims in `dir` with one for each of `commands`, running
+/// them in `env_id` of the store at `store_path`.
+pub fn write_shims(
+    dir: &Path,
+    commands: &[String],
+    env_id: &str,
+    karapace_bin: &str,
+    store_path: &str,
+) -> Result<(), RuntimeError> {
+    if let Some(bad) = commands.iter().find(|c| !is_command_name(c)) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("'{bad}' is not a command name"),
+        )
+        .into());
+    }
+    std::fs::create_dir_all(dir)?;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if !commands.iter().any(|c| name.to_str() == Some(c)) {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    for command in commands {
+        let path = dir.join(command);
+        std::fs::write(
+            &path,
+            shim_script(command, env_id, karapace_bin, store_path),
+        )?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    Ok(())
+}
+
+/// A shim running `command` in `env_id`, on a pseudo-terminal when it is
+/// run from one.
+fn shim_script(command: &str, env_id: &str, karapace_bin: &str, store_path: &str) -> String {
+    let exec = format!(
+        "exec {} --store {} exec",
+        sh_quote(karapace_bin),
+        sh_quote(store_path)
+    );
+    let target = format!("{} -- {} \"$@\"", sh_quote(env_id), sh_quote(command));
+    format!(
+        "#!/bin/sh\n\
+         # Runs {command} in karapace environment {}.\n\
+         if [ -t 0 ] && [ -t 1 ]; then\n    {exec} -t {target}\nfi\n\
+         {exec} {target}\n",
+        &env_id[..12.min(env_id.len())]
+    )
+}
+
+/// POSIX shell code exporting `KARAPACE_ENV` and putting `shims` first on
+/// `PATH`, once however often it is evaluated.
+pub fn posix_exports(env_id: &str, shims: &Path) -> String {
+    let dir = sh_quote(&shims.to_string_lossy());
+    format!(
+        "export KARAPACE_ENV={}\n\
+         case \":$PATH:\" in *:{dir}:*) ;; *) export PATH={dir}:\"$PATH\" ;; esac\n",
+        sh_quote(env_id)
+    )
+}
+
+/// [`posix_exports`] for fish.
+pub fn fish_exports(env_id: &str, shims: &Path) -> String {
+    let dir = fish_quote(&shims.to_string_lossy());
+    format!(
+        "set -gx KARAPACE_ENV {}\n\
+         contains -- {dir} $PATH; or set -gx PATH {dir} $PATH\n",
+        fish_quote(env_id)
+    )
+}
+
+/// An `.envrc` routing `commands` into the environment named `env_name`.
+pub fn envrc(env_name: &str, commands: &[String]) -> String {
+    let mut args = sh_quote(env_name);
+    let env_name = env_name.replace(['\n', '\r'], " ");
+    for command in commands {
+        args.push(' ');
+        args.push_str(&sh_quote(command));
+    }
+    format!(
+        "# Run these commands in the karapace environment {env_name}.\n\
+         # Build it with `karapace build --name {env_name}`, then `direnv allow`.\n\
+         eval \"$(karapace shellenv {args})\"\n"
+    )
+}
+
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_names_exclude_paths_and_options() {
+        assert!(is_command_name("cargo"));
+        assert!(is_command_name("g++"));
+        assert!(is_command_name("python3.12"));
+        for bad in ["", "-rf", ".hidden", "bin/sh", "a b", "x;y"] {
+            assert!(!is_command_name(bad), "{bad}");
+        }
+    }
+
+    #[test]
+    fn shims_replace_the_previous_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let shims = shims_dir(dir.path());
+        let env_id = "a".repeat(64);
+        write_shims(
+            &shims,
+            &["cargo".to_owned(), "rustc".to_owned()],
+            &env_id,
+            "/usr/bin/karapace",
+            "/home/u/store",
+        )
+        .unwrap();
+        write_shims(&shims, &["cargo".to_owned()], &env_id, "karapace", "/s").unwrap();
+
+        assert!(!shims.join("rustc").exists());
+        let script = std::fs::read_to_string(shims.join("cargo")).unwrap();
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains(&format!(
+            "exec 'karapace' --store '/s' exec '{env_id}' -- 'cargo' \"$@\""
+        )));
+        assert!(write_shims(&shims, &["../x".to_owned()], &env_id, "k", "/s").is_err());
+    }
+
+    #[test]
+    fn exports_quote_paths() {
+        let shims = Path::new("/store/it's/shims");
+        assert_eq!(
+            posix_exports("abc", shims),
+            "export KARAPACE_ENV='abc'\n\
+             case \":$PATH:\" in *:'/store/it'\\''s/shims':*) ;; \
+             *) export PATH='/store/it'\\''s/shims':\"$PATH\" ;; esac\n"
+        );
+        assert_eq!(
+            fish_exports("abc", shims),
+            "set -gx KARAPACE_ENV 'abc'\n\
+             contains -- '/store/it\\'s/shims' $PATH; or set -gx PATH '/store/it\\'s/shims' $PATH\n"
+        );
+    }
+}
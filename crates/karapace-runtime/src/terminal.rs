@@ -1,3 +1,4 @@
+use crate::watchdog::ResourceEvent;
 use std::io::Write;
 
 const OSC_START: &str = "\x1b]777;";
@@ -35,6 +36,19 @@ pub fn print_container_exit(env_id: &str) {
     }
 }
 
+pub fn print_resource_event(env_id: &str, event: &ResourceEvent) {
+    print_warning(env_id, &event.to_string());
+}
+
+pub fn print_warning(env_id: &str, message: &str) {
+    let short_id = &env_id[..12.min(env_id.len())];
+    if is_interactive_terminal() {
+        eprintln!("\r\x1b[1;33m[karapace]\x1b[0m {short_id}: {message}");
+    } else {
+        eprintln!("[karapace] {short_id}: {message}");
+    }
+}
+
 #[allow(unsafe_code)]
 fn is_interactive_terminal() -> bool {
     // SAFETY: isatty() is always safe — checks if fd is a terminal, no side effects.
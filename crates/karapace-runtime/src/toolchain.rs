@@ -0,0 +1,274 @@
+//! Language toolchains installed into a built environment.
+//!
+//! Each `[python]`, `[node]` or `[rust]` manifest section is installed under
+//! `/opt/karapace/<language>` by a shell script run in the environment after
+//! its system packages. A lockfile and its companion project file are copied
+//! into `/opt/karapace/<language>/project/` first ([`stage_lockfile`]), so
+//! the script installs exactly what the hashed lockfile pins.
+//!
+//! The interpreters themselves come from the image: the script checks that
+//! the requested version is present, and only Rust toolchains are installed
+//! (through `rustup`, when the image has it).
+
+use crate::sandbox::shell_quote;
+use crate::RuntimeError;
+use karapace_schema::{Language, NormalizedToolchain};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Where toolchains are installed inside the environment.
+pub const TOOLCHAIN_ROOT: &str = "/opt/karapace";
+
+/// Directory of `language`'s installation inside the environment.
+pub fn toolchain_prefix(language: Language) -> String {
+    format!("{TOOLCHAIN_ROOT}/{language}")
+}
+
+/// Directories holding installed executables, to be put on `PATH`.
+pub fn toolchain_bin_dirs(language: Language) -> Vec<String> {
+    let prefix = toolchain_prefix(language);
+    match language {
+        Language::Python | Language::Rust => vec![format!("{prefix}/bin")],
+        Language::Node => vec![
+            format!("{prefix}/bin"),
+            format!("{prefix}/project/node_modules/.bin"),
+        ],
+    }
+}
+
+/// Copy the lockfile of `toolchain`, and its companion project file, from
+/// `manifest_dir` into the environment's upper directory. Returns the
+/// lockfile's path inside the environment.
+pub fn stage_lockfile(
+    toolchain: &NormalizedToolchain,
+    manifest_dir: &Path,
+    upper_dir: &Path,
+) -> Result<Option<String>, RuntimeError> {
+    let Some(lockfile) = &toolchain.lockfile else {
+        return Ok(None);
+    };
+    let source = manifest_dir.join(lockfile);
+    let file_name = source
+        .file_name()
+        .ok_or_else(|| {
+            RuntimeError::ExecFailed(format!(
+                "[{}] lockfile '{lockfile}' does not name a file",
+                toolchain.language
+            ))
+        })?
+        .to_string_lossy()
+        .into_owned();
+    let project = format!("{}/project", toolchain_prefix(toolchain.language));
+    let staged = upper_dir.join(project.trim_start_matches('/'));
+    std::fs::create_dir_all(&staged)?;
+    let mut files: Vec<(PathBuf, String)> = vec![(source.clone(), file_name.clone())];
+    if let Some(companion) = toolchain.language.lockfile_companion() {
+        files.push((source.with_file_name(companion), companion.to_owned()));
+    }
+    for (from, name) in files {
+        std::fs::copy(&from, staged.join(&name)).map_err(|e| {
+            RuntimeError::ExecFailed(format!("copying {} failed: {e}", from.display()))
+        })?;
+    }
+    Ok(Some(format!("{project}/{file_name}")))
+}
+
+/// Shell script installing `toolchain`, with its lockfile already staged at
+/// `lockfile` inside the environment.
+pub fn install_script(toolchain: &NormalizedToolchain, lockfile: Option<&str>) -> String {
+    let prefix = toolchain_prefix(toolchain.language);
+    // Versions are validated by the schema and need no quoting.
+    let mut script = String::from("set -e\n");
+    match toolchain.language {
+        Language::Python => python_script(&mut script, toolchain, &prefix, lockfile),
+        Language::Node => node_script(&mut script, toolchain, &prefix, lockfile),
+        Language::Rust => rust_script(&mut script, toolchain, &prefix, lockfile),
+    }
+    let bins = toolchain_bin_dirs(toolchain.language).join(":");
+    let _ = writeln!(
+        script,
+        "mkdir -p /etc/profile.d\n\
+         echo 'export PATH={bins}:$PATH' > /etc/profile.d/karapace-{}.sh",
+        toolchain.language
+    );
+    script
+}
+
+fn require_command(script: &mut String, command: &str) {
+    let _ = writeln!(
+        script,
+        "command -v {command} >/dev/null 2>&1 || \
+         {{ echo 'karapace: {command} not found in the image; add it to [system] packages' >&2; exit 127; }}"
+    );
+}
+
+fn python_script(
+    script: &mut String,
+    toolchain: &NormalizedToolchain,
+    prefix: &str,
+    lockfile: Option<&str>,
+) {
+    let python = toolchain
+        .version
+        .as_deref()
+        .map_or_else(|| "python3".to_owned(), |v| format!("python{v}"));
+    require_command(script, &python);
+    let _ = writeln!(script, "{python} -m venv {prefix}");
+    let pip = format!("{prefix}/bin/pip install --no-input --disable-pip-version-check");
+    if let Some(lockfile) = lockfile {
+        let _ = writeln!(script, "{pip} -r {}", shell_quote(lockfile));
+    }
+    if !toolchain.packages.is_empty() {
+        let _ = writeln!(script, "{pip} {}", quoted(&toolchain.packages));
+    }
+}
+
+fn node_script(
+    script: &mut String,
+    toolchain: &NormalizedToolchain,
+    prefix: &str,
+    lockfile: Option<&str>,
+) {
+    require_command(script, "node");
+    require_command(script, "npm");
+    if let Some(version) = &toolchain.version {
+        let _ = writeln!(
+            script,
+            "case \"$(node --version)\" in v{version}|v{version}.*) ;; \
+             *) echo \"karapace: [node] version {version} requested, the image has $(node --version)\" >&2; exit 1 ;; esac"
+        );
+    }
+    let npm = "npm --no-audit --no-fund --no-update-notifier";
+    if lockfile.is_some() {
+        let _ = writeln!(script, "cd {prefix}/project && {npm} ci && cd /");
+    }
+    if !toolchain.packages.is_empty() {
+        let _ = writeln!(
+            script,
+            "{npm} install --global --prefix {prefix} {}",
+            quoted(&toolchain.packages)
+        );
+    }
+}
+
+fn rust_script(
+    script: &mut String,
+    toolchain: &NormalizedToolchain,
+    prefix: &str,
+    lockfile: Option<&str>,
+) {
+    if let Some(version) = &toolchain.version {
+        let _ = writeln!(
+            script,
+            "if command -v rustup >/dev/null 2>&1; then \
+             rustup toolchain install {version} --profile minimal && rustup default {version}; \
+             else case \"$(rustc --version 2>/dev/null)\" in \"rustc {version}\"|\"rustc {version} \"*|\"rustc {version}.\"*) ;; \
+             *) echo 'karapace: [rust] version {version} requested; add rustup or a matching rustc to the image' >&2; exit 1 ;; esac; fi"
+        );
+    }
+    require_command(script, "cargo");
+    let _ = writeln!(script, "export CARGO_HOME={prefix}");
+    if let Some(lockfile) = lockfile {
+        let manifest = Path::new(lockfile).with_file_name("Cargo.toml");
+        let _ = writeln!(
+            script,
+            "cargo fetch --locked --manifest-path {}",
+            shell_quote(&manifest.to_string_lossy())
+        );
+    }
+    for package in &toolchain.packages {
+        let (name, version) = package.rsplit_once('@').unwrap_or((package, ""));
+        let _ = writeln!(
+            script,
+            "cargo install --locked --root {prefix} {} --version {}",
+            shell_quote(name),
+            shell_quote(version)
+        );
+    }
+}
+
+fn quoted(packages: &[String]) -> String {
+    packages
+        .iter()
+        .map(|p| shell_quote(p))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use karapace_schema::parse_manifest_str;
+
+    fn toolchains(sections: &str) -> Vec<NormalizedToolchain> {
+        parse_manifest_str(&format!(
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n{sections}"
+        ))
+        .unwrap()
+        .normalize()
+        .unwrap()
+        .toolchains
+    }
+
+    #[test]
+    fn python_installs_into_a_venv_of_the_requested_interpreter() {
+        let python = &toolchains(
+            "[python]\nversion = \"3.12\"\npackages = [\"requests==2.32.3\"]\nlockfile = \"requirements.txt\"\n",
+        )[0];
+        let script = install_script(
+            python,
+            Some("/opt/karapace/python/project/requirements.txt"),
+        );
+        assert!(script.contains("python3.12 -m venv /opt/karapace/python\n"));
+        assert!(script.contains("-r '/opt/karapace/python/project/requirements.txt'\n"));
+        assert!(script
+            .contains("pip install --no-input --disable-pip-version-check 'requests==2.32.3'\n"));
+        assert!(script.contains("export PATH=/opt/karapace/python/bin:$PATH"));
+    }
+
+    #[test]
+    fn node_checks_the_version_and_installs_globally_under_its_prefix() {
+        let node = &toolchains("[node]\nversion = \"20\"\npackages = [\"typescript@5.4.5\"]\n")[0];
+        let script = install_script(node, None);
+        assert!(script.contains("in v20|v20.*)"));
+        assert!(!script.contains(" ci "));
+        assert!(script.contains("install --global --prefix /opt/karapace/node 'typescript@5.4.5'"));
+    }
+
+    #[test]
+    fn rust_installs_crates_at_their_pinned_versions() {
+        let rust = &toolchains(
+            "[rust]\nversion = \"1.79.0\"\npackages = [\"ripgrep@14.1.0\"]\nlockfile = \"tools/Cargo.lock\"\n",
+        )[0];
+        let script = install_script(rust, Some("/opt/karapace/rust/project/Cargo.lock"));
+        assert!(script.contains("rustup toolchain install 1.79.0 --profile minimal"));
+        assert!(script.contains(
+            "cargo fetch --locked --manifest-path '/opt/karapace/rust/project/Cargo.toml'"
+        ));
+        assert!(script.contains(
+            "cargo install --locked --root /opt/karapace/rust 'ripgrep' --version '14.1.0'"
+        ));
+    }
+
+    #[test]
+    fn lockfiles_are_staged_with_their_project_file() {
+        let project = tempfile::tempdir().unwrap();
+        let upper = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(project.path().join("web")).unwrap();
+        std::fs::write(project.path().join("web/package-lock.json"), "{}").unwrap();
+        let node = &toolchains("[node]\nlockfile = \"web/package-lock.json\"\n")[0];
+
+        // package.json is missing.
+        assert!(stage_lockfile(node, project.path(), upper.path()).is_err());
+
+        std::fs::write(project.path().join("web/package.json"), "{}").unwrap();
+        let staged = stage_lockfile(node, project.path(), upper.path()).unwrap();
+        assert_eq!(
+            staged.as_deref(),
+            Some("/opt/karapace/node/project/package-lock.json")
+        );
+        let dir = upper.path().join("opt/karapace/node/project");
+        assert!(dir.join("package-lock.json").is_file());
+        assert!(dir.join("package.json").is_file());
+    }
+}
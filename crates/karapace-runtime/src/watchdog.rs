@@ -0,0 +1,425 @@
+//! Resource watchdog for a running sandbox.
+//!
+//! While an environment is entered, a background thread samples the memory
+//! use and pressure of the sandbox's cgroup and the free space on the
+//! filesystem holding its overlay upper directory. Crossing a threshold emits
+//! a [`ResourceEvent`] once; the warning re-arms when the condition clears.
+//! When free space drops below the pause threshold, every process in the
+//! sandbox is stopped with `SIGSTOP` and continued once space is freed, so a
+//! runaway write does not fill the store. With a `max_overlay_mb` limit, the
+//! upper directory is measured too and the session is ended with `SIGTERM`
+//! once it grows past the limit.
+
+use crate::cgroup::cgroup_dir;
+use crate::process::{all_parents, process_tree};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchdogConfig {
+    /// How often resources are sampled.
+    pub interval: Duration,
+    /// Warn when cgroup memory use reaches this percentage of `memory.max`.
+    pub memory_warn_percent: u64,
+    /// Warn when the 10-second memory pressure average (`some avg10`)
+    /// reaches this percentage.
+    pub pressure_warn_avg10: f64,
+    /// Warn when free space falls below this many megabytes.
+    pub disk_warn_mb: u64,
+    /// Pause the sandbox when free space falls below this many megabytes.
+    pub disk_pause_mb: u64,
+    /// Continue a paused sandbox once free space is back above this.
+    pub disk_resume_mb: u64,
+    /// Size limit of the upper directory in megabytes, if any.
+    pub quota_mb: Option<u64>,
+    /// Warn when the upper directory reaches this percentage of `quota_mb`.
+    pub quota_warn_percent: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(2),
+            memory_warn_percent: 90,
+            pressure_warn_avg10: 20.0,
+            disk_warn_mb: 1024,
+            disk_pause_mb: 256,
+            disk_resume_mb: 512,
+            quota_mb: None,
+            quota_warn_percent: 90,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourceEvent {
+    MemoryNearLimit { current: u64, max: u64 },
+    MemoryPressure { avg10: f64 },
+    DiskLow { available_mb: u64 },
+    Paused { available_mb: u64 },
+    Resumed { available_mb: u64 },
+    QuotaNear { used_mb: u64, limit_mb: u64 },
+    QuotaExceeded { used_mb: u64, limit_mb: u64 },
+}
+
+impl fmt::Display for ResourceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MemoryNearLimit { current, max } => write!(
+                f,
+                "memory use at {} of {} MB, close to the OOM limit",
+                current / (1024 * 1024),
+                max / (1024 * 1024)
+            ),
+            Self::MemoryPressure { avg10 } => {
+                write!(f, "memory pressure high ({avg10:.1}% stalled over 10s)")
+            }
+            Self::DiskLow { available_mb } => {
+                write!(
+                    f,
+                    "low disk space for the environment: {available_mb} MB free"
+                )
+            }
+            Self::Paused { available_mb } => write!(
+                f,
+                "paused the environment: only {available_mb} MB free; free space to continue"
+            ),
+            Self::Resumed { available_mb } => {
+                write!(f, "resumed the environment: {available_mb} MB free")
+            }
+            Self::QuotaNear { used_mb, limit_mb } => write!(
+                f,
+                "environment uses {used_mb} of its {limit_mb} MB max_overlay_mb limit"
+            ),
+            Self::QuotaExceeded { used_mb, limit_mb } => write!(
+                f,
+                "ending the session: environment uses {used_mb} MB, over its {limit_mb} MB max_overlay_mb limit"
+            ),
+        }
+    }
+}
+
+/// One reading of the watched resources. Unreadable values are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceSample {
+    pub memory_current: Option<u64>,
+    pub memory_max: Option<u64>,
+    pub pressure_avg10: Option<f64>,
+    pub disk_available_mb: Option<u64>,
+    /// Size of the upper directory, measured only when a quota is set.
+    pub upper_used_mb: Option<u64>,
+}
+
+/// Which warnings are currently raised, so each fires once per episode.
+#[derive(Debug, Default)]
+#[allow(clippy::struct_excessive_bools)] // independent alert flags
+struct WatchdogState {
+    memory_warned: bool,
+    pressure_warned: bool,
+    disk_warned: bool,
+    paused: bool,
+    quota_warned: bool,
+    quota_exceeded: bool,
+}
+
+impl WatchdogState {
+    fn evaluate(&mut self, config: &WatchdogConfig, sample: &ResourceSample) -> Vec<ResourceEvent> {
+        let mut events = Vec::new();
+
+        if let (Some(current), Some(max)) = (sample.memory_current, sample.memory_max) {
+            let near = max > 0 && current.saturating_mul(100) / max >= config.memory_warn_percent;
+            if near && !self.memory_warned {
+                events.push(ResourceEvent::MemoryNearLimit { current, max });
+            }
+            self.memory_warned = near;
+        }
+
+        if let Some(avg10) = sample.pressure_avg10 {
+            let high = avg10 >= config.pressure_warn_avg10;
+            if high && !self.pressure_warned {
+                events.push(ResourceEvent::MemoryPressure { avg10 });
+            }
+            self.pressure_warned = high;
+        }
+
+        if let Some(available_mb) = sample.disk_available_mb {
+            if self.paused {
+                if available_mb >= config.disk_resume_mb {
+                    self.paused = false;
+                    events.push(ResourceEvent::Resumed { available_mb });
+                }
+            } else if available_mb < config.disk_pause_mb {
+                self.paused = true;
+                self.disk_warned = true;
+                events.push(ResourceEvent::Paused { available_mb });
+            }
+            let low = available_mb < config.disk_warn_mb;
+            if low && !self.disk_warned {
+                events.push(ResourceEvent::DiskLow { available_mb });
+            }
+            self.disk_warned = low;
+        }
+
+        if let (Some(used_mb), Some(limit_mb)) = (sample.upper_used_mb, config.quota_mb) {
+            if used_mb > limit_mb {
+                if !self.quota_exceeded {
+                    events.push(ResourceEvent::QuotaExceeded { used_mb, limit_mb });
+                }
+                self.quota_exceeded = true;
+            } else {
+                let near = used_mb.saturating_mul(100) >= limit_mb * config.quota_warn_percent;
+                if near && !self.quota_warned {
+                    events.push(ResourceEvent::QuotaNear { used_mb, limit_mb });
+                }
+                self.quota_warned = near;
+            }
+        }
+
+        events
+    }
+}
+
+/// Handle to a running watchdog thread. Dropping it without calling
+/// [`stop`](Self::stop) leaves the thread running until the process exits.
+pub struct Watchdog {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Watchdog {
+    /// Watch the sandbox rooted at process `pid`, whose writes land on the
+    /// filesystem holding `upper_dir`. `on_event` runs on the watchdog thread.
+    pub fn spawn(
+        pid: u32,
+        upper_dir: PathBuf,
+        config: WatchdogConfig,
+        on_event: impl Fn(&ResourceEvent) + Send + 'static,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let cgroup = cgroup_dir(pid);
+            let mut state = WatchdogState::default();
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(config.interval) {
+                let sample = sample_resources(cgroup.as_deref(), &upper_dir, config.quota_mb);
+                for event in state.evaluate(&config, &sample) {
+                    match event {
+                        ResourceEvent::Paused { .. } => signal_tree(pid, libc::SIGSTOP),
+                        ResourceEvent::Resumed { .. } => signal_tree(pid, libc::SIGCONT),
+                        ResourceEvent::QuotaExceeded { .. } => {
+                            if state.paused {
+                                signal_tree(pid, libc::SIGCONT);
+                            }
+                            signal_tree(pid, libc::SIGTERM);
+                        }
+                        _ => {}
+                    }
+                    on_event(&event);
+                }
+            }
+            if state.paused {
+                signal_tree(pid, libc::SIGCONT);
+            }
+        });
+        Self { stop, thread }
+    }
+
+    /// Stop sampling and continue the sandbox if it is paused.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+/// Megabytes available to unprivileged users on the filesystem holding `path`.
+pub fn available_disk_mb(path: &Path) -> Option<u64> {
+    let c_path = std::ffi::CString::new(path.to_string_lossy().as_bytes()).ok()?;
+
+    // SAFETY: zeroed statvfs is a valid initial state for the struct.
+    #[allow(unsafe_code, clippy::undocumented_unsafe_blocks)]
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: statvfs with a valid, NUL-terminated path and a properly
+    // zeroed output struct is well-defined. The struct is stack-allocated
+    // and only read after the call succeeds (ret == 0).
+    #[allow(unsafe_code, clippy::undocumented_unsafe_blocks)]
+    let ret = unsafe { libc::statvfs(c_path.as_ptr(), &raw mut stat) };
+    if ret != 0 {
+        return None;
+    }
+
+    let avail_bytes = stat.f_bavail * stat.f_frsize;
+    Some(avail_bytes / (1024 * 1024))
+}
+
+fn sample_resources(
+    cgroup: Option<&Path>,
+    upper_dir: &Path,
+    quota_mb: Option<u64>,
+) -> ResourceSample {
+    let read = |name: &str| cgroup.and_then(|dir| std::fs::read_to_string(dir.join(name)).ok());
+    let pressure =
+        read("memory.pressure").or_else(|| std::fs::read_to_string("/proc/pressure/memory").ok());
+    ResourceSample {
+        memory_current: read("memory.current").and_then(|v| v.trim().parse().ok()),
+        // "max" means unlimited and parses to None.
+        memory_max: read("memory.max").and_then(|v| v.trim().parse().ok()),
+        pressure_avg10: pressure.as_deref().and_then(parse_pressure_avg10),
+        disk_available_mb: available_disk_mb(upper_dir),
+        upper_used_mb: quota_mb.map(|_| crate::quota::usage_mb(crate::quota::dir_usage(upper_dir))),
+    }
+}
+
+/// `avg10` of the `some` line of a PSI file.
+fn parse_pressure_avg10(content: &str) -> Option<f64> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("some "))?
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+fn signal_tree(root: u32, signal: libc::c_int) {
+    let parents = all_parents();
+    for pid in process_tree(root, &parents) {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            continue;
+        };
+        // SAFETY: kill() only delivers a signal; a stale pid yields ESRCH.
+        #[allow(unsafe_code)]
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WatchdogConfig {
+        WatchdogConfig::default()
+    }
+
+    fn disk(mb: u64) -> ResourceSample {
+        ResourceSample {
+            disk_available_mb: Some(mb),
+            ..ResourceSample::default()
+        }
+    }
+
+    #[test]
+    fn memory_warning_fires_once_and_rearms() {
+        let mut state = WatchdogState::default();
+        let near = ResourceSample {
+            memory_current: Some(95),
+            memory_max: Some(100),
+            ..ResourceSample::default()
+        };
+        assert_eq!(
+            state.evaluate(&config(), &near),
+            [ResourceEvent::MemoryNearLimit {
+                current: 95,
+                max: 100
+            }]
+        );
+        assert!(state.evaluate(&config(), &near).is_empty());
+
+        let calm = ResourceSample {
+            memory_current: Some(50),
+            ..near.clone()
+        };
+        assert!(state.evaluate(&config(), &calm).is_empty());
+        assert_eq!(state.evaluate(&config(), &near).len(), 1);
+    }
+
+    #[test]
+    fn pressure_warning_uses_avg10() {
+        let mut state = WatchdogState::default();
+        let sample = ResourceSample {
+            pressure_avg10: Some(35.5),
+            ..ResourceSample::default()
+        };
+        assert_eq!(
+            state.evaluate(&config(), &sample),
+            [ResourceEvent::MemoryPressure { avg10: 35.5 }]
+        );
+    }
+
+    #[test]
+    fn disk_warns_then_pauses_then_resumes() {
+        let mut state = WatchdogState::default();
+        assert!(state.evaluate(&config(), &disk(4096)).is_empty());
+        assert_eq!(
+            state.evaluate(&config(), &disk(800)),
+            [ResourceEvent::DiskLow { available_mb: 800 }]
+        );
+        assert_eq!(
+            state.evaluate(&config(), &disk(100)),
+            [ResourceEvent::Paused { available_mb: 100 }]
+        );
+        // Still below the resume threshold: stay paused, no repeat events.
+        assert!(state.evaluate(&config(), &disk(300)).is_empty());
+        assert_eq!(
+            state.evaluate(&config(), &disk(600)),
+            [ResourceEvent::Resumed { available_mb: 600 }]
+        );
+    }
+
+    #[test]
+    fn sudden_disk_drop_pauses_without_separate_warning() {
+        let mut state = WatchdogState::default();
+        assert_eq!(
+            state.evaluate(&config(), &disk(10)),
+            [ResourceEvent::Paused { available_mb: 10 }]
+        );
+    }
+
+    #[test]
+    fn quota_warns_then_ends_session_once() {
+        let config = WatchdogConfig {
+            quota_mb: Some(100),
+            ..config()
+        };
+        let used = |mb| ResourceSample {
+            upper_used_mb: Some(mb),
+            ..ResourceSample::default()
+        };
+        let mut state = WatchdogState::default();
+        assert!(state.evaluate(&config, &used(50)).is_empty());
+        assert_eq!(
+            state.evaluate(&config, &used(95)),
+            [ResourceEvent::QuotaNear {
+                used_mb: 95,
+                limit_mb: 100
+            }]
+        );
+        assert!(state.evaluate(&config, &used(100)).is_empty());
+        assert_eq!(
+            state.evaluate(&config, &used(101)),
+            [ResourceEvent::QuotaExceeded {
+                used_mb: 101,
+                limit_mb: 100
+            }]
+        );
+        assert!(state.evaluate(&config, &used(120)).is_empty());
+    }
+
+    #[test]
+    fn parse_psi_files() {
+        let psi = "some avg10=12.34 avg60=1.00 avg300=0.10 total=100\n\
+                   full avg10=3.00 avg60=0.50 avg300=0.00 total=50\n";
+        assert_eq!(parse_pressure_avg10(psi), Some(12.34));
+        assert_eq!(parse_pressure_avg10("garbage"), None);
+    }
+
+    #[test]
+    fn disk_probe_reads_existing_path() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(available_disk_mb(dir.path()).is_some());
+        assert!(available_disk_mb(&dir.path().join("missing")).is_none());
+    }
+}
@@ -0,0 +1,226 @@
+//! Deprecated manifest keys.
+//!
+//! Each [`Deprecation`] maps an old key to its replacement. While a
+//! manifest's `manifest_version` is below the entry's `removed_in`, the old
+//! key is moved to the new one before the manifest is deserialized and a
+//! [`DeprecationWarning`] is recorded. From `removed_in` on, the old key is
+//! a parse error.
+
+use crate::manifest::ManifestError;
+use crate::version::manifest_version;
+use serde::Serialize;
+use std::fmt;
+
+/// One deprecated manifest key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deprecation {
+    /// Dotted path of the old key, e.g. `"hardware.audio"`.
+    pub key: &'static str,
+    /// Dotted path of the key that replaces it.
+    pub replacement: &'static str,
+    /// First `manifest_version` in which the old key is rejected.
+    pub removed_in: u32,
+}
+
+/// Every deprecated key, applied in order.
+pub const DEPRECATIONS: &[Deprecation] = &[Deprecation {
+    key: "hardware.audio",
+    replacement: "hardware.audio_out",
+    removed_in: 2,
+}];
+
+/// A deprecated key found while parsing a manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeprecationWarning {
+    pub key: String,
+    pub replacement: String,
+    pub removed_in: u32,
+}
+
+impl fmt::Display for DeprecationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is deprecated, use `{}` (rejected from manifest_version {})",
+            self.key, self.replacement, self.removed_in
+        )
+    }
+}
+
+/// Rewrite deprecated keys in `doc` to their replacements, or reject them
+/// once `doc`'s `manifest_version` reaches their `removed_in`.
+///
+/// When both the old and the new key are set, the new key wins, unless the
+/// old key holds a non-default value that differs from it.
+pub fn apply_deprecations(
+    doc: &mut toml::Table,
+    table: &[Deprecation],
+) -> Result<Vec<DeprecationWarning>, ManifestError> {
+    let version = manifest_version(doc);
+
+    let mut warnings = Vec::new();
+    for deprecation in table {
+        let Some(old) = take_key(doc, deprecation.key) else {
+            continue;
+        };
+        if version.is_some_and(|v| v >= deprecation.removed_in) {
+            return Err(ManifestError::RemovedField {
+                key: deprecation.key.to_owned(),
+                replacement: deprecation.replacement.to_owned(),
+                version: deprecation.removed_in,
+            });
+        }
+        match get_key(doc, deprecation.replacement) {
+            Some(new) if new != &old && !is_default(&old) => {
+                return Err(ManifestError::DeprecatedFieldConflict {
+                    key: deprecation.key.to_owned(),
+                    replacement: deprecation.replacement.to_owned(),
+                });
+            }
+            Some(_) => {}
+            None => set_key(doc, deprecation.replacement, old),
+        }
+        warnings.push(DeprecationWarning {
+            key: deprecation.key.to_owned(),
+            replacement: deprecation.replacement.to_owned(),
+            removed_in: deprecation.removed_in,
+        });
+    }
+    Ok(warnings)
+}
+
+fn is_default(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Boolean(b) => !b,
+        toml::Value::String(s) => s.is_empty(),
+        toml::Value::Array(a) => a.is_empty(),
+        _ => false,
+    }
+}
+
+fn split_key(key: &str) -> (Vec<&str>, &str) {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let leaf = parts.pop().unwrap_or_default();
+    (parts, leaf)
+}
+
+fn parent<'a>(doc: &'a toml::Table, path: &[&str]) -> Option<&'a toml::Table> {
+    path.iter()
+        .try_fold(doc, |table, part| table.get(*part)?.as_table())
+}
+
+fn parent_mut<'a>(doc: &'a mut toml::Table, path: &[&str]) -> Option<&'a mut toml::Table> {
+    path.iter()
+        .try_fold(doc, |table, part| table.get_mut(*part)?.as_table_mut())
+}
+
+pub(crate) fn get_key<'a>(doc: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let (path, leaf) = split_key(key);
+    parent(doc, &path)?.get(leaf)
+}
+
+pub(crate) fn take_key(doc: &mut toml::Table, key: &str) -> Option<toml::Value> {
+    let (path, leaf) = split_key(key);
+    parent_mut(doc, &path)?.remove(leaf)
+}
+
+pub(crate) fn set_key(doc: &mut toml::Table, key: &str, value: toml::Value) {
+    let (path, leaf) = split_key(key);
+    let mut table = doc;
+    for part in path {
+        let entry = table
+            .entry(part)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        let Some(next) = entry.as_table_mut() else {
+            return;
+        };
+        table = next;
+    }
+    table.insert(leaf.to_owned(), value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(input: &str) -> toml::Table {
+        input.parse().unwrap()
+    }
+
+    const RENAMED: &[Deprecation] = &[Deprecation {
+        key: "runtime.engine",
+        replacement: "runtime.backend",
+        removed_in: 3,
+    }];
+
+    #[test]
+    fn old_key_moves_to_replacement() {
+        let mut d = doc("manifest_version = 1\n[runtime]\nengine = \"oci\"\n");
+        let warnings = apply_deprecations(&mut d, RENAMED).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].key, "runtime.engine");
+        assert_eq!(
+            get_key(&d, "runtime.backend").unwrap().as_str(),
+            Some("oci")
+        );
+        assert!(get_key(&d, "runtime.engine").is_none());
+    }
+
+    #[test]
+    fn replacement_section_created_when_missing() {
+        let table = [Deprecation {
+            key: "gpu",
+            replacement: "hardware.gpu",
+            removed_in: 2,
+        }];
+        let mut d = doc("manifest_version = 1\ngpu = true\n");
+        apply_deprecations(&mut d, &table).unwrap();
+        assert_eq!(get_key(&d, "hardware.gpu").unwrap().as_bool(), Some(true));
+    }
+
+    #[test]
+    fn removed_from_version_bump() {
+        let mut d = doc("manifest_version = 3\n[runtime]\nengine = \"oci\"\n");
+        let err = apply_deprecations(&mut d, RENAMED).unwrap_err();
+        assert!(matches!(
+            err,
+            ManifestError::RemovedField { version: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn conflicting_values_rejected() {
+        let mut d = doc("manifest_version = 1\n[hardware]\naudio = true\naudio_out = false\n");
+        assert!(matches!(
+            apply_deprecations(&mut d, DEPRECATIONS),
+            Err(ManifestError::DeprecatedFieldConflict { .. })
+        ));
+
+        // A default-valued old key yields to the new one.
+        let mut d = doc("manifest_version = 1\n[hardware]\naudio = false\naudio_out = true\n");
+        assert_eq!(apply_deprecations(&mut d, DEPRECATIONS).unwrap().len(), 1);
+        assert_eq!(
+            get_key(&d, "hardware.audio_out").unwrap().as_bool(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn current_manifest_has_no_warnings() {
+        let mut d = doc("manifest_version = 1\n[hardware]\naudio_out = true\n");
+        assert!(apply_deprecations(&mut d, DEPRECATIONS).unwrap().is_empty());
+    }
+
+    #[test]
+    fn warning_message_names_both_keys() {
+        let warning = DeprecationWarning {
+            key: "hardware.audio".to_owned(),
+            replacement: "hardware.audio_out".to_owned(),
+            removed_in: 2,
+        };
+        assert_eq!(
+            warning.to_string(),
+            "`hardware.audio` is deprecated, use `hardware.audio_out` (rejected from manifest_version 2)"
+        );
+    }
+}
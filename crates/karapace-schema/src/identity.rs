@@ -42,18 +42,18 @@ pub fn compute_env_id(normalized: &NormalizedManifest) -> Result<EnvIdentity, se
     if normalized.hardware_gpu {
         hasher.update(b"hw:gpu");
     }
-    if normalized.hardware_audio {
+    if normalized.hardware_audio_out {
         hasher.update(b"hw:audio");
     }
+    if normalized.hardware_audio_in {
+        hasher.update(b"hw:audio_in");
+    }
+    if normalized.hardware_camera {
+        hasher.update(b"hw:camera");
+    }
 
     for mount in &normalized.mounts {
-        hasher.update(
-            format!(
-                "mount:{}:{}:{}",
-                mount.label, mount.host_path, mount.container_path
-            )
-            .as_bytes(),
-        );
+        hasher.update(format!("mount:{}", mount.canonical_spec()).as_bytes());
     }
 
     hasher.update(format!("backend:{}", normalized.runtime_backend).as_bytes());
@@ -61,6 +61,15 @@ pub fn compute_env_id(normalized: &NormalizedManifest) -> Result<EnvIdentity, se
     if normalized.network_isolation {
         hasher.update(b"net:isolated");
     }
+    if !normalized.network_mode.is_legacy() {
+        hasher.update(format!("net:{}", normalized.network_mode).as_bytes());
+    }
+    for server in &normalized.dns_servers {
+        hasher.update(format!("dns:{server}").as_bytes());
+    }
+    for host in &normalized.extra_hosts {
+        hasher.update(format!("host:{host}").as_bytes());
+    }
     if let Some(cpu) = normalized.cpu_shares {
         hasher.update(format!("cpu:{cpu}").as_bytes());
     }
@@ -5,19 +5,34 @@
 //! identity computation (`compute_env_id`), lock file generation/verification
 //! (`LockFile`), and built-in preset definitions.
 
+pub mod deprecation;
 pub mod identity;
 pub mod lock;
 pub mod manifest;
 pub mod normalize;
 pub mod preset;
 pub mod types;
+pub mod variables;
+pub mod version;
 
+pub use deprecation::{Deprecation, DeprecationWarning, DEPRECATIONS};
 pub use identity::{compute_env_id, EnvIdentity};
-pub use lock::{LockError, LockFile, ResolutionResult, ResolvedPackage};
+pub use lock::{
+    LockDiff, LockError, LockFieldChange, LockFile, LockPackageChange, ResolutionResult,
+    ResolvedPackage,
+};
 pub use manifest::{
-    parse_manifest_file, parse_manifest_str, BaseSection, GuiSection, HardwareSection,
-    ManifestError, ManifestV1, MountsSection, ResourceLimits, RuntimeSection, SystemSection,
+    parse_manifest_file, parse_manifest_file_with_warnings, parse_manifest_str,
+    parse_manifest_str_with_warnings, BaseSection, BuildSection, GuiSection, HardwareSection,
+    HooksSection, ManifestError, ManifestV1, MountsSection, NetworkMode, NetworkSection,
+    ResourceLimits, RuntimeSection, SystemSection, ToolchainSection,
+};
+pub use normalize::{
+    expand_package_patterns, is_package_pattern, join_mount_options, merge_manifest_tables,
+    package_pattern_matches, parse_age, parse_label, validate_label, ExtraHost, Language,
+    MountOption, NormalizedManifest, NormalizedMount, NormalizedToolchain, PortForward,
+    PortProtocol,
 };
-pub use normalize::{NormalizedManifest, NormalizedMount};
 pub use preset::{get_preset, list_presets, Preset, BUILTIN_PRESETS};
 pub use types::{EnvId, LayerHash, ObjectHash, ShortId};
+pub use version::{upgrade_manifest, CURRENT_MANIFEST_VERSION, MIN_MANIFEST_VERSION};
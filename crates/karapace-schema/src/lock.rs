@@ -1,7 +1,12 @@
 use crate::identity::EnvIdentity;
-use crate::manifest::ManifestError;
-use crate::normalize::{NormalizedManifest, NormalizedMount};
+use crate::manifest::{ManifestError, NetworkMode};
+use crate::normalize::{
+    is_false, is_package_pattern, package_pattern_matches, ExtraHost, NormalizedManifest,
+    NormalizedMount, NormalizedToolchain, PortForward,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 use thiserror::Error;
@@ -41,11 +46,61 @@ pub struct ResolutionResult {
     pub resolved_packages: Vec<ResolvedPackage>,
 }
 
+/// A single scalar lock field whose locked and resolved values differ.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LockFieldChange {
+    pub field: String,
+    pub locked: String,
+    pub resolved: String,
+}
+
+/// A package whose pinned version differs. `None` means the package is
+/// absent on that side (added or removed).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LockPackageChange {
+    pub name: String,
+    pub locked: Option<String>,
+    pub resolved: Option<String>,
+}
+
+/// Field-by-field difference between a lock file and a fresh resolution.
+///
+/// Produced by [`LockFile::diff`]. An empty diff means the lock is current.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LockDiff {
+    pub fields: Vec<LockFieldChange>,
+    pub packages: Vec<LockPackageChange>,
+}
+
+impl LockDiff {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.packages.is_empty()
+    }
+}
+
+impl fmt::Display for LockDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in &self.fields {
+            writeln!(f, "  ~ {}: {} → {}", c.field, c.locked, c.resolved)?;
+        }
+        for p in &self.packages {
+            match (&p.locked, &p.resolved) {
+                (None, Some(new)) => writeln!(f, "  + {} {new}", p.name)?,
+                (Some(old), None) => writeln!(f, "  - {} {old}", p.name)?,
+                (Some(old), Some(new)) => writeln!(f, "  ~ {} {old} → {new}", p.name)?,
+                (None, None) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
 /// The lock file captures the fully resolved state of an environment.
 ///
 /// The env_id is computed deterministically from the locked fields,
 /// not from unresolved manifest data. This guarantees:
 ///   same lockfile → same env_id → same environment.
+#[allow(clippy::struct_excessive_bools)] // independent policy flags
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct LockFile {
     pub lock_version: u32,
@@ -63,8 +118,18 @@ pub struct LockFile {
     // Runtime policy (included in hash contract)
     pub runtime_backend: String,
     pub hardware_gpu: bool,
-    pub hardware_audio: bool,
+    /// Audio playback (kept under its original key for compatibility).
+    #[serde(rename = "hardware_audio")]
+    pub hardware_audio_out: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub hardware_audio_in: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub hardware_camera: bool,
     pub network_isolation: bool,
+    /// Only modes other than `host` and `isolated` are written; see
+    /// [`NetworkMode::is_legacy`].
+    #[serde(default, skip_serializing_if = "NetworkMode::is_legacy")]
+    pub network_mode: NetworkMode,
 
     // Mount policy
     #[serde(default)]
@@ -75,6 +140,31 @@ pub struct LockFile {
     pub cpu_shares: Option<u64>,
     #[serde(default)]
     pub memory_limit_mb: Option<u64>,
+
+    // Build hooks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_build_hook: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub build_steps: Vec<String>,
+
+    // Port forwards (sorted in normalize)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forward_ports: Vec<PortForward>,
+
+    // Name resolution overrides (DNS in resolver order, hosts sorted)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns_servers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_hosts: Vec<ExtraHost>,
+
+    // Language toolchains (pinned packages and lockfile hashes)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub toolchains: Vec<NormalizedToolchain>,
+
+    // Variables expanded in mounts. Not hashed: their values enter the
+    // identity through the mounts.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
 }
 
 impl LockFile {
@@ -96,11 +186,21 @@ impl LockFile {
             resolved_apps: normalized.gui_apps.clone(),
             runtime_backend: normalized.runtime_backend.clone(),
             hardware_gpu: normalized.hardware_gpu,
-            hardware_audio: normalized.hardware_audio,
+            hardware_audio_out: normalized.hardware_audio_out,
+            hardware_audio_in: normalized.hardware_audio_in,
+            hardware_camera: normalized.hardware_camera,
             network_isolation: normalized.network_isolation,
+            network_mode: normalized.network_mode,
             mounts: normalized.mounts.clone(),
             cpu_shares: normalized.cpu_shares,
             memory_limit_mb: normalized.memory_limit_mb,
+            post_build_hook: normalized.post_build_hook.clone(),
+            build_steps: normalized.build_steps.clone(),
+            forward_ports: normalized.forward_ports.clone(),
+            dns_servers: normalized.dns_servers.clone(),
+            extra_hosts: normalized.extra_hosts.clone(),
+            toolchains: normalized.toolchains.clone(),
+            variables: normalized.variables.clone(),
         };
 
         let identity = lock.compute_identity();
@@ -135,19 +235,19 @@ impl LockFile {
         if self.hardware_gpu {
             hasher.update(b"hw:gpu");
         }
-        if self.hardware_audio {
+        if self.hardware_audio_out {
             hasher.update(b"hw:audio");
         }
+        if self.hardware_audio_in {
+            hasher.update(b"hw:audio_in");
+        }
+        if self.hardware_camera {
+            hasher.update(b"hw:camera");
+        }
 
         // Mount policy (sorted by label in normalize)
         for mount in &self.mounts {
-            hasher.update(
-                format!(
-                    "mount:{}:{}:{}",
-                    mount.label, mount.host_path, mount.container_path
-                )
-                .as_bytes(),
-            );
+            hasher.update(format!("mount:{}", mount.canonical_spec()).as_bytes());
         }
 
         // Runtime backend
@@ -157,6 +257,9 @@ impl LockFile {
         if self.network_isolation {
             hasher.update(b"net:isolated");
         }
+        if !self.network_mode.is_legacy() {
+            hasher.update(format!("net:{}", self.network_mode).as_bytes());
+        }
 
         // Resource limits
         if let Some(cpu) = self.cpu_shares {
@@ -166,6 +269,42 @@ impl LockFile {
             hasher.update(format!("mem:{mem}").as_bytes());
         }
 
+        // Build hooks
+        if let Some(script) = &self.post_build_hook {
+            hasher.update(format!("hook:post_build:{script}").as_bytes());
+        }
+        for (i, step) in self.build_steps.iter().enumerate() {
+            hasher.update(format!("build:step:{i}:{step}").as_bytes());
+        }
+
+        // Port forwards
+        for fwd in &self.forward_ports {
+            hasher.update(format!("port:{fwd}").as_bytes());
+        }
+
+        // Name resolution
+        for server in &self.dns_servers {
+            hasher.update(format!("dns:{server}").as_bytes());
+        }
+        for host in &self.extra_hosts {
+            hasher.update(format!("host:{host}").as_bytes());
+        }
+
+        // Language toolchains
+        for toolchain in &self.toolchains {
+            let language = toolchain.language;
+            hasher.update(format!("toolchain:{language}").as_bytes());
+            if let Some(version) = &toolchain.version {
+                hasher.update(format!("toolchain:{language}:version:{version}").as_bytes());
+            }
+            for pkg in &toolchain.packages {
+                hasher.update(format!("toolchain:{language}:pkg:{pkg}").as_bytes());
+            }
+            if let Some(hash) = &toolchain.lockfile_hash {
+                hasher.update(format!("toolchain:{language}:lockfile:{hash}").as_bytes());
+            }
+        }
+
         let hex = hasher.finalize().to_hex().to_string();
         let short = hex[..12].to_owned();
 
@@ -211,8 +350,16 @@ impl LockFile {
             .iter()
             .map(|p| p.name.as_str())
             .collect();
+        // (patterns must have expanded to at least one locked package).
         for pkg in &normalized.system_packages {
-            if !locked_names.contains(&pkg.as_str()) {
+            let present = if is_package_pattern(pkg) {
+                locked_names
+                    .iter()
+                    .any(|name| package_pattern_matches(pkg, name))
+            } else {
+                locked_names.contains(&pkg.as_str())
+            };
+            if !present {
                 return Err(LockError::ManifestDrift(format!(
                     "package '{pkg}' is in manifest but not in lock file. Run 'karapace build' to re-resolve."
                 )));
@@ -220,16 +367,237 @@ impl LockFile {
         }
 
         if self.hardware_gpu != normalized.hardware_gpu
-            || self.hardware_audio != normalized.hardware_audio
+            || self.hardware_audio_out != normalized.hardware_audio_out
+            || self.hardware_audio_in != normalized.hardware_audio_in
+            || self.hardware_camera != normalized.hardware_camera
         {
             return Err(LockError::ManifestDrift(
                 "hardware policy changed. Run 'karapace build' to re-resolve.".to_owned(),
             ));
         }
+        if self.post_build_hook != normalized.post_build_hook {
+            return Err(LockError::ManifestDrift(
+                "post_build hook changed. Run 'karapace build' to re-resolve.".to_owned(),
+            ));
+        }
+        if self.build_steps != normalized.build_steps {
+            return Err(LockError::ManifestDrift(
+                "build steps changed. Run 'karapace build' to re-resolve.".to_owned(),
+            ));
+        }
+        if self.network_mode != normalized.network_mode {
+            return Err(LockError::ManifestDrift(format!(
+                "network mode changed: lock has '{}', manifest has '{}'. Run 'karapace build' to re-resolve.",
+                self.network_mode, normalized.network_mode
+            )));
+        }
+        if self.forward_ports != normalized.forward_ports {
+            return Err(LockError::ManifestDrift(
+                "forwarded ports changed. Run 'karapace build' to re-resolve.".to_owned(),
+            ));
+        }
+        if self.dns_servers != normalized.dns_servers || self.extra_hosts != normalized.extra_hosts
+        {
+            return Err(LockError::ManifestDrift(
+                "DNS servers or extra hosts changed. Run 'karapace build' to re-resolve."
+                    .to_owned(),
+            ));
+        }
+        if let Some((name, value)) = normalized
+            .variables
+            .iter()
+            .find(|(name, value)| self.variables.get(*name) != Some(value))
+        {
+            return Err(LockError::ManifestDrift(format!(
+                "variable '{name}' changed: lock has '{}', now '{value}'. Run 'karapace build' to re-resolve.",
+                self.variables.get(name).map_or("unset", String::as_str)
+            )));
+        }
+        if self.toolchains != normalized.toolchains {
+            let changed = normalized
+                .toolchains
+                .iter()
+                .filter(|t| !self.toolchains.contains(t))
+                .chain(
+                    self.toolchains
+                        .iter()
+                        .filter(|t| !normalized.toolchains.contains(t)),
+                )
+                .map(|t| t.language.as_str())
+                .next()
+                .unwrap_or("toolchain");
+            return Err(LockError::ManifestDrift(format!(
+                "[{changed}] toolchain changed. Run 'karapace build' to re-resolve."
+            )));
+        }
 
         Ok(())
     }
 
+    /// Compare this (locked) file against `resolved`, typically a lock freshly
+    /// generated from the same manifest. Identity fields (`env_id`,
+    /// `short_id`) are derived and therefore not reported.
+    #[allow(clippy::too_many_lines)]
+    pub fn diff(&self, resolved: &LockFile) -> LockDiff {
+        let mut fields = Vec::new();
+        let mut field = |name: &str, locked: String, resolved: String| {
+            if locked != resolved {
+                fields.push(LockFieldChange {
+                    field: name.to_owned(),
+                    locked,
+                    resolved,
+                });
+            }
+        };
+        let opt = |v: Option<u64>| v.map_or_else(|| "none".to_owned(), |v| v.to_string());
+        let mounts = |m: &[NormalizedMount]| {
+            m.iter()
+                .map(NormalizedMount::canonical_spec)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let ports = |p: &[PortForward]| {
+            p.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        field(
+            "base_image",
+            self.base_image.clone(),
+            resolved.base_image.clone(),
+        );
+        field(
+            "base_image_digest",
+            self.base_image_digest.clone(),
+            resolved.base_image_digest.clone(),
+        );
+        field(
+            "resolved_apps",
+            self.resolved_apps.join(","),
+            resolved.resolved_apps.join(","),
+        );
+        field(
+            "runtime_backend",
+            self.runtime_backend.clone(),
+            resolved.runtime_backend.clone(),
+        );
+        field(
+            "hardware_gpu",
+            self.hardware_gpu.to_string(),
+            resolved.hardware_gpu.to_string(),
+        );
+        field(
+            "hardware_audio",
+            self.hardware_audio_out.to_string(),
+            resolved.hardware_audio_out.to_string(),
+        );
+        field(
+            "hardware_audio_in",
+            self.hardware_audio_in.to_string(),
+            resolved.hardware_audio_in.to_string(),
+        );
+        field(
+            "hardware_camera",
+            self.hardware_camera.to_string(),
+            resolved.hardware_camera.to_string(),
+        );
+        field(
+            "network_isolation",
+            self.network_isolation.to_string(),
+            resolved.network_isolation.to_string(),
+        );
+        field(
+            "network_mode",
+            self.network_mode.to_string(),
+            resolved.network_mode.to_string(),
+        );
+        field("mounts", mounts(&self.mounts), mounts(&resolved.mounts));
+        field("cpu_shares", opt(self.cpu_shares), opt(resolved.cpu_shares));
+        field(
+            "memory_limit_mb",
+            opt(self.memory_limit_mb),
+            opt(resolved.memory_limit_mb),
+        );
+        field(
+            "post_build_hook",
+            self.post_build_hook.as_deref().unwrap_or("none").to_owned(),
+            resolved
+                .post_build_hook
+                .as_deref()
+                .unwrap_or("none")
+                .to_owned(),
+        );
+        field(
+            "build_steps",
+            self.build_steps.join(" && "),
+            resolved.build_steps.join(" && "),
+        );
+        field(
+            "forward_ports",
+            ports(&self.forward_ports),
+            ports(&resolved.forward_ports),
+        );
+        field(
+            "dns_servers",
+            self.dns_servers.join(","),
+            resolved.dns_servers.join(","),
+        );
+        let hosts = |h: &[ExtraHost]| {
+            h.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        field(
+            "extra_hosts",
+            hosts(&self.extra_hosts),
+            hosts(&resolved.extra_hosts),
+        );
+        let toolchains = |t: &[NormalizedToolchain]| {
+            t.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; ")
+        };
+        field(
+            "toolchains",
+            toolchains(&self.toolchains),
+            toolchains(&resolved.toolchains),
+        );
+        let variables = |v: &BTreeMap<String, String>| {
+            v.iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        field(
+            "variables",
+            variables(&self.variables),
+            variables(&resolved.variables),
+        );
+
+        let mut versions: BTreeMap<&str, (Option<&str>, Option<&str>)> = BTreeMap::new();
+        for p in &self.resolved_packages {
+            versions.entry(p.name.as_str()).or_default().0 = Some(p.version.as_str());
+        }
+        for p in &resolved.resolved_packages {
+            versions.entry(p.name.as_str()).or_default().1 = Some(p.version.as_str());
+        }
+        let packages = versions
+            .into_iter()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(name, (old, new))| LockPackageChange {
+                name: name.to_owned(),
+                locked: old.map(str::to_owned),
+                resolved: new.map(str::to_owned),
+            })
+            .collect();
+
+        LockDiff { fields, packages }
+    }
+
     pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<(), LockError> {
         let path = path.as_ref();
         let content = toml::to_string_pretty(self)?;
@@ -247,7 +615,12 @@ impl LockFile {
 
     pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self, LockError> {
         let content = fs::read_to_string(path)?;
-        Ok(toml::from_str(&content)?)
+        let mut lock: Self = toml::from_str(&content)?;
+        // `isolated` is recorded as `network_isolation` alone.
+        if lock.network_isolation {
+            lock.network_mode = NetworkMode::Isolated;
+        }
+        Ok(lock)
     }
 }
 
@@ -255,6 +628,7 @@ impl LockFile {
 mod tests {
     use super::*;
     use crate::manifest::parse_manifest_str;
+    use crate::normalize::MountOption;
 
     fn sample_normalized() -> NormalizedManifest {
         parse_manifest_str(
@@ -299,6 +673,71 @@ packages = ["git", "clang"]
         lock.write_to_file(&path).unwrap();
         let loaded = LockFile::read_from_file(&path).unwrap();
         assert_eq!(lock, loaded);
+        assert!(!fs::read_to_string(&path).unwrap().contains("forward_ports"));
+    }
+
+    #[test]
+    fn lock_roundtrip_with_forward_ports() {
+        let mut normalized = sample_normalized();
+        normalized.forward_ports = vec![
+            PortForward::parse("8080:80").unwrap(),
+            PortForward::parse("53/udp").unwrap(),
+        ];
+        let lock = LockFile::from_resolved(&normalized, &sample_resolution());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("karapace.lock");
+        lock.write_to_file(&path).unwrap();
+        assert_eq!(LockFile::read_from_file(&path).unwrap(), lock);
+        assert!(lock.verify_manifest_intent(&normalized).is_ok());
+        assert!(lock.verify_manifest_intent(&sample_normalized()).is_err());
+    }
+
+    #[test]
+    fn lock_roundtrip_with_name_resolution() {
+        let mut normalized = sample_normalized();
+        normalized.dns_servers = vec!["10.0.0.53".to_owned(), "fd00::53".to_owned()];
+        normalized.extra_hosts = vec![ExtraHost::parse("db.local:10.0.0.5").unwrap()];
+        let lock = LockFile::from_resolved(&normalized, &sample_resolution());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("karapace.lock");
+        lock.write_to_file(&path).unwrap();
+        assert_eq!(LockFile::read_from_file(&path).unwrap(), lock);
+        assert!(lock.verify_manifest_intent(&normalized).is_ok());
+        assert!(lock.verify_manifest_intent(&sample_normalized()).is_err());
+
+        // Resolver order is part of the identity.
+        normalized.dns_servers.reverse();
+        assert_ne!(
+            LockFile::from_resolved(&normalized, &sample_resolution()).env_id,
+            lock.env_id
+        );
+    }
+
+    #[test]
+    fn lock_roundtrip_with_network_mode() {
+        let mut normalized = sample_normalized();
+        normalized.network_mode = NetworkMode::Slirp;
+        let lock = LockFile::from_resolved(&normalized, &sample_resolution());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("karapace.lock");
+        lock.write_to_file(&path).unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("network_mode = \"slirp\""));
+        assert_eq!(LockFile::read_from_file(&path).unwrap(), lock);
+        assert!(lock.verify_manifest_intent(&normalized).is_ok());
+        assert!(lock.verify_manifest_intent(&sample_normalized()).is_err());
+
+        // `isolated` is written as network_isolation alone and read back.
+        normalized.network_isolation = true;
+        normalized.network_mode = NetworkMode::Isolated;
+        let lock = LockFile::from_resolved(&normalized, &sample_resolution());
+        lock.write_to_file(&path).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("network_mode"));
+        assert_eq!(LockFile::read_from_file(&path).unwrap(), lock);
     }
 
     #[test]
@@ -318,6 +757,43 @@ packages = ["git", "clang"]
         assert!(lock.verify_integrity().is_err());
     }
 
+    #[test]
+    fn diff_of_identical_locks_is_empty() {
+        let lock = LockFile::from_resolved(&sample_normalized(), &sample_resolution());
+        let diff = lock.diff(&lock.clone());
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "");
+    }
+
+    #[test]
+    fn diff_reports_digest_and_package_drift() {
+        let normalized = sample_normalized();
+        let locked = LockFile::from_resolved(&normalized, &sample_resolution());
+
+        let mut resolution = sample_resolution();
+        resolution.base_image_digest = "b".repeat(64);
+        resolution.resolved_packages[0].version = "18.1.0-1".to_owned();
+        resolution.resolved_packages.remove(1);
+        resolution.resolved_packages.push(ResolvedPackage {
+            name: "make".to_owned(),
+            version: "4.4-1".to_owned(),
+        });
+        let fresh = LockFile::from_resolved(&normalized, &resolution);
+
+        let diff = locked.diff(&fresh);
+        assert_eq!(diff.fields.len(), 1);
+        assert_eq!(diff.fields[0].field, "base_image_digest");
+        assert_eq!(diff.packages.len(), 3);
+
+        let rendered = diff.to_string();
+        assert!(
+            rendered.contains("~ clang 17.0.6-1 → 18.1.0-1"),
+            "{rendered}"
+        );
+        assert!(rendered.contains("- git 2.44.0-1"), "{rendered}");
+        assert!(rendered.contains("+ make 4.4-1"), "{rendered}");
+    }
+
     #[test]
     fn lock_contains_real_digest() {
         let normalized = sample_normalized();
@@ -382,6 +858,47 @@ packages = ["git", "clang"]
         assert!(lock.verify_manifest_intent(&normalized).is_ok());
     }
 
+    #[test]
+    fn manifest_intent_accepts_expanded_patterns() {
+        let mut normalized = sample_normalized();
+        normalized.system_packages = vec!["cl*".to_owned(), "git".to_owned()];
+        let lock = LockFile::from_resolved(&normalized, &sample_resolution());
+        assert!(lock.verify_manifest_intent(&normalized).is_ok());
+
+        normalized.system_packages.push("python3-*".to_owned());
+        let err = lock.verify_manifest_intent(&normalized).unwrap_err();
+        assert!(err.to_string().contains("python3-*"));
+    }
+
+    #[test]
+    fn capture_grants_change_identity_and_intent() {
+        let normalized = sample_normalized();
+        let lock = LockFile::from_resolved(&normalized, &sample_resolution());
+
+        let mut mic = normalized.clone();
+        mic.hardware_audio_in = true;
+        let mut camera = normalized.clone();
+        camera.hardware_camera = true;
+
+        let mic_lock = LockFile::from_resolved(&mic, &sample_resolution());
+        let camera_lock = LockFile::from_resolved(&camera, &sample_resolution());
+        assert_ne!(lock.env_id, mic_lock.env_id);
+        assert_ne!(lock.env_id, camera_lock.env_id);
+        assert_ne!(mic_lock.env_id, camera_lock.env_id);
+
+        assert!(lock.verify_manifest_intent(&mic).is_err());
+        assert!(mic_lock.verify_manifest_intent(&mic).is_ok());
+
+        let diff = lock.diff(&camera_lock);
+        assert!(diff.fields.iter().any(|f| f.field == "hardware_camera"));
+
+        // Old lock files without the new keys still parse.
+        let toml = toml::to_string(&lock).unwrap();
+        assert!(!toml.contains("hardware_audio_in"));
+        let parsed: LockFile = toml::from_str(&toml).unwrap();
+        assert_eq!(parsed, lock);
+    }
+
     #[test]
     fn manifest_drift_detected() {
         let normalized = sample_normalized();
@@ -478,11 +995,13 @@ packages = ["git", "clang"]
                 label: "cache".to_owned(),
                 host_path: "/a".to_owned(),
                 container_path: "/b".to_owned(),
+                options: Vec::new(),
             },
             NormalizedMount {
                 label: "work".to_owned(),
                 host_path: "/c".to_owned(),
                 container_path: "/d".to_owned(),
+                options: Vec::new(),
             },
         ];
         let mut n2 = sample_normalized();
@@ -491,11 +1010,13 @@ packages = ["git", "clang"]
                 label: "work".to_owned(),
                 host_path: "/c".to_owned(),
                 container_path: "/d".to_owned(),
+                options: Vec::new(),
             },
             NormalizedMount {
                 label: "cache".to_owned(),
                 host_path: "/a".to_owned(),
                 container_path: "/b".to_owned(),
+                options: Vec::new(),
             },
         ];
         // Mounts are sorted by label in normalize(), but from_resolved doesn't re-sort.
@@ -521,6 +1042,7 @@ packages = ["git", "clang"]
             label: "src".to_owned(),
             host_path: "/home/user/src".to_owned(),
             container_path: "/workspace".to_owned(),
+            options: Vec::new(),
         }];
         let res = sample_resolution();
         let lock = LockFile::from_resolved(&n1, &res);
@@ -577,6 +1099,7 @@ packages = ["git", "clang"]
                 label: l.to_string(),
                 host_path: h.to_string(),
                 container_path: c.to_string(),
+                options: Vec::new(),
             })
             .collect();
         let normalized = NormalizedManifest {
@@ -585,12 +1108,30 @@ packages = ["git", "clang"]
             system_packages: packages.iter().map(|(n, _)| n.to_string()).collect(),
             gui_apps: Vec::new(),
             hardware_gpu: gpu,
-            hardware_audio: audio,
+            hardware_audio_out: audio,
+            hardware_audio_in: false,
+            hardware_camera: false,
             mounts: mount_specs,
             runtime_backend: backend.to_owned(),
             network_isolation,
+            network_mode: if network_isolation {
+                NetworkMode::Isolated
+            } else {
+                NetworkMode::Host
+            },
             cpu_shares: None,
             memory_limit_mb: None,
+            runtime_init: true,
+            max_overlay_mb: None,
+            expires_after_secs: None,
+            post_build_hook: None,
+            build_steps: Vec::new(),
+            forward_ports: Vec::new(),
+            dns_servers: Vec::new(),
+            extra_hosts: Vec::new(),
+            toolchains: Vec::new(),
+            variables: BTreeMap::new(),
+            labels: BTreeMap::new(),
         };
         let resolution = ResolutionResult {
             base_image_digest: base_digest.to_owned(),
@@ -682,6 +1223,7 @@ packages = ["git", "clang"]
                 label: l.to_string(),
                 host_path: h.to_string(),
                 container_path: c.to_string(),
+                options: Vec::new(),
             })
             .collect();
         let normalized = NormalizedManifest {
@@ -690,12 +1232,30 @@ packages = ["git", "clang"]
             system_packages: packages.iter().map(|(n, _)| n.to_string()).collect(),
             gui_apps: apps.iter().map(ToString::to_string).collect(),
             hardware_gpu: gpu,
-            hardware_audio: audio,
+            hardware_audio_out: audio,
+            hardware_audio_in: false,
+            hardware_camera: false,
             mounts: mount_specs,
             runtime_backend: backend.to_owned(),
             network_isolation,
+            network_mode: if network_isolation {
+                NetworkMode::Isolated
+            } else {
+                NetworkMode::Host
+            },
             cpu_shares,
             memory_limit_mb,
+            runtime_init: true,
+            max_overlay_mb: None,
+            expires_after_secs: None,
+            post_build_hook: None,
+            build_steps: Vec::new(),
+            forward_ports: Vec::new(),
+            dns_servers: Vec::new(),
+            extra_hosts: Vec::new(),
+            toolchains: Vec::new(),
+            variables: BTreeMap::new(),
+            labels: BTreeMap::new(),
         };
         let resolution = ResolutionResult {
             base_image_digest: base_digest.to_owned(),
@@ -855,6 +1415,75 @@ packages = ["git", "clang"]
             "memory_limit_mb"
         );
 
+        let mut n = base_norm.clone();
+        n.post_build_hook = Some("make bootstrap".to_owned());
+        assert_ne!(
+            LockFile::from_resolved(&n, &base_res).env_id,
+            base_id,
+            "post_build_hook"
+        );
+
+        let mut n = base_norm.clone();
+        n.build_steps = vec!["make deps".to_owned(), "./setup.sh".to_owned()];
+        let steps_id = LockFile::from_resolved(&n, &base_res).env_id;
+        assert_ne!(steps_id, base_id, "build_steps");
+        n.build_steps.reverse();
+        assert_ne!(
+            LockFile::from_resolved(&n, &base_res).env_id,
+            steps_id,
+            "build step order"
+        );
+
+        let mut n = base_norm.clone();
+        n.forward_ports = vec![PortForward::parse("8080:80").unwrap()];
+        assert_ne!(
+            LockFile::from_resolved(&n, &base_res).env_id,
+            base_id,
+            "forward_ports"
+        );
+
+        let mut n = base_norm.clone();
+        n.dns_servers = vec!["10.0.0.53".to_owned()];
+        assert_ne!(
+            LockFile::from_resolved(&n, &base_res).env_id,
+            base_id,
+            "dns_servers"
+        );
+
+        let mut n = base_norm.clone();
+        n.extra_hosts = vec![ExtraHost::parse("db.local:10.0.0.5").unwrap()];
+        assert_ne!(
+            LockFile::from_resolved(&n, &base_res).env_id,
+            base_id,
+            "extra_hosts"
+        );
+
+        let mut n = base_norm.clone();
+        n.mounts = vec![NormalizedMount {
+            label: "data".to_owned(),
+            host_path: "./data".to_owned(),
+            container_path: "/data".to_owned(),
+            options: Vec::new(),
+        }];
+        let writable_id = LockFile::from_resolved(&n, &base_res).env_id;
+        n.mounts[0].options = vec![MountOption::Ro];
+        assert_ne!(
+            LockFile::from_resolved(&n, &base_res).env_id,
+            writable_id,
+            "mount options"
+        );
+
+        let mut n = base_norm.clone();
+        n.network_mode = NetworkMode::Slirp;
+        let slirp_id = LockFile::from_resolved(&n, &base_res).env_id;
+        assert_ne!(slirp_id, base_id, "network_mode");
+        n.network_mode = NetworkMode::None;
+        assert_ne!(
+            LockFile::from_resolved(&n, &base_res).env_id,
+            slirp_id,
+            "network_mode"
+        );
+
         let mut n = base_norm.clone();
         n.runtime_backend = "oci".to_owned();
         assert_ne!(
@@ -871,4 +1500,54 @@ packages = ["git", "clang"]
             "gui_apps"
         );
     }
+
+    #[test]
+    fn toolchains_enter_identity_and_drift_checks() {
+        let base_res = sample_resolution();
+        let mut n = sample_normalized();
+        let base_id = LockFile::from_resolved(&n, &base_res).env_id;
+        n.toolchains = vec![NormalizedToolchain {
+            language: crate::normalize::Language::Python,
+            version: Some("3.12".to_owned()),
+            packages: Vec::new(),
+            lockfile: Some("requirements.txt".to_owned()),
+            lockfile_hash: Some("a".repeat(64)),
+        }];
+        let python_id = LockFile::from_resolved(&n, &base_res).env_id;
+        assert_ne!(python_id, base_id, "toolchains");
+        n.toolchains[0].lockfile_hash = Some("b".repeat(64));
+        let relocked = LockFile::from_resolved(&n, &base_res);
+        assert_ne!(relocked.env_id, python_id, "toolchain lockfile");
+        assert!(relocked.verify_manifest_intent(&n).is_ok());
+        n.toolchains[0].version = Some("3.13".to_owned());
+        let err = relocked.verify_manifest_intent(&n).unwrap_err();
+        assert!(
+            err.to_string().contains("[python] toolchain changed"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn changed_variables_are_drift_but_not_hashed() {
+        let res = sample_resolution();
+        let mut n = sample_normalized();
+        let base_id = LockFile::from_resolved(&n, &res).env_id;
+        n.variables
+            .insert("env:HOME".to_owned(), "/home/a".to_owned());
+        let lock = LockFile::from_resolved(&n, &res);
+        assert_eq!(lock.env_id, base_id);
+        assert_eq!(lock.variables, n.variables);
+        assert!(lock.verify_manifest_intent(&n).is_ok());
+
+        n.variables
+            .insert("env:HOME".to_owned(), "/home/b".to_owned());
+        let err = lock.verify_manifest_intent(&n).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("variable 'env:HOME' changed: lock has '/home/a', now '/home/b'"),
+            "{err}"
+        );
+        let diff = lock.diff(&LockFile::from_resolved(&n, &res));
+        assert_eq!(diff.fields[0].field, "variables");
+    }
 }
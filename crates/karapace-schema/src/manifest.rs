@@ -1,3 +1,7 @@
+use crate::deprecation::{apply_deprecations, DeprecationWarning, DEPRECATIONS};
+use crate::normalize::{merge_manifest_tables, Language};
+use crate::variables::expand_mount_variables;
+use crate::version::check_removed_keys;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::fs;
@@ -11,7 +15,7 @@ pub enum ManifestError {
     Io(#[from] std::io::Error),
     #[error("failed to parse manifest: {0}")]
     ParseToml(#[from] toml::de::Error),
-    #[error("unsupported manifest_version: {0}, expected 1")]
+    #[error("unsupported manifest_version: {0}, expected 1 or 2")]
     UnsupportedVersion(u32),
     #[error("base.image must not be empty")]
     EmptyBaseImage,
@@ -19,14 +23,102 @@ pub enum ManifestError {
     UnpinnedBaseImage(String),
     #[error("mount label must not be empty")]
     EmptyMountLabel,
-    #[error("invalid mount declaration for '{label}': '{spec}', expected '<host>:<container>'")]
+    #[error(
+        "invalid mount declaration for '{label}': '{spec}', expected '<host>:<container>[:<options>]'"
+    )]
     InvalidMount { label: String, spec: String },
+    #[error("unknown option '{option}' for mount '{label}', expected ro, nodev, noexec or nosuid")]
+    InvalidMountOption { label: String, option: String },
+    #[error(
+        "invalid port forward '{0}', expected '[<host>:]<container>[/tcp|/udp]' with ports 1-65535"
+    )]
+    InvalidPortForward(String),
+    #[error("host port {0} is forwarded more than once")]
+    DuplicateHostPort(u16),
+    #[error(
+        "network.forward_ports needs network access and cannot be combined with network mode '{0}'"
+    )]
+    ForwardPortsWithoutNetwork(NetworkMode),
+    #[error("runtime.network_isolation = true conflicts with network.mode = '{0}'")]
+    ConflictingNetworkMode(NetworkMode),
+    #[error(
+        "runtime.resource_limits.disk_limit_mb = {disk_limit_mb} conflicts with runtime.max_overlay_mb = {max_overlay_mb}"
+    )]
+    ConflictingDiskLimit {
+        max_overlay_mb: u64,
+        disk_limit_mb: u64,
+    },
+    #[error("invalid label: {0}")]
+    InvalidLabel(String),
+    #[error("invalid runtime.expires_after: {0}")]
+    InvalidExpiresAfter(String),
+    #[error("invalid DNS server '{0}', expected an IPv4 or IPv6 address")]
+    InvalidDnsServer(String),
+    #[error("invalid extra host '{0}', expected '<hostname>:<address>'")]
+    InvalidExtraHost(String),
+    #[error("invalid package pattern '{0}': patterns need at least one literal character and no whitespace")]
+    InvalidPackagePattern(String),
+    #[error("package pattern '{0}' matched no packages in the image's package index")]
+    UnmatchedPackagePattern(String),
+    #[error("'{key}' was removed in manifest_version {version}; use '{replacement}'")]
+    RemovedField {
+        key: String,
+        replacement: String,
+        version: u32,
+    },
+    #[error(
+        "'{key}' and '{replacement}' are both set to different values; keep only '{replacement}'"
+    )]
+    DeprecatedFieldConflict { key: String, replacement: String },
+    #[error("[{language}] package '{package}' must pin an exact version, e.g. '{example}'")]
+    UnpinnedToolchainPackage {
+        language: Language,
+        package: String,
+        example: &'static str,
+    },
+    #[error("invalid [{language}] version '{version}', expected e.g. '3.12' or '1.79.0'")]
+    InvalidToolchainVersion { language: Language, version: String },
+    #[error("cannot read [{language}] lockfile '{}': {source}", path.display())]
+    ToolchainLockfile {
+        language: Language,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("in manifest {}: {source}", path.display())]
+    Include {
+        path: PathBuf,
+        source: Box<ManifestError>,
+    },
+    #[error("manifest {} extends or includes itself", .0.display())]
+    IncludeCycle(PathBuf),
+    #[error("invalid '{0}', expected a path or, for include, a list of paths")]
+    InvalidInclude(&'static str),
+    #[error("'extends' and 'include' are resolved against the manifest's file; parse it with parse_manifest_file")]
+    IncludeWithoutFile,
+    #[error("invalid variable in '{0}', expected ${{env:NAME}} or ${{project_dir}}")]
+    InvalidVariable(String),
+    #[error("environment variable '{0}' used in [mounts] is not set")]
+    UnsetVariable(String),
+    #[error(
+        "${{project_dir}} is the manifest file's directory; parse it with parse_manifest_file"
+    )]
+    ProjectDirWithoutFile,
 }
 
+/// A parsed manifest of any supported `manifest_version` (see
+/// [`crate::version`]).
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct ManifestV1 {
     pub manifest_version: u32,
+    /// Manifest this one is merged onto, relative to its directory. Only
+    /// the manifest that was parsed keeps this field; the manifests it
+    /// names are already merged in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Manifests merged after `extends` and before this one, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
     pub base: BaseSection,
     #[serde(default)]
     pub system: SystemSection,
@@ -38,6 +130,26 @@ pub struct ManifestV1 {
     pub mounts: MountsSection,
     #[serde(default)]
     pub runtime: RuntimeSection,
+    #[serde(default, skip_serializing_if = "NetworkSection::is_empty")]
+    pub network: NetworkSection,
+    #[serde(default, skip_serializing_if = "HooksSection::is_empty")]
+    pub hooks: HooksSection,
+    #[serde(default, skip_serializing_if = "BuildSection::is_empty")]
+    pub build: BuildSection,
+    #[serde(default, skip_serializing_if = "ToolchainSection::is_empty")]
+    pub python: ToolchainSection,
+    #[serde(default, skip_serializing_if = "ToolchainSection::is_empty")]
+    pub node: ToolchainSection,
+    #[serde(default, skip_serializing_if = "ToolchainSection::is_empty")]
+    pub rust: ToolchainSection,
+    /// `[labels]`: `key = "value"` pairs recorded on the environment, such
+    /// as `team = "infra"`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Values of the variables expanded in `[mounts]`, by name (see
+    /// [`crate::variables`]). Set by parsing, never read from the manifest.
+    #[serde(skip)]
+    pub variables: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
@@ -60,13 +172,22 @@ pub struct GuiSection {
     pub apps: Vec<String>,
 }
 
+#[allow(clippy::struct_excessive_bools)] // independent policy flags
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct HardwareSection {
     #[serde(default)]
     pub gpu: bool,
-    #[serde(default)]
-    pub audio: bool,
+    /// Audio playback. The older `audio` key is rewritten to this one by the
+    /// deprecation table.
+    #[serde(default, skip_serializing_if = "crate::normalize::is_false")]
+    pub audio_out: bool,
+    /// Microphone / audio capture.
+    #[serde(default, skip_serializing_if = "crate::normalize::is_false")]
+    pub audio_in: bool,
+    /// Video capture devices (webcams).
+    #[serde(default, skip_serializing_if = "crate::normalize::is_false")]
+    pub camera: bool,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
@@ -80,10 +201,41 @@ pub struct MountsSection {
 pub struct RuntimeSection {
     #[serde(default = "default_backend")]
     pub backend: String,
-    #[serde(default)]
+    /// v1 spelling of `network.mode = "isolated"`.
+    #[serde(default, skip_serializing_if = "crate::normalize::is_false")]
     pub network_isolation: bool,
     #[serde(default)]
     pub resource_limits: ResourceLimits,
+    /// Run a minimal init as PID 1 that reaps zombies and forwards signals
+    /// to the session.
+    #[serde(
+        default = "crate::normalize::default_true",
+        skip_serializing_if = "crate::normalize::is_true"
+    )]
+    pub init: bool,
+    /// Largest size in megabytes the environment's writable upper layer
+    /// may grow to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_overlay_mb: Option<u64>,
+    /// Idle time, such as `"30d"`, after which `karapace gc` archives the
+    /// environment, and after twice as long destroys it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_after: Option<String>,
+}
+
+impl RuntimeSection {
+    /// `max_overlay_mb`, or its v1 spelling `resource_limits.disk_limit_mb`.
+    pub(crate) fn overlay_limit(&self) -> Result<Option<u64>, ManifestError> {
+        match (self.max_overlay_mb, self.resource_limits.disk_limit_mb) {
+            (Some(max_overlay_mb), Some(disk_limit_mb)) if max_overlay_mb != disk_limit_mb => {
+                Err(ManifestError::ConflictingDiskLimit {
+                    max_overlay_mb,
+                    disk_limit_mb,
+                })
+            }
+            (max_overlay_mb, disk_limit_mb) => Ok(max_overlay_mb.or(disk_limit_mb)),
+        }
+    }
 }
 
 impl Default for RuntimeSection {
@@ -92,10 +244,145 @@ impl Default for RuntimeSection {
             backend: default_backend(),
             network_isolation: false,
             resource_limits: ResourceLimits::default(),
+            init: true,
+            max_overlay_mb: None,
+            expires_after: None,
         }
     }
 }
 
+/// Network access of sessions, and host access to services running in them.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkSection {
+    /// Unset means `host`, or `isolated` with `runtime.network_isolation`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<NetworkMode>,
+    /// `"<host>:<container>"`, or a single port forwarded to the same port,
+    /// with an optional `/udp` suffix. Host ports listen on loopback only.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forward_ports: Vec<String>,
+    /// Nameservers of the session's `resolv.conf`, in order. Unset keeps
+    /// the host's resolver.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns: Vec<String>,
+    /// `"<hostname>:<address>"` entries added to the session's `/etc/hosts`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_hosts: Vec<String>,
+}
+
+impl NetworkSection {
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none()
+            && self.forward_ports.is_empty()
+            && self.dns.is_empty()
+            && self.extra_hosts.is_empty()
+    }
+}
+
+/// How a session reaches the network.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// Share the host's network namespace.
+    #[default]
+    Host,
+    /// A network namespace of its own with only loopback.
+    Isolated,
+    /// A network namespace of its own with outbound access through
+    /// slirp4netns. The host's loopback interface is not reachable.
+    Slirp,
+    /// A network namespace of its own with no interface up, not even loopback.
+    None,
+}
+
+impl NetworkMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Host => "host",
+            Self::Isolated => "isolated",
+            Self::Slirp => "slirp",
+            Self::None => "none",
+        }
+    }
+
+    /// Whether sessions can reach hosts outside their own namespace.
+    pub fn has_outbound(self) -> bool {
+        matches!(self, Self::Host | Self::Slirp)
+    }
+
+    /// Whether `network_isolation` alone describes the mode. Only other
+    /// modes are serialized, so identities from before network modes hold.
+    #[allow(clippy::trivially_copy_pass_by_ref)] // serde skip_serializing_if
+    pub fn is_legacy(&self) -> bool {
+        matches!(self, Self::Host | Self::Isolated)
+    }
+}
+
+impl fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Shell commands run inside the sandbox at points of the lifecycle.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HooksSection {
+    /// Run with `/bin/sh -c` after packages are installed; its changes
+    /// become part of the built environment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_build: Option<String>,
+}
+
+impl HooksSection {
+    pub fn is_empty(&self) -> bool {
+        self.post_build.is_none()
+    }
+}
+
+/// Commands run inside the sandbox while building, each cached as a layer.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BuildSection {
+    /// Run in order with `/bin/sh -c` after packages and toolchains are
+    /// installed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<String>,
+}
+
+impl BuildSection {
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+/// A language toolchain installed into the environment after the system
+/// packages: `[python]`, `[node]` or `[rust]`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ToolchainSection {
+    /// Interpreter or toolchain version, e.g. `"3.12"`, `"20"` or
+    /// `"1.79.0"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Packages with exact versions: `requests==2.32.3` for pip,
+    /// `typescript@5.4.5` for npm, `ripgrep@14.1.0` for cargo.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    /// Lockfile relative to the manifest: a `requirements.txt` for pip, a
+    /// `package-lock.json` next to its `package.json` for npm, a
+    /// `Cargo.lock` next to its `Cargo.toml` for cargo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockfile: Option<String>,
+}
+
+impl ToolchainSection {
+    pub fn is_empty(&self) -> bool {
+        self.version.is_none() && self.packages.is_empty() && self.lockfile.is_none()
+    }
+}
+
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct ResourceLimits {
@@ -103,6 +390,9 @@ pub struct ResourceLimits {
     pub cpu_shares: Option<u64>,
     #[serde(default)]
     pub memory_limit_mb: Option<u64>,
+    /// v1 spelling of `runtime.max_overlay_mb`.
+    #[serde(default)]
+    pub disk_limit_mb: Option<u64>,
 }
 
 fn default_backend() -> String {
@@ -128,22 +418,148 @@ impl StdError for ManifestIoWithPath {
 }
 
 pub fn parse_manifest_str(input: &str) -> Result<ManifestV1, ManifestError> {
-    Ok(toml::from_str(input)?)
+    Ok(parse_manifest_str_with_warnings(input)?.0)
+}
+
+/// Parse a manifest, also returning the deprecated keys it uses.
+pub fn parse_manifest_str_with_warnings(
+    input: &str,
+) -> Result<(ManifestV1, Vec<DeprecationWarning>), ManifestError> {
+    parse_manifest(input, None)
+}
+
+/// Parse manifest text whose `${project_dir}` is `project_dir`.
+fn parse_manifest(
+    input: &str,
+    project_dir: Option<&Path>,
+) -> Result<(ManifestV1, Vec<DeprecationWarning>), ManifestError> {
+    let mut doc: toml::Table = toml::from_str(input)?;
+    if doc.contains_key(EXTENDS_KEY) || doc.contains_key(INCLUDE_KEY) {
+        return Err(ManifestError::IncludeWithoutFile);
+    }
+    let mut warnings = apply_deprecations(&mut doc, DEPRECATIONS)?;
+    warnings.extend(check_removed_keys(&doc)?);
+    let variables = expand_mount_variables(&mut doc, project_dir, env_var)?;
+    // Parse the original text when nothing was rewritten, so errors keep
+    // their line and column.
+    let mut manifest: ManifestV1 = if warnings.is_empty() && variables.is_empty() {
+        toml::from_str(input)?
+    } else {
+        ManifestV1::deserialize(doc)?
+    };
+    manifest.variables = variables;
+    Ok((manifest, warnings))
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
 }
 
 pub fn parse_manifest_file(path: impl AsRef<Path>) -> Result<ManifestV1, ManifestError> {
-    let path = path.as_ref().to_path_buf();
-    let content = fs::read_to_string(&path).map_err(|e| {
+    Ok(parse_manifest_file_with_warnings(path)?.0)
+}
+
+/// Parse a manifest file, merging the manifests it `extends` and
+/// `include`s first (see [`merge_manifest_tables`]), and return the
+/// deprecated keys used by any of them.
+pub fn parse_manifest_file_with_warnings(
+    path: impl AsRef<Path>,
+) -> Result<(ManifestV1, Vec<DeprecationWarning>), ManifestError> {
+    let path = path.as_ref();
+    let content = read_manifest(path)?;
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let project_dir = fs::canonicalize(dir)?;
+    let doc: toml::Table = toml::from_str(&content)?;
+    if !doc.contains_key(EXTENDS_KEY) && !doc.contains_key(INCLUDE_KEY) {
+        return parse_manifest(&content, Some(&project_dir));
+    }
+    let mut warnings = Vec::new();
+    let mut merged = resolve_includes(path, doc.clone(), &mut Vec::new(), &mut warnings)?;
+    // Keep the parsed manifest's own references, for commands that rewrite
+    // it.
+    for key in [EXTENDS_KEY, INCLUDE_KEY] {
+        if let Some(value) = doc.get(key) {
+            merged.insert(key.to_owned(), value.clone());
+        }
+    }
+    let variables = expand_mount_variables(&mut merged, Some(&project_dir), env_var)?;
+    let mut manifest = ManifestV1::deserialize(merged)?;
+    manifest.variables = variables;
+    Ok((manifest, warnings))
+}
+
+const EXTENDS_KEY: &str = "extends";
+const INCLUDE_KEY: &str = "include";
+
+fn read_manifest(path: &Path) -> Result<String, ManifestError> {
+    fs::read_to_string(path).map_err(|e| {
         let kind = e.kind();
         ManifestError::Io(std::io::Error::new(
             kind,
             ManifestIoWithPath {
-                path: path.clone(),
+                path: path.to_path_buf(),
                 source: e,
             },
         ))
-    })?;
-    parse_manifest_str(&content)
+    })
+}
+
+/// The manifest table `doc`, read from `path`, merged onto the manifests it
+/// names. `chain` holds the manifests being resolved, to reject cycles.
+fn resolve_includes(
+    path: &Path,
+    mut doc: toml::Table,
+    chain: &mut Vec<PathBuf>,
+    warnings: &mut Vec<DeprecationWarning>,
+) -> Result<toml::Table, ManifestError> {
+    let canonical = fs::canonicalize(path)?;
+    if chain.contains(&canonical) {
+        return Err(ManifestError::IncludeCycle(path.to_path_buf()));
+    }
+    chain.push(canonical);
+    warnings.extend(apply_deprecations(&mut doc, DEPRECATIONS)?);
+    warnings.extend(check_removed_keys(&doc)?);
+
+    let extends = match doc.remove(EXTENDS_KEY) {
+        None => None,
+        Some(toml::Value::String(extends)) => Some(extends),
+        Some(_) => return Err(ManifestError::InvalidInclude(EXTENDS_KEY)),
+    };
+    let include = match doc.remove(INCLUDE_KEY) {
+        None => Vec::new(),
+        Some(toml::Value::Array(include)) => include
+            .into_iter()
+            .map(|value| match value {
+                toml::Value::String(include) => Ok(include),
+                _ => Err(ManifestError::InvalidInclude(INCLUDE_KEY)),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err(ManifestError::InvalidInclude(INCLUDE_KEY)),
+    };
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut merged = toml::Table::new();
+    for name in extends.iter().chain(&include) {
+        let included = dir.join(name);
+        let table = read_manifest(&included)
+            .and_then(|content| Ok(toml::from_str(&content)?))
+            .and_then(|table| resolve_includes(&included, table, chain, warnings))
+            .map_err(|e| match e {
+                // Report a cycle once, at the manifest that closes it.
+                ManifestError::IncludeCycle(_) => e,
+                e => ManifestError::Include {
+                    path: included.clone(),
+                    source: Box::new(e),
+                },
+            })?;
+        merge_manifest_tables(&mut merged, table);
+    }
+    merge_manifest_tables(&mut merged, doc);
+    chain.pop();
+    Ok(merged)
 }
 
 #[cfg(test)]
@@ -166,7 +582,7 @@ apps = ["ide", "debugger"]
 
 [hardware]
 gpu = true
-audio = true
+audio_out = true
 
 [mounts]
 workspace = "./:/workspace"
@@ -213,6 +629,39 @@ unknown_field = true
         assert!(parse_manifest_str(input).is_err());
     }
 
+    #[test]
+    fn deprecated_audio_key_maps_to_audio_out() {
+        let input = r#"
+manifest_version = 1
+
+[base]
+image = "rolling"
+
+[hardware]
+audio = true
+"#;
+        let (manifest, warnings) = parse_manifest_str_with_warnings(input).unwrap();
+        assert!(manifest.hardware.audio_out);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].replacement, "hardware.audio_out");
+    }
+
+    #[test]
+    fn parses_network_mode() {
+        let input = r#"
+manifest_version = 1
+
+[base]
+image = "rolling"
+
+[network]
+mode = "slirp"
+"#;
+        let manifest = parse_manifest_str(input).unwrap();
+        assert_eq!(manifest.network.mode, Some(NetworkMode::Slirp));
+        assert!(parse_manifest_str(&input.replace("slirp", "bridge")).is_err());
+    }
+
     #[test]
     fn rejects_missing_base() {
         let input = r"
@@ -220,4 +669,78 @@ manifest_version = 1
 ";
         assert!(parse_manifest_str(input).is_err());
     }
+
+    #[test]
+    fn manifests_extend_and_include_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        fs::write(
+            shared.join("base.karapace.toml"),
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n\
+             [system]\npackages = [\"git\"]\n[hardware]\naudio = true\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("gpu.toml"),
+            "[hardware]\ngpu = true\n[system]\npackages = [\"mesa\"]\n",
+        )
+        .unwrap();
+        let path = dir.path().join("karapace.toml");
+        fs::write(
+            &path,
+            "extends = \"shared/base.karapace.toml\"\ninclude = [\"gpu.toml\"]\n\
+             [system]\npackages = [\"clang\"]\n",
+        )
+        .unwrap();
+
+        let (manifest, warnings) = parse_manifest_file_with_warnings(&path).unwrap();
+        assert_eq!(manifest.system.packages, ["git", "mesa", "clang"]);
+        assert!(manifest.hardware.gpu && manifest.hardware.audio_out);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            manifest.extends.as_deref(),
+            Some("shared/base.karapace.toml")
+        );
+        assert!(matches!(
+            parse_manifest_str(&fs::read_to_string(&path).unwrap()),
+            Err(ManifestError::IncludeWithoutFile)
+        ));
+
+        fs::write(
+            dir.path().join("gpu.toml"),
+            "include = [\"karapace.toml\"]\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            parse_manifest_file(&path),
+            Err(ManifestError::IncludeCycle(_))
+        ));
+        fs::remove_file(dir.path().join("gpu.toml")).unwrap();
+        assert!(matches!(
+            parse_manifest_file(&path),
+            Err(ManifestError::Include { .. })
+        ));
+    }
+
+    #[test]
+    fn project_dir_is_the_manifest_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("karapace.toml");
+        let input = "manifest_version = 1\n[base]\nimage = \"rolling\"\n\
+                     [mounts]\nworkspace = \"${project_dir}/src:/workspace\"\n";
+        fs::write(&path, input).unwrap();
+        let manifest = parse_manifest_file(&path).unwrap();
+        let project_dir = fs::canonicalize(dir.path()).unwrap();
+        let project_dir = project_dir.to_string_lossy();
+        assert_eq!(
+            manifest.mounts.entries["workspace"],
+            format!("{project_dir}/src:/workspace")
+        );
+        assert_eq!(manifest.variables["project_dir"], project_dir);
+        assert!(matches!(
+            parse_manifest_str(input),
+            Err(ManifestError::ProjectDirWithoutFile)
+        ));
+    }
 }
@@ -1,23 +1,80 @@
-use crate::manifest::{ManifestError, ManifestV1};
+use crate::manifest::{ManifestError, ManifestV1, NetworkMode, ToolchainSection};
+use crate::version::{CURRENT_MANIFEST_VERSION, MIN_MANIFEST_VERSION};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
 
 /// Canonical, sorted, deduplicated representation of a parsed manifest.
 ///
 /// All optional fields are resolved to defaults, packages are sorted, and mounts
 /// are validated. This is the input to identity hashing and lock file generation.
+#[allow(clippy::struct_excessive_bools)] // independent policy flags
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct NormalizedManifest {
+    /// Version of the normalized form; 1 for manifests of every version.
     pub manifest_version: u32,
     pub base_image: String,
     pub system_packages: Vec<String>,
     pub gui_apps: Vec<String>,
     pub hardware_gpu: bool,
-    pub hardware_audio: bool,
+    /// Audio playback. Serialized under its original name so existing
+    /// manifests keep their canonical form.
+    #[serde(rename = "hardware_audio")]
+    pub hardware_audio_out: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub hardware_audio_in: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub hardware_camera: bool,
     pub mounts: Vec<NormalizedMount>,
     pub runtime_backend: String,
+    /// True exactly when `network_mode` is `isolated`. Kept so manifests
+    /// from before network modes keep their canonical form.
     pub network_isolation: bool,
+    /// `[network] mode`, or what `runtime.network_isolation` implies.
+    #[serde(default, skip_serializing_if = "NetworkMode::is_legacy")]
+    pub network_mode: NetworkMode,
     pub cpu_shares: Option<u64>,
     pub memory_limit_mb: Option<u64>,
+    /// Session setting only: it is left out of the canonical form while on,
+    /// and never part of the lock file identity.
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub runtime_init: bool,
+    /// Size limit of the upper layer. Enforced at build and session time,
+    /// not part of the lock file identity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_overlay_mb: Option<u64>,
+    /// `runtime.expires_after` in seconds. Recorded in the environment's
+    /// metadata at build, not part of the lock file identity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_after_secs: Option<u64>,
+    /// `[hooks] post_build`, trimmed. Part of the identity: it shapes what
+    /// the build produces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_build_hook: Option<String>,
+    /// `[build] steps`, trimmed, in declaration order. Part of the identity.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub build_steps: Vec<String>,
+    /// `[network] forward_ports`, sorted by protocol and host port.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forward_ports: Vec<PortForward>,
+    /// `[network] dns` in canonical address form, in declaration order
+    /// without repeats: the resolver tries them in that order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns_servers: Vec<String>,
+    /// `[network] extra_hosts`, sorted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_hosts: Vec<ExtraHost>,
+    /// `[python]`, `[node]` and `[rust]`, in that order, when present.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub toolchains: Vec<NormalizedToolchain>,
+    /// Values of the variables expanded in `[mounts]`, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+    /// `[labels]`. Recorded in the environment's metadata at build, not
+    /// part of the lock file identity.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
 }
 
 /// A validated bind-mount specification with label, host path, and container path.
@@ -26,12 +83,332 @@ pub struct NormalizedMount {
     pub label: String,
     pub host_path: String,
     pub container_path: String,
+    /// Sorted and deduplicated. Omitted when empty, so mounts without
+    /// options serialize as before.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<MountOption>,
+}
+
+impl NormalizedMount {
+    pub fn is_read_only(&self) -> bool {
+        self.options.contains(&MountOption::Ro)
+    }
+
+    /// The mount as hashed into the identity and shown in lock diffs:
+    /// `label:host:container`, then `:opt,opt` when it has options.
+    pub fn canonical_spec(&self) -> String {
+        let mut spec = format!("{}:{}:{}", self.label, self.host_path, self.container_path);
+        if !self.options.is_empty() {
+            spec.push(':');
+            spec.push_str(&join_mount_options(&self.options));
+        }
+        spec
+    }
+}
+
+/// A flag of a manifest bind mount, written after the container path:
+/// `"./data:/data:ro,nodev"`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MountOption {
+    Ro,
+    Nodev,
+    Noexec,
+    Nosuid,
+}
+
+impl MountOption {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ro => "ro",
+            Self::Nodev => "nodev",
+            Self::Noexec => "noexec",
+            Self::Nosuid => "nosuid",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "ro" => Some(Self::Ro),
+            "nodev" => Some(Self::Nodev),
+            "noexec" => Some(Self::Noexec),
+            "nosuid" => Some(Self::Nosuid),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for MountOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `options` as written in a mount spec, e.g. `"ro,nodev"`.
+pub fn join_mount_options(options: &[MountOption]) -> String {
+    options
+        .iter()
+        .map(|o| o.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum PortProtocol {
+    Tcp,
+    Udp,
+}
+
+impl PortProtocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        }
+    }
+}
+
+/// A host port forwarded into the environment.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PortForward {
+    pub protocol: PortProtocol,
+    pub host_port: u16,
+    pub container_port: u16,
+}
+
+impl PortForward {
+    /// Parse `"8080:80"`, `"5432"` or `"53:53/udp"`.
+    pub fn parse(spec: &str) -> Result<Self, ManifestError> {
+        let invalid = || ManifestError::InvalidPortForward(spec.to_owned());
+        let trimmed = spec.trim();
+        let (ports, protocol) = match trimmed.rsplit_once('/') {
+            Some((ports, "tcp")) => (ports, PortProtocol::Tcp),
+            Some((ports, "udp")) => (ports, PortProtocol::Udp),
+            Some(_) => return Err(invalid()),
+            None => (trimmed, PortProtocol::Tcp),
+        };
+        let port = |s: &str| match s.trim().parse::<u16>() {
+            Ok(0) | Err(_) => Err(invalid()),
+            Ok(port) => Ok(port),
+        };
+        let (host_port, container_port) = if let Some((host, container)) = ports.split_once(':') {
+            (port(host)?, port(container)?)
+        } else {
+            let p = port(ports)?;
+            (p, p)
+        };
+        Ok(Self {
+            protocol,
+            host_port,
+            container_port,
+        })
+    }
+}
+
+impl std::fmt::Display for PortForward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host_port, self.container_port)?;
+        match self.protocol {
+            PortProtocol::Tcp => Ok(()),
+            PortProtocol::Udp => f.write_str("/udp"),
+        }
+    }
+}
+
+/// An `/etc/hosts` entry from `[network] extra_hosts`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExtraHost {
+    /// Lowercased hostname.
+    pub hostname: String,
+    /// Address in canonical form, e.g. `"10.0.0.5"` or `"fd00::5"`.
+    pub address: String,
+}
+
+impl ExtraHost {
+    /// Parse `"db.local:10.0.0.5"`. Everything after the first `:` is the
+    /// address, so IPv6 addresses need no brackets.
+    pub fn parse(spec: &str) -> Result<Self, ManifestError> {
+        let invalid = || ManifestError::InvalidExtraHost(spec.to_owned());
+        let (hostname, address) = spec.trim().split_once(':').ok_or_else(invalid)?;
+        let hostname = hostname.trim().to_ascii_lowercase();
+        let valid_hostname = !hostname.is_empty()
+            && !hostname.starts_with(['.', '-'])
+            && !hostname.ends_with(['.', '-'])
+            && hostname
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if !valid_hostname {
+            return Err(invalid());
+        }
+        let address = parse_address(address).ok_or_else(invalid)?;
+        Ok(Self { hostname, address })
+    }
+}
+
+impl std::fmt::Display for ExtraHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.hostname, self.address)
+    }
+}
+
+/// `s` as an IP address in canonical form.
+fn parse_address(s: &str) -> Option<String> {
+    s.trim()
+        .parse::<std::net::IpAddr>()
+        .ok()
+        .map(|ip| ip.to_string())
+}
+
+/// A language whose packages the manifest can install.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Python,
+    Node,
+    Rust,
+}
+
+impl Language {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Python => "python",
+            Self::Node => "node",
+            Self::Rust => "rust",
+        }
+    }
+
+    /// The project file a lockfile of this language is read together with.
+    pub fn lockfile_companion(self) -> Option<&'static str> {
+        match self {
+            Self::Python => None,
+            Self::Node => Some("package.json"),
+            Self::Rust => Some("Cargo.toml"),
+        }
+    }
+
+    /// Whether `package` names an exact version in this language's syntax.
+    fn is_pinned(self, package: &str) -> bool {
+        let split = match self {
+            Self::Python => package.split_once("=="),
+            Self::Node | Self::Rust => package.rsplit_once('@'),
+        };
+        split.is_some_and(|(name, version)| {
+            !name.is_empty()
+                && !name.contains(['<', '>', '=', '!', '~', ',', ';'])
+                && is_exact_version(version)
+        })
+    }
+
+    fn pinned_example(self) -> &'static str {
+        match self {
+            Self::Python => "requests==2.32.3",
+            Self::Node => "typescript@5.4.5",
+            Self::Rust => "ripgrep@14.1.0",
+        }
+    }
+}
+
+/// `3.12`, `20` or `1.79.0-beta.1`: starts with a digit, no ranges.
+fn is_exact_version(version: &str) -> bool {
+    version.starts_with(|c: char| c.is_ascii_digit())
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-'))
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A `[python]`, `[node]` or `[rust]` section with its packages sorted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NormalizedToolchain {
+    pub language: Language,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Pinned packages, sorted and deduplicated.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    /// Lockfile path relative to the manifest directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockfile: Option<String>,
+    /// blake3 of the lockfile and its companion project file, set by
+    /// [`NormalizedManifest::hash_toolchain_lockfiles`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockfile_hash: Option<String>,
+}
+
+impl NormalizedToolchain {
+    fn from_section(
+        language: Language,
+        section: &ToolchainSection,
+    ) -> Result<Option<Self>, ManifestError> {
+        if section.is_empty() {
+            return Ok(None);
+        }
+        let packages = normalize_string_list(&section.packages);
+        if let Some(package) = packages.iter().find(|p| !language.is_pinned(p)) {
+            return Err(ManifestError::UnpinnedToolchainPackage {
+                language,
+                package: package.clone(),
+                example: language.pinned_example(),
+            });
+        }
+        let trimmed = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_owned)
+        };
+        let version = trimmed(&section.version);
+        if let Some(version) = version.as_ref().filter(|v| !is_exact_version(v)) {
+            return Err(ManifestError::InvalidToolchainVersion {
+                language,
+                version: version.clone(),
+            });
+        }
+        Ok(Some(Self {
+            language,
+            version,
+            packages,
+            lockfile: trimmed(&section.lockfile),
+            lockfile_hash: None,
+        }))
+    }
+
+    /// Whether installing the toolchain downloads anything.
+    pub fn needs_network(&self) -> bool {
+        !self.packages.is_empty() || self.lockfile.is_some()
+    }
+}
+
+/// `python 3.12 requests==2.32.3 lockfile:<hash prefix>`, as shown in lock
+/// diffs.
+impl std::fmt::Display for NormalizedToolchain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.language.as_str())?;
+        if let Some(version) = &self.version {
+            write!(f, " {version}")?;
+        }
+        for pkg in &self.packages {
+            write!(f, " {pkg}")?;
+        }
+        match (&self.lockfile_hash, &self.lockfile) {
+            (Some(hash), _) => write!(f, " lockfile:{}", &hash[..12.min(hash.len())]),
+            (None, Some(path)) => write!(f, " lockfile:{path}"),
+            (None, None) => Ok(()),
+        }
+    }
 }
 
 impl ManifestV1 {
     /// Normalize the manifest: validate fields, sort packages, resolve defaults.
     pub fn normalize(&self) -> Result<NormalizedManifest, ManifestError> {
-        if self.manifest_version != 1 {
+        if !(MIN_MANIFEST_VERSION..=CURRENT_MANIFEST_VERSION).contains(&self.manifest_version) {
             return Err(ManifestError::UnsupportedVersion(self.manifest_version));
         }
 
@@ -46,58 +423,388 @@ impl ManifestV1 {
             if trimmed_label.is_empty() {
                 return Err(ManifestError::EmptyMountLabel);
             }
-            let (host_path, container_path) = parse_mount_spec(label, spec)?;
+            let (host_path, container_path, options) = parse_mount_spec(label, spec)?;
             mounts.push(NormalizedMount {
                 label: trimmed_label,
                 host_path,
                 container_path,
+                options,
             });
         }
         mounts.sort_by(|a, b| a.label.cmp(&b.label));
 
         let runtime_backend = self.runtime.backend.trim().to_lowercase();
 
+        let system_packages = normalize_string_list(&self.system.packages);
+        for pattern in system_packages.iter().filter(|p| is_package_pattern(p)) {
+            validate_package_pattern(pattern)?;
+        }
+
+        let network_mode = match (self.network.mode, self.runtime.network_isolation) {
+            (None, false) => NetworkMode::Host,
+            (None | Some(NetworkMode::Isolated), true) => NetworkMode::Isolated,
+            (Some(mode), false) => mode,
+            (Some(mode), true) => return Err(ManifestError::ConflictingNetworkMode(mode)),
+        };
+        let max_overlay_mb = self.runtime.overlay_limit()?;
+        let forward_ports = normalize_port_forwards(&self.network.forward_ports)?;
+        if !forward_ports.is_empty() && !network_mode.has_outbound() {
+            return Err(ManifestError::ForwardPortsWithoutNetwork(network_mode));
+        }
+        let dns_servers = normalize_dns_servers(&self.network.dns)?;
+        let mut extra_hosts = self
+            .network
+            .extra_hosts
+            .iter()
+            .map(|spec| ExtraHost::parse(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+        extra_hosts.sort();
+        extra_hosts.dedup();
+        let toolchains = self.normalize_toolchains()?;
+
         Ok(NormalizedManifest {
-            manifest_version: self.manifest_version,
+            // Every version normalizes to the v1 form, so upgrading a
+            // manifest keeps its identity.
+            manifest_version: MIN_MANIFEST_VERSION,
             base_image,
-            system_packages: normalize_string_list(&self.system.packages),
+            system_packages,
             gui_apps: normalize_string_list(&self.gui.apps),
             hardware_gpu: self.hardware.gpu,
-            hardware_audio: self.hardware.audio,
+            hardware_audio_out: self.hardware.audio_out,
+            hardware_audio_in: self.hardware.audio_in,
+            hardware_camera: self.hardware.camera,
             mounts,
             runtime_backend,
-            network_isolation: self.runtime.network_isolation,
+            network_isolation: network_mode == NetworkMode::Isolated,
+            network_mode,
             cpu_shares: self.runtime.resource_limits.cpu_shares,
             memory_limit_mb: self.runtime.resource_limits.memory_limit_mb,
+            runtime_init: self.runtime.init,
+            max_overlay_mb,
+            expires_after_secs: self
+                .runtime
+                .expires_after
+                .as_deref()
+                .map(expires_after_secs)
+                .transpose()?,
+            post_build_hook: self
+                .hooks
+                .post_build
+                .as_deref()
+                .map(str::trim)
+                .filter(|script| !script.is_empty())
+                .map(str::to_owned),
+            build_steps: self
+                .build
+                .steps
+                .iter()
+                .map(|step| step.trim())
+                .filter(|step| !step.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            forward_ports,
+            dns_servers,
+            extra_hosts,
+            toolchains,
+            variables: self.variables.clone(),
+            labels: normalize_labels(&self.labels)?,
         })
     }
+
+    fn normalize_toolchains(&self) -> Result<Vec<NormalizedToolchain>, ManifestError> {
+        [
+            (Language::Python, &self.python),
+            (Language::Node, &self.node),
+            (Language::Rust, &self.rust),
+        ]
+        .into_iter()
+        .filter_map(|(language, section)| {
+            NormalizedToolchain::from_section(language, section).transpose()
+        })
+        .collect()
+    }
+}
+
+/// Merge the manifest table `overlay` onto `base`, as `extends` and
+/// `include` do. Tables are merged key by key and arrays are appended to,
+/// skipping elements `base` already has, so a manifest can add packages,
+/// mounts and build steps to the ones it extends. Any other value in
+/// `overlay` replaces the one in `base`.
+pub fn merge_manifest_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_manifest_tables(base, overlay);
+            }
+            (Some(toml::Value::Array(base)), toml::Value::Array(overlay)) => {
+                for value in overlay {
+                    if !base.contains(&value) {
+                        base.push(value);
+                    }
+                }
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn normalize_dns_servers(servers: &[String]) -> Result<Vec<String>, ManifestError> {
+    let mut addresses: Vec<String> = Vec::with_capacity(servers.len());
+    for server in servers {
+        let address =
+            parse_address(server).ok_or_else(|| ManifestError::InvalidDnsServer(server.clone()))?;
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    Ok(addresses)
+}
+
+fn normalize_port_forwards(specs: &[String]) -> Result<Vec<PortForward>, ManifestError> {
+    let mut forwards = specs
+        .iter()
+        .map(|spec| PortForward::parse(spec))
+        .collect::<Result<Vec<_>, _>>()?;
+    forwards.sort();
+    forwards.dedup();
+    for pair in forwards.windows(2) {
+        if pair[0].protocol == pair[1].protocol && pair[0].host_port == pair[1].host_port {
+            return Err(ManifestError::DuplicateHostPort(pair[0].host_port));
+        }
+    }
+    Ok(forwards)
 }
 
 impl NormalizedManifest {
     pub fn canonical_json(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string(self)
     }
+
+    /// Hash each toolchain lockfile, resolved against `manifest_dir`, into
+    /// `lockfile_hash`, so the identity changes whenever a lockfile does.
+    pub fn hash_toolchain_lockfiles(&mut self, manifest_dir: &Path) -> Result<(), ManifestError> {
+        for toolchain in &mut self.toolchains {
+            let Some(lockfile) = &toolchain.lockfile else {
+                continue;
+            };
+            let path = manifest_dir.join(lockfile);
+            let mut files = vec![path.clone()];
+            if let Some(companion) = toolchain.language.lockfile_companion() {
+                files.push(path.with_file_name(companion));
+            }
+            let mut hasher = blake3::Hasher::new();
+            for file in files {
+                let data =
+                    std::fs::read(&file).map_err(|source| ManifestError::ToolchainLockfile {
+                        language: toolchain.language,
+                        path: file.clone(),
+                        source,
+                    })?;
+                hasher.update(&(data.len() as u64).to_le_bytes());
+                hasher.update(&data);
+            }
+            toolchain.lockfile_hash = Some(hasher.finalize().to_hex().to_string());
+        }
+        Ok(())
+    }
 }
 
-fn parse_mount_spec(label: &str, spec: &str) -> Result<(String, String), ManifestError> {
-    let Some((host_raw, container_raw)) = spec.split_once(':') else {
-        return Err(ManifestError::InvalidMount {
-            label: label.to_owned(),
-            spec: spec.to_owned(),
-        });
+fn parse_mount_spec(
+    label: &str,
+    spec: &str,
+) -> Result<(String, String, Vec<MountOption>), ManifestError> {
+    let invalid = || ManifestError::InvalidMount {
+        label: label.to_owned(),
+        spec: spec.to_owned(),
     };
-
-    let host_path = host_raw.trim().to_owned();
-    let container_path = container_raw.trim().to_owned();
+    let mut parts = spec.splitn(3, ':');
+    let host_path = parts.next().unwrap_or_default().trim().to_owned();
+    let container_path = parts.next().ok_or_else(invalid)?.trim().to_owned();
 
     if host_path.is_empty() || container_path.is_empty() {
-        return Err(ManifestError::InvalidMount {
-            label: label.to_owned(),
-            spec: spec.to_owned(),
-        });
+        return Err(invalid());
     }
 
-    Ok((host_path, container_path))
+    let mut options = Vec::new();
+    if let Some(raw) = parts.next() {
+        for option in raw.split(',').map(str::trim) {
+            if option.is_empty() {
+                return Err(invalid());
+            }
+            options.push(MountOption::parse(option).ok_or_else(|| {
+                ManifestError::InvalidMountOption {
+                    label: label.to_owned(),
+                    option: option.to_owned(),
+                }
+            })?);
+        }
+        options.sort();
+        options.dedup();
+    }
+
+    Ok((host_path, container_path, options))
+}
+
+/// Whether a package entry is a glob pattern (`*` or `?`) rather than a name.
+pub fn is_package_pattern(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+fn validate_package_pattern(pattern: &str) -> Result<(), ManifestError> {
+    if pattern.chars().any(char::is_whitespace) || !pattern.contains(|c| c != '*' && c != '?') {
+        return Err(ManifestError::InvalidPackagePattern(pattern.to_owned()));
+    }
+    Ok(())
+}
+
+/// Match `name` against a glob where `*` is any run of characters and `?`
+/// is exactly one.
+pub fn package_pattern_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Expand glob patterns in a normalized package list against a package
+/// index. Literal names are kept as-is. The result is sorted and
+/// deduplicated so expansion is deterministic regardless of index order.
+pub fn expand_package_patterns(
+    packages: &[String],
+    index: &[String],
+) -> Result<Vec<String>, ManifestError> {
+    let mut out = Vec::with_capacity(packages.len());
+    for entry in packages {
+        if !is_package_pattern(entry) {
+            out.push(entry.clone());
+            continue;
+        }
+        let before = out.len();
+        out.extend(
+            index
+                .iter()
+                .filter(|name| package_pattern_matches(entry, name))
+                .cloned(),
+        );
+        if out.len() == before {
+            return Err(ManifestError::UnmatchedPackagePattern(entry.clone()));
+        }
+    }
+    out.sort();
+    out.dedup();
+    Ok(out)
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)] // signature required by serde
+pub(crate) fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)] // signature required by serde
+pub(crate) fn is_true(value: &bool) -> bool {
+    *value
+}
+
+pub(crate) fn default_true() -> bool {
+    true
+}
+
+/// Check a label: the key is 1-63 characters of `[A-Za-z0-9._/-]`
+/// starting with a letter or digit, the value up to 63 characters of
+/// `[A-Za-z0-9._-]`.
+pub fn validate_label(key: &str, value: &str) -> Result<(), String> {
+    let valid_key = key.len() <= 63
+        && key.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '/'));
+    if !valid_key {
+        return Err(format!(
+            "key '{key}' must be 1-63 characters of [A-Za-z0-9._/-] starting with a letter or digit"
+        ));
+    }
+    let valid_value = value.len() <= 63
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid_value {
+        return Err(format!(
+            "value '{value}' of '{key}' must be at most 63 characters of [A-Za-z0-9._-]"
+        ));
+    }
+    Ok(())
+}
+
+/// Parse and check a label written as `key=value`.
+pub fn parse_label(spec: &str) -> Result<(String, String), String> {
+    let (key, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("'{spec}' is not of the form key=value"))?;
+    validate_label(key, value)?;
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+/// Parse an age such as `30d`, `12h`, `2w` or `90m`.
+pub fn parse_age(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+    let count: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid age '{value}': expected e.g. 30d or 12h"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        "w" => 7 * 86_400,
+        _ => {
+            return Err(format!(
+                "invalid age unit in '{value}': use s, m, h, d or w"
+            ))
+        }
+    };
+    Ok(Duration::from_secs(count.saturating_mul(seconds)))
+}
+
+fn normalize_labels(
+    labels: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, ManifestError> {
+    for (key, value) in labels {
+        validate_label(key, value).map_err(ManifestError::InvalidLabel)?;
+    }
+    Ok(labels.clone())
+}
+
+fn expires_after_secs(value: &str) -> Result<u64, ManifestError> {
+    parse_age(value)
+        .map(|age| age.as_secs())
+        .map_err(ManifestError::InvalidExpiresAfter)
 }
 
 fn normalize_string_list(values: &[String]) -> Vec<String> {
@@ -113,6 +820,7 @@ fn normalize_string_list(values: &[String]) -> Vec<String> {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::manifest::parse_manifest_str;
 
     #[test]
@@ -206,6 +914,44 @@ workspace = "./no-colon"
         assert!(manifest.normalize().is_err());
     }
 
+    #[test]
+    fn mount_options_are_parsed_and_sorted() {
+        let parse = |spec: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[mounts]\ndata = \"{spec}\"\n"
+            ))
+            .unwrap()
+            .normalize()
+        };
+        let mount = parse("./data:/data").unwrap().mounts.remove(0);
+        assert!(mount.options.is_empty());
+        assert!(!mount.is_read_only());
+        assert_eq!(mount.canonical_spec(), "data:./data:/data");
+        let json = serde_json::to_string(&mount).unwrap();
+        assert!(!json.contains("options"));
+
+        let mount = parse("./data:/data: nosuid,ro,nodev,ro")
+            .unwrap()
+            .mounts
+            .remove(0);
+        assert_eq!(
+            mount.options,
+            [MountOption::Ro, MountOption::Nodev, MountOption::Nosuid]
+        );
+        assert!(mount.is_read_only());
+        assert_eq!(mount.container_path, "/data");
+        assert_eq!(mount.canonical_spec(), "data:./data:/data:ro,nodev,nosuid");
+
+        assert!(matches!(
+            parse("./data:/data:rw"),
+            Err(ManifestError::InvalidMountOption { option, .. }) if option == "rw"
+        ));
+        assert!(matches!(
+            parse("./data:/data:ro,"),
+            Err(ManifestError::InvalidMount { .. })
+        ));
+    }
+
     #[test]
     fn runtime_backend_included_in_normalization() {
         let manifest = parse_manifest_str(
@@ -221,4 +967,534 @@ backend = "OCI"
         let normalized = manifest.normalize().unwrap();
         assert_eq!(normalized.runtime_backend, "oci");
     }
+
+    #[test]
+    fn package_patterns_are_validated() {
+        let input = |pkg: &str| {
+            format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[system]\npackages = [\"{pkg}\"]\n"
+            )
+        };
+        let ok = parse_manifest_str(&input("python3-*-dev")).unwrap();
+        assert_eq!(
+            ok.normalize().unwrap().system_packages,
+            vec!["python3-*-dev"]
+        );
+
+        for bad in ["*", "?*", "lib *"] {
+            let manifest = parse_manifest_str(&input(bad)).unwrap();
+            assert!(
+                matches!(
+                    manifest.normalize(),
+                    Err(ManifestError::InvalidPackagePattern(_))
+                ),
+                "{bad} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(package_pattern_matches("python3-*-dev", "python3-foo-dev"));
+        assert!(package_pattern_matches("python3-*-dev", "python3-a-b-dev"));
+        assert!(!package_pattern_matches("python3-*-dev", "python3-dev"));
+        assert!(package_pattern_matches("lib?", "liba"));
+        assert!(!package_pattern_matches("lib?", "lib"));
+        assert!(package_pattern_matches("*-doc", "git-doc"));
+        assert!(package_pattern_matches("git*", "git"));
+        assert!(!package_pattern_matches("git", "gitk"));
+    }
+
+    #[test]
+    fn expansion_is_sorted_and_deterministic() {
+        let packages = vec!["git".to_owned(), "python3-*-dev".to_owned()];
+        let index: Vec<String> = ["python3-venv-dev", "python3-all-dev", "python3", "git"]
+            .iter()
+            .map(|s| (*s).to_owned())
+            .collect();
+        let mut reversed = index.clone();
+        reversed.reverse();
+
+        let expanded = expand_package_patterns(&packages, &index).unwrap();
+        assert_eq!(expanded, vec!["git", "python3-all-dev", "python3-venv-dev"]);
+        assert_eq!(
+            expanded,
+            expand_package_patterns(&packages, &reversed).unwrap()
+        );
+    }
+
+    #[test]
+    fn unmatched_pattern_is_an_error() {
+        let err =
+            expand_package_patterns(&["nothing-*".to_owned()], &["git".to_owned()]).unwrap_err();
+        assert!(matches!(err, ManifestError::UnmatchedPackagePattern(ref p) if p == "nothing-*"));
+        assert!(err.to_string().contains("nothing-*"));
+    }
+
+    #[test]
+    fn media_grants_normalize_separately() {
+        let parse = |hardware: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[hardware]\n{hardware}\n"
+            ))
+            .unwrap()
+            .normalize()
+            .unwrap()
+        };
+
+        let legacy = parse("audio = true");
+        assert!(legacy.hardware_audio_out);
+        assert!(!legacy.hardware_audio_in);
+        assert!(!legacy.hardware_camera);
+        assert_eq!(legacy, parse("audio_out = true"));
+
+        // Manifests without capture grants keep their canonical form.
+        let json = legacy.canonical_json().unwrap();
+        assert!(json.contains("\"hardware_audio\":true"));
+        assert!(!json.contains("hardware_audio_in"));
+        assert!(!json.contains("hardware_camera"));
+
+        let call = parse("audio_out = true\naudio_in = true\ncamera = true");
+        assert!(call.hardware_audio_in && call.hardware_camera);
+        assert!(call
+            .canonical_json()
+            .unwrap()
+            .contains("\"hardware_camera\":true"));
+    }
+
+    #[test]
+    fn init_is_on_by_default_and_left_out_of_canonical_form() {
+        let parse = |runtime: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\n{runtime}\n"
+            ))
+            .unwrap()
+            .normalize()
+            .unwrap()
+        };
+
+        let default = parse("");
+        assert!(default.runtime_init);
+        let json = default.canonical_json().unwrap();
+        assert!(!json.contains("runtime_init"));
+        // Manifests stored before the setting existed deserialize with it on.
+        let stored: NormalizedManifest = serde_json::from_str(&json).unwrap();
+        assert!(stored.runtime_init);
+
+        let off = parse("init = false");
+        assert!(!off.runtime_init);
+        assert!(off
+            .canonical_json()
+            .unwrap()
+            .contains("\"runtime_init\":false"));
+    }
+
+    #[test]
+    fn overlay_limit_is_left_out_of_lock_identity() {
+        let parse = |runtime: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\n{runtime}\n"
+            ))
+            .unwrap()
+            .normalize()
+            .unwrap()
+        };
+
+        let unlimited = parse("");
+        assert_eq!(unlimited.max_overlay_mb, None);
+        assert!(!unlimited
+            .canonical_json()
+            .unwrap()
+            .contains("max_overlay_mb"));
+
+        let limited = parse("max_overlay_mb = 512");
+        assert_eq!(limited.max_overlay_mb, Some(512));
+        let resolution = crate::ResolutionResult {
+            base_image_digest: "a".repeat(64),
+            resolved_packages: Vec::new(),
+        };
+        let identity = |n: &NormalizedManifest| {
+            crate::LockFile::from_resolved(n, &resolution).compute_identity()
+        };
+        assert_eq!(identity(&unlimited), identity(&limited));
+    }
+
+    #[test]
+    fn expiry_is_parsed_and_left_out_of_lock_identity() {
+        let parse = |runtime: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\n{runtime}\n"
+            ))
+            .unwrap()
+            .normalize()
+        };
+
+        let kept = parse("").unwrap();
+        assert_eq!(kept.expires_after_secs, None);
+        let expiring = parse("expires_after = \"30d\"").unwrap();
+        assert_eq!(expiring.expires_after_secs, Some(30 * 86_400));
+        let resolution = crate::ResolutionResult {
+            base_image_digest: "a".repeat(64),
+            resolved_packages: Vec::new(),
+        };
+        assert_eq!(
+            crate::LockFile::from_resolved(&kept, &resolution).compute_identity(),
+            crate::LockFile::from_resolved(&expiring, &resolution).compute_identity()
+        );
+        assert!(matches!(
+            parse("expires_after = \"soon\""),
+            Err(ManifestError::InvalidExpiresAfter(_))
+        ));
+    }
+
+    #[test]
+    fn labels_are_checked() {
+        assert_eq!(
+            parse_label("team=infra").unwrap(),
+            ("team".to_owned(), "infra".to_owned())
+        );
+        assert_eq!(parse_label("example.com/tier=").unwrap().1, "");
+        assert!(parse_label("team").is_err());
+        assert!(parse_label("=infra").is_err());
+        assert!(parse_label("team=two words").is_err());
+        assert!(parse_label(&format!("{}=x", "k".repeat(64))).is_err());
+
+        let manifest = |labels: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[labels]\n{labels}\n"
+            ))
+            .unwrap()
+            .normalize()
+        };
+        assert_eq!(
+            manifest("team = \"infra\"").unwrap().labels["team"],
+            "infra"
+        );
+        assert!(matches!(
+            manifest("team = \"a b\""),
+            Err(ManifestError::InvalidLabel(_))
+        ));
+    }
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("30d").unwrap(), Duration::from_hours(30 * 24));
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_hours(12));
+        assert_eq!(parse_age("2w").unwrap(), Duration::from_hours(14 * 24));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+
+    #[test]
+    fn disk_limit_mb_sets_the_overlay_limit() {
+        let parse = |runtime: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\n{runtime}\n\
+                 [runtime.resource_limits]\ndisk_limit_mb = 1024\n"
+            ))
+            .unwrap()
+            .normalize()
+        };
+
+        assert_eq!(parse("").unwrap().max_overlay_mb, Some(1024));
+        assert_eq!(
+            parse("max_overlay_mb = 1024").unwrap().max_overlay_mb,
+            Some(1024)
+        );
+        assert!(matches!(
+            parse("max_overlay_mb = 512"),
+            Err(ManifestError::ConflictingDiskLimit {
+                max_overlay_mb: 512,
+                disk_limit_mb: 1024,
+            })
+        ));
+    }
+
+    #[test]
+    fn post_build_hook_is_trimmed_and_enters_identity() {
+        let parse = |hooks: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n{hooks}\n"
+            ))
+            .unwrap()
+            .normalize()
+            .unwrap()
+        };
+
+        let plain = parse("");
+        assert_eq!(plain.post_build_hook, None);
+        assert!(!plain.canonical_json().unwrap().contains("post_build"));
+        assert_eq!(parse("[hooks]\npost_build = \"  \"").post_build_hook, None);
+
+        let hooked = parse("[hooks]\npost_build = \"\"\"\n./bootstrap.sh\n\"\"\"");
+        assert_eq!(hooked.post_build_hook.as_deref(), Some("./bootstrap.sh"));
+        let resolution = crate::ResolutionResult {
+            base_image_digest: "a".repeat(64),
+            resolved_packages: Vec::new(),
+        };
+        let lock = crate::LockFile::from_resolved(&hooked, &resolution);
+        assert_ne!(
+            lock.compute_identity(),
+            crate::LockFile::from_resolved(&plain, &resolution).compute_identity()
+        );
+        assert!(lock.verify_manifest_intent(&hooked).is_ok());
+        assert!(lock.verify_manifest_intent(&plain).is_err());
+
+        assert!(parse_manifest_str(
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[hooks]\npre_build = \"x\"\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn port_forwards_are_parsed_and_sorted() {
+        let parse = |extra: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n{extra}\n"
+            ))
+            .unwrap()
+            .normalize()
+        };
+
+        let plain = parse("").unwrap();
+        assert!(plain.forward_ports.is_empty());
+        assert!(!plain.canonical_json().unwrap().contains("forward_ports"));
+
+        let n = parse(
+            "[network]\nforward_ports = [\"8080:80\", \" 5432 \", \"53:5353/udp\", \"5432\"]",
+        )
+        .unwrap();
+        let specs: Vec<String> = n.forward_ports.iter().map(ToString::to_string).collect();
+        assert_eq!(specs, ["5432:5432", "8080:80", "53:5353/udp"]);
+        assert_eq!(
+            n.forward_ports[1],
+            PortForward {
+                protocol: PortProtocol::Tcp,
+                host_port: 8080,
+                container_port: 80,
+            }
+        );
+
+        for bad in ["0", "80:", "http", "70000:80", "80/sctp", "1:2:3"] {
+            assert!(
+                matches!(
+                    PortForward::parse(bad),
+                    Err(ManifestError::InvalidPortForward(_))
+                ),
+                "{bad}"
+            );
+        }
+        assert!(matches!(
+            parse("[network]\nforward_ports = [\"8080:80\", \"8080:81\"]"),
+            Err(ManifestError::DuplicateHostPort(8080))
+        ));
+        // The same host port can carry TCP and UDP.
+        assert!(parse("[network]\nforward_ports = [\"53\", \"53/udp\"]").is_ok());
+        assert!(matches!(
+            parse("[runtime]\nnetwork_isolation = true\n[network]\nforward_ports = [\"80\"]"),
+            Err(ManifestError::ForwardPortsWithoutNetwork(
+                NetworkMode::Isolated
+            ))
+        ));
+    }
+
+    #[test]
+    fn dns_and_extra_hosts_are_canonical() {
+        let parse = |network: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n[network]\n{network}"
+            ))
+            .unwrap()
+            .normalize()
+        };
+        let n = parse(
+            r#"dns = ["10.0.0.53", "fd00:0::53", "10.0.0.53", "1.1.1.1"]
+extra_hosts = ["registry.corp:10.0.0.7", "DB.local:10.0.0.5", "db.local:fd00::5"]"#,
+        )
+        .unwrap();
+        assert_eq!(n.dns_servers, ["10.0.0.53", "fd00::53", "1.1.1.1"]);
+        let hosts: Vec<String> = n.extra_hosts.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            hosts,
+            [
+                "db.local:10.0.0.5",
+                "db.local:fd00::5",
+                "registry.corp:10.0.0.7"
+            ]
+        );
+
+        assert!(matches!(
+            parse(r#"dns = ["dns.corp"]"#),
+            Err(ManifestError::InvalidDnsServer(_))
+        ));
+        for bad in [
+            "db.local",
+            ":10.0.0.5",
+            "db local:10.0.0.5",
+            "db.local:host",
+            "-db:1.2.3.4",
+        ] {
+            assert!(
+                matches!(
+                    ExtraHost::parse(bad),
+                    Err(ManifestError::InvalidExtraHost(_))
+                ),
+                "{bad}"
+            );
+        }
+
+        // Without overrides the canonical form is unchanged.
+        let plain = parse("").unwrap().canonical_json().unwrap();
+        assert!(!plain.contains("dns_servers") && !plain.contains("extra_hosts"));
+    }
+
+    #[test]
+    fn network_mode_resolves_with_legacy_isolation() {
+        let parse = |extra: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n{extra}"
+            ))
+            .unwrap()
+            .normalize()
+        };
+        let mode = |extra: &str| parse(extra).unwrap().network_mode;
+        assert_eq!(mode(""), NetworkMode::Host);
+        assert_eq!(
+            mode("[runtime]\nnetwork_isolation = true"),
+            NetworkMode::Isolated
+        );
+        assert_eq!(mode("[network]\nmode = \"slirp\""), NetworkMode::Slirp);
+        assert_eq!(
+            mode("[runtime]\nnetwork_isolation = true\n[network]\nmode = \"isolated\""),
+            NetworkMode::Isolated
+        );
+        assert!(matches!(
+            parse("[runtime]\nnetwork_isolation = true\n[network]\nmode = \"slirp\""),
+            Err(ManifestError::ConflictingNetworkMode(NetworkMode::Slirp))
+        ));
+        assert!(matches!(
+            parse("[network]\nmode = \"none\"\nforward_ports = [\"80\"]"),
+            Err(ManifestError::ForwardPortsWithoutNetwork(NetworkMode::None))
+        ));
+        assert!(parse("[network]\nmode = \"slirp\"\nforward_ports = [\"80\"]").is_ok());
+
+        // `isolated` spelled either way has the same canonical form.
+        let legacy = parse("[runtime]\nnetwork_isolation = true").unwrap();
+        let explicit = parse("[network]\nmode = \"isolated\"").unwrap();
+        assert_eq!(
+            legacy.canonical_json().unwrap(),
+            explicit.canonical_json().unwrap()
+        );
+        assert!(!legacy.canonical_json().unwrap().contains("network_mode"));
+        let slirp = parse("[network]\nmode = \"slirp\"").unwrap();
+        assert!(slirp
+            .canonical_json()
+            .unwrap()
+            .contains(r#""network_mode":"slirp""#));
+    }
+
+    #[test]
+    fn toolchain_sections_require_pinned_packages() {
+        let parse = |extra: &str| {
+            parse_manifest_str(&format!(
+                "manifest_version = 1\n[base]\nimage = \"rolling\"\n{extra}"
+            ))
+            .unwrap()
+            .normalize()
+        };
+        let normalized = parse(
+            "[rust]\npackages = [\"ripgrep@14.1.0\"]\n\
+             [python]\nversion = \" 3.12 \"\npackages = [\"requests==2.32.3\", \"black==24.4.2\"]\n\
+             [node]\npackages = [\"@types/node@20.14.2\"]\n",
+        )
+        .unwrap();
+        let languages: Vec<Language> = normalized.toolchains.iter().map(|t| t.language).collect();
+        assert_eq!(
+            languages,
+            [Language::Python, Language::Node, Language::Rust]
+        );
+        assert_eq!(normalized.toolchains[0].version.as_deref(), Some("3.12"));
+        assert_eq!(
+            normalized.toolchains[0].packages,
+            ["black==24.4.2", "requests==2.32.3"]
+        );
+
+        for (section, package) in [
+            ("python", "requests"),
+            ("python", "requests>=2"),
+            ("node", "typescript@^5.4.0"),
+            ("node", "@types/node"),
+            ("rust", "ripgrep"),
+        ] {
+            assert!(
+                matches!(
+                    parse(&format!("[{section}]\npackages = [\"{package}\"]")),
+                    Err(ManifestError::UnpinnedToolchainPackage { .. })
+                ),
+                "{package}"
+            );
+        }
+        assert!(matches!(
+            parse("[python]\nversion = \"3.12; rm -rf /\""),
+            Err(ManifestError::InvalidToolchainVersion { .. })
+        ));
+
+        // Manifests without toolchains keep their canonical form.
+        assert!(!parse("")
+            .unwrap()
+            .canonical_json()
+            .unwrap()
+            .contains("toolchains"));
+    }
+
+    #[test]
+    fn toolchain_lockfiles_are_hashed_with_their_project_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("package-lock.json"), "{\"v\":1}").unwrap();
+        let mut normalized = parse_manifest_str(
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[node]\nlockfile = \"package-lock.json\"\n",
+        )
+        .unwrap()
+        .normalize()
+        .unwrap();
+        assert!(matches!(
+            normalized.clone().hash_toolchain_lockfiles(dir.path()),
+            Err(ManifestError::ToolchainLockfile { .. })
+        ));
+
+        std::fs::write(dir.path().join("package.json"), "{}").unwrap();
+        normalized.hash_toolchain_lockfiles(dir.path()).unwrap();
+        let first = normalized.toolchains[0].lockfile_hash.clone().unwrap();
+
+        std::fs::write(dir.path().join("package.json"), "{\"name\":\"x\"}").unwrap();
+        normalized.hash_toolchain_lockfiles(dir.path()).unwrap();
+        assert_ne!(
+            normalized.toolchains[0].lockfile_hash.as_ref(),
+            Some(&first)
+        );
+    }
+
+    #[test]
+    fn merged_manifests_append_lists_and_override_values() {
+        let mut base: toml::Table = toml::from_str(
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n\
+             [system]\npackages = [\"git\", \"curl\"]\n\
+             [mounts]\nworkspace = \"./:/workspace\"\n\
+             [runtime]\nbackend = \"oci\"\n",
+        )
+        .unwrap();
+        let overlay: toml::Table = toml::from_str(
+            "[system]\npackages = [\"curl\", \"clang\"]\n\
+             [mounts]\ncache = \"~/.cache:/cache\"\n\
+             [runtime]\nbackend = \"namespace\"\n",
+        )
+        .unwrap();
+        merge_manifest_tables(&mut base, overlay);
+        let manifest = ManifestV1::deserialize(base).unwrap();
+        assert_eq!(manifest.system.packages, ["git", "curl", "clang"]);
+        assert_eq!(manifest.mounts.entries.len(), 2);
+        assert_eq!(manifest.runtime.backend, "namespace");
+        assert_eq!(manifest.base.image, "rolling");
+    }
 }
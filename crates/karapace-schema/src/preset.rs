@@ -63,7 +63,7 @@ image = "rolling"
 
 [hardware]
 gpu = true
-audio = true
+audio_out = true
 
 [runtime]
 backend = "namespace"
@@ -82,7 +82,7 @@ packages = ["mesa-dri", "vulkan-loader", "libvulkan1", "alsa-plugins"]
 
 [hardware]
 gpu = true
-audio = true
+audio_out = true
 
 [runtime]
 backend = "namespace"
@@ -0,0 +1,149 @@
+//! Variables in manifest mounts.
+//!
+//! `[mounts]` labels and specs may use `${env:NAME}`, the value of an
+//! environment variable of the process parsing the manifest, and
+//! `${project_dir}`, the absolute directory of the manifest file. They are
+//! expanded in the TOML table before the manifest is deserialized, so
+//! normalization and identity only ever see the expanded mounts. Every
+//! variable used is returned with its value, for the lock to record.
+//! Anywhere else in the manifest, `${` is left as written.
+
+use crate::manifest::ManifestError;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Name of the variable holding the manifest's directory.
+pub const PROJECT_DIR: &str = "project_dir";
+
+/// Expand the variables of `doc`'s `[mounts]` table in place, looking up
+/// environment variables with `env`. `project_dir` is `None` for manifests
+/// not read from a file. Returns the value of each variable used, keyed by
+/// its name as written (`env:HOME`, `project_dir`).
+pub fn expand_mount_variables(
+    doc: &mut toml::Table,
+    project_dir: Option<&Path>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<BTreeMap<String, String>, ManifestError> {
+    let mut used = BTreeMap::new();
+    let Some(toml::Value::Table(mounts)) = doc.get_mut("mounts") else {
+        return Ok(used);
+    };
+    if !mounts.iter().any(|(label, spec)| {
+        label.contains("${") || spec.as_str().is_some_and(|s| s.contains("${"))
+    }) {
+        return Ok(used);
+    }
+    let mut lookup = |name: &str| -> Result<String, ManifestError> {
+        let value = if name == PROJECT_DIR {
+            project_dir
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .ok_or(ManifestError::ProjectDirWithoutFile)?
+        } else if let Some(var) = name.strip_prefix("env:").filter(|var| is_env_name(var)) {
+            env(var).ok_or_else(|| ManifestError::UnsetVariable(var.to_owned()))?
+        } else {
+            return Err(ManifestError::InvalidVariable(format!("${{{name}}}")));
+        };
+        used.insert(name.to_owned(), value.clone());
+        Ok(value)
+    };
+    let mut expanded = toml::Table::new();
+    for (label, spec) in std::mem::take(mounts) {
+        let spec = match spec {
+            toml::Value::String(spec) => toml::Value::String(expand(&spec, &mut lookup)?),
+            // Left for deserialization to reject.
+            other => other,
+        };
+        expanded.insert(expand(&label, &mut lookup)?, spec);
+    }
+    *mounts = expanded;
+    Ok(used)
+}
+
+/// `input` with each `${name}` replaced by `lookup(name)`.
+fn expand(
+    input: &str,
+    lookup: &mut impl FnMut(&str) -> Result<String, ManifestError>,
+) -> Result<String, ManifestError> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| ManifestError::InvalidVariable(input.to_owned()))?;
+        out.push_str(&lookup(&after[..end])?);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mounts(specs: &str) -> toml::Table {
+        toml::from_str(&format!("[mounts]\n{specs}")).unwrap()
+    }
+
+    fn env(name: &str) -> Option<String> {
+        (name == "HOME").then(|| "/home/dev".to_owned())
+    }
+
+    #[test]
+    fn mount_labels_and_specs_are_expanded() {
+        let mut doc = mounts(
+            "\"cache-${env:HOME}\" = \"${env:HOME}/.cache:/cache:ro\"\n\
+             workspace = \"${project_dir}:/workspace\"\n",
+        );
+        let used = expand_mount_variables(&mut doc, Some(Path::new("/src/app")), env).unwrap();
+        let mounts = doc["mounts"].as_table().unwrap();
+        assert_eq!(
+            mounts["cache-/home/dev"].as_str(),
+            Some("/home/dev/.cache:/cache:ro")
+        );
+        assert_eq!(mounts["workspace"].as_str(), Some("/src/app:/workspace"));
+        assert_eq!(
+            used.into_iter().collect::<Vec<_>>(),
+            [
+                ("env:HOME".to_owned(), "/home/dev".to_owned()),
+                ("project_dir".to_owned(), "/src/app".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_unset_and_unterminated_variables_are_errors() {
+        let expand_one = |spec: &str, dir: Option<&Path>| {
+            expand_mount_variables(&mut mounts(&format!("m = \"{spec}\"\n")), dir, env)
+        };
+        assert!(matches!(
+            expand_one("${env:NOPE}:/x", None),
+            Err(ManifestError::UnsetVariable(name)) if name == "NOPE"
+        ));
+        assert!(matches!(
+            expand_one("${project_dir}:/x", None),
+            Err(ManifestError::ProjectDirWithoutFile)
+        ));
+        for spec in ["${home}:/x", "${env:1X}:/x", "${env:HOME:/x"] {
+            assert!(
+                matches!(
+                    expand_one(spec, Some(Path::new("/p"))),
+                    Err(ManifestError::InvalidVariable(_))
+                ),
+                "{spec}"
+            );
+        }
+        // Nothing to expand.
+        assert!(expand_one("./:/workspace", None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+//! Manifest versions.
+//!
+//! `manifest_version = 2` drops the v1 spellings that have a replacement:
+//! the keys in [`DEPRECATIONS`], and the keys in [`REMOVED_IN_V2`], whose
+//! replacement takes a different value. Version 1 still accepts both, with
+//! a warning. [`upgrade_manifest`] rewrites a v1 manifest to v2 with the
+//! same meaning, and both versions normalize to the same
+//! [`NormalizedManifest`](crate::NormalizedManifest), so upgrading does not
+//! change an environment's identity.
+
+use crate::deprecation::{
+    apply_deprecations, get_key, set_key, take_key, DeprecationWarning, DEPRECATIONS,
+};
+use crate::manifest::ManifestError;
+
+/// Version written by `karapace new` and `karapace manifest upgrade`.
+pub const CURRENT_MANIFEST_VERSION: u32 = 2;
+
+/// Oldest version still parsed.
+pub const MIN_MANIFEST_VERSION: u32 = 1;
+
+/// v1 keys rejected from version 2, with the key replacing each.
+pub const REMOVED_IN_V2: &[(&str, &str)] = &[
+    ("runtime.network_isolation", "network.mode"),
+    (
+        "runtime.resource_limits.disk_limit_mb",
+        "runtime.max_overlay_mb",
+    ),
+];
+
+/// The `manifest_version` of a manifest table, if it has a valid one.
+pub fn manifest_version(doc: &toml::Table) -> Option<u32> {
+    doc.get("manifest_version")
+        .and_then(toml::Value::as_integer)
+        .and_then(|v| u32::try_from(v).ok())
+}
+
+/// Reject the keys of [`REMOVED_IN_V2`] in a v2 manifest table, or return a
+/// warning for each one a v1 manifest uses.
+pub fn check_removed_keys(doc: &toml::Table) -> Result<Vec<DeprecationWarning>, ManifestError> {
+    let v2 = manifest_version(doc).is_some_and(|v| v >= 2);
+    let mut warnings = Vec::new();
+    for (key, replacement) in REMOVED_IN_V2 {
+        if get_key(doc, key).is_none() {
+            continue;
+        }
+        if v2 {
+            return Err(ManifestError::RemovedField {
+                key: (*key).to_owned(),
+                replacement: (*replacement).to_owned(),
+                version: 2,
+            });
+        }
+        warnings.push(DeprecationWarning {
+            key: (*key).to_owned(),
+            replacement: (*replacement).to_owned(),
+            removed_in: 2,
+        });
+    }
+    Ok(warnings)
+}
+
+/// Rewrite the manifest table `doc` to the current version, keeping its
+/// meaning. Returns `false` when it already is. A table without
+/// `manifest_version`, as a manifest that takes it from the one it
+/// `extends` may be, counts as version 1.
+pub fn upgrade_manifest(doc: &mut toml::Table) -> Result<bool, ManifestError> {
+    let version = match doc.get("manifest_version") {
+        None => MIN_MANIFEST_VERSION,
+        Some(_) => manifest_version(doc).ok_or(ManifestError::UnsupportedVersion(0))?,
+    };
+    if version == CURRENT_MANIFEST_VERSION {
+        return Ok(false);
+    }
+    if version != 1 {
+        return Err(ManifestError::UnsupportedVersion(version));
+    }
+
+    apply_deprecations(doc, DEPRECATIONS)?;
+    if let Some(isolated) = take_key(doc, "runtime.network_isolation") {
+        if isolated.as_bool() == Some(true) {
+            match get_key(doc, "network.mode").and_then(toml::Value::as_str) {
+                None => set_key(doc, "network.mode", "isolated".into()),
+                Some("isolated") => {}
+                Some(_) => {
+                    return Err(ManifestError::DeprecatedFieldConflict {
+                        key: "runtime.network_isolation".to_owned(),
+                        replacement: "network.mode".to_owned(),
+                    })
+                }
+            }
+        }
+    }
+    if let Some(limit) = take_key(doc, "runtime.resource_limits.disk_limit_mb") {
+        match get_key(doc, "runtime.max_overlay_mb") {
+            None => set_key(doc, "runtime.max_overlay_mb", limit),
+            Some(max) if *max == limit => {}
+            Some(_) => {
+                return Err(ManifestError::DeprecatedFieldConflict {
+                    key: "runtime.resource_limits.disk_limit_mb".to_owned(),
+                    replacement: "runtime.max_overlay_mb".to_owned(),
+                })
+            }
+        }
+    }
+    doc.insert(
+        "manifest_version".to_owned(),
+        i64::from(CURRENT_MANIFEST_VERSION).into(),
+    );
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{parse_manifest_str, parse_manifest_str_with_warnings};
+
+    const V1: &str = "manifest_version = 1\n[base]\nimage = \"rolling\"\n\
+                      [hardware]\naudio = true\n\
+                      [runtime]\nnetwork_isolation = true\n\
+                      [runtime.resource_limits]\ndisk_limit_mb = 2048\nmemory_limit_mb = 512\n";
+
+    #[test]
+    fn upgrade_keeps_the_normalized_manifest() {
+        let (v1, warnings) = parse_manifest_str_with_warnings(V1).unwrap();
+        assert_eq!(warnings.len(), 3);
+
+        let mut doc: toml::Table = toml::from_str(V1).unwrap();
+        assert!(upgrade_manifest(&mut doc).unwrap());
+        let upgraded = toml::to_string(&doc).unwrap();
+        let (v2, warnings) = parse_manifest_str_with_warnings(&upgraded).unwrap();
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(v2.manifest_version, 2);
+        assert_eq!(v1.normalize().unwrap(), v2.normalize().unwrap());
+
+        assert!(!upgrade_manifest(&mut doc).unwrap());
+    }
+
+    #[test]
+    fn v2_rejects_v1_spellings() {
+        for (key, section) in [
+            ("audio", "hardware"),
+            ("network_isolation", "runtime"),
+            ("disk_limit_mb", "runtime.resource_limits"),
+        ] {
+            let input = format!(
+                "manifest_version = 2\n[base]\nimage = \"rolling\"\n[{section}]\n{key} = true\n"
+            );
+            assert!(
+                matches!(
+                    parse_manifest_str(&input),
+                    Err(ManifestError::RemovedField { version: 2, .. })
+                ),
+                "{key}"
+            );
+        }
+        assert!(matches!(
+            parse_manifest_str("manifest_version = 3\n[base]\nimage = \"rolling\"\n")
+                .unwrap()
+                .normalize(),
+            Err(ManifestError::UnsupportedVersion(3))
+        ));
+    }
+
+    #[test]
+    fn conflicting_v1_values_are_not_upgraded() {
+        let mut doc: toml::Table = toml::from_str(
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n\
+             [runtime]\nnetwork_isolation = true\n[network]\nmode = \"slirp\"\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            upgrade_manifest(&mut doc),
+            Err(ManifestError::DeprecatedFieldConflict { .. })
+        ));
+        let mut doc: toml::Table = toml::from_str("[system]\npackages = [\"git\"]\n").unwrap();
+        assert!(upgrade_manifest(&mut doc).unwrap());
+        assert_eq!(manifest_version(&doc), Some(2));
+    }
+}
@@ -0,0 +1,188 @@
+//! Bearer-token access control.
+//!
+//! The token file lists one token per line with the scope it grants:
+//!
+//! ```text
+//! # scope  token
+//! read   3c5d0e...
+//! write  9f12ab...
+//! ```
+//!
+//! `read` tokens may `GET` and `HEAD`; `write` tokens may also `PUT`,
+//! `PATCH`, `POST` and `DELETE`. Blank lines and `#` comments are ignored.
+//! Only blake3 hashes of the tokens are kept in memory, and they are
+//! compared in constant time.
+
+use std::path::Path;
+use tiny_http::Method;
+
+/// Shortest token accepted from the token file.
+pub const MIN_TOKEN_LEN: usize = 16;
+
+/// What a token allows. `Write` includes `Read`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Read,
+    Write,
+}
+
+impl Scope {
+    /// Scope a request method needs.
+    pub fn for_method(method: &Method) -> Self {
+        match method {
+            Method::Get | Method::Head => Self::Read,
+            _ => Self::Write,
+        }
+    }
+}
+
+/// Outcome of checking a request's credentials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Granted,
+    /// No token, or one that is not in the token file: `401`.
+    Unauthenticated,
+    /// A valid token without the needed scope: `403`.
+    Forbidden,
+}
+
+/// The tokens a server accepts.
+#[derive(Debug, Clone, Default)]
+pub struct Tokens {
+    entries: Vec<(blake3::Hash, Scope)>,
+}
+
+impl Tokens {
+    /// Parse a token file.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(scope), Some(token), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(format!(
+                    "line {}: expected '<read|write> <token>'",
+                    number + 1
+                ));
+            };
+            let scope = match scope {
+                "read" => Scope::Read,
+                "write" => Scope::Write,
+                other => return Err(format!("line {}: unknown scope '{other}'", number + 1)),
+            };
+            if token.len() < MIN_TOKEN_LEN {
+                return Err(format!(
+                    "line {}: token is shorter than {MIN_TOKEN_LEN} characters",
+                    number + 1
+                ));
+            }
+            entries.push((blake3::hash(token.as_bytes()), scope));
+        }
+        if entries.is_empty() {
+            return Err("no tokens".to_owned());
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&content)
+    }
+
+    /// Add a token, for tests and embedding.
+    #[must_use]
+    pub fn with_token(mut self, token: &str, scope: Scope) -> Self {
+        self.entries.push((blake3::hash(token.as_bytes()), scope));
+        self
+    }
+
+    /// The widest scope `token` grants, if it is known.
+    pub fn scope_of(&self, token: &str) -> Option<Scope> {
+        let hash = blake3::hash(token.as_bytes());
+        // `blake3::Hash` equality is constant-time; look at every entry.
+        self.entries
+            .iter()
+            .filter(|(h, _)| *h == hash)
+            .map(|(_, scope)| *scope)
+            .max()
+    }
+
+    /// Check an `Authorization` header value against the scope a request
+    /// needs.
+    pub fn check(&self, authorization: Option<&str>, needed: Scope) -> Access {
+        let token = authorization
+            .and_then(|v| v.trim().strip_prefix("Bearer "))
+            .map(str::trim);
+        match token.and_then(|t| self.scope_of(t)) {
+            None => Access::Unauthenticated,
+            Some(scope) if scope >= needed => Access::Granted,
+            Some(_) => Access::Forbidden,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READ: &str = "read-token-0123456789";
+    const WRITE: &str = "write-token-0123456789";
+
+    #[test]
+    fn parses_token_file() {
+        let tokens = Tokens::parse(&format!(
+            "# ci runners\nread {READ}\n\n  write\t{WRITE}  \n"
+        ))
+        .unwrap();
+        assert_eq!(tokens.scope_of(READ), Some(Scope::Read));
+        assert_eq!(tokens.scope_of(WRITE), Some(Scope::Write));
+        assert_eq!(tokens.scope_of("unknown-token-0123"), None);
+    }
+
+    #[test]
+    fn rejects_bad_token_files() {
+        assert!(Tokens::parse("").is_err());
+        assert!(Tokens::parse("# only comments\n").is_err());
+        assert!(Tokens::parse(&format!("admin {WRITE}")).is_err());
+        assert!(Tokens::parse("write short").is_err());
+        assert!(Tokens::parse(&format!("write {WRITE} extra")).is_err());
+        let err = Tokens::parse(&format!("read {READ}\nwrite")).unwrap_err();
+        assert!(err.starts_with("line 2:"), "{err}");
+    }
+
+    #[test]
+    fn checks_scopes() {
+        let tokens = Tokens::default()
+            .with_token(READ, Scope::Read)
+            .with_token(WRITE, Scope::Write);
+        let bearer = |t: &str| format!("Bearer {t}");
+
+        assert_eq!(tokens.check(None, Scope::Read), Access::Unauthenticated);
+        assert_eq!(
+            tokens.check(Some("Basic abc"), Scope::Read),
+            Access::Unauthenticated
+        );
+        assert_eq!(
+            tokens.check(Some(&bearer("nope-nope-nope-nope")), Scope::Read),
+            Access::Unauthenticated
+        );
+        assert_eq!(
+            tokens.check(Some(&bearer(READ)), Scope::Read),
+            Access::Granted
+        );
+        assert_eq!(
+            tokens.check(Some(&bearer(READ)), Scope::Write),
+            Access::Forbidden
+        );
+        assert_eq!(
+            tokens.check(Some(&bearer(WRITE)), Scope::Write),
+            Access::Granted
+        );
+        assert_eq!(Scope::for_method(&Method::Head), Scope::Read);
+        assert_eq!(Scope::for_method(&Method::Patch), Scope::Write);
+    }
+}
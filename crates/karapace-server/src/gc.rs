@@ -0,0 +1,501 @@
+//! Reference-tracking garbage collection of stored blobs.
+//!
+//! Metadata blobs are the roots: each names an environment's layers and its
+//! manifest and attestation objects, and each layer names the objects
+//! holding its content. Layers and objects nothing reaches are deleted.
+//! Pushes without a registry key leave metadata no registry entry points
+//! at; with [`GcOptions::prune_untagged`] such metadata is deleted too, so
+//! only environments reachable from the registry survive. A signature is
+//! kept exactly as long as the metadata it signs.
+//!
+//! Clients push objects, then layers, then metadata, and only then update
+//! the registry, so a push in progress looks like garbage for a while. Blobs
+//! younger than [`GcOptions::grace`] are therefore always kept.
+//!
+//! Objects larger than the client's chunk threshold are pushed as a chunk
+//! list plus one object per chunk; the chunks of a live chunk list are live.
+//!
+//! Encrypted pushes store blobs the server cannot read. When a reachable
+//! metadata, layer or object blob cannot be parsed, the kinds it would
+//! reference are not swept, and the blob is listed under `unreadable` in
+//! the report.
+
+use crate::Store;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Read;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// Age below which a blob is never collected.
+pub const DEFAULT_GRACE: Duration = Duration::from_hours(1);
+
+#[derive(Debug, Clone, Copy)]
+pub struct GcOptions {
+    /// Report what would be removed without deleting anything.
+    pub dry_run: bool,
+    pub grace: Duration,
+    /// Also delete metadata no registry entry references.
+    pub prune_untagged: bool,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            grace: DEFAULT_GRACE,
+            prune_untagged: false,
+        }
+    }
+}
+
+/// What a collection found and removed, sent as the `POST /admin/gc` body.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub live_metadata: usize,
+    pub live_layers: usize,
+    pub live_objects: usize,
+    pub metadata_removed: usize,
+    /// Signatures of environments whose metadata is gone.
+    pub signatures_removed: usize,
+    /// Seal records of environments whose metadata is gone.
+    pub seals_removed: usize,
+    pub layers_removed: usize,
+    pub objects_removed: usize,
+    pub bytes_freed: u64,
+    /// Reachable blobs that could not be parsed, as `Kind/key`.
+    pub unreadable: Vec<String>,
+}
+
+/// Delete every blob no root reaches.
+pub fn collect(store: &Store, options: GcOptions) -> Result<GcReport, String> {
+    let mut report = GcReport {
+        dry_run: options.dry_run,
+        ..GcReport::default()
+    };
+
+    let live_metadata = live_metadata(store, options)?;
+
+    let mut live_layers = BTreeSet::new();
+    let mut live_objects = BTreeSet::new();
+    let mut layers_known = true;
+    for env_id in &live_metadata {
+        let Some(data) = store.get_blob("Metadata", env_id) else {
+            continue;
+        };
+        let Ok(meta) = serde_json::from_slice::<Value>(&data) else {
+            report.unreadable.push(format!("Metadata/{env_id}"));
+            layers_known = false;
+            continue;
+        };
+        live_layers.extend(
+            strings(&meta, "base_layer")
+                .chain(strings(&meta, "dependency_layers"))
+                .chain(strings(&meta, "policy_layer")),
+        );
+        live_objects.extend(strings(&meta, "manifest_hash").chain(strings(&meta, "attestation")));
+    }
+
+    let mut objects_known = layers_known;
+    for hash in &live_layers {
+        let Some(data) = store.get_blob("Layer", hash) else {
+            continue;
+        };
+        let Ok(layer) = serde_json::from_slice::<Value>(&data) else {
+            report.unreadable.push(format!("Layer/{hash}"));
+            objects_known = false;
+            continue;
+        };
+        live_objects.extend(strings(&layer, "object_refs").chain(strings(&layer, "tar_hash")));
+    }
+
+    // A chunked object's blob is its chunk list; the chunks are live too.
+    if objects_known {
+        let mut chunks = BTreeSet::new();
+        for hash in &live_objects {
+            if let Ok(refs) = chunk_refs(store, hash) {
+                chunks.extend(refs);
+            } else {
+                report.unreadable.push(format!("Object/{hash}"));
+                objects_known = false;
+            }
+        }
+        live_objects.extend(chunks);
+    }
+
+    report.live_metadata = live_metadata.len();
+    report.live_layers = live_layers.len();
+    report.live_objects = live_objects.len();
+
+    let (removed, bytes) = sweep(store, "Metadata", &live_metadata, options)?;
+    report.metadata_removed = removed;
+    report.bytes_freed += bytes;
+    let (removed, bytes) = sweep(store, "Signature", &live_metadata, options)?;
+    report.signatures_removed = removed;
+    report.bytes_freed += bytes;
+    let (removed, bytes) = sweep(store, "Seal", &live_metadata, options)?;
+    report.seals_removed = removed;
+    report.bytes_freed += bytes;
+    if layers_known {
+        let (removed, bytes) = sweep(store, "Layer", &live_layers, options)?;
+        report.layers_removed = removed;
+        report.bytes_freed += bytes;
+    }
+    if objects_known {
+        let (removed, bytes) = sweep(store, "Object", &live_objects, options)?;
+        report.objects_removed = removed;
+        report.bytes_freed += bytes;
+    }
+
+    if !report.unreadable.is_empty() {
+        warn!(
+            "gc: {} reachable blob(s) could not be parsed; kept everything they may reference",
+            report.unreadable.len()
+        );
+    }
+    info!(
+        "gc{}: removed {} metadata, {} layer(s), {} object(s), {} bytes",
+        if options.dry_run { " (dry run)" } else { "" },
+        report.metadata_removed,
+        report.layers_removed,
+        report.objects_removed,
+        report.bytes_freed
+    );
+    Ok(report)
+}
+
+/// Metadata keys that are roots: those named by the registry, or all of
+/// them unless `options.prune_untagged`.
+fn live_metadata(store: &Store, options: GcOptions) -> Result<BTreeSet<String>, String> {
+    let registry: Value = match store.get_registry() {
+        Some(data) => {
+            serde_json::from_slice(&data).map_err(|e| format!("invalid registry: {e}"))?
+        }
+        None => Value::Null,
+    };
+    let tagged = registry
+        .get("entries")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|entries| entries.values())
+        .filter_map(|entry| entry.get("env_id").and_then(Value::as_str))
+        .map(str::to_owned);
+    let mut live: BTreeSet<String> = if options.prune_untagged {
+        tagged.collect()
+    } else {
+        tagged.chain(store.list_blobs("Metadata")).collect()
+    };
+    // Encrypted environments keep their metadata under `<env_id>.<seal id>`.
+    // It cannot be parsed, so everything it may reference is kept.
+    let sealed: Vec<String> = store
+        .list_blobs("Metadata")
+        .into_iter()
+        .filter(|key| {
+            key.split_once('.')
+                .is_some_and(|(env_id, _)| live.contains(env_id))
+        })
+        .collect();
+    live.extend(sealed);
+    Ok(live)
+}
+
+/// The string, or strings of the array, stored under `field`.
+fn strings<'a>(value: &'a Value, field: &str) -> impl Iterator<Item = String> + 'a {
+    let field = value.get(field);
+    let single = field.and_then(Value::as_str);
+    let many = field.and_then(Value::as_array).into_iter().flatten();
+    single
+        .into_iter()
+        .chain(many.filter_map(Value::as_str))
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+}
+
+/// First line of a chunk list (see `karapace_store::chunking`).
+const CHUNK_LIST_MAGIC: &[u8] = b"karapace-chunks/1\n";
+/// First bytes of an age-encrypted blob.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+/// Chunks named by object blob `hash` when it holds a chunk list, otherwise
+/// none. `Err` when the blob is encrypted and might be one.
+fn chunk_refs(store: &Store, hash: &str) -> Result<Vec<String>, ()> {
+    let Ok(file) = fs::File::open(store.blob_path("Object", hash)) else {
+        return Ok(Vec::new());
+    };
+    let mut head = Vec::new();
+    let mut file = std::io::BufReader::new(file);
+    if (&mut file)
+        .take(CHUNK_LIST_MAGIC.len().max(AGE_MAGIC.len()) as u64)
+        .read_to_end(&mut head)
+        .is_err()
+    {
+        return Ok(Vec::new());
+    }
+    if head.starts_with(AGE_MAGIC) {
+        return Err(());
+    }
+    if !head.starts_with(CHUNK_LIST_MAGIC) {
+        return Ok(Vec::new());
+    }
+    if file.read_to_end(&mut head).is_err() {
+        return Ok(Vec::new());
+    }
+    Ok(String::from_utf8_lossy(&head[CHUNK_LIST_MAGIC.len()..])
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Remove the blobs of `kind` not in `live` and older than the grace period.
+/// Returns how many were (or would be) removed and their total size.
+fn sweep(
+    store: &Store,
+    kind: &str,
+    live: &BTreeSet<String>,
+    options: GcOptions,
+) -> Result<(usize, u64), String> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+    let mut bytes = 0;
+    for key in store.list_blobs(kind) {
+        if live.contains(&key) {
+            continue;
+        }
+        let path = store.blob_path(kind, &key);
+        let Ok(meta) = fs::metadata(&path) else {
+            continue;
+        };
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if age < options.grace {
+            continue;
+        }
+        if !options.dry_run {
+            fs::remove_file(&path).map_err(|e| format!("failed to remove {kind}/{key}: {e}"))?;
+        }
+        removed += 1;
+        bytes += meta.len();
+    }
+    Ok((removed, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: GcOptions = GcOptions {
+        dry_run: false,
+        grace: Duration::ZERO,
+        prune_untagged: true,
+    };
+
+    fn setup() -> (tempfile::TempDir, Store) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().to_path_buf());
+        store
+            .put_registry(br#"{"entries":{"app@latest":{"env_id":"env1","short_id":"env1","pushed_at":"now"}}}"#)
+            .unwrap();
+        store
+            .put_blob(
+                "Metadata",
+                "env1",
+                br#"{"manifest_hash":"m1","base_layer":"l1","dependency_layers":["l2"],"policy_layer":null}"#,
+            )
+            .unwrap();
+        store
+            .put_blob("Layer", "l1", br#"{"object_refs":["o1"],"tar_hash":"o1"}"#)
+            .unwrap();
+        store
+            .put_blob("Layer", "l2", br#"{"object_refs":["o2"]}"#)
+            .unwrap();
+        for key in ["m1", "o1", "o2"] {
+            store.put_blob("Object", key, b"live").unwrap();
+        }
+        // An environment dropped from the registry, with its own blobs.
+        store
+            .put_blob(
+                "Metadata",
+                "env0",
+                br#"{"manifest_hash":"m0","base_layer":"l0"}"#,
+            )
+            .unwrap();
+        store
+            .put_blob("Layer", "l0", br#"{"object_refs":["o0","o1"]}"#)
+            .unwrap();
+        store.put_blob("Object", "m0", b"dead").unwrap();
+        store.put_blob("Object", "o0", b"dead").unwrap();
+        for env_id in ["env0", "env1"] {
+            store.put_blob("Signature", env_id, b"{}").unwrap();
+        }
+        (dir, store)
+    }
+
+    fn sorted(store: &Store, kind: &str) -> Vec<String> {
+        let mut keys = store.list_blobs(kind);
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn removes_unreachable_blobs() {
+        let (_dir, store) = setup();
+        let report = collect(&store, NOW).unwrap();
+        assert_eq!(report.live_metadata, 1);
+        assert_eq!(report.live_layers, 2);
+        assert_eq!(report.live_objects, 3);
+        assert_eq!(
+            (
+                report.metadata_removed,
+                report.layers_removed,
+                report.objects_removed
+            ),
+            (1, 1, 2)
+        );
+        assert!(report.bytes_freed > 0);
+        assert_eq!(sorted(&store, "Metadata"), ["env1"]);
+        assert_eq!(report.signatures_removed, 1);
+        assert_eq!(sorted(&store, "Signature"), ["env1"]);
+        assert_eq!(sorted(&store, "Layer"), ["l1", "l2"]);
+        assert_eq!(sorted(&store, "Object"), ["m1", "o1", "o2"]);
+
+        // Nothing is left to collect.
+        let again = collect(&store, NOW).unwrap();
+        assert_eq!(again.bytes_freed, 0);
+    }
+
+    #[test]
+    fn encrypted_environments_keep_their_sealed_blobs() {
+        let (_dir, store) = setup();
+        store
+            .put_registry(br#"{"entries":{"app@latest":{"env_id":"env2","short_id":"env2","pushed_at":"now"}}}"#)
+            .unwrap();
+        store
+            .put_blob("Metadata", "env2.age-0123", b"age-encryption.org/v1\n")
+            .unwrap();
+        store.put_blob("Layer", "l2.age-0123", b"sealed").unwrap();
+        store.put_blob("Object", "o2.age-0123", b"sealed").unwrap();
+        store.put_blob("Seal", "env2", b"{}").unwrap();
+        store.put_blob("Seal", "env0", b"{}").unwrap();
+
+        let report = collect(&store, NOW).unwrap();
+        assert_eq!(report.unreadable, ["Metadata/env2.age-0123"]);
+        assert_eq!(sorted(&store, "Metadata"), ["env2.age-0123"]);
+        assert_eq!(report.seals_removed, 1);
+        assert_eq!(sorted(&store, "Seal"), ["env2"]);
+        assert!(sorted(&store, "Layer").contains(&"l2.age-0123".to_owned()));
+        assert!(sorted(&store, "Object").contains(&"o2.age-0123".to_owned()));
+    }
+
+    #[test]
+    fn untagged_metadata_is_a_root_by_default() {
+        let (_dir, store) = setup();
+        let report = collect(
+            &store,
+            GcOptions {
+                prune_untagged: false,
+                ..NOW
+            },
+        )
+        .unwrap();
+        assert_eq!(report.live_metadata, 2);
+        assert_eq!(report.bytes_freed, 0);
+        assert_eq!(store.list_blobs("Object").len(), 5);
+    }
+
+    #[test]
+    fn dry_run_and_grace_keep_blobs() {
+        let (_dir, store) = setup();
+        let report = collect(
+            &store,
+            GcOptions {
+                dry_run: true,
+                ..NOW
+            },
+        )
+        .unwrap();
+        assert!(report.dry_run);
+        assert_eq!(report.objects_removed, 2);
+        assert_eq!(store.list_blobs("Object").len(), 5);
+
+        let report = collect(&store, GcOptions::default()).unwrap();
+        assert_eq!(report.objects_removed, 0);
+        assert_eq!(store.list_blobs("Object").len(), 5);
+    }
+
+    #[test]
+    fn unreadable_layers_keep_all_objects() {
+        let (_dir, store) = setup();
+        store
+            .put_blob("Layer", "l2", b"age-encryption.org/v1\n...")
+            .unwrap();
+        let report = collect(&store, NOW).unwrap();
+        assert_eq!(report.unreadable, ["Layer/l2"]);
+        assert_eq!(report.layers_removed, 1);
+        assert_eq!(report.objects_removed, 0);
+        assert_eq!(store.list_blobs("Object").len(), 5);
+    }
+
+    #[test]
+    fn unreadable_metadata_keeps_layers_and_objects() {
+        let (_dir, store) = setup();
+        store
+            .put_blob("Metadata", "env1", b"age-encryption.org/v1\n...")
+            .unwrap();
+        let report = collect(&store, NOW).unwrap();
+        assert_eq!(report.unreadable, ["Metadata/env1"]);
+        assert_eq!(report.metadata_removed, 1);
+        assert_eq!(report.layers_removed + report.objects_removed, 0);
+    }
+
+    #[test]
+    fn chunks_of_live_objects_are_kept() {
+        let (_dir, store) = setup();
+        let list = format!(
+            "karapace-chunks/1\n{} 3\n{} 4\n",
+            "c1".repeat(32),
+            "c2".repeat(32)
+        );
+        store.put_blob("Object", "o2", list.as_bytes()).unwrap();
+        for key in ["c1".repeat(32), "c2".repeat(32), "c3".repeat(32)] {
+            store.put_blob("Object", &key, b"chunk").unwrap();
+        }
+        let report = collect(&store, NOW).unwrap();
+        assert_eq!(report.live_objects, 5);
+        assert_eq!(report.objects_removed, 3);
+        let objects = sorted(&store, "Object");
+        assert!(objects.contains(&"c1".repeat(32)));
+        assert!(objects.contains(&"c2".repeat(32)));
+        assert!(!objects.contains(&"c3".repeat(32)));
+    }
+
+    #[test]
+    fn unreadable_objects_keep_all_objects() {
+        let (_dir, store) = setup();
+        store
+            .put_blob("Object", "o2", b"age-encryption.org/v1\n...")
+            .unwrap();
+        let report = collect(&store, NOW).unwrap();
+        assert_eq!(report.unreadable, ["Object/o2"]);
+        assert_eq!(report.layers_removed, 1);
+        assert_eq!(report.objects_removed, 0);
+    }
+
+    #[test]
+    fn missing_registry_prunes_every_untagged_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().to_path_buf());
+        store.put_blob("Object", "o", b"x").unwrap();
+        let report = collect(&store, NOW).unwrap();
+        assert_eq!(report.objects_removed, 1);
+
+        store.put_registry(b"not json").unwrap();
+        assert!(collect(&store, NOW).is_err());
+    }
+}
@@ -2,16 +2,76 @@
 //!
 //! Implements the blob store and registry routes defined in `docs/protocol-v1.md`.
 //! Storage is file-backed: blobs go into `{data_dir}/blobs/{kind}/{key}`,
-//! the registry lives at `{data_dir}/registry.json`.
+//! the registry lives at `{data_dir}/registry.json`. Blob bodies are streamed
+//! through `{data_dir}/tmp/` on upload and straight from disk on download, so
+//! memory use does not grow with blob size.
+//!
+//! Large blobs can be transferred in resumable chunks: `PATCH
+//! /{kind}/{key}?offset=N` appends to an upload session kept in
+//! `{data_dir}/uploads/` (`&final=1` commits it), `GET /uploads/{kind}/{key}`
+//! reports how many bytes the session holds, and `GET` honours
+//! `Range: bytes=N-M`.
+//!
+//! `GET /registry` sends an `ETag` and answers `304 Not Modified` when the
+//! client's `If-None-Match` still matches it. `GET /registry/tags?name=N`
+//! lists the tags published under a name and `DELETE /registry/{name}@{tag}`
+//! unpublishes one.
+//!
+//! With a token file (see [`auth`]), every route but `/health` needs an
+//! `Authorization: Bearer` token: `read` tokens for `GET`/`HEAD`, `write`
+//! tokens for `PUT`/`PATCH`/`DELETE` and upload sessions.
+//!
+//! `POST /admin/gc` deletes blobs no environment references and answers
+//! with a JSON report (see [`gc`]); `?dry_run=1` only reports, `?untagged=1`
+//! also drops environments the registry does not name, and `?grace=N`
+//! overrides how many seconds new blobs are protected.
 //!
 //! The [`TestServer`] helper starts a server on a random port for integration testing.
 
+pub mod auth;
+pub mod gc;
+pub mod systemd;
+
+use auth::{Access, Scope, Tokens};
+
 use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tiny_http::{Header, Method, Response, Server, StatusCode};
 use tracing::{debug, error, info};
 
+/// Header carrying an upload session's byte offset.
+pub const UPLOAD_OFFSET_HEADER: &str = "X-Karapace-Upload-Offset";
+
+/// Why a chunk could not be appended to an upload session.
+#[derive(Debug)]
+pub enum UploadError {
+    /// The chunk does not start where the session ends; carries the
+    /// session's current length.
+    OffsetMismatch(u64),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OffsetMismatch(current) => {
+                write!(f, "upload offset mismatch, session holds {current} bytes")
+            }
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
 /// In-memory + file-backed blob store.
 pub struct Store {
     data_dir: PathBuf,
@@ -46,12 +106,88 @@ impl Store {
         self.blob_dir(kind).join(key)
     }
 
-    pub fn put_blob(&self, kind: &str, key: &str, data: &[u8]) -> std::io::Result<()> {
+    pub fn put_blob(&self, kind: &str, key: &str, mut data: &[u8]) -> std::io::Result<()> {
+        self.put_blob_from(kind, key, &mut data).map(|_| ())
+    }
+
+    /// Stream `reader` into a blob. The body is written to a temp file and
+    /// renamed into place, so readers never see a partial blob. Returns the
+    /// number of bytes written.
+    pub fn put_blob_from(
+        &self,
+        kind: &str,
+        key: &str,
+        reader: &mut dyn Read,
+    ) -> std::io::Result<u64> {
+        static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
         let dir = self.blob_dir(kind);
         fs::create_dir_all(&dir)?;
-        let path = dir.join(key);
-        fs::write(&path, data)?;
-        Ok(())
+        let tmp_dir = self.data_dir.join("tmp");
+        fs::create_dir_all(&tmp_dir)?;
+        let tmp_path = tmp_dir.join(format!(
+            "{kind}-{key}.{}.{}",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let mut write = || -> std::io::Result<u64> {
+            let mut file = fs::File::create(&tmp_path)?;
+            let written = std::io::copy(reader, &mut file)?;
+            file.flush()?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, dir.join(key))?;
+            Ok(written)
+        };
+        let result = write();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    fn upload_path(&self, kind: &str, key: &str) -> PathBuf {
+        self.data_dir.join("uploads").join(format!("{kind}-{key}"))
+    }
+
+    /// Bytes held by the upload session for a blob (0 when there is none).
+    pub fn upload_offset(&self, kind: &str, key: &str) -> u64 {
+        fs::metadata(self.upload_path(kind, key)).map_or(0, |m| m.len())
+    }
+
+    /// Append `reader` to the upload session for a blob, starting at
+    /// `offset`. Offset 0 starts a fresh session. Returns the new session
+    /// length.
+    pub fn append_upload(
+        &self,
+        kind: &str,
+        key: &str,
+        offset: u64,
+        reader: &mut dyn Read,
+    ) -> Result<u64, UploadError> {
+        let path = self.upload_path(kind, key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = if offset == 0 {
+            fs::File::create(&path)?
+        } else {
+            let current = self.upload_offset(kind, key);
+            if current != offset {
+                return Err(UploadError::OffsetMismatch(current));
+            }
+            fs::OpenOptions::new().append(true).open(&path)?
+        };
+        let written = std::io::copy(reader, &mut file)?;
+        file.sync_all()?;
+        Ok(offset + written)
+    }
+
+    /// Move a finished upload session into place as the blob.
+    pub fn commit_upload(&self, kind: &str, key: &str) -> std::io::Result<()> {
+        let dir = self.blob_dir(kind);
+        fs::create_dir_all(&dir)?;
+        fs::rename(self.upload_path(kind, key), dir.join(key))
     }
 
     pub fn get_blob(&self, kind: &str, key: &str) -> Option<Vec<u8>> {
@@ -59,10 +195,23 @@ impl Store {
         fs::read(&path).ok()
     }
 
+    /// Open a blob for streaming, with its size in bytes.
+    pub fn open_blob(&self, kind: &str, key: &str) -> Option<(fs::File, u64)> {
+        let file = fs::File::open(self.blob_path(kind, key)).ok()?;
+        let len = file.metadata().ok()?.len();
+        Some((file, len))
+    }
+
     pub fn has_blob(&self, kind: &str, key: &str) -> bool {
         self.blob_path(kind, key).exists()
     }
 
+    pub fn blob_size(&self, kind: &str, key: &str) -> Option<u64> {
+        fs::metadata(self.blob_path(kind, key))
+            .ok()
+            .map(|m| m.len())
+    }
+
     pub fn list_blobs(&self, kind: &str) -> Vec<String> {
         let dir = self.blob_dir(kind);
         if !dir.exists() {
@@ -78,17 +227,46 @@ impl Store {
     }
 
     pub fn put_registry(&self, data: &[u8]) -> std::io::Result<()> {
-        let reg_path = self.data_dir.join("registry.json");
-        fs::create_dir_all(&self.data_dir)?;
-        fs::write(&reg_path, data)?;
         let mut reg = match self.registry.write() {
             Ok(g) => g,
             Err(e) => e.into_inner(),
         };
+        self.write_registry(data)?;
         *reg = Some(data.to_vec());
         Ok(())
     }
 
+    fn write_registry(&self, data: &[u8]) -> std::io::Result<()> {
+        fs::create_dir_all(&self.data_dir)?;
+        fs::write(self.data_dir.join("registry.json"), data)
+    }
+
+    /// Remove registry entry `key`, returning it. `Ok(None)` when there is
+    /// no such entry. The registry stays locked between read and write, so
+    /// concurrent removals cannot resurrect each other's entries.
+    pub fn remove_registry_entry(&self, key: &str) -> Result<Option<serde_json::Value>, String> {
+        let mut reg = match self.registry.write() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        let Some(data) = reg.as_ref() else {
+            return Ok(None);
+        };
+        let mut registry: serde_json::Value =
+            serde_json::from_slice(data).map_err(|e| format!("invalid registry: {e}"))?;
+        let Some(entry) = registry
+            .get_mut("entries")
+            .and_then(serde_json::Value::as_object_mut)
+            .and_then(|entries| entries.remove(key))
+        else {
+            return Ok(None);
+        };
+        let data = serde_json::to_vec_pretty(&registry).map_err(|e| e.to_string())?;
+        self.write_registry(&data).map_err(|e| e.to_string())?;
+        *reg = Some(data);
+        Ok(Some(entry))
+    }
+
     pub fn get_registry(&self) -> Option<Vec<u8>> {
         let reg = match self.registry.read() {
             Ok(g) => g,
@@ -100,16 +278,19 @@ impl Store {
 
 /// Valid blob kinds per protocol spec.
 pub fn is_valid_kind(kind: &str) -> bool {
-    matches!(kind, "Object" | "Layer" | "Metadata")
+    matches!(kind, "Object" | "Layer" | "Metadata" | "Signature" | "Seal")
 }
 
 /// Map the HttpBackend's plural lowercase path prefix to the server's internal kind name.
-/// `/objects/` → "Object", `/layers/` → "Layer", `/metadata/` → "Metadata".
+/// `/objects/` → "Object", `/layers/` → "Layer", `/metadata/` → "Metadata",
+/// `/signatures/` → "Signature", `/seals/` → "Seal".
 fn map_client_kind(prefix: &str) -> Option<&'static str> {
     match prefix {
         "objects" => Some("Object"),
         "layers" => Some("Layer"),
         "metadata" => Some("Metadata"),
+        "signatures" => Some("Signature"),
+        "seals" => Some("Seal"),
         _ => None,
     }
 }
@@ -157,11 +338,84 @@ fn respond_err(req: tiny_http::Request, code: u16, msg: &str) {
     let _ = req.respond(Response::from_string(msg).with_status_code(StatusCode(code)));
 }
 
-fn respond_octet(req: tiny_http::Request, data: Vec<u8>) {
-    let mut resp = Response::from_data(data);
+/// Stream a blob file as the response body, with `Content-Length` set.
+/// A `Range: bytes=N-[M]` request header gets a `206` with that slice.
+fn respond_file(req: tiny_http::Request, mut file: fs::File, len: u64) {
+    let range = req
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Range"))
+        .map(|h| parse_range(h.value.as_str(), len));
+    let (status, start, end) = match range {
+        None => (200, 0, len),
+        Some(Some((start, end))) => (206, start, end),
+        Some(None) => {
+            let mut resp = Response::empty(416);
+            if let Ok(header) = Header::from_bytes("Content-Range", format!("bytes */{len}")) {
+                resp = resp.with_header(header);
+            }
+            let _ = req.respond(resp);
+            return;
+        }
+    };
+    if start > 0 && file.seek(SeekFrom::Start(start)).is_err() {
+        respond_err(req, 500, "seek error");
+        return;
+    }
+
+    let body_len = usize::try_from(end - start).ok();
+    // tiny_http switches to chunked encoding above 32 KiB unless told otherwise.
+    let mut resp = Response::new(
+        StatusCode(status),
+        Vec::new(),
+        file.take(end - start),
+        body_len,
+        None,
+    )
+    .with_chunked_threshold(usize::MAX);
     if let Ok(header) = Header::from_bytes("Content-Type", "application/octet-stream") {
         resp = resp.with_header(header);
     }
+    if status == 206 {
+        let value = format!("bytes {start}-{}/{len}", end - 1);
+        if let Ok(header) = Header::from_bytes("Content-Range", value) {
+            resp = resp.with_header(header);
+        }
+    }
+    let _ = req.respond(resp);
+}
+
+/// Parse a single `bytes=N-[M]` range against a blob of `len` bytes into a
+/// half-open `(start, end)`. `None` when the range is malformed or starts
+/// past the end.
+fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+    let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end = match end.trim() {
+        "" => len,
+        last => last.parse::<u64>().ok()?.saturating_add(1).min(len),
+    };
+    (start < end).then_some((start, end))
+}
+
+/// Value of `name` in a `a=1&b=2` query string.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+fn respond_offset(req: tiny_http::Request, code: u16, offset: u64) {
+    let mut resp = Response::from_string(format!(r#"{{"offset":{offset}}}"#))
+        .with_status_code(StatusCode(code));
+    if let Ok(header) = Header::from_bytes(UPLOAD_OFFSET_HEADER, offset.to_string()) {
+        resp = resp.with_header(header);
+    }
+    if let Ok(header) = Header::from_bytes("Content-Type", "application/json") {
+        resp = resp.with_header(header);
+    }
     let _ = req.respond(resp);
 }
 
@@ -188,32 +442,63 @@ fn handle_blob_keyed(
     method: &Method,
     kind: &str,
     key: &str,
+    query: &str,
 ) {
     match *method {
-        Method::Put => {
-            let Some(body) = read_body(&mut req) else {
-                respond_err(req, 500, "read error");
+        Method::Patch => {
+            let Some(offset) = query_param(query, "offset").and_then(|v| v.parse::<u64>().ok())
+            else {
+                respond_err(req, 400, "missing or invalid offset");
                 return;
             };
-            match store.put_blob(kind, key, &body) {
-                Ok(()) => {
-                    info!("PUT {kind}/{key}: {} bytes", body.len());
-                    let _ = req.respond(Response::from_string("ok"));
+            let last = query_param(query, "final") == Some("1");
+            match store.append_upload(kind, key, offset, req.as_reader()) {
+                Ok(new_offset) => {
+                    if last {
+                        if let Err(e) = store.commit_upload(kind, key) {
+                            error!("PATCH {kind}/{key}: commit: {e}");
+                            respond_err(req, 500, &format!("write error: {e}"));
+                            return;
+                        }
+                        info!("PATCH {kind}/{key}: committed {new_offset} bytes");
+                    } else {
+                        debug!("PATCH {kind}/{key}: {new_offset} bytes so far");
+                    }
+                    respond_offset(req, 200, new_offset);
                 }
+                Err(UploadError::OffsetMismatch(current)) => respond_offset(req, 409, current),
                 Err(e) => {
-                    error!("PUT {kind}/{key}: {e}");
+                    error!("PATCH {kind}/{key}: {e}");
                     respond_err(req, 500, &format!("write error: {e}"));
                 }
             }
         }
-        Method::Get => match store.get_blob(kind, key) {
-            Some(data) => respond_octet(req, data),
+        Method::Put => match store.put_blob_from(kind, key, req.as_reader()) {
+            Ok(written) => {
+                info!("PUT {kind}/{key}: {written} bytes");
+                let _ = req.respond(Response::from_string("ok"));
+            }
+            Err(e) => {
+                error!("PUT {kind}/{key}: {e}");
+                respond_err(req, 500, &format!("write error: {e}"));
+            }
+        },
+        Method::Get => match store.open_blob(kind, key) {
+            Some((file, len)) => respond_file(req, file, len),
             None => respond_err(req, 404, "not found"),
         },
-        Method::Head => {
-            let code = if store.has_blob(kind, key) { 200 } else { 404 };
-            let _ = req.respond(Response::empty(code));
-        }
+        Method::Head => match store.blob_size(kind, key) {
+            Some(size) => {
+                let mut resp = Response::empty(200);
+                if let Ok(header) = Header::from_bytes("X-Karapace-Blob-Size", size.to_string()) {
+                    resp = resp.with_header(header);
+                }
+                let _ = req.respond(resp);
+            }
+            None => {
+                let _ = req.respond(Response::empty(404));
+            }
+        },
         _ => respond_err(req, 405, "method not allowed"),
     }
 }
@@ -237,24 +522,188 @@ fn handle_registry(store: &Store, mut req: tiny_http::Request, method: &Method)
             }
         }
         Method::Get => match store.get_registry() {
-            Some(data) => respond_json(req, data),
+            Some(data) => respond_registry(req, data),
             None => respond_err(req, 404, "not found"),
         },
         _ => respond_err(req, 405, "method not allowed"),
     }
 }
 
+fn handle_gc(store: &Store, req: tiny_http::Request, method: &Method, query: &str) {
+    if *method != Method::Post {
+        respond_err(req, 405, "method not allowed");
+        return;
+    }
+    let mut options = gc::GcOptions {
+        dry_run: query_param(query, "dry_run") == Some("1"),
+        prune_untagged: query_param(query, "untagged") == Some("1"),
+        ..gc::GcOptions::default()
+    };
+    if let Some(grace) = query_param(query, "grace") {
+        let Ok(secs) = grace.parse::<u64>() else {
+            respond_err(req, 400, "invalid grace");
+            return;
+        };
+        options.grace = Duration::from_secs(secs);
+    }
+    match gc::collect(store, options) {
+        Ok(report) => {
+            let json = serde_json::to_vec(&report).unwrap_or_else(|_| b"{}".to_vec());
+            respond_json(req, json);
+        }
+        Err(e) => {
+            error!("POST /admin/gc: {e}");
+            respond_err(req, 500, &e);
+        }
+    }
+}
+
+/// Send the tags published under `name` as a JSON object of tag to entry.
+fn respond_tags(store: &Store, req: tiny_http::Request, name: &str) {
+    let registry: serde_json::Value = match store.get_registry() {
+        Some(data) => match serde_json::from_slice(&data) {
+            Ok(v) => v,
+            Err(e) => {
+                respond_err(req, 500, &format!("invalid registry: {e}"));
+                return;
+            }
+        },
+        None => serde_json::Value::Null,
+    };
+    let tags: serde_json::Map<String, serde_json::Value> = registry
+        .get("entries")
+        .and_then(serde_json::Value::as_object)
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, entry)| match key.split_once('@') {
+            Some((n, tag)) if n == name => Some((tag.to_owned(), entry.clone())),
+            _ => None,
+        })
+        .collect();
+    let json = serde_json::to_vec(&tags).unwrap_or_else(|_| b"{}".to_vec());
+    respond_json(req, json);
+}
+
+fn handle_untag(store: &Store, req: tiny_http::Request, key: &str) {
+    match store.remove_registry_entry(key) {
+        Ok(Some(entry)) => {
+            info!("DELETE /registry/{key}");
+            let json = serde_json::to_vec(&entry).unwrap_or_else(|_| b"{}".to_vec());
+            respond_json(req, json);
+        }
+        Ok(None) => respond_err(req, 404, "not found"),
+        Err(e) => {
+            error!("DELETE /registry/{key}: {e}");
+            respond_err(req, 500, &e);
+        }
+    }
+}
+
+/// Send the registry with its entity tag, or `304 Not Modified` when the
+/// client already holds it.
+fn respond_registry(req: tiny_http::Request, data: Vec<u8>) {
+    let etag = format!("\"{}\"", blake3::hash(&data).to_hex());
+    let cached = req
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("If-None-Match"))
+        .is_some_and(|h| {
+            h.value
+                .as_str()
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag == etag)
+        });
+    let Ok(header) = Header::from_bytes("ETag", etag.as_bytes()) else {
+        respond_json(req, data);
+        return;
+    };
+    if cached {
+        let _ = req.respond(Response::empty(304).with_header(header));
+        return;
+    }
+    let mut resp = Response::from_data(data).with_header(header);
+    if let Ok(header) = Header::from_bytes("Content-Type", "application/json") {
+        resp = resp.with_header(header);
+    }
+    let _ = req.respond(resp);
+}
+
+/// Answer `401`/`403` unless `tokens` is unset or the request carries a
+/// token with `needed` scope. Returns the request when it may proceed.
+fn authorize(
+    tokens: Option<&Tokens>,
+    req: tiny_http::Request,
+    needed: Scope,
+) -> Option<tiny_http::Request> {
+    let Some(tokens) = tokens else {
+        return Some(req);
+    };
+    let authorization = req
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.as_str());
+    match tokens.check(authorization, needed) {
+        Access::Granted => Some(req),
+        Access::Unauthenticated => {
+            info!("{} {}: missing or unknown token", req.method(), req.url());
+            let mut resp = Response::from_string("authentication required").with_status_code(401);
+            if let Ok(header) = Header::from_bytes("WWW-Authenticate", "Bearer realm=\"karapace\"")
+            {
+                resp = resp.with_header(header);
+            }
+            let _ = req.respond(resp);
+            None
+        }
+        Access::Forbidden => {
+            info!("{} {}: token is read-only", req.method(), req.url());
+            respond_err(req, 403, "token does not allow writes");
+            None
+        }
+    }
+}
+
 /// Handle a single HTTP request, dispatching to the appropriate route handler.
-pub fn handle_request(store: &Store, req: tiny_http::Request) {
+/// With `tokens` set, requests are checked against them first.
+pub fn handle_request(store: &Store, tokens: Option<&Tokens>, req: tiny_http::Request) {
     let method = req.method().clone();
     let url = req.url().to_owned();
     debug!("{method} {url}");
 
+    let (path, query) = url.split_once('?').unwrap_or((url.as_str(), ""));
+
+    if path == "/health" && method == Method::Get {
+        let _ = req.respond(Response::from_string(r#"{"status":"ok"}"#));
+        return;
+    }
+    // Upload sessions only serve pushes.
+    let needed = if path.starts_with("/uploads") {
+        Scope::Write
+    } else {
+        Scope::for_method(&method)
+    };
+    let Some(req) = authorize(tokens, req, needed) else {
+        return;
+    };
+
+    // Upload sessions: GET /uploads/{kind_plural}/{key}
+    if let Some(rest) = path.strip_prefix("/uploads") {
+        match parse_client_route(rest) {
+            Some((kind, Some(key))) if method == Method::Get => {
+                respond_offset(req, 200, store.upload_offset(kind, key));
+            }
+            Some(_) => respond_err(req, 405, "method not allowed"),
+            None => respond_err(req, 404, "not found"),
+        }
+        return;
+    }
+
     // Try both URL schemes: /blobs/Kind/key (server canonical) and /kind_plural/key (client)
-    let route = parse_blob_route(&url).or_else(|| parse_client_route(&url));
+    let route = parse_blob_route(path).or_else(|| parse_client_route(path));
     if let Some(parsed) = route {
         match parsed {
-            (kind, Some(key)) => handle_blob_keyed(store, req, &method, kind, key),
+            (kind, Some(key)) => handle_blob_keyed(store, req, &method, kind, key, query),
             (kind, None) if method == Method::Get => {
                 let keys = store.list_blobs(kind);
                 let json = serde_json::to_string(&keys).unwrap_or_else(|_| "[]".to_owned());
@@ -262,17 +711,25 @@ pub fn handle_request(store: &Store, req: tiny_http::Request) {
             }
             _ => respond_err(req, 405, "method not allowed"),
         }
-    } else if url == "/registry" {
+    } else if path == "/registry" {
         handle_registry(store, req, &method);
-    } else if url == "/health" && method == Method::Get {
-        let _ = req.respond(Response::from_string(r#"{"status":"ok"}"#));
+    } else if path == "/registry/tags" && method == Method::Get {
+        respond_tags(store, req, query_param(query, "name").unwrap_or_default());
+    } else if let Some(key) = path.strip_prefix("/registry/") {
+        if method == Method::Delete {
+            handle_untag(store, req, key);
+        } else {
+            respond_err(req, 405, "method not allowed");
+        }
+    } else if path == "/admin/gc" {
+        handle_gc(store, req, &method, query);
     } else {
         respond_err(req, 404, "not found");
     }
 }
 
 /// Start the server loop, blocking the current thread.
-pub fn run_server(store: &Arc<Store>, addr: &str) {
+pub fn run_server(store: &Arc<Store>, tokens: Option<&Tokens>, addr: &str) {
     let server = match Server::http(addr) {
         Ok(s) => s,
         Err(e) => {
@@ -281,8 +738,52 @@ pub fn run_server(store: &Arc<Store>, addr: &str) {
         }
     };
     for request in server.incoming_requests() {
-        handle_request(store, request);
+        handle_request(store, tokens, request);
+    }
+}
+
+/// How often the serve loop wakes up to check for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Serve requests until `shutdown` is set, then drain.
+///
+/// `tick` runs at least every [`POLL_INTERVAL`] (used for watchdog pings).
+/// On shutdown, requests the server already accepted are still answered
+/// until none are left or `drain_timeout` elapses. Returns the number of
+/// requests handled while draining.
+pub fn serve(
+    store: &Store,
+    tokens: Option<&Tokens>,
+    server: &Server,
+    shutdown: &AtomicBool,
+    drain_timeout: Duration,
+    mut tick: impl FnMut(),
+) -> usize {
+    while !shutdown.load(Ordering::SeqCst) {
+        match server.recv_timeout(POLL_INTERVAL) {
+            Ok(Some(request)) => handle_request(store, tokens, request),
+            Ok(None) => {}
+            Err(e) => {
+                error!("failed to receive request: {e}");
+                break;
+            }
+        }
+        tick();
     }
+
+    let deadline = Instant::now() + drain_timeout;
+    let mut drained = 0;
+    while Instant::now() < deadline {
+        match server.recv_timeout(Duration::from_millis(50)) {
+            Ok(Some(request)) => {
+                handle_request(store, tokens, request);
+                drained += 1;
+            }
+            Ok(None) | Err(_) => break,
+        }
+        tick();
+    }
+    drained
 }
 
 /// A test helper that starts a karapace-server on a random port in a background thread.
@@ -301,6 +802,11 @@ impl TestServer {
     /// Start a test server with a temporary data directory.
     /// Binds to `127.0.0.1:0` (random port).
     pub fn start(data_dir: PathBuf) -> Self {
+        Self::start_with_tokens(data_dir, None)
+    }
+
+    /// Start a test server that requires one of `tokens`.
+    pub fn start_with_tokens(data_dir: PathBuf, tokens: Option<Tokens>) -> Self {
         fs::create_dir_all(&data_dir).expect("failed to create test data dir");
         let server =
             Arc::new(Server::http("127.0.0.1:0").expect("failed to bind test HTTP server"));
@@ -311,7 +817,7 @@ impl TestServer {
         let srv = Arc::clone(&server);
         let handle = std::thread::spawn(move || {
             for request in srv.incoming_requests() {
-                handle_request(&store, request);
+                handle_request(&store, tokens.as_ref(), request);
             }
         });
 
@@ -369,6 +875,80 @@ mod tests {
         assert!(store.has_blob("Object", "hash1"));
         assert_eq!(store.get_blob("Object", "hash1"), Some(b"content".to_vec()));
         assert!(!store.has_blob("Object", "missing"));
+        assert_eq!(store.blob_size("Object", "hash1"), Some(7));
+        assert_eq!(store.blob_size("Object", "missing"), None);
+    }
+
+    #[test]
+    fn store_streams_blob_through_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().to_path_buf());
+
+        let size = 4 * 1024 * 1024;
+        let mut body = std::io::repeat(0xab).take(size);
+        assert_eq!(
+            store.put_blob_from("Layer", "big", &mut body).unwrap(),
+            size
+        );
+
+        let (mut file, len) = store.open_blob("Layer", "big").unwrap();
+        assert_eq!(len, size);
+        let mut first = [0u8; 4];
+        file.read_exact(&mut first).unwrap();
+        assert_eq!(first, [0xab; 4]);
+
+        assert_eq!(store.list_blobs("Layer"), vec!["big".to_owned()]);
+        assert_eq!(fs::read_dir(dir.path().join("tmp")).unwrap().count(), 0);
+        assert!(store.open_blob("Layer", "missing").is_none());
+    }
+
+    #[test]
+    fn store_appends_and_commits_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().to_path_buf());
+
+        assert_eq!(store.upload_offset("Object", "o1"), 0);
+        assert_eq!(
+            store
+                .append_upload("Object", "o1", 0, &mut &b"hello "[..])
+                .unwrap(),
+            6
+        );
+        assert!(matches!(
+            store.append_upload("Object", "o1", 3, &mut &b"x"[..]),
+            Err(UploadError::OffsetMismatch(6))
+        ));
+        assert_eq!(
+            store
+                .append_upload("Object", "o1", 6, &mut &b"world"[..])
+                .unwrap(),
+            11
+        );
+        assert_eq!(store.upload_offset("Object", "o1"), 11);
+        assert!(store.get_blob("Object", "o1").is_none());
+
+        store.commit_upload("Object", "o1").unwrap();
+        assert_eq!(store.get_blob("Object", "o1").unwrap(), b"hello world");
+        assert_eq!(store.upload_offset("Object", "o1"), 0);
+
+        // Offset 0 starts the session over.
+        store
+            .append_upload("Object", "o2", 0, &mut &b"stale"[..])
+            .unwrap();
+        store
+            .append_upload("Object", "o2", 0, &mut &b"new"[..])
+            .unwrap();
+        assert_eq!(store.upload_offset("Object", "o2"), 3);
+    }
+
+    #[test]
+    fn parse_range_forms() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 100)));
+        assert_eq!(parse_range("bytes=500-", 1000), Some((500, 1000)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 1000)));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=10-5", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
     }
 
     #[test]
@@ -393,6 +973,39 @@ mod tests {
         assert_eq!(store.get_registry(), Some(b"{\"entries\":{}}".to_vec()));
     }
 
+    #[test]
+    fn serve_stops_on_shutdown_flag() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().to_path_buf());
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let shutdown = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            let handle = s.spawn(|| {
+                serve(
+                    &store,
+                    None,
+                    &server,
+                    &shutdown,
+                    Duration::from_secs(1),
+                    || {},
+                )
+            });
+
+            let body = ureq::get(&format!("http://127.0.0.1:{port}/health"))
+                .call()
+                .unwrap()
+                .into_body()
+                .read_to_string()
+                .unwrap();
+            assert!(body.contains("ok"));
+
+            shutdown.store(true, Ordering::SeqCst);
+            assert_eq!(handle.join().unwrap(), 0);
+        });
+    }
+
     #[test]
     fn store_registry_persists_to_disk() {
         let dir = tempfile::tempdir().unwrap();
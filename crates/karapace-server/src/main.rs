@@ -1,20 +1,45 @@
 use clap::Parser;
+use karapace_server::auth::Tokens;
+use karapace_server::gc::{self, GcOptions};
+use karapace_server::systemd::{self, Notifier};
 use karapace_server::Store;
 use std::fs;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::{Duration, Instant};
+use tiny_http::Server;
+use tracing::{error, info, warn};
 
 #[derive(Parser)]
 #[command(name = "karapace-server", about = "Karapace remote protocol v1 server")]
 struct Cli {
-    /// Port to listen on.
+    /// Port to listen on. Ignored when systemd passes a socket.
     #[arg(long, default_value_t = 8321)]
     port: u16,
 
+    /// Seconds to keep answering already-accepted requests after SIGTERM.
+    #[arg(long, default_value_t = 10)]
+    drain_timeout: u64,
+
     /// Directory to store blobs and registry data.
     #[arg(long, default_value = "./karapace-remote-data")]
     data_dir: PathBuf,
+
+    /// File of `read <token>` / `write <token>` lines. Without it the
+    /// server accepts every request.
+    #[arg(long)]
+    token_file: Option<PathBuf>,
+
+    /// Seconds between background sweeps of unreferenced blobs. Off by
+    /// default; `POST /admin/gc` runs a sweep on demand.
+    #[arg(long)]
+    gc_interval: Option<u64>,
+
+    /// Let background sweeps also delete environments no registry entry
+    /// names.
+    #[arg(long)]
+    gc_prune_untagged: bool,
 }
 
 fn main() {
@@ -35,10 +60,74 @@ fn main() {
         std::process::exit(1);
     }
 
-    let addr = format!("0.0.0.0:{}", cli.port);
-    info!("starting karapace-server on {addr}");
+    let tokens = match &cli.token_file {
+        Some(path) => match Tokens::load(path) {
+            Ok(tokens) => Some(tokens),
+            Err(e) => {
+                error!("invalid token file {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let server = if let Some(listener) = systemd::take_listener() {
+        Server::from_listener(listener, None)
+    } else {
+        Server::http(format!("0.0.0.0:{}", cli.port))
+    };
+    let server = match server {
+        Ok(s) => s,
+        Err(e) => {
+            error!("failed to start HTTP server: {e}");
+            std::process::exit(1);
+        }
+    };
+    info!("starting karapace-server on {}", server.server_addr());
     info!("data directory: {}", cli.data_dir.display());
+    if tokens.is_none() {
+        warn!("no --token-file: anyone who can reach the server can read and write");
+    }
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        if let Err(e) = signal_hook::flag::register(signal, Arc::clone(&shutdown)) {
+            error!("failed to install signal handler: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    let store = Store::new(cli.data_dir);
+    let notifier = Notifier::from_env();
+    notifier.ready();
 
-    let store = Arc::new(Store::new(cli.data_dir));
-    karapace_server::run_server(&store, &addr);
+    let watchdog = notifier.watchdog_interval();
+    let mut last_ping = Instant::now();
+    let gc_interval = cli.gc_interval.filter(|&s| s > 0).map(Duration::from_secs);
+    let gc_options = GcOptions {
+        prune_untagged: cli.gc_prune_untagged,
+        ..GcOptions::default()
+    };
+    let mut last_gc = Instant::now();
+    let drained = karapace_server::serve(
+        &store,
+        tokens.as_ref(),
+        &server,
+        &shutdown,
+        Duration::from_secs(cli.drain_timeout),
+        || {
+            if watchdog.is_some_and(|interval| last_ping.elapsed() >= interval) {
+                notifier.watchdog();
+                last_ping = Instant::now();
+            }
+            if gc_interval.is_some_and(|interval| last_gc.elapsed() >= interval) {
+                if let Err(e) = gc::collect(&store, gc_options) {
+                    error!("background gc failed: {e}");
+                }
+                last_gc = Instant::now();
+            }
+        },
+    );
+    notifier.stopping();
+    info!("stopped after draining {drained} request(s)");
 }
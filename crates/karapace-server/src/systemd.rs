@@ -0,0 +1,220 @@
+//! systemd integration: socket activation (`LISTEN_FDS`) and `sd_notify`.
+//!
+//! Implements the small subset of the protocol the server needs without
+//! linking libsystemd. Everything is a no-op when not started by systemd.
+
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// First file descriptor passed by systemd (`SD_LISTEN_FDS_START`).
+const LISTEN_FDS_START: i32 = 3;
+
+/// Number of sockets passed to this process, given the values of
+/// `LISTEN_PID` and `LISTEN_FDS`. Zero unless `LISTEN_PID` names `pid`.
+pub fn listen_fds_count(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    let Some(target) = listen_pid.and_then(|p| p.trim().parse::<u32>().ok()) else {
+        return 0;
+    };
+    if target != pid {
+        return 0;
+    }
+    listen_fds
+        .and_then(|n| n.trim().parse::<usize>().ok())
+        .unwrap_or(0)
+}
+
+/// Take the listening socket inherited from systemd, if any.
+///
+/// Only the first socket is used. TCP and Unix stream sockets are
+/// supported. The activation variables are cleared so they are not
+/// inherited further.
+pub fn take_listener() -> Option<tiny_http::Listener> {
+    let count = listen_fds_count(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if count == 0 {
+        return None;
+    }
+    if count > 1 {
+        warn!("systemd passed {count} sockets; only the first is used");
+    }
+
+    let fd = inherited_fd();
+    let tcp = TcpListener::from(fd);
+    if tcp.local_addr().is_ok() {
+        debug!("using socket-activated TCP listener");
+        return Some(tcp.into());
+    }
+    debug!("using socket-activated Unix listener");
+    Some(UnixListener::from(OwnedFd::from(tcp)).into())
+}
+
+#[allow(unsafe_code)]
+fn inherited_fd() -> OwnedFd {
+    // SAFETY: LISTEN_PID matched this process, so systemd guarantees that
+    // descriptor 3 is an open socket we own and nothing else refers to it.
+    unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) }
+}
+
+/// Sends state updates to the service manager over `$NOTIFY_SOCKET`.
+pub struct Notifier {
+    socket: Option<(UnixDatagram, String)>,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Connect to `$NOTIFY_SOCKET` and read the watchdog settings. Returns a
+    /// no-op notifier when not running under systemd.
+    pub fn from_env() -> Self {
+        let socket =
+            std::env::var("NOTIFY_SOCKET")
+                .ok()
+                .and_then(|path| match UnixDatagram::unbound() {
+                    Ok(sock) => Some((sock, path)),
+                    Err(e) => {
+                        warn!("failed to create notify socket: {e}");
+                        None
+                    }
+                });
+        let watchdog = watchdog_interval(
+            std::env::var("WATCHDOG_USEC").ok().as_deref(),
+            std::env::var("WATCHDOG_PID").ok().as_deref(),
+            std::process::id(),
+        );
+        Self { socket, watchdog }
+    }
+
+    /// A notifier that never sends anything.
+    pub fn disabled() -> Self {
+        Self {
+            socket: None,
+            watchdog: None,
+        }
+    }
+
+    /// How often to send `WATCHDOG=1`: half the configured timeout.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    pub fn watchdog(&self) {
+        self.notify("WATCHDOG=1");
+    }
+
+    pub fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={status}"));
+    }
+
+    fn notify(&self, state: &str) {
+        let Some((sock, path)) = &self.socket else {
+            return;
+        };
+        let result = if let Some(name) = path.strip_prefix('@') {
+            send_abstract(sock, name, state)
+        } else {
+            sock.send_to(state.as_bytes(), path).map(|_| ())
+        };
+        if let Err(e) = result {
+            warn!("sd_notify {state}: {e}");
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(sock: &UnixDatagram, name: &str, state: &str) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+    sock.send_to_addr(state.as_bytes(), &addr).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_sock: &UnixDatagram, _name: &str, _state: &str) -> std::io::Result<()> {
+    Err(std::io::Error::other("abstract sockets are Linux-only"))
+}
+
+/// Watchdog ping interval from `WATCHDOG_USEC` / `WATCHDOG_PID`.
+pub fn watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.trim().parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+    let usec = usec?.trim().parse::<u64>().ok().filter(|&u| u > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_fds_requires_matching_pid() {
+        assert_eq!(listen_fds_count(Some("42"), Some("1"), 42), 1);
+        assert_eq!(listen_fds_count(Some("42"), Some("2"), 42), 2);
+        assert_eq!(listen_fds_count(Some("41"), Some("1"), 42), 0);
+        assert_eq!(listen_fds_count(None, Some("1"), 42), 0);
+        assert_eq!(listen_fds_count(Some("42"), None, 42), 0);
+        assert_eq!(listen_fds_count(Some("42"), Some("x"), 42), 0);
+    }
+
+    #[test]
+    fn watchdog_interval_is_half_the_timeout() {
+        assert_eq!(
+            watchdog_interval(Some("10000000"), None, 7),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            watchdog_interval(Some("10000000"), Some("7"), 7),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(watchdog_interval(Some("10000000"), Some("8"), 7), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 7), None);
+        assert_eq!(watchdog_interval(None, None, 7), None);
+    }
+
+    #[test]
+    fn notifier_sends_datagrams() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier {
+            socket: Some((
+                UnixDatagram::unbound().unwrap(),
+                path.to_string_lossy().into_owned(),
+            )),
+            watchdog: None,
+        };
+        notifier.ready();
+        notifier.stopping();
+
+        let mut buf = [0u8; 64];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STOPPING=1");
+    }
+
+    #[test]
+    fn disabled_notifier_is_silent() {
+        let notifier = Notifier::disabled();
+        assert!(notifier.watchdog_interval().is_none());
+        notifier.ready();
+    }
+}
@@ -4,12 +4,15 @@
 //! and exercise the real `HttpBackend` client against it. No mocks.
 
 use karapace_remote::http::HttpBackend;
-use karapace_remote::{BlobKind, RemoteBackend, RemoteConfig};
+use karapace_remote::{
+    BlobKind, RegistryCache, RegistryFetch, RegistryOrigin, RemoteBackend, RemoteConfig,
+};
 use karapace_server::TestServer;
 use karapace_store::{
     EnvMetadata, EnvState, LayerKind, LayerManifest, LayerStore, MetadataStore, ObjectStore,
     StoreLayout,
 };
+use std::collections::BTreeMap;
 fn start_server() -> (TestServer, tempfile::TempDir) {
     let dir = tempfile::tempdir().unwrap();
     let server = TestServer::start(dir.path().to_path_buf());
@@ -20,6 +23,7 @@ fn make_client(url: &str) -> HttpBackend {
     HttpBackend::new(RemoteConfig {
         url: url.to_owned(),
         auth_token: None,
+        ..RemoteConfig::new(url)
     })
 }
 
@@ -42,6 +46,10 @@ fn setup_local_env(dir: &std::path::Path) -> (StoreLayout, String) {
         object_refs: vec![obj_hash],
         read_only: true,
         tar_hash: String::new(),
+        workspace: None,
+        delta_parent: None,
+        file_objects: Vec::new(),
+        pax_metadata: false,
     };
     let layer_content_hash = layer_store.put(&layer).unwrap();
 
@@ -58,6 +66,18 @@ fn setup_local_env(dir: &std::path::Path) -> (StoreLayout, String) {
         created_at: "2025-01-01T00:00:00Z".to_owned(),
         updated_at: "2025-01-01T00:00:00Z".to_owned(),
         checksum: None,
+        aliases: Vec::new(),
+        host_gpu: None,
+        base_image_digest: None,
+        workspace: None,
+        snapshot: None,
+        expires_after_secs: None,
+        last_entered_at: None,
+        enter_count: 0,
+        last_built_at: None,
+        labels: BTreeMap::new(),
+        revision: 0,
+        attestation: None,
     };
     meta_store.put(&meta).unwrap();
 
@@ -86,6 +106,13 @@ fn http_e2e_blob_roundtrip() {
     // HEAD — missing
     assert!(!client.has_blob(BlobKind::Object, "missing").unwrap());
 
+    // HEAD — size
+    assert_eq!(
+        client.blob_size(BlobKind::Object, "hash1").unwrap(),
+        Some(11)
+    );
+    assert!(client.blob_size(BlobKind::Object, "missing").is_err());
+
     // Multiple kinds
     client
         .put_blob(BlobKind::Layer, "l1", b"layer-data")
@@ -168,6 +195,45 @@ fn http_e2e_registry_roundtrip() {
     assert_eq!(resolved, env_id);
 }
 
+#[test]
+fn http_e2e_registry_conditional_get() {
+    let (server, _dir) = start_server();
+    let client = make_client(&server.url);
+    let src_dir = tempfile::tempdir().unwrap();
+    let (src_layout, env_id) = setup_local_env(src_dir.path());
+    karapace_remote::push_env(&src_layout, &env_id, &client, Some("myapp@latest")).unwrap();
+
+    let RegistryFetch::Modified {
+        etag: Some(etag), ..
+    } = client.get_registry_if_changed(None).unwrap()
+    else {
+        panic!("expected a registry with an ETag");
+    };
+    assert_eq!(
+        client.get_registry_if_changed(Some(&etag)).unwrap(),
+        RegistryFetch::NotModified
+    );
+
+    // A cache refresh after another push downloads the new registry once.
+    let cache_dir = tempfile::tempdir().unwrap();
+    let cache = RegistryCache::new(cache_dir.path(), &server.url);
+    assert_eq!(
+        cache.refresh(&client).unwrap().unwrap().origin,
+        RegistryOrigin::Downloaded
+    );
+    karapace_remote::push_env(&src_layout, &env_id, &client, Some("myapp@v2")).unwrap();
+    let refreshed = cache.refresh(&client).unwrap().unwrap();
+    assert_eq!(refreshed.origin, RegistryOrigin::Downloaded);
+    assert!(refreshed.registry.lookup("myapp@v2").is_some());
+    assert_eq!(
+        cache.refresh(&client).unwrap().unwrap().origin,
+        RegistryOrigin::NotModified
+    );
+    let entry =
+        karapace_remote::resolve_entry_with_cache(&client, "myapp@v2", Some(&cache)).unwrap();
+    assert_eq!(entry.env_id, env_id);
+}
+
 #[test]
 fn http_e2e_concurrent_4_clients() {
     let (server, _dir) = start_server();
@@ -292,3 +358,319 @@ fn http_e2e_404_on_missing() {
         "error must indicate 404, got: {err_msg}"
     );
 }
+
+#[test]
+fn http_e2e_large_blob_streams_with_content_length() {
+    let (server, _dir) = start_server();
+    let client = make_client(&server.url);
+
+    let data: Vec<u8> = (0..16 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+    client.put_blob(BlobKind::Layer, "big", &data).unwrap();
+
+    let resp = ureq::get(&format!("{}/layers/big", server.url))
+        .call()
+        .unwrap();
+    let content_length = resp
+        .headers()
+        .get("Content-Length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    assert_eq!(content_length, Some(data.len() as u64));
+    // Drain the body: the test server answers one request at a time.
+    let mut body = resp.into_body().into_reader();
+    let drained = std::io::copy(&mut body, &mut std::io::sink()).unwrap();
+    assert_eq!(drained, data.len() as u64);
+
+    let fetched = client.get_blob(BlobKind::Layer, "big").unwrap();
+    assert_eq!(fetched.len(), data.len());
+    assert!(
+        fetched == data,
+        "streamed blob must round-trip byte-for-byte"
+    );
+}
+
+#[test]
+fn http_e2e_chunked_upload_and_ranged_download() {
+    let (server, _dir) = start_server();
+    let client = make_client(&server.url);
+
+    let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    assert!(client.supports_resume());
+    assert_eq!(
+        client.upload_offset(BlobKind::Object, "chunked").unwrap(),
+        0
+    );
+
+    client
+        .put_blob_chunk(BlobKind::Object, "chunked", 0, &data[..1000], false)
+        .unwrap();
+    client
+        .put_blob_chunk(BlobKind::Object, "chunked", 1000, &data[1000..2000], false)
+        .unwrap();
+    assert_eq!(
+        client.upload_offset(BlobKind::Object, "chunked").unwrap(),
+        2000
+    );
+    assert!(!client.has_blob(BlobKind::Object, "chunked").unwrap());
+
+    // A chunk at the wrong offset is rejected and leaves the session intact.
+    assert!(client
+        .put_blob_chunk(BlobKind::Object, "chunked", 500, &data[500..600], false)
+        .is_err());
+    assert_eq!(
+        client.upload_offset(BlobKind::Object, "chunked").unwrap(),
+        2000
+    );
+
+    client
+        .put_blob_chunk(BlobKind::Object, "chunked", 2000, &data[2000..], true)
+        .unwrap();
+    assert_eq!(client.get_blob(BlobKind::Object, "chunked").unwrap(), data);
+
+    let part = client
+        .get_blob_range(BlobKind::Object, "chunked", 2500, 1000)
+        .unwrap();
+    assert_eq!(part, &data[2500..]);
+    let past_end = client
+        .get_blob_range(BlobKind::Object, "chunked", 3000, 10)
+        .unwrap();
+    assert!(past_end.is_empty());
+}
+
+#[test]
+fn http_e2e_tokens_and_scopes() {
+    use karapace_server::auth::{Scope, Tokens};
+
+    const READ: &str = "read-token-0123456789";
+    const WRITE: &str = "write-token-0123456789";
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::start_with_tokens(
+        dir.path().to_path_buf(),
+        Some(
+            Tokens::default()
+                .with_token(READ, Scope::Read)
+                .with_token(WRITE, Scope::Write),
+        ),
+    );
+    let client = |token: Option<&str>| {
+        let config = RemoteConfig::new(&server.url);
+        HttpBackend::new(match token {
+            Some(t) => config.with_token(t),
+            None => config,
+        })
+    };
+
+    let src_dir = tempfile::tempdir().unwrap();
+    let (src_layout, env_id) = setup_local_env(src_dir.path());
+
+    let anonymous = client(None);
+    let err = karapace_remote::push_env(&src_layout, &env_id, &anonymous, Some("a@latest"))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("401"), "{err}");
+    assert!(anonymous.get_registry().is_err());
+    assert!(anonymous.has_blob(BlobKind::Object, "x").is_err());
+
+    let reader = client(Some(READ));
+    let err = karapace_remote::push_env(&src_layout, &env_id, &reader, Some("a@latest"))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("403"), "{err}");
+    assert!(reader.upload_offset(BlobKind::Object, "x").is_err());
+
+    let writer = client(Some(WRITE));
+    karapace_remote::push_env(&src_layout, &env_id, &writer, Some("a@latest")).unwrap();
+
+    let dst_dir = tempfile::tempdir().unwrap();
+    let dst_layout = StoreLayout::new(dst_dir.path());
+    dst_layout.initialize().unwrap();
+    let pulled = karapace_remote::pull_env(&dst_layout, &env_id, &reader).unwrap();
+    assert_eq!(pulled.objects_pulled, 2);
+    assert_eq!(
+        karapace_remote::resolve_ref(&reader, "a@latest").unwrap(),
+        env_id
+    );
+
+    // Health checks stay open.
+    let health = ureq::get(&format!("{}/health", server.url)).call().unwrap();
+    assert_eq!(health.status(), 200);
+}
+
+#[test]
+fn http_e2e_admin_gc_removes_orphans() {
+    let (server, _dir) = start_server();
+    let client = make_client(&server.url);
+
+    let src_dir = tempfile::tempdir().unwrap();
+    let (src_layout, env_id) = setup_local_env(src_dir.path());
+    karapace_remote::push_env(&src_layout, &env_id, &client, Some("app@latest")).unwrap();
+    client
+        .put_blob(BlobKind::Object, "stray", b"left by an aborted push")
+        .unwrap();
+    client
+        .put_blob(BlobKind::Layer, "stray", b"{\"object_refs\":[]}")
+        .unwrap();
+
+    let gc = |query: &str| -> serde_json::Value {
+        let mut resp = ureq::post(&format!("{}/admin/gc?{query}", server.url))
+            .send_empty()
+            .unwrap();
+        serde_json::from_str(&resp.body_mut().read_to_string().unwrap()).unwrap()
+    };
+
+    // Fresh blobs are inside the grace period.
+    let report = gc("");
+    assert_eq!(report["objects_removed"], 0);
+
+    let report = gc("grace=0&dry_run=1");
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["objects_removed"], 1);
+    assert!(client.has_blob(BlobKind::Object, "stray").unwrap());
+
+    let report = gc("grace=0");
+    assert_eq!(report["live_metadata"], 1);
+    assert_eq!(report["live_objects"], 2);
+    assert_eq!(report["layers_removed"], 1);
+    assert_eq!(report["objects_removed"], 1);
+    assert!(!client.has_blob(BlobKind::Object, "stray").unwrap());
+
+    // The pushed environment still pulls.
+    let dst_dir = tempfile::tempdir().unwrap();
+    let dst_layout = StoreLayout::new(dst_dir.path());
+    dst_layout.initialize().unwrap();
+    let pulled = karapace_remote::pull_env(&dst_layout, &env_id, &client).unwrap();
+    assert_eq!(pulled.objects_pulled, 2);
+
+    // Once untagged, the environment goes too.
+    client.put_registry(b"{\"entries\":{}}").unwrap();
+    let report = gc("grace=0&untagged=1");
+    assert_eq!(report["metadata_removed"], 1);
+    assert_eq!(report["layers_removed"], 1);
+    assert_eq!(report["objects_removed"], 2);
+    assert!(ureq::get(&format!("{}/admin/gc", server.url))
+        .call()
+        .is_err());
+}
+
+#[test]
+fn http_e2e_registry_tags_and_untag() {
+    let (server, _dir) = start_server();
+    let client = make_client(&server.url);
+    let src_dir = tempfile::tempdir().unwrap();
+    let (src_layout, env_id) = setup_local_env(src_dir.path());
+    for key in ["app@latest", "app@v1", "app-tools@latest"] {
+        karapace_remote::push_env(&src_layout, &env_id, &client, Some(key)).unwrap();
+    }
+
+    let tags = client.list_tags("app").unwrap();
+    let names: Vec<&str> = tags.iter().map(|(t, _)| t.as_str()).collect();
+    assert_eq!(names, ["latest", "v1"]);
+    assert_eq!(tags[0].1.env_id, env_id);
+    assert!(client.list_tags("nope").unwrap().is_empty());
+
+    assert_eq!(client.delete_tag("app", "v1").unwrap().env_id, env_id);
+    assert!(matches!(
+        client.delete_tag("app", "v1"),
+        Err(karapace_remote::RemoteError::NotFound(_))
+    ));
+    assert!(karapace_remote::resolve_ref(&client, "app@v1").is_err());
+    assert_eq!(
+        karapace_remote::list_refs(&client).unwrap(),
+        ["app-tools@latest", "app@latest"]
+    );
+
+    // The registry survives a restart without the removed tag.
+    let restarted = karapace_server::Store::new(server.data_dir.clone());
+    let reg = karapace_remote::Registry::from_bytes(&restarted.get_registry().unwrap()).unwrap();
+    assert!(reg.lookup("app@v1").is_none());
+}
+
+/// Store an environment whose single layer holds `data`.
+fn put_env_with_object(layout: &StoreLayout, env_id: &str, data: &[u8]) {
+    let obj_hash = ObjectStore::new(layout.clone()).put(data).unwrap();
+    let layer = LayerManifest {
+        hash: format!("layer_{env_id}"),
+        kind: LayerKind::Base,
+        parent: None,
+        object_refs: vec![obj_hash.clone()],
+        read_only: true,
+        tar_hash: String::new(),
+        workspace: None,
+        delta_parent: None,
+        file_objects: Vec::new(),
+        pax_metadata: false,
+    };
+    let layer_hash = LayerStore::new(layout.clone()).put(&layer).unwrap();
+    let meta = EnvMetadata {
+        env_id: env_id.into(),
+        short_id: env_id.into(),
+        name: None,
+        state: EnvState::Built,
+        base_layer: layer_hash.into(),
+        dependency_layers: vec![],
+        policy_layer: None,
+        manifest_hash: obj_hash.into(),
+        ref_count: 1,
+        created_at: "2025-01-01T00:00:00Z".to_owned(),
+        updated_at: "2025-01-01T00:00:00Z".to_owned(),
+        checksum: None,
+        aliases: Vec::new(),
+        host_gpu: None,
+        base_image_digest: None,
+        workspace: None,
+        snapshot: None,
+        expires_after_secs: None,
+        last_entered_at: None,
+        enter_count: 0,
+        last_built_at: None,
+        labels: BTreeMap::new(),
+        revision: 0,
+        attestation: None,
+    };
+    MetadataStore::new(layout.clone()).put(&meta).unwrap();
+}
+
+#[test]
+fn http_e2e_chunked_objects_deduplicate() {
+    let (server, _srv_dir) = start_server();
+    let client = make_client(&server.url);
+
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    let data: Vec<u8> = (0..12 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state.to_le_bytes()[0]
+        })
+        .collect();
+    let mut edited = data.clone();
+    edited.splice(6_000_000..6_000_000, b"a small edit".iter().copied());
+
+    let src_dir = tempfile::tempdir().unwrap();
+    let src = StoreLayout::new(src_dir.path());
+    src.initialize().unwrap();
+    put_env_with_object(&src, "env_v1", &data);
+    put_env_with_object(&src, "env_v2", &edited);
+
+    let first = karapace_remote::push_env(&src, "env_v1", &client, None).unwrap();
+    assert!(first.chunks_pushed > 1, "{first:?}");
+    assert_eq!(first.chunks_skipped, 0);
+
+    // Only the chunks around the edit are new.
+    let second = karapace_remote::push_env(&src, "env_v2", &client, None).unwrap();
+    assert!(second.chunks_skipped > 0, "{second:?}");
+    assert!(second.chunks_pushed <= 2, "{second:?}");
+
+    let dst_dir = tempfile::tempdir().unwrap();
+    let dst = StoreLayout::new(dst_dir.path());
+    dst.initialize().unwrap();
+    karapace_remote::pull_env(&dst, "env_v1", &client).unwrap();
+    let pulled = karapace_remote::pull_env(&dst, "env_v2", &client).unwrap();
+    assert!(pulled.chunks_skipped > 0, "{pulled:?}");
+    assert!(pulled.chunks_pulled <= 2, "{pulled:?}");
+
+    let meta = MetadataStore::new(dst.clone()).get("env_v2").unwrap();
+    let object = ObjectStore::new(dst).get(&meta.manifest_hash).unwrap();
+    assert_eq!(object, edited);
+}
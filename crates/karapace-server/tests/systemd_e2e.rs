@@ -0,0 +1,50 @@
+//! Lifecycle of the real `karapace-server` binary under a fake service
+//! manager: readiness notification, SIGTERM, and stop notification.
+
+use std::os::unix::net::UnixDatagram;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+#[test]
+fn server_notifies_ready_and_stops_on_sigterm() {
+    let dir = tempfile::tempdir().unwrap();
+    let notify_path = dir.path().join("notify.sock");
+    let manager = UnixDatagram::bind(&notify_path).unwrap();
+    manager
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_karapace-server"))
+        .args(["--port", "0", "--drain-timeout", "1", "--data-dir"])
+        .arg(dir.path().join("data"))
+        .env("NOTIFY_SOCKET", &notify_path)
+        .env("WATCHDOG_USEC", "400000")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut buf = [0u8; 64];
+    let n = manager.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"READY=1");
+
+    // The watchdog is pinged at half the configured timeout.
+    let n = manager.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"WATCHDOG=1");
+
+    let kill = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+
+    let mut states = Vec::new();
+    while let Ok(n) = manager.recv(&mut buf) {
+        states.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+        if states.last().map(String::as_str) == Some("STOPPING=1") {
+            break;
+        }
+    }
+    assert_eq!(states.last().map(String::as_str), Some("STOPPING=1"));
+    assert!(child.wait().unwrap().success());
+}
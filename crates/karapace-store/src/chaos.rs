@@ -0,0 +1,379 @@
+//! Fault injection for chaos tests, built with the `test-util` feature.
+//!
+//! Every durable write in the store goes through one atomic write helper.
+//! A [`FaultPlan`] installed with [`install`] applies to the writes under
+//! one root directory, so tests that each use their own temp store can run
+//! in parallel without seeing each other's faults. A plan can:
+//!
+//! - fail writes once a byte budget is spent, as a full disk would. The
+//!   write that crosses the budget leaves a partial temp file behind and
+//!   never reaches its destination;
+//! - sleep before every rename, widening the window in which a kill or a
+//!   concurrent reader sees the state before the rename;
+//! - capture a [`DirSnapshot`] of the root after every durable write. Each
+//!   snapshot is a state a power cut could leave on disk, and restoring it
+//!   checks that recovery copes with that state.
+
+use crate::StoreError;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// Faults to inject into the writes under one root.
+#[derive(Debug, Clone, Default)]
+pub struct FaultPlan {
+    /// Fail every write once this many bytes have been written under the root.
+    pub fail_writes_after: Option<u64>,
+    /// Sleep this long before each rename into place.
+    pub rename_delay: Option<Duration>,
+    /// Capture a snapshot of the root after every durable write.
+    pub record_snapshots: bool,
+}
+
+struct Active {
+    id: u64,
+    root: PathBuf,
+    plan: FaultPlan,
+    written: u64,
+    failed_writes: usize,
+    snapshots: Vec<DirSnapshot>,
+}
+
+/// Number of installed plans, so writes skip the lock when there are none.
+static INSTALLED: AtomicUsize = AtomicUsize::new(0);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static ACTIVE: Mutex<Vec<Active>> = Mutex::new(Vec::new());
+
+fn active() -> MutexGuard<'static, Vec<Active>> {
+    ACTIVE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Apply `plan` to writes under `root` until the returned guard is dropped.
+pub fn install(root: impl Into<PathBuf>, plan: FaultPlan) -> FaultGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    active().push(Active {
+        id,
+        root: root.into(),
+        plan,
+        written: 0,
+        failed_writes: 0,
+        snapshots: Vec::new(),
+    });
+    INSTALLED.fetch_add(1, Ordering::Release);
+    FaultGuard { id }
+}
+
+/// Run `op` while recording a snapshot of `root` after every durable write,
+/// and return its result with the snapshots in write order.
+pub fn record_durable_states<R>(root: &Path, op: impl FnOnce() -> R) -> (R, Vec<DirSnapshot>) {
+    let guard = install(
+        root,
+        FaultPlan {
+            record_snapshots: true,
+            ..FaultPlan::default()
+        },
+    );
+    let result = op();
+    (result, guard.take_snapshots())
+}
+
+/// Keeps a [`FaultPlan`] installed; dropping it removes the plan.
+#[derive(Debug)]
+pub struct FaultGuard {
+    id: u64,
+}
+
+impl FaultGuard {
+    fn with<T>(&self, f: impl FnOnce(&mut Active) -> T) -> Option<T> {
+        active().iter_mut().find(|a| a.id == self.id).map(f)
+    }
+
+    /// Bytes written under the root since the plan was installed.
+    pub fn bytes_written(&self) -> u64 {
+        self.with(|a| a.written).unwrap_or(0)
+    }
+
+    /// Writes that failed because of the byte budget.
+    pub fn failed_writes(&self) -> usize {
+        self.with(|a| a.failed_writes).unwrap_or(0)
+    }
+
+    /// Snapshots recorded so far, oldest first. Recording carries on.
+    pub fn take_snapshots(&self) -> Vec<DirSnapshot> {
+        self.with(|a| std::mem::take(&mut a.snapshots))
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for FaultGuard {
+    fn drop(&mut self) {
+        let mut active = active();
+        let before = active.len();
+        active.retain(|a| a.id != self.id);
+        if active.len() < before {
+            INSTALLED.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
+fn plan_for<T>(dest: &Path, f: impl FnOnce(&mut Active) -> T) -> Option<T> {
+    if INSTALLED.load(Ordering::Acquire) == 0 {
+        return None;
+    }
+    active()
+        .iter_mut()
+        .find(|a| dest.starts_with(&a.root))
+        .map(f)
+}
+
+/// Write `data` for `dest` into `out`, stopping short if the byte budget
+/// of the plan covering `dest` runs out.
+pub(crate) fn write(out: &mut impl Write, dest: &Path, data: &[u8]) -> io::Result<()> {
+    let allowed = plan_for(dest, |a| {
+        let allowed = match a.plan.fail_writes_after {
+            Some(budget) => usize::try_from(budget.saturating_sub(a.written))
+                .unwrap_or(usize::MAX)
+                .min(data.len()),
+            None => data.len(),
+        };
+        a.written += allowed as u64;
+        if allowed < data.len() {
+            a.failed_writes += 1;
+        }
+        allowed
+    })
+    .unwrap_or(data.len());
+
+    out.write_all(&data[..allowed])?;
+    if allowed < data.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "injected fault: write to {} failed after {allowed} bytes",
+                dest.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn before_rename(dest: &Path) {
+    if let Some(delay) = plan_for(dest, |a| a.plan.rename_delay).flatten() {
+        std::thread::sleep(delay);
+    }
+}
+
+pub(crate) fn after_durable(dest: &Path) -> Result<(), StoreError> {
+    let Some((id, root)) = plan_for(dest, |a| {
+        a.plan.record_snapshots.then(|| (a.id, a.root.clone()))
+    })
+    .flatten() else {
+        return Ok(());
+    };
+    // Captured without holding the lock so other stores keep writing.
+    let snapshot = DirSnapshot::capture(&root)?;
+    if let Some(a) = active().iter_mut().find(|a| a.id == id) {
+        a.snapshots.push(snapshot);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Dir { mode: u32 },
+    File { data: Vec<u8>, mode: u32 },
+    Symlink(PathBuf),
+}
+
+/// In-memory copy of a directory tree: contents, symlinks and permission
+/// bits, but not ownership or timestamps.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirSnapshot {
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl DirSnapshot {
+    /// Copy the tree under `root`. A missing root yields an empty snapshot.
+    pub fn capture(root: &Path) -> io::Result<Self> {
+        let mut snapshot = Self::default();
+        if root.exists() {
+            snapshot.walk(root, Path::new(""))?;
+        }
+        Ok(snapshot)
+    }
+
+    fn walk(&mut self, root: &Path, rel: &Path) -> io::Result<()> {
+        for entry in fs::read_dir(root.join(rel))? {
+            let entry = entry?;
+            let rel = rel.join(entry.file_name());
+            let path = entry.path();
+            // Entries can vanish while a concurrent writer renames or cleans up.
+            let meta = match fs::symlink_metadata(&path) {
+                Ok(meta) => meta,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let mode = meta.permissions().mode() & 0o7777;
+            if meta.is_dir() {
+                self.entries.insert(rel.clone(), Entry::Dir { mode });
+                self.walk(root, &rel)?;
+            } else if meta.is_symlink() {
+                self.entries
+                    .insert(rel, Entry::Symlink(fs::read_link(&path)?));
+            } else if meta.is_file() {
+                match fs::read(&path) {
+                    Ok(data) => {
+                        self.entries.insert(rel, Entry::File { data, mode });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace everything under `root` with the snapshot.
+    pub fn restore(&self, root: &Path) -> io::Result<()> {
+        if root.exists() {
+            fs::remove_dir_all(root)?;
+        }
+        fs::create_dir_all(root)?;
+        // Parents sort before their children, so one pass creates the tree.
+        for (rel, entry) in &self.entries {
+            let path = root.join(rel);
+            match entry {
+                Entry::Dir { .. } => fs::create_dir(&path)?,
+                Entry::File { data, mode } => {
+                    fs::write(&path, data)?;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(*mode))?;
+                }
+                Entry::Symlink(target) => std::os::unix::fs::symlink(target, &path)?,
+            }
+        }
+        // Directory modes last, children first, so read-only directories
+        // do not block the writes above.
+        for (rel, entry) in self.entries.iter().rev() {
+            if let Entry::Dir { mode } = entry {
+                fs::set_permissions(root.join(rel), fs::Permissions::from_mode(*mode))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of files, directories and symlinks in the snapshot.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the snapshot holds a file at `rel`, relative to its root.
+    pub fn contains_file(&self, rel: impl AsRef<Path>) -> bool {
+        matches!(self.entries.get(rel.as_ref()), Some(Entry::File { .. }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ObjectStore, StoreLayout};
+
+    fn test_store() -> (tempfile::TempDir, StoreLayout) {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        (dir, layout)
+    }
+
+    #[test]
+    fn write_budget_fails_without_reaching_destination() {
+        let (dir, layout) = test_store();
+        let store = ObjectStore::with_config(
+            layout.clone(),
+            crate::StoreConfig {
+                compression: crate::Compression::None,
+                ..crate::StoreConfig::default()
+            },
+        );
+        let guard = install(
+            dir.path(),
+            FaultPlan {
+                fail_writes_after: Some(10),
+                ..FaultPlan::default()
+            },
+        );
+
+        let small = store.put(b"0123").unwrap();
+        let err = store.put(b"a longer object").unwrap_err();
+        assert!(matches!(err, StoreError::Io(ref e) if e.kind() == io::ErrorKind::StorageFull));
+        assert_eq!(guard.bytes_written(), 10);
+        assert_eq!(guard.failed_writes(), 1);
+        assert!(layout.objects_dir().join(&small).exists());
+        let hash = blake3::hash(b"a longer object").to_hex().to_string();
+        assert!(!layout.objects_dir().join(hash).exists());
+
+        drop(guard);
+        store.put(b"a longer object").unwrap();
+    }
+
+    #[test]
+    fn plans_only_apply_under_their_root() {
+        let (dir, _) = test_store();
+        let (_other_dir, other) = test_store();
+        let _guard = install(
+            dir.path(),
+            FaultPlan {
+                fail_writes_after: Some(0),
+                ..FaultPlan::default()
+            },
+        );
+        ObjectStore::new(other).put(b"unaffected").unwrap();
+    }
+
+    #[test]
+    fn records_a_snapshot_per_durable_write() {
+        let (dir, layout) = test_store();
+        let store = ObjectStore::new(layout);
+        let (hashes, snapshots) = record_durable_states(dir.path(), || {
+            [store.put(b"one").unwrap(), store.put(b"two").unwrap()]
+        });
+        assert_eq!(snapshots.len(), 2);
+        let first = Path::new("store/objects").join(&hashes[0]);
+        let second = Path::new("store/objects").join(&hashes[1]);
+        assert!(snapshots[0].contains_file(&first));
+        assert!(!snapshots[0].contains_file(&second));
+        assert!(snapshots[1].contains_file(&second));
+    }
+
+    #[test]
+    fn snapshot_restore_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("tree");
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/file"), b"data").unwrap();
+        fs::set_permissions(root.join("a/b/file"), fs::Permissions::from_mode(0o600)).unwrap();
+        std::os::unix::fs::symlink("b/file", root.join("a/link")).unwrap();
+        let snapshot = DirSnapshot::capture(&root).unwrap();
+        assert_eq!(snapshot.len(), 4);
+
+        fs::write(root.join("a/b/file"), b"changed").unwrap();
+        fs::write(root.join("extra"), b"x").unwrap();
+        snapshot.restore(&root).unwrap();
+
+        assert_eq!(fs::read(root.join("a/b/file")).unwrap(), b"data");
+        assert!(!root.join("extra").exists());
+        assert_eq!(
+            fs::read_link(root.join("a/link")).unwrap(),
+            PathBuf::from("b/file")
+        );
+        assert_eq!(DirSnapshot::capture(&root).unwrap(), snapshot);
+    }
+}
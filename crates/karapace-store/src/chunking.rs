@@ -0,0 +1,211 @@
+//! Content-defined chunking of large objects.
+//!
+//! Chunk boundaries are picked with FastCDC: a gear hash rolls over the
+//! data and a boundary falls where its top bits are zero. Because the hash
+//! only sees the last 64 bytes, an edit moves at most the boundaries next to
+//! it, and every other chunk keeps its content and therefore its hash. A
+//! stricter mask before [`AVG_CHUNK_SIZE`] and a looser one after it keep
+//! chunk sizes close to the average ("normalized chunking").
+//!
+//! The gear table and sizes are part of the store format: changing them
+//! makes new chunks miss every chunk stored before.
+//!
+//! A chunked object is stored as a chunk list:
+//!
+//! ```text
+//! karapace-chunks/1
+//! <blake3 of chunk 1> <length>
+//! <blake3 of chunk 2> <length>
+//! ```
+
+use std::fmt::Write as _;
+
+/// No boundary is placed before this many bytes.
+pub const MIN_CHUNK_SIZE: usize = 256 * 1024;
+/// Target chunk size.
+pub const AVG_CHUNK_SIZE: usize = 1024 * 1024;
+/// A boundary is forced after this many bytes.
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// First line of every chunk list.
+pub const CHUNK_LIST_MAGIC: &[u8] = b"karapace-chunks/1\n";
+
+/// log2 of [`AVG_CHUNK_SIZE`].
+const AVG_BITS: u32 = AVG_CHUNK_SIZE.trailing_zeros();
+/// Mask used below the average size: two bits harder than the average.
+const MASK_SMALL: u64 = !0 << (64 - (AVG_BITS + 2));
+/// Mask used above the average size: two bits easier.
+const MASK_LARGE: u64 = !0 << (64 - (AVG_BITS - 2));
+
+/// Random values for each byte, from splitmix64 with a fixed seed.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x6b61_7261_7061_6365; // "karapace"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Length of the chunk at the start of `data`.
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let normal = end.min(AVG_CHUNK_SIZE);
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[usize::from(byte)]);
+        let mask = if i < normal { MASK_SMALL } else { MASK_LARGE };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Split `data` into content-defined chunks.
+pub fn split(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (chunk, tail) = rest.split_at(cut_point(rest));
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// One entry of a chunk list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkRef {
+    pub hash: String,
+    pub len: u64,
+}
+
+/// Serialize a chunk list.
+pub fn encode_chunk_list(chunks: &[ChunkRef]) -> Vec<u8> {
+    let mut out = String::from_utf8_lossy(CHUNK_LIST_MAGIC).into_owned();
+    for chunk in chunks {
+        let _ = writeln!(out, "{} {}", chunk.hash, chunk.len);
+    }
+    out.into_bytes()
+}
+
+/// Parse a chunk list. `None` when `data` is not one.
+pub fn parse_chunk_list(data: &[u8]) -> Option<Vec<ChunkRef>> {
+    let body = std::str::from_utf8(data.strip_prefix(CHUNK_LIST_MAGIC)?).ok()?;
+    body.lines()
+        .map(|line| {
+            let (hash, len) = line.split_once(' ')?;
+            let valid = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+            Some(ChunkRef {
+                hash: valid.then(|| hash.to_owned())?,
+                len: len.parse().ok()?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|chunks| !chunks.is_empty())
+}
+
+/// The chunks listed in `raw`, if it is the chunk list of object `hash`
+/// rather than the object itself. Content that merely looks like a chunk
+/// list hashes to its own name, so it is not mistaken for one.
+pub fn as_chunk_list(hash: &str, raw: &[u8]) -> Option<Vec<ChunkRef>> {
+    if !raw.starts_with(CHUNK_LIST_MAGIC) || blake3::hash(raw).to_hex().as_str() == hash {
+        return None;
+    }
+    parse_chunk_list(raw)
+}
+
+/// Deterministic incompressible bytes for tests.
+#[cfg(test)]
+pub(crate) fn noise(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state.to_le_bytes()[0]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(chunks: &[&[u8]]) -> Vec<String> {
+        chunks
+            .iter()
+            .map(|c| blake3::hash(c).to_hex().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn chunks_cover_the_data_within_bounds() {
+        let data = noise(20 * 1024 * 1024, 7);
+        let chunks = split(&data);
+        assert_eq!(chunks.concat(), data);
+        assert!(chunks.len() > 5, "{} chunks", chunks.len());
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!((MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk.len()));
+        }
+        assert_eq!(hashes(&split(&data)), hashes(&chunks));
+    }
+
+    #[test]
+    fn small_and_uniform_data() {
+        assert!(split(&[]).is_empty());
+        assert_eq!(split(b"tiny").len(), 1);
+        // No boundary ever matches in a run of zeros, so chunks hit the max.
+        let zeros = vec![0u8; MAX_CHUNK_SIZE * 2 + 10];
+        let sizes: Vec<usize> = split(&zeros).iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, [MAX_CHUNK_SIZE, MAX_CHUNK_SIZE, 10]);
+    }
+
+    #[test]
+    fn an_insertion_only_changes_nearby_chunks() {
+        let data = noise(16 * 1024 * 1024, 11);
+        let mut edited = data.clone();
+        edited.splice(5_000_000..5_000_000, b"inserted".iter().copied());
+
+        let before = hashes(&split(&data));
+        let after = hashes(&split(&edited));
+        let changed = after.iter().filter(|h| !before.contains(h)).count();
+        assert!(changed <= 2, "{changed} of {} chunks changed", after.len());
+    }
+
+    #[test]
+    fn chunk_list_roundtrip() {
+        let chunks = vec![
+            ChunkRef {
+                hash: "a".repeat(64),
+                len: 10,
+            },
+            ChunkRef {
+                hash: "b".repeat(64),
+                len: 3,
+            },
+        ];
+        let encoded = encode_chunk_list(&chunks);
+        assert!(encoded.starts_with(CHUNK_LIST_MAGIC));
+        assert_eq!(parse_chunk_list(&encoded).unwrap(), chunks);
+
+        assert!(parse_chunk_list(b"plain data").is_none());
+        assert!(parse_chunk_list(CHUNK_LIST_MAGIC).is_none());
+        assert!(parse_chunk_list(b"karapace-chunks/1\nnot-a-hash 3\n").is_none());
+        let mut truncated = encoded.clone();
+        truncated.truncate(encoded.len() - 2);
+        truncated.extend_from_slice(b"x\n");
+        assert!(parse_chunk_list(&truncated).is_none());
+    }
+}
@@ -0,0 +1,229 @@
+//! Per-store settings, read from `store/config.json`.
+//!
+//! A missing file yields [`StoreConfig::default`], so stores only carry a
+//! config file when a setting differs from the default.
+
+use crate::layout::StoreLayout;
+use crate::{write_atomic, StoreError};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// zstd level used when the config does not set one.
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// Objects larger than this many bytes are stored as chunk lists unless the
+/// config sets another threshold.
+pub const DEFAULT_CHUNK_THRESHOLD: u64 = crate::chunking::MAX_CHUNK_SIZE as u64;
+
+/// How loose objects are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    #[default]
+    Zstd,
+}
+
+/// Where environment metadata is kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataBackend {
+    /// One JSON file per environment in `store/metadata/`.
+    #[default]
+    Json,
+    /// A SQLite database, `store/metadata/.metadata.db`. Needs a build with
+    /// the `sqlite` feature.
+    Sqlite,
+}
+
+impl MetadataBackend {
+    pub fn is_json(&self) -> bool {
+        *self == Self::Json
+    }
+}
+
+impl std::fmt::Display for MetadataBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json => write!(f, "json"),
+            Self::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+impl std::str::FromStr for MetadataBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "sqlite" => Ok(Self::Sqlite),
+            other => Err(format!(
+                "unknown metadata backend '{other}' (expected json or sqlite)"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreConfig {
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default = "default_compression_level")]
+    pub compression_level: i32,
+    /// Objects larger than this are split into content-defined chunks, so
+    /// an edit only adds the chunks it touched. `0` stores every object
+    /// whole.
+    #[serde(default = "default_chunk_threshold")]
+    pub chunk_threshold: u64,
+    /// Store each regular file of a new layer as an object of its own, so
+    /// identical files across layers and snapshots are stored once.
+    #[serde(default)]
+    pub file_dedup: bool,
+    /// Root of a read-only store, such as `/var/lib/karapace`, whose
+    /// objects and layers are used when this store lacks them. New objects
+    /// and layers are written here only when the shared store has none of
+    /// them, and GC never touches the shared store.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_store: Option<PathBuf>,
+    /// Default idle time, such as `"30d"`, after which environments whose
+    /// manifest sets no `runtime.expires_after` expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_after: Option<String>,
+    /// Where environment metadata is kept. Change it with `karapace migrate
+    /// --metadata-backend`, which moves the existing metadata.
+    #[serde(default, skip_serializing_if = "MetadataBackend::is_json")]
+    pub metadata_backend: MetadataBackend,
+    /// For storage that is not trusted to keep what it was given (NFS, SD
+    /// cards). Reads always verify; this also verifies the stored copy
+    /// before a write skips an object or layer as already present, writing
+    /// a damaged copy again, and reads back every materialized object.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paranoid_reads: bool,
+}
+
+fn default_compression_level() -> i32 {
+    DEFAULT_COMPRESSION_LEVEL
+}
+
+fn default_chunk_threshold() -> u64 {
+    DEFAULT_CHUNK_THRESHOLD
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            compression: Compression::default(),
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            chunk_threshold: DEFAULT_CHUNK_THRESHOLD,
+            file_dedup: false,
+            shared_store: None,
+            expires_after: None,
+            metadata_backend: MetadataBackend::Json,
+            paranoid_reads: false,
+        }
+    }
+}
+
+impl StoreConfig {
+    pub fn load(layout: &StoreLayout) -> Result<Self, StoreError> {
+        let path = layout.config_file();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Like [`load`](Self::load), but an unreadable config is logged and
+    /// replaced by the defaults.
+    pub fn load_or_default(layout: &StoreLayout) -> Self {
+        Self::load(layout).unwrap_or_else(|e| {
+            tracing::warn!("ignoring {}: {e}", layout.config_file().display());
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, layout: &StoreLayout) -> Result<(), StoreError> {
+        let path = layout.config_file();
+        let dir = layout.root().join("store");
+        fs::create_dir_all(&dir)?;
+        let content = serde_json::to_string_pretty(self)?;
+        write_atomic(&dir, &path, content.as_bytes())?;
+        layout.forget_shared_store();
+        Ok(())
+    }
+}
+
+/// The [shared store](StoreConfig::shared_store) of a store, as read when
+/// the store's layout first needed it (see
+/// [`StoreLayout::shared_store`]).
+#[derive(Debug, Clone)]
+pub struct SharedStore {
+    pub layout: StoreLayout,
+    /// The shared store's own config, with its `shared_store` cleared so
+    /// stores do not chain.
+    pub config: StoreConfig,
+}
+
+impl SharedStore {
+    pub fn open(root: &Path) -> Self {
+        let layout = StoreLayout::new(root);
+        let config = StoreConfig {
+            shared_store: None,
+            ..StoreConfig::load_or_default(&layout)
+        };
+        Self { layout, config }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_config_defaults_to_zstd() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        let config = StoreConfig::load(&layout).unwrap();
+        assert_eq!(config.compression, Compression::Zstd);
+        assert_eq!(config.compression_level, DEFAULT_COMPRESSION_LEVEL);
+        assert_eq!(config.chunk_threshold, DEFAULT_CHUNK_THRESHOLD);
+        assert!(!config.file_dedup);
+        assert!(!config.paranoid_reads);
+    }
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        let config = StoreConfig {
+            compression: Compression::None,
+            compression_level: 9,
+            chunk_threshold: 0,
+            file_dedup: true,
+            shared_store: Some(PathBuf::from("/var/lib/karapace")),
+            expires_after: Some("30d".to_owned()),
+            metadata_backend: MetadataBackend::Sqlite,
+            paranoid_reads: true,
+        };
+        config.save(&layout).unwrap();
+        assert_eq!(StoreConfig::load(&layout).unwrap(), config);
+    }
+
+    #[test]
+    fn partial_config_fills_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        fs::create_dir_all(dir.path().join("store")).unwrap();
+        fs::write(layout.config_file(), r#"{ "compression": "none" }"#).unwrap();
+        let config = StoreConfig::load(&layout).unwrap();
+        assert_eq!(config.compression, Compression::None);
+        assert_eq!(config.compression_level, DEFAULT_COMPRESSION_LEVEL);
+
+        fs::write(layout.config_file(), r#"{ "compresion": "none" }"#).unwrap();
+        assert!(StoreConfig::load(&layout).is_err());
+    }
+}
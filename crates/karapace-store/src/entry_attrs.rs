@@ -0,0 +1,265 @@
+//! Extended attributes and sparse-file holes of the files a layer packs,
+//! and the device and FIFO nodes it restores.
+//!
+//! Attributes and holes are recorded in PAX headers (see [`XATTR_PAX_PREFIX`] and
+//! [`HOLES_PAX_KEY`]). POSIX ACLs and file capabilities are extended
+//! attributes (`system.posix_acl_*`, `security.capability`), so they travel
+//! the same way. Restoring them is best effort: an attribute the process
+//! may not set, or a filesystem without hole punching, leaves the content
+//! intact. So is creating a device node, which needs `CAP_MKNOD`.
+//!
+//! [`XATTR_PAX_PREFIX`]: crate::layers::XATTR_PAX_PREFIX
+//! [`HOLES_PAX_KEY`]: crate::layers::HOLES_PAX_KEY
+
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use tracing::warn;
+
+/// An extended attribute: name and value.
+pub(crate) type Xattr = (String, Vec<u8>);
+
+/// A run of a file that reads as zeros without being allocated: offset and
+/// length.
+pub(crate) type Hole = (u64, u64);
+
+/// Labels of the host's security policy, which mean nothing on another
+/// machine and which restoring would only fail to set.
+const SKIPPED_XATTRS: &[&str] = &["security.selinux"];
+
+/// Extended attributes of `path`, not following a symlink, sorted by name.
+/// Empty where the filesystem has none.
+pub(crate) fn read_xattrs(path: &Path) -> io::Result<Vec<Xattr>> {
+    let names = match xattr::list(path) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut xattrs = Vec::new();
+    for name in names {
+        let Some(name) = name.to_str() else {
+            warn!(
+                "skipping non-UTF-8 extended attribute of {}",
+                path.display()
+            );
+            continue;
+        };
+        if SKIPPED_XATTRS.contains(&name) {
+            continue;
+        }
+        if let Some(value) = xattr::get(path, name)? {
+            xattrs.push((name.to_owned(), value));
+        }
+    }
+    xattrs.sort();
+    Ok(xattrs)
+}
+
+/// Set `xattrs` on `path`, not following a symlink. Returns the first
+/// failure, after trying every attribute.
+pub(crate) fn apply_xattrs(path: &Path, xattrs: &[Xattr]) -> io::Result<()> {
+    let mut first = None;
+    for (name, value) in xattrs {
+        if let Err(e) = xattr::set(path, name, value) {
+            first.get_or_insert_with(|| io::Error::new(e.kind(), format!("{name}: {e}")));
+        }
+    }
+    first.map_or(Ok(()), Err)
+}
+
+/// Digest of `xattrs` for comparing entries; zero when there are none.
+pub(crate) fn xattrs_digest(xattrs: &[Xattr]) -> [u8; 32] {
+    if xattrs.is_empty() {
+        return [0; 32];
+    }
+    let mut hasher = blake3::Hasher::new();
+    for (name, value) in xattrs {
+        hasher.update(name.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(&(value.len() as u64).to_le_bytes());
+        hasher.update(value);
+    }
+    *hasher.finalize().as_bytes()
+}
+
+/// Holes of `file`, in order. Only asks the filesystem when fewer blocks
+/// are allocated than the size needs, so a dense file costs one `stat`.
+#[allow(unsafe_code)]
+pub(crate) fn find_holes(file: &fs::File) -> io::Result<Vec<Hole>> {
+    let meta = file.metadata()?;
+    let len = meta.len();
+    if meta.blocks() * 512 >= len {
+        return Ok(Vec::new());
+    }
+    let end = libc::off_t::try_from(len).map_err(io::Error::other)?;
+    let fd = file.as_raw_fd();
+    let mut holes = Vec::new();
+    let mut pos = 0;
+    while pos < end {
+        // SAFETY: lseek takes no pointers; the descriptor is open for the
+        // duration of the call.
+        let hole = unsafe { libc::lseek(fd, pos, libc::SEEK_HOLE) };
+        if hole < 0 {
+            let e = io::Error::last_os_error();
+            // The filesystem cannot report holes: treat the file as dense.
+            return match e.raw_os_error() {
+                Some(libc::EINVAL | libc::ENOTSUP) => Ok(Vec::new()),
+                _ => Err(e),
+            };
+        }
+        if hole >= end {
+            break;
+        }
+        // SAFETY: as above.
+        let data = unsafe { libc::lseek(fd, hole, libc::SEEK_DATA) };
+        let data = if data >= 0 {
+            data
+        } else {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ENXIO) {
+                return Err(e);
+            }
+            end
+        };
+        holes.push((offset(hole)?, offset(data - hole)?));
+        pos = data;
+    }
+    (&*file).seek(SeekFrom::Start(0))?;
+    Ok(holes)
+}
+
+fn offset(off: libc::off_t) -> io::Result<u64> {
+    u64::try_from(off).map_err(io::Error::other)
+}
+
+/// `holes` less any that do not read as zeros in `data`, which changed
+/// after they were found.
+pub(crate) fn zero_holes(holes: Vec<Hole>, data: &[u8]) -> Vec<Hole> {
+    holes
+        .into_iter()
+        .filter(|&(offset, len)| {
+            usize::try_from(offset)
+                .ok()
+                .zip(usize::try_from(offset + len).ok())
+                .and_then(|(start, end)| data.get(start..end))
+                .is_some_and(|run| run.iter().all(|b| *b == 0))
+        })
+        .collect()
+}
+
+/// Encode `holes` as the value of a [`HOLES_PAX_KEY`] record:
+/// `offset,length` pairs, comma separated.
+///
+/// [`HOLES_PAX_KEY`]: crate::layers::HOLES_PAX_KEY
+pub(crate) fn encode_holes(holes: &[Hole]) -> String {
+    holes
+        .iter()
+        .map(|(offset, len)| format!("{offset},{len}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parse a [`HOLES_PAX_KEY`] value for a file of `size` bytes. The holes
+/// must be in order, not overlap, and end within the file.
+///
+/// [`HOLES_PAX_KEY`]: crate::layers::HOLES_PAX_KEY
+pub(crate) fn decode_holes(value: &[u8], size: u64) -> io::Result<Vec<Hole>> {
+    let invalid = || io::Error::other("invalid sparse map in layer");
+    let value = std::str::from_utf8(value).map_err(|_| invalid())?;
+    let numbers = value
+        .split(',')
+        .map(str::parse::<u64>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    if numbers.len() % 2 != 0 {
+        return Err(invalid());
+    }
+    let mut holes = Vec::new();
+    let mut pos = 0;
+    for pair in numbers.chunks_exact(2) {
+        let (offset, len) = (pair[0], pair[1]);
+        let end = offset.checked_add(len).ok_or_else(invalid)?;
+        if offset < pos || end > size || len == 0 {
+            return Err(invalid());
+        }
+        holes.push((offset, len));
+        pos = end;
+    }
+    Ok(holes)
+}
+
+/// Write `size` bytes of `content` to a new file at `dest`, seeking over
+/// `holes` instead of writing their zeros.
+pub(crate) fn write_sparse(
+    mut content: impl Read,
+    dest: &Path,
+    size: u64,
+    holes: &[Hole],
+) -> io::Result<()> {
+    let mut file = fs::File::create(dest)?;
+    let mut pos = 0;
+    for &(offset, len) in holes {
+        io::copy(&mut (&mut content).take(offset - pos), &mut file)?;
+        io::copy(&mut (&mut content).take(len), &mut io::sink())?;
+        file.seek(SeekFrom::Start(offset + len))?;
+        pos = offset + len;
+    }
+    io::copy(&mut content.take(size - pos), &mut file)?;
+    file.set_len(size)
+}
+
+/// Deallocate `holes` of the file at `path`, which must already read as
+/// zeros there. Fails on a hole past the end of the file.
+#[allow(unsafe_code)]
+pub(crate) fn punch_holes(path: &Path, holes: &[Hole]) -> io::Result<()> {
+    if holes.is_empty() {
+        return Ok(());
+    }
+    let file = fs::OpenOptions::new().write(true).open(path)?;
+    let size = file.metadata()?.len();
+    if holes.iter().any(|(offset, len)| offset + len > size) {
+        return Err(io::Error::other("sparse map runs past the end of the file"));
+    }
+    for &(offset, len) in holes {
+        let offset = libc::off_t::try_from(offset).map_err(io::Error::other)?;
+        let len = libc::off_t::try_from(len).map_err(io::Error::other)?;
+        // SAFETY: fallocate takes no pointers; the descriptor is open for
+        // the duration of the call.
+        let rc = unsafe {
+            libc::fallocate(
+                file.as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                offset,
+                len,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Create a device or FIFO node at `path`. `file_type` is one of
+/// `S_IFCHR`, `S_IFBLK` and `S_IFIFO`; the umask applies to `mode`.
+#[allow(unsafe_code)]
+pub(crate) fn make_node(
+    path: &Path,
+    file_type: libc::mode_t,
+    mode: u32,
+    device: (u32, u32),
+) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let dev = libc::makedev(device.0, device.1);
+    // SAFETY: `path` is NUL-terminated and outlives the call.
+    let rc = unsafe { libc::mknod(path.as_ptr(), file_type | (mode & 0o7777), dev) };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
@@ -1,13 +1,51 @@
-use crate::layers::LayerStore;
+use crate::layers::{LayerKind, LayerStore};
 use crate::layout::StoreLayout;
-use crate::metadata::{EnvState, MetadataStore};
+use crate::metadata::{EnvMetadata, EnvState, MetadataStore};
 use crate::objects::ObjectStore;
 use crate::StoreError;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
 
 pub struct GarbageCollector {
     layout: StoreLayout,
+    pack_threshold: Option<u64>,
+    retention: RetentionPolicy,
+}
+
+/// Rules that evict archived environments and snapshots before orphans are
+/// collected. Every rule is off by default, so plain GC only removes strict
+/// orphans.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Keep only the newest `N` snapshots of each environment workspace.
+    /// Combined with `older_than`, the newest `N` are kept regardless of age.
+    pub keep_last: Option<usize>,
+    /// Evict archived environments and snapshots older than this.
+    pub older_than: Option<Duration>,
+    /// Evict the oldest archived environments and snapshots until objects
+    /// and environment directories fit in this many bytes. Snapshots kept by
+    /// `keep_last` are never evicted for size.
+    pub max_store_size: Option<u64>,
+}
+
+/// What the mark phase of GC found unreferenced, for
+/// [`GarbageCollector::sweep`].
+#[derive(Debug)]
+pub struct GcPlan {
+    report: GcReport,
+    /// Revision of every environment's metadata when marked.
+    revisions: HashMap<String, u64>,
+    /// Layers in the store when marked.
+    layers: HashSet<String>,
+}
+
+impl GcPlan {
+    /// Orphans and evictions as of the mark. Nothing is removed yet.
+    pub fn report(&self) -> &GcReport {
+        &self.report
+    }
 }
 
 #[derive(Debug, Default)]
@@ -15,25 +53,66 @@ pub struct GcReport {
     pub orphaned_envs: Vec<String>,
     pub orphaned_layers: Vec<String>,
     pub orphaned_objects: Vec<String>,
+    /// Archived environments evicted by the retention policy.
+    pub evicted_envs: Vec<String>,
+    /// Snapshot layers evicted by the retention policy. Their layers are
+    /// also listed in `orphaned_layers`.
+    pub evicted_snapshots: Vec<String>,
+    /// Bytes used by objects and environment directories before collection.
+    /// Only measured with `max_store_size`.
+    pub store_size_before: Option<u64>,
+    /// Estimated bytes left once evicted and orphaned data is removed.
+    /// Only measured with `max_store_size`.
+    pub store_size_after: Option<u64>,
     pub removed_envs: usize,
     pub removed_layers: usize,
     pub removed_objects: usize,
+    /// Loose objects consolidated into a pack after collection.
+    pub packed_objects: usize,
 }
 
 impl GarbageCollector {
     pub fn new(layout: StoreLayout) -> Self {
-        Self { layout }
+        Self {
+            layout,
+            pack_threshold: None,
+            retention: RetentionPolicy::default(),
+        }
+    }
+
+    /// After collecting, repack loose objects smaller than `threshold` bytes.
+    #[must_use]
+    pub fn with_pack_threshold(mut self, threshold: u64) -> Self {
+        self.pack_threshold = Some(threshold);
+        self
+    }
+
+    /// Evict archived environments and snapshots according to `retention`.
+    #[must_use]
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
     }
 
     pub fn collect(&self, dry_run: bool) -> Result<GcReport, StoreError> {
         self.collect_with_cancel(dry_run, || false)
     }
 
+    /// [`mark`](Self::mark), then [`sweep`](Self::sweep).
     pub fn collect_with_cancel(
         &self,
         dry_run: bool,
         should_stop: impl Fn() -> bool,
     ) -> Result<GcReport, StoreError> {
+        let plan = self.mark()?;
+        self.sweep(plan, dry_run, should_stop)
+    }
+
+    /// Find what is unreferenced, applying the retention policy. Only
+    /// reads the store, so it needs no store lock: builds and other
+    /// mutations may run meanwhile, and [`sweep`](Self::sweep) spares what
+    /// they reference.
+    pub fn mark(&self) -> Result<GcPlan, StoreError> {
         let meta_store = MetadataStore::new(self.layout.clone());
         let layer_store = LayerStore::new(self.layout.clone());
         let object_store = ObjectStore::new(self.layout.clone());
@@ -41,40 +120,34 @@ impl GarbageCollector {
         let mut report = GcReport::default();
 
         let all_meta = meta_store.list()?;
+        let all_layers = layer_store.list()?;
+        let (evicted_envs, evicted_snapshots) =
+            self.plan_retention(&all_meta, &all_layers, &object_store, &mut report)?;
+
         let mut live_layers: HashSet<String> = HashSet::new();
 
         // Objects directly referenced by live environments (manifest hashes)
         let mut live_objects: HashSet<String> = HashSet::new();
 
         for meta in &all_meta {
-            if meta.ref_count == 0
-                && meta.state != EnvState::Running
-                && meta.state != EnvState::Archived
-            {
+            if evicted_envs.contains(meta.env_id.as_str()) {
+                continue;
+            }
+            if is_orphan(meta) {
                 report.orphaned_envs.push(meta.env_id.to_string());
             } else {
-                live_layers.insert(meta.base_layer.to_string());
-                for dep in &meta.dependency_layers {
-                    live_layers.insert(dep.to_string());
-                }
-                if let Some(ref policy) = meta.policy_layer {
-                    live_layers.insert(policy.to_string());
-                }
-                // Manifest object is directly referenced by metadata
-                if !meta.manifest_hash.is_empty() {
-                    live_objects.insert(meta.manifest_hash.to_string());
-                }
+                live_layers.extend(env_layers(meta));
+                // Manifest and attestation objects are referenced by metadata
+                live_objects.extend(meta.direct_objects());
             }
         }
 
-        let all_layers = layer_store.list()?;
-
         // Preserve snapshot layers whose parent is a live layer.
         // Without this, snapshots created by commit() would be GC'd as orphans.
         for layer_hash in &all_layers {
-            if !live_layers.contains(layer_hash) {
+            if !live_layers.contains(layer_hash) && !evicted_snapshots.contains(layer_hash) {
                 if let Ok(layer) = layer_store.get(layer_hash) {
-                    if layer.kind == crate::layers::LayerKind::Snapshot {
+                    if layer.kind == LayerKind::Snapshot {
                         if let Some(ref parent) = layer.parent {
                             if live_layers.contains(parent) {
                                 live_layers.insert(layer_hash.clone());
@@ -85,18 +158,26 @@ impl GarbageCollector {
             }
         }
 
+        // Live layers found only in the shared store count too: objects
+        // they reference are live here even though GC never lists or
+        // removes the layers themselves.
+        for layer_hash in &live_layers {
+            if let Ok(layer) = layer_store.get(layer_hash) {
+                live_objects.extend(layer.object_refs);
+            }
+        }
         for layer_hash in &all_layers {
-            if live_layers.contains(layer_hash) {
-                if let Ok(layer) = layer_store.get(layer_hash) {
-                    for obj_ref in &layer.object_refs {
-                        live_objects.insert(obj_ref.clone());
-                    }
-                }
-            } else {
+            if !live_layers.contains(layer_hash) {
                 report.orphaned_layers.push(layer_hash.clone());
             }
         }
 
+        // The chunks of a live chunked object are live too.
+        let live_objects: HashSet<String> = object_store
+            .with_chunks(live_objects)?
+            .into_iter()
+            .collect();
+
         let all_objects = object_store.list()?;
         for obj_hash in &all_objects {
             if !live_objects.contains(obj_hash) {
@@ -104,8 +185,34 @@ impl GarbageCollector {
             }
         }
 
+        Ok(GcPlan {
+            report,
+            revisions: all_meta
+                .iter()
+                .map(|meta| (meta.env_id.to_string(), meta.revision))
+                .collect(),
+            layers: all_layers.into_iter().collect(),
+        })
+    }
+
+    /// Remove what `plan` found unreferenced and is still unreferenced,
+    /// then repack if configured. The caller must hold the store lock, so
+    /// the store only changed between the mark and the lock: environments
+    /// whose metadata revision changed are kept, and so is everything
+    /// they and layers written since the mark reference.
+    pub fn sweep(
+        &self,
+        plan: GcPlan,
+        dry_run: bool,
+        should_stop: impl Fn() -> bool,
+    ) -> Result<GcReport, StoreError> {
+        let meta_store = MetadataStore::new(self.layout.clone());
+        let layer_store = LayerStore::new(self.layout.clone());
+        let object_store = ObjectStore::new(self.layout.clone());
+        let mut report = recheck(plan, &meta_store, &layer_store, &object_store)?;
+
         if !dry_run {
-            for env_id in &report.orphaned_envs {
+            for env_id in report.orphaned_envs.iter().chain(&report.evicted_envs) {
                 if should_stop() {
                     break;
                 }
@@ -125,23 +232,440 @@ impl GarbageCollector {
                 report.removed_layers += 1;
             }
 
+            // Packed orphans are batched so each pack is rewritten once.
+            let mut packed_orphans = HashSet::new();
             for obj_hash in &report.orphaned_objects {
                 if should_stop() {
                     break;
                 }
-                object_store.remove(obj_hash)?;
-                report.removed_objects += 1;
+                if object_store.remove_loose(obj_hash)? {
+                    report.removed_objects += 1;
+                } else {
+                    packed_orphans.insert(obj_hash.clone());
+                }
+            }
+            if !packed_orphans.is_empty() && !should_stop() {
+                report.removed_objects += object_store.remove_many(&packed_orphans)?;
+            }
+
+            if let Some(threshold) = self.pack_threshold {
+                if !should_stop() {
+                    report.packed_objects = object_store.repack(threshold)?.loose_packed;
+                }
             }
         }
 
         Ok(report)
     }
+
+    /// Decide which archived environments and snapshots the retention
+    /// policy evicts, and record them in `report`.
+    fn plan_retention(
+        &self,
+        all_meta: &[EnvMetadata],
+        all_layers: &[String],
+        object_store: &ObjectStore,
+        report: &mut GcReport,
+    ) -> Result<(HashSet<String>, HashSet<String>), StoreError> {
+        let policy = self.retention;
+        let mut evicted_envs = HashSet::new();
+        let mut evicted_snapshots = HashSet::new();
+        if policy == RetentionPolicy::default() {
+            return Ok((evicted_envs, evicted_snapshots));
+        }
+        let now = SystemTime::now();
+        let is_older = |time: SystemTime| {
+            policy
+                .older_than
+                .is_some_and(|max| now.duration_since(time).unwrap_or_default() > max)
+        };
+
+        // Archived environments only age out; `keep_last` counts snapshots.
+        let mut archived: Vec<(&EnvMetadata, SystemTime)> = all_meta
+            .iter()
+            .filter(|m| m.state == EnvState::Archived)
+            .map(|m| (m, env_time(m)))
+            .collect();
+        archived.sort_by_key(|(_, time)| *time);
+        for (meta, time) in &archived {
+            if is_older(*time) {
+                evicted_envs.insert(meta.env_id.to_string());
+            }
+        }
+
+        let mut size_candidates = Vec::new();
+        for snapshots in self
+            .snapshot_lineages(all_meta, all_layers, now)
+            .values_mut()
+        {
+            snapshots.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.hash.cmp(&b.hash)));
+            for (i, snapshot) in snapshots.drain(..).enumerate() {
+                let kept = policy.keep_last.is_some_and(|n| i < n);
+                let evict = match (policy.keep_last, policy.older_than) {
+                    (_, Some(_)) => !kept && is_older(snapshot.time),
+                    (Some(n), None) => i >= n,
+                    (None, None) => false,
+                };
+                if evict {
+                    evicted_snapshots.insert(snapshot.hash);
+                } else if !kept {
+                    size_candidates.push(snapshot);
+                }
+            }
+        }
+
+        if let Some(budget) = policy.max_store_size {
+            let layer_store = LayerStore::new(self.layout.clone());
+            let mut model = SizeModel::new(self, all_meta, object_store, &evicted_envs)?;
+            report.store_size_before = Some(model.total_before);
+            model.add_snapshots(&evicted_snapshots, &layer_store, object_store, all_layers);
+
+            let mut queue: Vec<(SystemTime, Candidate)> = archived
+                .iter()
+                .filter(|(m, _)| !evicted_envs.contains(m.env_id.as_str()))
+                .map(|(m, time)| (*time, Candidate::Env(m.env_id.to_string())))
+                .chain(
+                    size_candidates
+                        .iter()
+                        .map(|s| (s.time, Candidate::Snapshot(s.hash.clone()))),
+                )
+                .collect();
+            queue.sort_by_key(|(time, _)| *time);
+
+            for (_, candidate) in queue {
+                if model.size <= budget {
+                    break;
+                }
+                match candidate {
+                    Candidate::Env(env_id) => {
+                        model.evict_env(&env_id);
+                        evicted_envs.insert(env_id);
+                    }
+                    Candidate::Snapshot(hash) => {
+                        if model.evict_snapshot(&hash) {
+                            evicted_snapshots.insert(hash);
+                        }
+                    }
+                }
+            }
+            report.store_size_after = Some(model.size);
+        }
+
+        report.evicted_envs = evicted_envs.iter().cloned().collect();
+        report.evicted_envs.sort();
+        report.evicted_snapshots = evicted_snapshots.iter().cloned().collect();
+        report.evicted_snapshots.sort();
+        Ok((evicted_envs, evicted_snapshots))
+    }
+}
+
+impl GarbageCollector {
+    /// Snapshots grouped by parent layer and workspace, i.e. per
+    /// environment workspace. Snapshots an environment references directly
+    /// are left out, so they are never evicted. A snapshot's age is the
+    /// modification time of its layer file.
+    fn snapshot_lineages(
+        &self,
+        all_meta: &[EnvMetadata],
+        all_layers: &[String],
+        now: SystemTime,
+    ) -> HashMap<(String, Option<String>), Vec<Snapshot>> {
+        let pinned: HashSet<String> = all_meta.iter().flat_map(env_layers).collect();
+        let layer_store = LayerStore::new(self.layout.clone());
+        let mut lineages: HashMap<_, Vec<Snapshot>> = HashMap::new();
+        for hash in all_layers {
+            if pinned.contains(hash) {
+                continue;
+            }
+            let Ok(layer) = layer_store.get(hash) else {
+                continue;
+            };
+            let (LayerKind::Snapshot, Some(parent)) = (layer.kind, layer.parent) else {
+                continue;
+            };
+            let time = fs::metadata(self.layout.layers_dir().join(hash))
+                .and_then(|m| m.modified())
+                .unwrap_or(now);
+            lineages
+                .entry((parent, layer.workspace))
+                .or_default()
+                .push(Snapshot {
+                    hash: hash.clone(),
+                    time,
+                });
+        }
+        lineages
+    }
+}
+
+/// The report of `plan` without whatever became referenced since the
+/// mark.
+fn recheck(
+    plan: GcPlan,
+    meta_store: &MetadataStore,
+    layer_store: &LayerStore,
+    object_store: &ObjectStore,
+) -> Result<GcReport, StoreError> {
+    let GcPlan {
+        mut report,
+        revisions,
+        layers,
+    } = plan;
+    let mut unchanged = HashSet::new();
+    let mut rescued_layers = HashSet::new();
+    let mut rescued_objects = HashSet::new();
+    for meta in meta_store.list()? {
+        if revisions.get(meta.env_id.as_str()) == Some(&meta.revision) {
+            unchanged.insert(meta.env_id.to_string());
+        } else if !is_orphan(&meta) {
+            rescued_layers.extend(env_layers(&meta));
+            rescued_objects.extend(meta.direct_objects());
+        }
+    }
+    // Removed or changed since the mark: left to the next GC.
+    report.orphaned_envs.retain(|id| unchanged.contains(id));
+    report.evicted_envs.retain(|id| unchanged.contains(id));
+
+    // Layers written since the mark belong to whatever wrote them.
+    rescued_layers.extend(
+        layer_store
+            .list()?
+            .into_iter()
+            .filter(|hash| !layers.contains(hash)),
+    );
+    if rescued_layers.is_empty() && rescued_objects.is_empty() {
+        return Ok(report);
+    }
+    // Snapshots of a rescued layer live on with it, as in the mark.
+    let snapshots: Vec<String> = report
+        .orphaned_layers
+        .iter()
+        .filter(|hash| !report.evicted_snapshots.contains(hash))
+        .filter(|hash| {
+            layer_store.get(hash).is_ok_and(|layer| {
+                layer.kind == LayerKind::Snapshot
+                    && layer
+                        .parent
+                        .is_some_and(|parent| rescued_layers.contains(&parent))
+            })
+        })
+        .cloned()
+        .collect();
+    rescued_layers.extend(snapshots);
+    for hash in &rescued_layers {
+        if let Ok(layer) = layer_store.get(hash) {
+            rescued_objects.extend(layer.object_refs);
+        }
+    }
+    report
+        .orphaned_layers
+        .retain(|hash| !rescued_layers.contains(hash));
+    let rescued_objects: HashSet<String> = object_store
+        .with_chunks(rescued_objects)?
+        .into_iter()
+        .collect();
+    report
+        .orphaned_objects
+        .retain(|hash| !rescued_objects.contains(hash));
+    Ok(report)
+}
+
+/// Environments GC removes outright: unreferenced and neither running nor
+/// archived.
+fn is_orphan(meta: &EnvMetadata) -> bool {
+    meta.ref_count == 0 && meta.state != EnvState::Running && meta.state != EnvState::Archived
+}
+
+/// Layers an environment's metadata references.
+fn env_layers(meta: &EnvMetadata) -> impl Iterator<Item = String> + '_ {
+    std::iter::once(meta.base_layer.to_string())
+        .chain(meta.dependency_layers.iter().map(ToString::to_string))
+        .chain(meta.policy_layer.iter().map(ToString::to_string))
+}
+
+/// When an environment last changed state. Unparseable timestamps count as
+/// now, so they never age out.
+fn env_time(meta: &EnvMetadata) -> SystemTime {
+    chrono::DateTime::parse_from_rfc3339(&meta.updated_at)
+        .map_or_else(|_| SystemTime::now(), SystemTime::from)
+}
+
+/// Total size of the files under `path`, without following symlinks.
+fn dir_size(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path).map_or(0, |entries| {
+        entries
+            .filter_map(Result::ok)
+            .map(|entry| dir_size(&entry.path()))
+            .sum()
+    })
+}
+
+struct Snapshot {
+    hash: String,
+    time: SystemTime,
+}
+
+enum Candidate {
+    Env(String),
+    Snapshot(String),
+}
+
+/// Estimates the store size as environments and snapshots are evicted, by
+/// counting how many of them still reference each object.
+struct SizeModel {
+    sizes: HashMap<String, u64>,
+    refs: HashMap<String, usize>,
+    /// Objects, layers and directory size of each live environment.
+    envs: HashMap<String, (Vec<String>, Vec<String>, u64)>,
+    /// Parent layer and objects of each counted snapshot.
+    snapshots: HashMap<String, (String, Vec<String>)>,
+    /// How many live environments reference each layer.
+    layer_refs: HashMap<String, usize>,
+    total_before: u64,
+    size: u64,
+}
+
+impl SizeModel {
+    fn new(
+        gc: &GarbageCollector,
+        all_meta: &[EnvMetadata],
+        object_store: &ObjectStore,
+        evicted_envs: &HashSet<String>,
+    ) -> Result<Self, StoreError> {
+        let layer_store = LayerStore::new(gc.layout.clone());
+        let sizes = object_store.stored_sizes()?;
+        let mut model = Self {
+            total_before: sizes.values().sum(),
+            sizes,
+            refs: HashMap::new(),
+            envs: HashMap::new(),
+            snapshots: HashMap::new(),
+            layer_refs: HashMap::new(),
+            size: 0,
+        };
+        for meta in all_meta {
+            let dir = dir_size(&gc.layout.env_path(&meta.env_id));
+            model.total_before += dir;
+            if is_orphan(meta) || evicted_envs.contains(meta.env_id.as_str()) {
+                continue;
+            }
+            let layers: Vec<String> = env_layers(meta).collect();
+            let mut objects: HashSet<String> = layers
+                .iter()
+                .filter_map(|hash| layer_store.get(hash).ok())
+                .flat_map(|layer| layer.object_refs)
+                .collect();
+            objects.extend(meta.direct_objects());
+            let objects = object_store.with_chunks(objects)?;
+            model.reference(&objects);
+            for layer in &layers {
+                *model.layer_refs.entry(layer.clone()).or_default() += 1;
+            }
+            model.size += dir;
+            model
+                .envs
+                .insert(meta.env_id.to_string(), (objects, layers, dir));
+        }
+        Ok(model)
+    }
+
+    /// Count every snapshot that is not evicted yet and whose parent is
+    /// still used by an environment.
+    fn add_snapshots(
+        &mut self,
+        evicted: &HashSet<String>,
+        layer_store: &LayerStore,
+        object_store: &ObjectStore,
+        all_layers: &[String],
+    ) {
+        for hash in all_layers {
+            if evicted.contains(hash) || self.layer_refs.contains_key(hash) {
+                continue;
+            }
+            let Ok(layer) = layer_store.get(hash) else {
+                continue;
+            };
+            let (LayerKind::Snapshot, Some(parent)) = (layer.kind, layer.parent) else {
+                continue;
+            };
+            if self.layer_refs.contains_key(&parent) {
+                let objects = object_store
+                    .with_chunks(layer.object_refs.iter().cloned())
+                    .unwrap_or(layer.object_refs);
+                self.reference(&objects);
+                self.snapshots.insert(hash.clone(), (parent, objects));
+            }
+        }
+    }
+
+    fn reference(&mut self, objects: &[String]) {
+        for hash in objects {
+            let count = self.refs.entry(hash.clone()).or_default();
+            if *count == 0 {
+                self.size += self.sizes.get(hash).copied().unwrap_or(0);
+            }
+            *count += 1;
+        }
+    }
+
+    fn release(&mut self, objects: &[String]) {
+        for hash in objects {
+            if let Some(count) = self.refs.get_mut(hash) {
+                *count -= 1;
+                if *count == 0 {
+                    self.size -= self.sizes.get(hash).copied().unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    fn evict_env(&mut self, env_id: &str) {
+        let Some((objects, layers, dir)) = self.envs.remove(env_id) else {
+            return;
+        };
+        self.release(&objects);
+        self.size -= dir;
+        for layer in layers {
+            if let Some(count) = self.layer_refs.get_mut(&layer) {
+                *count -= 1;
+                if *count == 0 {
+                    self.layer_refs.remove(&layer);
+                }
+            }
+        }
+        // Snapshots of layers no environment uses any more go with it.
+        let orphaned: Vec<String> = self
+            .snapshots
+            .iter()
+            .filter(|(_, (parent, _))| !self.layer_refs.contains_key(parent))
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        for hash in orphaned {
+            self.evict_snapshot(&hash);
+        }
+    }
+
+    /// Returns whether the snapshot was still counted.
+    fn evict_snapshot(&mut self, hash: &str) -> bool {
+        let Some((_, objects)) = self.snapshots.remove(hash) else {
+            return false;
+        };
+        self.release(&objects);
+        true
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::metadata::EnvMetadata;
+    use std::collections::BTreeMap;
 
     fn setup() -> (tempfile::TempDir, StoreLayout) {
         let dir = tempfile::tempdir().unwrap();
@@ -168,6 +692,18 @@ mod tests {
             updated_at: "2025-01-01T00:00:00Z".to_owned(),
             ref_count: 0,
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
         };
         meta_store.put(&meta).unwrap();
 
@@ -194,6 +730,18 @@ mod tests {
             updated_at: "2025-01-01T00:00:00Z".to_owned(),
             ref_count: 0,
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
         };
         meta_store.put(&meta).unwrap();
 
@@ -227,6 +775,18 @@ mod tests {
             updated_at: "2025-01-01T00:00:00Z".to_owned(),
             ref_count: 1,
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
         };
         meta_store.put(&meta).unwrap();
 
@@ -256,6 +816,18 @@ mod tests {
             updated_at: "2025-01-01T00:00:00Z".to_owned(),
             ref_count: 0,
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
         };
         meta_store.put(&meta).unwrap();
 
@@ -283,6 +855,18 @@ mod tests {
             updated_at: "2025-01-01T00:00:00Z".to_owned(),
             ref_count: 0,
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
         };
         meta_store.put(&meta).unwrap();
 
@@ -290,4 +874,391 @@ mod tests {
         let report = gc.collect(false).unwrap();
         assert_eq!(report.removed_envs, 0);
     }
+
+    #[test]
+    fn gc_packs_live_objects_and_drops_packed_orphans() {
+        let (_dir, layout) = setup();
+        let meta_store = MetadataStore::new(layout.clone());
+        let object_store = ObjectStore::new(layout.clone());
+
+        let manifest_hash = object_store.put(b"live-manifest").unwrap();
+        let orphan_hash = object_store.put(b"orphan-object").unwrap();
+        object_store.repack(crate::DEFAULT_PACK_THRESHOLD).unwrap();
+        let loose_hash = object_store.put(b"new-loose-object").unwrap();
+
+        let meta = EnvMetadata {
+            env_id: "live1".into(),
+            short_id: "live1".into(),
+            name: None,
+            state: EnvState::Built,
+            manifest_hash: manifest_hash.clone().into(),
+            base_layer: "base1".into(),
+            dependency_layers: vec![],
+            policy_layer: None,
+            created_at: "2025-01-01T00:00:00Z".to_owned(),
+            updated_at: "2025-01-01T00:00:00Z".to_owned(),
+            ref_count: 1,
+            checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
+        };
+        meta_store.put(&meta).unwrap();
+
+        let gc = GarbageCollector::new(layout.clone()).with_pack_threshold(1024);
+        let report = gc.collect(false).unwrap();
+        assert_eq!(report.removed_objects, 2);
+        assert_eq!(report.packed_objects, 0);
+        assert!(!object_store.exists(&orphan_hash));
+        assert!(!object_store.exists(&loose_hash));
+        assert_eq!(object_store.get(&manifest_hash).unwrap(), b"live-manifest");
+        assert_eq!(fs::read_dir(layout.objects_dir()).unwrap().count(), 0);
+    }
+
+    fn layer(layout: &StoreLayout, kind: LayerKind, parent: Option<&str>, data: &[u8]) -> String {
+        let object = ObjectStore::new(layout.clone()).put(data).unwrap();
+        LayerStore::new(layout.clone())
+            .put(&crate::layers::LayerManifest {
+                hash: String::new(),
+                kind,
+                parent: parent.map(str::to_owned),
+                object_refs: vec![object],
+                read_only: true,
+                tar_hash: String::new(),
+                workspace: None,
+                delta_parent: None,
+                file_objects: Vec::new(),
+                pax_metadata: false,
+            })
+            .unwrap()
+    }
+
+    fn snapshot(layout: &StoreLayout, parent: &str, name: &str, days_old: u64) -> String {
+        let hash = layer(layout, LayerKind::Snapshot, Some(parent), name.as_bytes());
+        let age = Duration::from_hours(days_old * 24);
+        fs::File::options()
+            .write(true)
+            .open(layout.layers_dir().join(&hash))
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+        hash
+    }
+
+    fn env(layout: &StoreLayout, env_id: &str, state: EnvState, base: &str, days_old: i64) {
+        let updated = chrono::Utc::now() - chrono::Duration::days(days_old);
+        let meta = EnvMetadata {
+            env_id: env_id.into(),
+            short_id: env_id.into(),
+            name: None,
+            state,
+            manifest_hash: "".into(),
+            base_layer: base.into(),
+            dependency_layers: vec![],
+            policy_layer: None,
+            created_at: updated.to_rfc3339(),
+            updated_at: updated.to_rfc3339(),
+            ref_count: 1,
+            checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
+        };
+        MetadataStore::new(layout.clone()).put(&meta).unwrap();
+    }
+
+    #[test]
+    fn sweep_spares_what_changed_since_the_mark() {
+        let (_dir, layout) = setup();
+        let meta_store = MetadataStore::new(layout.clone());
+        let layer_store = LayerStore::new(layout.clone());
+        let base = layer(&layout, LayerKind::Base, None, b"reused");
+        let snap = snapshot(&layout, &base, "snap", 0);
+        let garbage = layer(&layout, LayerKind::Base, None, b"garbage");
+        env(&layout, "idle1", EnvState::Built, &garbage, 0);
+        let mut idle = meta_store.get("idle1").unwrap();
+        idle.ref_count = 0;
+        meta_store.put(&idle).unwrap();
+
+        let gc = GarbageCollector::new(layout.clone());
+        let plan = gc.mark().unwrap();
+        assert_eq!(plan.report().orphaned_envs, ["idle1"]);
+        assert_eq!(plan.report().orphaned_layers.len(), 3);
+
+        // A build reusing the orphaned base, a layer it has not referenced
+        // yet, and a new reference to the idle environment, all between
+        // the mark and the sweep.
+        env(&layout, "late1", EnvState::Built, &base, 0);
+        let fresh = layer(&layout, LayerKind::Dependency, None, b"fresh");
+        let mut idle = meta_store.get("idle1").unwrap();
+        idle.ref_count = 1;
+        meta_store.put(&idle).unwrap();
+
+        let report = gc.sweep(plan, false, || false).unwrap();
+        assert!(report.orphaned_envs.is_empty());
+        assert!(report.orphaned_layers.is_empty());
+        assert!(report.orphaned_objects.is_empty());
+        for hash in [&base, &snap, &garbage, &fresh] {
+            let layer = layer_store.get(hash).unwrap();
+            assert!(ObjectStore::new(layout.clone()).exists(&layer.object_refs[0]));
+        }
+
+        // Once nothing changes in between, the idle environment's layer
+        // goes with it.
+        let mut idle = meta_store.get("idle1").unwrap();
+        idle.ref_count = 0;
+        meta_store.put(&idle).unwrap();
+        let report = gc.collect(false).unwrap();
+        assert_eq!(report.orphaned_envs, ["idle1"]);
+        assert!(report.orphaned_layers.contains(&garbage));
+        assert!(report.orphaned_layers.contains(&fresh));
+        assert!(!report.orphaned_layers.contains(&base));
+    }
+
+    #[test]
+    fn gc_keeps_chunks_of_live_objects() {
+        let (_dir, layout) = setup();
+        let object_store = ObjectStore::new(layout.clone());
+        let big = crate::chunking::noise(9 * 1024 * 1024, 1);
+        let base = layer(&layout, LayerKind::Base, None, &big);
+        env(&layout, "built1", EnvState::Built, &base, 0);
+        let tar = blake3::hash(&big).to_hex().to_string();
+        let chunks = object_store.chunk_list(&tar).unwrap().unwrap();
+
+        let report = GarbageCollector::new(layout.clone())
+            .collect(false)
+            .unwrap();
+        assert_eq!(report.removed_objects, 0);
+        assert_eq!(object_store.get(&tar).unwrap(), big);
+
+        // Once the environment goes, so do the chunks.
+        let meta_store = MetadataStore::new(layout.clone());
+        meta_store
+            .update::<StoreError>("built1", |m| {
+                m.ref_count = 0;
+                Ok(())
+            })
+            .unwrap();
+        let report = GarbageCollector::new(layout.clone())
+            .collect(false)
+            .unwrap();
+        assert_eq!(report.removed_objects, chunks.len() + 1);
+        assert!(object_store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn keep_last_evicts_older_snapshots() {
+        let (_dir, layout) = setup();
+        let base = layer(&layout, LayerKind::Base, None, b"base");
+        env(&layout, "built1", EnvState::Built, &base, 0);
+        let oldest = snapshot(&layout, &base, "s1", 3);
+        let middle = snapshot(&layout, &base, "s2", 2);
+        let newest = snapshot(&layout, &base, "s3", 1);
+
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            ..RetentionPolicy::default()
+        };
+        let report = GarbageCollector::new(layout.clone())
+            .with_retention(policy)
+            .collect(false)
+            .unwrap();
+
+        let mut expected = vec![oldest.clone(), middle.clone()];
+        expected.sort();
+        assert_eq!(report.evicted_snapshots, expected);
+        assert_eq!(report.removed_layers, 2);
+        assert_eq!(report.removed_objects, 2);
+        let layers = LayerStore::new(layout);
+        assert!(layers.exists(&newest));
+        assert!(!layers.exists(&oldest));
+        assert!(layers.exists(&base));
+    }
+
+    #[test]
+    fn older_than_evicts_archived_envs_and_unprotected_snapshots() {
+        let (_dir, layout) = setup();
+        let old_base = layer(&layout, LayerKind::Base, None, b"old");
+        let new_base = layer(&layout, LayerKind::Base, None, b"new");
+        env(&layout, "stale", EnvState::Archived, &old_base, 60);
+        env(&layout, "recent", EnvState::Archived, &new_base, 5);
+        env(&layout, "frozen", EnvState::Frozen, &old_base, 90);
+        let kept = snapshot(&layout, &new_base, "kept", 40);
+        let aged = snapshot(&layout, &new_base, "aged", 50);
+
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            older_than: Some(Duration::from_hours(30 * 24)),
+            ..RetentionPolicy::default()
+        };
+        let report = GarbageCollector::new(layout.clone())
+            .with_retention(policy)
+            .collect(false)
+            .unwrap();
+
+        assert_eq!(report.evicted_envs, vec!["stale".to_owned()]);
+        assert_eq!(report.evicted_snapshots, vec![aged]);
+        assert_eq!(report.removed_envs, 1);
+        let meta_store = MetadataStore::new(layout.clone());
+        assert!(!meta_store.exists("stale"));
+        assert!(meta_store.exists("recent"));
+        assert!(meta_store.exists("frozen"), "only archived envs age out");
+        // The frozen env still uses the stale env's base layer.
+        assert!(LayerStore::new(layout.clone()).exists(&old_base));
+        assert!(LayerStore::new(layout).exists(&kept));
+    }
+
+    #[test]
+    fn max_store_size_evicts_oldest_first() {
+        let (_dir, layout) = setup();
+        let oldest = layer(&layout, LayerKind::Base, None, &[1u8; 1000]);
+        let older = layer(&layout, LayerKind::Base, None, &[2u8; 1000]);
+        let live = layer(&layout, LayerKind::Base, None, &[3u8; 1000]);
+        env(&layout, "a-oldest", EnvState::Archived, &oldest, 30);
+        env(&layout, "b-older", EnvState::Archived, &older, 20);
+        env(&layout, "c-live", EnvState::Built, &live, 40);
+        let sizes = ObjectStore::new(layout.clone()).stored_sizes().unwrap();
+        let total: u64 = sizes.values().sum();
+        let largest = sizes.values().max().copied().unwrap();
+
+        let policy = RetentionPolicy {
+            max_store_size: Some(total - 1),
+            ..RetentionPolicy::default()
+        };
+        let gc = GarbageCollector::new(layout.clone()).with_retention(policy);
+        let report = gc.collect(true).unwrap();
+        assert_eq!(report.evicted_envs, vec!["a-oldest".to_owned()]);
+        assert_eq!(report.store_size_before, Some(total));
+        assert!(report.store_size_after.unwrap() < total);
+        assert_eq!(report.removed_envs, 0, "dry run must not remove");
+
+        let policy = RetentionPolicy {
+            max_store_size: Some(largest),
+            ..RetentionPolicy::default()
+        };
+        let report = GarbageCollector::new(layout.clone())
+            .with_retention(policy)
+            .collect(false)
+            .unwrap();
+        assert_eq!(
+            report.evicted_envs,
+            vec!["a-oldest".to_owned(), "b-older".to_owned()]
+        );
+        assert_eq!(report.removed_envs, 2);
+        assert!(MetadataStore::new(layout).exists("c-live"));
+    }
+
+    #[test]
+    fn default_retention_evicts_nothing() {
+        let (_dir, layout) = setup();
+        let base = layer(&layout, LayerKind::Base, None, b"base");
+        env(&layout, "archived", EnvState::Archived, &base, 365);
+        snapshot(&layout, &base, "s", 365);
+
+        let report = GarbageCollector::new(layout).collect(false).unwrap();
+        assert!(report.evicted_envs.is_empty());
+        assert!(report.evicted_snapshots.is_empty());
+        assert_eq!(report.store_size_before, None);
+        assert_eq!(report.removed_layers, 0);
+    }
+
+    #[test]
+    fn gc_uses_but_never_touches_the_shared_store() {
+        let (_dir, layout) = setup();
+        // Stored before the shared store was configured, and referenced
+        // only by a layer the user's store does not have itself.
+        let private = ObjectStore::new(layout.clone())
+            .put(b"private copy")
+            .unwrap();
+
+        let (_shared_dir, shared) = setup();
+        let shared_objects = ObjectStore::new(shared.clone());
+        let base = shared_objects.put(b"preseeded base tar").unwrap();
+        let base_layer = LayerStore::new(shared.clone())
+            .put(&crate::LayerManifest {
+                hash: "base".to_owned(),
+                kind: LayerKind::Base,
+                parent: None,
+                object_refs: vec![base.clone(), private.clone()],
+                read_only: true,
+                tar_hash: base.clone(),
+                workspace: None,
+                delta_parent: None,
+                file_objects: Vec::new(),
+                pax_metadata: false,
+            })
+            .unwrap();
+
+        crate::StoreConfig {
+            shared_store: Some(shared.root().to_path_buf()),
+            ..crate::StoreConfig::default()
+        }
+        .save(&layout)
+        .unwrap();
+        let object_store = ObjectStore::new(layout.clone());
+        let layer_store = LayerStore::new(layout.clone());
+        // Present in the shared store, so not written again.
+        assert_eq!(object_store.put(b"preseeded base tar").unwrap(), base);
+        assert!(object_store.exists(&base) && !object_store.exists_here(&base));
+        assert_eq!(object_store.get(&base).unwrap(), b"preseeded base tar");
+        assert_eq!(layer_store.get(&base_layer).unwrap().tar_hash, base);
+        assert!(layer_store.list().unwrap().is_empty());
+        let own = object_store.put(b"unreferenced").unwrap();
+
+        MetadataStore::new(layout.clone())
+            .put(&EnvMetadata {
+                env_id: "user1".into(),
+                short_id: "user1".into(),
+                name: None,
+                state: EnvState::Built,
+                manifest_hash: "".into(),
+                base_layer: base_layer.clone().into(),
+                dependency_layers: vec![],
+                policy_layer: None,
+                created_at: "2025-01-01T00:00:00Z".to_owned(),
+                updated_at: "2025-01-01T00:00:00Z".to_owned(),
+                ref_count: 1,
+                checksum: None,
+                aliases: Vec::new(),
+                host_gpu: None,
+                base_image_digest: None,
+                workspace: None,
+                snapshot: None,
+                expires_after_secs: None,
+                last_entered_at: None,
+                enter_count: 0,
+                last_built_at: None,
+                labels: BTreeMap::new(),
+                revision: 0,
+                attestation: None,
+            })
+            .unwrap();
+        let report = GarbageCollector::new(layout).collect(false).unwrap();
+        assert_eq!(report.orphaned_objects, vec![own]);
+        assert_eq!(report.removed_envs, 0);
+        assert!(report.orphaned_layers.is_empty());
+        assert!(object_store.exists_here(&private));
+
+        // The shared store itself is as it was, orphans and all.
+        assert!(shared_objects.exists_here(&base));
+        assert_eq!(LayerStore::new(shared).list().unwrap(), vec![base_layer]);
+    }
 }
@@ -0,0 +1,105 @@
+//! Index of the metadata store, so resolving a name or short id reads one
+//! file instead of every environment's metadata.
+//!
+//! The index lives at `store/metadata/.index`. [`MetadataStore`] updates it
+//! after every write and removal, inside a [`WalOpKind::Index`] WAL entry:
+//! if the process dies in between or the index cannot be written, the entry
+//! stays and WAL recovery rebuilds the index from the metadata entries. A
+//! hit is always checked against the entry it points to, so a stale index
+//! costs a scan, never a wrong answer.
+//!
+//! [`MetadataStore`]: crate::MetadataStore
+//! [`WalOpKind::Index`]: crate::WalOpKind::Index
+
+use crate::metadata::{EnvMetadata, EnvState};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Which map of the index a lookup reads.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Lookup {
+    Name,
+    ShortId,
+    Alias,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataIndex {
+    /// env_id by environment name.
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+    /// env_id by short id.
+    #[serde(default)]
+    pub short_ids: BTreeMap<String, String>,
+    /// env_id by former name.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// State of every environment, by env_id.
+    #[serde(default)]
+    pub states: BTreeMap<String, EnvState>,
+}
+
+impl MetadataIndex {
+    /// Index of `envs`.
+    pub fn build<'a>(envs: impl IntoIterator<Item = &'a EnvMetadata>) -> Self {
+        let mut index = Self::default();
+        for meta in envs {
+            index.insert(meta);
+        }
+        index
+    }
+
+    pub(crate) fn get(&self, lookup: Lookup, key: &str) -> Option<&String> {
+        match lookup {
+            Lookup::Name => self.names.get(key),
+            Lookup::ShortId => self.short_ids.get(key),
+            Lookup::Alias => self.aliases.get(key),
+        }
+    }
+
+    /// Number of indexed environments.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Number of environments in `state`.
+    pub fn count(&self, state: EnvState) -> usize {
+        self.states.values().filter(|s| **s == state).count()
+    }
+
+    /// Number of environments in each state that has any.
+    pub fn state_counts(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for state in self.states.values() {
+            *counts.entry(state.to_string()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    pub(crate) fn insert(&mut self, meta: &EnvMetadata) {
+        let env_id = meta.env_id.to_string();
+        self.remove(&env_id);
+        if let Some(name) = &meta.name {
+            self.names.insert(name.clone(), env_id.clone());
+        }
+        for alias in &meta.aliases {
+            self.aliases.insert(alias.clone(), env_id.clone());
+        }
+        self.short_ids
+            .insert(meta.short_id.to_string(), env_id.clone());
+        self.states.insert(env_id, meta.state);
+    }
+
+    pub(crate) fn remove(&mut self, env_id: &str) {
+        if self.states.remove(env_id).is_none() {
+            return;
+        }
+        self.names.retain(|_, id| id != env_id);
+        self.short_ids.retain(|_, id| id != env_id);
+        self.aliases.retain(|_, id| id != env_id);
+    }
+}
@@ -1,8 +1,18 @@
-use crate::layers::LayerStore;
+use crate::layers::{LayerManifest, LayerStore};
 use crate::layout::StoreLayout;
-use crate::metadata::MetadataStore;
+use crate::metadata::{EnvMetadata, MetadataStore};
 use crate::objects::ObjectStore;
-use crate::StoreError;
+use crate::{write_atomic, StoreError};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Number of buckets [`scrub_store`] divides objects and layers into, one
+/// per first byte of their hash.
+pub const SCRUB_BUCKETS: usize = 256;
+
+/// `store/scrub.json`: when each bucket was last verified (RFC 3339), keyed
+/// by the two hex digits its hashes start with.
+pub type ScrubState = BTreeMap<String, String>;
 
 #[derive(Debug, Default)]
 pub struct IntegrityReport {
@@ -15,12 +25,45 @@ pub struct IntegrityReport {
     pub metadata_passed: usize,
 }
 
+/// What an [`IntegrityFailure`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityKind {
+    Object,
+    Layer,
+    Metadata,
+}
+
+impl std::fmt::Display for IntegrityKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntegrityKind::Object => write!(f, "object"),
+            IntegrityKind::Layer => write!(f, "layer"),
+            IntegrityKind::Metadata => write!(f, "metadata"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct IntegrityFailure {
+    pub kind: IntegrityKind,
     pub hash: String,
     pub reason: String,
 }
 
+#[derive(Debug, Default)]
+pub struct ScrubReport {
+    /// What this run verified: the objects and layers of its buckets, and
+    /// all metadata.
+    pub integrity: IntegrityReport,
+    /// Buckets verified by this run.
+    pub buckets: usize,
+    /// Buckets never verified, after this run.
+    pub never_verified: usize,
+    /// When the least recently verified bucket was verified, once every
+    /// bucket has been.
+    pub oldest: Option<String>,
+}
+
 pub fn verify_store_integrity(layout: &StoreLayout) -> Result<IntegrityReport, StoreError> {
     let object_store = ObjectStore::new(layout.clone());
     let layer_store = LayerStore::new(layout.clone());
@@ -39,67 +82,249 @@ pub fn verify_store_integrity(layout: &StoreLayout) -> Result<IntegrityReport, S
 
     // Verify objects (blake3 content-addressed)
     for hash in &all_objects {
-        match object_store.get(hash) {
-            Ok(_) => report.passed += 1,
-            Err(StoreError::IntegrityFailure { actual, .. }) => {
-                report.failed.push(IntegrityFailure {
-                    hash: hash.clone(),
-                    reason: format!("object hash mismatch: got {actual}"),
-                });
-            }
-            Err(e) => {
-                report.failed.push(IntegrityFailure {
-                    hash: hash.clone(),
-                    reason: format!("object read error: {e}"),
-                });
-            }
+        if check_object(&object_store, hash, &mut report.failed) {
+            report.passed += 1;
         }
     }
 
     // Verify layers (blake3 content-addressed)
     for hash in &all_layers {
-        match layer_store.get(hash) {
-            Ok(_) => report.layers_passed += 1,
-            Err(StoreError::IntegrityFailure { actual, .. }) => {
-                report.failed.push(IntegrityFailure {
-                    hash: hash.clone(),
-                    reason: format!("layer hash mismatch: got {actual}"),
-                });
-            }
-            Err(e) => {
-                report.failed.push(IntegrityFailure {
-                    hash: hash.clone(),
-                    reason: format!("layer read error: {e}"),
-                });
-            }
+        if check_layer(&layer_store, hash, &mut report.failed).is_some() {
+            report.layers_passed += 1;
         }
     }
 
     // Verify metadata (embedded checksum)
     for meta in &all_meta {
-        match meta_store.get(&meta.env_id) {
-            Ok(_) => report.metadata_passed += 1,
-            Err(StoreError::IntegrityFailure { actual, .. }) => {
-                report.failed.push(IntegrityFailure {
-                    hash: meta.env_id.to_string(),
-                    reason: format!("metadata checksum mismatch: got {actual}"),
-                });
+        if check_metadata(&meta_store, &meta.env_id, &mut report.failed).is_some() {
+            report.metadata_passed += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Verify the least recently verified buckets of objects and layers until
+/// `max_duration` has passed, and all metadata, which is small.
+///
+/// At least one bucket is verified per run, and each finished bucket is
+/// recorded in `store/scrub.json` right away, so runs interrupted or cut
+/// short by the deadline still make progress. Repeated runs cycle through
+/// the whole store.
+pub fn scrub_store(
+    layout: &StoreLayout,
+    max_duration: Duration,
+) -> Result<ScrubReport, StoreError> {
+    let started = Instant::now();
+    let object_store = ObjectStore::new(layout.clone());
+    let layer_store = LayerStore::new(layout.clone());
+    let meta_store = MetadataStore::new(layout.clone());
+
+    let mut state = load_scrub_state(layout);
+    let mut buckets: BTreeMap<String, (Vec<String>, Vec<String>)> = (0..SCRUB_BUCKETS)
+        .map(|i| (format!("{i:02x}"), Default::default()))
+        .collect();
+    for hash in object_store.list()? {
+        if let Some(bucket) = hash.get(..2).and_then(|p| buckets.get_mut(p)) {
+            bucket.0.push(hash);
+        }
+    }
+    for hash in layer_store.list()? {
+        if let Some(bucket) = hash.get(..2).and_then(|p| buckets.get_mut(p)) {
+            bucket.1.push(hash);
+        }
+    }
+    // Never verified first, then oldest first.
+    let mut order: Vec<String> = buckets.keys().cloned().collect();
+    order.sort_by(|a, b| state.get(a).cmp(&state.get(b)).then(a.cmp(b)));
+
+    let mut report = ScrubReport::default();
+    let integrity = &mut report.integrity;
+    for prefix in order {
+        if report.buckets > 0 && started.elapsed() >= max_duration {
+            break;
+        }
+        let (objects, layers) = &buckets[&prefix];
+        integrity.checked += objects.len();
+        for hash in objects {
+            if check_object(&object_store, hash, &mut integrity.failed) {
+                integrity.passed += 1;
             }
-            Err(e) => {
-                report.failed.push(IntegrityFailure {
-                    hash: meta.env_id.to_string(),
-                    reason: format!("metadata read error: {e}"),
-                });
+        }
+        integrity.layers_checked += layers.len();
+        for hash in layers {
+            if check_layer(&layer_store, hash, &mut integrity.failed).is_some() {
+                integrity.layers_passed += 1;
+            }
+        }
+        state.insert(prefix, chrono::Utc::now().to_rfc3339());
+        report.buckets += 1;
+        if !objects.is_empty() || !layers.is_empty() {
+            save_scrub_state(layout, &state)?;
+        }
+    }
+    save_scrub_state(layout, &state)?;
+
+    let all_meta = meta_store.list()?;
+    integrity.metadata_checked = all_meta.len();
+    for meta in &all_meta {
+        if check_metadata(&meta_store, &meta.env_id, &mut integrity.failed).is_some() {
+            integrity.metadata_passed += 1;
+        }
+    }
+
+    report.never_verified = SCRUB_BUCKETS.saturating_sub(state.len());
+    if report.never_verified == 0 {
+        report.oldest = state.values().min().cloned();
+    }
+    Ok(report)
+}
+
+/// The recorded scrub progress. A missing or unreadable file means no
+/// bucket was verified yet.
+pub fn load_scrub_state(layout: &StoreLayout) -> ScrubState {
+    let path = layout.scrub_state_file();
+    let Ok(content) = std::fs::read_to_string(&path) else {
+        return ScrubState::new();
+    };
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        tracing::warn!("ignoring {}: {e}", path.display());
+        ScrubState::new()
+    })
+}
+
+fn save_scrub_state(layout: &StoreLayout, state: &ScrubState) -> Result<(), StoreError> {
+    let dir = layout.root().join("store");
+    let content = serde_json::to_string_pretty(state)?;
+    write_atomic(&dir, &layout.scrub_state_file(), content.as_bytes())
+}
+
+/// Verify only what one environment depends on: its metadata, manifest,
+/// layers, and the objects those layers reference.
+///
+/// Much cheaper than [`verify_store_integrity`] on large stores.
+pub fn verify_env_integrity(
+    layout: &StoreLayout,
+    env_id: &str,
+) -> Result<IntegrityReport, StoreError> {
+    let object_store = ObjectStore::new(layout.clone());
+    let layer_store = LayerStore::new(layout.clone());
+    let meta_store = MetadataStore::new(layout.clone());
+
+    let mut report = IntegrityReport {
+        metadata_checked: 1,
+        ..Default::default()
+    };
+    let Some(meta) = check_metadata(&meta_store, env_id, &mut report.failed) else {
+        return Ok(report);
+    };
+    report.metadata_passed = 1;
+
+    let mut objects: Vec<String> = meta.direct_objects();
+    let layers = std::iter::once(&meta.base_layer)
+        .chain(&meta.dependency_layers)
+        .chain(&meta.policy_layer);
+    for hash in layers {
+        report.layers_checked += 1;
+        if let Some(layer) = check_layer(&layer_store, hash, &mut report.failed) {
+            report.layers_passed += 1;
+            objects.extend(layer.object_refs);
+            if !layer.tar_hash.is_empty() {
+                objects.push(layer.tar_hash);
             }
         }
     }
 
+    objects.sort();
+    objects.dedup();
+    report.checked = objects.len();
+    for hash in &objects {
+        if check_object(&object_store, hash, &mut report.failed) {
+            report.passed += 1;
+        }
+    }
+
     Ok(report)
 }
 
+fn check_object(store: &ObjectStore, hash: &str, failed: &mut Vec<IntegrityFailure>) -> bool {
+    match store.get(hash) {
+        Ok(_) => true,
+        Err(StoreError::IntegrityFailure { actual, .. }) => {
+            failed.push(IntegrityFailure {
+                kind: IntegrityKind::Object,
+                hash: hash.to_owned(),
+                reason: format!("object hash mismatch: got {actual}"),
+            });
+            false
+        }
+        Err(e) => {
+            failed.push(IntegrityFailure {
+                kind: IntegrityKind::Object,
+                hash: hash.to_owned(),
+                reason: format!("object read error: {e}"),
+            });
+            false
+        }
+    }
+}
+
+fn check_layer(
+    store: &LayerStore,
+    hash: &str,
+    failed: &mut Vec<IntegrityFailure>,
+) -> Option<LayerManifest> {
+    match store.get(hash) {
+        Ok(layer) => Some(layer),
+        Err(StoreError::IntegrityFailure { actual, .. }) => {
+            failed.push(IntegrityFailure {
+                kind: IntegrityKind::Layer,
+                hash: hash.to_owned(),
+                reason: format!("layer hash mismatch: got {actual}"),
+            });
+            None
+        }
+        Err(e) => {
+            failed.push(IntegrityFailure {
+                kind: IntegrityKind::Layer,
+                hash: hash.to_owned(),
+                reason: format!("layer read error: {e}"),
+            });
+            None
+        }
+    }
+}
+
+fn check_metadata(
+    store: &MetadataStore,
+    env_id: &str,
+    failed: &mut Vec<IntegrityFailure>,
+) -> Option<EnvMetadata> {
+    match store.get(env_id) {
+        Ok(meta) => Some(meta),
+        Err(StoreError::IntegrityFailure { actual, .. }) => {
+            failed.push(IntegrityFailure {
+                kind: IntegrityKind::Metadata,
+                hash: env_id.to_owned(),
+                reason: format!("metadata checksum mismatch: got {actual}"),
+            });
+            None
+        }
+        Err(e) => {
+            failed.push(IntegrityFailure {
+                kind: IntegrityKind::Metadata,
+                hash: env_id.to_owned(),
+                reason: format!("metadata read error: {e}"),
+            });
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
 
     #[test]
     fn clean_store_passes_integrity() {
@@ -117,6 +342,34 @@ mod tests {
         assert!(report.failed.is_empty());
     }
 
+    #[test]
+    fn scrub_verifies_the_least_recently_verified_buckets() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        let obj_store = ObjectStore::new(layout.clone());
+        let hash = obj_store.put(b"original").unwrap();
+        std::fs::write(layout.objects_dir().join(&hash), b"corrupted").unwrap();
+
+        // Without time to spare, one bucket per run.
+        let report = scrub_store(&layout, Duration::ZERO).unwrap();
+        assert_eq!(report.buckets, 1);
+        assert_eq!(report.never_verified, SCRUB_BUCKETS - 1);
+        assert!(report.oldest.is_none());
+        assert!(load_scrub_state(&layout).contains_key("00"));
+        let report = scrub_store(&layout, Duration::ZERO).unwrap();
+        assert!(load_scrub_state(&layout).contains_key("01"));
+        assert_eq!(report.never_verified, SCRUB_BUCKETS - 2);
+
+        let report = scrub_store(&layout, Duration::MAX).unwrap();
+        assert_eq!(report.buckets, SCRUB_BUCKETS);
+        assert_eq!(report.never_verified, 0);
+        assert!(report.oldest.is_some());
+        assert_eq!(report.integrity.checked, 1);
+        assert_eq!(report.integrity.failed.len(), 1);
+        assert_eq!(report.integrity.failed[0].hash, hash);
+    }
+
     #[test]
     fn corrupted_object_detected() {
         let dir = tempfile::tempdir().unwrap();
@@ -140,13 +393,17 @@ mod tests {
         layout.initialize().unwrap();
 
         let layer_store = LayerStore::new(layout.clone());
-        let layer = crate::LayerManifest {
+        let layer = LayerManifest {
             hash: "test".to_owned(),
             kind: crate::LayerKind::Base,
             parent: None,
             object_refs: vec![],
             read_only: true,
             tar_hash: String::new(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata: false,
         };
         layer_store.put(&layer).unwrap();
 
@@ -162,13 +419,17 @@ mod tests {
         layout.initialize().unwrap();
 
         let layer_store = LayerStore::new(layout.clone());
-        let layer = crate::LayerManifest {
+        let layer = LayerManifest {
             hash: "test".to_owned(),
             kind: crate::LayerKind::Base,
             parent: None,
             object_refs: vec![],
             read_only: true,
             tar_hash: String::new(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata: false,
         };
         let hash = layer_store.put(&layer).unwrap();
 
@@ -188,7 +449,7 @@ mod tests {
         layout.initialize().unwrap();
 
         let meta_store = MetadataStore::new(layout.clone());
-        let meta = crate::EnvMetadata {
+        let meta = EnvMetadata {
             env_id: "test_env".into(),
             short_id: "test_env".into(),
             name: None,
@@ -201,6 +462,18 @@ mod tests {
             updated_at: "2025-01-01T00:00:00Z".to_owned(),
             ref_count: 1,
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
         };
         meta_store.put(&meta).unwrap();
 
@@ -221,4 +494,100 @@ mod tests {
         assert_eq!(report.metadata_checked, 0);
         assert!(report.failed.is_empty());
     }
+
+    fn put_env(layout: &StoreLayout, env_id: &str, payload: &[u8]) -> (String, String) {
+        let obj_store = ObjectStore::new(layout.clone());
+        let manifest_hash = obj_store.put(b"{}").unwrap();
+        let tar_hash = obj_store.put(payload).unwrap();
+        let layer = LayerManifest {
+            hash: tar_hash.clone(),
+            kind: crate::LayerKind::Base,
+            parent: None,
+            object_refs: vec![tar_hash.clone()],
+            read_only: true,
+            tar_hash: tar_hash.clone(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata: false,
+        };
+        let layer_hash = LayerStore::new(layout.clone()).put(&layer).unwrap();
+        let meta = EnvMetadata {
+            env_id: env_id.into(),
+            short_id: env_id.into(),
+            name: None,
+            state: crate::EnvState::Built,
+            manifest_hash: manifest_hash.into(),
+            base_layer: layer_hash.clone().into(),
+            dependency_layers: vec![],
+            policy_layer: None,
+            created_at: "2025-01-01T00:00:00Z".to_owned(),
+            updated_at: "2025-01-01T00:00:00Z".to_owned(),
+            ref_count: 1,
+            checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
+        };
+        MetadataStore::new(layout.clone()).put(&meta).unwrap();
+        (layer_hash, tar_hash)
+    }
+
+    #[test]
+    fn verify_env_checks_only_referenced_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+
+        put_env(&layout, "env_a", b"tar a");
+        let (_, other_tar) = put_env(&layout, "env_b", b"tar b");
+        std::fs::write(layout.objects_dir().join(&other_tar), b"corrupted").unwrap();
+
+        let report = verify_env_integrity(&layout, "env_a").unwrap();
+        assert_eq!(report.metadata_passed, 1);
+        assert_eq!(report.layers_checked, 1);
+        assert_eq!(report.layers_passed, 1);
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.passed, 2);
+        assert!(report.failed.is_empty());
+
+        let report = verify_env_integrity(&layout, "env_b").unwrap();
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].hash, other_tar);
+    }
+
+    #[test]
+    fn verify_env_reports_missing_layer() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+
+        let (layer_hash, _) = put_env(&layout, "env_a", b"tar a");
+        std::fs::remove_file(layout.layers_dir().join(&layer_hash)).unwrap();
+
+        let report = verify_env_integrity(&layout, "env_a").unwrap();
+        assert_eq!(report.layers_checked, 1);
+        assert_eq!(report.layers_passed, 0);
+        assert_eq!(report.failed[0].hash, layer_hash);
+    }
+
+    #[test]
+    fn verify_env_unknown_env_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+
+        let report = verify_env_integrity(&layout, "missing").unwrap();
+        assert_eq!(report.metadata_passed, 0);
+        assert_eq!(report.failed.len(), 1);
+    }
 }
@@ -1,12 +1,40 @@
+use crate::config::StoreConfig;
+use crate::entry_attrs::{self, Hole, Xattr};
 use crate::layout::StoreLayout;
-use crate::{fsync_dir, StoreError};
+use crate::objects::ObjectStore;
+use crate::{write_atomic, StoreError};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::io::Write;
-use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
-use tempfile::NamedTempFile;
-use tracing::warn;
+use std::io::Read;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Root entry of a delta tar: a JSON array of the paths removed since the
+/// parent snapshot. It is written first, so removals are applied before the
+/// entries that follow it.
+pub const DELTA_WHITEOUTS: &str = ".karapace-whiteouts";
+
+/// PAX key marking a regular-file entry whose content is the object named
+/// by its value rather than the (empty) entry data. Written by
+/// [`pack_layer_with_objects`] and [`pack_layer_delta_with_objects`].
+pub const FILE_OBJECT_PAX_KEY: &str = "KARAPACE.object";
+
+/// Prefix of the PAX keys holding an entry's extended attributes, one per
+/// attribute, as GNU tar and bsdtar write them. POSIX ACLs and file
+/// capabilities are among them.
+pub const XATTR_PAX_PREFIX: &str = "SCHILY.xattr.";
+
+/// PAX key listing the holes of a sparse regular file as `offset,length`
+/// pairs. The entry data stays dense, so a reader that ignores the key
+/// still unpacks the right content, only without the holes.
+pub const HOLES_PAX_KEY: &str = "KARAPACE.holes";
+
+/// Regular file bytes packing reads ahead in parallel before appending
+/// them to the tar.
+const PACK_BATCH_BYTES: u64 = 256 * 1024 * 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum LayerKind {
@@ -27,15 +55,77 @@ pub struct LayerManifest {
     /// Empty for legacy (v1) synthetic layers.
     #[serde(default)]
     pub tar_hash: String,
+    /// Workspace a snapshot was taken from. `None` for the default workspace
+    /// and for non-snapshot layers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    /// Snapshot this one is delta-encoded against. For a delta,
+    /// `object_refs` lists the tars of the whole chain, full snapshot first
+    /// and `tar_hash` last, so the layer restores without its ancestors'
+    /// manifests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta_parent: Option<String>,
+    /// File objects the tars of this layer refer to by
+    /// [`FILE_OBJECT_PAX_KEY`], sorted. They are listed in `object_refs`
+    /// too, after the tars, so GC and transfers treat them like any object.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub file_objects: Vec<String>,
+    /// The tars of this layer record extended attributes or sparse-file
+    /// holes ([`XATTR_PAX_PREFIX`], [`HOLES_PAX_KEY`]). Versions before
+    /// this flag restore such a layer with the right content but without
+    /// them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pax_metadata: bool,
+}
+
+impl LayerManifest {
+    /// Tars to unpack, in order, to reconstruct this layer.
+    pub fn tar_chain(&self) -> Vec<String> {
+        if self.delta_parent.is_some() {
+            self.object_refs
+                .iter()
+                .filter(|hash| self.file_objects.binary_search(hash).is_err())
+                .cloned()
+                .collect()
+        } else {
+            vec![self.tar_hash.clone()]
+        }
+    }
+}
+
+/// A layer tar whose regular files were stored as objects of their own.
+#[derive(Debug, Clone)]
+pub struct PackedLayer {
+    pub tar: Vec<u8>,
+    /// Hashes of the file objects the tar refers to, sorted.
+    pub files: Vec<String>,
+    /// See [`has_pax_metadata`].
+    pub pax_metadata: bool,
+}
+
+/// Where packing puts regular file contents: in the tar, or in the object
+/// store with a reference in the tar.
+struct FileObjects<'a> {
+    store: &'a ObjectStore,
+    refs: BTreeSet<String>,
 }
 
 pub struct LayerStore {
     layout: StoreLayout,
+    /// Layers of the [shared store](crate::StoreConfig::shared_store), read
+    /// when this store lacks them.
+    shared: Option<StoreLayout>,
+    /// [`StoreConfig::paranoid_reads`].
+    paranoid: bool,
 }
 
 impl LayerStore {
     pub fn new(layout: StoreLayout) -> Self {
-        Self { layout }
+        Self {
+            shared: layout.shared_store().map(|shared| shared.layout),
+            paranoid: StoreConfig::load_or_default(&layout).paranoid_reads,
+            layout,
+        }
     }
 
     /// Compute the content hash that `put()` would use for this manifest,
@@ -52,29 +142,23 @@ impl LayerStore {
         let hash = blake3::hash(content.as_bytes()).to_hex().to_string();
         let dest = self.layout.layers_dir().join(&hash);
 
-        if dest.exists() {
+        if (dest.exists() || self.in_shared(&hash)) && (!self.paranoid || self.intact(&hash)) {
             return Ok(hash);
         }
 
-        let dir = self.layout.layers_dir();
-        let mut tmp = NamedTempFile::new_in(&dir)?;
-        tmp.write_all(content.as_bytes())?;
-        tmp.as_file().sync_all()?;
-        tmp.persist(&dest).map_err(|e| StoreError::Io(e.error))?;
-        fsync_dir(&dir)?;
+        write_atomic(&self.layout.layers_dir(), &dest, content.as_bytes())?;
 
         Ok(hash)
     }
 
     pub fn get(&self, hash: &str) -> Result<LayerManifest, StoreError> {
-        let path = self.layout.layers_dir().join(hash);
-        if !path.exists() {
-            return Err(StoreError::LayerNotFound(hash.to_owned()));
-        }
+        let path = self
+            .path(hash)
+            .ok_or_else(|| StoreError::LayerNotFound(hash.to_owned()))?;
         let content = fs::read_to_string(&path)?;
 
         // Verify integrity: content hash must match filename
-        let actual = blake3::hash(content.as_bytes());
+        let actual = crate::verify_hash(content.as_bytes());
         let actual_hex = actual.to_hex();
         if actual_hex.as_str() != hash {
             return Err(StoreError::IntegrityFailure {
@@ -88,8 +172,35 @@ impl LayerStore {
         Ok(manifest)
     }
 
+    /// File of layer `hash`, here or in the shared store.
+    pub fn path(&self, hash: &str) -> Option<PathBuf> {
+        let path = self.layout.layers_dir().join(hash);
+        if path.exists() {
+            return Some(path);
+        }
+        let shared = self.shared.as_ref()?.layers_dir().join(hash);
+        shared.exists().then_some(shared)
+    }
+
     pub fn exists(&self, hash: &str) -> bool {
-        self.layout.layers_dir().join(hash).exists()
+        self.path(hash).is_some()
+    }
+
+    /// Whether the stored copy of `hash` reads back intact.
+    fn intact(&self, hash: &str) -> bool {
+        match self.get(hash) {
+            Ok(_) => true,
+            Err(e) => {
+                tracing::warn!("writing damaged layer {hash} again: {e}");
+                false
+            }
+        }
+    }
+
+    fn in_shared(&self, hash: &str) -> bool {
+        self.shared
+            .as_ref()
+            .is_some_and(|shared| shared.layers_dir().join(hash).exists())
     }
 
     pub fn remove(&self, hash: &str) -> Result<(), StoreError> {
@@ -123,6 +234,7 @@ impl LayerStore {
 ///
 /// Phase 1 supports regular files, directories, and symlinks.
 /// Device nodes, sockets, FIFOs, and extended attributes are skipped with warnings.
+/// Files are read on all cores; entries are still appended one by one.
 ///
 /// Determinism guarantees:
 /// - Entries sorted lexicographically by relative path
@@ -130,52 +242,826 @@ impl LayerStore {
 /// - All ownership set to 0:0 (root:root)
 /// - Permissions preserved as-is from source
 pub fn pack_layer(source_dir: &Path) -> Result<Vec<u8>, StoreError> {
+    pack(source_dir, None)
+}
+
+/// Like [`pack_layer`], but each regular file's content is stored in
+/// `objects` and the tar only refers to it, so a file shared by several
+/// layers is stored once.
+pub fn pack_layer_with_objects(
+    source_dir: &Path,
+    objects: &ObjectStore,
+) -> Result<PackedLayer, StoreError> {
+    let mut files = FileObjects {
+        store: objects,
+        refs: BTreeSet::new(),
+    };
+    let tar = pack(source_dir, Some(&mut files))?;
+    Ok(PackedLayer {
+        pax_metadata: has_pax_metadata(&tar)?,
+        tar,
+        files: files.refs.into_iter().collect(),
+    })
+}
+
+fn pack(source_dir: &Path, files: Option<&mut FileObjects<'_>>) -> Result<Vec<u8>, StoreError> {
     let mut entries = collect_entries(source_dir, source_dir)?;
     entries.sort_by(|a, b| a.0.cmp(&b.0));
 
+    let links = hardlinks(&entries);
     let mut ar = tar::Builder::new(Vec::new());
     ar.follow_symlinks(false);
+    append_entries(&mut ar, &entries, &links, files)?;
+    let data = ar.into_inner()?;
+    Ok(data)
+}
 
-    for (rel_path, full_path) in &entries {
-        let ft = match full_path.symlink_metadata() {
-            Ok(m) => m.file_type(),
-            Err(e) => {
-                warn!("skipping {}: metadata error: {e}", rel_path);
-                continue;
+/// Pack only what changed in `source_dir` since the tree described by
+/// `parent`, plus a [`DELTA_WHITEOUTS`] entry for what was removed.
+///
+/// A path whose type changed is both removed and packed again. Output is
+/// deterministic in the same way as [`pack_layer`].
+pub fn pack_layer_delta(source_dir: &Path, parent: &LayerIndex) -> Result<Vec<u8>, StoreError> {
+    pack_delta(source_dir, parent, None)
+}
+
+/// [`pack_layer_delta`] with file contents stored in `objects`, as in
+/// [`pack_layer_with_objects`]. `files` only lists the objects of the
+/// changed files.
+pub fn pack_layer_delta_with_objects(
+    source_dir: &Path,
+    parent: &LayerIndex,
+    objects: &ObjectStore,
+) -> Result<PackedLayer, StoreError> {
+    let mut files = FileObjects {
+        store: objects,
+        refs: BTreeSet::new(),
+    };
+    let tar = pack_delta(source_dir, parent, Some(&mut files))?;
+    Ok(PackedLayer {
+        pax_metadata: has_pax_metadata(&tar)?,
+        tar,
+        files: files.refs.into_iter().collect(),
+    })
+}
+
+fn pack_delta(
+    source_dir: &Path,
+    parent: &LayerIndex,
+    files: Option<&mut FileObjects<'_>>,
+) -> Result<Vec<u8>, StoreError> {
+    let mut entries = collect_entries(source_dir, source_dir)?;
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let links = hardlinks(&entries);
+    // A link is fingerprinted after the first path of its inode, which
+    // sorts before it.
+    let fingerprints = entries
+        .par_iter()
+        .map(|(rel_path, full_path)| {
+            if links.contains_key(rel_path) {
+                return Ok(None);
+            }
+            EntryFingerprint::from_path(full_path)
+        })
+        .collect::<Result<Vec<_>, StoreError>>()?;
+    let mut changed = Vec::new();
+    let mut removed = Vec::new();
+    let mut current: BTreeMap<String, EntryFingerprint> = BTreeMap::new();
+    for ((rel_path, full_path), fingerprint) in entries.into_iter().zip(fingerprints) {
+        let fingerprint = match links.get(&rel_path) {
+            Some(first) => current.get(first).map(|target| target.linked(first)),
+            None => fingerprint,
+        };
+        let Some(fingerprint) = fingerprint else {
+            continue;
+        };
+        match parent.entries.get(&rel_path) {
+            Some(old) if *old == fingerprint => {}
+            Some(old) => {
+                if old.kind != fingerprint.kind {
+                    removed.push(rel_path.clone());
+                }
+                changed.push((rel_path.clone(), full_path));
             }
+            None => changed.push((rel_path.clone(), full_path)),
+        }
+        current.insert(rel_path, fingerprint);
+    }
+    removed.extend(
+        parent
+            .entries
+            .keys()
+            .filter(|path| !current.contains_key(*path))
+            .cloned(),
+    );
+    removed.sort();
+    // Removing a directory removes everything below it.
+    let mut whiteouts: Vec<String> = Vec::new();
+    for path in removed {
+        if !whiteouts.iter().any(|dir| is_below(&path, dir)) {
+            whiteouts.push(path);
+        }
+    }
+
+    let mut ar = tar::Builder::new(Vec::new());
+    ar.follow_symlinks(false);
+    if !whiteouts.is_empty() {
+        let list = serde_json::to_vec(&whiteouts)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mode(0o644);
+        header.set_size(list.len() as u64);
+        header.set_cksum();
+        ar.append_data(&mut header, DELTA_WHITEOUTS, list.as_slice())?;
+    }
+    append_entries(&mut ar, &changed, &links, files)?;
+    let data = ar.into_inner()?;
+    Ok(data)
+}
+
+/// Append `entries` in order, those in `links` as hard links. Files are
+/// read, and stored as objects when `files` is given, in parallel a batch
+/// at a time; only appending to the tar is sequential, so the output does
+/// not depend on the thread count.
+fn append_entries(
+    ar: &mut tar::Builder<Vec<u8>>,
+    entries: &[(String, PathBuf)],
+    links: &HashMap<String, String>,
+    mut files: Option<&mut FileObjects<'_>>,
+) -> Result<(), StoreError> {
+    let store = files.as_deref().map(|files| files.store);
+    for batch in pack_batches(entries) {
+        let prepared = batch
+            .par_iter()
+            .map(|(rel_path, full_path)| match links.get(rel_path) {
+                Some(first) => prepare_link(full_path, first),
+                None => prepare_entry(rel_path, full_path, store),
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        for ((rel_path, _), entry) in batch.iter().zip(prepared) {
+            if let Some(entry) = entry {
+                append_prepared(ar, rel_path, entry, files.as_deref_mut())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Regular files of `entries` that are another path of an inode already
+/// listed, mapped to the first path of that inode.
+fn hardlinks(entries: &[(String, PathBuf)]) -> HashMap<String, String> {
+    let inodes: Vec<_> = entries
+        .par_iter()
+        .map(|(_, full_path)| {
+            full_path
+                .symlink_metadata()
+                .ok()
+                .filter(|m| m.is_file() && m.nlink() > 1)
+                .map(|m| (m.dev(), m.ino()))
+        })
+        .collect();
+    let mut first_paths = HashMap::new();
+    let mut links = HashMap::new();
+    for ((rel_path, _), inode) in entries.iter().zip(inodes) {
+        let Some(inode) = inode else {
+            continue;
         };
+        match first_paths.get(&inode) {
+            Some(first) => {
+                links.insert(rel_path.clone(), String::clone(first));
+            }
+            None => {
+                first_paths.insert(inode, rel_path.clone());
+            }
+        }
+    }
+    links
+}
+
+/// `entries` split into runs whose regular files add up to at most
+/// [`PACK_BATCH_BYTES`], bounding what parallel packing holds in memory.
+/// A larger file is a batch of its own.
+fn pack_batches(entries: &[(String, PathBuf)]) -> Vec<&[(String, PathBuf)]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut bytes = 0;
+    for (i, (_, full_path)) in entries.iter().enumerate() {
+        let size = full_path
+            .symlink_metadata()
+            .map_or(0, |m| if m.is_file() { m.len() } else { 0 });
+        if i > start && bytes + size > PACK_BATCH_BYTES {
+            batches.push(&entries[start..i]);
+            start = i;
+            bytes = 0;
+        }
+        bytes += size;
+    }
+    if start < entries.len() {
+        batches.push(&entries[start..]);
+    }
+    batches
+}
+
+/// An entry read, and for a file object stored, ready to be appended.
+struct PreparedEntry {
+    header: tar::Header,
+    /// PAX records for the entry's extended attributes and holes.
+    pax: Vec<(String, Vec<u8>)>,
+    body: EntryBody,
+}
+
+enum EntryBody {
+    File(Vec<u8>),
+    FileObject(String),
+    Dir,
+    Symlink(PathBuf),
+    /// Hard link to the path of the same inode packed first.
+    Link(String),
+    /// Device or FIFO node; the header holds its type and device number.
+    Node,
+}
+
+/// `full_path` ready to append, or `None` if it is skipped.
+fn prepare_entry(
+    rel_path: &str,
+    full_path: &Path,
+    store: Option<&ObjectStore>,
+) -> Result<Option<PreparedEntry>, StoreError> {
+    let meta = match full_path.symlink_metadata() {
+        Ok(m) => m,
+        Err(e) => {
+            warn!("skipping {}: metadata error: {e}", rel_path);
+            return Ok(None);
+        }
+    };
+    let ft = meta.file_type();
+    let mut pax: Vec<_> = entry_attrs::read_xattrs(full_path)?
+        .into_iter()
+        .map(|(name, value)| (format!("{XATTR_PAX_PREFIX}{name}"), value))
+        .collect();
+
+    let (entry_type, body) = if ft.is_file() {
+        let mut file = fs::File::open(full_path)?;
+        let holes = entry_attrs::find_holes(&file)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let holes = entry_attrs::zero_holes(holes, &data);
+        if !holes.is_empty() {
+            pax.push((
+                HOLES_PAX_KEY.to_owned(),
+                entry_attrs::encode_holes(&holes).into_bytes(),
+            ));
+        }
+        let body = match store {
+            Some(store) => EntryBody::FileObject(store.put(&data)?),
+            None => EntryBody::File(data),
+        };
+        (tar::EntryType::Regular, body)
+    } else if ft.is_dir() {
+        (tar::EntryType::Directory, EntryBody::Dir)
+    } else if ft.is_symlink() {
+        let target = fs::read_link(full_path)?;
+        (tar::EntryType::Symlink, EntryBody::Symlink(target))
+    } else if let Some(entry_type) = node_type(ft) {
+        (entry_type, EntryBody::Node)
+    } else {
+        warn!("skipping unsupported file type: {rel_path}");
+        return Ok(None);
+    };
+    let mut header = make_header(full_path, entry_type)?;
+    if matches!(body, EntryBody::Node) {
+        header.set_device_major(libc::major(meta.rdev()))?;
+        header.set_device_minor(libc::minor(meta.rdev()))?;
+    }
+    Ok(Some(PreparedEntry { header, pax, body }))
+}
+
+/// A hard link to `first`, the path its inode was packed under. The
+/// inode's attributes are packed with that path.
+fn prepare_link(full_path: &Path, first: &str) -> Result<Option<PreparedEntry>, StoreError> {
+    Ok(Some(PreparedEntry {
+        header: make_header(full_path, tar::EntryType::Link)?,
+        pax: Vec::new(),
+        body: EntryBody::Link(first.to_owned()),
+    }))
+}
+
+/// The tar entry type of a device or FIFO node, `None` for other types.
+fn node_type(ft: fs::FileType) -> Option<tar::EntryType> {
+    if ft.is_char_device() {
+        Some(tar::EntryType::Char)
+    } else if ft.is_block_device() {
+        Some(tar::EntryType::Block)
+    } else if ft.is_fifo() {
+        Some(tar::EntryType::Fifo)
+    } else {
+        None
+    }
+}
 
-        if ft.is_file() {
-            append_file(&mut ar, rel_path, full_path)?;
+fn is_below(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Hardlink,
+    Node,
+}
+
+/// What a delta compares: entry type, permission bits, a digest of the
+/// file content, link target or device number, and a digest of the
+/// extended attributes.
+///
+/// A hard link's digest covers its first path and that path's digest, so
+/// rewriting the inode, which unpacks as a new file, also repacks its
+/// links.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EntryFingerprint {
+    kind: EntryKind,
+    mode: u32,
+    digest: [u8; 32],
+    xattrs: [u8; 32],
+}
+
+impl EntryFingerprint {
+    fn from_path(path: &Path) -> Result<Option<Self>, StoreError> {
+        let meta = path.symlink_metadata()?;
+        let ft = meta.file_type();
+        let (kind, digest) = if ft.is_file() {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_reader(fs::File::open(path)?)?;
+            (EntryKind::File, *hasher.finalize().as_bytes())
         } else if ft.is_dir() {
-            append_dir(&mut ar, rel_path, full_path)?;
+            (EntryKind::Dir, [0; 32])
         } else if ft.is_symlink() {
-            append_symlink(&mut ar, rel_path, full_path)?;
+            let target = fs::read_link(path)?;
+            (
+                EntryKind::Symlink,
+                *blake3::hash(target.as_os_str().as_encoded_bytes()).as_bytes(),
+            )
+        } else if let Some(entry_type) = node_type(ft) {
+            let device = (libc::major(meta.rdev()), libc::minor(meta.rdev()));
+            (EntryKind::Node, node_digest(entry_type, device))
         } else {
-            warn!("skipping unsupported file type: {rel_path}");
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            kind,
+            mode: meta.permissions().mode() & 0o7777,
+            digest,
+            xattrs: entry_attrs::xattrs_digest(&entry_attrs::read_xattrs(path)?),
+        }))
+    }
+
+    /// Fingerprint of a hard link to `first`, whose fingerprint this is.
+    fn linked(&self, first: &str) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(first.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(&self.digest);
+        Self {
+            kind: EntryKind::Hardlink,
+            digest: *hasher.finalize().as_bytes(),
+            ..self.clone()
         }
     }
+}
 
-    let data = ar.into_inner()?;
-    Ok(data)
+fn node_digest(entry_type: tar::EntryType, (major, minor): (u32, u32)) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[entry_type.as_byte()]);
+    hasher.update(&major.to_le_bytes());
+    hasher.update(&minor.to_le_bytes());
+    *hasher.finalize().as_bytes()
 }
 
-/// Extract a tar archive to a target directory.
-pub fn unpack_layer(tar_data: &[u8], target_dir: &Path) -> Result<(), StoreError> {
-    fs::create_dir_all(target_dir)?;
+/// The tree a chain of layer tars unpacks to, as fingerprints by path.
+/// Input to [`pack_layer_delta`].
+#[derive(Debug, Default)]
+pub struct LayerIndex {
+    entries: BTreeMap<String, EntryFingerprint>,
+}
+
+impl LayerIndex {
+    /// Index the tree `tars` unpack to when applied in order.
+    pub fn from_tars(tars: &[Vec<u8>]) -> Result<Self, StoreError> {
+        let mut index = Self::default();
+        for tar_data in tars {
+            let mut ar = tar::Archive::new(tar_data.as_slice());
+            for entry in ar.entries()? {
+                let mut entry = entry?;
+                let path = entry_path(&entry)?;
+                if path == DELTA_WHITEOUTS {
+                    for removed in read_whiteouts(&mut entry)? {
+                        index.entries.retain(|p, _| !is_below(p, &removed));
+                    }
+                    continue;
+                }
+                let extensions = EntryExtensions::read(&mut entry)?;
+                let header = entry.header();
+                let mode = header.mode()? & 0o7777;
+                let (kind, digest) = match header.entry_type() {
+                    tar::EntryType::Regular => {
+                        let digest = if let Some(hash) = extensions.object {
+                            hash
+                        } else {
+                            let mut data = Vec::new();
+                            entry.read_to_end(&mut data)?;
+                            blake3::hash(&data)
+                        };
+                        (EntryKind::File, *digest.as_bytes())
+                    }
+                    tar::EntryType::Directory => (EntryKind::Dir, [0; 32]),
+                    tar::EntryType::Symlink => {
+                        let target = entry.link_name()?.unwrap_or_default();
+                        (
+                            EntryKind::Symlink,
+                            *blake3::hash(target.as_os_str().as_encoded_bytes()).as_bytes(),
+                        )
+                    }
+                    tar::EntryType::Link => {
+                        let first = link_target(&entry)?;
+                        if let Some(linked) = index.entries.get(&first).map(|f| f.linked(&first)) {
+                            index.entries.insert(path, linked);
+                        }
+                        continue;
+                    }
+                    entry_type @ (tar::EntryType::Char
+                    | tar::EntryType::Block
+                    | tar::EntryType::Fifo) => {
+                        let device = (
+                            header.device_major()?.unwrap_or(0),
+                            header.device_minor()?.unwrap_or(0),
+                        );
+                        (EntryKind::Node, node_digest(entry_type, device))
+                    }
+                    _ => continue,
+                };
+                let xattrs = entry_attrs::xattrs_digest(&extensions.xattrs);
+                index.entries.insert(
+                    path,
+                    EntryFingerprint {
+                        kind,
+                        mode,
+                        digest,
+                        xattrs,
+                    },
+                );
+            }
+        }
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Path a hard link entry links to, relative to the layer root.
+fn link_target<R: Read>(entry: &tar::Entry<'_, R>) -> Result<String, StoreError> {
+    let target = entry.link_name()?.unwrap_or_default();
+    let target = target.to_string_lossy();
+    if !is_plain_relative(&target) {
+        return Err(StoreError::Io(std::io::Error::other(format!(
+            "invalid hard link target in layer: {target:?}"
+        ))));
+    }
+    Ok(target.into_owned())
+}
+
+/// Entry path relative to the layer root, without a trailing `/`.
+fn entry_path<R: Read>(entry: &tar::Entry<'_, R>) -> Result<String, StoreError> {
+    Ok(entry
+        .path()?
+        .to_string_lossy()
+        .trim_end_matches('/')
+        .to_owned())
+}
+
+/// What the PAX header of an entry records.
+#[derive(Debug, Default)]
+struct EntryExtensions {
+    /// Object a [`FILE_OBJECT_PAX_KEY`] entry refers to.
+    object: Option<blake3::Hash>,
+    xattrs: Vec<Xattr>,
+    holes: Vec<Hole>,
+}
+
+impl EntryExtensions {
+    fn read<R: Read>(entry: &mut tar::Entry<'_, R>) -> Result<Self, StoreError> {
+        let mut extensions = Self::default();
+        let mut holes = None;
+        let Some(records) = entry.pax_extensions()? else {
+            return Ok(extensions);
+        };
+        for record in records {
+            let record = record?;
+            let key = record.key_bytes();
+            if key == FILE_OBJECT_PAX_KEY.as_bytes() {
+                let hash = blake3::Hash::from_hex(record.value_bytes()).map_err(|e| {
+                    StoreError::Io(std::io::Error::other(format!(
+                        "invalid file object reference in layer: {e}"
+                    )))
+                })?;
+                extensions.object = Some(hash);
+            } else if key == HOLES_PAX_KEY.as_bytes() {
+                holes = Some(record.value_bytes().to_vec());
+            } else if let Some(name) = key.strip_prefix(XATTR_PAX_PREFIX.as_bytes()) {
+                let name = String::from_utf8_lossy(name).into_owned();
+                extensions
+                    .xattrs
+                    .push((name, record.value_bytes().to_vec()));
+            }
+        }
+        if let Some(holes) = holes {
+            // A file object's size is only known once it is materialized.
+            let size = if extensions.object.is_some() {
+                u64::MAX
+            } else {
+                entry.size()
+            };
+            extensions.holes = entry_attrs::decode_holes(&holes, size)?;
+        }
+        extensions.xattrs.sort();
+        Ok(extensions)
+    }
+
+    fn has_pax_metadata(&self) -> bool {
+        !self.xattrs.is_empty() || !self.holes.is_empty()
+    }
+}
+
+/// Whether `tar_data` records extended attributes or sparse-file holes,
+/// so a layer with it in its tar chain sets
+/// [`LayerManifest::pax_metadata`].
+pub fn has_pax_metadata(tar_data: &[u8]) -> Result<bool, StoreError> {
     let mut ar = tar::Archive::new(tar_data);
-    ar.set_preserve_permissions(true);
-    ar.set_preserve_mtime(false);
-    ar.set_unpack_xattrs(false);
-    ar.unpack(target_dir)?;
+    for entry in ar.entries()? {
+        if EntryExtensions::read(&mut entry?)?.has_pax_metadata() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Extended attributes that could not be set and device nodes that could
+/// not be created while unpacking, reported once at the end.
+#[derive(Default)]
+struct Unrestored {
+    xattrs: usize,
+    first_xattr_error: Option<std::io::Error>,
+    nodes: usize,
+    first_node_error: Option<std::io::Error>,
+}
+
+impl Unrestored {
+    fn apply_xattrs(&mut self, path: &Path, xattrs: &[Xattr]) {
+        if let Err(e) = entry_attrs::apply_xattrs(path, xattrs) {
+            debug!(
+                "could not set extended attributes of {}: {e}",
+                path.display()
+            );
+            self.xattrs += 1;
+            self.first_xattr_error.get_or_insert(e);
+        }
+    }
+
+    fn node(&mut self, path: &str, e: std::io::Error) {
+        debug!("could not create node {path}: {e}");
+        self.nodes += 1;
+        self.first_node_error.get_or_insert(e);
+    }
+
+    fn report(self) {
+        if let Some(e) = self.first_xattr_error {
+            warn!(
+                "extended attributes of {} entries not restored (first: {e})",
+                self.xattrs
+            );
+        }
+        if let Some(e) = self.first_node_error {
+            warn!(
+                "{} device or FIFO nodes not restored (first: {e})",
+                self.nodes
+            );
+        }
+    }
+}
+
+/// Whether `path` is non-empty and relative with no `..` or `.` parts.
+fn is_plain_relative(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+fn read_whiteouts<R: Read>(entry: &mut tar::Entry<'_, R>) -> Result<Vec<String>, StoreError> {
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data)?;
+    let paths: Vec<String> = serde_json::from_slice(&data)?;
+    for path in &paths {
+        if !is_plain_relative(path) {
+            return Err(StoreError::Io(std::io::Error::other(format!(
+                "invalid whiteout path in delta layer: {path:?}"
+            ))));
+        }
+    }
+    Ok(paths)
+}
+
+/// Extract a chain of layer tars into `target_dir`, in order. A
+/// [`DELTA_WHITEOUTS`] entry removes its paths before the rest of that tar
+/// is extracted. Extended attributes are restored where the process may
+/// set them, and sparse files get their holes back.
+///
+/// Tars packed with file objects need [`unpack_layers_with_objects`].
+pub fn unpack_layers(tars: &[Vec<u8>], target_dir: &Path) -> Result<(), StoreError> {
+    unpack_chain(tars, target_dir, None)
+}
+
+/// [`unpack_layers`] for tars whose files may be stored in `objects`. Each
+/// such file is written with [`ObjectStore::materialize`], so it shares
+/// storage with the object where the filesystem supports reflinks.
+pub fn unpack_layers_with_objects(
+    tars: &[Vec<u8>],
+    target_dir: &Path,
+    objects: &ObjectStore,
+) -> Result<(), StoreError> {
+    unpack_chain(tars, target_dir, Some(objects))
+}
+
+fn unpack_chain<T: AsRef<[u8]>>(
+    tars: &[T],
+    target_dir: &Path,
+    objects: Option<&ObjectStore>,
+) -> Result<(), StoreError> {
+    fs::create_dir_all(target_dir)?;
+    // Directory permissions and attributes are applied last, so a read-only
+    // directory does not block extracting into it.
+    let mut dirs = BTreeMap::new();
+    let mut unrestored = Unrestored::default();
+    for tar_data in tars {
+        let mut ar = tar::Archive::new(tar_data.as_ref());
+        ar.set_preserve_permissions(true);
+        ar.set_preserve_mtime(false);
+        ar.set_unpack_xattrs(false);
+        for entry in ar.entries()? {
+            let mut entry = entry?;
+            let path = entry_path(&entry)?;
+            if path == DELTA_WHITEOUTS {
+                for removed in read_whiteouts(&mut entry)? {
+                    remove_path(&target_dir.join(&removed))?;
+                    dirs.retain(|p: &String, _| !is_below(p, &removed));
+                }
+                continue;
+            }
+            let extensions = EntryExtensions::read(&mut entry)?;
+            let entry_type = entry.header().entry_type();
+            if entry_type == tar::EntryType::Directory {
+                fs::create_dir_all(target_dir.join(&path))?;
+                dirs.insert(path, (entry.header().mode()?, extensions.xattrs));
+                continue;
+            }
+            let dest = unpack_entry(
+                &mut entry,
+                &path,
+                &extensions,
+                target_dir,
+                objects,
+                &mut unrestored,
+            )?;
+            if let Some(dest) = dest {
+                // After the content and mode, which would clear a capability.
+                unrestored.apply_xattrs(&dest, &extensions.xattrs);
+            }
+        }
+    }
+    for (path, (mode, xattrs)) in dirs.iter().rev() {
+        let dir = target_dir.join(path);
+        if dir.is_dir() {
+            fs::set_permissions(&dir, fs::Permissions::from_mode(mode & 0o7777))?;
+            unrestored.apply_xattrs(&dir, xattrs);
+        }
+    }
+    unrestored.report();
     Ok(())
 }
 
+/// Write the non-directory entry `path` under `target_dir`. Returns where
+/// its extended attributes go, or `None` for a hard link, whose inode has
+/// them already, and for what was not written.
+fn unpack_entry<R: Read>(
+    entry: &mut tar::Entry<'_, R>,
+    path: &str,
+    extensions: &EntryExtensions,
+    target_dir: &Path,
+    objects: Option<&ObjectStore>,
+    unrestored: &mut Unrestored,
+) -> Result<Option<PathBuf>, StoreError> {
+    let entry_type = entry.header().entry_type();
+    let mode = entry.header().mode()? & 0o7777;
+    let file_type = match entry_type {
+        tar::EntryType::Link => {
+            let src = file_dest(target_dir, &link_target(entry)?)?;
+            let dest = file_dest(target_dir, path)?;
+            remove_path(&dest)?;
+            fs::hard_link(src, dest)?;
+            return Ok(None);
+        }
+        tar::EntryType::Char => libc::S_IFCHR,
+        tar::EntryType::Block => libc::S_IFBLK,
+        tar::EntryType::Fifo => libc::S_IFIFO,
+        tar::EntryType::Regular if extensions.object.is_some() || !extensions.holes.is_empty() => {
+            let dest = file_dest(target_dir, path)?;
+            remove_path(&dest)?;
+            if let Some(hash) = extensions.object {
+                let Some(objects) = objects else {
+                    return Err(StoreError::Io(std::io::Error::other(format!(
+                        "{path} is stored as a file object; the layer needs an object store to unpack"
+                    ))));
+                };
+                objects.materialize(&hash.to_hex(), &dest)?;
+                if let Err(e) = entry_attrs::punch_holes(&dest, &extensions.holes) {
+                    debug!("could not punch holes in {path}: {e}");
+                }
+            } else {
+                let size = entry.size();
+                entry_attrs::write_sparse(&mut *entry, &dest, size, &extensions.holes)?;
+            }
+            fs::set_permissions(&dest, fs::Permissions::from_mode(mode))?;
+            return Ok(Some(dest));
+        }
+        _ => {
+            return Ok(entry.unpack_in(target_dir)?.then(|| target_dir.join(path)));
+        }
+    };
+    let dest = file_dest(target_dir, path)?;
+    remove_path(&dest)?;
+    let device = (
+        entry.header().device_major()?.unwrap_or(0),
+        entry.header().device_minor()?.unwrap_or(0),
+    );
+    if let Err(e) = entry_attrs::make_node(&dest, file_type, mode, device) {
+        unrestored.node(path, e);
+        return Ok(None);
+    }
+    // mknod applies the umask.
+    fs::set_permissions(&dest, fs::Permissions::from_mode(mode))?;
+    Ok(Some(dest))
+}
+
+/// Where to write the file entry `path` under `target_dir`, with its parent
+/// directories created. Refuses paths that leave `target_dir`, directly or
+/// through a symlinked parent.
+fn file_dest(target_dir: &Path, path: &str) -> Result<PathBuf, StoreError> {
+    let escapes = || {
+        StoreError::Io(std::io::Error::other(format!(
+            "invalid file path in layer: {path:?}"
+        )))
+    };
+    if !is_plain_relative(path) {
+        return Err(escapes());
+    }
+    let dest = target_dir.join(path);
+    let parent = dest.parent().ok_or_else(escapes)?;
+    fs::create_dir_all(parent)?;
+    if !fs::canonicalize(parent)?.starts_with(fs::canonicalize(target_dir)?) {
+        return Err(escapes());
+    }
+    Ok(dest)
+}
+
+fn remove_path(path: &Path) -> Result<(), StoreError> {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+/// Extract a tar archive to a target directory.
+pub fn unpack_layer(tar_data: &[u8], target_dir: &Path) -> Result<(), StoreError> {
+    unpack_chain(&[tar_data], target_dir, None)
+}
+
 /// Recursively collect (relative_path, full_path) pairs from a directory tree.
-fn collect_entries(
-    root: &Path,
-    current: &Path,
-) -> Result<Vec<(String, std::path::PathBuf)>, StoreError> {
+fn collect_entries(root: &Path, current: &Path) -> Result<Vec<(String, PathBuf)>, StoreError> {
     let mut result = Vec::new();
     if !current.exists() {
         return Ok(result);
@@ -211,46 +1097,69 @@ fn make_header(full_path: &Path, entry_type: tar::EntryType) -> Result<tar::Head
     Ok(header)
 }
 
-fn append_file(
-    ar: &mut tar::Builder<Vec<u8>>,
-    rel_path: &str,
-    full_path: &Path,
-) -> Result<(), StoreError> {
-    let data = fs::read(full_path)?;
-    let mut header = make_header(full_path, tar::EntryType::Regular)?;
-    header.set_size(data.len() as u64);
-    header.set_cksum();
-    ar.append_data(&mut header, rel_path, data.as_slice())?;
-    Ok(())
-}
-
-fn append_dir(
+fn append_prepared(
     ar: &mut tar::Builder<Vec<u8>>,
     rel_path: &str,
-    full_path: &Path,
-) -> Result<(), StoreError> {
-    let mut header = make_header(full_path, tar::EntryType::Directory)?;
-    header.set_size(0);
-    header.set_cksum();
-    let path = if rel_path.ends_with('/') {
-        rel_path.to_owned()
-    } else {
-        format!("{rel_path}/")
-    };
-    ar.append_data(&mut header, &path, &[] as &[u8])?;
-    Ok(())
-}
-
-fn append_symlink(
-    ar: &mut tar::Builder<Vec<u8>>,
-    rel_path: &str,
-    full_path: &Path,
+    entry: PreparedEntry,
+    files: Option<&mut FileObjects<'_>>,
 ) -> Result<(), StoreError> {
-    let target = fs::read_link(full_path)?;
-    let mut header = make_header(full_path, tar::EntryType::Symlink)?;
-    header.set_size(0);
-    header.set_cksum();
-    ar.append_link(&mut header, rel_path, &target)?;
+    let PreparedEntry {
+        mut header,
+        mut pax,
+        body,
+    } = entry;
+    if let EntryBody::FileObject(hash) = &body {
+        pax.insert(
+            0,
+            (FILE_OBJECT_PAX_KEY.to_owned(), hash.as_bytes().to_vec()),
+        );
+    }
+    if !pax.is_empty() {
+        ar.append_pax_extensions(
+            pax.iter()
+                .map(|(key, value)| (key.as_str(), value.as_slice())),
+        )?;
+    }
+    match body {
+        EntryBody::File(data) => {
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            ar.append_data(&mut header, rel_path, data.as_slice())?;
+        }
+        EntryBody::FileObject(hash) => {
+            if let Some(files) = files {
+                files.refs.insert(hash);
+            }
+            header.set_size(0);
+            header.set_cksum();
+            ar.append_data(&mut header, rel_path, &[] as &[u8])?;
+        }
+        EntryBody::Dir => {
+            header.set_size(0);
+            header.set_cksum();
+            let path = if rel_path.ends_with('/') {
+                rel_path.to_owned()
+            } else {
+                format!("{rel_path}/")
+            };
+            ar.append_data(&mut header, &path, &[] as &[u8])?;
+        }
+        EntryBody::Symlink(target) => {
+            header.set_size(0);
+            header.set_cksum();
+            ar.append_link(&mut header, rel_path, &target)?;
+        }
+        EntryBody::Link(first) => {
+            header.set_size(0);
+            header.set_cksum();
+            ar.append_link(&mut header, rel_path, &first)?;
+        }
+        EntryBody::Node => {
+            header.set_size(0);
+            header.set_cksum();
+            ar.append_data(&mut header, rel_path, &[] as &[u8])?;
+        }
+    }
     Ok(())
 }
 
@@ -273,6 +1182,10 @@ mod tests {
             object_refs: vec!["obj1".to_owned(), "obj2".to_owned()],
             read_only: true,
             tar_hash: String::new(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata: false,
         }
     }
 
@@ -293,6 +1206,25 @@ mod tests {
         store.put(&layer).unwrap();
     }
 
+    #[test]
+    fn paranoid_put_writes_a_damaged_layer_again() {
+        let (dir, _) = test_layer_store();
+        let layout = StoreLayout::new(dir.path());
+        StoreConfig {
+            paranoid_reads: true,
+            ..StoreConfig::default()
+        }
+        .save(&layout)
+        .unwrap();
+        let store = LayerStore::new(layout.clone());
+        let hash = store.put(&sample_layer()).unwrap();
+        fs::write(layout.layers_dir().join(&hash), "{}").unwrap();
+        assert!(store.get(&hash).is_err());
+
+        store.put(&sample_layer()).unwrap();
+        assert_eq!(store.get(&hash).unwrap(), sample_layer());
+    }
+
     #[test]
     fn get_nonexistent_fails() {
         let (_dir, store) = test_layer_store();
@@ -385,6 +1317,51 @@ mod tests {
         assert_eq!(tar1, tar2, "pack_layer must be deterministic");
     }
 
+    #[test]
+    fn parallel_packing_appends_in_sorted_order() {
+        let src = tempfile::tempdir().unwrap();
+        for dir in ["b", "a", "c/d"] {
+            fs::create_dir_all(src.path().join(dir)).unwrap();
+            for i in (0..50).rev() {
+                fs::write(src.path().join(dir).join(format!("f{i:02}")), [i]).unwrap();
+            }
+        }
+        let tar = pack_layer(src.path()).unwrap();
+        let mut archive = tar::Archive::new(tar.as_slice());
+        let paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!(paths.len(), 154);
+        assert_eq!(paths, sorted);
+
+        let objects = ObjectStore::new(StoreLayout::new(src.path().join("store")));
+        fs::create_dir_all(src.path().join("store/store/objects")).unwrap();
+        let first = pack_layer_with_objects(&src.path().join("a"), &objects).unwrap();
+        let second = pack_layer_with_objects(&src.path().join("a"), &objects).unwrap();
+        assert_eq!(first.tar, second.tar);
+        assert_eq!(first.files.len(), 50);
+    }
+
+    #[test]
+    fn pack_batches_bound_file_bytes() {
+        let src = tempfile::tempdir().unwrap();
+        let small = src.path().join("small");
+        fs::write(&small, b"x").unwrap();
+        let big = src.path().join("big");
+        fs::File::create(&big)
+            .unwrap()
+            .set_len(PACK_BATCH_BYTES)
+            .unwrap();
+        let entry = |path: &Path| (String::new(), path.to_path_buf());
+        let entries = [entry(&small), entry(&big), entry(&small), entry(src.path())];
+        let sizes: Vec<usize> = pack_batches(&entries).iter().map(|b| b.len()).collect();
+        assert_eq!(sizes, [1, 1, 2]);
+    }
+
     #[test]
     fn pack_deterministic_hash() {
         let src = tempfile::tempdir().unwrap();
@@ -448,7 +1425,7 @@ mod tests {
         let store_dir = tempfile::tempdir().unwrap();
         let layout = StoreLayout::new(store_dir.path());
         layout.initialize().unwrap();
-        let obj_store = crate::ObjectStore::new(layout.clone());
+        let obj_store = ObjectStore::new(layout.clone());
         let stored_hash = obj_store.put(&tar_data).unwrap();
 
         // Verify stored hash matches computed hash
@@ -502,6 +1479,10 @@ mod tests {
             object_refs: vec![tar_hash.clone()],
             read_only: true,
             tar_hash: tar_hash.clone(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata: false,
         };
 
         // Verify tar_hash in manifest matches actual content hash
@@ -523,7 +1504,7 @@ mod tests {
         let store_dir = tempfile::tempdir().unwrap();
         let layout = StoreLayout::new(store_dir.path());
         layout.initialize().unwrap();
-        let obj_store = crate::ObjectStore::new(layout);
+        let obj_store = ObjectStore::new(layout);
 
         // Write the full data first to get the correct hash
         let correct_hash = obj_store.put(&tar_data).unwrap();
@@ -573,4 +1554,391 @@ mod tests {
             );
         }
     }
+
+    // --- delta layers ---
+
+    /// Apply a full tar and deltas in order and check the result packs to
+    /// the same tar as `expected`.
+    fn assert_chain_matches(tars: &[Vec<u8>], expected: &Path) {
+        let dst = tempfile::tempdir().unwrap();
+        unpack_layers(tars, dst.path()).unwrap();
+        assert_eq!(
+            pack_layer(dst.path()).unwrap(),
+            pack_layer(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn delta_roundtrip_applies_changes_and_removals() {
+        let src = tempfile::tempdir().unwrap();
+        create_fixture_dir(src.path());
+        let full = pack_layer(src.path()).unwrap();
+
+        fs::write(src.path().join("hello.txt"), "changed").unwrap();
+        fs::remove_file(src.path().join("binary.bin")).unwrap();
+        fs::remove_dir_all(src.path().join("subdir")).unwrap();
+        fs::create_dir_all(src.path().join("new/deeper")).unwrap();
+        fs::write(src.path().join("new/deeper/file"), "new").unwrap();
+        // Symlink replaced by a directory of the same name.
+        fs::remove_file(src.path().join("link_to_hello")).unwrap();
+        fs::create_dir(src.path().join("link_to_hello")).unwrap();
+        fs::set_permissions(
+            src.path().join("empty_dir"),
+            fs::Permissions::from_mode(0o700),
+        )
+        .unwrap();
+
+        let index = LayerIndex::from_tars(std::slice::from_ref(&full)).unwrap();
+        let delta = pack_layer_delta(src.path(), &index).unwrap();
+        assert_chain_matches(&[full, delta], src.path());
+    }
+
+    #[test]
+    fn delta_packs_only_changed_entries() {
+        let src = tempfile::tempdir().unwrap();
+        create_fixture_dir(src.path());
+        fs::write(src.path().join("large.bin"), vec![9u8; 256 * 1024]).unwrap();
+        let full = pack_layer(src.path()).unwrap();
+        let index = LayerIndex::from_tars(std::slice::from_ref(&full)).unwrap();
+        assert_eq!(index.len(), 7);
+
+        let unchanged = pack_layer_delta(src.path(), &index).unwrap();
+        let mut ar = tar::Archive::new(unchanged.as_slice());
+        assert_eq!(ar.entries().unwrap().count(), 0);
+
+        fs::write(src.path().join("hello.txt"), "changed").unwrap();
+        let delta = pack_layer_delta(src.path(), &index).unwrap();
+        let mut ar = tar::Archive::new(delta.as_slice());
+        let paths: Vec<String> = ar
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(paths, ["hello.txt"]);
+        assert!(delta.len() < full.len() / 10);
+    }
+
+    #[test]
+    fn delta_packs_entries_whose_xattrs_changed() {
+        let src = tempfile::tempdir().unwrap();
+        create_fixture_dir(src.path());
+        let full = pack_layer(src.path()).unwrap();
+        let index = LayerIndex::from_tars(std::slice::from_ref(&full)).unwrap();
+
+        xattr::set(src.path().join("hello.txt"), "user.karapace", b"1").unwrap();
+        let delta = pack_layer_delta(src.path(), &index).unwrap();
+        let mut ar = tar::Archive::new(delta.as_slice());
+        let paths: Vec<String> = ar
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(paths, ["hello.txt"]);
+        assert!(has_pax_metadata(&delta).unwrap());
+    }
+
+    #[test]
+    fn delta_chain_tracks_earlier_deltas() {
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir(src.path().join("d")).unwrap();
+        fs::write(src.path().join("d/a"), "1").unwrap();
+        let mut tars = vec![pack_layer(src.path()).unwrap()];
+
+        fs::remove_dir_all(src.path().join("d")).unwrap();
+        let index = LayerIndex::from_tars(&tars).unwrap();
+        tars.push(pack_layer_delta(src.path(), &index).unwrap());
+
+        // Recreated after being removed by the previous delta.
+        fs::create_dir(src.path().join("d")).unwrap();
+        fs::write(src.path().join("d/b"), "2").unwrap();
+        let index = LayerIndex::from_tars(&tars).unwrap();
+        assert!(index.is_empty());
+        tars.push(pack_layer_delta(src.path(), &index).unwrap());
+
+        assert_chain_matches(&tars, src.path());
+        let dst = tempfile::tempdir().unwrap();
+        unpack_layers(&tars, dst.path()).unwrap();
+        assert!(!dst.path().join("d/a").exists());
+    }
+
+    fn test_object_store() -> (tempfile::TempDir, ObjectStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        (dir, ObjectStore::new(layout))
+    }
+
+    #[test]
+    fn file_objects_are_shared_between_layers() {
+        let (_store_dir, objects) = test_object_store();
+        let a = tempfile::tempdir().unwrap();
+        create_fixture_dir(a.path());
+        fs::write(a.path().join("large.bin"), vec![7u8; 64 * 1024]).unwrap();
+        fs::set_permissions(
+            a.path().join("hello.txt"),
+            fs::Permissions::from_mode(0o444),
+        )
+        .unwrap();
+        let b = tempfile::tempdir().unwrap();
+        fs::write(b.path().join("copy.bin"), vec![7u8; 64 * 1024]).unwrap();
+
+        let first = pack_layer_with_objects(a.path(), &objects).unwrap();
+        let second = pack_layer_with_objects(b.path(), &objects).unwrap();
+        assert_eq!(first.files.len(), 4);
+        assert_eq!(second.files.len(), 1);
+        assert!(first.files.contains(&second.files[0]));
+        assert!(first.tar.len() < 64 * 1024);
+
+        let dst = tempfile::tempdir().unwrap();
+        unpack_layers_with_objects(std::slice::from_ref(&first.tar), dst.path(), &objects).unwrap();
+        assert_eq!(
+            pack_layer(dst.path()).unwrap(),
+            pack_layer(a.path()).unwrap()
+        );
+
+        let err = unpack_layers(&[first.tar], tempfile::tempdir().unwrap().path());
+        assert!(err.is_err());
+    }
+
+    /// A 4 MiB file with data only in its first and last bytes.
+    fn write_sparse_fixture(path: &Path) {
+        use std::io::{Seek, SeekFrom, Write};
+        let mut file = fs::File::create(path).unwrap();
+        file.write_all(b"head").unwrap();
+        file.seek(SeekFrom::Start(4 * 1024 * 1024)).unwrap();
+        file.write_all(b"tail").unwrap();
+    }
+
+    fn allocated(path: &Path) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(path).unwrap().blocks() * 512
+    }
+
+    #[test]
+    fn xattrs_and_holes_survive_pack_and_unpack() {
+        let (_store_dir, objects) = test_object_store();
+        let src = tempfile::tempdir().unwrap();
+        create_fixture_dir(src.path());
+        write_sparse_fixture(&src.path().join("sparse.img"));
+        xattr::set(
+            src.path().join("hello.txt"),
+            "user.mime_type",
+            b"text/plain",
+        )
+        .unwrap();
+        xattr::set(src.path().join("subdir"), "user.origin", b"\0binary\xff").unwrap();
+        assert!(!has_pax_metadata(&pack_layer(&src.path().join("empty_dir")).unwrap()).unwrap());
+
+        let plain = pack_layer(src.path()).unwrap();
+        assert!(has_pax_metadata(&plain).unwrap());
+        // The sparse file is still stored dense, for readers that ignore
+        // the holes.
+        assert!(plain.len() > 4 * 1024 * 1024);
+        let packed = pack_layer_with_objects(src.path(), &objects).unwrap();
+        assert!(packed.pax_metadata);
+
+        let from_tar = tempfile::tempdir().unwrap();
+        unpack_layer(&plain, from_tar.path()).unwrap();
+        let from_objects = tempfile::tempdir().unwrap();
+        unpack_layers_with_objects(&[packed.tar], from_objects.path(), &objects).unwrap();
+        for dst in [from_tar.path(), from_objects.path()] {
+            assert_eq!(
+                xattr::get(dst.join("hello.txt"), "user.mime_type").unwrap(),
+                Some(b"text/plain".to_vec())
+            );
+            assert_eq!(
+                xattr::get(dst.join("subdir"), "user.origin").unwrap(),
+                Some(b"\0binary\xff".to_vec())
+            );
+            let sparse = dst.join("sparse.img");
+            assert_eq!(
+                fs::read(&sparse).unwrap(),
+                fs::read(src.path().join("sparse.img")).unwrap()
+            );
+            assert!(allocated(&sparse) < 1024 * 1024);
+            assert_eq!(pack_layer(dst).unwrap(), plain);
+        }
+    }
+
+    /// Hard link `subdir/hello_link` to `hello.txt`, a FIFO, and a
+    /// character device where the process may create one.
+    fn add_links_and_nodes(dir: &Path) -> bool {
+        fs::hard_link(dir.join("hello.txt"), dir.join("subdir/hello_link")).unwrap();
+        entry_attrs::make_node(&dir.join("fifo"), libc::S_IFIFO, 0o640, (0, 0)).unwrap();
+        entry_attrs::make_node(&dir.join("null"), libc::S_IFCHR, 0o666, (1, 3)).is_ok()
+    }
+
+    fn inode(path: &Path) -> u64 {
+        fs::symlink_metadata(path).unwrap().ino()
+    }
+
+    #[test]
+    fn hardlinks_and_nodes_survive_pack_and_unpack() {
+        let (_store_dir, objects) = test_object_store();
+        let src = tempfile::tempdir().unwrap();
+        create_fixture_dir(src.path());
+        let devices = add_links_and_nodes(src.path());
+
+        let plain = pack_layer(src.path()).unwrap();
+        let mut ar = tar::Archive::new(plain.as_slice());
+        let links: Vec<String> = ar
+            .entries()
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|e| e.header().entry_type() == tar::EntryType::Link)
+            .map(|e| link_target(&e).unwrap())
+            .collect();
+        assert_eq!(links, ["hello.txt"]);
+        let packed = pack_layer_with_objects(src.path(), &objects).unwrap();
+
+        let from_tar = tempfile::tempdir().unwrap();
+        unpack_layer(&plain, from_tar.path()).unwrap();
+        let from_objects = tempfile::tempdir().unwrap();
+        unpack_layers_with_objects(&[packed.tar], from_objects.path(), &objects).unwrap();
+        for dst in [from_tar.path(), from_objects.path()] {
+            assert_eq!(
+                inode(&dst.join("hello.txt")),
+                inode(&dst.join("subdir/hello_link"))
+            );
+            let fifo = fs::symlink_metadata(dst.join("fifo")).unwrap();
+            assert!(fifo.file_type().is_fifo());
+            assert_eq!(fifo.permissions().mode() & 0o7777, 0o640);
+            if devices {
+                let null = fs::symlink_metadata(dst.join("null")).unwrap();
+                assert!(null.file_type().is_char_device());
+                assert_eq!(null.rdev(), libc::makedev(1, 3));
+            }
+            assert_eq!(pack_layer(dst).unwrap(), plain);
+        }
+    }
+
+    #[test]
+    fn delta_repacks_links_of_a_rewritten_inode() {
+        let src = tempfile::tempdir().unwrap();
+        create_fixture_dir(src.path());
+        add_links_and_nodes(src.path());
+        let full = pack_layer(src.path()).unwrap();
+        let index = LayerIndex::from_tars(std::slice::from_ref(&full)).unwrap();
+        let unchanged = pack_layer_delta(src.path(), &index).unwrap();
+        let mut ar = tar::Archive::new(unchanged.as_slice());
+        assert_eq!(ar.entries().unwrap().count(), 0);
+
+        // Written in place, so the link sees the new content.
+        fs::write(src.path().join("hello.txt"), "changed").unwrap();
+        let delta = pack_layer_delta(src.path(), &index).unwrap();
+        let mut ar = tar::Archive::new(delta.as_slice());
+        let paths: Vec<String> = ar
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(paths, ["hello.txt", "subdir/hello_link"]);
+
+        let dst = tempfile::tempdir().unwrap();
+        unpack_layers(&[full, delta], dst.path()).unwrap();
+        assert_eq!(
+            inode(&dst.path().join("hello.txt")),
+            inode(&dst.path().join("subdir/hello_link"))
+        );
+        assert_eq!(
+            fs::read_to_string(dst.path().join("subdir/hello_link")).unwrap(),
+            "changed"
+        );
+    }
+
+    #[test]
+    fn holes_that_do_not_read_as_zeros_are_rejected() {
+        assert_eq!(
+            entry_attrs::decode_holes(b"0,4096,8192,4096", 16384).unwrap(),
+            [(0, 4096), (8192, 4096)]
+        );
+        for map in ["0,4096,2048,4096", "0,4096,8192", "16000,4096", "x,1"] {
+            assert!(entry_attrs::decode_holes(map.as_bytes(), 16384).is_err());
+        }
+        let data = [0, 0, 1, 0];
+        assert_eq!(
+            entry_attrs::zero_holes(vec![(0, 2), (1, 2), (3, 1)], &data),
+            [(0, 2), (3, 1)]
+        );
+    }
+
+    #[test]
+    fn delta_with_file_objects_compares_by_object_hash() {
+        let (_store_dir, objects) = test_object_store();
+        let src = tempfile::tempdir().unwrap();
+        create_fixture_dir(src.path());
+        let full = pack_layer_with_objects(src.path(), &objects).unwrap();
+        let index = LayerIndex::from_tars(std::slice::from_ref(&full.tar)).unwrap();
+
+        fs::write(src.path().join("subdir/nested.txt"), "edited").unwrap();
+        let delta = pack_layer_delta_with_objects(src.path(), &index, &objects).unwrap();
+        assert_eq!(delta.files, [blake3::hash(b"edited").to_hex().to_string()]);
+
+        let dst = tempfile::tempdir().unwrap();
+        unpack_layers_with_objects(&[full.tar, delta.tar], dst.path(), &objects).unwrap();
+        assert_eq!(
+            pack_layer(dst.path()).unwrap(),
+            pack_layer(src.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn tar_chain_skips_file_objects() {
+        let layer = LayerManifest {
+            object_refs: vec!["t1".into(), "t2".into(), "f1".into(), "f2".into()],
+            tar_hash: "t2".into(),
+            delta_parent: Some("parent".into()),
+            file_objects: vec!["f1".into(), "f2".into()],
+            ..sample_layer()
+        };
+        assert_eq!(layer.tar_chain(), ["t1", "t2"]);
+    }
+
+    #[test]
+    fn file_objects_do_not_follow_symlinked_parents() {
+        let (_store_dir, objects) = test_object_store();
+        let hash = objects.put(b"payload").unwrap();
+        let outside = tempfile::tempdir().unwrap();
+
+        let mut ar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        ar.append_link(&mut header, "link", outside.path()).unwrap();
+        ar.append_pax_extensions([(FILE_OBJECT_PAX_KEY, hash.as_bytes())])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        header.set_cksum();
+        ar.append_data(&mut header, "link/file", &[] as &[u8])
+            .unwrap();
+        let tar = ar.into_inner().unwrap();
+
+        let dst = tempfile::tempdir().unwrap();
+        assert!(unpack_layers_with_objects(&[tar], dst.path(), &objects).is_err());
+        assert!(!outside.path().join("file").exists());
+    }
+
+    #[test]
+    fn whiteouts_outside_the_layer_are_rejected() {
+        for bad in ["../escape", "/etc/passwd", "a/../../b", ""] {
+            let list = serde_json::to_vec(&[bad]).unwrap();
+            let mut ar = tar::Builder::new(Vec::new());
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_size(list.len() as u64);
+            header.set_cksum();
+            ar.append_data(&mut header, DELTA_WHITEOUTS, list.as_slice())
+                .unwrap();
+            let delta = ar.into_inner().unwrap();
+
+            let dst = tempfile::tempdir().unwrap();
+            assert!(
+                unpack_layers(&[delta], dst.path()).is_err(),
+                "whiteout {bad:?} accepted"
+            );
+        }
+    }
 }
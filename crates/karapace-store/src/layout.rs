@@ -1,12 +1,12 @@
+use crate::config::{SharedStore, StoreConfig};
 use crate::StoreError;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::io::Write;
 use std::path::{Path, PathBuf};
-use tempfile::NamedTempFile;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 
 /// Current store format version. Incremented on incompatible layout changes.
-pub const STORE_FORMAT_VERSION: u32 = 2;
+pub const STORE_FORMAT_VERSION: u32 = 3;
 const VERSION_FILE: &str = "version";
 
 /// Directory layout for the Karapace content-addressable store.
@@ -16,6 +16,8 @@ const VERSION_FILE: &str = "version";
 #[derive(Debug, Clone)]
 pub struct StoreLayout {
     root: PathBuf,
+    /// [`shared_store`](Self::shared_store) once read, shared by clones.
+    shared: Arc<Mutex<OnceLock<Option<SharedStore>>>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,7 +27,34 @@ struct StoreVersion {
 
 impl StoreLayout {
     pub fn new(root: impl Into<PathBuf>) -> Self {
-        Self { root: root.into() }
+        Self {
+            root: root.into(),
+            shared: Arc::default(),
+        }
+    }
+
+    /// The [shared store](StoreConfig::shared_store) this store falls back
+    /// to. Its path and its config are read on the first call only, so
+    /// object and layer stores opened on this layout or a clone of it do
+    /// not read them again; [`StoreConfig::save`] makes the next call
+    /// re-read them.
+    pub fn shared_store(&self) -> Option<SharedStore> {
+        self.shared
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_or_init(|| {
+                StoreConfig::load_or_default(self)
+                    .shared_store
+                    .map(|root| SharedStore::open(&root))
+            })
+            .clone()
+    }
+
+    pub(crate) fn forget_shared_store(&self) {
+        self.shared
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
     }
 
     #[inline]
@@ -38,6 +67,12 @@ impl StoreLayout {
         self.root.join("store").join("objects")
     }
 
+    /// Packfiles consolidating small objects. Created on first repack.
+    #[inline]
+    pub fn packs_dir(&self) -> PathBuf {
+        self.root.join("store").join("packs")
+    }
+
     #[inline]
     pub fn layers_dir(&self) -> PathBuf {
         self.root.join("store").join("layers")
@@ -48,6 +83,25 @@ impl StoreLayout {
         self.root.join("store").join("metadata")
     }
 
+    /// Index of environment names, short ids and states; see
+    /// [`MetadataIndex`](crate::MetadataIndex).
+    #[inline]
+    pub fn metadata_index_file(&self) -> PathBuf {
+        self.metadata_dir().join(".index")
+    }
+
+    /// Database of the SQLite metadata backend.
+    #[inline]
+    pub fn metadata_db_file(&self) -> PathBuf {
+        self.metadata_dir().join(".metadata.db")
+    }
+
+    /// Lock serializing writes to one environment's metadata entry.
+    #[inline]
+    pub fn metadata_lock_file(&self, env_id: &str) -> PathBuf {
+        self.metadata_dir().join(".locks").join(env_id)
+    }
+
     #[inline]
     pub fn env_dir(&self) -> PathBuf {
         self.root.join("env")
@@ -71,12 +125,80 @@ impl StoreLayout {
         self.env_path(env_id).join("upper")
     }
 
+    /// Parent of the upper dirs of inactive workspaces.
+    #[inline]
+    pub fn workspaces_dir(&self, env_id: &str) -> PathBuf {
+        self.env_path(env_id).join("workspaces")
+    }
+
+    /// Upper dir of an inactive workspace. The active one is [`Self::upper_dir`].
+    #[inline]
+    pub fn workspace_upper_dir(&self, env_id: &str, name: &str) -> PathBuf {
+        self.workspaces_dir(env_id).join(name).join("upper")
+    }
+
     /// Temporary staging area for layer packing/unpacking operations.
     #[inline]
     pub fn staging_dir(&self) -> PathBuf {
         self.root.join("store").join("staging")
     }
 
+    /// Per-store settings. See [`StoreConfig`](crate::StoreConfig).
+    #[inline]
+    pub fn config_file(&self) -> PathBuf {
+        self.root.join("store").join("config.json")
+    }
+
+    /// Environments brought in by `karapace sync`, per sync manifest.
+    #[inline]
+    pub fn sync_state_file(&self) -> PathBuf {
+        self.root.join("store").join("sync.json")
+    }
+
+    /// Snapshot schedules of `karapace autosnap`, per environment.
+    #[inline]
+    pub fn autosnap_file(&self) -> PathBuf {
+        self.root.join("store").join("autosnap.json")
+    }
+
+    /// When each bucket of objects and layers was last verified by
+    /// `karapace verify-store --incremental`.
+    #[inline]
+    pub fn scrub_state_file(&self) -> PathBuf {
+        self.root.join("store").join("scrub.json")
+    }
+
+    /// Last fetched copy of each remote's registry.
+    #[inline]
+    pub fn registry_cache_dir(&self) -> PathBuf {
+        self.root.join("store").join("registry-cache")
+    }
+
+    /// Package layers reusable by later builds, by cache key.
+    #[inline]
+    pub fn build_cache_dir(&self) -> PathBuf {
+        self.root.join("store").join("build-cache")
+    }
+
+    /// Vulnerability data fetched by `karapace audit`.
+    #[inline]
+    pub fn audit_cache_dir(&self) -> PathBuf {
+        self.root.join("store").join("audit-cache")
+    }
+
+    /// Damaged objects and layers moved aside by `karapace verify-store
+    /// --repair`.
+    #[inline]
+    pub fn quarantine_dir(&self) -> PathBuf {
+        self.root.join("store").join("quarantine")
+    }
+
+    /// ed25519 key that signs build attestations.
+    #[inline]
+    pub fn attestation_key_file(&self) -> PathBuf {
+        self.root.join("store").join("attestation.key")
+    }
+
     #[inline]
     pub fn lock_file(&self) -> PathBuf {
         self.root.join("store").join(".lock")
@@ -98,12 +220,7 @@ impl StoreLayout {
             };
             let content = serde_json::to_string_pretty(&ver)?;
             let store_dir = self.root.join("store");
-            let mut tmp = NamedTempFile::new_in(&store_dir)?;
-            tmp.write_all(content.as_bytes())?;
-            tmp.as_file().sync_all()?;
-            tmp.persist(&version_path)
-                .map_err(|e| StoreError::Io(e.error))?;
-            crate::fsync_dir(&store_dir)?;
+            crate::write_atomic(&store_dir, &version_path, content.as_bytes())?;
         }
 
         Ok(())
@@ -182,4 +299,26 @@ mod tests {
         layout.initialize().unwrap();
         layout.verify_version().unwrap();
     }
+
+    #[test]
+    fn shared_store_is_read_once_per_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        assert!(layout.shared_store().is_none());
+
+        let config = StoreConfig {
+            shared_store: Some(PathBuf::from("/var/lib/karapace")),
+            ..StoreConfig::default()
+        };
+        config.save(&layout).unwrap();
+        let shared = layout.clone().shared_store().unwrap();
+        assert_eq!(shared.layout.root(), Path::new("/var/lib/karapace"));
+        assert_eq!(shared.config.shared_store, None);
+
+        // Edited behind the layout's back: not read again.
+        fs::write(layout.config_file(), "{}").unwrap();
+        assert!(layout.shared_store().is_some());
+        assert!(StoreLayout::new(dir.path()).shared_store().is_none());
+    }
 }
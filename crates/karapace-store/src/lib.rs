@@ -1,31 +1,75 @@
 //! Content-addressable object store, layer management, and environment metadata for Karapace.
 //!
 //! This crate provides the storage layer: a content-addressable `ObjectStore` backed
-//! by blake3 hashing with atomic writes, `LayerStore` for overlay filesystem layer
+//! by blake3 hashing with atomic writes (large objects split into content-defined
+//! chunks), `LayerStore` for overlay filesystem layer
 //! manifests, `MetadataStore` for environment state tracking, `StoreLayout` for
-//! directory structure management, and `GarbageCollector` for orphan cleanup.
+//! directory structure management, `PackStore` for consolidating small objects,
+//! and `GarbageCollector` for orphan cleanup.
 
+#[cfg(feature = "test-util")]
+pub mod chaos;
+pub mod chunking;
+pub mod config;
+mod entry_attrs;
 pub mod gc;
+pub mod index;
 pub mod integrity;
 pub mod layers;
 pub mod layout;
 pub mod metadata;
 pub mod migration;
 pub mod objects;
+pub mod pack;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 pub mod wal;
 
-pub use gc::{GarbageCollector, GcReport};
-pub use integrity::{verify_store_integrity, IntegrityFailure, IntegrityReport};
-pub use layers::{pack_layer, unpack_layer, LayerKind, LayerManifest, LayerStore};
+pub use chunking::{ChunkRef, CHUNK_LIST_MAGIC};
+pub use config::{
+    Compression, MetadataBackend, SharedStore, StoreConfig, DEFAULT_CHUNK_THRESHOLD,
+    DEFAULT_COMPRESSION_LEVEL,
+};
+pub use gc::{GarbageCollector, GcPlan, GcReport, RetentionPolicy};
+pub use index::MetadataIndex;
+pub use integrity::{
+    load_scrub_state, scrub_store, verify_env_integrity, verify_store_integrity, IntegrityFailure,
+    IntegrityKind, IntegrityReport, ScrubReport, ScrubState, SCRUB_BUCKETS,
+};
+pub use layers::{
+    has_pax_metadata, pack_layer, pack_layer_delta, pack_layer_delta_with_objects,
+    pack_layer_with_objects, unpack_layer, unpack_layers, unpack_layers_with_objects, LayerIndex,
+    LayerKind, LayerManifest, LayerStore, PackedLayer, DELTA_WHITEOUTS, FILE_OBJECT_PAX_KEY,
+    HOLES_PAX_KEY, XATTR_PAX_PREFIX,
+};
 pub use layout::{StoreLayout, STORE_FORMAT_VERSION};
-pub use metadata::{validate_env_name, EnvMetadata, EnvState, MetadataStore};
-pub use migration::{migrate_store, MigrationResult};
+pub use metadata::{
+    validate_env_name, EnvMetadata, EnvState, GpuDriverInfo, MetadataStore, DEFAULT_WORKSPACE,
+    MAX_ALIASES,
+};
+pub use migration::{migrate_metadata_backend, migrate_store, MigrationResult};
 pub use objects::ObjectStore;
-pub use wal::{RollbackStep, WalOpKind, WriteAheadLog};
+pub use pack::{PackEntry, PackIndex, PackStore, RepackReport, DEFAULT_PACK_THRESHOLD};
+pub use wal::{RollbackStep, WalOpKind, WalOutcome, WalRecord, WriteAheadLog, HISTORY_LIMIT};
 
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use thiserror::Error;
 
+static VERIFIED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes this process has hashed to verify objects and layers it read.
+pub fn verified_bytes() -> u64 {
+    VERIFIED_BYTES.load(Ordering::Relaxed)
+}
+
+/// blake3 of `data`, read back from the store to be checked against its
+/// key. Counted in [`verified_bytes`].
+pub(crate) fn verify_hash(data: &[u8]) -> blake3::Hash {
+    VERIFIED_BYTES.fetch_add(data.len() as u64, Ordering::Relaxed);
+    blake3::hash(data)
+}
+
 /// Fsync a directory to ensure that a preceding `rename()` is durable.
 ///
 /// On Linux with ext4 `data=ordered` (the default), renames are usually
@@ -37,6 +81,27 @@ pub(crate) fn fsync_dir(dir: &Path) -> Result<(), std::io::Error> {
     f.sync_all()
 }
 
+/// Write `data` to `dest` atomically through a temp file in `dir`, then
+/// fsync `dir` so the rename is durable.
+///
+/// Every durable write in the store goes through here, which is also where
+/// the `test-util` fault hooks sit.
+pub(crate) fn write_atomic(dir: &Path, dest: &Path, data: &[u8]) -> Result<(), StoreError> {
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    #[cfg(feature = "test-util")]
+    chaos::write(&mut tmp, dest, data)?;
+    #[cfg(not(feature = "test-util"))]
+    std::io::Write::write_all(&mut tmp, data)?;
+    tmp.as_file().sync_all()?;
+    #[cfg(feature = "test-util")]
+    chaos::before_rename(dest);
+    tmp.persist(dest).map_err(|e| StoreError::Io(e.error))?;
+    fsync_dir(dir)?;
+    #[cfg(feature = "test-util")]
+    chaos::after_durable(dest)?;
+    Ok(())
+}
+
 #[derive(Debug, Error)]
 pub enum StoreError {
     #[error("store I/O error: {0}")]
@@ -61,11 +126,21 @@ pub enum StoreError {
     Serialization(#[from] serde_json::Error),
     #[error("invalid environment name: {0}")]
     InvalidName(String),
+    #[error("invalid label: {0}")]
+    InvalidLabel(String),
+    #[error("metadata backend: {0}")]
+    MetadataBackend(String),
     #[error("name '{name}' is already used by environment {existing_env_id}")]
     NameConflict {
         name: String,
         existing_env_id: String,
     },
+    #[error("metadata for '{env_id}' changed concurrently: expected revision {expected}, found {actual}")]
+    Conflict {
+        env_id: String,
+        expected: u64,
+        actual: u64,
+    },
 }
 
 #[cfg(test)]
@@ -89,6 +164,18 @@ mod tests {
         assert!(msg.contains("abc123"));
     }
 
+    #[test]
+    fn store_error_display_conflict() {
+        let e = StoreError::Conflict {
+            env_id: "abc123".to_owned(),
+            expected: 3,
+            actual: 4,
+        };
+        let msg = e.to_string();
+        assert!(msg.contains("abc123"));
+        assert!(msg.contains("expected revision 3, found 4"));
+    }
+
     #[test]
     fn store_error_display_object_not_found() {
         let e = StoreError::ObjectNotFound("hash123".to_owned());
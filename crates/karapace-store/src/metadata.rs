@@ -1,10 +1,15 @@
+use crate::config::{MetadataBackend, StoreConfig};
+use crate::index::{Lookup, MetadataIndex};
 use crate::layout::StoreLayout;
-use crate::{fsync_dir, StoreError};
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteMetadata;
+use crate::wal::{WalOpKind, WriteAheadLog};
+use crate::{write_atomic, StoreError};
+use fs2::FileExt;
 use karapace_schema::types::{EnvId, LayerHash, ObjectHash, ShortId};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::Write;
-use tempfile::NamedTempFile;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum EnvState {
@@ -27,6 +32,23 @@ impl std::fmt::Display for EnvState {
     }
 }
 
+/// Host GPU driver versions observed when an environment was built.
+///
+/// Userspace driver libraries inside the environment must match the host
+/// kernel driver; a host upgrade silently breaks GPU access until rebuild.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GpuDriverInfo {
+    /// NVIDIA kernel module version (`/sys/module/nvidia/version`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvidia_kernel: Option<String>,
+    /// NVIDIA userspace library version (`libnvidia-glcore.so.<version>`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvidia_userspace: Option<String>,
+    /// Kernel DRM drivers bound to `/sys/class/drm/card*`, sorted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drm_drivers: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct EnvMetadata {
     pub env_id: EnvId,
@@ -41,12 +63,92 @@ pub struct EnvMetadata {
     pub created_at: String,
     pub updated_at: String,
     pub ref_count: u32,
+    /// Former names, most recent first, still accepted when resolving an
+    /// environment. Capped at [`MAX_ALIASES`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Host GPU drivers at build time. Recorded only for `hardware.gpu`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_gpu: Option<GpuDriverInfo>,
+    /// Content digest of the resolved base image. `None` for environments
+    /// that were only initialized, and for legacy metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_image_digest: Option<String>,
+    /// Object holding the signed provenance of the build. `None` for
+    /// environments that were only initialized or built before attestations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<ObjectHash>,
+    /// Active writable workspace. `None` means [`DEFAULT_WORKSPACE`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    /// Snapshot the upper directory was last committed as or restored from.
+    /// Incremental commits are delta-encoded against it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    /// Idle time in seconds after which GC archives the environment, and
+    /// after twice as long destroys it. `None` never expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_after_secs: Option<u64>,
+    /// When a session last entered, ran a command in or attached to the
+    /// environment (RFC 3339). `None` if it never has.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_entered_at: Option<String>,
+    /// Sessions that entered, ran a command in or attached to the
+    /// environment.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub enter_count: u64,
+    /// When the environment was last built (RFC 3339). `None` for
+    /// environments that were only initialized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_built_at: Option<String>,
+    /// `key=value` labels, from the manifest's `[labels]` and `karapace label`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Bumped by every write. [`MetadataStore::put`] only succeeds against
+    /// the revision that was read. `0` for legacy metadata.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub revision: u64,
     /// blake3 checksum for integrity verification. `None` for legacy metadata.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub checksum: Option<String>,
 }
 
 impl EnvMetadata {
+    /// Objects referenced by the metadata itself rather than by a layer:
+    /// the manifest and the attestation.
+    pub fn direct_objects(&self) -> Vec<String> {
+        std::iter::once(&self.manifest_hash)
+            .chain(&self.attestation)
+            .filter(|hash| !hash.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Whether the environment matches `selector`: `key=value` matches that
+    /// label, a bare `key` any value of it.
+    pub fn matches_label(&self, selector: &str) -> bool {
+        match selector.split_once('=') {
+            Some((key, value)) => self.labels.get(key).is_some_and(|v| v == value),
+            None => self.labels.contains_key(selector),
+        }
+    }
+
+    /// Count a session entering the environment now.
+    pub fn record_entry(&mut self) {
+        self.last_entered_at = Some(chrono::Utc::now().to_rfc3339());
+        self.enter_count += 1;
+    }
+
+    /// `self` as stored at `revision`, with its checksum, and the JSON it is
+    /// stored as.
+    pub(crate) fn seal(&self, revision: u64) -> Result<(EnvMetadata, String), StoreError> {
+        let mut sealed = self.clone();
+        sealed.revision = revision;
+        sealed.checksum = Some(sealed.compute_checksum()?);
+        let content = serde_json::to_string_pretty(&sealed)?;
+        Ok((sealed, content))
+    }
+
     /// Compute the checksum over the metadata content (excluding the checksum field itself).
     fn compute_checksum(&self) -> Result<String, StoreError> {
         let mut copy = self.clone();
@@ -57,6 +159,35 @@ impl EnvMetadata {
     }
 }
 
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// How often [`MetadataStore::update`] re-reads an entry that another writer
+/// changed underneath it before giving up with [`StoreError::Conflict`].
+const UPDATE_ATTEMPTS: u64 = 16;
+
+/// Sleep a random, growing fraction of a millisecond so writers that keep
+/// colliding drift apart.
+fn backoff(attempt: u64) {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| u64::from(d.subsec_nanos()));
+    let micros = nanos % (250 * attempt);
+    std::thread::sleep(std::time::Duration::from_micros(micros));
+}
+
+/// Maximum number of former names kept as aliases per environment.
+pub const MAX_ALIASES: usize = 8;
+
+/// Lock entry serializing updates of the metadata index. Environment ids
+/// never start with a dot.
+const INDEX_LOCK: &str = ".index";
+
+/// Name of the workspace every environment starts in.
+pub const DEFAULT_WORKSPACE: &str = "default";
+
 pub fn validate_env_name(name: &str) -> Result<(), StoreError> {
     if name.is_empty() || name.len() > 64 {
         return Err(StoreError::InvalidName(
@@ -74,40 +205,284 @@ pub fn validate_env_name(name: &str) -> Result<(), StoreError> {
     Ok(())
 }
 
+/// Where a [`MetadataStore`] keeps its entries.
+enum Backend {
+    Json,
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteMetadata),
+    /// The store config selects SQLite, which this build does not include.
+    #[cfg(not(feature = "sqlite"))]
+    Unavailable,
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_unavailable() -> StoreError {
+    StoreError::MetadataBackend(
+        "the store keeps metadata in SQLite, but karapace was built without the sqlite feature"
+            .to_owned(),
+    )
+}
+
 pub struct MetadataStore {
     layout: StoreLayout,
+    backend: Backend,
 }
 
 impl MetadataStore {
+    /// Metadata store of `layout`, with the backend its config selects.
     pub fn new(layout: StoreLayout) -> Self {
-        Self { layout }
+        let backend = StoreConfig::load_or_default(&layout).metadata_backend;
+        Self::with_backend(layout, backend)
+    }
+
+    pub fn with_backend(layout: StoreLayout, backend: MetadataBackend) -> Self {
+        let backend = match backend {
+            MetadataBackend::Json => Backend::Json,
+            #[cfg(feature = "sqlite")]
+            MetadataBackend::Sqlite => Backend::Sqlite(SqliteMetadata::new(&layout)),
+            #[cfg(not(feature = "sqlite"))]
+            MetadataBackend::Sqlite => Backend::Unavailable,
+        };
+        Self { layout, backend }
     }
 
+    pub fn backend(&self) -> MetadataBackend {
+        match self.backend {
+            Backend::Json => MetadataBackend::Json,
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(_) => MetadataBackend::Sqlite,
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Unavailable => MetadataBackend::Sqlite,
+        }
+    }
+
+    /// Write `meta` if the stored entry is still at `meta.revision` (a missing
+    /// entry is at revision 0) and store it as the next revision. Fails with
+    /// [`StoreError::Conflict`] if another writer got there first.
     pub fn put(&self, meta: &EnvMetadata) -> Result<(), StoreError> {
+        self.write(meta, Some(meta.revision))
+    }
+
+    /// Write `meta` as the next revision whatever is stored, for writers
+    /// whose copy replaces the entry wholesale, like a pull.
+    pub fn overwrite(&self, meta: &EnvMetadata) -> Result<(), StoreError> {
+        self.write(meta, None)
+    }
+
+    fn write(&self, meta: &EnvMetadata, expected: Option<u64>) -> Result<(), StoreError> {
+        match &self.backend {
+            Backend::Json => self.write_json(meta, expected),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(db) => db.write(meta, expected),
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Unavailable => Err(sqlite_unavailable()),
+        }
+    }
+
+    fn write_json(&self, meta: &EnvMetadata, expected: Option<u64>) -> Result<(), StoreError> {
+        // The revision check and the write must not interleave with another
+        // process doing the same.
+        let _lock = self.lock_entry(&meta.env_id)?;
+        let actual = match self.stored_revision(&meta.env_id) {
+            Ok(actual) => actual,
+            // An unreadable entry can still be replaced wholesale.
+            Err(_) if expected.is_none() => 0,
+            Err(e) => return Err(e),
+        };
+        if let Some(expected) = expected.filter(|expected| *expected != actual) {
+            return Err(StoreError::Conflict {
+                env_id: meta.env_id.to_string(),
+                expected,
+                actual,
+            });
+        }
+
+        let (meta_with_checksum, content) = meta.seal(actual + 1)?;
+
         let dest = self.layout.metadata_dir().join(&meta.env_id);
+        let wal = WriteAheadLog::new(&self.layout);
+        let op_id = wal.begin(WalOpKind::Index, &meta.env_id)?;
+        let written = write_atomic(&self.layout.metadata_dir(), &dest, content.as_bytes());
+        let indexed = match written {
+            Ok(()) => self.update_index(|index| index.insert(&meta_with_checksum)),
+            // The entry may or may not have been replaced.
+            Err(_) => self.reindex(&meta.env_id),
+        };
+        // An index update that failed leaves the WAL entry for recovery,
+        // which rebuilds the index.
+        if indexed.is_ok() {
+            wal.commit(&op_id)?;
+        }
+        written?;
+        indexed
+    }
 
-        // Compute and embed checksum before writing
-        let mut meta_with_checksum = meta.clone();
-        meta_with_checksum.checksum = Some(meta_with_checksum.compute_checksum()?);
-        let content = serde_json::to_string_pretty(&meta_with_checksum)?;
+    /// Apply `f` to the stored index under the index lock and write it back.
+    fn update_index(&self, f: impl FnOnce(&mut MetadataIndex)) -> Result<(), StoreError> {
+        let _lock = self.lock_entry(INDEX_LOCK)?;
+        let mut index = match self.stored_index()? {
+            Some(index) => index,
+            None => self.scan_index()?,
+        };
+        f(&mut index);
+        self.write_index(&index)
+    }
 
-        let dir = self.layout.metadata_dir();
-        let mut tmp = NamedTempFile::new_in(&dir)?;
-        tmp.write_all(content.as_bytes())?;
-        tmp.as_file().sync_all()?;
-        tmp.persist(&dest).map_err(|e| StoreError::Io(e.error))?;
-        fsync_dir(&dir)?;
+    /// Bring the index entry of `env_id` in line with what is stored.
+    fn reindex(&self, env_id: &str) -> Result<(), StoreError> {
+        let stored = self.get(env_id).ok();
+        self.update_index(|index| match stored {
+            Some(meta) => index.insert(&meta),
+            None => index.remove(env_id),
+        })
+    }
 
-        Ok(())
+    fn write_index(&self, index: &MetadataIndex) -> Result<(), StoreError> {
+        let content = serde_json::to_vec(index)?;
+        write_atomic(
+            &self.layout.metadata_dir(),
+            &self.layout.metadata_index_file(),
+            &content,
+        )
     }
 
-    pub fn get(&self, env_id: &str) -> Result<EnvMetadata, StoreError> {
-        let path = self.layout.metadata_dir().join(env_id);
-        if !path.exists() {
-            return Err(StoreError::EnvNotFound(env_id.to_owned()));
+    fn scan_index(&self) -> Result<MetadataIndex, StoreError> {
+        Ok(MetadataIndex::build(&self.list()?))
+    }
+
+    /// The index as stored, `None` if there is none or it does not parse.
+    /// The SQLite backend has no index file; it queries the index from the
+    /// columns it is made of.
+    pub fn stored_index(&self) -> Result<Option<MetadataIndex>, StoreError> {
+        match &self.backend {
+            Backend::Json => {}
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(db) => return db.index().map(Some),
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Unavailable => return Err(sqlite_unavailable()),
+        }
+        match fs::read(self.layout.metadata_index_file()) {
+            Ok(content) => Ok(serde_json::from_slice(&content).ok()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The index, built from the metadata entries and stored if there is
+    /// none yet.
+    pub fn index(&self) -> Result<MetadataIndex, StoreError> {
+        if let Some(index) = self.stored_index()? {
+            return Ok(index);
+        }
+        self.rebuild_index().or_else(|e| {
+            tracing::debug!("cannot store the metadata index: {e}");
+            self.scan_index()
+        })
+    }
+
+    /// Rebuild the index from the metadata entries, replacing the stored one.
+    /// The SQLite backend keeps its index in step transactionally, so this
+    /// only queries it.
+    pub fn rebuild_index(&self) -> Result<MetadataIndex, StoreError> {
+        if !self.backend().is_json() {
+            return self.stored_index().map(Option::unwrap_or_default);
+        }
+        let _lock = self.lock_entry(INDEX_LOCK)?;
+        let index = self.scan_index()?;
+        self.write_index(&index)?;
+        Ok(index)
+    }
+
+    fn lock_entry(&self, env_id: &str) -> Result<fs::File, StoreError> {
+        let path = self.layout.metadata_lock_file(env_id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
         }
-        let content = fs::read_to_string(&path)?;
-        let meta: EnvMetadata = serde_json::from_str(&content)?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        file.lock_exclusive()
+            .map_err(|e| StoreError::LockFailed(format!("metadata entry '{env_id}': {e}")))?;
+        Ok(file)
+    }
+
+    /// Revision of the stored entry, read without verifying its checksum.
+    fn stored_revision(&self, env_id: &str) -> Result<u64, StoreError> {
+        #[derive(Deserialize)]
+        struct Stored {
+            #[serde(default)]
+            revision: u64,
+        }
+        match fs::read_to_string(self.layout.metadata_dir().join(env_id)) {
+            Ok(content) => Ok(serde_json::from_str::<Stored>(&content)?.revision),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Read-modify-write an entry: apply `f` to the stored metadata and put
+    /// it back, starting over from a fresh read when another writer changed
+    /// the entry in between. Returns the metadata as written.
+    pub fn update<E: From<StoreError>>(
+        &self,
+        env_id: &str,
+        mut f: impl FnMut(&mut EnvMetadata) -> Result<(), E>,
+    ) -> Result<EnvMetadata, E> {
+        let mut attempt = 1;
+        loop {
+            let mut meta = self.get(env_id)?;
+            f(&mut meta)?;
+            meta.updated_at = chrono::Utc::now().to_rfc3339();
+            match self.put(&meta) {
+                Ok(()) => {
+                    meta.revision += 1;
+                    return Ok(meta);
+                }
+                Err(StoreError::Conflict { .. }) if attempt < UPDATE_ATTEMPTS => {
+                    backoff(attempt);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// The stored JSON of `env_id`, `None` if there is no such entry.
+    fn read(&self, env_id: &str) -> Result<Option<String>, StoreError> {
+        match &self.backend {
+            Backend::Json => match fs::read_to_string(self.layout.metadata_dir().join(env_id)) {
+                Ok(content) => Ok(Some(content)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(db) => db.read(env_id),
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Unavailable => Err(sqlite_unavailable()),
+        }
+    }
+
+    /// The stored metadata of `env_id` without verifying its checksum, for
+    /// rollback of entries that may be damaged.
+    pub(crate) fn get_unverified(&self, env_id: &str) -> Result<Option<EnvMetadata>, StoreError> {
+        match self.read(env_id)? {
+            Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get(&self, env_id: &str) -> Result<EnvMetadata, StoreError> {
+        let content = self
+            .read(env_id)?
+            .ok_or_else(|| StoreError::EnvNotFound(env_id.to_owned()))?;
+        Self::parse(env_id, &content)
+    }
+
+    /// Parse a stored entry and verify its checksum.
+    fn parse(env_id: &str, content: &str) -> Result<EnvMetadata, StoreError> {
+        let meta: EnvMetadata = serde_json::from_str(content)?;
 
         // Verify checksum if present (backward-compatible: legacy files have None)
         if let Some(ref expected) = meta.checksum {
@@ -125,42 +500,95 @@ impl MetadataStore {
     }
 
     pub fn update_state(&self, env_id: &str, new_state: EnvState) -> Result<(), StoreError> {
-        let mut meta = self.get(env_id)?;
-        meta.state = new_state;
-        meta.updated_at = chrono::Utc::now().to_rfc3339();
-        self.put(&meta)
+        self.update(env_id, |meta| {
+            meta.state = new_state;
+            Ok(())
+        })
+        .map(drop)
+    }
+
+    /// Record the active workspace. `None` means [`DEFAULT_WORKSPACE`].
+    pub fn update_workspace(
+        &self,
+        env_id: &str,
+        workspace: Option<String>,
+    ) -> Result<(), StoreError> {
+        self.update(env_id, move |meta| {
+            meta.workspace.clone_from(&workspace);
+            Ok(())
+        })
+        .map(drop)
+    }
+
+    /// Record the snapshot the upper directory now matches.
+    pub fn update_snapshot(
+        &self,
+        env_id: &str,
+        snapshot: Option<String>,
+    ) -> Result<(), StoreError> {
+        self.update(env_id, move |meta| {
+            meta.snapshot.clone_from(&snapshot);
+            Ok(())
+        })
+        .map(drop)
+    }
+
+    /// Record that a session entered the environment just now.
+    pub fn record_entry(&self, env_id: &str) -> Result<(), StoreError> {
+        self.update(env_id, |meta| {
+            meta.record_entry();
+            Ok(())
+        })
+        .map(drop)
     }
 
     pub fn exists(&self, env_id: &str) -> bool {
-        self.layout.metadata_dir().join(env_id).exists()
+        match &self.backend {
+            Backend::Json => self.layout.metadata_dir().join(env_id).exists(),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(db) => db.exists(env_id).unwrap_or(false),
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Unavailable => false,
+        }
     }
 
     pub fn remove(&self, env_id: &str) -> Result<(), StoreError> {
+        match &self.backend {
+            Backend::Json => {}
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(db) => return db.remove(env_id),
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Unavailable => return Err(sqlite_unavailable()),
+        }
         let path = self.layout.metadata_dir().join(env_id);
-        if path.exists() {
-            fs::remove_file(path)?;
+        if !path.exists() {
+            return Ok(());
         }
+        // Writers waiting on the lock see the entry gone and conflict.
+        let _lock = self.lock_entry(env_id)?;
+        let wal = WriteAheadLog::new(&self.layout);
+        let op_id = wal.begin(WalOpKind::Index, env_id)?;
+        let removed = fs::remove_file(path);
+        let indexed = match removed {
+            Ok(()) => self.update_index(|index| index.remove(env_id)),
+            Err(_) => self.reindex(env_id),
+        };
+        if indexed.is_ok() {
+            wal.commit(&op_id)?;
+        }
+        removed?;
+        indexed?;
+        fs::remove_file(self.layout.metadata_lock_file(env_id))?;
         Ok(())
     }
 
     pub fn list(&self) -> Result<Vec<EnvMetadata>, StoreError> {
-        let dir = self.layout.metadata_dir();
-        if !dir.exists() {
-            return Ok(Vec::new());
-        }
         let mut results = Vec::new();
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            if entry.file_type()?.is_file() {
-                let name = entry.file_name();
-                let name_str = name.to_str().unwrap_or("");
-                if !name_str.starts_with('.') {
-                    match self.get(name_str) {
-                        Ok(meta) => results.push(meta),
-                        Err(e) => {
-                            tracing::warn!("skipping corrupted metadata entry '{name_str}': {e}");
-                        }
-                    }
+        for entry in self.list_with_errors()? {
+            match entry {
+                Ok(meta) => results.push(meta),
+                Err((name, e)) => {
+                    tracing::warn!("skipping corrupted metadata entry '{name}': {e}");
                 }
             }
         }
@@ -174,6 +602,21 @@ impl MetadataStore {
     pub fn list_with_errors(
         &self,
     ) -> Result<Vec<Result<EnvMetadata, (String, StoreError)>>, StoreError> {
+        match &self.backend {
+            Backend::Json => {}
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(db) => {
+                return Ok(db
+                    .read_all()?
+                    .into_iter()
+                    .map(|(env_id, content)| {
+                        Self::parse(&env_id, &content).map_err(|e| (env_id, e))
+                    })
+                    .collect());
+            }
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Unavailable => return Err(sqlite_unavailable()),
+        }
         let dir = self.layout.metadata_dir();
         if !dir.exists() {
             return Ok(Vec::new());
@@ -195,47 +638,170 @@ impl MetadataStore {
         Ok(results)
     }
 
+    /// Environments carrying every label `selectors` selects, as
+    /// [`EnvMetadata::matches_label`] reads them. The SQLite backend
+    /// answers the first selector with a query.
+    pub fn list_matching(&self, selectors: &[String]) -> Result<Vec<EnvMetadata>, StoreError> {
+        #[cfg(feature = "sqlite")]
+        if let (Backend::Sqlite(db), Some(first)) = (&self.backend, selectors.first()) {
+            let (key, value) = match first.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (first.as_str(), None),
+            };
+            let mut envs = Vec::new();
+            for env_id in db.with_label(key, value)? {
+                match self.get(&env_id) {
+                    Ok(meta) if selectors.iter().all(|s| meta.matches_label(s)) => {
+                        envs.push(meta);
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("skipping corrupted metadata entry '{env_id}': {e}"),
+                }
+            }
+            return Ok(envs);
+        }
+        let mut envs = self.list()?;
+        envs.retain(|meta| selectors.iter().all(|s| meta.matches_label(s)));
+        Ok(envs)
+    }
+
     pub fn increment_ref(&self, env_id: &str) -> Result<u32, StoreError> {
-        let mut meta = self.get(env_id)?;
-        meta.ref_count += 1;
-        meta.updated_at = chrono::Utc::now().to_rfc3339();
-        self.put(&meta)?;
+        let meta = self.update(env_id, |meta| {
+            meta.ref_count += 1;
+            Ok::<_, StoreError>(())
+        })?;
         Ok(meta.ref_count)
     }
 
     pub fn decrement_ref(&self, env_id: &str) -> Result<u32, StoreError> {
-        let mut meta = self.get(env_id)?;
-        meta.ref_count = meta.ref_count.saturating_sub(1);
-        meta.updated_at = chrono::Utc::now().to_rfc3339();
-        self.put(&meta)?;
+        let meta = self.update(env_id, |meta| {
+            meta.ref_count = meta.ref_count.saturating_sub(1);
+            Ok::<_, StoreError>(())
+        })?;
         Ok(meta.ref_count)
     }
 
+    /// Number of environments in each state, from the index.
+    pub fn state_counts(&self) -> Result<BTreeMap<String, usize>, StoreError> {
+        Ok(self.index()?.state_counts())
+    }
+
+    /// Look `key` up in the index and return the entry it points
+    /// to if `matches` it. A stale index falls back to scanning all entries.
+    fn lookup(
+        &self,
+        lookup: Lookup,
+        key: &str,
+        matches: impl Fn(&EnvMetadata) -> bool,
+    ) -> Result<Option<EnvMetadata>, StoreError> {
+        let env_id = match &self.backend {
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(db) => db.lookup(lookup, key)?,
+            _ => self.index()?.get(lookup, key).cloned(),
+        };
+        let Some(env_id) = env_id else {
+            return Ok(None);
+        };
+        match self.get(&env_id) {
+            Ok(meta) if matches(&meta) => return Ok(Some(meta)),
+            _ => tracing::debug!("metadata index entry '{key}' is stale"),
+        }
+        Ok(self.list()?.into_iter().find(|m| matches(m)))
+    }
+
     pub fn get_by_name(&self, name: &str) -> Result<EnvMetadata, StoreError> {
-        let all = self.list()?;
-        all.into_iter()
-            .find(|m| m.name.as_deref() == Some(name))
+        self.lookup(Lookup::Name, name, |m| m.name.as_deref() == Some(name))?
             .ok_or_else(|| StoreError::EnvNotFound(format!("name '{name}'")))
     }
 
+    pub fn get_by_short_id(&self, short_id: &str) -> Result<EnvMetadata, StoreError> {
+        self.lookup(Lookup::ShortId, short_id, |m| *m.short_id == *short_id)?
+            .ok_or_else(|| StoreError::EnvNotFound(format!("short id '{short_id}'")))
+    }
+
+    /// Find the environment that previously carried `alias` as its name.
+    pub fn get_by_alias(&self, alias: &str) -> Result<EnvMetadata, StoreError> {
+        self.lookup(Lookup::Alias, alias, |m| {
+            m.aliases.iter().any(|a| a == alias)
+        })?
+        .ok_or_else(|| StoreError::EnvNotFound(format!("alias '{alias}'")))
+    }
+
+    /// Find the environment whose env_id, short id or name is exactly
+    /// `key`, without reading any other entry.
+    pub fn find(&self, key: &str) -> Result<Option<EnvMetadata>, StoreError> {
+        if !key.is_empty() && !key.starts_with('.') && !key.contains('/') && self.exists(key) {
+            return self.get(key).map(Some);
+        }
+        for found in [self.get_by_short_id(key), self.get_by_name(key)] {
+            match found {
+                Ok(meta) => return Ok(Some(meta)),
+                Err(StoreError::EnvNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check that `name` is valid and not taken by an environment other
+    /// than `env_id`.
+    pub fn check_name_available(&self, env_id: &str, name: &str) -> Result<(), StoreError> {
+        validate_env_name(name)?;
+        if let Ok(existing) = self.get_by_name(name) {
+            if *existing.env_id != *env_id {
+                return Err(StoreError::NameConflict {
+                    name: name.to_owned(),
+                    existing_env_id: existing.env_id[..12.min(existing.env_id.len())].to_owned(),
+                });
+            }
+        }
+        Ok(())
+    }
+
     pub fn update_name(&self, env_id: &str, name: Option<String>) -> Result<(), StoreError> {
         if let Some(ref n) = name {
-            validate_env_name(n)?;
-            // Check uniqueness
-            if let Ok(existing) = self.get_by_name(n) {
-                if *existing.env_id != *env_id {
-                    return Err(StoreError::NameConflict {
-                        name: n.clone(),
-                        existing_env_id: existing.env_id[..12.min(existing.env_id.len())]
-                            .to_owned(),
-                    });
-                }
+            self.check_name_available(env_id, n)?;
+        }
+        self.update(env_id, |meta| {
+            meta.name.clone_from(&name);
+            Ok::<_, StoreError>(())
+        })?;
+        match name {
+            Some(name) => self.release_alias(env_id, &name),
+            None => Ok(()),
+        }
+    }
+
+    /// Rename an environment. With `keep_alias`, the previous name is kept
+    /// as an alias so existing references keep resolving.
+    pub fn rename(&self, env_id: &str, new_name: &str, keep_alias: bool) -> Result<(), StoreError> {
+        self.check_name_available(env_id, new_name)?;
+        self.update(env_id, |meta| {
+            let old = meta.name.replace(new_name.to_owned());
+            meta.aliases.retain(|a| a != new_name);
+            if let Some(old) = old.filter(|o| keep_alias && o != new_name) {
+                meta.aliases.retain(|a| *a != old);
+                meta.aliases.insert(0, old);
+                meta.aliases.truncate(MAX_ALIASES);
+            }
+            Ok::<_, StoreError>(())
+        })?;
+        self.release_alias(env_id, new_name)
+    }
+
+    /// Drop `name` from the aliases of every environment but `env_id`, which
+    /// has taken it as its name. An alias left behind would be shared once
+    /// `env_id` is renamed again and keeps it as an alias too.
+    fn release_alias(&self, env_id: &str, name: &str) -> Result<(), StoreError> {
+        for other in self.list()? {
+            if *other.env_id != *env_id && other.aliases.iter().any(|a| a == name) {
+                self.update(&other.env_id, |meta| {
+                    meta.aliases.retain(|a| a != name);
+                    Ok::<_, StoreError>(())
+                })?;
             }
         }
-        let mut meta = self.get(env_id)?;
-        meta.name = name;
-        meta.updated_at = chrono::Utc::now().to_rfc3339();
-        self.put(&meta)
+        Ok(())
     }
 }
 
@@ -264,6 +830,18 @@ mod tests {
             updated_at: "2025-01-01T00:00:00Z".to_owned(),
             ref_count: 1,
             checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            attestation: None,
         }
     }
 
@@ -519,6 +1097,224 @@ mod tests {
         assert_eq!(err_count, 1);
     }
 
+    #[test]
+    fn rename_keeps_previous_name_as_alias() {
+        let (_dir, store) = test_metadata_store();
+        store.put(&sample_meta()).unwrap();
+        store.rename("abc123def456", "first", true).unwrap();
+        store.rename("abc123def456", "second", true).unwrap();
+        store.rename("abc123def456", "third", true).unwrap();
+
+        let meta = store.get("abc123def456").unwrap();
+        assert_eq!(meta.name.as_deref(), Some("third"));
+        assert_eq!(meta.aliases, vec!["second", "first"]);
+        assert_eq!(store.get_by_alias("first").unwrap().env_id, meta.env_id);
+    }
+
+    #[test]
+    fn rename_without_alias_drops_previous_name() {
+        let (_dir, store) = test_metadata_store();
+        store.put(&sample_meta()).unwrap();
+        store.rename("abc123def456", "first", true).unwrap();
+        store.rename("abc123def456", "second", false).unwrap();
+
+        let meta = store.get("abc123def456").unwrap();
+        assert!(meta.aliases.is_empty());
+        assert!(store.get_by_alias("first").is_err());
+    }
+
+    #[test]
+    fn rename_back_removes_alias() {
+        let (_dir, store) = test_metadata_store();
+        store.put(&sample_meta()).unwrap();
+        store.rename("abc123def456", "first", true).unwrap();
+        store.rename("abc123def456", "second", true).unwrap();
+        store.rename("abc123def456", "first", true).unwrap();
+
+        let meta = store.get("abc123def456").unwrap();
+        assert_eq!(meta.name.as_deref(), Some("first"));
+        assert_eq!(meta.aliases, vec!["second"]);
+    }
+
+    #[test]
+    fn taking_a_name_releases_it_as_an_alias() {
+        let (_dir, store) = test_metadata_store();
+        let mut other = sample_meta();
+        other.env_id = "fff000111222".into();
+        other.short_id = "fff000111222".into();
+        store.put(&sample_meta()).unwrap();
+        store.put(&other).unwrap();
+
+        store.rename("abc123def456", "shared", true).unwrap();
+        store.rename("abc123def456", "moved", true).unwrap();
+        store.rename("fff000111222", "shared", true).unwrap();
+        store.rename("fff000111222", "other", true).unwrap();
+
+        assert!(store.get("abc123def456").unwrap().aliases.is_empty());
+        assert_eq!(store.get("fff000111222").unwrap().aliases, vec!["shared"]);
+        assert_eq!(store.get_by_alias("shared").unwrap().env_id, other.env_id);
+    }
+
+    #[test]
+    fn aliases_are_capped() {
+        let (_dir, store) = test_metadata_store();
+        store.put(&sample_meta()).unwrap();
+        for i in 0..=MAX_ALIASES + 2 {
+            store
+                .rename("abc123def456", &format!("name-{i}"), true)
+                .unwrap();
+        }
+        let meta = store.get("abc123def456").unwrap();
+        assert_eq!(meta.aliases.len(), MAX_ALIASES);
+        assert_eq!(meta.aliases[0], format!("name-{}", MAX_ALIASES + 1));
+    }
+
+    #[test]
+    fn empty_aliases_keep_legacy_checksum_stable() {
+        let meta = sample_meta();
+        let json = serde_json::to_string(&meta).unwrap();
+        assert!(!json.contains("aliases"));
+    }
+
+    #[test]
+    fn stale_revision_conflicts() {
+        let (_dir, store) = test_metadata_store();
+        store.put(&sample_meta()).unwrap();
+        let read = store.get("abc123def456").unwrap();
+        assert_eq!(read.revision, 1);
+
+        store.update_state("abc123def456", EnvState::Built).unwrap();
+        let mut stale = read;
+        stale.state = EnvState::Archived;
+        match store.put(&stale) {
+            Err(StoreError::Conflict {
+                expected, actual, ..
+            }) => {
+                assert_eq!(expected, 1);
+                assert_eq!(actual, 2);
+            }
+            other => panic!("expected a conflict, got {other:?}"),
+        }
+        assert_eq!(store.get("abc123def456").unwrap().state, EnvState::Built);
+
+        // Creating an entry that already exists conflicts too.
+        assert!(matches!(
+            store.put(&sample_meta()),
+            Err(StoreError::Conflict { .. })
+        ));
+        store.overwrite(&sample_meta()).unwrap();
+        assert_eq!(store.get("abc123def456").unwrap().revision, 3);
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let (_dir, store) = test_metadata_store();
+        store.put(&sample_meta()).unwrap();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        store.increment_ref("abc123def456").unwrap();
+                    }
+                });
+            }
+        });
+        let meta = store.get("abc123def456").unwrap();
+        assert_eq!(meta.ref_count, 41);
+        assert_eq!(meta.revision, 41);
+    }
+
+    #[test]
+    fn legacy_metadata_starts_at_revision_zero() {
+        let (_dir, store) = test_metadata_store();
+        let mut legacy = sample_meta();
+        legacy.checksum = Some(legacy.compute_checksum().unwrap());
+        let json = serde_json::to_string_pretty(&legacy).unwrap();
+        assert!(!json.contains("revision"));
+        fs::write(store.layout.metadata_dir().join("abc123def456"), json).unwrap();
+
+        let meta = store.get("abc123def456").unwrap();
+        assert_eq!(meta.revision, 0);
+        store.update_state("abc123def456", EnvState::Built).unwrap();
+        assert_eq!(store.get("abc123def456").unwrap().revision, 1);
+    }
+
+    #[test]
+    fn remove_drops_the_entry_lock() {
+        let (_dir, store) = test_metadata_store();
+        store.put(&sample_meta()).unwrap();
+        assert!(store.layout.metadata_lock_file("abc123def456").exists());
+        store.remove("abc123def456").unwrap();
+        assert!(!store.layout.metadata_lock_file("abc123def456").exists());
+        assert_eq!(store.list().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn index_follows_writes_renames_and_removals() {
+        let (_dir, store) = test_metadata_store();
+        let mut meta = sample_meta();
+        meta.name = Some("first".to_owned());
+        store.put(&meta).unwrap();
+        store.rename("abc123def456", "second", true).unwrap();
+        store.update_state("abc123def456", EnvState::Built).unwrap();
+
+        let index = store.stored_index().unwrap().unwrap();
+        assert_eq!(index.names.get("second").unwrap(), "abc123def456");
+        assert!(!index.names.contains_key("first"));
+        assert_eq!(index.aliases.get("first").unwrap(), "abc123def456");
+        assert_eq!(index.count(EnvState::Built), 1);
+        assert_eq!(index, MetadataIndex::build(&store.list().unwrap()));
+        assert!(WriteAheadLog::new(&store.layout)
+            .list_incomplete()
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            store.find("second").unwrap().unwrap().name.unwrap(),
+            "second"
+        );
+        assert!(store.find("abc123def456").unwrap().is_some());
+        assert!(store.find("first").unwrap().is_none());
+        assert_eq!(store.get_by_alias("first").unwrap().env_id, meta.env_id);
+
+        store.remove("abc123def456").unwrap();
+        assert!(store.stored_index().unwrap().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stale_or_missing_index_is_rebuilt() {
+        let (_dir, store) = test_metadata_store();
+        let mut meta = sample_meta();
+        meta.name = Some("named".to_owned());
+        store.put(&meta).unwrap();
+
+        // An index pointing elsewhere falls back to a scan.
+        let mut stale = store.stored_index().unwrap().unwrap();
+        stale.names.insert("named".to_owned(), "gone".to_owned());
+        store.write_index(&stale).unwrap();
+        assert_eq!(store.get_by_name("named").unwrap().env_id, meta.env_id);
+
+        fs::remove_file(store.layout.metadata_index_file()).unwrap();
+        assert_eq!(store.state_counts().unwrap().get("defined"), Some(&1));
+        assert!(store.stored_index().unwrap().is_some());
+
+        // A write interrupted before the index caught up is recovered.
+        let wal = WriteAheadLog::new(&store.layout);
+        wal.begin(WalOpKind::Index, "abc123def456").unwrap();
+        store.write_index(&MetadataIndex::default()).unwrap();
+        assert_eq!(wal.recover().unwrap(), 1);
+        assert_eq!(
+            store
+                .stored_index()
+                .unwrap()
+                .unwrap()
+                .names
+                .get("named")
+                .unwrap(),
+            "abc123def456"
+        );
+    }
+
     #[test]
     fn same_name_same_env_allowed() {
         let (_dir, store) = test_metadata_store();
@@ -4,12 +4,10 @@
 //! [`STORE_FORMAT_VERSION`]. Creates a backup of the version file before any
 //! modification and writes all changes atomically.
 
-use crate::layout::STORE_FORMAT_VERSION;
-use crate::{fsync_dir, StoreError};
+use crate::layout::{StoreLayout, STORE_FORMAT_VERSION};
+use crate::{write_atomic, MetadataBackend, MetadataStore, ObjectStore, StoreConfig, StoreError};
 use std::fs;
-use std::io::Write;
 use std::path::{Path, PathBuf};
-use tempfile::NamedTempFile;
 use tracing::{info, warn};
 
 /// Result of a successful migration.
@@ -18,6 +16,7 @@ pub struct MigrationResult {
     pub from_version: u32,
     pub to_version: u32,
     pub environments_migrated: usize,
+    pub objects_compressed: usize,
     pub backup_path: PathBuf,
 }
 
@@ -27,6 +26,8 @@ pub struct MigrationResult {
 /// - Returns `Err(VersionMismatch)` if the store is from a *newer* version.
 /// - Creates a backup of the version file at `store/version.backup.{timestamp}`.
 /// - Rewrites metadata files atomically to add any missing v2 fields.
+/// - Rebuilds the metadata index.
+/// - Compresses loose objects per `store/config.json` (v3).
 /// - Writes the new version file atomically as the final step.
 pub fn migrate_store(root: &Path) -> Result<Option<MigrationResult>, StoreError> {
     let store_dir = root.join("store");
@@ -71,7 +72,7 @@ pub fn migrate_store(root: &Path) -> Result<Option<MigrationResult>, StoreError>
         for entry in fs::read_dir(&metadata_dir)? {
             let entry = entry?;
             let path = entry.path();
-            if !path.is_file() {
+            if !path.is_file() || entry.file_name().to_string_lossy().starts_with('.') {
                 continue;
             }
             match migrate_metadata_file(&path) {
@@ -84,26 +85,85 @@ pub fn migrate_store(root: &Path) -> Result<Option<MigrationResult>, StoreError>
         }
     }
 
+    // --- Index metadata ---
+    // The index is rebuilt from the rewritten entries rather than migrated.
+    MetadataStore::new(StoreLayout::new(root)).rebuild_index()?;
+
+    // --- Compress loose objects (v3) ---
+    // Each object is rewritten atomically and stays readable either way, so
+    // an interrupted pass is simply resumed by the next migration.
+    let objects_compressed = ObjectStore::new(StoreLayout::new(root)).compress_loose()?;
+
     // --- Write new version file atomically (LAST step) ---
     let new_ver = serde_json::json!({ "format_version": STORE_FORMAT_VERSION });
     let new_content = serde_json::to_string_pretty(&new_ver).map_err(StoreError::Serialization)?;
-    let mut tmp = NamedTempFile::new_in(&store_dir)?;
-    tmp.write_all(new_content.as_bytes())?;
-    tmp.as_file().sync_all()?;
-    tmp.persist(&version_path)
-        .map_err(|e| StoreError::Io(e.error))?;
-    fsync_dir(&store_dir)?;
+    write_atomic(&store_dir, &version_path, new_content.as_bytes())?;
 
-    info!("migrated store from v{found} to v{STORE_FORMAT_VERSION} ({envs_migrated} environments)");
+    info!(
+        "migrated store from v{found} to v{STORE_FORMAT_VERSION} ({envs_migrated} environments, {objects_compressed} objects compressed)"
+    );
 
     Ok(Some(MigrationResult {
         from_version: found,
         to_version: STORE_FORMAT_VERSION,
         environments_migrated: envs_migrated,
+        objects_compressed,
         backup_path,
     }))
 }
 
+/// Move every environment's metadata to the `to` backend and select it in
+/// the store config. Returns how many environments were moved.
+///
+/// Entries are copied first and the config is switched last, so an
+/// interrupted move leaves the store on its old backend and can be run
+/// again. Entries the old backend cannot read fail the move rather than
+/// being lost. The caller holds the store lock.
+pub fn migrate_metadata_backend(root: &Path, to: MetadataBackend) -> Result<usize, StoreError> {
+    let layout = StoreLayout::new(root);
+    let mut config = StoreConfig::load(&layout)?;
+    if config.metadata_backend == to {
+        return Ok(0);
+    }
+    let source = MetadataStore::with_backend(layout.clone(), config.metadata_backend);
+    let target = MetadataStore::with_backend(layout.clone(), to);
+
+    let mut envs = Vec::new();
+    for entry in source.list_with_errors()? {
+        envs.push(entry.map_err(|(env_id, e)| {
+            StoreError::MetadataBackend(format!("cannot move metadata of {env_id}: {e}"))
+        })?);
+    }
+    // Leftovers of an earlier move the other way would come back to life.
+    for stale in target.list_with_errors()? {
+        let env_id = match stale {
+            Ok(meta) => meta.env_id.to_string(),
+            Err((env_id, _)) => env_id,
+        };
+        if !envs.iter().any(|meta| *meta.env_id == *env_id) {
+            target.remove(&env_id)?;
+        }
+    }
+    for meta in &envs {
+        target.overwrite(meta)?;
+    }
+    target.rebuild_index()?;
+
+    config.metadata_backend = to;
+    config.save(&layout)?;
+    for meta in &envs {
+        source.remove(&meta.env_id)?;
+    }
+    if to != MetadataBackend::Json {
+        let _ = fs::remove_file(layout.metadata_index_file());
+    }
+    info!(
+        "moved metadata of {} environments to the {to} backend",
+        envs.len()
+    );
+    Ok(envs.len())
+}
+
 /// Migrate a single metadata JSON file to v2 format.
 ///
 /// v2 added: `name` (Option<String>), `checksum` (Option<String>), `policy_layer` (Option).
@@ -145,11 +205,7 @@ fn migrate_metadata_file(path: &Path) -> Result<bool, StoreError> {
     // Rewrite atomically
     let new_content = serde_json::to_string_pretty(&val).map_err(StoreError::Serialization)?;
     let dir = path.parent().unwrap_or(Path::new("."));
-    let mut tmp = NamedTempFile::new_in(dir)?;
-    tmp.write_all(new_content.as_bytes())?;
-    tmp.as_file().sync_all()?;
-    tmp.persist(path).map_err(|e| StoreError::Io(e.error))?;
-    fsync_dir(dir)?;
+    write_atomic(dir, path, new_content.as_bytes())?;
 
     Ok(true)
 }
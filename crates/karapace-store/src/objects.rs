@@ -1,37 +1,152 @@
+use crate::chunking::{self, ChunkRef, CHUNK_LIST_MAGIC};
+use crate::config::{Compression, SharedStore, StoreConfig};
 use crate::layout::StoreLayout;
-use crate::{fsync_dir, StoreError};
+use crate::pack::{PackStore, RepackReport};
+use crate::{fsync_dir, write_atomic, StoreError};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::Write;
-use tempfile::NamedTempFile;
+use std::io::Read;
+use std::path::Path;
+
+/// First bytes of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
 
 /// Content-addressable object store backed by blake3 hashing.
 ///
-/// Objects are stored as files named by their blake3 hash. Writes are atomic
-/// via `NamedTempFile`, and reads verify integrity by recomputing the hash.
+/// Objects are stored as files named by the blake3 hash of their content.
+/// Writes are atomic via a temp file and rename, and reads verify integrity by
+/// recomputing the hash. With [`Compression::Zstd`] loose objects are
+/// written as zstd frames when that makes them smaller; the hash is always
+/// taken over the uncompressed content, so compressed and plain files are
+/// read alike. Objects consolidated into packs by [`repack`](Self::repack)
+/// are read transparently; loose copies take precedence.
+///
+/// Objects larger than [`StoreConfig::chunk_threshold`] are split into
+/// content-defined chunks (see [`chunking`](crate::chunking)). Each chunk is
+/// an object of its own, and the object's file holds the chunk list, so
+/// objects sharing most of their content share most of their chunks.
+///
+/// With [`StoreConfig::shared_store`] set, objects missing here are read
+/// from the shared store, and objects it has are not written again. Listing
+/// and removal only ever see this store's own objects.
 pub struct ObjectStore {
     layout: StoreLayout,
+    packs: PackStore,
+    config: StoreConfig,
+    shared: Option<Box<ObjectStore>>,
 }
 
 impl ObjectStore {
+    /// Open the object store with the settings from `store/config.json`.
+    /// An unreadable config falls back to the defaults.
     pub fn new(layout: StoreLayout) -> Self {
-        Self { layout }
+        let config = StoreConfig::load_or_default(&layout);
+        Self::with_config(layout, config)
+    }
+
+    pub fn with_config(layout: StoreLayout, config: StoreConfig) -> Self {
+        // The shared store the layout already read, unless `config` names
+        // another one.
+        let shared = config.shared_store.as_deref().map(|root| {
+            layout
+                .shared_store()
+                .filter(|shared| shared.layout.root() == root)
+                .unwrap_or_else(|| SharedStore::open(root))
+        });
+        Self::with_shared(layout, config, shared)
+    }
+
+    fn with_shared(layout: StoreLayout, config: StoreConfig, shared: Option<SharedStore>) -> Self {
+        let packs = PackStore::new(layout.clone());
+        // The shared store's objects are read as carefully as this store's.
+        let shared = shared.map(|shared| {
+            let shared_config = StoreConfig {
+                paranoid_reads: config.paranoid_reads,
+                ..shared.config
+            };
+            Box::new(Self::with_shared(shared.layout, shared_config, None))
+        });
+        Self {
+            layout,
+            packs,
+            config,
+            shared,
+        }
+    }
+
+    fn in_shared(&self, hash: &str) -> bool {
+        self.shared
+            .as_ref()
+            .is_some_and(|shared| shared.exists(hash))
+    }
+
+    pub fn config(&self) -> &StoreConfig {
+        &self.config
+    }
+
+    /// Whether a write of `hash`, loose at `dest`, can be skipped. With
+    /// [`StoreConfig::paranoid_reads`] the stored copy must also read back
+    /// intact; a damaged one is written again.
+    fn stored(&self, hash: &str, dest: &Path) -> bool {
+        if !dest.exists() && !self.in_shared(hash) {
+            return false;
+        }
+        if !self.config.paranoid_reads {
+            return true;
+        }
+        match self.get(hash) {
+            Ok(_) => true,
+            Err(e) => {
+                tracing::warn!("writing damaged object {hash} again: {e}");
+                false
+            }
+        }
     }
 
     /// Store data and return its blake3 hash. Idempotent — existing objects are skipped.
     pub fn put(&self, data: &[u8]) -> Result<String, StoreError> {
+        let threshold = self.config.chunk_threshold;
+        if threshold == 0 || data.len() as u64 <= threshold {
+            return self.put_unchunked(data);
+        }
+        let hash = blake3::hash(data).to_hex().to_string();
+        let dest = self.layout.objects_dir().join(&hash);
+        if self.stored(&hash, &dest) {
+            return Ok(hash);
+        }
+        let chunks = chunking::split(data)
+            .into_iter()
+            .map(|chunk| {
+                Ok(ChunkRef {
+                    hash: self.put_unchunked(chunk)?,
+                    len: chunk.len() as u64,
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        write_atomic(
+            &self.layout.objects_dir(),
+            &dest,
+            &chunking::encode_chunk_list(&chunks),
+        )?;
+        Ok(hash)
+    }
+
+    /// Store data as a single object regardless of its size. Used for
+    /// chunks, which must not be chunked again.
+    pub fn put_unchunked(&self, data: &[u8]) -> Result<String, StoreError> {
         let hash = blake3::hash(data).to_hex().to_string();
         let dest = self.layout.objects_dir().join(&hash);
 
-        if dest.exists() {
+        if self.stored(&hash, &dest) {
             return Ok(hash);
         }
 
-        let dir = self.layout.objects_dir();
-        let mut tmp = NamedTempFile::new_in(&dir)?;
-        tmp.write_all(data)?;
-        tmp.as_file().sync_all()?;
-        tmp.persist(&dest).map_err(|e| StoreError::Io(e.error))?;
-        fsync_dir(&dir)?;
+        let encoded = self.encode(data)?;
+        write_atomic(
+            &self.layout.objects_dir(),
+            &dest,
+            encoded.as_deref().unwrap_or(data),
+        )?;
 
         Ok(hash)
     }
@@ -40,33 +155,251 @@ impl ObjectStore {
     pub fn get(&self, hash: &str) -> Result<Vec<u8>, StoreError> {
         let path = self.layout.objects_dir().join(hash);
         if !path.exists() {
-            return Err(StoreError::ObjectNotFound(hash.to_owned()));
+            if let Some(data) = self.packs.get(hash)? {
+                return Ok(data);
+            }
+            return match &self.shared {
+                Some(shared) => shared.get(hash),
+                None => Err(StoreError::ObjectNotFound(hash.to_owned())),
+            };
+        }
+        let raw = fs::read(&path)?;
+        if let Some(chunks) = chunking::as_chunk_list(hash, &raw) {
+            return self.assemble(hash, &chunks);
         }
-        let data = fs::read(&path)?;
+        decode(hash, raw).map(|(data, _)| data)
+    }
 
-        let actual = blake3::hash(&data);
-        let actual_hex = actual.to_hex();
-        if actual_hex.as_str() != hash {
+    /// Concatenate the chunks of `hash` and verify the result.
+    fn assemble(&self, hash: &str, chunks: &[ChunkRef]) -> Result<Vec<u8>, StoreError> {
+        let total: u64 = chunks.iter().map(|c| c.len).sum();
+        let mut data = Vec::with_capacity(usize::try_from(total).unwrap_or(0));
+        for chunk in chunks {
+            data.extend_from_slice(&self.get(&chunk.hash)?);
+        }
+        let actual = crate::verify_hash(&data).to_hex();
+        if actual.as_str() != hash {
             return Err(StoreError::IntegrityFailure {
                 hash: hash.to_owned(),
                 expected: hash.to_owned(),
-                actual: actual_hex.to_string(),
+                actual: actual.to_string(),
             });
         }
-
         Ok(data)
     }
 
+    /// Write object `hash` to a new file at `dest`. When the object is
+    /// stored loose and uncompressed, `dest` is a reflink of it and shares
+    /// its extents on filesystems that support that (btrfs, XFS); otherwise
+    /// the content is copied. The content is verified either way, and with
+    /// [`StoreConfig::paranoid_reads`] `dest` is read back and verified too.
+    pub fn materialize(&self, hash: &str, dest: &Path) -> Result<(), StoreError> {
+        if let Some(shared) = &self.shared {
+            if !self.exists_here(hash) {
+                return shared.materialize(hash, dest);
+            }
+        }
+        let data = self.get(hash)?;
+        let src = self.layout.objects_dir().join(hash);
+        let plain = fs::metadata(&src).is_ok_and(|m| m.len() == data.len() as u64);
+        if !(plain && reflink(&src, dest).is_ok()) {
+            fs::write(dest, &data)?;
+        }
+        if self.config.paranoid_reads {
+            let actual = crate::verify_hash(&fs::read(dest)?).to_hex();
+            if actual.as_str() != hash {
+                return Err(StoreError::IntegrityFailure {
+                    hash: hash.to_owned(),
+                    expected: hash.to_owned(),
+                    actual: actual.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The chunks of a chunked object, or `None` when it is stored whole
+    /// (or not at all).
+    pub fn chunk_list(&self, hash: &str) -> Result<Option<Vec<ChunkRef>>, StoreError> {
+        let path = self.layout.objects_dir().join(hash);
+        let mut file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return match &self.shared {
+                    Some(shared) => shared.chunk_list(hash),
+                    None => Ok(None),
+                };
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut raw = Vec::new();
+        (&mut file)
+            .take(CHUNK_LIST_MAGIC.len() as u64)
+            .read_to_end(&mut raw)?;
+        if raw != CHUNK_LIST_MAGIC {
+            return Ok(None);
+        }
+        file.read_to_end(&mut raw)?;
+        Ok(chunking::as_chunk_list(hash, &raw))
+    }
+
+    /// `objects` followed by the chunks of those that are chunked, without
+    /// duplicates. Collecting garbage from this set keeps chunks alive.
+    pub fn with_chunks(
+        &self,
+        objects: impl IntoIterator<Item = String>,
+    ) -> Result<Vec<String>, StoreError> {
+        let mut seen = HashSet::new();
+        let mut all = Vec::new();
+        for hash in objects {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            let chunks = self.chunk_list(&hash)?;
+            all.push(hash);
+            for chunk in chunks.into_iter().flatten() {
+                if seen.insert(chunk.hash.clone()) {
+                    all.push(chunk.hash);
+                }
+            }
+        }
+        Ok(all)
+    }
+
+    /// Record `hash` as the concatenation of `chunks`, which must already
+    /// be stored. The chunks are read back and hashed first, so a wrong
+    /// list is refused with [`StoreError::IntegrityFailure`].
+    pub fn put_chunk_list(&self, hash: &str, chunks: &[ChunkRef]) -> Result<(), StoreError> {
+        let dest = self.layout.objects_dir().join(hash);
+        if self.stored(hash, &dest) {
+            return Ok(());
+        }
+        let mut hasher = blake3::Hasher::new();
+        for chunk in chunks {
+            let data = self.get(&chunk.hash)?;
+            if data.len() as u64 != chunk.len {
+                return Err(StoreError::IntegrityFailure {
+                    hash: chunk.hash.clone(),
+                    expected: format!("{} bytes", chunk.len),
+                    actual: format!("{} bytes", data.len()),
+                });
+            }
+            hasher.update(&data);
+        }
+        let actual = hasher.finalize().to_hex();
+        if actual.as_str() != hash {
+            return Err(StoreError::IntegrityFailure {
+                hash: hash.to_owned(),
+                expected: hash.to_owned(),
+                actual: actual.to_string(),
+            });
+        }
+        write_atomic(
+            &self.layout.objects_dir(),
+            &dest,
+            &chunking::encode_chunk_list(chunks),
+        )
+    }
+
+    /// Compressed form of `data`, or `None` when it should be stored as is.
+    fn encode(&self, data: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        if self.config.compression == Compression::None {
+            return Ok(None);
+        }
+        let compressed = zstd::encode_all(data, self.config.compression_level)?;
+        Ok((compressed.len() < data.len()).then_some(compressed))
+    }
+
+    /// Compress every plain loose object that shrinks under the store's
+    /// compression setting. Corrupted objects are left alone. Returns the
+    /// number of objects rewritten.
+    pub fn compress_loose(&self) -> Result<usize, StoreError> {
+        let dir = self.layout.objects_dir();
+        if self.config.compression == Compression::None || !dir.exists() {
+            return Ok(0);
+        }
+        let mut compressed = 0;
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            if name.starts_with('.') || !entry.file_type()?.is_file() {
+                continue;
+            }
+            let Ok((data, false)) = decode(&name, fs::read(entry.path())?) else {
+                continue;
+            };
+            if let Some(encoded) = self.encode(&data)? {
+                write_atomic(&dir, &entry.path(), &encoded)?;
+                compressed += 1;
+            }
+        }
+        Ok(compressed)
+    }
+
+    /// Bytes each object occupies on disk, keyed by hash. Loose copies take
+    /// precedence over packed ones.
+    pub fn stored_sizes(&self) -> Result<HashMap<String, u64>, StoreError> {
+        let mut sizes = self.packs.object_sizes()?;
+        let dir = self.layout.objects_dir();
+        if dir.exists() {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if let Some(name) = entry.file_name().to_str() {
+                    if !name.starts_with('.') {
+                        sizes.insert(name.to_owned(), entry.metadata()?.len());
+                    }
+                }
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Whether the object is stored here or in the shared store.
     pub fn exists(&self, hash: &str) -> bool {
-        self.layout.objects_dir().join(hash).exists()
+        self.exists_here(hash) || self.in_shared(hash)
+    }
+
+    /// Whether the object is stored in this store itself.
+    pub fn exists_here(&self, hash: &str) -> bool {
+        self.layout.objects_dir().join(hash).exists() || self.packs.contains(hash)
     }
 
     pub fn remove(&self, hash: &str) -> Result<(), StoreError> {
+        if !self.remove_loose(hash)? {
+            self.packs
+                .remove_objects(&HashSet::from([hash.to_owned()]))?;
+        }
+        Ok(())
+    }
+
+    /// Remove the loose copy of an object. Returns whether one existed.
+    pub(crate) fn remove_loose(&self, hash: &str) -> Result<bool, StoreError> {
         let path = self.layout.objects_dir().join(hash);
         if path.exists() {
             fs::remove_file(path)?;
+            return Ok(true);
         }
-        Ok(())
+        Ok(false)
+    }
+
+    /// Remove several objects, rewriting each affected pack once.
+    /// Returns the number of distinct objects removed.
+    pub fn remove_many(&self, hashes: &HashSet<String>) -> Result<usize, StoreError> {
+        let mut removed = HashSet::new();
+        for hash in hashes {
+            if self.remove_loose(hash)? {
+                removed.insert(hash.as_str());
+            }
+        }
+        let packed = self.packs.list_objects()?;
+        let packed: HashSet<String> = packed.into_iter().filter(|h| hashes.contains(h)).collect();
+        if !packed.is_empty() {
+            self.packs.remove_objects(&packed)?;
+            removed.extend(packed.iter().map(String::as_str));
+        }
+        Ok(removed.len())
     }
 
     pub fn list(&self) -> Result<Vec<String>, StoreError> {
@@ -83,9 +416,105 @@ impl ObjectStore {
                 }
             }
         }
+        hashes.extend(self.packs.list_objects()?);
         hashes.sort();
+        hashes.dedup();
         Ok(hashes)
     }
+
+    /// Consolidate loose objects smaller than `threshold` bytes, together with
+    /// all existing packs, into a single pack. Loose objects are deleted only
+    /// after the pack is durable, and corrupted ones are left in place.
+    pub fn repack(&self, threshold: u64) -> Result<RepackReport, StoreError> {
+        let dir = self.layout.objects_dir();
+        let mut loose = BTreeMap::new();
+        if dir.exists() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+                    continue;
+                };
+                if name.starts_with('.') || !entry.file_type()?.is_file() {
+                    continue;
+                }
+                if entry.metadata()?.len() >= threshold {
+                    continue;
+                }
+                if let Ok((data, _)) = decode(&name, fs::read(entry.path())?) {
+                    loose.insert(name, data);
+                }
+            }
+        }
+
+        let loose_hashes: Vec<String> = loose.keys().cloned().collect();
+        let (pack_id, packs_merged) = self.packs.consolidate(loose)?;
+        for hash in &loose_hashes {
+            fs::remove_file(dir.join(hash))?;
+        }
+        if !loose_hashes.is_empty() {
+            fsync_dir(&dir)?;
+        }
+
+        Ok(RepackReport {
+            loose_packed: loose_hashes.len(),
+            packs_merged,
+            pack_id,
+        })
+    }
+
+    /// Move every packed object back to a loose file and delete all packs.
+    pub fn unpack(&self) -> Result<usize, StoreError> {
+        let dir = self.layout.objects_dir();
+        fs::create_dir_all(&dir)?;
+        self.packs.explode(&dir)
+    }
+}
+
+/// Make `dest` a copy-on-write clone of `src` with `FICLONE`.
+#[cfg(target_os = "linux")]
+#[allow(unsafe_code)]
+fn reflink(src: &Path, dest: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let src = fs::File::open(src)?;
+    let dest = fs::File::create(dest)?;
+    // SAFETY: FICLONE reads one int argument; both descriptors stay open
+    // for the duration of the call.
+    let rc = unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_src: &Path, _dest: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Turn the bytes of a loose object file back into its content and verify
+/// it against `hash`. Also returns whether the file was compressed.
+///
+/// A file starting with the zstd magic is decompressed first, but plain
+/// content may start with those bytes too, so the raw bytes are checked as
+/// well before reporting a mismatch.
+fn decode(hash: &str, raw: Vec<u8>) -> Result<(Vec<u8>, bool), StoreError> {
+    if raw.starts_with(&ZSTD_MAGIC) {
+        if let Ok(data) = zstd::decode_all(raw.as_slice()) {
+            if crate::verify_hash(&data).to_hex().as_str() == hash {
+                return Ok((data, true));
+            }
+        }
+    }
+    let actual = crate::verify_hash(&raw).to_hex();
+    if actual.as_str() != hash {
+        return Err(StoreError::IntegrityFailure {
+            hash: hash.to_owned(),
+            expected: hash.to_owned(),
+            actual: actual.to_string(),
+        });
+    }
+    Ok((raw, false))
 }
 
 #[cfg(test)]
@@ -200,4 +629,305 @@ mod tests {
         let h3 = store.put(b"different").unwrap();
         assert_ne!(h1, h3);
     }
+
+    #[test]
+    fn repack_moves_small_objects_into_pack() {
+        let (dir, store) = test_store();
+        let small = store.put(b"small").unwrap();
+        // Incompressible, so it stays above the threshold on disk.
+        let mut noise = vec![0u8; 4096];
+        blake3::Hasher::new()
+            .update(b"large")
+            .finalize_xof()
+            .fill(&mut noise);
+        let large = store.put(&noise).unwrap();
+
+        let report = store.repack(1024).unwrap();
+        assert_eq!(report.loose_packed, 1);
+        assert!(report.pack_id.is_some());
+
+        let objects_dir = StoreLayout::new(dir.path()).objects_dir();
+        assert!(!objects_dir.join(&small).exists());
+        assert!(objects_dir.join(&large).exists());
+        assert_eq!(store.get(&small).unwrap(), b"small");
+        assert!(store.exists(&small));
+        assert_eq!(store.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn repack_skips_corrupted_loose_objects() {
+        let (dir, store) = test_store();
+        let hash = store.put(b"original").unwrap();
+        let path = StoreLayout::new(dir.path()).objects_dir().join(&hash);
+        fs::write(&path, b"tampered").unwrap();
+
+        let report = store.repack(1024).unwrap();
+        assert_eq!(report.loose_packed, 0);
+        assert!(path.exists());
+        assert!(store.get(&hash).is_err());
+    }
+
+    #[test]
+    fn unpack_restores_loose_objects() {
+        let (dir, store) = test_store();
+        let hash = store.put(b"roundtrip").unwrap();
+        store.repack(1024).unwrap();
+        assert_eq!(store.unpack().unwrap(), 1);
+
+        let layout = StoreLayout::new(dir.path());
+        assert!(layout.objects_dir().join(&hash).exists());
+        assert!(PackStore::new(layout).list_packs().unwrap().is_empty());
+        assert_eq!(store.get(&hash).unwrap(), b"roundtrip");
+    }
+
+    #[test]
+    fn compressible_objects_are_stored_compressed() {
+        let (dir, store) = test_store();
+        let data = vec![b'k'; 64 * 1024];
+        let hash = store.put(&data).unwrap();
+        assert_eq!(hash, blake3::hash(&data).to_hex().as_str());
+
+        let raw = fs::read(StoreLayout::new(dir.path()).objects_dir().join(&hash)).unwrap();
+        assert!(raw.starts_with(&ZSTD_MAGIC));
+        assert!(raw.len() < data.len());
+        assert_eq!(store.get(&hash).unwrap(), data);
+    }
+
+    #[test]
+    fn materialize_writes_verified_content() {
+        let (dir, store) = test_store();
+        let out = tempfile::tempdir().unwrap();
+        let compressed = store.put(&vec![b'm'; 64 * 1024]).unwrap();
+        let plain = store.put(b"plain").unwrap();
+        store
+            .materialize(&compressed, &out.path().join("a"))
+            .unwrap();
+        store.materialize(&plain, &out.path().join("b")).unwrap();
+        assert_eq!(
+            fs::read(out.path().join("a")).unwrap(),
+            vec![b'm'; 64 * 1024]
+        );
+        assert_eq!(fs::read(out.path().join("b")).unwrap(), b"plain");
+
+        let objects = StoreLayout::new(dir.path()).objects_dir();
+        fs::write(objects.join(&plain), b"plaiN").unwrap();
+        assert!(store.materialize(&plain, &out.path().join("c")).is_err());
+        assert!(!out.path().join("c").exists());
+    }
+
+    #[test]
+    fn incompressible_and_uncompressed_objects_stay_plain() {
+        let (dir, store) = test_store();
+        let objects = StoreLayout::new(dir.path()).objects_dir();
+        let small = store.put(b"tiny").unwrap();
+        assert_eq!(fs::read(objects.join(&small)).unwrap(), b"tiny");
+
+        let plain = ObjectStore::with_config(
+            StoreLayout::new(dir.path()),
+            StoreConfig {
+                compression: Compression::None,
+                ..StoreConfig::default()
+            },
+        );
+        let data = vec![b'p'; 4096];
+        let hash = plain.put(&data).unwrap();
+        assert_eq!(fs::read(objects.join(&hash)).unwrap(), data);
+        // Either store reads both kinds of file.
+        assert_eq!(store.get(&hash).unwrap(), data);
+    }
+
+    #[test]
+    fn plain_content_with_zstd_magic_roundtrips() {
+        let (_dir, store) = test_store();
+        let frame = zstd::encode_all(&b"inner"[..], 3).unwrap();
+        let hash = store.put(&frame).unwrap();
+        assert_eq!(store.get(&hash).unwrap(), frame);
+    }
+
+    #[test]
+    fn corrupted_compressed_object_detected() {
+        let (dir, store) = test_store();
+        let hash = store.put(&vec![b'c'; 8192]).unwrap();
+        let path = StoreLayout::new(dir.path()).objects_dir().join(&hash);
+        let tampered = zstd::encode_all(&vec![b'd'; 8192][..], 3).unwrap();
+        fs::write(&path, tampered).unwrap();
+        assert!(matches!(
+            store.get(&hash),
+            Err(StoreError::IntegrityFailure { .. })
+        ));
+    }
+
+    #[test]
+    fn paranoid_reads_write_damaged_objects_again() {
+        let (dir, store) = test_store();
+        let layout = StoreLayout::new(dir.path());
+        let hash = store.put(b"trusted content").unwrap();
+        let path = layout.objects_dir().join(&hash);
+        fs::write(&path, b"bit rot").unwrap();
+
+        // By default an existing file is taken for the object.
+        store.put(b"trusted content").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"bit rot");
+
+        let paranoid = ObjectStore::with_config(
+            layout,
+            StoreConfig {
+                paranoid_reads: true,
+                ..StoreConfig::default()
+            },
+        );
+        let before = crate::verified_bytes();
+        paranoid.put(b"trusted content").unwrap();
+        assert_eq!(paranoid.get(&hash).unwrap(), b"trusted content");
+        assert!(crate::verified_bytes() > before);
+
+        let dest = dir.path().join("materialized");
+        paranoid.materialize(&hash, &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"trusted content");
+    }
+
+    #[test]
+    fn compress_loose_rewrites_plain_objects() {
+        let (dir, store) = test_store();
+        let layout = StoreLayout::new(dir.path());
+        let plain = ObjectStore::with_config(
+            layout.clone(),
+            StoreConfig {
+                compression: Compression::None,
+                ..StoreConfig::default()
+            },
+        );
+        let data = vec![b'm'; 32 * 1024];
+        let big = plain.put(&data).unwrap();
+        let tiny = plain.put(b"x").unwrap();
+
+        assert_eq!(store.compress_loose().unwrap(), 1);
+        assert!(fs::read(layout.objects_dir().join(&big))
+            .unwrap()
+            .starts_with(&ZSTD_MAGIC));
+        assert_eq!(store.get(&big).unwrap(), data);
+        assert_eq!(store.get(&tiny).unwrap(), b"x");
+        assert_eq!(store.compress_loose().unwrap(), 0);
+        assert_eq!(plain.compress_loose().unwrap(), 0);
+    }
+
+    #[test]
+    fn repack_decompresses_into_packs() {
+        let (_dir, store) = test_store();
+        let data = vec![b'r'; 2048];
+        let hash = store.put(&data).unwrap();
+        assert_eq!(store.repack(16 * 1024).unwrap().loose_packed, 1);
+        assert_eq!(store.get(&hash).unwrap(), data);
+    }
+
+    #[test]
+    fn remove_packed_object() {
+        let (_dir, store) = test_store();
+        let a = store.put(b"a").unwrap();
+        let b = store.put(b"b").unwrap();
+        store.repack(1024).unwrap();
+
+        store.remove(&a).unwrap();
+        assert!(!store.exists(&a));
+        assert_eq!(store.get(&b).unwrap(), b"b");
+    }
+
+    #[test]
+    fn large_objects_are_chunked_and_share_chunks() {
+        let (dir, store) = test_store();
+        let data = chunking::noise(12 * 1024 * 1024, 3);
+        let hash = store.put(&data).unwrap();
+        let chunks = store.chunk_list(&hash).unwrap().unwrap();
+        assert!(chunks.len() > 2);
+        assert_eq!(chunks.iter().map(|c| c.len).sum::<u64>(), data.len() as u64);
+        assert_eq!(store.get(&hash).unwrap(), data);
+        assert!(
+            fs::metadata(StoreLayout::new(dir.path()).objects_dir().join(&hash))
+                .unwrap()
+                .len()
+                < 4096
+        );
+
+        // A small edit stores only the chunks around it.
+        let before = store.list().unwrap().len();
+        let mut edited = data.clone();
+        edited[6_000_000] ^= 0xff;
+        let edited_hash = store.put(&edited).unwrap();
+        assert_eq!(store.get(&edited_hash).unwrap(), edited);
+        let added = store.list().unwrap().len() - before;
+        assert!(added <= 3, "{added} new objects");
+
+        let all = store.with_chunks(vec![hash.clone()]).unwrap();
+        assert_eq!(all.len(), chunks.len() + 1);
+        assert_eq!(all[0], hash);
+        assert_eq!(store.with_chunks(vec!["x".to_owned()]).unwrap(), ["x"]);
+    }
+
+    #[test]
+    fn chunking_can_be_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ObjectStore::with_config(
+            StoreLayout::new(dir.path()),
+            StoreConfig {
+                chunk_threshold: 0,
+                ..StoreConfig::default()
+            },
+        );
+        StoreLayout::new(dir.path()).initialize().unwrap();
+        let data = chunking::noise(6 * 1024 * 1024, 5);
+        let hash = store.put(&data).unwrap();
+        assert!(store.chunk_list(&hash).unwrap().is_none());
+        assert_eq!(store.list().unwrap(), [hash]);
+    }
+
+    #[test]
+    fn missing_or_corrupted_chunk_fails_the_object() {
+        let (_dir, store) = test_store();
+        let data = chunking::noise(9 * 1024 * 1024, 9);
+        let hash = store.put(&data).unwrap();
+        let chunks = store.chunk_list(&hash).unwrap().unwrap();
+        store.remove(&chunks[1].hash).unwrap();
+        assert!(matches!(
+            store.get(&hash),
+            Err(StoreError::ObjectNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn put_chunk_list_verifies_the_chunks() {
+        let (_dir, store) = test_store();
+        let parts: [&[u8]; 2] = [b"hello ", b"chunks"];
+        let chunks: Vec<ChunkRef> = parts
+            .iter()
+            .map(|p| ChunkRef {
+                hash: store.put_unchunked(p).unwrap(),
+                len: p.len() as u64,
+            })
+            .collect();
+        let whole = blake3::hash(b"hello chunks").to_hex().to_string();
+
+        let mut swapped = chunks.clone();
+        swapped.reverse();
+        assert!(matches!(
+            store.put_chunk_list(&whole, &swapped),
+            Err(StoreError::IntegrityFailure { .. })
+        ));
+        assert!(!store.exists(&whole));
+
+        store.put_chunk_list(&whole, &chunks).unwrap();
+        assert_eq!(store.get(&whole).unwrap(), b"hello chunks");
+        assert_eq!(store.chunk_list(&whole).unwrap().unwrap(), chunks);
+    }
+
+    #[test]
+    fn content_that_looks_like_a_chunk_list_stays_plain() {
+        let (_dir, store) = test_store();
+        let fake = chunking::encode_chunk_list(&[ChunkRef {
+            hash: "0".repeat(64),
+            len: 1,
+        }]);
+        let hash = store.put(&fake).unwrap();
+        assert!(store.chunk_list(&hash).unwrap().is_none());
+        assert_eq!(store.get(&hash).unwrap(), fake);
+    }
 }
@@ -0,0 +1,369 @@
+use crate::layout::StoreLayout;
+use crate::{write_atomic, StoreError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Objects smaller than this many bytes are consolidated into packs by default.
+pub const DEFAULT_PACK_THRESHOLD: u64 = 16 * 1024;
+
+const PACK_FORMAT_VERSION: u32 = 1;
+
+/// Location of a single object inside a `.pack` file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PackEntry {
+    pub hash: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Index stored next to each pack as `<pack_id>.idx`. Entries are sorted by hash.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PackIndex {
+    pub format_version: u32,
+    pub entries: Vec<PackEntry>,
+}
+
+impl PackIndex {
+    fn find(&self, hash: &str) -> Option<&PackEntry> {
+        self.entries
+            .binary_search_by(|e| e.hash.as_str().cmp(hash))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RepackReport {
+    /// Loose objects moved into the pack.
+    pub loose_packed: usize,
+    /// Existing packs merged into the new pack.
+    pub packs_merged: usize,
+    /// Identifier of the resulting pack, if one was written.
+    pub pack_id: Option<String>,
+}
+
+/// Packfiles consolidating small objects to save inodes and space.
+///
+/// A pack is a `<id>.pack` file holding object bytes back to back, plus a
+/// `<id>.idx` JSON index. The id is the blake3 hash of the pack data. The
+/// index is written last, so a pack without an index is ignored. Every read
+/// re-hashes the object, exactly like loose objects.
+pub struct PackStore {
+    layout: StoreLayout,
+}
+
+impl PackStore {
+    pub fn new(layout: StoreLayout) -> Self {
+        Self { layout }
+    }
+
+    fn pack_path(&self, id: &str) -> PathBuf {
+        self.layout.packs_dir().join(format!("{id}.pack"))
+    }
+
+    fn index_path(&self, id: &str) -> PathBuf {
+        self.layout.packs_dir().join(format!("{id}.idx"))
+    }
+
+    /// List pack ids that have a complete index.
+    pub fn list_packs(&self) -> Result<Vec<String>, StoreError> {
+        let dir = self.layout.packs_dir();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if let Some(id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.strip_suffix(".idx"))
+            {
+                if !id.starts_with('.') {
+                    ids.push(id.to_owned());
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn read_index(&self, id: &str) -> Result<PackIndex, StoreError> {
+        let content = fs::read_to_string(self.index_path(id))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn find(&self, hash: &str) -> Result<Option<(String, PackEntry)>, StoreError> {
+        for id in self.list_packs()? {
+            let index = self.read_index(&id)?;
+            if let Some(entry) = index.find(hash) {
+                return Ok(Some((id, entry.clone())));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        matches!(self.find(hash), Ok(Some(_)))
+    }
+
+    /// Read a packed object, verifying its hash. `None` if no pack holds it.
+    pub fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        let Some((id, entry)) = self.find(hash)? else {
+            return Ok(None);
+        };
+        self.read_entry(&id, &entry).map(Some)
+    }
+
+    fn read_entry(&self, id: &str, entry: &PackEntry) -> Result<Vec<u8>, StoreError> {
+        let mut file = fs::File::open(self.pack_path(id))?;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        let len = usize::try_from(entry.len)
+            .map_err(|_| std::io::Error::other(format!("pack entry too large: {}", entry.len)))?;
+        let mut data = vec![0u8; len];
+        file.read_exact(&mut data)?;
+
+        let actual = crate::verify_hash(&data).to_hex();
+        if actual.as_str() != entry.hash {
+            return Err(StoreError::IntegrityFailure {
+                hash: entry.hash.clone(),
+                expected: entry.hash.clone(),
+                actual: actual.to_string(),
+            });
+        }
+        Ok(data)
+    }
+
+    /// All object hashes held in packs, sorted and deduplicated.
+    pub fn list_objects(&self) -> Result<Vec<String>, StoreError> {
+        let mut hashes = Vec::new();
+        for id in self.list_packs()? {
+            hashes.extend(self.read_index(&id)?.entries.into_iter().map(|e| e.hash));
+        }
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    /// Length of every packed object, keyed by hash.
+    pub fn object_sizes(&self) -> Result<HashMap<String, u64>, StoreError> {
+        let mut sizes = HashMap::new();
+        for id in self.list_packs()? {
+            for entry in self.read_index(&id)?.entries {
+                sizes.insert(entry.hash, entry.len);
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Read every object of a pack.
+    fn read_all(&self, id: &str) -> Result<Vec<(String, Vec<u8>)>, StoreError> {
+        let index = self.read_index(id)?;
+        index
+            .entries
+            .iter()
+            .map(|e| Ok((e.hash.clone(), self.read_entry(id, e)?)))
+            .collect()
+    }
+
+    /// Write a new pack holding `objects` and return its id. Data is durable
+    /// before the index appears.
+    pub fn write_pack(&self, objects: &BTreeMap<String, Vec<u8>>) -> Result<String, StoreError> {
+        let dir = self.layout.packs_dir();
+        fs::create_dir_all(&dir)?;
+
+        let mut pack = Vec::with_capacity(objects.values().map(Vec::len).sum());
+        let mut entries = Vec::with_capacity(objects.len());
+        for (hash, data) in objects {
+            entries.push(PackEntry {
+                hash: hash.clone(),
+                offset: pack.len() as u64,
+                len: data.len() as u64,
+            });
+            pack.extend_from_slice(data);
+        }
+        let id = blake3::hash(&pack).to_hex().to_string();
+        write_atomic(&dir, &self.pack_path(&id), &pack)?;
+
+        let index = PackIndex {
+            format_version: PACK_FORMAT_VERSION,
+            entries,
+        };
+        let index = serde_json::to_string_pretty(&index)?;
+        write_atomic(&dir, &self.index_path(&id), index.as_bytes())?;
+
+        Ok(id)
+    }
+
+    /// Delete a pack. The index goes first so readers never see a dangling entry.
+    pub fn remove_pack(&self, id: &str) -> Result<(), StoreError> {
+        let index = self.index_path(id);
+        if index.exists() {
+            fs::remove_file(index)?;
+        }
+        let pack = self.pack_path(id);
+        if pack.exists() {
+            fs::remove_file(pack)?;
+        }
+        Ok(())
+    }
+
+    /// Drop `hashes` from every pack that holds them by rewriting those packs.
+    /// Returns the number of distinct objects removed.
+    pub fn remove_objects(&self, hashes: &HashSet<String>) -> Result<usize, StoreError> {
+        let mut removed = HashSet::new();
+        for id in self.list_packs()? {
+            let index = self.read_index(&id)?;
+            if !index.entries.iter().any(|e| hashes.contains(&e.hash)) {
+                continue;
+            }
+            let mut keep = BTreeMap::new();
+            for entry in &index.entries {
+                if hashes.contains(&entry.hash) {
+                    removed.insert(entry.hash.clone());
+                } else {
+                    keep.insert(entry.hash.clone(), self.read_entry(&id, entry)?);
+                }
+            }
+            if !keep.is_empty() && self.write_pack(&keep)? == id {
+                continue;
+            }
+            self.remove_pack(&id)?;
+        }
+        Ok(removed.len())
+    }
+
+    /// Merge every existing pack plus `loose` into a single pack and remove
+    /// the merged packs. Returns the new pack id and the number merged.
+    pub(crate) fn consolidate(
+        &self,
+        loose: BTreeMap<String, Vec<u8>>,
+    ) -> Result<(Option<String>, usize), StoreError> {
+        let existing = self.list_packs()?;
+        if loose.is_empty() && existing.len() <= 1 {
+            return Ok((existing.into_iter().next(), 0));
+        }
+        let mut objects = loose;
+        for id in &existing {
+            objects.extend(self.read_all(id)?);
+        }
+        let new_id = self.write_pack(&objects)?;
+        for id in existing.iter().filter(|id| **id != new_id) {
+            self.remove_pack(id)?;
+        }
+        Ok((Some(new_id), existing.len()))
+    }
+
+    /// Move every packed object back into the loose object directory and
+    /// delete all packs. Returns the number of objects unpacked.
+    pub(crate) fn explode(&self, objects_dir: &std::path::Path) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for id in self.list_packs()? {
+            for (hash, data) in self.read_all(&id)? {
+                let dest = objects_dir.join(&hash);
+                if !dest.exists() {
+                    write_atomic(objects_dir, &dest, &data)?;
+                }
+                count += 1;
+            }
+            self.remove_pack(&id)?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_packs() -> (tempfile::TempDir, PackStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        (dir, PackStore::new(layout))
+    }
+
+    fn objects(items: &[&[u8]]) -> BTreeMap<String, Vec<u8>> {
+        items
+            .iter()
+            .map(|d| (blake3::hash(d).to_hex().to_string(), d.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn write_and_read_pack() {
+        let (_dir, packs) = test_packs();
+        let objs = objects(&[b"one", b"two", b"three"]);
+        let id = packs.write_pack(&objs).unwrap();
+
+        assert_eq!(packs.list_packs().unwrap(), vec![id]);
+        for (hash, data) in &objs {
+            assert_eq!(packs.get(hash).unwrap().as_deref(), Some(data.as_slice()));
+        }
+        assert!(packs.get("missing").unwrap().is_none());
+        assert_eq!(packs.list_objects().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn corrupted_pack_is_detected() {
+        let (_dir, packs) = test_packs();
+        let objs = objects(&[b"payload"]);
+        let id = packs.write_pack(&objs).unwrap();
+        fs::write(packs.pack_path(&id), b"PAYLOAD").unwrap();
+
+        let hash = objs.keys().next().unwrap();
+        assert!(matches!(
+            packs.get(hash),
+            Err(StoreError::IntegrityFailure { .. })
+        ));
+    }
+
+    #[test]
+    fn pack_without_index_is_ignored() {
+        let (_dir, packs) = test_packs();
+        let id = packs.write_pack(&objects(&[b"x"])).unwrap();
+        fs::remove_file(packs.index_path(&id)).unwrap();
+        assert!(packs.list_packs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn remove_objects_rewrites_pack() {
+        let (_dir, packs) = test_packs();
+        let objs = objects(&[b"keep", b"drop"]);
+        packs.write_pack(&objs).unwrap();
+
+        let drop_hash = blake3::hash(b"drop").to_hex().to_string();
+        let removed = packs
+            .remove_objects(&HashSet::from([drop_hash.clone()]))
+            .unwrap();
+        assert_eq!(removed, 1);
+        assert!(!packs.contains(&drop_hash));
+        assert!(packs.contains(blake3::hash(b"keep").to_hex().as_str()));
+        assert_eq!(packs.list_packs().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn removing_last_object_deletes_pack() {
+        let (_dir, packs) = test_packs();
+        let objs = objects(&[b"only"]);
+        packs.write_pack(&objs).unwrap();
+        let hashes: HashSet<String> = objs.keys().cloned().collect();
+        packs.remove_objects(&hashes).unwrap();
+        assert!(packs.list_packs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn consolidate_merges_packs() {
+        let (_dir, packs) = test_packs();
+        packs.write_pack(&objects(&[b"a"])).unwrap();
+        packs.write_pack(&objects(&[b"b"])).unwrap();
+
+        let (id, merged) = packs.consolidate(objects(&[b"c"])).unwrap();
+        assert_eq!(merged, 2);
+        assert_eq!(packs.list_packs().unwrap(), vec![id.unwrap()]);
+        assert_eq!(packs.list_objects().unwrap().len(), 3);
+    }
+}
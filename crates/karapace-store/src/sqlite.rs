@@ -0,0 +1,371 @@
+//! SQLite backend of the [`MetadataStore`](crate::MetadataStore).
+//!
+//! Selected with `"metadata_backend": "sqlite"` in the store config. The
+//! database is `store/metadata/.metadata.db`, in the directory the JSON
+//! backend uses, so watchers of that directory see its changes. Each row
+//! keeps the metadata exactly as the JSON backend would write it, checksum
+//! included, in `data`; the other columns and the `aliases` and `labels`
+//! tables copy fields out of it for queries. A write replaces all of them
+//! in one transaction.
+
+use crate::index::{Lookup, MetadataIndex};
+use crate::layout::StoreLayout;
+use crate::metadata::{EnvMetadata, EnvState};
+use crate::StoreError;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Bumped whenever [`SCHEMA`] changes.
+const SCHEMA_VERSION: i32 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE envs (
+    env_id TEXT PRIMARY KEY,
+    short_id TEXT NOT NULL,
+    name TEXT,
+    state TEXT NOT NULL,
+    revision INTEGER NOT NULL,
+    workspace TEXT,
+    snapshot TEXT,
+    last_entered_at TEXT,
+    enter_count INTEGER NOT NULL,
+    last_built_at TEXT,
+    data TEXT NOT NULL
+);
+CREATE INDEX envs_short_id ON envs (short_id);
+CREATE INDEX envs_name ON envs (name);
+CREATE TABLE aliases (
+    alias TEXT NOT NULL,
+    env_id TEXT NOT NULL,
+    PRIMARY KEY (alias, env_id)
+);
+CREATE TABLE labels (
+    env_id TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (env_id, key)
+);
+CREATE INDEX labels_key ON labels (key, value);
+";
+
+/// How long a writer waits for another process's transaction.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(crate) struct SqliteMetadata {
+    path: PathBuf,
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::MetadataBackend(format!("sqlite: {e}"))
+    }
+}
+
+impl SqliteMetadata {
+    pub(crate) fn new(layout: &StoreLayout) -> Self {
+        Self {
+            path: layout.metadata_db_file(),
+        }
+    }
+
+    fn open(&self) -> Result<Connection, StoreError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut conn = Connection::open(&self.path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version == 0 {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            // Another process may have created the schema meanwhile.
+            let version: i32 = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+            if version == 0 {
+                tx.execute_batch(SCHEMA)?;
+                tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            }
+            tx.commit()?;
+        } else if version != SCHEMA_VERSION {
+            return Err(StoreError::MetadataBackend(format!(
+                "{} has schema version {version}, expected {SCHEMA_VERSION}",
+                self.path.display()
+            )));
+        }
+        Ok(conn)
+    }
+
+    /// The stored metadata of `env_id`, as JSON.
+    pub(crate) fn read(&self, env_id: &str) -> Result<Option<String>, StoreError> {
+        Ok(self
+            .open()?
+            .query_row("SELECT data FROM envs WHERE env_id = ?1", [env_id], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    /// Every stored entry, as `(env_id, JSON)`.
+    pub(crate) fn read_all(&self) -> Result<Vec<(String, String)>, StoreError> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare("SELECT env_id, data FROM envs ORDER BY env_id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub(crate) fn exists(&self, env_id: &str) -> Result<bool, StoreError> {
+        Ok(self
+            .open()?
+            .query_row("SELECT 1 FROM envs WHERE env_id = ?1", [env_id], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    /// Store `meta` as the next revision if the stored revision is
+    /// `expected` (0 for a missing entry) or `expected` is `None`.
+    pub(crate) fn write(
+        &self,
+        meta: &EnvMetadata,
+        expected: Option<u64>,
+    ) -> Result<(), StoreError> {
+        let mut conn = self.open()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let actual: u64 = tx
+            .query_row(
+                "SELECT revision FROM envs WHERE env_id = ?1",
+                [meta.env_id.as_str()],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(0);
+        if let Some(expected) = expected.filter(|expected| *expected != actual) {
+            return Err(StoreError::Conflict {
+                env_id: meta.env_id.to_string(),
+                expected,
+                actual,
+            });
+        }
+        let (meta, data) = meta.seal(actual + 1)?;
+        delete(&tx, &meta.env_id)?;
+        tx.execute(
+            "INSERT INTO envs (env_id, short_id, name, state, revision, workspace, snapshot,
+                               last_entered_at, enter_count, last_built_at, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                meta.env_id.as_str(),
+                meta.short_id.as_str(),
+                meta.name,
+                meta.state.to_string(),
+                meta.revision,
+                meta.workspace,
+                meta.snapshot,
+                meta.last_entered_at,
+                meta.enter_count,
+                meta.last_built_at,
+                data,
+            ],
+        )?;
+        for alias in &meta.aliases {
+            tx.execute(
+                "INSERT OR IGNORE INTO aliases (alias, env_id) VALUES (?1, ?2)",
+                params![alias, meta.env_id.as_str()],
+            )?;
+        }
+        for (key, value) in &meta.labels {
+            tx.execute(
+                "INSERT INTO labels (env_id, key, value) VALUES (?1, ?2, ?3)",
+                params![meta.env_id.as_str(), key, value],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, env_id: &str) -> Result<(), StoreError> {
+        let mut conn = self.open()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        delete(&tx, env_id)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The env_id that `key` names in the way `lookup` says.
+    pub(crate) fn lookup(&self, lookup: Lookup, key: &str) -> Result<Option<String>, StoreError> {
+        let sql = match lookup {
+            Lookup::Name => "SELECT env_id FROM envs WHERE name = ?1 ORDER BY env_id",
+            Lookup::ShortId => "SELECT env_id FROM envs WHERE short_id = ?1 ORDER BY env_id",
+            Lookup::Alias => "SELECT env_id FROM aliases WHERE alias = ?1 ORDER BY env_id",
+        };
+        Ok(self
+            .open()?
+            .query_row(sql, [key], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// The env_ids of environments with label `key`, set to `value` if
+    /// given.
+    pub(crate) fn with_label(
+        &self,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<Vec<String>, StoreError> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare(
+            "SELECT env_id FROM labels WHERE key = ?1 AND (?2 IS NULL OR value = ?2)
+             ORDER BY env_id",
+        )?;
+        let rows = stmt.query_map(params![key, value], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// The index, queried from the columns it is made of.
+    pub(crate) fn index(&self) -> Result<MetadataIndex, StoreError> {
+        let conn = self.open()?;
+        let mut index = MetadataIndex::default();
+        let mut stmt = conn.prepare("SELECT env_id, short_id, name, state FROM envs")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let env_id: String = row.get(0)?;
+            let state: String = row.get(3)?;
+            let state = parse_state(&state).ok_or_else(|| {
+                StoreError::MetadataBackend(format!("unknown state '{state}' of {env_id}"))
+            })?;
+            if let Some(name) = row.get::<_, Option<String>>(2)? {
+                index.names.insert(name, env_id.clone());
+            }
+            index.short_ids.insert(row.get(1)?, env_id.clone());
+            index.states.insert(env_id, state);
+        }
+        let mut stmt = conn.prepare("SELECT alias, env_id FROM aliases")?;
+        let aliases = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for alias in aliases {
+            let (alias, env_id) = alias?;
+            index.aliases.insert(alias, env_id);
+        }
+        Ok(index)
+    }
+}
+
+fn delete(conn: &Connection, env_id: &str) -> Result<(), StoreError> {
+    for table in ["envs", "aliases", "labels"] {
+        conn.execute(&format!("DELETE FROM {table} WHERE env_id = ?1"), [env_id])?;
+    }
+    Ok(())
+}
+
+fn parse_state(state: &str) -> Option<EnvState> {
+    [
+        EnvState::Defined,
+        EnvState::Built,
+        EnvState::Running,
+        EnvState::Frozen,
+        EnvState::Archived,
+    ]
+    .into_iter()
+    .find(|s| s.to_string() == state)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EnvMetadata, EnvState, MetadataBackend, MetadataStore, StoreError, StoreLayout};
+    use std::collections::BTreeMap;
+
+    fn store() -> (tempfile::TempDir, MetadataStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        (
+            dir,
+            MetadataStore::with_backend(layout, MetadataBackend::Sqlite),
+        )
+    }
+
+    fn meta(env_id: &str) -> EnvMetadata {
+        EnvMetadata {
+            env_id: env_id.into(),
+            short_id: env_id[..6].into(),
+            name: None,
+            state: EnvState::Defined,
+            manifest_hash: "mhash".into(),
+            base_layer: "base".into(),
+            dependency_layers: Vec::new(),
+            policy_layer: None,
+            created_at: "2025-01-01T00:00:00Z".to_owned(),
+            updated_at: "2025-01-01T00:00:00Z".to_owned(),
+            ref_count: 1,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            attestation: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: BTreeMap::new(),
+            revision: 0,
+            checksum: None,
+        }
+    }
+
+    #[test]
+    fn entries_roundtrip_with_revisions() {
+        let (_dir, store) = store();
+        store.put(&meta("aaaaaa111")).unwrap();
+        store.update_state("aaaaaa111", EnvState::Built).unwrap();
+        let stored = store.get("aaaaaa111").unwrap();
+        assert_eq!(stored.state, EnvState::Built);
+        assert_eq!(stored.revision, 2);
+        assert!(store.exists("aaaaaa111"));
+
+        // A stale copy conflicts.
+        assert!(matches!(
+            store.put(&meta("aaaaaa111")),
+            Err(StoreError::Conflict { actual: 2, .. })
+        ));
+
+        store.remove("aaaaaa111").unwrap();
+        assert!(!store.exists("aaaaaa111"));
+        assert!(matches!(
+            store.get("aaaaaa111"),
+            Err(StoreError::EnvNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn lookups_and_label_queries_use_the_columns() {
+        let (_dir, store) = store();
+        let mut web = meta("bbbbbb222");
+        web.name = Some("web".to_owned());
+        web.labels.insert("team".to_owned(), "infra".to_owned());
+        store.put(&web).unwrap();
+        let mut db = meta("cccccc333");
+        db.labels.insert("team".to_owned(), "data".to_owned());
+        store.put(&db).unwrap();
+        store.rename("bbbbbb222", "frontend", true).unwrap();
+
+        assert_eq!(store.find("frontend").unwrap().unwrap().env_id, web.env_id);
+        assert_eq!(store.find("cccccc").unwrap().unwrap().env_id, db.env_id);
+        assert_eq!(store.get_by_alias("web").unwrap().env_id, web.env_id);
+        assert!(store.find("web").unwrap().is_none());
+
+        let ids = |selectors: &[&str]| -> Vec<String> {
+            let selectors: Vec<String> = selectors.iter().map(|s| (*s).to_owned()).collect();
+            store
+                .list_matching(&selectors)
+                .unwrap()
+                .into_iter()
+                .map(|m| m.env_id.to_string())
+                .collect()
+        };
+        assert_eq!(ids(&["team"]), ["bbbbbb222", "cccccc333"]);
+        assert_eq!(ids(&["team=data"]), ["cccccc333"]);
+        assert!(ids(&["team=infra", "tier"]).is_empty());
+
+        let index = store.index().unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.count(EnvState::Defined), 2);
+        assert_eq!(index.names.get("frontend").unwrap(), "bbbbbb222");
+    }
+}
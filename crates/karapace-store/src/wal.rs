@@ -1,11 +1,11 @@
 use crate::layout::StoreLayout;
 use crate::metadata::{EnvMetadata, EnvState, MetadataStore};
 use crate::StoreError;
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
-use tempfile::NamedTempFile;
 use tracing::{debug, info, warn};
 
 fn parse_env_state(s: &str) -> Option<EnvState> {
@@ -27,6 +27,24 @@ pub enum RollbackStep {
         env_id: String,
         target_state: String,
     },
+    /// Undo a directory rename by moving `from` back to `to`.
+    RenameDir {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// Restore the active workspace recorded in an environment's metadata.
+    ResetWorkspace {
+        env_id: String,
+        workspace: Option<String>,
+    },
+    /// Remove an environment's metadata entry, whichever backend holds it.
+    RemoveMetadata {
+        env_id: String,
+    },
+    /// Put back an environment's metadata as it was before the operation.
+    RestoreMetadata {
+        metadata: Box<EnvMetadata>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +57,14 @@ pub enum WalOpKind {
     Gc,
     Enter,
     Exec,
+    Workspace,
+    Clone,
+    DriftApply,
+    /// A metadata write, open until the metadata index reflects it. Not
+    /// recorded in the history.
+    Index,
+    /// A group of mutations made through `Engine::with_transaction`.
+    Transaction,
 }
 
 impl std::fmt::Display for WalOpKind {
@@ -52,6 +78,11 @@ impl std::fmt::Display for WalOpKind {
             WalOpKind::Gc => write!(f, "gc"),
             WalOpKind::Enter => write!(f, "enter"),
             WalOpKind::Exec => write!(f, "exec"),
+            WalOpKind::Workspace => write!(f, "workspace"),
+            WalOpKind::Clone => write!(f, "clone"),
+            WalOpKind::DriftApply => write!(f, "drift-apply"),
+            WalOpKind::Index => write!(f, "index"),
+            WalOpKind::Transaction => write!(f, "transaction"),
         }
     }
 }
@@ -65,6 +96,70 @@ pub struct WalEntry {
     pub rollback_steps: Vec<RollbackStep>,
 }
 
+/// Completed operations the history keeps when it is compacted.
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// Size of the history file past which appending compacts it.
+const HISTORY_COMPACT_BYTES: u64 = 512 * 1024;
+
+/// How an operation left the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WalOutcome {
+    Committed,
+    /// The operation failed and cleaned up after itself.
+    Failed,
+    /// The operation was interrupted and undone by recovery.
+    RolledBack,
+}
+
+impl std::fmt::Display for WalOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalOutcome::Committed => write!(f, "committed"),
+            WalOutcome::Failed => write!(f, "failed"),
+            WalOutcome::RolledBack => write!(f, "rolled back"),
+        }
+    }
+}
+
+/// A completed operation, as kept in `wal/history.jsonl`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalRecord {
+    pub op_id: String,
+    pub kind: WalOpKind,
+    pub env_id: String,
+    /// When the operation began.
+    pub timestamp: String,
+    /// Milliseconds from begin until commit, failure or rollback.
+    pub duration_ms: u64,
+    pub outcome: WalOutcome,
+}
+
+impl WalRecord {
+    fn new(entry: &WalEntry, outcome: WalOutcome) -> Self {
+        let duration_ms = chrono::DateTime::parse_from_rfc3339(&entry.timestamp)
+            .ok()
+            .and_then(|start| {
+                u64::try_from(
+                    chrono::Utc::now()
+                        .signed_duration_since(start)
+                        .num_milliseconds(),
+                )
+                .ok()
+            })
+            .unwrap_or(0);
+        Self {
+            op_id: entry.op_id.clone(),
+            kind: entry.kind.clone(),
+            env_id: entry.env_id.clone(),
+            timestamp: entry.timestamp.clone(),
+            duration_ms,
+            outcome,
+        }
+    }
+}
+
 pub struct WriteAheadLog {
     wal_dir: PathBuf,
 }
@@ -84,7 +179,7 @@ impl WriteAheadLog {
         let op_id = format!(
             "{}-{}",
             chrono::Utc::now().format("%Y%m%d%H%M%S%3f"),
-            &blake3::hash(env_id.as_bytes()).to_hex()[..8]
+            &blake3::hash(format!("{kind}/{env_id}").as_bytes()).to_hex()[..8]
         );
         let entry = WalEntry {
             op_id: op_id.clone(),
@@ -105,15 +200,121 @@ impl WriteAheadLog {
         Ok(())
     }
 
+    /// Complete an operation: remove its entry and record it in the history.
     pub fn commit(&self, op_id: &str) -> Result<(), StoreError> {
+        self.finish(op_id, WalOutcome::Committed)
+    }
+
+    /// Complete an operation that failed after undoing its own side effects.
+    pub fn abort(&self, op_id: &str) -> Result<(), StoreError> {
+        self.finish(op_id, WalOutcome::Failed)
+    }
+
+    /// Undo an operation that failed: run its rollback steps now, as
+    /// recovery would, and complete it as failed.
+    pub fn rollback(&self, op_id: &str) -> Result<(), StoreError> {
+        let entry = self.read_entry(op_id)?;
+        info!(
+            "WAL: rolling back {} on {} (op_id={op_id})",
+            entry.kind, entry.env_id
+        );
+        self.rollback_entry(&entry);
+        self.abort(op_id)
+    }
+
+    fn finish(&self, op_id: &str, outcome: WalOutcome) -> Result<(), StoreError> {
         let path = self.entry_path(op_id);
         if path.exists() {
+            let entry = self.read_entry(op_id).ok();
             fs::remove_file(&path)?;
-            debug!("WAL commit: {op_id}");
+            debug!("WAL {outcome}: {op_id}");
+            if let Some(entry) = entry {
+                self.record(&entry, outcome);
+            }
         }
         Ok(())
     }
 
+    /// Completed operations, oldest first.
+    pub fn history(&self) -> Result<Vec<WalRecord>, StoreError> {
+        let content = match fs::read_to_string(self.history_path()) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        // A line torn by a crash mid-append is skipped.
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Drop all but the newest `keep` history records. Returns how many
+    /// were dropped.
+    pub fn compact(&self, keep: usize) -> Result<usize, StoreError> {
+        let _lock = self.lock_history()?;
+        self.compact_locked(keep)
+    }
+
+    fn compact_locked(&self, keep: usize) -> Result<usize, StoreError> {
+        let records = self.history()?;
+        if records.len() <= keep {
+            return Ok(0);
+        }
+        let dropped = records.len() - keep;
+        let mut content = String::new();
+        for record in &records[dropped..] {
+            content.push_str(&serde_json::to_string(record)?);
+            content.push('\n');
+        }
+        crate::write_atomic(&self.wal_dir, &self.history_path(), content.as_bytes())?;
+        debug!("WAL history compacted: {dropped} records dropped");
+        Ok(dropped)
+    }
+
+    /// Append `entry` to the history. Failing to is logged, not returned:
+    /// the operation itself is complete.
+    fn record(&self, entry: &WalEntry, outcome: WalOutcome) {
+        if matches!(entry.kind, WalOpKind::Index) {
+            return;
+        }
+        if let Err(e) = self.append_history(&WalRecord::new(entry, outcome)) {
+            warn!("failed to record {} in the WAL history: {e}", entry.op_id);
+        }
+    }
+
+    fn append_history(&self, record: &WalRecord) -> Result<(), StoreError> {
+        let _lock = self.lock_history()?;
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.history_path())?;
+        file.write_all(line.as_bytes())?;
+        if file.metadata()?.len() > HISTORY_COMPACT_BYTES {
+            self.compact_locked(HISTORY_LIMIT)?;
+        }
+        Ok(())
+    }
+
+    /// Serializes appends and compaction, which replaces the history file.
+    fn lock_history(&self) -> Result<fs::File, StoreError> {
+        fs::create_dir_all(&self.wal_dir)?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.wal_dir.join("history.lock"))?;
+        file.lock_exclusive()
+            .map_err(|e| StoreError::LockFailed(format!("WAL history: {e}")))?;
+        Ok(file)
+    }
+
+    fn history_path(&self) -> PathBuf {
+        self.wal_dir.join("history.jsonl")
+    }
+
     pub fn list_incomplete(&self) -> Result<Vec<WalEntry>, StoreError> {
         if !self.wal_dir.exists() {
             return Ok(Vec::new());
@@ -152,8 +353,16 @@ impl WriteAheadLog {
             );
             self.rollback_entry(entry);
             let _ = fs::remove_file(self.entry_path(&entry.op_id));
+            self.record(entry, WalOutcome::RolledBack);
         }
         if count > 0 {
+            // Rollback may have changed or removed metadata entries, and an
+            // `Index` entry means the index may be behind.
+            if let Some(meta_store) = self.metadata_store() {
+                if let Err(e) = meta_store.rebuild_index() {
+                    warn!("WAL recovery: failed to rebuild the metadata index: {e}");
+                }
+            }
             info!("WAL recovery complete: {count} entries rolled back");
         }
         Ok(count)
@@ -191,49 +400,90 @@ impl WriteAheadLog {
                         warn!("WAL rollback: unknown target state '{target_state}' for {env_id}");
                         continue;
                     };
-
-                    let Some(store_dir) = self.wal_dir.parent() else {
+                    if self.patch_metadata(env_id, |meta| meta.state = new_state) {
+                        debug!("WAL rollback: reset {env_id} state to {target_state}");
+                    }
+                }
+                RollbackStep::RenameDir { from, to } => {
+                    // Only undo renames that actually happened.
+                    if from.exists() && !to.exists() {
+                        if let Err(e) = fs::rename(from, to) {
+                            warn!(
+                                "WAL rollback: failed to rename {} -> {}: {e}",
+                                from.display(),
+                                to.display()
+                            );
+                        } else {
+                            debug!(
+                                "WAL rollback: renamed {} -> {}",
+                                from.display(),
+                                to.display()
+                            );
+                        }
+                    }
+                }
+                RollbackStep::RemoveMetadata { env_id } => {
+                    let Some(meta_store) = self.metadata_store() else {
                         continue;
                     };
-                    let Some(root_dir) = store_dir.parent() else {
+                    if let Err(e) = meta_store.remove(env_id) {
+                        warn!("WAL rollback: failed to remove metadata of {env_id}: {e}");
+                    } else {
+                        debug!("WAL rollback: removed metadata of {env_id}");
+                    }
+                }
+                RollbackStep::RestoreMetadata { metadata } => {
+                    let Some(meta_store) = self.metadata_store() else {
                         continue;
                     };
-
-                    let meta_path = store_dir.join("metadata").join(env_id);
-                    if !meta_path.exists() {
-                        continue;
+                    let env_id = &metadata.env_id;
+                    if let Err(e) = meta_store.overwrite(metadata) {
+                        warn!("WAL rollback: failed to restore metadata of {env_id}: {e}");
+                    } else {
+                        debug!("WAL rollback: restored metadata of {env_id}");
+                    }
+                }
+                RollbackStep::ResetWorkspace { env_id, workspace } => {
+                    let target = workspace.clone();
+                    if self.patch_metadata(env_id, |meta| meta.workspace = target) {
+                        debug!("WAL rollback: reset {env_id} workspace");
                     }
+                }
+            }
+        }
+    }
 
-                    let content = match fs::read_to_string(&meta_path) {
-                        Ok(c) => c,
-                        Err(e) => {
-                            warn!("WAL rollback: failed to read metadata for {env_id}: {e}");
-                            continue;
-                        }
-                    };
+    /// Read, modify and rewrite an environment's metadata during rollback.
+    /// Returns whether the metadata was persisted.
+    fn patch_metadata(&self, env_id: &str, patch: impl FnOnce(&mut EnvMetadata)) -> bool {
+        let Some(meta_store) = self.metadata_store() else {
+            return false;
+        };
 
-                    let mut meta: EnvMetadata = match serde_json::from_str(&content) {
-                        Ok(m) => m,
-                        Err(e) => {
-                            warn!("WAL rollback: failed to parse metadata for {env_id}: {e}");
-                            continue;
-                        }
-                    };
+        let mut meta = match meta_store.get_unverified(env_id) {
+            Ok(Some(meta)) => meta,
+            Ok(None) => return false,
+            Err(e) => {
+                warn!("WAL rollback: failed to read metadata for {env_id}: {e}");
+                return false;
+            }
+        };
 
-                    meta.state = new_state;
-                    meta.updated_at = chrono::Utc::now().to_rfc3339();
-                    meta.checksum = None;
+        patch(&mut meta);
+        meta.updated_at = chrono::Utc::now().to_rfc3339();
+        meta.checksum = None;
 
-                    let layout = StoreLayout::new(root_dir);
-                    let meta_store = MetadataStore::new(layout);
-                    if let Err(e) = meta_store.put(&meta) {
-                        warn!("WAL rollback: failed to persist metadata for {env_id}: {e}");
-                    } else {
-                        debug!("WAL rollback: reset {env_id} state to {target_state}");
-                    }
-                }
-            }
+        if let Err(e) = meta_store.put(&meta) {
+            warn!("WAL rollback: failed to persist metadata for {env_id}: {e}");
+            return false;
         }
+        true
+    }
+
+    /// Metadata store of the store this WAL belongs to.
+    fn metadata_store(&self) -> Option<MetadataStore> {
+        let root_dir = self.wal_dir.parent()?.parent()?;
+        Some(MetadataStore::new(StoreLayout::new(root_dir)))
     }
 
     fn entry_path(&self, op_id: &str) -> PathBuf {
@@ -243,13 +493,8 @@ impl WriteAheadLog {
     fn write_entry(&self, entry: &WalEntry) -> Result<(), StoreError> {
         fs::create_dir_all(&self.wal_dir)?;
         let content = serde_json::to_string_pretty(entry)?;
-        let mut tmp = NamedTempFile::new_in(&self.wal_dir)?;
-        tmp.write_all(content.as_bytes())?;
-        tmp.as_file().sync_all()?;
         let dest = self.entry_path(&entry.op_id);
-        tmp.persist(&dest).map_err(|e| StoreError::Io(e.error))?;
-        crate::fsync_dir(&self.wal_dir)?;
-        Ok(())
+        crate::write_atomic(&self.wal_dir, &dest, content.as_bytes())
     }
 
     fn read_entry(&self, op_id: &str) -> Result<WalEntry, StoreError> {
@@ -302,6 +547,63 @@ mod tests {
         assert!(wal.list_incomplete().unwrap().is_empty());
     }
 
+    #[test]
+    fn completed_ops_are_kept_in_history() {
+        let (_dir, wal) = setup();
+        let build = wal.begin(WalOpKind::Build, "env1").unwrap();
+        wal.commit(&build).unwrap();
+        let enter = wal.begin(WalOpKind::Enter, "env1").unwrap();
+        wal.abort(&enter).unwrap();
+        let index = wal.begin(WalOpKind::Index, "env1").unwrap();
+        wal.commit(&index).unwrap();
+        let destroy = wal.begin(WalOpKind::Destroy, "env2").unwrap();
+        assert_eq!(wal.recover().unwrap(), 1);
+
+        let history = wal.history().unwrap();
+        let summary: Vec<_> = history
+            .iter()
+            .map(|r| (r.kind.to_string(), r.env_id.as_str(), r.outcome))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("build".to_owned(), "env1", WalOutcome::Committed),
+                ("enter".to_owned(), "env1", WalOutcome::Failed),
+                ("destroy".to_owned(), "env2", WalOutcome::RolledBack),
+            ]
+        );
+        assert_eq!(history[2].op_id, destroy);
+        assert!(wal.list_incomplete().unwrap().is_empty());
+    }
+
+    #[test]
+    fn compaction_keeps_the_newest_records() {
+        let (_dir, wal) = setup();
+        for i in 0..5 {
+            let op_id = wal.begin(WalOpKind::Exec, &format!("env{i}")).unwrap();
+            wal.commit(&op_id).unwrap();
+        }
+        // A record torn by a crash mid-append is skipped and compacted away.
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(wal.history_path())
+            .unwrap();
+        file.write_all(b"{\"op_id\":").unwrap();
+        assert_eq!(wal.history().unwrap().len(), 5);
+
+        assert_eq!(wal.compact(10).unwrap(), 0);
+        assert_eq!(wal.compact(2).unwrap(), 3);
+        let envs: Vec<_> = wal
+            .history()
+            .unwrap()
+            .into_iter()
+            .map(|r| r.env_id)
+            .collect();
+        assert_eq!(envs, ["env3", "env4"]);
+        let content = fs::read_to_string(wal.history_path()).unwrap();
+        assert_eq!(content.lines().count(), 2);
+    }
+
     #[test]
     fn add_rollback_step_persists() {
         let (_dir, wal) = setup();
@@ -368,6 +670,7 @@ mod tests {
         assert_eq!(WalOpKind::Destroy.to_string(), "destroy");
         assert_eq!(WalOpKind::Enter.to_string(), "enter");
         assert_eq!(WalOpKind::Exec.to_string(), "exec");
+        assert_eq!(WalOpKind::Workspace.to_string(), "workspace");
     }
 
     #[test]
@@ -411,6 +714,45 @@ mod tests {
         assert_eq!(meta["state"], "Built");
     }
 
+    #[test]
+    fn recover_undoes_workspace_switch() {
+        let (dir, wal) = setup();
+        let env_dir = dir.path().join("env").join("env1");
+        let upper = env_dir.join("upper");
+        let parked = env_dir.join("workspaces").join("default").join("upper");
+        let target = env_dir.join("workspaces").join("exp").join("upper");
+        fs::create_dir_all(&upper).unwrap();
+        fs::write(upper.join("marker"), "default").unwrap();
+        fs::create_dir_all(&target).unwrap();
+        fs::create_dir_all(parked.parent().unwrap()).unwrap();
+
+        // Crash after parking the current upper but before moving the
+        // target in: the second rename never happened.
+        let op_id = wal.begin(WalOpKind::Workspace, "env1").unwrap();
+        wal.add_rollback_step(
+            &op_id,
+            RollbackStep::RenameDir {
+                from: parked.clone(),
+                to: upper.clone(),
+            },
+        )
+        .unwrap();
+        fs::rename(&upper, &parked).unwrap();
+        wal.add_rollback_step(
+            &op_id,
+            RollbackStep::RenameDir {
+                from: upper.clone(),
+                to: target.clone(),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(wal.recover().unwrap(), 1);
+        assert_eq!(fs::read_to_string(upper.join("marker")).unwrap(), "default");
+        assert!(!parked.exists());
+        assert!(target.exists());
+    }
+
     #[test]
     fn recover_corrupt_wal_entry_is_removed() {
         let (dir, wal) = setup();
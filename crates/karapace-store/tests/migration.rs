@@ -114,6 +114,10 @@ fn migrate_preserves_objects_and_layers() {
         object_refs: vec![h1.clone(), h2.clone()],
         read_only: true,
         tar_hash: String::new(),
+        workspace: None,
+        delta_parent: None,
+        file_objects: Vec::new(),
+        pax_metadata: false,
     };
     let lh1 = layer_store.put(&layer).unwrap();
     let layer2 = LayerManifest {
@@ -123,6 +127,10 @@ fn migrate_preserves_objects_and_layers() {
         object_refs: vec![h3.clone()],
         read_only: false,
         tar_hash: String::new(),
+        workspace: None,
+        delta_parent: None,
+        file_objects: Vec::new(),
+        pax_metadata: false,
     };
     let lh2 = layer_store.put(&layer2).unwrap();
 
@@ -370,3 +378,89 @@ fn migrate_invalid_json_metadata_skipped() {
         "v2 'policy_layer' field must be present"
     );
 }
+
+#[test]
+fn migrate_v2_store_compresses_loose_objects() {
+    let dir = tempfile::tempdir().unwrap();
+    create_v1_store(dir.path(), 0);
+    let store_dir = dir.path().join("store");
+    fs::write(store_dir.join("version"), r#"{"format_version": 2}"#).unwrap();
+
+    // v2 objects are plain files named by the hash of their content.
+    let data = vec![b'z'; 64 * 1024];
+    let hash = blake3::hash(&data).to_hex().to_string();
+    fs::write(store_dir.join("objects").join(&hash), &data).unwrap();
+
+    let result = migrate_store(dir.path()).unwrap().unwrap();
+    assert_eq!(result.from_version, 2);
+    assert_eq!(result.objects_compressed, 1);
+
+    let on_disk = fs::metadata(store_dir.join("objects").join(&hash)).unwrap();
+    assert!(on_disk.len() < data.len() as u64);
+    let layout = StoreLayout::new(dir.path());
+    layout.verify_version().unwrap();
+    assert_eq!(ObjectStore::new(layout).get(&hash).unwrap(), data);
+}
+
+#[test]
+fn migrate_respects_disabled_compression() {
+    let dir = tempfile::tempdir().unwrap();
+    create_v1_store(dir.path(), 0);
+    let store_dir = dir.path().join("store");
+    fs::write(
+        store_dir.join("config.json"),
+        r#"{ "compression": "none" }"#,
+    )
+    .unwrap();
+
+    let data = vec![b'n'; 64 * 1024];
+    let hash = blake3::hash(&data).to_hex().to_string();
+    fs::write(store_dir.join("objects").join(&hash), &data).unwrap();
+
+    let result = migrate_store(dir.path()).unwrap().unwrap();
+    assert_eq!(result.objects_compressed, 0);
+    assert_eq!(
+        fs::read(store_dir.join("objects").join(&hash)).unwrap(),
+        data
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn metadata_moves_to_sqlite_and_back() {
+    use karapace_store::{migrate_metadata_backend, MetadataBackend, StoreConfig};
+
+    let dir = tempfile::tempdir().unwrap();
+    create_v1_store(dir.path(), 3);
+    migrate_store(dir.path()).unwrap();
+    let layout = StoreLayout::new(dir.path());
+    let json = MetadataStore::new(layout.clone());
+    json.rename("env_0001", "web", true).unwrap();
+    let before = json.list().unwrap();
+
+    assert_eq!(
+        migrate_metadata_backend(dir.path(), MetadataBackend::Sqlite).unwrap(),
+        3
+    );
+    assert_eq!(
+        StoreConfig::load(&layout).unwrap().metadata_backend,
+        MetadataBackend::Sqlite
+    );
+    assert!(!layout.metadata_dir().join("env_0001").exists());
+    let sqlite = MetadataStore::new(layout.clone());
+    assert_eq!(sqlite.backend(), MetadataBackend::Sqlite);
+    let moved = sqlite.list().unwrap();
+    assert_eq!(moved.len(), 3);
+    assert_eq!(sqlite.get_by_name("web").unwrap().env_id, before[1].env_id);
+    assert_eq!(moved[1].name, before[1].name);
+    assert_eq!(moved[1].created_at, before[1].created_at);
+
+    assert_eq!(
+        migrate_metadata_backend(dir.path(), MetadataBackend::Json).unwrap(),
+        3
+    );
+    let back = MetadataStore::new(layout.clone());
+    assert_eq!(back.backend(), MetadataBackend::Json);
+    assert_eq!(back.list().unwrap().len(), 3);
+    assert!(sqlite.list().unwrap().is_empty());
+}
@@ -1,13 +1,30 @@
+use crate::prefs::Preferences;
+use crate::task::Task;
 use crossterm::event::KeyCode;
-use karapace_core::Engine;
-use karapace_store::EnvMetadata;
+use karapace_core::{
+    health, BuildOptions, DriftReport, Engine, EnvStats, HealthCheck, ProjectBinding, SnapshotInfo,
+    StoreLock, StoreUsage,
+};
+use karapace_remote::{
+    AgeCipher, BlobCipher, RegistryCache, RegistryEntry, RegistryOrigin, RemoteConfig,
+    TransferOptions,
+};
+use karapace_runtime::ProgressSink;
+use karapace_store::{EnvMetadata, EnvState, IntegrityReport, StoreLayout};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often the detail view re-samples resource usage.
+const STATS_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum AppAction {
     None,
     Quit,
     Refresh,
+    /// Suspend the UI and open a shell in the environment with this id.
+    Enter(String),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -15,6 +32,58 @@ pub enum View {
     List,
     Detail,
     Help,
+    Integrity,
+    Usage,
+    Task,
+    Snapshots,
+    Remote,
+    Settings,
+}
+
+/// An entry of the settings view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    SortColumn,
+    SortAscending,
+    ShowShortId,
+    ShowName,
+    ShowState,
+    ShowEnvId,
+    ConfirmDestroy,
+    ConfirmSnapshots,
+    ConfirmQuit,
+    Remote,
+}
+
+impl Setting {
+    /// Every setting, in the order the settings view lists them.
+    pub const ALL: [Self; 10] = [
+        Self::SortColumn,
+        Self::SortAscending,
+        Self::ShowShortId,
+        Self::ShowName,
+        Self::ShowState,
+        Self::ShowEnvId,
+        Self::ConfirmDestroy,
+        Self::ConfirmSnapshots,
+        Self::ConfirmQuit,
+        Self::Remote,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::SortColumn => "Sort column",
+            Self::SortAscending => "Sort ascending",
+            Self::ShowShortId => "Show SHORT_ID column",
+            Self::ShowName => "Show NAME column",
+            Self::ShowState => "Show STATE column",
+            Self::ShowEnvId => "Show ENV_ID column",
+            Self::ConfirmDestroy => "Confirm destroy",
+            Self::ConfirmSnapshots => "Confirm snapshot restore, delete and drift discard",
+            Self::ConfirmQuit => "Confirm quit while a task runs",
+            Self::Remote => "Remote",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,13 +91,18 @@ pub enum InputMode {
     Normal,
     Search,
     Rename,
+    /// Editing the preferred remote URL in the settings view.
+    Remote,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SortColumn {
     ShortId,
     Name,
     State,
+    LastEntered,
+    EnterCount,
 }
 
 pub struct App {
@@ -45,6 +119,43 @@ pub struct App {
     pub sort_ascending: bool,
     pub status_message: String,
     pub show_confirm: Option<String>,
+    /// Failing or warning store health checks, shown as a banner.
+    pub health: Vec<HealthCheck>,
+    /// Label of the environment last checked, with its integrity report.
+    pub integrity: Option<(String, IntegrityReport)>,
+    /// Resource usage of the selected environment, for the detail view.
+    pub stats: Option<EnvStats>,
+    /// Disk use of the store, for the usage view.
+    pub usage: Option<StoreUsage>,
+    /// The project the TUI was started in, with its directory. Rebuilding
+    /// needs its manifest.
+    pub project: Option<(PathBuf, ProjectBinding)>,
+    /// Where `p` pushes to.
+    pub remote: Option<RemoteConfig>,
+    /// The last rebuild or push, running or finished.
+    pub task: Option<Task>,
+    /// Environment whose snapshots are browsed: env_id and label.
+    pub snapshot_env: Option<(String, String)>,
+    /// Its snapshots, newest first.
+    pub snapshots: Vec<SnapshotInfo>,
+    pub snapshot_selected: usize,
+    /// Changes since the selected snapshot, once asked for.
+    pub snapshot_diff: Option<DriftReport>,
+    /// Drift of the environment in the detail view.
+    pub drift: Option<DriftReport>,
+    /// First line of the drift pane shown.
+    pub drift_scroll: usize,
+    /// Registry entries of the remote, by `name@tag` key.
+    pub remote_entries: Vec<(String, RegistryEntry)>,
+    pub remote_selected: usize,
+    /// When the listed registry was last fetched, and whether it came from
+    /// the local cache because the remote was unreachable.
+    pub remote_fetched: Option<(String, bool)>,
+    pub prefs: Preferences,
+    /// Where preferences are saved; `None` keeps them for this run only.
+    pub prefs_path: Option<PathBuf>,
+    pub setting_selected: usize,
+    stats_sampled: Option<Instant>,
 }
 
 impl App {
@@ -63,6 +174,26 @@ impl App {
             sort_ascending: true,
             status_message: String::new(),
             show_confirm: None,
+            health: Vec::new(),
+            integrity: None,
+            stats: None,
+            usage: None,
+            project: None,
+            remote: None,
+            task: None,
+            snapshot_env: None,
+            snapshots: Vec::new(),
+            snapshot_selected: 0,
+            snapshot_diff: None,
+            drift: None,
+            drift_scroll: 0,
+            remote_entries: Vec::new(),
+            remote_selected: 0,
+            remote_fetched: None,
+            prefs: Preferences::default(),
+            prefs_path: None,
+            setting_selected: 0,
+            stats_sampled: None,
         }
     }
 
@@ -71,6 +202,7 @@ impl App {
     }
 
     pub fn refresh(&mut self) -> Result<(), String> {
+        self.refresh_health();
         match self.engine().list() {
             Ok(envs) => {
                 self.environments = envs;
@@ -86,6 +218,14 @@ impl App {
         }
     }
 
+    /// Re-run the quick store checks and keep only the ones worth showing.
+    pub fn refresh_health(&mut self) {
+        self.health = health::quick_check(&StoreLayout::new(&self.store_root))
+            .into_iter()
+            .filter(HealthCheck::is_problem)
+            .collect();
+    }
+
     pub fn apply_filter(&mut self) {
         if self.filter.is_empty() {
             self.filtered = (0..self.environments.len()).collect();
@@ -104,6 +244,9 @@ impl App {
                             .to_lowercase()
                             .contains(&needle)
                         || e.state.to_string().to_lowercase().contains(&needle)
+                        || e.labels.iter().any(|(key, value)| {
+                            format!("{key}={value}").to_lowercase().contains(&needle)
+                        })
                 })
                 .map(|(i, _)| i)
                 .collect();
@@ -146,6 +289,53 @@ impl App {
                     ord.reverse()
                 }
             }),
+            SortColumn::LastEntered => self.environments.sort_by(|a, b| {
+                let ord = a.last_entered_at.cmp(&b.last_entered_at);
+                if asc {
+                    ord
+                } else {
+                    ord.reverse()
+                }
+            }),
+            SortColumn::EnterCount => self.environments.sort_by(|a, b| {
+                let ord = a.enter_count.cmp(&b.enter_count);
+                if asc {
+                    ord
+                } else {
+                    ord.reverse()
+                }
+            }),
+        }
+    }
+
+    /// Sample resource usage of the selected environment.
+    pub fn refresh_stats(&mut self) {
+        self.stats = self
+            .selected_env()
+            .and_then(|env| self.engine().stats(&env.env_id).ok());
+        self.stats_sampled = Some(Instant::now());
+    }
+
+    /// Called between key presses; keeps the detail view's stats current
+    /// and collects the progress of a running task.
+    pub fn tick(&mut self) {
+        if let Some(task) = &mut self.task {
+            if task.poll() {
+                self.status_message = match &task.outcome {
+                    Some(Ok(summary)) => summary.clone(),
+                    Some(Err(e)) => format!("{} failed: {e}", task.label),
+                    None => String::new(),
+                };
+                let status = std::mem::take(&mut self.status_message);
+                self.refresh().ok();
+                self.status_message = status;
+            }
+        }
+        let stale = self
+            .stats_sampled
+            .is_none_or(|at| at.elapsed() >= STATS_INTERVAL);
+        if self.view == View::Detail && stale {
+            self.refresh_stats();
         }
     }
 
@@ -170,9 +360,16 @@ impl App {
             return self.handle_rename_key(key);
         }
 
+        if self.input_mode == InputMode::Remote {
+            return self.handle_remote_input_key(key);
+        }
+
         // Confirmation dialog active
         if let Some(ref action) = self.show_confirm.clone() {
             if let KeyCode::Char('y' | 'Y') = key {
+                if action == "quit" {
+                    return AppAction::Quit;
+                }
                 self.execute_confirmed_action(action);
                 self.show_confirm = None;
                 return AppAction::Refresh;
@@ -183,7 +380,7 @@ impl App {
         }
 
         match self.view {
-            View::Help => match key {
+            View::Help | View::Integrity | View::Usage | View::Task => match key {
                 KeyCode::Char('q') | KeyCode::Esc => {
                     self.view = View::List;
                     AppAction::None
@@ -192,6 +389,9 @@ impl App {
             },
             View::Detail => self.handle_detail_key(key),
             View::List => self.handle_list_key(key),
+            View::Snapshots => self.handle_snapshots_key(key),
+            View::Remote => self.handle_remote_key(key),
+            View::Settings => self.handle_settings_key(key),
         }
     }
 
@@ -201,6 +401,32 @@ impl App {
                 self.view = View::List;
                 AppAction::None
             }
+            KeyCode::Enter => self.shell_action(),
+            KeyCode::Char('J') | KeyCode::PageDown => {
+                let lines = self.drift.as_ref().map_or(0, |d| drift_tree(d).len());
+                self.drift_scroll = (self.drift_scroll + 1).min(lines.saturating_sub(1));
+                AppAction::None
+            }
+            KeyCode::Char('K') | KeyCode::PageUp => {
+                self.drift_scroll = self.drift_scroll.saturating_sub(1);
+                AppAction::None
+            }
+            KeyCode::Char('c') => {
+                self.action_commit_drift();
+                AppAction::None
+            }
+            KeyCode::Char('x') => {
+                self.prompt_discard_drift();
+                AppAction::None
+            }
+            _ => self.handle_env_key(key),
+        }
+    }
+
+    /// Actions on the selected environment, shared by the list and detail
+    /// views.
+    fn handle_env_key(&mut self, key: KeyCode) -> AppAction {
+        match key {
             KeyCode::Char('d') => {
                 self.prompt_destroy();
                 AppAction::None
@@ -217,12 +443,248 @@ impl App {
                 self.start_rename();
                 AppAction::None
             }
+            KeyCode::Char('i') => {
+                self.action_integrity();
+                AppAction::None
+            }
+            KeyCode::Char('b') => {
+                self.start_rebuild();
+                AppAction::None
+            }
+            KeyCode::Char('p') => {
+                self.start_push();
+                AppAction::None
+            }
+            KeyCode::Char('t') => {
+                self.open_snapshots();
+                AppAction::None
+            }
+            _ => AppAction::None,
+        }
+    }
+
+    fn handle_snapshots_key(&mut self, key: KeyCode) -> AppAction {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.view = View::List;
+                AppAction::None
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                if !self.snapshots.is_empty() {
+                    self.snapshot_selected =
+                        (self.snapshot_selected + 1).min(self.snapshots.len() - 1);
+                    self.snapshot_diff = None;
+                }
+                AppAction::None
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.snapshot_selected = self.snapshot_selected.saturating_sub(1);
+                self.snapshot_diff = None;
+                AppAction::None
+            }
+            KeyCode::Enter => {
+                self.diff_snapshot();
+                AppAction::None
+            }
+            KeyCode::Char('r') => {
+                self.prompt_snapshot("restore");
+                AppAction::None
+            }
+            KeyCode::Char('d') => {
+                self.prompt_snapshot("delete");
+                AppAction::None
+            }
+            _ => AppAction::None,
+        }
+    }
+
+    fn handle_remote_key(&mut self, key: KeyCode) -> AppAction {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.view = View::List;
+                AppAction::None
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                if !self.remote_entries.is_empty() {
+                    self.remote_selected =
+                        (self.remote_selected + 1).min(self.remote_entries.len() - 1);
+                }
+                AppAction::None
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.remote_selected = self.remote_selected.saturating_sub(1);
+                AppAction::None
+            }
+            KeyCode::Char('r') => {
+                self.load_remote();
+                AppAction::None
+            }
+            KeyCode::Enter => {
+                self.start_pull();
+                AppAction::None
+            }
+            _ => AppAction::None,
+        }
+    }
+
+    fn handle_settings_key(&mut self, key: KeyCode) -> AppAction {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.view = View::List;
+                AppAction::None
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.setting_selected = (self.setting_selected + 1).min(Setting::ALL.len() - 1);
+                AppAction::None
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.setting_selected = self.setting_selected.saturating_sub(1);
+                AppAction::None
+            }
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                self.change_setting(Setting::ALL[self.setting_selected]);
+                AppAction::None
+            }
             _ => AppAction::None,
         }
     }
 
+    fn handle_remote_input_key(&mut self, key: KeyCode) -> AppAction {
+        match key {
+            KeyCode::Esc => {
+                self.input_mode = InputMode::Normal;
+                "remote unchanged".clone_into(&mut self.status_message);
+            }
+            KeyCode::Enter => {
+                self.input_mode = InputMode::Normal;
+                let url = self.text_input.trim();
+                self.prefs.remote = (!url.is_empty()).then(|| url.to_owned());
+                self.remote = resolve_remote(self.prefs.remote.as_deref());
+                self.report_saved();
+            }
+            KeyCode::Char(c) => {
+                self.text_input.insert(self.input_cursor, c);
+                self.input_cursor += 1;
+                self.status_message = format!("remote: {}", self.text_input);
+            }
+            KeyCode::Backspace => {
+                if self.input_cursor > 0 {
+                    self.input_cursor -= 1;
+                    self.text_input.remove(self.input_cursor);
+                }
+                self.status_message = format!("remote: {}", self.text_input);
+            }
+            _ => {}
+        }
+        AppAction::None
+    }
+
+    /// Toggle or cycle `setting`, or start editing it, and save.
+    fn change_setting(&mut self, setting: Setting) {
+        let columns = &mut self.prefs.columns;
+        let flag = match setting {
+            Setting::SortColumn => {
+                self.cycle_sort();
+                None
+            }
+            Setting::SortAscending => {
+                self.sort_ascending = !self.sort_ascending;
+                self.apply_sort();
+                self.apply_filter();
+                None
+            }
+            Setting::ShowShortId => Some(&mut columns.short_id),
+            Setting::ShowName => Some(&mut columns.name),
+            Setting::ShowState => Some(&mut columns.state),
+            Setting::ShowEnvId => Some(&mut columns.env_id),
+            Setting::ConfirmDestroy => Some(&mut self.prefs.confirm_destroy),
+            Setting::ConfirmSnapshots => Some(&mut self.prefs.confirm_snapshots),
+            Setting::ConfirmQuit => Some(&mut self.prefs.confirm_quit),
+            Setting::Remote => {
+                self.input_mode = InputMode::Remote;
+                self.text_input = self.prefs.remote.clone().unwrap_or_default();
+                self.input_cursor = self.text_input.len();
+                self.status_message = format!("remote: {}", self.text_input);
+                return;
+            }
+        };
+        if let Some(flag) = flag {
+            *flag = !*flag;
+        }
+        let columns = self.prefs.columns;
+        if !(columns.short_id || columns.name || columns.state || columns.env_id) {
+            self.prefs.columns.env_id = true;
+            "the list needs at least one column".clone_into(&mut self.status_message);
+            return;
+        }
+        self.report_saved();
+    }
+
+    /// The current value of `setting`, as the settings view shows it.
+    pub fn setting_value(&self, setting: Setting) -> String {
+        let on_off = |on: bool| if on { "on" } else { "off" }.to_owned();
+        let columns = &self.prefs.columns;
+        match setting {
+            Setting::SortColumn => format!("{:?}", self.sort_column),
+            Setting::SortAscending => on_off(self.sort_ascending),
+            Setting::ShowShortId => on_off(columns.short_id),
+            Setting::ShowName => on_off(columns.name),
+            Setting::ShowState => on_off(columns.state),
+            Setting::ShowEnvId => on_off(columns.env_id),
+            Setting::ConfirmDestroy => on_off(self.prefs.confirm_destroy),
+            Setting::ConfirmSnapshots => on_off(self.prefs.confirm_snapshots),
+            Setting::ConfirmQuit => on_off(self.prefs.confirm_quit),
+            Setting::Remote => match (&self.prefs.remote, &self.remote) {
+                (Some(url), _) => url.clone(),
+                (None, Some(config)) => format!("{} (remote config)", config.location()),
+                (None, None) => "(none)".to_owned(),
+            },
+        }
+    }
+
+    /// Take over `prefs`: sort order, filter and remote apply at once.
+    pub fn apply_prefs(&mut self, prefs: Preferences) {
+        self.sort_column = prefs.sort_column;
+        self.sort_ascending = prefs.sort_ascending;
+        self.filter.clone_from(&prefs.filter);
+        self.remote = resolve_remote(prefs.remote.as_deref());
+        self.prefs = prefs;
+        self.apply_sort();
+        self.apply_filter();
+    }
+
+    /// Write the preferences, with the current sort order and filter, to
+    /// `prefs_path`.
+    pub fn save_prefs(&mut self) -> Result<(), String> {
+        self.prefs.sort_column = self.sort_column;
+        self.prefs.sort_ascending = self.sort_ascending;
+        self.prefs.filter.clone_from(&self.filter);
+        match &self.prefs_path {
+            Some(path) => self.prefs.save(path),
+            None => Ok(()),
+        }
+    }
+
+    fn report_saved(&mut self) {
+        self.status_message = match (self.save_prefs(), &self.prefs_path) {
+            (Ok(()), Some(path)) => format!("saved {}", path.display()),
+            (Ok(()), None) => "changed for this session".to_owned(),
+            (Err(e), _) => format!("saving preferences failed: {e}"),
+        };
+    }
+
     fn handle_list_key(&mut self, key: KeyCode) -> AppAction {
         match key {
+            KeyCode::Char('q')
+                if self.prefs.confirm_quit && self.task.as_ref().is_some_and(Task::is_running) =>
+            {
+                self.show_confirm = Some("quit".to_owned());
+                self.status_message = format!(
+                    "{} is still running; quit anyway? (y/n)",
+                    self.task.as_ref().map_or("", |t| t.label.as_str())
+                );
+                AppAction::None
+            }
             KeyCode::Char('q') => AppAction::Quit,
             KeyCode::Char('j') | KeyCode::Down => {
                 if !self.filtered.is_empty() {
@@ -247,24 +709,24 @@ impl App {
             KeyCode::Enter => {
                 if self.selected_env().is_some() {
                     self.view = View::Detail;
+                    self.refresh_stats();
+                    self.refresh_drift();
                 }
                 AppAction::None
             }
             KeyCode::Char('r') => AppAction::Refresh,
-            KeyCode::Char('d') => {
-                self.prompt_destroy();
+            KeyCode::Char('R') => {
+                self.load_remote();
                 AppAction::None
             }
-            KeyCode::Char('f') => {
-                self.action_freeze();
-                AppAction::Refresh
-            }
-            KeyCode::Char('a') => {
-                self.action_archive();
-                AppAction::Refresh
+            KeyCode::Char('u') => {
+                self.action_usage();
+                AppAction::None
             }
-            KeyCode::Char('n') => {
-                self.start_rename();
+            KeyCode::Char('l') => {
+                if self.task.is_some() {
+                    self.view = View::Task;
+                }
                 AppAction::None
             }
             KeyCode::Char('/') => {
@@ -288,7 +750,11 @@ impl App {
                 self.view = View::Help;
                 AppAction::None
             }
-            _ => AppAction::None,
+            KeyCode::Char('o') => {
+                self.view = View::Settings;
+                AppAction::None
+            }
+            _ => self.handle_env_key(key),
         }
     }
 
@@ -382,8 +848,9 @@ impl App {
     fn prompt_destroy(&mut self) {
         if let Some(env) = self.selected_env() {
             let label = env.name.clone().unwrap_or_else(|| env.short_id.to_string());
-            self.show_confirm = Some(format!("destroy:{}", env.env_id));
-            self.status_message = format!("destroy '{label}'? (y/n)");
+            let action = format!("destroy:{}", env.env_id);
+            let ask = self.prefs.confirm_destroy;
+            self.confirm(action, format!("destroy '{label}'? (y/n)"), ask);
         }
     }
 
@@ -409,6 +876,435 @@ impl App {
         }
     }
 
+    fn action_integrity(&mut self) {
+        let Some(env) = self.selected_env() else {
+            return;
+        };
+        let env_id = env.env_id.to_string();
+        let label = env.name.clone().unwrap_or_else(|| env.short_id.to_string());
+        let layout = StoreLayout::new(&self.store_root);
+        match karapace_store::verify_env_integrity(&layout, &env_id) {
+            Ok(report) => {
+                self.status_message = if report.failed.is_empty() {
+                    format!("integrity OK for '{label}'")
+                } else {
+                    format!("integrity: {} problem(s) in '{label}'", report.failed.len())
+                };
+                self.integrity = Some((label, report));
+                self.view = View::Integrity;
+            }
+            Err(e) => self.status_message = format!("integrity check failed: {e}"),
+        }
+    }
+
+    /// Request a shell in the selected environment, if it is built.
+    fn shell_action(&mut self) -> AppAction {
+        let Some(env) = self.selected_env() else {
+            return AppAction::None;
+        };
+        if env.state != EnvState::Built {
+            let label = env.name.clone().unwrap_or_else(|| env.short_id.to_string());
+            self.status_message = format!(
+                "'{label}' is {}; only built environments can be entered",
+                env.state
+            );
+            return AppAction::None;
+        }
+        AppAction::Enter(env.env_id.to_string())
+    }
+
+    /// The line printed above a shell opened with [`AppAction::Enter`].
+    pub fn shell_banner(&self, env_id: &str) -> String {
+        let label = self
+            .environments
+            .iter()
+            .find(|env| env.env_id.as_str() == env_id)
+            .and_then(|env| env.name.clone())
+            .unwrap_or_else(|| env_id[..12.min(env_id.len())].to_owned());
+        format!(
+            "karapace: shell in '{label}' ({}); exit it to return to the TUI",
+            &env_id[..12.min(env_id.len())]
+        )
+    }
+
+    /// Run an interactive session in `env_id` under the store lock and
+    /// report how it ended. The caller suspends the UI around this.
+    pub fn enter_shell(&mut self, env_id: &str) {
+        let layout = StoreLayout::new(&self.store_root);
+        let short = &env_id[..12.min(env_id.len())];
+        let result = StoreLock::try_acquire(&layout.lock_file())
+            .map_err(|e| e.to_string())
+            .and_then(|lock| lock.ok_or_else(|| "the store is busy".to_owned()))
+            .and_then(|_lock| self.engine().enter(env_id).map_err(|e| e.to_string()));
+        self.refresh().ok();
+        self.refresh_drift();
+        self.status_message = match result {
+            Ok(()) => format!("left the shell in {short}"),
+            Err(e) => format!("enter {short} failed: {e}"),
+        };
+    }
+
+    /// Re-scan the overlay of the selected environment.
+    pub fn refresh_drift(&mut self) {
+        let layout = StoreLayout::new(&self.store_root);
+        self.drift = self
+            .selected_env()
+            .and_then(|env| karapace_core::diff_overlay(&layout, &env.env_id).ok());
+        self.drift_scroll = 0;
+    }
+
+    fn action_commit_drift(&mut self) {
+        let Some(env) = self.selected_env() else {
+            return;
+        };
+        let env_id = env.env_id.to_string();
+        let label = env.name.clone().unwrap_or_else(|| env.short_id.to_string());
+        if !self.drift.as_ref().is_some_and(|d| d.has_drift) {
+            self.status_message = format!("'{label}' has no drift to commit");
+            return;
+        }
+        match self.engine().commit(&env_id) {
+            Ok(snapshot) => {
+                self.status_message = format!(
+                    "committed drift of '{label}' as {}",
+                    &snapshot[..12.min(snapshot.len())]
+                );
+            }
+            Err(e) => self.status_message = format!("commit failed: {e}"),
+        }
+        self.refresh_drift();
+    }
+
+    /// Ask before restoring the newest snapshot over the drift.
+    fn prompt_discard_drift(&mut self) {
+        let Some(env) = self.selected_env() else {
+            return;
+        };
+        let env_id = env.env_id.to_string();
+        let label = env.name.clone().unwrap_or_else(|| env.short_id.to_string());
+        let newest = self
+            .engine()
+            .snapshot_details(&env_id)
+            .ok()
+            .and_then(|snapshots| snapshots.into_iter().next());
+        let Some(snapshot) = newest else {
+            self.status_message = format!("'{label}' has no snapshot to go back to");
+            return;
+        };
+        let prompt = format!(
+            "discard the drift of '{label}' by restoring {}? (y/n)",
+            &snapshot.hash[..12.min(snapshot.hash.len())]
+        );
+        let ask = self.prefs.confirm_snapshots;
+        self.confirm(
+            format!("discard-drift:{env_id}:{}", snapshot.hash),
+            prompt,
+            ask,
+        );
+    }
+
+    fn action_usage(&mut self) {
+        match self.engine().store_usage() {
+            Ok(usage) => {
+                self.usage = Some(usage);
+                self.view = View::Usage;
+            }
+            Err(e) => self.status_message = format!("disk usage failed: {e}"),
+        }
+    }
+
+    fn open_snapshots(&mut self) {
+        let Some(env) = self.selected_env() else {
+            return;
+        };
+        let env_id = env.env_id.to_string();
+        let label = env.name.clone().unwrap_or_else(|| env.short_id.to_string());
+        self.snapshot_env = Some((env_id, label));
+        self.snapshot_selected = 0;
+        self.load_snapshots();
+        self.view = View::Snapshots;
+    }
+
+    /// Re-read the snapshots of the browsed environment.
+    fn load_snapshots(&mut self) {
+        self.snapshot_diff = None;
+        let Some((env_id, label)) = &self.snapshot_env else {
+            return;
+        };
+        match self.engine().snapshot_details(env_id) {
+            Ok(snapshots) => {
+                self.status_message = format!("{} snapshot(s) of '{label}'", snapshots.len());
+                self.snapshots = snapshots;
+            }
+            Err(e) => {
+                self.status_message = format!("listing snapshots failed: {e}");
+                self.snapshots.clear();
+            }
+        }
+        self.snapshot_selected = self
+            .snapshot_selected
+            .min(self.snapshots.len().saturating_sub(1));
+    }
+
+    /// Open the remote view and fetch the remote's registry, falling back
+    /// to the store's cached copy when the remote is unreachable.
+    fn load_remote(&mut self) {
+        let Some(config) = self.remote.clone() else {
+            "no remote configured; see 'karapace remote'".clone_into(&mut self.status_message);
+            return;
+        };
+        self.view = View::Remote;
+        let layout = StoreLayout::new(&self.store_root);
+        let cache = RegistryCache::new(&layout.registry_cache_dir(), &config.location());
+        let fetched = karapace_remote::open_backend(config.clone())
+            .and_then(|backend| cache.fetch(&*backend));
+        match fetched {
+            Ok(registry) => {
+                self.remote_fetched = registry.as_ref().map(|cached| {
+                    (
+                        cached.fetched_at.clone(),
+                        cached.origin == RegistryOrigin::Cached,
+                    )
+                });
+                self.remote_entries = registry
+                    .map(|cached| cached.registry.entries.into_iter().collect())
+                    .unwrap_or_default();
+                self.status_message = format!(
+                    "{} registry entr{} on {}",
+                    self.remote_entries.len(),
+                    if self.remote_entries.len() == 1 {
+                        "y"
+                    } else {
+                        "ies"
+                    },
+                    config.location()
+                );
+            }
+            Err(e) => {
+                self.remote_entries.clear();
+                self.remote_fetched = None;
+                self.status_message = format!("fetching the registry failed: {e}");
+            }
+        }
+        self.remote_selected = self
+            .remote_selected
+            .min(self.remote_entries.len().saturating_sub(1));
+    }
+
+    /// Whether the store has the environment with this id.
+    pub fn is_local(&self, env_id: &str) -> bool {
+        self.environments
+            .iter()
+            .any(|env| env.env_id.as_str() == env_id)
+    }
+
+    /// Pull the selected registry entry in the background.
+    fn start_pull(&mut self) {
+        let Some((key, entry)) = self.remote_entries.get(self.remote_selected).cloned() else {
+            return;
+        };
+        let Some(config) = self.remote.clone() else {
+            return;
+        };
+        let cipher = AgeCipher::from_config(&config);
+        if !entry.key_fingerprints.is_empty() && cipher.is_none() {
+            self.status_message = format!(
+                "'{key}' is encrypted for key {}; set age_identity in the remote config",
+                entry.key_fingerprints.join(", ")
+            );
+            return;
+        }
+        if !self.can_start_task() {
+            return;
+        }
+        let store_root = self.store_root.clone();
+        let task = Task::spawn(format!("pull '{key}'"), move |sink| {
+            let engine = Engine::new(&store_root);
+            let backend =
+                karapace_remote::open_backend(config.clone()).map_err(|e| e.to_string())?;
+            let trusted_keys = config.load_trusted_keys().map_err(|e| e.to_string())?;
+            sink.message(&format!("pulling from {}", config.location()));
+            let on_object = |p: &karapace_remote::ObjectProgress<'_>| sink.object(p);
+            let options = TransferOptions {
+                cipher: cipher.as_ref().map(|c| c as &dyn BlobCipher),
+                on_object: Some(&on_object),
+                trusted_keys: trusted_keys.as_ref(),
+                require_signed: config.require_signed,
+                ..TransferOptions::default()
+            };
+            let result = engine
+                .pull_with_options(&entry.env_id, &*backend, &options)
+                .map_err(|e| e.to_string())?;
+            Ok(format!(
+                "pulled '{key}' as {} ({} objects, {} layers; {} skipped)",
+                entry.short_id,
+                result.objects_pulled,
+                result.layers_pulled,
+                result.objects_skipped + result.layers_skipped
+            ))
+        });
+        self.start_task(task);
+    }
+
+    fn selected_snapshot(&self) -> Option<(&str, &SnapshotInfo)> {
+        let (env_id, _) = self.snapshot_env.as_ref()?;
+        Some((env_id, self.snapshots.get(self.snapshot_selected)?))
+    }
+
+    fn diff_snapshot(&mut self) {
+        let Some((env_id, snapshot)) = self.selected_snapshot() else {
+            return;
+        };
+        match self.engine().diff_snapshot(env_id, &snapshot.hash) {
+            Ok(report) => {
+                self.status_message = format!(
+                    "since {}: {} added, {} modified, {} removed",
+                    &snapshot.hash[..12.min(snapshot.hash.len())],
+                    report.added.len(),
+                    report.modified.len(),
+                    report.removed.len()
+                );
+                self.snapshot_diff = Some(report);
+            }
+            Err(e) => self.status_message = format!("diff failed: {e}"),
+        }
+    }
+
+    /// Ask before restoring or deleting the selected snapshot.
+    fn prompt_snapshot(&mut self, verb: &str) {
+        let Some((env_id, snapshot)) = self.selected_snapshot() else {
+            return;
+        };
+        let short = &snapshot.hash[..12.min(snapshot.hash.len())];
+        let prompt = if verb == "restore" {
+            format!("restore {short}, replacing the current upper dir? (y/n)")
+        } else {
+            format!("delete snapshot {short}? (y/n)")
+        };
+        let action = format!("{verb}-snapshot:{env_id}:{}", snapshot.hash);
+        let ask = self.prefs.confirm_snapshots;
+        self.confirm(action, prompt, ask);
+    }
+
+    /// Ask `prompt` before running the confirmed `action`, or run it at
+    /// once when the preferences turned that confirmation off.
+    fn confirm(&mut self, action: String, prompt: String, ask: bool) {
+        if ask {
+            self.show_confirm = Some(action);
+            self.status_message = prompt;
+            return;
+        }
+        self.execute_confirmed_action(&action);
+        let status = std::mem::take(&mut self.status_message);
+        self.refresh().ok();
+        self.status_message = status;
+    }
+
+    /// Whether a new task may start; reports why not otherwise.
+    fn can_start_task(&mut self) -> bool {
+        match &self.task {
+            Some(task) if task.is_running() => {
+                self.status_message = format!("{} is still running", task.label);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn start_task(&mut self, task: Task) {
+        self.status_message = format!("{}…", task.label);
+        self.task = Some(task);
+        self.view = View::Task;
+    }
+
+    /// Rebuild the selected environment from its project's manifest in the
+    /// background. Only the environment of the project the TUI was started
+    /// in has a known manifest.
+    fn start_rebuild(&mut self) {
+        let Some(env) = self.selected_env() else {
+            return;
+        };
+        let env_id = env.env_id.to_string();
+        let name = env.name.clone();
+        let label = name.clone().unwrap_or_else(|| env.short_id.to_string());
+        let store_root = self.store_root.clone();
+        let project = self.project.clone().filter(|(_, binding)| {
+            std::path::absolute(&store_root).is_ok_and(|root| root == binding.store)
+                && (binding.env_id == env_id || (name.is_some() && binding.name == name))
+        });
+        let Some((project_dir, mut binding)) = project else {
+            self.status_message =
+                format!("no manifest known for '{label}'; start the TUI in its project directory");
+            return;
+        };
+        if !self.can_start_task() {
+            return;
+        }
+        let task = Task::spawn(format!("rebuild '{label}'"), move |sink| {
+            let layout = StoreLayout::new(&store_root);
+            let _lock = StoreLock::try_acquire(&layout.lock_file())
+                .map_err(|e| e.to_string())?
+                .ok_or("the store is busy")?;
+            let engine = Engine::new(&store_root);
+            let manifest = project_dir.join("karapace.toml");
+            let result = engine
+                .rebuild_with_options(&manifest, BuildOptions::default(), sink)
+                .map_err(|e| e.to_string())?;
+            let new_id = result.identity.env_id.to_string();
+            if name.is_some() && engine.inspect(&new_id).map(|m| m.name).ok() != Some(name.clone())
+            {
+                engine.set_name(&new_id, name).map_err(|e| e.to_string())?;
+            }
+            binding.env_id.clone_from(&new_id);
+            if let Err(e) = binding.save(&project_dir) {
+                sink.message(&format!("failed to record the project binding: {e}"));
+            }
+            Ok(format!("rebuilt '{label}' as {}", result.identity.short_id))
+        });
+        self.start_task(task);
+    }
+
+    /// Push the selected environment to the configured remote in the
+    /// background.
+    fn start_push(&mut self) {
+        let Some(env) = self.selected_env() else {
+            return;
+        };
+        let env_id = env.env_id.to_string();
+        let label = env.name.clone().unwrap_or_else(|| env.short_id.to_string());
+        let Some(config) = self.remote.clone() else {
+            "no remote configured; see 'karapace remote'".clone_into(&mut self.status_message);
+            return;
+        };
+        if !self.can_start_task() {
+            return;
+        }
+        let store_root = self.store_root.clone();
+        let task = Task::spawn(format!("push '{label}'"), move |sink| {
+            let engine = Engine::new(&store_root);
+            let backend =
+                karapace_remote::open_backend(config.clone()).map_err(|e| e.to_string())?;
+            let cipher = AgeCipher::from_config(&config);
+            sink.message(&format!("pushing to {}", config.location()));
+            let on_object = |p: &karapace_remote::ObjectProgress<'_>| sink.object(p);
+            let options = TransferOptions {
+                cipher: cipher.as_ref().map(|c| c as &dyn BlobCipher),
+                on_object: Some(&on_object),
+                ..TransferOptions::default()
+            };
+            let result = engine
+                .push_with_options(&env_id, &*backend, None, &options)
+                .map_err(|e| e.to_string())?;
+            Ok(format!(
+                "pushed '{label}' ({} objects, {} layers; {} skipped)",
+                result.objects_pushed,
+                result.layers_pushed,
+                result.objects_skipped + result.layers_skipped
+            ))
+        });
+        self.start_task(task);
+    }
+
     fn start_rename(&mut self) {
         if self.selected_env().is_some() {
             self.input_mode = InputMode::Rename;
@@ -422,7 +1318,9 @@ impl App {
         self.sort_column = match self.sort_column {
             SortColumn::ShortId => SortColumn::Name,
             SortColumn::Name => SortColumn::State,
-            SortColumn::State => SortColumn::ShortId,
+            SortColumn::State => SortColumn::LastEntered,
+            SortColumn::LastEntered => SortColumn::EnterCount,
+            SortColumn::EnterCount => SortColumn::ShortId,
         };
         self.apply_sort();
         self.apply_filter();
@@ -434,6 +1332,38 @@ impl App {
     }
 
     fn execute_confirmed_action(&mut self, action: &str) {
+        if let Some((env_id, hash)) = action
+            .strip_prefix("discard-drift:")
+            .and_then(|a| a.split_once(':'))
+        {
+            self.status_message = match self.engine().restore(env_id, hash) {
+                Ok(()) => format!("drift discarded; restored {}", &hash[..12.min(hash.len())]),
+                Err(e) => format!("discard failed: {e}"),
+            };
+            self.refresh_drift();
+            return;
+        }
+        for (prefix, verb) in [
+            ("restore-snapshot:", "restored"),
+            ("delete-snapshot:", "deleted"),
+        ] {
+            let Some((env_id, hash)) = action.strip_prefix(prefix).and_then(|a| a.split_once(':'))
+            else {
+                continue;
+            };
+            let engine = self.engine();
+            let result = if verb == "restored" {
+                engine.restore(env_id, hash)
+            } else {
+                engine.remove_snapshot(env_id, hash)
+            };
+            self.load_snapshots();
+            self.status_message = match result {
+                Ok(()) => format!("{verb} snapshot {}", &hash[..12.min(hash.len())]),
+                Err(e) => format!("snapshot {} failed: {e}", &verb[..verb.len() - 1]),
+            };
+            return;
+        }
         if let Some(env_id) = action.strip_prefix("destroy:") {
             match self.engine().destroy(env_id) {
                 Ok(()) => {
@@ -446,3 +1376,61 @@ impl App {
         }
     }
 }
+
+/// A line of the drift tree: its depth, the name shown, and for files the
+/// kind of change (`+` added, `~` modified, `-` removed).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriftLine {
+    pub depth: usize,
+    pub name: String,
+    pub change: Option<char>,
+}
+
+/// The changed paths of `report` as a tree, directories first shown where
+/// their first changed file is.
+pub fn drift_tree(report: &DriftReport) -> Vec<DriftLine> {
+    let mut paths: Vec<(&str, char)> = report
+        .added
+        .iter()
+        .map(|p| (p.as_str(), '+'))
+        .chain(report.modified.iter().map(|p| (p.as_str(), '~')))
+        .chain(report.removed.iter().map(|p| (p.as_str(), '-')))
+        .collect();
+    paths.sort_unstable();
+
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    for (path, change) in paths {
+        let parts: Vec<&str> = path.split('/').collect();
+        let (file, dirs) = parts.split_last().unwrap_or((&path, &[]));
+        let common = open.iter().zip(dirs).take_while(|(a, b)| a == b).count();
+        open.truncate(common);
+        for dir in &dirs[common..] {
+            lines.push(DriftLine {
+                depth: open.len(),
+                name: format!("{dir}/"),
+                change: None,
+            });
+            open.push(dir);
+        }
+        lines.push(DriftLine {
+            depth: open.len(),
+            name: (*file).to_owned(),
+            change: Some(change),
+        });
+    }
+    lines
+}
+
+/// The remote `p` and `R` use: `url` when given, otherwise the one in the
+/// remote config file, with `KARAPACE_REMOTE_TOKEN` applied.
+pub fn resolve_remote(url: Option<&str>) -> Option<RemoteConfig> {
+    let config = match url {
+        Some(url) => RemoteConfig::new(url),
+        None => RemoteConfig::load_default().ok()?,
+    };
+    Some(match std::env::var("KARAPACE_REMOTE_TOKEN") {
+        Ok(token) if !token.is_empty() => config.with_token(&token),
+        _ => config,
+    })
+}
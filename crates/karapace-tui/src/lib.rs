@@ -1,13 +1,21 @@
 //! Terminal UI for interactive Karapace environment management.
 //!
-//! This crate provides a ratatui-based TUI with environment listing, detail views,
-//! search/filter, sorting, and keyboard-driven lifecycle actions (destroy, freeze,
-//! archive, rename).
+//! This crate provides a ratatui-based TUI with environment listing, detail views
+//! with live resource usage, search/filter, sorting, keyboard-driven lifecycle
+//! actions (destroy, freeze, archive, rename), shells opened by suspending the
+//! UI, a remote registry browser, rebuilds, pushes and pulls that run in the
+//! background with a progress gauge and log, and preferences kept between runs.
 
 mod app;
+mod prefs;
+mod task;
 mod ui;
 
-pub use app::{App, AppAction, InputMode, SortColumn, View};
+pub use app::{
+    drift_tree, resolve_remote, App, AppAction, DriftLine, InputMode, Setting, SortColumn, View,
+};
+pub use prefs::{Columns, Preferences};
+pub use task::{Task, TaskSink};
 
 use crossterm::{
     event::{self, Event, KeyEventKind},
@@ -27,9 +35,25 @@ pub fn run(store_root: &Path) -> Result<(), String> {
     let mut terminal = Terminal::new(backend).map_err(|e| format!("terminal init: {e}"))?;
 
     let mut app = App::new(store_root);
+    app.project = std::env::current_dir()
+        .ok()
+        .and_then(|dir| karapace_core::ProjectBinding::find(&dir));
+    app.prefs_path = Preferences::default_path();
+    let prefs = app
+        .prefs_path
+        .as_deref()
+        .map_or_else(|| Ok(Preferences::default()), Preferences::load);
+    let prefs_error = prefs.as_ref().err().cloned();
+    app.apply_prefs(prefs.unwrap_or_default());
     app.refresh().ok();
+    if let Some(e) = prefs_error {
+        // Leave a file that does not parse for the user to fix.
+        app.prefs_path = None;
+        app.status_message = format!("ignoring preferences: {e}");
+    }
 
     let result = run_loop(&mut terminal, &mut app);
+    let saved = app.save_prefs();
 
     disable_raw_mode().map_err(|e| format!("failed to disable raw mode: {e}"))?;
     execute!(terminal.backend_mut(), LeaveAlternateScreen)
@@ -38,7 +62,7 @@ pub fn run(store_root: &Path) -> Result<(), String> {
         .show_cursor()
         .map_err(|e| format!("show cursor: {e}"))?;
 
-    result
+    result.and(saved.map_err(|e| format!("saving preferences: {e}")))
 }
 
 fn run_loop(
@@ -61,12 +85,37 @@ fn run_loop(
                     AppAction::Refresh => {
                         app.refresh().ok();
                     }
+                    AppAction::Enter(env_id) => shell(terminal, app, &env_id)?,
                 }
             }
         }
+        app.tick();
     }
 }
 
+/// Hand the terminal to a shell in `env_id` and take it back when the
+/// shell exits.
+fn shell(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+    env_id: &str,
+) -> Result<(), String> {
+    disable_raw_mode().map_err(|e| format!("failed to disable raw mode: {e}"))?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)
+        .map_err(|e| format!("leave alternate screen: {e}"))?;
+    terminal
+        .show_cursor()
+        .map_err(|e| format!("show cursor: {e}"))?;
+    println!("{}", app.shell_banner(env_id));
+
+    app.enter_shell(env_id);
+
+    enable_raw_mode().map_err(|e| format!("failed to enable raw mode: {e}"))?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)
+        .map_err(|e| format!("alternate screen: {e}"))?;
+    terminal.clear().map_err(|e| format!("clear: {e}"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,6 +203,10 @@ mod tests {
         app.handle_key(KeyCode::Char('s'));
         assert_eq!(app.sort_column, SortColumn::State);
         app.handle_key(KeyCode::Char('s'));
+        assert_eq!(app.sort_column, SortColumn::LastEntered);
+        app.handle_key(KeyCode::Char('s'));
+        assert_eq!(app.sort_column, SortColumn::EnterCount);
+        app.handle_key(KeyCode::Char('s'));
         assert_eq!(app.sort_column, SortColumn::ShortId);
         app.handle_key(KeyCode::Char('s'));
         assert_eq!(app.sort_column, SortColumn::Name);
@@ -216,4 +269,535 @@ mod tests {
         app.apply_filter();
         assert!(app.filtered.is_empty());
     }
+
+    fn put_env(root: &Path, name: &str) -> String {
+        let layout = karapace_store::StoreLayout::new(root);
+        layout.initialize().unwrap();
+        let objects = karapace_store::ObjectStore::new(layout.clone());
+        let manifest_hash = objects.put(b"{}").unwrap();
+        let tar_hash = objects.put(name.as_bytes()).unwrap();
+        let layer = karapace_store::LayerManifest {
+            hash: tar_hash.clone(),
+            kind: karapace_store::LayerKind::Base,
+            parent: None,
+            object_refs: vec![tar_hash.clone()],
+            read_only: true,
+            tar_hash: tar_hash.clone(),
+            workspace: None,
+            delta_parent: None,
+            file_objects: Vec::new(),
+            pax_metadata: false,
+        };
+        let layer_hash = karapace_store::LayerStore::new(layout.clone())
+            .put(&layer)
+            .unwrap();
+        let meta = karapace_store::EnvMetadata {
+            env_id: format!("{name}_env_id").into(),
+            short_id: name.into(),
+            name: Some(name.to_owned()),
+            state: karapace_store::EnvState::Built,
+            manifest_hash: manifest_hash.into(),
+            base_layer: layer_hash.into(),
+            dependency_layers: vec![],
+            policy_layer: None,
+            created_at: "2025-01-01T00:00:00Z".to_owned(),
+            updated_at: "2025-01-01T00:00:00Z".to_owned(),
+            ref_count: 1,
+            checksum: None,
+            aliases: Vec::new(),
+            host_gpu: None,
+            base_image_digest: None,
+            workspace: None,
+            snapshot: None,
+            expires_after_secs: None,
+            last_entered_at: None,
+            enter_count: 0,
+            last_built_at: None,
+            labels: std::collections::BTreeMap::new(),
+            revision: 0,
+            attestation: None,
+        };
+        karapace_store::MetadataStore::new(layout)
+            .put(&meta)
+            .unwrap();
+        tar_hash
+    }
+
+    #[test]
+    fn app_health_banner_reports_incomplete_wal() {
+        let (dir, mut app) = make_app();
+        let layout = karapace_store::StoreLayout::new(dir.path());
+        layout.initialize().unwrap();
+        app.refresh_health();
+        assert!(app.health.iter().all(|c| c.name == "disk_space"));
+
+        let wal = karapace_store::WriteAheadLog::new(&layout);
+        wal.initialize().unwrap();
+        wal.begin(karapace_store::WalOpKind::Build, "env1").unwrap();
+        app.refresh().unwrap();
+        assert!(app.health.iter().any(|c| c.name == "wal_clean"));
+    }
+
+    #[test]
+    fn app_health_banner_reports_version_mismatch() {
+        let (dir, mut app) = make_app();
+        karapace_store::StoreLayout::new(dir.path())
+            .initialize()
+            .unwrap();
+        std::fs::write(
+            dir.path().join("store").join("version"),
+            r#"{"format_version": 999}"#,
+        )
+        .unwrap();
+        app.refresh_health();
+        assert!(app.health.iter().any(|c| c.name == "store_version"));
+    }
+
+    #[test]
+    fn app_detail_view_samples_stats() {
+        let (dir, mut app) = make_app();
+        put_env(dir.path(), "alpha");
+        app.refresh().unwrap();
+        assert!(app.stats.is_none());
+
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.view, View::Detail);
+        let stats = app.stats.as_ref().unwrap();
+        assert_eq!(stats.processes, 0);
+        assert_eq!(stats.memory_bytes, 0);
+    }
+
+    #[test]
+    fn app_filter_matches_labels() {
+        let (dir, mut app) = make_app();
+        put_env(dir.path(), "alpha");
+        put_env(dir.path(), "beta");
+        let labels = std::collections::BTreeMap::from([("team".to_owned(), "infra".to_owned())]);
+        app.engine()
+            .set_labels("beta_env_id", &labels, &[])
+            .unwrap();
+        app.refresh().unwrap();
+
+        app.filter = "team=infra".to_owned();
+        app.apply_filter();
+        assert_eq!(app.visible_count(), 1);
+        assert_eq!(app.selected_env().unwrap().short_id.as_str(), "beta");
+    }
+
+    #[test]
+    fn app_integrity_key_without_env() {
+        let (_dir, mut app) = make_app();
+        assert_eq!(app.handle_key(KeyCode::Char('i')), AppAction::None);
+        assert_eq!(app.view, View::List);
+        assert!(app.integrity.is_none());
+    }
+
+    #[test]
+    fn app_integrity_check_scoped_to_selected_env() {
+        let (dir, mut app) = make_app();
+        put_env(dir.path(), "alpha");
+        let beta_tar = put_env(dir.path(), "beta");
+        let objects_dir = karapace_store::StoreLayout::new(dir.path()).objects_dir();
+        std::fs::write(objects_dir.join(&beta_tar), b"corrupted").unwrap();
+        app.refresh().unwrap();
+        assert_eq!(app.visible_count(), 2);
+
+        // Sorted by name: alpha first.
+        app.handle_key(KeyCode::Char('i'));
+        assert_eq!(app.view, View::Integrity);
+        let (label, report) = app.integrity.as_ref().unwrap();
+        assert_eq!(label, "alpha");
+        assert!(report.failed.is_empty());
+
+        app.handle_key(KeyCode::Esc);
+        assert_eq!(app.view, View::List);
+        app.handle_key(KeyCode::Char('j'));
+        app.handle_key(KeyCode::Enter);
+        app.handle_key(KeyCode::Char('i'));
+        assert_eq!(app.view, View::Integrity);
+        let (label, report) = app.integrity.as_ref().unwrap();
+        assert_eq!(label, "beta");
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].hash, beta_tar);
+    }
+
+    #[test]
+    fn app_usage_view_splits_shared_objects() {
+        let (dir, mut app) = make_app();
+        put_env(dir.path(), "alpha");
+        put_env(dir.path(), "beta");
+        app.refresh().unwrap();
+
+        app.handle_key(KeyCode::Char('u'));
+        assert_eq!(app.view, View::Usage);
+        let usage = app.usage.as_ref().unwrap();
+        assert!(usage.objects_bytes > 0);
+        assert_eq!(usage.envs.len(), 2);
+        // Both share the manifest object and own their layer tar.
+        for env in &usage.envs {
+            assert!(env.exclusive_bytes > 0);
+            assert!(env.shared_bytes > 0);
+            assert_eq!(
+                env.attributed_bytes,
+                env.env_dir_bytes + env.exclusive_bytes + env.shared_bytes / 2
+            );
+        }
+        app.handle_key(KeyCode::Esc);
+        assert_eq!(app.view, View::List);
+    }
+
+    /// Tick until the task finishes, failing after ten seconds.
+    fn finish_task(app: &mut App) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while app.task.as_ref().is_some_and(Task::is_running) {
+            assert!(std::time::Instant::now() < deadline, "task did not finish");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            app.tick();
+        }
+    }
+
+    #[test]
+    fn app_rebuild_needs_the_project_manifest() {
+        let (dir, mut app) = make_app();
+        put_env(dir.path(), "alpha");
+        app.refresh().unwrap();
+        app.handle_key(KeyCode::Char('b'));
+        assert!(app.task.is_none());
+        assert_eq!(app.view, View::List);
+        assert!(app.status_message.contains("no manifest known"));
+    }
+
+    #[test]
+    fn app_rebuild_runs_in_the_background() {
+        let (dir, mut app) = make_app();
+        let project = tempfile::tempdir().unwrap();
+        let manifest = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest,
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\nbackend = \"mock\"\n",
+        )
+        .unwrap();
+        let engine = karapace_core::Engine::new(dir.path());
+        let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+        let binding = karapace_core::ProjectBinding {
+            env_id: env_id.clone(),
+            name: None,
+            store: dir.path().to_path_buf(),
+        };
+        app.project = Some((project.path().to_path_buf(), binding));
+        app.refresh().unwrap();
+
+        app.handle_key(KeyCode::Char('b'));
+        assert_eq!(app.view, View::Task);
+        finish_task(&mut app);
+
+        let task = app.task.as_ref().unwrap();
+        assert!(matches!(task.outcome, Some(Ok(_))), "{:?}", task.log);
+        assert!((task.ratio - 1.0).abs() < f64::EPSILON);
+        assert!(app.status_message.starts_with("rebuilt"));
+        assert_eq!(app.environments.len(), 1);
+        assert_eq!(app.environments[0].env_id.to_string(), env_id);
+        let saved = karapace_core::ProjectBinding::load(project.path()).unwrap();
+        assert_eq!(saved.unwrap().env_id, env_id);
+
+        app.handle_key(KeyCode::Esc);
+        assert_eq!(app.view, View::List);
+        app.handle_key(KeyCode::Char('l'));
+        assert_eq!(app.view, View::Task);
+    }
+
+    #[test]
+    fn app_push_failure_is_reported() {
+        let (dir, mut app) = make_app();
+        put_env(dir.path(), "alpha");
+        app.refresh().unwrap();
+        app.handle_key(KeyCode::Char('p'));
+        assert!(app.status_message.contains("no remote"));
+
+        app.remote = Some(karapace_remote::RemoteConfig::new("http://127.0.0.1:1"));
+        app.handle_key(KeyCode::Char('p'));
+        assert_eq!(app.view, View::Task);
+        finish_task(&mut app);
+        assert!(matches!(app.task.as_ref().unwrap().outcome, Some(Err(_))));
+        assert!(app.status_message.starts_with("push 'alpha' failed"));
+    }
+
+    #[test]
+    fn app_quit_asks_while_a_task_runs() {
+        let (_dir, mut app) = make_app();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        app.task = Some(Task::spawn("wait".to_owned(), move |_| {
+            rx.recv().ok();
+            Ok(String::new())
+        }));
+        assert_eq!(app.handle_key(KeyCode::Char('q')), AppAction::None);
+        assert!(app.show_confirm.is_some());
+        assert_eq!(app.handle_key(KeyCode::Char('n')), AppAction::None);
+        tx.send(()).unwrap();
+        finish_task(&mut app);
+        assert_eq!(app.handle_key(KeyCode::Char('q')), AppAction::Quit);
+    }
+
+    #[test]
+    fn app_snapshots_view_diffs_restores_and_deletes() {
+        let (dir, mut app) = make_app();
+        let project = tempfile::tempdir().unwrap();
+        let manifest = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest,
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\nbackend = \"mock\"\n",
+        )
+        .unwrap();
+        let engine = karapace_core::Engine::new(dir.path());
+        let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+        let upper = engine.store_layout().upper_dir(&env_id);
+        std::fs::create_dir_all(&upper).unwrap();
+        std::fs::write(upper.join("notes.txt"), "v1").unwrap();
+        engine.commit(&env_id).unwrap();
+        std::fs::write(upper.join("notes.txt"), "v2").unwrap();
+        std::fs::write(upper.join("new.txt"), "x").unwrap();
+        app.refresh().unwrap();
+
+        app.handle_key(KeyCode::Char('t'));
+        assert_eq!(app.view, View::Snapshots);
+        assert_eq!(app.snapshots.len(), 1);
+        assert!(app.snapshots[0].current);
+
+        app.handle_key(KeyCode::Enter);
+        let diff = app.snapshot_diff.as_ref().unwrap();
+        assert_eq!(diff.added, vec!["new.txt"]);
+        assert_eq!(diff.modified, vec!["notes.txt"]);
+
+        app.handle_key(KeyCode::Char('r'));
+        assert!(app.show_confirm.is_some());
+        app.handle_key(KeyCode::Char('y'));
+        assert_eq!(
+            std::fs::read_to_string(upper.join("notes.txt")).unwrap(),
+            "v1"
+        );
+        assert!(!upper.join("new.txt").exists());
+        assert!(app.status_message.starts_with("restored"));
+
+        app.handle_key(KeyCode::Char('d'));
+        app.handle_key(KeyCode::Char('y'));
+        assert!(app.snapshots.is_empty());
+        assert!(engine.list_snapshots(&env_id).unwrap().is_empty());
+        app.handle_key(KeyCode::Esc);
+        assert_eq!(app.view, View::List);
+    }
+
+    #[test]
+    fn app_detail_drift_pane_commits_and_discards() {
+        let (dir, mut app) = make_app();
+        let project = tempfile::tempdir().unwrap();
+        let manifest = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest,
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\nbackend = \"mock\"\n",
+        )
+        .unwrap();
+        let engine = karapace_core::Engine::new(dir.path());
+        let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+        let upper = engine.store_layout().upper_dir(&env_id);
+        std::fs::create_dir_all(upper.join("etc/app")).unwrap();
+        std::fs::write(upper.join("etc/app/conf"), "v1").unwrap();
+        std::fs::write(upper.join("notes.txt"), "x").unwrap();
+        app.refresh().unwrap();
+
+        app.handle_key(KeyCode::Char('x'));
+        assert!(app.show_confirm.is_none());
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.view, View::Detail);
+        let drift = app.drift.as_ref().unwrap();
+        assert_eq!(drift.added.len(), 3);
+        let tree: Vec<(usize, String, Option<char>)> = drift_tree(drift)
+            .into_iter()
+            .map(|l| (l.depth, l.name, l.change))
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                (0, ".karapace-mock".to_owned(), Some('+')),
+                (0, "etc/".to_owned(), None),
+                (1, "app/".to_owned(), None),
+                (2, "conf".to_owned(), Some('+')),
+                (0, "notes.txt".to_owned(), Some('+')),
+            ]
+        );
+        app.handle_key(KeyCode::Char('J'));
+        assert_eq!(app.drift_scroll, 1);
+        app.handle_key(KeyCode::Char('K'));
+        assert_eq!(app.drift_scroll, 0);
+
+        app.handle_key(KeyCode::Char('x'));
+        assert!(app.show_confirm.is_none());
+        assert!(app.status_message.contains("no snapshot"));
+
+        app.handle_key(KeyCode::Char('c'));
+        assert!(app.status_message.starts_with("committed drift"));
+        assert_eq!(engine.list_snapshots(&env_id).unwrap().len(), 1);
+
+        std::fs::write(upper.join("etc/app/conf"), "v2").unwrap();
+        std::fs::write(upper.join("stray.txt"), "y").unwrap();
+        app.refresh_drift();
+        assert_eq!(app.drift.as_ref().unwrap().added.len(), 4);
+        app.handle_key(KeyCode::Char('x'));
+        assert!(app.show_confirm.is_some());
+        app.handle_key(KeyCode::Char('y'));
+        assert!(app.status_message.starts_with("drift discarded"));
+        assert_eq!(
+            std::fs::read_to_string(upper.join("etc/app/conf")).unwrap(),
+            "v1"
+        );
+        assert!(!upper.join("stray.txt").exists());
+        assert_eq!(app.drift.as_ref().unwrap().added.len(), 3);
+    }
+
+    #[test]
+    fn app_enter_opens_a_shell_only_in_built_environments() {
+        let (dir, mut app) = make_app();
+        let project = tempfile::tempdir().unwrap();
+        let manifest = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest,
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\nbackend = \"mock\"\n",
+        )
+        .unwrap();
+        let engine = karapace_core::Engine::new(dir.path());
+        let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+        engine.set_name(&env_id, Some("web".to_owned())).unwrap();
+        app.refresh().unwrap();
+
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.view, View::Detail);
+        assert_eq!(
+            app.handle_key(KeyCode::Enter),
+            AppAction::Enter(env_id.clone())
+        );
+        assert!(app.shell_banner(&env_id).contains("shell in 'web'"));
+        app.enter_shell(&env_id);
+        assert!(app.status_message.starts_with("left the shell"));
+
+        engine.freeze(&env_id).unwrap();
+        app.refresh().unwrap();
+        assert_eq!(app.handle_key(KeyCode::Enter), AppAction::None);
+        assert!(app.status_message.contains("is frozen"));
+    }
+
+    #[test]
+    fn app_remote_view_lists_the_registry_and_pulls() {
+        let server_dir = tempfile::tempdir().unwrap();
+        let server = karapace_server::TestServer::start(server_dir.path().to_path_buf());
+        let source = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        let manifest = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest,
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\nbackend = \"mock\"\n",
+        )
+        .unwrap();
+        let engine = karapace_core::Engine::new(source.path());
+        let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+        let backend =
+            karapace_remote::open_backend(karapace_remote::RemoteConfig::new(&server.url)).unwrap();
+        engine.push(&env_id, &*backend, Some("web@latest")).unwrap();
+
+        let (_dir, mut app) = make_app();
+        app.handle_key(KeyCode::Char('R'));
+        assert_eq!(app.view, View::List);
+        assert!(app.status_message.contains("no remote configured"));
+
+        app.remote = Some(karapace_remote::RemoteConfig::new(&server.url));
+        app.handle_key(KeyCode::Char('R'));
+        assert_eq!(app.view, View::Remote);
+        assert_eq!(app.remote_entries.len(), 1);
+        assert_eq!(app.remote_entries[0].0, "web@latest");
+        assert!(!app.is_local(&env_id));
+
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.view, View::Task);
+        finish_task(&mut app);
+        let task = app.task.as_ref().unwrap();
+        assert!(matches!(&task.outcome, Some(Ok(s)) if s.starts_with("pulled 'web@latest'")));
+        assert!(app.is_local(&env_id));
+    }
+
+    #[test]
+    fn app_settings_persist_and_skip_confirmations() {
+        let (dir, mut app) = make_app();
+        let project = tempfile::tempdir().unwrap();
+        let manifest = project.path().join("karapace.toml");
+        std::fs::write(
+            &manifest,
+            "manifest_version = 1\n[base]\nimage = \"rolling\"\n[runtime]\nbackend = \"mock\"\n",
+        )
+        .unwrap();
+        let engine = karapace_core::Engine::new(dir.path());
+        let env_id = engine.build(&manifest).unwrap().identity.env_id.to_string();
+        engine.set_name(&env_id, Some("alpha".to_owned())).unwrap();
+        put_env(dir.path(), "beta");
+        let config = tempfile::tempdir().unwrap();
+        let path = config.path().join("karapace/tui.toml");
+        app.prefs_path = Some(path.clone());
+        app.refresh().unwrap();
+
+        app.handle_key(KeyCode::Char('o'));
+        assert_eq!(app.view, View::Settings);
+        // Sort descending, hide ENV_ID, turn off the destroy confirmation.
+        app.handle_key(KeyCode::Char('j'));
+        app.handle_key(KeyCode::Enter);
+        for _ in 0..4 {
+            app.handle_key(KeyCode::Char('j'));
+        }
+        app.handle_key(KeyCode::Enter);
+        app.handle_key(KeyCode::Char('j'));
+        app.handle_key(KeyCode::Enter);
+        assert!(app.status_message.starts_with("saved"));
+        // The remote is edited in place.
+        for _ in 0..3 {
+            app.handle_key(KeyCode::Char('j'));
+        }
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.input_mode, InputMode::Remote);
+        for c in "http://remote.example".chars() {
+            app.handle_key(KeyCode::Char(c));
+        }
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(
+            app.remote
+                .as_ref()
+                .map(karapace_remote::RemoteConfig::location),
+            Some("http://remote.example".to_owned())
+        );
+        app.handle_key(KeyCode::Esc);
+        app.handle_key(KeyCode::Char('/'));
+        for c in "al".chars() {
+            app.handle_key(KeyCode::Char(c));
+        }
+        app.handle_key(KeyCode::Enter);
+        app.save_prefs().unwrap();
+
+        let prefs = Preferences::load(&path).unwrap();
+        assert!(!prefs.sort_ascending);
+        assert!(!prefs.columns.env_id && prefs.columns.name);
+        assert!(!prefs.confirm_destroy && prefs.confirm_snapshots);
+        assert_eq!(prefs.filter, "al");
+        assert_eq!(prefs.remote.as_deref(), Some("http://remote.example"));
+
+        let mut next = App::new(dir.path());
+        next.apply_prefs(prefs);
+        next.refresh().unwrap();
+        assert_eq!(next.visible_count(), 1);
+        next.handle_key(KeyCode::Char('d'));
+        assert!(next.show_confirm.is_none());
+        assert!(next.status_message.starts_with("destroyed"));
+        assert_eq!(next.environments.len(), 1);
+
+        std::fs::write(&path, "sort_column = 3\n").unwrap();
+        assert!(Preferences::load(&path).is_err());
+        assert_eq!(
+            Preferences::load(&config.path().join("missing.toml")).unwrap(),
+            Preferences::default()
+        );
+    }
 }
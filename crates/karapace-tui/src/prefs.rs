@@ -0,0 +1,95 @@
+//! Preferences kept between runs.
+//!
+//! The settings view (`o`) edits them and writes them at once; the sort
+//! order and filter in effect when the TUI quits are written back too, so
+//! the next run starts where this one left off.
+
+use crate::app::SortColumn;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Contents of `~/.config/karapace/tui.toml`. Missing keys take their
+/// defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools)] // independent settings
+pub struct Preferences {
+    pub sort_column: SortColumn,
+    pub sort_ascending: bool,
+    /// Filter of the environment list; empty for none.
+    pub filter: String,
+    /// Ask before destroying an environment.
+    pub confirm_destroy: bool,
+    /// Ask before restoring or deleting a snapshot and discarding drift.
+    pub confirm_snapshots: bool,
+    /// Ask before quitting while a rebuild, push or pull runs.
+    pub confirm_quit: bool,
+    /// URL of the remote to push to and browse, instead of the one in
+    /// `~/.config/karapace/remote.json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    pub columns: Columns,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            sort_column: SortColumn::Name,
+            sort_ascending: true,
+            filter: String::new(),
+            confirm_destroy: true,
+            confirm_snapshots: true,
+            confirm_quit: true,
+            remote: None,
+            columns: Columns::default(),
+        }
+    }
+}
+
+/// Which columns the environment list shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools)] // one flag per column
+pub struct Columns {
+    pub short_id: bool,
+    pub name: bool,
+    pub state: bool,
+    pub env_id: bool,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            short_id: true,
+            name: true,
+            state: true,
+            env_id: true,
+        }
+    }
+}
+
+impl Preferences {
+    /// `~/.config/karapace/tui.toml`, or `None` when `HOME` is not set.
+    pub fn default_path() -> Option<PathBuf> {
+        let home = std::env::var("HOME").ok()?;
+        Some(PathBuf::from(home).join(".config/karapace/tui.toml"))
+    }
+
+    /// Read `path`; a missing file yields the defaults.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| format!("{}: {}", path.display(), e.message().trim())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {e}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+        }
+        std::fs::write(path, content).map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
@@ -0,0 +1,160 @@
+//! Operations that outlive a key press.
+//!
+//! Rebuilds and pushes run on a background thread so the UI keeps drawing.
+//! The thread reports through a [`TaskSink`], which is a [`ProgressSink`]
+//! for builds and takes object counts for transfers; [`Task::poll`] folds
+//! those events into the gauge and log the task view shows.
+
+use karapace_remote::ObjectProgress;
+use karapace_runtime::{BuildPhase, ProgressSink};
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Log lines kept per task.
+const LOG_LINES: usize = 200;
+
+enum TaskEvent {
+    Phase(BuildPhase),
+    Message(String),
+    Progress { done: u64, total: u64 },
+    Finished(Result<String, String>),
+}
+
+/// Progress reporter handed to a task's thread.
+pub struct TaskSink(Sender<TaskEvent>);
+
+impl TaskSink {
+    /// An object of a transfer finished.
+    pub fn object(&self, progress: &ObjectProgress<'_>) {
+        let _ = self.0.send(TaskEvent::Progress {
+            done: progress.done as u64,
+            total: progress.total as u64,
+        });
+    }
+}
+
+impl ProgressSink for TaskSink {
+    fn phase(&self, phase: BuildPhase) {
+        let _ = self.0.send(TaskEvent::Phase(phase));
+    }
+
+    fn message(&self, message: &str) {
+        let _ = self.0.send(TaskEvent::Message(message.to_owned()));
+    }
+
+    fn download(&self, done: u64, total: Option<u64>) {
+        if let Some(total) = total {
+            let _ = self.0.send(TaskEvent::Progress { done, total });
+        }
+    }
+}
+
+/// A background operation and what it reported so far.
+pub struct Task {
+    /// What is running, e.g. `"rebuild 'web'"`.
+    pub label: String,
+    /// Current build phase, if the task has phases.
+    pub phase: Option<BuildPhase>,
+    /// Completion between 0 and 1.
+    pub ratio: f64,
+    pub log: VecDeque<String>,
+    /// Summary on success, error on failure; `None` while running.
+    pub outcome: Option<Result<String, String>>,
+    events: Receiver<TaskEvent>,
+}
+
+impl Task {
+    /// Run `work` on a new thread. Its return value becomes the outcome.
+    pub fn spawn(
+        label: String,
+        work: impl FnOnce(&TaskSink) -> Result<String, String> + Send + 'static,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let sink = TaskSink(tx);
+            let outcome = work(&sink);
+            let _ = sink.0.send(TaskEvent::Finished(outcome));
+        });
+        Self {
+            label,
+            phase: None,
+            ratio: 0.0,
+            log: VecDeque::new(),
+            outcome: None,
+            events: rx,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.outcome.is_none()
+    }
+
+    /// Apply the events reported since the last poll. Returns `true` when
+    /// the task finished during this poll.
+    pub fn poll(&mut self) -> bool {
+        let mut finished = false;
+        loop {
+            let event = match self.events.try_recv() {
+                Ok(event) => event,
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    if self.is_running() {
+                        self.outcome = Some(Err("task ended unexpectedly".to_owned()));
+                        finished = true;
+                    }
+                    break;
+                }
+            };
+            match event {
+                TaskEvent::Phase(phase) => {
+                    self.phase = Some(phase);
+                    self.ratio = phase_ratio(phase);
+                    self.push_log(format!("== {}", phase.label()));
+                }
+                TaskEvent::Message(message) => self.push_log(message),
+                TaskEvent::Progress { done, total } if total > 0 => {
+                    let fraction = done.min(total) as f64 / total as f64;
+                    self.ratio = match self.phase {
+                        Some(phase) => phase_ratio(phase) + fraction / PHASES,
+                        None => fraction,
+                    };
+                }
+                TaskEvent::Progress { .. } => {}
+                TaskEvent::Finished(outcome) => {
+                    if outcome.is_ok() {
+                        self.ratio = 1.0;
+                    }
+                    self.push_log(match &outcome {
+                        Ok(summary) => summary.clone(),
+                        Err(e) => format!("error: {e}"),
+                    });
+                    self.outcome = Some(outcome);
+                    finished = true;
+                }
+            }
+        }
+        finished
+    }
+
+    fn push_log(&mut self, line: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+}
+
+/// Number of build phases.
+const PHASES: f64 = 5.0;
+
+/// Share of a build done when `phase` starts.
+fn phase_ratio(phase: BuildPhase) -> f64 {
+    let index = match phase {
+        BuildPhase::Resolve => 0.0,
+        BuildPhase::FetchImage => 1.0,
+        BuildPhase::Unpack => 2.0,
+        BuildPhase::InstallPackages => 3.0,
+        BuildPhase::PackLayer => 4.0,
+    };
+    index / PHASES
+}
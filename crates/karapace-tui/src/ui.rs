@@ -1,34 +1,45 @@
-use crate::app::{App, InputMode, View};
+use crate::app::{drift_tree, App, InputMode, Setting, View};
+use karapace_core::CheckStatus;
 use ratatui::{
     prelude::*,
-    widgets::{Block, Borders, Cell, Paragraph, Row, Table, Wrap},
+    widgets::{Block, Borders, Cell, Gauge, Paragraph, Row, Table, Wrap},
 };
 
 pub fn draw(f: &mut Frame<'_>, app: &App) {
+    let banner_height = u16::try_from(app.health.len()).unwrap_or(u16::MAX);
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Length(1),
+            Constraint::Length(banner_height),
             Constraint::Min(5),
             Constraint::Length(1),
         ])
         .split(f.area());
 
-    draw_header(f, chunks[0]);
+    draw_header(f, app, chunks[0]);
+    draw_health_banner(f, app, chunks[1]);
 
     match app.view {
-        View::List => draw_list(f, app, chunks[1]),
-        View::Detail => draw_detail(f, app, chunks[1]),
-        View::Help => draw_help(f, chunks[1]),
+        View::List => draw_list(f, app, chunks[2]),
+        View::Detail => draw_detail(f, app, chunks[2]),
+        View::Help => draw_help(f, chunks[2]),
+        View::Integrity => draw_integrity(f, app, chunks[2]),
+        View::Usage => draw_usage(f, app, chunks[2]),
+        View::Task => draw_task(f, app, chunks[2]),
+        View::Snapshots => draw_snapshots(f, app, chunks[2]),
+        View::Remote => draw_remote(f, app, chunks[2]),
+        View::Settings => draw_settings(f, app, chunks[2]),
     }
 
-    draw_status_bar(f, app, chunks[2]);
+    draw_status_bar(f, app, chunks[3]);
 }
 
-fn draw_header(f: &mut Frame<'_>, area: Rect) {
+fn draw_header(f: &mut Frame<'_>, app: &App, area: Rect) {
     let title = Paragraph::new(format!(
-        " Karapace Environment Manager  v{}",
-        env!("CARGO_PKG_VERSION")
+        " Karapace Environment Manager  v{}  store: {}",
+        env!("CARGO_PKG_VERSION"),
+        app.store_root.display()
     ))
     .style(
         Style::default()
@@ -38,6 +49,27 @@ fn draw_header(f: &mut Frame<'_>, area: Rect) {
     f.render_widget(title, area);
 }
 
+fn draw_health_banner(f: &mut Frame<'_>, app: &App, area: Rect) {
+    if app.health.is_empty() {
+        return;
+    }
+    let lines: Vec<Line<'_>> = app
+        .health
+        .iter()
+        .map(|check| {
+            let (icon, color) = match check.status {
+                CheckStatus::Fail => ("✗", Color::Red),
+                _ => ("⚠", Color::Yellow),
+            };
+            Line::from(Span::styled(
+                format!(" {icon} {}", check.message),
+                Style::default().fg(color).add_modifier(Modifier::BOLD),
+            ))
+        })
+        .collect();
+    f.render_widget(Paragraph::new(lines), area);
+}
+
 fn draw_list(f: &mut Frame<'_>, app: &App, area: Rect) {
     if app.environments.is_empty() {
         let msg = Paragraph::new("  No environments found. Press 'q' to quit.").block(
@@ -49,12 +81,22 @@ fn draw_list(f: &mut Frame<'_>, app: &App, area: Rect) {
         return;
     }
 
-    let header = Row::new(vec![
-        Cell::from("SHORT_ID").style(Style::default().add_modifier(Modifier::BOLD)),
-        Cell::from("NAME").style(Style::default().add_modifier(Modifier::BOLD)),
-        Cell::from("STATE").style(Style::default().add_modifier(Modifier::BOLD)),
-        Cell::from("ENV_ID").style(Style::default().add_modifier(Modifier::BOLD)),
-    ])
+    let columns = app.prefs.columns;
+    let shown = [
+        columns.short_id,
+        columns.name,
+        columns.state,
+        columns.env_id,
+    ];
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let header = Row::new(
+        ["SHORT_ID", "NAME", "STATE", "ENV_ID"]
+            .into_iter()
+            .zip(shown)
+            .filter(|(_, show)| *show)
+            .map(|(title, _)| Cell::from(title).style(bold))
+            .collect::<Vec<_>>(),
+    )
     .height(1);
 
     let rows: Vec<Row<'_>> = app
@@ -71,31 +113,42 @@ fn draw_list(f: &mut Frame<'_>, app: &App, area: Rect) {
                 Style::default()
             };
             let state_style = state_color(&env.state.to_string());
-            Row::new(vec![
+            let cells = [
                 Cell::from(env.short_id.to_string()),
                 Cell::from(env.name.as_deref().unwrap_or("").to_owned()),
                 Cell::from(env.state.to_string()).style(state_style),
                 Cell::from(env.env_id.to_string()),
-            ])
+            ];
+            Row::new(
+                cells
+                    .into_iter()
+                    .zip(shown)
+                    .filter(|(_, show)| *show)
+                    .map(|(cell, _)| cell)
+                    .collect::<Vec<_>>(),
+            )
             .style(style)
         })
         .collect();
 
-    let table = Table::new(
-        rows,
-        [
-            Constraint::Length(14),
-            Constraint::Length(16),
-            Constraint::Length(10),
-            Constraint::Min(20),
-        ],
-    )
-    .header(header)
-    .block(Block::default().borders(Borders::ALL).title(format!(
-        " Environments ({}/{}) ",
-        app.visible_count(),
-        app.environments.len()
-    )));
+    let widths: Vec<Constraint> = [
+        Constraint::Length(14),
+        Constraint::Length(16),
+        Constraint::Length(10),
+        Constraint::Min(20),
+    ]
+    .into_iter()
+    .zip(shown)
+    .filter(|(_, show)| *show)
+    .map(|(width, _)| width)
+    .collect();
+    let table = Table::new(rows, widths).header(header).block(
+        Block::default().borders(Borders::ALL).title(format!(
+            " Environments ({}/{}) ",
+            app.visible_count(),
+            app.environments.len()
+        )),
+    );
 
     f.render_widget(table, area);
 }
@@ -109,76 +162,52 @@ fn draw_detail(f: &mut Frame<'_>, app: &App, area: Rect) {
     };
 
     let text = vec![
-        Line::from(vec![
-            Span::styled(
-                "env_id:      ",
-                Style::default().add_modifier(Modifier::BOLD),
-            ),
-            Span::raw(env.env_id.to_string()),
-        ]),
-        Line::from(vec![
-            Span::styled(
-                "short_id:    ",
-                Style::default().add_modifier(Modifier::BOLD),
-            ),
-            Span::raw(env.short_id.to_string()),
-        ]),
-        Line::from(vec![
-            Span::styled(
-                "name:        ",
-                Style::default().add_modifier(Modifier::BOLD),
-            ),
+        detail_line("env_id:      ", Span::raw(env.env_id.to_string())),
+        detail_line("short_id:    ", Span::raw(env.short_id.to_string())),
+        detail_line(
+            "name:        ",
             Span::raw(env.name.as_deref().unwrap_or("(none)")),
-        ]),
-        Line::from(vec![
-            Span::styled(
-                "state:       ",
-                Style::default().add_modifier(Modifier::BOLD),
-            ),
+        ),
+        detail_line(
+            "state:       ",
             Span::styled(env.state.to_string(), state_color(&env.state.to_string())),
-        ]),
-        Line::from(vec![
-            Span::styled(
-                "base_layer:  ",
-                Style::default().add_modifier(Modifier::BOLD),
-            ),
-            Span::raw(env.base_layer.to_string()),
-        ]),
-        Line::from(vec![
-            Span::styled(
-                "deps:        ",
-                Style::default().add_modifier(Modifier::BOLD),
-            ),
+        ),
+        detail_line("base_layer:  ", Span::raw(env.base_layer.to_string())),
+        detail_line(
+            "deps:        ",
             Span::raw(env.dependency_layers.len().to_string()),
-        ]),
-        Line::from(vec![
-            Span::styled(
-                "ref_count:   ",
-                Style::default().add_modifier(Modifier::BOLD),
-            ),
-            Span::raw(env.ref_count.to_string()),
-        ]),
-        Line::from(vec![
-            Span::styled(
-                "created_at:  ",
-                Style::default().add_modifier(Modifier::BOLD),
-            ),
-            Span::raw(&env.created_at),
-        ]),
-        Line::from(vec![
-            Span::styled(
-                "updated_at:  ",
-                Style::default().add_modifier(Modifier::BOLD),
-            ),
-            Span::raw(&env.updated_at),
-        ]),
+        ),
+        detail_line("ref_count:   ", Span::raw(env.ref_count.to_string())),
+        detail_line("created_at:  ", Span::raw(&env.created_at)),
+        detail_line("updated_at:  ", Span::raw(&env.updated_at)),
+        detail_line(
+            "built_at:    ",
+            Span::raw(env.last_built_at.as_deref().unwrap_or("never")),
+        ),
+        detail_line(
+            "entered_at:  ",
+            Span::raw(format!(
+                "{} ({} sessions)",
+                env.last_entered_at.as_deref().unwrap_or("never"),
+                env.enter_count
+            )),
+        ),
         Line::from(""),
         Line::from(Span::styled(
-            "  [Esc] back  [d] destroy  [f] freeze  [a] archive  [n] rename",
+            "  [Esc] back  [Enter] shell  [d] destroy  [f] freeze  [a] archive  [n] rename  [i] integrity  [b] rebuild  [p] push  [t] snapshots  [c] commit drift  [x] discard drift  [J/K] scroll",
             Style::default().fg(Color::DarkGray),
         )),
     ];
 
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(5), Constraint::Length(7)])
+        .split(area);
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+        .split(chunks[0]);
+
     let detail = Paragraph::new(text)
         .block(Block::default().borders(Borders::ALL).title(format!(
             " {} ",
@@ -186,7 +215,462 @@ fn draw_detail(f: &mut Frame<'_>, app: &App, area: Rect) {
         )))
         .wrap(Wrap { trim: false });
 
-    f.render_widget(detail, area);
+    f.render_widget(detail, top[0]);
+    draw_drift(f, app, top[1]);
+    draw_stats(f, app, chunks[1]);
+}
+
+/// A `label: value` line of the detail view.
+fn detail_line<'a>(label: &'static str, value: Span<'a>) -> Line<'a> {
+    Line::from(vec![
+        Span::styled(label, Style::default().add_modifier(Modifier::BOLD)),
+        value,
+    ])
+}
+
+fn draw_drift(f: &mut Frame<'_>, app: &App, area: Rect) {
+    let Some(report) = &app.drift else {
+        let msg = Paragraph::new("  Drift unavailable.")
+            .block(Block::default().borders(Borders::ALL).title(" Drift "));
+        f.render_widget(msg, area);
+        return;
+    };
+
+    let mut lines: Vec<Line<'_>> = drift_tree(report)
+        .into_iter()
+        .map(|line| {
+            let indent = "  ".repeat(line.depth);
+            match line.change {
+                Some(change) => {
+                    let color = match change {
+                        '+' => Color::Green,
+                        '~' => Color::Yellow,
+                        _ => Color::Red,
+                    };
+                    Line::from(Span::styled(
+                        format!(" {indent}{change} {}", line.name),
+                        Style::default().fg(color),
+                    ))
+                }
+                None => Line::from(Span::styled(
+                    format!(" {indent}  {}", line.name),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )),
+            }
+        })
+        .collect();
+    if lines.is_empty() {
+        lines.push(Line::from("  No drift from the built layers."));
+    }
+
+    let scroll = u16::try_from(app.drift_scroll).unwrap_or(u16::MAX);
+    let drift = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(format!(
+            " Drift  +{} ~{} -{} ",
+            report.added.len(),
+            report.modified.len(),
+            report.removed.len()
+        )))
+        .scroll((scroll, 0));
+    f.render_widget(drift, area);
+}
+
+fn draw_stats(f: &mut Frame<'_>, app: &App, area: Rect) {
+    let block = Block::default().borders(Borders::ALL).title(" Resources ");
+    let Some(stats) = &app.stats else {
+        let msg = Paragraph::new("  Resource usage unavailable.").block(block);
+        f.render_widget(msg, area);
+        return;
+    };
+
+    let field = |label: &'static str, value: String| {
+        Line::from(vec![
+            Span::styled(label, Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(value),
+        ])
+    };
+    let text = vec![
+        field("processes:   ", stats.processes.to_string()),
+        field(
+            "cpu:         ",
+            format!("{:.1}s ({:.1}%)", stats.cpu_secs, stats.cpu_percent),
+        ),
+        field("memory:      ", format_mib(stats.memory_bytes)),
+        field("disk:        ", format_mib(stats.disk_bytes)),
+    ];
+    f.render_widget(Paragraph::new(text).block(block), area);
+}
+
+fn format_mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn draw_integrity(f: &mut Frame<'_>, app: &App, area: Rect) {
+    let Some((label, report)) = &app.integrity else {
+        let msg = Paragraph::new("  No integrity check has been run.")
+            .block(Block::default().borders(Borders::ALL).title(" Integrity "));
+        f.render_widget(msg, area);
+        return;
+    };
+
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let mut text = vec![
+        Line::from(vec![
+            Span::styled("metadata:    ", bold),
+            Span::raw(format!(
+                "{}/{} ok",
+                report.metadata_passed, report.metadata_checked
+            )),
+        ]),
+        Line::from(vec![
+            Span::styled("layers:      ", bold),
+            Span::raw(format!(
+                "{}/{} ok",
+                report.layers_passed, report.layers_checked
+            )),
+        ]),
+        Line::from(vec![
+            Span::styled("objects:     ", bold),
+            Span::raw(format!("{}/{} ok", report.passed, report.checked)),
+        ]),
+        Line::from(""),
+    ];
+    if report.failed.is_empty() {
+        text.push(Line::from(Span::styled(
+            "  No problems found.",
+            Style::default().fg(Color::Green),
+        )));
+    } else {
+        for failure in &report.failed {
+            text.push(Line::from(Span::styled(
+                format!("  ✗ {}: {}", failure.hash, failure.reason),
+                Style::default().fg(Color::Red),
+            )));
+        }
+        text.push(Line::from(""));
+        text.push(Line::from(Span::styled(
+            "  Run 'karapace verify-store' for a full check, or rebuild the environment.",
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+
+    let view = Paragraph::new(text)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(" Integrity: {label} ")),
+        )
+        .wrap(Wrap { trim: false });
+
+    f.render_widget(view, area);
+}
+
+fn draw_usage(f: &mut Frame<'_>, app: &App, area: Rect) {
+    let Some(usage) = &app.usage else {
+        let msg = Paragraph::new("  Disk usage unavailable.")
+            .block(Block::default().borders(Borders::ALL).title(" Disk usage "));
+        f.render_widget(msg, area);
+        return;
+    };
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(9), Constraint::Min(3)])
+        .split(area);
+
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let field = |label: &'static str, bytes: u64| {
+        Line::from(vec![
+            Span::styled(label, bold),
+            Span::raw(format_mib(bytes)),
+        ])
+    };
+    let text = vec![
+        field("objects:     ", usage.objects_bytes),
+        field("layers:      ", usage.layers_bytes),
+        field("envs:        ", usage.env_bytes),
+        field("images:      ", usage.images_bytes),
+        field("wal:         ", usage.wal_bytes),
+        field("other:       ", usage.other_bytes),
+        field("total:       ", usage.total_bytes()),
+    ];
+    f.render_widget(
+        Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(" Store ")),
+        chunks[0],
+    );
+
+    let header = Row::new(vec![
+        Cell::from("NAME").style(bold),
+        Cell::from("ENV DIR").style(bold),
+        Cell::from("EXCLUSIVE").style(bold),
+        Cell::from("SHARED").style(bold),
+        Cell::from("ATTRIBUTED").style(bold),
+    ]);
+    let rows: Vec<Row<'_>> = usage
+        .envs
+        .iter()
+        .map(|env| {
+            Row::new(vec![
+                Cell::from(
+                    env.name
+                        .clone()
+                        .unwrap_or_else(|| env.env_id[..12.min(env.env_id.len())].to_owned()),
+                ),
+                Cell::from(format_mib(env.env_dir_bytes)),
+                Cell::from(format_mib(env.exclusive_bytes)),
+                Cell::from(format_mib(env.shared_bytes)),
+                Cell::from(format_mib(env.attributed_bytes)),
+            ])
+        })
+        .collect();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(16),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+        ],
+    )
+    .header(header)
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Environments "),
+    );
+    f.render_widget(table, chunks[1]);
+}
+
+fn draw_task(f: &mut Frame<'_>, app: &App, area: Rect) {
+    let Some(task) = &app.task else {
+        let msg = Paragraph::new("  No task has been started.")
+            .block(Block::default().borders(Borders::ALL).title(" Task "));
+        f.render_widget(msg, area);
+        return;
+    };
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(3)])
+        .split(area);
+
+    let (state, color) = match &task.outcome {
+        None => (
+            task.phase.map_or("running", |phase| phase.label()),
+            Color::Cyan,
+        ),
+        Some(Ok(_)) => ("done", Color::Green),
+        Some(Err(_)) => ("failed", Color::Red),
+    };
+    let gauge = Gauge::default()
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(" {}: {state} ", task.label)),
+        )
+        .gauge_style(Style::default().fg(color))
+        .ratio(task.ratio.clamp(0.0, 1.0));
+    f.render_widget(gauge, chunks[0]);
+
+    // Show the tail of the log that fits.
+    let height = usize::from(chunks[1].height.saturating_sub(2));
+    let lines: Vec<Line<'_>> = task
+        .log
+        .iter()
+        .skip(task.log.len().saturating_sub(height))
+        .map(|line| Line::from(format!(" {line}")))
+        .collect();
+    let log = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(" Log "))
+        .wrap(Wrap { trim: false });
+    f.render_widget(log, chunks[1]);
+}
+
+fn draw_snapshots(f: &mut Frame<'_>, app: &App, area: Rect) {
+    let label = app
+        .snapshot_env
+        .as_ref()
+        .map_or("", |(_, label)| label.as_str());
+    let diff_height = if app.snapshot_diff.is_some() {
+        area.height / 2
+    } else {
+        0
+    };
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(diff_height)])
+        .split(area);
+
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let header = Row::new(vec![
+        Cell::from("SNAPSHOT").style(bold),
+        Cell::from("CREATED").style(bold),
+        Cell::from("SIZE").style(bold),
+        Cell::from("KIND").style(bold),
+    ]);
+    let rows: Vec<Row<'_>> = app
+        .snapshots
+        .iter()
+        .enumerate()
+        .map(|(i, snapshot)| {
+            let style = if i == app.snapshot_selected {
+                Style::default()
+                    .bg(Color::DarkGray)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            let created: chrono::DateTime<chrono::Local> = snapshot.created.into();
+            let kind = match (&snapshot.layer.delta_parent, snapshot.current) {
+                (Some(_), true) => "delta, current",
+                (Some(_), false) => "delta",
+                (None, true) => "full, current",
+                (None, false) => "full",
+            };
+            Row::new(vec![
+                Cell::from(snapshot.hash[..12.min(snapshot.hash.len())].to_owned()),
+                Cell::from(created.format("%Y-%m-%d %H:%M:%S").to_string()),
+                Cell::from(format_mib(snapshot.size_bytes)),
+                Cell::from(kind),
+            ])
+            .style(style)
+        })
+        .collect();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(14),
+            Constraint::Length(21),
+            Constraint::Length(12),
+            Constraint::Min(8),
+        ],
+    )
+    .header(header)
+    .block(Block::default().borders(Borders::ALL).title(format!(
+        " Snapshots: {label} ({})  [Enter] diff  [r] restore  [d] delete  [Esc] back ",
+        app.snapshots.len()
+    )));
+    f.render_widget(table, chunks[0]);
+
+    if let Some(report) = &app.snapshot_diff {
+        let mut lines: Vec<Line<'_>> = Vec::new();
+        for (marker, color, paths) in [
+            ('+', Color::Green, &report.added),
+            ('~', Color::Yellow, &report.modified),
+            ('-', Color::Red, &report.removed),
+        ] {
+            lines.extend(paths.iter().map(|path| {
+                Line::from(Span::styled(
+                    format!(" {marker} {path}"),
+                    Style::default().fg(color),
+                ))
+            }));
+        }
+        if lines.is_empty() {
+            lines.push(Line::from("  No changes since this snapshot."));
+        }
+        let diff = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Changes since snapshot "),
+        );
+        f.render_widget(diff, chunks[1]);
+    }
+}
+
+fn draw_remote(f: &mut Frame<'_>, app: &App, area: Rect) {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let header = Row::new(vec![
+        Cell::from("REFERENCE").style(bold),
+        Cell::from("ENV").style(bold),
+        Cell::from("PUSHED").style(bold),
+        Cell::from("ENCRYPTED").style(bold),
+        Cell::from("LOCAL").style(bold),
+    ]);
+    let rows: Vec<Row<'_>> = app
+        .remote_entries
+        .iter()
+        .enumerate()
+        .map(|(i, (key, entry))| {
+            let style = if i == app.remote_selected {
+                Style::default()
+                    .bg(Color::DarkGray)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            let local = if app.is_local(&entry.env_id) {
+                Cell::from("yes").style(Style::default().fg(Color::Green))
+            } else {
+                Cell::from("no")
+            };
+            Row::new(vec![
+                Cell::from(key.as_str()),
+                Cell::from(entry.short_id.as_str()),
+                Cell::from(entry.pushed_at.as_str()),
+                Cell::from(if entry.key_fingerprints.is_empty() {
+                    "no"
+                } else {
+                    "yes"
+                }),
+                local,
+            ])
+            .style(style)
+        })
+        .collect();
+    let fetched = match &app.remote_fetched {
+        Some((at, true)) => format!(", cached {at}"),
+        Some((at, false)) => format!(", fetched {at}"),
+        None => String::new(),
+    };
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(20),
+            Constraint::Length(14),
+            Constraint::Length(27),
+            Constraint::Length(10),
+            Constraint::Length(6),
+        ],
+    )
+    .header(header)
+    .block(Block::default().borders(Borders::ALL).title(format!(
+        " Remote ({}{fetched})  [Enter] pull  [r] refresh  [Esc] back ",
+        app.remote_entries.len()
+    )));
+    f.render_widget(table, area);
+}
+
+fn draw_settings(f: &mut Frame<'_>, app: &App, area: Rect) {
+    let rows: Vec<Row<'_>> = Setting::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, &setting)| {
+            let style = if i == app.setting_selected {
+                Style::default()
+                    .bg(Color::DarkGray)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            Row::new(vec![
+                Cell::from(setting.label()),
+                Cell::from(app.setting_value(setting)),
+            ])
+            .style(style)
+        })
+        .collect();
+    let saved = app
+        .prefs_path
+        .as_ref()
+        .map_or_else(|| "not saved".to_owned(), |p| p.display().to_string());
+    let table = Table::new(rows, [Constraint::Length(52), Constraint::Min(10)]).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" Settings ({saved})  [Enter] change  [Esc] back ")),
+    );
+    f.render_widget(table, area);
 }
 
 fn draw_help(f: &mut Frame<'_>, area: Rect) {
@@ -200,11 +684,21 @@ fn draw_help(f: &mut Frame<'_>, area: Rect) {
         Line::from("  k / ↑       Move up"),
         Line::from("  g / Home    Go to top"),
         Line::from("  G / End     Go to bottom"),
-        Line::from("  Enter       View details"),
+        Line::from("  Enter       View details; in details, open a shell"),
         Line::from("  d           Destroy (with confirm)"),
         Line::from("  f           Freeze environment"),
         Line::from("  a           Archive environment"),
         Line::from("  n           Rename environment"),
+        Line::from("  i           Quick integrity check"),
+        Line::from("  u           Store disk usage"),
+        Line::from("  R           Browse and pull from the remote registry"),
+        Line::from("  o           Settings, kept in ~/.config/karapace/tui.toml"),
+        Line::from("  b           Rebuild in the background"),
+        Line::from("  p           Push to the remote in the background"),
+        Line::from("  l           Show the last rebuild or push"),
+        Line::from("  t           Browse, diff and restore snapshots"),
+        Line::from("  c / x       Commit / discard drift (detail view)"),
+        Line::from("  J / K       Scroll the drift pane (detail view)"),
         Line::from("  /           Search / filter"),
         Line::from("  s           Cycle sort column"),
         Line::from("  S           Toggle sort direction"),